@@ -1,6 +1,8 @@
 #version 450
 
 layout(location = 0) in vec2 frag_tex_coord;
+// Linear light. Textures are _SRGB images, so sampling decodes them too,
+// and the sRGB framebuffer encodes what is written.
 layout(location = 1) in vec4 frag_color;
 
 layout(location = 0) out vec4 out_color;
@@ -6,6 +6,8 @@ layout(location = 0) out vec4 out_color;
 layout(set = 0, binding = 0) uniform sampler2D scene_texture;
 
 layout(push_constant) uniform PostProcessParams {
+    // Unused: the sRGB target encodes the output, and encoding it here as
+    // well would brighten everything twice. Kept for the layout.
     float gamma;
     float exposure;
     float contrast;
@@ -14,8 +16,7 @@ layout(push_constant) uniform PostProcessParams {
 
 vec3 tonemap(vec3 color) {
     color *= params.exposure;
-    color = color / (color + vec3(1.0));
-    return pow(color, vec3(1.0 / params.gamma));
+    return color / (color + vec3(1.0));
 }
 
 void main() {
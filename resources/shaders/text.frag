@@ -1,6 +1,7 @@
 #version 450
 
 layout(location = 0) in vec2 frag_tex_coord;
+// Linear light; the sRGB framebuffer encodes it.
 layout(location = 1) in vec4 frag_color;
 
 layout(location = 0) out vec4 out_color;
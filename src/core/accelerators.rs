@@ -0,0 +1,271 @@
+//! Keyboard shortcuts of the embedder.
+//!
+//! A key press goes to the page first. Only a key the page left alone (no
+//! editable element took it and no `keydown` listener canceled it) is
+//! looked up in the [`AcceleratorTable`]. Keystrokes are written like
+//! `Ctrl+Shift+R` or `F5`: modifiers joined by `+`, then a key named as in
+//! `KeyboardEvent.key`. Letters match regardless of case; Shift is a
+//! modifier like the others.
+//!
+//! The table starts with the engine's own shortcuts (see
+//! [`BUILTIN_ACCELERATORS`]); the embedder can rebind or add to them.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::BitOr;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AcceleratorError {
+    #[error("Invalid keystroke {0:?}: {1}")]
+    InvalidKeystroke(String, &'static str),
+    #[error("Accelerator action ids must not be empty")]
+    EmptyAction,
+}
+
+/// The modifier keys held with a key, as carried by
+/// [`InputEvent::KeyPress`](crate::InputEvent).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const SHIFT: Self = Self(1 << 0);
+    pub const CTRL: Self = Self(1 << 1);
+    pub const ALT: Self = Self(1 << 2);
+    pub const META: Self = Self(1 << 3);
+
+    /// Unknown bits are dropped.
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 0b1111)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether the key is a shortcut rather than text: Ctrl, Alt or Meta is
+    /// held.
+    pub fn is_command(self) -> bool {
+        self.0 & (Self::CTRL.0 | Self::ALT.0 | Self::META.0) != 0
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Modifier names in the order keystrokes are written back.
+const MODIFIER_NAMES: &[(&str, Modifiers)] = &[
+    ("Ctrl", Modifiers::CTRL),
+    ("Alt", Modifiers::ALT),
+    ("Shift", Modifiers::SHIFT),
+    ("Meta", Modifiers::META),
+];
+
+/// `KeyboardEvent.key` values of the non-character keys a shortcut can use.
+const NAMED_KEYS: &[&str] = &[
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+    "F11",
+    "F12",
+    "ArrowLeft",
+    "ArrowRight",
+    "ArrowUp",
+    "ArrowDown",
+    "Home",
+    "End",
+    "PageUp",
+    "PageDown",
+    "Escape",
+    "Enter",
+    "Tab",
+    "Backspace",
+    "Delete",
+    "Insert",
+    "BrowserBack",
+    "BrowserForward",
+    "BrowserRefresh",
+];
+
+/// A key with the modifiers held.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Keystroke {
+    key: String,
+    modifiers: Modifiers,
+}
+
+impl Keystroke {
+    /// The keystroke of a key press, `key` being `KeyboardEvent.key`.
+    /// `None` for keys no shortcut can use, such as a lone `Shift`.
+    pub fn from_key(key: &str, modifiers: Modifiers) -> Option<Self> {
+        Some(Self {
+            key: canonical_key(key)?,
+            modifiers,
+        })
+    }
+
+    /// Parse `Ctrl+Shift+R`-style notation. Modifier names are
+    /// case-insensitive, with `Control`, `Cmd` and `Super` accepted too.
+    pub fn parse(notation: &str) -> Result<Self, AcceleratorError> {
+        let invalid = |reason| AcceleratorError::InvalidKeystroke(notation.to_string(), reason);
+        let notation = notation.trim();
+        // `+` as the key itself: `Ctrl++` or a bare `+`.
+        let (modifier_part, key) = match notation.strip_suffix('+') {
+            Some(rest) if rest.is_empty() || rest.ends_with('+') => {
+                (rest.strip_suffix('+').unwrap_or(rest), "+")
+            }
+            Some(_) => return Err(invalid("it ends with a modifier")),
+            None => match notation.rsplit_once('+') {
+                Some((modifiers, key)) => (modifiers, key),
+                None => ("", notation),
+            },
+        };
+
+        let mut modifiers = Modifiers::NONE;
+        for name in modifier_part.split('+').filter(|name| !name.is_empty()) {
+            let modifier = match name.trim().to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => Modifiers::CTRL,
+                "alt" | "option" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                "meta" | "cmd" | "command" | "super" => Modifiers::META,
+                _ => return Err(invalid("unknown modifier")),
+            };
+            modifiers = modifiers | modifier;
+        }
+        let key = canonical_key(key.trim()).ok_or_else(|| invalid("unknown key"))?;
+        Ok(Self { key, modifiers })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+}
+
+impl fmt::Display for Keystroke {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, modifier) in MODIFIER_NAMES {
+            if self.modifiers.contains(*modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        f.write_str(&self.key)
+    }
+}
+
+/// Single characters upper-case; named keys in their `KeyboardEvent.key`
+/// spelling, whatever the case they were given in.
+fn canonical_key(key: &str) -> Option<String> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_whitespace() => Some(c.to_uppercase().collect()),
+        (Some(_), Some(_)) => NAMED_KEYS
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(key))
+            .map(|name| name.to_string()),
+        _ => None,
+    }
+}
+
+/// Action ids the engine carries out itself when their shortcut fires.
+pub mod actions {
+    pub const RELOAD: &str = "reload";
+    pub const BACK: &str = "back";
+    pub const FORWARD: &str = "forward";
+    pub const ZOOM_IN: &str = "zoom_in";
+    pub const ZOOM_OUT: &str = "zoom_out";
+    pub const ZOOM_RESET: &str = "zoom_reset";
+    pub const FIND: &str = "find";
+    pub const PASTE: &str = "paste";
+}
+
+/// The shortcuts every table starts with.
+pub const BUILTIN_ACCELERATORS: &[(&str, &str)] = &[
+    ("F5", actions::RELOAD),
+    ("Ctrl+R", actions::RELOAD),
+    ("BrowserRefresh", actions::RELOAD),
+    ("Alt+ArrowLeft", actions::BACK),
+    ("BrowserBack", actions::BACK),
+    ("Alt+ArrowRight", actions::FORWARD),
+    ("BrowserForward", actions::FORWARD),
+    ("Ctrl+=", actions::ZOOM_IN),
+    ("Ctrl++", actions::ZOOM_IN),
+    ("Ctrl+Shift++", actions::ZOOM_IN),
+    ("Ctrl+-", actions::ZOOM_OUT),
+    ("Ctrl+0", actions::ZOOM_RESET),
+    ("Ctrl+F", actions::FIND),
+    ("Ctrl+V", actions::PASTE),
+    ("Shift+Insert", actions::PASTE),
+];
+
+/// Keystrokes bound to action ids.
+pub struct AcceleratorTable {
+    bindings: Mutex<HashMap<Keystroke, String>>,
+}
+
+impl Default for AcceleratorTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AcceleratorTable {
+    /// A table with [`BUILTIN_ACCELERATORS`].
+    pub fn new() -> Self {
+        let bindings = BUILTIN_ACCELERATORS
+            .iter()
+            .filter_map(|(keystroke, action)| {
+                Keystroke::parse(keystroke)
+                    .ok()
+                    .map(|keystroke| (keystroke, action.to_string()))
+            })
+            .collect();
+        Self {
+            bindings: Mutex::new(bindings),
+        }
+    }
+
+    /// Bind `keystroke` to `action`, replacing what it was bound to.
+    pub fn register(&self, keystroke: &str, action: &str) -> Result<Keystroke, AcceleratorError> {
+        if action.trim().is_empty() {
+            return Err(AcceleratorError::EmptyAction);
+        }
+        let keystroke = Keystroke::parse(keystroke)?;
+        self.bindings
+            .lock()
+            .insert(keystroke.clone(), action.to_string());
+        Ok(keystroke)
+    }
+
+    /// Unbind `keystroke`, returning the action it had.
+    pub fn unregister(&self, keystroke: &str) -> Result<Option<String>, AcceleratorError> {
+        let keystroke = Keystroke::parse(keystroke)?;
+        Ok(self.bindings.lock().remove(&keystroke))
+    }
+
+    /// The action bound to `keystroke`, if any.
+    pub fn lookup(&self, keystroke: &Keystroke) -> Option<String> {
+        self.bindings.lock().get(keystroke).cloned()
+    }
+}
@@ -0,0 +1,131 @@
+//! Finding the references in a stylesheet, `url()`s and `@import` rules,
+//! and replacing them. Comments and strings are copied as they are; the
+//! rest of the sheet is not parsed.
+
+pub(super) enum Reference<'a> {
+    Url(&'a str),
+    Import { href: &'a str, media: &'a str },
+}
+
+/// `text` with each reference replaced by what `replace` returns for it: a
+/// URL for a `url()`, the text of the whole rule for an `@import`. `None`
+/// leaves the reference as it was.
+pub(super) fn rewrite(
+    text: &str,
+    mut replace: impl FnMut(Reference<'_>) -> Option<String>,
+) -> String {
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = text[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| i + 2 + end + 2);
+            }
+            b'"' | b'\'' => i = string_end(bytes, i),
+            b'@' if starts_with_ignore_case(&bytes[i + 1..], b"import") => {
+                let Some((end, href, media)) = import_rule(text, i + "@import".len()) else {
+                    i += 1;
+                    continue;
+                };
+                if let Some(rule) = replace(Reference::Import { href, media }) {
+                    out.push_str(&text[copied..i]);
+                    out.push_str(&rule);
+                    copied = end;
+                }
+                i = end;
+            }
+            b'u' | b'U'
+                if starts_with_ignore_case(&bytes[i..], b"url(")
+                    && (i == 0 || !is_name_byte(bytes[i - 1])) =>
+            {
+                let Some((end, href)) = url_token(text, i + "url(".len()) else {
+                    i += 1;
+                    continue;
+                };
+                if let Some(url) = replace(Reference::Url(href)) {
+                    out.push_str(&text[copied..i]);
+                    out.push_str("url(\"");
+                    out.push_str(&url.replace('"', "%22").replace(['\n', '\r'], ""));
+                    out.push_str("\")");
+                    copied = end;
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Just past the string starting with the quote at `start`, or at the end
+/// of the line it runs to unclosed.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            byte if byte == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// The end of the `@import` rule whose prelude starts at `start`, its URL
+/// and its media query list.
+fn import_rule(text: &str, start: usize) -> Option<(usize, &str, &str)> {
+    let bytes = text.as_bytes();
+    let at = skip_whitespace(bytes, start);
+    let (after, href) = match bytes.get(at)? {
+        b'"' | b'\'' => {
+            let end = string_end(bytes, at);
+            (end, text.get(at + 1..end.checked_sub(1)?)?)
+        }
+        _ if starts_with_ignore_case(&bytes[at..], b"url(") => url_token(text, at + 4)?,
+        _ => return None,
+    };
+    let prelude = text.get(after..)?;
+    let end = prelude.find(';').map_or(bytes.len(), |semi| after + semi);
+    Some(((end + 1).min(bytes.len()), href, text[after..end].trim()))
+}
+
+/// Just past the `)` closing the `url(` whose argument starts at `start`,
+/// and the URL.
+fn url_token(text: &str, start: usize) -> Option<(usize, &str)> {
+    let bytes = text.as_bytes();
+    let at = skip_whitespace(bytes, start);
+    match bytes.get(at)? {
+        b'"' | b'\'' => {
+            let end = string_end(bytes, at);
+            let href = text.get(at + 1..end.checked_sub(1)?)?;
+            let close = skip_whitespace(bytes, end);
+            (bytes.get(close) == Some(&b')')).then_some((close + 1, href))
+        }
+        _ => {
+            let close = at + text[at..].find(')')?;
+            Some((close + 1, text[at..close].trim()))
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).is_some_and(|byte| byte.is_ascii_whitespace()) {
+        i += 1;
+    }
+    i
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn is_name_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || byte >= 0x80
+}
@@ -0,0 +1,114 @@
+//! Writing an MHTML archive: a `multipart/related` message (RFC 2557)
+//! whose first part is the document and the rest the resources, each under
+//! the URL it was fetched from as its `Content-Location`. Text goes
+//! quoted-printable, everything else base64.
+
+use super::Part;
+use base64::Engine;
+use std::fmt::Write;
+
+/// Occurs in no encoded part: quoted-printable writes every `=` as `=3D`
+/// or a soft line break, and base64 has `=` only at the end of its data.
+const BOUNDARY: &str = "----=_NextPart_000_Archive";
+
+/// Longest encoded line, not counting its line break.
+const LINE_LENGTH: usize = 76;
+
+pub(super) fn write(url: &str, title: &str, html: &str, parts: &[Part]) -> Vec<u8> {
+    let mut out = String::new();
+    out.push_str("From: <Saved by Vulkan Browser Engine>\r\n");
+    let _ = write!(out, "Snapshot-Content-Location: {}\r\n", url);
+    let _ = write!(out, "Subject: {}\r\n", encoded_word(title));
+    out.push_str("MIME-Version: 1.0\r\n");
+    let _ = write!(
+        out,
+        "Content-Type: multipart/related;\r\n\ttype=\"text/html\";\r\n\tboundary=\"{}\"\r\n\r\n",
+        BOUNDARY
+    );
+    write_part(&mut out, "text/html; charset=utf-8", url, html.as_bytes());
+    for part in parts {
+        write_part(&mut out, &part.content_type, &part.url, &part.body);
+    }
+    let _ = write!(out, "--{}--\r\n", BOUNDARY);
+    out.into_bytes()
+}
+
+fn write_part(out: &mut String, content_type: &str, location: &str, body: &[u8]) {
+    let textual = is_textual(content_type);
+    let _ = write!(out, "--{}\r\n", BOUNDARY);
+    let _ = write!(out, "Content-Type: {}\r\n", content_type);
+    let encoding = if textual {
+        "quoted-printable"
+    } else {
+        "base64"
+    };
+    let _ = write!(out, "Content-Transfer-Encoding: {}\r\n", encoding);
+    let _ = write!(out, "Content-Location: {}\r\n\r\n", location);
+    if textual {
+        quoted_printable(body, out);
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(body);
+        for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+            // Base64 is ASCII.
+            out.push_str(std::str::from_utf8(line).unwrap_or_default());
+            out.push_str("\r\n");
+        }
+    }
+    if !out.ends_with("\r\n") {
+        out.push_str("\r\n");
+    }
+}
+
+fn is_textual(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("/xml")
+        || essence.ends_with("/json")
+        || essence.ends_with("/javascript")
+}
+
+/// `body` quoted-printable (RFC 2045), its line breaks written as CRLF.
+fn quoted_printable(body: &[u8], out: &mut String) {
+    let mut line_length = 0;
+    let mut i = 0;
+    while i < body.len() {
+        let byte = body[i];
+        if byte == b'\n' || (byte == b'\r' && body.get(i + 1) == Some(&b'\n')) {
+            i += if byte == b'\r' { 2 } else { 1 };
+            out.push_str("\r\n");
+            line_length = 0;
+            continue;
+        }
+        // Whitespace at the end of a line would be lost on the way.
+        let ends_line = matches!(body.get(i + 1), None | Some(b'\r' | b'\n'));
+        let literal = matches!(byte, b'!'..=b'<' | b'>'..=b'~')
+            || (matches!(byte, b' ' | b'\t') && !ends_line);
+        let width = if literal { 1 } else { 3 };
+        if line_length + width > LINE_LENGTH - 1 {
+            out.push_str("=\r\n");
+            line_length = 0;
+        }
+        if literal {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "={:02X}", byte);
+        }
+        line_length += width;
+        i += 1;
+    }
+}
+
+/// `text` as a header value: as it is when plain ASCII, an RFC 2047
+/// encoded word otherwise.
+fn encoded_word(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    if text.bytes().all(|byte| (b' '..=b'~').contains(&byte)) {
+        text
+    } else {
+        format!(
+            "=?utf-8?B?{}?=",
+            base64::engine::general_purpose::STANDARD.encode(text)
+        )
+    }
+}
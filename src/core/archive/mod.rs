@@ -0,0 +1,530 @@
+//! Saving a page as one file.
+//!
+//! An archive holds the document as script has left it, with the
+//! subresources the page loaded: stylesheets, their `@import`s flattened
+//! into them and their `url()`s archived in turn, images, fonts and, when
+//! kept, scripts. [`ArchiveFormat::Mhtml`] writes a `multipart/related`
+//! message with a part for each resource; [`ArchiveFormat::SingleFileHtml`]
+//! writes one HTML file, stylesheets inlined in `<style>` and the rest as
+//! `data:` URLs. Only responses the page got go in. A resource that was
+//! blocked, failed or never requested stays a reference to its absolute
+//! URL, and the [`ArchiveManifest`] says why it was left out.
+//!
+//! Scripts are neutered by default, made `type="text/plain"` and inline
+//! event handlers dropped, so the archive opens as the page looked rather
+//! than running it again.
+
+mod css;
+mod mhtml;
+
+use crate::core::dom::document::NodeType;
+use crate::core::dom::{Document, DocumentError, NodeId};
+use crate::core::network::{KeptBody, KeptResponse};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+pub const DEFAULT_MAX_ARCHIVE_RESOURCE_BYTES: usize = 8 * 1024 * 1024;
+pub const DEFAULT_MAX_ARCHIVE_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("The document has no URL to archive it under")]
+    NoUrl,
+    #[error("Document error: {0}")]
+    Document(#[from] DocumentError),
+}
+
+pub type Result<T> = std::result::Result<T, ArchiveError>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    /// A `multipart/related` MHTML message.
+    #[default]
+    Mhtml,
+    /// One HTML file with its resources inlined.
+    SingleFileHtml,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    pub format: ArchiveFormat,
+    /// Leave scripts to run, archiving external ones like any resource.
+    pub keep_scripts: bool,
+}
+
+/// Bounds on what goes in an archive. The page keeps the bodies of its
+/// subresource responses within them, ready to be saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageArchiveConfig {
+    /// Largest resource archived; larger ones are left out.
+    pub max_resource_bytes: usize,
+    /// Bytes of resources an archive holds together, before encoding. Zero
+    /// keeps no response bodies, and archives hold the document alone.
+    pub max_archive_bytes: usize,
+}
+
+impl Default for PageArchiveConfig {
+    fn default() -> Self {
+        Self {
+            max_resource_bytes: DEFAULT_MAX_ARCHIVE_RESOURCE_BYTES,
+            max_archive_bytes: DEFAULT_MAX_ARCHIVE_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedResource {
+    pub url: String,
+    pub content_type: String,
+    /// As fetched, or as rewritten for a stylesheet.
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OmissionReason {
+    /// The page got no response: it never asked, was blocked or failed.
+    NotLoaded,
+    /// The response was an HTTP error.
+    HttpStatus(u16),
+    /// Over `max_resource_bytes`.
+    TooLarge,
+    /// Past `max_archive_bytes` with what went in before it.
+    ArchiveFull,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OmittedResource {
+    pub url: String,
+    pub reason: OmissionReason,
+}
+
+/// The resources an archive references, in the order they were met.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub included: Vec<ArchivedResource>,
+    pub omitted: Vec<OmittedResource>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PageArchive {
+    pub data: Vec<u8>,
+    pub manifest: ArchiveManifest,
+}
+
+/// Archive `document`, taking the responses to its subresource requests,
+/// by URL, from `responses`.
+pub fn save_page(
+    document: &Document,
+    responses: impl Fn(&str) -> Option<KeptResponse>,
+    options: ArchiveOptions,
+    config: &PageArchiveConfig,
+) -> Result<PageArchive> {
+    let url = document.get_url().ok_or(ArchiveError::NoUrl)?;
+    // Rewritten on a copy; the page keeps its own tree.
+    let copy = Document::parse(&document.serialize())?;
+    copy.set_url(url.clone());
+    let base = copy.base_url().ok_or(ArchiveError::NoUrl)?;
+
+    let mut archiver = Archiver {
+        responses: &responses,
+        options,
+        config,
+        taken: HashMap::new(),
+        references: HashMap::new(),
+        stylesheets: HashMap::new(),
+        parts: Vec::new(),
+        manifest: ArchiveManifest::default(),
+        bytes: 0,
+    };
+    archiver.rewrite_document(&copy, &base)?;
+    let html = copy.serialize();
+    let data = match options.format {
+        ArchiveFormat::Mhtml => mhtml::write(&url, &document.get_title(), &html, &archiver.parts),
+        ArchiveFormat::SingleFileHtml => html.into_bytes(),
+    };
+    Ok(PageArchive {
+        data,
+        manifest: archiver.manifest,
+    })
+}
+
+/// A resource's content type and body.
+type Resource = (String, Arc<[u8]>);
+
+/// A resource written as an MHTML part of its own.
+struct Part {
+    url: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+struct Archiver<'a> {
+    responses: &'a dyn Fn(&str) -> Option<KeptResponse>,
+    options: ArchiveOptions,
+    config: &'a PageArchiveConfig,
+    /// Each URL's body and content type, or `None` when it is left out.
+    taken: HashMap<String, Option<Resource>>,
+    /// What a reference to each URL becomes.
+    references: HashMap<String, String>,
+    /// Each stylesheet's rewritten text, for inlining.
+    stylesheets: HashMap<String, Option<String>>,
+    parts: Vec<Part>,
+    manifest: ArchiveManifest,
+    bytes: usize,
+}
+
+impl Archiver<'_> {
+    fn rewrite_document(&mut self, document: &Document, base: &Url) -> Result<()> {
+        let mut elements = Vec::new();
+        let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+        while let Some(node_id) = stack.pop() {
+            let Some(node) = document.get_node(node_id) else {
+                continue;
+            };
+            {
+                let node = node.read();
+                if node.node_type == NodeType::Element {
+                    let tag = node.tag_name.to_ascii_lowercase();
+                    elements.push((node_id, tag, node.attributes.clone()));
+                }
+            }
+            stack.extend(document.get_children(node_id).into_iter().rev());
+        }
+
+        for (node_id, tag, attributes) in elements {
+            let attribute = |name: &str| attributes.get(name).map(String::as_str);
+            if !self.options.keep_scripts {
+                for name in attributes.keys() {
+                    let is_handler = name.len() > 2
+                        && name
+                            .get(..2)
+                            .is_some_and(|on| on.eq_ignore_ascii_case("on"));
+                    if is_handler {
+                        document.remove_attribute(node_id, name)?;
+                    }
+                }
+            }
+            if let Some(style) = attribute("style") {
+                let style = self.rewrite_stylesheet(style.as_bytes(), base);
+                document.set_attribute(node_id, "style", &style)?;
+            }
+            match tag.as_str() {
+                "link" => self.rewrite_link(document, node_id, &attributes, base)?,
+                "style" => {
+                    let text = document.text_content(node_id).unwrap_or_default();
+                    let text = self.rewrite_stylesheet(text.as_bytes(), base);
+                    document.set_text_content(node_id, &text)?;
+                }
+                "script" => {
+                    if self.options.keep_scripts {
+                        self.archive_attribute(document, node_id, attribute("src"), "src", base)?;
+                    } else {
+                        document.set_attribute(node_id, "type", "text/plain")?;
+                        absolutize(document, node_id, attribute("src"), "src", base)?;
+                    }
+                }
+                "img" | "source" => {
+                    self.archive_attribute(document, node_id, attribute("src"), "src", base)?;
+                    if let Some(srcset) = attribute("srcset") {
+                        let srcset = rewrite_srcset(srcset, |href| match base.join(href) {
+                            Ok(url) => self.reference(&url),
+                            Err(_) => href.to_string(),
+                        });
+                        document.set_attribute(node_id, "srcset", &srcset)?;
+                    }
+                }
+                "input" => {
+                    self.archive_attribute(document, node_id, attribute("src"), "src", base)?
+                }
+                "video" => {
+                    self.archive_attribute(document, node_id, attribute("poster"), "poster", base)?;
+                    absolutize(document, node_id, attribute("src"), "src", base)?;
+                }
+                "audio" | "iframe" | "embed" | "track" => {
+                    absolutize(document, node_id, attribute("src"), "src", base)?
+                }
+                "a" | "area" => absolutize(document, node_id, attribute("href"), "href", base)?,
+                "form" => absolutize(document, node_id, attribute("action"), "action", base)?,
+                _ => {}
+            }
+        }
+
+        // Every URL is absolute now, or archived; a `<base>` could only
+        // point them somewhere else.
+        for base_element in document.query_selector_all("base[href]")? {
+            document.remove_attribute(base_element, "href")?;
+        }
+        Ok(())
+    }
+
+    fn rewrite_link(
+        &mut self,
+        document: &Document,
+        node_id: NodeId,
+        attributes: &HashMap<String, String>,
+        base: &Url,
+    ) -> Result<()> {
+        let rel = attributes
+            .get("rel")
+            .map(|rel| rel.to_ascii_lowercase())
+            .unwrap_or_default();
+        let href = attributes.get("href").map(String::as_str);
+        let Some(url) = href.and_then(|href| base.join(href.trim()).ok()) else {
+            return Ok(());
+        };
+        let is_stylesheet = rel
+            .split_ascii_whitespace()
+            .any(|token| token == "stylesheet");
+        let is_icon = rel
+            .split_ascii_whitespace()
+            .any(|token| token.ends_with("icon"));
+
+        if is_stylesheet && self.options.format == ArchiveFormat::SingleFileHtml {
+            let (Some(text), Some(parent)) = (self.stylesheet(&url), document.get_parent(node_id))
+            else {
+                return absolutize(document, node_id, href, "href", base);
+            };
+            let style = document.create_node(NodeType::Element, "style".to_string())?;
+            if let Some(media) = attributes.get("media") {
+                document.set_attribute(style, "media", media)?;
+            }
+            let text = document.create_node(NodeType::Text, text)?;
+            document.append_child(style, text)?;
+            document.insert_before(parent, style, Some(node_id))?;
+            document.remove_child(parent, node_id)?;
+            Ok(())
+        } else if is_stylesheet || is_icon {
+            self.archive_attribute(document, node_id, href, "href", base)
+        } else {
+            absolutize(document, node_id, href, "href", base)
+        }
+    }
+
+    /// Point `name` at what its resource became in the archive.
+    fn archive_attribute(
+        &mut self,
+        document: &Document,
+        node_id: NodeId,
+        value: Option<&str>,
+        name: &str,
+        base: &Url,
+    ) -> Result<()> {
+        if let Some(url) = value.and_then(|value| base.join(value.trim()).ok()) {
+            let reference = self.reference(&url);
+            document.set_attribute(node_id, name, &reference)?;
+        }
+        Ok(())
+    }
+
+    /// What a reference to `url` becomes: a `data:` URL in a single file,
+    /// or the absolute URL, which an MHTML part answers to when archived.
+    fn reference(&mut self, url: &Url) -> String {
+        if url.scheme() == "data" {
+            return url.to_string();
+        }
+        let key = without_fragment(url);
+        if let Some(reference) = self.references.get(&key) {
+            return with_fragment(reference, url);
+        }
+        // Stylesheets referring to each other meet this URL again as it is.
+        self.references.insert(key.clone(), key.clone());
+        let reference = match self.take(&key) {
+            None => key.clone(),
+            Some((content_type, body)) => {
+                let body = if essence(&content_type) == "text/css" {
+                    self.rewrite_stylesheet(&body, url).into_bytes()
+                } else {
+                    body.to_vec()
+                };
+                match self.options.format {
+                    ArchiveFormat::SingleFileHtml => data_url(&content_type, &body),
+                    ArchiveFormat::Mhtml => {
+                        self.parts.push(Part {
+                            url: key.clone(),
+                            content_type,
+                            body,
+                        });
+                        key.clone()
+                    }
+                }
+            }
+        };
+        self.references.insert(key, reference.clone());
+        with_fragment(&reference, url)
+    }
+
+    /// The text of the stylesheet at `url`, rewritten, to inline.
+    fn stylesheet(&mut self, url: &Url) -> Option<String> {
+        let key = without_fragment(url);
+        if let Some(text) = self.stylesheets.get(&key) {
+            return text.clone();
+        }
+        // A sheet importing itself, however indirectly, imports nothing.
+        self.stylesheets.insert(key.clone(), None);
+        let text = self
+            .take(&key)
+            .map(|(_, body)| self.rewrite_stylesheet(&body, url));
+        self.stylesheets.insert(key, text.clone());
+        text
+    }
+
+    fn rewrite_stylesheet(&mut self, text: &[u8], base: &Url) -> String {
+        let text = String::from_utf8_lossy(text);
+        css::rewrite(&text, |reference| match reference {
+            css::Reference::Url(href) => base.join(href).ok().map(|url| self.reference(&url)),
+            css::Reference::Import { href, media } => {
+                let sheet = base.join(href).ok().and_then(|url| self.stylesheet(&url));
+                Some(match sheet {
+                    None => String::new(),
+                    Some(sheet) if media.is_empty() => sheet,
+                    Some(sheet) => format!("@media {} {{\n{}\n}}", media, sheet),
+                })
+            }
+        })
+    }
+
+    /// The content type and body of the response to `url`, counted into
+    /// the archive the first time; `None` when it is left out, which the
+    /// manifest records.
+    fn take(&mut self, url: &str) -> Option<Resource> {
+        if let Some(taken) = self.taken.get(url) {
+            return taken.clone();
+        }
+        let taken = match self.response(url) {
+            Ok((content_type, body)) => {
+                self.bytes += body.len();
+                self.manifest.included.push(ArchivedResource {
+                    url: url.to_string(),
+                    content_type: content_type.clone(),
+                    bytes: body.len(),
+                });
+                Some((content_type, body))
+            }
+            Err(reason) => {
+                self.manifest.omitted.push(OmittedResource {
+                    url: url.to_string(),
+                    reason,
+                });
+                None
+            }
+        };
+        self.taken.insert(url.to_string(), taken.clone());
+        taken
+    }
+
+    fn response(&self, url: &str) -> std::result::Result<Resource, OmissionReason> {
+        let response = (self.responses)(url).ok_or(OmissionReason::NotLoaded)?;
+        if !(200..300).contains(&response.status) {
+            return Err(OmissionReason::HttpStatus(response.status));
+        }
+        let body = match response.body {
+            KeptBody::Bytes(body) => body,
+            KeptBody::TooLarge(_) => return Err(OmissionReason::TooLarge),
+            KeptBody::OverBudget => return Err(OmissionReason::ArchiveFull),
+        };
+        if body.len() > self.config.max_resource_bytes {
+            return Err(OmissionReason::TooLarge);
+        }
+        if self.bytes + body.len() > self.config.max_archive_bytes {
+            return Err(OmissionReason::ArchiveFull);
+        }
+        let content_type = match response.content_type.trim() {
+            "" => "application/octet-stream".to_string(),
+            content_type => content_type.to_string(),
+        };
+        Ok((content_type, body))
+    }
+}
+
+/// Make a URL attribute absolute, so it still leads where it did from
+/// wherever the archive is opened.
+fn absolutize(
+    document: &Document,
+    node_id: NodeId,
+    value: Option<&str>,
+    name: &str,
+    base: &Url,
+) -> Result<()> {
+    let Some(value) = value else {
+        return Ok(());
+    };
+    // A link within the page stays one.
+    if value.trim_start().starts_with('#') {
+        return Ok(());
+    }
+    if let Ok(url) = base.join(value.trim()) {
+        document.set_attribute(node_id, name, url.as_str())?;
+    }
+    Ok(())
+}
+
+/// `srcset` with each candidate's URL replaced by `replace`, descriptors
+/// kept.
+fn rewrite_srcset(srcset: &str, mut replace: impl FnMut(&str) -> String) -> String {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let url_end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(url_end);
+        // A URL running into the next candidate ends the one it is in.
+        let (url, descriptors, after) = match url.strip_suffix(',') {
+            Some(url) => (url, "", after),
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (url, after[..end].trim(), &after[end..])
+            }
+        };
+        let url = replace(url);
+        candidates.push(if descriptors.is_empty() {
+            url
+        } else {
+            format!("{} {}", url, descriptors)
+        });
+        rest = after;
+    }
+    candidates.join(", ")
+}
+
+fn without_fragment(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+/// `reference` with the fragment of `url`, which picks a part of the
+/// resource rather than another one.
+fn with_fragment(reference: &str, url: &Url) -> String {
+    match url.fragment() {
+        Some(fragment) => format!("{}#{}", reference, fragment),
+        None => reference.to_string(),
+    }
+}
+
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn data_url(content_type: &str, body: &[u8]) -> String {
+    format!(
+        "data:{};base64,{}",
+        content_type.replace(' ', ""),
+        base64::engine::general_purpose::STANDARD.encode(body)
+    )
+}
@@ -0,0 +1,102 @@
+//! A tab's audio session: whether it makes sound, and whether the user
+//! muted it.
+//!
+//! Whatever plays sound joins the session while it plays: media elements
+//! the embedder's playback handler took, and Web Audio contexts once there
+//! are any. Muting the tab silences every source without pausing it, so
+//! pages keep seeing their media play. The muted flag belongs to the tab
+//! and outlives navigations; the sources belong to the document.
+
+use crate::core::dom::NodeId;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+
+/// What a tab sounds like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioState {
+    /// Something plays and the tab is not muted.
+    Audible,
+    /// Something plays but the tab is muted.
+    Muted,
+    /// Nothing plays.
+    Silent,
+}
+
+/// Something that plays sound into a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AudioSource {
+    Media(NodeId),
+}
+
+#[derive(Default)]
+struct SessionState {
+    muted: bool,
+    playing: HashSet<AudioSource>,
+    // Audibility as last reported by `take_audible_change`.
+    announced: bool,
+}
+
+#[derive(Default)]
+pub struct AudioSession {
+    state: Mutex<SessionState>,
+}
+
+impl AudioSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mute or unmute the tab. `false` when it already was.
+    pub fn set_muted(&self, muted: bool) -> bool {
+        let mut state = self.state.lock();
+        let changed = state.muted != muted;
+        state.muted = muted;
+        changed
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.state.lock().muted
+    }
+
+    /// `source` started playing.
+    pub fn start(&self, source: AudioSource) {
+        self.state.lock().playing.insert(source);
+    }
+
+    /// `source` stopped playing.
+    pub fn stop(&self, source: AudioSource) {
+        self.state.lock().playing.remove(&source);
+    }
+
+    /// Stop every media source, whose document went away.
+    pub fn stop_media(&self) {
+        self.state
+            .lock()
+            .playing
+            .retain(|source| !matches!(source, AudioSource::Media(_)));
+    }
+
+    pub fn state(&self) -> AudioState {
+        let state = self.state.lock();
+        match (state.playing.is_empty(), state.muted) {
+            (true, _) => AudioState::Silent,
+            (false, true) => AudioState::Muted,
+            (false, false) => AudioState::Audible,
+        }
+    }
+
+    pub fn is_audible(&self) -> bool {
+        self.state() == AudioState::Audible
+    }
+
+    /// Whether the tab is audible, if that changed since the last call.
+    pub fn take_audible_change(&self) -> Option<bool> {
+        let audible = self.is_audible();
+        let mut state = self.state.lock();
+        if state.announced == audible {
+            return None;
+        }
+        state.announced = audible;
+        Some(audible)
+    }
+}
@@ -0,0 +1,183 @@
+//! The clipboard pages reach through `navigator.clipboard` and paste from.
+//!
+//! It holds what a platform clipboard holds: text, markup and bitmaps.
+//! Pages see the web formats instead, `text/plain`, `text/html` and
+//! `image/png`, so an image is converted at the boundary: a PNG a page
+//! writes is decoded to a bitmap, which also drops whatever metadata it
+//! carried, and a bitmap is encoded to PNG for a page to read. The embedder
+//! syncs it with the platform's clipboard, or uses it as the only one.
+//!
+//! Reading is gated per origin by
+//! [`Permission::ClipboardRead`](crate::core::permissions::Permission),
+//! writing by `ClipboardWrite`. A paste gesture needs neither: what it
+//! pastes is only visible to the page's `paste` listeners.
+
+use image::{ImageFormat, RgbaImage};
+use parking_lot::RwLock;
+use std::io::Cursor;
+use thiserror::Error;
+
+pub const TEXT_PLAIN: &str = "text/plain";
+pub const TEXT_HTML: &str = "text/html";
+pub const IMAGE_PNG: &str = "image/png";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardError {
+    #[error("Type {0:?} is not supported on the clipboard")]
+    UnsupportedType(String),
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+    #[error("Text on the clipboard must be UTF-8")]
+    InvalidText,
+}
+
+/// An image as platforms put it on the clipboard: unpremultiplied RGBA,
+/// rows top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl ClipboardBitmap {
+    pub fn from_png(png: &[u8]) -> Result<Self, ClipboardError> {
+        let image = image::load_from_memory_with_format(png, ImageFormat::Png)
+            .map_err(|e| ClipboardError::InvalidImage(e.to_string()))?
+            .into_rgba8();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            rgba: image.into_raw(),
+        })
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, ClipboardError> {
+        let image =
+            RgbaImage::from_raw(self.width, self.height, self.rgba.clone()).ok_or_else(|| {
+                ClipboardError::InvalidImage(format!(
+                    "{} bytes is not {}x{} RGBA",
+                    self.rgba.len(),
+                    self.width,
+                    self.height
+                ))
+            })?;
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(|e| ClipboardError::InvalidImage(e.to_string()))?;
+        Ok(png)
+    }
+}
+
+/// One representation of a clipboard item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardData {
+    Text(String),
+    Html(String),
+    Image(ClipboardBitmap),
+}
+
+impl ClipboardData {
+    /// Take a page's `type` and bytes.
+    pub fn from_web(mime: &str, bytes: &[u8]) -> Result<Self, ClipboardError> {
+        let text = || String::from_utf8(bytes.to_vec()).map_err(|_| ClipboardError::InvalidText);
+        match mime.to_ascii_lowercase().as_str() {
+            TEXT_PLAIN => Ok(Self::Text(text()?)),
+            TEXT_HTML => Ok(Self::Html(text()?)),
+            IMAGE_PNG => Ok(Self::Image(ClipboardBitmap::from_png(bytes)?)),
+            _ => Err(ClipboardError::UnsupportedType(mime.to_string())),
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Text(_) => TEXT_PLAIN,
+            Self::Html(_) => TEXT_HTML,
+            Self::Image(_) => IMAGE_PNG,
+        }
+    }
+
+    /// The bytes a page reads for this representation.
+    pub fn to_web(&self) -> Result<Vec<u8>, ClipboardError> {
+        match self {
+            Self::Text(text) | Self::Html(text) => Ok(text.as_bytes().to_vec()),
+            Self::Image(bitmap) => bitmap.to_png(),
+        }
+    }
+}
+
+/// Something copied: the same content in one or more representations, at
+/// most one of each type.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClipboardItem {
+    representations: Vec<ClipboardData>,
+}
+
+impl ClipboardItem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::new().with(ClipboardData::Text(text.into()))
+    }
+
+    pub fn image(bitmap: ClipboardBitmap) -> Self {
+        Self::new().with(ClipboardData::Image(bitmap))
+    }
+
+    /// Add `data`, replacing the representation of its type if there is one.
+    pub fn with(mut self, data: ClipboardData) -> Self {
+        self.representations
+            .retain(|existing| existing.mime_type() != data.mime_type());
+        self.representations.push(data);
+        self
+    }
+
+    pub fn representations(&self) -> &[ClipboardData] {
+        &self.representations
+    }
+
+    pub fn get(&self, mime: &str) -> Option<&ClipboardData> {
+        self.representations
+            .iter()
+            .find(|data| data.mime_type().eq_ignore_ascii_case(mime))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.representations.is_empty()
+    }
+}
+
+/// The engine's clipboard. Writing replaces everything on it.
+#[derive(Default)]
+pub struct Clipboard {
+    items: RwLock<Vec<ClipboardItem>>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&self, items: Vec<ClipboardItem>) {
+        *self.items.write() = items.into_iter().filter(|item| !item.is_empty()).collect();
+    }
+
+    pub fn read(&self) -> Vec<ClipboardItem> {
+        self.items.read().clone()
+    }
+
+    /// The first representation of type `mime` on the clipboard.
+    pub fn find(&self, mime: &str) -> Option<ClipboardData> {
+        self.items
+            .read()
+            .iter()
+            .find_map(|item| item.get(mime).cloned())
+    }
+
+    pub fn clear(&self) {
+        self.items.write().clear();
+    }
+}
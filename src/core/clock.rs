@@ -0,0 +1,80 @@
+//! The time script sees.
+//!
+//! By default script reads the system clock: `performance.now()` counts from
+//! when its context was created and `Date` is the wall clock. With
+//! [`BrowserConfig::virtual_time`](crate::BrowserConfig::virtual_time) set,
+//! both read a [`VirtualClock`] instead, which moves only when the embedder
+//! moves it, and `Math.random` is a generator seeded from the config. Timers
+//! fall due by the same clock, so a page fed the same inputs at the same
+//! virtual times runs the same way every time.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub struct VirtualClock {
+    /// Wall clock time when the clock read zero, in milliseconds since the
+    /// Unix epoch.
+    epoch_ms: f64,
+    now_ms: Mutex<f64>,
+}
+
+impl VirtualClock {
+    /// A clock at zero, `epoch_ms` after the Unix epoch.
+    pub fn new(epoch_ms: f64) -> Self {
+        Self {
+            epoch_ms,
+            now_ms: Mutex::new(0.0),
+        }
+    }
+
+    /// A clock at zero, now.
+    pub fn starting_now() -> Self {
+        let epoch_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_millis() as f64);
+        Self::new(epoch_ms)
+    }
+
+    /// Milliseconds since the clock read zero.
+    pub fn now_ms(&self) -> f64 {
+        *self.now_ms.lock()
+    }
+
+    pub fn epoch_ms(&self) -> f64 {
+        self.epoch_ms
+    }
+
+    /// What `Date.now()` answers.
+    pub fn wall_ms(&self) -> f64 {
+        self.epoch_ms + self.now_ms()
+    }
+
+    /// Move the clock to `ms`. It never goes back; an earlier time leaves
+    /// it where it is.
+    pub fn set(&self, ms: f64) {
+        let mut now = self.now_ms.lock();
+        if ms > *now {
+            *now = ms;
+        }
+    }
+
+    pub fn advance(&self, ms: f64) {
+        *self.now_ms.lock() += ms.max(0.0);
+    }
+}
+
+/// A virtual clock and the seed of `Math.random`, for every context of an
+/// engine.
+#[derive(Debug, Clone)]
+pub struct VirtualTime {
+    pub clock: Arc<VirtualClock>,
+    pub random_seed: u64,
+}
+
+impl VirtualTime {
+    pub fn new(clock: Arc<VirtualClock>, random_seed: u64) -> Self {
+        Self { clock, random_seed }
+    }
+}
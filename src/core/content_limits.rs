@@ -0,0 +1,176 @@
+//! Hard limits on what a page's markup and stylesheets may ask for.
+//!
+//! A page can stall or exhaust the engine with input no real site has:
+//! nesting half a million elements deep, a selector of ten thousand
+//! compounds, a million style rules, an attribute of a hundred megabytes.
+//! [`ContentLimits`] bounds each of these where the input is parsed. Going
+//! past a limit does not fail the load: the excess is flattened, cut short
+//! or dropped, as each limit says, and the breach is recorded as a
+//! [`LimitBreach`] for the engine to report.
+//!
+//! The defaults sit well above what real content uses.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Compounds in a selector past which it is dropped by default.
+pub const DEFAULT_MAX_SELECTOR_COMPONENTS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLimits {
+    /// Elements nested deeper than this are made siblings of the deepest
+    /// allowed one instead of its descendants.
+    pub max_dom_depth: usize,
+    /// Nodes the parser creates per document; the markup after the last
+    /// one is ignored.
+    pub max_nodes: usize,
+    /// Bytes of an attribute value; longer values are cut short.
+    pub max_attribute_length: usize,
+    /// Bytes of a text node outside `<script>` and `<style>`; longer text
+    /// is cut short.
+    pub max_text_length: usize,
+    /// Bytes of an inline script; a longer one is left empty rather than
+    /// run cut short.
+    pub max_inline_script_length: usize,
+    /// Style rules per stylesheet, `@media` and `@supports` contents
+    /// included; the rules after the last one are ignored.
+    pub max_stylesheet_rules: usize,
+    /// Compound selectors in one complex selector, those in `:is()`,
+    /// `:where()` and `:not()` included; a rule with a longer one is
+    /// dropped.
+    pub max_selector_components: usize,
+}
+
+impl Default for ContentLimits {
+    fn default() -> Self {
+        Self {
+            max_dom_depth: 512,
+            max_nodes: 1_000_000,
+            max_attribute_length: 8 * 1024 * 1024,
+            max_text_length: 16 * 1024 * 1024,
+            max_inline_script_length: 16 * 1024 * 1024,
+            max_stylesheet_rules: 100_000,
+            max_selector_components: DEFAULT_MAX_SELECTOR_COMPONENTS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentLimit {
+    DomDepth,
+    NodeCount,
+    AttributeLength,
+    TextLength,
+    InlineScriptLength,
+    StylesheetRules,
+    SelectorComponents,
+}
+
+impl ContentLimit {
+    /// The name a `PerformanceWarning` reports the breach under.
+    pub fn metric(self) -> &'static str {
+        match self {
+            ContentLimit::DomDepth => "dom_depth",
+            ContentLimit::NodeCount => "dom_nodes",
+            ContentLimit::AttributeLength => "attribute_length",
+            ContentLimit::TextLength => "text_length",
+            ContentLimit::InlineScriptLength => "inline_script_length",
+            ContentLimit::StylesheetRules => "stylesheet_rules",
+            ContentLimit::SelectorComponents => "selector_components",
+        }
+    }
+}
+
+/// A limit the content went past.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitBreach {
+    pub limit: ContentLimit,
+    /// What the limit was set to.
+    pub threshold: usize,
+    /// The most the content asked for. For the node and rule counts,
+    /// which stop parsing once reached, one past the limit.
+    pub value: usize,
+}
+
+/// Add a breach to `breaches`, merging it with an earlier one of the same
+/// limit.
+pub(crate) fn note(
+    breaches: &mut Vec<LimitBreach>,
+    limit: ContentLimit,
+    threshold: usize,
+    value: usize,
+) {
+    match breaches.iter_mut().find(|breach| breach.limit == limit) {
+        Some(breach) => breach.value = breach.value.max(value),
+        None => breaches.push(LimitBreach {
+            limit,
+            threshold,
+            value,
+        }),
+    }
+}
+
+/// `text` cut to at most `max` bytes, at a character boundary.
+pub(crate) fn truncate_str(text: &str, max: usize) -> &str {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[derive(Default)]
+struct BreachState {
+    breaches: Vec<LimitBreach>,
+    /// Breaches already reported, by limit.
+    reported: Vec<ContentLimit>,
+}
+
+/// The breaches of the current page, from its markup and every stylesheet
+/// it loaded, one per limit.
+#[derive(Default)]
+pub struct LimitBreaches {
+    state: Mutex<BreachState>,
+}
+
+impl LimitBreaches {
+    pub fn record(&self, breaches: impl IntoIterator<Item = LimitBreach>) {
+        let mut state = self.state.lock();
+        for breach in breaches {
+            note(
+                &mut state.breaches,
+                breach.limit,
+                breach.threshold,
+                breach.value,
+            );
+        }
+    }
+
+    pub fn all(&self) -> Vec<LimitBreach> {
+        self.state.lock().breaches.clone()
+    }
+
+    /// Breaches of limits not reported before on this page. Stylesheets
+    /// are parsed again on every restyle, so the same breach comes back.
+    pub(crate) fn take_unreported(&self) -> Vec<LimitBreach> {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        let new: Vec<LimitBreach> = state
+            .breaches
+            .iter()
+            .filter(|breach| !state.reported.contains(&breach.limit))
+            .cloned()
+            .collect();
+        state.reported.extend(new.iter().map(|breach| breach.limit));
+        new
+    }
+
+    /// A new page starts with none.
+    pub(crate) fn clear(&self) {
+        *self.state.lock() = BreachState::default();
+    }
+}
@@ -0,0 +1,172 @@
+//! Color spaces other than sRGB, and the conversions rendering needs.
+//!
+//! A computed [`Color`] is sRGB, the space the swapchain presents in.
+//! `color(display-p3 …)`, `color(srgb-linear …)`, `oklab()` and `oklch()`
+//! are converted when parsed, and whatever falls outside sRGB is brought
+//! into it by [`gamut_map`]. That is the only place out-of-gamut colors
+//! are dealt with, so a wide-gamut swapchain changes what it maps to
+//! rather than every parser.
+//!
+//! Blending and gradients work on linear light: [`srgb_to_linear`] before
+//! mixing, [`linear_to_srgb`] after. Mixing the encoded values instead
+//! makes a half-transparent white over black come out at 128, darker
+//! than the 188 the two lights average to.
+
+use super::Color;
+
+/// An sRGB-encoded channel in 0..=1 as linear light. Negative values,
+/// which extended-range conversions produce, keep their sign.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    let magnitude = c.abs();
+    let linear = if magnitude <= 0.04045 {
+        magnitude / 12.92
+    } else {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    };
+    linear.copysign(c)
+}
+
+/// The inverse of [`srgb_to_linear`].
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let magnitude = c.abs();
+    let encoded = if magnitude <= 0.003_130_8 {
+        magnitude * 12.92
+    } else {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    };
+    encoded.copysign(c)
+}
+
+/// Linear display-p3 to linear sRGB; both are D65, so this is one matrix.
+const P3_TO_SRGB: [[f32; 3]; 3] = [
+    [1.224_940_2, -0.224_940_18, 0.0],
+    [-0.042_056_955, 1.042_056_9, 0.0],
+    [-0.019_637_555, -0.078_636_05, 1.098_273_6],
+];
+
+fn multiply(matrix: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Linear sRGB as Oklab `[L, a, b]`.
+pub fn linear_srgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = 0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b;
+    let m = 0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b;
+    let s = 0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b;
+    let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+/// Oklab `[L, a, b]` as linear sRGB, possibly outside 0..=1.
+pub fn oklab_to_linear_srgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = lightness + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m = lightness - 0.105_561_346 * a - 0.063_854_17 * b;
+    let s = lightness - 0.089_484_18 * a - 1.291_485_5 * b;
+    let (l, m, s) = (l * l * l, m * m * m, s * s * s);
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+}
+
+/// Linear sRGB brought into the sRGB gamut, by clipping each channel.
+pub fn gamut_map(linear: [f32; 3]) -> [f32; 3] {
+    linear.map(|c| if c.is_nan() { 0.0 } else { c.clamp(0.0, 1.0) })
+}
+
+/// A color given in linear sRGB, gamut mapped.
+pub fn color_from_linear_srgb(linear: [f32; 3], alpha: f32) -> Color {
+    let [r, g, b] = gamut_map(linear).map(|c| (linear_to_srgb(c) * 255.0).round() as u8);
+    Color::new(r, g, b, alpha)
+}
+
+/// `color()`, `oklab()` or `oklch()` as an sRGB color. `None` for any
+/// other function, an unknown color space or a malformed argument.
+pub fn parse_color_function(value: &str) -> Option<Color> {
+    let value = value.trim();
+    let open = value.find('(')?;
+    let name = value[..open].trim().to_ascii_lowercase();
+    let arguments = value[open + 1..].strip_suffix(')')?;
+
+    // `c1 c2 c3 [/ alpha]`; the slash may come without spaces around it.
+    let (channels, alpha) = match arguments.split_once('/') {
+        Some((channels, alpha)) => (channels, Some(alpha.trim())),
+        None => (arguments, None),
+    };
+    let mut channels = channels.split_whitespace();
+    let alpha = match alpha {
+        Some(alpha) => component(alpha, 1.0)?,
+        None => 1.0,
+    };
+
+    let linear = match name.as_str() {
+        "color" => {
+            let space = channels.next()?.to_ascii_lowercase();
+            let rgb = three(&mut channels, [1.0; 3])?;
+            match space.as_str() {
+                "srgb" => rgb.map(srgb_to_linear),
+                "srgb-linear" => rgb,
+                "display-p3" => multiply(&P3_TO_SRGB, rgb.map(srgb_to_linear)),
+                _ => return None,
+            }
+        }
+        // Percentages of a and b, and of chroma, are of 0.4.
+        "oklab" => oklab_to_linear_srgb(three(&mut channels, [1.0, 0.4, 0.4])?),
+        "oklch" => {
+            let lightness = component(channels.next()?, 1.0)?;
+            let chroma = component(channels.next()?, 0.4)?.max(0.0);
+            let hue = hue(channels.next()?)?.to_radians();
+            oklab_to_linear_srgb([lightness, chroma * hue.cos(), chroma * hue.sin()])
+        }
+        _ => return None,
+    };
+    if channels.next().is_some() {
+        return None;
+    }
+    Some(color_from_linear_srgb(linear, alpha))
+}
+
+fn three<'a>(
+    channels: &mut impl Iterator<Item = &'a str>,
+    percent_of: [f32; 3],
+) -> Option<[f32; 3]> {
+    let mut values = [0.0; 3];
+    for (value, percent_of) in values.iter_mut().zip(percent_of) {
+        *value = component(channels.next()?, percent_of)?;
+    }
+    Some(values)
+}
+
+/// A number, a percentage of `percent_of`, or `none` for zero.
+fn component(text: &str, percent_of: f32) -> Option<f32> {
+    if text.eq_ignore_ascii_case("none") {
+        return Some(0.0);
+    }
+    match text.strip_suffix('%') {
+        Some(percentage) => Some(percentage.parse::<f32>().ok()? / 100.0 * percent_of),
+        None => text.parse().ok(),
+    }
+}
+
+/// A hue in degrees, from a number or an angle.
+fn hue(text: &str) -> Option<f32> {
+    let text = text.to_ascii_lowercase();
+    if text == "none" {
+        return Some(0.0);
+    }
+    let (number, per_degree) = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / std::f32::consts::PI),
+        ("turn", 360.0),
+    ]
+    .into_iter()
+    .find_map(|(unit, scale)| Some((text.strip_suffix(unit)?, scale)))
+    .unwrap_or((text.as_str(), 1.0));
+    Some(number.parse::<f32>().ok()? * per_degree)
+}
@@ -1,12 +1,13 @@
 use dashmap::DashMap;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, LazyLock};
 use thiserror::Error;
 
-use super::parser::{CSSMediaRule, CSSRule, CSSStyleRule};
+use super::parser::{CSSMediaRule, CSSRule, CSSStyleRule, MediaCondition};
 use super::selector::SelectorEngine;
-use super::{CSSUnit, Color, ComputedValue, LayoutContext};
+use super::{color_space, CSSUnit, Color, ComputedValue, LayoutContext};
 use crate::core::dom::{Document, NodeId};
 
 #[derive(Error, Debug)]
@@ -23,6 +24,15 @@ pub enum ComputedStyleError {
 
 pub type Result<T> = std::result::Result<T, ComputedStyleError>;
 
+/// Cascade weights layered on top of selector specificity. Normal
+/// declarations go user, then author, then inline; `!important` beats every
+/// normal one and reverses the order between origins, so an important user
+/// declaration beats an important author or inline one.
+const AUTHOR_WEIGHT: u32 = 1 << 16;
+const INLINE_STYLE_WEIGHT: u32 = 1 << 20;
+const IMPORTANT_WEIGHT: u32 = 1 << 24;
+const USER_IMPORTANT_WEIGHT: u32 = 1 << 25;
+
 #[derive(Debug, Clone)]
 pub struct PropertyDefinition {
     pub name: &'static str,
@@ -69,6 +79,7 @@ static INHERITED_PROPERTIES: &[&str] = &[
     "list-style-image",
     "list-style-position",
     "list-style-type",
+    "pointer-events",
     "quotes",
     "text-align",
     "text-decoration",
@@ -202,6 +213,8 @@ pub struct ComputedStyles {
     parent_styles: Option<Arc<ComputedStyles>>,
     context: LayoutContext,
     is_dirty: RwLock<bool>,
+    /// Content language of the element, inherited like a property.
+    language: RwLock<Option<String>>,
 }
 
 impl CSSValueParser for ComputedStyles {
@@ -233,6 +246,10 @@ impl CSSValueParser for ComputedStyles {
             return self.parse_color_function(trimmed);
         }
 
+        if let Some(color) = color_space::parse_color_function(trimmed) {
+            return Ok(ComputedValue::Color(color));
+        }
+
         if trimmed.starts_with("url(") {
             let url = trimmed
                 .trim_start_matches("url(")
@@ -287,6 +304,7 @@ impl ComputedStyles {
             parent_styles: None,
             context,
             is_dirty: RwLock::new(true),
+            language: RwLock::new(None),
         };
         styles.initialize_defaults();
         styles
@@ -297,6 +315,7 @@ impl ComputedStyles {
             properties: DashMap::new(),
             specificity_map: DashMap::new(),
             source_map: DashMap::new(),
+            language: RwLock::new(parent.language()),
             parent_styles: Some(parent),
             context,
             is_dirty: RwLock::new(true),
@@ -337,6 +356,16 @@ impl ComputedStyles {
         *self.is_dirty.write() = true;
     }
 
+    /// The BCP 47 tag text is shaped and hyphenated for; `None` when the
+    /// language is unknown.
+    pub fn language(&self) -> Option<String> {
+        self.language.read().clone()
+    }
+
+    pub fn set_language(&self, language: Option<String>) {
+        *self.language.write() = language;
+    }
+
     pub fn get_property(&self, name: &str) -> Option<ComputedValue> {
         self.properties.get(name).map(|entry| entry.clone())
     }
@@ -890,12 +919,49 @@ impl ComputedStyles {
     }
 }
 
+/// An author rule that matched an element, as the inspector lists it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedRule {
+    /// The rule's place among the style rules of every stylesheet, nested
+    /// ones included, in cascade order.
+    pub index: usize,
+    pub specificity: u32,
+    pub declarations: Vec<MatchedDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedDeclaration {
+    pub name: String,
+    pub value: String,
+    pub important: bool,
+    /// False when switched off with [`StyleEngine::set_declaration_enabled`].
+    pub enabled: bool,
+}
+
 pub struct StyleEngine {
     selector_engine: Arc<SelectorEngine>,
     style_cache: DashMap<NodeId, Arc<ComputedStyles>>,
     stylesheet_cache: RwLock<Vec<Arc<CSSRule>>>,
+    // The embedder's stylesheets for the current document: user-origin
+    // rules, and rules cascaded after the page's own.
+    user_stylesheet: RwLock<Vec<Arc<CSSRule>>>,
+    injected_author_stylesheet: RwLock<Vec<Arc<CSSRule>>>,
+    // Author declarations left out of the cascade, by rule index and name.
+    disabled_declarations: RwLock<HashSet<(usize, String)>>,
     media_queries: RwLock<Vec<CSSMediaRule>>,
     context_stack: RwLock<Vec<LayoutContext>>,
+    media_type: RwLock<MediaType>,
+    prefers_reduced_motion: RwLock<bool>,
+    // Nodes the last style pass styled.
+    styled_nodes: AtomicUsize,
+}
+
+/// The media type `@media` rules are evaluated against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MediaType {
+    #[default]
+    Screen,
+    Print,
 }
 
 impl StyleEngine {
@@ -904,48 +970,149 @@ impl StyleEngine {
             selector_engine: Arc::new(SelectorEngine::new()),
             style_cache: DashMap::new(),
             stylesheet_cache: RwLock::new(Vec::new()),
+            user_stylesheet: RwLock::new(Vec::new()),
+            injected_author_stylesheet: RwLock::new(Vec::new()),
+            disabled_declarations: RwLock::new(HashSet::new()),
             media_queries: RwLock::new(Vec::new()),
             context_stack: RwLock::new(vec![LayoutContext::default()]),
+            media_type: RwLock::new(MediaType::Screen),
+            prefers_reduced_motion: RwLock::new(false),
+            styled_nodes: AtomicUsize::new(0),
         }
     }
 
     pub fn compute_styles(&self, document: &Document) -> Result<()> {
         self.style_cache.clear();
 
+        let mut styled = 0;
         if let Some(root_node) = document.get_root_node() {
             let context = self.get_current_context();
-            self.compute_styles_recursive(root_node, None, document, context)?;
+            styled = self.compute_subtree_styles(root_node, None, document, context)?;
         }
+        self.styled_nodes.store(styled, Ordering::Relaxed);
 
         Ok(())
     }
 
-    fn compute_styles_recursive(
+    /// Style again the subtrees at `dirty` and at their following siblings,
+    /// whose sibling selectors may match through them, keeping the cached
+    /// styles of the rest of the document. Everything is styled when a
+    /// subtree's parent never was.
+    pub fn restyle(&self, document: &Document, dirty: &[NodeId]) -> Result<()> {
+        let mut marked: HashSet<NodeId> = HashSet::new();
+        for &node in dirty {
+            marked.insert(node);
+            if let Some(parent) = document.get_parent(node) {
+                let siblings = document.get_children(parent);
+                if let Some(index) = siblings.iter().position(|&sibling| sibling == node) {
+                    marked.extend(&siblings[index + 1..]);
+                }
+            }
+        }
+
+        // A marked ancestor's subtree already covers a node.
+        let mut roots = Vec::new();
+        for &node in &marked {
+            let mut ancestor = document.get_parent(node);
+            while let Some(id) = ancestor {
+                if marked.contains(&id) {
+                    break;
+                }
+                ancestor = document.get_parent(id);
+            }
+            if ancestor.is_some() {
+                continue;
+            }
+            let parent_styles = match document.get_parent(node) {
+                Some(parent) => match self.get_computed_styles(parent) {
+                    Some(styles) => Some(styles),
+                    None => return self.compute_styles(document),
+                },
+                None => None,
+            };
+            roots.push((node, parent_styles));
+        }
+
+        // Matches cached against the old tree are stale throughout.
+        let mut restyled = HashSet::new();
+        let mut stack: Vec<NodeId> = roots.iter().map(|(node, _)| *node).collect();
+        while let Some(node) = stack.pop() {
+            restyled.insert(node);
+            stack.extend(document.get_children(node));
+        }
+        self.selector_engine.invalidate_nodes(&restyled);
+
+        let context = self.get_current_context();
+        let mut styled = 0;
+        for (root, parent_styles) in roots {
+            styled +=
+                self.compute_subtree_styles(root, parent_styles, document, context.clone())?;
+        }
+        self.styled_nodes.store(styled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// How many nodes the last [`Self::compute_styles`] or
+    /// [`Self::restyle`] styled.
+    pub fn styled_node_count(&self) -> usize {
+        self.styled_nodes.load(Ordering::Relaxed)
+    }
+
+    /// Styles the subtree at `root` in tree order, returning how many nodes
+    /// it holds. The walk keeps its own stack, so the document's depth is
+    /// not the thread's.
+    fn compute_subtree_styles(
         &self,
-        node: NodeId,
+        root: NodeId,
         parent_styles: Option<Arc<ComputedStyles>>,
         document: &Document,
         context: LayoutContext,
-    ) -> Result<()> {
+    ) -> Result<usize> {
+        let mut styled = 0;
+        let mut stack = vec![(root, parent_styles)];
+        while let Some((node, parent_styles)) = stack.pop() {
+            let computed_styles =
+                self.compute_node_styles(node, parent_styles, document, &context)?;
+            styled += 1;
+            // Reversed, so the first child is styled first.
+            for child_node in document.get_children(node).into_iter().rev() {
+                stack.push((child_node, Some(computed_styles.clone())));
+            }
+        }
+        Ok(styled)
+    }
+
+    fn compute_node_styles(
+        &self,
+        node: NodeId,
+        parent_styles: Option<Arc<ComputedStyles>>,
+        document: &Document,
+        context: &LayoutContext,
+    ) -> Result<Arc<ComputedStyles>> {
+        let is_root = parent_styles.is_none();
         let computed_styles = if let Some(parent) = parent_styles {
             Arc::new(ComputedStyles::with_parent(context.clone(), parent))
         } else {
             Arc::new(ComputedStyles::new(context.clone()))
         };
 
-        self.apply_matching_rules(node, &computed_styles, document)?;
-        self.style_cache.insert(node, computed_styles.clone());
-
-        for &child_node in &document.get_children(node) {
-            self.compute_styles_recursive(
-                child_node,
-                Some(computed_styles.clone()),
-                document,
-                context.clone(),
-            )?;
+        // An element declaring no language keeps its parent's; the root
+        // starts from the document's default.
+        match document.declared_language(node) {
+            Some(language) => {
+                computed_styles.set_language(Some(language).filter(|tag| !tag.is_empty()))
+            }
+            None if is_root => computed_styles.set_language(document.content_language()),
+            None => {}
         }
 
-        Ok(())
+        // What a dirty node's selectors see has changed; match them afresh.
+        if document.is_style_dirty(node) {
+            self.selector_engine.invalidate_node_cache(node);
+        }
+        self.apply_matching_rules(node, &computed_styles, document)?;
+        self.style_cache.insert(node, computed_styles.clone());
+        Ok(computed_styles)
     }
 
     fn apply_matching_rules(
@@ -954,74 +1121,142 @@ impl StyleEngine {
         computed_styles: &ComputedStyles,
         document: &Document,
     ) -> Result<()> {
-        let stylesheet_cache = self.stylesheet_cache.read();
-
-        for rule_arc in stylesheet_cache.iter() {
-            match rule_arc.as_ref() {
-                CSSRule::Style(style_rule) => {
-                    self.try_apply_style_rule(node, style_rule, computed_styles, document)?;
+        let user_stylesheet = self.user_stylesheet.read();
+        self.for_each_style_rule_in(user_stylesheet.iter(), |_, style_rule| {
+            if let Some(specificity) = self.rule_specificity(node, style_rule, document) {
+                for (property_name, property_value, is_important) in
+                    &style_rule.declarations.properties
+                {
+                    let computed_value =
+                        computed_styles.parse_raw(property_value, *is_important)?;
+                    let weight = if *is_important {
+                        specificity + USER_IMPORTANT_WEIGHT
+                    } else {
+                        specificity
+                    };
+                    computed_styles.set_property(property_name, computed_value, weight, "user");
                 }
-                CSSRule::Media(media_rule) => {
-                    if self.evaluate_media_query(&media_rule.media_query) {
-                        self.apply_media_rule_styles(node, media_rule, computed_styles, document)?;
-                    }
-                }
-                _ => {}
             }
-        }
+            Ok(())
+        })?;
 
-        Ok(())
+        self.for_each_style_rule(|index, style_rule| {
+            if let Some(specificity) = self.rule_specificity(node, style_rule, document) {
+                self.apply_declarations_from_style_rule(
+                    index,
+                    style_rule,
+                    specificity,
+                    computed_styles,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        self.apply_inline_style(node, computed_styles, document)
     }
 
-    fn try_apply_style_rule(
+    fn apply_inline_style(
         &self,
         node: NodeId,
-        style_rule: &CSSStyleRule,
         computed_styles: &ComputedStyles,
         document: &Document,
     ) -> Result<()> {
-        for selector in &style_rule.selectors {
-            let selector_str = format!("{:?}", selector);
-
-            if self
-                .selector_engine
-                .matches(&selector_str, node, document)
-                .map_err(|e| ComputedStyleError::Cascade(e.to_string()))?
-            {
-                self.apply_declarations_from_style_rule(style_rule, computed_styles)?;
-                break;
+        if let Some(declaration) = document.get_inline_style(node, false) {
+            for (property_name, value) in declaration.get_all_properties() {
+                let computed_value = computed_styles.parse_raw(&value.raw, value.important)?;
+                let weight = if value.important {
+                    INLINE_STYLE_WEIGHT + IMPORTANT_WEIGHT
+                } else {
+                    INLINE_STYLE_WEIGHT
+                };
+                computed_styles.set_property(&property_name, computed_value, weight, "inline");
             }
         }
+        document.clear_style_dirty(node);
         Ok(())
     }
 
-    fn apply_media_rule_styles(
+    /// The specificity `style_rule` applies to `node` with: that of the
+    /// most specific of its selectors matching it, so `.a, #b` weighs as a
+    /// class on an element only `.a` matches. `None` when none matches.
+    fn rule_specificity(
         &self,
         node: NodeId,
-        media_rule: &CSSMediaRule,
-        computed_styles: &ComputedStyles,
+        style_rule: &CSSStyleRule,
         document: &Document,
+    ) -> Option<u32> {
+        style_rule
+            .selectors
+            .iter()
+            .filter_map(|selector| {
+                self.selector_engine
+                    .matched_specificity(selector, node, document)
+            })
+            .max()
+    }
+
+    /// Call `f` with every author style rule in cascade order and its
+    /// index, the embedder's injected ones last. Rules of `@media` blocks
+    /// that do not apply keep their index but are skipped, so indices do
+    /// not shift when the media type changes.
+    fn for_each_style_rule(&self, f: impl FnMut(usize, &CSSStyleRule) -> Result<()>) -> Result<()> {
+        let stylesheet_cache = self.stylesheet_cache.read();
+        let injected = self.injected_author_stylesheet.read();
+        self.for_each_style_rule_in(stylesheet_cache.iter().chain(injected.iter()), f)
+    }
+
+    fn for_each_style_rule_in<'a>(
+        &self,
+        rules: impl Iterator<Item = &'a Arc<CSSRule>>,
+        mut f: impl FnMut(usize, &CSSStyleRule) -> Result<()>,
     ) -> Result<()> {
-        for nested_rule in &media_rule.rules {
-            if let CSSRule::Style(style_rule) = nested_rule {
-                self.try_apply_style_rule(node, style_rule, computed_styles, document)?;
+        let mut index = 0;
+        for rule_arc in rules {
+            match rule_arc.as_ref() {
+                CSSRule::Style(style_rule) => {
+                    f(index, style_rule)?;
+                    index += 1;
+                }
+                CSSRule::Media(media_rule) => {
+                    let applies = self.evaluate_media_query(&media_rule.media_query);
+                    for style_rule in Self::media_style_rules(media_rule) {
+                        if applies {
+                            f(index, style_rule)?;
+                        }
+                        index += 1;
+                    }
+                }
+                _ => {}
             }
         }
         Ok(())
     }
 
+    fn media_style_rules(media_rule: &CSSMediaRule) -> impl Iterator<Item = &CSSStyleRule> {
+        media_rule.rules.iter().filter_map(|rule| match rule {
+            CSSRule::Style(style_rule) => Some(style_rule),
+            _ => None,
+        })
+    }
+
     fn apply_declarations_from_style_rule(
         &self,
+        index: usize,
         style_rule: &CSSStyleRule,
+        specificity: u32,
         computed_styles: &ComputedStyles,
     ) -> Result<()> {
+        let disabled = self.disabled_declarations.read();
         for (property_name, property_value, is_important) in &style_rule.declarations.properties {
+            if disabled.contains(&(index, property_name.clone())) {
+                continue;
+            }
             let computed_value = computed_styles.parse_raw(property_value, *is_important)?;
 
             let effective_specificity = if *is_important {
-                style_rule.specificity + 1000
+                specificity + AUTHOR_WEIGHT + IMPORTANT_WEIGHT
             } else {
-                style_rule.specificity
+                specificity + AUTHOR_WEIGHT
             };
 
             computed_styles.set_property(
@@ -1035,8 +1270,64 @@ impl StyleEngine {
         Ok(())
     }
 
-    fn evaluate_media_query(&self, _media_query: &crate::core::css::parser::MediaQuery) -> bool {
-        true
+    /// Whether any of `queries` matches; an empty list matches all media.
+    pub fn matches_media_list(&self, queries: &[crate::core::css::parser::MediaQuery]) -> bool {
+        queries.is_empty() || queries.iter().any(|query| self.evaluate_media_query(query))
+    }
+
+    /// Matches on media type and `prefers-reduced-motion`; other feature
+    /// conditions are not evaluated.
+    fn evaluate_media_query(&self, media_query: &crate::core::css::parser::MediaQuery) -> bool {
+        let matches = match media_query.media_type.as_deref() {
+            None => true,
+            Some(media_type) => match media_type.to_ascii_lowercase().as_str() {
+                "all" => true,
+                "screen" => *self.media_type.read() == MediaType::Screen,
+                "print" => *self.media_type.read() == MediaType::Print,
+                _ => false,
+            },
+        };
+        let features = media_query
+            .conditions
+            .iter()
+            .all(|condition| self.evaluate_media_feature(condition));
+        (matches && features) != media_query.is_not
+    }
+
+    /// Features other than `prefers-reduced-motion` match whatever they ask.
+    fn evaluate_media_feature(&self, condition: &MediaCondition) -> bool {
+        if !condition
+            .feature
+            .eq_ignore_ascii_case("prefers-reduced-motion")
+        {
+            return true;
+        }
+        let reduce = *self.prefers_reduced_motion.read();
+        match condition.value.as_deref() {
+            None => reduce,
+            Some(value) if value.eq_ignore_ascii_case("reduce") => reduce,
+            Some(value) if value.eq_ignore_ascii_case("no-preference") => !reduce,
+            Some(_) => false,
+        }
+    }
+
+    pub fn media_type(&self) -> MediaType {
+        *self.media_type.read()
+    }
+
+    /// Takes effect on the next `compute_styles`.
+    pub fn set_media_type(&self, media_type: MediaType) {
+        *self.media_type.write() = media_type;
+    }
+
+    pub fn prefers_reduced_motion(&self) -> bool {
+        *self.prefers_reduced_motion.read()
+    }
+
+    /// Whether `(prefers-reduced-motion: reduce)` matches. Takes effect on
+    /// the next `compute_styles`.
+    pub fn set_prefers_reduced_motion(&self, reduce: bool) {
+        *self.prefers_reduced_motion.write() = reduce;
     }
 
     pub fn get_computed_styles(&self, node: NodeId) -> Option<Arc<ComputedStyles>> {
@@ -1048,6 +1339,63 @@ impl StyleEngine {
         stylesheet_cache.extend(rules.into_iter().map(Arc::new));
     }
 
+    /// Replace every stylesheet with `rules`, as when the document's
+    /// `<style>` elements are re-read. Switched-off declarations are all
+    /// switched back on; the indices no longer name the same rules.
+    pub fn set_stylesheets(&self, rules: Vec<CSSRule>) {
+        *self.stylesheet_cache.write() = rules.into_iter().map(Arc::new).collect();
+        self.disabled_declarations.write().clear();
+    }
+
+    /// Replace the embedder's stylesheets: `user` rules cascade at the user
+    /// origin, below the page's normal declarations and above its important
+    /// ones; `author` rules cascade as if the page's last stylesheet. Both
+    /// outlive [`Self::set_stylesheets`].
+    pub fn set_user_stylesheets(&self, user: Vec<CSSRule>, author: Vec<CSSRule>) {
+        *self.user_stylesheet.write() = user.into_iter().map(Arc::new).collect();
+        *self.injected_author_stylesheet.write() = author.into_iter().map(Arc::new).collect();
+    }
+
+    /// The author rules matching `node`, in cascade order, with every
+    /// declaration whether switched off or not.
+    pub fn matched_rules(&self, node: NodeId, document: &Document) -> Vec<MatchedRule> {
+        let disabled = self.disabled_declarations.read();
+        let mut matched = Vec::new();
+        let _ = self.for_each_style_rule(|index, style_rule| {
+            if let Some(specificity) = self.rule_specificity(node, style_rule, document) {
+                matched.push(MatchedRule {
+                    index,
+                    specificity,
+                    declarations: style_rule
+                        .declarations
+                        .properties
+                        .iter()
+                        .map(|(name, value, important)| MatchedDeclaration {
+                            name: name.clone(),
+                            value: value.clone(),
+                            important: *important,
+                            enabled: !disabled.contains(&(index, name.clone())),
+                        })
+                        .collect(),
+                });
+            }
+            Ok(())
+        });
+        matched
+    }
+
+    /// Switch the `property` declaration of the rule at `index` out of the
+    /// cascade or back in. Takes effect on the next `compute_styles`.
+    pub fn set_declaration_enabled(&self, index: usize, property: &str, enabled: bool) {
+        let key = (index, property.to_string());
+        let mut disabled = self.disabled_declarations.write();
+        if enabled {
+            disabled.remove(&key);
+        } else {
+            disabled.insert(key);
+        }
+    }
+
     pub fn invalidate_node(&self, node: NodeId) {
         self.style_cache.remove(&node);
         self.selector_engine.invalidate_node_cache(node);
@@ -1058,6 +1406,15 @@ impl StyleEngine {
         self.selector_engine.invalidate_cache();
     }
 
+    /// Drop computed styles of nodes `document` has freed, and give back
+    /// the room they took.
+    pub fn retain_nodes(&self, document: &Document) {
+        self.style_cache
+            .retain(|node_id, _| document.contains_node(*node_id));
+        self.style_cache.shrink_to_fit();
+        self.selector_engine.retain_nodes(document);
+    }
+
     fn get_current_context(&self) -> LayoutContext {
         self.context_stack
             .read()
@@ -0,0 +1,399 @@
+//! `<link rel="stylesheet">` loading and how long first paint waits for it.
+//!
+//! Parser-inserted stylesheets in `<head>`, and any link marked
+//! `blocking="render"`, are render-blocking. Under
+//! [`FoucControl::BlockUntilBudget`] first paint waits for them, but for no
+//! sheet longer than `stylesheet_timeout_ms` after its load started and for
+//! all of them together no longer than `render_blocking_budget_ms`. A sheet
+//! that misses its slot is applied when it arrives, with a restyle. Links
+//! script inserts later only block if they say `blocking="render"`, and then
+//! hold back repaints rather than a first paint.
+
+use super::parser::{CSSParser, CSSRule, ParseError};
+use crate::core::content_limits::{ContentLimits, LimitBreaches};
+use crate::core::dom::NodeId;
+use crate::core::features::DocumentFeatures;
+use crate::core::network::{
+    FetchRequest, NetworkError, NetworkManager, Priority, RequestInitiator,
+};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::Notify;
+use url::Url;
+
+#[derive(Error, Debug)]
+pub enum StylesheetError {
+    #[error("Network error: {0}")]
+    Network(#[from] NetworkError),
+    #[error("Blocked: {0}")]
+    Blocked(String),
+    #[error("HTTP {status} for {url}")]
+    Http { status: u16, url: String },
+    #[error("Parse error: {0}")]
+    Parse(#[from] ParseError),
+}
+
+pub type Result<T> = std::result::Result<T, StylesheetError>;
+
+/// What first paint does about render-blocking stylesheets still loading.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FoucControl {
+    /// Wait for them within the timeout and budget, then paint with
+    /// whatever arrived.
+    #[default]
+    BlockUntilBudget,
+    /// Paint right away with UA and inline styles; sheets apply as they
+    /// arrive.
+    PaintImmediately,
+}
+
+#[derive(Debug, Clone)]
+pub struct StylesheetLoadingConfig {
+    /// Longest first paint waits on any one stylesheet.
+    pub stylesheet_timeout_ms: u64,
+    /// Longest first paint waits on all render-blocking stylesheets together.
+    pub render_blocking_budget_ms: u64,
+    pub fouc_control: FoucControl,
+}
+
+impl Default for StylesheetLoadingConfig {
+    fn default() -> Self {
+        Self {
+            stylesheet_timeout_ms: 2_000,
+            render_blocking_budget_ms: 3_000,
+            fouc_control: FoucControl::BlockUntilBudget,
+        }
+    }
+}
+
+impl StylesheetLoadingConfig {
+    fn stylesheet_timeout(&self) -> Duration {
+        Duration::from_millis(self.stylesheet_timeout_ms)
+    }
+}
+
+pub struct StylesheetLoader {
+    network: Arc<NetworkManager>,
+    initiator: RequestInitiator,
+    limits: ContentLimits,
+    breaches: Option<Arc<LimitBreaches>>,
+    features: Option<Arc<DocumentFeatures>>,
+}
+
+impl StylesheetLoader {
+    pub fn new(network: Arc<NetworkManager>, initiator: RequestInitiator) -> Self {
+        Self {
+            network,
+            initiator,
+            limits: ContentLimits::default(),
+            breaches: None,
+            features: None,
+        }
+    }
+
+    /// Parse sheets with the document's features, recording its uses of
+    /// disabled ones.
+    pub fn with_features(mut self, features: Arc<DocumentFeatures>) -> Self {
+        self.features = Some(features);
+        self
+    }
+
+    /// Parse sheets under `limits`, recording what they go past in
+    /// `breaches`.
+    pub fn with_content_limits(
+        mut self,
+        limits: ContentLimits,
+        breaches: Arc<LimitBreaches>,
+    ) -> Self {
+        self.limits = limits;
+        self.breaches = Some(breaches);
+        self
+    }
+
+    pub fn initiator(&self) -> &RequestInitiator {
+        &self.initiator
+    }
+
+    /// Fetch and parse the stylesheet at `url`. Stylesheets are no-cors
+    /// requests, so cross-origin sheets apply without CORS headers.
+    pub async fn load(&self, url: &Url) -> Result<Vec<CSSRule>> {
+        if !self.initiator.allows_style(url) {
+            return Err(StylesheetError::Blocked(format!(
+                "{} violates the document's style-src policy",
+                url
+            )));
+        }
+
+        let response = self
+            .network
+            .fetch_subresource(
+                FetchRequest {
+                    url: url.to_string(),
+                    method: "GET".to_string(),
+                    headers: HashMap::new(),
+                    body: None,
+                    timeout_ms: None,
+                    follow_redirects: true,
+                    cache_policy: None,
+                    priority: Priority::High,
+                    idempotent: false,
+                },
+                &self.initiator,
+            )
+            .await?;
+        if !(200..300).contains(&response.status) {
+            return Err(StylesheetError::Http {
+                status: response.status,
+                url: url.to_string(),
+            });
+        }
+
+        let text = String::from_utf8_lossy(&response.body);
+        let mut parser = CSSParser::with_limits(&self.limits);
+        if let Some(features) = &self.features {
+            parser = parser.with_features(features.current());
+        }
+        let rules = parser.parse(&text)?;
+        if let Some(breaches) = &self.breaches {
+            breaches.record(parser.take_breaches());
+        }
+        if let Some(features) = &self.features {
+            features.record(parser.take_disabled_uses());
+        }
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StylesheetStatus {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+struct LinkedSheet {
+    node: NodeId,
+    url: Url,
+    render_blocking: bool,
+    started: Instant,
+    /// When the load settled either way.
+    settled: Option<Instant>,
+    status: StylesheetStatus,
+    rules: Vec<CSSRule>,
+}
+
+/// A stylesheet that failed to load, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedStylesheet {
+    pub url: Url,
+    pub error: String,
+}
+
+/// A render-blocking stylesheet first paint stopped waiting for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LateStylesheet {
+    pub url: Url,
+    pub waited: Duration,
+}
+
+/// The current document's linked stylesheets, keyed by their `<link>`.
+pub struct LinkedStylesheets {
+    sheets: RwLock<Vec<LinkedSheet>>,
+    loader: RwLock<Option<Arc<StylesheetLoader>>>,
+    /// Bumped per document, so loads of a previous one are dropped.
+    generation: AtomicU64,
+    settled: Notify,
+    restyle_needed: AtomicBool,
+    /// Failures not yet reported.
+    failures: RwLock<Vec<FailedStylesheet>>,
+}
+
+impl LinkedStylesheets {
+    pub fn new() -> Self {
+        Self {
+            sheets: RwLock::new(Vec::new()),
+            loader: RwLock::new(None),
+            generation: AtomicU64::new(0),
+            settled: Notify::new(),
+            restyle_needed: AtomicBool::new(false),
+            failures: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Forget every sheet and load the next document's through `loader`;
+    /// `None` for documents that load none, like source listings.
+    pub fn reset(&self, loader: Option<Arc<StylesheetLoader>>) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.sheets.write().clear();
+        self.failures.write().clear();
+        *self.loader.write() = loader;
+        self.restyle_needed.store(false, Ordering::SeqCst);
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.sheets.read().iter().any(|sheet| sheet.node == node)
+    }
+
+    /// Start loading `url` for the `<link>` `node`. `false` when there is no
+    /// loader or no runtime to load on.
+    pub fn start(self: &Arc<Self>, node: NodeId, url: Url, render_blocking: bool) -> bool {
+        let loader = match self.loader.read().clone() {
+            Some(loader) => loader,
+            None => return false,
+        };
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+        self.sheets.write().push(LinkedSheet {
+            node,
+            url: url.clone(),
+            render_blocking,
+            started: Instant::now(),
+            settled: None,
+            status: StylesheetStatus::Loading,
+            rules: Vec::new(),
+        });
+
+        let set = Arc::clone(self);
+        let generation = self.generation.load(Ordering::SeqCst);
+        handle.spawn(async move {
+            let result = loader.load(&url).await;
+            set.finish_load(generation, node, result);
+        });
+        true
+    }
+
+    fn finish_load(&self, generation: u64, node: NodeId, result: Result<Vec<CSSRule>>) {
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        {
+            let mut sheets = self.sheets.write();
+            let sheet = match sheets.iter_mut().find(|sheet| sheet.node == node) {
+                Some(sheet) => sheet,
+                None => return,
+            };
+            sheet.settled = Some(Instant::now());
+            match result {
+                Ok(rules) => {
+                    sheet.status = StylesheetStatus::Loaded;
+                    sheet.rules = rules;
+                    self.restyle_needed.store(true, Ordering::SeqCst);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to load stylesheet {}: {}", sheet.url, e);
+                    sheet.status = StylesheetStatus::Failed;
+                    self.failures.write().push(FailedStylesheet {
+                        url: sheet.url.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        self.settled.notify_waiters();
+    }
+
+    /// The rules of `node`'s sheet once it has loaded.
+    pub fn rules(&self, node: NodeId) -> Option<Vec<CSSRule>> {
+        self.sheets
+            .read()
+            .iter()
+            .find(|sheet| sheet.node == node && sheet.status == StylesheetStatus::Loaded)
+            .map(|sheet| sheet.rules.clone())
+    }
+
+    /// The sheets that failed to load since the last call.
+    pub fn take_failures(&self) -> Vec<FailedStylesheet> {
+        std::mem::take(&mut *self.failures.write())
+    }
+
+    /// Whether a sheet loaded since the last call.
+    pub fn take_restyle_needed(&self) -> bool {
+        self.restyle_needed.swap(false, Ordering::SeqCst)
+    }
+
+    /// Whether a render-blocking sheet is still loading within its timeout,
+    /// so a repaint now would flash unstyled content.
+    pub fn blocks_rendering(&self, config: &StylesheetLoadingConfig) -> bool {
+        let timeout = config.stylesheet_timeout();
+        self.sheets.read().iter().any(|sheet| {
+            sheet.render_blocking
+                && sheet.status == StylesheetStatus::Loading
+                && sheet.started.elapsed() < timeout
+        })
+    }
+
+    /// How many render-blocking sheets there are and how long their loads
+    /// took in all, one still loading counting until now.
+    pub fn render_blocking_loads(&self) -> (usize, Duration) {
+        let sheets = self.sheets.read();
+        let blocking = sheets.iter().filter(|sheet| sheet.render_blocking);
+        let count = blocking.clone().count();
+        let total = blocking
+            .map(|sheet| match sheet.settled {
+                Some(settled) => settled - sheet.started,
+                None => sheet.started.elapsed(),
+            })
+            .sum();
+        (count, total)
+    }
+
+    /// Wait until no render-blocking sheet is loading, each one's timeout
+    /// having passed or the budget running out first. Returns the sheets
+    /// still loading when waiting stopped.
+    pub async fn wait_for_render_blocking(
+        &self,
+        config: &StylesheetLoadingConfig,
+    ) -> Vec<LateStylesheet> {
+        let started = Instant::now();
+        let budget_end = started + Duration::from_millis(config.render_blocking_budget_ms);
+        let timeout = config.stylesheet_timeout();
+        loop {
+            // Register before looking, so a load settling in between still
+            // wakes us.
+            let settled = self.settled.notified();
+            let (late, last_deadline) = {
+                let sheets = self.sheets.read();
+                let pending: Vec<&LinkedSheet> = sheets
+                    .iter()
+                    .filter(|sheet| {
+                        sheet.render_blocking && sheet.status == StylesheetStatus::Loading
+                    })
+                    .collect();
+                let last_deadline = pending
+                    .iter()
+                    .map(|sheet| sheet.started + timeout)
+                    .max()
+                    .map(|deadline| deadline.min(budget_end));
+                let late: Vec<LateStylesheet> = pending
+                    .iter()
+                    .map(|sheet| LateStylesheet {
+                        url: sheet.url.clone(),
+                        waited: started.elapsed(),
+                    })
+                    .collect();
+                (late, last_deadline)
+            };
+            let deadline = match last_deadline {
+                Some(deadline) => deadline,
+                None => return Vec::new(),
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return late;
+            }
+            let _ = tokio::time::timeout(deadline - now, settled).await;
+        }
+    }
+}
+
+impl Default for LinkedStylesheets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,11 +1,14 @@
+pub mod color_space;
 pub mod computed;
+pub mod loader;
 pub mod parser;
 pub mod selector;
 
-pub use computed::{ComputedStyles, StyleEngine};
+pub use computed::{ComputedStyles, MatchedDeclaration, MatchedRule, MediaType, StyleEngine};
+pub use loader::{FoucControl, LinkedStylesheets, StylesheetLoader, StylesheetLoadingConfig};
 pub use parser::{
-    CSSFontFaceRule, CSSImportRule, CSSKeyframeRule, CSSKeyframesRule, CSSMediaRule, CSSParser,
-    CSSRule, CSSStyleRule, ParseError,
+    CSSFontFaceRule, CSSImportRule, CSSKeyframeRule, CSSKeyframesRule, CSSMediaRule, CSSPageRule,
+    CSSParser, CSSRule, CSSStyleRule, ParseError,
 };
 pub use selector::{Selector, SelectorEngine, SelectorMatcher, Specificity};
 
@@ -599,6 +602,10 @@ impl CSSStyleDeclaration {
                     return self.parse_color_function(value);
                 }
 
+                if let Some(color) = color_space::parse_color_function(value) {
+                    return Ok(ComputedValue::Color(color));
+                }
+
                 if value.starts_with("url(") {
                     let url = value.trim_start_matches("url(").trim_end_matches(')');
                     let url = url.trim_matches('"').trim_matches('\'');
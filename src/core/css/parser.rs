@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::CSSStyleDeclaration;
-use crate::core::css::selector::Selector;
+use crate::core::content_limits::{self, ContentLimit, ContentLimits, LimitBreach};
+use crate::core::css::selector::{Selector, SelectorError};
+use crate::core::features::{DisabledFeatureUse, FeatureSet};
 
 #[derive(Error, Debug)]
 pub enum ParseError {
@@ -133,6 +135,14 @@ impl<'a> Tokenizer<'a> {
                 '#' => return Some(self.consume_hash()),
                 '"' | '\'' => return Some(self.consume_string(ch)),
                 '@' => return Some(self.consume_at_keyword()),
+                // `-vk-paint(`, `--gap`: a hyphen starts an identifier
+                // when a name follows it rather than a number.
+                '-' if self
+                    .peek()
+                    .is_some_and(|next| next.is_alphabetic() || next == '_' || next == '-') =>
+                {
+                    return Some(self.consume_ident_like())
+                }
                 '-' | '0'..='9' => return Some(self.consume_numeric()),
                 'a'..='z' | 'A'..='Z' | '_' => return Some(self.consume_ident_like()),
                 c => {
@@ -470,20 +480,58 @@ pub enum MediaOperator {
 pub struct CSSParser {
     tokens: Vec<Token>,
     position: usize,
+    max_rules: usize,
+    max_selector_components: usize,
+    /// Style rules parsed from the current stylesheet.
+    style_rules: usize,
+    breaches: Vec<LimitBreach>,
+    features: FeatureSet,
+    disabled_uses: Vec<DisabledFeatureUse>,
 }
 
 impl CSSParser {
     pub fn new() -> Self {
+        Self::with_limits(&ContentLimits::default())
+    }
+
+    /// A parser that keeps a stylesheet's first `max_stylesheet_rules`
+    /// style rules and drops rules with selectors past
+    /// `max_selector_components`.
+    pub fn with_limits(limits: &ContentLimits) -> Self {
         Self {
             tokens: Vec::new(),
             position: 0,
+            max_rules: limits.max_stylesheet_rules,
+            max_selector_components: limits.max_selector_components,
+            style_rules: 0,
+            breaches: Vec::new(),
+            features: FeatureSet::default(),
+            disabled_uses: Vec::new(),
         }
     }
 
+    /// Parse the properties of features missing from `features` as
+    /// invalid.
+    pub fn with_features(mut self, features: FeatureSet) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// The limits the stylesheets parsed so far went past.
+    pub fn take_breaches(&mut self) -> Vec<LimitBreach> {
+        std::mem::take(&mut self.breaches)
+    }
+
+    /// The declarations of disabled features dropped so far.
+    pub fn take_disabled_uses(&mut self) -> Vec<DisabledFeatureUse> {
+        std::mem::take(&mut self.disabled_uses)
+    }
+
     pub fn parse(&mut self, input: &str) -> Result<Vec<CSSRule>> {
         let mut tokenizer = Tokenizer::new(input);
         self.tokens = tokenizer.tokenize();
         self.position = 0;
+        self.style_rules = 0;
 
         let mut rules = Vec::new();
 
@@ -495,6 +543,14 @@ impl CSSParser {
             }
 
             match self.parse_rule() {
+                // The rest of the stylesheet is ignored. An at-rule that
+                // crossed the limit already dropped its rules past it.
+                Ok(rule) if self.over_rule_limit() => {
+                    if !matches!(rule, CSSRule::Style(_)) {
+                        rules.push(rule);
+                    }
+                    break;
+                }
                 Ok(rule) => rules.push(rule),
                 Err(e) => {
                     tracing::warn!("CSS parse error: {}", e);
@@ -506,12 +562,38 @@ impl CSSParser {
         Ok(rules)
     }
 
+    /// The queries of a media query list, such as a `media` attribute
+    /// holds. An empty list matches all media.
+    pub fn parse_media_list(&mut self, input: &str) -> Vec<MediaQuery> {
+        let mut tokenizer = Tokenizer::new(input);
+        self.tokens = tokenizer.tokenize();
+        self.position = 0;
+
+        let mut queries = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.is_at_end() {
+                break;
+            }
+            if let Ok(query) = self.parse_media_query() {
+                queries.push(query);
+            }
+            // Skip what the query left, through the comma ending it.
+            while !self.is_at_end() && !self.check_token(&Token::Comma) {
+                self.advance();
+            }
+            self.advance();
+        }
+        queries
+    }
+
     pub fn parse_declarations(&self, input: &str) -> Result<Vec<(String, String, bool)>> {
         let mut tokenizer = Tokenizer::new(input);
         let tokens = tokenizer.tokenize();
         let mut parser = Self {
             tokens,
-            position: 0,
+            features: self.features.clone(),
+            ..Self::new()
         };
 
         let mut declarations = Vec::new();
@@ -566,6 +648,7 @@ impl CSSParser {
         self.expect_token(&Token::RightBrace)?;
 
         let specificity = selectors.iter().map(|s| s.specificity()).max().unwrap_or(0);
+        self.style_rules += 1;
 
         Ok(CSSRule::Style(CSSStyleRule {
             selectors,
@@ -574,6 +657,42 @@ impl CSSParser {
         }))
     }
 
+    /// Rules of a group up to its closing `}`. As at the top level, a rule
+    /// that fails to parse is dropped on its own.
+    fn parse_nested_rules(&mut self) -> Vec<CSSRule> {
+        let mut rules = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.check_token(&Token::RightBrace) || self.is_at_end() {
+                return rules;
+            }
+            match self.parse_rule() {
+                // Parsed to find the end of the block, but dropped.
+                Ok(CSSRule::Style(_)) if self.over_rule_limit() => {}
+                Ok(rule) => rules.push(rule),
+                Err(e) => {
+                    tracing::warn!("CSS parse error: {}", e);
+                    self.recover_from_error();
+                }
+            }
+        }
+    }
+
+    /// Whether the style rules so far are past the limit, the last of them
+    /// to be dropped.
+    fn over_rule_limit(&mut self) -> bool {
+        if self.style_rules <= self.max_rules {
+            return false;
+        }
+        content_limits::note(
+            &mut self.breaches,
+            ContentLimit::StylesheetRules,
+            self.max_rules,
+            self.max_rules + 1,
+        );
+        true
+    }
+
     fn parse_media_rule(&mut self) -> Result<CSSRule> {
         self.advance();
         self.skip_whitespace();
@@ -583,10 +702,7 @@ impl CSSParser {
         self.skip_whitespace();
         self.expect_token(&Token::LeftBrace)?;
 
-        let mut rules = Vec::new();
-        while !self.check_token(&Token::RightBrace) && !self.is_at_end() {
-            rules.push(self.parse_rule()?);
-        }
+        let rules = self.parse_nested_rules();
 
         self.expect_token(&Token::RightBrace)?;
 
@@ -809,104 +925,84 @@ impl CSSParser {
         self.skip_whitespace();
         self.expect_token(&Token::LeftBrace)?;
 
-        let mut rules = Vec::new();
-        while !self.check_token(&Token::RightBrace) && !self.is_at_end() {
-            rules.push(self.parse_rule()?);
-        }
+        let rules = self.parse_nested_rules();
 
         self.expect_token(&Token::RightBrace)?;
 
         Ok(CSSRule::Supports(CSSSupportsRule { condition, rules }))
     }
 
+    /// The rule's selector list, one [`Selector`] per complex selector so
+    /// the cascade can weigh whichever matched. The list is unforgiving: a
+    /// selector that does not parse drops the whole rule.
     fn parse_selectors(&mut self) -> Result<Vec<Selector>> {
-        let mut selectors = Vec::new();
-
-        loop {
-            self.skip_whitespace();
-            selectors.push(self.parse_selector()?);
-
-            self.skip_whitespace();
-            if self.consume_if_match(&Token::Comma) {
-                continue;
-            } else {
-                break;
-            }
-        }
-
-        Ok(selectors)
-    }
-
-    fn parse_selector(&mut self) -> Result<Selector> {
         let mut selector_text = String::new();
 
-        while !self.check_token(&Token::LeftBrace)
-            && !self.check_token(&Token::Comma)
-            && !self.is_at_end()
-        {
+        while !self.check_token(&Token::LeftBrace) && !self.is_at_end() {
             match self.current_token() {
-                Some(Token::Ident(s)) => selector_text.push_str(s),
+                Some(Token::Ident(s)) | Some(Token::Function(s)) => selector_text.push_str(s),
                 Some(Token::Hash(s)) => {
                     selector_text.push('#');
                     selector_text.push_str(s);
                 }
-                Some(Token::Delim('.')) => selector_text.push('.'),
-                Some(Token::Delim('>')) => {
-                    selector_text.push(' ');
-                    selector_text.push('>');
-                    selector_text.push(' ');
-                }
-                Some(Token::Delim('+')) => {
-                    selector_text.push(' ');
-                    selector_text.push('+');
-                    selector_text.push(' ');
-                }
-                Some(Token::Delim('~')) => {
-                    selector_text.push(' ');
-                    selector_text.push('~');
-                    selector_text.push(' ');
+                Some(Token::String(s)) => {
+                    selector_text.push('"');
+                    selector_text.push_str(s);
+                    selector_text.push('"');
                 }
-                Some(Token::Delim('*')) => selector_text.push('*'),
-                Some(Token::LeftBracket) => {
-                    selector_text.push('[');
-                    self.advance();
-                    while !self.check_token(&Token::RightBracket) && !self.is_at_end() {
-                        match self.current_token() {
-                            Some(Token::Ident(s)) => selector_text.push_str(s),
-                            Some(Token::String(s)) => {
-                                selector_text.push('"');
-                                selector_text.push_str(s);
-                                selector_text.push('"');
-                            }
-                            Some(Token::Delim(c)) => selector_text.push(*c),
-                            _ => {}
-                        }
-                        self.advance();
-                    }
-                    if self.check_token(&Token::RightBracket) {
-                        selector_text.push(']');
-                    }
+                Some(Token::Number(n)) => selector_text.push_str(&n.to_string()),
+                Some(Token::Dimension(n, unit)) => {
+                    selector_text.push_str(&n.to_string());
+                    selector_text.push_str(unit);
                 }
-                Some(Token::Colon) => {
-                    selector_text.push(':');
-                    self.advance();
-                    if let Some(Token::Ident(pseudo)) = self.current_token() {
-                        selector_text.push_str(pseudo);
-                    }
+                Some(Token::Percentage(n)) => {
+                    selector_text.push_str(&n.to_string());
+                    selector_text.push('%');
                 }
+                Some(Token::Delim(c)) => selector_text.push(*c),
+                Some(Token::LeftParen) => selector_text.push('('),
+                Some(Token::RightParen) => selector_text.push(')'),
+                Some(Token::LeftBracket) => selector_text.push('['),
+                Some(Token::RightBracket) => selector_text.push(']'),
+                Some(Token::Colon) => selector_text.push(':'),
+                Some(Token::Comma) => selector_text.push(','),
                 Some(Token::Whitespace) => {
                     if !selector_text.ends_with(' ') && !selector_text.is_empty() {
                         selector_text.push(' ');
                     }
                 }
-                _ => {}
+                Some(Token::Comment(_)) => {}
+                Some(other) => {
+                    return Err(ParseError::InvalidSelector(format!(
+                        "Unexpected {:?} in selector",
+                        other
+                    )))
+                }
+                None => break,
             }
 
             self.advance();
         }
 
-        Selector::parse(selector_text.trim())
-            .map_err(|e| ParseError::InvalidSelector(e.to_string()))
+        let list = Selector::parse_with_limit(selector_text.trim(), self.max_selector_components)
+            .map_err(|e| {
+            if let SelectorError::TooComplex(max) = e {
+                content_limits::note(
+                    &mut self.breaches,
+                    ContentLimit::SelectorComponents,
+                    max,
+                    max + 1,
+                );
+            }
+            ParseError::InvalidSelector(e.to_string())
+        })?;
+        Ok(list
+            .complex_selectors
+            .into_iter()
+            .map(|complex| Selector {
+                complex_selectors: std::iter::once(complex).collect(),
+            })
+            .collect())
     }
 
     fn parse_declarations_block(&mut self) -> Result<SerializableDeclarations> {
@@ -1014,6 +1110,8 @@ impl CSSParser {
                     value.push_str(f);
                     value.push('(');
                     self.advance();
+                    // The tokenizer leaves the opening parenthesis as its own token.
+                    self.consume_if_match(&Token::LeftParen);
 
                     let mut paren_count = 1;
                     while paren_count > 0 && !self.is_at_end() {
@@ -1027,7 +1125,17 @@ impl CSSParser {
                                 value.push(')');
                             }
                             Some(Token::Ident(s)) => value.push_str(s),
+                            Some(Token::String(s)) => {
+                                value.push('"');
+                                value.push_str(s);
+                                value.push('"');
+                            }
                             Some(Token::Number(n)) => value.push_str(&n.to_string()),
+                            Some(Token::Dimension(n, unit)) => {
+                                value.push_str(&format!("{}{}", n, unit))
+                            }
+                            Some(Token::Percentage(p)) => value.push_str(&format!("{}%", p)),
+                            Some(Token::Delim(c)) => value.push(*c),
                             Some(Token::Comma) => value.push(','),
                             Some(Token::Whitespace) => value.push(' '),
                             _ => {}
@@ -1040,10 +1148,8 @@ impl CSSParser {
                 Some(Token::Comma) => value.push(','),
                 Some(Token::LeftParen) => value.push('('),
                 Some(Token::RightParen) => value.push(')'),
-                Some(Token::Whitespace) => {
-                    if !value.is_empty() && !value.ends_with(' ') {
-                        value.push(' ');
-                    }
+                Some(Token::Whitespace) if !value.is_empty() && !value.ends_with(' ') => {
+                    value.push(' ');
                 }
                 _ => {}
             }
@@ -1051,6 +1157,18 @@ impl CSSParser {
             self.advance();
         }
 
+        if let Some(feature) = self.features.disabled_css_property(&property) {
+            self.disabled_uses.push(DisabledFeatureUse {
+                feature,
+                usage: property.clone(),
+            });
+            return Err(ParseError::InvalidSyntax(format!(
+                "{} needs the disabled {} feature",
+                property,
+                feature.name()
+            )));
+        }
+
         Ok((property, value.trim().to_string(), important))
     }
 
@@ -1095,19 +1213,17 @@ impl CSSParser {
 
             while !self.check_token(&Token::RightParen) && !self.is_at_end() {
                 match self.current_token() {
-                    Some(Token::Ident(s)) => {
-                        if feature.is_empty() {
-                            // First identifier inside the parens is the feature name.
-                            feature = s.clone();
-
-                            // Handle min-/max- prefix form (e.g., min-width, max-height).
-                            if feature.starts_with("min-") {
-                                operator = Some(MediaOperator::Min);
-                                feature = feature.trim_start_matches("min-").to_string();
-                            } else if feature.starts_with("max-") {
-                                operator = Some(MediaOperator::Max);
-                                feature = feature.trim_start_matches("max-").to_string();
-                            }
+                    Some(Token::Ident(s)) if feature.is_empty() => {
+                        // First identifier inside the parens is the feature name.
+                        feature = s.clone();
+
+                        // Handle min-/max- prefix form (e.g., min-width, max-height).
+                        if feature.starts_with("min-") {
+                            operator = Some(MediaOperator::Min);
+                            feature = feature.trim_start_matches("min-").to_string();
+                        } else if feature.starts_with("max-") {
+                            operator = Some(MediaOperator::Max);
+                            feature = feature.trim_start_matches("max-").to_string();
                         }
                     }
                     Some(Token::Colon) => {
@@ -1125,10 +1241,10 @@ impl CSSParser {
                                 Some(Token::Percentage(p)) => val.push_str(&format!("{}%", p)),
                                 Some(Token::Ident(s)) => val.push_str(s),
                                 Some(Token::Delim(c)) => val.push(*c),
-                                Some(Token::Whitespace) => {
-                                    if !val.ends_with(' ') && !val.is_empty() {
-                                        val.push(' ');
-                                    }
+                                Some(Token::Whitespace)
+                                    if !val.ends_with(' ') && !val.is_empty() =>
+                                {
+                                    val.push(' ');
                                 }
                                 _ => {}
                             }
@@ -1234,8 +1350,10 @@ impl CSSParser {
         while !self.is_at_end() {
             match self.current_token() {
                 Some(Token::LeftBrace) => brace_count += 1,
+                // The block of the rule that failed, or the one the error
+                // was inside of, ends the rule.
                 Some(Token::RightBrace) => {
-                    if brace_count > 0 {
+                    if brace_count > 1 {
                         brace_count -= 1;
                     } else {
                         self.advance();
@@ -5,7 +5,9 @@ use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error;
 
+use crate::core::content_limits::DEFAULT_MAX_SELECTOR_COMPONENTS;
 use crate::core::dom::{Document, NodeId};
+use crate::core::forms;
 
 #[derive(Error, Debug)]
 pub enum SelectorError {
@@ -17,6 +19,8 @@ pub enum SelectorError {
     UnsupportedPseudoClass(String),
     #[error("Unsupported pseudo-element: {0}")]
     UnsupportedPseudoElement(String),
+    #[error("More than {0} compound selectors in a complex selector")]
+    TooComplex(usize),
 }
 
 pub type Result<T> = std::result::Result<T, SelectorError>;
@@ -118,7 +122,12 @@ pub enum PseudoClass {
     NthLastChild(NthPattern),
     NthOfType(NthPattern),
     NthLastOfType(NthPattern),
-    Not(Box<SimpleSelector>),
+    /// `:is()`: matches what any of its selectors match.
+    Is(Box<Selector>),
+    /// `:where()`: as `:is()`, without adding specificity.
+    Where(Box<Selector>),
+    /// `:not()`: matches what none of its selectors match.
+    Not(Box<Selector>),
     Hover,
     Active,
     Focus,
@@ -288,8 +297,14 @@ impl SimpleSelector {
         }
         spec.c += self.classes.len() as u32;
         spec.c += self.attributes.len() as u32;
-        spec.c += self.pseudo_classes.len() as u32;
-        if self.element_name.is_some() {
+        for pseudo_class in &self.pseudo_classes {
+            match pseudo_class {
+                PseudoClass::Is(list) | PseudoClass::Not(list) => spec.add(&list.max_specificity()),
+                PseudoClass::Where(_) => {}
+                _ => spec.c += 1,
+            }
+        }
+        if self.element_name.as_deref().is_some_and(|name| name != "*") {
             spec.d += 1
         }
         if self.pseudo_element.is_some() {
@@ -352,15 +367,45 @@ impl Selector {
         }
     }
     pub fn parse(input: &str) -> Result<Self> {
-        let mut parser = SelectorParser::new(input);
+        Self::parse_with_limit(input, DEFAULT_MAX_SELECTOR_COMPONENTS)
+    }
+
+    /// Parse `input`, failing with [`SelectorError::TooComplex`] as soon
+    /// as a complex selector has more than `max_components` compounds.
+    /// Matching walks a complex selector recursively, so this bounds its
+    /// depth as well as its cost.
+    pub fn parse_with_limit(input: &str, max_components: usize) -> Result<Self> {
+        let mut parser = SelectorParser::new(input, max_components);
         parser.parse()
     }
     pub fn specificity(&self) -> u32 {
+        self.max_specificity().value()
+    }
+
+    /// Specificity of the most specific selector in the list, which is what
+    /// `:is()` and `:not()` count as.
+    pub fn max_specificity(&self) -> Specificity {
+        self.complex_selectors
+            .iter()
+            .map(ComplexSelector::specificity)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Specificity of the most specific selector in the list that matches
+    /// `node_id`, which is what the cascade weighs a rule's declarations
+    /// by. `None` when none matches.
+    pub fn matching_specificity(
+        &self,
+        node_id: NodeId,
+        document: &Document,
+        matcher: &SelectorMatcher,
+    ) -> Option<u32> {
         self.complex_selectors
             .iter()
+            .filter(|cs| matcher.matches_complex_selector(cs, node_id, document))
             .map(|cs| cs.specificity().value())
             .max()
-            .unwrap_or(0)
     }
     pub fn matches(&self, node_id: NodeId, document: &Document, matcher: &SelectorMatcher) -> bool {
         self.complex_selectors
@@ -373,51 +418,118 @@ pub struct SelectorParser<'a> {
     input: &'a str,
     position: usize,
     current: Option<char>,
+    max_components: usize,
+    /// Compounds of the current top-level complex selector so far, those
+    /// in its functional pseudo-classes included.
+    components: usize,
+    /// Functional pseudo-classes the parser is inside.
+    nesting: usize,
+    too_complex: bool,
 }
 
 impl<'a> SelectorParser<'a> {
-    fn new(input: &'a str) -> Self {
+    fn new(input: &'a str, max_components: usize) -> Self {
         let mut p = Self {
             input,
             position: 0,
             current: None,
+            max_components,
+            components: 0,
+            nesting: 0,
+            too_complex: false,
         };
         p.advance();
         p
     }
 
+    /// A whole selector list. Lists are unforgiving: one invalid selector
+    /// makes the list invalid.
     fn parse(&mut self) -> Result<Selector> {
+        let selector = self.parse_selector_list(false);
+        // A forgiving list inside may have swallowed the error.
+        if self.too_complex {
+            return Err(SelectorError::TooComplex(self.max_components));
+        }
+        let selector = selector?;
+        match self.current {
+            None => Ok(selector),
+            Some(c) => Err(SelectorError::Parse(format!("Unexpected '{}'", c))),
+        }
+    }
+
+    /// Comma-separated complex selectors, up to the end of input or a `)`
+    /// closing a functional pseudo-class. A forgiving list, as `:is()` and
+    /// `:where()` take, drops the selectors it cannot parse instead.
+    fn parse_selector_list(&mut self, forgiving: bool) -> Result<Selector> {
         let mut complex_selectors = SmallVec::new();
         loop {
             self.skip_whitespace();
-            if self.is_at_end() {
-                break;
+            if self.nesting == 0 {
+                self.components = 0;
+            }
+            match self.parse_complex_selector() {
+                Ok(selector) => match self.current {
+                    None | Some(',') | Some(')') => complex_selectors.push(selector),
+                    Some(c) if !forgiving => {
+                        return Err(SelectorError::Parse(format!("Unexpected '{}'", c)))
+                    }
+                    Some(_) => self.skip_list_item(),
+                },
+                Err(e) if !forgiving => return Err(e),
+                Err(_) => self.skip_list_item(),
             }
-            complex_selectors.push(self.parse_complex_selector()?);
-            self.skip_whitespace();
             if !self.consume_char(',') {
                 break;
             }
         }
+        if complex_selectors.is_empty() && !forgiving {
+            return Err(SelectorError::Parse("Expected selector".into()));
+        }
         Ok(Selector { complex_selectors })
     }
 
-    fn parse_complex_selector(&mut self) -> Result<ComplexSelector> {
-        let simple = self.parse_simple_selector()?;
-        self.skip_whitespace();
-        if self.is_at_end() || self.current == Some(',') {
-            return Ok(ComplexSelector::new(simple));
+    /// Skip the rest of an invalid list item, up to the `,` or `)` that
+    /// ends it.
+    fn skip_list_item(&mut self) {
+        let mut depth = 0usize;
+        while let Some(c) = self.current {
+            match c {
+                ',' if depth == 0 => return,
+                ')' if depth == 0 => return,
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth = depth.saturating_sub(1),
+                '"' | '\'' => {
+                    let _ = self.parse_string();
+                    continue;
+                }
+                _ => {}
+            }
+            self.advance();
         }
-        let comb = self.parse_combinator();
-        if comb != Combinator::None {
-            let next = self.parse_complex_selector()?;
-            Ok(ComplexSelector::with_combinator(simple, comb, next))
-        } else {
-            Ok(ComplexSelector::new(simple))
+    }
+
+    /// Compound selectors joined by combinators, kept with the rightmost
+    /// compound, the one matched against the element, on top and `next`
+    /// leading leftwards.
+    fn parse_complex_selector(&mut self) -> Result<ComplexSelector> {
+        let mut selector = ComplexSelector::new(self.parse_simple_selector()?);
+        loop {
+            let combinator = self.parse_combinator();
+            if combinator == Combinator::None {
+                return Ok(selector);
+            }
+            let compound = self.parse_simple_selector()?;
+            selector = ComplexSelector::with_combinator(compound, combinator, selector);
         }
     }
 
     fn parse_simple_selector(&mut self) -> Result<SimpleSelector> {
+        self.components += 1;
+        if self.components > self.max_components {
+            self.too_complex = true;
+            return Err(SelectorError::TooComplex(self.max_components));
+        }
+        let start = self.position;
         let mut sel = SimpleSelector::new();
         while !self.is_at_end() {
             match self.current {
@@ -453,32 +565,30 @@ impl<'a> SelectorParser<'a> {
                 _ => break,
             }
         }
+        if self.position == start {
+            return Err(SelectorError::Parse(match self.current {
+                Some(c) => format!("Expected selector, found '{}'", c),
+                None => "Expected selector".into(),
+            }));
+        }
         Ok(sel)
     }
 
+    /// The combinator after a compound selector, `None` where the complex
+    /// selector ends. Whitespace is a descendant combinator only when
+    /// another compound follows it.
     fn parse_combinator(&mut self) -> Combinator {
+        let start = self.position;
         self.skip_whitespace();
         let comb = match self.current {
-            Some('>') => {
-                self.advance();
-                Combinator::Child
-            }
-            Some('+') => {
-                self.advance();
-                Combinator::NextSibling
-            }
-            Some('~') => {
-                self.advance();
-                Combinator::SubsequentSibling
-            }
-            _ => {
-                if !self.is_at_end() && self.current != Some(',') {
-                    Combinator::Descendant
-                } else {
-                    Combinator::None
-                }
-            }
+            Some('>') => Combinator::Child,
+            Some('+') => Combinator::NextSibling,
+            Some('~') => Combinator::SubsequentSibling,
+            None | Some(',') | Some(')') => return Combinator::None,
+            Some(_) if self.position > start => return Combinator::Descendant,
+            Some(_) => return Combinator::None,
         };
+        self.advance();
         self.skip_whitespace();
         comb
     }
@@ -599,12 +709,18 @@ impl<'a> SelectorParser<'a> {
                 self.expect_char(')')?;
                 Ok(PseudoClass::NthLastOfType(pat))
             }
-            "not" => {
-                self.expect_char('(')?;
-                let sel = self.parse_simple_selector()?;
-                self.expect_char(')')?;
-                Ok(PseudoClass::Not(Box::new(sel)))
-            }
+            "is" => Ok(PseudoClass::Is(Box::new(
+                self.parse_selector_arguments(true)?,
+            ))),
+            "where" => Ok(PseudoClass::Where(Box::new(
+                self.parse_selector_arguments(true)?,
+            ))),
+            "not" => Ok(PseudoClass::Not(Box::new(
+                self.parse_selector_arguments(false)?,
+            ))),
+            "has" => Err(SelectorError::UnsupportedPseudoClass(
+                ":has() is not supported".into(),
+            )),
             "lang" => {
                 self.expect_char('(')?;
                 let lang = self.parse_string().or_else(|_| self.parse_name())?;
@@ -621,6 +737,18 @@ impl<'a> SelectorParser<'a> {
         }
     }
 
+    /// The parenthesized selector list of `:is()`, `:where()` or `:not()`.
+    fn parse_selector_arguments(&mut self, forgiving: bool) -> Result<Selector> {
+        self.expect_char('(')?;
+        self.nesting += 1;
+        let list = self.parse_selector_list(forgiving);
+        self.nesting -= 1;
+        let list = list?;
+        self.skip_whitespace();
+        self.expect_char(')')?;
+        Ok(list)
+    }
+
     fn parse_pseudo_element(&mut self) -> Result<PseudoElement> {
         let name = self.parse_name()?;
         match name.as_str() {
@@ -852,7 +980,7 @@ impl SelectorMatcher {
         &self,
         node_id: NodeId,
         document: &Document,
-    ) -> dashmap::mapref::one::Ref<NodeId, NodeCache> {
+    ) -> dashmap::mapref::one::Ref<'_, NodeId, NodeCache> {
         if !self.node_cache.contains_key(&node_id) {
             if let Some(node) = document.get_node(node_id) {
                 let nd = node.read();
@@ -1026,7 +1154,29 @@ impl SelectorMatcher {
                     })
                     .unwrap_or(false)
             }),
-            PseudoClass::Not(inner) => !self.matches_simple_selector(inner, node_id, document),
+            PseudoClass::Is(list) | PseudoClass::Where(list) => {
+                list.matches(node_id, document, self)
+            }
+            PseudoClass::Not(list) => !list.matches(node_id, document, self),
+            PseudoClass::Valid => forms::validity(document, node_id).is_some_and(|v| v.is_valid()),
+            PseudoClass::Invalid => {
+                forms::validity(document, node_id).is_some_and(|v| !v.is_valid())
+            }
+            PseudoClass::Required => {
+                document
+                    .get_node(node_id)
+                    .and_then(|node| forms::is_required(&node.read()))
+                    == Some(true)
+            }
+            PseudoClass::Optional => {
+                document
+                    .get_node(node_id)
+                    .and_then(|node| forms::is_required(&node.read()))
+                    == Some(false)
+            }
+            PseudoClass::Lang(range) => document
+                .language_of(node_id)
+                .is_some_and(|language| language_matches(&language, range)),
             _ => false,
         }
     }
@@ -1104,6 +1254,30 @@ impl SelectorMatcher {
             self.match_cache.remove(&key);
         }
     }
+
+    /// Forget what is cached of each of `nodes`, in one pass over the
+    /// matches.
+    pub fn invalidate_nodes(&self, nodes: &HashSet<NodeId>) {
+        self.node_cache
+            .retain(|node_id, _| !nodes.contains(node_id));
+        self.match_cache
+            .retain(|(_, node_id), _| !nodes.contains(node_id));
+    }
+}
+
+/// Whether `language` falls under the `:lang()` range `range`: the same
+/// tag, or one starting with it followed by `-`, ignoring case. `*`
+/// matches any known language.
+fn language_matches(language: &str, range: &str) -> bool {
+    if range == "*" {
+        return true;
+    }
+    match language.get(..range.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(range) => {
+            language.len() == range.len() || language.as_bytes()[range.len()] == b'-'
+        }
+        _ => false,
+    }
 }
 
 pub struct SelectorEngine {
@@ -1131,9 +1305,7 @@ impl SelectorEngine {
             let mut stack = vec![root];
             while let Some(n) = stack.pop() {
                 nodes.push(n);
-                for &c in &document.get_children(n) {
-                    stack.push(c);
-                }
+                stack.extend(document.get_children(n).into_iter().rev());
             }
         }
         nodes
@@ -1158,6 +1330,31 @@ impl SelectorEngine {
         Ok(self.matcher.matches(&sel, node_id, document))
     }
 
+    /// Match an already parsed selector, as stylesheet rules hold them.
+    pub fn matches_parsed(
+        &self,
+        selector: &Selector,
+        node_id: NodeId,
+        document: &Document,
+    ) -> bool {
+        self.matcher.matches(selector, node_id, document)
+    }
+
+    /// The specificity a match of `selector` on `node_id` counts with,
+    /// that of its most specific matching selector. `None` when it does not
+    /// match.
+    pub fn matched_specificity(
+        &self,
+        selector: &Selector,
+        node_id: NodeId,
+        document: &Document,
+    ) -> Option<u32> {
+        if !self.matcher.matches(selector, node_id, document) {
+            return None;
+        }
+        selector.matching_specificity(node_id, document, &self.matcher)
+    }
+
     pub fn query_selector(
         &self,
         selector_text: &str,
@@ -1196,6 +1393,23 @@ impl SelectorEngine {
         self.matcher.invalidate_node_cache(node_id);
     }
 
+    pub fn invalidate_nodes(&self, nodes: &HashSet<NodeId>) {
+        self.matcher.invalidate_nodes(nodes);
+    }
+
+    /// Forget cached matches of nodes `document` has freed.
+    pub fn retain_nodes(&self, document: &Document) {
+        let matcher = &self.matcher;
+        matcher
+            .node_cache
+            .retain(|node_id, _| document.contains_node(*node_id));
+        matcher
+            .match_cache
+            .retain(|(_, node_id), _| document.contains_node(*node_id));
+        matcher.node_cache.shrink_to_fit();
+        matcher.match_cache.shrink_to_fit();
+    }
+
     pub fn get_cache_stats(&self) -> serde_json::Value {
         serde_json::json!({
             "selector_cache_size": self.cached_selectors.len(),
@@ -0,0 +1,422 @@
+//! The devtools protocol: inspecting and editing the page from a client on
+//! a loopback WebSocket.
+//!
+//! Messages are JSON text. The client sends commands,
+//! `{"id", "method", "params"}`, and gets `{"id", "result"}` or
+//! `{"id", "error": {"message"}}` back; the engine sends notifications,
+//! `{"method", "params"}`, in between. `protocol.json` is the JSON Schema of
+//! every command and notification, and `Schema.getProtocol` returns it.
+//!
+//! Node ids are the engine's own, as decimal strings since they do not fit a
+//! JS number, and name nothing once the document is replaced. Edits take the
+//! same DOM and style paths as script: they are recorded as mutations, mark
+//! styles dirty and repaint. Switching a declaration off leaves the
+//! stylesheet alone; switched-off author declarations are skipped by the
+//! cascade, and inline ones are removed and kept here to be put back.
+
+pub mod overlay;
+
+use crate::core::dom::document::{MutationObserver, MutationRecord, MutationType, NodeType};
+use crate::core::dom::{Document, NodeId};
+use parking_lot::Mutex;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// JSON Schema of the protocol's commands and notifications.
+pub const PROTOCOL_SCHEMA: &str = include_str!("protocol.json");
+
+fn node_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NodeId, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    id.parse()
+        .map(NodeId)
+        .map_err(|_| serde::de::Error::custom(format!("invalid node id '{}'", id)))
+}
+
+fn optional_node_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<NodeId>, D::Error> {
+    node_id(deserializer).map(Some)
+}
+
+/// A node id as the protocol spells it.
+pub fn wire_id(node_id: NodeId) -> String {
+    node_id.0.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(
+    tag = "method",
+    content = "params",
+    rename_all_fields = "camelCase",
+    deny_unknown_fields
+)]
+pub enum Command {
+    #[serde(rename = "Schema.getProtocol")]
+    GetProtocol {},
+    #[serde(rename = "DOM.getDocument")]
+    GetDocument {},
+    #[serde(rename = "DOM.querySelector")]
+    QuerySelector { selector: String },
+    #[serde(rename = "DOM.setAttributeValue")]
+    SetAttributeValue {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+        name: String,
+        value: String,
+    },
+    #[serde(rename = "DOM.setTextContent")]
+    SetTextContent {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+        text: String,
+    },
+    #[serde(rename = "DOM.enableMutations")]
+    EnableMutations {},
+    #[serde(rename = "DOM.disableMutations")]
+    DisableMutations {},
+    #[serde(rename = "CSS.getMatchedStyles")]
+    GetMatchedStyles {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+    },
+    #[serde(rename = "CSS.getComputedStyle")]
+    GetComputedStyle {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+    },
+    /// No `rule_index` means the inline declaration.
+    #[serde(rename = "CSS.setDeclarationEnabled")]
+    SetDeclarationEnabled {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+        #[serde(default)]
+        rule_index: Option<usize>,
+        name: String,
+        enabled: bool,
+    },
+    #[serde(rename = "CSS.addInlineDeclaration")]
+    AddInlineDeclaration {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+        name: String,
+        value: String,
+        #[serde(default)]
+        important: bool,
+    },
+    #[serde(rename = "Overlay.highlightNode")]
+    HighlightNode {
+        #[serde(deserialize_with = "node_id")]
+        node_id: NodeId,
+    },
+    #[serde(rename = "Overlay.hideHighlight")]
+    HideHighlight {},
+    #[serde(rename = "Overlay.setInspectMode")]
+    SetInspectMode { enabled: bool },
+    /// No `node_id` outlines every box.
+    #[serde(rename = "Overlay.setDebugOverlays")]
+    SetDebugOverlays {
+        overlays: Vec<String>,
+        #[serde(default, deserialize_with = "optional_node_id")]
+        node_id: Option<NodeId>,
+    },
+    #[serde(rename = "Page.captureSnapshot")]
+    CaptureSnapshot {},
+}
+
+impl Command {
+    /// The command `method` names, with `params`; no params are as good as
+    /// empty ones.
+    pub fn parse(method: &str, params: Value) -> Result<Self, String> {
+        let params = if params.is_null() { json!({}) } else { params };
+        serde_json::from_value(json!({ "method": method, "params": params }))
+            .map_err(|e| format!("{}: {}", method, e))
+    }
+}
+
+/// One message from the client.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Request {
+    pub id: u64,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        serde_json::from_str(text).map_err(|e| format!("Malformed message: {}", e))
+    }
+}
+
+/// The reply to command `id`.
+pub fn response(id: u64, outcome: Result<Value, String>) -> Value {
+    match outcome {
+        Ok(result) => json!({ "id": id, "result": result }),
+        Err(message) => json!({ "id": id, "error": { "message": message } }),
+    }
+}
+
+/// `node_id` as the protocol describes it, with `depth` levels of children;
+/// `None` for all of them.
+pub fn describe_node(document: &Document, node_id: NodeId, depth: Option<usize>) -> Value {
+    let node = match document.get_node(node_id) {
+        Some(node) => node,
+        None => return Value::Null,
+    };
+    let mut description = {
+        let node = node.read();
+        let (node_type, node_name) = match node.node_type {
+            NodeType::Element => (1, node.tag_name.to_ascii_uppercase()),
+            NodeType::Text => (3, "#text".to_string()),
+            NodeType::Comment => (8, "#comment".to_string()),
+            NodeType::Document => (9, "#document".to_string()),
+            NodeType::DocumentType => (10, node.tag_name.clone()),
+        };
+        let mut description = json!({
+            "nodeId": wire_id(node_id),
+            "nodeType": node_type,
+            "nodeName": node_name,
+        });
+        match node.node_type {
+            NodeType::Element => {
+                let attributes: BTreeMap<&String, &String> = node.attributes.iter().collect();
+                description["attributes"] = json!(attributes);
+            }
+            NodeType::Text | NodeType::Comment => {
+                description["nodeValue"] = node.text_content.clone().into();
+            }
+            _ => {}
+        }
+        description
+    };
+    if depth != Some(0) {
+        let children: Vec<Value> = document
+            .get_children(node_id)
+            .into_iter()
+            .map(|child| describe_node(document, child, depth.map(|depth| depth - 1)))
+            .collect();
+        description["children"] = children.into();
+    }
+    description
+}
+
+/// `DOM.mutation` params for `record`. Added nodes come with their subtrees,
+/// so a client's tree stays whole without asking.
+fn describe_mutation(document: &Document, record: &MutationRecord) -> Value {
+    let mut params = Map::new();
+    params.insert("target".to_string(), wire_id(record.target).into());
+    match record.mutation_type {
+        MutationType::Attributes => {
+            params.insert("type".to_string(), "attributes".into());
+            params.insert("attributeName".to_string(), json!(record.attribute_name));
+            let value = record.attribute_name.as_ref().and_then(|name| {
+                document
+                    .get_node(record.target)
+                    .and_then(|node| node.read().get_attribute(name))
+            });
+            params.insert("value".to_string(), json!(value));
+            params.insert("oldValue".to_string(), json!(record.old_value));
+        }
+        MutationType::CharacterData => {
+            params.insert("type".to_string(), "characterData".into());
+            let value = document
+                .get_node(record.target)
+                .map(|node| node.read().text_content.clone());
+            params.insert("value".to_string(), json!(value));
+            params.insert("oldValue".to_string(), json!(record.old_value));
+        }
+        MutationType::ChildList => {
+            params.insert("type".to_string(), "childList".into());
+            let added: Vec<Value> = record
+                .added_nodes
+                .iter()
+                .map(|&node_id| describe_node(document, node_id, None))
+                .collect();
+            let removed: Vec<String> = record.removed_nodes.iter().copied().map(wire_id).collect();
+            params.insert("addedNodes".to_string(), added.into());
+            params.insert("removedNodes".to_string(), json!(removed));
+        }
+    }
+    json!({ "method": "DOM.mutation", "params": params })
+}
+
+#[derive(Debug, Default)]
+struct InspectorState {
+    highlighted: Option<NodeId>,
+    picking: bool,
+    hovered: Option<NodeId>,
+    // Whether the current document has our mutation observer.
+    observing: bool,
+    // Inline declarations switched off, by element and property: their
+    // value and whether they were `!important`.
+    disabled_inline: HashMap<(NodeId, String), (String, bool)>,
+}
+
+/// Devtools state of one engine: the highlighted node, inspect mode, and
+/// notifications waiting for the client.
+#[derive(Debug, Default)]
+pub struct Inspector {
+    state: Mutex<InspectorState>,
+    mutations_enabled: Arc<AtomicBool>,
+    mutations: Arc<Mutex<Vec<MutationRecord>>>,
+    notifications: Mutex<Vec<Value>>,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Outline `node`, or nothing.
+    pub fn highlight(&self, node: Option<NodeId>) {
+        self.state.lock().highlighted = node;
+    }
+
+    /// What the overlay outlines: the node under the pointer while picking,
+    /// otherwise the highlighted one.
+    pub fn outlined(&self) -> Option<NodeId> {
+        let state = self.state.lock();
+        if state.picking {
+            state.hovered.or(state.highlighted)
+        } else {
+            state.highlighted
+        }
+    }
+
+    /// Enter or leave inspect mode, where the next click picks a node rather
+    /// than reaching the page.
+    pub fn set_picking(&self, enabled: bool) {
+        let mut state = self.state.lock();
+        state.picking = enabled;
+        state.hovered = None;
+    }
+
+    pub fn is_picking(&self) -> bool {
+        self.state.lock().picking
+    }
+
+    /// The pointer moved over `node` while picking. True when the outline
+    /// changed.
+    pub fn hover(&self, node: Option<NodeId>) -> bool {
+        let mut state = self.state.lock();
+        let changed = state.hovered != node;
+        state.hovered = node;
+        changed
+    }
+
+    /// Leave inspect mode with `node` picked, telling the client. The
+    /// picked node stays highlighted.
+    pub fn pick(&self, node: Option<NodeId>) {
+        {
+            let mut state = self.state.lock();
+            state.picking = false;
+            state.hovered = None;
+            if node.is_some() {
+                state.highlighted = node;
+            }
+        }
+        self.notify(
+            "Overlay.inspectNodeRequested",
+            json!({ "nodeId": node.map(wire_id) }),
+        );
+    }
+
+    /// Start or stop `DOM.mutation` notifications.
+    pub fn set_mutations_enabled(&self, enabled: bool, document: &Document) {
+        self.mutations_enabled.store(enabled, Ordering::Relaxed);
+        if enabled {
+            self.observe(document);
+        } else {
+            self.mutations.lock().clear();
+        }
+    }
+
+    fn observe(&self, document: &Document) {
+        let mut state = self.state.lock();
+        if state.observing {
+            return;
+        }
+        state.observing = true;
+        let enabled = self.mutations_enabled.clone();
+        let sink = self.mutations.clone();
+        document.add_mutation_observer(MutationObserver {
+            callback: Arc::new(move |records| {
+                if enabled.load(Ordering::Relaxed) {
+                    sink.lock().extend_from_slice(records);
+                }
+            }),
+            observe_child_list: true,
+            observe_attributes: true,
+            observe_character_data: true,
+            observe_subtree: true,
+            attribute_filter: None,
+        });
+    }
+
+    /// The page navigated: what was known about the old document's nodes
+    /// goes, and a client following mutations is told to fetch the new one.
+    pub fn document_replaced(&self, document: &Document) {
+        {
+            let mut state = self.state.lock();
+            state.highlighted = None;
+            state.hovered = None;
+            state.observing = false;
+            state.disabled_inline.clear();
+        }
+        self.mutations.lock().clear();
+        if self.mutations_enabled.load(Ordering::Relaxed) {
+            self.observe(document);
+            self.notify("DOM.documentUpdated", json!({}));
+        }
+    }
+
+    /// Keep an inline declaration that was switched off.
+    pub fn disable_inline(&self, node: NodeId, name: &str, value: String, important: bool) {
+        self.state
+            .lock()
+            .disabled_inline
+            .insert((node, name.to_string()), (value, important));
+    }
+
+    /// Take back an inline declaration that was switched off.
+    pub fn take_disabled_inline(&self, node: NodeId, name: &str) -> Option<(String, bool)> {
+        self.state
+            .lock()
+            .disabled_inline
+            .remove(&(node, name.to_string()))
+    }
+
+    /// Inline declarations of `node` that are switched off, by name.
+    pub fn disabled_inline(&self, node: NodeId) -> Vec<(String, String, bool)> {
+        let state = self.state.lock();
+        let mut disabled: Vec<(String, String, bool)> = state
+            .disabled_inline
+            .iter()
+            .filter(|((owner, _), _)| *owner == node)
+            .map(|((_, name), (value, important))| (name.clone(), value.clone(), *important))
+            .collect();
+        disabled.sort();
+        disabled
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.notifications
+            .lock()
+            .push(json!({ "method": method, "params": params }));
+    }
+
+    /// Notifications since the last call: mutations described against
+    /// `document`, then the rest in the order they came.
+    pub fn take_notifications(&self, document: &Document) -> Vec<Value> {
+        let records = std::mem::take(&mut *self.mutations.lock());
+        let mut notifications: Vec<Value> = records
+            .iter()
+            .map(|record| describe_mutation(document, record))
+            .collect();
+        notifications.append(&mut self.notifications.lock());
+        notifications
+    }
+}
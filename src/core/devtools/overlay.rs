@@ -0,0 +1,76 @@
+//! The node highlight: a box's margin, border, padding and content areas
+//! filled in the translucent colors inspectors conventionally use, painted
+//! over the page.
+
+use crate::core::layout::LayoutBox;
+use crate::renderer::{BoxOutline, ClipChain, DrawQuad, Rect};
+
+pub const MARGIN_COLOR: [f32; 4] = [246.0 / 255.0, 178.0 / 255.0, 107.0 / 255.0, 0.66];
+pub const BORDER_COLOR: [f32; 4] = [1.0, 229.0 / 255.0, 153.0 / 255.0, 0.66];
+pub const PADDING_COLOR: [f32; 4] = [147.0 / 255.0, 196.0 / 255.0, 125.0 / 255.0, 0.55];
+pub const CONTENT_COLOR: [f32; 4] = [111.0 / 255.0, 168.0 / 255.0, 220.0 / 255.0, 0.66];
+
+/// Quads outlining `layout_box`: one for the content area and up to four
+/// for each ring around it, so no pixel is covered twice.
+pub fn box_model_quads(layout_box: &LayoutBox) -> Vec<DrawQuad> {
+    let BoxOutline {
+        margin,
+        border,
+        padding,
+        content,
+    } = BoxOutline::from_layout_box(layout_box);
+
+    let rings = [
+        (ring(&margin, &border), MARGIN_COLOR),
+        (ring(&border, &padding), BORDER_COLOR),
+        (ring(&padding, &content), PADDING_COLOR),
+        (vec![content], CONTENT_COLOR),
+    ];
+    rings
+        .into_iter()
+        .flat_map(|(rects, color)| {
+            rects.into_iter().map(move |bounds| DrawQuad {
+                bounds,
+                color,
+                clip: ClipChain::new(),
+                blur_radius: 0.0,
+                mask: None,
+                image: None,
+            })
+        })
+        .filter(|quad| quad.bounds.width > 0.0 && quad.bounds.height > 0.0)
+        .collect()
+}
+
+/// The part of `outer` outside `inner`, as the strips above, below, left
+/// and right of it.
+fn ring(outer: &Rect, inner: &Rect) -> Vec<Rect> {
+    let (outer_right, outer_bottom) = (outer.x + outer.width, outer.y + outer.height);
+    let (inner_right, inner_bottom) = (inner.x + inner.width, inner.y + inner.height);
+    vec![
+        Rect {
+            x: outer.x,
+            y: outer.y,
+            width: outer.width,
+            height: inner.y - outer.y,
+        },
+        Rect {
+            x: outer.x,
+            y: inner_bottom,
+            width: outer.width,
+            height: outer_bottom - inner_bottom,
+        },
+        Rect {
+            x: outer.x,
+            y: inner.y,
+            width: inner.x - outer.x,
+            height: inner.height,
+        },
+        Rect {
+            x: inner_right,
+            y: inner.y,
+            width: outer_right - inner_right,
+            height: inner.height,
+        },
+    ]
+}
@@ -0,0 +1,113 @@
+//! `document.write()`: markup a script hands the parser while it runs.
+//!
+//! A script the parser runs has an insertion point right after its end
+//! tag. What it writes is collected here and, once it returns, inserted
+//! into the parser's input there (see [`Document::write_after_script`]),
+//! so the scripts in it run before the next one of the document and an
+//! element it leaves open takes in the markup that follows. Written markup
+//! is never seen by the preload scanner, which only reads the response.
+//!
+//! Scripts loaded `async` or `defer` have no insertion point, so their
+//! writes are ignored with a console warning. Once the document's scripts
+//! have run, a write opens a new document instead: the current one is torn
+//! down and replaced by what is written, at the end of the task that wrote
+//! it.
+//!
+//! [`Document::write_after_script`]: crate::core::dom::Document::write_after_script
+
+use parking_lot::Mutex;
+
+/// How deep written scripts may write in turn; deeper writes are dropped.
+pub const MAX_WRITE_DEPTH: usize = 20;
+
+/// What became of a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// Queued for the insertion point.
+    Inserted,
+    /// The writing script has no insertion point.
+    Ignored,
+    /// The document is to be replaced by what was written.
+    Reopened,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Phase {
+    /// Parsing, with no parser-run script running.
+    #[default]
+    NoInsertionPoint,
+    /// A script run in parser order is running.
+    InsertionPoint,
+    /// The document's scripts have run.
+    Closed,
+}
+
+#[derive(Default)]
+struct WriteState {
+    phase: Phase,
+    written: String,
+    reopened: Option<String>,
+}
+
+/// The current document's `document.write()` calls.
+#[derive(Default)]
+pub struct DocumentWrites {
+    state: Mutex<WriteState>,
+}
+
+impl DocumentWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start over for a new document, dropping writes not taken yet.
+    pub fn reset(&self) {
+        *self.state.lock() = WriteState::default();
+    }
+
+    /// Whether the script about to run has an insertion point.
+    pub fn set_insertion_point(&self, present: bool) {
+        let mut state = self.state.lock();
+        if state.phase != Phase::Closed {
+            state.phase = if present {
+                Phase::InsertionPoint
+            } else {
+                Phase::NoInsertionPoint
+            };
+        }
+    }
+
+    /// The document's scripts have run; later writes replace it.
+    pub fn close(&self) {
+        self.state.lock().phase = Phase::Closed;
+    }
+
+    pub fn write(&self, markup: &str) -> WriteOutcome {
+        let mut state = self.state.lock();
+        match state.phase {
+            Phase::InsertionPoint => {
+                state.written.push_str(markup);
+                WriteOutcome::Inserted
+            }
+            Phase::NoInsertionPoint => WriteOutcome::Ignored,
+            Phase::Closed => {
+                state
+                    .reopened
+                    .get_or_insert_with(String::new)
+                    .push_str(markup);
+                WriteOutcome::Reopened
+            }
+        }
+    }
+
+    /// What was written at the insertion point since last taken.
+    pub fn take_written(&self) -> String {
+        std::mem::take(&mut self.state.lock().written)
+    }
+
+    /// The markup of the document to open in place of this one, once a
+    /// write came after its scripts ran.
+    pub fn take_reopened(&self) -> Option<String> {
+        self.state.lock().reopened.take()
+    }
+}
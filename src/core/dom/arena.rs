@@ -0,0 +1,183 @@
+//! Storage for a document's nodes.
+//!
+//! Nodes sit in the slots of one vector and are found through an index
+//! from their [`NodeId`]. Ids are generation-tagged: the high half is the
+//! generation of the arena that issued them, renewed on every
+//! [`NodeArena::clear`], and the low half a serial, so no id is ever handed
+//! out twice and one kept past its node's removal or a teardown never
+//! resolves to another node.
+//!
+//! A freed slot goes on a free list and takes the next node created.
+//! [`NodeArena::compact`] packs the live nodes into a vector sized for them
+//! and rebuilds the index; it moves storage without renumbering, so ids
+//! held by layout, style or script stay valid across it.
+
+use super::document::{Node, NodeId};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Fewest dead slots worth an automatic compaction.
+pub const MIN_COMPACTION_SLOTS: usize = 4096;
+
+/// Generations are process-wide, so ids of separate documents differ too.
+static NEXT_GENERATION: AtomicU32 = AtomicU32::new(1);
+
+struct Slot {
+    id: NodeId,
+    node: Arc<RwLock<Node>>,
+}
+
+/// What one [`NodeArena::compact`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub live_nodes: usize,
+    pub slots_before: usize,
+    pub slots_after: usize,
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+pub struct NodeArena {
+    slots: Vec<Option<Slot>>,
+    /// Vacant slots, reused before the vector grows.
+    free: Vec<u32>,
+    index: HashMap<NodeId, u32>,
+    generation: u32,
+    next_serial: u32,
+}
+
+impl Default for NodeArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NodeArena {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+            next_serial: 0,
+        }
+    }
+
+    /// An id no node of any arena has had.
+    pub fn allocate_id(&mut self) -> NodeId {
+        if self.next_serial == u32::MAX {
+            self.generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+            self.next_serial = 0;
+        }
+        self.next_serial += 1;
+        NodeId(((self.generation as u64) << 32) | self.next_serial as u64)
+    }
+
+    /// Store `node` under `id`, in a freed slot when there is one.
+    pub fn insert(&mut self, id: NodeId, node: Arc<RwLock<Node>>) {
+        if let Some(&slot) = self.index.get(&id) {
+            self.slots[slot as usize] = Some(Slot { id, node });
+            return;
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot as usize] = Some(Slot { id, node });
+                slot
+            }
+            None => {
+                self.slots.push(Some(Slot { id, node }));
+                (self.slots.len() - 1) as u32
+            }
+        };
+        self.index.insert(id, slot);
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Arc<RwLock<Node>>> {
+        let slot = *self.index.get(&id)?;
+        self.slots[slot as usize].as_ref().map(|slot| &slot.node)
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn remove(&mut self, id: NodeId) -> Option<Arc<RwLock<Node>>> {
+        let slot = self.index.remove(&id)?;
+        self.free.push(slot);
+        self.slots[slot as usize].take().map(|slot| slot.node)
+    }
+
+    /// Live nodes.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Slots freed and not yet reused.
+    pub fn dead_slots(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Slots the arena has room for without growing.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Live nodes in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Arc<RwLock<Node>>)> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|slot| (slot.id, &slot.node))
+    }
+
+    /// Drop every node and start a new generation, so ids of the old nodes
+    /// resolve to nothing.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// More than `ratio` dead slots per live node, and enough of them to be
+    /// worth packing.
+    pub fn is_sparse(&self, ratio: f64) -> bool {
+        self.free.len() >= MIN_COMPACTION_SLOTS
+            && self.free.len() as f64 > self.len() as f64 * ratio
+    }
+
+    /// Pack the live nodes into storage sized for them, keeping their ids.
+    pub fn compact(&mut self) -> CompactionReport {
+        let slots_before = self.slots.len();
+        let bytes_before = self.bytes();
+        let mut slots = Vec::with_capacity(self.len());
+        let mut index = HashMap::with_capacity(self.len());
+        for slot in self.slots.drain(..).flatten() {
+            index.insert(slot.id, slots.len() as u32);
+            slots.push(Some(slot));
+        }
+        self.slots = slots;
+        self.index = index;
+        self.free = Vec::new();
+        CompactionReport {
+            live_nodes: self.len(),
+            slots_before,
+            slots_after: self.slots.len(),
+            bytes_before,
+            bytes_after: self.bytes(),
+        }
+    }
+
+    /// Bytes held by the slots, free list and index, whether in use or not;
+    /// the nodes themselves are not included.
+    pub fn bytes(&self) -> usize {
+        // hashbrown keeps one control byte per bucket beside each entry.
+        let index_entry = std::mem::size_of::<(NodeId, u32)>() + 1;
+        self.slots.capacity() * std::mem::size_of::<Option<Slot>>()
+            + self.free.capacity() * std::mem::size_of::<u32>()
+            + self.index.capacity() * index_entry
+    }
+}
@@ -0,0 +1,260 @@
+//! Synchronous attribute change hooks for engine subsystems.
+//!
+//! A subsystem registers the `(tag, attribute)` pairs it cares about and is
+//! called with every change to them, whether script, the engine or the
+//! parser made it. Changes are delivered in the order they were made, and
+//! for each change the interested observers are called in the order they
+//! registered. An observer that changes attributes itself doesn't recurse:
+//! its changes are queued and delivered once the current one has reached
+//! every observer. Changes made on another thread while a delivery is under
+//! way join the same queue.
+//!
+//! `MutationObserver`s see attribute changes through the same hook, so there
+//! is one path every attribute change takes.
+
+use parking_lot::{Mutex, RwLock};
+use smallvec::SmallVec;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::document::{Document, Node, NodeId};
+
+pub type AttributeCallback = dyn Fn(&Document, &AttributeChange) + Send + Sync;
+
+/// One attribute of one element changing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeChange {
+    pub node: NodeId,
+    pub tag_name: String,
+    pub name: String,
+    /// `None` when the attribute was added.
+    pub old_value: Option<String>,
+    /// `None` when the attribute was removed.
+    pub new_value: Option<String>,
+    /// Set by the parser as it built the element rather than by a mutation
+    /// of the tree.
+    pub parsed: bool,
+}
+
+/// Which attribute changes an observer is called for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AttributeInterest {
+    /// Elements with this tag name; any element when `None`.
+    pub tag: Option<String>,
+    /// These attribute names; every attribute when `None`.
+    pub attributes: Option<Vec<String>>,
+}
+
+impl AttributeInterest {
+    /// `attribute` on any element.
+    pub fn attribute(attribute: &str) -> Self {
+        Self {
+            tag: None,
+            attributes: Some(vec![attribute.to_string()]),
+        }
+    }
+
+    /// Narrow the interest to elements named `tag`.
+    pub fn on(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AttributeObserverId(u64);
+
+struct Observer {
+    tag: Option<String>,
+    callback: Arc<AttributeCallback>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Ids are handed out in increasing order, so this is registration order.
+    observers: BTreeMap<AttributeObserverId, Observer>,
+    /// Observers of named attributes, by lowercase name.
+    by_name: HashMap<String, Vec<AttributeObserverId>>,
+    /// Observers of every attribute.
+    any: Vec<AttributeObserverId>,
+}
+
+#[derive(Default)]
+struct Delivery {
+    queue: VecDeque<AttributeChange>,
+    delivering: bool,
+}
+
+/// The attribute observers of one document.
+#[derive(Default)]
+pub struct AttributeObservers {
+    registry: RwLock<Registry>,
+    delivery: Mutex<Delivery>,
+    next_id: AtomicU64,
+}
+
+impl AttributeObservers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(
+        &self,
+        interest: AttributeInterest,
+        callback: Arc<AttributeCallback>,
+    ) -> AttributeObserverId {
+        let id = AttributeObserverId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut registry = self.registry.write();
+        match interest.attributes {
+            Some(names) => {
+                for name in names {
+                    let ids = registry
+                        .by_name
+                        .entry(name.to_ascii_lowercase())
+                        .or_default();
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+            }
+            None => registry.any.push(id),
+        }
+        registry.observers.insert(
+            id,
+            Observer {
+                tag: interest.tag,
+                callback,
+            },
+        );
+        id
+    }
+
+    pub fn unregister(&self, id: AttributeObserverId) {
+        let mut registry = self.registry.write();
+        if registry.observers.remove(&id).is_none() {
+            return;
+        }
+        registry.any.retain(|&other| other != id);
+        registry.by_name.retain(|_, ids| {
+            ids.retain(|&other| other != id);
+            !ids.is_empty()
+        });
+    }
+
+    pub fn clear(&self) {
+        *self.registry.write() = Registry::default();
+        self.delivery.lock().queue.clear();
+    }
+
+    /// The change to `name` on `node`, if anyone is observing it. Cheap when
+    /// no one is: one hash lookup and no allocation.
+    pub(super) fn change(
+        &self,
+        node: &Node,
+        name: &str,
+        old_value: Option<&str>,
+        new_value: Option<&str>,
+        parsed: bool,
+    ) -> Option<AttributeChange> {
+        if !self.observes(&node.tag_name, name) {
+            return None;
+        }
+        Some(AttributeChange {
+            node: node.id,
+            tag_name: node.tag_name.clone(),
+            name: name.to_string(),
+            old_value: old_value.map(str::to_string),
+            new_value: new_value.map(str::to_string),
+            parsed,
+        })
+    }
+
+    /// Call the observers of `change`, or queue it behind the delivery
+    /// already under way.
+    pub(super) fn deliver(&self, document: &Document, change: AttributeChange) {
+        {
+            let mut delivery = self.delivery.lock();
+            delivery.queue.push_back(change);
+            if delivery.delivering {
+                return;
+            }
+            delivery.delivering = true;
+        }
+        let _guard = DeliveryGuard(&self.delivery);
+        loop {
+            let change = {
+                let mut delivery = self.delivery.lock();
+                match delivery.queue.pop_front() {
+                    Some(change) => change,
+                    None => {
+                        delivery.delivering = false;
+                        return;
+                    }
+                }
+            };
+            // Observers may register and unregister from their callbacks.
+            for callback in self.callbacks(&change.tag_name, &change.name) {
+                callback(document, &change);
+            }
+        }
+    }
+
+    fn observes(&self, tag_name: &str, name: &str) -> bool {
+        let registry = self.registry.read();
+        if registry.observers.is_empty() {
+            return false;
+        }
+        let mut matching = registry.matching(tag_name, name);
+        matching.next().is_some()
+    }
+
+    fn callbacks(&self, tag_name: &str, name: &str) -> SmallVec<[Arc<AttributeCallback>; 4]> {
+        let registry = self.registry.read();
+        let mut matching: SmallVec<[(AttributeObserverId, Arc<AttributeCallback>); 4]> = registry
+            .matching(tag_name, name)
+            .map(|(id, observer)| (id, observer.callback.clone()))
+            .collect();
+        matching.sort_unstable_by_key(|(id, _)| *id);
+        matching.into_iter().map(|(_, callback)| callback).collect()
+    }
+}
+
+impl Registry {
+    fn matching<'a>(
+        &'a self,
+        tag_name: &'a str,
+        name: &str,
+    ) -> impl Iterator<Item = (AttributeObserverId, &'a Observer)> + 'a {
+        let named = if name.bytes().any(|b| b.is_ascii_uppercase()) {
+            self.by_name.get(&name.to_ascii_lowercase())
+        } else {
+            self.by_name.get(name)
+        };
+        named
+            .into_iter()
+            .flatten()
+            .chain(&self.any)
+            .filter_map(move |id| {
+                let observer = self.observers.get(id)?;
+                let tag_matches = observer
+                    .tag
+                    .as_deref()
+                    .map_or(true, |tag| tag.eq_ignore_ascii_case(tag_name));
+                tag_matches.then_some((*id, observer))
+            })
+    }
+}
+
+/// Ends a delivery an observer panicked out of, dropping what it queued.
+struct DeliveryGuard<'a>(&'a Mutex<Delivery>);
+
+impl Drop for DeliveryGuard<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let mut delivery = self.0.lock();
+            delivery.delivering = false;
+            delivery.queue.clear();
+        }
+    }
+}
@@ -1,11 +1,24 @@
 use dashmap::DashMap;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use thiserror::Error;
 
+use super::arena::{CompactionReport, NodeArena};
+use super::attribute_observers::{
+    AttributeChange, AttributeInterest, AttributeObserverId, AttributeObservers,
+};
+use super::element_index::ElementIndex;
+use super::parser::HTMLParser;
+use super::serialize::{self, DomSource, MarkupFormat, SerializeOptions};
+use super::DOMRange;
+use crate::core::content_limits::{ContentLimits, LimitBreach};
+use crate::core::css::selector::{Selector, SelectorMatcher};
+use crate::core::css::CSSStyleDeclaration;
+
 #[derive(Error, Debug)]
 pub enum DocumentError {
     #[error("Parse error: {0}")]
@@ -22,7 +35,7 @@ pub enum DocumentError {
 
 pub type Result<T> = std::result::Result<T, DocumentError>;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub struct NodeId(pub u64);
 
 impl Default for NodeId {
@@ -58,6 +71,10 @@ pub struct DocumentMetadata {
     pub content_type: String,
     pub last_modified: Option<std::time::SystemTime>,
     pub ready_state: DocumentReadyState,
+    /// Language of content no `lang` attribute covers, from the
+    /// `Content-Language` header.
+    #[serde(default)]
+    pub content_language: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -76,6 +93,7 @@ impl Default for DocumentMetadata {
             content_type: "text/html".to_string(),
             last_modified: None,
             ready_state: DocumentReadyState::Loading,
+            content_language: None,
         }
     }
 }
@@ -111,6 +129,11 @@ pub struct Node {
     pub parent: Option<NodeId>,
     pub children: SmallVec<[NodeId; 8]>,
     pub namespace_uri: Option<String>,
+    /// Parsed `style` attribute, built lazily and kept in sync with the attribute.
+    pub inline_style: Option<Arc<CSSStyleDeclaration>>,
+    /// Set when an attribute, the inline style or (for elements) the text
+    /// inside changes; cleared by the style engine.
+    pub style_dirty: bool,
 }
 
 impl Node {
@@ -124,6 +147,8 @@ impl Node {
             parent: None,
             children: SmallVec::new(),
             namespace_uri: None,
+            inline_style: None,
+            style_dirty: false,
         }
     }
 
@@ -137,6 +162,8 @@ impl Node {
             parent: None,
             children: SmallVec::new(),
             namespace_uri: None,
+            inline_style: None,
+            style_dirty: false,
         }
     }
 
@@ -150,6 +177,8 @@ impl Node {
             parent: None,
             children: SmallVec::new(),
             namespace_uri: None,
+            inline_style: None,
+            style_dirty: false,
         }
     }
 
@@ -163,6 +192,8 @@ impl Node {
             parent: None,
             children: SmallVec::new(),
             namespace_uri: None,
+            inline_style: None,
+            style_dirty: false,
         }
     }
 
@@ -176,13 +207,62 @@ impl Node {
             parent: None,
             children: SmallVec::new(),
             namespace_uri: None,
+            inline_style: None,
+            style_dirty: false,
         }
     }
 
     pub fn set_attribute(&mut self, name: &str, value: &str) {
+        if name.eq_ignore_ascii_case("style") {
+            // Re-parse lazily from the new attribute text on next access.
+            self.inline_style = None;
+        }
+        // Selectors can match on any attribute, and `:valid`/`:invalid` on
+        // those of form controls.
+        self.style_dirty = true;
         self.attributes.insert(name.to_string(), value.to_string());
     }
 
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        let removed = self.attributes.remove(name)?;
+        if name.eq_ignore_ascii_case("style") {
+            self.inline_style = None;
+        }
+        self.style_dirty = true;
+        Some(removed)
+    }
+
+    /// The inline style declaration, parsed from the `style` attribute on first use.
+    /// Returns `None` for non-elements and for elements without a `style` attribute,
+    /// unless `create` is set.
+    pub fn inline_style(&mut self, create: bool) -> Option<Arc<CSSStyleDeclaration>> {
+        if self.node_type != NodeType::Element {
+            return None;
+        }
+        if self.inline_style.is_none() {
+            let css_text = self.attributes.get("style");
+            if css_text.is_none() && !create {
+                return None;
+            }
+            let declaration = CSSStyleDeclaration::new();
+            if let Some(css_text) = css_text {
+                // Invalid declarations are dropped, as browsers do for inline styles.
+                let _ = declaration.set_css_text(css_text);
+            }
+            self.inline_style = Some(Arc::new(declaration));
+        }
+        self.inline_style.clone()
+    }
+
+    /// Write the declaration's serialization back into the `style` attribute.
+    fn sync_style_attribute(&mut self) {
+        if let Some(declaration) = &self.inline_style {
+            self.attributes
+                .insert("style".to_string(), declaration.get_css_text());
+        }
+        self.style_dirty = true;
+    }
+
     pub fn get_attribute(&self, name: &str) -> Option<String> {
         self.attributes.get(name).cloned()
     }
@@ -207,18 +287,22 @@ impl Node {
         self.parent = None;
         self.children.clear();
         self.namespace_uri = None;
+        self.inline_style = None;
+        self.style_dirty = false;
     }
 
     pub fn is_text(&self) -> bool {
         self.node_type == NodeType::Text
     }
+
+    pub fn is_element(&self) -> bool {
+        self.node_type == NodeType::Element
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct QueryCache {
     selector_cache: Arc<DashMap<String, Vec<NodeId>>>,
-    id_cache: Arc<DashMap<String, NodeId>>,
-    class_cache: Arc<DashMap<String, Vec<NodeId>>>,
     tag_cache: Arc<DashMap<String, Vec<NodeId>>>,
     cache_version: Arc<RwLock<u64>>,
 }
@@ -233,8 +317,6 @@ impl QueryCache {
     pub fn new() -> Self {
         Self {
             selector_cache: Arc::new(DashMap::new()),
-            id_cache: Arc::new(DashMap::new()),
-            class_cache: Arc::new(DashMap::new()),
             tag_cache: Arc::new(DashMap::new()),
             cache_version: Arc::new(RwLock::new(0)),
         }
@@ -244,8 +326,6 @@ impl QueryCache {
         let mut version = self.cache_version.write();
         *version += 1;
         self.selector_cache.clear();
-        self.id_cache.clear();
-        self.class_cache.clear();
         self.tag_cache.clear();
     }
 
@@ -261,22 +341,6 @@ impl QueryCache {
         self.selector_cache.insert(selector.to_string(), result);
     }
 
-    pub fn get_by_id(&self, id: &str) -> Option<NodeId> {
-        self.id_cache.get(id).map(|entry| *entry.value())
-    }
-
-    pub fn cache_id(&self, id: &str, node_id: NodeId) {
-        self.id_cache.insert(id.to_string(), node_id);
-    }
-
-    pub fn get_by_class(&self, class_name: &str) -> Option<Vec<NodeId>> {
-        self.class_cache.get(class_name).map(|entry| entry.clone())
-    }
-
-    pub fn cache_class(&self, class_name: &str, node_ids: Vec<NodeId>) {
-        self.class_cache.insert(class_name.to_string(), node_ids);
-    }
-
     pub fn get_by_tag(&self, tag_name: &str) -> Option<Vec<NodeId>> {
         self.tag_cache.get(tag_name).map(|entry| entry.clone())
     }
@@ -292,6 +356,9 @@ impl QueryCache {
 
 #[derive(Clone)]
 pub struct InlineScript {
+    /// The `src` attribute as written. An external script's text comes from
+    /// there, so `content` is ignored.
+    pub src: Option<String>,
     pub content: String,
     pub script_type: String,
     pub async_loading: bool,
@@ -300,8 +367,52 @@ pub struct InlineScript {
     pub nonce: Option<String>,
 }
 
+/// The `type` values of a classic script, besides none or an empty one.
+const JAVASCRIPT_MIME_TYPES: &[&str] = &[
+    "application/ecmascript",
+    "application/javascript",
+    "application/x-ecmascript",
+    "application/x-javascript",
+    "text/ecmascript",
+    "text/javascript",
+    "text/javascript1.0",
+    "text/javascript1.1",
+    "text/javascript1.2",
+    "text/javascript1.3",
+    "text/javascript1.4",
+    "text/javascript1.5",
+    "text/jscript",
+    "text/livescript",
+    "text/x-ecmascript",
+    "text/x-javascript",
+];
+
+impl InlineScript {
+    /// Whether this is a classic script, the only kind run. Modules are
+    /// not supported yet, and other types are data blocks.
+    pub fn is_classic(&self) -> bool {
+        let essence = self.script_type.split(';').next().unwrap_or("").trim();
+        essence.is_empty()
+            || JAVASCRIPT_MIME_TYPES
+                .iter()
+                .any(|mime| essence.eq_ignore_ascii_case(mime))
+    }
+}
+
+/// A script element as the parser reached it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserScript {
+    pub node: NodeId,
+    /// How many `document.write()`s deep its markup was written; 0 for the
+    /// document's own scripts.
+    pub write_depth: usize,
+}
+
 type MutationCallback = dyn Fn(&[MutationRecord]) + Send + Sync;
 
+/// Markup a tree was parsed from, and the limits it was parsed within.
+type ParsedSource = (Arc<str>, ContentLimits);
+
 #[derive(Clone)]
 pub struct MutationObserver {
     pub callback: Arc<MutationCallback>,
@@ -324,13 +435,127 @@ impl std::fmt::Debug for MutationObserver {
     }
 }
 
+fn attribute_record(target: NodeId, name: &str, old_value: Option<String>) -> MutationRecord {
+    MutationRecord {
+        mutation_type: MutationType::Attributes,
+        target,
+        added_nodes: Vec::new(),
+        removed_nodes: Vec::new(),
+        previous_sibling: None,
+        next_sibling: None,
+        attribute_name: Some(name.to_string()),
+        attribute_namespace: None,
+        old_value,
+        timestamp: std::time::Instant::now(),
+    }
+}
+
+/// Byte index of the char `chars` into `text`, or its end.
+fn char_to_byte(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(index, _)| index)
+}
+
+/// Script wrapper bookkeeping, as reported by [`Document::wrapper_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WrapperStats {
+    /// Wrappers handed to script and not yet collected.
+    pub live_wrappers: u64,
+    /// Nodes out of the tree that are kept because script still holds them.
+    pub detached_referenced: u64,
+    /// Nodes freed by [`Document::reclaim_detached`] since the last teardown.
+    pub reclaimed: u64,
+}
+
+/// What one [`Document::reclaim_detached`] pass did.
+#[derive(Debug, Clone, Default)]
+pub struct ReclaimReport {
+    pub released_wrappers: usize,
+    pub reclaimed: Vec<NodeId>,
+    /// Set when freeing left the arena sparse enough to compact it.
+    pub compaction: Option<CompactionReport>,
+}
+
+/// A range the document keeps up to date as the tree changes; see
+/// [`Document::track_range`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LiveRangeId(u64);
+
+/// Unique across documents, so an id kept past a teardown never names a
+/// range of the next document.
+static NEXT_LIVE_RANGE: AtomicU64 = AtomicU64::new(1);
+
+/// Dead arena slots per live node past which a reclaim compacts.
+pub const DEFAULT_COMPACTION_RATIO: f64 = 1.0;
+
+/// Memory held by a document, as reported by [`Document::memory_usage`].
+/// String sizes are capacities, so they count what is allocated.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DocumentMemory {
+    /// Slots, free list and id index of the node arena, used or not.
+    pub arena_bytes: usize,
+    /// The live nodes' own structs.
+    pub node_bytes: usize,
+    /// Attribute names and values.
+    pub attribute_bytes: usize,
+    /// Text and comment data and tag names.
+    pub text_bytes: usize,
+    pub live_nodes: usize,
+    pub dead_slots: usize,
+    /// Text nodes created since the document was built.
+    pub text_nodes_created: u64,
+    /// Text that joined a text node already there rather than getting a
+    /// node of its own, or a node merged into its neighbour by
+    /// [`Document::normalize`].
+    pub text_nodes_coalesced: u64,
+}
+
+impl DocumentMemory {
+    pub fn total_bytes(&self) -> usize {
+        self.arena_bytes + self.node_bytes + self.attribute_bytes + self.text_bytes
+    }
+}
+
+/// Cloning a `Document` is cheap and yields another handle to the same tree.
+///
+/// Nodes wrapped for script follow this contract: a wrapper keeps its node
+/// (and the subtree around it) alive, the wrapper itself is held weakly by
+/// the binding, and a node that is both out of the tree and unwrapped is
+/// freed by the next [`Document::reclaim_detached`]. Wrapper collection only
+/// queues the node on a free list, so it is safe to report from a GC callback.
+#[derive(Clone)]
 pub struct Document {
     metadata: Arc<RwLock<DocumentMetadata>>,
     root_node: Arc<RwLock<Option<NodeId>>>,
-    nodes: Arc<DashMap<NodeId, Arc<RwLock<Node>>>>,
+    nodes: Arc<RwLock<NodeArena>>,
+    /// Dead slots per live node past which freeing nodes compacts the
+    /// arena; `None` leaves compaction to [`Document::compact`].
+    compaction_ratio: Arc<RwLock<Option<f64>>>,
+    last_compaction: Arc<Mutex<Option<CompactionReport>>>,
     query_cache: Arc<QueryCache>,
+    /// Connected elements by `id` and `class`.
+    element_index: Arc<RwLock<ElementIndex>>,
     mutation_observers: Arc<RwLock<Vec<MutationObserver>>>,
     mutation_records: Arc<RwLock<Vec<MutationRecord>>>,
+    attribute_observers: Arc<AttributeObservers>,
+    wrapper_counts: Arc<DashMap<NodeId, u32>>,
+    released_wrappers: Arc<Mutex<Vec<NodeId>>>,
+    detached_roots: Arc<Mutex<HashSet<NodeId>>>,
+    reclaimed_count: Arc<AtomicU64>,
+    /// The markup the tree was parsed from and the limits it was parsed
+    /// within, for serializing it as parsed.
+    parsed_source: Arc<RwLock<Option<ParsedSource>>>,
+    /// The parser, kept after parsing until the document's scripts have
+    /// run, since their writes resume it.
+    parser: Arc<Mutex<Option<HTMLParser>>>,
+    /// `setCustomValidity()` messages of form controls; never empty.
+    custom_validity: Arc<DashMap<NodeId, String>>,
+    live_ranges: Arc<Mutex<HashMap<LiveRangeId, DOMRange>>>,
+    text_nodes_created: Arc<AtomicU64>,
+    text_nodes_coalesced: Arc<AtomicU64>,
+    /// Nodes marked for restyle since they were last styled.
+    style_dirty_nodes: Arc<Mutex<HashSet<NodeId>>>,
 }
 
 impl Default for Document {
@@ -344,10 +569,25 @@ impl Document {
         Self {
             metadata: Arc::new(RwLock::new(DocumentMetadata::default())),
             root_node: Arc::new(RwLock::new(None)),
-            nodes: Arc::new(DashMap::new()),
+            nodes: Arc::new(RwLock::new(NodeArena::new())),
+            compaction_ratio: Arc::new(RwLock::new(Some(DEFAULT_COMPACTION_RATIO))),
+            last_compaction: Arc::new(Mutex::new(None)),
             query_cache: Arc::new(QueryCache::new()),
+            element_index: Arc::new(RwLock::new(ElementIndex::default())),
             mutation_observers: Arc::new(RwLock::new(Vec::new())),
             mutation_records: Arc::new(RwLock::new(Vec::new())),
+            attribute_observers: Arc::new(AttributeObservers::new()),
+            wrapper_counts: Arc::new(DashMap::new()),
+            released_wrappers: Arc::new(Mutex::new(Vec::new())),
+            detached_roots: Arc::new(Mutex::new(HashSet::new())),
+            reclaimed_count: Arc::new(AtomicU64::new(0)),
+            parsed_source: Arc::new(RwLock::new(None)),
+            parser: Arc::new(Mutex::new(None)),
+            custom_validity: Arc::new(DashMap::new()),
+            live_ranges: Arc::new(Mutex::new(HashMap::new())),
+            text_nodes_created: Arc::new(AtomicU64::new(0)),
+            text_nodes_coalesced: Arc::new(AtomicU64::new(0)),
+            style_dirty_nodes: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
@@ -355,20 +595,142 @@ impl Document {
         *self.root_node.read()
     }
 
+    /// Point the document at a tree built outside the HTML parser.
+    pub fn set_root_node(&self, node_id: NodeId) {
+        self.query_cache.invalidate();
+        self.element_index.write().clear();
+        *self.root_node.write() = Some(node_id);
+        self.index_subtree(node_id, true);
+        *self.parsed_source.write() = None;
+        *self.parser.lock() = None;
+    }
+
+    /// The root's first element child, `<html>` in a well-formed document.
+    pub fn document_element(&self) -> Option<NodeId> {
+        let root = self.get_root_node()?;
+        self.get_children(root).into_iter().find(|&child| {
+            self.get_node(child)
+                .is_some_and(|node| node.read().node_type == NodeType::Element)
+        })
+    }
+
+    /// The whole document as HTML, reflecting every change made since it
+    /// was parsed.
+    pub fn serialize(&self) -> String {
+        self.serialize_current(MarkupFormat::Html)
+    }
+
+    /// The whole document in `options.format`, either as it is now or as the
+    /// parser built it. A tree not built by [`Self::parse_html`] has no
+    /// parsed state and serializes as it is now.
+    pub fn serialize_with(&self, options: SerializeOptions) -> Result<String> {
+        let source = self.parsed_source.read().clone();
+        match (options.source, source) {
+            (DomSource::AsParsed, Some((source, limits))) => {
+                let parsed = Self::new();
+                parsed.parse_html_with_limits(&source, limits)?;
+                Ok(parsed.serialize_current(options.format))
+            }
+            _ => Ok(self.serialize_current(options.format)),
+        }
+    }
+
+    fn serialize_current(&self, format: MarkupFormat) -> String {
+        let mut out = String::with_capacity(self.node_count() * serialize::BYTES_PER_NODE);
+        if let Some(root) = self.get_root_node() {
+            serialize::write_children(self, root, format, &mut out);
+        }
+        out
+    }
+
+    /// `node_id` and its subtree as HTML, the `outerHTML` of an element.
+    pub fn outer_html(&self, node_id: NodeId) -> Result<String> {
+        self.node_or_err(node_id)?;
+        let mut out = String::new();
+        serialize::write_node(self, node_id, MarkupFormat::Html, &mut out);
+        Ok(out)
+    }
+
+    /// The children of `node_id` as HTML, the `innerHTML` of an element.
+    pub fn inner_html(&self, node_id: NodeId) -> Result<String> {
+        self.node_or_err(node_id)?;
+        let mut out = String::new();
+        serialize::write_children(self, node_id, MarkupFormat::Html, &mut out);
+        Ok(out)
+    }
+
+    /// `node_id` and its subtree as XML, as `XMLSerializer` writes it.
+    pub fn serialize_xml(&self, node_id: NodeId) -> Result<String> {
+        self.node_or_err(node_id)?;
+        let mut out = String::new();
+        serialize::write_node(self, node_id, MarkupFormat::Xml, &mut out);
+        Ok(out)
+    }
+
     pub fn parse_html(&self, html: &str) -> Result<()> {
+        self.parse_html_with_limits(html, ContentLimits::default())?;
+        Ok(())
+    }
+
+    /// Parse `html` within `limits`, returning the ones it went past. The
+    /// excess is flattened, cut short or dropped; the parse still succeeds.
+    pub fn parse_html_with_limits(
+        &self,
+        html: &str,
+        limits: ContentLimits,
+    ) -> Result<Vec<LimitBreach>> {
         let parse_start = std::time::Instant::now();
         self.query_cache.invalidate();
+        self.element_index.write().clear();
         let document_node_id = self.create_node(NodeType::Document, "".to_string())?;
         *self.root_node.write() = Some(document_node_id);
-        let parser = HTMLParser::new();
-        parser.parse(html, document_node_id, self)?;
+        let mut parser = HTMLParser::new(limits);
+        let breaches = parser.parse(html, document_node_id, self)?;
+        *self.parsed_source.write() = Some((Arc::from(html), limits));
+        *self.parser.lock() = Some(parser);
         {
             let mut metadata = self.metadata.write();
             metadata.ready_state = DocumentReadyState::Interactive;
         }
         let parse_time = parse_start.elapsed();
         tracing::debug!("HTML parsing completed in {:?}", parse_time);
-        Ok(())
+        Ok(breaches)
+    }
+
+    /// The script element the parser reached `index`th; `None` past the
+    /// last one, or once [`Self::finish_parsing`] was called.
+    pub fn parser_script(&self, index: usize) -> Option<ParserScript> {
+        let (node, write_depth) = self.parser.lock().as_ref()?.script(index)?;
+        Some(ParserScript { node, write_depth })
+    }
+
+    /// Insert `markup` into the parser's input right after the end tag of
+    /// its `index`th script, as `document.write()` from that script does,
+    /// and parse on from there. What the parser built past that point is
+    /// built again from the written markup and the rest of the input, so
+    /// an element the write leaves open takes in the markup after it.
+    /// Returns the limits the markup went past.
+    pub fn write_after_script(&self, index: usize, markup: &str) -> Result<Vec<LimitBreach>> {
+        let mut parser = self.parser.lock();
+        let Some(parser) = parser.as_mut() else {
+            return Err(DocumentError::InvalidOperation(
+                "the document is no longer being parsed".to_string(),
+            ));
+        };
+        let breaches = parser.write(index, markup, self)?;
+        self.query_cache.invalidate();
+        // Serializing as parsed includes what was written.
+        let mut parsed_source = self.parsed_source.write();
+        if let Some((source, _)) = parsed_source.as_mut() {
+            *source = Arc::from(parser.input());
+        }
+        Ok(breaches)
+    }
+
+    /// The document's scripts have run: later writes can no longer resume
+    /// the parser, so it is let go.
+    pub fn finish_parsing(&self) {
+        *self.parser.lock() = None;
     }
 
     pub fn parse(html: &str) -> Result<Self> {
@@ -378,7 +740,10 @@ impl Document {
     }
 
     pub fn create_node(&self, node_type: NodeType, content: String) -> Result<NodeId> {
-        let node_id = NodeId::new();
+        let node_id = self.nodes.write().allocate_id();
+        if node_type == NodeType::Text {
+            self.text_nodes_created.fetch_add(1, Ordering::Relaxed);
+        }
         let node = match node_type {
             NodeType::Element => Arc::new(RwLock::new(Node::new_element(content, node_id))),
             NodeType::Text => Arc::new(RwLock::new(Node::new_text(content, node_id))),
@@ -386,17 +751,47 @@ impl Document {
             NodeType::Document => Arc::new(RwLock::new(Node::new_document(node_id))),
             NodeType::DocumentType => Arc::new(RwLock::new(Node::new_doctype(content, node_id))),
         };
-        self.nodes.insert(node_id, node);
+        self.nodes.write().insert(node_id, node);
         Ok(node_id)
     }
 
     pub fn append_child(&self, parent_id: NodeId, child_id: NodeId) -> Result<()> {
-        if let Some(parent_node) = self.nodes.get(&parent_id) {
-            parent_node.write().children.push(child_id);
+        self.insert_before(parent_id, child_id, None)
+    }
+
+    /// Insert `child_id` into `parent_id` before its child `reference`, or
+    /// last when `reference` is `None` or not a child of `parent_id`.
+    pub fn insert_before(
+        &self,
+        parent_id: NodeId,
+        child_id: NodeId,
+        reference: Option<NodeId>,
+    ) -> Result<()> {
+        // Inserting a node that is already in the tree moves it.
+        if let Some(old_parent) = self.get_parent(child_id) {
+            self.remove_child(old_parent, child_id)?;
+        }
+        self.detached_roots.lock().remove(&child_id);
+        let connected = self.is_connected(parent_id);
+        if let Some(parent_node) = self.get_node(parent_id) {
+            let mut parent_node = parent_node.write();
+            let index = reference
+                .and_then(|reference| parent_node.children.iter().position(|id| *id == reference))
+                .unwrap_or(parent_node.children.len());
+            parent_node.children.insert(index, child_id);
+            drop(parent_node);
+            self.update_ranges(|container, offset| {
+                if *container == parent_id && *offset as usize > index {
+                    *offset += 1;
+                }
+            });
         }
-        if let Some(child_node) = self.nodes.get(&child_id) {
+        if let Some(child_node) = self.get_node(child_id) {
             child_node.write().parent = Some(parent_id);
         }
+        if connected {
+            self.index_subtree(child_id, true);
+        }
         let record = MutationRecord {
             mutation_type: MutationType::ChildList,
             target: parent_id,
@@ -411,16 +806,38 @@ impl Document {
         };
         self.record_mutation(record);
         self.query_cache.invalidate_partial(parent_id);
+        // The new child is styled with its parent, as are the siblings
+        // whose position changed.
+        self.mark_style_dirty(parent_id);
         Ok(())
     }
 
     pub fn remove_child(&self, parent_id: NodeId, child_id: NodeId) -> Result<()> {
-        if let Some(parent_node) = self.nodes.get(&parent_id) {
-            parent_node.write().children.retain(|id| *id != child_id);
-        }
-        if let Some(child_node) = self.nodes.get(&child_id) {
+        let connected = self.is_connected(parent_id);
+        let index = self.get_node(parent_id).and_then(|parent_node| {
+            let mut parent_node = parent_node.write();
+            let index = parent_node.children.iter().position(|id| *id == child_id)?;
+            parent_node.children.remove(index);
+            Some(index)
+        });
+        if let Some(child_node) = self.get_node(child_id) {
             child_node.write().parent = None;
         }
+        if connected && index.is_some() {
+            self.index_subtree(child_id, false);
+        }
+        // Boundary points inside the removed node move to where it was.
+        if let Some(index) = index {
+            self.update_ranges(|container, offset| {
+                if *container == parent_id && *offset as usize > index {
+                    *offset -= 1;
+                } else if self.is_inclusive_ancestor(child_id, *container) {
+                    *container = parent_id;
+                    *offset = index as u32;
+                }
+            });
+        }
+        self.detached_roots.lock().insert(child_id);
         let record = MutationRecord {
             mutation_type: MutationType::ChildList,
             target: parent_id,
@@ -435,41 +852,21 @@ impl Document {
         };
         self.record_mutation(record);
         self.query_cache.invalidate_partial(parent_id);
+        self.mark_style_dirty(parent_id);
         Ok(())
     }
 
+    /// The first connected element in document order whose `id` is `id`.
     pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
-        if let Some(cached) = self.query_cache.get_by_id(id) {
-            return Some(cached);
-        }
-        for entry in self.nodes.iter() {
-            let node = entry.value().read();
-            if let Some(attr) = node.get_attribute("id") {
-                if attr == id {
-                    let node_id = *entry.key();
-                    self.query_cache.cache_id(id, node_id);
-                    return Some(node_id);
-                }
-            }
-        }
-        None
+        let found = self.element_index.read().with_id(id);
+        self.in_document_order(found).first().copied()
     }
 
-    pub fn get_elements_by_class_name(&self, class_name: &str) -> Vec<NodeId> {
-        if let Some(cached) = self.query_cache.get_by_class(class_name) {
-            return cached;
-        }
-        let mut result = Vec::new();
-        for entry in self.nodes.iter() {
-            let node = entry.value().read();
-            if let Some(classes) = node.get_attribute("class") {
-                if classes.split_whitespace().any(|c| c == class_name) {
-                    result.push(*entry.key());
-                }
-            }
-        }
-        self.query_cache.cache_class(class_name, result.clone());
-        result
+    /// The connected elements that have every class of the
+    /// space-separated `class_names`, in document order.
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<NodeId> {
+        let found = self.element_index.read().with_classes(class_names);
+        self.in_document_order(found)
     }
 
     pub fn get_elements_by_tag_name(&self, tag_name: &str) -> Vec<NodeId> {
@@ -477,10 +874,10 @@ impl Document {
             return cached;
         }
         let mut result = Vec::new();
-        for entry in self.nodes.iter() {
-            let node = entry.value().read();
+        for (node_id, node) in self.live_nodes() {
+            let node = node.read();
             if node.get_tag_name().eq_ignore_ascii_case(tag_name) {
-                result.push(*entry.key());
+                result.push(node_id);
             }
         }
         self.query_cache.cache_tag(tag_name, result.clone());
@@ -502,59 +899,755 @@ impl Document {
         Ok(result)
     }
 
+    /// The first element under `scope` matching `selector`, as
+    /// `element.querySelector`.
+    pub fn query_selector_within(&self, scope: NodeId, selector: &str) -> Result<Option<NodeId>> {
+        let results = self.query_selector_all_within(scope, selector)?;
+        Ok(results.first().copied())
+    }
+
+    /// Elements under `scope`, not `scope` itself, matching `selector`, in
+    /// document order. The whole selector has to match, so `div span`
+    /// finds spans under `scope` inside any `div`, even one above `scope`.
+    pub fn query_selector_all_within(&self, scope: NodeId, selector: &str) -> Result<Vec<NodeId>> {
+        let selector = Self::parse_query(selector)?;
+        let candidates = self.subtree(scope).into_iter().skip(1);
+        Ok(self.matching_elements(&selector, candidates))
+    }
+
+    /// Elements matching `selector`, in document order. The selector is read
+    /// by the same parser as stylesheet rules, so what a rule can select a
+    /// query can too.
     fn execute_css_selector(&self, selector: &str) -> Result<Vec<NodeId>> {
-        let mut result = Vec::new();
-        if let Some(id_part) = selector.strip_prefix('#') {
-            if let Some(id) = self.get_element_by_id(id_part) {
-                result.push(id);
-            }
-        } else if let Some(class_part) = selector.strip_prefix('.') {
-            result = self.get_elements_by_class_name(class_part);
-        } else if !selector.contains(' ') && !selector.contains('.') && !selector.contains('#') {
-            result = self.get_elements_by_tag_name(selector);
-        } else {
-            result = self.complex_selector_matching(selector)?;
+        let selector = Self::parse_query(selector)?;
+        let tree = self
+            .get_root_node()
+            .map(|root| self.subtree(root))
+            .unwrap_or_default();
+        Ok(self.matching_elements(&selector, tree))
+    }
+
+    /// `selector` as a query reads it; an empty one is as invalid as a
+    /// malformed one.
+    fn parse_query(selector: &str) -> Result<Selector> {
+        let invalid = |reason: String| DocumentError::Query(format!("'{}': {}", selector, reason));
+        let parsed = Selector::parse(selector.trim()).map_err(|e| invalid(e.to_string()))?;
+        if parsed.complex_selectors.is_empty() {
+            return Err(invalid("empty selector".to_string()));
         }
-        Ok(result)
+        Ok(parsed)
     }
 
-    fn complex_selector_matching(&self, _selector: &str) -> Result<Vec<NodeId>> {
-        Ok(Vec::new())
+    fn matching_elements(
+        &self,
+        selector: &Selector,
+        candidates: impl IntoIterator<Item = NodeId>,
+    ) -> Vec<NodeId> {
+        let matcher = SelectorMatcher::new();
+        candidates
+            .into_iter()
+            .filter(|&node_id| {
+                self.get_node(node_id)
+                    .is_some_and(|node| node.read().node_type == NodeType::Element)
+                    && matcher.matches(selector, node_id, self)
+            })
+            .collect()
     }
 
     pub fn get_inline_scripts(&self) -> Vec<InlineScript> {
-        let mut scripts = Vec::new();
-        for node_id in self.get_elements_by_tag_name("script") {
-            if let Some(node_arc) = self.nodes.get(&node_id) {
-                let node = node_arc.read();
-                if node.get_attribute("src").is_some() {
-                    continue;
+        self.get_scripts()
+            .into_iter()
+            .filter(|script| script.src.is_none())
+            .collect()
+    }
+
+    /// Every script of the document with something to run, inline or
+    /// external, in document order.
+    pub fn get_scripts(&self) -> Vec<InlineScript> {
+        // Scripts run in document order, so walk the tree rather than the
+        // tag index.
+        self.get_root_node()
+            .map(|root| self.subtree(root))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|node_id| self.script(node_id))
+            .collect()
+    }
+
+    /// The script `node_id` is, if it is a script element with something
+    /// to run.
+    pub fn script(&self, node_id: NodeId) -> Option<InlineScript> {
+        let node_arc = self.get_node(node_id)?;
+        if !node_arc.read().tag_name.eq_ignore_ascii_case("script") {
+            return None;
+        }
+        // The parser puts script text in child text nodes.
+        let content: String = self
+            .get_children(node_id)
+            .into_iter()
+            .filter_map(|child| self.get_node(child))
+            .map(|child| child.read().get_text_content())
+            .collect();
+        let node = node_arc.read();
+        let src = node.get_attribute("src");
+        if src.is_none() && content.trim().is_empty() {
+            return None;
+        }
+        Some(InlineScript {
+            src,
+            content,
+            script_type: node
+                .get_attribute("type")
+                .unwrap_or_else(|| "text/javascript".to_string()),
+            async_loading: node.has_attribute("async"),
+            defer_execution: node.has_attribute("defer"),
+            integrity: node.get_attribute("integrity"),
+            nonce: node.get_attribute("nonce"),
+        })
+    }
+
+    /// Set an attribute and record the mutation. Setting `style` replaces the
+    /// inline declaration.
+    pub fn set_attribute(&self, node_id: NodeId, name: &str, value: &str) -> Result<()> {
+        let node = self.node_or_err(node_id)?;
+        let (old_value, change) = {
+            let mut node = node.write();
+            let old_value = node.get_attribute(name);
+            node.set_attribute(name, value);
+            let change = self.attribute_observers.change(
+                &node,
+                name,
+                old_value.as_deref(),
+                Some(value),
+                false,
+            );
+            (old_value, change)
+        };
+        self.reindex_attribute(node_id, name, old_value.as_deref(), Some(value));
+        self.attribute_changed(node_id, name, old_value, change);
+        Ok(())
+    }
+
+    /// Remove an attribute and record the mutation, returning its value.
+    /// Removing an attribute the element doesn't have changes nothing.
+    pub fn remove_attribute(&self, node_id: NodeId, name: &str) -> Result<Option<String>> {
+        let node = self.node_or_err(node_id)?;
+        let (old_value, change) = {
+            let mut node = node.write();
+            let Some(old_value) = node.remove_attribute(name) else {
+                return Ok(None);
+            };
+            let change =
+                self.attribute_observers
+                    .change(&node, name, Some(&old_value), None, false);
+            (old_value, change)
+        };
+        self.reindex_attribute(node_id, name, Some(&old_value), None);
+        self.attribute_changed(node_id, name, Some(old_value.clone()), change);
+        Ok(Some(old_value))
+    }
+
+    /// Give an element the parser has just created its attributes, then
+    /// deliver one change per observed attribute.
+    pub(super) fn set_parsed_attributes(&self, node_id: NodeId, attributes: &[(String, String)]) {
+        let Some(node) = self.get_node(node_id) else {
+            return;
+        };
+        let changes: Vec<AttributeChange> = {
+            let mut node = node.write();
+            for (name, value) in attributes {
+                node.set_attribute(name, value);
+            }
+            attributes
+                .iter()
+                .filter_map(|(name, value)| {
+                    self.attribute_observers
+                        .change(&node, name, None, Some(value), true)
+                })
+                .collect()
+        };
+        for (name, value) in attributes {
+            self.reindex_attribute(node_id, name, None, Some(value));
+        }
+        for change in changes {
+            self.attribute_observers.deliver(self, change);
+        }
+    }
+
+    /// Call `callback` for every change to the attributes `interest` names,
+    /// those the parser sets included, as it happens. See
+    /// [`attribute_observers`](super::attribute_observers) for the order
+    /// changes arrive in.
+    pub fn observe_attributes<F>(
+        &self,
+        interest: AttributeInterest,
+        callback: F,
+    ) -> AttributeObserverId
+    where
+        F: Fn(&Document, &AttributeChange) + Send + Sync + 'static,
+    {
+        self.attribute_observers
+            .register(interest, Arc::new(callback))
+    }
+
+    pub fn unobserve_attributes(&self, id: AttributeObserverId) {
+        self.attribute_observers.unregister(id);
+    }
+
+    /// Set a text or comment node's data, or replace an element's children
+    /// with one text node, recording the mutations.
+    pub fn set_text_content(&self, node_id: NodeId, text: &str) -> Result<()> {
+        let node = self.node_or_err(node_id)?;
+        let node_type = node.read().node_type;
+        match node_type {
+            NodeType::Text | NodeType::Comment => {
+                let length = node.read().text_content.chars().count();
+                self.replace_data(node_id, 0, length, text)
+            }
+            NodeType::Element => {
+                for child in self.get_children(node_id) {
+                    self.remove_child(node_id, child)?;
+                }
+                if !text.is_empty() {
+                    self.append_text(node_id, text)?;
                 }
-                let content = node.get_text_content();
-                if content.trim().is_empty() {
-                    continue;
+                self.mark_style_dirty(node_id);
+                Ok(())
+            }
+            _ => Err(DocumentError::InvalidOperation(
+                "Only elements, text and comments have settable text".to_string(),
+            )),
+        }
+    }
+
+    /// DOM "replace data": replace `count` chars of a text or comment
+    /// node's data from `offset` with `data`, moving the boundary points of
+    /// live ranges past the change and recording it. Offsets are in chars,
+    /// as the caret's are; `count` stops at the end of the data.
+    pub fn replace_data(
+        &self,
+        node_id: NodeId,
+        offset: usize,
+        count: usize,
+        data: &str,
+    ) -> Result<()> {
+        let node = self.node_or_err(node_id)?;
+        let (old_value, parent, count) = {
+            let mut node = node.write();
+            if !matches!(node.node_type, NodeType::Text | NodeType::Comment) {
+                return Err(DocumentError::InvalidOperation(
+                    "Only text and comments have character data".to_string(),
+                ));
+            }
+            let length = node.text_content.chars().count();
+            if offset > length {
+                return Err(DocumentError::InvalidOperation(format!(
+                    "Offset {} is past the end of data {} chars long",
+                    offset, length
+                )));
+            }
+            let count = count.min(length - offset);
+            let start = char_to_byte(&node.text_content, offset);
+            let end = char_to_byte(&node.text_content, offset + count);
+            let old_value = node.text_content.clone();
+            node.text_content.replace_range(start..end, data);
+            (old_value, node.parent, count)
+        };
+        // Text does not restyle, but its box changes size.
+        if let Some(parent) = parent {
+            self.mark_style_dirty(parent);
+        }
+        self.record_mutation(MutationRecord {
+            mutation_type: MutationType::CharacterData,
+            target: node_id,
+            added_nodes: Vec::new(),
+            removed_nodes: Vec::new(),
+            previous_sibling: None,
+            next_sibling: None,
+            attribute_name: None,
+            attribute_namespace: None,
+            old_value: Some(old_value),
+            timestamp: std::time::Instant::now(),
+        });
+        let inserted = data.chars().count();
+        self.update_ranges(|container, point| {
+            let at = *point as usize;
+            if *container != node_id || at <= offset {
+                return;
+            }
+            let moved = if at <= offset + count {
+                offset
+            } else {
+                at + inserted - count
+            };
+            *point = moved as u32;
+        });
+        Ok(())
+    }
+
+    pub fn insert_data(&self, node_id: NodeId, offset: usize, data: &str) -> Result<()> {
+        self.replace_data(node_id, offset, 0, data)
+    }
+
+    pub fn delete_data(&self, node_id: NodeId, offset: usize, count: usize) -> Result<()> {
+        self.replace_data(node_id, offset, count, "")
+    }
+
+    /// DOM `splitText()`: move the data of a text node from `offset` on
+    /// into a new text node right after it, with the boundary points of
+    /// live ranges in that part. Returns the new node.
+    pub fn split_text(&self, node_id: NodeId, offset: usize) -> Result<NodeId> {
+        let node = self.node_or_err(node_id)?;
+        let (after, parent) = {
+            let node = node.read();
+            if node.node_type != NodeType::Text {
+                return Err(DocumentError::InvalidOperation(
+                    "Only text nodes split".to_string(),
+                ));
+            }
+            let length = node.text_content.chars().count();
+            if offset > length {
+                return Err(DocumentError::InvalidOperation(format!(
+                    "Offset {} is past the end of text {} chars long",
+                    offset, length
+                )));
+            }
+            let split = char_to_byte(&node.text_content, offset);
+            (node.text_content[split..].to_string(), node.parent)
+        };
+        let count = after.chars().count();
+        let new_node = self.create_node(NodeType::Text, after)?;
+        if let Some(parent) = parent {
+            let index = self.index_in_parent(node_id).unwrap_or_default();
+            let next = self.get_children(parent).get(index + 1).copied();
+            self.insert_before(parent, new_node, next)?;
+            self.update_ranges(|container, point| {
+                if *container == node_id && *point as usize > offset {
+                    *container = new_node;
+                    *point -= offset as u32;
+                } else if *container == parent && *point as usize == index + 1 {
+                    *point += 1;
                 }
-                scripts.push(InlineScript {
-                    content,
-                    script_type: node
-                        .get_attribute("type")
-                        .unwrap_or_else(|| "text/javascript".to_string()),
-                    async_loading: node.has_attribute("async"),
-                    defer_execution: node.has_attribute("defer"),
-                    integrity: node.get_attribute("integrity"),
-                    nonce: node.get_attribute("nonce"),
+            });
+        }
+        self.replace_data(node_id, offset, count, "")?;
+        Ok(new_node)
+    }
+
+    /// Insert `text` into `parent_id` before its child `reference`, or
+    /// last, as part of the text node just before or after that point when
+    /// there is one, rather than in a new node beside it. Returns the node
+    /// holding the text. The records say what happened: a character data
+    /// change of the node joined, or the new node added.
+    pub fn insert_text(
+        &self,
+        parent_id: NodeId,
+        text: &str,
+        reference: Option<NodeId>,
+    ) -> Result<NodeId> {
+        self.node_or_err(parent_id)?;
+        let children = self.get_children(parent_id);
+        let index = reference
+            .and_then(|reference| children.iter().position(|id| *id == reference))
+            .unwrap_or(children.len());
+        let before = index.checked_sub(1).map(|index| children[index]);
+        if let Some(before) = before.filter(|&id| self.is_text_node(id)) {
+            self.insert_data(before, self.data_length(before), text)?;
+            self.text_nodes_coalesced.fetch_add(1, Ordering::Relaxed);
+            return Ok(before);
+        }
+        let after = children.get(index).copied();
+        if let Some(after) = after.filter(|&id| self.is_text_node(id)) {
+            self.insert_data(after, 0, text)?;
+            self.text_nodes_coalesced.fetch_add(1, Ordering::Relaxed);
+            return Ok(after);
+        }
+        let text_node = self.create_node(NodeType::Text, text.to_string())?;
+        self.insert_before(parent_id, text_node, reference)?;
+        Ok(text_node)
+    }
+
+    /// Append `text` to `parent_id`, extending its last child when that is
+    /// text; see [`Self::insert_text`].
+    pub fn append_text(&self, parent_id: NodeId, text: &str) -> Result<NodeId> {
+        self.insert_text(parent_id, text, None)
+    }
+
+    /// DOM `normalize()`: below `node_id`, drop empty text nodes and merge
+    /// each run of adjacent text nodes into its first. Live ranges keep
+    /// their place in the text: a boundary point in a merged node, or
+    /// between two merged nodes, moves into the node they merged into.
+    pub fn normalize(&self, node_id: NodeId) -> Result<()> {
+        self.node_or_err(node_id)?;
+        for id in self.subtree(node_id).into_iter().skip(1) {
+            // Nodes merged into an earlier one are out of the tree by now.
+            let Some(parent) = self.get_parent(id) else {
+                continue;
+            };
+            if !self.is_text_node(id) {
+                continue;
+            }
+            let mut length = self.data_length(id);
+            if length == 0 {
+                self.remove_child(parent, id)?;
+                continue;
+            }
+            let children = self.get_children(parent);
+            let index = self.index_in_parent(id).unwrap_or_default();
+            let siblings: Vec<(usize, NodeId)> = children
+                .iter()
+                .copied()
+                .enumerate()
+                .skip(index + 1)
+                .take_while(|&(_, sibling)| self.is_text_node(sibling))
+                .collect();
+            if siblings.is_empty() {
+                continue;
+            }
+            let data: String = siblings
+                .iter()
+                .filter_map(|&(_, sibling)| self.get_node(sibling))
+                .map(|sibling| sibling.read().text_content.clone())
+                .collect();
+            self.insert_data(id, length, &data)?;
+            for &(sibling_index, sibling) in &siblings {
+                self.update_ranges(|container, offset| {
+                    if *container == sibling {
+                        *container = id;
+                        *offset += length as u32;
+                    } else if *container == parent && *offset as usize == sibling_index {
+                        *container = id;
+                        *offset = length as u32;
+                    }
                 });
+                length += self.data_length(sibling);
+            }
+            for &(_, sibling) in &siblings {
+                self.remove_child(parent, sibling)?;
+            }
+            self.text_nodes_coalesced
+                .fetch_add(siblings.len() as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Keep `range` up to date as the tree changes, the way the DOM keeps
+    /// the ranges script holds and the selection, until it is untracked.
+    /// Offsets into text are in chars.
+    pub fn track_range(&self, range: DOMRange) -> LiveRangeId {
+        let id = LiveRangeId(NEXT_LIVE_RANGE.fetch_add(1, Ordering::Relaxed));
+        self.live_ranges.lock().insert(id, range);
+        id
+    }
+
+    /// The tracked range `id` as the tree has moved it.
+    pub fn live_range(&self, id: LiveRangeId) -> Option<DOMRange> {
+        self.live_ranges.lock().get(&id).cloned()
+    }
+
+    /// Replace the tracked range `id`; `false` when it is not tracked.
+    pub fn set_live_range(&self, id: LiveRangeId, range: DOMRange) -> bool {
+        match self.live_ranges.lock().get_mut(&id) {
+            Some(tracked) => {
+                *tracked = range;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn untrack_range(&self, id: LiveRangeId) {
+        self.live_ranges.lock().remove(&id);
+    }
+
+    /// Apply `f` to both boundary points of every tracked range.
+    fn update_ranges<F>(&self, mut f: F)
+    where
+        F: FnMut(&mut NodeId, &mut u32),
+    {
+        let mut ranges = self.live_ranges.lock();
+        for range in ranges.values_mut() {
+            range.update_boundaries(self, &mut f);
+        }
+    }
+
+    fn is_text_node(&self, node_id: NodeId) -> bool {
+        self.get_node(node_id)
+            .is_some_and(|node| node.read().node_type == NodeType::Text)
+    }
+
+    /// Length in chars of a text or comment node's data.
+    fn data_length(&self, node_id: NodeId) -> usize {
+        self.get_node(node_id)
+            .map_or(0, |node| node.read().text_content.chars().count())
+    }
+
+    fn index_in_parent(&self, node_id: NodeId) -> Option<usize> {
+        let parent = self.get_parent(node_id)?;
+        self.get_children(parent)
+            .iter()
+            .position(|id| *id == node_id)
+    }
+
+    /// Add the elements of `node_id`'s subtree to the id and class index,
+    /// or with `add` unset take them out.
+    fn index_subtree(&self, node_id: NodeId, add: bool) {
+        let subtree = self.subtree(node_id);
+        let mut index = self.element_index.write();
+        for id in subtree {
+            let Some(node) = self.get_node(id) else {
+                continue;
+            };
+            let node = node.read();
+            if node.node_type != NodeType::Element {
+                continue;
+            }
+            let element_id = node.get_attribute("id");
+            let class = node.get_attribute("class");
+            if add {
+                index.insert(id, element_id.as_deref(), class.as_deref());
+            } else {
+                index.remove(id, element_id.as_deref(), class.as_deref());
+            }
+        }
+    }
+
+    /// Move a connected element in the index after its `id` or `class`
+    /// went from `old` to `new`.
+    fn reindex_attribute(&self, node_id: NodeId, name: &str, old: Option<&str>, new: Option<&str>) {
+        let is_id = name.eq_ignore_ascii_case("id");
+        if !is_id && !name.eq_ignore_ascii_case("class") {
+            return;
+        }
+        let is_element = self
+            .get_node(node_id)
+            .is_some_and(|node| node.read().node_type == NodeType::Element);
+        if !is_element || !self.is_connected(node_id) {
+            return;
+        }
+        let mut index = self.element_index.write();
+        if is_id {
+            index.remove(node_id, old, None);
+            index.insert(node_id, new, None);
+        } else {
+            index.remove(node_id, None, old);
+            index.insert(node_id, None, new);
+        }
+    }
+
+    /// `nodes` sorted into document order: by the path of child indexes
+    /// from the root down to each, numbering the children of each parent on
+    /// the way once.
+    fn in_document_order(&self, mut nodes: Vec<NodeId>) -> Vec<NodeId> {
+        if nodes.len() < 2 {
+            return nodes;
+        }
+        let mut positions: HashMap<NodeId, usize> = HashMap::new();
+        let mut paths: HashMap<NodeId, Vec<usize>> = HashMap::with_capacity(nodes.len());
+        for &node_id in &nodes {
+            let mut path = Vec::new();
+            let mut current = node_id;
+            while let Some(parent) = self.get_parent(current) {
+                if !positions.contains_key(&current) {
+                    for (index, child) in self.get_children(parent).into_iter().enumerate() {
+                        positions.insert(child, index);
+                    }
+                }
+                path.push(positions.get(&current).copied().unwrap_or_default());
+                current = parent;
+            }
+            path.reverse();
+            paths.insert(node_id, path);
+        }
+        nodes.sort_by(|a, b| paths[a].cmp(&paths[b]));
+        nodes
+    }
+
+    /// Whether `node_id` is `ancestor` or inside it.
+    fn is_inclusive_ancestor(&self, ancestor: NodeId, node_id: NodeId) -> bool {
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
             }
+            current = self.get_parent(id);
+        }
+        false
+    }
+
+    /// Inline style of an element; created empty when `create` is set and the
+    /// element has no `style` attribute yet.
+    pub fn get_inline_style(
+        &self,
+        node_id: NodeId,
+        create: bool,
+    ) -> Option<Arc<CSSStyleDeclaration>> {
+        self.get_node(node_id)?.write().inline_style(create)
+    }
+
+    pub fn set_style_property(
+        &self,
+        node_id: NodeId,
+        property: &str,
+        value: &str,
+        priority: &str,
+    ) -> Result<()> {
+        self.update_inline_style(node_id, |declaration| {
+            if value.is_empty() {
+                let _ = declaration.remove_property(property);
+                Ok(())
+            } else {
+                declaration.set_property(property, value, priority)
+            }
+        })
+    }
+
+    /// Remove one inline property, returning its previous value (empty if unset).
+    pub fn remove_style_property(&self, node_id: NodeId, property: &str) -> Result<String> {
+        let mut removed = String::new();
+        self.update_inline_style(node_id, |declaration| {
+            removed = declaration.remove_property(property).unwrap_or_default();
+            Ok(())
+        })?;
+        Ok(removed)
+    }
+
+    pub fn set_style_css_text(&self, node_id: NodeId, css_text: &str) -> Result<()> {
+        self.update_inline_style(node_id, |declaration| declaration.set_css_text(css_text))
+    }
+
+    pub fn is_style_dirty(&self, node_id: NodeId) -> bool {
+        self.get_node(node_id)
+            .is_some_and(|node| node.read().style_dirty)
+    }
+
+    pub fn has_style_dirty_nodes(&self) -> bool {
+        !self.style_dirty_nodes().is_empty()
+    }
+
+    /// The connected nodes marked for restyle, in no particular order. A
+    /// node's subtree is styled again with it. Marks of nodes out of the
+    /// tree are dropped: inserting them marks their new parent.
+    pub fn style_dirty_nodes(&self) -> Vec<NodeId> {
+        let mut dirty = self.style_dirty_nodes.lock();
+        dirty.retain(|&node_id| self.is_connected(node_id));
+        dirty.iter().copied().collect()
+    }
+
+    pub fn mark_style_dirty(&self, node_id: NodeId) {
+        if let Some(node) = self.get_node(node_id) {
+            node.write().style_dirty = true;
+            self.style_dirty_nodes.lock().insert(node_id);
+        }
+    }
+
+    pub fn clear_style_dirty(&self, node_id: NodeId) {
+        if let Some(node) = self.get_node(node_id) {
+            node.write().style_dirty = false;
+        }
+        self.style_dirty_nodes.lock().remove(&node_id);
+    }
+
+    /// Set the custom validity message of a form control; an empty message
+    /// makes it valid again.
+    pub fn set_custom_validity(&self, node_id: NodeId, message: &str) -> Result<()> {
+        self.node_or_err(node_id)?;
+        let old = if message.is_empty() {
+            self.custom_validity.remove(&node_id).map(|(_, old)| old)
+        } else {
+            self.custom_validity.insert(node_id, message.to_string())
+        };
+        if old.as_deref().unwrap_or_default() != message {
+            self.mark_style_dirty(node_id);
+        }
+        Ok(())
+    }
+
+    pub fn custom_validity(&self, node_id: NodeId) -> Option<String> {
+        self.custom_validity
+            .get(&node_id)
+            .map(|message| message.clone())
+    }
+
+    fn update_inline_style<F>(&self, node_id: NodeId, f: F) -> Result<()>
+    where
+        F: FnOnce(&CSSStyleDeclaration) -> crate::core::css::Result<()>,
+    {
+        let node = self.node_or_err(node_id)?;
+        let (old_value, change) = {
+            let mut node = node.write();
+            let declaration = node.inline_style(true).ok_or_else(|| {
+                DocumentError::InvalidOperation("Only elements have inline styles".to_string())
+            })?;
+            let old_value = node.get_attribute("style");
+            f(&declaration).map_err(|e| DocumentError::InvalidOperation(e.to_string()))?;
+            node.sync_style_attribute();
+            let new_value = node.attributes.get("style").map(String::as_str);
+            let change = self.attribute_observers.change(
+                &node,
+                "style",
+                old_value.as_deref(),
+                new_value,
+                false,
+            );
+            (old_value, change)
+        };
+        self.attribute_changed(node_id, "style", old_value, change);
+        Ok(())
+    }
+
+    fn node_or_err(&self, node_id: NodeId) -> Result<Arc<RwLock<Node>>> {
+        self.get_node(node_id)
+            .ok_or_else(|| DocumentError::NodeNotFound(format!("{:?}", node_id)))
+    }
+
+    /// Record a script-visible attribute change and deliver `change`, if
+    /// anyone observes it.
+    fn attribute_changed(
+        &self,
+        node_id: NodeId,
+        name: &str,
+        old_value: Option<String>,
+        change: Option<AttributeChange>,
+    ) {
+        // Descendants inherit the language, so what `:lang()` matches below
+        // may change too.
+        if name == "lang" || name == "xml:lang" {
+            for id in self.subtree(node_id).into_iter().skip(1) {
+                self.mark_style_dirty(id);
+            }
+        }
+        self.mark_style_dirty(node_id);
+        self.mutation_records
+            .write()
+            .push(attribute_record(node_id, name, old_value));
+        self.query_cache.invalidate_partial(node_id);
+        if let Some(change) = change {
+            self.attribute_observers.deliver(self, change);
         }
-        scripts
     }
 
     pub fn get_node(&self, node_id: NodeId) -> Option<Arc<RwLock<Node>>> {
-        self.nodes.get(&node_id).map(|e| e.clone())
+        self.nodes.read().get(node_id).cloned()
+    }
+
+    /// Whether `node_id` names a node of this document that was not freed.
+    pub fn contains_node(&self, node_id: NodeId) -> bool {
+        self.nodes.read().contains(node_id)
+    }
+
+    /// Every node in the arena with its id, taken under one short lock so
+    /// callers can lock nodes and call back into the document freely.
+    fn live_nodes(&self) -> Vec<(NodeId, Arc<RwLock<Node>>)> {
+        self.nodes
+            .read()
+            .iter()
+            .map(|(id, node)| (id, node.clone()))
+            .collect()
     }
 
     pub fn get_children(&self, node_id: NodeId) -> Vec<NodeId> {
-        if let Some(node) = self.nodes.get(&node_id) {
+        if let Some(node) = self.get_node(node_id) {
             node.read().children.iter().copied().collect()
         } else {
             Vec::new()
@@ -562,13 +1655,49 @@ impl Document {
     }
 
     pub fn get_parent(&self, node_id: NodeId) -> Option<NodeId> {
-        if let Some(node) = self.nodes.get(&node_id) {
+        if let Some(node) = self.get_node(node_id) {
             node.read().parent
         } else {
             None
         }
     }
 
+    /// Whether `node_id` is in the tree under the root, rather than
+    /// detached or freed.
+    pub fn is_connected(&self, node_id: NodeId) -> bool {
+        let root = match self.get_root_node() {
+            Some(root) => root,
+            None => return false,
+        };
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            if id == root {
+                return true;
+            }
+            current = self.get_parent(id);
+        }
+        false
+    }
+
+    /// DOM `textContent`: the data of a text or comment node, or the text
+    /// of every text node under an element, in document order.
+    pub fn text_content(&self, node_id: NodeId) -> Option<String> {
+        let node = self.get_node(node_id)?;
+        let node_type = node.read().node_type;
+        match node_type {
+            NodeType::Text | NodeType::Comment => Some(node.read().get_text_content()),
+            NodeType::Element => Some(
+                self.subtree(node_id)
+                    .into_iter()
+                    .filter_map(|id| self.get_node(id))
+                    .filter(|node| node.read().node_type == NodeType::Text)
+                    .map(|node| node.read().get_text_content())
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
     pub fn get_url(&self) -> Option<String> {
         self.metadata.read().url.clone()
     }
@@ -577,6 +1706,51 @@ impl Document {
         self.metadata.write().url = Some(url);
     }
 
+    /// The URL relative references resolve against: the first `<base>`
+    /// with an `href`, resolved against the document URL, else the document
+    /// URL. An `href` that does not resolve leaves the document URL.
+    pub fn base_url(&self) -> Option<url::Url> {
+        let document_url = self.get_url().and_then(|url| url::Url::parse(&url).ok());
+        let with_href = |node_id: NodeId| {
+            self.get_node(node_id)
+                .and_then(|node| node.read().get_attribute("href"))
+        };
+        let bases: Vec<NodeId> = self
+            .get_elements_by_tag_name("base")
+            .into_iter()
+            .filter(|&node_id| with_href(node_id).is_some() && self.is_connected(node_id))
+            .collect();
+        let first = match bases.as_slice() {
+            [] => None,
+            [base] => Some(*base),
+            // The tag index is not in tree order; walk the tree to choose.
+            _ => self
+                .get_root_node()
+                .and_then(|root| self.subtree(root).into_iter().find(|id| bases.contains(id))),
+        };
+        let href = match first.and_then(with_href) {
+            Some(href) => href,
+            None => return document_url,
+        };
+        let href = href.trim();
+        let base = match &document_url {
+            Some(document_url) => document_url.join(href),
+            None => url::Url::parse(href),
+        };
+        base.ok().or(document_url)
+    }
+
+    /// `reference` resolved against [`base_url`](Self::base_url), or on its
+    /// own when there is no base. Absolute URLs, `data:` among them, come
+    /// back as they are.
+    pub fn resolve_url(&self, reference: &str) -> Option<url::Url> {
+        let reference = reference.trim();
+        match self.base_url() {
+            Some(base) => base.join(reference).ok(),
+            None => url::Url::parse(reference).ok(),
+        }
+    }
+
     pub fn get_title(&self) -> String {
         self.metadata.read().title.clone()
     }
@@ -585,6 +1759,49 @@ impl Document {
         self.metadata.write().title = title;
     }
 
+    pub fn content_language(&self) -> Option<String> {
+        self.metadata.read().content_language.clone()
+    }
+
+    /// Take the document's default language from a `Content-Language`
+    /// header. A header naming several languages, or none, gives no default.
+    pub fn set_content_language(&self, header: Option<&str>) {
+        let language = header
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && !value.contains(','))
+            .map(str::to_string);
+        self.metadata.write().content_language = language;
+    }
+
+    /// The language `node_id` declares itself with `xml:lang` or `lang`,
+    /// `xml:lang` winning when it has both. `Some("")` declares it unknown.
+    pub fn declared_language(&self, node_id: NodeId) -> Option<String> {
+        let node = self.get_node(node_id)?;
+        let node = node.read();
+        if node.node_type != NodeType::Element {
+            return None;
+        }
+        node.get_attribute("xml:lang")
+            .or_else(|| node.get_attribute("lang"))
+            .map(|lang| lang.trim().to_string())
+    }
+
+    /// The content language of `node_id`: the nearest language declared on
+    /// it or an ancestor, else the document's [`content_language`]. `None`
+    /// when it is unknown.
+    ///
+    /// [`content_language`]: Self::content_language
+    pub fn language_of(&self, node_id: NodeId) -> Option<String> {
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            if let Some(lang) = self.declared_language(id) {
+                return (!lang.is_empty()).then_some(lang);
+            }
+            current = self.get_parent(id);
+        }
+        self.content_language()
+    }
+
     pub fn get_ready_state(&self) -> DocumentReadyState {
         self.metadata.read().ready_state
     }
@@ -596,11 +1813,29 @@ impl Document {
     fn record_mutation(&self, record: MutationRecord) {
         self.mutation_records.write().push(record.clone());
         for observer in self.mutation_observers.read().iter() {
-            (observer.callback)(&[record.clone()]);
+            (observer.callback)(std::slice::from_ref(&record));
         }
     }
 
+    /// Attribute records reach `observer` through the attribute hook, in
+    /// order with its other observers; the parser's attributes come with
+    /// the element and are not mutations of it.
     pub fn add_mutation_observer(&self, observer: MutationObserver) {
+        if observer.observe_attributes {
+            let callback = observer.callback.clone();
+            self.attribute_observers.register(
+                AttributeInterest {
+                    tag: None,
+                    attributes: observer.attribute_filter.clone(),
+                },
+                Arc::new(move |_: &Document, change: &AttributeChange| {
+                    if !change.parsed {
+                        let old_value = change.old_value.clone();
+                        callback(&[attribute_record(change.node, &change.name, old_value)]);
+                    }
+                }),
+            );
+        }
         self.mutation_observers.write().push(observer);
     }
 
@@ -611,41 +1846,244 @@ impl Document {
         result
     }
 
+    /// Number of nodes in the arena, attached or not.
+    pub fn node_count(&self) -> usize {
+        self.nodes.read().len()
+    }
+
+    /// Bytes held by the node arena's own storage; cheap, unlike
+    /// [`Self::memory_usage`].
+    pub fn arena_bytes(&self) -> usize {
+        self.nodes.read().bytes()
+    }
+
+    /// Bytes held by the arena and by the nodes' attribute and text strings.
+    /// Walks every node.
+    pub fn memory_usage(&self) -> DocumentMemory {
+        let (arena_bytes, live_nodes, dead_slots, nodes) = {
+            let arena = self.nodes.read();
+            (
+                arena.bytes(),
+                arena.len(),
+                arena.dead_slots(),
+                arena
+                    .iter()
+                    .map(|(_, node)| node.clone())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut memory = DocumentMemory {
+            arena_bytes,
+            node_bytes: live_nodes * std::mem::size_of::<Node>(),
+            live_nodes,
+            dead_slots,
+            text_nodes_created: self.text_nodes_created.load(Ordering::Relaxed),
+            text_nodes_coalesced: self.text_nodes_coalesced.load(Ordering::Relaxed),
+            ..DocumentMemory::default()
+        };
+        for node in nodes {
+            let node = node.read();
+            memory.attribute_bytes += node
+                .attributes
+                .iter()
+                .map(|(name, value)| name.capacity() + value.capacity())
+                .sum::<usize>();
+            memory.text_bytes += node.text_content.capacity() + node.tag_name.capacity();
+        }
+        memory
+    }
+
+    /// Dead slots per live node past which freeing nodes compacts the arena
+    /// by itself; `None` only compacts on [`Self::compact`].
+    pub fn set_compaction_ratio(&self, ratio: Option<f64>) {
+        *self.compaction_ratio.write() = ratio;
+    }
+
+    /// Pack the arena's live nodes into storage sized for them, e.g. while
+    /// the embedder is idle. Node ids are unchanged.
+    pub fn compact(&self) -> CompactionReport {
+        self.nodes.write().compact()
+    }
+
+    /// The compaction freeing nodes set off since the last call, if any, so
+    /// tables elsewhere keyed by node can drop what was freed too.
+    pub fn take_compaction(&self) -> Option<CompactionReport> {
+        self.last_compaction.lock().take()
+    }
+
+    /// Compact if freeing nodes left the arena sparse enough.
+    fn compact_if_sparse(&self) -> Option<CompactionReport> {
+        let ratio = (*self.compaction_ratio.read())?;
+        let mut arena = self.nodes.write();
+        if !arena.is_sparse(ratio) {
+            return None;
+        }
+        let report = arena.compact();
+        *self.last_compaction.lock() = Some(report);
+        tracing::debug!(
+            "Compacted the node arena from {} to {} slots",
+            report.slots_before,
+            report.slots_after
+        );
+        Some(report)
+    }
+
+    /// Let [`Self::reclaim_detached`] free `node_id` while it has no parent,
+    /// e.g. a node script created but has not inserted yet.
+    pub fn mark_detached(&self, node_id: NodeId) {
+        if self.get_parent(node_id).is_none() {
+            self.detached_roots.lock().insert(node_id);
+        }
+    }
+
+    /// Record that script now holds a wrapper for `node_id`.
+    pub fn retain_wrapper(&self, node_id: NodeId) {
+        *self.wrapper_counts.entry(node_id).or_insert(0) += 1;
+    }
+
+    /// Queue the release of one wrapper of `node_id`. Only pushes onto the
+    /// free list; the count drops at the next [`Self::reclaim_detached`].
+    pub fn release_wrapper(&self, node_id: NodeId) {
+        self.released_wrappers.lock().push(node_id);
+    }
+
+    /// Apply queued wrapper releases, then free every detached subtree none
+    /// of whose nodes is wrapped any more.
+    pub fn reclaim_detached(&self) -> ReclaimReport {
+        let released = std::mem::take(&mut *self.released_wrappers.lock());
+        for node_id in &released {
+            // Releases from before a teardown name nodes that are gone.
+            if let Some(mut count) = self.wrapper_counts.get_mut(node_id) {
+                *count = count.saturating_sub(1);
+            }
+            self.wrapper_counts
+                .remove_if(node_id, |_, count| *count == 0);
+        }
+
+        let root = self.get_root_node();
+        let mut reclaimed = Vec::new();
+        self.detached_roots.lock().retain(|&candidate| {
+            if Some(candidate) == root
+                || !self.contains_node(candidate)
+                || self.get_parent(candidate).is_some()
+            {
+                return false;
+            }
+            let subtree = self.subtree(candidate);
+            if subtree
+                .iter()
+                .any(|id| self.wrapper_counts.contains_key(id))
+            {
+                return true;
+            }
+            {
+                let mut arena = self.nodes.write();
+                for node_id in &subtree {
+                    arena.remove(*node_id);
+                }
+            }
+            for node_id in &subtree {
+                self.custom_validity.remove(node_id);
+            }
+            reclaimed.extend(subtree);
+            false
+        });
+
+        let mut compaction = None;
+        if !reclaimed.is_empty() {
+            self.query_cache.invalidate();
+            self.reclaimed_count
+                .fetch_add(reclaimed.len() as u64, Ordering::Relaxed);
+            compaction = self.compact_if_sparse();
+        }
+        ReclaimReport {
+            released_wrappers: released.len(),
+            reclaimed,
+            compaction,
+        }
+    }
+
+    pub fn wrapper_stats(&self) -> WrapperStats {
+        let pending = self.released_wrappers.lock().len() as u64;
+        let wrapped: u64 = self
+            .wrapper_counts
+            .iter()
+            .map(|entry| *entry.value() as u64)
+            .sum();
+        let detached_referenced = self
+            .detached_roots
+            .lock()
+            .iter()
+            .map(|&root| self.subtree(root))
+            .filter(|subtree| {
+                subtree
+                    .iter()
+                    .any(|id| self.wrapper_counts.contains_key(id))
+            })
+            .map(|subtree| subtree.len() as u64)
+            .sum();
+        WrapperStats {
+            live_wrappers: wrapped.saturating_sub(pending),
+            detached_referenced,
+            reclaimed: self.reclaimed_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drop the whole tree and everything tied to it, ready for the next
+    /// document to be built into this handle. Wrappers of the old nodes must
+    /// be invalidated by the script binding before this is called.
+    pub fn teardown(&self) {
+        self.query_cache.invalidate();
+        self.element_index.write().clear();
+        *self.root_node.write() = None;
+        self.nodes.write().clear();
+        self.mutation_observers.write().clear();
+        self.mutation_records.write().clear();
+        self.attribute_observers.clear();
+        self.wrapper_counts.clear();
+        self.released_wrappers.lock().clear();
+        self.detached_roots.lock().clear();
+        self.reclaimed_count.store(0, Ordering::Relaxed);
+        *self.parsed_source.write() = None;
+        *self.parser.lock() = None;
+        self.custom_validity.clear();
+        *self.last_compaction.lock() = None;
+        self.live_ranges.lock().clear();
+        self.text_nodes_created.store(0, Ordering::Relaxed);
+        self.text_nodes_coalesced.store(0, Ordering::Relaxed);
+    }
+
+    /// `node_id` and all of its descendants, in document order.
+    fn subtree(&self, node_id: NodeId) -> Vec<NodeId> {
+        let mut subtree = Vec::new();
+        let mut stack = vec![node_id];
+        while let Some(id) = stack.pop() {
+            subtree.push(id);
+            stack.extend(self.get_children(id).into_iter().rev());
+        }
+        subtree
+    }
+
     pub fn get_performance_metrics(&self) -> serde_json::Value {
+        let (ids, classes) = self.element_index.read().len();
         serde_json::json!({
-            "node_count": self.nodes.len(),
+            "node_count": self.node_count(),
             "cache_version": self.query_cache.get_version(),
             "cache_entries": {
                 "selector": self.query_cache.selector_cache.len(),
-                "id": self.query_cache.id_cache.len(),
-                "class": self.query_cache.class_cache.len(),
                 "tag": self.query_cache.tag_cache.len()
+            },
+            "element_index": {
+                "ids": ids,
+                "classes": classes
             }
         })
     }
 
     pub async fn cleanup(&self) {
         self.query_cache.invalidate();
+        self.element_index.write().clear();
         self.mutation_records.write().clear();
-        let all_node_ids: Vec<NodeId> = self.nodes.iter().map(|e| *e.key()).collect();
-        for node_id in all_node_ids {
-            self.nodes.remove(&node_id);
-        }
-    }
-}
-
-struct HTMLParser {
-    _current_node: Option<NodeId>,
-}
-
-impl HTMLParser {
-    fn new() -> Self {
-        Self {
-            _current_node: None,
-        }
-    }
-
-    fn parse(&self, _html: &str, _root_id: NodeId, _document: &Document) -> Result<()> {
-        Ok(())
+        self.nodes.write().clear();
     }
 }
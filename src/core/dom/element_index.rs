@@ -0,0 +1,89 @@
+//! The id and class index behind `getElementById` and
+//! `getElementsByClassName`.
+//!
+//! Only elements in the tree under the document's root are indexed. The
+//! document keeps the index in step as subtrees are inserted and removed
+//! and as `id` and `class` attributes change, so a lookup costs the
+//! elements it finds rather than a walk of the tree. Ids and classes map to
+//! their elements in no particular order; the document sorts what a lookup
+//! finds into document order.
+
+use super::document::NodeId;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Default)]
+pub(super) struct ElementIndex {
+    ids: HashMap<String, Vec<NodeId>>,
+    classes: HashMap<String, HashSet<NodeId>>,
+}
+
+impl ElementIndex {
+    pub(super) fn clear(&mut self) {
+        self.ids.clear();
+        self.classes.clear();
+    }
+
+    /// Index `node_id` under the `id` and `class` values it has.
+    pub(super) fn insert(&mut self, node_id: NodeId, id: Option<&str>, class: Option<&str>) {
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            self.ids.entry(id.to_string()).or_default().push(node_id);
+        }
+        for class in class.into_iter().flat_map(str::split_whitespace) {
+            self.classes
+                .entry(class.to_string())
+                .or_default()
+                .insert(node_id);
+        }
+    }
+
+    /// Drop `node_id` from under the `id` and `class` values it had.
+    pub(super) fn remove(&mut self, node_id: NodeId, id: Option<&str>, class: Option<&str>) {
+        if let Some(id) = id {
+            if let Some(elements) = self.ids.get_mut(id) {
+                elements.retain(|&element| element != node_id);
+                if elements.is_empty() {
+                    self.ids.remove(id);
+                }
+            }
+        }
+        for class in class.into_iter().flat_map(str::split_whitespace) {
+            if let Some(elements) = self.classes.get_mut(class) {
+                elements.remove(&node_id);
+                if elements.is_empty() {
+                    self.classes.remove(class);
+                }
+            }
+        }
+    }
+
+    /// The elements whose `id` is `id`; several when ids are duplicated.
+    pub(super) fn with_id(&self, id: &str) -> Vec<NodeId> {
+        self.ids.get(id).cloned().unwrap_or_default()
+    }
+
+    /// The elements that have every class of the space-separated
+    /// `class_names`; none for an empty list.
+    pub(super) fn with_classes(&self, class_names: &str) -> Vec<NodeId> {
+        let mut sets = Vec::new();
+        for class in class_names.split_whitespace() {
+            match self.classes.get(class) {
+                Some(elements) => sets.push(elements),
+                None => return Vec::new(),
+            }
+        }
+        sets.sort_by_key(|elements| elements.len());
+        let Some((smallest, rest)) = sets.split_first() else {
+            return Vec::new();
+        };
+        smallest
+            .iter()
+            .filter(|element| rest.iter().all(|elements| elements.contains(element)))
+            .copied()
+            .collect()
+    }
+
+    /// Distinct ids and classes indexed.
+    pub(super) fn len(&self) -> (usize, usize) {
+        (self.ids.len(), self.classes.len())
+    }
+}
@@ -1,10 +1,21 @@
+pub mod arena;
+pub mod attribute_observers;
 pub mod document;
 pub mod element;
+mod element_index;
 pub mod node;
+mod parser;
+pub mod serialize;
+pub mod view_source;
 
+pub use arena::CompactionReport;
+pub use attribute_observers::{
+    AttributeCallback, AttributeChange, AttributeInterest, AttributeObserverId,
+};
 pub use document::{
-    Document, DocumentError, DocumentMetadata, DocumentReadyState, InlineScript, MutationRecord,
-    MutationType, NodeId,
+    Document, DocumentError, DocumentMemory, DocumentMetadata, DocumentReadyState, InlineScript,
+    LiveRangeId, MutationRecord, MutationType, NodeId, ParserScript, ReclaimReport, WrapperStats,
+    DEFAULT_COMPACTION_RATIO,
 };
 pub use element::{
     AnimationId, AnimationOptions, DOMRect, Element, ElementError, ShadowRootInit, ShadowRootMode,
@@ -13,6 +24,7 @@ pub use node::{
     AttributeMap, ClearType, ComputedStyle, DisplayType, FloatType, LayoutData, Node, NodeType,
     OverflowType, PositionType,
 };
+pub use serialize::{DomSource, MarkupFormat, SerializeOptions};
 
 use crate::core::dom::document::NodeType as DocumentNodeType;
 use parking_lot::RwLock;
@@ -676,4 +688,36 @@ impl DOMRange {
     pub fn common_ancestor(&self) -> Option<NodeId> {
         self.common_ancestor_container
     }
+
+    pub fn start_container(&self) -> NodeId {
+        self.start_container
+    }
+
+    pub fn start_offset(&self) -> u32 {
+        self.start_offset
+    }
+
+    pub fn end_container(&self) -> NodeId {
+        self.end_container
+    }
+
+    pub fn end_offset(&self) -> u32 {
+        self.end_offset
+    }
+
+    pub fn collapsed(&self) -> bool {
+        self.collapsed
+    }
+
+    /// Move both boundary points with `f`, as the document does for the
+    /// ranges it tracks when the tree changes under them.
+    pub(crate) fn update_boundaries<F>(&mut self, document: &Document, mut f: F)
+    where
+        F: FnMut(&mut NodeId, &mut u32),
+    {
+        f(&mut self.start_container, &mut self.start_offset);
+        f(&mut self.end_container, &mut self.end_offset);
+        self.update_collapsed();
+        self.recompute_common_ancestor(document);
+    }
 }
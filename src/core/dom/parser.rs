@@ -0,0 +1,704 @@
+//! The HTML parser behind [`Document::parse_html`].
+//!
+//! A single pass tokenizes the markup and builds the tree as it goes. It
+//! covers what real pages lean on: doctypes, comments, quoted, unquoted and
+//! bare attributes, void elements, raw-text (`script`, `style`, ...) and
+//! escapable raw-text (`title`, `textarea`) content, character references,
+//! and the implied end tags of `p`, `li`, `dt`/`dd` and `option`. It does not
+//! synthesize missing `html`/`head`/`body` elements or run the adoption
+//! agency for misnested formatting tags; an end tag with no matching open
+//! element is dropped.
+//!
+//! Parsing is deterministic, so re-parsing a document's source rebuilds the
+//! tree exactly as it was first parsed.
+//!
+//! The parser runs ahead of the document's scripts: it builds the whole tree
+//! first and keeps its state as it was after each `</script>`. A script
+//! that calls `document.write()` has its markup inserted into the input at
+//! that point, and the parser resumes from there: the nodes it built past
+//! the script are removed and built again from the written markup followed
+//! by the rest of the input, so an element the write leaves open takes in
+//! the markup after it.
+//!
+//! [`ContentLimits`] bound what the markup builds. Elements at the depth
+//! limit get no children: what would have been their contents follows them
+//! as their siblings, so the tree below the limit is flattened into one
+//! level. Their end tags are still matched, so the markup after them lands
+//! where it would have. Past the node limit the rest of the markup is
+//! ignored.
+
+use super::document::{Document, DocumentError, NodeId, NodeType, Result};
+use crate::core::content_limits::{self, ContentLimit, ContentLimits, LimitBreach};
+use std::collections::HashSet;
+
+/// Elements that never have children or an end tag.
+pub(crate) const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is text up to their end tag, without character
+/// references.
+pub(crate) const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "script",
+    "style",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "plaintext",
+];
+
+/// Elements whose content is text up to their end tag, with character
+/// references.
+const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["title", "textarea"];
+
+/// Elements that drop a newline right after their start tag.
+pub(crate) const LEADING_NEWLINE_ELEMENTS: &[&str] = &["pre", "textarea", "listing"];
+
+/// Start tags that close an open `p`.
+const CLOSES_P: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Elements an implied `</p>` does not look past.
+const P_SCOPE_BOUNDARIES: &[&str] = &[
+    "button", "table", "td", "th", "caption", "object", "marquee", "applet", "template",
+];
+
+const NAMED_REFERENCES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("copy", '\u{a9}'),
+    ("reg", '\u{ae}'),
+    ("trade", '\u{2122}'),
+    ("deg", '\u{b0}'),
+    ("middot", '\u{b7}'),
+    ("times", '\u{d7}'),
+    ("divide", '\u{f7}'),
+    ("laquo", '\u{ab}'),
+    ("raquo", '\u{bb}'),
+    ("lsquo", '\u{2018}'),
+    ("rsquo", '\u{2019}'),
+    ("ldquo", '\u{201c}'),
+    ("rdquo", '\u{201d}'),
+    ("ndash", '\u{2013}'),
+    ("mdash", '\u{2014}'),
+    ("hellip", '\u{2026}'),
+    ("bull", '\u{2022}'),
+    ("euro", '\u{20ac}'),
+];
+
+pub(crate) struct HTMLParser {
+    /// Open elements, innermost last, with their lowercase tag names.
+    open: Vec<(NodeId, String)>,
+    /// Names of the elements open past the depth limit, innermost last.
+    /// They are in the tree, but not as parents of what follows.
+    flattened: Vec<String>,
+    text: String,
+    limits: ContentLimits,
+    nodes: usize,
+    /// The node limit was reached; the rest of the markup is ignored.
+    full: bool,
+    breaches: Vec<LimitBreach>,
+    /// The markup, with what scripts wrote inserted.
+    input: String,
+    /// Every node created, with the parent it went into, in order.
+    inserted: Vec<(NodeId, NodeId)>,
+    /// The state after each script's end tag, in the order reached.
+    checkpoints: Vec<Checkpoint>,
+    /// Where written markup sits in `input`, as start and end offsets.
+    written: Vec<(usize, usize)>,
+}
+
+/// What the parser resumes from after a script.
+#[derive(Clone)]
+struct Checkpoint {
+    script: NodeId,
+    /// Where the input goes on after the script's end tag.
+    pos: usize,
+    /// How many writes the script is nested in.
+    write_depth: usize,
+    open: Vec<(NodeId, String)>,
+    flattened: Vec<String>,
+    nodes: usize,
+    inserted: usize,
+}
+
+impl HTMLParser {
+    pub(crate) fn new(limits: ContentLimits) -> Self {
+        Self {
+            open: Vec::new(),
+            flattened: Vec::new(),
+            text: String::new(),
+            limits,
+            nodes: 0,
+            full: false,
+            breaches: Vec::new(),
+            input: String::new(),
+            inserted: Vec::new(),
+            checkpoints: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Parse `html` into children of `root_id`. Returns the limits the
+    /// markup went past.
+    pub(crate) fn parse(
+        &mut self,
+        html: &str,
+        root_id: NodeId,
+        document: &Document,
+    ) -> Result<Vec<LimitBreach>> {
+        self.input = normalize_newlines(html);
+        self.open.clear();
+        self.open.push((root_id, String::new()));
+        self.flattened.clear();
+        self.inserted.clear();
+        self.checkpoints.clear();
+        self.written.clear();
+        self.run(0, document)
+    }
+
+    /// The markup parsed so far, written markup included.
+    pub(crate) fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The script element the parser reached `index`th, and how many
+    /// writes deep it is.
+    pub(crate) fn script(&self, index: usize) -> Option<(NodeId, usize)> {
+        self.checkpoints
+            .get(index)
+            .map(|checkpoint| (checkpoint.script, checkpoint.write_depth))
+    }
+
+    /// Insert `markup` into the input right after the end tag of the
+    /// `index`th script and parse on from there. The nodes built past that
+    /// point are removed first: the input they came from now follows the
+    /// written markup. Returns the limits the markup went past.
+    pub(crate) fn write(
+        &mut self,
+        index: usize,
+        markup: &str,
+        document: &Document,
+    ) -> Result<Vec<LimitBreach>> {
+        let Some(checkpoint) = self.checkpoints.get(index).cloned() else {
+            return Err(DocumentError::InvalidOperation(format!(
+                "the parser has not reached a script {}",
+                index
+            )));
+        };
+        self.checkpoints.truncate(index + 1);
+
+        // Only the tops of what was built are removed; what is under them
+        // goes with them.
+        let built = self.inserted.split_off(checkpoint.inserted);
+        let dropped: HashSet<NodeId> = built.iter().map(|&(_, child)| child).collect();
+        for (parent, child) in built {
+            if !dropped.contains(&parent) && document.get_parent(child) == Some(parent) {
+                document.remove_child(parent, child)?;
+                document.mark_detached(child);
+            }
+        }
+        self.open = checkpoint.open;
+        self.flattened = checkpoint.flattened;
+        self.nodes = checkpoint.nodes;
+        self.full = false;
+        self.text.clear();
+
+        let markup = normalize_newlines(markup);
+        let at = checkpoint.pos;
+        for (start, end) in &mut self.written {
+            if *start >= at {
+                *start += markup.len();
+            }
+            // A write from a script at the end of written markup nests in it.
+            if *end >= at {
+                *end += markup.len();
+            }
+        }
+        self.written.push((at, at + markup.len()));
+        self.input.insert_str(at, &markup);
+        self.run(at, document)
+    }
+
+    /// Tokenize the input from `from` to its end.
+    fn run(&mut self, from: usize, document: &Document) -> Result<Vec<LimitBreach>> {
+        let input = std::mem::take(&mut self.input);
+        let result = self.tokenize(&input, from, document);
+        self.input = input;
+        result?;
+        Ok(std::mem::take(&mut self.breaches))
+    }
+
+    fn tokenize(&mut self, html: &str, from: usize, document: &Document) -> Result<()> {
+        let bytes = html.as_bytes();
+        let mut pos = from;
+        while pos < bytes.len() && !self.full {
+            if bytes[pos] != b'<' {
+                let end = html[pos..].find('<').map_or(bytes.len(), |i| pos + i);
+                decode_into(&html[pos..end], &mut self.text);
+                pos = end;
+                continue;
+            }
+
+            let rest = &html[pos..];
+            if rest.starts_with("<!-->") || rest.starts_with("<!--->") {
+                // Abruptly closed empty comments.
+                self.append(document, NodeType::Comment, String::new())?;
+                pos += if rest.starts_with("<!-->") { 5 } else { 6 };
+            } else if let Some(comment) = rest.strip_prefix("<!--") {
+                let (data, end) = match comment.find("-->") {
+                    Some(i) => (&comment[..i], pos + 4 + i + 3),
+                    None => (comment, bytes.len()),
+                };
+                self.append(document, NodeType::Comment, data.to_string())?;
+                pos = end;
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                let end = rest.find('>').map_or(bytes.len(), |i| pos + i + 1);
+                let inner = html[pos + 2..end].trim_end_matches('>');
+                if starts_with_ignore_case(inner, "doctype") {
+                    let name = inner[7..].split_whitespace().next().unwrap_or("html");
+                    self.append(document, NodeType::DocumentType, name.to_ascii_lowercase())?;
+                } else {
+                    // Bogus comments keep the `?` of a processing instruction.
+                    let data = if rest.starts_with("<?") {
+                        html[pos + 1..end].trim_end_matches('>')
+                    } else {
+                        inner
+                    };
+                    self.append(document, NodeType::Comment, data.to_string())?;
+                }
+                pos = end;
+            } else if rest.starts_with("</")
+                && bytes.get(pos + 2).is_some_and(u8::is_ascii_alphabetic)
+            {
+                let end = rest.find('>').map_or(bytes.len(), |i| pos + i + 1);
+                let name = tag_name(&html[pos + 2..end]);
+                self.end_tag(document, &name)?;
+                pos = end;
+            } else if rest.starts_with("</") {
+                // `</>` is dropped; anything else is a bogus comment.
+                let end = rest.find('>').map_or(bytes.len(), |i| pos + i + 1);
+                if end > pos + 3 {
+                    let data = html[pos + 2..end].trim_end_matches('>');
+                    self.append(document, NodeType::Comment, data.to_string())?;
+                }
+                pos = end;
+            } else if bytes.get(pos + 1).is_some_and(u8::is_ascii_alphabetic) {
+                pos = self.start_tag(html, pos, document)?;
+            } else {
+                self.text.push('<');
+                pos += 1;
+            }
+        }
+
+        self.flush_text(document)
+    }
+
+    /// Parse the start tag at `start` and, for raw-text elements, their
+    /// content and end tag. Returns the position after what was consumed.
+    fn start_tag(&mut self, html: &str, start: usize, document: &Document) -> Result<usize> {
+        let bytes = html.as_bytes();
+        let mut pos = start + 1;
+        while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) {
+            pos += 1;
+        }
+        let name = html[start + 1..pos].to_ascii_lowercase();
+        self.flush_text(document)?;
+
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        loop {
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+                pos += 1;
+            }
+            if pos >= bytes.len() {
+                // A tag cut off by the end of input is dropped.
+                return Ok(pos);
+            }
+            if bytes[pos] == b'>' {
+                pos += 1;
+                break;
+            }
+
+            let name_start = pos;
+            pos += 1;
+            while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) && bytes[pos] != b'=' {
+                pos += 1;
+            }
+            let attribute = html[name_start..pos].to_ascii_lowercase();
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+
+            let mut value = String::new();
+            if pos < bytes.len() && bytes[pos] == b'=' {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                let raw = match bytes.get(pos) {
+                    Some(&quote) if quote == b'"' || quote == b'\'' => {
+                        let end = html[pos + 1..]
+                            .find(quote as char)
+                            .map_or(bytes.len(), |i| pos + 1 + i);
+                        let raw = &html[pos + 1..end];
+                        pos = (end + 1).min(bytes.len());
+                        raw
+                    }
+                    _ => {
+                        let value_start = pos;
+                        while pos < bytes.len()
+                            && !bytes[pos].is_ascii_whitespace()
+                            && bytes[pos] != b'>'
+                        {
+                            pos += 1;
+                        }
+                        &html[value_start..pos]
+                    }
+                };
+                let max = self.limits.max_attribute_length;
+                if raw.len() > max {
+                    self.note(ContentLimit::AttributeLength, max, raw.len());
+                }
+                // References only shorten what they stand for, so cutting
+                // the raw value keeps the decoded one within the limit.
+                decode_into(content_limits::truncate_str(raw, max), &mut value);
+            }
+            // The first of duplicate attributes wins.
+            if !attributes
+                .iter()
+                .any(|(existing, _)| *existing == attribute)
+            {
+                attributes.push((attribute, value));
+            }
+        }
+
+        self.close_implied(&name);
+        let Some(element) = self.append(document, NodeType::Element, name.clone())? else {
+            return Ok(pos);
+        };
+        document.set_parsed_attributes(element, &attributes);
+        if VOID_ELEMENTS.contains(&name.as_str()) {
+            return Ok(pos);
+        }
+
+        let raw = RAW_TEXT_ELEMENTS.contains(&name.as_str());
+        let text_only = raw || ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&name.as_str());
+        // How deep the element would be, counting the root as 0.
+        let depth = self.open.len() + self.flattened.len();
+        if depth > self.limits.max_dom_depth {
+            self.note(ContentLimit::DomDepth, self.limits.max_dom_depth, depth);
+        }
+        // Text-only elements close again below, so they keep their text at
+        // any depth.
+        if self.open.len() >= self.limits.max_dom_depth && !text_only {
+            self.flattened.push(name);
+            return Ok(pos);
+        }
+        self.open.push((element, name.clone()));
+
+        if text_only {
+            let end = if name == "plaintext" {
+                bytes.len()
+            } else {
+                find_end_tag(html, pos, &name)
+            };
+            let mut content = &html[pos..end];
+            if name == "textarea" {
+                content = content.strip_prefix('\n').unwrap_or(content);
+            }
+            let max = self.limits.max_inline_script_length;
+            if name == "script" && content.len() > max {
+                self.note(ContentLimit::InlineScriptLength, max, content.len());
+            } else if raw {
+                self.text.push_str(content);
+            } else {
+                decode_into(content, &mut self.text);
+            }
+            self.end_tag(document, &name)?;
+            let after = html[end..].find('>').map_or(bytes.len(), |i| end + i + 1);
+            if name == "script" {
+                self.checkpoint(element, after);
+            }
+            return Ok(after);
+        }
+        if LEADING_NEWLINE_ELEMENTS.contains(&name.as_str()) && bytes.get(pos) == Some(&b'\n') {
+            pos += 1;
+        }
+        Ok(pos)
+    }
+
+    /// Keep the state after the end tag of `script`, which ends at `pos`.
+    fn checkpoint(&mut self, script: NodeId, pos: usize) {
+        let write_depth = self
+            .written
+            .iter()
+            .filter(|&&(start, end)| start < pos && pos <= end)
+            .count();
+        self.checkpoints.push(Checkpoint {
+            script,
+            pos,
+            write_depth,
+            open: self.open.clone(),
+            flattened: self.flattened.clone(),
+            nodes: self.nodes,
+            inserted: self.inserted.len(),
+        });
+    }
+
+    /// Pop the elements a start tag for `name` implicitly ends: the nearest
+    /// open one of its kind, unless a scope boundary comes first.
+    fn close_implied(&mut self, name: &str) {
+        let (targets, boundaries): (&[&str], &[&str]) = match name {
+            "li" => (&["li"], &["ul", "ol"]),
+            "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+            "option" => (&["option"], &["select", "datalist"]),
+            _ if CLOSES_P.contains(&name) => (&["p"], P_SCOPE_BOUNDARIES),
+            _ => return,
+        };
+        for index in (0..self.flattened.len()).rev() {
+            let open = self.flattened[index].as_str();
+            if targets.contains(&open) {
+                self.flattened.truncate(index);
+                return;
+            }
+            if boundaries.contains(&open) {
+                return;
+            }
+        }
+        for index in (1..self.open.len()).rev() {
+            let open = self.open[index].1.as_str();
+            if targets.contains(&open) {
+                self.open.truncate(index);
+                self.flattened.clear();
+                return;
+            }
+            if boundaries.contains(&open) {
+                return;
+            }
+        }
+    }
+
+    fn end_tag(&mut self, document: &Document, name: &str) -> Result<()> {
+        self.flush_text(document)?;
+        if let Some(index) = self.flattened.iter().rposition(|open| open == name) {
+            self.flattened.truncate(index);
+        } else if let Some(index) = self.open.iter().rposition(|(_, open)| open == name) {
+            // Flattened elements were inside it, unless it is a text-only
+            // element opened among them.
+            if index < self.limits.max_dom_depth {
+                self.flattened.clear();
+            }
+            self.open.truncate(index);
+        }
+        Ok(())
+    }
+
+    /// Append a node to the current element; `None` once the node limit
+    /// is reached.
+    fn append(
+        &mut self,
+        document: &Document,
+        node_type: NodeType,
+        content: String,
+    ) -> Result<Option<NodeId>> {
+        self.flush_text(document)?;
+        if !self.reserve_node() {
+            return Ok(None);
+        }
+        let parent = self.current();
+        let node_id = document.create_node(node_type, content)?;
+        document.append_child(parent, node_id)?;
+        self.inserted.push((parent, node_id));
+        Ok(Some(node_id))
+    }
+
+    /// Count a node about to be created, unless that would pass the limit.
+    fn reserve_node(&mut self) -> bool {
+        if self.nodes >= self.limits.max_nodes {
+            self.full = true;
+            let max = self.limits.max_nodes;
+            self.note(ContentLimit::NodeCount, max, max + 1);
+            return false;
+        }
+        self.nodes += 1;
+        true
+    }
+
+    fn note(&mut self, limit: ContentLimit, threshold: usize, value: usize) {
+        content_limits::note(&mut self.breaches, limit, threshold, value);
+    }
+
+    fn flush_text(&mut self, document: &Document) -> Result<()> {
+        if self.text.is_empty() {
+            return Ok(());
+        }
+        let mut text = std::mem::take(&mut self.text);
+        let parent = self.current();
+        // A script's text is bounded by the script limit instead, and a
+        // style sheet's by the rule limit.
+        let max = match self.open.last() {
+            Some((_, name)) if name == "script" || name == "style" => usize::MAX,
+            _ => self.limits.max_text_length,
+        };
+        // Text right after other text, say across a dropped end tag, joins it.
+        let previous = document
+            .get_node(parent)
+            .and_then(|node| node.read().children.last().copied())
+            .and_then(|last| document.get_node(last));
+        if let Some(previous) = previous {
+            let mut previous = previous.write();
+            if previous.node_type == NodeType::Text {
+                let length = previous.text_content.len() + text.len();
+                if length > max {
+                    self.note(ContentLimit::TextLength, max, length);
+                    let room = max.saturating_sub(previous.text_content.len());
+                    text.truncate(content_limits::truncate_str(&text, room).len());
+                }
+                previous.text_content.push_str(&text);
+                return Ok(());
+            }
+        }
+        if text.len() > max {
+            self.note(ContentLimit::TextLength, max, text.len());
+            text.truncate(content_limits::truncate_str(&text, max).len());
+        }
+        if !self.reserve_node() {
+            return Ok(());
+        }
+        let node_id = document.create_node(NodeType::Text, text)?;
+        document.append_child(parent, node_id)?;
+        self.inserted.push((parent, node_id));
+        Ok(())
+    }
+
+    fn current(&self) -> NodeId {
+        self.open.last().map(|(id, _)| *id).unwrap_or_default()
+    }
+}
+
+fn normalize_newlines(html: &str) -> String {
+    if html.contains('\r') {
+        html.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        html.to_string()
+    }
+}
+
+fn is_tag_delimiter(b: u8) -> bool {
+    b.is_ascii_whitespace() || b == b'>' || b == b'/'
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.len() >= prefix.len()
+        && text.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+/// The lowercase name at the start of a tag's body.
+fn tag_name(body: &str) -> String {
+    let end = body
+        .bytes()
+        .position(is_tag_delimiter)
+        .unwrap_or(body.len());
+    body[..end].to_ascii_lowercase()
+}
+
+/// Where the end tag for `name` starts, searching from `from`; the end of
+/// input if there is none.
+fn find_end_tag(html: &str, from: usize, name: &str) -> usize {
+    let bytes = html.as_bytes();
+    let mut pos = from;
+    while let Some(i) = html[pos..].find("</") {
+        let start = pos + i;
+        let name_end = start + 2 + name.len();
+        if name_end <= bytes.len()
+            && bytes[start + 2..name_end].eq_ignore_ascii_case(name.as_bytes())
+            && bytes.get(name_end).map_or(true, |&b| is_tag_delimiter(b))
+        {
+            return start;
+        }
+        pos = start + 2;
+    }
+    bytes.len()
+}
+
+/// Append `raw` to `out` with character references decoded. References
+/// need their `;`; anything unrecognized stays as written.
+fn decode_into(raw: &str, out: &mut String) {
+    let mut rest = raw;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_reference(rest) {
+            Some((c, consumed)) => {
+                out.push(c);
+                rest = &rest[consumed..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+/// The character `text` (starting at `&`) refers to, and the length of the
+/// reference.
+fn decode_reference(text: &str) -> Option<(char, usize)> {
+    let semicolon = text[1..].find(';').map(|i| i + 1)?;
+    let body = &text[1..semicolon];
+    if body.is_empty() || body.len() > 10 {
+        return None;
+    }
+    let c = if let Some(number) = body.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse::<u32>().ok()?,
+        };
+        match code {
+            0 => '\u{fffd}',
+            _ => char::from_u32(code).unwrap_or('\u{fffd}'),
+        }
+    } else {
+        NAMED_REFERENCES
+            .iter()
+            .find(|(name, _)| *name == body)
+            .map(|(_, c)| *c)?
+    };
+    Some((c, semicolon + 1))
+}
@@ -0,0 +1,236 @@
+//! Writing a tree back out as markup: `outerHTML`, `innerHTML`,
+//! `XMLSerializer` and [`Document::serialize`].
+//!
+//! HTML output follows the HTML fragment serialization algorithm: void
+//! elements get no end tag, raw-text elements (`script`, `style`, ...) keep
+//! their text unescaped, and `pre`/`textarea`/`listing` repeat a leading
+//! newline the parser would drop. Attributes are written in name order, as
+//! the DOM does not keep their source order. Only DOM nodes are written;
+//! what layout generates on its own, like an in-progress IME composition,
+//! never appears.
+//!
+//! The walk is iterative and appends to one buffer, so deep or large trees
+//! cost stack and time linear in their size.
+
+use super::document::{Document, NodeId, NodeType};
+use super::parser::{LEADING_NEWLINE_ELEMENTS, RAW_TEXT_ELEMENTS, VOID_ELEMENTS};
+use std::sync::Arc;
+
+pub const XHTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+
+/// Typical serialized size of a node, for pre-sizing whole-document output.
+pub(crate) const BYTES_PER_NODE: usize = 48;
+
+/// Which state of the document to serialize.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DomSource {
+    /// The tree as it is now, with every script mutation.
+    #[default]
+    Current,
+    /// The tree as the parser built it.
+    AsParsed,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarkupFormat {
+    #[default]
+    Html,
+    /// Well-formed XML, as `XMLSerializer` writes HTML elements.
+    Xml,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SerializeOptions {
+    pub source: DomSource,
+    pub format: MarkupFormat,
+}
+
+#[derive(Clone, Default)]
+struct Context {
+    /// Text children are written unescaped.
+    raw_text: bool,
+    /// The default namespace in scope in XML output.
+    namespace: Option<Arc<str>>,
+}
+
+enum Step {
+    Open(NodeId, Context),
+    Close(String),
+}
+
+/// Append `node_id` and its subtree to `out`.
+pub fn write_node(document: &Document, node_id: NodeId, format: MarkupFormat, out: &mut String) {
+    let raw_text = format == MarkupFormat::Html
+        && document
+            .get_parent(node_id)
+            .is_some_and(|parent| is_raw_text_element(document, parent));
+    let context = Context {
+        raw_text,
+        namespace: None,
+    };
+    walk(document, vec![Step::Open(node_id, context)], format, out);
+}
+
+/// Append the children of `node_id` and their subtrees to `out`.
+pub fn write_children(
+    document: &Document,
+    node_id: NodeId,
+    format: MarkupFormat,
+    out: &mut String,
+) {
+    let context = Context {
+        raw_text: format == MarkupFormat::Html && is_raw_text_element(document, node_id),
+        namespace: None,
+    };
+    let mut stack = Vec::new();
+    push_children(document, node_id, &context, &mut stack);
+    walk(document, stack, format, out);
+}
+
+fn push_children(document: &Document, node_id: NodeId, context: &Context, stack: &mut Vec<Step>) {
+    let children = match document.get_node(node_id) {
+        Some(node) => node.read().children.clone(),
+        None => return,
+    };
+    stack.extend(
+        children
+            .iter()
+            .rev()
+            .map(|&child| Step::Open(child, context.clone())),
+    );
+}
+
+fn walk(document: &Document, mut stack: Vec<Step>, format: MarkupFormat, out: &mut String) {
+    let xml = format == MarkupFormat::Xml;
+    while let Some(step) = stack.pop() {
+        let (node_id, context) = match step {
+            Step::Open(node_id, context) => (node_id, context),
+            Step::Close(tag) => {
+                out.push_str("</");
+                out.push_str(&tag);
+                out.push('>');
+                continue;
+            }
+        };
+        let node = match document.get_node(node_id) {
+            Some(node) => node,
+            None => continue,
+        };
+        let node = node.read();
+        match node.node_type {
+            NodeType::Text if context.raw_text => out.push_str(&node.text_content),
+            NodeType::Text => escape(&node.text_content, xml, false, out),
+            NodeType::Comment => {
+                out.push_str("<!--");
+                out.push_str(&node.text_content);
+                out.push_str("-->");
+            }
+            NodeType::DocumentType => {
+                out.push_str("<!DOCTYPE ");
+                out.push_str(&node.tag_name);
+                out.push('>');
+            }
+            NodeType::Document => push_children(document, node_id, &context, &mut stack),
+            NodeType::Element => {
+                let is_html = is_html_namespace(node.namespace_uri.as_deref());
+                let tag = if is_html {
+                    node.tag_name.to_ascii_lowercase()
+                } else {
+                    node.tag_name.clone()
+                };
+                out.push('<');
+                out.push_str(&tag);
+
+                let mut namespace = context.namespace.clone();
+                if xml && !node.attributes.contains_key("xmlns") {
+                    let own = node.namespace_uri.as_deref().unwrap_or(XHTML_NAMESPACE);
+                    if namespace.as_deref() != Some(own) {
+                        out.push_str(" xmlns=\"");
+                        escape(own, true, true, out);
+                        out.push('"');
+                        namespace = Some(Arc::from(own));
+                    }
+                }
+                let mut attributes: Vec<(&String, &String)> = node.attributes.iter().collect();
+                attributes.sort_unstable_by(|a, b| a.0.cmp(b.0));
+                for (name, value) in attributes {
+                    out.push(' ');
+                    out.push_str(name);
+                    out.push_str("=\"");
+                    escape(value, xml, true, out);
+                    out.push('"');
+                }
+
+                if is_html && VOID_ELEMENTS.contains(&tag.as_str()) {
+                    out.push_str(if xml { " />" } else { ">" });
+                    continue;
+                }
+                out.push('>');
+                if !xml && LEADING_NEWLINE_ELEMENTS.contains(&tag.as_str()) {
+                    let leading_newline = node
+                        .children
+                        .first()
+                        .and_then(|&child| document.get_node(child))
+                        .is_some_and(|child| {
+                            let child = child.read();
+                            child.node_type == NodeType::Text
+                                && child.text_content.starts_with('\n')
+                        });
+                    if leading_newline {
+                        out.push('\n');
+                    }
+                }
+
+                let child_context = Context {
+                    raw_text: !xml && is_html && RAW_TEXT_ELEMENTS.contains(&tag.as_str()),
+                    namespace,
+                };
+                stack.push(Step::Close(tag));
+                stack.extend(
+                    node.children
+                        .iter()
+                        .rev()
+                        .map(|&child| Step::Open(child, child_context.clone())),
+                );
+            }
+        }
+    }
+}
+
+fn is_raw_text_element(document: &Document, node_id: NodeId) -> bool {
+    document.get_node(node_id).is_some_and(|node| {
+        let node = node.read();
+        node.node_type == NodeType::Element
+            && is_html_namespace(node.namespace_uri.as_deref())
+            && RAW_TEXT_ELEMENTS
+                .iter()
+                .any(|raw| node.tag_name.eq_ignore_ascii_case(raw))
+    })
+}
+
+fn is_html_namespace(namespace: Option<&str>) -> bool {
+    namespace.map_or(true, |namespace| namespace == XHTML_NAMESPACE)
+}
+
+/// Append `text` escaped for a text node or, with `attribute`, a
+/// double-quoted attribute value.
+fn escape(text: &str, xml: bool, attribute: bool, out: &mut String) {
+    let mut start = 0;
+    for (index, c) in text.char_indices() {
+        let replacement = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            '"' if attribute => "&quot;",
+            '\u{a0}' if !xml => "&nbsp;",
+            '\t' if xml && attribute => "&#9;",
+            '\n' if xml && attribute => "&#10;",
+            '\r' if xml && attribute => "&#13;",
+            _ => continue,
+        };
+        out.push_str(&text[start..index]);
+        out.push_str(replacement);
+        start = index + c.len_utf8();
+    }
+    out.push_str(&text[start..]);
+}
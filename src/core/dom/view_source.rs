@@ -0,0 +1,232 @@
+//! Synthetic `view-source:` documents.
+//!
+//! The raw markup is split by a small tokenizer (not the HTML parser) into
+//! highlighted spans, one `span.line` per source line inside a single `pre`.
+//! Nothing here creates `script`, `img` or `link` elements, so the result can
+//! never run code or pull in subresources from the viewed page.
+
+use super::document::{Document, NodeId, NodeType, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Tag,
+    AttributeName,
+    AttributeValue,
+    Comment,
+    Doctype,
+    Text,
+}
+
+impl TokenKind {
+    fn class_name(self) -> &'static str {
+        match self {
+            TokenKind::Tag => "vs-tag",
+            TokenKind::AttributeName => "vs-attr-name",
+            TokenKind::AttributeValue => "vs-attr-value",
+            TokenKind::Comment => "vs-comment",
+            TokenKind::Doctype => "vs-doctype",
+            TokenKind::Text => "vs-text",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            TokenKind::Tag => "#881280",
+            TokenKind::AttributeName => "#994500",
+            TokenKind::AttributeValue => "#1a1aa6",
+            TokenKind::Comment => "#236e25",
+            TokenKind::Doctype => "#c0c0c0",
+            TokenKind::Text => "#000000",
+        }
+    }
+}
+
+/// Split raw markup into highlightable tokens. Concatenating the token texts
+/// always yields the input unchanged.
+pub fn tokenize(source: &str) -> Vec<(TokenKind, &str)> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        if source[pos..].starts_with("<!--") {
+            let end = source[pos + 4..]
+                .find("-->")
+                .map_or(bytes.len(), |i| pos + 4 + i + 3);
+            tokens.push((TokenKind::Comment, &source[pos..end]));
+            pos = end;
+        } else if source[pos..].starts_with("<!") || source[pos..].starts_with("<?") {
+            let end = source[pos..].find('>').map_or(bytes.len(), |i| pos + i + 1);
+            tokens.push((TokenKind::Doctype, &source[pos..end]));
+            pos = end;
+        } else if bytes[pos] == b'<'
+            && bytes
+                .get(pos + 1)
+                .is_some_and(|b| b.is_ascii_alphabetic() || *b == b'/')
+        {
+            pos = tokenize_tag(source, pos, &mut tokens);
+        } else {
+            // A stray `<` belongs to this text run; skip it before searching.
+            let from = if bytes[pos] == b'<' { pos + 1 } else { pos };
+            let end = source[from..].find('<').map_or(bytes.len(), |i| from + i);
+            tokens.push((TokenKind::Text, &source[pos..end]));
+            pos = end;
+        }
+    }
+
+    tokens
+}
+
+/// Tokenize one tag starting at `start` (which points at `<`), returning the
+/// position just past it. Raw-text elements swallow their body as text.
+fn tokenize_tag<'a>(
+    source: &'a str,
+    start: usize,
+    tokens: &mut Vec<(TokenKind, &'a str)>,
+) -> usize {
+    let bytes = source.as_bytes();
+    let is_end_tag = bytes[start + 1] == b'/';
+    let mut pos = start + if is_end_tag { 2 } else { 1 };
+    while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) {
+        pos += 1;
+    }
+    let name = &source[start + if is_end_tag { 2 } else { 1 }..pos];
+    tokens.push((TokenKind::Tag, &source[start..pos]));
+
+    while pos < bytes.len() {
+        let b = bytes[pos];
+        if b == b'>' {
+            tokens.push((TokenKind::Tag, &source[pos..pos + 1]));
+            pos += 1;
+            break;
+        } else if b == b'"' || b == b'\'' {
+            let end = source[pos + 1..]
+                .find(b as char)
+                .map_or(bytes.len(), |i| pos + 1 + i + 1);
+            tokens.push((TokenKind::AttributeValue, &source[pos..end]));
+            pos = end;
+        } else if !b.is_ascii_whitespace()
+            && tokens.last().is_some_and(|(_, text)| text.ends_with('='))
+        {
+            let value_start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() && bytes[pos] != b'>' {
+                pos += 1;
+            }
+            tokens.push((TokenKind::AttributeValue, &source[value_start..pos]));
+        } else if b.is_ascii_whitespace() || b == b'/' || b == b'=' {
+            let run_start = pos;
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+                pos += 1;
+            }
+            if pos == run_start {
+                pos += 1;
+            }
+            tokens.push((TokenKind::Tag, &source[run_start..pos]));
+        } else {
+            let name_start = pos;
+            while pos < bytes.len() && !is_tag_delimiter(bytes[pos]) && bytes[pos] != b'=' {
+                pos += 1;
+            }
+            tokens.push((TokenKind::AttributeName, &source[name_start..pos]));
+        }
+    }
+
+    if !is_end_tag
+        && (name.eq_ignore_ascii_case("script") || name.eq_ignore_ascii_case("style"))
+        && pos < bytes.len()
+    {
+        let lower = source[pos..].to_ascii_lowercase();
+        let close = format!("</{}", name.to_ascii_lowercase());
+        let end = lower.find(&close).map_or(bytes.len(), |i| pos + i);
+        if end > pos {
+            tokens.push((TokenKind::Text, &source[pos..end]));
+        }
+        pos = end;
+    }
+
+    pos
+}
+
+fn is_tag_delimiter(b: u8) -> bool {
+    b.is_ascii_whitespace() || b == b'>' || b == b'/'
+}
+
+/// Replace `document`'s content with a highlighted, line-numbered listing of
+/// `source`. Returns the number of line elements generated.
+pub fn build_view_source(document: &Document, source: &str, title: &str) -> Result<usize> {
+    let root = document.create_node(NodeType::Document, String::new())?;
+    document.set_root_node(root);
+
+    let html = append_element(document, root, "html", None)?;
+    let head = append_element(document, html, "head", None)?;
+    let title_el = append_element(document, head, "title", None)?;
+    append_text(document, title_el, title)?;
+    document.set_title(title.to_string());
+
+    let body = append_element(document, html, "body", None)?;
+    let pre = append_element(document, body, "pre", Some("view-source"))?;
+    set_attribute(document, pre, "style", "font-family: monospace")?;
+
+    let mut lines: Vec<Vec<(TokenKind, &str)>> = vec![Vec::new()];
+    for (kind, text) in tokenize(source) {
+        let mut segments = text.split('\n').peekable();
+        while let Some(segment) = segments.next() {
+            let segment = segment.strip_suffix('\r').unwrap_or(segment);
+            if !segment.is_empty() {
+                lines.last_mut().unwrap().push((kind, segment));
+            }
+            if segments.peek().is_some() {
+                lines.push(Vec::new());
+            }
+        }
+    }
+    if lines.len() > 1 && source.ends_with('\n') {
+        lines.pop();
+    }
+
+    for (index, tokens) in lines.iter().enumerate() {
+        let number = (index + 1).to_string();
+        let line = append_element(document, pre, "span", Some("line"))?;
+        set_attribute(document, line, "data-line", &number)?;
+
+        let gutter = append_element(document, line, "span", Some("line-number"))?;
+        append_text(document, gutter, &number)?;
+
+        for (kind, text) in tokens {
+            let span = append_element(document, line, "span", Some(kind.class_name()))?;
+            set_attribute(document, span, "style", &format!("color: {}", kind.color()))?;
+            append_text(document, span, text)?;
+        }
+        append_text(document, line, "\n")?;
+    }
+
+    Ok(lines.len())
+}
+
+fn append_element(
+    document: &Document,
+    parent: NodeId,
+    tag: &str,
+    class: Option<&str>,
+) -> Result<NodeId> {
+    let id = document.create_node(NodeType::Element, tag.to_string())?;
+    if let Some(class) = class {
+        set_attribute(document, id, "class", class)?;
+    }
+    document.append_child(parent, id)?;
+    Ok(id)
+}
+
+fn append_text(document: &Document, parent: NodeId, text: &str) -> Result<NodeId> {
+    let id = document.create_node(NodeType::Text, text.to_string())?;
+    document.append_child(parent, id)?;
+    Ok(id)
+}
+
+fn set_attribute(document: &Document, node_id: NodeId, name: &str, value: &str) -> Result<()> {
+    let node = document
+        .get_node(node_id)
+        .ok_or_else(|| super::document::DocumentError::NodeNotFound(format!("{:?}", node_id)))?;
+    node.write().set_attribute(name, value);
+    Ok(())
+}
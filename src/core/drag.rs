@@ -0,0 +1,411 @@
+//! Drag-and-drop: the drag data store and the state of a drag in progress.
+//!
+//! A drag starts when the pointer, pressed on a draggable element, moves
+//! more than [`DRAG_THRESHOLD`] pixels, or when the embedder drops files on
+//! the page. The engine fires the DOM events; what they share lives here:
+//! the data store behind `event.dataTransfer`, the mode it is in for the
+//! event being fired, the current drop target and the operation it asked
+//! for. Only one drag runs at a time.
+
+use crate::core::dom::document::{Node, NodeType};
+use crate::core::dom::NodeId;
+use crate::sandbox::files::FileGrant;
+use parking_lot::Mutex;
+use serde::Serialize;
+use url::Url;
+
+/// How far, in CSS pixels, a pressed pointer moves before it drags.
+pub const DRAG_THRESHOLD: f32 = 4.0;
+
+/// What script may do with the data store during the current event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataTransferMode {
+    /// `dragstart`: data can be added and read.
+    ReadWrite,
+    /// `drop`: data and files can be read.
+    ReadOnly,
+    /// Everything else: only the formats are visible.
+    Protected,
+}
+
+/// A drag operation, as in `dropEffect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropEffect {
+    None,
+    Copy,
+    Move,
+    Link,
+}
+
+impl DropEffect {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" => Some(Self::None),
+            "copy" => Some(Self::Copy),
+            "move" => Some(Self::Move),
+            "link" => Some(Self::Link),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Copy => "copy",
+            Self::Move => "move",
+            Self::Link => "link",
+        }
+    }
+
+    /// The operation a target gets before it picks one, given the source's
+    /// `effectAllowed`.
+    pub fn default_for(effect_allowed: &str) -> Self {
+        match effect_allowed {
+            "none" => Self::None,
+            "move" => Self::Move,
+            "link" | "linkMove" => Self::Link,
+            _ => Self::Copy,
+        }
+    }
+
+    /// Whether a source allowing `effect_allowed` lets a target pick `self`.
+    pub fn allowed_by(self, effect_allowed: &str) -> bool {
+        match self {
+            Self::None => true,
+            Self::Copy => matches!(
+                effect_allowed,
+                "copy" | "copyLink" | "copyMove" | "all" | "uninitialized"
+            ),
+            Self::Move => matches!(
+                effect_allowed,
+                "move" | "copyMove" | "linkMove" | "all" | "uninitialized"
+            ),
+            Self::Link => matches!(
+                effect_allowed,
+                "link" | "copyLink" | "linkMove" | "all" | "uninitialized"
+            ),
+        }
+    }
+}
+
+const EFFECTS_ALLOWED: &[&str] = &[
+    "none",
+    "copy",
+    "copyLink",
+    "copyMove",
+    "link",
+    "linkMove",
+    "move",
+    "all",
+    "uninitialized",
+];
+
+/// A file from an external drop, as `dataTransfer.files` describes it.
+/// `grant` is what the page reads it through.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedFile {
+    pub grant: u64,
+    pub name: String,
+    pub size: u64,
+    #[serde(rename = "type")]
+    pub mime_type: String,
+    /// Milliseconds since the Unix epoch.
+    pub last_modified: u64,
+}
+
+impl From<FileGrant> for DroppedFile {
+    fn from(grant: FileGrant) -> Self {
+        Self {
+            grant: grant.id,
+            name: grant.name,
+            size: grant.size,
+            mime_type: grant.mime_type,
+            last_modified: grant.last_modified,
+        }
+    }
+}
+
+/// The drag data store: string items keyed by format, in the order they
+/// were set, plus any dropped files.
+#[derive(Debug, Clone)]
+pub struct DataTransfer {
+    items: Vec<(String, String)>,
+    files: Vec<DroppedFile>,
+    effect_allowed: String,
+    drop_effect: DropEffect,
+    mode: DataTransferMode,
+}
+
+impl DataTransfer {
+    pub fn new(effect_allowed: &str) -> Self {
+        Self {
+            items: Vec::new(),
+            files: Vec::new(),
+            effect_allowed: effect_allowed.to_string(),
+            drop_effect: DropEffect::None,
+            mode: DataTransferMode::Protected,
+        }
+    }
+
+    /// A store holding files dropped from outside the page.
+    pub fn with_files(files: Vec<DroppedFile>) -> Self {
+        Self {
+            files,
+            ..Self::new("uninitialized")
+        }
+    }
+
+    pub fn mode(&self) -> DataTransferMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DataTransferMode) {
+        self.mode = mode;
+    }
+
+    /// `getData(format)`: empty unless the data is readable in this event.
+    pub fn get_data(&self, format: &str) -> String {
+        if self.mode == DataTransferMode::Protected {
+            return String::new();
+        }
+        let format = normalize_format(format);
+        self.items
+            .iter()
+            .find(|(existing, _)| *existing == format)
+            .map(|(_, data)| data.clone())
+            .unwrap_or_default()
+    }
+
+    /// `setData(format, data)`; only `dragstart` may add data.
+    pub fn set_data(&mut self, format: &str, data: &str) -> bool {
+        if self.mode != DataTransferMode::ReadWrite {
+            return false;
+        }
+        let format = normalize_format(format);
+        match self
+            .items
+            .iter_mut()
+            .find(|(existing, _)| *existing == format)
+        {
+            Some(item) => item.1 = data.to_string(),
+            None => self.items.push((format, data.to_string())),
+        }
+        true
+    }
+
+    /// `clearData(format)`, or of every string item without a format.
+    pub fn clear_data(&mut self, format: Option<&str>) -> bool {
+        if self.mode != DataTransferMode::ReadWrite {
+            return false;
+        }
+        match format.map(normalize_format) {
+            Some(format) => self.items.retain(|(existing, _)| *existing != format),
+            None => self.items.clear(),
+        }
+        true
+    }
+
+    /// `types`: the item formats, then `Files` when there are files.
+    pub fn types(&self) -> Vec<String> {
+        let mut types: Vec<String> = self
+            .items
+            .iter()
+            .map(|(format, _)| format.clone())
+            .collect();
+        if !self.files.is_empty() {
+            types.push("Files".to_string());
+        }
+        types
+    }
+
+    /// `files`: empty unless the drop is being handled.
+    pub fn files(&self) -> &[DroppedFile] {
+        match self.mode {
+            DataTransferMode::Protected => &[],
+            _ => &self.files,
+        }
+    }
+
+    pub fn effect_allowed(&self) -> &str {
+        &self.effect_allowed
+    }
+
+    /// Only `dragstart` may change `effectAllowed`; unknown values are
+    /// ignored.
+    pub fn set_effect_allowed(&mut self, value: &str) {
+        if self.mode == DataTransferMode::ReadWrite && EFFECTS_ALLOWED.contains(&value) {
+            self.effect_allowed = value.to_string();
+        }
+    }
+
+    pub fn drop_effect(&self) -> DropEffect {
+        self.drop_effect
+    }
+
+    pub fn set_drop_effect(&mut self, effect: DropEffect) {
+        self.drop_effect = effect;
+    }
+}
+
+/// `text` and `url` are the legacy names of `text/plain` and
+/// `text/uri-list`; formats are case-insensitive.
+fn normalize_format(format: &str) -> String {
+    let format = format.trim().to_ascii_lowercase();
+    match format.as_str() {
+        "text" => "text/plain".to_string(),
+        "url" => "text/uri-list".to_string(),
+        _ => format,
+    }
+}
+
+/// Whether a press on `node` can start a drag: `draggable="true"`, or a
+/// link or image that does not opt out with `draggable="false"`.
+pub fn is_draggable(node: &Node) -> bool {
+    if node.node_type != NodeType::Element {
+        return false;
+    }
+    match node
+        .get_attribute("draggable")
+        .map(|v| v.to_ascii_lowercase())
+    {
+        Some(value) if value == "true" => true,
+        Some(value) if value == "false" => false,
+        _ => {
+            node.tag_name.eq_ignore_ascii_case("img")
+                || (node.tag_name.eq_ignore_ascii_case("a") && node.has_attribute("href"))
+        }
+    }
+}
+
+/// What dragging `node` carries before `dragstart` adds anything: the
+/// address of a link or image, resolved against `base`.
+pub fn default_items(node: &Node, base: Option<&Url>) -> Vec<(String, String)> {
+    let attribute = if node.tag_name.eq_ignore_ascii_case("a") {
+        "href"
+    } else if node.tag_name.eq_ignore_ascii_case("img") {
+        "src"
+    } else {
+        return Vec::new();
+    };
+    let raw = match node.get_attribute(attribute) {
+        Some(raw) => raw,
+        None => return Vec::new(),
+    };
+    let address = match base.and_then(|base| base.join(raw.trim()).ok()) {
+        Some(url) => url.to_string(),
+        None => raw.trim().to_string(),
+    };
+    vec![
+        ("text/uri-list".to_string(), address.clone()),
+        ("text/plain".to_string(), address),
+    ]
+}
+
+/// Where the ghost of a dragged element sits relative to the pointer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DragImage {
+    pub node: NodeId,
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+/// A drag in progress.
+#[derive(Debug, Clone)]
+pub struct DragSession {
+    /// The dragged element; `None` for files from outside the page.
+    pub source: Option<NodeId>,
+    pub transfer: DataTransfer,
+    /// The element the pointer is over, which gets `dragover` and `drop`.
+    pub target: Option<NodeId>,
+    /// What dropping now would do; `None` unless the target's last
+    /// `dragover` was canceled.
+    pub operation: DropEffect,
+    pub image: Option<DragImage>,
+    pub pointer: (f32, f32),
+}
+
+impl DragSession {
+    pub fn new(source: Option<NodeId>, transfer: DataTransfer, pointer: (f32, f32)) -> Self {
+        Self {
+            source,
+            transfer,
+            target: None,
+            operation: DropEffect::None,
+            image: None,
+            pointer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Press {
+    node: NodeId,
+    x: f32,
+    y: f32,
+}
+
+/// The engine's drag state: a press that may turn into a drag, and the drag
+/// itself. Script reaches the session's data store while events fire, so
+/// callers never hold it across script.
+#[derive(Default)]
+pub struct DragAndDrop {
+    press: Mutex<Option<Press>>,
+    session: Mutex<Option<DragSession>>,
+}
+
+impl DragAndDrop {
+    /// Remember a press on the draggable element `node`.
+    pub fn press(&self, node: NodeId, x: f32, y: f32) {
+        *self.press.lock() = Some(Press { node, x, y });
+    }
+
+    /// Forget the press, if any; the button is up.
+    pub fn release(&self) {
+        self.press.lock().take();
+    }
+
+    /// The element a pointer moved to `(x, y)` starts dragging, with where
+    /// it was pressed. The press is consumed.
+    pub fn crosses_threshold(&self, x: f32, y: f32) -> Option<(NodeId, f32, f32)> {
+        let mut press = self.press.lock();
+        let moved = press.is_some_and(|p| (x - p.x).hypot(y - p.y) > DRAG_THRESHOLD);
+        if !moved || self.session.lock().is_some() {
+            return None;
+        }
+        press.take().map(|p| (p.node, p.x, p.y))
+    }
+
+    pub fn start(&self, session: DragSession) {
+        *self.session.lock() = Some(session);
+    }
+
+    pub fn end(&self) -> Option<DragSession> {
+        self.session.lock().take()
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.session.lock().is_some()
+    }
+
+    /// A copy of the drag in progress.
+    pub fn session(&self) -> Option<DragSession> {
+        self.session.lock().clone()
+    }
+
+    /// Run `f` on the drag in progress.
+    pub fn update<R>(&self, f: impl FnOnce(&mut DragSession) -> R) -> Option<R> {
+        self.session.lock().as_mut().map(f)
+    }
+
+    /// Run `f` on the data store of the drag in progress.
+    pub fn with_transfer<R>(&self, f: impl FnOnce(&mut DataTransfer) -> R) -> Option<R> {
+        self.update(|session| f(&mut session.transfer))
+    }
+
+    /// Drop any press and drag; the document they belong to is going away.
+    pub fn reset(&self) {
+        self.press.lock().take();
+        self.session.lock().take();
+    }
+}
@@ -0,0 +1,762 @@
+//! Focus, caret and IME composition for editable elements.
+//!
+//! While an IME is composing, the composition string lives here rather than in
+//! the DOM: it is drawn inline at the caret with an underline and only written
+//! into the element's text when the IME commits. Every state change returns
+//! the DOM events it implies, in UI Events order, for the caller to dispatch.
+//!
+//! Text typed into a `contenteditable` element goes into the text node
+//! there through the document's character data primitives, so it grows
+//! rather than gaining siblings, and the caret is a live range of the
+//! document: it stays on the same character when that text is merged with
+//! its neighbours.
+//!
+//! A selection in a `contenteditable` element, as [`select`] or an editing
+//! command makes, is kept as char offsets into the text of all the text
+//! nodes below it, which formatting leaves as it is. While there is one,
+//! typing and deleting go there rather than to the caret.
+//!
+//! [`select`]: EditingSession::select
+
+use crate::core::dom::document::NodeType;
+use crate::core::dom::{DOMRange, Document, LiveRangeId, NodeId};
+use crate::ImeCompositionState;
+use serde_json::json;
+
+/// An event to fire at the focused element.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditingEvent {
+    pub target: NodeId,
+    pub event_type: &'static str,
+    pub data: Option<String>,
+    pub input_type: Option<&'static str>,
+    pub is_composing: bool,
+}
+
+impl EditingEvent {
+    fn composition(target: NodeId, event_type: &'static str, data: &str) -> Self {
+        Self {
+            target,
+            event_type,
+            data: Some(data.to_string()),
+            input_type: None,
+            is_composing: event_type != "compositionend",
+        }
+    }
+
+    fn input(
+        target: NodeId,
+        input_type: &'static str,
+        data: Option<&str>,
+        is_composing: bool,
+    ) -> Self {
+        Self {
+            target,
+            event_type: "input",
+            data: data.map(str::to_string),
+            input_type: Some(input_type),
+            is_composing,
+        }
+    }
+
+    /// The event's init dictionary as handed to JS.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.event_type,
+            "data": self.data,
+            "inputType": self.input_type,
+            "isComposing": self.is_composing,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Composition {
+    pub text: String,
+    /// IME cursor or selection within `text`, in bytes.
+    pub cursor_range: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaretMovement {
+    Left,
+    Right,
+}
+
+/// A selection in an editing host, in chars of [`host_text`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSelection {
+    pub anchor: usize,
+    pub focus: usize,
+    /// Whether a collapsed selection where one text node ends and the next
+    /// starts is at the start of the later one, as after a paragraph
+    /// break, rather than at the end of the earlier one.
+    pub downstream: bool,
+}
+
+impl TextSelection {
+    pub fn new(anchor: usize, focus: usize) -> Self {
+        Self {
+            anchor,
+            focus,
+            downstream: false,
+        }
+    }
+
+    pub fn collapsed(at: usize) -> Self {
+        Self::new(at, at)
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.focus)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.focus)
+    }
+
+    pub fn is_collapsed(&self) -> bool {
+        self.anchor == self.focus
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EditingSession {
+    focused: Option<NodeId>,
+    /// Insertion point in the focused element's text, in chars.
+    caret: usize,
+    /// The caret as a range the document keeps in place, in a
+    /// `contenteditable` element with text.
+    caret_range: Option<LiveRangeId>,
+    composition: Option<Composition>,
+    selection: Option<TextSelection>,
+    undo_units: u64,
+}
+
+impl EditingSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn focused(&self) -> Option<NodeId> {
+        self.focused
+    }
+
+    /// The caret as of the last edit or move; see [`Self::text_before_caret`]
+    /// for where it is now.
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    pub fn composition(&self) -> Option<&Composition> {
+        self.composition.as_ref()
+    }
+
+    /// Focus `node` with the caret at the end of its text. Returns `false`
+    /// (leaving focus unchanged) when `node` is not editable.
+    pub fn focus(&mut self, document: &Document, node: NodeId) -> bool {
+        if !is_editable(document, node) {
+            return false;
+        }
+        if let Some(range) = self.caret_range.take() {
+            document.untrack_range(range);
+        }
+        self.focused = Some(node);
+        self.composition = None;
+        self.selection = None;
+        self.set_caret(document, editable_text(document, node).chars().count());
+        true
+    }
+
+    /// Drop focus. A caret range still tracked goes with the document's
+    /// other ranges when it is torn down.
+    pub fn blur(&mut self) {
+        self.focused = None;
+        self.caret = 0;
+        self.caret_range = None;
+        self.composition = None;
+        self.selection = None;
+    }
+
+    /// The selection in the focused `contenteditable` element, if one was
+    /// made since it was focused.
+    pub fn selection(&self) -> Option<TextSelection> {
+        self.selection
+    }
+
+    /// The selection, or the caret as a collapsed one, in the focused
+    /// `contenteditable` element.
+    pub fn selection_or_caret(&self, document: &Document) -> Option<TextSelection> {
+        let target = self.editing_host(document)?;
+        if let Some(selection) = self.selection {
+            return Some(selection);
+        }
+        let before: usize = match last_text_child(document, target) {
+            Some(text) => text_segments(document, target)
+                .iter()
+                .take_while(|segment| segment.node != text)
+                .map(|segment| segment.len)
+                .sum(),
+            None => host_text(document, target).chars().count(),
+        };
+        Some(TextSelection::collapsed(
+            before + self.current_caret(document),
+        ))
+    }
+
+    /// Select chars `anchor` to `focus` of the focused `contenteditable`
+    /// element's text, clamped to it. Returns `false` (selecting nothing)
+    /// without one, or while an IME composes.
+    pub fn select(&mut self, document: &Document, anchor: usize, focus: usize) -> bool {
+        let target = match self.editing_host(document) {
+            Some(target) if self.composition.is_none() => target,
+            _ => return false,
+        };
+        let len = host_text(document, target).chars().count();
+        self.selection = Some(TextSelection::new(anchor.min(len), focus.min(len)));
+        true
+    }
+
+    /// Select all the text of the focused `contenteditable` element.
+    pub fn select_all(&mut self, document: &Document) -> bool {
+        self.select(document, 0, usize::MAX)
+    }
+
+    /// Replace the selection, as an editing command leaves it.
+    pub fn set_selection(&mut self, selection: TextSelection) {
+        self.selection = Some(selection);
+    }
+
+    /// The selected text; empty when the selection is collapsed or there
+    /// is none.
+    pub fn selected_text(&self, document: &Document) -> String {
+        match (self.editing_host(document), self.selection) {
+            (Some(target), Some(selection)) => host_text(document, target)
+                .chars()
+                .skip(selection.start())
+                .take(selection.end() - selection.start())
+                .collect(),
+            _ => String::new(),
+        }
+    }
+
+    /// The focused element when it is a `contenteditable` one.
+    pub fn editing_host(&self, document: &Document) -> Option<NodeId> {
+        self.focused
+            .filter(|&target| is_editable(document, target) && !is_form_control(document, target))
+    }
+
+    /// End the undo unit of one editing command. There is no undo stack
+    /// yet; when there is one, a unit is what a single undo takes back.
+    pub fn end_undo_unit(&mut self) {
+        self.undo_units += 1;
+    }
+
+    /// The undo units ended so far.
+    pub fn undo_units(&self) -> u64 {
+        self.undo_units
+    }
+
+    /// Apply one IME transition to the focused element. Without focus, or for
+    /// an update/commit/cancel with nothing to act on, nothing happens.
+    pub fn compose(
+        &mut self,
+        document: &Document,
+        state: ImeCompositionState,
+    ) -> Vec<EditingEvent> {
+        let target = match self.focused {
+            Some(target) => target,
+            None => return Vec::new(),
+        };
+        let mut events = Vec::new();
+
+        match state {
+            ImeCompositionState::Start => {
+                if self.composition.is_none() {
+                    self.composition = Some(Composition::default());
+                    events.push(EditingEvent::composition(target, "compositionstart", ""));
+                }
+            }
+            ImeCompositionState::Update { text, cursor_range } => {
+                if self.composition.is_none() {
+                    if text.is_empty() {
+                        return events;
+                    }
+                    events.push(EditingEvent::composition(target, "compositionstart", ""));
+                }
+                events.push(EditingEvent::composition(
+                    target,
+                    "compositionupdate",
+                    &text,
+                ));
+                events.push(EditingEvent::input(
+                    target,
+                    "insertCompositionText",
+                    Some(&text),
+                    true,
+                ));
+                self.composition = Some(Composition { text, cursor_range });
+            }
+            ImeCompositionState::Commit { text } => {
+                if self.composition.is_none() {
+                    events.push(EditingEvent::composition(target, "compositionstart", ""));
+                }
+                self.composition = None;
+                self.write_text(document, target, &text);
+
+                events.push(EditingEvent::composition(
+                    target,
+                    "compositionupdate",
+                    &text,
+                ));
+                events.push(EditingEvent::input(
+                    target,
+                    "insertFromComposition",
+                    Some(&text),
+                    true,
+                ));
+                events.push(EditingEvent::composition(target, "compositionend", &text));
+            }
+            ImeCompositionState::Cancel => {
+                if self.composition.take().is_some() {
+                    events.push(EditingEvent::composition(target, "compositionupdate", ""));
+                    events.push(EditingEvent::composition(target, "compositionend", ""));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Type `text` at the caret of the focused element. Keys go to the IME
+    /// while it composes, so nothing happens then, or without focus.
+    pub fn insert_text(&mut self, document: &Document, text: &str) -> Vec<EditingEvent> {
+        self.insert(document, text, "insertText")
+    }
+
+    /// Insert pasted `text` at the caret of the focused element.
+    pub fn paste_text(&mut self, document: &Document, text: &str) -> Vec<EditingEvent> {
+        self.insert(document, text, "insertFromPaste")
+    }
+
+    fn insert(
+        &mut self,
+        document: &Document,
+        text: &str,
+        input_type: &'static str,
+    ) -> Vec<EditingEvent> {
+        let target = match self.focused {
+            Some(target) if self.composition.is_none() && !text.is_empty() => target,
+            _ => return Vec::new(),
+        };
+        self.write_text(document, target, text);
+        vec![EditingEvent::input(target, input_type, Some(text), false)]
+    }
+
+    /// Put `text` in place of the selection, or at the caret without one.
+    fn write_text(&mut self, document: &Document, target: NodeId, text: &str) {
+        if let Some(selection) = self.selection {
+            delete_host_text(document, target, selection.start(), selection.end());
+            insert_host_text(document, target, selection, text);
+            self.selection = Some(TextSelection::collapsed(
+                selection.start() + text.chars().count(),
+            ));
+            return;
+        }
+        let caret = self.current_caret(document);
+        insert_editable_text(document, target, caret, text);
+        self.set_caret(document, caret + text.chars().count());
+    }
+
+    /// Insert the pasted `element` at the caret of the focused
+    /// `contenteditable` element, splitting the text there; the text after
+    /// the caret becomes the one edited from then on, with the caret at its
+    /// start. Form controls only take text, so nothing happens for them.
+    pub fn paste_element(&mut self, document: &Document, element: NodeId) -> Vec<EditingEvent> {
+        let target = match self.focused {
+            Some(target) if self.composition.is_none() && !is_form_control(document, target) => {
+                target
+            }
+            _ => return Vec::new(),
+        };
+        let caret = self.current_caret(document);
+        // Between the two halves of the split text, or last in an element
+        // without any, before the empty text edited from then on.
+        let inserted = match last_text_child(document, target) {
+            Some(text) => document
+                .split_text(text, caret)
+                .and_then(|after| document.insert_before(target, element, Some(after))),
+            None => document.append_child(target, element).and_then(|()| {
+                let after = document.create_node(NodeType::Text, String::new())?;
+                document.append_child(target, after)
+            }),
+        };
+        if inserted.is_err() {
+            return Vec::new();
+        }
+        self.set_caret(document, 0);
+        vec![EditingEvent::input(target, "insertFromPaste", None, false)]
+    }
+
+    /// Delete the character before the caret, or the selected text, as
+    /// Backspace does.
+    pub fn delete_backward(&mut self, document: &Document) -> Vec<EditingEvent> {
+        if let Some(selection) = self.selection {
+            let target = match self.focused {
+                Some(target) if self.composition.is_none() => target,
+                _ => return Vec::new(),
+            };
+            let start = match selection.is_collapsed() {
+                true if selection.start() == 0 => return Vec::new(),
+                true => selection.start() - 1,
+                false => selection.start(),
+            };
+            delete_host_text(document, target, start, selection.end());
+            self.selection = Some(TextSelection::collapsed(start));
+            return vec![EditingEvent::input(
+                target,
+                "deleteContentBackward",
+                None,
+                false,
+            )];
+        }
+        let caret = self.current_caret(document);
+        let target = match self.focused {
+            Some(target) if self.composition.is_none() && caret > 0 => target,
+            _ => return Vec::new(),
+        };
+        delete_editable_text(document, target, caret - 1);
+        self.set_caret(document, caret - 1);
+        vec![EditingEvent::input(
+            target,
+            "deleteContentBackward",
+            None,
+            false,
+        )]
+    }
+
+    /// Move the caret one step. While composing the composition is a single
+    /// unit: the IME cursor can only sit at either edge of it, and the
+    /// committed caret does not move.
+    pub fn move_caret(&mut self, document: &Document, movement: CaretMovement) {
+        if let Some(composition) = &mut self.composition {
+            let edge = match movement {
+                CaretMovement::Left => 0,
+                CaretMovement::Right => composition.text.len(),
+            };
+            composition.cursor_range = Some((edge, edge));
+            return;
+        }
+
+        let target = match self.focused {
+            Some(target) => target,
+            None => return,
+        };
+        // A selection collapses to the edge moved toward, or moves a char.
+        if let Some(selection) = self.selection {
+            let len = host_text(document, target).chars().count();
+            let at = match (movement, selection.is_collapsed()) {
+                (CaretMovement::Left, true) => selection.start().saturating_sub(1),
+                (CaretMovement::Right, true) => (selection.end() + 1).min(len),
+                (CaretMovement::Left, false) => selection.start(),
+                (CaretMovement::Right, false) => selection.end(),
+            };
+            self.selection = Some(TextSelection::collapsed(at));
+            return;
+        }
+        let len = editable_text(document, target).chars().count();
+        let caret = self.current_caret(document);
+        let caret = match movement {
+            CaretMovement::Left => caret.saturating_sub(1),
+            CaretMovement::Right => (caret + 1).min(len),
+        };
+        self.set_caret(document, caret);
+    }
+
+    /// Text before the visual caret: committed text up to the caret plus the
+    /// composition up to its IME cursor.
+    pub fn text_before_caret(&self, document: &Document) -> String {
+        let mut before = self.text_before_composition(document);
+        if let Some(composition) = &self.composition {
+            before.push_str(&composition.text[..composition_cursor(composition)]);
+        }
+        before
+    }
+
+    /// Committed text up to the caret, where the composition is drawn.
+    pub fn text_before_composition(&self, document: &Document) -> String {
+        match (self.focused, self.selection) {
+            (Some(target), Some(selection)) => host_text(document, target)
+                .chars()
+                .take(selection.focus)
+                .collect(),
+            (Some(target), None) => editable_text(document, target)
+                .chars()
+                .take(self.current_caret(document))
+                .collect(),
+            (None, _) => String::new(),
+        }
+    }
+
+    /// Where the caret is now: where its range was moved to in the text
+    /// edited, or where it was last put.
+    fn current_caret(&self, document: &Document) -> usize {
+        let Some(target) = self.focused else {
+            return self.caret;
+        };
+        let text = last_text_child(document, target);
+        let tracked = self
+            .caret_range
+            .and_then(|id| document.live_range(id))
+            .filter(|range| text == Some(range.start_container()))
+            .map(|range| range.start_offset() as usize);
+        let len = editable_text(document, target).chars().count();
+        tracked.unwrap_or(self.caret).min(len)
+    }
+
+    /// Put the caret `caret` chars into the focused element's text.
+    fn set_caret(&mut self, document: &Document, caret: usize) {
+        self.caret = caret;
+        let text = match self.focused {
+            Some(target) if !is_form_control(document, target) => last_text_child(document, target),
+            _ => None,
+        };
+        let Some(text) = text else {
+            return;
+        };
+        let mut range = DOMRange::new();
+        if range.set_start(text, caret as u32).is_err() {
+            return;
+        }
+        range.collapse(true);
+        match self.caret_range {
+            Some(id) if document.set_live_range(id, range.clone()) => {}
+            _ => self.caret_range = Some(document.track_range(range)),
+        }
+    }
+}
+
+fn composition_cursor(composition: &Composition) -> usize {
+    let end = composition
+        .cursor_range
+        .map_or(composition.text.len(), |(_, end)| end)
+        .min(composition.text.len());
+    (0..=end)
+        .rev()
+        .find(|&index| composition.text.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+fn char_to_byte(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(index, _)| index)
+}
+
+/// `<input>`, `<textarea>` and `contenteditable` elements accept text input.
+pub fn is_editable(document: &Document, node: NodeId) -> bool {
+    let node = match document.get_node(node) {
+        Some(node) => node,
+        None => return false,
+    };
+    let node = node.read();
+    if node.node_type != NodeType::Element {
+        return false;
+    }
+    if node.tag_name.eq_ignore_ascii_case("input") || node.tag_name.eq_ignore_ascii_case("textarea")
+    {
+        return true;
+    }
+    match node.get_attribute("contenteditable") {
+        Some(value) => !value.eq_ignore_ascii_case("false"),
+        None => false,
+    }
+}
+
+/// `<input>` and `<textarea>`, which edit a `value` of plain text.
+pub fn is_form_control(document: &Document, node: NodeId) -> bool {
+    document.get_node(node).is_some_and(|node| {
+        let node = node.read();
+        node.tag_name.eq_ignore_ascii_case("input")
+            || node.tag_name.eq_ignore_ascii_case("textarea")
+    })
+}
+
+/// The text an editing host edits: a form control's `value`, otherwise its
+/// last text child.
+pub fn editable_text(document: &Document, node: NodeId) -> String {
+    if is_form_control(document, node) {
+        return document
+            .get_node(node)
+            .and_then(|node| node.read().get_attribute("value"))
+            .unwrap_or_default();
+    }
+    last_text_child(document, node)
+        .and_then(|text| document.get_node(text))
+        .map(|text| text.read().get_text_content())
+        .unwrap_or_default()
+}
+
+/// Insert `text` `offset` chars into the text `node` edits.
+fn insert_editable_text(document: &Document, node: NodeId, offset: usize, text: &str) {
+    if is_form_control(document, node) {
+        let current = editable_text(document, node);
+        let split = char_to_byte(&current, offset);
+        let value = format!("{}{}{}", &current[..split], text, &current[split..]);
+        let _ = document.set_attribute(node, "value", &value);
+        return;
+    }
+    let _ = match last_text_child(document, node) {
+        Some(edited) => document.insert_data(edited, offset, text),
+        None => document.append_text(node, text).map(|_| ()),
+    };
+}
+
+/// Delete the char `offset` chars into the text `node` edits.
+fn delete_editable_text(document: &Document, node: NodeId, offset: usize) {
+    if is_form_control(document, node) {
+        let current = editable_text(document, node);
+        let start = char_to_byte(&current, offset);
+        let end = char_to_byte(&current, offset + 1);
+        let value = format!("{}{}", &current[..start], &current[end..]);
+        let _ = document.set_attribute(node, "value", &value);
+        return;
+    }
+    if let Some(edited) = last_text_child(document, node) {
+        let _ = document.delete_data(edited, offset, 1);
+    }
+}
+
+fn last_text_child(document: &Document, node: NodeId) -> Option<NodeId> {
+    document.get_children(node).into_iter().rev().find(|child| {
+        document
+            .get_node(*child)
+            .is_some_and(|child| child.read().is_text())
+    })
+}
+
+/// A text node below an editing host, and where its text lies in the
+/// host's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSegment {
+    pub node: NodeId,
+    pub start: usize,
+    pub len: usize,
+}
+
+impl TextSegment {
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+/// The text nodes below `host`, in tree order.
+pub fn text_segments(document: &Document, host: NodeId) -> Vec<TextSegment> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut stack: Vec<NodeId> = document.get_children(host).into_iter().rev().collect();
+    while let Some(id) = stack.pop() {
+        let Some(node) = document.get_node(id) else {
+            continue;
+        };
+        let node = node.read();
+        if node.is_text() {
+            let len = node.text_content.chars().count();
+            segments.push(TextSegment {
+                node: id,
+                start,
+                len,
+            });
+            start += len;
+        } else {
+            stack.extend(node.children.iter().rev().copied());
+        }
+    }
+    segments
+}
+
+/// The text of all the text nodes below `host`, which selections count in.
+pub fn host_text(document: &Document, host: NodeId) -> String {
+    text_segments(document, host)
+        .into_iter()
+        .filter_map(|segment| document.get_node(segment.node))
+        .map(|node| node.read().text_content.clone())
+        .collect()
+}
+
+/// The text node and offset in it where `selection` starts: the first node
+/// touching that point, or the last with `downstream`.
+pub fn text_position(
+    segments: &[TextSegment],
+    selection: TextSelection,
+) -> Option<(NodeId, usize)> {
+    let at = selection.start();
+    let mut touching = segments
+        .iter()
+        .filter(|segment| segment.start <= at && at <= segment.end());
+    let segment = match selection.downstream {
+        true => touching.next_back(),
+        false => touching.next(),
+    }?;
+    Some((segment.node, at - segment.start))
+}
+
+/// Insert `text` in `host` where `selection` starts.
+pub fn insert_host_text(document: &Document, host: NodeId, selection: TextSelection, text: &str) {
+    let _ = match text_position(&text_segments(document, host), selection) {
+        Some((node, offset)) => document.insert_data(node, offset, text),
+        None => document.append_text(host, text).map(|_| ()),
+    };
+}
+
+/// Delete chars `start` to `end` of `host`'s text, and the text nodes and
+/// formatting that leaves empty.
+pub fn delete_host_text(document: &Document, host: NodeId, start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    for segment in text_segments(document, host) {
+        let from = start.max(segment.start);
+        let to = end.min(segment.end());
+        if from < to {
+            let _ = document.delete_data(segment.node, from - segment.start, to - from);
+        }
+    }
+    prune_empty(document, host);
+}
+
+/// Inline elements that do nothing but format the text in them.
+pub const FORMATTING_TAGS: &[&str] = &[
+    "b", "strong", "i", "em", "u", "s", "strike", "sub", "sup", "font", "big", "small", "tt",
+];
+
+/// Whether `node` is an element with one of `tags`.
+pub fn is_formatting_element(document: &Document, node: NodeId, tags: &[&str]) -> bool {
+    document.get_node(node).is_some_and(|node| {
+        let node = node.read();
+        node.is_element()
+            && tags
+                .iter()
+                .any(|tag| node.tag_name.eq_ignore_ascii_case(tag))
+    })
+}
+
+/// Drop the formatting elements below `host` left without text, and
+/// merge the text nodes left side by side.
+pub fn prune_empty(document: &Document, host: NodeId) {
+    let mut stack = document.get_children(host);
+    while let Some(id) = stack.pop() {
+        if !is_formatting_element(document, id, FORMATTING_TAGS) {
+            stack.extend(document.get_children(id));
+            continue;
+        }
+        if host_text(document, id).is_empty() {
+            if let Some(parent) = document.get_parent(id) {
+                let _ = document.remove_child(parent, id);
+            }
+        } else {
+            stack.extend(document.get_children(id));
+        }
+    }
+    let _ = document.normalize(host);
+}
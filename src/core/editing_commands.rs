@@ -0,0 +1,531 @@
+//! `document.execCommand()`: the editing commands editor libraries still
+//! call, over the focused `contenteditable` element and its selection.
+//!
+//! Formatting wraps the selected text in `<b>`, `<i>` or `<u>`, or, when
+//! all of it already is (`<strong>` and `<em>` count), takes it out of
+//! those elements, splitting them where the selection starts and ends.
+//! Formatting elements left side by side with the same attributes merge
+//! into one, and empty ones go. Deleting works on chars: a paragraph break
+//! is none, so deleting at the start of a paragraph takes the last char of
+//! the one before rather than joining the two.
+//!
+//! The JS half fires `beforeinput` and `input` with
+//! [`Command::input_type`] around [`execute`], and `copy`, `cut` and
+//! `paste` before the clipboard commands, which need user activation. Each
+//! command that changes the text ends an undo unit.
+
+use crate::core::clipboard::{self, Clipboard, ClipboardData, ClipboardItem};
+use crate::core::dom::document::NodeType;
+use crate::core::dom::{Document, NodeId};
+use crate::core::editing::{
+    delete_host_text, host_text, insert_host_text, is_formatting_element, prune_empty,
+    text_position, text_segments, EditingSession, TextSelection, FORMATTING_TAGS,
+};
+
+const BOLD_TAGS: &[&str] = &["b", "strong"];
+const ITALIC_TAGS: &[&str] = &["i", "em"];
+const UNDERLINE_TAGS: &[&str] = &["u"];
+/// Elements `insertParagraph` splits in two.
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "li",
+    "blockquote",
+    "pre",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Bold,
+    Italic,
+    Underline,
+    InsertText,
+    InsertParagraph,
+    Delete,
+    ForwardDelete,
+    SelectAll,
+    RemoveFormat,
+    Copy,
+    Cut,
+    Paste,
+}
+
+impl Command {
+    pub const ALL: [Command; 12] = [
+        Command::Bold,
+        Command::Italic,
+        Command::Underline,
+        Command::InsertText,
+        Command::InsertParagraph,
+        Command::Delete,
+        Command::ForwardDelete,
+        Command::SelectAll,
+        Command::RemoveFormat,
+        Command::Copy,
+        Command::Cut,
+        Command::Paste,
+    ];
+
+    /// The command named `name`, in any case, as `execCommand` takes it.
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|command| command.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Bold => "bold",
+            Command::Italic => "italic",
+            Command::Underline => "underline",
+            Command::InsertText => "insertText",
+            Command::InsertParagraph => "insertParagraph",
+            Command::Delete => "delete",
+            Command::ForwardDelete => "forwardDelete",
+            Command::SelectAll => "selectAll",
+            Command::RemoveFormat => "removeFormat",
+            Command::Copy => "copy",
+            Command::Cut => "cut",
+            Command::Paste => "paste",
+        }
+    }
+
+    /// The `inputType` of the `beforeinput` and `input` events the command
+    /// fires; `None` for those that leave the text as it is.
+    pub fn input_type(self) -> Option<&'static str> {
+        match self {
+            Command::Bold => Some("formatBold"),
+            Command::Italic => Some("formatItalic"),
+            Command::Underline => Some("formatUnderline"),
+            Command::InsertText => Some("insertText"),
+            Command::InsertParagraph => Some("insertParagraph"),
+            Command::Delete => Some("deleteContentBackward"),
+            Command::ForwardDelete => Some("deleteContentForward"),
+            Command::RemoveFormat => Some("formatRemove"),
+            Command::Cut => Some("deleteByCut"),
+            Command::Paste => Some("insertFromPaste"),
+            Command::SelectAll | Command::Copy => None,
+        }
+    }
+
+    /// Whether the command reaches the clipboard, which only a user
+    /// gesture lets a page do.
+    pub fn needs_user_activation(self) -> bool {
+        matches!(self, Command::Copy | Command::Cut | Command::Paste)
+    }
+
+    /// The element the command wraps text in, and the ones that count as
+    /// that formatting.
+    fn formatting(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Command::Bold => Some(("b", BOLD_TAGS)),
+            Command::Italic => Some(("i", ITALIC_TAGS)),
+            Command::Underline => Some(("u", UNDERLINE_TAGS)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether `command` would do anything now, as `queryCommandEnabled`
+/// answers.
+pub fn is_enabled(
+    session: &EditingSession,
+    document: &Document,
+    clipboard: &Clipboard,
+    command: Command,
+) -> bool {
+    let (host, selection) = match target(session, document) {
+        Some(target) => target,
+        None => return false,
+    };
+    match command {
+        Command::Bold
+        | Command::Italic
+        | Command::Underline
+        | Command::RemoveFormat
+        | Command::Copy
+        | Command::Cut => !selection.is_collapsed(),
+        Command::Delete => !selection.is_collapsed() || selection.start() > 0,
+        Command::ForwardDelete => {
+            !selection.is_collapsed() || selection.end() < host_text(document, host).chars().count()
+        }
+        Command::Paste => pasted_text(clipboard).is_some(),
+        Command::InsertText | Command::InsertParagraph | Command::SelectAll => true,
+    }
+}
+
+/// Whether the selection has `command`'s formatting, as
+/// `queryCommandState` answers: all of the selected text, or the text
+/// before a collapsed selection. `false` for commands that do not format.
+pub fn state(session: &EditingSession, document: &Document, command: Command) -> bool {
+    match (command.formatting(), target(session, document)) {
+        (Some((_, tags)), Some((host, selection))) => {
+            has_formatting(document, host, selection, tags)
+        }
+        _ => false,
+    }
+}
+
+/// Run `command` with `value`, for `insertText` the text, on the focused
+/// `contenteditable` element. Returns `false` when it did nothing.
+pub fn execute(
+    session: &mut EditingSession,
+    document: &Document,
+    clipboard: &Clipboard,
+    command: Command,
+    value: &str,
+) -> bool {
+    if !is_enabled(session, document, clipboard, command) {
+        return false;
+    }
+    let Some((host, selection)) = target(session, document) else {
+        return false;
+    };
+    let (start, end) = (selection.start(), selection.end());
+    let selection = match command {
+        Command::Bold | Command::Italic | Command::Underline => {
+            if let Some((tag, tags)) = command.formatting() {
+                if has_formatting(document, host, selection, tags) {
+                    remove_formatting(document, host, start, end, tags);
+                } else {
+                    apply_formatting(document, host, start, end, tag, tags);
+                }
+            }
+            selection
+        }
+        Command::RemoveFormat => {
+            remove_formatting(document, host, start, end, FORMATTING_TAGS);
+            selection
+        }
+        Command::InsertText => replace_selection(document, host, selection, value),
+        Command::Paste => {
+            let text = pasted_text(clipboard).unwrap_or_default();
+            replace_selection(document, host, selection, &text)
+        }
+        Command::InsertParagraph => insert_paragraph(document, host, selection),
+        Command::Delete | Command::ForwardDelete | Command::Cut => {
+            let (start, end) = match (command, selection.is_collapsed()) {
+                (Command::Delete, true) => (start - 1, end),
+                (Command::ForwardDelete, true) => (start, end + 1),
+                _ => (start, end),
+            };
+            if command == Command::Cut {
+                copy_selection(session, document, clipboard);
+            }
+            delete_host_text(document, host, start, end);
+            TextSelection::collapsed(start)
+        }
+        Command::SelectAll => TextSelection::new(0, host_text(document, host).chars().count()),
+        Command::Copy => {
+            copy_selection(session, document, clipboard);
+            selection
+        }
+    };
+    session.set_selection(selection);
+    if command.input_type().is_some() {
+        session.end_undo_unit();
+    }
+    true
+}
+
+/// The focused `contenteditable` element and its selection, unless an IME
+/// is composing there.
+fn target(session: &EditingSession, document: &Document) -> Option<(NodeId, TextSelection)> {
+    if session.composition().is_some() {
+        return None;
+    }
+    let host = session.editing_host(document)?;
+    Some((host, session.selection_or_caret(document)?))
+}
+
+fn pasted_text(clipboard: &Clipboard) -> Option<String> {
+    match clipboard.find(clipboard::TEXT_PLAIN) {
+        Some(ClipboardData::Text(text)) => Some(text),
+        _ => None,
+    }
+}
+
+fn copy_selection(session: &EditingSession, document: &Document, clipboard: &Clipboard) {
+    let text = session.selected_text(document);
+    if !text.is_empty() {
+        clipboard.write(vec![ClipboardItem::text(text)]);
+    }
+}
+
+fn replace_selection(
+    document: &Document,
+    host: NodeId,
+    selection: TextSelection,
+    text: &str,
+) -> TextSelection {
+    delete_host_text(document, host, selection.start(), selection.end());
+    insert_host_text(document, host, selection, text);
+    TextSelection::collapsed(selection.start() + text.chars().count())
+}
+
+fn has_formatting(
+    document: &Document,
+    host: NodeId,
+    selection: TextSelection,
+    tags: &[&str],
+) -> bool {
+    let segments = text_segments(document, host);
+    if selection.is_collapsed() {
+        return text_position(&segments, selection)
+            .is_some_and(|(node, _)| ancestor_with(document, host, node, tags).is_some());
+    }
+    let mut selected = segments
+        .iter()
+        .filter(|segment| segment.start < selection.end() && selection.start() < segment.end())
+        .peekable();
+    selected.peek().is_some()
+        && selected.all(|segment| ancestor_with(document, host, segment.node, tags).is_some())
+}
+
+fn apply_formatting(
+    document: &Document,
+    host: NodeId,
+    start: usize,
+    end: usize,
+    tag: &str,
+    tags: &[&str],
+) {
+    for node in isolate(document, host, start, end) {
+        if ancestor_with(document, host, node, tags).is_none() {
+            wrap(document, node, tag);
+        }
+    }
+    tidy(document, host);
+}
+
+fn remove_formatting(document: &Document, host: NodeId, start: usize, end: usize, tags: &[&str]) {
+    for node in isolate(document, host, start, end) {
+        while let Some(element) = ancestor_with(document, host, node, tags) {
+            split_off_before(document, node, element);
+            split_off_after(document, node, element);
+            unwrap(document, element);
+        }
+    }
+    tidy(document, host);
+}
+
+/// Break the paragraph at the start of `selection`, after deleting what it
+/// selects: the block holding it splits in two, or, for text straight in
+/// `host`, what follows moves into a `<div>`. The selection goes to the
+/// start of the new paragraph.
+fn insert_paragraph(document: &Document, host: NodeId, selection: TextSelection) -> TextSelection {
+    delete_host_text(document, host, selection.start(), selection.end());
+    let at = TextSelection {
+        downstream: selection.downstream,
+        ..TextSelection::collapsed(selection.start())
+    };
+    let after = match text_position(&text_segments(document, host), at) {
+        Some((node, offset)) => document.split_text(node, offset).ok(),
+        None => document
+            .create_node(NodeType::Text, String::new())
+            .ok()
+            .filter(|&text| document.append_child(host, text).is_ok()),
+    };
+    let Some(after) = after else {
+        return at;
+    };
+
+    match ancestor_with(document, host, after, BLOCK_TAGS) {
+        Some(block) => {
+            // At the very start of the block, the paragraph before is empty.
+            if split_off_before(document, after, block).is_none() {
+                if let (Some(parent), Some(copy)) =
+                    (document.get_parent(block), shallow_copy(document, block))
+                {
+                    let _ = document.insert_before(parent, copy, Some(block));
+                }
+            }
+        }
+        None => {
+            let mut top = after;
+            while let Some(parent) = document.get_parent(top).filter(|&parent| parent != host) {
+                top = parent;
+            }
+            if top != after {
+                split_off_before(document, after, top);
+            }
+            if let Ok(div) = document.create_node(NodeType::Element, "div".to_string()) {
+                let children = document.get_children(host);
+                let index = children.iter().position(|&id| id == top).unwrap_or(0);
+                let _ = document.insert_before(host, div, Some(top));
+                for child in children[index..]
+                    .iter()
+                    .take_while(|&&child| !is_formatting_element(document, child, BLOCK_TAGS))
+                {
+                    let _ = document.append_child(div, *child);
+                }
+            }
+        }
+    }
+    TextSelection {
+        downstream: true,
+        ..TextSelection::collapsed(selection.start())
+    }
+}
+
+/// Merge formatting elements left side by side below `host`, drop empty
+/// ones and merge the text nodes that leaves adjacent.
+fn tidy(document: &Document, host: NodeId) {
+    merge_adjacent(document, host);
+    prune_empty(document, host);
+}
+
+fn merge_adjacent(document: &Document, parent: NodeId) {
+    let mut previous: Option<NodeId> = None;
+    for child in document.get_children(parent) {
+        if let Some(previous) =
+            previous.filter(|&previous| same_formatting(document, previous, child))
+        {
+            for grandchild in document.get_children(child) {
+                let _ = document.append_child(previous, grandchild);
+            }
+            let _ = document.remove_child(parent, child);
+            continue;
+        }
+        previous = Some(child);
+    }
+    for child in document.get_children(parent) {
+        merge_adjacent(document, child);
+    }
+}
+
+fn same_formatting(document: &Document, a: NodeId, b: NodeId) -> bool {
+    if !is_formatting_element(document, a, FORMATTING_TAGS)
+        || !is_formatting_element(document, b, FORMATTING_TAGS)
+    {
+        return false;
+    }
+    match (document.get_node(a), document.get_node(b)) {
+        (Some(a), Some(b)) => {
+            let (a, b) = (a.read(), b.read());
+            a.tag_name.eq_ignore_ascii_case(&b.tag_name) && a.attributes == b.attributes
+        }
+        _ => false,
+    }
+}
+
+/// Split the text nodes of `host` where chars `start` and `end` fall, and
+/// return those between them.
+fn isolate(document: &Document, host: NodeId, start: usize, end: usize) -> Vec<NodeId> {
+    for at in [end, start] {
+        let split = text_segments(document, host)
+            .into_iter()
+            .find(|segment| segment.start < at && at < segment.end());
+        if let Some(segment) = split {
+            let _ = document.split_text(segment.node, at - segment.start);
+        }
+    }
+    text_segments(document, host)
+        .into_iter()
+        .filter(|segment| segment.len > 0 && segment.start >= start && segment.end() <= end)
+        .map(|segment| segment.node)
+        .collect()
+}
+
+/// The nearest element holding `node` below `host` with one of `tags`.
+fn ancestor_with(document: &Document, host: NodeId, node: NodeId, tags: &[&str]) -> Option<NodeId> {
+    let mut current = document.get_parent(node);
+    while let Some(id) = current.filter(|&id| id != host) {
+        if is_formatting_element(document, id, tags) {
+            return Some(id);
+        }
+        current = document.get_parent(id);
+    }
+    None
+}
+
+fn wrap(document: &Document, node: NodeId, tag: &str) {
+    let (Some(parent), Ok(element)) = (
+        document.get_parent(node),
+        document.create_node(NodeType::Element, tag.to_string()),
+    ) else {
+        return;
+    };
+    let _ = document.insert_before(parent, element, Some(node));
+    let _ = document.append_child(element, node);
+}
+
+/// Put `element`'s children where it is.
+fn unwrap(document: &Document, element: NodeId) {
+    let Some(parent) = document.get_parent(element) else {
+        return;
+    };
+    for child in document.get_children(element) {
+        let _ = document.insert_before(parent, child, Some(element));
+    }
+    let _ = document.remove_child(parent, element);
+}
+
+/// An element like `element`, empty and without its `id`.
+fn shallow_copy(document: &Document, element: NodeId) -> Option<NodeId> {
+    let (tag, attributes) = {
+        let element = document.get_node(element)?;
+        let element = element.read();
+        (element.tag_name.clone(), element.attributes.clone())
+    };
+    let copy = document.create_node(NodeType::Element, tag).ok()?;
+    for (name, value) in attributes.iter().filter(|(name, _)| *name != "id") {
+        let _ = document.set_attribute(copy, name, value);
+    }
+    Some(copy)
+}
+
+/// Move what comes before `node` inside `ancestor` into copies of the
+/// elements holding it, each placed before its original. Returns the copy
+/// of `ancestor`, when there was anything before `node` to move.
+fn split_off_before(document: &Document, node: NodeId, ancestor: NodeId) -> Option<NodeId> {
+    split_off(document, node, ancestor, false)
+}
+
+/// Move what comes after `node` inside `ancestor` into copies of the
+/// elements holding it, each placed after its original.
+fn split_off_after(document: &Document, node: NodeId, ancestor: NodeId) -> Option<NodeId> {
+    split_off(document, node, ancestor, true)
+}
+
+fn split_off(document: &Document, node: NodeId, ancestor: NodeId, after: bool) -> Option<NodeId> {
+    let mut child = node;
+    let mut copy = None;
+    while child != ancestor {
+        let parent = document.get_parent(child)?;
+        let grandparent = document.get_parent(parent)?;
+        let children = document.get_children(parent);
+        let index = children.iter().position(|&id| id == child)?;
+        let moved = match after {
+            true => &children[index + 1..],
+            false => &children[..index],
+        };
+        copy = None;
+        if !moved.is_empty() {
+            let element = shallow_copy(document, parent)?;
+            let reference = match after {
+                true => {
+                    let siblings = document.get_children(grandparent);
+                    let position = siblings.iter().position(|&id| id == parent)?;
+                    siblings.get(position + 1).copied()
+                }
+                false => Some(parent),
+            };
+            document
+                .insert_before(grandparent, element, reference)
+                .ok()?;
+            for &moved in moved {
+                document.append_child(element, moved).ok()?;
+            }
+            copy = Some(element);
+        }
+        child = parent;
+    }
+    copy
+}
@@ -0,0 +1,271 @@
+//! Flight recorder for engine events.
+//!
+//! Every [`BrowserEvent`] the engine emits is also appended here, together with
+//! navigation phase milestones, so the recent history can be pulled out after
+//! something went wrong. The log is a fixed-capacity ring: once full, the
+//! oldest entry is dropped for each new one. Sequence numbers keep counting
+//! across evictions and [`EventLog::clear`], so `since_seq` cursors held by a
+//! reader stay valid.
+
+use crate::BrowserEvent;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::ops::{BitOr, BitOrAssign};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Default number of entries kept by [`EventLog`].
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 4096;
+
+/// Steps of a navigation, recorded as they start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NavigationPhase {
+    Fetch,
+    Parse,
+    StyleAndLayout,
+    Scripts,
+    Render,
+}
+
+/// What a log entry holds.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LoggedEvent {
+    /// An event as delivered to subscribers. Error-handler invocations show
+    /// up as [`BrowserEvent::ErrorHandled`].
+    Browser {
+        event: BrowserEvent,
+    },
+    NavigationPhase {
+        url: String,
+        phase: NavigationPhase,
+    },
+}
+
+impl LoggedEvent {
+    pub fn kind(&self) -> EventKindMask {
+        match self {
+            LoggedEvent::Browser { event } => match event {
+                BrowserEvent::PageLoaded { .. } => EventKindMask::PAGE_LOADED,
+                BrowserEvent::NavigationStarted { .. } => EventKindMask::NAVIGATION_STARTED,
+                BrowserEvent::DocumentReopened { .. } => EventKindMask::DOCUMENT_REOPENED,
+                BrowserEvent::NavigationCancelled { .. } => EventKindMask::NAVIGATION_CANCELLED,
+                BrowserEvent::LoadProgress { .. } => EventKindMask::LOAD_PROGRESS,
+                BrowserEvent::JavaScriptError { .. } => EventKindMask::JAVASCRIPT_ERROR,
+                BrowserEvent::NetworkError { .. } => EventKindMask::NETWORK_ERROR,
+                BrowserEvent::SecurityViolation { .. } => EventKindMask::SECURITY_VIOLATION,
+                BrowserEvent::SecurityStateChanged { .. } => EventKindMask::SECURITY_STATE_CHANGED,
+                BrowserEvent::PerformanceWarning { .. } => EventKindMask::PERFORMANCE_WARNING,
+                BrowserEvent::ErrorHandled { .. } => EventKindMask::ERROR_HANDLED,
+                BrowserEvent::PrintRequested { .. } => EventKindMask::PRINT_REQUESTED,
+                BrowserEvent::ValidationMessage { .. } => EventKindMask::VALIDATION_MESSAGE,
+                BrowserEvent::AcceleratorTriggered { .. } => EventKindMask::ACCELERATOR_TRIGGERED,
+                BrowserEvent::FaviconChanged { .. } => EventKindMask::FAVICON_CHANGED,
+                BrowserEvent::ThemeColorChanged { .. } => EventKindMask::THEME_COLOR_CHANGED,
+                BrowserEvent::PwaInstallable { .. } => EventKindMask::PWA_INSTALLABLE,
+                BrowserEvent::UserScriptError { .. } => EventKindMask::USER_SCRIPT_ERROR,
+                BrowserEvent::AudibleStateChanged { .. } => EventKindMask::AUDIBLE_STATE_CHANGED,
+                BrowserEvent::NavigationTiming { .. } => EventKindMask::NAVIGATION_TIMING,
+                BrowserEvent::NotificationRequested { .. } => EventKindMask::NOTIFICATION_REQUESTED,
+                BrowserEvent::PrerenderDiscarded { .. } => EventKindMask::PRERENDER_DISCARDED,
+                BrowserEvent::LiveRegionAnnouncement { .. } => {
+                    EventKindMask::LIVE_REGION_ANNOUNCEMENT
+                }
+                BrowserEvent::FrameJank { .. } => EventKindMask::FRAME_JANK,
+                BrowserEvent::DisabledFeatureUsed { .. } => EventKindMask::DISABLED_FEATURE_USED,
+            },
+            LoggedEvent::NavigationPhase { .. } => EventKindMask::NAVIGATION_PHASE,
+        }
+    }
+}
+
+/// A set of event kinds, used to filter [`EventLog::recent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventKindMask(u32);
+
+impl EventKindMask {
+    pub const PAGE_LOADED: Self = Self(1 << 0);
+    pub const NAVIGATION_STARTED: Self = Self(1 << 1);
+    pub const JAVASCRIPT_ERROR: Self = Self(1 << 2);
+    pub const NETWORK_ERROR: Self = Self(1 << 3);
+    pub const SECURITY_VIOLATION: Self = Self(1 << 4);
+    pub const PERFORMANCE_WARNING: Self = Self(1 << 5);
+    pub const ERROR_HANDLED: Self = Self(1 << 6);
+    pub const NAVIGATION_PHASE: Self = Self(1 << 7);
+    pub const PRINT_REQUESTED: Self = Self(1 << 8);
+    pub const VALIDATION_MESSAGE: Self = Self(1 << 9);
+    pub const ACCELERATOR_TRIGGERED: Self = Self(1 << 10);
+    pub const FAVICON_CHANGED: Self = Self(1 << 11);
+    pub const THEME_COLOR_CHANGED: Self = Self(1 << 12);
+    pub const SECURITY_STATE_CHANGED: Self = Self(1 << 13);
+    pub const PWA_INSTALLABLE: Self = Self(1 << 14);
+    pub const USER_SCRIPT_ERROR: Self = Self(1 << 15);
+    pub const AUDIBLE_STATE_CHANGED: Self = Self(1 << 16);
+    pub const NAVIGATION_TIMING: Self = Self(1 << 17);
+    pub const NOTIFICATION_REQUESTED: Self = Self(1 << 18);
+    pub const PRERENDER_DISCARDED: Self = Self(1 << 19);
+    pub const LIVE_REGION_ANNOUNCEMENT: Self = Self(1 << 20);
+    /// Never logged: progress goes to subscribers only.
+    pub const LOAD_PROGRESS: Self = Self(1 << 21);
+    pub const DOCUMENT_REOPENED: Self = Self(1 << 22);
+    pub const FRAME_JANK: Self = Self(1 << 23);
+    pub const DISABLED_FEATURE_USED: Self = Self(1 << 24);
+    pub const NAVIGATION_CANCELLED: Self = Self(1 << 25);
+
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << 26) - 1);
+
+    /// Navigation start, phases and completion or cancellation.
+    pub const NAVIGATION: Self = Self(
+        Self::NAVIGATION_STARTED.0
+            | Self::NAVIGATION_PHASE.0
+            | Self::PAGE_LOADED.0
+            | Self::NAVIGATION_CANCELLED.0,
+    );
+    /// Every kind that reports a failure.
+    pub const ERRORS: Self = Self(
+        Self::JAVASCRIPT_ERROR.0
+            | Self::NETWORK_ERROR.0
+            | Self::SECURITY_VIOLATION.0
+            | Self::ERROR_HANDLED.0
+            | Self::USER_SCRIPT_ERROR.0,
+    );
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for EventKindMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for EventKindMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// One log entry. `timestamp_ms` is wall-clock time for correlating with
+/// other logs; `elapsed_us` is monotonic, measured from the log's creation.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimestampedEvent {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub elapsed_us: u64,
+    #[serde(flatten)]
+    pub event: LoggedEvent,
+}
+
+pub struct EventLog {
+    capacity: usize,
+    started: Instant,
+    next_seq: AtomicU64,
+    entries: Mutex<VecDeque<TimestampedEvent>>,
+}
+
+impl EventLog {
+    /// A log keeping the last `capacity` entries; zero disables recording.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            started: Instant::now(),
+            next_seq: AtomicU64::new(1),
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Append `event`, evicting the oldest entry when full. Returns the
+    /// entry's sequence number.
+    pub fn record(&self, event: LoggedEvent) -> u64 {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let elapsed_us = self.started.elapsed().as_micros() as u64;
+
+        // Numbered under the lock so the ring stays sorted by `seq`.
+        let mut entries = self.entries.lock();
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        if self.capacity == 0 {
+            return seq;
+        }
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(TimestampedEvent {
+            seq,
+            timestamp_ms,
+            elapsed_us,
+            event,
+        });
+        seq
+    }
+
+    /// Entries with a sequence number greater than `since_seq` whose kind is
+    /// in `filter`, oldest first.
+    pub fn recent(
+        &self,
+        since_seq: Option<u64>,
+        filter: Option<EventKindMask>,
+    ) -> Vec<TimestampedEvent> {
+        let since_seq = since_seq.unwrap_or(0);
+        let filter = filter.unwrap_or(EventKindMask::ALL);
+        let entries = self.entries.lock();
+        // Sequence numbers are ascending, so skip the older part without filtering it.
+        let start = entries.partition_point(|entry| entry.seq <= since_seq);
+        entries
+            .range(start..)
+            .filter(|entry| filter.intersects(entry.event.kind()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    /// The whole buffer as a JSON document, for attaching to bug reports.
+    pub fn to_json(&self) -> serde_json::Value {
+        let events = self.recent(None, None);
+        serde_json::json!({
+            "capacity": self.capacity,
+            "next_seq": self.next_seq.load(Ordering::Relaxed),
+            "events": events,
+        })
+    }
+
+    /// Write [`Self::to_json`] to `path`.
+    pub fn dump_to_file(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.to_json())?;
+        std::fs::write(path, json)
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_LOG_CAPACITY)
+    }
+}
@@ -0,0 +1,260 @@
+//! Engine features switched on and off per origin, for rolling out the
+//! experimental ones gradually.
+//!
+//! Each [`Feature`] has a default state. `BrowserConfig::feature_overrides`
+//! overrides it for every origin, and [`FeatureRegistry::set_origin`] for
+//! one tuple origin, which wins over both. A document's features are
+//! resolved once, as it loads, so a change applies from the next
+//! navigation on.
+//!
+//! A disabled feature is absent rather than broken: its CSS properties are
+//! invalid, so their declarations are dropped as unknown ones are, and its
+//! JS globals are not defined.
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Feature {
+    /// `content-visibility` and `contain-intrinsic-size`.
+    ContentVisibility,
+    /// `speechSynthesis` and `SpeechSynthesisUtterance`.
+    SpeechSynthesis,
+    /// The `WebAssembly` global. `BrowserConfig::enable_webassembly` turns
+    /// it off everywhere regardless.
+    WebAssembly,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 3] = [
+        Feature::ContentVisibility,
+        Feature::SpeechSynthesis,
+        Feature::WebAssembly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::ContentVisibility => "content-visibility",
+            Feature::SpeechSynthesis => "speech-synthesis",
+            Feature::WebAssembly => "webassembly",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|feature| feature.name() == name)
+    }
+
+    /// What an origin nobody overrode it for gets.
+    pub fn default_state(self) -> FeatureState {
+        match self {
+            Feature::ContentVisibility => FeatureState::Enabled,
+            Feature::SpeechSynthesis => FeatureState::Enabled,
+            Feature::WebAssembly => FeatureState::Enabled,
+        }
+    }
+
+    /// The CSS properties the feature brings; disabled, they do not parse.
+    pub fn css_properties(self) -> &'static [&'static str] {
+        match self {
+            Feature::ContentVisibility => &["content-visibility", "contain-intrinsic-size"],
+            Feature::SpeechSynthesis | Feature::WebAssembly => &[],
+        }
+    }
+
+    /// The globals the feature brings; disabled, they are not defined.
+    pub fn js_globals(self) -> &'static [&'static str] {
+        match self {
+            Feature::SpeechSynthesis => &["speechSynthesis", "SpeechSynthesisUtterance"],
+            Feature::WebAssembly => &["WebAssembly"],
+            Feature::ContentVisibility => &[],
+        }
+    }
+
+    /// The feature that brings the CSS `property`, if one does.
+    pub fn for_css_property(property: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|feature| {
+            feature
+                .css_properties()
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(property))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeatureState {
+    Enabled,
+    Disabled,
+    /// Off unless an override turns it on; for features still being
+    /// rolled out.
+    Experimental,
+}
+
+/// Features turned on (`true`) or off (`false`) over their default.
+pub type FeatureOverrides = HashMap<Feature, bool>;
+
+/// The features a document was loaded with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureSet(BTreeSet<Feature>);
+
+impl Default for FeatureSet {
+    /// The features on by default.
+    fn default() -> Self {
+        Self::resolve(&[])
+    }
+}
+
+impl FeatureSet {
+    /// The default states with `overrides` applied in order, later ones
+    /// winning.
+    fn resolve(overrides: &[&FeatureOverrides]) -> Self {
+        Self(
+            Feature::ALL
+                .into_iter()
+                .filter(|&feature| {
+                    overrides
+                        .iter()
+                        .rev()
+                        .find_map(|overrides| overrides.get(&feature).copied())
+                        .unwrap_or(feature.default_state() == FeatureState::Enabled)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.0.contains(&feature)
+    }
+
+    /// The disabled feature that brings the CSS `property`, if any.
+    pub fn disabled_css_property(&self, property: &str) -> Option<Feature> {
+        Feature::for_css_property(property).filter(|&feature| !self.contains(feature))
+    }
+
+    /// The globals of the disabled features.
+    pub fn disabled_globals(&self) -> Vec<&'static str> {
+        Feature::ALL
+            .into_iter()
+            .filter(|&feature| !self.contains(feature))
+            .flat_map(|feature| feature.js_globals().iter().copied())
+            .collect()
+    }
+
+    /// The names of the enabled features, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.0.iter().map(|f| f.name().to_string()).collect();
+        names.sort();
+        names
+    }
+}
+
+/// The global and per-origin overrides of an engine.
+pub struct FeatureRegistry {
+    global: FeatureOverrides,
+    origins: RwLock<HashMap<String, FeatureOverrides>>,
+}
+
+impl FeatureRegistry {
+    pub fn new(global: FeatureOverrides) -> Self {
+        Self {
+            global,
+            origins: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The key `url`'s origin is stored under; `None` for opaque origins,
+    /// which only ever get the global features.
+    fn origin_key(url: &Url) -> Option<String> {
+        let origin = url.origin();
+        origin.is_tuple().then(|| origin.ascii_serialization())
+    }
+
+    /// Override features for the origin of `url`, replacing what was set
+    /// for it before; empty `overrides` forget them. Opaque origins are
+    /// ignored.
+    pub fn set_origin(&self, url: &Url, overrides: FeatureOverrides) {
+        let origin = match Self::origin_key(url) {
+            Some(origin) => origin,
+            None => {
+                tracing::debug!("Ignoring feature overrides for opaque {}", url);
+                return;
+            }
+        };
+        let mut origins = self.origins.write();
+        if overrides.is_empty() {
+            origins.remove(&origin);
+        } else {
+            origins.insert(origin, overrides);
+        }
+    }
+
+    /// The features a document at `url` gets; `None` for one without an
+    /// address, such as a source listing.
+    pub fn for_document(&self, url: Option<&Url>) -> FeatureSet {
+        let origins = self.origins.read();
+        let own = url
+            .and_then(Self::origin_key)
+            .and_then(|origin| origins.get(&origin));
+        match own {
+            Some(own) => FeatureSet::resolve(&[&self.global, own]),
+            None => FeatureSet::resolve(&[&self.global]),
+        }
+    }
+
+    pub fn is_enabled(&self, feature: Feature, url: &Url) -> bool {
+        self.for_document(Some(url)).contains(feature)
+    }
+}
+
+/// A disabled feature a document tried to use.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DisabledFeatureUse {
+    pub feature: Feature,
+    /// What used it, as a CSS property.
+    pub usage: String,
+}
+
+#[derive(Default)]
+struct UseState {
+    uses: BTreeSet<DisabledFeatureUse>,
+    reported: BTreeSet<DisabledFeatureUse>,
+}
+
+/// The current document's features, and what it tried to use of the
+/// others.
+#[derive(Default)]
+pub struct DocumentFeatures {
+    features: RwLock<FeatureSet>,
+    uses: Mutex<UseState>,
+}
+
+impl DocumentFeatures {
+    /// A new document loads with `features`.
+    pub fn reset(&self, features: FeatureSet) {
+        *self.features.write() = features;
+        *self.uses.lock() = UseState::default();
+    }
+
+    pub fn current(&self) -> FeatureSet {
+        self.features.read().clone()
+    }
+
+    pub fn record(&self, uses: impl IntoIterator<Item = DisabledFeatureUse>) {
+        self.uses.lock().uses.extend(uses);
+    }
+
+    /// Uses not reported before on this page. Stylesheets are parsed again
+    /// on every restyle, so the same use comes back.
+    pub(crate) fn take_unreported(&self) -> Vec<DisabledFeatureUse> {
+        let mut state = self.uses.lock();
+        let state = &mut *state;
+        let new: Vec<DisabledFeatureUse> =
+            state.uses.difference(&state.reported).cloned().collect();
+        state.reported.extend(new.iter().cloned());
+        new
+    }
+}
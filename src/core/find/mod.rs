@@ -0,0 +1,180 @@
+//! Searching the text a page shows.
+//!
+//! [`PageText`] is the document's rendered text as a reader sees it: text
+//! nodes in tree order, less those of unrendered elements and of
+//! `display: none` subtrees, with whitespace collapsed the way layout
+//! collapses it. Runs of whitespace are one space whichever elements they
+//! straddle, and the edges of non-inline boxes count as whitespace, so
+//! `quick <b>brown</b> fox` reads as `quick brown fox` and words in
+//! adjacent paragraphs stay apart. Searches are case-insensitive and only
+//! match whole words.
+
+pub mod text_fragment;
+
+use super::css::{ComputedValue, StyleEngine};
+use super::dom::{Document, NodeId};
+use super::layout::LayoutEngine;
+use std::ops::Range;
+
+pub struct PageText {
+    // Case-folded, whitespace collapsed.
+    chars: Vec<char>,
+    // Each text node that contributed, with the span of `chars` it did.
+    runs: Vec<(NodeId, Range<usize>)>,
+}
+
+impl PageText {
+    pub fn collect(document: &Document, style_engine: &StyleEngine) -> Self {
+        let mut text = Self {
+            chars: Vec::new(),
+            runs: Vec::new(),
+        };
+        if let Some(root) = document.get_root_node() {
+            let mut pending_space = false;
+            text.walk(root, document, style_engine, &mut pending_space);
+        }
+        text
+    }
+
+    fn walk(
+        &mut self,
+        node_id: NodeId,
+        document: &Document,
+        style_engine: &StyleEngine,
+        pending_space: &mut bool,
+    ) {
+        let node = match document.get_node(node_id) {
+            Some(node) => node,
+            None => return,
+        };
+        let (is_text, is_element, content) = {
+            let node = node.read();
+            (node.is_text(), node.is_element(), node.get_text_content())
+        };
+        if is_text {
+            self.push_text(node_id, &content, pending_space);
+            return;
+        }
+
+        let mut inline = false;
+        if is_element {
+            if LayoutEngine::is_unrendered(node_id, document) {
+                return;
+            }
+            let display = style_engine
+                .get_computed_styles(node_id)
+                .and_then(|styles| match styles.get_computed_value("display") {
+                    Ok(ComputedValue::Keyword(display)) => Some(display.to_ascii_lowercase()),
+                    _ => None,
+                });
+            match display.as_deref() {
+                Some("none") => return,
+                Some("inline") => inline = true,
+                _ => {}
+            }
+        }
+        if !inline {
+            *pending_space = true;
+        }
+        for child in document.get_children(node_id) {
+            self.walk(child, document, style_engine, pending_space);
+        }
+        if !inline {
+            *pending_space = true;
+        }
+    }
+
+    fn push_text(&mut self, node_id: NodeId, content: &str, pending_space: &mut bool) {
+        let mut start = None;
+        for c in content.chars() {
+            if c.is_whitespace() {
+                *pending_space = true;
+                continue;
+            }
+            if *pending_space && !self.chars.is_empty() {
+                self.chars.push(' ');
+            }
+            *pending_space = false;
+            start.get_or_insert(self.chars.len());
+            self.chars.extend(c.to_lowercase());
+        }
+        if let Some(start) = start {
+            self.runs.push((node_id, start..self.chars.len()));
+        }
+    }
+
+    /// `query` as the page text holds it: case-folded, whitespace
+    /// collapsed and trimmed.
+    fn normalize(query: &str) -> Vec<char> {
+        let mut chars = Vec::new();
+        for word in query.split_whitespace() {
+            if !chars.is_empty() {
+                chars.push(' ');
+            }
+            chars.extend(word.chars().flat_map(char::to_lowercase));
+        }
+        chars
+    }
+
+    /// Whether a word ends between `at - 1` and `at`, or one starts.
+    fn is_word_boundary(&self, at: usize) -> bool {
+        let is_word = |c: &char| c.is_alphanumeric();
+        at == 0
+            || at >= self.chars.len()
+            || !self.chars.get(at - 1).is_some_and(is_word)
+            || !self.chars.get(at).is_some_and(is_word)
+    }
+
+    fn is_word_at(&self, range: &Range<usize>) -> bool {
+        self.is_word_boundary(range.start) && self.is_word_boundary(range.end)
+    }
+
+    /// The first whole-word occurrence of `query` starting at or after
+    /// `from`.
+    pub fn find(&self, query: &str, from: usize) -> Option<Range<usize>> {
+        let needle = Self::normalize(query);
+        if needle.is_empty() || needle.len() > self.chars.len() {
+            return None;
+        }
+        (from..=self.chars.len() - needle.len())
+            .map(|start| start..start + needle.len())
+            .find(|range| self.chars[range.clone()] == needle[..] && self.is_word_at(range))
+    }
+
+    /// Whether `query` is the whole words right before `at`, whitespace
+    /// between them aside.
+    pub fn is_preceded_by(&self, query: &str, at: usize) -> bool {
+        let needle = Self::normalize(query);
+        let mut end = at.min(self.chars.len());
+        if end > 0 && self.chars[end - 1] == ' ' {
+            end -= 1;
+        }
+        !needle.is_empty()
+            && end >= needle.len()
+            && self.chars[end - needle.len()..end] == needle[..]
+            && self.is_word_at(&(end - needle.len()..end))
+    }
+
+    /// Whether `query` is the whole words right after `at`, whitespace
+    /// between them aside.
+    pub fn is_followed_by(&self, query: &str, at: usize) -> bool {
+        let needle = Self::normalize(query);
+        let mut start = at.min(self.chars.len());
+        if self.chars.get(start) == Some(&' ') {
+            start += 1;
+        }
+        !needle.is_empty()
+            && start + needle.len() <= self.chars.len()
+            && self.chars[start..start + needle.len()] == needle[..]
+            && self.is_word_at(&(start..start + needle.len()))
+    }
+
+    /// The text nodes `range` takes text from, in tree order.
+    pub fn nodes_in(&self, range: &Range<usize>) -> Vec<NodeId> {
+        self.runs
+            .iter()
+            .filter(|(_, run)| run.start < range.end && range.start < run.end)
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    }
+}
@@ -0,0 +1,217 @@
+//! Text fragments: `#:~:text=` links that scroll to a passage.
+//!
+//! A URL's fragment may end in a fragment directive, `:~:` followed by
+//! `&`-separated directives. A text directive is
+//! `text=[prefix-,]start[,end][,-suffix]`, each term percent-encoded; it
+//! matches `start`, or the range from `start` to the next `end`, where the
+//! words right before it are `prefix` and those right after it `suffix`.
+//! The directive is no part of the document's URL: it is split off before
+//! the document loads, so scripts never see it in `location`.
+//!
+//! The first directive that matches is scrolled to and highlighted until
+//! the user clicks or scrolls more than [`DISMISS_SCROLL_DISTANCE`] away.
+//! One that matches nothing is ignored.
+
+use super::PageText;
+use crate::core::dom::{Document, NodeId};
+use crate::core::layout::LayoutEngine;
+use crate::renderer::{ClipChain, DrawQuad, Rect};
+use parking_lot::Mutex;
+use percent_encoding::percent_decode_str;
+use std::ops::Range;
+
+/// The UA highlight, a translucent version of `<mark>`'s yellow.
+pub const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 0.4];
+
+/// How far the user may scroll from the highlighted match before it goes.
+pub const DISMISS_SCROLL_DISTANCE: f32 = 200.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDirective {
+    pub prefix: Option<String>,
+    pub start: String,
+    pub end: Option<String>,
+    pub suffix: Option<String>,
+}
+
+impl TextDirective {
+    /// Parse the value of a `text=` directive. `None` when it is malformed.
+    pub fn parse(value: &str) -> Option<Self> {
+        let decode = |term: &str| {
+            let term = percent_decode_str(term).decode_utf8().ok()?;
+            (!term.trim().is_empty()).then(|| term.into_owned())
+        };
+        let mut terms: Vec<&str> = value.split(',').collect();
+        let prefix = match terms.first() {
+            Some(term) if terms.len() > 1 && term.ends_with('-') => {
+                let term = terms.remove(0);
+                Some(decode(&term[..term.len() - 1])?)
+            }
+            _ => None,
+        };
+        let suffix = match terms.last() {
+            Some(term) if terms.len() > 1 && term.starts_with('-') => {
+                let term = terms.pop()?;
+                Some(decode(&term[1..])?)
+            }
+            _ => None,
+        };
+        let (start, end) = match terms.as_slice() {
+            [start] => (decode(start)?, None),
+            [start, end] => (decode(start)?, Some(decode(end)?)),
+            _ => return None,
+        };
+        Some(Self {
+            prefix,
+            start,
+            end,
+            suffix,
+        })
+    }
+
+    /// The first range of `text` the directive matches.
+    pub fn find(&self, text: &PageText) -> Option<Range<usize>> {
+        let mut from = 0;
+        while let Some(start) = text.find(&self.start, from) {
+            from = start.start + 1;
+            if let Some(prefix) = &self.prefix {
+                if !text.is_preceded_by(prefix, start.start) {
+                    continue;
+                }
+            }
+            let range = match &self.end {
+                // No `end` after this start means none after later ones.
+                Some(end) => start.start..text.find(end, start.end)?.end,
+                None => start,
+            };
+            if let Some(suffix) = &self.suffix {
+                if !text.is_followed_by(suffix, range.end) {
+                    continue;
+                }
+            }
+            return Some(range);
+        }
+        None
+    }
+}
+
+/// Split the fragment directive off `url`: the URL without it, and the
+/// text directives it held. A URL without one comes back as it was.
+pub fn split_fragment_directive(url: &str) -> (String, Vec<TextDirective>) {
+    let directive_at = url
+        .find('#')
+        .and_then(|hash| url[hash..].find(":~:").map(|at| hash + at));
+    match directive_at {
+        Some(at) => {
+            let directives = url[at + 3..]
+                .split('&')
+                .filter_map(|directive| directive.strip_prefix("text="))
+                .filter_map(TextDirective::parse)
+                .collect();
+            (url[..at].to_string(), directives)
+        }
+        None => (url.to_string(), Vec::new()),
+    }
+}
+
+/// The text nodes the first matching directive covers, in tree order.
+pub fn find_first(directives: &[TextDirective], text: &PageText) -> Option<Vec<NodeId>> {
+    directives
+        .iter()
+        .find_map(|directive| directive.find(text))
+        .map(|range| text.nodes_in(&range))
+}
+
+/// Where `nodes` are painted: the box of each, or of its nearest laid-out
+/// ancestor for text inside inline elements, which lay out no boxes of
+/// their own.
+pub fn highlight_rects(
+    nodes: &[NodeId],
+    document: &Document,
+    layout_engine: &LayoutEngine,
+) -> Vec<Rect> {
+    let mut rects: Vec<Rect> = Vec::new();
+    for &node_id in nodes {
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            let painted = layout_engine.get_layout_box(id).filter(|layout_box| {
+                layout_box.border_box_width() > 0.0 && layout_box.border_box_height() > 0.0
+            });
+            if let Some(layout_box) = painted {
+                let rect = Rect {
+                    x: layout_box.border_box_x(),
+                    y: layout_box.border_box_y(),
+                    width: layout_box.border_box_width(),
+                    height: layout_box.border_box_height(),
+                };
+                if !rects.contains(&rect) {
+                    rects.push(rect);
+                }
+                break;
+            }
+            current = document.get_parent(id);
+        }
+    }
+    rects
+}
+
+pub fn highlight_quads(rects: &[Rect]) -> Vec<DrawQuad> {
+    rects
+        .iter()
+        .map(|bounds| DrawQuad {
+            bounds: bounds.clone(),
+            color: HIGHLIGHT_COLOR,
+            clip: ClipChain::new(),
+            blur_radius: 0.0,
+            mask: None,
+            image: None,
+        })
+        .collect()
+}
+
+struct Highlighted {
+    nodes: Vec<NodeId>,
+    // Where the match was scrolled to.
+    scroll: (f32, f32),
+}
+
+/// The current document's highlighted match, if any.
+#[derive(Default)]
+pub struct TextHighlight {
+    state: Mutex<Option<Highlighted>>,
+}
+
+impl TextHighlight {
+    pub fn set(&self, nodes: Vec<NodeId>, scroll: (f32, f32)) {
+        *self.state.lock() = Some(Highlighted { nodes, scroll });
+    }
+
+    pub fn nodes(&self) -> Vec<NodeId> {
+        self.state
+            .lock()
+            .as_ref()
+            .map(|highlighted| highlighted.nodes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Drop the highlight. `false` when there was none.
+    pub fn clear(&self) -> bool {
+        self.state.lock().take().is_some()
+    }
+
+    /// The viewport scrolled to `scroll`: past the dismiss distance, the
+    /// highlight goes.
+    pub fn scrolled(&self, scroll: (f32, f32)) {
+        let mut state = self.state.lock();
+        let dismissed = state.as_ref().is_some_and(|highlighted| {
+            let (dx, dy) = (
+                scroll.0 - highlighted.scroll.0,
+                scroll.1 - highlighted.scroll.1,
+            );
+            dx.hypot(dy) > DISMISS_SCROLL_DISTANCE
+        });
+        if dismissed {
+            *state = None;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Turn downloaded font binaries into plain sfnt (TrueType/OpenType) data.
+//!
+//! WOFF 1.0 tables are zlib-compressed individually; WOFF 2.0 is handled in
+//! [`super::woff2`]. Whatever the container, the result must parse as a font
+//! before it is accepted.
+
+use super::{woff2, FontError, Result};
+use std::io::Read;
+
+const WOFF_SIGNATURE: u32 = u32::from_be_bytes(*b"wOFF");
+const WOFF2_SIGNATURE: u32 = u32::from_be_bytes(*b"wOF2");
+const TRUETYPE: u32 = 0x0001_0000;
+const OPENTYPE: u32 = u32::from_be_bytes(*b"OTTO");
+const APPLE_TRUETYPE: u32 = u32::from_be_bytes(*b"true");
+
+/// The container a font binary arrived in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontFormat {
+    TrueType,
+    OpenType,
+    Woff,
+    Woff2,
+}
+
+impl FontFormat {
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match read_u32(data, 0)? {
+            TRUETYPE | APPLE_TRUETYPE => Some(FontFormat::TrueType),
+            OPENTYPE => Some(FontFormat::OpenType),
+            WOFF_SIGNATURE => Some(FontFormat::Woff),
+            WOFF2_SIGNATURE => Some(FontFormat::Woff2),
+            _ => None,
+        }
+    }
+
+    /// Whether a `format()` hint from a `src` descriptor names a format we
+    /// can load. Unknown hints make the source be skipped.
+    pub fn supports_hint(hint: &str) -> bool {
+        matches!(
+            hint.to_ascii_lowercase().as_str(),
+            "truetype" | "opentype" | "woff" | "woff2"
+        )
+    }
+}
+
+/// Unwrap `data` to sfnt bytes and check that they parse.
+pub fn decode_font(data: &[u8]) -> Result<Vec<u8>> {
+    let sfnt = match FontFormat::sniff(data) {
+        Some(FontFormat::TrueType) | Some(FontFormat::OpenType) => data.to_vec(),
+        Some(FontFormat::Woff) => decode_woff(data)?,
+        Some(FontFormat::Woff2) => woff2::decode(data)?,
+        None => {
+            return Err(FontError::Decode(
+                "Not a TrueType, OpenType, WOFF or WOFF2 font".to_string(),
+            ))
+        }
+    };
+
+    if let Err(e) = ttf_parser::Face::parse(&sfnt, 0) {
+        return Err(FontError::Decode(format!("Invalid font data: {}", e)));
+    }
+    Ok(sfnt)
+}
+
+fn decode_woff(data: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || FontError::Decode("Truncated WOFF data".to_string());
+    let flavor = read_u32(data, 4).ok_or_else(truncated)?;
+    let num_tables = read_u16(data, 12).ok_or_else(truncated)? as usize;
+
+    let mut tables = Vec::with_capacity(num_tables);
+    for index in 0..num_tables {
+        let entry = 44 + index * 20;
+        let tag = data.get(entry..entry + 4).ok_or_else(truncated)?;
+        let offset = read_u32(data, entry + 4).ok_or_else(truncated)? as usize;
+        let compressed_length = read_u32(data, entry + 8).ok_or_else(truncated)? as usize;
+        let original_length = read_u32(data, entry + 12).ok_or_else(truncated)? as usize;
+
+        let stored = offset
+            .checked_add(compressed_length)
+            .and_then(|end| data.get(offset..end))
+            .ok_or_else(truncated)?;
+        let table = if compressed_length < original_length {
+            let mut table = Vec::with_capacity(original_length);
+            flate2::read::ZlibDecoder::new(stored)
+                .take(original_length as u64)
+                .read_to_end(&mut table)
+                .map_err(|e| FontError::Decode(format!("Bad WOFF table data: {}", e)))?;
+            table
+        } else {
+            stored.to_vec()
+        };
+        if table.len() != original_length {
+            return Err(FontError::Decode(
+                "WOFF table length does not match its header".to_string(),
+            ));
+        }
+
+        tables.push(([tag[0], tag[1], tag[2], tag[3]], table));
+    }
+
+    Ok(build_sfnt(flavor, tables))
+}
+
+/// Assemble an sfnt from `tables`, sorting the directory by tag, padding each
+/// table to four bytes and filling in checksums.
+pub(super) fn build_sfnt(flavor: u32, mut tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+
+    let num_tables = tables.len() as u16;
+    let entry_selector = num_tables.max(1).ilog2() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+    let range_shift = num_tables * 16 - search_range.min(num_tables * 16);
+
+    let mut font = Vec::new();
+    font.extend_from_slice(&flavor.to_be_bytes());
+    font.extend_from_slice(&num_tables.to_be_bytes());
+    font.extend_from_slice(&search_range.to_be_bytes());
+    font.extend_from_slice(&entry_selector.to_be_bytes());
+    font.extend_from_slice(&range_shift.to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = None;
+    for (tag, table) in &mut tables {
+        if tag == b"head" && table.len() >= 12 {
+            // Zeroed while checksumming, per the spec.
+            table[8..12].copy_from_slice(&[0; 4]);
+            head_offset = Some(offset);
+        }
+        font.extend_from_slice(tag);
+        font.extend_from_slice(&checksum(table).to_be_bytes());
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    for (_, table) in &tables {
+        font.extend_from_slice(table);
+        font.resize((font.len() + 3) & !3, 0);
+    }
+
+    if let Some(head) = head_offset {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+        font[head + 8..head + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    font
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+pub(super) fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub(super) fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
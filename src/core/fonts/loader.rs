@@ -0,0 +1,193 @@
+//! Fetching the sources of a font face.
+//!
+//! Font requests are CORS requests: a cross-origin font is only used when the
+//! response's `Access-Control-Allow-Origin` admits the document's origin. The
+//! document's CSP `font-src` is checked before anything is fetched.
+
+use super::{decode_font, FontError, FontRequest, FontSource, FontStyle, Result};
+use crate::core::network::{
+    FetchRequest, NetworkError, NetworkManager, Priority, RequestInitiator,
+};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Installed fonts for `local()` sources, scanned on first use.
+static SYSTEM_FONTS: Lazy<fontdb::Database> = Lazy::new(|| {
+    let mut database = fontdb::Database::new();
+    database.load_system_fonts();
+    database
+});
+
+/// Validated sfnt data and the face index within it.
+#[derive(Debug, Clone)]
+pub struct LoadedFont {
+    pub data: Vec<u8>,
+    pub index: u32,
+}
+
+pub struct FontLoader {
+    network: Arc<NetworkManager>,
+    initiator: RequestInitiator,
+}
+
+impl FontLoader {
+    pub fn new(network: Arc<NetworkManager>, initiator: RequestInitiator) -> Self {
+        Self { network, initiator }
+    }
+
+    pub fn initiator(&self) -> &RequestInitiator {
+        &self.initiator
+    }
+
+    /// Try `sources` in order and return the first that loads. The error is
+    /// the last source's, or [`FontError::NoUsableSource`] if none was tried.
+    pub async fn load(&self, family: &str, sources: &[FontSource]) -> Result<LoadedFont> {
+        let mut last_error = None;
+        for source in sources {
+            let result = match source {
+                FontSource::Local(name) => {
+                    local_font(name).ok_or_else(|| FontError::NoUsableSource(name.clone()))
+                }
+                FontSource::Url { url, .. } => self.fetch(url).await,
+            };
+            match result {
+                Ok(font) => return Ok(font),
+                Err(e) => {
+                    tracing::debug!("Font source for '{}' failed: {}", family, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| FontError::NoUsableSource(family.to_string())))
+    }
+
+    async fn fetch(&self, url: &Url) -> Result<LoadedFont> {
+        if !self.initiator.allows_font(url) {
+            return Err(FontError::Blocked(format!(
+                "{} violates the document's font-src policy",
+                url
+            )));
+        }
+
+        // A `data:` font carries itself; there is no other origin to ask.
+        let same_origin = url.scheme() == "data" || self.initiator.is_same_origin(url);
+        let origin = self.initiator.origin_key();
+        let mut headers = HashMap::new();
+        if !same_origin {
+            headers.insert("Origin".to_string(), origin.clone());
+        }
+        let response = self
+            .network
+            .fetch_subresource(
+                FetchRequest {
+                    url: url.to_string(),
+                    method: "GET".to_string(),
+                    headers,
+                    body: None,
+                    timeout_ms: None,
+                    follow_redirects: true,
+                    cache_policy: None,
+                    priority: Priority::High,
+                    idempotent: false,
+                },
+                &self.initiator,
+            )
+            .await?;
+
+        if !(200..300).contains(&response.status) {
+            return Err(FontError::Network(NetworkError::RequestFailed(format!(
+                "HTTP {} for {}",
+                response.status, url
+            ))));
+        }
+        if !same_origin {
+            let allowed = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("access-control-allow-origin"))
+                .map(|(_, value)| value.trim());
+            if !matches!(allowed, Some(value) if value == "*" || value == origin) {
+                return Err(FontError::Blocked(format!(
+                    "{} is cross-origin and not shared with {}",
+                    url, origin
+                )));
+            }
+        }
+
+        let data = decode_font(&response.body)?;
+        Ok(LoadedFont { data, index: 0 })
+    }
+}
+
+/// Find an installed face by full family or PostScript name.
+fn local_font(name: &str) -> Option<LoadedFont> {
+    let face = SYSTEM_FONTS.faces().find(|face| {
+        face.post_script_name.eq_ignore_ascii_case(name)
+            || face
+                .families
+                .iter()
+                .any(|(family, _)| family.eq_ignore_ascii_case(name))
+    })?;
+    system_face_data(face.id)
+}
+
+/// The installed face a `font-family` list picks for text drawn as `font`
+/// asks: the first family in the list that is installed, generic families
+/// included. Without one it is the default sans-serif face, or failing that
+/// any face at all.
+pub fn system_face(families: &str, font: &FontRequest) -> Option<fontdb::ID> {
+    let mut query: Vec<fontdb::Family> = families
+        .split(',')
+        .map(|name| name.trim().trim_matches(|c| c == '"' || c == '\''))
+        .filter(|name| !name.is_empty())
+        .map(|name| match name.to_ascii_lowercase().as_str() {
+            "serif" => fontdb::Family::Serif,
+            "sans-serif" => fontdb::Family::SansSerif,
+            "monospace" => fontdb::Family::Monospace,
+            "cursive" => fontdb::Family::Cursive,
+            "fantasy" => fontdb::Family::Fantasy,
+            _ => fontdb::Family::Name(name),
+        })
+        .collect();
+    // The generic sans-serif family is Arial unless configured, which few
+    // systems besides Windows and macOS have.
+    query.push(fontdb::Family::SansSerif);
+    query.extend(
+        [
+            "DejaVu Sans",
+            "Liberation Sans",
+            "Noto Sans",
+            "Helvetica",
+            "Segoe UI",
+        ]
+        .map(fontdb::Family::Name),
+    );
+    SYSTEM_FONTS
+        .query(&fontdb::Query {
+            families: &query,
+            weight: fontdb::Weight(font.weight.clamp(1.0, 1000.0) as u16),
+            stretch: fontdb::Stretch::Normal,
+            style: match font.style {
+                FontStyle::Normal => fontdb::Style::Normal,
+                FontStyle::Italic => fontdb::Style::Italic,
+                FontStyle::Oblique => fontdb::Style::Oblique,
+            },
+        })
+        .or_else(|| SYSTEM_FONTS.faces().next().map(|face| face.id))
+}
+
+/// The data of the installed face `id`.
+pub fn system_face_data(id: fontdb::ID) -> Option<LoadedFont> {
+    SYSTEM_FONTS
+        .with_face_data(id, |data, index| {
+            ttf_parser::Face::parse(data, index)
+                .ok()
+                .map(|_| LoadedFont {
+                    data: data.to_vec(),
+                    index,
+                })
+        })
+        .flatten()
+}
@@ -0,0 +1,887 @@
+//! Web fonts: `@font-face` rules, the faces they describe and their loading.
+//!
+//! A [`FontFaceSet`] holds every face known to the document, whether declared
+//! in CSS or constructed from script. Loads run on the tokio runtime through a
+//! [`FontLoader`]; each one that settles is queued as a [`FontLoadEvent`] for
+//! the engine to pick up on its next tick, relayout and tell script about.
+//!
+//! `font-display` is honoured for swapping only: a face that finishes after
+//! its swap period is kept loaded but never used for this document. Text is
+//! not hidden during the block period; fallback text renders from the start.
+//!
+//! Text asks for a face with a [`FontRequest`]. Variable faces are drawn at
+//! the position on their axes the request asks for; see [`variation`].
+
+pub mod decode;
+pub mod loader;
+pub mod shape;
+pub mod variation;
+pub mod woff2;
+
+pub use decode::{decode_font, FontFormat};
+pub use loader::{system_face, system_face_data, FontLoader, LoadedFont};
+pub use shape::{ShapedRun, ShapedText, ShapingObserver};
+pub use variation::{FontRequest, VariationCoords};
+
+use crate::core::css::{CSSFontFaceRule, CSSRule};
+use crate::core::network::NetworkError;
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use url::Url;
+
+/// How long `optional` and `fallback` faces may take before fallback text is
+/// considered final. Also the `block` period of `auto`, `block` and `swap`.
+pub const BLOCK_PERIOD: Duration = Duration::from_millis(100);
+/// Swap period of `font-display: fallback`, counted after the block period.
+pub const FALLBACK_SWAP_PERIOD: Duration = Duration::from_secs(3);
+
+#[derive(Error, Debug)]
+pub enum FontError {
+    #[error("Invalid font: {0}")]
+    Decode(String),
+    #[error("Unsupported font feature: {0}")]
+    Unsupported(String),
+    #[error("Invalid font descriptor: {0}")]
+    Descriptor(String),
+    #[error("Font blocked: {0}")]
+    Blocked(String),
+    #[error("Network error: {0}")]
+    Network(#[from] NetworkError),
+    #[error("No usable source for font family '{0}'")]
+    NoUsableSource(String),
+}
+
+pub type Result<T> = std::result::Result<T, FontError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FontDisplay {
+    #[default]
+    Auto,
+    Block,
+    Swap,
+    Fallback,
+    Optional,
+}
+
+impl FontDisplay {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(FontDisplay::Auto),
+            "block" => Some(FontDisplay::Block),
+            "swap" => Some(FontDisplay::Swap),
+            "fallback" => Some(FontDisplay::Fallback),
+            "optional" => Some(FontDisplay::Optional),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FontDisplay::Auto => "auto",
+            FontDisplay::Block => "block",
+            FontDisplay::Swap => "swap",
+            FontDisplay::Fallback => "fallback",
+            FontDisplay::Optional => "optional",
+        }
+    }
+
+    /// Whether a face whose load took `elapsed` may still replace fallback
+    /// text that is already on screen.
+    pub fn allows_swap_after(&self, elapsed: Duration) -> bool {
+        match self {
+            FontDisplay::Optional => elapsed <= BLOCK_PERIOD,
+            FontDisplay::Fallback => elapsed <= BLOCK_PERIOD + FALLBACK_SWAP_PERIOD,
+            FontDisplay::Auto | FontDisplay::Block | FontDisplay::Swap => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FontStyle {
+    #[default]
+    Normal,
+    Italic,
+    Oblique,
+}
+
+impl FontStyle {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        match value.split_ascii_whitespace().next() {
+            Some("normal") => Some(FontStyle::Normal),
+            Some("italic") => Some(FontStyle::Italic),
+            // Oblique angles are accepted but not distinguished.
+            Some("oblique") => Some(FontStyle::Oblique),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FontStyle::Normal => "normal",
+            FontStyle::Italic => "italic",
+            FontStyle::Oblique => "oblique",
+        }
+    }
+}
+
+/// One entry of a `src` descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontSource {
+    Url { url: Url, format: Option<String> },
+    Local(String),
+}
+
+/// The descriptors of one `@font-face` rule or `FontFace` object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFaceDescriptor {
+    pub family: String,
+    pub sources: Vec<FontSource>,
+    /// Inclusive weight range; a single weight has equal bounds. `None` for
+    /// `auto`: the range of the font's `wght` axis, or 400 without one.
+    pub weight: Option<(u16, u16)>,
+    pub style: FontStyle,
+    pub display: FontDisplay,
+}
+
+impl FontFaceDescriptor {
+    pub fn new(family: &str) -> Self {
+        Self {
+            family: unquote(family.trim()).to_string(),
+            sources: Vec::new(),
+            weight: None,
+            style: FontStyle::Normal,
+            display: FontDisplay::Auto,
+        }
+    }
+
+    /// Build a descriptor from an `@font-face` rule. Relative URLs resolve
+    /// against `base`. Rules without `font-family` or `src` are invalid.
+    pub fn from_rule(rule: &CSSFontFaceRule, base: &Url) -> Result<Self> {
+        let value = |name: &str| {
+            rule.declarations
+                .properties
+                .iter()
+                .rev()
+                .find(|(property, _, _)| property.eq_ignore_ascii_case(name))
+                .map(|(_, value, _)| value.as_str())
+        };
+
+        let family = value("font-family")
+            .ok_or_else(|| FontError::Descriptor("@font-face without font-family".to_string()))?;
+        let src = value("src")
+            .ok_or_else(|| FontError::Descriptor("@font-face without src".to_string()))?;
+
+        let mut descriptor = Self::new(family);
+        descriptor.sources = parse_src(src, base)?;
+        for (name, value, _) in &rule.declarations.properties {
+            descriptor.set(name, value)?;
+        }
+        Ok(descriptor)
+    }
+
+    /// Apply one of the `font-weight`, `font-style` or `font-display`
+    /// descriptors. Other names are ignored.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let invalid = || FontError::Descriptor(format!("Invalid {}: '{}'", name, value));
+        match name.to_ascii_lowercase().as_str() {
+            "font-weight" | "weight" if value.trim().eq_ignore_ascii_case("auto") => {
+                self.weight = None
+            }
+            "font-weight" | "weight" => {
+                self.weight = Some(parse_weight(value).ok_or_else(invalid)?)
+            }
+            "font-style" | "style" => self.style = FontStyle::parse(value).ok_or_else(invalid)?,
+            "font-display" | "display" => {
+                self.display = FontDisplay::parse(value).ok_or_else(invalid)?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `src` descriptor. Entries are tried in order; `url()` entries
+/// whose `format()` hints are all unsupported are dropped here.
+pub fn parse_src(value: &str, base: &Url) -> Result<Vec<FontSource>> {
+    let mut sources = Vec::new();
+    for entry in split_top_level(value) {
+        let entry = entry.trim();
+        if let Some(name) = function_argument(entry, "local") {
+            sources.push(FontSource::Local(unquote(name.trim()).to_string()));
+            continue;
+        }
+
+        let argument = function_argument(entry, "url")
+            .ok_or_else(|| FontError::Descriptor(format!("Unrecognised src entry '{}'", entry)))?;
+        let url = base
+            .join(unquote(argument.trim()))
+            .map_err(|e| FontError::Descriptor(format!("Bad font URL '{}': {}", argument, e)))?;
+
+        let rest = &entry["url(".len() + argument.len() + 1..];
+        let format = function_argument(rest.trim(), "format").map(|hints| {
+            split_top_level(hints)
+                .into_iter()
+                .map(|hint| unquote(hint.trim()).to_string())
+                .collect::<Vec<_>>()
+        });
+        match format {
+            Some(hints) => {
+                if let Some(hint) = hints
+                    .into_iter()
+                    .find(|hint| FontFormat::supports_hint(hint))
+                {
+                    sources.push(FontSource::Url {
+                        url,
+                        format: Some(hint),
+                    });
+                }
+            }
+            None => sources.push(FontSource::Url { url, format: None }),
+        }
+    }
+
+    if sources.is_empty() {
+        return Err(FontError::Descriptor(format!(
+            "No supported source in '{}'",
+            value
+        )));
+    }
+    Ok(sources)
+}
+
+/// `normal`, `bold`, a number, or a `<min> <max>` range.
+fn parse_weight(value: &str) -> Option<(u16, u16)> {
+    let single = |value: &str| match value.to_ascii_lowercase().as_str() {
+        "normal" => Some(400),
+        "bold" => Some(700),
+        number => number
+            .parse::<f32>()
+            .ok()
+            .filter(|weight| (1.0..=1000.0).contains(weight))
+            .map(|weight| weight.round() as u16),
+    };
+    let mut parts = value.split_ascii_whitespace();
+    let low = single(parts.next()?)?;
+    let high = match parts.next() {
+        Some(high) => single(high)?,
+        None => low,
+    };
+    Some((low.min(high), low.max(high)))
+}
+
+fn split_top_level(value: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut start) = (0usize, None, 0);
+    for (index, ch) in value.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (c, Some(open)) if c == open => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => depth = depth.saturating_sub(1),
+            (',', None) if depth == 0 => {
+                parts.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// The text between `name(` and its closing parenthesis.
+fn function_argument<'a>(entry: &'a str, name: &str) -> Option<&'a str> {
+    let open = entry.get(..name.len() + 1)?;
+    if !open[..name.len()].eq_ignore_ascii_case(name) || !open.ends_with('(') {
+        return None;
+    }
+    let inner = &entry[name.len() + 1..];
+    let (mut depth, mut quote) = (0usize, None);
+    for (index, ch) in inner.char_indices() {
+        match (ch, quote) {
+            ('"' | '\'', None) => quote = Some(ch),
+            (c, Some(open)) if c == open => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => return Some(&inner[..index]),
+            (')', None) => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unquote(value: &str) -> &str {
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+    if quoted {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontFaceStatus {
+    Unloaded,
+    Loading,
+    Loaded,
+    Error,
+}
+
+impl FontFaceStatus {
+    /// The `FontFace.status` string.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FontFaceStatus::Unloaded => "unloaded",
+            FontFaceStatus::Loading => "loading",
+            FontFaceStatus::Loaded => "loaded",
+            FontFaceStatus::Error => "error",
+        }
+    }
+}
+
+/// A face finishing its load, successfully or not.
+#[derive(Debug, Clone)]
+pub struct FontLoadEvent {
+    pub id: u64,
+    pub family: String,
+    pub status: FontFaceStatus,
+    pub error: Option<String>,
+}
+
+impl FontLoadEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "family": self.family,
+            "status": self.status.as_str(),
+            "error": self.error,
+        })
+    }
+}
+
+struct FontFaceEntry {
+    id: u64,
+    descriptor: FontFaceDescriptor,
+    status: FontFaceStatus,
+    error: Option<String>,
+    font: Option<Arc<LoadedFont>>,
+    /// In `document.fonts`; only member faces are matched for text.
+    in_set: bool,
+    /// Loaded within its `font-display` swap period.
+    active: bool,
+    load_started: Option<Instant>,
+    /// The weights the face covers once loaded; see
+    /// [`FontFaceDescriptor::weight`].
+    weights: (u16, u16),
+    /// The loaded font has a `wght` axis to draw other weights with.
+    variable: bool,
+}
+
+impl FontFaceEntry {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "family": self.descriptor.family,
+            "style": self.descriptor.style.as_str(),
+            "weight": match self.descriptor.weight {
+                None => "auto".to_string(),
+                Some((low, high)) if low == high => low.to_string(),
+                Some((low, high)) => format!("{} {}", low, high),
+            },
+            "display": self.descriptor.display.as_str(),
+            "status": self.status.as_str(),
+            "error": self.error,
+        })
+    }
+}
+
+/// Vertical metrics of a face at one size, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    /// Distance from the baseline down to the top of an underline.
+    pub underline_offset: f32,
+    pub underline_thickness: f32,
+    /// Distance from the baseline up to the top of a line-through.
+    pub strikeout_offset: f32,
+    pub strikeout_thickness: f32,
+}
+
+impl FontMetrics {
+    /// Metrics for text without a usable face, matching the proportions
+    /// text layout assumes.
+    pub fn fallback(font_size: f32) -> Self {
+        let thickness = (font_size / 14.0).max(1.0);
+        Self {
+            ascent: font_size * 0.8,
+            descent: font_size * 0.2,
+            underline_offset: font_size * 0.075,
+            underline_thickness: thickness,
+            strikeout_offset: font_size * 0.3 + thickness / 2.0,
+            strikeout_thickness: thickness,
+        }
+    }
+
+    /// Metrics from `face`'s `hhea`, `post` and `OS/2` tables, falling back
+    /// per value where a table is missing.
+    pub fn from_face(face: &ttf_parser::Face, font_size: f32) -> Self {
+        let scale = font_size / face.units_per_em() as f32;
+        let mut metrics = Self::fallback(font_size);
+        metrics.ascent = face.ascender() as f32 * scale;
+        metrics.descent = -(face.descender() as f32) * scale;
+        if let Some(underline) = face.underline_metrics() {
+            metrics.underline_offset = -(underline.position as f32) * scale;
+            metrics.underline_thickness = (underline.thickness as f32 * scale).max(1.0);
+        }
+        if let Some(strikeout) = face.strikeout_metrics() {
+            metrics.strikeout_offset = strikeout.position as f32 * scale;
+            metrics.strikeout_thickness = (strikeout.thickness as f32 * scale).max(1.0);
+        }
+        metrics
+    }
+}
+
+/// A loaded face and the position on its variation axes text is drawn at.
+#[derive(Debug, Clone)]
+pub struct FontInstance {
+    /// Id of the face in its [`FontFaceSet`].
+    pub face: u64,
+    pub font: Arc<LoadedFont>,
+    pub coords: VariationCoords,
+}
+
+impl FontInstance {
+    /// The face parsed and instanced at [`Self::coords`].
+    pub fn ttf_face(&self) -> Option<ttf_parser::Face<'_>> {
+        let mut face = ttf_parser::Face::parse(&self.font.data, self.font.index).ok()?;
+        self.coords.apply(&mut face);
+        Some(face)
+    }
+}
+
+/// Every font face of the current document, with its load state.
+#[derive(Default)]
+pub struct FontFaceSet {
+    faces: RwLock<Vec<FontFaceEntry>>,
+    events: Mutex<Vec<FontLoadEvent>>,
+    /// A face started or stopped being used for text since the last
+    /// [`take_layout_dirty`](Self::take_layout_dirty).
+    layout_dirty: AtomicBool,
+    next_id: AtomicU64,
+    shaping_observer: RwLock<Option<ShapingObserver>>,
+    /// The positions each variable face has been drawn at, by face id.
+    instances: Mutex<HashMap<u64, HashSet<VariationCoords>>>,
+}
+
+impl FontFaceSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget every face; pending loads still finish but are ignored.
+    pub fn clear(&self) {
+        self.faces.write().clear();
+        self.events.lock().clear();
+        self.instances.lock().clear();
+        self.layout_dirty.store(false, Ordering::SeqCst);
+    }
+
+    /// Register a face. CSS-declared faces are members of the set right
+    /// away; script-constructed ones join on `document.fonts.add()`.
+    pub fn add(&self, descriptor: FontFaceDescriptor, in_set: bool) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.faces.write().push(FontFaceEntry {
+            id,
+            descriptor,
+            status: FontFaceStatus::Unloaded,
+            error: None,
+            font: None,
+            in_set,
+            active: false,
+            load_started: None,
+            weights: (400, 400),
+            variable: false,
+        });
+        id
+    }
+
+    /// Register a face from font bytes already in memory (a `FontFace`
+    /// built from an `ArrayBuffer`). It is loaded, or failed, immediately.
+    pub fn add_data(&self, descriptor: FontFaceDescriptor, data: &[u8]) -> u64 {
+        let id = self.add(descriptor, false);
+        let result = decode_font(data).map(|data| LoadedFont { data, index: 0 });
+        self.finish_load(id, result, false);
+        id
+    }
+
+    /// Register the faces of every `@font-face` rule in `rules`, including
+    /// those nested in `@media`. Invalid rules are skipped.
+    pub fn add_rules(&self, rules: &[CSSRule], base: &Url) -> Vec<u64> {
+        let mut ids = Vec::new();
+        for rule in rules {
+            match rule {
+                CSSRule::FontFace(rule) => match FontFaceDescriptor::from_rule(rule, base) {
+                    Ok(descriptor) => ids.push(self.add(descriptor, true)),
+                    Err(e) => tracing::warn!("Ignoring @font-face rule: {}", e),
+                },
+                CSSRule::Media(media) => ids.extend(self.add_rules(&media.rules, base)),
+                _ => {}
+            }
+        }
+        ids
+    }
+
+    pub fn set_membership(&self, id: u64, in_set: bool) -> bool {
+        let mut faces = self.faces.write();
+        match faces.iter_mut().find(|face| face.id == id) {
+            Some(face) => {
+                if face.in_set != in_set && face.active {
+                    self.layout_dirty.store(true, Ordering::SeqCst);
+                }
+                face.in_set = in_set;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn status(&self, id: u64) -> Option<FontFaceStatus> {
+        self.faces
+            .read()
+            .iter()
+            .find(|face| face.id == id)
+            .map(|face| face.status)
+    }
+
+    /// Face `id` as handed to script, or `None` when it is unknown.
+    pub fn describe(&self, id: u64) -> Option<serde_json::Value> {
+        self.faces
+            .read()
+            .iter()
+            .find(|face| face.id == id)
+            .map(FontFaceEntry::to_json)
+    }
+
+    /// Ids of the faces in `document.fonts`, in insertion order.
+    pub fn members(&self) -> Vec<u64> {
+        self.faces
+            .read()
+            .iter()
+            .filter(|face| face.in_set)
+            .map(|face| face.id)
+            .collect()
+    }
+
+    /// Whether any face in the set is still loading.
+    pub fn is_loading(&self) -> bool {
+        self.faces
+            .read()
+            .iter()
+            .any(|face| face.in_set && face.status == FontFaceStatus::Loading)
+    }
+
+    /// Start loading face `id` if it has not been started. Returns `false`
+    /// when the face is unknown or there is no runtime to load on.
+    pub fn start_load(self: &Arc<Self>, id: u64, loader: &Arc<FontLoader>) -> bool {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+        let sources = {
+            let mut faces = self.faces.write();
+            let face = match faces.iter_mut().find(|face| face.id == id) {
+                Some(face) => face,
+                None => return false,
+            };
+            if face.status != FontFaceStatus::Unloaded {
+                return true;
+            }
+            face.status = FontFaceStatus::Loading;
+            face.load_started = Some(Instant::now());
+            (
+                face.descriptor.family.clone(),
+                face.descriptor.sources.clone(),
+            )
+        };
+
+        let set = Arc::clone(self);
+        let loader = Arc::clone(loader);
+        handle.spawn(async move {
+            let (family, sources) = sources;
+            let result = loader.load(&family, &sources).await;
+            set.finish_load(id, result, true);
+        });
+        true
+    }
+
+    /// Start every face in the set that has not been loaded yet.
+    pub fn load_all(self: &Arc<Self>, loader: &Arc<FontLoader>) {
+        for id in self.members() {
+            self.start_load(id, loader);
+        }
+    }
+
+    fn finish_load(&self, id: u64, result: Result<LoadedFont>, queue_event: bool) {
+        let mut faces = self.faces.write();
+        let face = match faces.iter_mut().find(|face| face.id == id) {
+            Some(face) => face,
+            None => return,
+        };
+
+        match result {
+            Ok(font) => {
+                let elapsed = face
+                    .load_started
+                    .map_or(Duration::ZERO, |start| start.elapsed());
+                face.status = FontFaceStatus::Loaded;
+                let weight_axis = ttf_parser::Face::parse(&font.data, font.index)
+                    .ok()
+                    .and_then(|parsed| {
+                        parsed
+                            .variation_axes()
+                            .into_iter()
+                            .find(|axis| axis.tag == ttf_parser::Tag::from_bytes(b"wght"))
+                    })
+                    .map(|axis| (axis.min_value.round() as u16, axis.max_value.round() as u16));
+                face.variable = weight_axis.is_some();
+                face.weights = face.descriptor.weight.or(weight_axis).unwrap_or((400, 400));
+                face.font = Some(Arc::new(font));
+                face.active = face.descriptor.display.allows_swap_after(elapsed);
+                if !face.active {
+                    tracing::debug!(
+                        "Font '{}' loaded after its swap period; keeping fallback",
+                        face.descriptor.family
+                    );
+                }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to load font '{}': {}", face.descriptor.family, e);
+                face.status = FontFaceStatus::Error;
+                face.error = Some(e.to_string());
+            }
+        }
+
+        if face.active && face.in_set {
+            self.layout_dirty.store(true, Ordering::SeqCst);
+        }
+        if queue_event {
+            self.events.lock().push(FontLoadEvent {
+                id,
+                family: face.descriptor.family.clone(),
+                status: face.status,
+                error: face.error.clone(),
+            });
+        }
+    }
+
+    /// Loads that settled since the last call.
+    pub fn take_events(&self) -> Vec<FontLoadEvent> {
+        std::mem::take(&mut *self.events.lock())
+    }
+
+    /// Whether text must be laid out again because the faces it may use
+    /// changed. Clears the flag.
+    pub fn take_layout_dirty(&self) -> bool {
+        self.layout_dirty.swap(false, Ordering::SeqCst)
+    }
+
+    /// Width of `text` shaped as a run of unknown language in the initial
+    /// font properties; see [`Self::shape`].
+    pub fn measure_text(&self, font_family: &str, font_size: f32, text: &str) -> Option<f32> {
+        self.shape(font_family, &FontRequest::default(), font_size, text, None)
+            .map(|shaped| shaped.width())
+    }
+
+    /// Shape `text` as one run in the CSS `font-family` list and `font`
+    /// properties at `font_size`, in content language `language`. `None`
+    /// when no web font applies and the caller should fall back to its own
+    /// metrics.
+    pub fn shape(
+        &self,
+        font_family: &str,
+        font: &FontRequest,
+        font_size: f32,
+        text: &str,
+        language: Option<&str>,
+    ) -> Option<ShapedText> {
+        let (family, instance) = self.face_for(font_family, font, text, language)?;
+        let mut face = rustybuzz::Face::from_slice(&instance.font.data, instance.font.index)?;
+        instance.coords.apply(&mut face);
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(text);
+        if let Some(language) = language.and_then(|tag| tag.parse().ok()) {
+            buffer.set_language(language);
+        }
+        buffer.guess_segment_properties();
+
+        let observer = self.shaping_observer.read().clone();
+        if let Some(observer) = observer {
+            observer(&ShapedRun {
+                family,
+                language: language.map(str::to_string),
+                text: text.to_string(),
+            });
+        }
+        let shaped = rustybuzz::shape(&face, &[], buffer);
+
+        let scale = font_size / face.units_per_em() as f32;
+        Some(ShapedText::new(
+            shaped
+                .glyph_infos()
+                .iter()
+                .zip(shaped.glyph_positions())
+                .map(|(info, position)| (info.cluster as usize, position.x_advance as f32 * scale))
+                .collect(),
+        ))
+    }
+
+    /// Have `observer` called with every run [`Self::shape`] shapes from now
+    /// on; `None` stops.
+    pub fn set_shaping_observer(&self, observer: Option<ShapingObserver>) {
+        *self.shaping_observer.write() = observer;
+    }
+
+    pub fn shaping_observer(&self) -> Option<ShapingObserver> {
+        self.shaping_observer.read().clone()
+    }
+
+    /// The face of the first listed family with a usable one, instanced for
+    /// `font`: the one [`Self::shape`] uses for text it covers. `None` when
+    /// no web font applies.
+    pub fn instance(&self, font_family: &str, font: &FontRequest) -> Option<FontInstance> {
+        split_top_level(font_family)
+            .into_iter()
+            .find_map(|family| self.match_face(unquote(family.trim()), font))
+    }
+
+    /// Metrics of the face [`Self::instance`] picks, at its position on the
+    /// variation axes. `None` when no web font applies.
+    pub fn metrics(
+        &self,
+        font_family: &str,
+        font: &FontRequest,
+        font_size: f32,
+    ) -> Option<FontMetrics> {
+        let instance = self.instance(font_family, font)?;
+        let face = instance.ttf_face()?;
+        Some(FontMetrics::from_face(&face, font_size))
+    }
+
+    /// The listed family a run of `text` in `language` is shaped with, and
+    /// its face: the first covering every letter of `text` and the script of
+    /// `language`, else the first covering `text`, else the first with a
+    /// face at all.
+    fn face_for(
+        &self,
+        font_family: &str,
+        font: &FontRequest,
+        text: &str,
+        language: Option<&str>,
+    ) -> Option<(String, FontInstance)> {
+        let probe = language.and_then(shape::script_probe);
+        let mut best: Option<((bool, bool), &str, FontInstance)> = None;
+        for family in split_top_level(font_family) {
+            let family = unquote(family.trim());
+            let instance = match self.match_face(family, font) {
+                Some(instance) => instance,
+                None => continue,
+            };
+            let face = match ttf_parser::Face::parse(&instance.font.data, instance.font.index) {
+                Ok(face) => face,
+                Err(_) => continue,
+            };
+            let covers = text
+                .chars()
+                .filter(|c| !c.is_whitespace() && !c.is_control())
+                .all(|c| face.glyph_index(c).is_some());
+            let suits = probe.map_or(true, |c| face.glyph_index(c).is_some());
+            if covers && suits {
+                return Some((family.to_string(), instance));
+            }
+            if best
+                .as_ref()
+                .map_or(true, |(rank, ..)| (covers, suits) > *rank)
+            {
+                best = Some(((covers, suits), family, instance));
+            }
+        }
+        best.map(|(_, family, instance)| (family.to_string(), instance))
+    }
+
+    /// The active member face of `family` closest to `font`, instanced for
+    /// it. Among faces covering the weight, a variable one wins over a
+    /// static one, so bold text gets the font's own bold.
+    fn match_face(&self, family: &str, font: &FontRequest) -> Option<FontInstance> {
+        let (id, loaded, weights) = {
+            let faces = self.faces.read();
+            let face = faces
+                .iter()
+                .filter(|face| {
+                    face.in_set
+                        && face.active
+                        && face.descriptor.family.eq_ignore_ascii_case(family)
+                })
+                .min_by_key(|face| {
+                    let (low, high) = face.weights;
+                    let weight = font.weight.round() as u16;
+                    let distance = if weight < low {
+                        low - weight
+                    } else {
+                        weight.saturating_sub(high)
+                    };
+                    (
+                        face.descriptor.style != font.style,
+                        distance,
+                        !face.variable,
+                    )
+                })?;
+            (face.id, face.font.clone()?, face.weights)
+        };
+
+        let face = ttf_parser::Face::parse(&loaded.data, loaded.index).ok()?;
+        let exact = VariationCoords::for_request(&face, font, weights);
+        let coords = self.settle_instance(id, &face, exact);
+        Some(FontInstance {
+            face: id,
+            font: loaded,
+            coords,
+        })
+    }
+
+    /// `coords` if face `id` may be drawn there, else its nearest named
+    /// instance once it has been drawn at
+    /// [`variation::MAX_INSTANCES_PER_FACE`] positions already.
+    fn settle_instance(
+        &self,
+        id: u64,
+        face: &ttf_parser::Face,
+        coords: VariationCoords,
+    ) -> VariationCoords {
+        if coords.is_empty() {
+            return coords;
+        }
+        let mut instances = self.instances.lock();
+        let seen = instances.entry(id).or_default();
+        if seen.contains(&coords) || seen.len() < variation::MAX_INSTANCES_PER_FACE {
+            seen.insert(coords.clone());
+            return coords;
+        }
+        match coords.nearest_named_instance(face) {
+            Some(named) => {
+                seen.insert(named.clone());
+                named
+            }
+            None => coords,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Shaping runs of text with their content language.
+//!
+//! A run is text in one `font-family` list, size and language. The language
+//! goes to the shaper, which picks the OpenType `locl` forms some languages
+//! write differently (Serbian Cyrillic italics, Han in Japanese versus
+//! Chinese text), and to fallback among the listed families: a face that
+//! covers the run wins over one that does not, and among those one that
+//! also covers the script the language is written in, so Japanese Han text
+//! prefers a face with kana to a Chinese-only one.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// What the shaper was handed for one run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapedRun {
+    /// The listed family whose face shaped the run.
+    pub family: String,
+    /// BCP 47 tag, or `None` when the run's language is unknown.
+    pub language: Option<String>,
+    pub text: String,
+}
+
+/// Called with every run as it is shaped, for tests and diagnostics.
+pub type ShapingObserver = Arc<dyn Fn(&ShapedRun) + Send + Sync>;
+
+/// Advances of shaped text, by cluster.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShapedText {
+    /// Byte offset of each cluster into the text, ascending.
+    clusters: Vec<usize>,
+    /// Width of the clusters before each index, and of all of them last.
+    widths: Vec<f32>,
+}
+
+impl ShapedText {
+    /// From the cluster and advance of each glyph, in any order, as
+    /// right-to-left runs come out of the shaper.
+    pub(crate) fn new(mut glyphs: Vec<(usize, f32)>) -> Self {
+        glyphs.sort_by_key(|&(cluster, _)| cluster);
+        let mut clusters: Vec<usize> = Vec::with_capacity(glyphs.len());
+        let mut widths = Vec::with_capacity(glyphs.len() + 1);
+        let mut width = 0.0;
+        widths.push(width);
+        for (cluster, advance) in glyphs {
+            if clusters.last() == Some(&cluster) {
+                width += advance;
+                *widths.last_mut().expect("pushed above") = width;
+                continue;
+            }
+            clusters.push(cluster);
+            width += advance;
+            widths.push(width);
+        }
+        Self { clusters, widths }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.widths.last().copied().unwrap_or_default()
+    }
+
+    /// Width of the clusters starting within `bytes` of the shaped text.
+    pub fn width_of(&self, bytes: Range<usize>) -> f32 {
+        let start = self
+            .clusters
+            .partition_point(|&cluster| cluster < bytes.start);
+        let end = self
+            .clusters
+            .partition_point(|&cluster| cluster < bytes.end);
+        self.widths[end.max(start)] - self.widths[start]
+    }
+}
+
+/// A letter of the script `language` is written in that neighbouring
+/// scripts lack, to tell a face made for it from one that only shares its
+/// Han or Latin letters. `None` for Latin-script and unknown languages.
+pub(crate) fn script_probe(language: &str) -> Option<char> {
+    let primary = language.split(['-', '_']).next()?.to_ascii_lowercase();
+    Some(match primary.as_str() {
+        "ja" => 'あ',
+        "ko" => '가',
+        "zh" => '中',
+        "ru" | "uk" | "be" | "bg" | "mk" | "sr" | "kk" | "ky" | "mn" => 'д',
+        "el" => 'λ',
+        "ar" | "fa" | "ur" | "ps" => 'ع',
+        "he" | "yi" => 'א',
+        "hi" | "mr" | "ne" | "sa" => 'क',
+        "bn" => 'ক',
+        "ta" => 'க',
+        "th" => 'ก',
+        "ka" => 'ა',
+        "hy" => 'ա',
+        _ => return None,
+    })
+}
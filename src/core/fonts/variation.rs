@@ -0,0 +1,328 @@
+//! Variable fonts: where on its `fvar` axes a face is drawn for the text
+//! styled with it.
+//!
+//! `font-weight`, `font-stretch` and `font-style` map to the registered
+//! `wght`, `wdth`, `ital` and `slnt` axes, and `font-variation-settings`
+//! sets any axis directly, winning over them. Values are clamped to the
+//! axis range. Every distinct instance of a face costs its own shaping and
+//! glyph atlas entries, so once a face has been drawn at
+//! [`MAX_INSTANCES_PER_FACE`] positions, further requests take the named
+//! instance nearest to them instead of an exact one.
+
+use super::FontStyle;
+use crate::core::css::{ComputedStyles, ComputedValue};
+use std::hash::{Hash, Hasher};
+
+/// An OpenType axis tag, such as `*b"wght"`.
+pub type AxisTag = [u8; 4];
+
+pub const MAX_INSTANCES_PER_FACE: usize = 16;
+
+/// The slant of `font-style: oblique` without an angle, in degrees.
+pub const DEFAULT_OBLIQUE_ANGLE: f32 = 14.0;
+
+/// The font properties of a run of text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontRequest {
+    pub weight: f32,
+    /// `font-stretch`, as a percentage of the normal width.
+    pub stretch: f32,
+    pub style: FontStyle,
+    /// Clockwise slant of `oblique` text, in degrees.
+    pub oblique_angle: f32,
+    /// `font-variation-settings`, in declaration order; later ones win.
+    pub variation_settings: Vec<(AxisTag, f32)>,
+}
+
+impl Default for FontRequest {
+    fn default() -> Self {
+        Self {
+            weight: 400.0,
+            stretch: 100.0,
+            style: FontStyle::Normal,
+            oblique_angle: DEFAULT_OBLIQUE_ANGLE,
+            variation_settings: Vec::new(),
+        }
+    }
+}
+
+impl FontRequest {
+    /// The request of text in `styles`. Properties that are unset or fail
+    /// to parse keep their initial values.
+    pub fn from_styles(styles: &ComputedStyles) -> Self {
+        // A `font-stretch` percentage has no base to compute against, so
+        // the cascaded value is read when computing it fails.
+        let text = |name: &str| {
+            styles
+                .get_computed_value(name)
+                .ok()
+                .or_else(|| styles.get_property(name))
+                .map(|value| css_text(&value))
+        };
+
+        let mut request = Self::default();
+        if let Some(weight) = text("font-weight").and_then(|value| parse_font_weight(&value)) {
+            request.weight = weight;
+        }
+        if let Some(stretch) = text("font-stretch").and_then(|value| parse_font_stretch(&value)) {
+            request.stretch = stretch;
+        }
+        if let Some((style, angle)) = text("font-style").and_then(|value| parse_font_style(&value))
+        {
+            request.style = style;
+            request.oblique_angle = angle;
+        }
+        if let Some(settings) =
+            text("font-variation-settings").and_then(|value| parse_variation_settings(&value))
+        {
+            request.variation_settings = settings;
+        }
+        request
+    }
+}
+
+/// Specified values come back from the cascade split into words; join them
+/// into text again for the parsers below.
+fn css_text(value: &ComputedValue) -> String {
+    match value {
+        ComputedValue::List(values) => values.iter().map(css_text).collect::<Vec<_>>().join(" "),
+        ComputedValue::Keyword(word) => word.clone(),
+        ComputedValue::String(string) => format!("\"{}\"", string),
+        ComputedValue::Length(number) | ComputedValue::Number(number) => number.to_string(),
+        ComputedValue::Percentage(number) => format!("{}%", number),
+        ComputedValue::Integer(number) => number.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// `normal`, `bold` or a number from 1 to 1000.
+pub fn parse_font_weight(value: &str) -> Option<f32> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "normal" => Some(400.0),
+        "bold" => Some(700.0),
+        number => number
+            .parse::<f32>()
+            .ok()
+            .filter(|weight| (1.0..=1000.0).contains(weight)),
+    }
+}
+
+/// A width keyword or a non-negative percentage.
+pub fn parse_font_stretch(value: &str) -> Option<f32> {
+    let value = value.trim().to_ascii_lowercase();
+    let keyword = match value.as_str() {
+        "ultra-condensed" => Some(50.0),
+        "extra-condensed" => Some(62.5),
+        "condensed" => Some(75.0),
+        "semi-condensed" => Some(87.5),
+        "normal" => Some(100.0),
+        "semi-expanded" => Some(112.5),
+        "expanded" => Some(125.0),
+        "extra-expanded" => Some(150.0),
+        "ultra-expanded" => Some(200.0),
+        _ => None,
+    };
+    keyword.or_else(|| {
+        value
+            .strip_suffix('%')?
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|stretch| *stretch >= 0.0)
+    })
+}
+
+/// `normal`, `italic` or `oblique` with an optional angle between -90deg
+/// and 90deg, and the angle.
+pub fn parse_font_style(value: &str) -> Option<(FontStyle, f32)> {
+    let value = value.trim().to_ascii_lowercase();
+    let mut words = value.split_ascii_whitespace();
+    let style = match words.next()? {
+        "normal" => FontStyle::Normal,
+        "italic" => FontStyle::Italic,
+        "oblique" => FontStyle::Oblique,
+        _ => return None,
+    };
+    let angle = match words.next() {
+        Some(angle) if style == FontStyle::Oblique => {
+            let degrees = angle.strip_suffix("deg").unwrap_or(angle);
+            degrees
+                .parse::<f32>()
+                .ok()
+                .filter(|degrees| (-90.0..=90.0).contains(degrees))?
+        }
+        Some(_) => return None,
+        None => DEFAULT_OBLIQUE_ANGLE,
+    };
+    if words.next().is_some() {
+        return None;
+    }
+    Some((style, angle))
+}
+
+/// `normal`, or comma-separated `"tag" value` pairs.
+pub fn parse_variation_settings(value: &str) -> Option<Vec<(AxisTag, f32)>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("normal") {
+        return Some(Vec::new());
+    }
+    let mut settings = Vec::new();
+    for setting in value.split(',') {
+        let mut words = setting.split_ascii_whitespace();
+        let tag = words.next()?;
+        let tag = tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .or_else(|| tag.strip_prefix('\'')?.strip_suffix('\''))?;
+        let tag: AxisTag = tag.as_bytes().try_into().ok()?;
+        if !tag.iter().all(|byte| (0x20..=0x7e).contains(byte)) {
+            return None;
+        }
+        let number = words.next()?.parse::<f32>().ok()?;
+        if words.next().is_some() {
+            return None;
+        }
+        settings.push((tag, number));
+    }
+    Some(settings)
+}
+
+/// The axis values a face is drawn at, in `fvar` order; empty for a static
+/// face. Compared and hashed by value, for keying caches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VariationCoords(Vec<(AxisTag, f32)>);
+
+impl Eq for VariationCoords {}
+
+impl Hash for VariationCoords {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for (tag, value) in &self.0 {
+            tag.hash(state);
+            value.to_bits().hash(state);
+        }
+    }
+}
+
+impl VariationCoords {
+    fn new(coords: impl IntoIterator<Item = (AxisTag, f32)>) -> Self {
+        // Adding zero turns -0.0 into 0.0, which `Hash` would tell apart.
+        Self(
+            coords
+                .into_iter()
+                .map(|(tag, value)| (tag, value + 0.0))
+                .collect(),
+        )
+    }
+
+    /// Where `face` is drawn for `request`. The weight is first clamped to
+    /// `weights`, the face's `font-weight` descriptor range.
+    pub fn for_request(
+        face: &ttf_parser::Face,
+        request: &FontRequest,
+        weights: (u16, u16),
+    ) -> Self {
+        Self::new(face.variation_axes().into_iter().map(|axis| {
+            let tag = axis.tag.to_bytes();
+            let registered = match &tag {
+                b"wght" => Some(request.weight.clamp(weights.0 as f32, weights.1 as f32)),
+                b"wdth" => Some(request.stretch),
+                b"ital" => Some(if request.style == FontStyle::Italic {
+                    1.0
+                } else {
+                    0.0
+                }),
+                // `slnt` counts counter-clockwise degrees.
+                b"slnt" if request.style == FontStyle::Oblique => Some(-request.oblique_angle),
+                _ => None,
+            };
+            let value = request
+                .variation_settings
+                .iter()
+                .rev()
+                .find(|(setting, _)| *setting == tag)
+                .map(|(_, value)| *value)
+                .or(registered)
+                .unwrap_or(axis.def_value);
+            (tag, value.clamp(axis.min_value, axis.max_value))
+        }))
+    }
+
+    /// The named instance of `face` closest to these coordinates, measured
+    /// across axes scaled to their ranges. `None` when the face names none.
+    pub fn nearest_named_instance(&self, face: &ttf_parser::Face) -> Option<Self> {
+        let axes: Vec<_> = face.variation_axes().into_iter().collect();
+        let distance = |instance: &[f32]| -> f32 {
+            axes.iter()
+                .zip(instance)
+                .map(|(axis, value)| {
+                    let range = (axis.max_value - axis.min_value).max(f32::EPSILON);
+                    let wanted = self.get(axis.tag.to_bytes()).unwrap_or(axis.def_value);
+                    ((value - wanted) / range).powi(2)
+                })
+                .sum()
+        };
+        let nearest = named_instances(face, axes.len())
+            .into_iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))?;
+        Some(Self::new(
+            axes.iter()
+                .zip(nearest)
+                .map(|(axis, value)| (axis.tag.to_bytes(), value)),
+        ))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, tag: AxisTag) -> Option<f32> {
+        self.0
+            .iter()
+            .find(|(axis, _)| *axis == tag)
+            .map(|(_, value)| *value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(AxisTag, f32)> {
+        self.0.iter()
+    }
+
+    /// Instance `face` at these coordinates, for its metrics and outlines.
+    pub fn apply(&self, face: &mut ttf_parser::Face) {
+        for (tag, value) in &self.0 {
+            face.set_variation(ttf_parser::Tag::from_bytes(tag), *value);
+        }
+    }
+}
+
+/// The axis values of every named instance in `face`'s `fvar` table, which
+/// ttf-parser does not expose.
+fn named_instances(face: &ttf_parser::Face, axis_count: usize) -> Vec<Vec<f32>> {
+    use super::decode::{read_u16, read_u32};
+
+    let fvar = match face.raw_face().table(ttf_parser::Tag::from_bytes(b"fvar")) {
+        Some(fvar) => fvar,
+        None => return Vec::new(),
+    };
+    let header = (|| {
+        let axes_offset = read_u16(fvar, 4)? as usize;
+        let axis_size = read_u16(fvar, 10)? as usize;
+        let count = read_u16(fvar, 12)? as usize;
+        let instance_size = read_u16(fvar, 14)? as usize;
+        Some((axes_offset + axis_count * axis_size, count, instance_size))
+    })();
+    let (start, count, instance_size) = match header {
+        Some(header) if header.2 >= 4 + axis_count * 4 => header,
+        _ => return Vec::new(),
+    };
+
+    (0..count)
+        .map_while(|index| {
+            let record = start + index * instance_size;
+            (0..axis_count)
+                .map(|axis| {
+                    read_u32(fvar, record + 4 + axis * 4).map(|fixed| fixed as i32 as f32 / 65536.0)
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,563 @@
+//! WOFF 2.0 decoding (<https://www.w3.org/TR/WOFF2/>).
+//!
+//! All table data shares one Brotli stream. `glyf` and `loca` are normally
+//! stored in the transformed form of section 5, and `hmtx` may be; all three
+//! are rebuilt here. Font collections are not supported.
+
+use super::decode::{build_sfnt, read_u16};
+use super::{FontError, Result};
+use std::io::Read;
+
+const HEADER_SIZE: usize = 48;
+const COLLECTION_FLAVOR: u32 = u32::from_be_bytes(*b"ttcf");
+
+/// Tags indexed by the 6-bit known-table code of the table directory.
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+// Simple glyph flags in the rebuilt `glyf` table.
+const ON_CURVE: u8 = 0x01;
+const X_SHORT: u8 = 0x02;
+const Y_SHORT: u8 = 0x04;
+const X_SAME_OR_POSITIVE: u8 = 0x10;
+const Y_SAME_OR_POSITIVE: u8 = 0x20;
+const OVERLAP_SIMPLE: u8 = 0x40;
+
+// Composite glyph component flags.
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+struct TableEntry {
+    tag: [u8; 4],
+    original_length: usize,
+    /// Bytes the table occupies in the decompressed stream.
+    stored_length: usize,
+    transformed: bool,
+}
+
+/// Decode a WOFF2 file to sfnt bytes.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut header = Reader::new(data);
+    header.skip(4)?;
+    let flavor = header.u32()?;
+    if flavor == COLLECTION_FLAVOR {
+        return Err(FontError::Unsupported("WOFF2 font collections".to_string()));
+    }
+    header.skip(4)?;
+    let num_tables = header.u16()? as usize;
+    header.skip(2 + 4)?;
+    let compressed_size = header.u32()? as usize;
+
+    let mut directory = Reader::new(data);
+    directory.skip(HEADER_SIZE)?;
+    let mut entries = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let flags = directory.u8()?;
+        let tag = match flags & 0x3f {
+            63 => directory.tag()?,
+            known => *KNOWN_TAGS[known as usize],
+        };
+        let version = flags >> 6;
+        let transformed = if &tag == b"glyf" || &tag == b"loca" {
+            version != 3
+        } else {
+            version != 0
+        };
+        let known_transform = match &tag {
+            b"glyf" | b"loca" => version == 0,
+            b"hmtx" => version == 1,
+            _ => false,
+        };
+        let original_length = directory.base128()? as usize;
+        let stored_length = if transformed {
+            directory.base128()? as usize
+        } else {
+            original_length
+        };
+        if transformed && !known_transform {
+            return Err(FontError::Unsupported(format!(
+                "WOFF2 transform {} of '{}'",
+                version,
+                String::from_utf8_lossy(&tag)
+            )));
+        }
+        entries.push(TableEntry {
+            tag,
+            original_length,
+            stored_length,
+            transformed,
+        });
+    }
+
+    let compressed = directory.bytes(compressed_size)?;
+    let expected: usize = entries.iter().map(|entry| entry.stored_length).sum();
+    let mut stream = Vec::with_capacity(expected);
+    brotli::Decompressor::new(compressed, 4096)
+        .take(expected as u64 + 1)
+        .read_to_end(&mut stream)
+        .map_err(|e| FontError::Decode(format!("Bad WOFF2 Brotli stream: {}", e)))?;
+    if stream.len() != expected {
+        return Err(FontError::Decode(
+            "WOFF2 table data does not match the directory".to_string(),
+        ));
+    }
+
+    let mut tables = Vec::with_capacity(entries.len());
+    let mut rebuilt_loca = None;
+    let mut x_mins = None;
+    let mut transformed_hmtx = None;
+    let mut offset = 0;
+    for entry in &entries {
+        let stored = &stream[offset..offset + entry.stored_length];
+        offset += entry.stored_length;
+
+        if !entry.transformed {
+            tables.push((entry.tag, stored.to_vec()));
+        } else if &entry.tag == b"glyf" {
+            let glyphs = reconstruct_glyf(stored)?;
+            tables.push((entry.tag, glyphs.glyf));
+            rebuilt_loca = Some(glyphs.loca);
+            x_mins = Some(glyphs.x_mins);
+        } else if &entry.tag == b"hmtx" {
+            transformed_hmtx = Some(stored);
+        }
+    }
+
+    // The transformed `hmtx` leaves out bearings that equal the glyphs'
+    // `xMin`, so it can only be rebuilt once `glyf` has been.
+    if let Some(stored) = transformed_hmtx {
+        let x_mins = x_mins.as_deref().ok_or_else(|| {
+            FontError::Decode("Transformed hmtx without transformed glyf".to_string())
+        })?;
+        let num_h_metrics = tables
+            .iter()
+            .find(|(tag, _)| tag == b"hhea")
+            .and_then(|(_, hhea)| read_u16(hhea, 34))
+            .ok_or_else(|| FontError::Decode("Transformed hmtx without hhea".to_string()))?;
+        let hmtx = reconstruct_hmtx(stored, num_h_metrics as usize, x_mins)?;
+        tables.push((*b"hmtx", hmtx));
+    }
+
+    // `loca` has no data of its own when transformed; it comes out of `glyf`.
+    for entry in entries
+        .iter()
+        .filter(|e| e.transformed && &e.tag == b"loca")
+    {
+        let loca = rebuilt_loca.take().ok_or_else(|| {
+            FontError::Decode("Transformed loca without transformed glyf".to_string())
+        })?;
+        if loca.len() != entry.original_length {
+            return Err(FontError::Decode(
+                "Rebuilt loca does not match its declared length".to_string(),
+            ));
+        }
+        tables.push((entry.tag, loca));
+    }
+
+    Ok(build_sfnt(flavor, tables))
+}
+
+/// Tables rebuilt from a transformed `glyf`.
+struct Glyphs {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    /// `xMin` of every glyph; 0 for empty ones.
+    x_mins: Vec<i16>,
+}
+
+/// Rebuild `glyf` and `loca` from the transformed `glyf` table.
+fn reconstruct_glyf(data: &[u8]) -> Result<Glyphs> {
+    let mut header = Reader::new(data);
+    header.skip(2)?;
+    let option_flags = header.u16()?;
+    let num_glyphs = header.u16()? as usize;
+    let long_loca = header.u16()? != 0;
+
+    let mut sizes = [0usize; 7];
+    for size in &mut sizes {
+        *size = header.u32()? as usize;
+    }
+    let mut n_contours = header.sub(sizes[0])?;
+    let mut n_points = header.sub(sizes[1])?;
+    let mut flags = header.sub(sizes[2])?;
+    let mut glyphs = header.sub(sizes[3])?;
+    let mut composites = header.sub(sizes[4])?;
+    let mut bbox_stream = header.sub(sizes[5])?;
+    let mut instructions = header.sub(sizes[6])?;
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(header.bytes(num_glyphs.div_ceil(8))?)
+    } else {
+        None
+    };
+
+    let bbox_bitmap = bbox_stream.bytes(4 * num_glyphs.div_ceil(32))?;
+    let has_bit = |bitmap: &[u8], glyph: usize| bitmap[glyph >> 3] & (0x80 >> (glyph & 7)) != 0;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = vec![0; num_glyphs];
+    for (glyph, x_min) in x_mins.iter_mut().enumerate() {
+        offsets.push(glyf.len());
+        let contours = n_contours.i16()?;
+        let explicit_bbox = has_bit(bbox_bitmap, glyph);
+
+        if contours == 0 {
+            if explicit_bbox {
+                return Err(FontError::Decode("Empty glyph with a bbox".to_string()));
+            }
+        } else if contours > 0 {
+            let mut end_points = Vec::with_capacity(contours as usize);
+            let mut total = 0usize;
+            for _ in 0..contours {
+                total += n_points.u255()? as usize;
+                if total == 0 || total > u16::MAX as usize + 1 {
+                    return Err(FontError::Decode("Bad contour point count".to_string()));
+                }
+                end_points.push((total - 1) as u16);
+            }
+
+            let point_flags = flags.bytes(total)?;
+            let points = decode_triplets(point_flags, &mut glyphs)?;
+            let instruction_length = glyphs.u255()? as usize;
+            let program = instructions.bytes(instruction_length)?;
+
+            let bbox = if explicit_bbox {
+                [
+                    bbox_stream.i16()?,
+                    bbox_stream.i16()?,
+                    bbox_stream.i16()?,
+                    bbox_stream.i16()?,
+                ]
+            } else {
+                bounding_box(&points)
+            };
+
+            *x_min = bbox[0];
+            push_i16(&mut glyf, contours);
+            for value in bbox {
+                push_i16(&mut glyf, value);
+            }
+            for end in end_points {
+                glyf.extend_from_slice(&end.to_be_bytes());
+            }
+            glyf.extend_from_slice(&(instruction_length as u16).to_be_bytes());
+            glyf.extend_from_slice(program);
+            let overlap = overlap_bitmap.is_some_and(|bitmap| has_bit(bitmap, glyph));
+            encode_points(&mut glyf, &points, overlap);
+        } else if contours == -1 {
+            if !explicit_bbox {
+                return Err(FontError::Decode(
+                    "Composite glyph without a bbox".to_string(),
+                ));
+            }
+            push_i16(&mut glyf, -1);
+            *x_min = bbox_stream.i16()?;
+            push_i16(&mut glyf, *x_min);
+            for _ in 0..3 {
+                push_i16(&mut glyf, bbox_stream.i16()?);
+            }
+
+            let mut has_instructions = false;
+            loop {
+                let component_flags = composites.u16()?;
+                has_instructions |= component_flags & WE_HAVE_INSTRUCTIONS != 0;
+                let mut length = 2 + if component_flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                    4
+                } else {
+                    2
+                };
+                if component_flags & WE_HAVE_A_SCALE != 0 {
+                    length += 2;
+                } else if component_flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                    length += 4;
+                } else if component_flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                    length += 8;
+                }
+                glyf.extend_from_slice(&component_flags.to_be_bytes());
+                glyf.extend_from_slice(composites.bytes(length)?);
+                if component_flags & MORE_COMPONENTS == 0 {
+                    break;
+                }
+            }
+
+            if has_instructions {
+                let instruction_length = glyphs.u255()? as usize;
+                glyf.extend_from_slice(&(instruction_length as u16).to_be_bytes());
+                glyf.extend_from_slice(instructions.bytes(instruction_length)?);
+            }
+        } else {
+            return Err(FontError::Decode(format!(
+                "Bad contour count {} for glyph {}",
+                contours, glyph
+            )));
+        }
+
+        glyf.resize((glyf.len() + 3) & !3, 0);
+    }
+    offsets.push(glyf.len());
+
+    let mut loca = Vec::with_capacity(offsets.len() * if long_loca { 4 } else { 2 });
+    for offset in offsets {
+        if long_loca {
+            loca.extend_from_slice(&(offset as u32).to_be_bytes());
+        } else {
+            let half = u16::try_from(offset / 2)
+                .map_err(|_| FontError::Decode("glyf too large for a short loca".to_string()))?;
+            loca.extend_from_slice(&half.to_be_bytes());
+        }
+    }
+
+    Ok(Glyphs { glyf, loca, x_mins })
+}
+
+/// Rebuild `hmtx` from its transformed form (section 5.4). Flag bit 0 drops
+/// the bearings of the proportional glyphs and bit 1 those of the trailing
+/// monospaced ones; a dropped bearing is the glyph's `xMin`.
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: usize, x_mins: &[i16]) -> Result<Vec<u8>> {
+    let mut stream = Reader::new(data);
+    let flags = stream.u8()?;
+    if flags & 0xfc != 0 {
+        return Err(FontError::Decode(
+            "Reserved hmtx transform flags set".to_string(),
+        ));
+    }
+    let num_glyphs = x_mins.len();
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs {
+        return Err(FontError::Decode(format!(
+            "Bad numberOfHMetrics {} for {} glyphs",
+            num_h_metrics, num_glyphs
+        )));
+    }
+
+    let mut advances = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics {
+        advances.push(stream.u16()?);
+    }
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+    for (glyph, advance) in advances.into_iter().enumerate() {
+        let bearing = if flags & 1 != 0 {
+            x_mins[glyph]
+        } else {
+            stream.i16()?
+        };
+        hmtx.extend_from_slice(&advance.to_be_bytes());
+        push_i16(&mut hmtx, bearing);
+    }
+    for &x_min in &x_mins[num_h_metrics..] {
+        let bearing = if flags & 2 != 0 { x_min } else { stream.i16()? };
+        push_i16(&mut hmtx, bearing);
+    }
+    Ok(hmtx)
+}
+
+#[derive(Clone, Copy)]
+struct Point {
+    x: i32,
+    y: i32,
+    on_curve: bool,
+}
+
+/// Decode the variable-length coordinate triplets of section 5.2.
+fn decode_triplets(flags: &[u8], stream: &mut Reader) -> Result<Vec<Point>> {
+    let with_sign = |flag: u8, value: i32| if flag & 1 != 0 { value } else { -value };
+
+    let mut points = Vec::with_capacity(flags.len());
+    let (mut x, mut y) = (0i32, 0i32);
+    for &raw in flags {
+        let on_curve = raw & 0x80 == 0;
+        let flag = raw & 0x7f;
+        let (dx, dy) = if flag < 10 {
+            let b0 = stream.u8()? as i32;
+            (0, with_sign(flag, (((flag & 14) as i32) << 7) + b0))
+        } else if flag < 20 {
+            let b0 = stream.u8()? as i32;
+            (with_sign(flag, ((((flag - 10) & 14) as i32) << 7) + b0), 0)
+        } else if flag < 84 {
+            let code = (flag - 20) as i32;
+            let b0 = stream.u8()? as i32;
+            (
+                with_sign(flag, 1 + (code & 0x30) + (b0 >> 4)),
+                with_sign(flag >> 1, 1 + ((code & 0x0c) << 2) + (b0 & 0x0f)),
+            )
+        } else if flag < 120 {
+            let code = (flag - 84) as i32;
+            let b0 = stream.u8()? as i32;
+            let b1 = stream.u8()? as i32;
+            (
+                with_sign(flag, 1 + ((code / 12) << 8) + b0),
+                with_sign(flag >> 1, 1 + (((code % 12) >> 2) << 8) + b1),
+            )
+        } else if flag < 124 {
+            let b0 = stream.u8()? as i32;
+            let b1 = stream.u8()? as i32;
+            let b2 = stream.u8()? as i32;
+            (
+                with_sign(flag, (b0 << 4) + (b1 >> 4)),
+                with_sign(flag >> 1, ((b1 & 0x0f) << 8) + b2),
+            )
+        } else {
+            let b0 = stream.u8()? as i32;
+            let b1 = stream.u8()? as i32;
+            let b2 = stream.u8()? as i32;
+            let b3 = stream.u8()? as i32;
+            (
+                with_sign(flag, (b0 << 8) + b1),
+                with_sign(flag >> 1, (b2 << 8) + b3),
+            )
+        };
+        x += dx;
+        y += dy;
+        points.push(Point { x, y, on_curve });
+    }
+    Ok(points)
+}
+
+fn bounding_box(points: &[Point]) -> [i16; 4] {
+    let mut bbox = [i32::MAX, i32::MAX, i32::MIN, i32::MIN];
+    for point in points {
+        bbox[0] = bbox[0].min(point.x);
+        bbox[1] = bbox[1].min(point.y);
+        bbox[2] = bbox[2].max(point.x);
+        bbox[3] = bbox[3].max(point.y);
+    }
+    bbox.map(|value| value.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+}
+
+/// Write TrueType simple-glyph flags and coordinates for `points`.
+fn encode_points(out: &mut Vec<u8>, points: &[Point], overlap: bool) {
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let (mut last_x, mut last_y) = (0i32, 0i32);
+
+    for (index, point) in points.iter().enumerate() {
+        let mut flag = if point.on_curve { ON_CURVE } else { 0 };
+        if overlap && index == 0 {
+            flag |= OVERLAP_SIMPLE;
+        }
+        let (dx, dy) = (point.x - last_x, point.y - last_y);
+        (last_x, last_y) = (point.x, point.y);
+
+        flag |= encode_delta(&mut xs, dx, X_SHORT, X_SAME_OR_POSITIVE);
+        flag |= encode_delta(&mut ys, dy, Y_SHORT, Y_SAME_OR_POSITIVE);
+        flags.push(flag);
+    }
+
+    out.extend_from_slice(&flags);
+    out.extend_from_slice(&xs);
+    out.extend_from_slice(&ys);
+}
+
+fn encode_delta(out: &mut Vec<u8>, delta: i32, short: u8, same_or_positive: u8) -> u8 {
+    if delta == 0 {
+        same_or_positive
+    } else if delta.abs() <= 255 {
+        out.push(delta.unsigned_abs() as u8);
+        short | if delta > 0 { same_or_positive } else { 0 }
+    } else {
+        out.extend_from_slice(&(delta as i16).to_be_bytes());
+        0
+    }
+}
+
+fn push_i16(out: &mut Vec<u8>, value: i16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Bounds-checked big-endian cursor; every overrun is a decode error.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(length)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| FontError::Decode("Truncated WOFF2 data".to_string()))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn sub(&mut self, length: usize) -> Result<Reader<'a>> {
+        Ok(Reader::new(self.bytes(length)?))
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        self.bytes(length).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        self.u16().map(|value| value as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn tag(&mut self) -> Result<[u8; 4]> {
+        let bytes = self.bytes(4)?;
+        Ok([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// `UIntBase128`: up to five bytes, seven bits each, no leading zeros.
+    fn base128(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for index in 0..5 {
+            let byte = self.u8()?;
+            if index == 0 && byte == 0x80 {
+                return Err(FontError::Decode(
+                    "UIntBase128 with leading zeros".to_string(),
+                ));
+            }
+            if value & 0xFE00_0000 != 0 {
+                return Err(FontError::Decode("UIntBase128 overflow".to_string()));
+            }
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FontError::Decode(
+            "UIntBase128 longer than five bytes".to_string(),
+        ))
+    }
+
+    /// `255UInt16`.
+    fn u255(&mut self) -> Result<u16> {
+        match self.u8()? {
+            253 => self.u16(),
+            254 => Ok(self.u8()? as u16 + 253 * 2),
+            255 => Ok(self.u8()? as u16 + 253),
+            code => Ok(code as u16),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Constraint validation of form controls.
+//!
+//! A control's validity is derived from the DOM every time it is asked for:
+//! its attributes, its `value` attribute (which text input edits) and the
+//! document's custom validity messages. Nothing is cached, so `:valid` and
+//! `:invalid` only need the control's style marked dirty when one of those
+//! changes, which every attribute change does.
+//!
+//! Interactive validation (`requestSubmit()`, `reportValidity()`) fires
+//! `invalid` from script and hands the first unhandled control to
+//! [`ValidationReports`], from which the engine focuses it and tells the
+//! embedder what to show.
+
+pub mod validation;
+
+pub use validation::{ControlKind, ControlState, InputType, ValidityState};
+
+use crate::core::dom::document::{Node, NodeType};
+use crate::core::dom::{Document, NodeId};
+use parking_lot::Mutex;
+
+/// The kind of a submittable element; `None` for anything else.
+pub fn control_kind(node: &Node) -> Option<ControlKind> {
+    if node.node_type != NodeType::Element {
+        return None;
+    }
+    let tag = node.tag_name.as_str();
+    if tag.eq_ignore_ascii_case("input") {
+        let input_type = InputType::parse(node.get_attribute("type").as_deref());
+        Some(ControlKind::Input(input_type))
+    } else if tag.eq_ignore_ascii_case("textarea") {
+        Some(ControlKind::TextArea)
+    } else if tag.eq_ignore_ascii_case("select") {
+        Some(ControlKind::Select)
+    } else if tag.eq_ignore_ascii_case("button") {
+        Some(ControlKind::Button)
+    } else {
+        None
+    }
+}
+
+pub fn is_form(node: &Node) -> bool {
+    node.node_type == NodeType::Element && node.tag_name.eq_ignore_ascii_case("form")
+}
+
+/// `:required` matches inputs, selects and text areas with `required`;
+/// `:optional` the rest of them.
+pub fn is_required(node: &Node) -> Option<bool> {
+    match control_kind(node)? {
+        ControlKind::Button => None,
+        _ => Some(node.has_attribute("required")),
+    }
+}
+
+/// Whether `node_id` is a candidate for constraint validation: a
+/// submittable element that is neither disabled, read-only nor of a type
+/// that is never validated.
+pub fn will_validate(document: &Document, node_id: NodeId) -> bool {
+    let node = match document.get_node(node_id) {
+        Some(node) => node,
+        None => return false,
+    };
+    let node = node.read();
+    let barred = match control_kind(&node) {
+        Some(ControlKind::Input(InputType::Barred)) | None => true,
+        Some(ControlKind::Button) => node
+            .get_attribute("type")
+            .is_some_and(|kind| !kind.trim().eq_ignore_ascii_case("submit")),
+        Some(ControlKind::Input(_)) | Some(ControlKind::TextArea) => node.has_attribute("readonly"),
+        Some(ControlKind::Select) => false,
+    };
+    !barred && !node.has_attribute("disabled") && !in_disabled_fieldset(document, node_id)
+}
+
+/// The validity of a candidate for constraint validation; `None` for
+/// anything else, which matches neither `:valid` nor `:invalid`.
+pub fn validity(document: &Document, node_id: NodeId) -> Option<ValidityState> {
+    if !will_validate(document, node_id) {
+        return None;
+    }
+    let (kind, state) = control_state(document, node_id)?;
+    Some(validation::validate(kind, &state))
+}
+
+/// The message `validationMessage` reports: empty unless the control is a
+/// candidate that fails a constraint.
+pub fn validation_message(document: &Document, node_id: NodeId) -> String {
+    if !will_validate(document, node_id) {
+        return String::new();
+    }
+    match control_state(document, node_id) {
+        Some((kind, state)) => {
+            let validity = validation::validate(kind, &state);
+            validation::validation_message(kind, &state, &validity)
+        }
+        None => String::new(),
+    }
+}
+
+/// A control's `value`, sanitized for its type.
+pub fn value(document: &Document, node_id: NodeId) -> Option<String> {
+    let (kind, state) = control_state(document, node_id)?;
+    Some(validation::sanitize_value(
+        kind,
+        &state.raw_value,
+        state.multiple,
+    ))
+}
+
+/// The form `node_id` belongs to: the one its `form` attribute names, or
+/// the nearest ancestor form.
+pub fn form_owner(document: &Document, node_id: NodeId) -> Option<NodeId> {
+    let named = document.get_node(node_id)?.read().get_attribute("form");
+    if let Some(id) = named {
+        return document
+            .get_element_by_id(&id)
+            .filter(|&form| document.get_node(form).is_some_and(|f| is_form(&f.read())));
+    }
+    let mut current = document.get_parent(node_id);
+    while let Some(ancestor) = current {
+        if document
+            .get_node(ancestor)
+            .is_some_and(|a| is_form(&a.read()))
+        {
+            return Some(ancestor);
+        }
+        current = document.get_parent(ancestor);
+    }
+    None
+}
+
+/// The submittable elements owned by `form`, in tree order.
+pub fn form_controls(document: &Document, form: NodeId) -> Vec<NodeId> {
+    tree_order(document)
+        .into_iter()
+        .filter(|&node_id| {
+            document
+                .get_node(node_id)
+                .is_some_and(|node| control_kind(&node.read()).is_some())
+                && form_owner(document, node_id) == Some(form)
+        })
+        .collect()
+}
+
+/// The controls of `form` that fail their constraints, in tree order.
+pub fn invalid_controls(document: &Document, form: NodeId) -> Vec<NodeId> {
+    form_controls(document, form)
+        .into_iter()
+        .filter(|&control| validity(document, control).is_some_and(|v| !v.is_valid()))
+        .collect()
+}
+
+/// A control interactive validation singled out for the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub node: NodeId,
+    pub message: String,
+}
+
+/// Reports from script waiting for the engine to show them.
+#[derive(Default)]
+pub struct ValidationReports {
+    pending: Mutex<Vec<ValidationReport>>,
+}
+
+impl ValidationReports {
+    /// Queue `node_id` with its current validation message. Valid controls
+    /// have nothing to report.
+    pub fn report(&self, document: &Document, node_id: NodeId) -> bool {
+        let message = validation_message(document, node_id);
+        if message.is_empty() {
+            return false;
+        }
+        self.pending.lock().push(ValidationReport {
+            node: node_id,
+            message,
+        });
+        true
+    }
+
+    pub fn take(&self) -> Vec<ValidationReport> {
+        std::mem::take(&mut *self.pending.lock())
+    }
+}
+
+fn control_state(document: &Document, node_id: NodeId) -> Option<(ControlKind, ControlState)> {
+    // A copy, so that looking at the radio group does not lock it again.
+    let node = document.get_node(node_id)?.read().clone();
+    let kind = control_kind(&node)?;
+    let raw_value = match kind {
+        ControlKind::Input(_) => node.get_attribute("value").unwrap_or_default(),
+        ControlKind::TextArea => node
+            .get_attribute("value")
+            .unwrap_or_else(|| text_of(document, node_id)),
+        ControlKind::Select => selected_value(document, node_id),
+        ControlKind::Button => String::new(),
+    };
+    let group_checked = kind == ControlKind::Input(InputType::Radio)
+        && radio_group_checked(document, node_id, &node);
+    let state = ControlState {
+        raw_value,
+        required: node.has_attribute("required"),
+        multiple: node.has_attribute("multiple"),
+        checked: node.has_attribute("checked"),
+        group_checked,
+        pattern: node.get_attribute("pattern"),
+        min: node.get_attribute("min"),
+        max: node.get_attribute("max"),
+        step: node.get_attribute("step"),
+        custom_error: document.custom_validity(node_id),
+    };
+    Some((kind, state))
+}
+
+fn in_disabled_fieldset(document: &Document, node_id: NodeId) -> bool {
+    let mut current = document.get_parent(node_id);
+    while let Some(ancestor) = current {
+        let disabled = document.get_node(ancestor).is_some_and(|a| {
+            let a = a.read();
+            a.tag_name.eq_ignore_ascii_case("fieldset") && a.has_attribute("disabled")
+        });
+        if disabled {
+            return true;
+        }
+        current = document.get_parent(ancestor);
+    }
+    false
+}
+
+/// The value of a select's selected option: the first with `selected`,
+/// otherwise the first option.
+fn selected_value(document: &Document, select: NodeId) -> String {
+    let mut first = None;
+    let mut stack = document.get_children(select);
+    stack.reverse();
+    while let Some(node_id) = stack.pop() {
+        let option = match document.get_node(node_id) {
+            Some(node) => node,
+            None => continue,
+        };
+        let option = option.read();
+        if option.tag_name.eq_ignore_ascii_case("option") {
+            let value = option
+                .get_attribute("value")
+                .unwrap_or_else(|| text_of(document, node_id).trim().to_string());
+            if option.has_attribute("selected") {
+                return value;
+            }
+            first.get_or_insert(value);
+        } else if option.tag_name.eq_ignore_ascii_case("optgroup") {
+            stack.extend(document.get_children(node_id).into_iter().rev());
+        }
+    }
+    first.unwrap_or_default()
+}
+
+/// Whether any radio button named like `radio` in the same form is checked.
+fn radio_group_checked(document: &Document, radio: NodeId, node: &Node) -> bool {
+    if node.has_attribute("checked") {
+        return true;
+    }
+    let name = match node.get_attribute("name").filter(|name| !name.is_empty()) {
+        Some(name) => name,
+        None => return false,
+    };
+    let owner = form_owner(document, radio);
+    tree_order(document)
+        .into_iter()
+        .filter(|&other| other != radio)
+        .any(|other| {
+            let checked_peer = document.get_node(other).is_some_and(|other| {
+                let other = other.read();
+                InputType::parse(other.get_attribute("type").as_deref()) == InputType::Radio
+                    && other.get_attribute("name").as_deref() == Some(name.as_str())
+                    && other.has_attribute("checked")
+            });
+            checked_peer && form_owner(document, other) == owner
+        })
+}
+
+fn tree_order(document: &Document) -> Vec<NodeId> {
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        found.push(node_id);
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    found
+}
+
+fn text_of(document: &Document, node_id: NodeId) -> String {
+    document
+        .get_children(node_id)
+        .into_iter()
+        .filter_map(|child| document.get_node(child))
+        .filter(|child| child.read().is_text())
+        .map(|child| child.read().get_text_content())
+        .collect()
+}
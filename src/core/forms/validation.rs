@@ -0,0 +1,351 @@
+//! The constraints of one form control, checked against a snapshot of its
+//! attributes and value.
+//!
+//! Values are sanitized the way the HTML value sanitization algorithm does
+//! for the input type: newlines stripped from text, whitespace trimmed from
+//! e-mail addresses and URLs, and anything but a valid floating-point number
+//! dropped from `type=number`. `maxlength`/`minlength` only apply to values
+//! the user typed since the last script change, which is not tracked, so
+//! `tooLong` and `tooShort` never hold.
+
+use regex::Regex;
+use serde_json::json;
+use url::Url;
+
+/// What a control is, as far as its constraints go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlKind {
+    /// An `<input>` of the given type. Unknown types are `text`.
+    Input(InputType),
+    TextArea,
+    Select,
+    Button,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputType {
+    Text,
+    Search,
+    Tel,
+    Password,
+    Email,
+    Url,
+    Number,
+    Checkbox,
+    Radio,
+    /// Types with no constraints of their own here: dates, colors, ranges,
+    /// files and the like.
+    Other,
+    /// `hidden`, `reset` and `button`: never validated.
+    Barred,
+}
+
+impl InputType {
+    pub fn parse(value: Option<&str>) -> Self {
+        let value = value.unwrap_or_default().trim().to_ascii_lowercase();
+        match value.as_str() {
+            "search" => Self::Search,
+            "tel" => Self::Tel,
+            "password" => Self::Password,
+            "email" => Self::Email,
+            "url" => Self::Url,
+            "number" => Self::Number,
+            "checkbox" => Self::Checkbox,
+            "radio" => Self::Radio,
+            "hidden" | "reset" | "button" => Self::Barred,
+            "date" | "datetime-local" | "month" | "week" | "time" | "color" | "range" | "file"
+            | "submit" | "image" => Self::Other,
+            _ => Self::Text,
+        }
+    }
+
+    fn takes_pattern(self) -> bool {
+        matches!(
+            self,
+            Self::Text | Self::Search | Self::Tel | Self::Password | Self::Email | Self::Url
+        )
+    }
+}
+
+/// A control's constraint-relevant state, read from the DOM.
+#[derive(Debug, Clone, Default)]
+pub struct ControlState {
+    /// The `value` as stored, before sanitization.
+    pub raw_value: String,
+    pub required: bool,
+    pub multiple: bool,
+    pub checked: bool,
+    /// For radio buttons: whether any button of the group is checked.
+    pub group_checked: bool,
+    pub pattern: Option<String>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub step: Option<String>,
+    pub custom_error: Option<String>,
+}
+
+/// The `ValidityState` flags of a control.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidityState {
+    pub value_missing: bool,
+    pub type_mismatch: bool,
+    pub pattern_mismatch: bool,
+    pub range_underflow: bool,
+    pub range_overflow: bool,
+    pub step_mismatch: bool,
+    pub bad_input: bool,
+    pub custom_error: bool,
+}
+
+impl ValidityState {
+    pub fn is_valid(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "valueMissing": self.value_missing,
+            "typeMismatch": self.type_mismatch,
+            "patternMismatch": self.pattern_mismatch,
+            "tooLong": false,
+            "tooShort": false,
+            "rangeUnderflow": self.range_underflow,
+            "rangeOverflow": self.range_overflow,
+            "stepMismatch": self.step_mismatch,
+            "badInput": self.bad_input,
+            "customError": self.custom_error,
+            "valid": self.is_valid(),
+        })
+    }
+}
+
+/// The value a control of `kind` reports for `raw`.
+pub fn sanitize_value(kind: ControlKind, raw: &str, multiple: bool) -> String {
+    let input_type = match kind {
+        ControlKind::Input(input_type) => input_type,
+        _ => return raw.to_string(),
+    };
+    match input_type {
+        InputType::Text | InputType::Search | InputType::Tel | InputType::Password => {
+            strip_newlines(raw)
+        }
+        InputType::Url => trim_ascii_whitespace(&strip_newlines(raw)).to_string(),
+        InputType::Email if multiple => email_list(raw).join(","),
+        InputType::Email => trim_ascii_whitespace(&strip_newlines(raw)).to_string(),
+        InputType::Number => match parse_number(raw) {
+            Some(_) => raw.to_string(),
+            None => String::new(),
+        },
+        _ => raw.to_string(),
+    }
+}
+
+/// Check every constraint of a control of `kind` in `state`.
+pub fn validate(kind: ControlKind, state: &ControlState) -> ValidityState {
+    let mut validity = ValidityState {
+        custom_error: state.custom_error.as_deref().is_some_and(|m| !m.is_empty()),
+        ..ValidityState::default()
+    };
+    let input_type = match kind {
+        ControlKind::Input(input_type) => input_type,
+        ControlKind::TextArea | ControlKind::Select => {
+            validity.value_missing = state.required && state.raw_value.is_empty();
+            return validity;
+        }
+        ControlKind::Button => return validity,
+    };
+
+    let value = sanitize_value(kind, &state.raw_value, state.multiple);
+    validity.value_missing = state.required
+        && match input_type {
+            InputType::Checkbox => !state.checked,
+            InputType::Radio => !state.group_checked,
+            InputType::Barred => false,
+            _ => value.is_empty(),
+        };
+    if value.is_empty() {
+        validity.bad_input =
+            input_type == InputType::Number && !trim_ascii_whitespace(&state.raw_value).is_empty();
+        return validity;
+    }
+
+    let values = if input_type == InputType::Email && state.multiple {
+        email_list(&value)
+    } else {
+        vec![value.clone()]
+    };
+    validity.type_mismatch = match input_type {
+        InputType::Email => !values.iter().all(|address| is_valid_email(address)),
+        InputType::Url => Url::parse(&value).is_err(),
+        _ => false,
+    };
+    if input_type.takes_pattern() {
+        if let Some(pattern) = state.pattern.as_deref().and_then(compile_pattern) {
+            validity.pattern_mismatch = !values.iter().all(|v| pattern.is_match(v));
+        }
+    }
+    if input_type == InputType::Number {
+        if let Some(number) = parse_number(&value) {
+            let min = state.min.as_deref().and_then(parse_number);
+            let max = state.max.as_deref().and_then(parse_number);
+            validity.range_underflow = min.is_some_and(|min| number < min);
+            validity.range_overflow = max.is_some_and(|max| number > max);
+            if let Some(step) = allowed_step(state.step.as_deref()) {
+                let steps = (number - min.unwrap_or(0.0)) / step;
+                validity.step_mismatch =
+                    (steps - steps.round()).abs() > 1e-9 * steps.abs().max(1.0);
+            }
+        }
+    }
+    validity
+}
+
+/// What to tell the user about a control failing `validity`. Custom
+/// messages win; otherwise the first failing constraint is described.
+pub fn validation_message(
+    kind: ControlKind,
+    state: &ControlState,
+    validity: &ValidityState,
+) -> String {
+    if validity.custom_error {
+        return state.custom_error.clone().unwrap_or_default();
+    }
+    if validity.value_missing {
+        return match kind {
+            ControlKind::Input(InputType::Checkbox) => {
+                "Please check this box if you want to proceed."
+            }
+            ControlKind::Input(InputType::Radio) => "Please select one of these options.",
+            ControlKind::Select => "Please select an item in the list.",
+            _ => "Please fill out this field.",
+        }
+        .to_string();
+    }
+    if validity.bad_input {
+        return "Please enter a number.".to_string();
+    }
+    if validity.type_mismatch {
+        return match kind {
+            ControlKind::Input(InputType::Email) => "Please enter an email address.",
+            _ => "Please enter a URL.",
+        }
+        .to_string();
+    }
+    if validity.pattern_mismatch {
+        return "Please match the requested format.".to_string();
+    }
+    if validity.range_underflow {
+        return format!(
+            "Value must be greater than or equal to {}.",
+            state.min.as_deref().unwrap_or_default().trim()
+        );
+    }
+    if validity.range_overflow {
+        return format!(
+            "Value must be less than or equal to {}.",
+            state.max.as_deref().unwrap_or_default().trim()
+        );
+    }
+    if validity.step_mismatch {
+        return "Please enter a valid value.".to_string();
+    }
+    String::new()
+}
+
+/// A valid floating-point number: optional `-`, digits with an optional
+/// fraction, optional exponent. No `+`, no trailing `.` and no whitespace.
+pub fn parse_number(value: &str) -> Option<f64> {
+    let bytes = value.as_bytes();
+    let mut at = usize::from(bytes.first() == Some(&b'-'));
+    let digits = |at: &mut usize| {
+        let start = *at;
+        while bytes.get(*at).is_some_and(u8::is_ascii_digit) {
+            *at += 1;
+        }
+        *at - start
+    };
+    let integer = digits(&mut at);
+    if bytes.get(at) == Some(&b'.') {
+        at += 1;
+        if digits(&mut at) == 0 {
+            return None;
+        }
+    } else if integer == 0 {
+        return None;
+    }
+    if matches!(bytes.get(at), Some(b'e' | b'E')) {
+        at += 1;
+        if matches!(bytes.get(at), Some(b'-' | b'+')) {
+            at += 1;
+        }
+        if digits(&mut at) == 0 {
+            return None;
+        }
+    }
+    if at != bytes.len() {
+        return None;
+    }
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+/// The HTML spec's valid e-mail address: a dot-atom-ish local part and a
+/// domain of LDH labels, without the quoting RFC 5322 allows.
+pub fn is_valid_email(address: &str) -> bool {
+    let (local, domain) = match address.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let local_ok = !local.is_empty()
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c));
+    local_ok
+        && domain.split('.').all(|label| {
+            let bytes = label.as_bytes();
+            (1..=63).contains(&bytes.len())
+                && bytes
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || *b == b'-')
+                && bytes[0] != b'-'
+                && bytes[bytes.len() - 1] != b'-'
+        })
+}
+
+fn email_list(value: &str) -> Vec<String> {
+    if trim_ascii_whitespace(value).is_empty() {
+        return Vec::new();
+    }
+    value
+        .split(',')
+        .map(|address| trim_ascii_whitespace(address).to_string())
+        .collect()
+}
+
+/// `pattern` must match the whole value. A pattern that does not compile
+/// imposes no constraint.
+fn compile_pattern(pattern: &str) -> Option<Regex> {
+    Regex::new(&format!("^(?:{})$", pattern)).ok()
+}
+
+/// `step="any"` allows every value; an invalid or missing step means 1.
+fn allowed_step(step: Option<&str>) -> Option<f64> {
+    match step.map(trim_ascii_whitespace) {
+        Some(step) if step.eq_ignore_ascii_case("any") => None,
+        Some(step) => Some(parse_number(step).filter(|step| *step > 0.0).unwrap_or(1.0)),
+        None => Some(1.0),
+    }
+}
+
+fn strip_newlines(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !matches!(c, '\n' | '\r'))
+        .collect()
+}
+
+fn trim_ascii_whitespace(value: &str) -> &str {
+    value.trim_matches(|c: char| c.is_ascii_whitespace())
+}
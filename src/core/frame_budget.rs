@@ -0,0 +1,256 @@
+//! Where the time of a frame went, and which frames went over budget.
+//!
+//! The engine times the phases of each frame on [`FrameWatchdog`]: the
+//! input dispatched since the frame before, timers, animation frame
+//! callbacks, style, layout, paint and the GPU submit. What no phase
+//! accounts for is [`FramePhase::Other`], so the phases add up to the
+//! frame. A frame longer than the budget the target frame rate leaves is
+//! jank, and [`FrameWatchdog::end_frame`] says which phase took the most of
+//! it. The last [`FrameBudgetConfig::window`] frames make the percentiles of
+//! [`FrameStatistics`].
+//!
+//! Timing a frame allocates nothing: the window is allocated up front and a
+//! frame's phases are a fixed array. Only a frame over budget is described.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::time::Duration;
+
+pub const DEFAULT_TARGET_FPS: f64 = 60.0;
+/// Frames the percentiles cover by default: four seconds at 60 FPS.
+pub const DEFAULT_FRAME_WINDOW: usize = 240;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameBudgetConfig {
+    /// The frame rate to keep up; a frame may take one period of it.
+    pub target_fps: f64,
+    /// The frames [`FrameStatistics`] percentiles are taken over.
+    pub window: usize,
+}
+
+impl Default for FrameBudgetConfig {
+    fn default() -> Self {
+        Self {
+            target_fps: DEFAULT_TARGET_FPS,
+            window: DEFAULT_FRAME_WINDOW,
+        }
+    }
+}
+
+impl FrameBudgetConfig {
+    /// How long a frame may take.
+    pub fn budget(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.target_fps.max(1.0))
+    }
+}
+
+/// A step of a frame, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FramePhase {
+    /// Input events handled since the frame before, with the script,
+    /// style and layout they ran.
+    InputDispatch,
+    /// Timers, and the events and messages delivered to script.
+    Timers,
+    /// `requestAnimationFrame` callbacks.
+    AnimationFrames,
+    Style,
+    Layout,
+    /// Building the layout tree and recording the frame's draws.
+    Paint,
+    /// Uploading vertices, submitting and waiting to present.
+    Submit,
+    /// The rest of the frame.
+    Other,
+}
+
+impl FramePhase {
+    pub const ALL: [FramePhase; 8] = [
+        FramePhase::InputDispatch,
+        FramePhase::Timers,
+        FramePhase::AnimationFrames,
+        FramePhase::Style,
+        FramePhase::Layout,
+        FramePhase::Paint,
+        FramePhase::Submit,
+        FramePhase::Other,
+    ];
+
+    /// Whether the phase is spent running page script.
+    pub fn runs_script(self) -> bool {
+        matches!(self, FramePhase::Timers | FramePhase::AnimationFrames)
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+const PHASES: usize = FramePhase::ALL.len();
+
+/// A frame that went over budget.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameJank {
+    pub total_ms: f64,
+    /// The phases that took any time, in the order they ran.
+    pub phases: Vec<(FramePhase, f64)>,
+    /// The phase that took the most.
+    pub worst_offender: FramePhase,
+}
+
+/// Frame times, over every frame for the counts and over the last
+/// [`FrameBudgetConfig::window`] frames for the percentiles.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FrameStatistics {
+    pub frames: u64,
+    pub jank_frames: u64,
+    pub budget_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Each phase's own 95th percentile, in the order phases run.
+    pub phase_p95_ms: Vec<(FramePhase, f64)>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameSample {
+    total_ms: f64,
+    phases_ms: [f64; PHASES],
+}
+
+struct State {
+    /// Time spent in each phase of the frame under way.
+    current: [Duration; PHASES],
+    in_frame: bool,
+    /// The last frames, oldest at `next` once the window is full.
+    samples: Vec<FrameSample>,
+    next: usize,
+    frames: u64,
+    jank_frames: u64,
+}
+
+/// Times the frames of one engine.
+pub struct FrameWatchdog {
+    budget: Duration,
+    window: usize,
+    state: Mutex<State>,
+}
+
+impl FrameWatchdog {
+    pub fn new(config: &FrameBudgetConfig) -> Self {
+        let window = config.window.max(1);
+        Self {
+            budget: config.budget(),
+            window,
+            state: Mutex::new(State {
+                current: [Duration::ZERO; PHASES],
+                in_frame: false,
+                samples: Vec::with_capacity(window),
+                next: 0,
+                frames: 0,
+                jank_frames: 0,
+            }),
+        }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    pub fn begin_frame(&self) {
+        self.state.lock().in_frame = true;
+    }
+
+    /// Add `elapsed` to `phase` of the frame under way. Input dispatched
+    /// between frames counts toward the next; other phases run outside a
+    /// frame, as the first layout of a load, are not frame time.
+    pub fn record(&self, phase: FramePhase, elapsed: Duration) {
+        let mut state = self.state.lock();
+        if state.in_frame || phase == FramePhase::InputDispatch {
+            state.current[phase.index()] += elapsed;
+        }
+    }
+
+    /// End the frame under way, which took `elapsed` plus the input
+    /// dispatched before it. Returns what took the time when that is over
+    /// budget.
+    pub fn end_frame(&self, elapsed: Duration) -> Option<FrameJank> {
+        let mut state = self.state.lock();
+        let current = std::mem::replace(&mut state.current, [Duration::ZERO; PHASES]);
+        state.in_frame = false;
+
+        let total = elapsed + current[FramePhase::InputDispatch.index()];
+        let mut sample = FrameSample {
+            total_ms: total.as_secs_f64() * 1000.0,
+            phases_ms: [0.0; PHASES],
+        };
+        let mut accounted = Duration::ZERO;
+        for (ms, duration) in sample.phases_ms.iter_mut().zip(current) {
+            *ms = duration.as_secs_f64() * 1000.0;
+            accounted += duration;
+        }
+        sample.phases_ms[FramePhase::Other.index()] +=
+            total.saturating_sub(accounted).as_secs_f64() * 1000.0;
+
+        if state.samples.len() < self.window {
+            state.samples.push(sample);
+        } else {
+            let next = state.next;
+            state.samples[next] = sample;
+        }
+        state.next = (state.next + 1) % self.window;
+        state.frames += 1;
+        if total <= self.budget {
+            return None;
+        }
+        state.jank_frames += 1;
+        drop(state);
+
+        let worst_offender = FramePhase::ALL
+            .into_iter()
+            .max_by(|a, b| sample.phases_ms[a.index()].total_cmp(&sample.phases_ms[b.index()]))
+            .unwrap_or(FramePhase::Other);
+        Some(FrameJank {
+            total_ms: sample.total_ms,
+            phases: FramePhase::ALL
+                .into_iter()
+                .map(|phase| (phase, sample.phases_ms[phase.index()]))
+                .filter(|(_, ms)| *ms > 0.0)
+                .collect(),
+            worst_offender,
+        })
+    }
+
+    pub fn statistics(&self) -> FrameStatistics {
+        let state = self.state.lock();
+        let percentile = |rank: f64, value: &dyn Fn(&FrameSample) -> f64| {
+            let mut values: Vec<f64> = state.samples.iter().map(value).collect();
+            values.sort_by(f64::total_cmp);
+            nearest_rank(&values, rank)
+        };
+        FrameStatistics {
+            frames: state.frames,
+            jank_frames: state.jank_frames,
+            budget_ms: self.budget.as_secs_f64() * 1000.0,
+            p95_ms: percentile(0.95, &|sample| sample.total_ms),
+            p99_ms: percentile(0.99, &|sample| sample.total_ms),
+            phase_p95_ms: FramePhase::ALL
+                .into_iter()
+                .map(|phase| {
+                    let p95 = percentile(0.95, &|sample| sample.phases_ms[phase.index()]);
+                    (phase, p95)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The smallest of `sorted` that `rank` of them are at most; zero when
+/// there are none.
+fn nearest_rank(sorted: &[f64], rank: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((rank * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
@@ -0,0 +1,159 @@
+//! CSS containment and `content-visibility`.
+//!
+//! `contain` promises that a box's inside does not affect its outside:
+//! `layout` makes it an independent formatting context, `paint` clips its
+//! descendants to its padding box, and `size` sizes it as if it were empty,
+//! from `contain-intrinsic-size`. `content-visibility` builds on that:
+//! `hidden` skips the box's contents outright, and `auto` skips them while
+//! the box is not *relevant to the user*, which here means its border box is
+//! more than [`RELEVANCE_MARGIN`] viewports away from the viewport. A
+//! skipped box keeps its own box so scroll geometry stays put; its contents
+//! are neither laid out nor painted, and hit testing cannot reach them.
+
+use super::LayoutBox;
+use crate::core::css::{ComputedStyles, ComputedValue};
+
+/// How far outside the viewport, as a fraction of its size, a
+/// `content-visibility: auto` box still counts as relevant.
+pub const RELEVANCE_MARGIN: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentVisibility {
+    #[default]
+    Visible,
+    Auto,
+    Hidden,
+}
+
+impl ContentVisibility {
+    pub fn of(styles: &ComputedStyles) -> Self {
+        match styles.get_computed_value("content-visibility") {
+            Ok(ComputedValue::Keyword(keyword)) => match keyword.to_ascii_lowercase().as_str() {
+                "hidden" => Self::Hidden,
+                _ => Self::Visible,
+            },
+            Ok(ComputedValue::Auto) => Self::Auto,
+            _ => Self::Visible,
+        }
+    }
+}
+
+/// The containment a box has, from `contain` and what its
+/// `content-visibility` implies. Style containment changes nothing this
+/// engine does and is not tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Containment {
+    pub size: bool,
+    pub layout: bool,
+    pub paint: bool,
+}
+
+impl Containment {
+    pub fn of(styles: &ComputedStyles) -> Self {
+        let mut containment = Self::default();
+        let keywords = match styles.get_computed_value("contain") {
+            Ok(ComputedValue::Keyword(keyword)) => vec![keyword],
+            Ok(ComputedValue::List(values)) => values
+                .into_iter()
+                .filter_map(|value| match value {
+                    ComputedValue::Keyword(keyword) => Some(keyword),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        for keyword in keywords {
+            match keyword.to_ascii_lowercase().as_str() {
+                "size" => containment.size = true,
+                "layout" => containment.layout = true,
+                "paint" => containment.paint = true,
+                "content" => {
+                    containment.layout = true;
+                    containment.paint = true;
+                }
+                "strict" => {
+                    containment.size = true;
+                    containment.layout = true;
+                    containment.paint = true;
+                }
+                _ => {}
+            }
+        }
+        // A skipped `auto` box is size-contained as well; the layout engine
+        // knows when that is.
+        match ContentVisibility::of(styles) {
+            ContentVisibility::Visible => {}
+            ContentVisibility::Auto => {
+                containment.layout = true;
+                containment.paint = true;
+            }
+            ContentVisibility::Hidden => {
+                containment.size = true;
+                containment.layout = true;
+                containment.paint = true;
+            }
+        }
+        containment
+    }
+}
+
+/// `contain-intrinsic-size`: the content size a size-contained box takes,
+/// per axis. With `auto`, the size the box last had while laid out for real
+/// wins over the given one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct IntrinsicSize {
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    pub remember: bool,
+}
+
+impl IntrinsicSize {
+    pub fn of(styles: &ComputedStyles) -> Self {
+        let mut size = Self::default();
+        let values = match styles.get_computed_value("contain-intrinsic-size") {
+            Ok(ComputedValue::List(values)) => values,
+            Ok(value) => vec![value],
+            Err(_) => Vec::new(),
+        };
+        // `[auto]? <length>`, once for both axes or once per axis.
+        let mut axes = Vec::new();
+        let mut remember = false;
+        for value in values {
+            match value {
+                ComputedValue::Auto => remember = true,
+                ComputedValue::Length(length) => {
+                    axes.push(Some(length));
+                    size.remember |= remember;
+                    remember = false;
+                }
+                _ => axes.push(None),
+            }
+        }
+        match axes.as_slice() {
+            [both] => (size.width, size.height) = (*both, *both),
+            [width, height, ..] => (size.width, size.height) = (*width, *height),
+            [] => {}
+        }
+        for (property, axis) in [
+            ("contain-intrinsic-width", &mut size.width),
+            ("contain-intrinsic-height", &mut size.height),
+        ] {
+            if let Ok(ComputedValue::Length(length)) = styles.get_computed_value(property) {
+                *axis = Some(length);
+            }
+        }
+        size
+    }
+}
+
+/// Whether `layout_box`'s border box is within [`RELEVANCE_MARGIN`] of a
+/// viewport of `viewport` size at the origin.
+pub fn is_relevant(layout_box: &LayoutBox, viewport: (f32, f32)) -> bool {
+    let (width, height) = viewport;
+    let (margin_x, margin_y) = (width * RELEVANCE_MARGIN, height * RELEVANCE_MARGIN);
+    let (x, y) = (layout_box.border_box_x(), layout_box.border_box_y());
+    x < width + margin_x
+        && x + layout_box.border_box_width() > -margin_x
+        && y < height + margin_y
+        && y + layout_box.border_box_height() > -margin_y
+}
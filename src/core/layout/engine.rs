@@ -1,15 +1,26 @@
 use async_recursion::async_recursion;
 use dashmap::DashMap;
+use futures::future::LocalBoxFuture;
 use parking_lot::RwLock;
 use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error;
 
-use super::{flexbox::FlexboxLayout, grid::GridLayout};
+use super::{
+    containment::{self, Containment, ContentVisibility, IntrinsicSize},
+    flexbox::FlexboxLayout,
+    float::{Clear, FloatContext, FloatSide},
+    grid::GridLayout,
+};
 use crate::core::{
     css::{ComputedStyles, ComputedValue, StyleEngine},
     dom::{DisplayType, Document, NodeId},
+    fonts::{FontFaceSet, FontRequest},
+    media::{self, MediaElements},
+    network::image_source,
 };
+use crate::renderer::image::{ImageCache, LoadedImage};
 
 #[derive(Error, Debug)]
 pub enum LayoutError {
@@ -156,6 +167,16 @@ impl Default for LayoutConstraints {
     }
 }
 
+/// One line of a text node's text, as wide as the room floats leave it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub text: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct LayoutResult {
     pub layout_box: LayoutBox,
@@ -163,6 +184,8 @@ pub struct LayoutResult {
     pub intrinsic_width: f32,
     pub intrinsic_height: f32,
     pub children_overflow: bool,
+    /// A text node's lines; empty for every other box.
+    pub line_boxes: Vec<LineBox>,
 }
 
 impl Default for LayoutResult {
@@ -173,15 +196,56 @@ impl Default for LayoutResult {
             intrinsic_width: 0.0,
             intrinsic_height: 0.0,
             children_overflow: false,
+            line_boxes: Vec::new(),
         }
     }
 }
 
+/// The block formatting context a block box lays out in: its floats, where
+/// the box's local origin sits in their coordinates, and whether the box is
+/// the context's root, whose height contains them.
+struct BlockFlow<'a> {
+    floats: &'a mut FloatContext,
+    origin: (f32, f32),
+    contains_floats: bool,
+}
+
+/// Layouts of one frame at most, while `content-visibility: auto` boxes
+/// turn relevant or stop being so.
+const MAX_RELEVANCE_PASSES: usize = 4;
+
+/// Boxes deeper than this are left out of layout, and so are not painted.
+/// Layout descends by recursion, so this bounds the stack it takes; the
+/// parser already flattens markup this deep, so only trees script builds
+/// reach it.
+pub const MAX_LAYOUT_DEPTH: usize = 512;
+
+/// What `content-visibility` and scrolling keep between layouts.
+#[derive(Debug, Default)]
+struct VisibilityState {
+    /// `content-visibility: auto` boxes whose contents are laid out.
+    relevant: HashSet<NodeId>,
+    /// Boxes whose contents the current layout skipped.
+    skipped: HashSet<NodeId>,
+    /// `content-visibility: auto` boxes the current layout met.
+    auto_boxes: Vec<NodeId>,
+    /// Content sizes of `contain-intrinsic-size: auto` boxes the last time
+    /// they were laid out for real.
+    remembered: HashMap<NodeId, (f32, f32)>,
+    /// Where the viewport's top left is in the document.
+    scroll: (f32, f32),
+    /// Laying out for print: everything is relevant and nothing scrolls.
+    printing: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct LayoutCache {
     constraints: LayoutConstraints,
     result: LayoutResult,
     generation: u64,
+    /// Laid out apart from its parent's flow at a size its contents cannot
+    /// change, so changes inside it need no layout further up.
+    relayout_boundary: bool,
 }
 
 pub struct LayoutEngine {
@@ -194,6 +258,15 @@ pub struct LayoutEngine {
     grid_layout: Arc<GridLayout>,
     parallel_threshold: usize,
     performance_metrics: Arc<RwLock<LayoutMetrics>>,
+    /// Natural sizes of `<video>` posters and media, for replaced sizing.
+    media: Option<Arc<MediaElements>>,
+    /// Web fonts text is shaped with when its `font-family` names one.
+    fonts: Option<Arc<FontFaceSet>>,
+    /// Decoded `<img>` sources, for replaced sizing.
+    images: Option<Arc<ImageCache>>,
+    visibility: Arc<RwLock<VisibilityState>>,
+    /// How deep each box entered during layout is, the root being 0.
+    depths: Arc<DashMap<NodeId, usize>>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -204,7 +277,13 @@ pub struct LayoutMetrics {
     pub parallel_layouts: u64,
     pub average_layout_time_us: f64,
     pub max_layout_time_us: f64,
+    /// Time spent in every layout so far.
+    pub total_layout_time_us: f64,
     pub memory_usage_bytes: usize,
+    /// Boxes the last layout computed, over all its passes.
+    pub last_layout_boxes: usize,
+    /// Layouts kept to the relayout boundaries around what changed.
+    pub scoped_layouts: u64,
 }
 
 impl LayoutEngine {
@@ -219,25 +298,47 @@ impl LayoutEngine {
             grid_layout: Arc::new(GridLayout::new()),
             parallel_threshold: 100, // parallelize when a node has 100+ children
             performance_metrics: Arc::new(RwLock::new(LayoutMetrics::default())),
+            media: None,
+            fonts: None,
+            images: None,
+            visibility: Arc::new(RwLock::new(VisibilityState::default())),
+            depths: Arc::new(DashMap::new()),
         }
     }
 
+    /// Size `<video>` elements from what `media` knows of their posters
+    /// and resources; without it they fall back to the default size.
+    pub fn with_media_elements(mut self, media: Arc<MediaElements>) -> Self {
+        self.media = Some(media);
+        self
+    }
+
+    /// Break text with the advances `fonts` shapes it to, in the language
+    /// of its element; without it, or for text no web font applies to,
+    /// every character is taken as 0.6em wide.
+    pub fn with_fonts(mut self, fonts: Arc<FontFaceSet>) -> Self {
+        self.fonts = Some(fonts);
+        self
+    }
+
+    /// Size `<img>` elements from the images `images` has decoded;
+    /// without it they are laid out as ordinary inline boxes.
+    pub fn with_images(mut self, images: Arc<ImageCache>) -> Self {
+        self.images = Some(images);
+        self
+    }
+
     pub async fn compute_layout(
         &self,
         document: &Document,
         style_engine: &StyleEngine,
     ) -> Result<()> {
         let start_time = std::time::Instant::now();
+        self.performance_metrics.write().last_layout_boxes = 0;
 
         // Ensure no locks are held across await
         self.process_invalidation_queue().await;
 
-        let current_generation = {
-            let mut generation = self.layout_generation.write();
-            *generation += 1;
-            *generation
-        };
-
         if let Some(root_id) = document.get_root_node() {
             let (viewport_width, viewport_height) = {
                 // Read both under the same scope and drop before awaiting
@@ -252,15 +353,36 @@ impl LayoutEngine {
                 ..Default::default()
             };
 
-            // No guards alive here
-            self.layout_node_recursive(
-                root_id,
-                constraints,
-                document,
-                style_engine,
-                current_generation,
-            )
-            .await?;
+            // Laying a `content-visibility: auto` box out for real, or
+            // skipping it again, moves what follows it; lay out again until
+            // which boxes are relevant settles.
+            for _ in 0..MAX_RELEVANCE_PASSES {
+                let anchor = self.scroll_anchor();
+                let current_generation = {
+                    let mut generation = self.layout_generation.write();
+                    *generation += 1;
+                    *generation
+                };
+                {
+                    let mut visibility = self.visibility.write();
+                    visibility.skipped.clear();
+                    visibility.auto_boxes.clear();
+                }
+
+                // No guards alive here
+                self.layout_node_recursive(
+                    root_id,
+                    constraints.clone(),
+                    document,
+                    style_engine,
+                    current_generation,
+                )
+                .await?;
+                self.apply_scroll(root_id, anchor, document);
+                if !self.update_relevance() {
+                    break;
+                }
+            }
         }
 
         let layout_time = start_time.elapsed();
@@ -268,6 +390,157 @@ impl LayoutEngine {
         Ok(())
     }
 
+    /// Lay out again after the nodes at `changed`, and what they contain,
+    /// changed since the last layout: only within the nearest relayout
+    /// boundary above each, put back where it was. A boundary that comes
+    /// out another size after all gives way to the next one up, and past
+    /// the last the whole document is laid out, as it is when there was no
+    /// layout before or `content-visibility: auto` boxes may need to
+    /// settle.
+    pub async fn compute_layout_for_changes(
+        &self,
+        document: &Document,
+        style_engine: &StyleEngine,
+        changed: &[NodeId],
+    ) -> Result<()> {
+        let scopes = match self.relayout_scopes(document, style_engine, changed) {
+            Some(scopes) => scopes,
+            None => return self.compute_layout(document, style_engine).await,
+        };
+        let start_time = std::time::Instant::now();
+        self.performance_metrics.write().last_layout_boxes = 0;
+        let generation = {
+            let mut generation = self.layout_generation.write();
+            *generation += 1;
+            *generation
+        };
+
+        let mut laid_out: Vec<NodeId> = Vec::new();
+        for boundaries in scopes {
+            let mut contained = false;
+            for boundary in boundaries {
+                let covered = laid_out.iter().any(|&done| {
+                    std::iter::successors(Some(boundary), |&id| document.get_parent(id))
+                        .any(|id| id == done)
+                });
+                if covered
+                    || self
+                        .relayout_within(boundary, document, style_engine, generation)
+                        .await?
+                {
+                    laid_out.push(boundary);
+                    contained = true;
+                    break;
+                }
+            }
+            if !contained {
+                return self.compute_layout(document, style_engine).await;
+            }
+        }
+        // New contents may hold boxes whose relevance has to settle.
+        if !self.visibility.read().auto_boxes.is_empty() {
+            return self.compute_layout(document, style_engine).await;
+        }
+
+        self.performance_metrics.write().scoped_layouts += 1;
+        self.update_performance_metrics(start_time.elapsed()).await;
+        Ok(())
+    }
+
+    /// The relayout boundaries above each of `changed`, nearest first.
+    /// None when the whole document has to be laid out: there is no layout
+    /// to keep, a node has none above it, or one is inside a box whose
+    /// contents were not laid out.
+    fn relayout_scopes(
+        &self,
+        document: &Document,
+        style_engine: &StyleEngine,
+        changed: &[NodeId],
+    ) -> Option<Vec<Vec<NodeId>>> {
+        let root_id = document.get_root_node()?;
+        if !self.layout_cache.contains_key(&root_id) || !self.invalidation_queue.read().is_empty() {
+            return None;
+        }
+        {
+            let visibility = self.visibility.read();
+            if visibility.printing || !visibility.auto_boxes.is_empty() {
+                return None;
+            }
+        }
+
+        let mut scopes = Vec::with_capacity(changed.len());
+        for &node_id in changed {
+            let mut boundaries = Vec::new();
+            let mut top = node_id;
+            let mut ancestor = document.get_parent(node_id);
+            while let Some(id) = ancestor {
+                let boundary = self.layout_cache.get(&id)?.relayout_boundary;
+                let styles = style_engine.get_computed_styles(id)?;
+                if self.get_display_type(&styles).ok()? == DisplayType::None
+                    || self.is_skipped(id)
+                    || Self::is_unrendered(id, document)
+                {
+                    return None;
+                }
+                if boundary {
+                    boundaries.push(id);
+                }
+                top = id;
+                ancestor = document.get_parent(id);
+            }
+            if top != root_id || boundaries.is_empty() {
+                return None;
+            }
+            scopes.push(boundaries);
+        }
+        Some(scopes)
+    }
+
+    /// Lay out `boundary` again under the constraints it last had. When it
+    /// comes out the size it was, put it back where it was and return
+    /// true; otherwise its ancestors need laying out too.
+    async fn relayout_within(
+        &self,
+        boundary: NodeId,
+        document: &Document,
+        style_engine: &StyleEngine,
+        generation: u64,
+    ) -> Result<bool> {
+        let (constraints, old) = match self.layout_cache.get(&boundary) {
+            Some(cached) => (cached.constraints.clone(), cached.result.clone()),
+            None => return Ok(false),
+        };
+        {
+            let mut visibility = self.visibility.write();
+            if !visibility.skipped.is_empty() {
+                let mut pending = vec![boundary];
+                while let Some(current) = pending.pop() {
+                    visibility.skipped.remove(&current);
+                    pending.extend(document.get_children(current));
+                }
+            }
+        }
+
+        let result = self
+            .layout_node_recursive(boundary, constraints, document, style_engine, generation)
+            .await?;
+        let (old_box, new_box) = (old.layout_box, result.layout_box);
+        if new_box.margin_box_width() != old_box.margin_box_width()
+            || new_box.margin_box_height() != old_box.margin_box_height()
+            || result.children_overflow != old.children_overflow
+        {
+            return Ok(false);
+        }
+        self.move_subtree(
+            boundary,
+            old_box.margin_box_x(),
+            old_box.margin_box_y(),
+            document,
+        );
+        self.mark_relayout_boundary(boundary);
+        Ok(true)
+    }
+
     #[async_recursion(?Send)]
     async fn layout_node_recursive(
         &self,
@@ -277,6 +550,16 @@ impl LayoutEngine {
         style_engine: &StyleEngine,
         generation: u64,
     ) -> Result<LayoutResult> {
+        // A parent is entered before its children, so its depth is known.
+        let depth = document
+            .get_parent(node_id)
+            .and_then(|parent| self.depths.get(&parent).map(|depth| *depth + 1))
+            .unwrap_or(0);
+        if depth > MAX_LAYOUT_DEPTH {
+            return Ok(LayoutResult::default());
+        }
+        self.depths.insert(node_id, depth);
+
         if let Some(cached) = self.get_cached_layout(node_id, &constraints, generation) {
             {
                 let mut metrics = self.performance_metrics.write();
@@ -294,11 +577,54 @@ impl LayoutEngine {
         let computed_styles = style_engine
             .get_computed_styles(node_id)
             .ok_or_else(|| LayoutError::Computation("No computed styles found".to_string()))?;
-        let display = self.get_display_type(&computed_styles)?;
+        let mut display = self.get_display_type(&computed_styles)?;
+        // Floats lay out as blocks whatever their display.
+        if matches!(display, DisplayType::Inline | DisplayType::InlineBlock)
+            && FloatSide::of(&computed_styles).is_some()
+        {
+            display = DisplayType::Block;
+        }
 
         // Clone constraints early for caching later
         let constraints_for_cache = constraints.clone();
 
+        // Text outside a block container's flow has no floats to avoid.
+        if let Some(text) = Self::text_of(node_id, document) {
+            let result = self.layout_text(
+                &text,
+                Some(&computed_styles),
+                &FloatContext::new(),
+                (0.0, 0.0),
+                constraints.available_width.unwrap_or(0.0),
+            );
+            self.cache_layout_result(node_id, constraints_for_cache, result.clone(), generation);
+            return Ok(result);
+        }
+
+        if display != DisplayType::None {
+            if let Some(size) = self.replaced_size(node_id, document) {
+                let result = self.layout_replaced_node(&computed_styles, &constraints, size)?;
+                self.cache_layout_result(
+                    node_id,
+                    constraints_for_cache,
+                    result.clone(),
+                    generation,
+                );
+                return Ok(result);
+            }
+            if let Some(result) =
+                self.skipped_contents(node_id, &computed_styles, &constraints, document)?
+            {
+                self.cache_layout_result(
+                    node_id,
+                    constraints_for_cache,
+                    result.clone(),
+                    generation,
+                );
+                return Ok(result);
+            }
+        }
+
         let result = match display {
             DisplayType::None => LayoutResult::default(),
             DisplayType::Block => {
@@ -349,10 +675,260 @@ impl LayoutEngine {
             }
         };
 
+        if ContentVisibility::of(&computed_styles) == ContentVisibility::Auto
+            && IntrinsicSize::of(&computed_styles).remember
+        {
+            let layout_box = &result.layout_box;
+            self.visibility.write().remembered.insert(
+                node_id,
+                (layout_box.content_width, layout_box.content_height),
+            );
+        }
+
         self.cache_layout_result(node_id, constraints_for_cache, result.clone(), generation);
         Ok(result)
     }
 
+    /// The placeholder for a box whose contents are skipped: the box itself,
+    /// size-contained, and nothing inside. `None` when its contents are to
+    /// be laid out. Boxes inside it keep no layout from before.
+    fn skipped_contents(
+        &self,
+        node_id: NodeId,
+        styles: &ComputedStyles,
+        constraints: &LayoutConstraints,
+        document: &Document,
+    ) -> Result<Option<LayoutResult>> {
+        let skip = match ContentVisibility::of(styles) {
+            ContentVisibility::Visible => false,
+            ContentVisibility::Hidden => true,
+            ContentVisibility::Auto => {
+                let mut visibility = self.visibility.write();
+                visibility.auto_boxes.push(node_id);
+                !visibility.printing && !visibility.relevant.contains(&node_id)
+            }
+        };
+        if !skip {
+            return Ok(None);
+        }
+        self.visibility.write().skipped.insert(node_id);
+        let children = document.get_children(node_id);
+        if children
+            .iter()
+            .any(|child| self.layout_cache.contains_key(child))
+        {
+            let mut pending = children;
+            while let Some(current) = pending.pop() {
+                self.layout_cache.remove(&current);
+                pending.extend(document.get_children(current));
+            }
+        }
+
+        let mut layout_box = self.compute_box_model(styles, constraints)?;
+        let (width, height) = self.contained_size(node_id, styles);
+        if self
+            .resolve_length_property(styles, "height", constraints.available_height)?
+            .is_none()
+        {
+            layout_box.content_height = height.max(constraints.min_height);
+        }
+        Ok(Some(LayoutResult {
+            layout_box,
+            baseline: Some(layout_box.content_y + layout_box.content_height),
+            intrinsic_width: width,
+            intrinsic_height: height,
+            children_overflow: false,
+            line_boxes: Vec::new(),
+        }))
+    }
+
+    /// The content size of a size-contained box: what it last had, for
+    /// `contain-intrinsic-size: auto`, otherwise what that property says.
+    fn contained_size(&self, node_id: NodeId, styles: &ComputedStyles) -> (f32, f32) {
+        let intrinsic = IntrinsicSize::of(styles);
+        if intrinsic.remember {
+            if let Some(&size) = self.visibility.read().remembered.get(&node_id) {
+                return size;
+            }
+        }
+        (
+            intrinsic.width.unwrap_or(0.0),
+            intrinsic.height.unwrap_or(0.0),
+        )
+    }
+
+    /// The `content-visibility: auto` box nearest the top of the viewport
+    /// and where its top is: what must not move when boxes above it are
+    /// laid out for real or skipped. None at the top of the page.
+    fn scroll_anchor(&self) -> Option<(NodeId, f32)> {
+        let visibility = self.visibility.read();
+        if visibility.printing || visibility.scroll.1 <= 0.0 {
+            return None;
+        }
+        visibility
+            .auto_boxes
+            .iter()
+            .filter_map(|&node_id| Some((node_id, self.get_layout_box(node_id)?)))
+            .filter(|(_, layout_box)| {
+                layout_box.border_box_y() + layout_box.border_box_height() > 0.0
+            })
+            .map(|(node_id, layout_box)| (node_id, layout_box.border_box_y()))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Move the laid-out document by the scroll offset, first shifting the
+    /// offset by however far `anchor` moved, and keep it within the
+    /// document.
+    fn apply_scroll(&self, root_id: NodeId, anchor: Option<(NodeId, f32)>, document: &Document) {
+        let scroll = {
+            let mut visibility = self.visibility.write();
+            if visibility.printing {
+                return;
+            }
+            let (x, mut y) = visibility.scroll;
+            if let Some((node_id, top)) = anchor {
+                if let Some(layout_box) = self.get_layout_box(node_id) {
+                    y = layout_box.border_box_y() - top;
+                }
+            }
+            let (max_x, max_y) = self.scroll_range(root_id);
+            visibility.scroll = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+            visibility.scroll
+        };
+        self.move_subtree(root_id, -scroll.0, -scroll.1, document);
+    }
+
+    /// How far the document at `root_id` can scroll each way.
+    fn scroll_range(&self, root_id: NodeId) -> (f32, f32) {
+        let (viewport_width, viewport_height) = self.viewport_size();
+        match self.get_layout_result(root_id) {
+            Some(result) => {
+                let layout_box = result.layout_box;
+                let width = layout_box.margin_box_width().max(result.intrinsic_width);
+                let height = layout_box.margin_box_height().max(result.intrinsic_height);
+                (
+                    (width - viewport_width).max(0.0),
+                    (height - viewport_height).max(0.0),
+                )
+            }
+            None => (0.0, 0.0),
+        }
+    }
+
+    /// Work out which `content-visibility: auto` boxes are relevant where
+    /// they now are. True when that changed, so the document needs laying
+    /// out again.
+    fn update_relevance(&self) -> bool {
+        let viewport = self.viewport_size();
+        let mut visibility = self.visibility.write();
+        if visibility.printing {
+            return false;
+        }
+        let relevant: HashSet<NodeId> = visibility
+            .auto_boxes
+            .iter()
+            .copied()
+            .filter(|&node_id| {
+                self.get_layout_box(node_id)
+                    .is_some_and(|layout_box| containment::is_relevant(&layout_box, viewport))
+            })
+            .collect();
+        let changed = relevant != visibility.relevant;
+        visibility.relevant = relevant;
+        changed
+    }
+
+    /// Scroll the page so `(x, y)` of the document is at the viewport's
+    /// top left, as far as the document allows. True when that changed
+    /// which `content-visibility: auto` boxes are relevant, so the document
+    /// needs laying out again.
+    pub fn scroll_to(&self, x: f32, y: f32, document: &Document) -> bool {
+        let root_id = match document.get_root_node() {
+            Some(root_id) => root_id,
+            None => return false,
+        };
+        let (max_x, max_y) = self.scroll_range(root_id);
+        let scroll = (x.clamp(0.0, max_x), y.clamp(0.0, max_y));
+        {
+            let mut visibility = self.visibility.write();
+            if visibility.printing {
+                return false;
+            }
+            visibility.scroll = scroll;
+        }
+        self.move_subtree(root_id, -scroll.0, -scroll.1, document);
+        self.update_relevance()
+    }
+
+    /// Where the viewport's top left is in the document.
+    pub fn scroll_position(&self) -> (f32, f32) {
+        self.visibility.read().scroll
+    }
+
+    /// The furthest `document` scrolls either way: how far its laid-out
+    /// content reaches past the viewport.
+    pub fn max_scroll(&self, document: &Document) -> (f32, f32) {
+        document
+            .get_root_node()
+            .map_or((0.0, 0.0), |root_id| self.scroll_range(root_id))
+    }
+
+    /// Whether the last layout skipped `node_id`'s contents.
+    pub fn is_skipped(&self, node_id: NodeId) -> bool {
+        self.visibility.read().skipped.contains(&node_id)
+    }
+
+    /// Whether `node_id`'s border box, where it was last laid out or
+    /// scrolled to, overlaps the viewport.
+    pub fn is_in_viewport(&self, node_id: NodeId) -> bool {
+        let (width, height) = self.viewport_size();
+        self.get_layout_box(node_id).is_some_and(|layout_box| {
+            let (x, y) = (layout_box.border_box_x(), layout_box.border_box_y());
+            x < width
+                && x + layout_box.border_box_width() > 0.0
+                && y < height
+                && y + layout_box.border_box_height() > 0.0
+        })
+    }
+
+    /// Lay out for print, with every box relevant and no scrolling, or
+    /// back for the screen.
+    pub fn set_printing(&self, printing: bool) {
+        self.visibility.write().printing = printing;
+    }
+
+    /// Drop boxes and remembered sizes of nodes `document` has freed, and
+    /// give back the room they took.
+    pub fn retain_nodes(&self, document: &Document) {
+        self.layout_cache
+            .retain(|node_id, _| document.contains_node(*node_id));
+        self.layout_cache.shrink_to_fit();
+        let mut visibility = self.visibility.write();
+        visibility
+            .relevant
+            .retain(|node_id| document.contains_node(*node_id));
+        visibility
+            .remembered
+            .retain(|node_id, _| document.contains_node(*node_id));
+        visibility.relevant.shrink_to_fit();
+        visibility.remembered.shrink_to_fit();
+    }
+
+    /// A new document: scroll back to the top and forget what was known of
+    /// the old one's boxes.
+    pub fn forget_document(&self) {
+        let mut visibility = self.visibility.write();
+        let printing = visibility.printing;
+        *visibility = VisibilityState {
+            printing,
+            ..VisibilityState::default()
+        };
+        drop(visibility);
+        self.depths.clear();
+    }
+
+    /// Lay out a box that establishes a block formatting context: its floats
+    /// stay inside it and its auto height grows to contain them.
     async fn layout_block_node(
         &self,
         node_id: NodeId,
@@ -361,75 +937,577 @@ impl LayoutEngine {
         style_engine: &StyleEngine,
         generation: u64,
     ) -> Result<LayoutResult> {
-        let computed_styles = style_engine
-            .get_computed_styles(node_id)
-            .ok_or_else(|| LayoutError::Computation("No computed styles found".to_string()))?;
+        let mut floats = FloatContext::new();
+        self.layout_block_flow(
+            node_id,
+            constraints,
+            document,
+            style_engine,
+            generation,
+            BlockFlow {
+                floats: &mut floats,
+                origin: (0.0, 0.0),
+                contains_floats: true,
+            },
+        )
+        .await
+    }
 
-        let mut layout_box = self.compute_box_model(&computed_styles, &constraints)?;
+    /// Lay out a block box and its children in `flow`'s block formatting
+    /// context, with the box's margin box at its local origin. Children are
+    /// stacked top to bottom; text becomes line boxes that shorten beside
+    /// floats, floats are placed in the context, and boxes that establish a
+    /// context of their own sit beside the floats.
+    fn layout_block_flow<'a>(
+        &'a self,
+        node_id: NodeId,
+        constraints: LayoutConstraints,
+        document: &'a Document,
+        style_engine: &'a StyleEngine,
+        generation: u64,
+        flow: BlockFlow<'a>,
+    ) -> LocalBoxFuture<'a, Result<LayoutResult>> {
+        Box::pin(async move {
+            let BlockFlow {
+                floats,
+                origin,
+                contains_floats,
+            } = flow;
+            let computed_styles = style_engine
+                .get_computed_styles(node_id)
+                .ok_or_else(|| LayoutError::Computation("No computed styles found".to_string()))?;
+
+            let mut layout_box = self.compute_box_model(&computed_styles, &constraints)?;
+            let auto_height = self
+                .resolve_length_property(&computed_styles, "height", constraints.available_height)?
+                .is_none();
+
+            let content_constraints = LayoutConstraints {
+                available_width: Some(layout_box.content_width),
+                available_height: constraints.available_height,
+                ..Default::default()
+            };
+            let left = layout_box.content_x;
+            let right = left + layout_box.content_width;
+            let top = layout_box.content_y;
+
+            let children = document.get_children(node_id);
+            // Without floats anywhere, children lay out independently.
+            let prelaid = children.len() > self.parallel_threshold
+                && floats.is_empty()
+                && !self.has_floats(&children, document, style_engine);
+            if prelaid {
+                self.layout_children_parallel(
+                    &children,
+                    content_constraints.clone(),
+                    document,
+                    style_engine,
+                    generation,
+                )
+                .await?;
+            }
 
-        let content_constraints = LayoutConstraints {
-            available_width: Some(layout_box.content_width),
-            available_height: constraints.available_height,
-            ..Default::default()
-        };
+            let mut current_y = top;
+            let mut max_width = 0.0f32;
+            let mut children_overflow = false;
+
+            for &child_id in &children {
+                if let Some(text) = Self::text_of(child_id, document) {
+                    let mut result = self.layout_text(
+                        &text,
+                        style_engine.get_computed_styles(child_id).as_deref(),
+                        floats,
+                        (origin.0 + left, origin.1 + current_y),
+                        right - left,
+                    );
+                    Self::translate(&mut result, left, current_y);
+                    current_y += result.layout_box.content_height;
+                    max_width = max_width.max(result.intrinsic_width);
+                    self.cache_layout_result(
+                        child_id,
+                        content_constraints.clone(),
+                        result,
+                        generation,
+                    );
+                    continue;
+                }
 
-        let children = document.get_children(node_id);
+                let child_styles = match style_engine.get_computed_styles(child_id) {
+                    Some(styles) => styles,
+                    None => continue,
+                };
+                let display = self.get_display_type(&child_styles)?;
+                if display == DisplayType::None || Self::is_unrendered(child_id, document) {
+                    self.cache_layout_result(
+                        child_id,
+                        content_constraints.clone(),
+                        LayoutResult::default(),
+                        generation,
+                    );
+                    continue;
+                }
 
-        if children.len() > self.parallel_threshold {
-            self.layout_children_parallel(
-                &children,
-                content_constraints,
-                document,
-                style_engine,
-                generation,
-            )
-            .await?;
-        } else {
-            self.layout_children_sequential(
-                &children,
-                content_constraints,
+                if let Some(side) = FloatSide::of(&child_styles) {
+                    let result = self
+                        .layout_float(
+                            child_id,
+                            &child_styles,
+                            &content_constraints,
+                            document,
+                            style_engine,
+                            generation,
+                        )
+                        .await?;
+                    let child_box = result.layout_box;
+                    let (x, y) = floats.place(
+                        side,
+                        child_box.margin_box_width(),
+                        child_box.margin_box_height(),
+                        origin.1 + current_y,
+                        origin.0 + left,
+                        origin.0 + right,
+                    );
+                    self.move_subtree(child_id, x - origin.0, y - origin.1, document);
+                    max_width = max_width.max(child_box.margin_box_width());
+                    continue;
+                }
+
+                if let Some(clear) = Clear::of(&child_styles) {
+                    // Clearance puts the border edge, not the margin, below the
+                    // floats.
+                    let margin_top = Self::length(&child_styles, "margin-top");
+                    let border_top = floats.clearance(clear, origin.1 + current_y + margin_top);
+                    current_y = border_top - origin.1 - margin_top;
+                }
+
+                let in_flow = matches!(
+                    display,
+                    DisplayType::Block
+                        | DisplayType::ListItem
+                        | DisplayType::Table
+                        | DisplayType::TableRow
+                        | DisplayType::TableCell
+                ) && !Self::establishes_bfc(&child_styles)
+                    && self.replaced_size(child_id, document).is_none();
+
+                let mut result = if prelaid {
+                    self.get_layout_result(child_id).unwrap_or_default()
+                } else if in_flow {
+                    let result = self
+                        .layout_block_flow(
+                            child_id,
+                            content_constraints.clone(),
+                            document,
+                            style_engine,
+                            generation,
+                            BlockFlow {
+                                floats: &mut *floats,
+                                origin: (origin.0 + left, origin.1 + current_y),
+                                contains_floats: false,
+                            },
+                        )
+                        .await?;
+                    self.cache_layout_result(
+                        child_id,
+                        content_constraints.clone(),
+                        result.clone(),
+                        generation,
+                    );
+                    result
+                } else {
+                    self.layout_node_recursive(
+                        child_id,
+                        content_constraints.clone(),
+                        document,
+                        style_engine,
+                        generation,
+                    )
+                    .await?
+                };
+
+                // A box with a context of its own goes beside the floats,
+                // narrowed to the band they leave.
+                let mut x = left;
+                if !in_flow && !floats.is_empty() && !prelaid {
+                    let (free_left, free_right) = floats.available(
+                        origin.1 + current_y,
+                        result.layout_box.margin_box_height(),
+                        origin.0 + left,
+                        origin.0 + right,
+                    );
+                    x = free_left - origin.0;
+                    if free_right - free_left < result.layout_box.margin_box_width() {
+                        result = self
+                            .layout_node_recursive(
+                                child_id,
+                                LayoutConstraints {
+                                    available_width: Some(free_right - free_left),
+                                    ..content_constraints.clone()
+                                },
+                                document,
+                                style_engine,
+                                generation,
+                            )
+                            .await?;
+                    }
+                }
+                if !in_flow
+                    && self.bounds_relayout(
+                        child_id,
+                        display,
+                        &child_styles,
+                        &content_constraints,
+                        document,
+                    )?
+                {
+                    self.mark_relayout_boundary(child_id);
+                }
+                self.move_subtree(child_id, x, current_y, document);
+
+                current_y += result.layout_box.margin_box_height();
+                max_width = max_width.max(result.layout_box.margin_box_width());
+                if result.children_overflow {
+                    children_overflow = true;
+                }
+            }
+
+            let mut content_bottom = current_y;
+            if contains_floats {
+                content_bottom = content_bottom.max(floats.bottom() - origin.1);
+            }
+            // A size-contained box is as tall as if it were empty.
+            if Containment::of(&computed_styles).size {
+                content_bottom = top + self.contained_size(node_id, &computed_styles).1;
+            }
+            if auto_height {
+                layout_box.content_height = (content_bottom - top).max(constraints.min_height);
+            }
+
+            if children_overflow || content_bottom > top + layout_box.content_height {
+                children_overflow = true;
+            }
+
+            Ok(LayoutResult {
+                layout_box,
+                baseline: Some(layout_box.content_y + layout_box.content_height),
+                intrinsic_width: max_width,
+                intrinsic_height: content_bottom - top,
+                children_overflow,
+                line_boxes: Vec::new(),
+            })
+        })
+    }
+
+    /// Lay out a float at its local origin, shrink-wrapped to its content
+    /// when it has no width of its own.
+    async fn layout_float(
+        &self,
+        node_id: NodeId,
+        styles: &ComputedStyles,
+        constraints: &LayoutConstraints,
+        document: &Document,
+        style_engine: &StyleEngine,
+        generation: u64,
+    ) -> Result<LayoutResult> {
+        let result = self
+            .layout_node_recursive(
+                node_id,
+                constraints.clone(),
                 document,
                 style_engine,
                 generation,
             )
             .await?;
+        let auto_width = self
+            .resolve_length_property(styles, "width", constraints.available_width)?
+            .is_none();
+        if !auto_width
+            || self.replaced_size(node_id, document).is_some()
+            || result.intrinsic_width >= result.layout_box.content_width
+        {
+            return Ok(result);
         }
 
-        let mut current_y = layout_box.content_y;
-        let mut max_width = 0.0f32;
-        let mut children_overflow = false;
-
-        for &child_id in &children {
-            if let Some(child_cache) = self.layout_cache.get(&child_id) {
-                let child_box = &child_cache.result.layout_box;
+        let layout_box = result.layout_box;
+        let extras = layout_box.margin_box_width() - layout_box.content_width;
+        self.layout_node_recursive(
+            node_id,
+            LayoutConstraints {
+                available_width: Some(result.intrinsic_width + extras),
+                ..constraints.clone()
+            },
+            document,
+            style_engine,
+            generation,
+        )
+        .await
+    }
 
-                layout_box.content_y = current_y;
-                current_y += child_box.margin_box_height();
-                max_width = max_width.max(child_box.margin_box_width());
+    /// Break `text` into line boxes starting at the top of a block whose
+    /// content box is `width` wide at `origin` in `floats`' coordinates. Each
+    /// line takes the band the floats leave free; a line too narrow for its
+    /// first word moves down past the float in the way. The result is in
+    /// coordinates local to `origin`.
+    fn layout_text(
+        &self,
+        text: &str,
+        styles: Option<&ComputedStyles>,
+        floats: &FloatContext,
+        origin: (f32, f32),
+        width: f32,
+    ) -> LayoutResult {
+        let font_size = match styles.map(|styles| styles.get_computed_value("font-size")) {
+            Some(Ok(ComputedValue::Length(size))) => size.max(1.0),
+            _ => 16.0,
+        };
+        let char_width = font_size * 0.6;
+        let line_height = font_size * 1.2;
+        // A run shaped with a web font is shaped whole; each word then takes
+        // the advances of its clusters.
+        let shaped = match (&self.fonts, styles) {
+            (Some(fonts), Some(styles)) => Self::font_family(styles).and_then(|family| {
+                fonts.shape(
+                    &family,
+                    &FontRequest::from_styles(styles),
+                    font_size,
+                    text,
+                    styles.language().as_deref(),
+                )
+            }),
+            _ => None,
+        };
+        let measure = |word: &str| match &shaped {
+            Some(shaped) => {
+                let start = word.as_ptr() as usize - text.as_ptr() as usize;
+                shaped.width_of(start..start + word.len())
+            }
+            None => word.chars().count() as f32 * char_width,
+        };
+        let space = shaped
+            .as_ref()
+            .and_then(|shaped| {
+                text.char_indices()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map(|(at, c)| shaped.width_of(at..at + c.len_utf8()))
+            })
+            .unwrap_or(char_width);
+
+        let mut line_boxes = Vec::new();
+        let mut y = 0.0;
+        let mut words = text.split_whitespace().peekable();
+        while let Some(&first) = words.peek() {
+            let (free_left, free_right) =
+                floats.available(origin.1 + y, line_height, origin.0, origin.0 + width);
+            if free_right - free_left < measure(first) {
+                if let Some(edge) = floats.next_edge(origin.1 + y) {
+                    y = edge - origin.1;
+                    continue;
+                }
+            }
 
-                if child_cache.result.children_overflow {
-                    children_overflow = true;
+            let mut line = String::new();
+            let mut used = 0.0;
+            while let Some(&word) = words.peek() {
+                let advance = if line.is_empty() {
+                    measure(word)
+                } else {
+                    space + measure(word)
+                };
+                if !line.is_empty() && used + advance > free_right - free_left {
+                    break;
+                }
+                if !line.is_empty() {
+                    line.push(' ');
                 }
+                line.push_str(word);
+                used += advance;
+                words.next();
             }
+            line_boxes.push(LineBox {
+                x: free_left - origin.0,
+                y,
+                width: free_right - free_left,
+                height: line_height,
+                text: line,
+            });
+            y += line_height;
         }
 
-        if constraints.available_height.is_none() {
-            layout_box.content_height = current_y - layout_box.content_y;
+        let max_content = text.split_whitespace().map(measure).sum::<f32>()
+            + space * text.split_whitespace().count().saturating_sub(1) as f32;
+        let layout_box = LayoutBox {
+            content_width: width,
+            content_height: y,
+            ..LayoutBox::default()
+        };
+        LayoutResult {
+            layout_box,
+            baseline: line_boxes.first().map(|line| line.y + font_size * 0.8),
+            intrinsic_width: max_content,
+            intrinsic_height: y,
+            children_overflow: false,
+            line_boxes,
         }
+    }
 
-        if children_overflow || current_y > layout_box.content_y + layout_box.content_height {
-            children_overflow = true;
+    /// The `font-family` list as written.
+    fn font_family(styles: &ComputedStyles) -> Option<String> {
+        match styles.get_computed_value("font-family").ok()? {
+            ComputedValue::String(family) | ComputedValue::Keyword(family) => Some(family),
+            // Unquoted lists parse as one value per space-separated word.
+            ComputedValue::List(values) => Some(
+                values
+                    .iter()
+                    .filter_map(|value| match value {
+                        ComputedValue::String(word) | ComputedValue::Keyword(word) => {
+                            Some(word.as_str())
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            _ => None,
         }
+    }
 
-        Ok(LayoutResult {
-            layout_box,
-            baseline: Some(layout_box.content_y + layout_box.content_height),
-            intrinsic_width: max_width,
-            intrinsic_height: current_y - layout_box.content_y,
-            children_overflow,
+    /// Text of a text node with something besides white space in it.
+    fn text_of(node_id: NodeId, document: &Document) -> Option<String> {
+        let node = document.get_node(node_id)?;
+        let node = node.read();
+        if !node.is_text() {
+            return None;
+        }
+        let text = node.get_text_content();
+        if text.trim().is_empty() {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    /// Elements that take no room whatever their style: document metadata,
+    /// scripts and templates.
+    pub(crate) fn is_unrendered(node_id: NodeId, document: &Document) -> bool {
+        const UNRENDERED: &[&str] = &[
+            "head", "link", "meta", "noscript", "script", "style", "template", "title",
+        ];
+        document.get_node(node_id).is_some_and(|node| {
+            let node = node.read();
+            UNRENDERED
+                .iter()
+                .any(|tag| node.get_tag_name().eq_ignore_ascii_case(tag))
+        })
+    }
+
+    /// Whether a block box lays its children out in a formatting context of
+    /// its own: `display: flow-root`, `overflow` other than `visible`, or
+    /// layout or paint containment.
+    fn establishes_bfc(styles: &ComputedStyles) -> bool {
+        if let Ok(ComputedValue::Keyword(display)) = styles.get_computed_value("display") {
+            if display.eq_ignore_ascii_case("flow-root") {
+                return true;
+            }
+        }
+        let containment = Containment::of(styles);
+        if containment.layout || containment.paint {
+            return true;
+        }
+        ["overflow", "overflow-x", "overflow-y"].iter().any(|name| {
+            match styles.get_computed_value(name) {
+                Ok(ComputedValue::Keyword(keyword)) => !keyword.eq_ignore_ascii_case("visible"),
+                _ => false,
+            }
+        })
+    }
+
+    /// Whether a child its parent's flow lays out apart keeps its size
+    /// whatever its contents: a block with a formatting context of its own
+    /// whose height is definite or size-contained. Its width fills its
+    /// containing block either way.
+    fn bounds_relayout(
+        &self,
+        node_id: NodeId,
+        display: DisplayType,
+        styles: &ComputedStyles,
+        constraints: &LayoutConstraints,
+        document: &Document,
+    ) -> Result<bool> {
+        if !matches!(display, DisplayType::Block | DisplayType::ListItem)
+            || !Self::establishes_bfc(styles)
+            || self.replaced_size(node_id, document).is_some()
+        {
+            return Ok(false);
+        }
+        Ok(Containment::of(styles).size
+            || self
+                .resolve_length_property(styles, "height", constraints.available_height)?
+                .is_some())
+    }
+
+    /// Whether anything in the subtrees of `nodes` floats, short of floats
+    /// a box of theirs keeps in a formatting context of its own.
+    fn has_floats(
+        &self,
+        nodes: &[NodeId],
+        document: &Document,
+        style_engine: &StyleEngine,
+    ) -> bool {
+        nodes.iter().any(|&node_id| {
+            let styles = style_engine.get_computed_styles(node_id);
+            if styles
+                .as_ref()
+                .is_some_and(|styles| FloatSide::of(styles).is_some())
+            {
+                return true;
+            }
+            !styles.is_some_and(|styles| Self::establishes_bfc(&styles))
+                && self.has_floats(&document.get_children(node_id), document, style_engine)
         })
     }
 
+    fn length(styles: &ComputedStyles, property: &str) -> f32 {
+        match styles.get_computed_value(property) {
+            Ok(ComputedValue::Length(v)) => v,
+            _ => 0.0,
+        }
+    }
+
+    fn translate(result: &mut LayoutResult, dx: f32, dy: f32) {
+        result.layout_box.content_x += dx;
+        result.layout_box.content_y += dy;
+        for line in &mut result.line_boxes {
+            line.x += dx;
+            line.y += dy;
+        }
+    }
+
+    /// Move a laid-out subtree so `node_id`'s margin box starts at `(x, y)`
+    /// in its parent's coordinates. Boxes are laid out at their own origin
+    /// and placed by their parent, so placing twice moves them once.
+    fn move_subtree(&self, node_id: NodeId, x: f32, y: f32, document: &Document) {
+        let (dx, dy) = match self.layout_cache.get(&node_id) {
+            Some(cached) => {
+                let layout_box = &cached.result.layout_box;
+                (x - layout_box.margin_box_x(), y - layout_box.margin_box_y())
+            }
+            None => return,
+        };
+        if dx == 0.0 && dy == 0.0 {
+            return;
+        }
+        let mut pending = vec![node_id];
+        while let Some(current) = pending.pop() {
+            if let Some(mut cached) = self.layout_cache.get_mut(&current) {
+                Self::translate(&mut cached.result, dx, dy);
+            }
+            // Skipped contents have no layout to move.
+            if !self.is_skipped(current) {
+                pending.extend(document.get_children(current));
+            }
+        }
+    }
+
     async fn layout_inline_node(
         &self,
         node_id: NodeId,
@@ -448,6 +1526,7 @@ impl LayoutEngine {
             intrinsic_width: layout_box.content_width,
             intrinsic_height: layout_box.content_height,
             children_overflow: false,
+            line_boxes: Vec::new(),
         })
     }
 
@@ -463,6 +1542,64 @@ impl LayoutEngine {
             .await
     }
 
+    /// The intrinsic size of a replaced element, `None` for anything else.
+    fn replaced_size(&self, node_id: NodeId, document: &Document) -> Option<(f32, f32)> {
+        let node = document.get_node(node_id)?;
+        let natural = self
+            .media
+            .as_ref()
+            .and_then(|media| media.natural_size(node_id));
+        let node = node.read();
+        if let Some(images) = self.images.as_ref() {
+            if node.tag_name.eq_ignore_ascii_case("img") {
+                let loaded = document
+                    .base_url()
+                    .and_then(|base| image_source(&node, &base))
+                    .and_then(|url| images.get(url.as_str()));
+                return Some(image_intrinsic_size(&node, loaded.as_ref()));
+            }
+        }
+        media::intrinsic_size(&node, natural)
+    }
+
+    /// A box sized by CSS where it says so and by `intrinsic` elsewhere,
+    /// keeping the intrinsic aspect ratio when only one side is set. Its
+    /// children are fallback content and are not laid out.
+    fn layout_replaced_node(
+        &self,
+        computed_styles: &ComputedStyles,
+        constraints: &LayoutConstraints,
+        intrinsic: (f32, f32),
+    ) -> Result<LayoutResult> {
+        let mut layout_box = self.compute_box_model(computed_styles, constraints)?;
+        let width =
+            self.resolve_length_property(computed_styles, "width", constraints.available_width)?;
+        let height =
+            self.resolve_length_property(computed_styles, "height", constraints.available_height)?;
+        let (intrinsic_width, intrinsic_height) = intrinsic;
+        let ratio = (intrinsic_height > 0.0).then(|| intrinsic_width / intrinsic_height);
+        let (content_width, content_height) = match (width, height, ratio) {
+            (Some(width), Some(height), _) => (width, height),
+            (Some(width), None, Some(ratio)) => (width, width / ratio),
+            (None, Some(height), Some(ratio)) => (height * ratio, height),
+            (width, height, _) => (
+                width.unwrap_or(intrinsic_width),
+                height.unwrap_or(intrinsic_height),
+            ),
+        };
+        layout_box.content_width = content_width.max(constraints.min_width);
+        layout_box.content_height = content_height.max(constraints.min_height);
+
+        Ok(LayoutResult {
+            layout_box,
+            baseline: Some(layout_box.content_y + layout_box.content_height),
+            intrinsic_width,
+            intrinsic_height,
+            children_overflow: false,
+            line_boxes: Vec::new(),
+        })
+    }
+
     async fn layout_children_parallel(
         &self,
         children: &[NodeId],
@@ -498,27 +1635,6 @@ impl LayoutEngine {
         Ok(())
     }
 
-    async fn layout_children_sequential(
-        &self,
-        children: &[NodeId],
-        constraints: LayoutConstraints,
-        document: &Document,
-        style_engine: &StyleEngine,
-        generation: u64,
-    ) -> Result<()> {
-        for &child_id in children {
-            self.layout_node_recursive(
-                child_id,
-                constraints.clone(),
-                document,
-                style_engine,
-                generation,
-            )
-            .await?;
-        }
-        Ok(())
-    }
-
     fn compute_box_model(
         &self,
         computed_styles: &ComputedStyles,
@@ -702,8 +1818,24 @@ impl LayoutEngine {
             constraints,
             result,
             generation,
+            relayout_boundary: false,
         };
         self.layout_cache.insert(node_id, cache_entry);
+        self.performance_metrics.write().last_layout_boxes += 1;
+    }
+
+    fn mark_relayout_boundary(&self, node_id: NodeId) {
+        if let Some(mut cached) = self.layout_cache.get_mut(&node_id) {
+            cached.relayout_boundary = true;
+        }
+    }
+
+    /// Whether the last layout found `node_id` a relayout boundary: a box
+    /// whose size its contents cannot change.
+    pub fn is_relayout_boundary(&self, node_id: NodeId) -> bool {
+        self.layout_cache
+            .get(&node_id)
+            .is_some_and(|cached| cached.relayout_boundary)
     }
 
     async fn process_invalidation_queue(&self) {
@@ -731,6 +1863,10 @@ impl LayoutEngine {
         }
     }
 
+    pub fn viewport_size(&self) -> (f32, f32) {
+        (*self.viewport_width.read(), *self.viewport_height.read())
+    }
+
     pub async fn resize_viewport(&self, width: u32, height: u32) -> Result<()> {
         {
             let mut vw = self.viewport_width.write();
@@ -748,6 +1884,11 @@ impl LayoutEngine {
             .map(|cache| cache.result.layout_box)
     }
 
+    /// Laid-out boxes currently held.
+    pub fn box_count(&self) -> usize {
+        self.layout_cache.len()
+    }
+
     pub fn get_layout_result(&self, node_id: NodeId) -> Option<LayoutResult> {
         self.layout_cache
             .get(&node_id)
@@ -757,6 +1898,7 @@ impl LayoutEngine {
     async fn update_performance_metrics(&self, layout_time: std::time::Duration) {
         let mut metrics = self.performance_metrics.write();
         let layout_time_us = layout_time.as_micros() as f64;
+        metrics.total_layout_time_us += layout_time_us;
 
         if metrics.total_layouts == 1 {
             metrics.average_layout_time_us = layout_time_us;
@@ -812,3 +1954,37 @@ impl LayoutEngine {
             .await
     }
 }
+
+/// Size of a broken `<img>` with neither dimension attribute.
+const BROKEN_IMAGE_SIZE: (f32, f32) = (16.0, 16.0);
+
+/// The intrinsic size of an `<img>`: its `width`/`height` attributes, the
+/// missing one following the natural aspect ratio once decoded. With
+/// neither, a decoded image is its natural size, a broken one a small
+/// placeholder, and one still loading takes no room.
+fn image_intrinsic_size(
+    node: &crate::core::dom::document::Node,
+    loaded: Option<&LoadedImage>,
+) -> (f32, f32) {
+    let natural = match loaded {
+        Some(LoadedImage::Decoded(image)) if image.width > 0 && image.height > 0 => {
+            Some((image.width as f32, image.height as f32))
+        }
+        _ => None,
+    };
+    let width = media::dimension_attribute(node, "width");
+    let height = media::dimension_attribute(node, "height");
+    match (width, height, natural) {
+        (Some(width), Some(height), _) => (width, height),
+        (Some(width), None, Some((w, h))) => (width, width * h / w),
+        (None, Some(height), Some((w, h))) => (height * w / h, height),
+        (None, None, Some(natural)) => natural,
+        (width, height, _) => {
+            let fallback = match loaded {
+                Some(LoadedImage::Broken) => BROKEN_IMAGE_SIZE,
+                _ => (0.0, 0.0),
+            };
+            (width.unwrap_or(fallback.0), height.unwrap_or(fallback.1))
+        }
+    }
+}
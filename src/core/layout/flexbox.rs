@@ -266,6 +266,7 @@ impl FlexboxLayout {
             intrinsic_width: max_main_size,
             intrinsic_height: total_cross_size,
             children_overflow,
+            line_boxes: Vec::new(),
         })
     }
 
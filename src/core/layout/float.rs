@@ -0,0 +1,189 @@
+//! Floats and the block formatting contexts that contain them.
+//!
+//! A [`FloatContext`] holds the margin boxes of the floats placed so far in
+//! one block formatting context (BFC), in the coordinates of the box that
+//! established it. Floats are placed as high as they fit, no higher than an
+//! earlier float or the content before them, against the containing block's
+//! edge or the outer edge of the last float on their side. Line boxes of the
+//! same BFC shorten to the band the floats leave free; boxes that establish
+//! a BFC of their own sit beside the floats rather than under them.
+
+use crate::core::css::{ComputedStyles, ComputedValue};
+
+/// Which edge a float goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatSide {
+    Left,
+    Right,
+}
+
+impl FloatSide {
+    /// The computed `float`; `None` when the box does not float.
+    pub fn of(styles: &ComputedStyles) -> Option<Self> {
+        match styles.get_computed_value("float") {
+            Ok(ComputedValue::Keyword(keyword)) => match keyword.to_ascii_lowercase().as_str() {
+                "left" | "inline-start" => Some(Self::Left),
+                "right" | "inline-end" => Some(Self::Right),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Which floats a box moves below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clear {
+    Left,
+    Right,
+    Both,
+}
+
+impl Clear {
+    /// The computed `clear`; `None` when the box clears nothing.
+    pub fn of(styles: &ComputedStyles) -> Option<Self> {
+        match styles.get_computed_value("clear") {
+            Ok(ComputedValue::Keyword(keyword)) => match keyword.to_ascii_lowercase().as_str() {
+                "left" | "inline-start" => Some(Self::Left),
+                "right" | "inline-end" => Some(Self::Right),
+                "both" => Some(Self::Both),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn applies_to(self, side: FloatSide) -> bool {
+        matches!(
+            (self, side),
+            (Self::Both, _) | (Self::Left, FloatSide::Left) | (Self::Right, FloatSide::Right)
+        )
+    }
+}
+
+/// A placed float's margin box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlacedFloat {
+    pub side: FloatSide,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PlacedFloat {
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    /// Whether the float takes room from the band `y..y + height`. A band
+    /// of no height still meets the floats that cover `y`.
+    fn overlaps(&self, y: f32, height: f32) -> bool {
+        self.y < y + height.max(f32::EPSILON) && self.bottom() > y
+    }
+}
+
+/// The floats of one block formatting context.
+#[derive(Debug, Clone, Default)]
+pub struct FloatContext {
+    floats: Vec<PlacedFloat>,
+    // A float's top may not be above an earlier float's.
+    floor: f32,
+}
+
+impl FloatContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.floats.is_empty()
+    }
+
+    pub fn floats(&self) -> &[PlacedFloat] {
+        &self.floats
+    }
+
+    /// The part of `left..right` that floats leave free over the band
+    /// `y..y + height`, as `(left, right)`.
+    pub fn available(&self, y: f32, height: f32, left: f32, right: f32) -> (f32, f32) {
+        let (mut free_left, mut free_right) = (left, right);
+        for float in self.floats.iter().filter(|float| float.overlaps(y, height)) {
+            match float.side {
+                FloatSide::Left => free_left = free_left.max(float.x + float.width),
+                FloatSide::Right => free_right = free_right.min(float.x),
+            }
+        }
+        (free_left, free_right.max(free_left))
+    }
+
+    /// The first float bottom below `y` among the floats covering it: where
+    /// the free band next gets wider.
+    pub fn next_edge(&self, y: f32) -> Option<f32> {
+        self.floats
+            .iter()
+            .filter(|float| float.overlaps(y, 0.0))
+            .map(PlacedFloat::bottom)
+            .fold(None, |lowest: Option<f32>, bottom| {
+                Some(lowest.map_or(bottom, |lowest| lowest.min(bottom)))
+            })
+    }
+
+    /// Place a float whose margin box is `width` by `height`, no higher than
+    /// `y`, within `left..right`. Returns the margin box's position.
+    pub fn place(
+        &mut self,
+        side: FloatSide,
+        width: f32,
+        height: f32,
+        y: f32,
+        left: f32,
+        right: f32,
+    ) -> (f32, f32) {
+        let mut y = y.max(self.floor);
+        let (free_left, free_right) = loop {
+            let (free_left, free_right) = self.available(y, height, left, right);
+            // Too wide for any band: it goes where nothing else is beside it.
+            let unobstructed = free_left <= left && free_right >= right;
+            if free_right - free_left >= width || unobstructed {
+                break (free_left, free_right);
+            }
+            match self.next_edge(y) {
+                Some(edge) => y = edge,
+                None => break (free_left, free_right),
+            }
+        };
+        let x = match side {
+            FloatSide::Left => free_left,
+            FloatSide::Right => free_right - width,
+        };
+        self.floats.push(PlacedFloat {
+            side,
+            x,
+            y,
+            width,
+            height,
+        });
+        self.floor = y;
+        (x, y)
+    }
+
+    /// Where a box that clears `clear` may start: below every float it
+    /// clears, and not above `y`.
+    pub fn clearance(&self, clear: Clear, y: f32) -> f32 {
+        self.floats
+            .iter()
+            .filter(|float| clear.applies_to(float.side))
+            .map(PlacedFloat::bottom)
+            .fold(y, f32::max)
+    }
+
+    /// The bottom of the lowest float; what a BFC root's auto height grows
+    /// to contain.
+    pub fn bottom(&self) -> f32 {
+        self.floats
+            .iter()
+            .map(PlacedFloat::bottom)
+            .fold(0.0, f32::max)
+    }
+}
@@ -287,6 +287,7 @@ impl GridLayout {
             intrinsic_width: grid_width,
             intrinsic_height: grid_height,
             children_overflow,
+            line_boxes: Vec::new(),
         })
     }
 
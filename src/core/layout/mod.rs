@@ -1,20 +1,26 @@
+pub mod containment;
 pub mod engine;
 pub mod flexbox;
+pub mod float;
 pub mod grid;
+pub mod scroll;
 
+pub use containment::{Containment, ContentVisibility, IntrinsicSize, RELEVANCE_MARGIN};
 pub use engine::{
-    LayoutBox, LayoutConstraints, LayoutEngine, LayoutError, LayoutMetrics, LayoutResult,
+    LayoutBox, LayoutConstraints, LayoutEngine, LayoutError, LayoutMetrics, LayoutResult, LineBox,
 };
 pub use flexbox::{
     AlignContent as FlexAlignContent, AlignItems as FlexAlignItems, AlignSelf, FlexContainer,
     FlexDirection, FlexItem, FlexLine, FlexWrap, FlexboxLayout,
     JustifyContent as FlexJustifyContent,
 };
+pub use float::{Clear, FloatContext, FloatSide, PlacedFloat};
 pub use grid::{
     AlignContent as GridAlignContent, AlignItems as GridAlignItems, GridArea, GridAutoFlow,
     GridContainer, GridItem, GridLayout, GridLine, JustifyContent as GridJustifyContent,
     JustifyItems, TrackSize,
 };
+pub use scroll::ViewportScroll;
 
 use parking_lot::RwLock;
 use std::sync::Arc;
@@ -0,0 +1,54 @@
+//! The document's scroll position as script sees it.
+//!
+//! The engine scrolls the layout itself and copies where it is, and how far
+//! it can go, here whenever it paints. `window.scrollTo()` and friends take
+//! effect here at once, clamped to that range, so script reads back where
+//! it scrolled to. The engine scrolls the layout once script is done,
+//! clamping again to the document as script left it.
+
+use parking_lot::Mutex;
+
+#[derive(Debug, Default)]
+struct ScrollState {
+    position: (f32, f32),
+    max: (f32, f32),
+    requested: Option<(f32, f32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct ViewportScroll {
+    state: Mutex<ScrollState>,
+}
+
+impl ViewportScroll {
+    /// Where the layout is scrolled to and the furthest it can go. A
+    /// request not yet taken keeps the position script gave it.
+    pub fn update(&self, position: (f32, f32), max: (f32, f32)) {
+        let mut state = self.state.lock();
+        state.max = max;
+        if state.requested.is_none() {
+            state.position = position;
+        }
+    }
+
+    /// Where the viewport's top left is in the document.
+    pub fn position(&self) -> (f32, f32) {
+        self.state.lock().position
+    }
+
+    /// Script scrolls to `(x, y)`, clamped to the document as last laid
+    /// out. Returns where that is.
+    pub fn request(&self, x: f32, y: f32) -> (f32, f32) {
+        let mut state = self.state.lock();
+        let position = (x.clamp(0.0, state.max.0), y.clamp(0.0, state.max.1));
+        state.position = position;
+        state.requested = Some((x.max(0.0), y.max(0.0)));
+        position
+    }
+
+    /// Where script last asked to scroll to, unclamped, if it has since the
+    /// last call.
+    pub fn take_request(&self) -> Option<(f32, f32)> {
+        self.state.lock().requested.take()
+    }
+}
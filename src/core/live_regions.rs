@@ -0,0 +1,382 @@
+//! Announcing what changes inside ARIA live regions.
+//!
+//! A live region is an element with `aria-live="polite"` or `"assertive"`,
+//! or with a role that implies one: `status` and `log` are polite, `alert`
+//! is assertive. `aria-live="off"`, `marquee` and `timer` stop the search
+//! for one. [`LiveRegionTracker`] follows the document's mutations and,
+//! when the engine asks, turns everything that changed since it last asked
+//! into at most one [`Announcement`] per region:
+//!
+//! - the text of what was added and of text that was edited, unless the
+//!   region's `aria-relevant` leaves `additions` or `text` out;
+//! - what was removed, when `aria-relevant` asks for `removals`;
+//! - the whole text of the element made `aria-atomic="true"`, when the
+//!   nearest `aria-atomic` between the change and the region says so.
+//!
+//! An alert inserted into the page is announced as a whole. Nothing inside
+//! an `aria-hidden="true"` or `display: none` subtree is announced.
+//! Assertive announcements go first, then polite ones, each in the order
+//! their regions first changed.
+
+use super::css::{ComputedValue, StyleEngine};
+use super::dom::document::{MutationObserver, MutationRecord, MutationType};
+use super::dom::{Document, NodeId};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// How urgently assistive technology should say an announcement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Politeness {
+    /// Said once the user is idle.
+    Polite,
+    /// Said at once, interrupting.
+    Assertive,
+}
+
+/// What a live region wants said.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Announcement {
+    /// The live region element.
+    pub region: NodeId,
+    pub politeness: Politeness,
+    /// Whitespace collapsed, never empty.
+    pub text: String,
+}
+
+pub type AnnouncementHandler = Arc<dyn Fn(Announcement) + Send + Sync>;
+
+/// The changes `aria-relevant` asks to be told about.
+#[derive(Debug, Clone, Copy)]
+struct Relevant {
+    additions: bool,
+    removals: bool,
+    text: bool,
+}
+
+impl Relevant {
+    fn parse(value: Option<&str>) -> Self {
+        let value = value.unwrap_or("additions text");
+        let has = |token: &str| {
+            value
+                .split_ascii_whitespace()
+                .any(|t| t.eq_ignore_ascii_case(token) || t.eq_ignore_ascii_case("all"))
+        };
+        Self {
+            additions: has("additions"),
+            removals: has("removals"),
+            text: has("text"),
+        }
+    }
+}
+
+/// The live region a change happened in.
+struct Region {
+    id: NodeId,
+    politeness: Politeness,
+    relevant: Relevant,
+    /// The element to read as a whole, per `aria-atomic`.
+    atomic: Option<NodeId>,
+    /// From where the change happened up to the region.
+    path: Vec<NodeId>,
+}
+
+/// The politeness `node` makes it a live region with; `Some(None)` when
+/// it turns live regions off for what is inside it.
+fn live_politeness(document: &Document, node: NodeId) -> Option<Option<Politeness>> {
+    let node = document.get_node(node)?;
+    let node = node.read();
+    if !node.is_element() {
+        return None;
+    }
+    if let Some(live) = node.get_attribute("aria-live") {
+        match live.trim().to_ascii_lowercase().as_str() {
+            "polite" => return Some(Some(Politeness::Polite)),
+            "assertive" => return Some(Some(Politeness::Assertive)),
+            "off" => return Some(None),
+            _ => {}
+        }
+    }
+    let role = node.get_attribute("role")?;
+    match role
+        .split_ascii_whitespace()
+        .next()?
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "alert" => Some(Some(Politeness::Assertive)),
+        "status" | "log" => Some(Some(Politeness::Polite)),
+        "marquee" | "timer" => Some(None),
+        _ => None,
+    }
+}
+
+fn attribute(document: &Document, node: NodeId, name: &str) -> Option<String> {
+    document.get_node(node)?.read().get_attribute(name)
+}
+
+/// The region a change at `start` belongs to, walking up from it.
+fn region_of(document: &Document, start: NodeId) -> Option<Region> {
+    let mut path = Vec::new();
+    let mut atomic = None;
+    let mut atomic_settled = false;
+    let mut current = Some(start);
+    while let Some(node) = current {
+        path.push(node);
+        if !atomic_settled {
+            match attribute(document, node, "aria-atomic")
+                .as_deref()
+                .map(str::trim)
+            {
+                Some(value) if value.eq_ignore_ascii_case("true") => {
+                    atomic = Some(node);
+                    atomic_settled = true;
+                }
+                Some(value) if value.eq_ignore_ascii_case("false") => atomic_settled = true,
+                _ => {}
+            }
+        }
+        match live_politeness(document, node) {
+            Some(Some(politeness)) => {
+                return Some(Region {
+                    id: node,
+                    politeness,
+                    relevant: Relevant::parse(
+                        attribute(document, node, "aria-relevant").as_deref(),
+                    ),
+                    atomic,
+                    path,
+                })
+            }
+            Some(None) => return None,
+            None => current = document.get_parent(node),
+        }
+    }
+    None
+}
+
+/// Whether `node` is in the tree and neither it nor an ancestor is
+/// `aria-hidden` or `display: none`.
+fn is_exposed(document: &Document, style_engine: &StyleEngine, node: NodeId) -> bool {
+    if !document.is_connected(node) {
+        return false;
+    }
+    let mut current = Some(node);
+    while let Some(id) = current {
+        let hidden = attribute(document, id, "aria-hidden")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        let undisplayed = style_engine
+            .get_computed_styles(id)
+            .and_then(|styles| styles.get_computed_value("display").ok())
+            .is_some_and(|display| {
+                matches!(display, ComputedValue::Keyword(keyword) if keyword.eq_ignore_ascii_case("none"))
+            });
+        if hidden || undisplayed {
+            return false;
+        }
+        current = document.get_parent(id);
+    }
+    true
+}
+
+fn collapsed_text(document: &Document, node: NodeId) -> String {
+    document
+        .text_content(node)
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// An announcement being put together from one batch of changes.
+struct Pending {
+    region: NodeId,
+    politeness: Politeness,
+    parts: Vec<String>,
+    /// Elements read as a whole already.
+    atomic: HashSet<NodeId>,
+}
+
+impl Pending {
+    fn push(&mut self, text: String) {
+        if !text.is_empty() {
+            self.parts.push(text);
+        }
+    }
+}
+
+/// The live region changes of the current document since they were last
+/// announced.
+pub struct LiveRegionTracker {
+    records: Mutex<Arc<Mutex<Vec<MutationRecord>>>>,
+}
+
+impl Default for LiveRegionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LiveRegionTracker {
+    pub fn new() -> Self {
+        Self {
+            records: Mutex::new(Arc::new(Mutex::new(Vec::new()))),
+        }
+    }
+
+    /// Start following a newly loaded `document`. What the parser built is
+    /// not a change.
+    pub fn reset(&self, document: &Document) {
+        // The previous document's observer keeps the queue it was given.
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        document.add_mutation_observer(MutationObserver {
+            callback: Arc::new(move |batch: &[MutationRecord]| {
+                sink.lock().extend(
+                    batch
+                        .iter()
+                        .filter(|record| record.mutation_type != MutationType::Attributes)
+                        .cloned(),
+                );
+            }),
+            observe_child_list: true,
+            observe_attributes: false,
+            observe_character_data: true,
+            observe_subtree: true,
+            attribute_filter: None,
+        });
+        *self.records.lock() = records;
+    }
+
+    /// Forget the changes made so far, as those of a page no one saw.
+    pub fn clear(&self) {
+        self.records.lock().lock().clear();
+    }
+
+    /// The announcements for what changed since the last call, in the order
+    /// they are to be said.
+    pub fn take_announcements(
+        &self,
+        document: &Document,
+        style_engine: &StyleEngine,
+    ) -> Vec<Announcement> {
+        let records = std::mem::take(&mut *self.records.lock().lock());
+        if records.is_empty() {
+            return Vec::new();
+        }
+        let added: HashSet<NodeId> = records
+            .iter()
+            .flat_map(|record| record.added_nodes.iter().copied())
+            .collect();
+        let mut pending: Vec<Pending> = Vec::new();
+        for record in &records {
+            match record.mutation_type {
+                MutationType::ChildList => {
+                    for &node in &record.added_nodes {
+                        // An alert is announced when it is inserted; other
+                        // regions only for what changes inside them.
+                        let start = match live_politeness(document, node) {
+                            Some(Some(Politeness::Assertive))
+                                if attribute(document, node, "aria-live").is_none() =>
+                            {
+                                node
+                            }
+                            _ => record.target,
+                        };
+                        if let Some(region) = region_of(document, start) {
+                            if region.relevant.additions && is_exposed(document, style_engine, node)
+                            {
+                                add_change(&mut pending, document, &region, node, &added);
+                            }
+                        }
+                    }
+                    for &node in &record.removed_nodes {
+                        let Some(region) = region_of(document, record.target) else {
+                            continue;
+                        };
+                        if !region.relevant.removals
+                            || !is_exposed(document, style_engine, record.target)
+                        {
+                            continue;
+                        }
+                        let text = collapsed_text(document, node);
+                        add_text(&mut pending, document, &region, text);
+                    }
+                }
+                MutationType::CharacterData => {
+                    let Some(region) = region_of(document, record.target) else {
+                        continue;
+                    };
+                    if region.relevant.text && is_exposed(document, style_engine, record.target) {
+                        add_change(&mut pending, document, &region, record.target, &added);
+                    }
+                }
+                MutationType::Attributes => {}
+            }
+        }
+
+        let mut announcements: Vec<Announcement> = pending
+            .into_iter()
+            .filter(|pending| !pending.parts.is_empty())
+            .map(|pending| Announcement {
+                region: pending.region,
+                politeness: pending.politeness,
+                text: pending.parts.join(" "),
+            })
+            .collect();
+        // Stable: regions of one politeness keep their order.
+        announcements.sort_by_key(|announcement| std::cmp::Reverse(announcement.politeness));
+        announcements
+    }
+}
+
+fn pending_for<'a>(pending: &'a mut Vec<Pending>, region: &Region) -> &'a mut Pending {
+    let index = match pending.iter().position(|p| p.region == region.id) {
+        Some(index) => index,
+        None => {
+            pending.push(Pending {
+                region: region.id,
+                politeness: region.politeness,
+                parts: Vec::new(),
+                atomic: HashSet::new(),
+            });
+            pending.len() - 1
+        }
+    };
+    &mut pending[index]
+}
+
+/// Note the text of `node`, added or edited in `region`. Nodes inside one
+/// added in the same batch are read with it.
+fn add_change(
+    pending: &mut Vec<Pending>,
+    document: &Document,
+    region: &Region,
+    node: NodeId,
+    added: &HashSet<NodeId>,
+) {
+    let inside_added = region
+        .path
+        .iter()
+        .any(|&id| id != node && added.contains(&id));
+    if region.atomic.is_none() && inside_added {
+        return;
+    }
+    let text = collapsed_text(document, node);
+    add_text(pending, document, region, text);
+}
+
+/// Note `text` changing in `region`, or the whole of its atomic element.
+fn add_text(pending: &mut Vec<Pending>, document: &Document, region: &Region, text: String) {
+    let entry = pending_for(pending, region);
+    match region.atomic {
+        Some(atomic) => {
+            if entry.atomic.insert(atomic) {
+                entry.push(collapsed_text(document, atomic));
+            }
+        }
+        None => entry.push(text),
+    }
+}
@@ -0,0 +1,94 @@
+//! Fetching what a media element needs before playback: the first bytes of
+//! its resource and its poster.
+//!
+//! The resource is asked for with a ranged GET of [`PROBE_BYTES`]; a server
+//! ignoring `Range` sends the whole file, of which only that much is read.
+//! Posters go through the image decoder the renderer paints them with, so a
+//! poster the renderer cannot draw never sizes the element either. The
+//! document's CSP `media-src` and `img-src` are checked before anything is
+//! fetched.
+
+use super::probe::{probe, MediaMetadata, PROBE_BYTES};
+use super::{MediaError, Result};
+use crate::core::network::{FetchRequest, NetworkManager, Priority, RequestInitiator};
+use crate::renderer::image::ImageLoader;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+pub struct MediaLoader {
+    network: Arc<NetworkManager>,
+    initiator: RequestInitiator,
+}
+
+impl MediaLoader {
+    pub fn new(network: Arc<NetworkManager>, initiator: RequestInitiator) -> Self {
+        Self { network, initiator }
+    }
+
+    pub fn initiator(&self) -> &RequestInitiator {
+        &self.initiator
+    }
+
+    /// Sniff the resource at `url` for its container, duration and size.
+    pub async fn load_metadata(&self, url: &Url) -> Result<MediaMetadata> {
+        if !self.initiator.allows_media(url) {
+            return Err(MediaError::Blocked(format!(
+                "{} violates the document's media-src policy",
+                url
+            )));
+        }
+
+        let mut headers = HashMap::new();
+        headers.insert("Range".to_string(), format!("bytes=0-{}", PROBE_BYTES - 1));
+        let body = self.fetch(url, headers).await?;
+        probe(&body[..body.len().min(PROBE_BYTES)])
+            .ok_or_else(|| MediaError::Unsupported(format!("{} is no known media format", url)))
+    }
+
+    /// The natural size of the poster image at `url`.
+    pub async fn load_poster(&self, url: &Url) -> Result<(u32, u32)> {
+        if !self.initiator.allows_image(url) {
+            return Err(MediaError::Blocked(format!(
+                "{} violates the document's img-src policy",
+                url
+            )));
+        }
+        let images = ImageLoader::new();
+        let image = if url.scheme() == "data" {
+            images.load_image_data(url.as_str()).await
+        } else {
+            let body = self.fetch(url, HashMap::new()).await?;
+            images.load_image_data_from_bytes(&body)
+        }
+        .map_err(|e| MediaError::Poster(e.to_string()))?;
+        Ok((image.width(), image.height()))
+    }
+
+    async fn fetch(&self, url: &Url, headers: HashMap<String, String>) -> Result<Vec<u8>> {
+        let response = self
+            .network
+            .fetch_subresource(
+                FetchRequest {
+                    url: url.to_string(),
+                    method: "GET".to_string(),
+                    headers,
+                    body: None,
+                    timeout_ms: None,
+                    follow_redirects: true,
+                    cache_policy: None,
+                    priority: Priority::Low,
+                    idempotent: false,
+                },
+                &self.initiator,
+            )
+            .await?;
+        if !(200..300).contains(&response.status) {
+            return Err(MediaError::Http {
+                status: response.status,
+                url: url.to_string(),
+            });
+        }
+        Ok(response.body)
+    }
+}
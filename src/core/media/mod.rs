@@ -0,0 +1,793 @@
+//! `<video>` and `<audio>` up to their metadata, without playback.
+//!
+//! An element with a source goes through the start of the resource
+//! selection algorithm: `loadstart`, a [`MediaLoader`] fetch of the first
+//! bytes, then `durationchange`, `loadedmetadata` and `suspend` once the
+//! container is recognized, or `error` when it is not. Nothing is fetched
+//! after the metadata, so `readyState` stops at `HAVE_METADATA` and every
+//! failure is the pre-metadata `MEDIA_ERR_SRC_NOT_SUPPORTED`. `play()` is
+//! refused unless the embedder enabled [`MediaConfig::external_playback`]
+//! and installed a [`PlaybackHandler`] that takes the request.
+//!
+//! Elements the handler plays join the tab's [`AudioSession`]. An element
+//! is muted when it or its tab is, and the handler hears of every change to
+//! that or to the volume while it plays, so the host can silence its
+//! output. Where the origin is denied [`Permission::Autoplay`], `play()`
+//! of an unmuted element also needs [`UserActivation`].
+//!
+//! Events are queued per element and delivered on the engine's next tick,
+//! as [`FontFaceSet`](crate::core::fonts::FontFaceSet) does for font loads.
+
+pub mod loader;
+pub mod probe;
+
+pub use loader::MediaLoader;
+pub use probe::{probe, Container, MediaMetadata, PROBE_BYTES};
+
+use crate::core::audio::{AudioSession, AudioSource};
+use crate::core::dom::document::Node;
+use crate::core::dom::{Document, NodeId};
+use crate::core::network::NetworkError;
+use crate::core::permissions::{Permission, PermissionStore};
+use crate::core::user_activation::UserActivation;
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+/// Size of a `<video>` with no dimensions from attributes, video or poster.
+pub const DEFAULT_VIDEO_SIZE: (f32, f32) = (300.0, 150.0);
+
+/// Size of an `<audio controls>`; without `controls` it takes no space.
+pub const AUDIO_CONTROLS_SIZE: (f32, f32) = (300.0, 54.0);
+
+#[derive(Error, Debug)]
+pub enum MediaError {
+    #[error("Network error: {0}")]
+    Network(#[from] NetworkError),
+    #[error("Blocked: {0}")]
+    Blocked(String),
+    #[error("HTTP {status} for {url}")]
+    Http { status: u16, url: String },
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Poster error: {0}")]
+    Poster(String),
+}
+
+pub type Result<T> = std::result::Result<T, MediaError>;
+
+/// `MediaError.code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaErrorCode {
+    Aborted = 1,
+    Network = 2,
+    Decode = 3,
+    SrcNotSupported = 4,
+}
+
+/// `HTMLMediaElement.networkState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkState {
+    Empty = 0,
+    Idle = 1,
+    Loading = 2,
+    NoSource = 3,
+}
+
+/// `HTMLMediaElement.readyState`. Without playback nothing gets past
+/// `HaveMetadata`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReadyState {
+    HaveNothing = 0,
+    HaveMetadata = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Audio,
+    Video,
+}
+
+impl MediaKind {
+    /// The kind of a `<video>` or `<audio>` element.
+    pub fn of(node: &Node) -> Option<Self> {
+        if node.tag_name.eq_ignore_ascii_case("video") {
+            Some(Self::Video)
+        } else if node.tag_name.eq_ignore_ascii_case("audio") {
+            Some(Self::Audio)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MediaConfig {
+    /// Let [`MediaElements::set_playback_handler`] install a handler that
+    /// `play()` is handed to. Off, `play()` always rejects.
+    pub external_playback: bool,
+}
+
+/// What a [`PlaybackRequest`] asks of the embedder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackAction {
+    /// `play()`: start playing, if the handler will.
+    Play,
+    /// `pause()`, or the element went away: stop playing.
+    Pause,
+    /// Whether the element is muted, or its volume, changed while playing.
+    SetOutput,
+}
+
+/// A playback change handed to the embedder.
+#[derive(Debug, Clone)]
+pub struct PlaybackRequest {
+    pub node: NodeId,
+    pub src: Url,
+    pub action: PlaybackAction,
+    /// Muted by the element or by its tab: the output must be silent.
+    pub muted: bool,
+    /// From 0 to 1.
+    pub volume: f64,
+}
+
+/// Takes a playback request. For [`PlaybackAction::Play`] it returns whether
+/// it will play; a refused request rejects like one with no handler. What
+/// it returns for other actions is ignored.
+pub type PlaybackHandler = Arc<dyn Fn(PlaybackRequest) -> bool + Send + Sync>;
+
+/// Why `play()` was refused, as the `DOMException` it rejects with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayError {
+    /// The autoplay policy wants a user gesture.
+    NotAllowed,
+    /// Nothing will play the element.
+    NotSupported,
+}
+
+impl PlayError {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NotAllowed => "NotAllowedError",
+            Self::NotSupported => "NotSupportedError",
+        }
+    }
+}
+
+/// An event for a media element, with a non-bubbling `Event` of this type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaEvent {
+    pub node: NodeId,
+    pub kind: &'static str,
+}
+
+impl MediaEvent {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({ "type": self.kind, "bubbles": false })
+    }
+}
+
+struct MediaElement {
+    kind: MediaKind,
+    src: Option<Url>,
+    poster: Option<Url>,
+    /// Bumped by each load, so results of an earlier one are dropped.
+    load: u64,
+    network_state: NetworkState,
+    ready_state: ReadyState,
+    error: Option<(MediaErrorCode, String)>,
+    metadata: Option<MediaMetadata>,
+    poster_size: Option<(u32, u32)>,
+    current_time: f64,
+    /// The element's own `muted`, whatever its tab's.
+    muted: bool,
+    volume: f64,
+    /// A playback handler took `play()` and no `pause()` followed.
+    playing: bool,
+}
+
+impl MediaElement {
+    fn new(kind: MediaKind, src: Option<Url>, poster: Option<Url>, load: u64) -> Self {
+        Self {
+            kind,
+            src,
+            poster,
+            load,
+            network_state: NetworkState::Empty,
+            ready_state: ReadyState::HaveNothing,
+            error: None,
+            metadata: None,
+            poster_size: None,
+            current_time: 0.0,
+            muted: false,
+            volume: 1.0,
+            playing: false,
+        }
+    }
+
+    fn duration(&self) -> f64 {
+        self.metadata.map_or(f64::NAN, |metadata| metadata.duration)
+    }
+}
+
+/// The current document's media elements, keyed by node.
+pub struct MediaElements {
+    elements: RwLock<HashMap<NodeId, MediaElement>>,
+    loader: RwLock<Option<Arc<MediaLoader>>>,
+    /// Bumped per document, so loads of a previous one are dropped.
+    generation: AtomicU64,
+    next_load: AtomicU64,
+    events: Mutex<Vec<MediaEvent>>,
+    /// A poster or video size arrived since the last
+    /// [`take_layout_dirty`](Self::take_layout_dirty).
+    layout_dirty: AtomicBool,
+    playback: RwLock<Option<PlaybackHandler>>,
+    audio: Arc<AudioSession>,
+    permissions: Arc<PermissionStore>,
+    activation: Arc<UserActivation>,
+}
+
+impl MediaElements {
+    pub fn new(
+        audio: Arc<AudioSession>,
+        permissions: Arc<PermissionStore>,
+        activation: Arc<UserActivation>,
+    ) -> Self {
+        Self {
+            elements: RwLock::new(HashMap::new()),
+            loader: RwLock::new(None),
+            generation: AtomicU64::new(0),
+            next_load: AtomicU64::new(1),
+            events: Mutex::new(Vec::new()),
+            layout_dirty: AtomicBool::new(false),
+            playback: RwLock::new(None),
+            audio,
+            permissions,
+            activation,
+        }
+    }
+
+    /// Forget every element and load the next document's through `loader`;
+    /// `None` for documents that load nothing, like source listings. The
+    /// playback handler stays, and is told to stop what was playing.
+    pub fn reset(&self, loader: Option<Arc<MediaLoader>>) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        let stopped: Vec<PlaybackRequest> = self
+            .elements
+            .write()
+            .drain()
+            .filter(|(_, element)| element.playing)
+            .filter_map(|(node, element)| self.request(node, &element, PlaybackAction::Pause))
+            .collect();
+        self.audio.stop_media();
+        self.hand_off(stopped);
+        self.events.lock().clear();
+        *self.loader.write() = loader;
+        self.layout_dirty.store(false, Ordering::SeqCst);
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.elements.read().contains_key(&node)
+    }
+
+    /// Run the load algorithm for `node`: abort what an earlier load of it
+    /// left, then fetch the metadata of `src` and the size of `poster`. A
+    /// first load takes `muted` as the element's; a later one keeps its
+    /// muted state and volume but stops it playing. `false` when there is
+    /// no loader or no runtime to load on.
+    pub fn start(
+        self: &Arc<Self>,
+        node: NodeId,
+        kind: MediaKind,
+        src: Option<Url>,
+        poster: Option<Url>,
+        muted: bool,
+    ) -> bool {
+        let loader = match self.loader.read().clone() {
+            Some(loader) => loader,
+            None => return false,
+        };
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+        let load = self.next_load.fetch_add(1, Ordering::SeqCst);
+        let poster = poster.filter(|_| kind == MediaKind::Video);
+        let mut stopped = None;
+        {
+            let mut elements = self.elements.write();
+            let mut events = self.events.lock();
+            let mut element = MediaElement::new(kind, src.clone(), poster.clone(), load);
+            element.muted = muted;
+            if let Some(previous) = elements.get(&node) {
+                element.muted = previous.muted;
+                element.volume = previous.volume;
+                if previous.playing {
+                    self.audio.stop(AudioSource::Media(node));
+                    stopped = self.request(node, previous, PlaybackAction::Pause);
+                    events.push(MediaEvent {
+                        node,
+                        kind: "pause",
+                    });
+                }
+                if previous.network_state == NetworkState::Loading {
+                    events.push(MediaEvent {
+                        node,
+                        kind: "abort",
+                    });
+                }
+                if previous.network_state != NetworkState::Empty {
+                    events.push(MediaEvent {
+                        node,
+                        kind: "emptied",
+                    });
+                }
+            }
+            if src.is_some() {
+                element.network_state = NetworkState::Loading;
+                events.push(MediaEvent {
+                    node,
+                    kind: "loadstart",
+                });
+            }
+            let previous = elements.insert(node, element);
+            if previous.is_some_and(|previous| {
+                previous.metadata.is_some_and(|m| m.video_width > 0)
+                    || previous.poster_size.is_some()
+            }) {
+                self.layout_dirty.store(true, Ordering::SeqCst);
+            }
+        }
+        self.hand_off(stopped);
+
+        let generation = self.generation.load(Ordering::SeqCst);
+        if let Some(src) = src {
+            let set = Arc::clone(self);
+            let loader = Arc::clone(&loader);
+            handle.spawn(async move {
+                let result = loader.load_metadata(&src).await;
+                set.finish_metadata(generation, node, load, result);
+            });
+        }
+        if let Some(poster) = poster {
+            let set = Arc::clone(self);
+            handle.spawn(async move {
+                let result = loader.load_poster(&poster).await;
+                set.finish_poster(generation, node, load, result);
+            });
+        }
+        true
+    }
+
+    /// [`start`](Self::start) `node_id` of `document` with its source and
+    /// poster resolved against the document's base URL. An empty URL or one
+    /// that does not resolve counts as none.
+    pub fn load_element(self: &Arc<Self>, document: &Document, node_id: NodeId) -> bool {
+        let base = match document.base_url() {
+            Some(base) => base,
+            None => return false,
+        };
+        let (kind, poster, muted) = match document.get_node(node_id) {
+            Some(node) => {
+                let node = node.read();
+                match MediaKind::of(&node) {
+                    Some(kind) => (
+                        kind,
+                        node.get_attribute("poster"),
+                        node.has_attribute("muted"),
+                    ),
+                    None => return false,
+                }
+            }
+            None => return false,
+        };
+        let resolve = |value: Option<String>| {
+            let value = value.filter(|value| !value.trim().is_empty())?;
+            match base.join(value.trim()) {
+                Ok(url) => Some(url),
+                Err(e) => {
+                    tracing::debug!("Ignoring media URL '{}': {}", value, e);
+                    None
+                }
+            }
+        };
+        let src = resolve(source_of(document, node_id));
+        self.start(node_id, kind, src, resolve(poster), muted)
+    }
+
+    fn finish_metadata(
+        &self,
+        generation: u64,
+        node: NodeId,
+        load: u64,
+        result: Result<MediaMetadata>,
+    ) {
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let mut elements = self.elements.write();
+        let element = match elements.get_mut(&node) {
+            Some(element) if element.load == load => element,
+            _ => return,
+        };
+        let mut events = self.events.lock();
+        match result {
+            Ok(metadata) => {
+                element.metadata = Some(metadata);
+                element.ready_state = ReadyState::HaveMetadata;
+                element.network_state = NetworkState::Idle;
+                let duration = metadata.duration;
+                if element.current_time > duration {
+                    element.current_time = duration;
+                }
+                events.push(MediaEvent {
+                    node,
+                    kind: "durationchange",
+                });
+                if metadata.video_width > 0 {
+                    events.push(MediaEvent {
+                        node,
+                        kind: "resize",
+                    });
+                    self.layout_dirty.store(true, Ordering::SeqCst);
+                }
+                events.push(MediaEvent {
+                    node,
+                    kind: "loadedmetadata",
+                });
+                events.push(MediaEvent {
+                    node,
+                    kind: "suspend",
+                });
+            }
+            Err(e) => {
+                let src = element.src.as_ref().map(Url::as_str).unwrap_or_default();
+                tracing::warn!("Failed to load media {}: {}", src, e);
+                element.error = Some((MediaErrorCode::SrcNotSupported, e.to_string()));
+                element.network_state = NetworkState::NoSource;
+                events.push(MediaEvent {
+                    node,
+                    kind: "error",
+                });
+            }
+        }
+    }
+
+    fn finish_poster(&self, generation: u64, node: NodeId, load: u64, result: Result<(u32, u32)>) {
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let mut elements = self.elements.write();
+        let element = match elements.get_mut(&node) {
+            Some(element) if element.load == load => element,
+            _ => return,
+        };
+        match result {
+            Ok(size) => {
+                element.poster_size = Some(size);
+                self.layout_dirty.store(true, Ordering::SeqCst);
+            }
+            Err(e) => {
+                let poster = element.poster.as_ref().map(Url::as_str).unwrap_or_default();
+                tracing::debug!("Failed to load poster {}: {}", poster, e);
+            }
+        }
+    }
+
+    /// Events since the last call, in the order they happened.
+    pub fn take_events(&self) -> Vec<MediaEvent> {
+        std::mem::take(&mut *self.events.lock())
+    }
+
+    /// Whether a media element's natural size changed. Clears the flag.
+    pub fn take_layout_dirty(&self) -> bool {
+        self.layout_dirty.swap(false, Ordering::SeqCst)
+    }
+
+    /// The natural size of `node`: its video's once the metadata has it,
+    /// else its poster's.
+    pub fn natural_size(&self, node: NodeId) -> Option<(u32, u32)> {
+        let elements = self.elements.read();
+        let element = elements.get(&node)?;
+        element
+            .metadata
+            .filter(|metadata| metadata.video_width > 0 && metadata.video_height > 0)
+            .map(|metadata| (metadata.video_width, metadata.video_height))
+            .or(element.poster_size)
+    }
+
+    /// The poster of `node` once it has loaded.
+    pub fn poster(&self, node: NodeId) -> Option<Url> {
+        let elements = self.elements.read();
+        let element = elements.get(&node)?;
+        element.poster_size.and(element.poster.clone())
+    }
+
+    /// What script sees of `node`, a `kind` element. One not picked up yet
+    /// reads as a fresh element with nothing loaded, muted when
+    /// `default_muted`. A NaN duration is `null`; `muted` is the element's
+    /// effective state, its tab's included.
+    pub fn state_json(
+        &self,
+        node: NodeId,
+        kind: MediaKind,
+        default_muted: bool,
+    ) -> serde_json::Value {
+        let elements = self.elements.read();
+        let fresh;
+        let element = match elements.get(&node) {
+            Some(element) => element,
+            None => {
+                let mut element = MediaElement::new(kind, None, None, 0);
+                element.muted = default_muted;
+                fresh = element;
+                &fresh
+            }
+        };
+        let (video_width, video_height) = element.metadata.map_or((0, 0), |metadata| {
+            (metadata.video_width, metadata.video_height)
+        });
+        let duration = element.duration();
+        json!({
+            "src": element.src.as_ref().map(Url::as_str).unwrap_or_default(),
+            "networkState": element.network_state as u8,
+            "readyState": element.ready_state as u8,
+            "currentTime": element.current_time,
+            "duration": if duration.is_nan() { None } else { Some(duration) },
+            "videoWidth": if element.kind == MediaKind::Video { video_width } else { 0 },
+            "videoHeight": if element.kind == MediaKind::Video { video_height } else { 0 },
+            "error": element.error.as_ref().map(|(code, message)| json!({
+                "code": *code as u8,
+                "message": message,
+            })),
+            "muted": element.muted || self.audio.is_muted(),
+            "volume": element.volume,
+            "paused": !element.playing,
+        })
+    }
+
+    /// Seek `node` to `time`, clamped to the media's bounds, and return the
+    /// new position. Before the metadata the position is only remembered.
+    pub fn set_current_time(&self, node: NodeId, time: f64) -> Option<f64> {
+        let mut elements = self.elements.write();
+        let element = elements.get_mut(&node)?;
+        let mut time = if time.is_nan() { 0.0 } else { time.max(0.0) };
+        let duration = element.duration();
+        if !duration.is_nan() {
+            time = time.min(duration);
+        }
+        element.current_time = time;
+        if element.ready_state >= ReadyState::HaveMetadata {
+            let mut events = self.events.lock();
+            for kind in ["seeking", "timeupdate", "seeked"] {
+                events.push(MediaEvent { node, kind });
+            }
+        }
+        Some(time)
+    }
+
+    pub fn set_playback_handler(&self, handler: Option<PlaybackHandler>) {
+        *self.playback.write() = handler;
+    }
+
+    pub fn playback_handler(&self) -> Option<PlaybackHandler> {
+        self.playback.read().clone()
+    }
+
+    /// `play()` on `node` of a document at `document_url`. Without a user
+    /// gesture, an unmuted element only plays where autoplay is allowed.
+    /// Then it goes to the playback handler; without one, or a source, or
+    /// when the handler refuses, nothing plays it.
+    pub fn play(
+        &self,
+        node: NodeId,
+        document_url: Option<&Url>,
+    ) -> std::result::Result<(), PlayError> {
+        let request = {
+            let elements = self.elements.read();
+            let element = elements.get(&node).ok_or(PlayError::NotSupported)?;
+            if element.playing {
+                return Ok(());
+            }
+            let autoplay = document_url
+                .is_some_and(|url| self.permissions.is_granted(url, Permission::Autoplay));
+            if !(element.muted || autoplay || self.activation.is_active()) {
+                return Err(PlayError::NotAllowed);
+            }
+            self.request(node, element, PlaybackAction::Play)
+                .ok_or(PlayError::NotSupported)?
+        };
+        let handler = self
+            .playback
+            .read()
+            .clone()
+            .ok_or(PlayError::NotSupported)?;
+        if !handler(request) {
+            return Err(PlayError::NotSupported);
+        }
+        if let Some(element) = self.elements.write().get_mut(&node) {
+            element.playing = true;
+            self.audio.start(AudioSource::Media(node));
+            self.events.lock().push(MediaEvent { node, kind: "play" });
+        }
+        Ok(())
+    }
+
+    /// `pause()` on `node`.
+    pub fn pause(&self, node: NodeId) {
+        let stopped = {
+            let mut elements = self.elements.write();
+            let element = match elements.get_mut(&node) {
+                Some(element) if element.playing => element,
+                _ => return,
+            };
+            element.playing = false;
+            self.audio.stop(AudioSource::Media(node));
+            self.events.lock().push(MediaEvent {
+                node,
+                kind: "pause",
+            });
+            self.request(node, element, PlaybackAction::Pause)
+        };
+        self.hand_off(stopped);
+    }
+
+    /// Set the element's own `muted`. `None` for an element not picked up
+    /// yet.
+    pub fn set_muted(&self, node: NodeId, muted: bool) -> Option<()> {
+        self.set_output(node, |element| {
+            std::mem::replace(&mut element.muted, muted) != muted
+        })
+    }
+
+    /// Set the volume of `node`, clamped to 0 to 1. `None` for an element
+    /// not picked up yet.
+    pub fn set_volume(&self, node: NodeId, volume: f64) -> Option<()> {
+        let volume = volume.clamp(0.0, 1.0);
+        self.set_output(node, |element| {
+            std::mem::replace(&mut element.volume, volume) != volume
+        })
+    }
+
+    /// Apply `change` to `node`; when it reports a change, fire
+    /// `volumechange` and tell the handler if the element plays.
+    fn set_output(
+        &self,
+        node: NodeId,
+        change: impl FnOnce(&mut MediaElement) -> bool,
+    ) -> Option<()> {
+        let update = {
+            let mut elements = self.elements.write();
+            let element = elements.get_mut(&node)?;
+            if !change(element) {
+                return Some(());
+            }
+            self.events.lock().push(MediaEvent {
+                node,
+                kind: "volumechange",
+            });
+            if element.playing {
+                self.request(node, element, PlaybackAction::SetOutput)
+            } else {
+                None
+            }
+        };
+        self.hand_off(update);
+        Some(())
+    }
+
+    /// The tab was muted or unmuted: tell the handler about every element
+    /// that plays and is not muted by itself.
+    pub fn tab_muted_changed(&self) {
+        let updates: Vec<PlaybackRequest> = self
+            .elements
+            .read()
+            .iter()
+            .filter(|(_, element)| element.playing && !element.muted)
+            .filter_map(|(&node, element)| self.request(node, element, PlaybackAction::SetOutput))
+            .collect();
+        self.hand_off(updates);
+    }
+
+    /// `action` on `element` as the handler gets it; `None` without a
+    /// source.
+    fn request(
+        &self,
+        node: NodeId,
+        element: &MediaElement,
+        action: PlaybackAction,
+    ) -> Option<PlaybackRequest> {
+        Some(PlaybackRequest {
+            node,
+            src: element.src.clone()?,
+            action,
+            muted: element.muted || self.audio.is_muted(),
+            volume: element.volume,
+        })
+    }
+
+    /// Give `requests` to the handler, outside the locks it might want.
+    fn hand_off(&self, requests: impl IntoIterator<Item = PlaybackRequest>) {
+        let handler = match self.playback.read().clone() {
+            Some(handler) => handler,
+            None => return,
+        };
+        for request in requests {
+            handler(request);
+        }
+    }
+}
+
+impl Default for MediaElements {
+    fn default() -> Self {
+        Self::new(
+            Arc::new(AudioSession::new()),
+            Arc::new(PermissionStore::new()),
+            Arc::new(UserActivation::new()),
+        )
+    }
+}
+
+/// The media resource of `node_id`: its `src`, else the first `<source>`
+/// child with one, as written in the markup.
+pub fn source_of(document: &Document, node_id: NodeId) -> Option<String> {
+    let own = document.get_node(node_id)?.read().get_attribute("src");
+    own.or_else(|| {
+        document
+            .get_children(node_id)
+            .into_iter()
+            .find_map(|child| {
+                let child = document.get_node(child)?;
+                let child = child.read();
+                if child.tag_name.eq_ignore_ascii_case("source") {
+                    child.get_attribute("src")
+                } else {
+                    None
+                }
+            })
+    })
+    .filter(|src| !src.trim().is_empty())
+}
+
+/// The size a `<video>` or `<audio>` lays out at when CSS does not set one,
+/// given its `natural` size. `None` for other elements.
+pub fn intrinsic_size(node: &Node, natural: Option<(u32, u32)>) -> Option<(f32, f32)> {
+    match MediaKind::of(node)? {
+        MediaKind::Audio if node.attributes.contains_key("controls") => Some(AUDIO_CONTROLS_SIZE),
+        MediaKind::Audio => Some((0.0, 0.0)),
+        MediaKind::Video => {
+            let natural = natural
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .map_or(DEFAULT_VIDEO_SIZE, |(width, height)| {
+                    (width as f32, height as f32)
+                });
+            let ratio = natural.0 / natural.1;
+            let width = dimension_attribute(node, "width");
+            let height = dimension_attribute(node, "height");
+            Some(match (width, height) {
+                (Some(width), Some(height)) => (width, height),
+                (Some(width), None) => (width, width / ratio),
+                (None, Some(height)) => (height * ratio, height),
+                (None, None) => natural,
+            })
+        }
+    }
+}
+
+/// A `width`/`height` attribute in CSS pixels; percentages and garbage are
+/// ignored.
+pub(crate) fn dimension_attribute(node: &Node, name: &str) -> Option<f32> {
+    let value = node.get_attribute(name)?;
+    let value = value.trim_start();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    if value[end..].starts_with('%') {
+        return None;
+    }
+    value[..end].parse::<f32>().ok().filter(|v| v.is_finite())
+}
@@ -0,0 +1,192 @@
+//! Reading a media resource's metadata from its first bytes.
+//!
+//! Only the container is looked at, never a codec. Duration and video
+//! dimensions come from headers where they sit near the start of the file:
+//! MP4/QuickTime `moov` boxes, WAV `fmt `/`data` chunks and FLAC
+//! `STREAMINFO`. WebM, Ogg and MP3 are recognized but would need the whole
+//! stream scanned, so their duration is NaN.
+
+/// Bytes fetched to sniff a resource.
+pub const PROBE_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    WebM,
+    Ogg,
+    Wav,
+    Mp3,
+    Flac,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MediaMetadata {
+    pub container: Container,
+    /// Seconds; NaN when not known from the headers.
+    pub duration: f64,
+    /// Natural video size; zero for audio or when not known.
+    pub video_width: u32,
+    pub video_height: u32,
+}
+
+impl MediaMetadata {
+    fn new(container: Container) -> Self {
+        Self {
+            container,
+            duration: f64::NAN,
+            video_width: 0,
+            video_height: 0,
+        }
+    }
+}
+
+/// The metadata of the resource starting with `data`, or `None` when it is
+/// no container we know.
+pub fn probe(data: &[u8]) -> Option<MediaMetadata> {
+    if data.get(4..8) == Some(b"ftyp") {
+        return Some(probe_mp4(data));
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        return Some(probe_wav(data));
+    }
+    if data.starts_with(b"fLaC") {
+        return Some(probe_flac(data));
+    }
+    if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        return Some(MediaMetadata::new(Container::WebM));
+    }
+    if data.starts_with(b"OggS") {
+        return Some(MediaMetadata::new(Container::Ogg));
+    }
+    let frame_sync = data.len() >= 2 && data[0] == 0xff && data[1] & 0xe0 == 0xe0;
+    if data.starts_with(b"ID3") || frame_sync {
+        return Some(MediaMetadata::new(Container::Mp3));
+    }
+    None
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn read_u32_le(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The ISO BMFF boxes directly inside `data`, as (type, payload). A box
+/// running past the end is cut short; walking stops at a malformed size.
+fn boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut found = Vec::new();
+    let mut at = 0;
+    while let (Some(size), Some(kind)) = (read_u32(data, at), data.get(at + 4..at + 8)) {
+        let (header, size) = match size {
+            0 => (8, data.len() - at),
+            1 => match read_u64(data, at + 8) {
+                Some(size) => (16, usize::try_from(size).unwrap_or(usize::MAX)),
+                None => break,
+            },
+            size => (8, size as usize),
+        };
+        if size < header {
+            break;
+        }
+        let end = at.saturating_add(size).min(data.len());
+        found.push((kind, &data[at + header..end]));
+        at = end;
+    }
+    found
+}
+
+fn probe_mp4(data: &[u8]) -> MediaMetadata {
+    let mut metadata = MediaMetadata::new(Container::Mp4);
+    let moov = match boxes(data).into_iter().find(|(kind, _)| *kind == b"moov") {
+        Some((_, moov)) => moov,
+        // `moov` after the media data: finding it means fetching the file.
+        None => return metadata,
+    };
+    for (kind, payload) in boxes(moov) {
+        if kind == b"mvhd" {
+            metadata.duration = mvhd_duration(payload).unwrap_or(f64::NAN);
+        } else if kind == b"trak" && metadata.video_width == 0 {
+            let size = boxes(payload)
+                .into_iter()
+                .find(|(kind, _)| *kind == b"tkhd")
+                .and_then(|(_, tkhd)| tkhd_size(tkhd));
+            if let Some((width, height)) = size {
+                metadata.video_width = width;
+                metadata.video_height = height;
+            }
+        }
+    }
+    metadata
+}
+
+fn mvhd_duration(mvhd: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (read_u32(mvhd, 12)?, u64::from(read_u32(mvhd, 16)?)),
+        1 => (read_u32(mvhd, 20)?, read_u64(mvhd, 24)?),
+        _ => return None,
+    };
+    // All ones means the duration is not known.
+    let unknown = duration == u64::MAX || duration == u64::from(u32::MAX);
+    if timescale == 0 || unknown {
+        return None;
+    }
+    Some(duration as f64 / f64::from(timescale))
+}
+
+/// A track's presentation size; `None` for tracks without one, like audio.
+fn tkhd_size(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let at = match tkhd.first()? {
+        0 => 76,
+        1 => 88,
+        _ => return None,
+    };
+    // 16.16 fixed point.
+    let width = read_u32(tkhd, at)? >> 16;
+    let height = read_u32(tkhd, at + 4)? >> 16;
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+fn probe_wav(data: &[u8]) -> MediaMetadata {
+    let mut metadata = MediaMetadata::new(Container::Wav);
+    let mut byte_rate = None;
+    let mut at = 12;
+    while let (Some(kind), Some(size)) = (data.get(at..at + 4), read_u32_le(data, at + 4)) {
+        match kind {
+            b"fmt " => byte_rate = read_u32_le(data, at + 16),
+            b"data" => {
+                // Streams written live leave the size unset.
+                if let Some(rate) = byte_rate.filter(|&rate| rate > 0 && size != u32::MAX) {
+                    metadata.duration = f64::from(size) / f64::from(rate);
+                }
+                break;
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even size.
+        at = at.saturating_add(8 + size as usize + (size as usize & 1));
+    }
+    metadata
+}
+
+fn probe_flac(data: &[u8]) -> MediaMetadata {
+    let mut metadata = MediaMetadata::new(Container::Flac);
+    // STREAMINFO is always the first metadata block.
+    let streaminfo = match data.get(4) {
+        Some(header) if header & 0x7f == 0 => data.get(8..).unwrap_or_default(),
+        _ => return metadata,
+    };
+    let sample_rate = read_u32(streaminfo, 10).map(|bits| bits >> 12);
+    let total_samples = read_u64(streaminfo, 10).map(|bits| bits & 0xf_ffff_ffff);
+    if let (Some(rate), Some(samples)) = (sample_rate, total_samples) {
+        if rate > 0 && samples > 0 {
+            metadata.duration = samples as f64 / f64::from(rate);
+        }
+    }
+    metadata
+}
@@ -0,0 +1,271 @@
+//! Picking and fetching a page's icon.
+//!
+//! Candidates come from `<link rel="icon">` (including `shortcut icon`)
+//! and `<link rel="apple-touch-icon">`, in tree order, followed by the
+//! `/favicon.ico` an http(s) page is assumed to have. [`rank_icons`] orders
+//! them for a wanted size: the closest declared `sizes` first, scalable
+//! icons matching any size, icons of unknown size after every sized one
+//! and the guessed `/favicon.ico` last.
+
+use crate::core::dom::{Document, NodeId};
+use crate::core::network::{FetchRequest, NetworkManager, Priority, RequestInitiator};
+use crate::renderer::image::{DecodedImage, ImageLoader};
+use parking_lot::Mutex;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Size [`BrowserEvent::FaviconChanged`](crate::BrowserEvent::FaviconChanged)
+/// resolves the favicon at, that of a tab strip icon.
+pub const DEFAULT_FAVICON_SIZE: u32 = 32;
+
+/// Size of an apple-touch-icon that does not declare one.
+const APPLE_TOUCH_ICON_SIZE: u32 = 180;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconRel {
+    Icon,
+    AppleTouchIcon,
+    /// No `<link>` named it: the `/favicon.ico` of the page's origin.
+    Fallback,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconCandidate {
+    pub url: Url,
+    pub rel: IconRel,
+    /// Largest side of each `WxH` in `sizes`; empty when none is given.
+    pub sizes: Vec<u32>,
+    /// `sizes="any"` or an SVG, drawn at whatever size is wanted.
+    pub scalable: bool,
+    pub mime_type: Option<String>,
+}
+
+impl IconCandidate {
+    /// How far the icon is from `size` pixels; lower is better.
+    fn cost(&self, size: u32) -> u64 {
+        if self.rel == IconRel::Fallback {
+            return u64::MAX;
+        }
+        if self.scalable {
+            return 0;
+        }
+        let closest = self
+            .sizes
+            .iter()
+            .map(|&declared| declared.abs_diff(size) as u64)
+            .min();
+        closest.unwrap_or(u64::MAX - 1)
+    }
+
+    fn largest(&self) -> u32 {
+        if self.scalable {
+            u32::MAX
+        } else {
+            self.sizes.iter().copied().max().unwrap_or(0)
+        }
+    }
+}
+
+/// The document's icon links in tree order, then `/favicon.ico` for http(s)
+/// pages. Links whose `type` is not an image type are left out.
+pub fn icon_candidates(document: &Document) -> Vec<IconCandidate> {
+    let base = match document.base_url() {
+        Some(base) => base,
+        None => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("template") {
+                continue;
+            }
+            if node.tag_name.eq_ignore_ascii_case("link") {
+                if let Some(candidate) = link_candidate(&node, &base) {
+                    found.push(candidate);
+                }
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    if matches!(base.scheme(), "http" | "https") {
+        if let Ok(url) = base.join("/favicon.ico") {
+            found.push(IconCandidate {
+                url,
+                rel: IconRel::Fallback,
+                sizes: Vec::new(),
+                scalable: false,
+                mime_type: None,
+            });
+        }
+    }
+    found
+}
+
+fn link_candidate(node: &crate::core::dom::document::Node, base: &Url) -> Option<IconCandidate> {
+    let rel_attr = node.get_attribute("rel")?;
+    let rel = rel_attr.split_ascii_whitespace().find_map(|token| {
+        if token.eq_ignore_ascii_case("icon") {
+            Some(IconRel::Icon)
+        } else if token.eq_ignore_ascii_case("apple-touch-icon")
+            || token.eq_ignore_ascii_case("apple-touch-icon-precomposed")
+        {
+            Some(IconRel::AppleTouchIcon)
+        } else {
+            None
+        }
+    })?;
+    let href = node.get_attribute("href")?;
+    let url = base.join(href.trim()).ok()?;
+
+    let mime_type = node
+        .get_attribute("type")
+        .map(|mime| mime.trim().to_ascii_lowercase())
+        .filter(|mime| !mime.is_empty());
+    if mime_type
+        .as_deref()
+        .is_some_and(|mime| !mime.starts_with("image/"))
+    {
+        return None;
+    }
+
+    let mut sizes = Vec::new();
+    let mut any = false;
+    for token in node
+        .get_attribute("sizes")
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+    {
+        if token.eq_ignore_ascii_case("any") {
+            any = true;
+        } else if let Some((width, height)) = token.to_ascii_lowercase().split_once('x') {
+            if let (Ok(width), Ok(height)) = (width.parse::<u32>(), height.parse::<u32>()) {
+                sizes.push(width.max(height));
+            }
+        }
+    }
+    if sizes.is_empty() && rel == IconRel::AppleTouchIcon {
+        sizes.push(APPLE_TOUCH_ICON_SIZE);
+    }
+    let is_svg = mime_type.as_deref() == Some("image/svg+xml")
+        || url.path().to_ascii_lowercase().ends_with(".svg")
+        || (url.scheme() == "data" && url.path().starts_with("image/svg+xml"));
+
+    Some(IconCandidate {
+        url,
+        rel,
+        sizes,
+        scalable: any || is_svg,
+        mime_type,
+    })
+}
+
+/// `candidates` best first for a `size` pixel icon. Equally close icons go
+/// by `rel="icon"` over apple-touch-icons, then the larger one, as scaling
+/// down looks better than up, then the later link.
+pub fn rank_icons(candidates: &[IconCandidate], size: u32) -> Vec<&IconCandidate> {
+    let mut ranked: Vec<(usize, &IconCandidate)> = candidates.iter().enumerate().collect();
+    ranked.sort_by_key(|(index, candidate)| {
+        (
+            candidate.cost(size),
+            candidate.rel != IconRel::Icon,
+            Reverse(candidate.largest()),
+            Reverse(*index),
+        )
+    });
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Fetches icons for one document, keeping each response so asking for
+/// another size does not fetch again.
+pub struct FaviconLoader {
+    network: Arc<NetworkManager>,
+    initiator: RequestInitiator,
+    /// Body per URL; `None` for ones that failed.
+    fetched: Mutex<HashMap<Url, Option<Arc<Vec<u8>>>>>,
+}
+
+impl FaviconLoader {
+    pub fn new(network: Arc<NetworkManager>, initiator: RequestInitiator) -> Self {
+        Self {
+            network,
+            initiator,
+            fetched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The first of `candidates` that fetches and decodes, fitted to a
+    /// `size` pixel square, and where it came from.
+    pub async fn load_first(
+        &self,
+        candidates: &[IconCandidate],
+        size: u32,
+    ) -> Option<(Url, DecodedImage)> {
+        let images = ImageLoader::new();
+        for candidate in candidates {
+            let body = match self.body(&candidate.url).await {
+                Some(body) => body,
+                None => continue,
+            };
+            match images.decode_image_to_fit(&body, size) {
+                Ok(image) => return Some((candidate.url.clone(), image.into())),
+                Err(e) => tracing::debug!("Icon {} failed to decode: {}", candidate.url, e),
+            }
+        }
+        None
+    }
+
+    async fn body(&self, url: &Url) -> Option<Arc<Vec<u8>>> {
+        if let Some(known) = self.fetched.lock().get(url) {
+            return known.clone();
+        }
+        let body = self.fetch(url).await.map(Arc::new);
+        self.fetched.lock().insert(url.clone(), body.clone());
+        body
+    }
+
+    async fn fetch(&self, url: &Url) -> Option<Vec<u8>> {
+        if !self.initiator.allows_image(url) {
+            tracing::debug!("{} violates the document's img-src policy", url);
+            return None;
+        }
+        if url.scheme() == "data" {
+            return match crate::parse_data_url(&url.as_str()["data:".len()..]) {
+                Ok((_, bytes)) => Some(bytes),
+                Err(e) => {
+                    tracing::debug!("Icon {} is not a data URL: {}", url, e);
+                    None
+                }
+            };
+        }
+        let request = FetchRequest {
+            url: url.to_string(),
+            method: "GET".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: None,
+            follow_redirects: true,
+            cache_policy: None,
+            priority: Priority::Low,
+            idempotent: false,
+        };
+        match self
+            .network
+            .fetch_subresource(request, &self.initiator)
+            .await
+        {
+            Ok(response) if (200..300).contains(&response.status) => Some(response.body),
+            Ok(response) => {
+                tracing::debug!("Icon {} failed with HTTP {}", url, response.status);
+                None
+            }
+            Err(e) => {
+                tracing::debug!("Icon {} failed: {}", url, e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! What a page says about itself in `<meta>` and icon `<link>`s.
+//!
+//! [`PageMetadataTracker`] watches the document's mutations and
+//! re-extracts [`PageMetadata`] and the [`IconCandidate`]s only after
+//! something that could change them did: a node inserted or removed, text
+//! edited, or one of the attributes `<meta>` and `<link>` are read from.
+//! The engine asks it for changes after loads, script runs and ticks, and
+//! turns them into `ThemeColorChanged` and `FaviconChanged` events.
+
+pub mod icons;
+
+pub use icons::{
+    icon_candidates, rank_icons, FaviconLoader, IconCandidate, IconRel, DEFAULT_FAVICON_SIZE,
+};
+
+use crate::core::dom::document::{MutationObserver, MutationType};
+use crate::core::dom::{Document, NodeId};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use url::Url;
+
+/// Attributes of `<meta>` and `<link>` that metadata is read from.
+const WATCHED_ATTRIBUTES: [&str; 7] = [
+    "name", "property", "content", "rel", "href", "sizes", "type",
+];
+
+/// The document's `<meta name>` and Open Graph `<meta property>` values;
+/// the first non-empty one of each wins.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageMetadata {
+    pub description: Option<String>,
+    pub theme_color: Option<String>,
+    pub og_title: Option<String>,
+    pub og_description: Option<String>,
+    /// Resolved against the document's base URL.
+    pub og_image: Option<String>,
+}
+
+/// Read [`PageMetadata`] from the document's `<meta>` elements.
+pub fn extract_metadata(document: &Document) -> PageMetadata {
+    let base = document.base_url();
+    let mut metadata = PageMetadata::default();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("template") {
+                continue;
+            }
+            if node.tag_name.eq_ignore_ascii_case("meta") {
+                let key = node
+                    .get_attribute("property")
+                    .or_else(|| node.get_attribute("name"))
+                    .map(|key| key.trim().to_ascii_lowercase());
+                let content = node
+                    .get_attribute("content")
+                    .map(|content| content.trim().to_string())
+                    .filter(|content| !content.is_empty());
+                let field = match key.as_deref() {
+                    Some("description") => Some(&mut metadata.description),
+                    Some("theme-color") => Some(&mut metadata.theme_color),
+                    Some("og:title") => Some(&mut metadata.og_title),
+                    Some("og:description") => Some(&mut metadata.og_description),
+                    Some("og:image") => Some(&mut metadata.og_image),
+                    _ => None,
+                };
+                if let Some(field) = field.filter(|field| field.is_none()) {
+                    *field = content;
+                }
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    metadata.og_image =
+        metadata
+            .og_image
+            .map(|image| match base.as_ref().map(|base| base.join(&image)) {
+                Some(Ok(url)) => url.to_string(),
+                _ => image,
+            });
+    metadata
+}
+
+/// What changed since the last [`PageMetadataTracker::refresh`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataChanges {
+    /// The new theme color, when it changed.
+    pub theme_color: Option<Option<String>>,
+    /// The icon links changed, so the favicon has to be resolved again.
+    pub icons: bool,
+}
+
+#[derive(Default)]
+struct TrackedState {
+    metadata: PageMetadata,
+    candidates: Vec<IconCandidate>,
+    /// The favicon was resolved for this document's candidates.
+    resolved: bool,
+    loader: Option<Arc<FaviconLoader>>,
+    /// Last theme color and favicon reported, kept across documents so a
+    /// navigation reports the difference.
+    announced_theme_color: Option<String>,
+    announced_favicon: Option<Url>,
+}
+
+/// The current document's metadata and icons, kept up to date with its
+/// mutations.
+pub struct PageMetadataTracker {
+    dirty: Arc<AtomicBool>,
+    state: Mutex<TrackedState>,
+}
+
+impl PageMetadataTracker {
+    pub fn new() -> Self {
+        Self {
+            dirty: Arc::new(AtomicBool::new(false)),
+            state: Mutex::new(TrackedState::default()),
+        }
+    }
+
+    /// Start following a newly loaded `document`, fetching its icons
+    /// through `loader`; `None` for documents that load nothing.
+    pub fn reset(&self, document: &Document, loader: Option<Arc<FaviconLoader>>) {
+        {
+            let mut state = self.state.lock();
+            state.metadata = PageMetadata::default();
+            state.candidates.clear();
+            state.resolved = false;
+            state.loader = loader;
+        }
+        let dirty = self.dirty.clone();
+        document.add_mutation_observer(MutationObserver {
+            callback: Arc::new(move |records| {
+                let relevant = records.iter().any(|record| match record.mutation_type {
+                    MutationType::Attributes => {
+                        record.attribute_name.as_deref().is_some_and(|name| {
+                            WATCHED_ATTRIBUTES
+                                .iter()
+                                .any(|watched| watched.eq_ignore_ascii_case(name))
+                        })
+                    }
+                    MutationType::ChildList | MutationType::CharacterData => true,
+                });
+                if relevant {
+                    dirty.store(true, Ordering::SeqCst);
+                }
+            }),
+            observe_child_list: true,
+            observe_attributes: true,
+            observe_character_data: true,
+            observe_subtree: true,
+            attribute_filter: Some(WATCHED_ATTRIBUTES.iter().map(|a| a.to_string()).collect()),
+        });
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Re-read `document` if it changed since the last call, returning
+    /// what differs from what was last reported.
+    pub fn refresh(&self, document: &Document) -> MetadataChanges {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return MetadataChanges::default();
+        }
+        let metadata = extract_metadata(document);
+        let candidates = icon_candidates(document);
+
+        let mut state = self.state.lock();
+        let mut changes = MetadataChanges::default();
+        if metadata.theme_color != state.announced_theme_color {
+            state.announced_theme_color = metadata.theme_color.clone();
+            changes.theme_color = Some(metadata.theme_color.clone());
+        }
+        changes.icons = !state.resolved || candidates != state.candidates;
+        state.resolved = true;
+        state.metadata = metadata;
+        state.candidates = candidates;
+        changes
+    }
+
+    pub fn metadata(&self) -> PageMetadata {
+        self.state.lock().metadata.clone()
+    }
+
+    /// The icon candidates, best first for `size`, and the loader to fetch
+    /// them with. `declared_only` leaves out the guessed `/favicon.ico`.
+    pub fn ranked_icons(
+        &self,
+        size: u32,
+        declared_only: bool,
+    ) -> (Vec<IconCandidate>, Option<Arc<FaviconLoader>>) {
+        let state = self.state.lock();
+        let ranked = rank_icons(&state.candidates, size)
+            .into_iter()
+            .filter(|candidate| !declared_only || candidate.rel != IconRel::Fallback)
+            .cloned()
+            .collect();
+        (ranked, state.loader.clone())
+    }
+
+    /// Record `favicon` as the one shown, returning whether that differs
+    /// from the last one recorded.
+    pub fn announce_favicon(&self, favicon: Option<Url>) -> bool {
+        let mut state = self.state.lock();
+        if state.announced_favicon == favicon {
+            return false;
+        }
+        state.announced_favicon = favicon;
+        true
+    }
+}
+
+impl Default for PageMetadataTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
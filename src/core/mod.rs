@@ -1,8 +1,37 @@
+pub mod accelerators;
+pub mod archive;
+pub mod audio;
+pub mod clipboard;
+pub mod clock;
+pub mod content_limits;
 pub mod css;
+pub mod devtools;
+pub mod document_write;
 pub mod dom;
+pub mod drag;
+pub mod editing;
+pub mod editing_commands;
+pub mod event_log;
 pub mod events;
+pub mod features;
+pub mod find;
+pub mod fonts;
+pub mod forms;
+pub mod frame_budget;
 pub mod layout;
+pub mod live_regions;
+pub mod media;
+pub mod metadata;
+pub mod navigation_timing;
 pub mod network;
+pub mod permissions;
+pub mod prerender;
+pub mod print;
+pub mod spatial_navigation;
+pub mod speech;
+pub mod storage;
+pub mod user_activation;
+pub mod user_content;
 
 use crate::js_engine::{JSError, JSRuntime};
 use crate::renderer::{ElementType, LayoutTree, RenderError, VulkanRenderer};
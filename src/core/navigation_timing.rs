@@ -0,0 +1,484 @@
+//! Where the time of a navigation went.
+//!
+//! The engine marks the steps of a load on [`NavigationTimings`] as it
+//! reaches them. The stretch from one mark to the next is a phase: each runs
+//! as a `navigation_phase` tracing span, and the durations those spans
+//! measured make both the [`NavigationTiming`] reported once the page has
+//! loaded and the `performance.timing` its scripts read, so the two always
+//! agree with each other and with a trace. Phases follow one another without
+//! gaps, so they add up to the total.
+//!
+//! Counts that are not phases ride along: the render-blocking stylesheets,
+//! the compile and run time of each script, the boxes laid out and the
+//! subresources fetched.
+
+use crate::core::network::{BodyObserver, PageResourceCounts};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Navigations [`NavigationTimings`] keeps by default.
+pub const DEFAULT_NAVIGATION_TIMING_HISTORY: usize = 16;
+
+/// The step of a load a mark starts. Marks come in this order; a load that
+/// skips a step, as a source listing skips scripts, marks it anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NavigationMark {
+    /// The outgoing page got `pagehide`; the document fetch starts.
+    FetchStart,
+    /// The document's response is in; parsing starts.
+    ResponseEnd,
+    /// The document is built; render-blocking stylesheets are awaited.
+    DomInteractive,
+    /// The render-blocking stylesheets arrived or timed out; styles are
+    /// computed.
+    StylesheetsReady,
+    /// Styles are computed; layout starts.
+    StyleDone,
+    /// Layout is done; the page's scripts run.
+    LayoutDone,
+    /// The scripts ran; the layout tree is built.
+    DomContentLoaded,
+    /// The layout tree is built; the page renders for the first time.
+    LayoutTreeBuilt,
+    /// The first render is done; the rest of `load` waits on images.
+    FirstRender,
+    /// The page has loaded.
+    LoadEnd,
+}
+
+impl NavigationMark {
+    /// The phase this mark ends.
+    fn phase(self) -> &'static str {
+        match self {
+            Self::FetchStart => "unload",
+            Self::ResponseEnd => "fetch",
+            Self::DomInteractive => "parse",
+            Self::StylesheetsReady => "stylesheets",
+            Self::StyleDone => "style",
+            Self::LayoutDone => "layout",
+            Self::DomContentLoaded => "scripts",
+            Self::LayoutTreeBuilt => "layout_tree",
+            Self::FirstRender => "first_render",
+            Self::LoadEnd => "load",
+        }
+    }
+}
+
+/// Inline or external, for [`ScriptTiming`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptSource {
+    Inline,
+    External,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FetchTiming {
+    /// From the request to the last byte of the document.
+    pub total_ms: f64,
+    /// From the request to the first byte; the whole fetch for documents
+    /// that arrive at once, as `data:` URLs do.
+    pub time_to_first_byte_ms: f64,
+    /// Connection setup; `None` as long as the HTTP client does not report
+    /// it, and for reused connections.
+    pub dns_ms: Option<f64>,
+    pub connect_ms: Option<f64>,
+    pub tls_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StylesheetTiming {
+    /// Waiting for render-blocking stylesheets after parsing.
+    pub wait_ms: f64,
+    /// Render-blocking stylesheets and the time their fetches took in all;
+    /// they load side by side, so this can exceed the wait.
+    pub blocking_count: u64,
+    pub blocking_fetch_ms: f64,
+}
+
+/// Scripts of one kind. Compiling includes fetching compiled code from the
+/// code cache.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScriptBucket {
+    pub count: u64,
+    pub compile_ms: f64,
+    pub execute_ms: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScriptTiming {
+    /// The whole phase: binding the page's APIs, user scripts, and the
+    /// document's scripts with the fetches of external ones.
+    pub total_ms: f64,
+    pub inline: ScriptBucket,
+    pub external: ScriptBucket,
+}
+
+/// A navigation's breakdown. Durations are milliseconds; the phases, in
+/// load order, are `unload_ms`, `fetch.total_ms`, `parse_ms`,
+/// `stylesheets.wait_ms`, `style_ms`, `layout_ms`, `scripts.total_ms`,
+/// `layout_tree_ms`, `first_render_ms` and `load_ms`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NavigationTiming {
+    pub url: String,
+    /// Milliseconds since the Unix epoch, as `performance.timing.navigationStart`.
+    pub navigation_start: f64,
+    /// From the start of the navigation to the end of `load`.
+    pub total_ms: f64,
+    /// The outgoing page's `pagehide`.
+    pub unload_ms: f64,
+    pub fetch: FetchTiming,
+    /// Parsing the markup into the new document and setting it up.
+    pub parse_ms: f64,
+    pub stylesheets: StylesheetTiming,
+    pub style_ms: f64,
+    pub layout_ms: f64,
+    /// Boxes the first layout produced.
+    pub layout_node_count: u64,
+    pub scripts: ScriptTiming,
+    pub layout_tree_ms: f64,
+    pub first_render_ms: f64,
+    /// Images, the text fragment scroll and idle user scripts.
+    pub load_ms: f64,
+    pub subresources: PageResourceCounts,
+    /// The engine features the document was loaded with.
+    #[serde(default)]
+    pub document_features: Vec<String>,
+}
+
+impl NavigationTiming {
+    /// The phases in load order, which add up to `total_ms`.
+    pub fn phases(&self) -> [f64; 10] {
+        [
+            self.unload_ms,
+            self.fetch.total_ms,
+            self.parse_ms,
+            self.stylesheets.wait_ms,
+            self.style_ms,
+            self.layout_ms,
+            self.scripts.total_ms,
+            self.layout_tree_ms,
+            self.first_render_ms,
+            self.load_ms,
+        ]
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// One navigation in progress, or the last one done.
+struct NavigationTimer {
+    url: String,
+    started: Instant,
+    /// `started` as milliseconds since the Unix epoch.
+    started_at: f64,
+    /// When each mark was reached, from `started`.
+    marks: Vec<(NavigationMark, Duration)>,
+    response_start: Option<Duration>,
+    /// The phase under way.
+    span: tracing::Span,
+    stylesheets: (u64, Duration),
+    layout_node_count: u64,
+    inline: ScriptBucket,
+    external: ScriptBucket,
+    document_features: Vec<String>,
+    done: bool,
+}
+
+impl NavigationTimer {
+    fn new(url: &str) -> Self {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, ms);
+        Self {
+            url: url.to_string(),
+            started: Instant::now(),
+            started_at,
+            marks: Vec::new(),
+            response_start: None,
+            span: Self::phase_span(url, NavigationMark::FetchStart),
+            stylesheets: (0, Duration::ZERO),
+            layout_node_count: 0,
+            inline: ScriptBucket::default(),
+            external: ScriptBucket::default(),
+            document_features: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// The span of the phase `mark` ends.
+    fn phase_span(url: &str, mark: NavigationMark) -> tracing::Span {
+        tracing::info_span!("navigation_phase", phase = mark.phase(), url = url)
+    }
+
+    fn at(&self, mark: NavigationMark) -> Option<Duration> {
+        self.marks
+            .iter()
+            .find(|(reached, _)| *reached == mark)
+            .map(|(_, at)| *at)
+    }
+
+    fn mark(&mut self, mark: NavigationMark) {
+        if self.done || self.marks.last().is_some_and(|(last, _)| *last >= mark) {
+            return;
+        }
+        let at = self.started.elapsed();
+        let previous = self.marks.last().map_or(Duration::ZERO, |(_, at)| *at);
+        tracing::debug!(
+            parent: &self.span,
+            duration_ms = ms(at.saturating_sub(previous)),
+            "{} done",
+            mark.phase()
+        );
+        self.marks.push((mark, at));
+        self.span = match NAVIGATION_MARKS.iter().find(|next| **next > mark) {
+            Some(next) => Self::phase_span(&self.url, *next),
+            None => tracing::Span::none(),
+        };
+    }
+
+    /// The duration of the phase `mark` ends; zero until it has ended.
+    fn phase(&self, mark: NavigationMark) -> Duration {
+        let end = match self.at(mark) {
+            Some(end) => end,
+            None => return Duration::ZERO,
+        };
+        let start = self
+            .marks
+            .iter()
+            .take_while(|(reached, _)| *reached < mark)
+            .last()
+            .map_or(Duration::ZERO, |(_, at)| *at);
+        end.saturating_sub(start)
+    }
+
+    fn timing(&self, subresources: PageResourceCounts) -> NavigationTiming {
+        let phase = |mark| ms(self.phase(mark));
+        let fetch_start = self.at(NavigationMark::FetchStart).unwrap_or_default();
+        let fetch_total = phase(NavigationMark::ResponseEnd);
+        NavigationTiming {
+            url: self.url.clone(),
+            navigation_start: self.started_at,
+            total_ms: self.at(NavigationMark::LoadEnd).map_or(0.0, ms),
+            unload_ms: phase(NavigationMark::FetchStart),
+            fetch: FetchTiming {
+                total_ms: fetch_total,
+                time_to_first_byte_ms: self
+                    .response_start
+                    .map_or(fetch_total, |at| ms(at.saturating_sub(fetch_start))),
+                dns_ms: None,
+                connect_ms: None,
+                tls_ms: None,
+            },
+            parse_ms: phase(NavigationMark::DomInteractive),
+            stylesheets: StylesheetTiming {
+                wait_ms: phase(NavigationMark::StylesheetsReady),
+                blocking_count: self.stylesheets.0,
+                blocking_fetch_ms: ms(self.stylesheets.1),
+            },
+            style_ms: phase(NavigationMark::StyleDone),
+            layout_ms: phase(NavigationMark::LayoutDone),
+            layout_node_count: self.layout_node_count,
+            scripts: ScriptTiming {
+                total_ms: phase(NavigationMark::DomContentLoaded),
+                inline: self.inline.clone(),
+                external: self.external.clone(),
+            },
+            layout_tree_ms: phase(NavigationMark::LayoutTreeBuilt),
+            first_render_ms: phase(NavigationMark::FirstRender),
+            load_ms: phase(NavigationMark::LoadEnd),
+            subresources,
+            document_features: self.document_features.clone(),
+        }
+    }
+}
+
+/// `performance.timing` of `timer`'s document: whole milliseconds since the
+/// Unix epoch, 0 for what has not happened yet. There are no redirects,
+/// unload events or connection details to report, so those read as the
+/// spec has them read without.
+fn performance_timing(timer: Option<&NavigationTimer>) -> serde_json::Value {
+    let epoch = |offset: Option<Duration>| match (timer, offset) {
+        (Some(timer), Some(offset)) => (timer.started_at + ms(offset)).round() as u64,
+        _ => 0,
+    };
+    let mark = |mark| epoch(timer.and_then(|timer| timer.at(mark)));
+    let fetch_start = mark(NavigationMark::FetchStart);
+    let response_end = mark(NavigationMark::ResponseEnd);
+    let response_start = match timer.and_then(|timer| timer.response_start) {
+        Some(at) => epoch(Some(at)),
+        None => response_end,
+    };
+    let dom_content_loaded = mark(NavigationMark::DomContentLoaded);
+    let load_end = mark(NavigationMark::LoadEnd);
+    json!({
+        "navigationStart": epoch(Some(Duration::ZERO)),
+        "unloadEventStart": 0,
+        "unloadEventEnd": 0,
+        "redirectStart": 0,
+        "redirectEnd": 0,
+        "fetchStart": fetch_start,
+        "domainLookupStart": fetch_start,
+        "domainLookupEnd": fetch_start,
+        "connectStart": fetch_start,
+        "connectEnd": fetch_start,
+        "secureConnectionStart": 0,
+        "requestStart": fetch_start,
+        "responseStart": response_start,
+        "responseEnd": response_end,
+        "domLoading": response_end,
+        "domInteractive": mark(NavigationMark::DomInteractive),
+        "domContentLoadedEventStart": dom_content_loaded,
+        "domContentLoadedEventEnd": dom_content_loaded,
+        "domComplete": load_end,
+        "loadEventStart": load_end,
+        "loadEventEnd": load_end,
+    })
+}
+
+const NAVIGATION_MARKS: [NavigationMark; 10] = [
+    NavigationMark::FetchStart,
+    NavigationMark::ResponseEnd,
+    NavigationMark::DomInteractive,
+    NavigationMark::StylesheetsReady,
+    NavigationMark::StyleDone,
+    NavigationMark::LayoutDone,
+    NavigationMark::DomContentLoaded,
+    NavigationMark::LayoutTreeBuilt,
+    NavigationMark::FirstRender,
+    NavigationMark::LoadEnd,
+];
+
+/// The tab's navigation in progress and the breakdowns of its last ones.
+pub struct NavigationTimings {
+    current: Mutex<Option<NavigationTimer>>,
+    history: Mutex<VecDeque<NavigationTiming>>,
+    capacity: usize,
+}
+
+impl NavigationTimings {
+    /// Keeping the last `capacity` breakdowns; zero keeps none.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            current: Mutex::new(None),
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// A navigation to `url` started now; one still in progress is dropped.
+    pub fn begin(&self, url: &str) {
+        *self.current.lock() = Some(NavigationTimer::new(url));
+    }
+
+    /// The navigation reached `mark`. Marks reached already, and marks
+    /// after [`Self::finish`], are ignored.
+    pub fn mark(&self, mark: NavigationMark) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            timer.mark(mark);
+        }
+    }
+
+    /// The first byte of the document arrived.
+    pub fn response_started(&self) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            timer.response_start.get_or_insert(timer.started.elapsed());
+        }
+    }
+
+    /// `count` render-blocking stylesheets took `fetch_time` to fetch in all.
+    pub fn set_blocking_stylesheets(&self, count: usize, fetch_time: Duration) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            timer.stylesheets = (count as u64, fetch_time);
+        }
+    }
+
+    pub fn set_layout_node_count(&self, count: usize) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            timer.layout_node_count = count as u64;
+        }
+    }
+
+    /// The document loads with the features `names`.
+    pub fn set_document_features(&self, names: Vec<String>) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            timer.document_features = names;
+        }
+    }
+
+    /// A document script took `compile` to compile and `execute` to run.
+    pub fn add_script(&self, source: ScriptSource, compile: Duration, execute: Duration) {
+        if let Some(timer) = self.current.lock().as_mut() {
+            let bucket = match source {
+                ScriptSource::Inline => &mut timer.inline,
+                ScriptSource::External => &mut timer.external,
+            };
+            bucket.count += 1;
+            bucket.compile_ms += ms(compile);
+            bucket.execute_ms += ms(execute);
+        }
+    }
+
+    /// The page has loaded: mark [`NavigationMark::LoadEnd`] and keep the
+    /// breakdown, which is returned. `None` without a navigation under way.
+    pub fn finish(&self, subresources: PageResourceCounts) -> Option<NavigationTiming> {
+        let timing = {
+            let mut current = self.current.lock();
+            let timer = current.as_mut().filter(|timer| !timer.done)?;
+            timer.mark(NavigationMark::LoadEnd);
+            timer.done = true;
+            timer.timing(subresources)
+        };
+        if self.capacity > 0 {
+            let mut history = self.history.lock();
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+            history.push_back(timing.clone());
+        }
+        Some(timing)
+    }
+
+    /// The breakdowns of the last navigations, oldest first.
+    pub fn recent(&self) -> Vec<NavigationTiming> {
+        self.history.lock().iter().cloned().collect()
+    }
+
+    /// What `performance.timing` reads for the current document.
+    pub fn performance_timing(&self) -> serde_json::Value {
+        performance_timing(self.current.lock().as_ref())
+    }
+}
+
+impl Default for NavigationTimings {
+    fn default() -> Self {
+        Self::new(DEFAULT_NAVIGATION_TIMING_HISTORY)
+    }
+}
+
+/// Shows a document's body to `inner`, telling `timings` when it starts
+/// to arrive.
+pub struct ResponseStartObserver<'a> {
+    timings: &'a NavigationTimings,
+    inner: &'a mut dyn BodyObserver,
+}
+
+impl<'a> ResponseStartObserver<'a> {
+    pub fn new(timings: &'a NavigationTimings, inner: &'a mut dyn BodyObserver) -> Self {
+        Self { timings, inner }
+    }
+}
+
+impl BodyObserver for ResponseStartObserver<'_> {
+    fn start(&mut self, url: &Url, headers: &HashMap<String, String>) {
+        self.timings.response_started();
+        self.inner.start(url, headers);
+    }
+
+    fn chunk(&mut self, bytes: &[u8]) {
+        self.inner.chunk(bytes);
+    }
+}
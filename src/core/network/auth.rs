@@ -0,0 +1,278 @@
+//! HTTP authentication (`WWW-Authenticate` / `Proxy-Authenticate`).
+//!
+//! A 401 or 407 response is answered from the session credential store when
+//! it holds credentials for the origin and realm, and otherwise by asking the
+//! embedder's [`AuthHandler`]. Credentials only enter the store once a retry
+//! with them has succeeded, and are dropped again as soon as the server
+//! rejects them. Basic and Bearer are supported; other schemes are ignored.
+
+use base64::Engine;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// Retries with credentials per request before the challenge response is
+/// returned as is.
+pub const MAX_AUTH_RETRIES: usize = 3;
+
+/// Asked for credentials when nothing is stored for a challenge; `None`
+/// gives up and lets the 401/407 response through.
+pub type AuthHandler =
+    Arc<dyn Fn(AuthChallenge) -> BoxFuture<'static, Option<Credentials>> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthTarget {
+    /// The origin server: 401, `WWW-Authenticate`, `Authorization`.
+    Server,
+    /// A proxy: 407, `Proxy-Authenticate`, `Proxy-Authorization`.
+    Proxy,
+}
+
+impl AuthTarget {
+    pub fn from_status(status: u16) -> Option<Self> {
+        match status {
+            401 => Some(AuthTarget::Server),
+            407 => Some(AuthTarget::Proxy),
+            _ => None,
+        }
+    }
+
+    pub fn challenge_header(self) -> &'static str {
+        match self {
+            AuthTarget::Server => "www-authenticate",
+            AuthTarget::Proxy => "proxy-authenticate",
+        }
+    }
+
+    pub fn authorization_header(self) -> &'static str {
+        match self {
+            AuthTarget::Server => "Authorization",
+            AuthTarget::Proxy => "Proxy-Authorization",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthScheme {
+    Basic,
+    Bearer,
+}
+
+impl AuthScheme {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "basic" => Some(AuthScheme::Basic),
+            "bearer" => Some(AuthScheme::Bearer),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+impl Credentials {
+    /// The `Authorization` (or `Proxy-Authorization`) header value.
+    pub fn header_value(&self) -> String {
+        match self {
+            Credentials::Basic { username, password } => format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"))
+            ),
+            Credentials::Bearer { token } => format!("Bearer {token}"),
+        }
+    }
+}
+
+/// A challenge the request could not satisfy on its own.
+#[derive(Debug, Clone)]
+pub struct AuthChallenge {
+    /// The request that was challenged.
+    pub url: String,
+    pub origin: String,
+    pub target: AuthTarget,
+    pub scheme: AuthScheme,
+    pub realm: Option<String>,
+    /// Every auth-param of the challenge, names lowercased.
+    pub params: HashMap<String, String>,
+}
+
+impl AuthChallenge {
+    /// The first supported challenge in `response_headers`, if any.
+    pub fn from_response(
+        url: &Url,
+        target: AuthTarget,
+        response_headers: &HashMap<String, String>,
+    ) -> Option<Self> {
+        let header = target.challenge_header();
+        response_headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(header))
+            .flat_map(|(_, value)| parse_challenges(value))
+            .find_map(|(scheme, params)| {
+                Some(AuthChallenge {
+                    url: url.to_string(),
+                    origin: url.origin().ascii_serialization(),
+                    target,
+                    scheme: AuthScheme::parse(&scheme)?,
+                    realm: params.get("realm").cloned(),
+                    params,
+                })
+            })
+    }
+
+    /// Store key. Proxy challenges are keyed by the requested origin too,
+    /// since requests do not go through a configurable proxy yet.
+    pub fn key(&self) -> AuthKey {
+        AuthKey {
+            target: self.target,
+            origin: self.origin.clone(),
+            realm: self.realm.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AuthKey {
+    pub target: AuthTarget,
+    pub origin: String,
+    pub realm: String,
+}
+
+/// Session credential store plus the embedder's prompt.
+#[derive(Default)]
+pub struct AuthManager {
+    credentials: DashMap<AuthKey, Credentials>,
+    handler: RwLock<Option<AuthHandler>>,
+}
+
+impl AuthManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_handler(&self, handler: Option<AuthHandler>) {
+        *self.handler.write() = handler;
+    }
+
+    pub fn lookup(&self, key: &AuthKey) -> Option<Credentials> {
+        self.credentials.get(key).map(|entry| entry.clone())
+    }
+
+    /// Remember credentials the server has accepted.
+    pub fn store(&self, key: AuthKey, credentials: Credentials) {
+        self.credentials.insert(key, credentials);
+    }
+
+    pub fn forget(&self, key: &AuthKey) {
+        self.credentials.remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.credentials.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.credentials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.credentials.is_empty()
+    }
+
+    /// Ask the embedder; `None` when no handler is installed.
+    pub async fn prompt(&self, challenge: AuthChallenge) -> Option<Credentials> {
+        let handler = self.handler.read().clone()?;
+        handler(challenge).await
+    }
+}
+
+/// Split a challenge header into `(scheme, params)` pairs. A token68 (as in
+/// `Bearer abc==`) is reported under the `token68` param.
+pub fn parse_challenges(value: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut challenges: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for piece in split_top_level(value) {
+        let piece = piece.trim();
+        if piece.is_empty() {
+            continue;
+        }
+
+        // A scheme is a bare token followed by whitespace or the end.
+        let head_end = piece.find(char::is_whitespace).unwrap_or(piece.len());
+        let head = &piece[..head_end];
+        let rest = if head.contains('=') {
+            piece
+        } else {
+            challenges.push((head.to_string(), HashMap::new()));
+            piece[head_end..].trim()
+        };
+        if rest.is_empty() {
+            continue;
+        }
+
+        let params = match challenges.last_mut() {
+            Some((_, params)) => params,
+            // A parameter before any scheme; nothing to attach it to.
+            None => continue,
+        };
+        match rest.split_once('=') {
+            Some((name, value)) if !value.trim_start().trim_matches('=').is_empty() => {
+                params.insert(name.trim().to_ascii_lowercase(), unquote(value.trim()));
+            }
+            _ => {
+                params.insert("token68".to_string(), rest.to_string());
+            }
+        }
+    }
+    challenges
+}
+
+/// Split at commas outside quoted strings.
+fn split_top_level(value: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                pieces.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&value[start..]);
+    pieces
+}
+
+fn unquote(value: &str) -> String {
+    let inner = match value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(inner) => inner,
+        None => return value.to_string(),
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                unquoted.push(next);
+            }
+        } else {
+            unquoted.push(c);
+        }
+    }
+    unquoted
+}
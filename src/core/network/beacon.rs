@@ -0,0 +1,199 @@
+//! Keepalive requests (`navigator.sendBeacon`, `fetch(..., { keepalive: true })`).
+//!
+//! These run on their own tasks, outside the page's request bookkeeping, so
+//! navigating away or cancelling page requests never drops them. Each origin
+//! may have at most [`BEACON_QUOTA_BYTES`] of request bodies in flight.
+
+use super::csp::ContentSecurityPolicy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use url::Url;
+
+/// In-flight keepalive body budget per origin, from the Fetch spec.
+pub const BEACON_QUOTA_BYTES: usize = 64 * 1024;
+
+/// The document a request is made on behalf of.
+#[derive(Debug, Clone)]
+pub struct RequestInitiator {
+    pub document_url: Url,
+    pub csp: Option<Arc<ContentSecurityPolicy>>,
+}
+
+impl RequestInitiator {
+    pub fn new(document_url: Url) -> Self {
+        Self {
+            document_url,
+            csp: None,
+        }
+    }
+
+    pub fn with_csp(mut self, csp: Option<ContentSecurityPolicy>) -> Self {
+        self.csp = csp.map(Arc::new);
+        self
+    }
+
+    /// Quota key; every opaque origin (e.g. `data:` documents) shares `"null"`.
+    pub fn origin_key(&self) -> String {
+        self.document_url.origin().ascii_serialization()
+    }
+
+    pub fn allows_connect(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_connect(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    pub fn allows_font(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_font(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    pub fn allows_style(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_style(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    pub fn allows_image(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_image(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    pub fn allows_media(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_media(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    pub fn allows_script(&self, target: &Url) -> bool {
+        match &self.csp {
+            Some(csp) => csp.allows_script(target, &self.document_url),
+            None => true,
+        }
+    }
+
+    /// Whether `target` is same-origin with the document.
+    pub fn is_same_origin(&self, target: &Url) -> bool {
+        let origin = self.document_url.origin();
+        origin.is_tuple() && target.origin() == origin
+    }
+
+    /// The headers of a ping to `ping` for following a link to `target`.
+    /// `Ping-From` tells the pinged server which page the link was on, so an
+    /// https page only sends it to its own origin.
+    pub fn ping_headers(&self, ping: &Url, target: &Url) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), "text/ping".to_string());
+        headers.insert("Ping-To".to_string(), target.to_string());
+        if self.is_same_origin(ping) || self.document_url.scheme() != "https" {
+            headers.insert("Ping-From".to_string(), self.document_url.to_string());
+        }
+        headers
+    }
+}
+
+pub struct BeaconQueue {
+    in_flight: Mutex<HashMap<String, usize>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    closed: AtomicBool,
+}
+
+impl BeaconQueue {
+    pub fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Claim `bytes` of `origin`'s quota. Returns `false` when the claim would
+    /// exceed it or the queue is shutting down.
+    pub fn try_reserve(&self, origin: &str, bytes: usize) -> bool {
+        if self.closed.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let mut in_flight = self.in_flight.lock();
+        let used = in_flight.entry(origin.to_string()).or_insert(0);
+        if *used + bytes > BEACON_QUOTA_BYTES {
+            return false;
+        }
+        *used += bytes;
+        true
+    }
+
+    pub fn release(&self, origin: &str, bytes: usize) {
+        let mut in_flight = self.in_flight.lock();
+        if let Some(used) = in_flight.get_mut(origin) {
+            *used = used.saturating_sub(bytes);
+            if *used == 0 {
+                in_flight.remove(origin);
+            }
+        }
+    }
+
+    pub fn in_flight_bytes(&self, origin: &str) -> usize {
+        self.in_flight.lock().get(origin).copied().unwrap_or(0)
+    }
+
+    /// Number of keepalive requests still transmitting.
+    pub fn pending(&self) -> usize {
+        self.tasks
+            .lock()
+            .iter()
+            .filter(|task| !task.is_finished())
+            .count()
+    }
+
+    /// Run `request` detached from the caller. Returns `false` when there is
+    /// no Tokio runtime to run it on.
+    pub fn spawn<F>(&self, request: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => return false,
+        };
+
+        let mut tasks = self.tasks.lock();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle.spawn(request));
+        true
+    }
+
+    /// Stop accepting requests, give in-flight ones `grace` to finish, then
+    /// abort whatever is left.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        let mut tasks = std::mem::take(&mut *self.tasks.lock());
+        let drained =
+            tokio::time::timeout(grace, futures::future::join_all(tasks.iter_mut())).await;
+        if drained.is_err() {
+            for task in &tasks {
+                task.abort();
+            }
+        }
+        self.in_flight.lock().clear();
+    }
+}
+
+impl Default for BeaconQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
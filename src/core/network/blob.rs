@@ -0,0 +1,88 @@
+//! `blob:` URLs: bytes a page registered with `URL.createObjectURL`.
+//!
+//! A URL is `blob:` followed by the registering document's origin and a
+//! random id, and resolves until the page revokes it or the document goes
+//! away; the engine clears the store on every navigation. Only GET reads
+//! one, and only the origin in the URL may.
+
+use super::{FetchResponse, NetworkError, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub content_type: String,
+    pub data: Arc<Vec<u8>>,
+}
+
+#[derive(Default)]
+pub struct BlobStore {
+    blobs: RwLock<HashMap<String, Blob>>,
+}
+
+impl BlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `blob` for a document at `document_url` and return its URL.
+    pub fn register(&self, document_url: &Url, blob: Blob) -> String {
+        let url = format!(
+            "blob:{}/{}",
+            document_url.origin().ascii_serialization(),
+            uuid::Uuid::new_v4()
+        );
+        self.blobs.write().insert(url.clone(), blob);
+        url
+    }
+
+    /// Forget `url`. `false` when it named nothing.
+    pub fn revoke(&self, url: &str) -> bool {
+        self.blobs.write().remove(url).is_some()
+    }
+
+    /// The blob `url` names; a fragment is ignored.
+    pub fn resolve(&self, url: &str) -> Option<Blob> {
+        let url = url.split('#').next().unwrap_or(url);
+        self.blobs.read().get(url).cloned()
+    }
+
+    pub fn clear(&self) {
+        self.blobs.write().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Answer a request for `url` as a fetch would.
+    pub(crate) fn fetch(&self, url: &str, method: &str) -> Result<FetchResponse> {
+        if !method.eq_ignore_ascii_case("GET") {
+            return Err(NetworkError::RequestFailed(format!(
+                "{} of a blob: URL",
+                method
+            )));
+        }
+        let blob = self
+            .resolve(url)
+            .ok_or_else(|| NetworkError::RequestFailed(format!("{} is not registered", url)))?;
+        let mut headers = HashMap::new();
+        headers.insert("content-type".to_string(), blob.content_type.clone());
+        headers.insert("content-length".to_string(), blob.data.len().to_string());
+        Ok(FetchResponse {
+            status: 200,
+            headers,
+            body: blob.data.to_vec(),
+            url: url.to_string(),
+            redirected: false,
+            tls: None,
+            retries: 0,
+        })
+    }
+}
@@ -0,0 +1,385 @@
+//! HTTP cookies (RFC 6265).
+//!
+//! [`CookieJar`] keeps what `Set-Cookie` response headers store and builds
+//! the `Cookie` header of each request from it: the cookies whose domain
+//! and path match the URL, `Secure` ones over HTTPS only, and none that
+//! expired. A cookie with `Max-Age` or `Expires` is persistent; one
+//! without lasts the session. The embedder keeps either across restarts
+//! with [`CookieJar::to_json`] and [`CookieJar::load_json`].
+//!
+//! There is no public suffix list: a `Domain` attribute naming a single
+//! label, such as `com`, is refused unless it is the host itself. As in
+//! RFC 6265bis, a `Secure` cookie is only stored from an HTTPS response.
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Cookies kept per domain; past it, the oldest go first.
+pub const MAX_COOKIES_PER_DOMAIN: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Sent to `domain` only, not to its subdomains: the response that set
+    /// it had no `Domain` attribute.
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    /// Seconds since the Unix epoch; `None` for a session cookie.
+    pub expires: Option<u64>,
+    /// Microseconds since the Unix epoch. Replacing a cookie keeps it.
+    pub created: u64,
+}
+
+impl Cookie {
+    /// Parse a `Set-Cookie` header value received from `url`. `None` when
+    /// the header is malformed or the URL may not set it.
+    pub fn parse(url: &Url, header: &str) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let (pair, attributes) = header.split_once(';').unwrap_or((header, ""));
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let now = now_micros();
+        let mut expires = None;
+        let mut max_age = None;
+        let mut domain = None;
+        let mut path = None;
+        let mut secure = false;
+        let mut http_only = false;
+        for attribute in attributes.split(';') {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if let Some(date) = parse_cookie_date(value) {
+                        expires = Some(date);
+                    }
+                }
+                "max-age" => {
+                    let digits = value.strip_prefix('-').unwrap_or(value);
+                    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                        continue;
+                    }
+                    // Zero or less expires it now; overflow is as good as never.
+                    max_age = Some(match value.parse::<i64>() {
+                        Ok(seconds) if seconds <= 0 => 0,
+                        Ok(seconds) => (now / 1_000_000).saturating_add(seconds as u64),
+                        Err(_) if value.starts_with('-') => 0,
+                        Err(_) => u64::MAX,
+                    });
+                }
+                "domain" if !value.is_empty() => {
+                    domain = Some(value.trim_start_matches('.').to_ascii_lowercase());
+                }
+                "path" => {
+                    path = value.starts_with('/').then(|| value.to_string());
+                }
+                "secure" => secure = true,
+                "httponly" => http_only = true,
+                _ => {}
+            }
+        }
+
+        if secure && url.scheme() != "https" {
+            return None;
+        }
+        let (domain, host_only) = match domain {
+            Some(domain) if domain == host => (domain, false),
+            Some(domain) if !domain.contains('.') || !domain_matches(&host, &domain) => {
+                return None
+            }
+            Some(domain) => (domain, false),
+            None => (host, true),
+        };
+        Some(Self {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain,
+            host_only,
+            path: path.unwrap_or_else(|| default_path(url.path())),
+            secure,
+            http_only,
+            // Max-Age wins over Expires.
+            expires: max_age.or(expires),
+            created: now,
+        })
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.expires.is_some()
+    }
+
+    /// Whether the cookie expired by `now`, in seconds since the epoch.
+    pub fn is_expired_at(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether a request to `url` carries the cookie, expiry aside.
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+/// The cookies of a browsing session.
+#[derive(Default)]
+pub struct CookieJar {
+    cookies: RwLock<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store the cookie of a `Set-Cookie` header received from `url`; an
+    /// expired one deletes the cookie it replaces. Returns whether the
+    /// header was accepted.
+    pub fn set_cookie(&self, url: &Url, header: &str) -> bool {
+        match Cookie::parse(url, header) {
+            Some(cookie) => {
+                self.insert(cookie);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Store the cookies of a response's `Set-Cookie` header, which holds one
+    /// per line when the response had several.
+    pub fn set_cookies_from_header(&self, url: &Url, header: &str) {
+        for line in header.lines() {
+            self.set_cookie(url, line);
+        }
+    }
+
+    fn insert(&self, mut cookie: Cookie) {
+        let now = now_micros() / 1_000_000;
+        let mut cookies = self.cookies.write();
+        if let Some(index) = cookies.iter().position(|old| old.same_slot(&cookie)) {
+            cookie.created = cookies.remove(index).created;
+        }
+        if cookie.is_expired_at(now) {
+            return;
+        }
+        let domain = cookie.domain.clone();
+        cookies.push(cookie);
+
+        let mut count = cookies.iter().filter(|c| c.domain == domain).count();
+        if count > MAX_COOKIES_PER_DOMAIN {
+            cookies.retain(|c| c.domain != domain || !c.is_expired_at(now));
+            count = cookies.iter().filter(|c| c.domain == domain).count();
+        }
+        while count > MAX_COOKIES_PER_DOMAIN {
+            let oldest = cookies
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.domain == domain)
+                .min_by_key(|(_, c)| c.created)
+                .map(|(index, _)| index);
+            let Some(index) = oldest else {
+                break;
+            };
+            cookies.remove(index);
+            count -= 1;
+        }
+    }
+
+    /// The unexpired cookies a request to `url` carries, longest path first
+    /// and then oldest first.
+    pub fn cookies_for(&self, url: &Url) -> Vec<Cookie> {
+        let now = now_micros() / 1_000_000;
+        let mut matching: Vec<Cookie> = self
+            .cookies
+            .read()
+            .iter()
+            .filter(|cookie| !cookie.is_expired_at(now) && cookie.matches(url))
+            .cloned()
+            .collect();
+        matching.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+        });
+        matching
+    }
+
+    /// The `Cookie` header of a request to `url`, when it carries any.
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let cookies = self.cookies_for(url);
+        if cookies.is_empty() {
+            return None;
+        }
+        let pairs: Vec<String> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    pub fn clear(&self) {
+        self.cookies.write().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.read().is_empty()
+    }
+
+    /// Every unexpired cookie, session ones included, as a JSON array.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let now = now_micros() / 1_000_000;
+        let cookies = self.cookies.read();
+        let unexpired: Vec<&Cookie> = cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired_at(now))
+            .collect();
+        serde_json::to_string(&unexpired)
+    }
+
+    /// Add the cookies [`CookieJar::to_json`] saved, over any the jar holds
+    /// for the same name, domain and path. Expired ones are dropped.
+    pub fn load_json(&self, json: &str) -> serde_json::Result<()> {
+        let cookies: Vec<Cookie> = serde_json::from_str(json)?;
+        for cookie in cookies {
+            self.insert(cookie);
+        }
+        Ok(())
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+/// Whether `host` is `domain` or a subdomain of it. IP addresses only match
+/// themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    let is_ip = host.starts_with('[') || host.parse::<std::net::Ipv4Addr>().is_ok();
+    !is_ip
+        && host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The directory of `path`, for a cookie set without a `Path` attribute.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+/// The leading `min` to `max` digits of `token` and what follows them.
+fn leading_number(token: &str, min: usize, max: usize) -> Option<(u32, &str)> {
+    let end = token
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(token.len());
+    if end < min || end > max {
+        return None;
+    }
+    Some((token[..end].parse().ok()?, &token[end..]))
+}
+
+/// `hh:mm:ss`, each with one or two digits.
+fn parse_time(token: &str) -> Option<(u32, u32, u32)> {
+    let (hour, rest) = leading_number(token, 1, 2)?;
+    let (minute, rest) = leading_number(rest.strip_prefix(':')?, 1, 2)?;
+    let (second, _) = leading_number(rest.strip_prefix(':')?, 1, 2)?;
+    Some((hour, minute, second))
+}
+
+/// An `Expires` date as seconds since the epoch, by the lenient algorithm
+/// of RFC 6265 section 5.1.1. Dates before the epoch give zero.
+fn parse_cookie_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    for token in value.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(parsed) = parse_time(token) {
+                time = Some(parsed);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some((parsed, _)) = leading_number(token, 1, 2) {
+                day = Some(parsed);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(index) = MONTHS.iter().position(|m| prefix.as_deref() == Some(*m)) {
+                month = Some(index as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some((parsed, _)) = leading_number(token, 2, 4) {
+                year = Some(match parsed {
+                    70..=99 => parsed + 1900,
+                    0..=69 => parsed + 2000,
+                    _ => parsed,
+                });
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, year) = (day?, month?, year?);
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let seconds = chrono::NaiveDate::from_ymd_opt(year as i32, month, day)?
+        .and_hms_opt(hour, minute, second)?
+        .and_utc()
+        .timestamp();
+    Some(seconds.max(0) as u64)
+}
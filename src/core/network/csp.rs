@@ -0,0 +1,186 @@
+//! Content-Security-Policy source lists, as far as connection checks need them.
+//!
+//! Only fetch directives are evaluated; everything else in the header is
+//! parsed and kept but has no effect here.
+
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    /// One map per comma-separated policy; every policy must allow a request.
+    /// Each maps a lowercase directive name to its source expressions.
+    policies: Vec<HashMap<String, Vec<String>>>,
+}
+
+impl ContentSecurityPolicy {
+    /// Parse a `Content-Security-Policy` header value. When a directive is
+    /// repeated within a policy the first occurrence wins, as in the spec.
+    pub fn parse(header: &str) -> Self {
+        let policies = header
+            .split(',')
+            .map(|policy| {
+                let mut directives = HashMap::new();
+                for directive in policy.split(';') {
+                    let mut tokens = directive.split_ascii_whitespace();
+                    let name = match tokens.next() {
+                        Some(name) => name.to_ascii_lowercase(),
+                        None => continue,
+                    };
+                    directives
+                        .entry(name)
+                        .or_insert_with(|| tokens.map(str::to_string).collect());
+                }
+                directives
+            })
+            .filter(|directives: &HashMap<String, Vec<String>>| !directives.is_empty())
+            .collect();
+
+        Self { policies }
+    }
+
+    /// Whether `connect-src` (or `default-src`) lets a document at
+    /// `document_url` open a connection to `target`.
+    pub fn allows_connect(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("connect-src", target, document_url)
+    }
+
+    /// Whether `font-src` (or `default-src`) lets a document at
+    /// `document_url` load a font from `target`.
+    pub fn allows_font(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("font-src", target, document_url)
+    }
+
+    /// Whether `style-src` (or `default-src`) lets a document at
+    /// `document_url` load a stylesheet from `target`.
+    pub fn allows_style(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("style-src", target, document_url)
+    }
+
+    /// Whether `img-src` (or `default-src`) lets a document at
+    /// `document_url` load an image from `target`.
+    pub fn allows_image(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("img-src", target, document_url)
+    }
+
+    /// Whether `media-src` (or `default-src`) lets a document at
+    /// `document_url` load audio or video from `target`.
+    pub fn allows_media(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("media-src", target, document_url)
+    }
+
+    /// Whether `script-src` (or `default-src`) lets a document at
+    /// `document_url` load a script from `target`.
+    pub fn allows_script(&self, target: &Url, document_url: &Url) -> bool {
+        self.allows("script-src", target, document_url)
+    }
+
+    /// Whether `script-src` (or `default-src`) lets the document compile
+    /// WebAssembly: it must list `'wasm-unsafe-eval'` or `'unsafe-eval'`.
+    pub fn allows_wasm_eval(&self) -> bool {
+        self.policies.iter().all(|directives| {
+            match directives
+                .get("script-src")
+                .or_else(|| directives.get("default-src"))
+            {
+                Some(sources) => sources.iter().any(|source| {
+                    source.eq_ignore_ascii_case("'wasm-unsafe-eval'")
+                        || source.eq_ignore_ascii_case("'unsafe-eval'")
+                }),
+                None => true,
+            }
+        })
+    }
+
+    fn allows(&self, directive: &str, target: &Url, document_url: &Url) -> bool {
+        self.policies.iter().all(|directives| {
+            match directives
+                .get(directive)
+                .or_else(|| directives.get("default-src"))
+            {
+                Some(sources) => sources
+                    .iter()
+                    .any(|source| source_matches(source, target, document_url)),
+                None => true,
+            }
+        })
+    }
+}
+
+fn source_matches(source: &str, target: &Url, document_url: &Url) -> bool {
+    let source = source.to_ascii_lowercase();
+    let network_scheme = matches!(target.scheme(), "http" | "https" | "ws" | "wss");
+
+    match source.as_str() {
+        "'none'" => false,
+        "*" => network_scheme,
+        "'self'" => {
+            let self_origin = document_url.origin();
+            self_origin.is_tuple() && target.origin() == self_origin
+        }
+        _ if source.starts_with('\'') => false,
+        _ if source.ends_with(':') && !source.contains('/') => {
+            target.scheme() == &source[..source.len() - 1]
+        }
+        _ => host_source_matches(&source, target),
+    }
+}
+
+/// `[scheme://]host[:port][/path]`, where host may start with `*.` and port may be `*`.
+fn host_source_matches(source: &str, target: &Url) -> bool {
+    let (scheme, rest) = match source.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, source),
+    };
+
+    match scheme {
+        Some(scheme) => {
+            let upgraded = (scheme == "http" && target.scheme() == "https")
+                || (scheme == "ws" && target.scheme() == "wss");
+            if target.scheme() != scheme && !upgraded {
+                return false;
+            }
+        }
+        None => {
+            if !matches!(target.scheme(), "http" | "https" | "ws" | "wss") {
+                return false;
+            }
+        }
+    }
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], Some(&rest[i..])),
+        None => (rest, None),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    };
+
+    let target_host = match target.host_str() {
+        Some(host) => host.to_ascii_lowercase(),
+        None => return false,
+    };
+    let host_ok = match host.strip_prefix("*.") {
+        Some(suffix) => target_host.ends_with(&format!(".{suffix}")),
+        None => host == "*" || target_host == host,
+    };
+    if !host_ok {
+        return false;
+    }
+
+    let port_ok = match port {
+        Some("*") => true,
+        Some(port) => port.parse::<u16>().ok() == target.port_or_known_default(),
+        None => target.port().is_none(),
+    };
+    if !port_ok {
+        return false;
+    }
+
+    match path {
+        None | Some("/") => true,
+        Some(path) if path.ends_with('/') => target.path().starts_with(path),
+        Some(path) => target.path() == path,
+    }
+}
@@ -0,0 +1,331 @@
+//! Persistent second tier behind the in-memory [`HttpCache`](super::HttpCache).
+//!
+//! Layout under the cache directory:
+//! - `blobs/<sha256>`: response bodies named by their content hash, so a blob
+//!   torn by a crash no longer matches its name and is evicted when read.
+//! - `index.json`: URL → entry metadata. Always written to a temporary file
+//!   and renamed over the old one, so a power cut leaves either the previous
+//!   or the new index, never a partial one.
+//!
+//! Nothing is read at construction; the index is loaded on first use.
+
+use super::{CachePolicy, NetworkError, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskCacheConfig {
+    /// Where entries are persisted; `None` keeps the HTTP cache memory-only.
+    pub directory: Option<PathBuf>,
+    pub max_size_bytes: u64,
+}
+
+impl Default for DiskCacheConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_size_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskCacheEntry {
+    /// Hex SHA-256 of the body; also the blob's file name.
+    pub blob: String,
+    pub size: u64,
+    pub cache_policy: CachePolicy,
+    pub headers: Vec<(String, String)>,
+    /// Request header values the response varies on, by lowercase name.
+    pub vary: Vec<(String, String)>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub last_accessed: u64,
+}
+
+impl DiskCacheEntry {
+    pub fn created_at_time(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_secs(self.created_at)
+    }
+
+    fn matches_request(&self, request_headers: &HashMap<String, String>) -> bool {
+        self.vary.iter().all(|(name, stored)| {
+            let sent = request_headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map_or("", |(_, value)| value.as_str());
+            sent == stored
+        })
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct DiskIndex {
+    version: u32,
+    entries: HashMap<String, DiskCacheEntry>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    entries: HashMap<String, DiskCacheEntry>,
+    total_bytes: u64,
+    /// Access times changed since the index was last written.
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskCacheStats {
+    pub entry_count: usize,
+    pub total_size_bytes: u64,
+    pub max_size_bytes: u64,
+}
+
+pub struct DiskCache {
+    root: PathBuf,
+    max_size_bytes: u64,
+    /// `None` until the first lookup or write loads `index.json`.
+    state: Mutex<Option<IndexState>>,
+}
+
+impl DiskCache {
+    pub fn new(root: impl Into<PathBuf>, max_size_bytes: u64) -> Self {
+        Self {
+            root: root.into(),
+            max_size_bytes,
+            state: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &DiskCacheConfig) -> Option<Self> {
+        config
+            .directory
+            .as_ref()
+            .map(|directory| Self::new(directory, config.max_size_bytes))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.root
+    }
+
+    /// Look up `url` for a request carrying `request_headers`. A blob that is
+    /// missing or fails its hash check is evicted and reported as a miss.
+    pub fn get(
+        &self,
+        url: &str,
+        request_headers: &HashMap<String, String>,
+    ) -> Option<(DiskCacheEntry, Vec<u8>)> {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+
+        let entry = state.entries.get(url)?.clone();
+        if !entry.matches_request(request_headers) {
+            return None;
+        }
+
+        let body = match fs::read(self.blob_path(&entry.blob)) {
+            Ok(body) if content_hash(&body) == entry.blob => body,
+            _ => {
+                tracing::warn!("Evicting corrupt disk cache entry for {}", url);
+                self.remove_entry(state, url);
+                if let Err(e) = self.write_index(state) {
+                    tracing::warn!("Failed to persist disk cache index: {}", e);
+                }
+                return None;
+            }
+        };
+
+        if let Some(stored) = state.entries.get_mut(url) {
+            stored.last_accessed = now_secs();
+        }
+        state.dirty = true;
+        Some((entry, body))
+    }
+
+    /// Persist `body` under `url`. `no-store` entries and bodies larger than
+    /// the whole budget are ignored.
+    pub fn put(&self, url: &str, mut entry: DiskCacheEntry, body: &[u8]) -> Result<()> {
+        if entry.cache_policy.no_store || body.len() as u64 > self.max_size_bytes {
+            return Ok(());
+        }
+
+        entry.blob = content_hash(body);
+        entry.size = body.len() as u64;
+
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+
+        let blob_path = self.blob_path(&entry.blob);
+        if !blob_path.exists() {
+            write_atomically(&blob_path, body)?;
+        }
+
+        self.remove_entry(state, url);
+        state.total_bytes += entry.size;
+        state.entries.insert(url.to_string(), entry);
+
+        while state.total_bytes > self.max_size_bytes {
+            let oldest = state
+                .entries
+                .iter()
+                .filter(|(key, _)| key.as_str() != url)
+                .min_by_key(|(_, entry)| entry.last_accessed)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(key) => self.remove_entry(state, &key),
+                None => break,
+            }
+        }
+
+        self.write_index(state)
+    }
+
+    pub fn remove(&self, url: &str) -> Result<()> {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+        if state.entries.contains_key(url) {
+            self.remove_entry(state, url);
+            self.write_index(state)?;
+        }
+        Ok(())
+    }
+
+    /// Drop every entry and delete the files backing them.
+    pub fn clear(&self) -> Result<()> {
+        let mut guard = self.state.lock();
+        *guard = Some(IndexState::default());
+
+        for path in [self.root.join(INDEX_FILE), self.root.join("blobs")] {
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            match removed {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(NetworkError::Cache(format!(
+                        "Failed to remove {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out access times recorded since the last index update.
+    pub fn flush(&self) -> Result<()> {
+        let mut guard = self.state.lock();
+        match guard.as_mut() {
+            Some(state) if state.dirty => self.write_index(state),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_stats(&self) -> DiskCacheStats {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+        DiskCacheStats {
+            entry_count: state.entries.len(),
+            total_size_bytes: state.total_bytes,
+            max_size_bytes: self.max_size_bytes,
+        }
+    }
+
+    fn load<'a>(&self, guard: &'a mut Option<IndexState>) -> &'a mut IndexState {
+        guard.get_or_insert_with(|| {
+            let index = match fs::read(self.root.join(INDEX_FILE)) {
+                Ok(bytes) => match serde_json::from_slice::<DiskIndex>(&bytes) {
+                    Ok(index) if index.version == INDEX_VERSION => index,
+                    Ok(_) => DiskIndex::default(),
+                    Err(e) => {
+                        tracing::warn!("Ignoring unreadable disk cache index: {}", e);
+                        DiskIndex::default()
+                    }
+                },
+                Err(_) => DiskIndex::default(),
+            };
+
+            IndexState {
+                total_bytes: index.entries.values().map(|entry| entry.size).sum(),
+                entries: index.entries,
+                dirty: false,
+            }
+        })
+    }
+
+    /// Remove `url` from the index, deleting its blob unless another entry
+    /// shares it. The caller persists the index.
+    fn remove_entry(&self, state: &mut IndexState, url: &str) {
+        if let Some(entry) = state.entries.remove(url) {
+            state.total_bytes = state.total_bytes.saturating_sub(entry.size);
+            if !state.entries.values().any(|other| other.blob == entry.blob) {
+                let _ = fs::remove_file(self.blob_path(&entry.blob));
+            }
+        }
+    }
+
+    fn write_index(&self, state: &mut IndexState) -> Result<()> {
+        let index = DiskIndex {
+            version: INDEX_VERSION,
+            entries: state.entries.clone(),
+        };
+        let bytes = serde_json::to_vec(&index)
+            .map_err(|e| NetworkError::Cache(format!("Failed to encode index: {}", e)))?;
+        write_atomically(&self.root.join(INDEX_FILE), &bytes)?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    fn blob_path(&self, blob: &str) -> PathBuf {
+        self.root.join("blobs").join(blob)
+    }
+}
+
+/// Write `data` beside `path`, fsync it, then rename it into place.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let io_error = |e: std::io::Error| NetworkError::Cache(format!("{}: {}", path.display(), e));
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let temp = PathBuf::from(temp);
+
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(io_error(e));
+    }
+    Ok(())
+}
+
+pub(crate) fn content_hash(data: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Current time in the index's representation.
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -1,3 +1,4 @@
+use super::tls::TlsInfo;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -52,12 +53,71 @@ pub enum CorsMode {
     NoCors,
 }
 
+#[derive(Clone)]
 pub struct FetchResponse {
     pub status: u16,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
     pub url: String,
     pub redirected: bool,
+    /// The connection's TLS details, for an HTTPS response that came off
+    /// the network; `None` for plain HTTP and cached responses.
+    pub tls: Option<TlsInfo>,
+    /// Times the request was sent again after a transient failure.
+    pub retries: u32,
+}
+
+impl FetchResponse {
+    /// Charset of the body: BOM first, then the `Content-Type` header, then a
+    /// `<meta charset>` sniffed from the first 1024 bytes, falling back to UTF-8.
+    pub fn detect_charset(&self) -> &'static encoding_rs::Encoding {
+        if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(&self.body) {
+            return encoding;
+        }
+
+        let header_charset = self
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+            .and_then(|(_, v)| charset_param(v));
+        if let Some(encoding) =
+            header_charset.and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        {
+            return encoding;
+        }
+
+        let head = &self.body[..self.body.len().min(1024)];
+        let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+        if let Some(pos) = head.find("charset=") {
+            let label: String = head[pos + "charset=".len()..]
+                .trim_start_matches(['"', '\''])
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect();
+            if let Some(encoding) = encoding_rs::Encoding::for_label(label.as_bytes()) {
+                return encoding;
+            }
+        }
+
+        encoding_rs::UTF_8
+    }
+
+    /// Decode the raw body bytes with the detected charset.
+    pub fn decode_text(&self) -> String {
+        let (text, _, _) = self.detect_charset().decode(&self.body);
+        text.into_owned()
+    }
+}
+
+fn charset_param(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
 }
 
 pub struct FetchEngine {
@@ -149,6 +209,8 @@ impl FetchEngine {
             body,
             url: final_url.to_string(),
             redirected,
+            tls: None,
+            retries: 0,
         })
     }
 }
@@ -202,6 +264,8 @@ impl ResponseCache {
                     body: entry.response.body.clone(),
                     url: entry.response.url.clone(),
                     redirected: entry.response.redirected,
+                    tls: entry.response.tls.clone(),
+                    retries: 0,
                 });
             }
         }
@@ -225,6 +289,8 @@ impl ResponseCache {
                     body: response.body.clone(),
                     url: response.url.clone(),
                     redirected: response.redirected,
+                    tls: response.tls.clone(),
+                    retries: response.retries,
                 },
                 expires,
                 etag,
@@ -0,0 +1,335 @@
+//! A [`Transport`] for tests that answers from routes instead of the network.
+//!
+//! Routes match a method, or `*` for any, and a URL pattern in which `*`
+//! stands for any run of characters. The route added last wins, so a test
+//! can override a catch-all. A request no route matches gets a 404.
+//!
+//! Every request is recorded, matched or not, in the order the manager sent
+//! it, with its headers, body and priority. Requests answered from the HTTP
+//! cache never reach the transport and so are not recorded.
+//!
+//! ```ignore
+//! let mock = Arc::new(MockTransport::new());
+//! mock.route("GET", "http://shop.test/", MockResponse::ok("text/html", "<p>hi</p>"));
+//! mock.route_sequence(
+//!     "GET",
+//!     "http://shop.test/api/*",
+//!     vec![MockResponse::new(500), MockResponse::ok("application/json", "{}")],
+//! );
+//! let network = NetworkManager::with_transport(&config, mock.clone()).await?;
+//! ```
+
+use super::transport::{BodyStream, PreparedRequest, RawResponse, Transport};
+use super::{NetworkError, Priority, Result};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// How a [`MockResponse`] goes wrong.
+#[derive(Debug, Clone)]
+enum Fault {
+    /// No response at all.
+    Connection(String),
+    /// Something came back that is not HTTP.
+    Malformed(String),
+    /// The connection closes after this many body bytes.
+    CutOff(usize),
+}
+
+/// A canned response.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+    latency: Duration,
+    /// Body chunk size and the pause before each chunk after the first.
+    chunks: Option<(usize, Duration)>,
+    fault: Option<Fault>,
+}
+
+impl MockResponse {
+    /// An empty response with `status`.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: HashMap::new(),
+            body: Vec::new(),
+            latency: Duration::ZERO,
+            chunks: None,
+            fault: None,
+        }
+    }
+
+    /// A 200 with `body` of `content_type`.
+    pub fn ok(content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self::new(200)
+            .header("Content-Type", content_type)
+            .body(body)
+    }
+
+    /// The connection fails before any response, with `message`.
+    pub fn connection_error(message: &str) -> Self {
+        Self {
+            fault: Some(Fault::Connection(message.to_string())),
+            ..Self::new(0)
+        }
+    }
+
+    /// The server answers with something that does not parse as HTTP.
+    pub fn malformed(message: &str) -> Self {
+        Self {
+            fault: Some(Fault::Malformed(message.to_string())),
+            ..Self::new(0)
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Wait `latency` before the headers arrive.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Send the body `size` bytes at a time, `interval` apart.
+    pub fn chunked(mut self, size: usize, interval: Duration) -> Self {
+        self.chunks = Some((size.max(1), interval));
+        self
+    }
+
+    /// Close the connection once `bytes` of the body went out.
+    pub fn cut_off_after(mut self, bytes: usize) -> Self {
+        self.fault = Some(Fault::CutOff(bytes));
+        self
+    }
+}
+
+/// A request as the transport saw it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: Url,
+    pub headers: HashMap<String, String>,
+    pub body: Option<Vec<u8>>,
+    pub priority: Priority,
+}
+
+impl RecordedRequest {
+    /// The value of header `name`, whatever its case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+type Responder = Arc<dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync>;
+
+enum Reply {
+    Always(MockResponse),
+    Computed(Responder),
+    /// In turn, the last one repeating.
+    Sequence(Mutex<VecDeque<MockResponse>>),
+}
+
+struct Route {
+    method: String,
+    pattern: String,
+    reply: Reply,
+}
+
+impl Route {
+    fn matches(&self, request: &RecordedRequest) -> bool {
+        (self.method == "*" || self.method.eq_ignore_ascii_case(&request.method))
+            && glob_match(&self.pattern, request.url.as_str())
+    }
+
+    fn respond(&self, request: &RecordedRequest) -> MockResponse {
+        match &self.reply {
+            Reply::Always(response) => response.clone(),
+            Reply::Computed(responder) => responder(request),
+            Reply::Sequence(responses) => {
+                let mut responses = responses.lock();
+                match responses.len() {
+                    0 => MockResponse::new(404),
+                    1 => responses[0].clone(),
+                    _ => responses.pop_front().unwrap(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MockTransport {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `method` requests to URLs matching `pattern` with `response`.
+    pub fn route(&self, method: &str, pattern: &str, response: MockResponse) {
+        self.add(method, pattern, Reply::Always(response));
+    }
+
+    /// Answer matching requests with what `responder` makes of each.
+    pub fn route_fn(
+        &self,
+        method: &str,
+        pattern: &str,
+        responder: impl Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static,
+    ) {
+        self.add(method, pattern, Reply::Computed(Arc::new(responder)));
+    }
+
+    /// Answer matching requests with `responses` in turn, then keep
+    /// answering with the last, as for a server that fails and recovers.
+    pub fn route_sequence(&self, method: &str, pattern: &str, responses: Vec<MockResponse>) {
+        self.add(
+            method,
+            pattern,
+            Reply::Sequence(Mutex::new(responses.into())),
+        );
+    }
+
+    fn add(&self, method: &str, pattern: &str, reply: Reply) {
+        self.routes.lock().push(Route {
+            method: method.to_string(),
+            pattern: pattern.to_string(),
+            reply,
+        });
+    }
+
+    /// Every request so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().clone()
+    }
+
+    /// The requests so far to URLs matching `pattern`.
+    pub fn requests_to(&self, pattern: &str) -> Vec<RecordedRequest> {
+        self.requests
+            .lock()
+            .iter()
+            .filter(|request| glob_match(pattern, request.url.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.requests.lock().clear();
+    }
+
+    fn respond(&self, request: &RecordedRequest) -> MockResponse {
+        let routes = self.routes.lock();
+        match routes.iter().rev().find(|route| route.matches(request)) {
+            Some(route) => route.respond(request),
+            None => MockResponse::new(404),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn execute(&self, request: PreparedRequest) -> BoxFuture<'static, Result<RawResponse>> {
+        let recorded = RecordedRequest {
+            method: request.method,
+            url: request.url,
+            headers: request.headers,
+            body: request.body,
+            priority: request.priority,
+        };
+        let response = self.respond(&recorded);
+        let url = recorded.url.clone();
+        self.requests.lock().push(recorded);
+
+        async move {
+            if !response.latency.is_zero() {
+                tokio::time::sleep(response.latency).await;
+            }
+            let cut_off = match response.fault {
+                Some(Fault::Connection(message)) => return Err(NetworkError::Connection(message)),
+                Some(Fault::Malformed(message)) => {
+                    return Err(NetworkError::RequestFailed(format!(
+                        "Malformed response: {}",
+                        message
+                    )))
+                }
+                Some(Fault::CutOff(bytes)) => Some(bytes),
+                None => None,
+            };
+            Ok(RawResponse {
+                status: response.status,
+                url,
+                headers: response.headers,
+                tls: None,
+                body: body_stream(response.body, response.chunks, cut_off),
+            })
+        }
+        .boxed()
+    }
+}
+
+fn body_stream(
+    mut body: Vec<u8>,
+    chunks: Option<(usize, Duration)>,
+    cut_off: Option<usize>,
+) -> BodyStream {
+    let cut = cut_off.filter(|&bytes| bytes < body.len());
+    if let Some(bytes) = cut {
+        body.truncate(bytes);
+    }
+    let (size, interval) = chunks.unwrap_or((body.len().max(1), Duration::ZERO));
+    let mut pieces: Vec<Result<Vec<u8>>> =
+        body.chunks(size).map(|chunk| Ok(chunk.to_vec())).collect();
+    if cut.is_some() {
+        pieces.push(Err(NetworkError::RequestFailed(
+            "Failed to read body: connection closed before the body ended".to_string(),
+        )));
+    }
+    futures::stream::iter(pieces.into_iter().enumerate())
+        .then(move |(i, piece)| async move {
+            if i > 0 && !interval.is_zero() {
+                tokio::time::sleep(interval).await;
+            }
+            piece
+        })
+        .boxed()
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
@@ -1,18 +1,62 @@
+pub mod auth;
+pub mod beacon;
+pub mod blob;
+pub mod cookies;
+pub mod csp;
+pub mod disk_cache;
 pub mod fetch;
-
+#[cfg(feature = "test-util")]
+pub mod mock;
+pub mod page_resources;
+pub mod politeness;
+pub mod preload;
+pub mod retry;
+pub mod script_fetch;
+pub mod tls;
+pub mod transport;
+pub mod x509;
+
+pub use auth::{
+    AuthChallenge, AuthHandler, AuthManager, AuthScheme, AuthTarget, Credentials, MAX_AUTH_RETRIES,
+};
+pub use beacon::{BeaconQueue, RequestInitiator, BEACON_QUOTA_BYTES};
+pub use blob::{Blob, BlobStore};
+pub use cookies::{Cookie, CookieJar};
+pub use csp::ContentSecurityPolicy;
+pub use disk_cache::{DiskCache, DiskCacheConfig, DiskCacheEntry, DiskCacheStats};
 pub use fetch::FetchResponse;
+pub use page_resources::{
+    KeptBody, KeptResponse, PageResourceCounts, ResourceCount, ResourceLogEntry, ResourceType,
+    RESOURCE_LOG_CAPACITY,
+};
+pub use politeness::{PolitenessConfig, PolitenessController, RobotsDecision, RobotsRules};
+pub use preload::{
+    image_source, select_image_source, BodyObserver, Destination, Preload, PreloadScanner,
+    Preloader, Priority, SpeculativeFetches, DEFAULT_MAX_SPECULATIVE_FETCHES,
+};
+pub use retry::RetryConfig;
+pub use script_fetch::{
+    is_forbidden_request_header, ScriptFetches, SettledFetch, DEFAULT_MAX_SCRIPT_FETCHES,
+};
+pub use tls::{SecurityState, TlsConfig, TlsInfo, TlsVersion};
+pub use transport::{BodyStream, PreparedRequest, RawResponse, ReqwestTransport, Transport};
+pub use x509::{CertificateInfo, SubjectAltName};
 
 use dashmap::DashMap;
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
 use parking_lot::RwLock;
 use reqwest::{header::HeaderMap, Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::time::{timeout, Duration};
+use tokio::time::Duration;
 use url::Url;
 
 use crate::BrowserConfig;
+use page_resources::PageResources;
+use tls::{PageSecurity, TlsMonitor};
 
 #[derive(Error, Debug)]
 pub enum NetworkError {
@@ -34,6 +78,8 @@ pub enum NetworkError {
     Cache(String),
     #[error("Security policy violation: {0}")]
     SecurityPolicy(String),
+    #[error("Disallowed by robots.txt: {0}")]
+    RobotsDisallowed(String),
 }
 
 pub type Result<T> = std::result::Result<T, NetworkError>;
@@ -57,6 +103,8 @@ pub struct NetworkConfig {
     pub enable_connection_reuse: bool,
     pub tcp_nodelay: bool,
     pub socket_timeout_ms: u64,
+    /// How long shutdown waits for in-flight beacons before aborting them.
+    pub beacon_shutdown_grace_ms: u64,
 }
 
 impl Default for NetworkConfig {
@@ -79,6 +127,7 @@ impl Default for NetworkConfig {
             enable_connection_reuse: true,
             tcp_nodelay: true,
             socket_timeout_ms: 5000,
+            beacon_shutdown_grace_ms: 2000,
         }
     }
 }
@@ -96,6 +145,34 @@ pub struct CachePolicy {
     pub stale_if_error: Option<u64>,
 }
 
+impl CachePolicy {
+    /// Apply a response's `Cache-Control` on top of the request's policy. The
+    /// restrictive flags only ever get switched on.
+    pub fn merge_cache_control(&mut self, header: &str) {
+        for directive in header.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value.and_then(|value| value.parse::<u64>().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => self.max_age = seconds.or(self.max_age),
+                "must-revalidate" => self.must_revalidate = true,
+                "no-cache" => self.no_cache = true,
+                "no-store" => self.no_store = true,
+                "private" => self.private = true,
+                "public" => self.public = true,
+                "immutable" => self.immutable = true,
+                "stale-while-revalidate" => {
+                    self.stale_while_revalidate = seconds.or(self.stale_while_revalidate)
+                }
+                "stale-if-error" => self.stale_if_error = seconds.or(self.stale_if_error),
+                _ => {}
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
     pub data: Vec<u8>,
@@ -252,36 +329,62 @@ pub struct CacheStats {
 
 pub struct ConnectionPool {
     clients: Arc<DashMap<String, Client>>,
+    /// Sent in place of the configured user agent once set. Clients are
+    /// built holding it, so none is pooled with one it replaced.
+    user_agent: RwLock<Option<String>>,
     max_connections_per_host: usize,
     #[allow(dead_code)]
     connection_timeout: Duration,
+    tls: Option<Arc<TlsMonitor>>,
 }
 
 impl ConnectionPool {
     pub fn new(max_connections_per_host: usize, connection_timeout: Duration) -> Self {
         Self {
             clients: Arc::new(DashMap::new()),
+            user_agent: RwLock::new(None),
             max_connections_per_host,
             connection_timeout,
+            tls: None,
         }
     }
 
+    /// Verify certificates through `tls`, which then knows what each
+    /// response's connection was.
+    pub(crate) fn with_tls(mut self, tls: Arc<TlsMonitor>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
     pub fn get_client(&self, host: &str, config: &NetworkConfig) -> Result<Client> {
+        let user_agent = self.user_agent.read();
         if let Some(client) = self.clients.get(host) {
             return Ok(client.clone());
         }
 
-        let client = ClientBuilder::new()
+        let mut builder = ClientBuilder::new()
             .timeout(Duration::from_millis(config.request_timeout_ms))
             .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
             .pool_max_idle_per_host(self.max_connections_per_host)
             .pool_idle_timeout(Duration::from_secs(config.keep_alive_timeout_s))
-            .user_agent(&config.user_agent)
+            .user_agent(user_agent.as_deref().unwrap_or(&config.user_agent))
             .gzip(config.enable_gzip)
             .brotli(config.enable_brotli)
-            .http2_prior_knowledge()
             .tcp_nodelay(config.tcp_nodelay)
-            .redirect(reqwest::redirect::Policy::limited(config.max_redirects))
+            .redirect(reqwest::redirect::Policy::none());
+
+        // HTTP/2 is negotiated via ALPN; prior knowledge would break every
+        // HTTP/1.1-only server.
+        if !config.enable_http2 {
+            builder = builder.http1_only();
+        }
+        if let Some(tls) = &self.tls {
+            builder = builder
+                .use_preconfigured_tls(tls.client_config(config.enable_http2))
+                .tls_info(true);
+        }
+
+        let client = builder
             .build()
             .map_err(|e| NetworkError::Connection(e.to_string()))?;
 
@@ -293,6 +396,19 @@ impl ConnectionPool {
         self.clients.remove(host);
     }
 
+    /// Build clients with `user_agent` from now on, dropping those built
+    /// with another.
+    pub fn set_user_agent(&self, user_agent: &str) {
+        let mut current = self.user_agent.write();
+        *current = Some(user_agent.to_string());
+        self.clients.clear();
+    }
+
+    /// The user agent set in place of the configured one, if any.
+    pub fn user_agent(&self) -> Option<String> {
+        self.user_agent.read().clone()
+    }
+
     pub fn clear(&self) {
         self.clients.clear();
     }
@@ -382,6 +498,22 @@ pub struct NetworkMetrics {
     pub ssl_handshake_time_ms: f64,
     pub first_byte_time_ms: f64,
     pub active_connections: usize,
+    pub throttled_requests: u64,
+    pub robots_denied_requests: u64,
+    /// Preload scanner fetches started.
+    pub speculative_fetches_issued: u64,
+    /// Speculative fetches a document request picked up.
+    pub speculative_fetches_used: u64,
+    /// Speculative fetches nothing asked for before the page went away.
+    pub speculative_fetches_wasted: u64,
+    /// Requests sent again after a transient failure, and how many times in
+    /// all.
+    pub retried_requests: u64,
+    pub retries: u64,
+    /// Hyperlink auditing pings queued, and those a security policy or CSP
+    /// refused.
+    pub pings_sent: u64,
+    pub pings_blocked: u64,
 }
 
 impl Default for NetworkMetrics {
@@ -400,6 +532,15 @@ impl Default for NetworkMetrics {
             ssl_handshake_time_ms: 0.0,
             first_byte_time_ms: 0.0,
             active_connections: 0,
+            throttled_requests: 0,
+            robots_denied_requests: 0,
+            speculative_fetches_issued: 0,
+            speculative_fetches_used: 0,
+            speculative_fetches_wasted: 0,
+            retried_requests: 0,
+            retries: 0,
+            pings_sent: 0,
+            pings_blocked: 0,
         }
     }
 }
@@ -528,18 +669,57 @@ impl SecurityPolicy {
 pub struct NetworkManager {
     config: NetworkConfig,
     http_cache: Arc<HttpCache>,
+    disk_cache: Option<Arc<DiskCache>>,
     connection_pool: Arc<ConnectionPool>,
     dns_cache: Arc<DnsCache>,
     request_limiter: Arc<RequestLimiter>,
-    security_policy: Arc<SecurityPolicy>,
+    security_policy: Arc<RwLock<SecurityPolicy>>,
     metrics: Arc<RwLock<NetworkMetrics>>,
     active_requests: Arc<DashMap<String, tokio::sync::oneshot::Sender<()>>>,
+    politeness: Arc<PolitenessController>,
+    beacons: Arc<BeaconQueue>,
+    auth: Arc<AuthManager>,
+    cookies: Arc<CookieJar>,
+    retry: RetryConfig,
+    speculative: SpeculativeFetches,
+    tls: Arc<TlsMonitor>,
+    page_security: PageSecurity,
+    page_resources: PageResources,
+    blobs: Arc<BlobStore>,
+    transport: Arc<dyn Transport>,
 }
 
 impl NetworkManager {
     pub async fn new(browser_config: &BrowserConfig) -> Result<Self> {
+        Self::build(browser_config, None, |transport| transport)
+    }
+
+    /// A manager whose requests go through `transport` rather than the
+    /// network, such as a test's mock.
+    pub async fn with_transport(
+        browser_config: &BrowserConfig,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Self> {
+        Self::build(browser_config, Some(transport), |transport| transport)
+    }
+
+    /// A manager whose requests go through what `wrap` makes of the
+    /// network, such as a transport that records them.
+    pub async fn wrapping_transport(
+        browser_config: &BrowserConfig,
+        wrap: impl FnOnce(Arc<dyn Transport>) -> Arc<dyn Transport>,
+    ) -> Result<Self> {
+        Self::build(browser_config, None, wrap)
+    }
+
+    fn build(
+        browser_config: &BrowserConfig,
+        transport: Option<Arc<dyn Transport>>,
+        wrap: impl FnOnce(Arc<dyn Transport>) -> Arc<dyn Transport>,
+    ) -> Result<Self> {
         let config = NetworkConfig {
             user_agent: browser_config.user_agent.clone(),
+            max_response_size_mb: browser_config.max_response_size_mb,
             max_concurrent_requests: if browser_config.max_processes > 0 {
                 browser_config.max_processes * 10
             } else {
@@ -553,29 +733,102 @@ impl NetworkManager {
             10000,            // Max 10k entries
         ));
 
-        let connection_pool = Arc::new(ConnectionPool::new(
-            config.connection_pool_size,
-            Duration::from_millis(config.connect_timeout_ms),
-        ));
+        let tls = Arc::new(TlsMonitor::new(browser_config.tls.clone()));
+        let connection_pool = Arc::new(
+            ConnectionPool::new(
+                config.connection_pool_size,
+                Duration::from_millis(config.connect_timeout_ms),
+            )
+            .with_tls(tls.clone()),
+        );
 
         let dns_cache = Arc::new(DnsCache::new(Duration::from_secs(config.dns_cache_ttl_s)));
 
         let request_limiter = Arc::new(RequestLimiter::new(config.max_concurrent_requests));
+        let transport = wrap(transport.unwrap_or_else(|| {
+            Arc::new(ReqwestTransport::new(
+                connection_pool.clone(),
+                tls.clone(),
+                config.clone(),
+            ))
+        }));
 
         Ok(Self {
             config,
             http_cache,
+            // Private mode keeps the HTTP cache in memory only.
+            disk_cache: if browser_config.private_mode {
+                None
+            } else {
+                DiskCache::from_config(&browser_config.disk_cache).map(Arc::new)
+            },
             connection_pool,
             dns_cache,
             request_limiter,
-            security_policy: Arc::new(SecurityPolicy::default()),
+            security_policy: Arc::new(RwLock::new(SecurityPolicy::default())),
             metrics: Arc::new(RwLock::new(NetworkMetrics::default())),
             active_requests: Arc::new(DashMap::new()),
+            politeness: Arc::new(PolitenessController::new(browser_config.politeness.clone())),
+            beacons: Arc::new(BeaconQueue::new()),
+            auth: Arc::new(AuthManager::new()),
+            cookies: Arc::new(CookieJar::new()),
+            retry: browser_config.network_retry.clone(),
+            speculative: SpeculativeFetches::new(browser_config.max_speculative_fetches),
+            tls,
+            page_security: PageSecurity::new(),
+            page_resources: PageResources::keeping_bodies(
+                browser_config.page_archive.max_resource_bytes,
+                browser_config.page_archive.max_archive_bytes,
+            ),
+            blobs: Arc::new(BlobStore::new()),
+            transport,
         })
     }
 
+    /// The same network stack for another page, such as one prerendered
+    /// beside the current one. Caches, connections, credentials, cookies,
+    /// beacons and metrics are shared; the security state, speculative fetches,
+    /// `blob:` URLs and subresource counts are the new page's own.
+    pub fn for_other_page(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            http_cache: self.http_cache.clone(),
+            disk_cache: self.disk_cache.clone(),
+            connection_pool: self.connection_pool.clone(),
+            dns_cache: self.dns_cache.clone(),
+            request_limiter: self.request_limiter.clone(),
+            security_policy: self.security_policy.clone(),
+            metrics: self.metrics.clone(),
+            active_requests: self.active_requests.clone(),
+            politeness: self.politeness.clone(),
+            beacons: self.beacons.clone(),
+            auth: self.auth.clone(),
+            cookies: self.cookies.clone(),
+            retry: self.retry.clone(),
+            speculative: self.speculative.for_other_page(),
+            tls: self.tls.clone(),
+            page_security: PageSecurity::new(),
+            page_resources: self.page_resources.for_other_page(),
+            blobs: Arc::new(BlobStore::new()),
+            transport: self.transport.clone(),
+        }
+    }
+
     pub async fn fetch(&self, url: &str) -> Result<String> {
-        let request = FetchRequest {
+        let response = self.fetch_response(url).await?;
+        String::from_utf8(response.body)
+            .map_err(|e| NetworkError::Protocol(format!("Invalid UTF-8: {}", e)))
+    }
+
+    /// Plain GET through cache and policy, returning the undecoded response.
+    /// Authentication challenges are answered from stored credentials only.
+    pub async fn fetch_response(&self, url: &str) -> Result<FetchResponse> {
+        self.fetch_authenticated(self.get_request(url), false, None)
+            .await
+    }
+
+    fn get_request(&self, url: &str) -> FetchRequest {
+        FetchRequest {
             url: url.to_string(),
             method: "GET".to_string(),
             headers: HashMap::new(),
@@ -583,26 +836,359 @@ impl NetworkManager {
             timeout_ms: Some(self.config.request_timeout_ms),
             follow_redirects: true,
             cache_policy: Some(CachePolicy::default()),
+            priority: Priority::High,
+            idempotent: false,
+        }
+    }
+
+    /// Fetch a subresource for the document described by `initiator`. Only
+    /// same-origin requests may prompt the embedder for credentials; a
+    /// cross-origin challenge nothing is stored for just fails, so a page
+    /// cannot pop a login prompt on behalf of another site.
+    ///
+    /// A plain GET the preload scanner already started takes that fetch's
+    /// response, waiting for it if need be. One that failed, or met a
+    /// challenge it could not prompt for, is made again.
+    pub async fn fetch_subresource(
+        &self,
+        request: FetchRequest,
+        initiator: &RequestInitiator,
+    ) -> Result<FetchResponse> {
+        let prompt = Url::parse(&request.url)
+            .map(|url| initiator.is_same_origin(&url))
+            .unwrap_or(false);
+        // A blob: URL's origin is the one that registered it.
+        if request.url.starts_with("blob:") && !prompt {
+            return Err(NetworkError::SecurityPolicy(format!(
+                "{} belongs to another origin",
+                request.url
+            )));
+        }
+        let plain_get =
+            request.method == "GET" && request.headers.is_empty() && request.body.is_none();
+        if let Some(speculative) = plain_get
+            .then(|| self.speculative.take(&request.url))
+            .flatten()
+        {
+            self.metrics.write().speculative_fetches_used += 1;
+            match speculative.await {
+                Ok(response) if AuthTarget::from_status(response.status).is_none() || !prompt => {
+                    return Ok(response)
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("Speculative fetch of {} failed: {}", request.url, e),
+            }
+        }
+        self.fetch_authenticated(request, prompt, None).await
+    }
+
+    /// Start fetching `preload` for the document `initiator` describes,
+    /// ahead of it being needed. Does nothing for a URL started already
+    /// this page, past the page's cap, or when the document's CSP would
+    /// refuse the request. Low-priority fetches queue for a few slots of
+    /// their own. Responses also go into the HTTP cache.
+    pub fn preload(self: &Arc<Self>, preload: Preload, initiator: &RequestInitiator) -> bool {
+        if !preload.is_allowed_by(initiator) {
+            return false;
+        }
+        let url = preload.url.to_string();
+        let start = || {
+            let network = self.clone();
+            let request = FetchRequest {
+                priority: preload.destination.priority(),
+                ..self.get_request(&url)
+            };
+            let low_priority = (preload.destination.priority() == Priority::Low)
+                .then(|| self.speculative.low_priority());
+            async move {
+                let _permit = match &low_priority {
+                    Some(slots) => slots.acquire().await.ok(),
+                    None => None,
+                };
+                // Speculation never prompts for credentials.
+                network
+                    .fetch_authenticated(request, false, None)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            .boxed()
+            .shared()
+        };
+        let fetch = match self.speculative.start(&url, start) {
+            Some(fetch) => fetch,
+            None => return false,
         };
+        self.metrics.write().speculative_fetches_issued += 1;
+        tokio::spawn(fetch.map(drop));
+        true
+    }
 
-        let response = self.fetch_with_request(request).await?;
-        String::from_utf8(response.body)
-            .map_err(|e| NetworkError::Protocol(format!("Invalid UTF-8: {}", e)))
+    /// What `blob:` URLs resolve to.
+    pub fn blob_store(&self) -> &Arc<BlobStore> {
+        &self.blobs
+    }
+
+    /// A new page: speculative fetches of the last one that were never
+    /// picked up count as wasted, the cap starts over, the security state
+    /// waits for the new document, the last one's `blob:` URLs are revoked
+    /// and its subresource counts start over.
+    pub fn begin_page(&self) {
+        self.blobs.clear();
+        self.page_resources.begin();
+        let wasted = self.speculative.reset();
+        self.metrics.write().speculative_fetches_wasted += wasted as u64;
+        self.page_security.begin();
+    }
+
+    /// What the current page fetched besides its document, by type.
+    pub fn page_resource_counts(&self) -> PageResourceCounts {
+        self.page_resources.counts()
+    }
+
+    /// The response the current page got to its request for `url`, body
+    /// and all as far as the page archive caps keep it. `None` when it
+    /// asked for no such thing or got no response.
+    pub fn page_response(&self, url: &str) -> Option<KeptResponse> {
+        self.page_resources.kept(url)
+    }
+
+    /// The last subresources fetched and pings sent, across pages, oldest
+    /// first.
+    pub fn resource_log(&self) -> Vec<ResourceLogEntry> {
+        self.page_resources.log_entries()
+    }
+
+    /// The current page's security state, from its document and every
+    /// response since.
+    pub fn security_state(&self) -> SecurityState {
+        self.page_security.state()
+    }
+
+    /// The security state, when it changed since this last returned it.
+    pub fn take_security_state_change(&self) -> Option<SecurityState> {
+        self.page_security.take_change()
+    }
+
+    /// Certificate pin failures since the last call, as descriptions.
+    pub fn take_security_violations(&self) -> Vec<String> {
+        self.tls.take_violations()
+    }
+
+    /// Install (or remove) the embedder's credential prompt.
+    pub fn set_auth_handler(&self, handler: Option<AuthHandler>) {
+        self.auth.set_handler(handler);
+    }
+
+    /// Forget every credential validated this session.
+    pub fn clear_credentials(&self) {
+        self.auth.clear();
+    }
+
+    /// The cookies a request to `url` would carry, `HttpOnly` ones included.
+    pub fn get_cookies(&self, url: &str) -> Result<Vec<Cookie>> {
+        let url = Url::parse(url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+        self.security_policy.read().check_url(&url)?;
+        Ok(self.cookies.cookies_for(&url))
+    }
+
+    /// Store `cookie`, in `Set-Cookie` syntax, as if a response from `url`
+    /// had set it. Returns whether it was accepted.
+    pub fn set_cookie(&self, url: &str, cookie: &str) -> Result<bool> {
+        let url = Url::parse(url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+        self.security_policy.read().check_url(&url)?;
+        Ok(self.cookies.set_cookie(&url, cookie))
+    }
+
+    pub fn clear_cookies(&self) {
+        self.cookies.clear();
+    }
+
+    /// The cookie jar, to save with [`CookieJar::to_json`] and restore with
+    /// [`CookieJar::load_json`].
+    pub fn cookie_jar(&self) -> &CookieJar {
+        &self.cookies
+    }
+
+    /// Add the `Cookie` header a request to `url` carries, unless the
+    /// request has its own.
+    fn redirect_follower(&self) -> RedirectFollower {
+        RedirectFollower {
+            transport: self.transport.clone(),
+            cookies: self.cookies.clone(),
+            security_policy: self.security_policy.clone(),
+            metrics: self.metrics.clone(),
+            max_redirects: self.config.max_redirects,
+        }
+    }
+
+    /// `fetch_with_request`, answering 401/407 challenges: stored credentials
+    /// first, then the embedder prompt when `prompt` is set. Gives up after
+    /// [`MAX_AUTH_RETRIES`] retries and returns the last challenge response.
+    async fn fetch_authenticated(
+        &self,
+        mut request: FetchRequest,
+        prompt: bool,
+        mut observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let mut retries = 0;
+        // Credentials sent with the current attempt and where they came from.
+        let mut attempt: Option<(auth::AuthKey, Credentials)> = None;
+        loop {
+            let response = self
+                .fetch_observed(
+                    request.clone(),
+                    observer
+                        .as_deref_mut()
+                        .map(|observer| observer as &mut dyn BodyObserver),
+                )
+                .await?;
+            let target = match AuthTarget::from_status(response.status) {
+                Some(target) => target,
+                None => {
+                    if let Some((key, credentials)) = attempt {
+                        self.auth.store(key, credentials);
+                    }
+                    return Ok(response);
+                }
+            };
+            if let Some((key, _)) = attempt.take() {
+                self.auth.forget(&key);
+            }
+            if retries == MAX_AUTH_RETRIES {
+                return Ok(response);
+            }
+
+            let url = Url::parse(&response.url)
+                .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+            let challenge = match AuthChallenge::from_response(&url, target, &response.headers) {
+                Some(challenge) => challenge,
+                None => return Ok(response),
+            };
+            let key = challenge.key();
+            let credentials = match self.auth.lookup(&key) {
+                Some(credentials) => credentials,
+                None if prompt => match self.auth.prompt(challenge).await {
+                    Some(credentials) => credentials,
+                    None => return Ok(response),
+                },
+                None => return Ok(response),
+            };
+
+            let header = target.authorization_header();
+            request
+                .headers
+                .retain(|name, _| !name.eq_ignore_ascii_case(header));
+            request
+                .headers
+                .insert(header.to_string(), credentials.header_value());
+            attempt = Some((key, credentials));
+            retries += 1;
+        }
     }
 
     pub async fn fetch_with_request(&self, request: FetchRequest) -> Result<FetchResponse> {
-        let request_id = uuid::Uuid::new_v4().to_string();
-        let start_time = std::time::Instant::now();
+        self.fetch_observed(request, None).await
+    }
 
-        // Acquire request limiter permit
-        let _permit = self.request_limiter.acquire().await;
+    /// `fetch_with_request`, returning the request's id along with it so
+    /// that it can be stopped with `cancel_request` while under way.
+    pub fn fetch_with_handle(&self, request: FetchRequest) -> RequestHandle<'_> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        // Registered now, so a cancel before the fetch is polled still counts.
+        self.active_requests.insert(id.clone(), cancel_tx);
+        let active = ActiveRequest {
+            requests: self.active_requests.clone(),
+            id: id.clone(),
+        };
+        RequestHandle {
+            id,
+            future: self.fetch_tracked(request, active, cancel_rx, None).boxed(),
+        }
+    }
+
+    /// `fetch_with_request`, handing a successful response's body to `sink`
+    /// chunk by chunk as it arrives instead of keeping it; the response
+    /// comes back without its body. A body past `max_response_size_mb`
+    /// fails the fetch as soon as it gets there, or before any of it is
+    /// read when its `Content-Length` says so.
+    pub async fn fetch_streaming(
+        &self,
+        request: FetchRequest,
+        sink: impl FnMut(&[u8]) + Send,
+    ) -> Result<FetchResponse> {
+        let mut sink = StreamSink { sink };
+        let response = self.fetch_observed(request, Some(&mut sink)).await?;
+        Ok(FetchResponse {
+            body: if (200..300).contains(&response.status) {
+                Vec::new()
+            } else {
+                response.body
+            },
+            ..response
+        })
+    }
+
+    /// `fetch_with_request`, showing `observer` the body of a successful
+    /// response as it arrives; a cached one arrives in one chunk.
+    async fn fetch_observed(
+        &self,
+        request: FetchRequest,
+        observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        self.active_requests.insert(id.clone(), cancel_tx);
+        let active = ActiveRequest {
+            requests: self.active_requests.clone(),
+            id,
+        };
+        self.fetch_tracked(request, active, cancel_rx, observer)
+            .await
+    }
+
+    /// `fetch_observed` for a request registered in `active_requests` as
+    /// `_active`, which `cancel_rx` hears the cancellation of.
+    async fn fetch_tracked(
+        &self,
+        mut request: FetchRequest,
+        _active: ActiveRequest,
+        mut cancel_rx: tokio::sync::oneshot::Receiver<()>,
+        mut observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let start_time = std::time::Instant::now();
 
         // Parse URL
         let url = Url::parse(&request.url)
             .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
 
+        if url.scheme() == "blob" {
+            return self.blobs.fetch(&request.url, &request.method);
+        }
+        if url.scheme() == "data" {
+            return data_url_response(&url, &request.method);
+        }
+
         // Check security policy
-        self.security_policy.check_url(&url)?;
+        self.security_policy.read().check_url(&url)?;
+
+        // Crawler politeness runs before taking a limiter permit so a throttled
+        // host never starves requests to other hosts.
+        if self.politeness.is_enabled() {
+            self.enforce_robots(&url).await?;
+            if self
+                .politeness
+                .throttle_request(url.host_str().unwrap_or(""))
+                .await
+            {
+                self.metrics.write().throttled_requests += 1;
+            }
+        }
+
+        // Acquire request limiter permit
+        let _permit = self.request_limiter.acquire().await;
 
         // Update metrics
         {
@@ -611,14 +1197,17 @@ impl NetworkManager {
         }
 
         // Check cache first
+        let mut revalidating = None;
         if let Some(cache_policy) = &request.cache_policy {
             if !cache_policy.no_cache {
-                if let Some(cached_response) = self.get_cached_response(&request.url) {
+                if let Some(cached_response) =
+                    self.get_cached_response(&request.url, &request.headers)
+                {
                     if !cached_response.is_stale() || cached_response.can_serve_stale() {
-                        let mut metrics = self.metrics.write();
-                        metrics.cache_hits += 1;
+                        self.metrics.write().cache_hits += 1;
+                        let request_url = request.url.clone();
 
-                        return Ok(FetchResponse {
+                        let response = FetchResponse {
                             status: 200,
                             headers: cached_response
                                 .headers
@@ -628,11 +1217,28 @@ impl NetworkManager {
                             body: cached_response.data,
                             url: request.url,
                             redirected: true,
-                        });
+                            tls: None,
+                            retries: 0,
+                        };
+                        if let Some(observer) = observer {
+                            observer.start(&url, &response.headers);
+                            observer.chunk(&response.body);
+                        }
+                        self.page_security.loaded(&response);
+                        self.page_resources.loaded(&request_url, &response, true);
+                        return Ok(response);
+                    }
+                    // A stale entry the server can vouch for is revalidated
+                    // rather than fetched again.
+                    if cached_response.etag.is_some() || cached_response.last_modified.is_some() {
+                        revalidating = Some(cached_response);
                     }
                 }
             }
         }
+        if let Some(entry) = &revalidating {
+            add_validators(&mut request.headers, entry);
+        }
 
         // Cache miss
         {
@@ -640,15 +1246,37 @@ impl NetworkManager {
             metrics.cache_misses += 1;
         }
 
-        // Create cancellation token for this request
-        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
-        self.active_requests.insert(request_id.clone(), cancel_tx);
+        let request_url = request.url.clone();
+        let request_headers = request.headers.clone();
+        let cache_policy = request.cache_policy.clone();
 
         // Perform the actual request
-        let result = self.perform_request(request, cancel_rx).await;
-
-        // Clean up
-        self.active_requests.remove(&request_id);
+        let result = self
+            .perform_with_retries(
+                request,
+                &mut cancel_rx,
+                observer
+                    .as_deref_mut()
+                    .map(|observer| observer as &mut dyn BodyObserver),
+            )
+            .await;
+        let (result, revalidated) = match (result, revalidating) {
+            (Ok(response), Some(entry)) if response.status == 304 => {
+                let response = self.revalidated(
+                    &request_url,
+                    entry,
+                    response,
+                    &request_headers,
+                    cache_policy.unwrap_or_default(),
+                );
+                if let Some(observer) = observer {
+                    observer.start(&url, &response.headers);
+                    observer.chunk(&response.body);
+                }
+                (Ok(response), true)
+            }
+            (result, _) => (result, false),
+        };
 
         // Update metrics
         let request_time = start_time.elapsed();
@@ -657,7 +1285,12 @@ impl NetworkManager {
             match &result {
                 Ok(response) => {
                     metrics.successful_requests += 1;
-                    metrics.total_bytes_downloaded += response.body.len() as u64;
+                    if !revalidated {
+                        metrics.total_bytes_downloaded += response.body.len() as u64;
+                    }
+                    self.page_security.loaded(response);
+                    self.page_resources
+                        .loaded(&request_url, response, revalidated);
                 }
                 Err(_) => {
                     metrics.failed_requests += 1;
@@ -675,130 +1308,575 @@ impl NetworkManager {
         result
     }
 
-    async fn perform_request(
+    /// Fetch a top-level document. On top of `fetch_response`, this holds a
+    /// navigation slot and spaces navigations to the same host when politeness
+    /// is enabled, and may prompt the embedder for credentials.
+    pub async fn fetch_navigation(&self, url: &str) -> Result<FetchResponse> {
+        self.navigate(url, None).await
+    }
+
+    /// `fetch_navigation`, showing `observer` the document as it downloads.
+    pub async fn fetch_navigation_observed(
         &self,
-        request: FetchRequest,
-        mut cancel_rx: tokio::sync::oneshot::Receiver<()>,
+        url: &str,
+        observer: &mut dyn BodyObserver,
     ) -> Result<FetchResponse> {
-        let url = Url::parse(&request.url)
+        self.navigate(url, Some(observer)).await
+    }
+
+    /// `fetch_navigation_observed`, returning an id along with it that
+    /// `cancel_request` stops the whole navigation fetch by, including the
+    /// requests it makes to answer auth challenges.
+    pub fn fetch_navigation_with_handle<'a>(
+        &'a self,
+        url: &'a str,
+        observer: &'a mut dyn BodyObserver,
+    ) -> RequestHandle<'a> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+        // Registered now, so a cancel before the fetch is polled still counts.
+        self.active_requests.insert(id.clone(), cancel_tx);
+        let active = ActiveRequest {
+            requests: self.active_requests.clone(),
+            id: id.clone(),
+        };
+        RequestHandle {
+            id,
+            future: async move {
+                let _active = active;
+                tokio::select! {
+                    _ = cancel_rx => {
+                        Err(NetworkError::RequestFailed("Request cancelled".to_string()))
+                    }
+                    response = self.navigate(url, Some(observer)) => response,
+                }
+            }
+            .boxed(),
+        }
+    }
+
+    async fn navigate(
+        &self,
+        url: &str,
+        observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let host = Url::parse(url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?
+            .host_str()
+            .unwrap_or("")
+            .to_string();
+
+        let (_navigation_permit, waited) = self.politeness.begin_navigation(&host).await;
+        if waited {
+            self.metrics.write().throttled_requests += 1;
+        }
+
+        self.page_resources.expect_document(url);
+        let response = self
+            .fetch_authenticated(self.get_request(url), true, observer)
+            .await;
+        self.page_resources.document_loaded();
+        let response = response?;
+        self.page_security.document_loaded(&response);
+        Ok(response)
+    }
+
+    /// What the origin's robots.txt says about `url` for the configured product
+    /// token. Fetches and caches robots.txt per origin; an unreachable or
+    /// non-2xx robots.txt allows everything.
+    pub async fn robots_decision(&self, url: &str) -> Result<RobotsDecision> {
+        let url = Url::parse(url)
             .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+        let rules = self.robots_rules_for(&url).await;
 
-        let host = url.host_str().unwrap_or("localhost");
-        let client = self.connection_pool.get_client(host, &self.config)?;
-
-        let mut req_builder = match request.method.as_str() {
-            "GET" => client.get(&request.url),
-            "POST" => client.post(&request.url),
-            "PUT" => client.put(&request.url),
-            "DELETE" => client.delete(&request.url),
-            "HEAD" => client.head(&request.url),
-            "PATCH" => client.patch(&request.url),
-            _ => {
-                return Err(NetworkError::RequestFailed(format!(
-                    "Unsupported method: {}",
-                    request.method
-                )))
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        Ok(rules.decide(&self.politeness.config().robots_user_agent, &path))
+    }
+
+    /// `navigator.sendBeacon`: queue a POST of `body` that outlives the
+    /// initiating document. `Ok(false)` means the origin's keepalive quota is
+    /// exhausted and nothing was sent.
+    pub fn send_beacon(
+        &self,
+        url: &str,
+        body: Vec<u8>,
+        content_type: Option<&str>,
+        initiator: &RequestInitiator,
+    ) -> Result<bool> {
+        let mut headers = HashMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert("Content-Type".to_string(), content_type.to_string());
+        }
+
+        self.send_keepalive(
+            FetchRequest {
+                url: url.to_string(),
+                method: "POST".to_string(),
+                headers,
+                body: Some(body),
+                timeout_ms: Some(self.config.request_timeout_ms),
+                follow_redirects: true,
+                cache_policy: None,
+                priority: Priority::Low,
+                idempotent: false,
+            },
+            initiator,
+        )
+    }
+
+    /// The hyperlink auditing ping for following a link to `target`: a POST
+    /// of `PING` to `ping` on the keepalive queue, so it neither holds up
+    /// nor dies with the navigation. `Ok(false)` means the quota is
+    /// exhausted and nothing was sent.
+    pub fn send_ping(
+        &self,
+        ping: &Url,
+        target: &Url,
+        initiator: &RequestInitiator,
+    ) -> Result<bool> {
+        let sent = self.send_keepalive(
+            FetchRequest {
+                url: ping.to_string(),
+                method: "POST".to_string(),
+                headers: initiator.ping_headers(ping, target),
+                body: Some(b"PING".to_vec()),
+                timeout_ms: Some(self.config.request_timeout_ms),
+                follow_redirects: true,
+                cache_policy: None,
+                priority: Priority::Low,
+                idempotent: false,
+            },
+            initiator,
+        );
+        match &sent {
+            Ok(true) => {
+                self.metrics.write().pings_sent += 1;
+                self.page_resources.pinged(ping.as_str());
             }
-        };
+            Err(NetworkError::SecurityPolicy(_)) => self.metrics.write().pings_blocked += 1,
+            _ => {}
+        }
+        sent
+    }
+
+    /// Queue `request` on the keepalive queue. It passes the same security
+    /// policy and CSP `connect-src` checks as any fetch and its redirects are
+    /// followed as a fetch's are, every hop checked against the policy, but
+    /// it is not tracked in `active_requests` and so survives
+    /// `cancel_all_requests`. Only `shutdown` stops it, after a grace period.
+    pub fn send_keepalive(
+        &self,
+        request: FetchRequest,
+        initiator: &RequestInitiator,
+    ) -> Result<bool> {
+        let url = initiator
+            .document_url
+            .join(&request.url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
 
-        // Add headers
-        for (key, value) in request.headers {
-            req_builder = req_builder.header(&key, &value);
+        self.security_policy.read().check_url(&url)?;
+        if !initiator.allows_connect(&url) {
+            return Err(NetworkError::SecurityPolicy(format!(
+                "Refused to connect to '{}' (Content-Security-Policy connect-src)",
+                url
+            )));
         }
 
-        // Add body if present
-        if let Some(body) = request.body {
-            req_builder = req_builder.body(body);
+        let size = request.body.as_ref().map_or(0, Vec::len);
+        let origin = initiator.origin_key();
+        if !self.beacons.try_reserve(&origin, size) {
+            return Ok(false);
         }
 
-        // Set timeout
         let timeout_duration =
             Duration::from_millis(request.timeout_ms.unwrap_or(self.config.request_timeout_ms));
+        let follower = self.redirect_follower();
+        let beacons = self.beacons.clone();
+        let metrics = self.metrics.clone();
+        self.metrics.write().total_requests += 1;
+
+        let spawned = self.beacons.spawn({
+            let origin = origin.clone();
+            async move {
+                // Keep the sender alive: only `shutdown` stops the request.
+                let (_cancel_tx, mut cancel_rx) = tokio::sync::oneshot::channel();
+                let deadline = tokio::time::Instant::now() + timeout_duration;
+                let result = follower.send(url, &request, deadline, &mut cancel_rx).await;
+                beacons.release(&origin, size);
+
+                let mut metrics = metrics.write();
+                match result {
+                    Ok(_) => metrics.successful_requests += 1,
+                    Err(e) => {
+                        metrics.failed_requests += 1;
+                        tracing::debug!("keepalive request failed: {}", e);
+                    }
+                }
+            }
+        });
+
+        if !spawned {
+            self.beacons.release(&origin, size);
+            return Err(NetworkError::RequestFailed(
+                "Keepalive requests need a Tokio runtime".to_string(),
+            ));
+        }
+        Ok(true)
+    }
+
+    /// Keepalive request bytes currently in flight for `initiator`'s origin.
+    pub fn keepalive_bytes_in_flight(&self, initiator: &RequestInitiator) -> usize {
+        self.beacons.in_flight_bytes(&initiator.origin_key())
+    }
+
+    async fn robots_rules_for(&self, url: &Url) -> Arc<RobotsRules> {
+        let origin = url.origin().ascii_serialization();
+        if let Some(rules) = self.politeness.cached_robots(&origin) {
+            return rules;
+        }
+
+        let robots_url = format!("{}/robots.txt", origin);
+        self.politeness
+            .throttle_request(url.host_str().unwrap_or(""))
+            .await;
+
+        // Keep the sender alive so the request is not treated as cancelled.
+        let (_cancel_tx, mut cancel_rx) = tokio::sync::oneshot::channel();
+        let request = FetchRequest {
+            url: robots_url,
+            method: "GET".to_string(),
+            headers: HashMap::new(),
+            body: None,
+            timeout_ms: Some(self.config.request_timeout_ms),
+            follow_redirects: true,
+            cache_policy: None,
+            priority: Priority::High,
+            idempotent: false,
+        };
+
+        let rules = match self.perform_request(request, &mut cancel_rx, None).await {
+            Ok(response) if (200..300).contains(&response.status) => {
+                RobotsRules::parse(&response.decode_text())
+            }
+            _ => RobotsRules::allow_all(),
+        };
+        self.politeness.store_robots(origin, rules)
+    }
 
-        // Execute request with timeout and cancellation
-        let request_future = req_builder.send();
-        let timeout_future = timeout(timeout_duration, request_future);
+    async fn enforce_robots(&self, url: &Url) -> Result<()> {
+        let config = self.politeness.config();
+        if !config.respect_robots_txt || url.path() == "/robots.txt" {
+            return Ok(());
+        }
 
-        let response = tokio::select! {
-            _ = &mut cancel_rx => {
-                return Err(NetworkError::RequestFailed("Request cancelled".to_string()));
+        match self.robots_decision(url.as_str()).await? {
+            RobotsDecision::Allowed => Ok(()),
+            RobotsDecision::Disallowed { rule } if config.override_robots => {
+                tracing::debug!("robots.txt override for {} ({})", url, rule);
+                Ok(())
             }
-            result = timeout_future => {
-                match result {
-                    Ok(Ok(response)) => response,
-                    Ok(Err(e)) => return Err(NetworkError::RequestFailed(e.to_string())),
-                    Err(_) => return Err(NetworkError::Timeout("Request timeout".to_string())),
+            RobotsDecision::Disallowed { rule } => {
+                self.metrics.write().robots_denied_requests += 1;
+                Err(NetworkError::RobotsDisallowed(format!(
+                    "{} ({})",
+                    url, rule
+                )))
+            }
+        }
+    }
+
+    /// `perform_request`, sent again after transient failures as the
+    /// [`RetryConfig`] allows. Cancelling also ends a wait between attempts.
+    async fn perform_with_retries(
+        &self,
+        request: FetchRequest,
+        cancel_rx: &mut tokio::sync::oneshot::Receiver<()>,
+        mut observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let may_retry = request.idempotent || retry::is_idempotent(&request.method);
+        let mut retries = 0;
+        loop {
+            let mut watched = observer.as_deref_mut().map(|inner| StartWatch {
+                inner,
+                started: false,
+            });
+            let result = self
+                .perform_request(
+                    request.clone(),
+                    cancel_rx,
+                    watched.as_mut().map(|watch| watch as &mut dyn BodyObserver),
+                )
+                .await;
+            let started = watched.is_some_and(|watch| watch.started);
+
+            let wait = match &result {
+                _ if !may_retry || started || retries >= self.retry.max_retries => None,
+                Ok(response) => retry::retry_after(
+                    response.status,
+                    &response.headers,
+                    std::time::SystemTime::now(),
+                ),
+                Err(error) if retry::is_transient(error) => Some(self.retry.backoff(retries)),
+                Err(_) => None,
+            };
+            let Some(wait) = wait.filter(|wait| *wait <= self.retry.max_delay()) else {
+                return result.map(|response| FetchResponse {
+                    retries,
+                    ..response
+                });
+            };
+            tracing::debug!("retrying {} in {:?}", request.url, wait);
+            tokio::select! {
+                _ = &mut *cancel_rx => {
+                    return Err(NetworkError::RequestFailed("Request cancelled".to_string()));
                 }
+                _ = tokio::time::sleep(wait) => {}
             }
-        };
 
-        // Read response body
-        let status = response.status().as_u16();
-        let headers: HashMap<String, String> = response
-            .headers()
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
-            .collect();
+            let mut metrics = self.metrics.write();
+            if retries == 0 {
+                metrics.retried_requests += 1;
+            }
+            metrics.retries += 1;
+            retries += 1;
+        }
+    }
 
-        let body = response
-            .bytes()
-            .await
-            .map_err(|e| NetworkError::RequestFailed(format!("Failed to read body: {}", e)))?
-            .to_vec();
+    async fn perform_request(
+        &self,
+        request: FetchRequest,
+        cancel_rx: &mut tokio::sync::oneshot::Receiver<()>,
+        mut observer: Option<&mut dyn BodyObserver>,
+    ) -> Result<FetchResponse> {
+        let url = Url::parse(&request.url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+
+        // The timeout covers every hop of a redirect chain.
+        let deadline = tokio::time::Instant::now()
+            + Duration::from_millis(request.timeout_ms.unwrap_or(self.config.request_timeout_ms));
 
-        // Check response size limit
-        if body.len() > self.config.max_response_size_mb * 1024 * 1024 {
+        let (response, redirects) = self
+            .redirect_follower()
+            .send(url, &request, deadline, cancel_rx)
+            .await?;
+        let RawResponse {
+            status,
+            url: response_url,
+            headers,
+            tls,
+            body: mut chunks,
+        } = response;
+
+        // A body declared too large is refused before any of it is read.
+        let max_bytes = self.config.max_response_size_mb * 1024 * 1024;
+        let declared = header_value(&headers, "content-length")
+            .and_then(|length| length.trim().parse::<usize>().ok());
+        if declared.is_some_and(|length| length > max_bytes) {
             return Err(NetworkError::RequestFailed(
                 "Response too large".to_string(),
             ));
         }
 
+        if !(200..300).contains(&status) {
+            observer = None;
+        }
+        if let Some(observer) = observer.as_deref_mut() {
+            observer.start(&response_url, &headers);
+        }
+        let keep_body = observer
+            .as_deref()
+            .map_or(true, |observer| !observer.takes_body());
+        let mut body = Vec::new();
+        let mut received = 0;
+        loop {
+            let chunk = tokio::select! {
+                _ = &mut *cancel_rx => {
+                    return Err(NetworkError::RequestFailed("Request cancelled".to_string()));
+                }
+                chunk = chunks.next() => match chunk {
+                    Some(chunk) => chunk?,
+                    None => break,
+                },
+            };
+            // Check response size limit
+            received += chunk.len();
+            if received > max_bytes {
+                return Err(NetworkError::RequestFailed(
+                    "Response too large".to_string(),
+                ));
+            }
+            if let Some(observer) = observer.as_deref_mut() {
+                observer.chunk(&chunk);
+            }
+            if keep_body {
+                body.extend_from_slice(&chunk);
+            }
+        }
+
         let fetch_response = FetchResponse {
             status,
             headers: headers.clone(),
             body: body.clone(),
-            url: request.url.clone(),
-            redirected: false,
+            url: if redirects > 0 {
+                response_url.to_string()
+            } else {
+                request.url.clone()
+            },
+            redirected: redirects > 0,
+            tls,
+            retries: 0,
         };
 
         // Cache the response if appropriate
-        if let Some(cache_policy) = request.cache_policy {
-            if !cache_policy.no_store && status == 200 {
-                self.cache_response(&request.url, &fetch_response, cache_policy);
+        if let Some(mut cache_policy) = request.cache_policy {
+            if let Some(cache_control) = header_value(&headers, "cache-control") {
+                cache_policy.merge_cache_control(cache_control);
+            }
+            if !cache_policy.no_store && status == 200 && keep_body {
+                self.cache_response(
+                    &request.url,
+                    &request.headers,
+                    &fetch_response,
+                    cache_policy,
+                );
             }
         }
 
         Ok(fetch_response)
     }
 
-    fn get_cached_response(&self, url: &str) -> Option<CacheEntry> {
-        self.http_cache.get(url)
+    /// The cached response `not_modified` confirmed: its headers updated
+    /// from the 304's, and stored again as fresh under the policy they give,
+    /// at `cache_key` where it was found, even when the 304 came from where
+    /// the request was redirected.
+    fn revalidated(
+        &self,
+        cache_key: &str,
+        entry: CacheEntry,
+        not_modified: FetchResponse,
+        request_headers: &HashMap<String, String>,
+        mut cache_policy: CachePolicy,
+    ) -> FetchResponse {
+        let mut headers: HashMap<String, String> = entry
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
+            .collect();
+        for (name, value) in not_modified.headers {
+            headers.retain(|key, _| !key.eq_ignore_ascii_case(&name));
+            headers.insert(name, value);
+        }
+        if let Some(cache_control) = header_value(&headers, "cache-control") {
+            cache_policy.merge_cache_control(cache_control);
+        }
+        let response = FetchResponse {
+            status: 200,
+            headers,
+            body: entry.data,
+            url: not_modified.url,
+            redirected: not_modified.redirected,
+            tls: not_modified.tls,
+            retries: not_modified.retries,
+        };
+        if !cache_policy.no_store {
+            self.cache_response(cache_key, request_headers, &response, cache_policy);
+        }
+        response
     }
 
-    fn cache_response(&self, url: &str, response: &FetchResponse, cache_policy: CachePolicy) {
-        let mut headers = HeaderMap::new();
-        for (key, value) in &response.headers {
-            if let (Ok(header_name), Ok(header_value)) = (
-                key.parse::<reqwest::header::HeaderName>(),
-                value.parse::<reqwest::header::HeaderValue>(),
-            ) {
-                headers.insert(header_name, header_value);
-            }
+    /// Memory first, then disk; disk hits are promoted into the memory tier.
+    fn get_cached_response(
+        &self,
+        url: &str,
+        request_headers: &HashMap<String, String>,
+    ) -> Option<CacheEntry> {
+        if let Some(entry) = self.http_cache.get(url) {
+            return Some(entry);
         }
 
+        let (stored, body) = self.disk_cache.as_ref()?.get(url, request_headers)?;
+        let now = std::time::SystemTime::now();
+        let created_at = stored.created_at_time();
+        let entry = CacheEntry {
+            size: body.len(),
+            data: body,
+            headers: to_header_map(stored.headers.iter().map(|(name, value)| (name, value))),
+            cache_policy: stored.cache_policy,
+            created_at,
+            last_accessed: now,
+            hit_count: 0,
+            etag: stored.etag,
+            last_modified: stored.last_modified,
+        };
+        self.http_cache.put(url.to_string(), entry.clone());
+        Some(entry)
+    }
+
+    fn cache_response(
+        &self,
+        url: &str,
+        request_headers: &HashMap<String, String>,
+        response: &FetchResponse,
+        cache_policy: CachePolicy,
+    ) {
+        let now = std::time::SystemTime::now();
         let cache_entry = CacheEntry {
             data: response.body.clone(),
-            headers,
-            cache_policy,
-            created_at: std::time::SystemTime::now(),
-            last_accessed: std::time::SystemTime::now(),
+            headers: to_header_map(response.headers.iter()),
+            cache_policy: cache_policy.clone(),
+            created_at: now,
+            last_accessed: now,
             hit_count: 0,
             size: response.body.len(),
             etag: response.headers.get("etag").cloned(),
             last_modified: response.headers.get("last-modified").cloned(),
         };
-
         self.http_cache.put(url.to_string(), cache_entry);
+
+        let disk_cache = match &self.disk_cache {
+            Some(disk_cache) => disk_cache,
+            None => return,
+        };
+        // `Vary: *` can never be matched by a later request.
+        let vary = header_value(&response.headers, "vary").unwrap_or("");
+        if vary.trim() == "*" {
+            return;
+        }
+        let vary = vary
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = header_value(request_headers, &name)
+                    .unwrap_or("")
+                    .to_string();
+                (name, value)
+            })
+            .collect();
+
+        let now_secs = disk_cache::now_secs();
+        let entry = DiskCacheEntry {
+            blob: String::new(),
+            size: 0,
+            cache_policy,
+            headers: response
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            vary,
+            etag: response.headers.get("etag").cloned(),
+            last_modified: response.headers.get("last-modified").cloned(),
+            created_at: now_secs,
+            last_accessed: now_secs,
+        };
+        if let Err(e) = disk_cache.put(url, entry, &response.body) {
+            tracing::warn!("Failed to write {} to the disk cache: {}", url, e);
+        }
     }
 
     pub async fn cancel_request(&self, request_id: &str) -> bool {
@@ -822,8 +1900,31 @@ impl NetworkManager {
         }
     }
 
+    /// Send `user_agent` as the `User-Agent` of requests from now on, from
+    /// this page and every other sharing its connections. Requests already
+    /// under way keep the one they were sent with.
+    pub fn set_user_agent(&self, user_agent: &str) {
+        self.connection_pool.set_user_agent(user_agent);
+    }
+
+    pub fn user_agent(&self) -> String {
+        self.connection_pool
+            .user_agent()
+            .unwrap_or_else(|| self.config.user_agent.clone())
+    }
+
+    /// Wipe both cache tiers.
     pub fn clear_cache(&self) {
         self.http_cache.clear();
+        if let Some(disk_cache) = &self.disk_cache {
+            if let Err(e) = disk_cache.clear() {
+                tracing::warn!("Failed to clear the disk cache: {}", e);
+            }
+        }
+    }
+
+    pub fn clear_robots_cache(&self) {
+        self.politeness.clear_robots_cache();
     }
 
     pub fn clear_dns_cache(&self) {
@@ -840,17 +1941,34 @@ impl NetworkManager {
         self.http_cache.get_stats()
     }
 
-    pub fn update_security_policy(&self, _policy: SecurityPolicy) {
-        // In a real implementation, this would need proper synchronization
-        // For now, we'll note that this is a design limitation
+    pub fn get_disk_cache_stats(&self) -> Option<DiskCacheStats> {
+        self.disk_cache
+            .as_ref()
+            .map(|disk_cache| disk_cache.get_stats())
+    }
+
+    /// Check requests against `policy` from now on, on this page and every
+    /// other sharing its connections.
+    pub fn update_security_policy(&self, policy: SecurityPolicy) {
+        *self.security_policy.write() = policy;
     }
 
     pub async fn shutdown(&self) -> Result<()> {
+        // Let beacons finish within the grace period; they outlive page teardown but not us.
+        self.beacons
+            .shutdown(Duration::from_millis(self.config.beacon_shutdown_grace_ms))
+            .await;
+
         // Cancel all active requests
         self.cancel_all_requests().await;
 
-        // Clear all caches
-        self.clear_cache();
+        // Clear in-memory caches; the disk tier only needs its access times saved.
+        self.http_cache.clear();
+        if let Some(disk_cache) = &self.disk_cache {
+            if let Err(e) = disk_cache.flush() {
+                tracing::warn!("Failed to flush the disk cache index: {}", e);
+            }
+        }
         self.clear_dns_cache();
         self.connection_pool.clear();
 
@@ -858,6 +1976,274 @@ impl NetworkManager {
     }
 }
 
+/// Notes whether a response reached a body observer, after which it is not
+/// retried.
+struct StartWatch<'a> {
+    inner: &'a mut dyn BodyObserver,
+    started: bool,
+}
+
+impl BodyObserver for StartWatch<'_> {
+    fn start(&mut self, url: &Url, headers: &HashMap<String, String>) {
+        self.started = true;
+        self.inner.start(url, headers);
+    }
+
+    fn chunk(&mut self, bytes: &[u8]) {
+        self.inner.chunk(bytes);
+    }
+
+    fn takes_body(&self) -> bool {
+        self.inner.takes_body()
+    }
+}
+
+/// A fetch started by [`NetworkManager::fetch_with_handle`] or
+/// [`NetworkManager::fetch_navigation_with_handle`].
+pub struct RequestHandle<'a> {
+    /// What to pass `cancel_request` to stop it.
+    pub id: String,
+    pub future: BoxFuture<'a, Result<FetchResponse>>,
+}
+
+/// A request's entry in `active_requests`, removed once it is done with.
+struct ActiveRequest {
+    requests: Arc<DashMap<String, tokio::sync::oneshot::Sender<()>>>,
+    id: String,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.requests.remove(&self.id);
+    }
+}
+
+/// Hands each chunk of a body to a callback instead of keeping it.
+struct StreamSink<F> {
+    sink: F,
+}
+
+impl<F: FnMut(&[u8]) + Send> BodyObserver for StreamSink<F> {
+    fn start(&mut self, _url: &Url, _headers: &HashMap<String, String>) {}
+
+    fn chunk(&mut self, bytes: &[u8]) {
+        (self.sink)(bytes);
+    }
+
+    fn takes_body(&self) -> bool {
+        true
+    }
+}
+
+fn header_value<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Ask the server to confirm `entry` instead of sending the body again,
+/// unless the caller already made the request conditional.
+fn add_validators(headers: &mut HashMap<String, String>, entry: &CacheEntry) {
+    if let Some(etag) = &entry.etag {
+        if header_value(headers, "if-none-match").is_none() {
+            headers.insert("If-None-Match".to_string(), etag.clone());
+        }
+    }
+    if let Some(last_modified) = &entry.last_modified {
+        if header_value(headers, "if-modified-since").is_none() {
+            headers.insert("If-Modified-Since".to_string(), last_modified.clone());
+        }
+    }
+}
+
+/// Sends a request and follows the redirects it gets. Redirects are followed
+/// here rather than by the transport, so every hop goes through the security
+/// policy before it is requested and carries its own cookies. Cloned into
+/// the tasks that outlive a manager borrow, such as keepalive requests.
+#[derive(Clone)]
+struct RedirectFollower {
+    transport: Arc<dyn Transport>,
+    cookies: Arc<CookieJar>,
+    security_policy: Arc<RwLock<SecurityPolicy>>,
+    metrics: Arc<RwLock<NetworkMetrics>>,
+    max_redirects: usize,
+}
+
+impl RedirectFollower {
+    /// The response `request`, sent to `url`, ends up with, and how many
+    /// redirects led to it. `deadline` covers every hop.
+    async fn send(
+        &self,
+        mut url: Url,
+        request: &FetchRequest,
+        deadline: tokio::time::Instant,
+        cancel_rx: &mut tokio::sync::oneshot::Receiver<()>,
+    ) -> Result<(RawResponse, usize)> {
+        let mut method = request.method.clone();
+        let mut body = request.body.clone();
+        let mut request_headers = request.headers.clone();
+        let mut redirects = 0;
+        loop {
+            let mut headers = request_headers.clone();
+            attach_cookies(
+                &self.cookies,
+                &self.security_policy.read(),
+                &url,
+                &mut headers,
+            );
+            let uploaded = body.as_ref().map_or(0, Vec::len) as u64;
+
+            // Execute request with timeout and cancellation
+            let request_future = self.transport.execute(PreparedRequest {
+                url: url.clone(),
+                method: method.clone(),
+                headers,
+                body: body.clone(),
+                priority: request.priority,
+            });
+            let timeout_future = tokio::time::timeout_at(deadline, request_future);
+
+            let response = tokio::select! {
+                _ = &mut *cancel_rx => {
+                    return Err(NetworkError::RequestFailed("Request cancelled".to_string()));
+                }
+                result = timeout_future => {
+                    match result {
+                        Ok(Ok(response)) => response,
+                        Ok(Err(e)) => return Err(e),
+                        Err(_) => return Err(NetworkError::Timeout("Request timeout".to_string())),
+                    }
+                }
+            };
+            // The server answered, so it took the whole body.
+            self.metrics.write().total_bytes_uploaded += uploaded;
+            store_cookies(
+                &self.cookies,
+                &self.security_policy.read(),
+                &response.url,
+                &response.headers,
+            );
+
+            let location = match header_value(&response.headers, "location") {
+                Some(location)
+                    if request.follow_redirects
+                        && matches!(response.status, 301 | 302 | 303 | 307 | 308) =>
+                {
+                    location
+                }
+                _ => return Ok((response, redirects)),
+            };
+            redirects += 1;
+            if redirects > self.max_redirects {
+                return Err(NetworkError::Redirect(format!(
+                    "More than {} redirects",
+                    self.max_redirects
+                )));
+            }
+            let next = response.url.join(location.trim()).map_err(|e| {
+                NetworkError::Redirect(format!("Invalid Location {:?}: {}", location, e))
+            })?;
+            self.security_policy.read().check_url(&next)?;
+
+            // 301 and 302 turn a POST into a GET, as 303 does anything but
+            // HEAD; 307 and 308 repeat the request as it was.
+            let to_get = match response.status {
+                301 | 302 => method == "POST",
+                303 => method != "HEAD",
+                _ => false,
+            };
+            if to_get {
+                method = "GET".to_string();
+                body = None;
+                request_headers.retain(|name, _| {
+                    !["content-type", "content-length", "content-encoding"]
+                        .iter()
+                        .any(|header| name.eq_ignore_ascii_case(header))
+                });
+            }
+            // Credentials meant for one origin are not handed to another.
+            if next.origin() != url.origin() {
+                request_headers.retain(|name, _| {
+                    !name.eq_ignore_ascii_case("authorization")
+                        && !name.eq_ignore_ascii_case("cookie")
+                });
+            }
+            tracing::debug!("{} redirected to {}", url, next);
+            url = next;
+        }
+    }
+}
+
+/// Add the jar's cookies for `url` to `headers`, unless they already carry
+/// some or the policy blocks its host.
+fn attach_cookies(
+    cookies: &CookieJar,
+    security_policy: &SecurityPolicy,
+    url: &Url,
+    headers: &mut HashMap<String, String>,
+) {
+    if header_value(headers, "cookie").is_some() || security_policy.check_url(url).is_err() {
+        return;
+    }
+    if let Some(cookie) = cookies.cookie_header(url) {
+        headers.insert("Cookie".to_string(), cookie);
+    }
+}
+
+/// Store the cookies a response from `url` sets, unless the policy blocks
+/// its host.
+fn store_cookies(
+    cookies: &CookieJar,
+    security_policy: &SecurityPolicy,
+    url: &Url,
+    headers: &HashMap<String, String>,
+) {
+    if security_policy.check_url(url).is_err() {
+        return;
+    }
+    if let Some(set_cookie) = header_value(headers, "set-cookie") {
+        cookies.set_cookies_from_header(url, set_cookie);
+    }
+}
+
+/// The payload of a `data:` URL as a response; nothing goes on the wire.
+fn data_url_response(url: &Url, method: &str) -> Result<FetchResponse> {
+    if !method.eq_ignore_ascii_case("GET") {
+        return Err(NetworkError::RequestFailed(format!(
+            "{} of a data: URL",
+            method
+        )));
+    }
+    let (content_type, body) = crate::parse_data_url(&url.as_str()["data:".len()..])
+        .map_err(NetworkError::RequestFailed)?;
+    let mut headers = HashMap::new();
+    headers.insert("content-type".to_string(), content_type);
+    headers.insert("content-length".to_string(), body.len().to_string());
+    Ok(FetchResponse {
+        status: 200,
+        headers,
+        body,
+        url: url.to_string(),
+        redirected: false,
+        tls: None,
+        retries: 0,
+    })
+}
+
+fn to_header_map<'a>(headers: impl Iterator<Item = (&'a String, &'a String)>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (key, value) in headers {
+        if let (Ok(header_name), Ok(header_value)) = (
+            key.parse::<reqwest::header::HeaderName>(),
+            value.parse::<reqwest::header::HeaderValue>(),
+        ) {
+            map.insert(header_name, header_value);
+        }
+    }
+    map
+}
+
 #[derive(Debug, Clone)]
 pub struct FetchRequest {
     pub url: String,
@@ -867,4 +2253,9 @@ pub struct FetchRequest {
     pub timeout_ms: Option<u64>,
     pub follow_redirects: bool,
     pub cache_policy: Option<CachePolicy>,
+    /// How soon the page needs the response, for the transport to schedule by.
+    pub priority: Priority,
+    /// Retry after a transient failure even though the method is neither
+    /// GET nor HEAD.
+    pub idempotent: bool,
 }
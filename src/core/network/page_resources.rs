@@ -0,0 +1,253 @@
+//! What the current page fetched, by type.
+//!
+//! Every response the page gets over HTTP counts once, whether it came from
+//! the network or the HTTP cache, speculative fetches included. The type
+//! comes from the response's `Content-Type`. The document's own response is
+//! left out, so the counts are its subresources'.
+//!
+//! The resource log lists the same responses, and the hyperlink auditing
+//! pings sent, across pages: a ping goes out as its page is left, so a log
+//! that started over with each page would lose it. It keeps the last
+//! [`RESOURCE_LOG_CAPACITY`] entries.
+//!
+//! The page's subresource responses are also kept, bodies and all, for
+//! saving the page: each up to a size of its own and all of them together
+//! up to another, both from
+//! [`PageArchiveConfig`](crate::core::archive::PageArchiveConfig).
+
+use super::FetchResponse;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+pub const RESOURCE_LOG_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResourceType {
+    Stylesheet,
+    Script,
+    Image,
+    Font,
+    Media,
+    /// A hyperlink auditing ping; never a response type.
+    Ping,
+    Other,
+}
+
+impl ResourceType {
+    /// The type of a response with `content_type`.
+    pub fn of(content_type: &str) -> Self {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "text/css" => Self::Stylesheet,
+            "text/javascript"
+            | "application/javascript"
+            | "application/x-javascript"
+            | "text/ecmascript"
+            | "application/ecmascript" => Self::Script,
+            "application/font-woff" | "application/vnd.ms-fontobject" => Self::Font,
+            _ if essence.starts_with("image/") => Self::Image,
+            _ if essence.starts_with("font/") => Self::Font,
+            _ if essence.starts_with("audio/") || essence.starts_with("video/") => Self::Media,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceCount {
+    pub count: u64,
+    pub cache_hits: u64,
+    /// `cache_hits / count`; 0 when nothing was fetched.
+    pub cache_hit_ratio: f64,
+}
+
+impl ResourceCount {
+    fn add(&mut self, cache_hit: bool) {
+        self.count += 1;
+        if cache_hit {
+            self.cache_hits += 1;
+        }
+        self.cache_hit_ratio = self.cache_hits as f64 / self.count as f64;
+    }
+}
+
+/// A page's subresources, per type and in all.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PageResourceCounts {
+    pub stylesheet: ResourceCount,
+    pub script: ResourceCount,
+    pub image: ResourceCount,
+    pub font: ResourceCount,
+    pub media: ResourceCount,
+    #[serde(default)]
+    pub ping: ResourceCount,
+    pub other: ResourceCount,
+    pub total: ResourceCount,
+}
+
+impl PageResourceCounts {
+    fn add(&mut self, resource_type: ResourceType, cache_hit: bool) {
+        let count = match resource_type {
+            ResourceType::Stylesheet => &mut self.stylesheet,
+            ResourceType::Script => &mut self.script,
+            ResourceType::Image => &mut self.image,
+            ResourceType::Font => &mut self.font,
+            ResourceType::Media => &mut self.media,
+            ResourceType::Ping => &mut self.ping,
+            ResourceType::Other => &mut self.other,
+        };
+        count.add(cache_hit);
+        self.total.add(cache_hit);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceLogEntry {
+    pub url: String,
+    pub resource_type: ResourceType,
+    pub cache_hit: bool,
+}
+
+/// What is kept of a response's body.
+#[derive(Debug, Clone)]
+pub enum KeptBody {
+    Bytes(Arc<[u8]>),
+    /// Over the cap on one response; how big it was.
+    TooLarge(usize),
+    /// Past the cap on the page's responses together.
+    OverBudget,
+}
+
+/// A subresource response of the current page, as kept for saving it.
+#[derive(Debug, Clone)]
+pub struct KeptResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: KeptBody,
+}
+
+#[derive(Default)]
+struct PageState {
+    /// The document's URL while its response is awaited.
+    document: Option<String>,
+    counts: PageResourceCounts,
+    /// By request URL; a URL fetched again keeps its first response.
+    kept: HashMap<String, KeptResponse>,
+    kept_bytes: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct PageResources {
+    state: Mutex<PageState>,
+    log: Mutex<VecDeque<ResourceLogEntry>>,
+    /// Largest body kept of one response.
+    max_kept_body: usize,
+    /// Most body bytes kept of the page's responses together.
+    max_kept_bytes: usize,
+}
+
+impl PageResources {
+    /// Counts and a log that keep bodies up to `max_body` bytes each and
+    /// `max_total` together; zero keeps none.
+    pub(crate) fn keeping_bodies(max_body: usize, max_total: usize) -> Self {
+        Self {
+            max_kept_body: max_body,
+            max_kept_bytes: max_total,
+            ..Self::default()
+        }
+    }
+
+    /// Counts and a log of their own, keeping bodies within the same caps.
+    pub(crate) fn for_other_page(&self) -> Self {
+        Self::keeping_bodies(self.max_kept_body, self.max_kept_bytes)
+    }
+
+    /// A new page, whose counts start over.
+    pub(crate) fn begin(&self) {
+        *self.state.lock() = PageState::default();
+    }
+
+    /// Responses for `url` are the document's until [`Self::document_loaded`].
+    pub(crate) fn expect_document(&self, url: &str) {
+        self.state.lock().document = Some(url.to_string());
+    }
+
+    pub(crate) fn document_loaded(&self) {
+        self.state.lock().document = None;
+    }
+
+    /// The response to a request for `url` arrived, from the HTTP cache
+    /// when `cache_hit`.
+    pub(crate) fn loaded(&self, url: &str, response: &FetchResponse, cache_hit: bool) {
+        let mut state = self.state.lock();
+        if state.document.as_deref() == Some(url) {
+            return;
+        }
+        let content_type = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map_or("", |(_, value)| value.as_str());
+        let resource_type = ResourceType::of(content_type);
+        state.counts.add(resource_type, cache_hit);
+        if self.max_kept_bytes > 0 && !state.kept.contains_key(url) {
+            let size = response.body.len();
+            let body = if size > self.max_kept_body {
+                KeptBody::TooLarge(size)
+            } else if state.kept_bytes + size > self.max_kept_bytes {
+                KeptBody::OverBudget
+            } else {
+                state.kept_bytes += size;
+                KeptBody::Bytes(response.body.as_slice().into())
+            };
+            let kept = KeptResponse {
+                status: response.status,
+                content_type: content_type.to_string(),
+                body,
+            };
+            state.kept.insert(url.to_string(), kept);
+        }
+        drop(state);
+        self.log(url, resource_type, cache_hit);
+    }
+
+    /// The response the page got to a request for `url`.
+    pub(crate) fn kept(&self, url: &str) -> Option<KeptResponse> {
+        self.state.lock().kept.get(url).cloned()
+    }
+
+    /// A hyperlink auditing ping to `url` was queued.
+    pub(crate) fn pinged(&self, url: &str) {
+        self.state.lock().counts.add(ResourceType::Ping, false);
+        self.log(url, ResourceType::Ping, false);
+    }
+
+    fn log(&self, url: &str, resource_type: ResourceType, cache_hit: bool) {
+        let mut log = self.log.lock();
+        if log.len() == RESOURCE_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(ResourceLogEntry {
+            url: url.to_string(),
+            resource_type,
+            cache_hit,
+        });
+    }
+
+    pub(crate) fn counts(&self) -> PageResourceCounts {
+        self.state.lock().counts.clone()
+    }
+
+    /// The logged entries, oldest first.
+    pub(crate) fn log_entries(&self) -> Vec<ResourceLogEntry> {
+        self.log.lock().iter().cloned().collect()
+    }
+}
@@ -0,0 +1,366 @@
+//! Crawler politeness: per-host rate limiting, navigation spacing and
+//! robots.txt evaluation. Everything here is inert unless
+//! `PolitenessConfig::enabled` is set.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolitenessConfig {
+    pub enabled: bool,
+    /// Sustained per-host request rate; `0.0` disables the token bucket.
+    pub requests_per_second: f64,
+    /// Requests a host may receive back-to-back before throttling kicks in.
+    pub burst: u32,
+    /// Minimum spacing between top-level navigations to the same host.
+    pub min_navigation_delay_ms: u64,
+    /// Cap on concurrent top-level navigations, separate from the subresource limiter.
+    pub max_concurrent_navigations: usize,
+    pub respect_robots_txt: bool,
+    /// Product token matched against `User-agent:` lines in robots.txt.
+    pub robots_user_agent: String,
+    /// Fetch URLs even when robots.txt disallows them; the decision is still recorded.
+    pub override_robots: bool,
+    /// Treat a robots.txt `Crawl-delay` as a per-host minimum request interval.
+    pub honor_crawl_delay: bool,
+}
+
+impl Default for PolitenessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            requests_per_second: 1.0,
+            burst: 1,
+            min_navigation_delay_ms: 1000,
+            max_concurrent_navigations: 4,
+            respect_robots_txt: true,
+            robots_user_agent: "VulkanBrowser".to_string(),
+            override_robots: false,
+            honor_crawl_delay: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RobotsDecision {
+    Allowed,
+    Disallowed { rule: String },
+}
+
+impl RobotsDecision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, RobotsDecision::Allowed)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RobotsGroup {
+    agents: Vec<String>,
+    /// `(allow, pattern)` in file order.
+    rules: Vec<(bool, String)>,
+    crawl_delay: Option<f64>,
+}
+
+/// Parsed robots.txt for one origin.
+#[derive(Debug, Clone, Default)]
+pub struct RobotsRules {
+    groups: Vec<RobotsGroup>,
+}
+
+impl RobotsRules {
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut groups: Vec<RobotsGroup> = Vec::new();
+        let mut current = RobotsGroup::default();
+        let mut in_agent_lines = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (field, value) = match line.split_once(':') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let field = field.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            match field.as_str() {
+                "user-agent" => {
+                    if !in_agent_lines && !current.agents.is_empty() {
+                        groups.push(std::mem::take(&mut current));
+                    }
+                    current.agents.push(value.to_ascii_lowercase());
+                    in_agent_lines = true;
+                }
+                "allow" | "disallow" => {
+                    in_agent_lines = false;
+                    // An empty Disallow means "allow everything" and adds no rule;
+                    // rules before any User-agent line belong to no group.
+                    if !value.is_empty() && !current.agents.is_empty() {
+                        current.rules.push((field == "allow", value.to_string()));
+                    }
+                }
+                "crawl-delay" => {
+                    in_agent_lines = false;
+                    current.crawl_delay = value.parse::<f64>().ok().filter(|d| *d >= 0.0);
+                }
+                _ => {}
+            }
+        }
+        if !current.agents.is_empty() {
+            groups.push(current);
+        }
+
+        Self { groups }
+    }
+
+    /// The group whose agent token is the longest match for `product`, else `*`.
+    fn group_for(&self, product: &str) -> Option<&RobotsGroup> {
+        let product = product.to_ascii_lowercase();
+        self.groups
+            .iter()
+            .filter_map(|group| {
+                group
+                    .agents
+                    .iter()
+                    .filter(|agent| *agent != "*" && product.contains(agent.as_str()))
+                    .map(|agent| agent.len())
+                    .max()
+                    .map(|len| (len, group))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, group)| group)
+            .or_else(|| {
+                self.groups
+                    .iter()
+                    .find(|group| group.agents.iter().any(|agent| agent == "*"))
+            })
+    }
+
+    /// Longest matching rule wins; on a tie `Allow` wins.
+    pub fn decide(&self, product: &str, path: &str) -> RobotsDecision {
+        let group = match self.group_for(product) {
+            Some(group) => group,
+            None => return RobotsDecision::Allowed,
+        };
+
+        let best = group
+            .rules
+            .iter()
+            .filter(|(_, pattern)| pattern_matches(pattern, path))
+            .max_by_key(|(allow, pattern)| (pattern.len(), *allow));
+
+        match best {
+            Some((false, pattern)) => RobotsDecision::Disallowed {
+                rule: format!("Disallow: {}", pattern),
+            },
+            _ => RobotsDecision::Allowed,
+        }
+    }
+
+    pub fn crawl_delay(&self, product: &str) -> Option<Duration> {
+        self.group_for(product)
+            .and_then(|group| group.crawl_delay)
+            .map(Duration::from_secs_f64)
+    }
+}
+
+/// robots.txt path matching: prefix match with `*` wildcards and a `$` end anchor.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !path.starts_with(first) {
+        return false;
+    }
+    let mut pos = first.len();
+    let rest: Vec<&str> = parts.collect();
+
+    for (i, part) in rest.iter().enumerate() {
+        let is_last = i + 1 == rest.len();
+        if is_last && anchored {
+            return path.len() >= pos + part.len() && path.ends_with(part);
+        }
+        match path[pos..].find(part) {
+            Some(found) => pos += found + part.len(),
+            None => return false,
+        }
+    }
+
+    !anchored || pos == path.len()
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Per-host throttling state shared by every request the `NetworkManager` makes.
+pub struct PolitenessController {
+    config: PolitenessConfig,
+    buckets: DashMap<String, Arc<Mutex<TokenBucket>>>,
+    /// Earliest instant the next request to a host may start (crawl-delay).
+    next_request_at: DashMap<String, Instant>,
+    /// Earliest instant the next navigation to a host may start.
+    next_navigation_at: DashMap<String, Instant>,
+    navigation_limiter: Semaphore,
+    robots: DashMap<String, Arc<RobotsRules>>,
+}
+
+impl PolitenessController {
+    pub fn new(config: PolitenessConfig) -> Self {
+        let navigation_limit = config.max_concurrent_navigations.max(1);
+        Self {
+            config,
+            buckets: DashMap::new(),
+            next_request_at: DashMap::new(),
+            next_navigation_at: DashMap::new(),
+            navigation_limiter: Semaphore::new(navigation_limit),
+            robots: DashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PolitenessConfig {
+        &self.config
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Wait until `host` may receive another request. Returns whether the
+    /// caller had to wait.
+    pub async fn throttle_request(&self, host: &str) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+
+        let mut throttled = false;
+
+        if let Some(delay) = self.crawl_delay_for(host) {
+            throttled |= Self::wait_for_slot(&self.next_request_at, host, delay).await;
+        }
+
+        if self.config.requests_per_second > 0.0 {
+            let bucket = self
+                .buckets
+                .entry(host.to_string())
+                .or_insert_with(|| {
+                    Arc::new(Mutex::new(TokenBucket {
+                        tokens: self.config.burst.max(1) as f64,
+                        last_refill: Instant::now(),
+                    }))
+                })
+                .clone();
+
+            loop {
+                let wait = {
+                    let mut bucket = bucket.lock();
+                    let now = Instant::now();
+                    let refill = now.duration_since(bucket.last_refill).as_secs_f64()
+                        * self.config.requests_per_second;
+                    bucket.tokens = (bucket.tokens + refill).min(self.config.burst.max(1) as f64);
+                    bucket.last_refill = now;
+
+                    if bucket.tokens >= 1.0 {
+                        bucket.tokens -= 1.0;
+                        None
+                    } else {
+                        Some(Duration::from_secs_f64(
+                            (1.0 - bucket.tokens) / self.config.requests_per_second,
+                        ))
+                    }
+                };
+
+                match wait {
+                    Some(wait) => {
+                        throttled = true;
+                        tokio::time::sleep(wait).await;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        throttled
+    }
+
+    /// Hold a navigation slot and enforce the per-host navigation spacing.
+    /// Returns the permit (if politeness is on) and whether the caller waited.
+    pub async fn begin_navigation(&self, host: &str) -> (Option<SemaphorePermit<'_>>, bool) {
+        if !self.config.enabled {
+            return (None, false);
+        }
+
+        let permit = self
+            .navigation_limiter
+            .acquire()
+            .await
+            .expect("Navigation semaphore closed");
+        let delay = Duration::from_millis(self.config.min_navigation_delay_ms);
+        let waited = Self::wait_for_slot(&self.next_navigation_at, host, delay).await;
+        (Some(permit), waited)
+    }
+
+    /// Reserve the next slot for `host`, sleeping until it opens.
+    async fn wait_for_slot(
+        slots: &DashMap<String, Instant>,
+        host: &str,
+        interval: Duration,
+    ) -> bool {
+        let start = {
+            let mut slot = slots.entry(host.to_string()).or_insert_with(Instant::now);
+            let start = (*slot).max(Instant::now());
+            *slot = start + interval;
+            start
+        };
+
+        if start > Instant::now() {
+            tokio::time::sleep_until(start).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn crawl_delay_for(&self, host: &str) -> Option<Duration> {
+        if !self.config.honor_crawl_delay {
+            return None;
+        }
+        self.robots
+            .iter()
+            .find(|entry| origin_host(entry.key()) == Some(host))
+            .and_then(|entry| entry.value().crawl_delay(&self.config.robots_user_agent))
+    }
+
+    pub fn cached_robots(&self, origin: &str) -> Option<Arc<RobotsRules>> {
+        self.robots.get(origin).map(|entry| entry.clone())
+    }
+
+    pub fn store_robots(&self, origin: String, rules: RobotsRules) -> Arc<RobotsRules> {
+        let rules = Arc::new(rules);
+        self.robots.insert(origin, rules.clone());
+        rules
+    }
+
+    pub fn clear_robots_cache(&self) {
+        self.robots.clear();
+    }
+}
+
+fn origin_host(origin: &str) -> Option<&str> {
+    let rest = origin.split_once("://")?.1;
+    let host = rest.split('/').next()?;
+    Some(host.rsplit_once(':').map_or(host, |(h, _)| h))
+}
@@ -0,0 +1,537 @@
+//! The preload scanner: subresource fetches started while a page is still
+//! downloading, ahead of the document needing them.
+//!
+//! [`PreloadScanner`] tokenizes raw markup chunk by chunk, looking only at
+//! start tags that reference something fetchable. It never builds or touches
+//! a DOM, and skips comments, `<template>` contents and the raw text of
+//! `<script>`, `<style>` and their kind. What it finds goes to
+//! [`NetworkManager::preload`](super::NetworkManager::preload), which starts
+//! the fetch and hands its response to the first `fetch_subresource` of the
+//! same URL, so the resource is fetched once however the two race.
+
+use super::{FetchResponse, RequestInitiator};
+use crate::core::dom::document::Node;
+use futures::future::{BoxFuture, Shared};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use url::Url;
+
+/// Speculative fetches one page may start.
+pub const DEFAULT_MAX_SPECULATIVE_FETCHES: usize = 32;
+
+/// Low-priority speculative fetches in flight at once, so images never
+/// crowd out stylesheets and scripts.
+pub const LOW_PRIORITY_SPECULATIVE_FETCHES: usize = 6;
+
+/// A start tag left unfinished this long is taken for garbage and skipped.
+const MAX_TAG_BYTES: usize = 16 * 1024;
+
+/// Elements whose content the tokenizer reads as text up to their end tag.
+/// `<noscript>` is among them because scripting is on.
+const RAW_TEXT_ELEMENTS: &[&str] = &[
+    "script", "style", "textarea", "title", "xmp", "iframe", "noembed", "noframes", "noscript",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Destination {
+    Style,
+    Script,
+    Font,
+    Image,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+impl Destination {
+    /// Render-blocking kinds go first; images wait their turn.
+    pub fn priority(self) -> Priority {
+        match self {
+            Destination::Style | Destination::Script | Destination::Font => Priority::High,
+            Destination::Image => Priority::Low,
+        }
+    }
+}
+
+/// A reference found ahead of the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preload {
+    pub url: Url,
+    pub destination: Destination,
+}
+
+impl Preload {
+    /// Whether the document's CSP lets it fetch this at all.
+    pub fn is_allowed_by(&self, initiator: &RequestInitiator) -> bool {
+        match self.destination {
+            Destination::Style => initiator.allows_style(&self.url),
+            Destination::Script => initiator.allows_script(&self.url),
+            Destination::Font => initiator.allows_font(&self.url),
+            Destination::Image => initiator.allows_image(&self.url),
+        }
+    }
+}
+
+/// Which of an `<img>`'s `src` and `srcset` candidates a 1x display loads:
+/// a `1x` (or descriptor-less) candidate, else `src`, else the first
+/// candidate.
+pub fn select_image_source(src: Option<&str>, srcset: Option<&str>) -> Option<String> {
+    let candidates: Vec<(&str, Option<&str>)> = srcset
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|candidate| {
+            let mut parts = candidate.split_ascii_whitespace();
+            let url = parts.next()?;
+            Some((url, parts.next()))
+        })
+        .collect();
+    let one_x = candidates
+        .iter()
+        .find(|(_, descriptor)| descriptor.map_or(true, |d| d.eq_ignore_ascii_case("1x")));
+    let src = src.map(str::trim).filter(|src| !src.is_empty());
+    one_x
+        .map(|(url, _)| *url)
+        .or(src)
+        .or(candidates.first().map(|(url, _)| *url))
+        .map(str::to_string)
+}
+
+/// The URL an `<img>` fetches, resolved against `base`; `None` for other
+/// elements and for sources that are not HTTP(S), `blob:` or `data:`.
+pub fn image_source(node: &Node, base: &Url) -> Option<Url> {
+    if !node.tag_name.eq_ignore_ascii_case("img") {
+        return None;
+    }
+    let source = select_image_source(
+        node.get_attribute("src").as_deref(),
+        node.get_attribute("srcset").as_deref(),
+    )?;
+    match base.join(source.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https" | "blob" | "data") => Some(url),
+        _ => None,
+    }
+}
+
+enum Mode {
+    Data,
+    Comment,
+    /// Inside the named raw text element.
+    RawText(&'static str),
+}
+
+/// An error-tolerant tokenizer over a document's bytes as they arrive.
+pub struct PreloadScanner {
+    base: Url,
+    base_seen: bool,
+    pending: Vec<u8>,
+    mode: Mode,
+    template_depth: usize,
+    found: HashSet<Url>,
+}
+
+impl PreloadScanner {
+    /// A scanner for the document at `url`, which is also its base URL until
+    /// a `<base href>` turns up.
+    pub fn new(url: Url) -> Self {
+        Self {
+            base: url,
+            base_seen: false,
+            pending: Vec::new(),
+            mode: Mode::Data,
+            template_depth: 0,
+            found: HashSet::new(),
+        }
+    }
+
+    /// Scan the next chunk and return what it references that was not seen
+    /// before, high priority first. A tag cut off by the chunk's end is
+    /// picked up with the next one.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Preload> {
+        self.pending.extend_from_slice(chunk);
+        let mut found = Vec::new();
+        let consumed = self.scan(&mut found);
+        self.pending.drain(..consumed);
+        found.sort_by_key(|preload| preload.destination.priority());
+        found
+    }
+
+    /// Scan `pending` as far as it is complete; returns how much was used.
+    fn scan(&mut self, found: &mut Vec<Preload>) -> usize {
+        let mut pos = 0;
+        loop {
+            let rest = &self.pending[pos..];
+            match self.mode {
+                Mode::Comment => match find(rest, b"-->") {
+                    Some(end) => {
+                        pos += end + 3;
+                        self.mode = Mode::Data;
+                    }
+                    // Keep what could be the start of `-->`.
+                    None => return self.pending.len().saturating_sub(2).max(pos),
+                },
+                Mode::RawText(name) => match find_end_tag(rest, name) {
+                    Some(end) => {
+                        pos += end;
+                        self.mode = Mode::Data;
+                    }
+                    None => return self.pending.len().saturating_sub(name.len() + 2).max(pos),
+                },
+                Mode::Data => {
+                    let start = match rest.iter().position(|&byte| byte == b'<') {
+                        Some(start) => pos + start,
+                        None => return self.pending.len(),
+                    };
+                    pos = start;
+                    match self.markup(start, found) {
+                        Some(end) => pos = end,
+                        None if self.pending.len() - start > MAX_TAG_BYTES => pos += 1,
+                        None => return pos,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle the markup at `start`, a `<`; returns where it ends, or `None`
+    /// when it is not all here yet.
+    fn markup(&mut self, start: usize, found: &mut Vec<Preload>) -> Option<usize> {
+        let bytes = &self.pending[start..];
+        if bytes.len() < 4 && b"<!--".starts_with(bytes) {
+            return None;
+        }
+        if bytes.starts_with(b"<!--") {
+            self.mode = Mode::Comment;
+            return Some(start + 4);
+        }
+        match bytes.get(1)? {
+            b'!' | b'?' => {
+                let end = bytes.iter().position(|&byte| byte == b'>')?;
+                Some(start + end + 1)
+            }
+            b'/' => {
+                let end = bytes.iter().position(|&byte| byte == b'>')?;
+                let name = tag_name(&bytes[2..end]);
+                if name == "template" {
+                    self.template_depth = self.template_depth.saturating_sub(1);
+                }
+                Some(start + end + 1)
+            }
+            byte if byte.is_ascii_alphabetic() => {
+                let (tag, length) = parse_start_tag(bytes)?;
+                self.start_tag(&tag, found);
+                Some(start + length)
+            }
+            _ => Some(start + 1),
+        }
+    }
+
+    fn start_tag(&mut self, tag: &StartTag, found: &mut Vec<Preload>) {
+        if let Some(&name) = RAW_TEXT_ELEMENTS.iter().find(|&&name| name == tag.name) {
+            self.mode = Mode::RawText(name);
+        }
+        if tag.name == "template" {
+            self.template_depth += 1;
+        }
+        if self.template_depth > 0 {
+            return;
+        }
+        let (reference, destination) = match tag.name.as_str() {
+            "base" => {
+                let href = tag
+                    .attribute("href")
+                    .and_then(|href| self.base.join(href).ok());
+                if let (false, Some(base)) = (self.base_seen, href) {
+                    self.base = base;
+                    self.base_seen = true;
+                }
+                return;
+            }
+            "img" => (
+                select_image_source(tag.attribute("src"), tag.attribute("srcset")),
+                Destination::Image,
+            ),
+            // Media sources are probed with range requests; only the poster
+            // is an ordinary fetch.
+            "video" => (
+                tag.attribute("poster").map(str::to_string),
+                Destination::Image,
+            ),
+            "source" => (
+                select_image_source(None, tag.attribute("srcset")),
+                Destination::Image,
+            ),
+            "script" if is_classic_or_module(tag.attribute("type")) => (
+                tag.attribute("src").map(str::to_string),
+                Destination::Script,
+            ),
+            "link" => match link_destination(tag) {
+                Some(destination) => (tag.attribute("href").map(str::to_string), destination),
+                None => return,
+            },
+            _ => return,
+        };
+        let url = match reference.and_then(|reference| self.base.join(reference.trim()).ok()) {
+            Some(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => return,
+        };
+        if self.found.insert(url.clone()) {
+            found.push(Preload { url, destination });
+        }
+    }
+}
+
+struct StartTag {
+    name: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl StartTag {
+    /// The first attribute called `name`, as the parser keeps it.
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What a `<link>` fetches as: stylesheets, and preloads of the kinds the
+/// document loads.
+fn link_destination(tag: &StartTag) -> Option<Destination> {
+    let rel = tag.attribute("rel").unwrap_or("").to_ascii_lowercase();
+    let rel: Vec<&str> = rel.split_ascii_whitespace().collect();
+    if rel.contains(&"stylesheet") {
+        return (!rel.contains(&"alternate")).then_some(Destination::Style);
+    }
+    if !rel.contains(&"preload") {
+        return None;
+    }
+    match tag.attribute("as")?.to_ascii_lowercase().as_str() {
+        "style" => Some(Destination::Style),
+        "script" => Some(Destination::Script),
+        "font" => Some(Destination::Font),
+        "image" => Some(Destination::Image),
+        _ => None,
+    }
+}
+
+fn is_classic_or_module(script_type: Option<&str>) -> bool {
+    match script_type.map(|value| value.trim().to_ascii_lowercase()) {
+        None => true,
+        Some(value) => {
+            value.is_empty()
+                || value == "module"
+                || value == "text/javascript"
+                || value == "application/javascript"
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Where `</name` followed by whitespace, `/` or `>` starts, in any case.
+fn find_end_tag(bytes: &[u8], name: &str) -> Option<usize> {
+    let length = name.len() + 2;
+    (0..bytes.len().saturating_sub(length)).find(|&at| {
+        bytes[at] == b'<'
+            && bytes[at + 1] == b'/'
+            && bytes[at + 2..at + length].eq_ignore_ascii_case(name.as_bytes())
+            && matches!(
+                bytes[at + length],
+                b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r' | b'\x0c'
+            )
+    })
+}
+
+fn tag_name(bytes: &[u8]) -> String {
+    let end = bytes
+        .iter()
+        .position(|byte| byte.is_ascii_whitespace() || *byte == b'/' || *byte == b'>')
+        .unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_ascii_lowercase()
+}
+
+/// The start tag at the beginning of `bytes` and its length in bytes, or
+/// `None` when its `>` has not arrived.
+fn parse_start_tag(bytes: &[u8]) -> Option<(StartTag, usize)> {
+    let is_name_end = |byte: u8| byte.is_ascii_whitespace() || byte == b'/' || byte == b'>';
+    let name_end = 1 + bytes[1..].iter().position(|&byte| is_name_end(byte))?;
+    let mut tag = StartTag {
+        name: tag_name(&bytes[1..name_end]),
+        attributes: Vec::new(),
+    };
+    let mut pos = name_end;
+    loop {
+        while bytes.get(pos)?.is_ascii_whitespace() || bytes[pos] == b'/' {
+            pos += 1;
+        }
+        if bytes[pos] == b'>' {
+            return Some((tag, pos + 1));
+        }
+        let name_start = pos;
+        while !matches!(bytes.get(pos)?, b'=' | b'>' | b'/') && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let name = String::from_utf8_lossy(&bytes[name_start..pos]).to_ascii_lowercase();
+        while bytes.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        let mut value = String::new();
+        if bytes[pos] == b'=' {
+            pos += 1;
+            while bytes.get(pos)?.is_ascii_whitespace() {
+                pos += 1;
+            }
+            let (value_start, value_end, next) = match bytes[pos] {
+                quote @ (b'"' | b'\'') => {
+                    let end = pos + 1 + bytes[pos + 1..].iter().position(|&byte| byte == quote)?;
+                    (pos + 1, end, end + 1)
+                }
+                _ => {
+                    let end = pos
+                        + bytes[pos..]
+                            .iter()
+                            .position(|&byte| byte.is_ascii_whitespace() || byte == b'>')?;
+                    (pos, end, end)
+                }
+            };
+            value = String::from_utf8_lossy(&bytes[value_start..value_end]).into_owned();
+            pos = next;
+        }
+        if !tag.attributes.iter().any(|(existing, _)| *existing == name) {
+            tag.attributes.push((name, value));
+        }
+    }
+}
+
+pub type SpeculativeFetch = Shared<BoxFuture<'static, Result<FetchResponse, String>>>;
+
+#[derive(Default)]
+struct PageFetches {
+    /// Started and not yet taken by the document.
+    pending: HashMap<String, SpeculativeFetch>,
+    /// Everything started this page, taken or not.
+    started: HashSet<String>,
+}
+
+/// The current page's speculative fetches.
+pub struct SpeculativeFetches {
+    page: Mutex<PageFetches>,
+    limit: usize,
+    low_priority: Arc<Semaphore>,
+}
+
+impl SpeculativeFetches {
+    /// At most `limit` fetches per page; zero turns speculation off.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            page: Mutex::new(PageFetches::default()),
+            limit,
+            low_priority: Arc::new(Semaphore::new(LOW_PRIORITY_SPECULATIVE_FETCHES)),
+        }
+    }
+
+    /// Speculative fetches of another page, under the same limit and
+    /// sharing the low-priority slots.
+    pub fn for_other_page(&self) -> Self {
+        Self {
+            page: Mutex::new(PageFetches::default()),
+            limit: self.limit,
+            low_priority: self.low_priority.clone(),
+        }
+    }
+
+    pub fn low_priority(&self) -> Arc<Semaphore> {
+        self.low_priority.clone()
+    }
+
+    /// Track the fetch `start` makes for `url`, unless `url` was started
+    /// this page already or the page is at its limit.
+    pub fn start(
+        &self,
+        url: &str,
+        start: impl FnOnce() -> SpeculativeFetch,
+    ) -> Option<SpeculativeFetch> {
+        let mut page = self.page.lock();
+        if page.started.len() >= self.limit || page.started.contains(url) {
+            return None;
+        }
+        let fetch = start();
+        page.started.insert(url.to_string());
+        page.pending.insert(url.to_string(), fetch.clone());
+        Some(fetch)
+    }
+
+    /// The fetch started for `url`, for the document's own request of it.
+    pub fn take(&self, url: &str) -> Option<SpeculativeFetch> {
+        self.page.lock().pending.remove(url)
+    }
+
+    /// Forget the page; returns how many of its fetches went unused.
+    pub fn reset(&self) -> usize {
+        let mut page = self.page.lock();
+        let wasted = page.pending.len();
+        *page = PageFetches::default();
+        wasted
+    }
+}
+
+/// Sees a successful response's body as it arrives.
+pub trait BodyObserver: Send {
+    /// Called once, before any chunk, with the URL the response came from
+    /// after redirects.
+    fn start(&mut self, url: &Url, headers: &HashMap<String, String>);
+
+    fn chunk(&mut self, bytes: &[u8]);
+
+    /// Whether the observer keeps the chunks itself, so the response comes
+    /// back without its body.
+    fn takes_body(&self) -> bool {
+        false
+    }
+}
+
+/// Scans a navigation response as it downloads and preloads what it finds.
+pub struct Preloader {
+    network: Arc<super::NetworkManager>,
+    scan: Option<(PreloadScanner, RequestInitiator)>,
+}
+
+impl Preloader {
+    pub fn new(network: Arc<super::NetworkManager>) -> Self {
+        Self {
+            network,
+            scan: None,
+        }
+    }
+}
+
+impl BodyObserver for Preloader {
+    fn start(&mut self, url: &Url, headers: &HashMap<String, String>) {
+        let csp = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-security-policy"))
+            .map(|(_, value)| super::ContentSecurityPolicy::parse(value));
+        self.scan = Some((
+            PreloadScanner::new(url.clone()),
+            RequestInitiator::new(url.clone()).with_csp(csp),
+        ));
+    }
+
+    fn chunk(&mut self, bytes: &[u8]) {
+        if let Some((scanner, initiator)) = &mut self.scan {
+            for preload in scanner.feed(bytes) {
+                self.network.preload(preload, initiator);
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Sending a request again after a transient failure.
+//!
+//! A request is retried when sending it again is safe and may help. It is
+//! safe when the method is GET or HEAD, or the caller marked the request
+//! [`idempotent`](super::FetchRequest::idempotent); a POST is only retried
+//! that way. It may help when the connection failed, the response timed
+//! out before it began, the stream was refused (an HTTP/2 GOAWAY or
+//! `REFUSED_STREAM`), or the server answered 408, or 429 or 503 with a
+//! `Retry-After`. Once a streaming consumer saw the response, it is not
+//! retried.
+//!
+//! Retries wait out an exponential backoff with jitter, or the
+//! `Retry-After` the response asked for. A wait longer than
+//! [`RetryConfig::max_delay_ms`] is not waited: the response is returned.
+
+use super::NetworkError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Retries after the first attempt; zero turns retrying off.
+    pub max_retries: u32,
+    /// The backoff before the first retry; each further one doubles it.
+    pub base_delay_ms: u64,
+    /// The longest wait before a retry.
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay_ms: 250,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// The wait before retry `retry`, counted from zero: the base delay
+    /// doubled that many times, less up to half of it at random.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let full = self
+            .base_delay_ms
+            .saturating_mul(1u64 << retry.min(32))
+            .min(self.max_delay_ms);
+        Duration::from_millis(full - fastrand::u64(..=full / 2))
+    }
+
+    pub fn max_delay(&self) -> Duration {
+        Duration::from_millis(self.max_delay_ms)
+    }
+}
+
+/// Whether sending `method` twice does no more than sending it once.
+pub fn is_idempotent(method: &str) -> bool {
+    method.eq_ignore_ascii_case("GET") || method.eq_ignore_ascii_case("HEAD")
+}
+
+/// Whether `error` came before any response and may not happen again.
+pub fn is_transient(error: &NetworkError) -> bool {
+    matches!(
+        error,
+        NetworkError::Connection(_) | NetworkError::Timeout(_) | NetworkError::DnsResolution(_)
+    )
+}
+
+/// How long to wait before retrying a response with `status`; `None` when
+/// it is not one to retry. A 408 may be retried at once.
+pub fn retry_after(
+    status: u16,
+    headers: &HashMap<String, String>,
+    now: SystemTime,
+) -> Option<Duration> {
+    let header = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))
+        .and_then(|(_, value)| parse_retry_after(value, now));
+    match status {
+        408 => Some(header.unwrap_or(Duration::ZERO)),
+        429 | 503 => header,
+        _ => None,
+    }
+}
+
+/// A `Retry-After` value, delay-seconds or an HTTP date, as a wait from
+/// `now`.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let at = SystemTime::UNIX_EPOCH + Duration::from_secs(date.timestamp().max(0) as u64);
+    Some(at.duration_since(now).unwrap_or(Duration::ZERO))
+}
@@ -0,0 +1,183 @@
+//! `fetch()` from script.
+//!
+//! Requests run on the Tokio runtime and settle at the next engine tick,
+//! which resolves their promises in the document's context. Navigating away
+//! aborts whatever is still in flight; ids are never reused, so a result that
+//! slips through matches no promise of the next document. A page has at most
+//! [`ScriptFetches::new`]'s limit in flight; past it, `fetch()` rejects.
+
+use super::{FetchRequest, FetchResponse, NetworkError, NetworkManager, RequestInitiator, Result};
+use parking_lot::Mutex;
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_MAX_SCRIPT_FETCHES: usize = 256;
+
+/// Whether script may not set the request header `name`: the browser owns
+/// it, as the Fetch standard's forbidden request headers.
+pub fn is_forbidden_request_header(name: &str) -> bool {
+    const FORBIDDEN: [&str; 21] = [
+        "accept-charset",
+        "accept-encoding",
+        "access-control-request-headers",
+        "access-control-request-method",
+        "connection",
+        "content-length",
+        "cookie",
+        "cookie2",
+        "date",
+        "dnt",
+        "expect",
+        "host",
+        "keep-alive",
+        "origin",
+        "referer",
+        "set-cookie",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+        "via",
+    ];
+    let name = name.to_ascii_lowercase();
+    FORBIDDEN.contains(&name.as_str()) || name.starts_with("proxy-") || name.starts_with("sec-")
+}
+
+/// A script fetch that finished, well or not.
+pub struct SettledFetch {
+    pub id: u64,
+    pub outcome: std::result::Result<FetchResponse, String>,
+}
+
+impl SettledFetch {
+    /// The form `__vbeFetchSettle` takes: the response with header names
+    /// lower-cased and the body as a byte string (one char per byte), or an
+    /// `error` message.
+    pub fn to_json(&self) -> Value {
+        match &self.outcome {
+            Ok(response) => {
+                let headers: Map<String, Value> = response
+                    .headers
+                    .iter()
+                    .map(|(name, value)| (name.to_ascii_lowercase(), json!(value)))
+                    .collect();
+                let body: String = response.body.iter().map(|&byte| byte as char).collect();
+                json!({
+                    "id": self.id.to_string(),
+                    "status": response.status,
+                    "url": response.url,
+                    "redirected": response.redirected,
+                    "headers": headers,
+                    "body": body,
+                })
+            }
+            Err(message) => json!({ "id": self.id.to_string(), "error": message }),
+        }
+    }
+}
+
+/// The script fetches of the current document.
+pub struct ScriptFetches {
+    next_id: AtomicU64,
+    settled: Arc<Mutex<Vec<SettledFetch>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Default for ScriptFetches {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SCRIPT_FETCHES)
+    }
+}
+
+/// A fetch counted in flight until its task ends or is aborted.
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ScriptFetches {
+    /// Fetches for a page that may have `max_in_flight` at once.
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            settled: Arc::new(Mutex::new(Vec::new())),
+            tasks: Mutex::new(Vec::new()),
+            max_in_flight,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Start `request` on behalf of `initiator`'s document and return its
+    /// id. The URL resolves against the document and must pass CSP
+    /// `connect-src`; the page must have fewer than its limit in flight.
+    pub fn start(
+        &self,
+        network: Arc<NetworkManager>,
+        mut request: FetchRequest,
+        initiator: RequestInitiator,
+    ) -> Result<u64> {
+        let url = initiator
+            .document_url
+            .join(&request.url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Invalid URL: {}", e)))?;
+        if !initiator.allows_connect(&url) {
+            return Err(NetworkError::SecurityPolicy(format!(
+                "Refused to connect to '{}' (Content-Security-Policy connect-src)",
+                url
+            )));
+        }
+        let handle = tokio::runtime::Handle::try_current().map_err(|_| {
+            NetworkError::RequestFailed("Script fetches need a Tokio runtime".to_string())
+        })?;
+        request.url = url.to_string();
+        let max = self.max_in_flight;
+        self.in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .map_err(|_| {
+                NetworkError::RequestFailed(format!(
+                    "Too many fetches in flight (the page may have {})",
+                    max
+                ))
+            })?;
+        let in_flight = InFlight(self.in_flight.clone());
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let settled = self.settled.clone();
+        let task = handle.spawn(async move {
+            let _in_flight = in_flight;
+            let outcome = network
+                .fetch_subresource(request, &initiator)
+                .await
+                .map_err(|e| e.to_string());
+            settled.lock().push(SettledFetch { id, outcome });
+        });
+
+        let mut tasks = self.tasks.lock();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+        Ok(id)
+    }
+
+    /// Fetches that finished since the last call.
+    pub fn take_settled(&self) -> Vec<SettledFetch> {
+        std::mem::take(&mut *self.settled.lock())
+    }
+
+    /// Abort every fetch in flight and drop unclaimed results; the document
+    /// that made them is gone.
+    pub fn reset(&self) {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.settled.lock().clear();
+    }
+}
@@ -0,0 +1,430 @@
+//! TLS details of HTTPS responses, certificate pinning, and the security
+//! state of the page they add up to.
+//!
+//! reqwest reports no more of a connection than its leaf certificate, so
+//! every client is built on a rustls config whose certificate verifier and
+//! session store are a [`TlsMonitor`]. As verifier it sees the chain of each
+//! full handshake, enforces the host's pins and self-signed allowance, and
+//! learns the protocol version from the signature check it is asked for. As
+//! session store it sees the TLS 1.3 tickets a server hands out, which name
+//! the cipher suite, and any session the client offers back: a handshake
+//! that offered one and then verified no certificate was resumed. rustls
+//! keeps a TLS 1.2 session's cipher suite to itself, so the suite is only
+//! known for TLS 1.3.
+//!
+//! Handshakes are recorded per server name and matched to a response by its
+//! host and leaf certificate. A response gets the version, suite and
+//! resumption of its host's latest handshake, which is its own connection's
+//! unless another connection to the host was set up meanwhile.
+
+use super::x509::CertificateInfo;
+use super::FetchResponse;
+use parking_lot::Mutex;
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, HandshakeSignatureValid, Resumption,
+    ServerCertVerified, ServerCertVerifier, Tls12ClientSessionValue, Tls13ClientSessionValue,
+    WebPkiVerifier,
+};
+use rustls::{
+    Certificate, DigitallySignedStruct, Error, NamedGroup, OwnedTrustAnchor, RootCertStore,
+    ServerName, SignatureScheme,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::SystemTime;
+use url::Url;
+
+/// Sessions kept for resumption, across all hosts.
+const SESSION_CACHE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// The TLS connection a response came over.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    /// `None` when the handshake ran no check that tells.
+    pub version: Option<TlsVersion>,
+    /// IANA name, as `TLS13_AES_128_GCM_SHA256`; see the module docs for
+    /// when it is known.
+    pub cipher_suite: Option<String>,
+    /// The server's chain, leaf first, as it was verified.
+    pub certificates: Vec<CertificateInfo>,
+    /// Set up by resuming an earlier session rather than a full handshake.
+    pub resumed: bool,
+    /// The chain did not verify and was accepted only because the host is
+    /// in [`TlsConfig::allow_self_signed`].
+    pub self_signed_allowed: bool,
+}
+
+/// Certificate pins and self-signed exceptions, by host.
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// SHA-256 hashes of SubjectPublicKeyInfos, as
+    /// [`CertificateInfo::spki_sha256`]. A listed host's chain must include
+    /// one of them; a connection whose chain does not fails.
+    pub pins: HashMap<String, Vec<[u8; 32]>>,
+    /// Hosts whose self-signed certificate is accepted, provided it names
+    /// the host and is in date. Pages using one are only secure with
+    /// warnings.
+    pub allow_self_signed: HashSet<String>,
+}
+
+/// How far the current page can be trusted, as a browser's address bar
+/// shows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityState {
+    /// An HTTPS document whose every load came over verified HTTPS.
+    Secure,
+    /// An HTTPS document that loaded something over plain HTTP, or used a
+    /// certificate accepted only as an allowed self-signed one.
+    SecureWithWarnings,
+    /// Anything but an HTTPS document, including no document yet.
+    Insecure,
+}
+
+/// Certificate verifier and session store of every client; see the module
+/// docs.
+pub(crate) struct TlsMonitor {
+    config: TlsConfig,
+    webpki: WebPkiVerifier,
+    sessions: ClientSessionMemoryCache,
+    hosts: Mutex<HashMap<String, HostHandshakes>>,
+    // Pin failures not yet turned into a request error, by host.
+    pin_failures: Mutex<HashMap<String, String>>,
+    // Pin failures not yet reported to the embedder.
+    violations: Mutex<Vec<String>>,
+}
+
+#[derive(Default)]
+struct HostHandshakes {
+    /// Verified chains by leaf certificate, each with whether it was
+    /// accepted as an allowed self-signed one.
+    chains: HashMap<Vec<u8>, (Vec<CertificateInfo>, bool)>,
+    latest: Handshake,
+}
+
+#[derive(Default)]
+struct Handshake {
+    leaf: Vec<u8>,
+    version: Option<TlsVersion>,
+    cipher_suite: Option<String>,
+    resumed: bool,
+}
+
+impl TlsMonitor {
+    pub(crate) fn new(config: TlsConfig) -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        Self {
+            config,
+            webpki: WebPkiVerifier::new(roots, None),
+            sessions: ClientSessionMemoryCache::new(SESSION_CACHE_SIZE),
+            hosts: Mutex::new(HashMap::new()),
+            pin_failures: Mutex::new(HashMap::new()),
+            violations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The rustls config for a client, offering HTTP/2 over ALPN when
+    /// `http2` is set.
+    pub(crate) fn client_config(self: &Arc<Self>, http2: bool) -> rustls::ClientConfig {
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(self.clone())
+            .with_no_client_auth();
+        config.resumption = Resumption::store(self.clone());
+        config.alpn_protocols = if http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        config
+    }
+
+    /// What is known of the connection to `host` that presented `leaf`.
+    pub(crate) fn response_info(&self, host: &str, leaf: &[u8]) -> Option<TlsInfo> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let hosts = self.hosts.lock();
+        let handshakes = hosts.get(host)?;
+        let (certificates, self_signed_allowed) = handshakes.chains.get(leaf)?;
+        let latest = &handshakes.latest;
+        Some(TlsInfo {
+            version: latest.version,
+            cipher_suite: latest.cipher_suite.clone(),
+            certificates: certificates.clone(),
+            resumed: latest.resumed,
+            self_signed_allowed: *self_signed_allowed,
+        })
+    }
+
+    /// Why the last connection to `host` failed its pins, if it did.
+    pub(crate) fn take_pin_failure(&self, host: &str) -> Option<String> {
+        self.pin_failures.lock().remove(host)
+    }
+
+    pub(crate) fn take_violations(&self) -> Vec<String> {
+        std::mem::take(&mut *self.violations.lock())
+    }
+
+    fn allows_self_signed(&self, host: &str, chain: &[CertificateInfo], now: SystemTime) -> bool {
+        self.config.allow_self_signed.contains(host)
+            && match chain {
+                [leaf] => leaf.is_self_issued() && leaf.is_valid_at(now) && leaf.matches_host(host),
+                _ => false,
+            }
+    }
+
+    fn record_version(&self, leaf: &Certificate, version: TlsVersion) {
+        for handshakes in self.hosts.lock().values_mut() {
+            let latest = &mut handshakes.latest;
+            if latest.leaf == leaf.0 && !latest.resumed && latest.version.is_none() {
+                latest.version = Some(version);
+            }
+        }
+    }
+
+    /// The client offered a stored session to `server_name`.
+    fn record_offer(
+        &self,
+        server_name: &ServerName,
+        version: TlsVersion,
+        cipher_suite: Option<String>,
+    ) {
+        let mut hosts = self.hosts.lock();
+        let latest = &mut hosts.entry(host_name(server_name)).or_default().latest;
+        *latest = Handshake {
+            leaf: std::mem::take(&mut latest.leaf),
+            version: Some(version),
+            cipher_suite,
+            resumed: true,
+        };
+    }
+}
+
+impl ServerCertVerifier for TlsMonitor {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let host = host_name(server_name);
+        let verified = self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        let chain: Vec<CertificateInfo> = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|certificate| CertificateInfo::parse(&certificate.0))
+            .collect();
+        let self_signed_allowed = match verified {
+            Ok(_) => false,
+            Err(_) if self.allows_self_signed(&host, &chain, now) => {
+                tracing::warn!("Accepting the self-signed certificate of {}", host);
+                true
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(pins) = self.config.pins.get(&host) {
+            if !chain
+                .iter()
+                .any(|certificate| pins.contains(&certificate.spki_sha256))
+            {
+                let reason = format!("Certificate pin mismatch for {}", host);
+                tracing::warn!("{}", reason);
+                self.pin_failures.lock().insert(host, reason.clone());
+                self.violations.lock().push(reason.clone());
+                return Err(Error::General(reason));
+            }
+        }
+
+        let mut hosts = self.hosts.lock();
+        let handshakes = hosts.entry(host).or_default();
+        handshakes.latest = Handshake {
+            leaf: end_entity.0.clone(),
+            ..Handshake::default()
+        };
+        handshakes
+            .chains
+            .insert(end_entity.0.clone(), (chain, self_signed_allowed));
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        let valid = self.webpki.verify_tls12_signature(message, cert, dss)?;
+        self.record_version(cert, TlsVersion::Tls12);
+        Ok(valid)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        let valid = self.webpki.verify_tls13_signature(message, cert, dss)?;
+        self.record_version(cert, TlsVersion::Tls13);
+        Ok(valid)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+impl ClientSessionStore for TlsMonitor {
+    fn set_kx_hint(&self, server_name: &ServerName, group: NamedGroup) {
+        self.sessions.set_kx_hint(server_name, group);
+    }
+
+    fn kx_hint(&self, server_name: &ServerName) -> Option<NamedGroup> {
+        self.sessions.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: &ServerName, value: Tls12ClientSessionValue) {
+        self.sessions.set_tls12_session(server_name, value);
+    }
+
+    fn tls12_session(&self, server_name: &ServerName) -> Option<Tls12ClientSessionValue> {
+        let value = self.sessions.tls12_session(server_name)?;
+        self.record_offer(server_name, TlsVersion::Tls12, None);
+        Some(value)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName) {
+        self.sessions.remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(&self, server_name: &ServerName, value: Tls13ClientSessionValue) {
+        if let Some(handshakes) = self.hosts.lock().get_mut(&host_name(server_name)) {
+            handshakes
+                .latest
+                .cipher_suite
+                .get_or_insert_with(|| suite_name(&value));
+        }
+        self.sessions.insert_tls13_ticket(server_name, value);
+    }
+
+    fn take_tls13_ticket(&self, server_name: &ServerName) -> Option<Tls13ClientSessionValue> {
+        let value = self.sessions.take_tls13_ticket(server_name)?;
+        self.record_offer(server_name, TlsVersion::Tls13, Some(suite_name(&value)));
+        Some(value)
+    }
+}
+
+fn host_name(server_name: &ServerName) -> String {
+    match server_name {
+        ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+        ServerName::IpAddress(ip) => ip.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn suite_name(value: &Tls13ClientSessionValue) -> String {
+    let suite = value.suite().common.suite;
+    match suite.as_str() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", suite),
+    }
+}
+
+/// The security state of the current page, from its document and
+/// everything it loaded.
+pub(crate) struct PageSecurity {
+    inner: Mutex<PageSecurityInner>,
+}
+
+struct PageSecurityInner {
+    /// Whether the document came over HTTPS; `None` until it arrives.
+    document_https: Option<bool>,
+    warnings: bool,
+    /// The state last handed out by `take_change`.
+    reported: SecurityState,
+}
+
+impl PageSecurity {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Mutex::new(PageSecurityInner {
+                document_https: None,
+                warnings: false,
+                reported: SecurityState::Insecure,
+            }),
+        }
+    }
+
+    /// A new page, whose document has not arrived yet.
+    pub(crate) fn begin(&self) {
+        let mut inner = self.inner.lock();
+        inner.document_https = None;
+        inner.warnings = false;
+    }
+
+    pub(crate) fn document_loaded(&self, response: &FetchResponse) {
+        self.inner.lock().document_https = Some(is_https(&response.url));
+        self.loaded(response);
+    }
+
+    /// Any response of the current page, its document's included.
+    pub(crate) fn loaded(&self, response: &FetchResponse) {
+        let self_signed = response
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.self_signed_allowed);
+        if self_signed || !is_https(&response.url) {
+            self.inner.lock().warnings = true;
+        }
+    }
+
+    pub(crate) fn state(&self) -> SecurityState {
+        state_of(&self.inner.lock())
+    }
+
+    /// The state, when it differs from what this last returned.
+    pub(crate) fn take_change(&self) -> Option<SecurityState> {
+        let mut inner = self.inner.lock();
+        let state = state_of(&inner);
+        if state == inner.reported {
+            return None;
+        }
+        inner.reported = state;
+        Some(state)
+    }
+}
+
+fn state_of(inner: &PageSecurityInner) -> SecurityState {
+    match inner.document_https {
+        Some(true) if inner.warnings => SecurityState::SecureWithWarnings,
+        Some(true) => SecurityState::Secure,
+        _ => SecurityState::Insecure,
+    }
+}
+
+fn is_https(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.scheme() == "https")
+}
@@ -0,0 +1,175 @@
+//! What puts a request on the wire.
+//!
+//! [`NetworkManager`](super::NetworkManager) does everything a fetch needs
+//! above the connection itself: policy, caching, authentication, limits,
+//! cancellation and timeouts. The exchange is left to a [`Transport`],
+//! which is [`ReqwestTransport`] unless the manager is built with another.
+//! Tests swap in the `test-util` feature's
+//! [`MockTransport`](super::mock::MockTransport) to load pages without
+//! opening a socket.
+
+use super::tls::{TlsInfo, TlsMonitor};
+use super::{ConnectionPool, NetworkConfig, NetworkError, Priority, Result};
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, StreamExt};
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// A response body as it arrives; an error ends it early.
+pub type BodyStream = BoxStream<'static, Result<Vec<u8>>>;
+
+/// A request that passed the manager's checks and missed the cache.
+#[derive(Debug, Clone)]
+pub struct PreparedRequest {
+    pub url: Url,
+    pub method: String,
+    pub headers: HashMap<String, String>,
+    pub body: Option<Vec<u8>>,
+    pub priority: Priority,
+}
+
+/// A response whose headers arrived; the body may still be on its way.
+pub struct RawResponse {
+    pub status: u16,
+    /// Where the response came from. Redirects are not followed: they come
+    /// back as they are, for the manager to check and follow.
+    pub url: Url,
+    /// One value per header; repeated `Set-Cookie` headers are joined with
+    /// newlines, which their values cannot contain.
+    pub headers: HashMap<String, String>,
+    pub tls: Option<TlsInfo>,
+    pub body: BodyStream,
+}
+
+pub trait Transport: Send + Sync {
+    /// Send `request` and wait for the response headers. Timeouts and
+    /// cancellation are the caller's: it drops the future.
+    fn execute(&self, request: PreparedRequest) -> BoxFuture<'static, Result<RawResponse>>;
+}
+
+/// The network, through pooled reqwest clients.
+pub struct ReqwestTransport {
+    pool: Arc<ConnectionPool>,
+    tls: Arc<TlsMonitor>,
+    config: NetworkConfig,
+}
+
+impl ReqwestTransport {
+    pub(crate) fn new(
+        pool: Arc<ConnectionPool>,
+        tls: Arc<TlsMonitor>,
+        config: NetworkConfig,
+    ) -> Self {
+        Self { pool, tls, config }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn execute(&self, request: PreparedRequest) -> BoxFuture<'static, Result<RawResponse>> {
+        let host = request.url.host_str().unwrap_or("localhost").to_string();
+        let client = self.pool.get_client(&host, &self.config);
+        let tls = self.tls.clone();
+        async move {
+            let client = client?;
+            let url = request.url.as_str();
+            let mut req_builder = match request.method.as_str() {
+                "GET" => client.get(url),
+                "POST" => client.post(url),
+                "PUT" => client.put(url),
+                "DELETE" => client.delete(url),
+                "HEAD" => client.head(url),
+                "PATCH" => client.patch(url),
+                _ => {
+                    return Err(NetworkError::RequestFailed(format!(
+                        "Unsupported method: {}",
+                        request.method
+                    )))
+                }
+            };
+            for (key, value) in &request.headers {
+                req_builder = req_builder.header(key, value);
+            }
+            if let Some(body) = request.body {
+                req_builder = req_builder.body(body);
+            }
+
+            // A pin failure is the certificate's fault, not the connection's.
+            let response =
+                req_builder
+                    .send()
+                    .await
+                    .map_err(|e| match tls.take_pin_failure(&host) {
+                        Some(reason) => NetworkError::SecurityPolicy(reason),
+                        None => send_error(e),
+                    })?;
+            let info = response
+                .extensions()
+                .get::<reqwest::tls::TlsInfo>()
+                .and_then(|info| info.peer_certificate())
+                .and_then(|leaf| tls.response_info(response.url().host_str().unwrap_or(""), leaf));
+
+            Ok(RawResponse {
+                status: response.status().as_u16(),
+                url: response.url().clone(),
+                headers: collect_headers(response.headers()),
+                tls: info,
+                body: response
+                    .bytes_stream()
+                    .map(|chunk| {
+                        chunk.map(|bytes| bytes.to_vec()).map_err(|e| {
+                            NetworkError::RequestFailed(format!("Failed to read body: {}", e))
+                        })
+                    })
+                    .boxed(),
+            })
+        }
+        .boxed()
+    }
+}
+
+/// Sorts a failed send into what [`retry`](super::retry) tells apart: the
+/// connection failing or the stream being refused, a timeout, or anything
+/// else.
+fn send_error(error: reqwest::Error) -> NetworkError {
+    if error.is_connect() || stream_refused(&error) {
+        NetworkError::Connection(error.to_string())
+    } else if error.is_timeout() {
+        NetworkError::Timeout(error.to_string())
+    } else {
+        NetworkError::RequestFailed(error.to_string())
+    }
+}
+
+/// Whether the server sent GOAWAY or refused the stream. reqwest's h2 is
+/// not the version this crate depends on, so its errors are recognised by
+/// their messages.
+fn stream_refused(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        let message = cause.to_string();
+        if message.starts_with("connection error received") || message.contains("refused stream") {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+fn collect_headers(headers: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    let mut collected: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+        let value = value.to_str().unwrap_or("");
+        match collected.get_mut(name.as_str()) {
+            Some(joined) if name == reqwest::header::SET_COOKIE => {
+                joined.push('\n');
+                joined.push_str(value);
+            }
+            _ => {
+                collected.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    collected
+}
@@ -0,0 +1,304 @@
+//! The parts of an X.509 certificate shown to embedders: names, validity,
+//! subject alternative names and the hash certificate pins are made of.
+//!
+//! Nothing here verifies anything; webpki does that during the handshake.
+//! This only reads a DER certificate far enough to describe it.
+
+use ring::digest::{digest, SHA256};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An entry of a certificate's subject alternative name extension. Other
+/// kinds of name are skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+}
+
+/// One certificate of a server's chain.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub der: Vec<u8>,
+    /// Distinguished name, attributes in certificate order, as
+    /// `C=US, O=Example, CN=example.com`.
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// SHA-256 of the DER SubjectPublicKeyInfo, the value pins are made of.
+    pub spki_sha256: [u8; 32],
+}
+
+impl CertificateInfo {
+    /// `None` when `der` is not a certificate this can read.
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let mut certificate = Der::new(der).sequence()?;
+        let mut tbs = certificate.sequence()?;
+        if tbs.peek() == Some(CONTEXT_0) {
+            tbs.element(CONTEXT_0)?;
+        }
+        tbs.element(INTEGER)?;
+        tbs.sequence()?;
+        let issuer = distinguished_name(tbs.sequence()?)?;
+        let mut validity = tbs.sequence()?;
+        let not_before = validity.time()?;
+        let not_after = validity.time()?;
+        let subject = distinguished_name(tbs.sequence()?)?;
+        let spki = tbs.raw(SEQUENCE)?;
+
+        let mut subject_alt_names = Vec::new();
+        while let Some((tag, contents)) = tbs.next() {
+            if tag == CONTEXT_3 {
+                subject_alt_names = alt_names(Der::new(contents).sequence()?)?;
+            }
+        }
+
+        let mut spki_sha256 = [0; 32];
+        spki_sha256.copy_from_slice(digest(&SHA256, spki).as_ref());
+        Some(Self {
+            der: der.to_vec(),
+            subject,
+            issuer,
+            not_before,
+            not_after,
+            subject_alt_names,
+            spki_sha256,
+        })
+    }
+
+    /// Issued by itself, as a self-signed certificate is.
+    pub fn is_self_issued(&self) -> bool {
+        self.subject == self.issuer
+    }
+
+    pub fn is_valid_at(&self, time: SystemTime) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+
+    /// Whether a subject alternative name covers `host`: an IP address
+    /// entry equal to it, or a DNS name matching it, `*.` matching one
+    /// leftmost label.
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let ip = host.parse::<IpAddr>().ok();
+        self.subject_alt_names.iter().any(|name| match (name, ip) {
+            (SubjectAltName::Ip(name), Some(ip)) => *name == ip,
+            (SubjectAltName::Dns(name), None) => match name.strip_prefix("*.") {
+                Some(suffix) => host
+                    .split_once('.')
+                    .is_some_and(|(_, rest)| rest.eq_ignore_ascii_case(suffix)),
+                None => name.eq_ignore_ascii_case(host),
+            },
+            _ => false,
+        })
+    }
+}
+
+const INTEGER: u8 = 0x02;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const CONTEXT_0: u8 = 0xa0;
+const CONTEXT_3: u8 = 0xa3;
+const DNS_NAME: u8 = 0x82;
+const IP_ADDRESS: u8 = 0x87;
+
+/// 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Reads DER elements one after another.
+struct Der<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// The next element's tag and contents, with the whole encoded element.
+    fn next_raw(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let tag = *self.data.first()?;
+        let first = *self.data.get(1)?;
+        let (length, header) = if first < 0x80 {
+            (first as usize, 2)
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 {
+                return None;
+            }
+            let bytes = self.data.get(2..2 + count)?;
+            let length = bytes
+                .iter()
+                .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+            (length, 2 + count)
+        };
+        let end = header.checked_add(length)?;
+        let whole = self.data.get(..end)?;
+        self.data = &self.data[end..];
+        Some((tag, &whole[header..], whole))
+    }
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        self.next_raw().map(|(tag, contents, _)| (tag, contents))
+    }
+
+    /// Contents of the next element, which must be tagged `tag`.
+    fn element(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.next()? {
+            (found, contents) if found == tag => Some(contents),
+            _ => None,
+        }
+    }
+
+    /// The whole encoding of the next element, which must be tagged `tag`.
+    fn raw(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.next_raw()? {
+            (found, _, whole) if found == tag => Some(whole),
+            _ => None,
+        }
+    }
+
+    fn sequence(&mut self) -> Option<Der<'a>> {
+        self.element(SEQUENCE).map(Der::new)
+    }
+
+    fn time(&mut self) -> Option<SystemTime> {
+        let (tag, contents) = self.next()?;
+        let text = std::str::from_utf8(contents).ok()?.strip_suffix('Z')?;
+        let (year, rest) = match tag {
+            // Two-digit years are 1950 to 2049.
+            UTC_TIME => {
+                let year: i32 = text.get(..2)?.parse().ok()?;
+                (
+                    if year < 50 { 2000 + year } else { 1900 + year },
+                    &text[2..],
+                )
+            }
+            GENERALIZED_TIME => (text.get(..4)?.parse().ok()?, &text[4..]),
+            _ => return None,
+        };
+        if rest.len() != 10 || !rest.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let field = |at: usize| rest[at..at + 2].parse::<u32>().ok();
+        let seconds = chrono::NaiveDate::from_ymd_opt(year, field(0)?, field(2)?)?
+            .and_hms_opt(field(4)?, field(6)?, field(8)?)?
+            .and_utc()
+            .timestamp();
+        Some(if seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(seconds as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+        })
+    }
+}
+
+fn distinguished_name(mut name: Der) -> Option<String> {
+    let mut attributes = Vec::new();
+    while name.peek().is_some() {
+        let mut rdn = Der::new(name.element(SET)?);
+        while rdn.peek().is_some() {
+            let mut attribute = rdn.sequence()?;
+            let oid = attribute.element(OBJECT_IDENTIFIER)?;
+            let (tag, value) = attribute.next()?;
+            attributes.push(format!(
+                "{}={}",
+                attribute_name(oid),
+                string_value(tag, value)
+            ));
+        }
+    }
+    Some(attributes.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x05] => "SERIALNUMBER".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "E".to_string(),
+        _ => dotted(oid),
+    }
+}
+
+fn dotted(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut value = 0u64;
+    for &byte in oid {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Directory strings: BMPString is UTF-16, the rest are read as UTF-8.
+fn string_value(tag: u8, value: &[u8]) -> String {
+    const BMP_STRING: u8 = 0x1e;
+    if tag == BMP_STRING {
+        let units: Vec<u16> = value
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    String::from_utf8_lossy(value).into_owned()
+}
+
+fn alt_names(mut extensions: Der) -> Option<Vec<SubjectAltName>> {
+    let mut names = Vec::new();
+    while extensions.peek().is_some() {
+        let mut extension = extensions.sequence()?;
+        if extension.element(OBJECT_IDENTIFIER)? != SUBJECT_ALT_NAME {
+            continue;
+        }
+        // `critical` is a BOOLEAN that may come before the value.
+        let value = match extension.next()? {
+            (0x01, _) => extension.element(0x04)?,
+            (0x04, value) => value,
+            _ => return None,
+        };
+        let mut general_names = Der::new(value).sequence()?;
+        while let Some((tag, contents)) = general_names.next() {
+            match tag {
+                DNS_NAME => names.push(SubjectAltName::Dns(
+                    String::from_utf8_lossy(contents).into_owned(),
+                )),
+                IP_ADDRESS => {
+                    let ip = match contents.len() {
+                        4 => IpAddr::from(<[u8; 4]>::try_from(contents).ok()?),
+                        16 => IpAddr::from(<[u8; 16]>::try_from(contents).ok()?),
+                        _ => continue,
+                    };
+                    names.push(SubjectAltName::Ip(ip));
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(names)
+}
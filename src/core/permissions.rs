@@ -0,0 +1,118 @@
+//! Per-origin decisions on what pages may use.
+//!
+//! Grants are keyed by the serialized origin of a tuple origin and last for
+//! the session; nothing is written to disk. An origin without a decision of
+//! its own, and every opaque origin, gets the permission's default.
+
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use url::Url;
+
+/// A web-facing feature gated per origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// `speechSynthesis.speak()`.
+    SpeechSynthesis,
+    /// `navigator.clipboard.read()` and `readText()`.
+    ClipboardRead,
+    /// `navigator.clipboard.write()` and `writeText()`.
+    ClipboardWrite,
+    /// Media `play()` without a user gesture. Denied, only muted media
+    /// plays without one.
+    Autoplay,
+    /// `<a ping>` requests as the origin's links are followed.
+    /// `BrowserConfig::enable_hyperlink_auditing` off sends none anywhere.
+    HyperlinkAuditing,
+}
+
+impl Permission {
+    /// What an origin nobody decided for gets.
+    fn default_state(self) -> PermissionState {
+        match self {
+            // Browsers speak without asking; an embedder that wants read-aloud
+            // opt-in sets the default to denied.
+            Permission::SpeechSynthesis => PermissionState::Granted,
+            // Reading what the user copied elsewhere takes the embedder's
+            // say-so; overwriting it is what copy buttons do.
+            Permission::ClipboardRead => PermissionState::Denied,
+            Permission::ClipboardWrite => PermissionState::Granted,
+            // Pages play media when they like unless the embedder opts into
+            // a gesture-gated autoplay policy.
+            Permission::Autoplay => PermissionState::Granted,
+            // Browsers ping by default; the embedder's config switch is the
+            // global opt-out, a denial here the per-site one.
+            Permission::HyperlinkAuditing => PermissionState::Granted,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionState {
+    Granted,
+    Denied,
+}
+
+/// Session-only permission decisions, per origin.
+#[derive(Default)]
+pub struct PermissionStore {
+    decisions: RwLock<HashMap<(String, Permission), PermissionState>>,
+    defaults: RwLock<HashMap<Permission, PermissionState>>,
+}
+
+impl PermissionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The key `url`'s origin is stored under; `None` for opaque origins,
+    /// which cannot be granted anything of their own.
+    fn origin_key(url: &Url) -> Option<String> {
+        let origin = url.origin();
+        origin.is_tuple().then(|| origin.ascii_serialization())
+    }
+
+    /// Decide `permission` for the origin of `url`; `None` forgets the
+    /// decision so the default applies again. Opaque origins are ignored.
+    pub fn set(&self, url: &Url, permission: Permission, state: Option<PermissionState>) {
+        let origin = match Self::origin_key(url) {
+            Some(origin) => origin,
+            None => {
+                tracing::debug!("Ignoring a {:?} decision for opaque {}", permission, url);
+                return;
+            }
+        };
+        let mut decisions = self.decisions.write();
+        match state {
+            Some(state) => decisions.insert((origin, permission), state),
+            None => decisions.remove(&(origin, permission)),
+        };
+    }
+
+    /// What origins without a decision of their own get for `permission`.
+    pub fn set_default(&self, permission: Permission, state: PermissionState) {
+        self.defaults.write().insert(permission, state);
+    }
+
+    /// Whether the origin of `url` may use `permission`.
+    pub fn state(&self, url: &Url, permission: Permission) -> PermissionState {
+        let decided = Self::origin_key(url)
+            .and_then(|origin| self.decisions.read().get(&(origin, permission)).copied());
+        decided.unwrap_or_else(|| {
+            self.defaults
+                .read()
+                .get(&permission)
+                .copied()
+                .unwrap_or_else(|| permission.default_state())
+        })
+    }
+
+    pub fn is_granted(&self, url: &Url, permission: Permission) -> bool {
+        self.state(url, permission) == PermissionState::Granted
+    }
+
+    /// Forget every decision and default.
+    pub fn clear(&self) {
+        self.decisions.write().clear();
+        self.defaults.write().clear();
+    }
+}
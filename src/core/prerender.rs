@@ -0,0 +1,187 @@
+//! Pages loaded in the background so navigating to them is instant.
+//!
+//! A prerendered page is fetched, parsed, styled and laid out and runs its
+//! scripts like any other, but hidden: `document.visibilityState` is
+//! `hidden` and `document.prerendering` true, its timers run throttled,
+//! animation frames wait, its audio is muted, and what it asks of the
+//! embedder (printing, notifications) waits until it is shown. Activating it
+//! swaps it in for the tab's document, which then goes as on any navigation.
+//!
+//! A tab keeps at most [`PrerenderConfig::max_prerenders`] of them, within
+//! [`PrerenderConfig::memory_budget_bytes`]; past either, the oldest go.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_PRERENDERS: usize = 2;
+pub const DEFAULT_PRERENDER_MEMORY_BUDGET_BYTES: usize = 256 * 1024 * 1024;
+
+/// How often a prerendered page's timers run.
+pub const PRERENDER_TIMER_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrerenderConfig {
+    /// Prerendered pages a tab keeps; zero turns prerendering off.
+    pub max_prerenders: usize,
+    /// What a tab's prerendered pages may take together: script heap, DOM
+    /// and decoded images, as sampled once each has loaded.
+    pub memory_budget_bytes: usize,
+}
+
+impl Default for PrerenderConfig {
+    fn default() -> Self {
+        Self {
+            max_prerenders: DEFAULT_MAX_PRERENDERS,
+            memory_budget_bytes: DEFAULT_PRERENDER_MEMORY_BUDGET_BYTES,
+        }
+    }
+}
+
+/// A page prerendered for a tab. Activating one that was discarded
+/// navigates to its URL the ordinary way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PrerenderHandle {
+    id: u64,
+    url: String,
+}
+
+impl PrerenderHandle {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Why a prerendered page was dropped without being shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrerenderDiscardReason {
+    /// The response said `Cache-Control: no-store`.
+    NoStore,
+    /// The page failed to load.
+    LoadFailed,
+    /// Newer prerenders went past the count or the memory budget.
+    Evicted,
+}
+
+struct Entry<P> {
+    handle: PrerenderHandle,
+    page: P,
+    bytes: usize,
+    timers_run: Instant,
+}
+
+/// A tab's prerendered pages, oldest first.
+pub struct PrerenderSet<P> {
+    config: PrerenderConfig,
+    next_id: AtomicU64,
+    entries: Mutex<VecDeque<Entry<P>>>,
+}
+
+impl<P: Clone> PrerenderSet<P> {
+    pub fn new(config: &PrerenderConfig) -> Self {
+        Self {
+            config: config.clone(),
+            next_id: AtomicU64::new(1),
+            entries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.max_prerenders > 0
+    }
+
+    /// A new handle for prerendering `url`.
+    pub fn new_handle(&self, url: &str) -> PrerenderHandle {
+        PrerenderHandle {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            url: url.to_string(),
+        }
+    }
+
+    /// The handle of the page kept for `url`, if there is one.
+    pub fn find(&self, url: &str) -> Option<PrerenderHandle> {
+        self.entries
+            .lock()
+            .iter()
+            .find(|entry| entry.handle.url == url)
+            .map(|entry| entry.handle.clone())
+    }
+
+    /// Keep `page`, prerendered for `handle` and taking `bytes`. Returns
+    /// what went to make room, oldest first; `page` itself when it alone
+    /// is over the budget.
+    pub fn insert(
+        &self,
+        handle: PrerenderHandle,
+        page: P,
+        bytes: usize,
+    ) -> Vec<(PrerenderHandle, P)> {
+        let mut entries = self.entries.lock();
+        entries.push_back(Entry {
+            handle,
+            page,
+            bytes,
+            timers_run: Instant::now(),
+        });
+        let mut evicted = Vec::new();
+        loop {
+            let total: usize = entries.iter().map(|entry| entry.bytes).sum();
+            if entries.len() <= self.config.max_prerenders
+                && total <= self.config.memory_budget_bytes
+            {
+                break;
+            }
+            match entries.pop_front() {
+                Some(entry) => evicted.push((entry.handle, entry.page)),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Stop keeping the page of `handle`, and return it.
+    pub fn take(&self, handle: &PrerenderHandle) -> Option<P> {
+        let mut entries = self.entries.lock();
+        let index = entries.iter().position(|entry| entry.handle == *handle)?;
+        entries.remove(index).map(|entry| entry.page)
+    }
+
+    /// Stop keeping the page prerendered for `url`, and return it.
+    pub fn take_url(&self, url: &str) -> Option<(PrerenderHandle, P)> {
+        let handle = self.find(url)?;
+        let page = self.take(&handle)?;
+        Some((handle, page))
+    }
+
+    pub fn take_all(&self) -> Vec<(PrerenderHandle, P)> {
+        self.entries
+            .lock()
+            .drain(..)
+            .map(|entry| (entry.handle, entry.page))
+            .collect()
+    }
+
+    /// The pages whose timers are due at `now`, which count as run.
+    pub fn timers_due(&self, now: Instant) -> Vec<P> {
+        self.entries
+            .lock()
+            .iter_mut()
+            .filter(|entry| now.duration_since(entry.timers_run) >= PRERENDER_TIMER_INTERVAL)
+            .map(|entry| {
+                entry.timers_run = now;
+                entry.page.clone()
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
@@ -0,0 +1,359 @@
+//! Printing: `window.print()` requests, `@page` rules and print options.
+//!
+//! A page calling `window.print()` gets `beforeprint` right away and a
+//! pending request the engine announces as
+//! [`BrowserEvent::PrintRequested`](crate::BrowserEvent). The embedder
+//! resolves it once, printing with [`PrintOptions`] or dismissing it, and
+//! the page then gets `afterprint`. A request nobody resolves within
+//! [`PRINT_REQUEST_TIMEOUT`] is dismissed. Only one request is pending at a
+//! time; `window.print()` while one is pending does nothing.
+//!
+//! Lengths here are CSS pixels (96 per inch).
+
+pub mod pdf;
+
+use crate::core::css::{CSSPageRule, CSSRule};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// How long a print request may stay unresolved before it is dismissed.
+pub const PRINT_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum PrintError {
+    #[error("Print request {0} is not pending")]
+    NotPending(u64),
+    #[error("Invalid page size: {0}")]
+    InvalidPageSize(String),
+}
+
+pub type Result<T> = std::result::Result<T, PrintError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PageSize {
+    pub const A5: Self = Self::mm(148.0, 210.0);
+    pub const A4: Self = Self::mm(210.0, 297.0);
+    pub const A3: Self = Self::mm(297.0, 420.0);
+    pub const B5: Self = Self::mm(176.0, 250.0);
+    pub const B4: Self = Self::mm(250.0, 353.0);
+    pub const LETTER: Self = Self::inches(8.5, 11.0);
+    pub const LEGAL: Self = Self::inches(8.5, 14.0);
+    pub const LEDGER: Self = Self::inches(11.0, 17.0);
+
+    const fn mm(width: f32, height: f32) -> Self {
+        Self {
+            width: width * 96.0 / 25.4,
+            height: height * 96.0 / 25.4,
+        }
+    }
+
+    const fn inches(width: f32, height: f32) -> Self {
+        Self {
+            width: width * 96.0,
+            height: height * 96.0,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "a5" => Self::A5,
+            "a4" => Self::A4,
+            "a3" => Self::A3,
+            "b5" => Self::B5,
+            "b4" => Self::B4,
+            "letter" => Self::LETTER,
+            "legal" => Self::LEGAL,
+            "ledger" => Self::LEDGER,
+            _ => return None,
+        })
+    }
+
+    pub fn landscape(self) -> Self {
+        Self {
+            width: self.width.max(self.height),
+            height: self.width.min(self.height),
+        }
+    }
+
+    pub fn portrait(self) -> Self {
+        Self {
+            width: self.width.min(self.height),
+            height: self.width.max(self.height),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageMargins {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl PageMargins {
+    pub fn uniform(margin: f32) -> Self {
+        Self {
+            top: margin,
+            right: margin,
+            bottom: margin,
+            left: margin,
+        }
+    }
+}
+
+/// The parts of an `@page` rule that printing honors: `size` and the
+/// margins. Page selectors (`:first`, named pages) are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageRule {
+    pub size: Option<PageSize>,
+    pub margin_top: Option<f32>,
+    pub margin_right: Option<f32>,
+    pub margin_bottom: Option<f32>,
+    pub margin_left: Option<f32>,
+}
+
+impl PageRule {
+    /// The `@page` rules of a stylesheet merged in order, later
+    /// declarations winning. `None` when there are none.
+    pub fn from_rules(rules: &[CSSRule]) -> Option<Self> {
+        let mut merged: Option<Self> = None;
+        for rule in rules {
+            if let CSSRule::Page(page) = rule {
+                merged.get_or_insert_with(Self::default).apply(page);
+            }
+        }
+        merged
+    }
+
+    fn apply(&mut self, rule: &CSSPageRule) {
+        for (name, value, _) in &rule.declarations.properties {
+            match name.to_ascii_lowercase().as_str() {
+                "size" => match parse_page_size(value) {
+                    Ok(size) => self.size = size,
+                    Err(e) => tracing::debug!("Ignoring @page size: {}", e),
+                },
+                "margin" => {
+                    let lengths: Vec<f32> =
+                        value.split_whitespace().filter_map(parse_length).collect();
+                    let [top, right, bottom, left] = match lengths[..] {
+                        [all] => [all; 4],
+                        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+                        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+                        [top, right, bottom, left] => [top, right, bottom, left],
+                        _ => continue,
+                    };
+                    self.margin_top = Some(top);
+                    self.margin_right = Some(right);
+                    self.margin_bottom = Some(bottom);
+                    self.margin_left = Some(left);
+                }
+                "margin-top" => self.margin_top = parse_length(value).or(self.margin_top),
+                "margin-right" => self.margin_right = parse_length(value).or(self.margin_right),
+                "margin-bottom" => self.margin_bottom = parse_length(value).or(self.margin_bottom),
+                "margin-left" => self.margin_left = parse_length(value).or(self.margin_left),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `auto` (`None`), a page size name, an orientation, both, or one or two
+/// lengths.
+fn parse_page_size(value: &str) -> Result<Option<PageSize>> {
+    let invalid = || PrintError::InvalidPageSize(value.to_string());
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.iter().any(|part| part.eq_ignore_ascii_case("auto")) {
+        return Ok(None);
+    }
+
+    let lengths: Vec<f32> = parts.iter().filter_map(|part| parse_length(part)).collect();
+    match lengths[..] {
+        [side] if parts.len() == 1 => {
+            return Ok(Some(PageSize {
+                width: side,
+                height: side,
+            }))
+        }
+        [width, height] if parts.len() == 2 => return Ok(Some(PageSize { width, height })),
+        [] => {}
+        _ => return Err(invalid()),
+    }
+
+    let mut size = PageSize::LETTER;
+    let mut orientation: Option<fn(PageSize) -> PageSize> = None;
+    for part in parts {
+        if part.eq_ignore_ascii_case("landscape") {
+            orientation = Some(PageSize::landscape);
+        } else if part.eq_ignore_ascii_case("portrait") {
+            orientation = Some(PageSize::portrait);
+        } else {
+            size = PageSize::from_name(part).ok_or_else(invalid)?;
+        }
+    }
+    Ok(Some(match orientation {
+        Some(orient) => orient(size),
+        None => size,
+    }))
+}
+
+/// An absolute length in CSS pixels.
+fn parse_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+        .unwrap_or(value.len());
+    let number: f32 = value[..split].parse().ok()?;
+    let scale = match value[split..].to_ascii_lowercase().as_str() {
+        "px" => 1.0,
+        "in" => 96.0,
+        "cm" => 96.0 / 2.54,
+        "mm" => 96.0 / 25.4,
+        "pt" => 96.0 / 72.0,
+        "pc" => 16.0,
+        "" if number == 0.0 => 1.0,
+        _ => return None,
+    };
+    Some(number * scale)
+}
+
+/// What to print with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintOptions {
+    pub page_size: PageSize,
+    pub margins: PageMargins,
+    /// Let the document's `@page` size and margins override the two above.
+    pub prefer_css_page_size: bool,
+    /// Paint background colors, not just text.
+    pub print_background: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::LETTER,
+            margins: PageMargins::uniform(0.4 * 96.0),
+            prefer_css_page_size: true,
+            print_background: false,
+        }
+    }
+}
+
+impl PrintOptions {
+    /// The page size and margins to lay out with, given the document's
+    /// `@page` rules.
+    pub fn page_layout(&self, page_rule: Option<&PageRule>) -> (PageSize, PageMargins) {
+        let mut size = self.page_size;
+        let mut margins = self.margins;
+        if let (true, Some(rule)) = (self.prefer_css_page_size, page_rule) {
+            size = rule.size.unwrap_or(size);
+            margins = PageMargins {
+                top: rule.margin_top.unwrap_or(margins.top),
+                right: rule.margin_right.unwrap_or(margins.right),
+                bottom: rule.margin_bottom.unwrap_or(margins.bottom),
+                left: rule.margin_left.unwrap_or(margins.left),
+            };
+        }
+        (size, margins)
+    }
+}
+
+/// How the embedder resolves a print request.
+#[derive(Debug, Clone)]
+pub enum PrintDecision {
+    Print(PrintOptions),
+    Dismiss,
+}
+
+struct PendingPrint {
+    id: u64,
+    deadline: Instant,
+    announced: bool,
+}
+
+/// The document's `window.print()` request, if any.
+pub struct PrintRequests {
+    next_id: AtomicU64,
+    timeout: Duration,
+    pending: Mutex<Option<PendingPrint>>,
+}
+
+impl PrintRequests {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            timeout,
+            pending: Mutex::new(None),
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.lock().is_some()
+    }
+
+    /// Start a request. `None` while another one is pending.
+    pub fn request(&self) -> Option<u64> {
+        let mut pending = self.pending.lock();
+        if pending.is_some() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        *pending = Some(PendingPrint {
+            id,
+            deadline: Instant::now() + self.timeout,
+            announced: false,
+        });
+        Some(id)
+    }
+
+    /// The pending request if the embedder has not been told about it yet.
+    pub fn take_unannounced(&self) -> Option<u64> {
+        match self.pending.lock().as_mut() {
+            Some(pending) if !pending.announced => {
+                pending.announced = true;
+                Some(pending.id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Settle request `id`. Succeeds exactly once per request.
+    pub fn resolve(&self, id: u64) -> Result<()> {
+        let mut pending = self.pending.lock();
+        match pending.as_ref() {
+            Some(request) if request.id == id => {
+                *pending = None;
+                Ok(())
+            }
+            _ => Err(PrintError::NotPending(id)),
+        }
+    }
+
+    /// Settle and return the pending request if its deadline passed.
+    pub fn take_expired(&self, now: Instant) -> Option<u64> {
+        let mut pending = self.pending.lock();
+        match pending.as_ref() {
+            Some(request) if request.deadline <= now => pending.take().map(|request| request.id),
+            _ => None,
+        }
+    }
+
+    /// Forget the pending request without resolving it, as when its
+    /// document goes away.
+    pub fn cancel(&self) {
+        self.pending.lock().take();
+    }
+}
+
+impl Default for PrintRequests {
+    fn default() -> Self {
+        Self::new(PRINT_REQUEST_TIMEOUT)
+    }
+}
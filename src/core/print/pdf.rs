@@ -0,0 +1,136 @@
+//! A minimal PDF 1.4 writer for printed pages: filled rects and single
+//! lines of Helvetica text, with uncompressed content streams.
+//!
+//! Coordinates are CSS pixels from the top-left of the page; they are
+//! converted to PDF points (72 per inch) from the bottom-left on output.
+//! Text outside WinAnsi's Latin-1 range is written as `?`.
+
+use std::fmt::Write;
+
+const POINTS_PER_PX: f32 = 0.75;
+
+/// One page's drawing operations.
+pub struct PdfPage {
+    width: f32,
+    height: f32,
+    content: String,
+}
+
+impl PdfPage {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            content: String::new(),
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 3]) {
+        let _ = writeln!(
+            self.content,
+            "{} rg {} {} {} {} re f",
+            rgb(color),
+            number(x * POINTS_PER_PX),
+            number((self.height - y - height) * POINTS_PER_PX),
+            number(width * POINTS_PER_PX),
+            number(height * POINTS_PER_PX),
+        );
+    }
+
+    /// `text` with its baseline starting at `(x, baseline)`.
+    pub fn text(&mut self, x: f32, baseline: f32, font_size: f32, color: [f32; 3], text: &str) {
+        let _ = writeln!(
+            self.content,
+            "BT {} rg /F1 {} Tf {} {} Td ({}) Tj ET",
+            rgb(color),
+            number(font_size * POINTS_PER_PX),
+            number(x * POINTS_PER_PX),
+            number((self.height - baseline) * POINTS_PER_PX),
+            escape(text),
+        );
+    }
+}
+
+/// Serialize `pages` into a complete PDF file.
+pub fn write_pdf(pages: &[PdfPage]) -> Vec<u8> {
+    // Objects: 1 catalog, 2 page tree, 3 font, then a page and its
+    // content stream per page.
+    let page_ids: Vec<usize> = (0..pages.len()).map(|index| 4 + index * 2).collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            page_ids
+                .iter()
+                .map(|id| format!("{} 0 R", id))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+    ];
+    for (page, id) in pages.iter().zip(&page_ids) {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            number(page.width * POINTS_PER_PX),
+            number(page.height * POINTS_PER_PX),
+            id + 1
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            page.content.len(),
+            page.content
+        ));
+    }
+
+    let mut out = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+    }
+    let xref = out.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    out.extend_from_slice(trailer.as_bytes());
+    out
+}
+
+fn number(value: f32) -> String {
+    let rounded = (value * 100.0).round() / 100.0 + 0.0;
+    rounded.to_string()
+}
+
+fn rgb([r, g, b]: [f32; 3]) -> String {
+    format!("{} {} {}", number(r), number(g), number(b))
+}
+
+/// A PDF literal string body. Content streams are written as UTF-8 text,
+/// so non-ASCII Latin-1 goes out as octal escapes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(escaped, "\\{:03o}", c as u32);
+            }
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
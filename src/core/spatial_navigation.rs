@@ -0,0 +1,359 @@
+//! Spatial navigation: arrow keys move focus to the element that lies that
+//! way on screen rather than the next one in tree order, for D-pad and
+//! remote control input.
+//!
+//! The candidates are the document's focusable areas with a box: links,
+//! form controls, `contenteditable` hosts, `<iframe>`s and anything with a
+//! `tabindex` of zero or more. A negative `tabindex`, `disabled` and being
+//! in an `inert` subtree take an element out. From the focused element, or
+//! the viewport's edge opposite the direction without one, the best
+//! candidate is the nearest one wholly that way, where distance along the
+//! axis of travel counts for less than distance across it and candidates
+//! overlapping the axis are favored. Candidates on screen come first; only
+//! when there are none does the best one beyond the viewport get scrolled
+//! into view.
+//!
+//! An `<iframe>` is a candidate as a whole: frames have no document of
+//! their own to move focus into.
+
+use crate::core::dom::document::NodeType;
+use crate::core::dom::{Document, NodeId};
+use crate::core::layout::{LayoutBox, LayoutEngine};
+use crate::renderer::{ClipChain, DrawQuad, Rect};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const FOCUS_RING_COLOR: [f32; 4] = [0.0, 95.0 / 255.0, 204.0 / 255.0, 1.0];
+pub const FOCUS_RING_WIDTH: f32 = 3.0;
+
+/// How much more distance across the axis of travel weighs than distance
+/// along it, moving left or right and moving up or down. Rows are usually
+/// wider than columns are tall, so sideways moves are held to their row.
+const HORIZONTAL_ORTHOGONAL_WEIGHT: f32 = 30.0;
+const VERTICAL_ORTHOGONAL_WEIGHT: f32 = 2.0;
+/// What a candidate wholly in line with the origin across the axis of
+/// travel gains over one merely touching it, in pixels.
+const ALIGNMENT_BIAS: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// The direction an arrow key moves in.
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "ArrowUp" => Some(Direction::Up),
+            "ArrowDown" => Some(Direction::Down),
+            "ArrowLeft" => Some(Direction::Left),
+            "ArrowRight" => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    fn is_horizontal(self) -> bool {
+        matches!(self, Direction::Left | Direction::Right)
+    }
+}
+
+/// A focusable element and its border box, in viewport coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FocusableArea {
+    pub node: NodeId,
+    pub rect: Rect,
+}
+
+/// Where a move goes: the element to focus, after scrolling the viewport
+/// by `scroll_by`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpatialMove {
+    pub target: NodeId,
+    pub scroll_by: (f32, f32),
+}
+
+/// Whether spatial navigation is on and which element it focused.
+#[derive(Debug, Default)]
+pub struct SpatialNavigation {
+    enabled: AtomicBool,
+    focused: Mutex<Option<NodeId>>,
+}
+
+impl SpatialNavigation {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+            focused: Mutex::new(None),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turn the mode on or off; turning it off drops its focus.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.blur();
+        }
+    }
+
+    pub fn focused(&self) -> Option<NodeId> {
+        *self.focused.lock()
+    }
+
+    pub fn focus(&self, node: NodeId) {
+        *self.focused.lock() = Some(node);
+    }
+
+    pub fn blur(&self) {
+        *self.focused.lock() = None;
+    }
+}
+
+/// Whether `node` itself can take focus from spatial navigation; the
+/// subtree it is in is not looked at.
+pub fn is_focusable(document: &Document, node: NodeId) -> bool {
+    let node = match document.get_node(node) {
+        Some(node) => node,
+        None => return false,
+    };
+    let node = node.read();
+    if node.node_type != NodeType::Element {
+        return false;
+    }
+    let tag = node.tag_name.to_ascii_lowercase();
+    let disableable = matches!(tag.as_str(), "button" | "input" | "select" | "textarea");
+    if disableable && node.has_attribute("disabled") {
+        return false;
+    }
+    if let Some(index) = node
+        .get_attribute("tabindex")
+        .and_then(|value| value.trim().parse::<i32>().ok())
+    {
+        return index >= 0;
+    }
+    match tag.as_str() {
+        "a" | "area" => node.has_attribute("href"),
+        "input" => !node
+            .get_attribute("type")
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")),
+        "button" | "select" | "textarea" | "iframe" | "summary" => true,
+        _ => node
+            .get_attribute("contenteditable")
+            .is_some_and(|value| !value.eq_ignore_ascii_case("false")),
+    }
+}
+
+/// The focusable areas of `document` with a box, in tree order.
+/// `<template>` contents, `inert` subtrees and contents layout skipped
+/// have none.
+pub fn focusable_areas(document: &Document, layout_engine: &LayoutEngine) -> Vec<FocusableArea> {
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("template") || node.has_attribute("inert") {
+                continue;
+            }
+        }
+        if is_focusable(document, node_id) {
+            let rect = layout_engine
+                .get_layout_box(node_id)
+                .map(|layout_box| border_box(&layout_box));
+            if let Some(rect) = rect.filter(|rect| rect.width > 0.0 && rect.height > 0.0) {
+                found.push(FocusableArea {
+                    node: node_id,
+                    rect,
+                });
+            }
+        }
+        if !layout_engine.is_skipped(node_id) {
+            stack.extend(document.get_children(node_id).into_iter().rev());
+        }
+    }
+    found
+}
+
+/// Where moving `direction` from `from` goes in a `viewport`-sized view,
+/// or from the viewport's edge when `from` is `None` or has no box.
+/// `None` when nothing lies that way.
+pub fn search(
+    areas: &[FocusableArea],
+    from: Option<NodeId>,
+    direction: Direction,
+    viewport: (f32, f32),
+) -> Option<SpatialMove> {
+    let origin = from
+        .and_then(|from| areas.iter().find(|area| area.node == from))
+        .map(|area| area.rect.clone())
+        .unwrap_or_else(|| viewport_edge(direction, viewport));
+    let ahead: Vec<&FocusableArea> = areas
+        .iter()
+        .filter(|area| Some(area.node) != from && lies_ahead(&origin, &area.rect, direction))
+        .collect();
+
+    let on_screen = ahead
+        .iter()
+        .copied()
+        .filter(|area| intersects_viewport(&area.rect, viewport));
+    if let Some(best) = best_candidate(&origin, direction, on_screen) {
+        return Some(SpatialMove {
+            target: best.node,
+            scroll_by: (0.0, 0.0),
+        });
+    }
+    let best = best_candidate(&origin, direction, ahead.into_iter())?;
+    Some(SpatialMove {
+        target: best.node,
+        scroll_by: scroll_into_view(&best.rect, direction, viewport),
+    })
+}
+
+/// The nearest of `candidates` going `direction` from `origin`; the first
+/// in tree order of those as near.
+fn best_candidate<'a>(
+    origin: &Rect,
+    direction: Direction,
+    candidates: impl Iterator<Item = &'a FocusableArea>,
+) -> Option<&'a FocusableArea> {
+    let mut best: Option<(f32, &FocusableArea)> = None;
+    for candidate in candidates {
+        let distance = distance(origin, &candidate.rect, direction);
+        if best.map_or(true, |(nearest, _)| distance < nearest) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, area)| area)
+}
+
+/// How far `candidate` is going `direction` from `origin`: the straight
+/// distance between the nearest points of their facing edges, plus the
+/// weighted distance across the axis of travel, less a bias for how much
+/// of the candidate is in line with the origin.
+fn distance(origin: &Rect, candidate: &Rect, direction: Direction) -> f32 {
+    // Along the axis of travel, from the origin's leading edge to the
+    // candidate's facing one; across it, the two spans.
+    let (along, origin_span, candidate_span) = match direction {
+        Direction::Down => (
+            candidate.y - (origin.y + origin.height),
+            (origin.x, origin.x + origin.width),
+            (candidate.x, candidate.x + candidate.width),
+        ),
+        Direction::Up => (
+            origin.y - (candidate.y + candidate.height),
+            (origin.x, origin.x + origin.width),
+            (candidate.x, candidate.x + candidate.width),
+        ),
+        Direction::Right => (
+            candidate.x - (origin.x + origin.width),
+            (origin.y, origin.y + origin.height),
+            (candidate.y, candidate.y + candidate.height),
+        ),
+        Direction::Left => (
+            origin.x - (candidate.x + candidate.width),
+            (origin.y, origin.y + origin.height),
+            (candidate.y, candidate.y + candidate.height),
+        ),
+    };
+    let overlap =
+        (origin_span.1.min(candidate_span.1) - origin_span.0.max(candidate_span.0)).max(0.0);
+    let across = if candidate_span.0 > origin_span.1 {
+        candidate_span.0 - origin_span.1
+    } else if candidate_span.1 < origin_span.0 {
+        origin_span.0 - candidate_span.1
+    } else {
+        0.0
+    };
+    let weight = if direction.is_horizontal() {
+        HORIZONTAL_ORTHOGONAL_WEIGHT
+    } else {
+        VERTICAL_ORTHOGONAL_WEIGHT
+    };
+    let aligned = overlap / (candidate_span.1 - candidate_span.0).max(f32::EPSILON);
+    along.max(0.0).hypot(across) + weight * across - ALIGNMENT_BIAS * aligned
+}
+
+/// Whether `candidate` lies wholly past `origin`'s leading edge.
+fn lies_ahead(origin: &Rect, candidate: &Rect, direction: Direction) -> bool {
+    match direction {
+        Direction::Down => candidate.y >= origin.y + origin.height,
+        Direction::Up => candidate.y + candidate.height <= origin.y,
+        Direction::Right => candidate.x >= origin.x + origin.width,
+        Direction::Left => candidate.x + candidate.width <= origin.x,
+    }
+}
+
+/// The edge of the viewport a move `direction` starts from without focus.
+fn viewport_edge(direction: Direction, (width, height): (f32, f32)) -> Rect {
+    let (x, y, width, height) = match direction {
+        Direction::Down => (0.0, 0.0, width, 0.0),
+        Direction::Up => (0.0, height, width, 0.0),
+        Direction::Right => (0.0, 0.0, 0.0, height),
+        Direction::Left => (width, 0.0, 0.0, height),
+    };
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+fn intersects_viewport(rect: &Rect, (width, height): (f32, f32)) -> bool {
+    rect.x < width && rect.x + rect.width > 0.0 && rect.y < height && rect.y + rect.height > 0.0
+}
+
+/// How far to scroll for `rect`, beyond the viewport going `direction`, to
+/// come into view with its far edge at the viewport's.
+fn scroll_into_view(rect: &Rect, direction: Direction, (width, height): (f32, f32)) -> (f32, f32) {
+    match direction {
+        Direction::Down => (0.0, (rect.y + rect.height - height).max(0.0)),
+        Direction::Up => (0.0, rect.y.min(0.0)),
+        Direction::Right => ((rect.x + rect.width - width).max(0.0), 0.0),
+        Direction::Left => (rect.x.min(0.0), 0.0),
+    }
+}
+
+pub fn border_box(layout_box: &LayoutBox) -> Rect {
+    Rect {
+        x: layout_box.border_box_x(),
+        y: layout_box.border_box_y(),
+        width: layout_box.border_box_width(),
+        height: layout_box.border_box_height(),
+    }
+}
+
+/// The focus ring around `rect`: four strips just outside it.
+pub fn focus_ring_quads(rect: &Rect) -> Vec<DrawQuad> {
+    let w = FOCUS_RING_WIDTH;
+    let strips = [
+        (rect.x - w, rect.y - w, rect.width + 2.0 * w, w),
+        (rect.x - w, rect.y + rect.height, rect.width + 2.0 * w, w),
+        (rect.x - w, rect.y, w, rect.height),
+        (rect.x + rect.width, rect.y, w, rect.height),
+    ];
+    strips
+        .into_iter()
+        .map(|(x, y, width, height)| DrawQuad {
+            bounds: Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            color: FOCUS_RING_COLOR,
+            clip: ClipChain::new(),
+            blur_radius: 0.0,
+            mask: None,
+            image: None,
+        })
+        .collect()
+}
@@ -0,0 +1,191 @@
+//! The text-to-speech engine behind `speechSynthesis`.
+//!
+//! The engine never talks to a platform speech service itself. A host
+//! wires one in (SAPI, AVSpeechSynthesizer, speech-dispatcher, ...) by
+//! implementing [`TtsBackend`] and registering it with
+//! `BrowserEngine::set_tts_backend`. Until it does, [`NullTtsBackend`]
+//! speaks silently, reporting each word after a simulated delay so pages
+//! that wait for `end` still get it.
+
+use super::{Boundary, SpeechProgress, SpeechRequest};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long [`NullTtsBackend`] takes per word at rate 1.
+pub const NULL_WORD_DURATION: Duration = Duration::from_millis(300);
+
+/// `SpeechSynthesisVoice`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Voice {
+    #[serde(rename = "voiceURI")]
+    pub voice_uri: String,
+    pub name: String,
+    /// BCP 47 tag.
+    pub lang: String,
+    /// Synthesized on this machine rather than by a remote service.
+    pub local_service: bool,
+    /// The voice used when an utterance picks none.
+    pub default: bool,
+}
+
+/// A speech engine. The queue hands it one utterance at a time through
+/// [`speak`](Self::speak) and waits for the utterance's
+/// [`SpeechProgress::ended`] or [`SpeechProgress::failed`] before handing it
+/// the next, so a backend never queues. Methods are called without engine
+/// locks held and should return quickly; progress may be reported from any
+/// thread, including from within `speak`.
+pub trait TtsBackend: Send + Sync {
+    /// What `getVoices()` lists.
+    fn voices(&self) -> Vec<Voice>;
+
+    /// Start speaking `request`, reporting through `progress`.
+    fn speak(&self, request: SpeechRequest, progress: SpeechProgress);
+
+    /// Hold the utterance being spoken where it is.
+    fn pause(&self);
+
+    /// Carry on with a paused utterance.
+    fn resume(&self);
+
+    /// Stop the utterance being spoken. Whatever it reports afterwards is
+    /// ignored.
+    fn cancel(&self);
+}
+
+/// Which utterance [`NullTtsBackend`] is on, and whether it is paused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Playback {
+    utterance: u64,
+    paused: bool,
+}
+
+/// Speaks nothing, taking [`NULL_WORD_DURATION`] per word divided by the
+/// utterance's rate, with a `boundary` event per word. Outside a Tokio
+/// runtime every event is reported at once.
+pub struct NullTtsBackend {
+    word_duration: Duration,
+    playback: watch::Sender<Playback>,
+}
+
+impl NullTtsBackend {
+    pub fn new() -> Self {
+        Self::with_word_duration(NULL_WORD_DURATION)
+    }
+
+    pub fn with_word_duration(word_duration: Duration) -> Self {
+        Self {
+            word_duration,
+            playback: watch::Sender::new(Playback::default()),
+        }
+    }
+}
+
+impl Default for NullTtsBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// UTF-16 offset and length of each whitespace-separated word of `text`.
+fn words(text: &str) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    let mut start = None;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                found.push((start, offset - start));
+            }
+        } else if start.is_none() {
+            start = Some(offset);
+        }
+        offset += c.len_utf16();
+    }
+    if let Some(start) = start {
+        found.push((start, offset - start));
+    }
+    found
+}
+
+/// Wait out a pause of `utterance`; `false` once a newer utterance or a
+/// cancel replaced it.
+async fn wait_while_paused(playback: &mut watch::Receiver<Playback>, utterance: u64) -> bool {
+    loop {
+        let current = *playback.borrow_and_update();
+        if current.utterance != utterance {
+            return false;
+        }
+        if !current.paused {
+            return true;
+        }
+        if playback.changed().await.is_err() {
+            return false;
+        }
+    }
+}
+
+impl TtsBackend for NullTtsBackend {
+    fn voices(&self) -> Vec<Voice> {
+        vec![Voice {
+            voice_uri: "urn:vbe:null-voice".to_string(),
+            name: "Silent".to_string(),
+            lang: "en-US".to_string(),
+            local_service: true,
+            default: true,
+        }]
+    }
+
+    fn speak(&self, request: SpeechRequest, progress: SpeechProgress) {
+        let words = words(&request.text);
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                progress.started();
+                for (index, length) in words {
+                    progress.boundary(Boundary::Word, index, length);
+                }
+                progress.ended();
+                return;
+            }
+        };
+        let mut utterance = 0;
+        self.playback.send_modify(|playback| {
+            playback.utterance += 1;
+            playback.paused = false;
+            utterance = playback.utterance;
+        });
+        let mut playback = self.playback.subscribe();
+        let word_duration = self.word_duration.div_f32(request.rate.max(0.1));
+        handle.spawn(async move {
+            progress.started();
+            for (index, length) in words {
+                if !wait_while_paused(&mut playback, utterance).await {
+                    return;
+                }
+                progress.boundary(Boundary::Word, index, length);
+                tokio::time::sleep(word_duration).await;
+            }
+            if wait_while_paused(&mut playback, utterance).await {
+                progress.ended();
+            }
+        });
+    }
+
+    fn pause(&self) {
+        self.playback.send_modify(|playback| playback.paused = true);
+    }
+
+    fn resume(&self) {
+        self.playback
+            .send_modify(|playback| playback.paused = false);
+    }
+
+    fn cancel(&self) {
+        self.playback.send_modify(|playback| {
+            playback.utterance += 1;
+            playback.paused = false;
+        });
+    }
+}
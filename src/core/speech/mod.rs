@@ -0,0 +1,562 @@
+//! `speechSynthesis`: the utterance queue in front of a [`TtsBackend`].
+//!
+//! The queue follows the Web Speech API. `speak()` appends an utterance,
+//! which starts at once unless another one is being spoken or synthesis is
+//! paused; `cancel()` drops the queue, interrupting the utterance being
+//! spoken, without changing whether synthesis is paused; `pause()` and
+//! `resume()` act on the utterance being spoken, and `resume()` starts the
+//! next one when none is. The backend speaks one utterance at a time and
+//! reports its progress through a [`SpeechProgress`]; reports for an
+//! utterance that is no longer the one being spoken are dropped.
+//!
+//! Speaking is gated per origin by
+//! [`Permission::SpeechSynthesis`](crate::core::permissions::Permission); a
+//! refused utterance gets an `error` event with `not-allowed`. A new
+//! document drops everything the previous one queued, without events.
+//!
+//! Events are queued per utterance and delivered on the engine's next tick,
+//! as media element events are.
+
+pub mod backend;
+
+pub use backend::{NullTtsBackend, TtsBackend, Voice, NULL_WORD_DURATION};
+
+use crate::core::permissions::{Permission, PermissionStore};
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Weak};
+use std::time::Instant;
+use url::Url;
+
+/// What one utterance asks for, with rate, pitch and volume clamped to the
+/// ranges the API allows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeechRequest {
+    pub text: String,
+    /// BCP 47 tag; empty for the document's language.
+    pub lang: String,
+    /// `voiceURI` of the voice picked, if one was.
+    pub voice: Option<String>,
+    /// 0.1 to 10, 1 being the voice's normal rate.
+    pub rate: f32,
+    /// 0 to 2, 1 being the voice's normal pitch.
+    pub pitch: f32,
+    /// 0 to 1.
+    pub volume: f32,
+}
+
+impl SpeechRequest {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            lang: String::new(),
+            voice: None,
+            rate: 1.0,
+            pitch: 1.0,
+            volume: 1.0,
+        }
+    }
+
+    /// Clamp the numbers into their ranges; NaN takes the default.
+    pub fn clamped(mut self) -> Self {
+        let clamp = |value: f32, min: f32, max: f32| {
+            if value.is_nan() {
+                1.0f32.clamp(min, max)
+            } else {
+                value.clamp(min, max)
+            }
+        };
+        self.rate = clamp(self.rate, 0.1, 10.0);
+        self.pitch = clamp(self.pitch, 0.0, 2.0);
+        self.volume = clamp(self.volume, 0.0, 1.0);
+        self
+    }
+}
+
+/// `SpeechSynthesisErrorEvent.error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeechErrorCode {
+    /// Removed from the queue by `cancel()` before it started.
+    Canceled,
+    /// Stopped by `cancel()` while being spoken.
+    Interrupted,
+    AudioBusy,
+    AudioHardware,
+    Network,
+    SynthesisUnavailable,
+    SynthesisFailed,
+    LanguageUnavailable,
+    VoiceUnavailable,
+    TextTooLong,
+    InvalidArgument,
+    /// The origin may not use speech synthesis.
+    NotAllowed,
+}
+
+impl SpeechErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpeechErrorCode::Canceled => "canceled",
+            SpeechErrorCode::Interrupted => "interrupted",
+            SpeechErrorCode::AudioBusy => "audio-busy",
+            SpeechErrorCode::AudioHardware => "audio-hardware",
+            SpeechErrorCode::Network => "network",
+            SpeechErrorCode::SynthesisUnavailable => "synthesis-unavailable",
+            SpeechErrorCode::SynthesisFailed => "synthesis-failed",
+            SpeechErrorCode::LanguageUnavailable => "language-unavailable",
+            SpeechErrorCode::VoiceUnavailable => "voice-unavailable",
+            SpeechErrorCode::TextTooLong => "text-too-long",
+            SpeechErrorCode::InvalidArgument => "invalid-argument",
+            SpeechErrorCode::NotAllowed => "not-allowed",
+        }
+    }
+}
+
+/// `SpeechSynthesisEvent.name` of a `boundary` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    Word,
+    Sentence,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeechEventKind {
+    Start,
+    End,
+    Pause,
+    Resume,
+    Boundary(Boundary),
+    Error(SpeechErrorCode),
+}
+
+/// An event for the utterance `speak()` returned `utterance` for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeechEvent {
+    pub utterance: u64,
+    pub kind: SpeechEventKind,
+    /// UTF-16 offset and length of the text reached, as script counts.
+    pub char_index: usize,
+    pub char_length: usize,
+    /// Seconds since the utterance started.
+    pub elapsed_time: f64,
+}
+
+impl SpeechEvent {
+    pub fn type_name(&self) -> &'static str {
+        match self.kind {
+            SpeechEventKind::Start => "start",
+            SpeechEventKind::End => "end",
+            SpeechEventKind::Pause => "pause",
+            SpeechEventKind::Resume => "resume",
+            SpeechEventKind::Boundary(_) => "boundary",
+            SpeechEventKind::Error(_) => "error",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let name = match self.kind {
+            SpeechEventKind::Boundary(Boundary::Word) => "word",
+            SpeechEventKind::Boundary(Boundary::Sentence) => "sentence",
+            _ => "",
+        };
+        let error = match self.kind {
+            SpeechEventKind::Error(code) => Some(code.as_str()),
+            _ => None,
+        };
+        json!({
+            "id": self.utterance,
+            "type": self.type_name(),
+            "charIndex": self.char_index,
+            "charLength": self.char_length,
+            "elapsedTime": self.elapsed_time,
+            "name": name,
+            "error": error,
+        })
+    }
+}
+
+/// The utterance being spoken.
+struct Active {
+    id: u64,
+    /// Set when the backend reports it started.
+    started: Option<Instant>,
+    paused: bool,
+    /// End of the last boundary reported, where an `end` event points.
+    reached: usize,
+    text_length: usize,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// Origin of the document speaking; `None` before one is loaded.
+    origin: Option<Url>,
+    waiting: VecDeque<(u64, SpeechRequest)>,
+    active: Option<Active>,
+    paused: bool,
+    next_id: u64,
+}
+
+/// `speechSynthesis` of the current document.
+pub struct SpeechSynthesis {
+    this: Weak<SpeechSynthesis>,
+    backend: RwLock<Arc<dyn TtsBackend>>,
+    permissions: Arc<PermissionStore>,
+    queue: Mutex<Queue>,
+    events: Mutex<Vec<SpeechEvent>>,
+}
+
+impl SpeechSynthesis {
+    pub fn new(backend: Arc<dyn TtsBackend>, permissions: Arc<PermissionStore>) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            backend: RwLock::new(backend),
+            permissions,
+            queue: Mutex::new(Queue {
+                next_id: 1,
+                ..Default::default()
+            }),
+            events: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Speak through `backend` from now on. An utterance the old backend
+    /// was speaking is interrupted; the queue goes to the new one.
+    pub fn set_backend(&self, backend: Arc<dyn TtsBackend>) {
+        let old = std::mem::replace(&mut *self.backend.write(), backend);
+        let interrupted = {
+            let mut queue = self.queue.lock();
+            match queue.active.take() {
+                Some(active) => {
+                    self.push_event(
+                        &active,
+                        SpeechEventKind::Error(SpeechErrorCode::Interrupted),
+                    );
+                    true
+                }
+                None => false,
+            }
+        };
+        if interrupted {
+            old.cancel();
+        }
+        self.start_next();
+    }
+
+    /// Drop whatever the previous document queued, without events, and take
+    /// utterances from a document of `origin`; `None` when the new document
+    /// runs no script.
+    pub fn reset(&self, origin: Option<Url>) {
+        let was_speaking = {
+            let mut queue = self.queue.lock();
+            queue.origin = origin;
+            queue.waiting.clear();
+            queue.paused = false;
+            queue.active.take().is_some()
+        };
+        self.events.lock().clear();
+        if was_speaking {
+            self.backend().cancel();
+        }
+    }
+
+    /// `speak()`: queue `request` and return the id its events carry. An
+    /// origin without permission gets a `not-allowed` error instead.
+    pub fn speak(&self, request: SpeechRequest) -> u64 {
+        let request = request.clamped();
+        let id = {
+            let mut queue = self.queue.lock();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            let allowed = queue.origin.as_ref().is_some_and(|origin| {
+                self.permissions
+                    .is_granted(origin, Permission::SpeechSynthesis)
+            });
+            if !allowed {
+                self.events.lock().push(SpeechEvent {
+                    utterance: id,
+                    kind: SpeechEventKind::Error(SpeechErrorCode::NotAllowed),
+                    char_index: 0,
+                    char_length: 0,
+                    elapsed_time: 0.0,
+                });
+                return id;
+            }
+            queue.waiting.push_back((id, request));
+            id
+        };
+        self.start_next();
+        id
+    }
+
+    /// `cancel()`: interrupt the utterance being spoken and drop the queued
+    /// ones. A paused synthesis stays paused.
+    pub fn cancel(&self) {
+        let interrupted = {
+            let mut queue = self.queue.lock();
+            let active = queue.active.take();
+            if let Some(active) = &active {
+                self.push_event(active, SpeechEventKind::Error(SpeechErrorCode::Interrupted));
+            }
+            let mut events = self.events.lock();
+            for (id, _) in queue.waiting.drain(..) {
+                events.push(SpeechEvent {
+                    utterance: id,
+                    kind: SpeechEventKind::Error(SpeechErrorCode::Canceled),
+                    char_index: 0,
+                    char_length: 0,
+                    elapsed_time: 0.0,
+                });
+            }
+            active.is_some()
+        };
+        if interrupted {
+            self.backend().cancel();
+        }
+    }
+
+    /// `pause()`: hold the utterance being spoken where it is and keep the
+    /// queue from moving on.
+    pub fn pause(&self) {
+        let pause_backend = {
+            let mut queue = self.queue.lock();
+            if queue.paused {
+                return;
+            }
+            queue.paused = true;
+            match queue.active.as_mut() {
+                Some(active) => {
+                    active.paused = true;
+                    self.push_event(active, SpeechEventKind::Pause);
+                    true
+                }
+                None => false,
+            }
+        };
+        if pause_backend {
+            self.backend().pause();
+        }
+    }
+
+    /// `resume()`: carry on with the paused utterance, or start the next
+    /// one when none was being spoken.
+    pub fn resume(&self) {
+        let resume_backend = {
+            let mut queue = self.queue.lock();
+            if !queue.paused {
+                return;
+            }
+            queue.paused = false;
+            match queue.active.as_mut() {
+                Some(active) if active.paused => {
+                    active.paused = false;
+                    self.push_event(active, SpeechEventKind::Resume);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if resume_backend {
+            self.backend().resume();
+        } else {
+            self.start_next();
+        }
+    }
+
+    /// `pending`: utterances are queued behind the one being spoken.
+    pub fn pending(&self) -> bool {
+        !self.queue.lock().waiting.is_empty()
+    }
+
+    /// `speaking`: an utterance has been handed to the backend and not
+    /// finished, paused or not.
+    pub fn speaking(&self) -> bool {
+        self.queue.lock().active.is_some()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.queue.lock().paused
+    }
+
+    /// `getVoices()`.
+    pub fn voices(&self) -> Vec<Voice> {
+        self.backend().voices()
+    }
+
+    /// Events since the last call, in the order they happened.
+    pub fn take_events(&self) -> Vec<SpeechEvent> {
+        std::mem::take(&mut *self.events.lock())
+    }
+
+    /// Hand the head of the queue to the backend, unless one is being
+    /// spoken or synthesis is paused. The backend is called without the
+    /// queue locked, so it may report progress before returning.
+    fn start_next(&self) {
+        let (id, request) = {
+            let mut queue = self.queue.lock();
+            if queue.paused || queue.active.is_some() {
+                return;
+            }
+            let (id, request) = match queue.waiting.pop_front() {
+                Some(next) => next,
+                None => return,
+            };
+            queue.active = Some(Active {
+                id,
+                started: None,
+                paused: false,
+                reached: 0,
+                text_length: request.text.encode_utf16().count(),
+            });
+            (id, request)
+        };
+        let progress = SpeechProgress {
+            synthesis: self.this.clone(),
+            utterance: id,
+        };
+        self.backend().speak(request, progress);
+    }
+
+    /// The backend, not kept locked while it is called.
+    pub fn backend(&self) -> Arc<dyn TtsBackend> {
+        self.backend.read().clone()
+    }
+
+    fn push_event(&self, active: &Active, kind: SpeechEventKind) {
+        let (char_index, char_length) = match kind {
+            SpeechEventKind::End => (active.text_length, 0),
+            _ => (active.reached, 0),
+        };
+        self.events.lock().push(SpeechEvent {
+            utterance: active.id,
+            kind,
+            char_index,
+            char_length,
+            elapsed_time: active
+                .started
+                .map_or(0.0, |started| started.elapsed().as_secs_f64()),
+        });
+    }
+
+    fn report(&self, utterance: u64, report: Report) {
+        let finished = {
+            let mut queue = self.queue.lock();
+            let active = match queue.active.as_mut() {
+                Some(active) if active.id == utterance => active,
+                _ => return,
+            };
+            if active.started.is_none() {
+                if let Report::Failed(code) = report {
+                    self.push_event(active, SpeechEventKind::Error(code));
+                    queue.active = None;
+                    true
+                } else {
+                    active.started = Some(Instant::now());
+                    self.push_event(active, SpeechEventKind::Start);
+                    self.finish_report(&mut queue, report)
+                }
+            } else {
+                self.finish_report(&mut queue, report)
+            }
+        };
+        if finished {
+            self.start_next();
+        }
+    }
+
+    /// Apply `report` to the started active utterance, returning whether it
+    /// finished.
+    fn finish_report(&self, queue: &mut Queue, report: Report) -> bool {
+        let active = match queue.active.as_mut() {
+            Some(active) => active,
+            None => return false,
+        };
+        match report {
+            Report::Started => false,
+            Report::Boundary {
+                boundary,
+                char_index,
+                char_length,
+            } => {
+                active.reached = char_index + char_length;
+                let mut event = SpeechEvent {
+                    utterance: active.id,
+                    kind: SpeechEventKind::Boundary(boundary),
+                    char_index,
+                    char_length,
+                    elapsed_time: 0.0,
+                };
+                if let Some(started) = active.started {
+                    event.elapsed_time = started.elapsed().as_secs_f64();
+                }
+                self.events.lock().push(event);
+                false
+            }
+            Report::Ended => {
+                self.push_event(active, SpeechEventKind::End);
+                queue.active = None;
+                true
+            }
+            Report::Failed(code) => {
+                self.push_event(active, SpeechEventKind::Error(code));
+                queue.active = None;
+                true
+            }
+        }
+    }
+}
+
+enum Report {
+    Started,
+    Boundary {
+        boundary: Boundary,
+        char_index: usize,
+        char_length: usize,
+    },
+    Ended,
+    Failed(SpeechErrorCode),
+}
+
+/// Where a [`TtsBackend`] reports on the one utterance it was handed.
+/// Reports after the utterance finished or was cancelled are ignored, so a
+/// backend may send them from any thread without tracking cancellation.
+/// An utterance that ends without having reported its start gets `start`
+/// first.
+#[derive(Clone)]
+pub struct SpeechProgress {
+    synthesis: Weak<SpeechSynthesis>,
+    utterance: u64,
+}
+
+impl SpeechProgress {
+    fn report(&self, report: Report) {
+        if let Some(synthesis) = self.synthesis.upgrade() {
+            synthesis.report(self.utterance, report);
+        }
+    }
+
+    /// Audio started.
+    pub fn started(&self) {
+        self.report(Report::Started);
+    }
+
+    /// Speech reached the word or sentence at `char_index`, `char_length`
+    /// long, both in UTF-16 code units of the text.
+    pub fn boundary(&self, boundary: Boundary, char_index: usize, char_length: usize) {
+        self.report(Report::Boundary {
+            boundary,
+            char_index,
+            char_length,
+        });
+    }
+
+    /// The whole text was spoken.
+    pub fn ended(&self) {
+        self.report(Report::Ended);
+    }
+
+    /// Speaking failed; the queue moves on.
+    pub fn failed(&self, error: SpeechErrorCode) {
+        self.report(Report::Failed(error));
+    }
+}
@@ -0,0 +1,375 @@
+//! Web Storage (`localStorage` / `sessionStorage`) partitioned by
+//! [`StorageKey`].
+//!
+//! A storage key is the pair (top-level site, origin). Top-level documents
+//! and first-party frames use their own origin for both halves. With
+//! third-party partitioning on, a frame embedded under a different
+//! top-level origin gets a bucket of its own per embedding site, so the
+//! same tracker frame cannot share state across the sites that embed it.
+//!
+//! `localStorage` is written through to `<directory>/localStorage-<hash>.json`
+//! when a directory is configured. Without one (always in private mode) all
+//! areas live in memory and vanish with the engine; scripts see the same
+//! API, limits and errors either way.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use thiserror::Error;
+use url::Url;
+
+/// Per-area limit on the UTF-16 length of all keys and values.
+pub const STORAGE_AREA_QUOTA: usize = 5 * 1024 * 1024;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StorageError {
+    #[error("Storage quota of {0} code units exceeded")]
+    QuotaExceeded(usize),
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Where `localStorage` is persisted; `None` keeps it memory-only.
+    /// Ignored in private mode.
+    pub directory: Option<PathBuf>,
+    /// Key third-party frames by (top-level origin, frame origin).
+    pub partition_third_party: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            directory: dirs::data_dir().map(|dir| dir.join("vulkan-renderer").join("storage")),
+            partition_third_party: false,
+        }
+    }
+}
+
+/// Which storage bucket a document uses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StorageKey {
+    /// Serialized origin of the top-level document.
+    pub top_level_site: String,
+    /// Serialized origin of the document using the storage.
+    pub origin: String,
+}
+
+impl StorageKey {
+    /// Key of a top-level document, or of anything else not partitioned, from
+    /// an already serialized origin.
+    pub fn first_party(origin: impl Into<String>) -> Self {
+        let origin = origin.into();
+        Self {
+            top_level_site: origin.clone(),
+            origin,
+        }
+    }
+
+    pub fn for_url(url: &Url) -> Self {
+        Self::first_party(url.origin().ascii_serialization())
+    }
+
+    /// Key of a frame at `frame` embedded (at any depth) under the top-level
+    /// document at `top_level`.
+    pub fn for_frame(top_level: &Url, frame: &Url, partition_third_party: bool) -> Self {
+        let origin = frame.origin().ascii_serialization();
+        if !partition_third_party {
+            return Self::first_party(origin);
+        }
+        Self {
+            top_level_site: top_level.origin().ascii_serialization(),
+            origin,
+        }
+    }
+
+    /// Whether this is a third-party bucket separate from the origin's own.
+    pub fn is_partitioned(&self) -> bool {
+        self.top_level_site != self.origin
+    }
+
+    /// Stable file-name-safe digest of the key.
+    fn file_stem(&self) -> String {
+        let digest = ring::digest::digest(
+            &ring::digest::SHA256,
+            format!("{}\n{}", self.top_level_site, self.origin).as_bytes(),
+        );
+        digest.as_ref()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AreaFile {
+    key: StorageKey,
+    items: BTreeMap<String, String>,
+}
+
+/// One `Storage` object's items.
+pub struct StorageArea {
+    key: StorageKey,
+    file: Option<PathBuf>,
+    items: Mutex<BTreeMap<String, String>>,
+}
+
+impl StorageArea {
+    /// An area that is never persisted.
+    pub fn in_memory(key: StorageKey) -> Self {
+        Self {
+            key,
+            file: None,
+            items: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// An area backed by `file`, starting from its contents. A missing or
+    /// unreadable file starts empty.
+    fn persistent(key: StorageKey, file: PathBuf) -> Self {
+        let items = match fs::read(&file) {
+            Ok(bytes) => match serde_json::from_slice::<AreaFile>(&bytes) {
+                Ok(stored) if stored.key == key => stored.items,
+                Ok(_) => {
+                    tracing::warn!("{} belongs to another storage key", file.display());
+                    BTreeMap::new()
+                }
+                Err(e) => {
+                    tracing::warn!("Discarding unreadable {}: {}", file.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            key,
+            file: Some(file),
+            items: Mutex::new(items),
+        }
+    }
+
+    pub fn key(&self) -> &StorageKey {
+        &self.key
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.items.lock().get(name).cloned()
+    }
+
+    /// Store `value` under `name`. Fails without changing anything when the
+    /// area would exceed [`STORAGE_AREA_QUOTA`].
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        let mut items = self.items.lock();
+        let old = items
+            .get(name)
+            .map_or(0, |old| utf16_len(name) + utf16_len(old));
+        let used = usage(&items) - old + utf16_len(name) + utf16_len(value);
+        if used > STORAGE_AREA_QUOTA {
+            return Err(StorageError::QuotaExceeded(STORAGE_AREA_QUOTA));
+        }
+        if items.get(name).map(String::as_str) == Some(value) {
+            return Ok(());
+        }
+        items.insert(name.to_string(), value.to_string());
+        self.persist(&items);
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) {
+        let mut items = self.items.lock();
+        if items.remove(name).is_some() {
+            self.persist(&items);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut items = self.items.lock();
+        if !items.is_empty() {
+            items.clear();
+            self.persist(&items);
+        }
+    }
+
+    /// The name at `index` in the area's (stable, sorted) order.
+    pub fn key_at(&self, index: usize) -> Option<String> {
+        self.items.lock().keys().nth(index).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.lock().is_empty()
+    }
+
+    /// Quota usage: UTF-16 code units of all names and values.
+    pub fn usage(&self) -> usize {
+        usage(&self.items.lock())
+    }
+
+    /// Write-through. A failed write keeps the in-memory value so scripts
+    /// see the same behavior whether or not the disk is usable.
+    fn persist(&self, items: &BTreeMap<String, String>) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let result = if items.is_empty() {
+            match fs::remove_file(file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        } else {
+            let stored = AreaFile {
+                key: self.key.clone(),
+                items: items.clone(),
+            };
+            serde_json::to_vec(&stored)
+                .map_err(std::io::Error::from)
+                .and_then(|bytes| write_atomically(file, &bytes))
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to persist {}: {}", file.display(), e);
+        }
+    }
+}
+
+fn utf16_len(value: &str) -> usize {
+    value.encode_utf16().count()
+}
+
+fn usage(items: &BTreeMap<String, String>) -> usize {
+    items
+        .iter()
+        .map(|(name, value)| utf16_len(name) + utf16_len(value))
+        .sum()
+}
+
+/// Write `data` beside `path`, then rename it into place.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let temp = PathBuf::from(temp);
+
+    let written = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// All storage areas of an engine.
+pub struct WebStorage {
+    directory: Option<PathBuf>,
+    partition_third_party: bool,
+    local: DashMap<StorageKey, Arc<StorageArea>>,
+    session: DashMap<StorageKey, Arc<StorageArea>>,
+}
+
+impl WebStorage {
+    pub fn new(config: &StorageConfig, private_mode: bool) -> Self {
+        Self {
+            directory: if private_mode {
+                None
+            } else {
+                config.directory.clone()
+            },
+            partition_third_party: config.partition_third_party,
+            local: DashMap::new(),
+            session: DashMap::new(),
+        }
+    }
+
+    /// Memory-only storage without partitioning.
+    pub fn in_memory() -> Self {
+        Self::new(
+            &StorageConfig {
+                directory: None,
+                partition_third_party: false,
+            },
+            false,
+        )
+    }
+
+    /// Where `localStorage` is persisted; `None` when memory-only.
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+
+    pub fn is_ephemeral(&self) -> bool {
+        self.directory.is_none()
+    }
+
+    pub fn partitions_third_party(&self) -> bool {
+        self.partition_third_party
+    }
+
+    /// The key for a document at `frame` under the top-level document at
+    /// `top_level`, honoring the partitioning setting.
+    pub fn key_for(&self, top_level: &Url, frame: &Url) -> StorageKey {
+        StorageKey::for_frame(top_level, frame, self.partition_third_party)
+    }
+
+    /// The `localStorage` area for `key`, loaded from disk on first use.
+    pub fn local(&self, key: &StorageKey) -> Arc<StorageArea> {
+        self.local
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(match &self.directory {
+                    Some(directory) => StorageArea::persistent(
+                        key.clone(),
+                        directory.join(format!("localStorage-{}.json", key.file_stem())),
+                    ),
+                    None => StorageArea::in_memory(key.clone()),
+                })
+            })
+            .clone()
+    }
+
+    /// The `sessionStorage` area for `key`; never persisted.
+    pub fn session(&self, key: &StorageKey) -> Arc<StorageArea> {
+        self.session
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(StorageArea::in_memory(key.clone())))
+            .clone()
+    }
+
+    /// Drop `key`'s `localStorage`, on disk too.
+    pub fn clear_local(&self, key: &StorageKey) {
+        self.local(key).clear();
+        self.local.remove(key);
+    }
+
+    pub fn clear_session(&self, key: &StorageKey) {
+        self.session.remove(key);
+    }
+
+    /// End the browsing session: every `sessionStorage` area goes, and so
+    /// does `localStorage` when it is memory-only.
+    pub fn end_session(&self) {
+        self.session.clear();
+        if self.is_ephemeral() {
+            self.local.clear();
+        }
+    }
+}
+
+impl Default for WebStorage {
+    fn default() -> Self {
+        Self::new(&StorageConfig::default(), false)
+    }
+}
@@ -0,0 +1,47 @@
+//! User activation: whether the user has just interacted with the page.
+//!
+//! A trusted `keydown` other than Escape, or a `mousedown`, activates the
+//! document. The activation is transient: features that need a user
+//! gesture, such as playing media under an autoplay policy, allow
+//! themselves for [`TRANSIENT_ACTIVATION_DURATION`] after it. Events
+//! scripts dispatch themselves activate nothing. A new document starts
+//! without activation.
+
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an interaction counts as a user gesture.
+pub const TRANSIENT_ACTIVATION_DURATION: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct UserActivation {
+    last: Mutex<Option<Instant>>,
+}
+
+impl UserActivation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The user interacted with the document.
+    pub fn activate(&self) {
+        *self.last.lock() = Some(Instant::now());
+    }
+
+    /// Whether the document has transient activation.
+    pub fn is_active(&self) -> bool {
+        self.last
+            .lock()
+            .is_some_and(|last| last.elapsed() < TRANSIENT_ACTIVATION_DURATION)
+    }
+
+    /// Whether the user ever interacted with the document.
+    pub fn has_been_active(&self) -> bool {
+        self.last.lock().is_some()
+    }
+
+    /// Forget the activation, for a new document.
+    pub fn reset(&self) {
+        *self.last.lock() = None;
+    }
+}
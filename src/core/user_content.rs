@@ -0,0 +1,350 @@
+//! Stylesheets and scripts the embedder injects into pages, for branding a
+//! kiosk or shimming analytics without an extension system.
+//!
+//! Each registration names the origins it applies to with patterns:
+//!
+//! - `*` matches every `http` and `https` page;
+//! - `scheme://host` or `scheme://host:port` matches that origin, where the
+//!   scheme may be `*` for `http` or `https`, the host `*` for any host and
+//!   `*.example.com` for `example.com` and its subdomains, and the port `*`
+//!   for any port. Without a port, only the scheme's default one matches.
+//!
+//! Registrations last until removed or the engine goes away; an embedder
+//! that wants them after a restart adds them again.
+
+use super::css::{CSSParser, CSSRule};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UserContentError {
+    #[error("Invalid origin pattern {0:?}: {1}")]
+    InvalidPattern(String, &'static str),
+    #[error("User content must name at least one origin pattern")]
+    NoOrigins,
+    #[error("Invalid user stylesheet: {0}")]
+    InvalidStylesheet(String),
+}
+
+/// The handle a registration is removed by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserContentId(pub u64);
+
+/// Where a user stylesheet's rules enter the cascade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CascadeLevel {
+    /// The user origin: normal declarations lose to the page's, important
+    /// ones beat even the page's important declarations.
+    #[default]
+    User,
+    /// As if the page's own last stylesheet.
+    Author,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UserStyleOptions {
+    pub origins: Vec<String>,
+    pub cascade_level: CascadeLevel,
+}
+
+/// When in a document's load a user script runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RunAt {
+    /// Before any of the page's scripts.
+    DocumentStart,
+    /// After the page's inline scripts, before its images have loaded.
+    #[default]
+    DocumentEnd,
+    /// Once the page has loaded, just before
+    /// [`BrowserEvent::PageLoaded`](crate::BrowserEvent).
+    DocumentIdle,
+}
+
+/// Which global a user script runs against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScriptWorld {
+    /// The page's own: the script and the page see each other's globals.
+    #[default]
+    Main,
+    /// A world of the script's own over the same DOM.
+    Isolated,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UserScriptOptions {
+    pub origins: Vec<String>,
+    pub run_at: RunAt,
+    pub world: ScriptWorld,
+}
+
+/// A script due to run in a document.
+#[derive(Debug, Clone)]
+pub struct UserScript {
+    pub id: UserContentId,
+    pub source: String,
+    pub world: ScriptWorld,
+}
+
+/// A parsed origin pattern. See the module documentation for the syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginPattern {
+    // `None` for `http` or `https`.
+    scheme: Option<String>,
+    host: HostPattern,
+    // `None` for any port.
+    port: Option<PortPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Any,
+    Exact(String),
+    /// The domain and every subdomain of it.
+    Domain(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PortPattern {
+    Default,
+    Exact(u16),
+}
+
+impl OriginPattern {
+    pub fn parse(pattern: &str) -> Result<Self, UserContentError> {
+        let invalid = |reason| UserContentError::InvalidPattern(pattern.to_string(), reason);
+        let trimmed = pattern.trim();
+        if trimmed == "*" {
+            return Ok(Self {
+                scheme: None,
+                host: HostPattern::Any,
+                port: None,
+            });
+        }
+        let (scheme, rest) = trimmed
+            .split_once("://")
+            .ok_or_else(|| invalid("expected scheme://host"))?;
+        let scheme = match scheme {
+            "*" => None,
+            scheme
+                if !scheme.is_empty()
+                    && scheme
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) =>
+            {
+                Some(scheme.to_ascii_lowercase())
+            }
+            _ => return Err(invalid("bad scheme")),
+        };
+        // A trailing `/` is tolerated; a path is not.
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        if rest.contains(['/', '?', '#', '@']) {
+            return Err(invalid("patterns name origins, not paths"));
+        }
+        // The port follows the last `:`, after the `]` of an IPv6 address.
+        let host_end = rest.rfind(']').map_or(0, |end| end + 1);
+        let (host, port) = match rest[host_end..].rfind(':') {
+            Some(colon) => (
+                &rest[..host_end + colon],
+                Some(&rest[host_end + colon + 1..]),
+            ),
+            None => (rest, None),
+        };
+        let port = match port {
+            None => Some(PortPattern::Default),
+            Some("*") => None,
+            Some(port) => Some(PortPattern::Exact(
+                port.parse().map_err(|_| invalid("bad port"))?,
+            )),
+        };
+        let host = match host {
+            "" => return Err(invalid("missing host")),
+            "*" => HostPattern::Any,
+            host => match host.strip_prefix("*.") {
+                Some("") => return Err(invalid("missing domain after *.")),
+                Some(domain) if !domain.contains('*') => {
+                    HostPattern::Domain(domain.to_ascii_lowercase())
+                }
+                None if !host.contains('*') => HostPattern::Exact(host.to_ascii_lowercase()),
+                _ => {
+                    return Err(invalid(
+                        "* may only stand for the whole host or its leftmost labels",
+                    ))
+                }
+            },
+        };
+        Ok(Self { scheme, host, port })
+    }
+
+    pub fn matches(&self, url: &url::Url) -> bool {
+        let scheme_matches = match &self.scheme {
+            Some(scheme) => *scheme == url.scheme(),
+            None => matches!(url.scheme(), "http" | "https"),
+        };
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        let host_matches = match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Exact(exact) => host == *exact,
+            HostPattern::Domain(domain) => {
+                host == *domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            }
+        };
+        let port_matches = match self.port {
+            None => true,
+            Some(PortPattern::Default) => url.port().is_none(),
+            Some(PortPattern::Exact(port)) => url.port_or_known_default() == Some(port),
+        };
+        scheme_matches && host_matches && port_matches
+    }
+}
+
+fn parse_origins(origins: &[String]) -> Result<Vec<OriginPattern>, UserContentError> {
+    if origins.is_empty() {
+        return Err(UserContentError::NoOrigins);
+    }
+    origins
+        .iter()
+        .map(|pattern| OriginPattern::parse(pattern))
+        .collect()
+}
+
+struct Registered<T> {
+    id: UserContentId,
+    origins: Vec<OriginPattern>,
+    item: T,
+}
+
+impl<T> Registered<T> {
+    fn applies_to(&self, url: &url::Url) -> bool {
+        self.origins.iter().any(|origin| origin.matches(url))
+    }
+}
+
+struct StyleEntry {
+    rules: Vec<CSSRule>,
+    cascade_level: CascadeLevel,
+}
+
+struct ScriptEntry {
+    source: String,
+    run_at: RunAt,
+    world: ScriptWorld,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    styles: Vec<Registered<StyleEntry>>,
+    scripts: Vec<Registered<ScriptEntry>>,
+}
+
+impl Registry {
+    fn allocate(&mut self) -> UserContentId {
+        self.next_id += 1;
+        UserContentId(self.next_id)
+    }
+}
+
+/// The embedder's user stylesheets and user scripts, in the order they were
+/// added.
+#[derive(Default)]
+pub struct UserContent {
+    registry: Mutex<Registry>,
+}
+
+impl UserContent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_stylesheet(
+        &self,
+        css: &str,
+        options: UserStyleOptions,
+    ) -> Result<UserContentId, UserContentError> {
+        let origins = parse_origins(&options.origins)?;
+        let rules = CSSParser::new()
+            .parse(css)
+            .map_err(|e| UserContentError::InvalidStylesheet(e.to_string()))?;
+        let mut registry = self.registry.lock();
+        let id = registry.allocate();
+        registry.styles.push(Registered {
+            id,
+            origins,
+            item: StyleEntry {
+                rules,
+                cascade_level: options.cascade_level,
+            },
+        });
+        Ok(id)
+    }
+
+    pub fn add_script(
+        &self,
+        source: &str,
+        options: UserScriptOptions,
+    ) -> Result<UserContentId, UserContentError> {
+        let origins = parse_origins(&options.origins)?;
+        let mut registry = self.registry.lock();
+        let id = registry.allocate();
+        registry.scripts.push(Registered {
+            id,
+            origins,
+            item: ScriptEntry {
+                source: source.to_string(),
+                run_at: options.run_at,
+                world: options.world,
+            },
+        });
+        Ok(id)
+    }
+
+    /// Remove the stylesheet or script added as `id`. `false` when there is
+    /// none.
+    pub fn remove(&self, id: UserContentId) -> bool {
+        let mut registry = self.registry.lock();
+        let before = registry.styles.len() + registry.scripts.len();
+        registry.styles.retain(|style| style.id != id);
+        registry.scripts.retain(|script| script.id != id);
+        registry.styles.len() + registry.scripts.len() != before
+    }
+
+    /// The rules of the stylesheets applying to a document at `url`: those
+    /// for the user origin, then those cascading with the page's.
+    pub fn stylesheets_for(&self, url: &url::Url) -> (Vec<CSSRule>, Vec<CSSRule>) {
+        let mut user = Vec::new();
+        let mut author = Vec::new();
+        for style in self.registry.lock().styles.iter() {
+            if !style.applies_to(url) {
+                continue;
+            }
+            let rules = style.item.rules.iter().cloned();
+            match style.item.cascade_level {
+                CascadeLevel::User => user.extend(rules),
+                CascadeLevel::Author => author.extend(rules),
+            }
+        }
+        (user, author)
+    }
+
+    /// The scripts to run at `run_at` in a document at `url`, in the order
+    /// they were added.
+    pub fn scripts_for(&self, url: &url::Url, run_at: RunAt) -> Vec<UserScript> {
+        self.registry
+            .lock()
+            .scripts
+            .iter()
+            .filter(|script| script.item.run_at == run_at && script.applies_to(url))
+            .map(|script| UserScript {
+                id: script.id,
+                source: script.item.source.clone(),
+                world: script.item.world,
+            })
+            .collect()
+    }
+}
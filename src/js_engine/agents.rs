@@ -0,0 +1,192 @@
+//! Origin-keyed agents: which documents share a JS context.
+//!
+//! Every document belongs to the agent of its origin. Documents of one
+//! origin, say a page and its same-origin iframes, join the same agent;
+//! documents of different origins never share one and can only reach each
+//! other through `postMessage`. Opaque origins (`data:` documents, sandboxed
+//! frames) match nothing, so each gets an agent of its own.
+//!
+//! The agents of a runtime are context groups in its one isolate: each has
+//! its own V8 context, bindings and heap account. V8 cannot tell which
+//! context owns what, so an agent is charged whatever the heap grew by while
+//! its script ran, and the accounts are scaled to the isolate's real usage
+//! after each collection. The runtime's JS memory budget is split evenly
+//! between the live agents.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AgentId(pub u64);
+
+/// What an agent is keyed by: the ASCII serialization of a tuple origin, or
+/// nothing for an opaque one.
+pub fn origin_key(url: &Url) -> Option<String> {
+    let origin = url.origin();
+    if origin.is_tuple() {
+        Some(origin.ascii_serialization())
+    } else {
+        None
+    }
+}
+
+/// One agent's share of the runtime, as reported in metrics.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AgentMetrics {
+    pub agent: AgentId,
+    /// `None` for an opaque origin.
+    pub origin: Option<String>,
+    pub documents: usize,
+    pub heap_used_bytes: u64,
+    pub heap_limit_bytes: u64,
+}
+
+#[derive(Debug)]
+struct Agent {
+    id: AgentId,
+    origin: Option<String>,
+    documents: usize,
+    heap_used_bytes: u64,
+}
+
+/// The live agents of a runtime and their documents and heap accounts.
+#[derive(Debug)]
+pub struct AgentRegistry {
+    agents: Vec<Agent>,
+    next_id: u64,
+    budget_bytes: u64,
+}
+
+impl AgentRegistry {
+    pub fn new(budget_bytes: u64) -> Self {
+        Self {
+            agents: Vec::new(),
+            next_id: 0,
+            budget_bytes,
+        }
+    }
+
+    /// Attach a document of `origin` to its agent. Returns the agent and
+    /// whether it was just created.
+    pub fn join(&mut self, origin: Option<String>) -> (AgentId, bool) {
+        if let Some(origin) = &origin {
+            if let Some(agent) = self
+                .agents
+                .iter_mut()
+                .find(|agent| agent.origin.as_ref() == Some(origin))
+            {
+                agent.documents += 1;
+                return (agent.id, false);
+            }
+        }
+        let id = AgentId(self.next_id);
+        self.next_id += 1;
+        self.agents.push(Agent {
+            id,
+            origin,
+            documents: 1,
+            heap_used_bytes: 0,
+        });
+        (id, true)
+    }
+
+    /// Detach one document from `agent`. True when that was its last one and
+    /// the agent is gone.
+    pub fn leave(&mut self, agent: AgentId) -> bool {
+        let index = match self.agents.iter().position(|a| a.id == agent) {
+            Some(index) => index,
+            None => return false,
+        };
+        let record = &mut self.agents[index];
+        record.documents = record.documents.saturating_sub(1);
+        if record.documents > 0 {
+            return false;
+        }
+        self.agents.remove(index);
+        true
+    }
+
+    pub fn contains(&self, agent: AgentId) -> bool {
+        self.agents.iter().any(|a| a.id == agent)
+    }
+
+    /// How many documents `agent` has; `None` if it is gone.
+    pub fn documents(&self, agent: AgentId) -> Option<usize> {
+        self.agents
+            .iter()
+            .find(|a| a.id == agent)
+            .map(|a| a.documents)
+    }
+
+    pub fn ids(&self) -> Vec<AgentId> {
+        self.agents.iter().map(|a| a.id).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    /// `agent`'s origin as `MessageEvent.origin` spells it: `"null"` when
+    /// opaque.
+    pub fn origin(&self, agent: AgentId) -> Option<String> {
+        self.agents
+            .iter()
+            .find(|a| a.id == agent)
+            .map(|a| a.origin.clone().unwrap_or_else(|| "null".to_string()))
+    }
+
+    /// Each agent's even share of the budget.
+    pub fn heap_limit(&self) -> u64 {
+        self.budget_bytes / self.agents.len().max(1) as u64
+    }
+
+    /// Charge `agent` for the heap growing from `before` to `after` bytes
+    /// while its script ran; shrinking is credited back.
+    pub fn charge(&mut self, agent: AgentId, before: u64, after: u64) {
+        if let Some(agent) = self.agents.iter_mut().find(|a| a.id == agent) {
+            agent.heap_used_bytes = if after >= before {
+                agent.heap_used_bytes.saturating_add(after - before)
+            } else {
+                agent.heap_used_bytes.saturating_sub(before - after)
+            };
+        }
+    }
+
+    /// Scale every account so they add up to `used`, the isolate's heap
+    /// after a collection.
+    pub fn rescale(&mut self, used: u64) {
+        let charged: u64 = self.agents.iter().map(|a| a.heap_used_bytes).sum();
+        if charged == 0 {
+            return;
+        }
+        let ratio = used as f64 / charged as f64;
+        for agent in &mut self.agents {
+            agent.heap_used_bytes = (agent.heap_used_bytes as f64 * ratio) as u64;
+        }
+    }
+
+    pub fn over_limit(&self, agent: AgentId) -> bool {
+        let limit = self.heap_limit();
+        self.agents
+            .iter()
+            .any(|a| a.id == agent && a.heap_used_bytes > limit)
+    }
+
+    pub fn metrics(&self) -> Vec<AgentMetrics> {
+        let limit = self.heap_limit();
+        self.agents
+            .iter()
+            .map(|agent| AgentMetrics {
+                agent: agent.id,
+                origin: agent.origin.clone(),
+                documents: agent.documents,
+                heap_used_bytes: agent.heap_used_bytes,
+                heap_limit_bytes: limit,
+            })
+            .collect()
+    }
+}
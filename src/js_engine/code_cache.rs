@@ -0,0 +1,274 @@
+//! Persistent V8 code cache for page scripts.
+//!
+//! After a script runs, V8 can serialize the code it compiled for it; given
+//! that data on the next compile of the same source, it skips parsing and
+//! compiling. Entries are keyed by the origin of the document the script ran
+//! in, the script's URL, a hash of its source and the V8 version, so an
+//! edited script or an upgraded V8 simply misses, and one site cannot time
+//! its own loads to learn which scripts another site has cached.
+//!
+//! Layout under the cache directory, as for the HTTP disk cache:
+//! - `data/<key>`: the cached data. The index holds its SHA-256; data that
+//!   no longer matches is evicted and reported as a miss.
+//! - `index.json`: key → entry, written to a temporary file and renamed.
+//!
+//! V8 may still refuse data that passed the check; the caller then
+//! [`reject`](CodeCache::reject)s the entry and compiles in full.
+
+use super::{JSError, Result};
+use crate::core::network::disk_cache::{content_hash, now_secs, write_atomically};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = "index.json";
+const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeCacheConfig {
+    /// Where compiled code is persisted; `None` disables the cache.
+    pub directory: Option<PathBuf>,
+    pub max_size_bytes: u64,
+    /// Inline scripts shorter than this compile without the cache: looking
+    /// them up costs about what compiling them does.
+    pub min_inline_bytes: usize,
+}
+
+impl Default for CodeCacheConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_size_bytes: 64 * 1024 * 1024,
+            min_inline_bytes: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CodeCacheEntry {
+    /// Hex SHA-256 of the data.
+    checksum: String,
+    size: u64,
+    /// Seconds since the Unix epoch.
+    last_accessed: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CodeCacheIndex {
+    version: u32,
+    entries: HashMap<String, CodeCacheEntry>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    entries: HashMap<String, CodeCacheEntry>,
+    total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CodeCacheStats {
+    pub entry_count: usize,
+    pub total_size_bytes: u64,
+    pub max_size_bytes: u64,
+}
+
+pub struct CodeCache {
+    root: PathBuf,
+    max_size_bytes: u64,
+    /// `None` until the first lookup or write loads `index.json`.
+    state: Mutex<Option<IndexState>>,
+}
+
+impl CodeCache {
+    pub fn new(root: impl Into<PathBuf>, max_size_bytes: u64) -> Self {
+        Self {
+            root: root.into(),
+            max_size_bytes,
+            state: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &CodeCacheConfig) -> Option<Self> {
+        config
+            .directory
+            .as_ref()
+            .map(|directory| Self::new(directory, config.max_size_bytes))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.root
+    }
+
+    /// The key of `source`, loaded from `url` into a document of `origin`
+    /// and compiled by V8 `v8_version`.
+    pub fn key(origin: &str, url: &str, source: &str, v8_version: &str) -> String {
+        let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+        for part in [origin, url, v8_version, source] {
+            context.update(&(part.len() as u64).to_le_bytes());
+            context.update(part.as_bytes());
+        }
+        context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The data stored under `key`. Data that is missing or fails its
+    /// checksum is evicted and reported as a miss.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+
+        let entry = state.entries.get_mut(key)?;
+        match fs::read(self.data_path(key)) {
+            Ok(data) if content_hash(&data) == entry.checksum => {
+                entry.last_accessed = now_secs();
+                Some(data)
+            }
+            _ => {
+                tracing::warn!("Evicting corrupt code cache entry {}", key);
+                self.remove_entry(state, key);
+                if let Err(e) = self.write_index(state) {
+                    tracing::warn!("Failed to persist code cache index: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    /// Store `data` under `key`, evicting the least recently used entries
+    /// past the budget. Data larger than the whole budget is ignored.
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        if data.len() as u64 > self.max_size_bytes {
+            return Ok(());
+        }
+
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+
+        write_atomically(&self.data_path(key), data)
+            .map_err(|e| JSError::CodeCache(e.to_string()))?;
+        if let Some(previous) = state.entries.remove(key) {
+            state.total_bytes = state.total_bytes.saturating_sub(previous.size);
+        }
+        state.total_bytes += data.len() as u64;
+        state.entries.insert(
+            key.to_string(),
+            CodeCacheEntry {
+                checksum: content_hash(data),
+                size: data.len() as u64,
+                last_accessed: now_secs(),
+            },
+        );
+
+        while state.total_bytes > self.max_size_bytes {
+            let oldest = state
+                .entries
+                .iter()
+                .filter(|(other, _)| other.as_str() != key)
+                .min_by_key(|(_, entry)| entry.last_accessed)
+                .map(|(other, _)| other.clone());
+            match oldest {
+                Some(other) => self.remove_entry(state, &other),
+                None => break,
+            }
+        }
+
+        self.write_index(state)
+    }
+
+    /// V8 refused the data under `key`; drop it.
+    pub fn reject(&self, key: &str) -> Result<()> {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+        if state.entries.contains_key(key) {
+            self.remove_entry(state, key);
+            self.write_index(state)?;
+        }
+        Ok(())
+    }
+
+    /// Drop every entry and delete the files backing them.
+    pub fn clear(&self) -> Result<()> {
+        let mut guard = self.state.lock();
+        *guard = Some(IndexState::default());
+
+        for path in [self.root.join(INDEX_FILE), self.root.join("data")] {
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            match removed {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(JSError::CodeCache(format!(
+                        "Failed to remove {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> CodeCacheStats {
+        let mut guard = self.state.lock();
+        let state = self.load(&mut guard);
+        CodeCacheStats {
+            entry_count: state.entries.len(),
+            total_size_bytes: state.total_bytes,
+            max_size_bytes: self.max_size_bytes,
+        }
+    }
+
+    fn load<'a>(&self, guard: &'a mut Option<IndexState>) -> &'a mut IndexState {
+        guard.get_or_insert_with(|| {
+            let index = match fs::read(self.root.join(INDEX_FILE)) {
+                Ok(bytes) => match serde_json::from_slice::<CodeCacheIndex>(&bytes) {
+                    Ok(index) if index.version == INDEX_VERSION => index,
+                    Ok(_) => CodeCacheIndex::default(),
+                    Err(e) => {
+                        tracing::warn!("Ignoring unreadable code cache index: {}", e);
+                        CodeCacheIndex::default()
+                    }
+                },
+                Err(_) => CodeCacheIndex::default(),
+            };
+
+            IndexState {
+                total_bytes: index.entries.values().map(|entry| entry.size).sum(),
+                entries: index.entries,
+            }
+        })
+    }
+
+    /// Remove `key` from the index and delete its data. The caller persists
+    /// the index.
+    fn remove_entry(&self, state: &mut IndexState, key: &str) {
+        if let Some(entry) = state.entries.remove(key) {
+            state.total_bytes = state.total_bytes.saturating_sub(entry.size);
+            let _ = fs::remove_file(self.data_path(key));
+        }
+    }
+
+    fn write_index(&self, state: &IndexState) -> Result<()> {
+        let index = CodeCacheIndex {
+            version: INDEX_VERSION,
+            entries: state.entries.clone(),
+        };
+        let bytes = serde_json::to_vec(&index)
+            .map_err(|e| JSError::CodeCache(format!("Failed to encode index: {}", e)))?;
+        write_atomically(&self.root.join(INDEX_FILE), &bytes)
+            .map_err(|e| JSError::CodeCache(e.to_string()))
+    }
+
+    fn data_path(&self, key: &str) -> PathBuf {
+        self.root.join("data").join(key)
+    }
+}
@@ -2,6 +2,7 @@ use ahash::AHasher;
 use dashmap::DashMap;
 use parking_lot::{Mutex, RwLock};
 use serde_json::Value;
+use std::collections::HashSet;
 use std::hash::Hasher;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -9,17 +10,50 @@ use thiserror::Error;
 use tokio::sync::Mutex as AsyncMutex;
 use tokio::sync::Semaphore;
 
+pub mod agents;
+pub mod code_cache;
 pub mod gc;
 pub mod jit;
 pub mod modules;
 pub mod v8_binding;
-
-use crate::core::dom::Document;
+pub mod wasm;
+
+use crate::core::clipboard::Clipboard;
+use crate::core::document_write::DocumentWrites;
+use crate::core::dom::{Document, NodeId};
+use crate::core::drag::DragAndDrop;
+use crate::core::editing::EditingSession;
+use crate::core::features::FeatureSet;
+use crate::core::fonts::{FontFaceSet, FontLoadEvent, FontLoader};
+use crate::core::forms::ValidationReports;
+use crate::core::layout::ViewportScroll;
+use crate::core::media::MediaElements;
+use crate::core::navigation_timing::NavigationTimings;
+use crate::core::network::{
+    BlobStore, ContentSecurityPolicy, NetworkManager, RequestInitiator, ScriptFetches, SettledFetch,
+};
+use crate::core::permissions::PermissionStore;
+use crate::core::print::PrintRequests;
+use crate::core::speech::{SpeechEvent, SpeechSynthesis};
+use crate::core::storage::StorageArea;
+use crate::core::user_activation::UserActivation;
+use crate::pwa::install::InstallPrompts;
+use crate::renderer::HitRegions;
+use crate::sandbox::files::FileGrants;
 use crate::BrowserConfig;
+use agents::{origin_key, AgentId, AgentMetrics, AgentRegistry};
+use code_cache::{CodeCache, CodeCacheStats};
 use gc::{GarbageCollector, Heap as HeapManager};
 use jit::{CompiledFunction, JITCompiler, JSFunction, OptimizationLevel};
 use modules::ModuleResolver;
-use v8_binding::V8Runtime;
+use url::Url;
+use v8_binding::{
+    AgentBinding, ClipboardBinding, DocumentWriteBinding, DragBinding, EditingBinding, FontBinding,
+    FormBinding, HitTestBinding, InstallBinding, LongTask, MediaBinding, NavigationTimingBinding,
+    NetworkBinding, PostedMessage, PrintBinding, ScrollBinding, SpeechBinding, StorageBinding,
+    V8Error, V8Runtime, WasmBinding,
+};
+use wasm::{WasmPolicy, WasmStats};
 
 const MAX_EXECUTION_CONTEXTS: usize = 1000;
 const SCRIPT_CACHE_MAX_SIZE: usize = 10000;
@@ -93,8 +127,14 @@ pub enum JSError {
     JIT(String),
     #[error("Context limit exceeded")]
     ContextLimit,
+    #[error("Feature disabled: {0}")]
+    FeatureDisabled(&'static str),
     #[error("Runtime disposed")]
     Disposed,
+    #[error("Agent error: {0}")]
+    Agent(String),
+    #[error("Code cache error: {0}")]
+    CodeCache(String),
 }
 
 pub type Result<T> = std::result::Result<T, JSError>;
@@ -108,7 +148,7 @@ pub struct ExecutionContext {
     pub last_used: Instant,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct JSPerformanceMetrics {
     pub compilation_time_us: u64,
     pub execution_time_us: u64,
@@ -120,6 +160,17 @@ pub struct JSPerformanceMetrics {
     pub module_count: u32,
     pub context_count: u32,
     pub cache_hit_rate: f64,
+    pub jit_enabled: bool,
+    /// The live agents, in the order they were created.
+    pub agents: Vec<AgentMetrics>,
+    pub wasm_compile_time_us: u64,
+    pub wasm_instantiate_time_us: u64,
+    /// Live WebAssembly linear memory across agents.
+    pub wasm_memory_bytes: u64,
+    /// Scripts compiled from the code cache, and scripts looked up there
+    /// that compiled in full.
+    pub code_cache_hits: u64,
+    pub code_cache_misses: u64,
 }
 
 impl Default for JSPerformanceMetrics {
@@ -135,6 +186,13 @@ impl Default for JSPerformanceMetrics {
             module_count: 0,
             context_count: 0,
             cache_hit_rate: 0.0,
+            jit_enabled: false,
+            agents: Vec::new(),
+            wasm_compile_time_us: 0,
+            wasm_instantiate_time_us: 0,
+            wasm_memory_bytes: 0,
+            code_cache_hits: 0,
+            code_cache_misses: 0,
         }
     }
 }
@@ -151,9 +209,126 @@ pub struct ScriptInfo {
     pub jit_function: Option<Arc<CompiledFunction>>,
 }
 
+/// The isolate and what is tied to it. Each agent is a V8 context created
+/// under the agent's id; the current context is the current agent's.
 struct RuntimeCore {
     v8_runtime: V8Runtime,
     heap_stats: HeapStats,
+    agents: AgentRegistry,
+    // Messages posted between agents, delivered by `deliver_messages`.
+    mailbox: Arc<Mutex<Vec<PostedMessage>>>,
+    wasm_stats: Arc<WasmStats>,
+    // `BrowserConfig::enable_webassembly`.
+    webassembly: bool,
+}
+
+impl RuntimeCore {
+    fn current_agent(&self) -> AgentId {
+        AgentId(self.v8_runtime.context_id())
+    }
+
+    /// Bind what every agent's context has, once it exists.
+    fn bind_agent(&mut self, agent: AgentId) -> std::result::Result<(), V8Error> {
+        let binding = AgentBinding {
+            agent: agent.0,
+            origin: self
+                .agents
+                .origin(agent)
+                .unwrap_or_else(|| "null".to_string()),
+            mailbox: self.mailbox.clone(),
+        };
+        // Until a document's CSP says otherwise, compiling is allowed.
+        let wasm = self.wasm_binding(agent, true);
+        self.in_agent(agent, |v8| {
+            v8.bind_agent_api(binding)?;
+            v8.bind_wasm_api(wasm)
+        })
+    }
+
+    /// `agent`'s WebAssembly policy: modules and live linear memory are each
+    /// capped at its share of the JS memory budget.
+    fn wasm_binding(&self, agent: AgentId, allow_compile: bool) -> WasmBinding {
+        let limit = self.agents.heap_limit();
+        WasmBinding {
+            agent: agent.0,
+            policy: WasmPolicy {
+                enabled: self.webassembly,
+                allow_compile,
+                max_module_bytes: limit,
+                max_memory_bytes: limit,
+            },
+            stats: self.wasm_stats.clone(),
+        }
+    }
+
+    /// Run `f` in `agent`'s context, then switch back.
+    fn in_agent<T>(
+        &mut self,
+        agent: AgentId,
+        f: impl FnOnce(&mut V8Runtime) -> std::result::Result<T, V8Error>,
+    ) -> std::result::Result<T, V8Error> {
+        let previous = self.v8_runtime.context_id();
+        self.v8_runtime.switch_context(agent.0)?;
+        let result = f(&mut self.v8_runtime);
+        self.v8_runtime.switch_context(previous)?;
+        result
+    }
+
+    /// Attach a document of `origin` to its agent, creating the agent and
+    /// its context if needed.
+    fn open_document(&mut self, origin: Option<String>) -> Result<AgentId> {
+        let (agent, created) = self.agents.join(origin);
+        if created {
+            let spawned = self
+                .v8_runtime
+                .create_context(agent.0)
+                .and_then(|_| self.bind_agent(agent));
+            if let Err(e) = spawned {
+                self.agents.leave(agent);
+                let _ = self.v8_runtime.dispose_context(agent.0);
+                return Err(JSError::RuntimeInit(e.to_string()));
+            }
+            tracing::debug!("Agent {} created", agent.0);
+        }
+        Ok(agent)
+    }
+
+    /// Detach a document from `agent`, tearing the agent down with its last
+    /// one. The current agent's context must not be the one going away.
+    fn close_document(&mut self, agent: AgentId) -> Result<()> {
+        if !self.agents.leave(agent) {
+            return Ok(());
+        }
+        self.v8_runtime
+            .dispose_context(agent.0)
+            .map_err(|e| JSError::Agent(e.to_string()))?;
+        self.mailbox
+            .lock()
+            .retain(|message| message.target != agent.0);
+        self.wasm_stats.forget(agent.0);
+        tracing::debug!("Agent {} torn down", agent.0);
+        Ok(())
+    }
+
+    /// Collect the whole isolate and scale the agents' heap accounts to what
+    /// survived.
+    fn collect_heap(&mut self) {
+        self.v8_runtime.force_gc();
+        let used = self.v8_runtime.memory_usage() as u64;
+        self.agents.rescale(used);
+    }
+
+    /// Run an event loop step in every agent; returns the total it ran.
+    fn each_agent(
+        &mut self,
+        step: impl Fn(&mut V8Runtime) -> std::result::Result<usize, V8Error>,
+    ) -> std::result::Result<usize, V8Error> {
+        let mut ran = 0;
+        for agent in self.agents.ids() {
+            ran += self.in_agent(agent, &step)?;
+        }
+        Ok(ran)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -205,18 +380,32 @@ pub struct JSRuntime {
     disposed: Arc<parking_lot::RwLock<bool>>,
     cache_hits: Arc<Mutex<u64>>,
     cache_misses: Arc<Mutex<u64>>,
+    injected_apis: Arc<RwLock<HashSet<&'static str>>>,
+    code_cache: Option<Arc<CodeCache>>,
 }
 
 impl JSRuntime {
     pub async fn new(config: &BrowserConfig) -> Result<Self> {
-        let v8_runtime = V8Runtime::new()
+        // With JIT disabled, V8 itself runs interpreter-only as well as skipping cranelift.
+        let mut v8_runtime = V8Runtime::with_jitless(!config.enable_jit)
             .map_err(|e| JSError::RuntimeInit(format!("V8Runtime creation failed: {}", e)))?;
+        v8_runtime.set_virtual_time(config.virtual_time.clone());
 
         let heap_stats = HeapStats::new();
 
+        // The runtime starts with one opaque agent in the initial context;
+        // the first navigation replaces it.
+        let mut agents = AgentRegistry::new(config.js_memory_budget_mb as u64 * 1024 * 1024);
+        let (initial, _) = agents.join(None);
+        debug_assert_eq!(initial.0, V8Runtime::INITIAL_CONTEXT);
+
         let core = Arc::new(Mutex::new(RuntimeCore {
             v8_runtime,
             heap_stats,
+            agents,
+            mailbox: Arc::new(Mutex::new(Vec::new())),
+            wasm_stats: Arc::new(WasmStats::new()),
+            webassembly: config.enable_webassembly,
         }));
 
         let optimization_level = if config.enable_jit {
@@ -241,13 +430,25 @@ impl JSRuntime {
             module_resolver,
             execution_contexts: Arc::new(DashMap::new()),
             script_cache: Arc::new(DashMap::new()),
-            performance_metrics: Arc::new(RwLock::new(JSPerformanceMetrics::default())),
+            performance_metrics: Arc::new(RwLock::new(JSPerformanceMetrics {
+                // V8 is set up once per process; a runtime asking for JIT
+                // after it was set up jitless runs without.
+                jit_enabled: !V8Runtime::is_jitless(),
+                ..JSPerformanceMetrics::default()
+            })),
             config: config.clone(),
             next_context_id: Arc::new(Mutex::new(1)),
             context_semaphore: Arc::new(Semaphore::new(MAX_EXECUTION_CONTEXTS)),
             disposed: Arc::new(parking_lot::RwLock::new(false)),
             cache_hits: Arc::new(Mutex::new(0)),
             cache_misses: Arc::new(Mutex::new(0)),
+            injected_apis: Arc::new(RwLock::new(HashSet::new())),
+            // Private sessions leave nothing on disk.
+            code_cache: if config.private_mode {
+                None
+            } else {
+                CodeCache::from_config(&config.code_cache).map(Arc::new)
+            },
         };
 
         runtime.setup_global_apis().await?;
@@ -255,7 +456,134 @@ impl JSRuntime {
     }
 
     async fn setup_global_apis(&self) -> Result<()> {
-        Ok(())
+        let mut core = self.core.lock();
+        core.v8_runtime
+            .bind_event_loop()
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))?;
+        let initial = core.current_agent();
+        core.bind_agent(initial)
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// The agent script runs in.
+    pub fn current_agent(&self) -> AgentId {
+        self.core.lock().current_agent()
+    }
+
+    /// Attach a document at `url` to the agent of its origin and return it.
+    /// A new agent's context starts with only the event loop and messaging
+    /// bound. With `opener`, the document's `window.opener` is that agent's
+    /// window, reachable only through `postMessage` when it is another one.
+    pub fn open_document(&self, url: &Url, opener: Option<AgentId>) -> Result<AgentId> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let mut core = self.core.lock();
+        if let Some(opener) = opener {
+            if !core.agents.contains(opener) {
+                return Err(JSError::Agent(format!("no agent {}", opener.0)));
+            }
+        }
+        let agent = core.open_document(origin_key(url))?;
+        if let Some(opener) = opener {
+            core.in_agent(agent, |v8| v8.set_opener(opener.0))
+                .map_err(|e| JSError::Execution(e.to_string()))?;
+        }
+        Ok(agent)
+    }
+
+    /// Detach a document from `agent`; the agent goes away with its last
+    /// one. The current agent's last document only goes away by navigating.
+    pub fn close_document(&self, agent: AgentId) -> Result<()> {
+        let mut core = self.core.lock();
+        let documents = core
+            .agents
+            .documents(agent)
+            .ok_or_else(|| JSError::Agent(format!("no agent {}", agent.0)))?;
+        if agent == core.current_agent() && documents == 1 {
+            return Err(JSError::Agent(format!(
+                "agent {} runs the current document",
+                agent.0
+            )));
+        }
+        core.close_document(agent)
+    }
+
+    /// Make `agent` the one script runs in.
+    pub fn switch_agent(&self, agent: AgentId) -> Result<()> {
+        let mut core = self.core.lock();
+        if !core.agents.contains(agent) {
+            return Err(JSError::Agent(format!("no agent {}", agent.0)));
+        }
+        core.v8_runtime
+            .switch_context(agent.0)
+            .map_err(|e| JSError::Agent(e.to_string()))
+    }
+
+    /// Move the current document to `url`, `None` standing for an opaque
+    /// origin: attach the new document to its agent, make that agent
+    /// current and detach the old document. A same-origin navigation stays
+    /// in its agent; any other gets fresh globals.
+    pub fn navigate_agent(&self, url: Option<&Url>) -> Result<AgentId> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let mut core = self.core.lock();
+        let previous = core.current_agent();
+        let agent = core.open_document(url.and_then(origin_key))?;
+        core.v8_runtime
+            .switch_context(agent.0)
+            .map_err(|e| JSError::Agent(e.to_string()))?;
+        core.close_document(previous)?;
+        Ok(agent)
+    }
+
+    /// Deliver the messages agents posted to each other since the last call,
+    /// each as a `message` event task in its target. Messages whose
+    /// `targetOrigin` does not match the target, or whose target is gone,
+    /// are dropped. Returns how many were delivered.
+    pub fn deliver_messages(&self) -> Result<usize> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let mut core = self.core.lock();
+        let messages = std::mem::take(&mut *core.mailbox.lock());
+        let mut delivered = 0;
+        for message in messages {
+            let target = AgentId(message.target);
+            let target_origin = match core.agents.origin(target) {
+                Some(origin) => origin,
+                None => continue,
+            };
+            let matches = match message.target_origin.as_str() {
+                "*" => true,
+                "/" => {
+                    message.source == message.target
+                        || (message.origin != "null" && message.origin == target_origin)
+                }
+                requested => Url::parse(requested)
+                    .ok()
+                    .and_then(|url| origin_key(&url))
+                    .is_some_and(|requested| requested == target_origin),
+            };
+            if !matches {
+                tracing::debug!(
+                    "Dropped a message from agent {}: {} is not {}",
+                    message.source,
+                    target_origin,
+                    message.target_origin
+                );
+                continue;
+            }
+            let result = core.in_agent(target, |v8| {
+                v8.deliver_message(message.source, &message.origin, &message.data)
+            });
+            match result {
+                Ok(()) => delivered += 1,
+                Err(e) => tracing::warn!("message listener of agent {} failed: {}", target.0, e),
+            }
+        }
+        Ok(delivered)
     }
 
     pub async fn create_context(&self) -> Result<u64> {
@@ -300,11 +628,63 @@ impl JSRuntime {
         self.execute_in_context(context_id, script, "inline").await
     }
 
+    /// Run `script` in the current document's isolated world, which shares
+    /// the page's DOM but none of its globals.
+    pub async fn execute_isolated(&self, script: &str) -> Result<Value> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        self.core
+            .lock()
+            .v8_runtime
+            .execute_isolated(script)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
     pub async fn execute_in_context(
         &self,
         context_id: u64,
         script: &str,
         filename: &str,
+    ) -> Result<Value> {
+        self.run_in_context(context_id, script, filename, None)
+            .await
+    }
+
+    /// Run a classic script of the document at `document_url`, fetched from
+    /// `script_url` or inline. External scripts, and inline ones of at least
+    /// [`min_inline_bytes`](code_cache::CodeCacheConfig::min_inline_bytes), go through the code cache when
+    /// there is one; scripts of opaque origins never do.
+    pub async fn execute_document_script(
+        &self,
+        source: &str,
+        script_url: Option<&Url>,
+        document_url: &Url,
+    ) -> Result<Value> {
+        let context_id = self.create_context().await?;
+        let origin = document_url.origin();
+        let cacheable = self.code_cache.is_some()
+            && origin.is_tuple()
+            && (script_url.is_some() || source.len() >= self.config.code_cache.min_inline_bytes);
+        let code_cache_key = cacheable.then(|| {
+            CodeCache::key(
+                &origin.ascii_serialization(),
+                script_url.unwrap_or(document_url).as_str(),
+                source,
+                V8Runtime::v8_version(),
+            )
+        });
+        let filename = script_url.map_or("inline", Url::as_str);
+        self.run_in_context(context_id, source, filename, code_cache_key.as_deref())
+            .await
+    }
+
+    async fn run_in_context(
+        &self,
+        context_id: u64,
+        script: &str,
+        filename: &str,
+        code_cache_key: Option<&str>,
     ) -> Result<Value> {
         if *self.disposed.read() {
             return Err(JSError::Disposed);
@@ -325,11 +705,35 @@ impl JSRuntime {
         let result = if let Some(jit_function) = self.get_jit_compiled_function(script_hash).await {
             self.execute_jit_function(&jit_function, context_id).await
         } else {
-            self.core
-                .lock()
-                .v8_runtime
-                .execute(script)
-                .map_err(|e| JSError::Execution(e.to_string()))
+            let mut core = self.core.lock();
+            let agent = core.current_agent();
+            if core.agents.over_limit(agent) {
+                core.collect_heap();
+                if core.agents.over_limit(agent) {
+                    return Err(JSError::Memory(format!(
+                        "agent {} is over its {} byte heap limit",
+                        agent.0,
+                        core.agents.heap_limit()
+                    )));
+                }
+            }
+            let before = core.v8_runtime.memory_usage() as u64;
+            // What the script schedules is put down to it.
+            core.v8_runtime
+                .set_current_script(Some(filename).filter(|name| *name != "inline"));
+            let result = match code_cache_key.zip(self.code_cache.as_deref()) {
+                Some((key, code_cache)) => {
+                    self.execute_cached(&mut core.v8_runtime, script, code_cache, key)
+                }
+                None => core
+                    .v8_runtime
+                    .execute(script)
+                    .map_err(|e| JSError::Execution(e.to_string())),
+            };
+            core.v8_runtime.set_current_script(None);
+            let after = core.v8_runtime.memory_usage() as u64;
+            core.agents.charge(agent, before, after);
+            result
         }?;
 
         self.update_context_usage(context_id).await;
@@ -339,6 +743,57 @@ impl JSRuntime {
         Ok(result)
     }
 
+    /// Run `script` compiled from the code cache entry under `key`, falling
+    /// back to a full compile, and store what V8 produces for next time.
+    /// Scripts that throw leave the cache as it was.
+    fn execute_cached(
+        &self,
+        v8_runtime: &mut V8Runtime,
+        script: &str,
+        code_cache: &CodeCache,
+        key: &str,
+    ) -> Result<Value> {
+        let cached = code_cache.get(key);
+        let (value, outcome) = v8_runtime
+            .execute_with_code_cache(script, cached.as_deref(), true)
+            .map_err(|e| JSError::Execution(e.to_string()))?;
+
+        {
+            let mut metrics = self.performance_metrics.write();
+            if cached.is_some() && !outcome.rejected {
+                metrics.code_cache_hits += 1;
+            } else {
+                metrics.code_cache_misses += 1;
+            }
+        }
+        if outcome.rejected {
+            tracing::debug!("V8 rejected code cache entry {}", key);
+            if let Err(e) = code_cache.reject(key) {
+                tracing::warn!("Failed to drop rejected code cache entry: {}", e);
+            }
+        }
+        if let Some(data) = outcome.produced {
+            if let Err(e) = code_cache.put(key, &data) {
+                tracing::warn!("Failed to store code cache entry: {}", e);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Drop the compiled code cached for page scripts.
+    pub fn clear_code_cache(&self) -> Result<()> {
+        match &self.code_cache {
+            Some(code_cache) => code_cache.clear(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get_code_cache_stats(&self) -> Option<CodeCacheStats> {
+        self.code_cache
+            .as_ref()
+            .map(|code_cache| code_cache.get_stats())
+    }
+
     fn calculate_script_hash(&self, script: &str, filename: &str) -> u64 {
         let mut hasher = AHasher::default();
         hasher.write(filename.as_bytes());
@@ -357,12 +812,11 @@ impl JSRuntime {
                 && self.config.enable_jit;
         }
 
-        let compilation_start = Instant::now();
         let script_info = ScriptInfo {
             source_hash: script_hash,
             filename: filename.to_string(),
             is_module: false,
-            compilation_time: compilation_start.elapsed(),
+            compilation_time: Duration::ZERO,
             execution_count: 1,
             last_execution: Instant::now(),
             jit_compiled: false,
@@ -372,11 +826,7 @@ impl JSRuntime {
         self.script_cache.insert(script_hash, script_info);
         *self.cache_misses.lock() += 1;
 
-        {
-            let mut metrics = self.performance_metrics.write();
-            metrics.script_count += 1;
-            metrics.compilation_time_us += compilation_start.elapsed().as_micros() as u64;
-        }
+        self.performance_metrics.write().script_count += 1;
 
         false
     }
@@ -503,14 +953,445 @@ impl JSRuntime {
         Ok(result)
     }
 
-    pub async fn inject_document_api(&self, _document: &Document) -> Result<()> {
-        Ok(())
+    pub async fn inject_document_api(&self, document: &Document) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_dom_api(document.clone())
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Set what `document.visibilityState` and `document.prerendering`
+    /// read. A change of visibility fires `visibilitychange`; leaving
+    /// prerendering then fires `prerenderingchange`.
+    pub async fn set_page_state(&self, hidden: bool, prerendering: bool) -> Result<()> {
+        self.execute(&format!(
+            "typeof __vbePageState === 'function' && __vbePageState('{}', {})",
+            if hidden { "hidden" } else { "visible" },
+            prerendering
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    /// Fire `DOMContentLoaded` at the document, once its scripts ran.
+    pub async fn fire_dom_content_loaded(&self) -> Result<()> {
+        self.execute(
+            "typeof document === 'object' && typeof document.dispatchEvent === 'function' && \
+             document.dispatchEvent({ type: 'DOMContentLoaded', bubbles: true })",
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Invalidate the JS wrappers of the current document ahead of its
+    /// teardown; touching one afterwards throws.
+    pub async fn teardown_document_api(&self) -> Result<()> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        // Platform APIs were enabled for the outgoing page only.
+        self.reset_injected_apis();
+        self.core
+            .lock()
+            .v8_runtime
+            .unbind_dom_api()
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Free detached DOM nodes whose wrappers V8 has collected since the last
+    /// call. Cheap when nothing was collected; returns the nodes freed.
+    pub async fn reclaim_dom_nodes(&self) -> Result<usize> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        self.core
+            .lock()
+            .v8_runtime
+            .reclaim_dom_nodes()
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Force a full V8 collection and free the DOM nodes it made unreachable.
+    pub async fn collect_garbage(&self) -> Result<usize> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let gc_start = Instant::now();
+        let reclaimed = {
+            let mut core = self.core.lock();
+            let reclaimed = core
+                .v8_runtime
+                .collect_dom_garbage()
+                .map_err(|e| JSError::Memory(e.to_string()))?;
+            let used = core.v8_runtime.memory_usage() as u64;
+            core.agents.rescale(used);
+            reclaimed
+        };
+        self.performance_metrics.write().gc_time_us += gc_start.elapsed().as_micros() as u64;
+        Ok(reclaimed)
+    }
+
+    /// Fire `event` (an init dictionary with at least `type`) at `node`; it
+    /// bubbles to `window` unless `bubbles` is false.
+    pub async fn dispatch_element_event(&self, node: NodeId, event: &Value) -> Result<()> {
+        let script = format!(
+            "typeof __vbeFireEvent === 'function' && __vbeFireEvent({}, {})",
+            Value::String(node.0.to_string()),
+            event
+        );
+        self.execute(&script).await.map(|_| ())
+    }
+
+    /// Fire a cancelable event at `node`; false when a listener called
+    /// `preventDefault()`.
+    pub async fn dispatch_cancelable_event(&self, node: NodeId, event: &Value) -> Result<bool> {
+        let script = format!(
+            "typeof __vbeFireCancelableEvent !== 'function' || __vbeFireCancelableEvent({}, {})",
+            Value::String(node.0.to_string()),
+            event
+        );
+        self.execute(&script)
+            .await
+            .map(|outcome| outcome.as_bool().unwrap_or(true))
+    }
+
+    /// Route `navigator.sendBeacon` and `fetch` through `network` on behalf
+    /// of the document described by `initiator`; non-keepalive fetches run
+    /// in `fetches` and settle through [`deliver_fetches`](Self::deliver_fetches).
+    pub async fn inject_network_api(
+        &self,
+        network: Arc<NetworkManager>,
+        initiator: RequestInitiator,
+        fetches: Arc<ScriptFetches>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_network_api(NetworkBinding {
+                network,
+                initiator,
+                fetches,
+            })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Resolve or reject the `fetch()` promises of `results`.
+    pub async fn deliver_fetches(&self, results: &[SettledFetch]) -> Result<()> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let results = Value::Array(results.iter().map(SettledFetch::to_json).collect());
+        self.core
+            .lock()
+            .v8_runtime
+            .deliver_fetches(&results)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Take the globals of the features `features` lacks away from the
+    /// current document, and give back those it has again.
+    pub async fn apply_features(&self, features: &FeatureSet) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .gate_globals(&features.disabled_globals())
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Apply the current document's CSP to WebAssembly compilation: without
+    /// `'wasm-unsafe-eval'` or `'unsafe-eval'` in a `script-src` it has,
+    /// compiling throws a `CompileError`.
+    pub async fn inject_wasm_api(&self, csp: Option<&ContentSecurityPolicy>) -> Result<()> {
+        let mut core = self.core.lock();
+        let agent = core.current_agent();
+        let binding = core.wasm_binding(agent, csp.map_or(true, |csp| csp.allows_wasm_eval()));
+        core.v8_runtime
+            .bind_wasm_api(binding)
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `localStorage` and `sessionStorage` for the current document.
+    pub async fn inject_storage_api(
+        &self,
+        local: Arc<StorageArea>,
+        session: Arc<StorageArea>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_storage_api(StorageBinding { local, session })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `document.elementFromPoint()` and `elementsFromPoint()` over
+    /// the regions of the engine's last paint list.
+    pub async fn inject_hit_test_api(&self, regions: Arc<HitRegions>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_hit_test_api(HitTestBinding { regions })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `window.print()` over the engine's print request.
+    pub async fn inject_print_api(&self, requests: Arc<PrintRequests>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_print_api(PrintBinding { requests })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `document.write()` and `writeln()`, handing what is written
+    /// to `writes`.
+    pub async fn inject_document_write_api(&self, writes: Arc<DocumentWrites>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_document_write_api(DocumentWriteBinding { writes })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `window.scrollTo()`, `scrollBy()`, `scrollX` and `scrollY`
+    /// over the engine's scroll position.
+    pub async fn inject_scroll_api(&self, scroll: Arc<ViewportScroll>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_scroll_api(ScrollBinding { scroll })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Let `beforeinstallprompt` events prompt for the document's install
+    /// offer.
+    pub async fn inject_install_api(&self, prompts: Arc<InstallPrompts>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_install_api(InstallBinding { prompts })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Fire `beforeinstallprompt` at the window.
+    pub async fn fire_before_install_prompt(&self) -> Result<()> {
+        self.execute(
+            "typeof __vbeBeforeInstallPrompt === 'function' && __vbeBeforeInstallPrompt(['web'])",
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Settle the prompted `userChoice` and, when the app was installed,
+    /// fire `appinstalled`.
+    pub async fn settle_install_prompt(&self, installed: bool) -> Result<()> {
+        self.execute(&format!(
+            "typeof __vbeInstallSettled === 'function' && __vbeInstallSettled({})",
+            installed
+        ))
+        .await
+        .map(|_| ())
+    }
+
+    /// Expose constraint validation, queueing what interactive validation
+    /// reports into `reports`.
+    pub async fn inject_form_api(&self, reports: Arc<ValidationReports>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_form_api(FormBinding { reports })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `document.execCommand()` over the engine's editing session,
+    /// with the clipboard commands gated on the page's user activation.
+    pub async fn inject_editing_api(
+        &self,
+        editing: Arc<tokio::sync::RwLock<EditingSession>>,
+        clipboard: Arc<Clipboard>,
+        activation: Arc<UserActivation>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_editing_api(EditingBinding {
+                editing,
+                clipboard,
+                activation,
+            })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `DataTransfer` over the engine's drag, and dropped files
+    /// through the document's grants.
+    pub async fn inject_drag_api(
+        &self,
+        drag: Arc<DragAndDrop>,
+        files: Arc<FileGrants>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_drag_api(DragBinding { drag, files })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Fire the drag event `init` at `node` and report whether it went
+    /// uncanceled.
+    pub async fn dispatch_drag_event(&self, node: NodeId, init: &Value) -> Result<bool> {
+        let script = format!(
+            "typeof __vbeFireDragEvent !== 'function' || __vbeFireDragEvent({}, {})",
+            Value::String(node.0.to_string()),
+            init
+        );
+        self.execute(&script)
+            .await
+            .map(|outcome| outcome.as_bool().unwrap_or(true))
+    }
+
+    /// Expose `Blob`, object URLs in `blobs` and `navigator.clipboard` over
+    /// `clipboard` to the document at `document_url`, as `permissions` allow.
+    pub async fn inject_clipboard_api(
+        &self,
+        clipboard: Arc<Clipboard>,
+        permissions: Arc<PermissionStore>,
+        blobs: Arc<BlobStore>,
+        document_url: Url,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_clipboard_api(ClipboardBinding {
+                clipboard,
+                permissions,
+                blobs,
+                document_url,
+            })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Fire `paste` at `node` with `entries`, the `{ type, data }` of each
+    /// representation pasted, and report whether it went uncanceled.
+    pub async fn dispatch_paste_event(&self, node: NodeId, entries: &Value) -> Result<bool> {
+        let script = format!(
+            "typeof __vbeFirePasteEvent !== 'function' || __vbeFirePasteEvent({}, {})",
+            Value::String(node.0.to_string()),
+            entries
+        );
+        self.execute(&script)
+            .await
+            .map(|outcome| outcome.as_bool().unwrap_or(true))
+    }
+
+    /// Expose `<video>`/`<audio>` state over the document's media elements.
+    pub async fn inject_media_api(&self, media: Arc<MediaElements>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_media_api(MediaBinding { media })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `performance.timing` over the tab's navigation timings.
+    pub async fn inject_navigation_timing_api(
+        &self,
+        timings: Arc<NavigationTimings>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_navigation_timing_api(NavigationTimingBinding { timings })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Expose `speechSynthesis` over the engine's utterance queue.
+    pub async fn inject_speech_api(&self, speech: Arc<SpeechSynthesis>) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_speech_api(SpeechBinding { speech })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Fire `start`, `boundary`, `end` and the other speech events at the
+    /// utterances they belong to.
+    pub async fn deliver_speech_events(&self, events: &[SpeechEvent]) -> Result<()> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let events = Value::Array(events.iter().map(SpeechEvent::to_json).collect());
+        self.core
+            .lock()
+            .v8_runtime
+            .deliver_speech_events(&events)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Expose `FontFace` and `document.fonts` for the current document.
+    pub async fn inject_font_api(
+        &self,
+        fonts: Arc<FontFaceSet>,
+        loader: Arc<FontLoader>,
+    ) -> Result<()> {
+        self.core
+            .lock()
+            .v8_runtime
+            .bind_font_api(FontBinding { fonts, loader })
+            .map_err(|e| JSError::RuntimeInit(e.to_string()))
+    }
+
+    /// Report settled font loads to `document.fonts`, resolving `loaded`
+    /// promises and, once nothing is loading, `document.fonts.ready`.
+    pub async fn deliver_font_events(&self, events: &[FontLoadEvent]) -> Result<()> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        let events = Value::Array(events.iter().map(FontLoadEvent::to_json).collect());
+        self.core
+            .lock()
+            .v8_runtime
+            .deliver_font_events(&events)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Run the timers that are due in every agent, each as a task followed
+    /// by a microtask checkpoint. Returns how many ran.
+    pub async fn run_timers(&self) -> Result<usize> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        self.core
+            .lock()
+            .each_agent(V8Runtime::run_timers)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// Run this frame's `requestAnimationFrame` callbacks in every agent,
+    /// with a microtask checkpoint after each. Call once per frame, before
+    /// rendering.
+    pub async fn run_animation_frames(&self) -> Result<usize> {
+        if *self.disposed.read() {
+            return Err(JSError::Disposed);
+        }
+        self.core
+            .lock()
+            .each_agent(V8Runtime::run_animation_frames)
+            .map_err(|e| JSError::Execution(e.to_string()))
+    }
+
+    /// The longest timer or animation frame callback since the last call,
+    /// in any agent, with the script that scheduled it.
+    pub fn take_longest_task(&self) -> Option<LongTask> {
+        if *self.disposed.read() {
+            return None;
+        }
+        self.core.lock().v8_runtime.take_longest_task()
     }
 
     pub async fn execute_inline_scripts(&self, document: &Document) -> Result<()> {
         let scripts = document.get_inline_scripts();
 
-        for script in scripts {
+        for script in scripts.iter().filter(|script| script.is_classic()) {
             if let Err(e) = self.execute(&script.content).await {
                 tracing::warn!("Failed to execute inline script: {}", e);
             }
@@ -520,31 +1401,61 @@ impl JSRuntime {
     }
 
     pub async fn inject_serial_api(&self) -> Result<()> {
-        Ok(())
+        self.mark_api_injected("serial")
     }
 
     pub async fn inject_usb_api(&self) -> Result<()> {
-        Ok(())
+        self.mark_api_injected("usb")
     }
 
     pub async fn inject_bluetooth_api(&self) -> Result<()> {
-        Ok(())
+        self.mark_api_injected("bluetooth")
     }
 
     pub async fn inject_gamepad_api(&self) -> Result<()> {
-        Ok(())
+        self.mark_api_injected("gamepad")
     }
 
     pub async fn inject_webrtc_api(&self) -> Result<()> {
-        Ok(())
+        self.mark_api_injected("webrtc")
     }
 
     pub async fn inject_websocket_api(&self) -> Result<()> {
+        self.mark_api_injected("websocket")
+    }
+
+    fn mark_api_injected(&self, api: &'static str) -> Result<()> {
+        if !self.config.enable_chrome_apis {
+            return Err(JSError::FeatureDisabled("chrome_apis"));
+        }
+        self.injected_apis.write().insert(api);
         Ok(())
     }
 
+    pub fn is_api_injected(&self, api: &str) -> bool {
+        self.injected_apis.read().contains(api)
+    }
+
+    /// Drop every injected platform API so fresh page contexts start without them.
+    pub fn reset_injected_apis(&self) {
+        self.injected_apis.write().clear();
+    }
+
+    /// Microseconds V8 has spent compiling scripts so far.
+    pub fn compile_time_us(&self) -> u64 {
+        self.core.lock().v8_runtime.compile_time_us()
+    }
+
     pub async fn get_metrics(&self) -> JSPerformanceMetrics {
-        *self.performance_metrics.read()
+        let core = self.core.lock();
+        JSPerformanceMetrics {
+            compilation_time_us: core.v8_runtime.compile_time_us(),
+            agents: core.agents.metrics(),
+            wasm_compile_time_us: core.wasm_stats.compile_time_us(),
+            wasm_instantiate_time_us: core.wasm_stats.instantiate_time_us(),
+            wasm_memory_bytes: core.wasm_stats.memory_bytes(),
+            ..self.performance_metrics.read().clone()
+        }
     }
 
     pub async fn optimize_hot_functions(&self) -> Result<()> {
@@ -1,5 +1,35 @@
-use parking_lot::RwLock;
+use crate::core::clipboard::{Clipboard, ClipboardData, ClipboardItem};
+use crate::core::clock::VirtualClock;
+use crate::core::document_write::{DocumentWrites, WriteOutcome};
+use crate::core::dom::document::{DocumentError, NodeType};
+use crate::core::dom::{Document, MutationRecord, MutationType, NodeId};
+use crate::core::drag::{DataTransferMode, DragAndDrop, DragImage, DropEffect};
+use crate::core::editing::EditingSession;
+use crate::core::editing_commands::{self, Command};
+use crate::core::fonts::{parse_src, FontFaceDescriptor, FontFaceSet, FontLoader};
+use crate::core::forms::{self, ControlKind, ValidationReports};
+use crate::core::layout::ViewportScroll;
+use crate::core::media::{MediaElements, MediaKind};
+use crate::core::navigation_timing::NavigationTimings;
+use crate::core::network::{
+    is_forbidden_request_header, Blob, BlobStore, FetchRequest, NetworkManager, Priority,
+    RequestInitiator, ScriptFetches,
+};
+use crate::core::permissions::{Permission, PermissionStore};
+use crate::core::print::PrintRequests;
+use crate::core::speech::{SpeechRequest, SpeechSynthesis};
+use crate::core::storage::StorageArea;
+use crate::core::user_activation::UserActivation;
+use crate::js_engine::wasm::{WasmPolicy, WasmStats};
+use crate::pwa::install::InstallPrompts;
+use crate::renderer::HitRegions;
+use crate::sandbox::files::FileGrants;
+use parking_lot::{Mutex, RwLock};
+use serde_json::json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tracing::{debug, error, info, warn};
 use v8::{
     Function, FunctionCallbackArguments, HandleScope, Local, Object, PromiseResolver, ReturnValue,
@@ -247,318 +277,3213 @@ impl FetchCallbacks {
     }
 }
 
+/// Isolate slot payload for `localStorage` / `sessionStorage`: the current
+/// document's two areas.
+#[derive(Clone)]
+pub struct StorageBinding {
+    pub local: Arc<StorageArea>,
+    pub session: Arc<StorageArea>,
+}
+
+/// Native half of the `Storage` objects. Every method takes a leading
+/// `session` flag selecting `sessionStorage` over `localStorage`.
 pub struct StorageCallbacks;
 
 impl StorageCallbacks {
+    fn area(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+    ) -> Option<Arc<StorageArea>> {
+        match scope.get_slot::<StorageBinding>().cloned() {
+            Some(binding) if args.get(0).is_true() => Some(binding.session),
+            Some(binding) => Some(binding.local),
+            None => {
+                V8CallbackHelper::throw_error(scope, "Storage is not bound to this context");
+                None
+            }
+        }
+    }
+
+    fn string_argument(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        index: i32,
+        method: &str,
+    ) -> Option<String> {
+        match V8CallbackHelper::extract_string_argument(scope, args, index) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                V8CallbackHelper::throw_error(scope, &format!("{}: {}", method, e));
+                None
+            }
+        }
+    }
+
+    fn set_optional_string(
+        scope: &mut v8::HandleScope,
+        retval: &mut v8::ReturnValue,
+        value: Option<String>,
+    ) {
+        match value.map(|v| V8CallbackHelper::create_v8_string(scope, &v)) {
+            Some(Ok(string)) => retval.set(string.into()),
+            _ => V8CallbackHelper::set_null_return(scope, retval),
+        }
+    }
+
+    /// `getItem(session, key)`: the value, or `null`.
     pub fn get_item(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let _key = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            Ok(key) => key,
-            Err(_) => {
-                V8CallbackHelper::set_null_return(scope, &mut retval);
-                return;
-            }
+        let area = match Self::area(scope, &args) {
+            Some(area) => area,
+            None => return,
         };
-
-        match V8CallbackHelper::create_v8_string(scope, "mock_stored_value") {
-            Ok(value) => retval.set(value.into()),
-            Err(_) => V8CallbackHelper::set_null_return(scope, &mut retval),
-        }
+        let key = match Self::string_argument(scope, &args, 1, "getItem") {
+            Some(key) => key,
+            None => return,
+        };
+        Self::set_optional_string(scope, &mut retval, area.get(&key));
     }
 
+    /// `setItem(session, key, value)`: `false` when over quota, in which case
+    /// nothing was stored.
     pub fn set_item(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let key = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            Ok(key) => key,
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "setItem requires a key argument");
-                return;
-            }
+        let area = match Self::area(scope, &args) {
+            Some(area) => area,
+            None => return,
+        };
+        let key = match Self::string_argument(scope, &args, 1, "setItem") {
+            Some(key) => key,
+            None => return,
+        };
+        let value = match Self::string_argument(scope, &args, 2, "setItem") {
+            Some(value) => value,
+            None => return,
         };
 
-        let value = match V8CallbackHelper::extract_string_argument(scope, &args, 1) {
-            Ok(value) => value,
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "setItem requires a value argument");
-                return;
+        let stored = match area.set(&key, &value) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("setItem on {:?} refused: {}", area.key(), e);
+                false
             }
         };
-
-        info!("localStorage.setItem('{}', '{}')", key, value);
-        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+        retval.set(v8::Boolean::new(scope, stored).into());
     }
 
+    /// `removeItem(session, key)`.
     pub fn remove_item(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let key = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            Ok(key) => key,
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "removeItem requires a key argument");
-                return;
-            }
+        let area = match Self::area(scope, &args) {
+            Some(area) => area,
+            None => return,
         };
-
-        info!("localStorage.removeItem('{}')", key);
+        let key = match Self::string_argument(scope, &args, 1, "removeItem") {
+            Some(key) => key,
+            None => return,
+        };
+        area.remove(&key);
         V8CallbackHelper::set_undefined_return(scope, &mut retval);
     }
 
+    /// `clear(session)`.
     pub fn clear(
         scope: &mut v8::HandleScope,
-        _args: v8::FunctionCallbackArguments,
+        args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        info!("localStorage.clear()");
-        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+        if let Some(area) = Self::area(scope, &args) {
+            area.clear();
+            V8CallbackHelper::set_undefined_return(scope, &mut retval);
+        }
+    }
+
+    /// `key(session, index)`: the name at `index`, or `null`.
+    pub fn key(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let area = match Self::area(scope, &args) {
+            Some(area) => area,
+            None => return,
+        };
+        let name = match args.get(1).number_value(scope) {
+            Some(index) if index >= 0.0 && index.is_finite() => area.key_at(index as usize),
+            _ => None,
+        };
+        Self::set_optional_string(scope, &mut retval, name);
+    }
+
+    /// `length(session)`.
+    pub fn length(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(area) = Self::area(scope, &args) {
+            retval.set(v8::Number::new(scope, area.len() as f64).into());
+        }
     }
 }
 
-pub struct SerialCallbacks;
+/// Isolate slot payload for `document.elementFromPoint()`: the regions of
+/// the last paint list.
+#[derive(Clone)]
+pub struct HitTestBinding {
+    pub regions: Arc<HitRegions>,
+}
 
-impl SerialCallbacks {
-    pub fn request_port(
+/// Native half of `document.elementFromPoint()` and `elementsFromPoint()`.
+pub struct HitTestCallbacks;
+
+impl HitTestCallbacks {
+    /// `elementsFromPoint(x, y)`: the ids of the elements at the viewport
+    /// point, topmost first, as JSON.
+    pub fn elements_from_point(
         scope: &mut v8::HandleScope,
-        _args: v8::FunctionCallbackArguments,
+        args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+        let regions = match scope.get_slot::<HitTestBinding>().cloned() {
+            Some(binding) => binding.regions,
+            None => {
+                V8CallbackHelper::throw_error(scope, "Hit testing is not bound to this context");
                 return;
             }
         };
+        let (document, _) = match DomCallbacks::prepare(scope, &args, 0, "elementsFromPoint") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let x = args.get(0).number_value(scope).unwrap_or(f64::NAN);
+        let y = args.get(1).number_value(scope).unwrap_or(f64::NAN);
+        let ids: Vec<String> = regions
+            .elements_at(&document, x as f32, y as f32)
+            .iter()
+            .map(|id| id.0.to_string())
+            .collect();
+        DomCallbacks::set_string(scope, &mut retval, &json!(ids).to_string());
+    }
+}
 
-        let port_object = V8CallbackHelper::create_empty_object(scope);
+/// Isolate slot payload for `window.print()`: the document's print request.
+#[derive(Clone)]
+pub struct PrintBinding {
+    pub requests: Arc<PrintRequests>,
+}
 
-        if let Err(e) =
-            V8CallbackHelper::bind_method_to_object(scope, port_object, "open", Self::port_open)
-        {
-            error!("Failed to bind port.open: {}", e);
+/// Native half of `window.print()`.
+pub struct PrintCallbacks;
+
+impl PrintCallbacks {
+    fn requests(scope: &mut v8::HandleScope) -> Option<Arc<PrintRequests>> {
+        match scope.get_slot::<PrintBinding>().cloned() {
+            Some(binding) => Some(binding.requests),
+            None => {
+                V8CallbackHelper::throw_error(scope, "print is not bound to this context");
+                None
+            }
         }
+    }
 
-        if let Err(e) =
-            V8CallbackHelper::bind_method_to_object(scope, port_object, "write", Self::port_write)
-        {
-            error!("Failed to bind port.write: {}", e);
+    /// `pending()`: whether a request is waiting on the embedder.
+    pub fn pending(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(requests) = Self::requests(scope) {
+            retval.set(v8::Boolean::new(scope, requests.is_pending()).into());
         }
+    }
 
-        if let Err(e) =
-            V8CallbackHelper::bind_method_to_object(scope, port_object, "close", Self::port_close)
-        {
-            error!("Failed to bind port.close: {}", e);
+    /// `request()`: start a request for the engine to announce. `false` when
+    /// one is already pending.
+    pub fn request(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(requests) = Self::requests(scope) {
+            let started = requests.request().is_some();
+            retval.set(v8::Boolean::new(scope, started).into());
         }
+    }
+}
 
-        debug!("Serial port object created");
-        resolver.resolve(scope, port_object.into());
-        retval.set(promise.into());
+/// Isolate slot payload for `window.scrollTo()` and `scrollY`: the
+/// document's scroll position.
+#[derive(Clone)]
+pub struct ScrollBinding {
+    pub scroll: Arc<ViewportScroll>,
+}
+
+/// Native half of `window.scrollX`, `scrollY`, `scrollTo()` and
+/// `scrollBy()`.
+pub struct ScrollCallbacks;
+
+impl ScrollCallbacks {
+    fn scroll(scope: &mut v8::HandleScope) -> Option<Arc<ViewportScroll>> {
+        match scope.get_slot::<ScrollBinding>().cloned() {
+            Some(binding) => Some(binding.scroll),
+            None => {
+                V8CallbackHelper::throw_error(scope, "Scrolling is not bound to this context");
+                None
+            }
+        }
     }
 
-    pub fn port_open(
+    fn set_position(scope: &mut v8::HandleScope, retval: &mut v8::ReturnValue, (x, y): (f32, f32)) {
+        let position = v8::Array::new(scope, 2);
+        let x = v8::Number::new(scope, x as f64);
+        let y = v8::Number::new(scope, y as f64);
+        position.set_index(scope, 0, x.into());
+        position.set_index(scope, 1, y.into());
+        retval.set(position.into());
+    }
+
+    /// `position()`: `[scrollX, scrollY]`.
+    pub fn position(
         scope: &mut v8::HandleScope,
         _args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
-                return;
+        if let Some(scroll) = Self::scroll(scope) {
+            Self::set_position(scope, &mut retval, scroll.position());
+        }
+    }
+
+    /// `scrollTo(x, y)`: scroll the document, as far as it reaches. Returns
+    /// where it ended up.
+    pub fn scroll_to(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let Some(scroll) = Self::scroll(scope) else {
+            return;
+        };
+        let mut coordinate = |index| {
+            let value = args.get(index).number_value(scope).unwrap_or(0.0);
+            if value.is_finite() {
+                value as f32
+            } else {
+                0.0
             }
         };
-
-        info!("Serial port opened");
-        let undefined = v8::undefined(scope);
-        resolver.resolve(scope, undefined.into());
-        retval.set(promise.into());
+        let (x, y) = (coordinate(0), coordinate(1));
+        Self::set_position(scope, &mut retval, scroll.request(x, y));
     }
+}
 
-    pub fn port_write(
+/// Isolate slot payload for `document.write()`: the document's writes.
+#[derive(Clone)]
+pub struct DocumentWriteBinding {
+    pub writes: Arc<DocumentWrites>,
+}
+
+/// Native half of `document.write()` and `writeln()`.
+pub struct DocumentWriteCallbacks;
+
+impl DocumentWriteCallbacks {
+    /// `write(markup)`: hand `markup` to the parser, or open a new document
+    /// with it once the document is closed. Returns false when the script
+    /// has no insertion point, so nothing was written.
+    pub fn write(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+        let Some(binding) = scope.get_slot::<DocumentWriteBinding>().cloned() else {
+            V8CallbackHelper::throw_error(scope, "document.write is not bound to this context");
+            return;
+        };
+        let markup = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            Ok(markup) => markup,
+            Err(e) => {
+                V8CallbackHelper::throw_error(scope, &format!("write: {}", e));
                 return;
             }
         };
+        let written = binding.writes.write(&markup) != WriteOutcome::Ignored;
+        retval.set(v8::Boolean::new(scope, written).into());
+    }
+}
 
-        if let Ok(data) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            info!("Serial port write: {}", data);
-        }
+/// Isolate slot payload for `beforeinstallprompt`: the document's install
+/// offer.
+#[derive(Clone)]
+pub struct InstallBinding {
+    pub prompts: Arc<InstallPrompts>,
+}
 
-        let undefined = v8::undefined(scope);
-        resolver.resolve(scope, undefined.into());
-        retval.set(promise.into());
-    }
+/// Native half of `beforeinstallprompt`.
+pub struct InstallCallbacks;
 
-    pub fn port_close(
+impl InstallCallbacks {
+    /// `prompt()`: ask the engine to install the offered app. `false` when
+    /// nothing is offered or the page prompted already.
+    pub fn prompt(
         scope: &mut v8::HandleScope,
         _args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+        let prompts = match scope.get_slot::<InstallBinding>().cloned() {
+            Some(binding) => binding.prompts,
+            None => {
+                V8CallbackHelper::throw_error(
+                    scope,
+                    "Install prompts are not bound to this context",
+                );
                 return;
             }
         };
-
-        info!("Serial port closed");
-        let undefined = v8::undefined(scope);
-        resolver.resolve(scope, undefined.into());
-        retval.set(promise.into());
+        retval.set(v8::Boolean::new(scope, prompts.request()).into());
     }
 }
 
-pub struct TimerCallbacks;
+/// Isolate slot payload for `<video>`/`<audio>`: the document's media
+/// elements.
+#[derive(Clone)]
+pub struct MediaBinding {
+    pub media: Arc<MediaElements>,
+}
 
-impl TimerCallbacks {
-    pub fn set_timeout(
+/// Native half of the media element properties. Elements are DOM node ids;
+/// the document comes from the DOM binding's slot.
+pub struct MediaCallbacks;
+
+impl MediaCallbacks {
+    fn prepare(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        method: &str,
+    ) -> Option<(Arc<MediaElements>, Document, NodeId)> {
+        let media = match scope.get_slot::<MediaBinding>().cloned() {
+            Some(binding) => binding.media,
+            None => {
+                V8CallbackHelper::throw_error(scope, "Media is not bound to this context");
+                return None;
+            }
+        };
+        let (document, values) = DomCallbacks::prepare(scope, args, 1, method)?;
+        let node = DomCallbacks::node_id(scope, &values[0])?;
+        Some((media, document, node))
+    }
+
+    /// `state(id)`: the element's state as JSON, `null` for anything but
+    /// `<video>` and `<audio>`.
+    pub fn state(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        if args.length() < 2 {
-            V8CallbackHelper::throw_error(scope, "setTimeout requires callback and delay");
-            return;
-        }
-
-        let timer_id = v8::Number::new(scope, 1.0);
-        debug!("setTimeout called");
-        retval.set(timer_id.into());
+        let (media, document, node) = match Self::prepare(scope, &args, "state") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let state = document.get_node(node).and_then(|element| {
+            let element = element.read();
+            let kind = MediaKind::of(&element)?;
+            Some(media.state_json(node, kind, element.has_attribute("muted")))
+        });
+        let state = state.map(|state| state.to_string());
+        DomCallbacks::set_optional_string(scope, &mut retval, state);
     }
 
-    pub fn set_interval(
+    /// `setCurrentTime(id, seconds)`: seek and return the clamped position.
+    pub fn set_current_time(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        if args.length() < 2 {
-            V8CallbackHelper::throw_error(scope, "setInterval requires callback and delay");
-            return;
+        let (media, _, node) = match Self::prepare(scope, &args, "setCurrentTime") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let time = args.get(1).number_value(scope).unwrap_or(f64::NAN);
+        match media.set_current_time(node, time) {
+            Some(time) => retval.set(v8::Number::new(scope, time).into()),
+            None => V8CallbackHelper::set_undefined_return(scope, &mut retval),
         }
-
-        let timer_id = v8::Number::new(scope, 2.0);
-        debug!("setInterval called");
-        retval.set(timer_id.into());
     }
 
-    pub fn clear_timeout(
+    /// `play(id)`: `null` when the element plays, otherwise the name of the
+    /// `DOMException` to reject with.
+    pub fn play(
         scope: &mut v8::HandleScope,
-        _args: v8::FunctionCallbackArguments,
+        args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        debug!("clearTimeout called");
-        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+        if let Some((media, document, node)) = Self::prepare(scope, &args, "play") {
+            let document_url = document
+                .get_url()
+                .and_then(|url| url::Url::parse(&url).ok());
+            let refused = media
+                .play(node, document_url.as_ref())
+                .err()
+                .map(|error| error.name().to_string());
+            DomCallbacks::set_optional_string(scope, &mut retval, refused);
+        }
     }
 
-    pub fn clear_interval(
+    /// `pause(id)`.
+    pub fn pause(
         scope: &mut v8::HandleScope,
-        _args: v8::FunctionCallbackArguments,
-        mut retval: v8::ReturnValue,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
     ) {
-        debug!("clearInterval called");
-        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+        if let Some((media, _, node)) = Self::prepare(scope, &args, "pause") {
+            media.pause(node);
+        }
     }
-}
-
-pub struct CacheCallbacks;
 
-impl CacheCallbacks {
-    pub fn open(
+    /// `setMuted(id, muted)`: set the element's own muted state.
+    pub fn set_muted(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
-        mut retval: v8::ReturnValue,
+        _retval: v8::ReturnValue,
     ) {
-        let cache_name = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            Ok(name) => name,
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Cache name required");
-                return;
-            }
-        };
-
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
-                return;
-            }
-        };
-
-        let cache_object = V8CallbackHelper::create_empty_object(scope);
-
-        if let Err(e) =
-            V8CallbackHelper::bind_method_to_object(scope, cache_object, "add", Self::cache_add)
-        {
-            error!("Failed to bind cache.add: {}", e);
+        if let Some((media, _, node)) = Self::prepare(scope, &args, "setMuted") {
+            media.set_muted(node, args.get(1).is_true());
         }
+    }
 
-        if let Err(e) =
-            V8CallbackHelper::bind_method_to_object(scope, cache_object, "match", Self::cache_match)
-        {
-            error!("Failed to bind cache.match: {}", e);
+    /// `setVolume(id, volume)`; the prelude has checked the range.
+    pub fn set_volume(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some((media, _, node)) = Self::prepare(scope, &args, "setVolume") {
+            let volume = args.get(1).number_value(scope).unwrap_or(1.0);
+            media.set_volume(node, volume);
         }
-
-        debug!("Cache opened: {}", cache_name);
-        resolver.resolve(scope, cache_object.into());
-        retval.set(promise.into());
     }
 
-    pub fn cache_add(
+    /// `load(id)`: run the load algorithm again with the current `src` and
+    /// `poster`.
+    pub fn load(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some((media, document, node)) = Self::prepare(scope, &args, "load") {
+            media.load_element(&document, node);
+        }
+    }
+}
+
+/// Isolate slot payload for `performance.timing`: the tab's navigation
+/// timings.
+#[derive(Clone)]
+pub struct NavigationTimingBinding {
+    pub timings: Arc<NavigationTimings>,
+}
+
+/// Native half of `performance.timing`.
+pub struct NavigationTimingCallbacks;
+
+impl NavigationTimingCallbacks {
+    /// `timing()`: the current document's `performance.timing` as JSON.
+    pub fn timing(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
-            Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+        let timings = match scope.get_slot::<NavigationTimingBinding>().cloned() {
+            Some(binding) => binding.timings,
+            None => {
+                V8CallbackHelper::throw_error(
+                    scope,
+                    "Navigation timing is not bound to this context",
+                );
                 return;
             }
         };
+        let json = timings.performance_timing().to_string();
+        match v8::String::new(scope, &json) {
+            Some(json) => retval.set(json.into()),
+            None => V8CallbackHelper::set_undefined_return(scope, &mut retval),
+        }
+    }
+}
+
+/// Isolate slot payload for `speechSynthesis`: the engine's utterance
+/// queue.
+#[derive(Clone)]
+pub struct SpeechBinding {
+    pub speech: Arc<SpeechSynthesis>,
+}
+
+/// Native half of `speechSynthesis`. Utterances are the ids `speak` returns;
+/// their events come back through the engine's tick.
+pub struct SpeechCallbacks;
+
+impl SpeechCallbacks {
+    fn speech(scope: &mut v8::HandleScope) -> Option<Arc<SpeechSynthesis>> {
+        match scope.get_slot::<SpeechBinding>().cloned() {
+            Some(binding) => Some(binding.speech),
+            None => {
+                V8CallbackHelper::throw_error(scope, "Speech is not bound to this context");
+                None
+            }
+        }
+    }
+
+    /// `speak(text, lang, voiceURI, rate, pitch, volume)`: queue an
+    /// utterance and return its id. `voiceURI` is `null` for the default
+    /// voice.
+    pub fn speak(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let speech = match Self::speech(scope) {
+            Some(speech) => speech,
+            None => return,
+        };
+        let mut strings = Vec::with_capacity(2);
+        for index in 0..2 {
+            match V8CallbackHelper::extract_string_argument(scope, &args, index) {
+                Ok(value) => strings.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("speak: {}", e));
+                    return;
+                }
+            }
+        }
+        let voice = if args.get(2).is_null_or_undefined() {
+            None
+        } else {
+            V8CallbackHelper::extract_string_argument(scope, &args, 2).ok()
+        };
+        let mut number = |index: i32| {
+            args.get(index)
+                .number_value(scope)
+                .map_or(f32::NAN, |value| value as f32)
+        };
+        let (rate, pitch, volume) = (number(3), number(4), number(5));
+        let lang = strings.pop().unwrap_or_default();
+        let text = strings.pop().unwrap_or_default();
+        let id = speech.speak(SpeechRequest {
+            text,
+            lang,
+            voice,
+            rate,
+            pitch,
+            volume,
+        });
+        retval.set(v8::Number::new(scope, id as f64).into());
+    }
+
+    /// `cancel()`.
+    pub fn cancel(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some(speech) = Self::speech(scope) {
+            speech.cancel();
+        }
+    }
+
+    /// `pause()`.
+    pub fn pause(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some(speech) = Self::speech(scope) {
+            speech.pause();
+        }
+    }
+
+    /// `resume()`.
+    pub fn resume(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some(speech) = Self::speech(scope) {
+            speech.resume();
+        }
+    }
+
+    /// `status()`: `{pending, speaking, paused}` as JSON.
+    pub fn status(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(speech) = Self::speech(scope) {
+            let status = json!({
+                "pending": speech.pending(),
+                "speaking": speech.speaking(),
+                "paused": speech.paused(),
+            });
+            DomCallbacks::set_optional_string(scope, &mut retval, Some(status.to_string()));
+        }
+    }
+
+    /// `voices()`: the backend's voices as a JSON array.
+    pub fn voices(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(speech) = Self::speech(scope) {
+            let voices = serde_json::to_string(&speech.voices()).unwrap_or_else(|_| "[]".into());
+            DomCallbacks::set_optional_string(scope, &mut retval, Some(voices));
+        }
+    }
+}
+
+/// Isolate slot payload for constraint validation: where interactive
+/// validation queues the controls to show the user.
+#[derive(Clone)]
+pub struct FormBinding {
+    pub reports: Arc<ValidationReports>,
+}
+
+/// Native half of constraint validation. Controls are DOM node ids; the
+/// document comes from the DOM binding's slot.
+pub struct FormCallbacks;
+
+impl FormCallbacks {
+    fn prepare(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        count: i32,
+        method: &str,
+    ) -> Option<(Document, NodeId, Vec<String>)> {
+        let (document, values) = DomCallbacks::prepare(scope, args, count, method)?;
+        let node = DomCallbacks::node_id(scope, &values[0])?;
+        Some((document, node, values))
+    }
+
+    /// `state(id)`: the control's value and validity as JSON, `null` for
+    /// anything but a submittable element.
+    pub fn state(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, node, _) = match Self::prepare(scope, &args, 1, "state") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let kind = document
+            .get_node(node)
+            .and_then(|element| forms::control_kind(&element.read()));
+        let state = kind.map(|kind| {
+            json!({
+                "editable": matches!(kind, ControlKind::Input(_) | ControlKind::TextArea),
+                "value": forms::value(&document, node).unwrap_or_default(),
+                "willValidate": forms::will_validate(&document, node),
+                "validationMessage": forms::validation_message(&document, node),
+                "validity": forms::validity(&document, node).unwrap_or_default().to_json(),
+            })
+            .to_string()
+        });
+        DomCallbacks::set_optional_string(scope, &mut retval, state);
+    }
+
+    /// `setCustomValidity(id, message)`.
+    pub fn set_custom_validity(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, node, values) = match Self::prepare(scope, &args, 2, "setCustomValidity") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        match document.set_custom_validity(node, &values[1]) {
+            Ok(()) => V8CallbackHelper::set_undefined_return(scope, &mut retval),
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// `invalidControls(id)`: the ids of the form's failing controls as a
+    /// JSON array, `null` when `id` is no form.
+    pub fn invalid_controls(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, form, _) = match Self::prepare(scope, &args, 1, "invalidControls") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let is_form = document
+            .get_node(form)
+            .is_some_and(|element| forms::is_form(&element.read()));
+        let invalid = is_form.then(|| {
+            let ids: Vec<String> = forms::invalid_controls(&document, form)
+                .into_iter()
+                .map(|id| id.0.to_string())
+                .collect();
+            json!(ids).to_string()
+        });
+        DomCallbacks::set_optional_string(scope, &mut retval, invalid);
+    }
+
+    /// `report(id)`: queue the control for the engine to focus and describe.
+    pub fn report(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let reports = match scope.get_slot::<FormBinding>().cloned() {
+            Some(binding) => binding.reports,
+            None => {
+                V8CallbackHelper::throw_error(scope, "Forms are not bound to this context");
+                return;
+            }
+        };
+        if let Some((document, node, _)) = Self::prepare(scope, &args, 1, "report") {
+            let reported = reports.report(&document, node);
+            retval.set(v8::Boolean::new(scope, reported).into());
+        }
+    }
+}
+
+/// Isolate slot payload for drag-and-drop: the engine's drag, whose data
+/// store `event.dataTransfer` reads and writes, and the files drops granted.
+#[derive(Clone)]
+pub struct DragBinding {
+    pub drag: Arc<DragAndDrop>,
+    pub files: Arc<FileGrants>,
+}
+
+/// Native half of `DataTransfer` and `File`. Every method works on the
+/// drag in progress; without one, reads come back empty and writes are
+/// ignored.
+pub struct DragCallbacks;
+
+impl DragCallbacks {
+    fn binding(scope: &mut v8::HandleScope) -> Option<DragBinding> {
+        let binding = scope.get_slot::<DragBinding>().cloned();
+        if binding.is_none() {
+            V8CallbackHelper::throw_error(scope, "Drag and drop is not bound to this context");
+        }
+        binding
+    }
+
+    fn arguments(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        count: i32,
+        method: &str,
+    ) -> Option<(Arc<DragAndDrop>, Vec<String>)> {
+        let drag = Self::binding(scope)?.drag;
+        let mut values = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            match V8CallbackHelper::extract_string_argument(scope, args, index) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("{}: {}", method, e));
+                    return None;
+                }
+            }
+        }
+        Some((drag, values))
+    }
+
+    /// `getData(format)`: empty outside `dragstart` and `drop`.
+    pub fn get_data(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, values)) = Self::arguments(scope, &args, 1, "getData") {
+            let data = drag
+                .with_transfer(|transfer| transfer.get_data(&values[0]))
+                .unwrap_or_default();
+            DomCallbacks::set_string(scope, &mut retval, &data);
+        }
+    }
+
+    /// `setData(format, data)`: ignored outside `dragstart`.
+    pub fn set_data(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, values)) = Self::arguments(scope, &args, 2, "setData") {
+            drag.with_transfer(|transfer| transfer.set_data(&values[0], &values[1]));
+        }
+    }
+
+    /// `clearData(format)`, or `clearData()` for every format.
+    pub fn clear_data(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let count = i32::from(!args.get(0).is_null_or_undefined());
+        if let Some((drag, values)) = Self::arguments(scope, &args, count, "clearData") {
+            drag.with_transfer(|transfer| transfer.clear_data(values.first().map(String::as_str)));
+        }
+    }
+
+    /// `types()`: the formats as a JSON array.
+    pub fn types(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, _)) = Self::arguments(scope, &args, 0, "types") {
+            let types = drag
+                .with_transfer(|transfer| transfer.types())
+                .unwrap_or_default();
+            DomCallbacks::set_string(scope, &mut retval, &json!(types).to_string());
+        }
+    }
+
+    /// `files()`: the dropped files as a JSON array, empty outside `drop`.
+    pub fn files(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, _)) = Self::arguments(scope, &args, 0, "files") {
+            let files = drag
+                .with_transfer(|transfer| json!(transfer.files()).to_string())
+                .unwrap_or_else(|| "[]".to_string());
+            DomCallbacks::set_string(scope, &mut retval, &files);
+        }
+    }
+
+    pub fn effect_allowed(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, _)) = Self::arguments(scope, &args, 0, "effectAllowed") {
+            let effect = drag
+                .with_transfer(|transfer| transfer.effect_allowed().to_string())
+                .unwrap_or_else(|| "none".to_string());
+            DomCallbacks::set_string(scope, &mut retval, &effect);
+        }
+    }
+
+    /// `setEffectAllowed(value)`: only `dragstart` may change it.
+    pub fn set_effect_allowed(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, values)) = Self::arguments(scope, &args, 1, "setEffectAllowed") {
+            drag.with_transfer(|transfer| transfer.set_effect_allowed(&values[0]));
+        }
+    }
+
+    pub fn drop_effect(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, _)) = Self::arguments(scope, &args, 0, "dropEffect") {
+            let effect = drag
+                .with_transfer(|transfer| transfer.drop_effect())
+                .unwrap_or(DropEffect::None);
+            DomCallbacks::set_string(scope, &mut retval, effect.as_str());
+        }
+    }
+
+    /// `setDropEffect(value)`: anything but the four operations is ignored.
+    pub fn set_drop_effect(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some((drag, values)) = Self::arguments(scope, &args, 1, "setDropEffect") {
+            if let Some(effect) = DropEffect::parse(&values[0]) {
+                drag.with_transfer(|transfer| transfer.set_drop_effect(effect));
+            }
+        }
+    }
+
+    /// `setDragImage(id, x, y)`: drag the ghost of element `id` with the
+    /// pointer at `(x, y)` within it. Only `dragstart` may change it.
+    pub fn set_drag_image(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let (drag, values) = match Self::arguments(scope, &args, 1, "setDragImage") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node = match DomCallbacks::node_id(scope, &values[0]) {
+            Some(node) => node,
+            None => return,
+        };
+        let offset_x = args.get(1).number_value(scope).unwrap_or(0.0) as f32;
+        let offset_y = args.get(2).number_value(scope).unwrap_or(0.0) as f32;
+        drag.update(|session| {
+            if session.transfer.mode() == DataTransferMode::ReadWrite {
+                session.image = Some(DragImage {
+                    node,
+                    offset_x,
+                    offset_y,
+                });
+            }
+        });
+    }
+
+    /// `readFile(grant, asText)`: the file's contents as UTF-8 text, or as
+    /// a byte string (one char per byte). Throws when the grant no longer
+    /// holds.
+    pub fn read_file(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let files = match Self::binding(scope) {
+            Some(binding) => binding.files,
+            None => return,
+        };
+        let grant = args.get(0).number_value(scope).unwrap_or(-1.0);
+        if grant < 0.0 || grant.fract() != 0.0 {
+            V8CallbackHelper::throw_error(scope, "readFile: invalid file handle");
+            return;
+        }
+        let bytes = match files.read(grant as u64) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                V8CallbackHelper::throw_error(scope, &e.to_string());
+                return;
+            }
+        };
+        let contents: String = if args.get(1).is_true() {
+            String::from_utf8_lossy(&bytes).into_owned()
+        } else {
+            bytes.iter().map(|&byte| byte as char).collect()
+        };
+        DomCallbacks::set_string(scope, &mut retval, &contents);
+    }
+}
+
+/// Isolate slot payload for `navigator.clipboard` and `blob:` URLs: the
+/// engine's clipboard, the permissions gating it and the store object URLs
+/// are registered in, all on behalf of the document at `document_url`.
+#[derive(Clone)]
+pub struct ClipboardBinding {
+    pub clipboard: Arc<Clipboard>,
+    pub permissions: Arc<PermissionStore>,
+    pub blobs: Arc<BlobStore>,
+    pub document_url: url::Url,
+}
+
+/// Native half of `navigator.clipboard` and `URL.createObjectURL`. Bytes
+/// cross as byte strings (one char per byte).
+pub struct ClipboardCallbacks;
+
+impl ClipboardCallbacks {
+    fn binding(scope: &mut v8::HandleScope) -> Option<ClipboardBinding> {
+        let binding = scope.get_slot::<ClipboardBinding>().cloned();
+        if binding.is_none() {
+            V8CallbackHelper::throw_error(scope, "The clipboard is not bound to this context");
+        }
+        binding
+    }
+
+    /// `read()`: the clipboard's items as a JSON array of objects mapping
+    /// each type to its bytes, or `null` when the origin may not read it.
+    pub fn read(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        if !binding
+            .permissions
+            .is_granted(&binding.document_url, Permission::ClipboardRead)
+        {
+            retval.set(v8::null(scope).into());
+            return;
+        }
+        let mut items = Vec::new();
+        for item in binding.clipboard.read() {
+            let mut representations = serde_json::Map::new();
+            for data in item.representations() {
+                match data.to_web() {
+                    Ok(bytes) => {
+                        let bytes: String = bytes.iter().map(|&byte| byte as char).collect();
+                        representations.insert(data.mime_type().to_string(), json!(bytes));
+                    }
+                    Err(e) => {
+                        V8CallbackHelper::throw_error(scope, &e.to_string());
+                        return;
+                    }
+                }
+            }
+            items.push(serde_json::Value::Object(representations));
+        }
+        DomCallbacks::set_string(scope, &mut retval, &json!(items).to_string());
+    }
+
+    /// `write(items)`, `items` being what [`read`](Self::read) returns.
+    /// `false` when the origin may not write; throws for a type the
+    /// clipboard does not take or data that is not what its type says.
+    pub fn write(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let parsed = V8CallbackHelper::extract_string_argument(scope, &args, 0)
+            .map_err(|e| e.to_string())
+            .and_then(|items| {
+                serde_json::from_str::<Vec<HashMap<String, String>>>(&items)
+                    .map_err(|e| e.to_string())
+            });
+        let written = match parsed {
+            Ok(written) => written,
+            Err(e) => {
+                V8CallbackHelper::throw_error(scope, &format!("write: {}", e));
+                return;
+            }
+        };
+        if !binding
+            .permissions
+            .is_granted(&binding.document_url, Permission::ClipboardWrite)
+        {
+            retval.set(v8::Boolean::new(scope, false).into());
+            return;
+        }
+        let mut items = Vec::with_capacity(written.len());
+        for representations in written {
+            let mut item = ClipboardItem::new();
+            for (mime, bytes) in representations {
+                let bytes: Vec<u8> = bytes.chars().map(|c| c as u32 as u8).collect();
+                match ClipboardData::from_web(&mime, &bytes) {
+                    Ok(data) => item = item.with(data),
+                    Err(e) => {
+                        V8CallbackHelper::throw_error(scope, &e.to_string());
+                        return;
+                    }
+                }
+            }
+            items.push(item);
+        }
+        binding.clipboard.write(items);
+        retval.set(v8::Boolean::new(scope, true).into());
+    }
+
+    /// `createObjectURL(bytes, type)`: a `blob:` URL for the bytes.
+    pub fn create_object_url(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let mut values = Vec::with_capacity(2);
+        for index in 0..2 {
+            match V8CallbackHelper::extract_string_argument(scope, &args, index) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("createObjectURL: {}", e));
+                    return;
+                }
+            }
+        }
+        let blob = Blob {
+            content_type: values[1].clone(),
+            data: Arc::new(values[0].chars().map(|c| c as u32 as u8).collect()),
+        };
+        let url = binding.blobs.register(&binding.document_url, blob);
+        DomCallbacks::set_string(scope, &mut retval, &url);
+    }
+
+    /// `revokeObjectURL(url)`: only the document's own origin's URLs go.
+    pub fn revoke_object_url(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let url = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let own = format!(
+            "blob:{}/",
+            binding.document_url.origin().ascii_serialization()
+        );
+        if url.starts_with(&own) {
+            binding.blobs.revoke(&url);
+        }
+    }
+}
+
+/// Isolate slot payload for `document.execCommand()`: the engine's
+/// editing session, the clipboard the clipboard commands reach, and the
+/// page's user activation they need.
+#[derive(Clone)]
+pub struct EditingBinding {
+    pub editing: Arc<tokio::sync::RwLock<EditingSession>>,
+    pub clipboard: Arc<Clipboard>,
+    pub activation: Arc<UserActivation>,
+}
+
+/// Native half of `execCommand` and the `queryCommand*` methods. Commands
+/// are named as `execCommand` takes them; an unsupported one is neither
+/// enabled nor done. The document comes from the DOM binding's slot.
+pub struct EditingCallbacks;
+
+impl EditingCallbacks {
+    fn prepare(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        count: i32,
+        method: &str,
+    ) -> Option<(EditingBinding, Document, Vec<String>)> {
+        let binding = scope.get_slot::<EditingBinding>().cloned();
+        let binding = match binding {
+            Some(binding) => binding,
+            None => {
+                V8CallbackHelper::throw_error(scope, "Editing is not bound to this context");
+                return None;
+            }
+        };
+        let (document, values) = DomCallbacks::prepare(scope, args, count, method)?;
+        Some((binding, document, values))
+    }
+
+    /// The command named by `values[0]`, when it is supported and the page
+    /// may run it now.
+    fn command(binding: &EditingBinding, values: &[String]) -> Option<Command> {
+        Command::parse(&values[0])
+            .filter(|command| !command.needs_user_activation() || binding.activation.is_active())
+    }
+
+    /// `host()`: the id of the focused `contenteditable` element, or `null`.
+    pub fn host(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((binding, document, _)) = Self::prepare(scope, &args, 0, "host") {
+            let host = binding
+                .editing
+                .try_read()
+                .ok()
+                .and_then(|editing| editing.editing_host(&document))
+                .map(|host| host.0.to_string());
+            DomCallbacks::set_optional_string(scope, &mut retval, host);
+        }
+    }
+
+    /// `supported(command)`.
+    pub fn supported(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((_, _, values)) = Self::prepare(scope, &args, 1, "supported") {
+            let supported = Command::parse(&values[0]).is_some();
+            retval.set(v8::Boolean::new(scope, supported).into());
+        }
+    }
+
+    /// `enabled(command)`: false for the clipboard commands without user
+    /// activation.
+    pub fn enabled(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((binding, document, values)) = Self::prepare(scope, &args, 1, "enabled") {
+            let enabled = Self::command(&binding, &values).is_some_and(|command| {
+                binding.editing.try_read().is_ok_and(|editing| {
+                    editing_commands::is_enabled(&editing, &document, &binding.clipboard, command)
+                })
+            });
+            retval.set(v8::Boolean::new(scope, enabled).into());
+        }
+    }
+
+    /// `state(command)`.
+    pub fn state(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((binding, document, values)) = Self::prepare(scope, &args, 1, "state") {
+            let state = Command::parse(&values[0]).is_some_and(|command| {
+                binding
+                    .editing
+                    .try_read()
+                    .is_ok_and(|editing| editing_commands::state(&editing, &document, command))
+            });
+            retval.set(v8::Boolean::new(scope, state).into());
+        }
+    }
+
+    /// `inputType(command)`: what its `beforeinput` and `input` events
+    /// carry, `null` when it fires none.
+    pub fn input_type(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((_, _, values)) = Self::prepare(scope, &args, 1, "inputType") {
+            let input_type = Command::parse(&values[0])
+                .and_then(Command::input_type)
+                .map(str::to_string);
+            DomCallbacks::set_optional_string(scope, &mut retval, input_type);
+        }
+    }
+
+    /// `execute(command, value)`: whether it did anything.
+    pub fn execute(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (binding, document, values) = match Self::prepare(scope, &args, 2, "execute") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        // The engine lets go of the session before running script.
+        let done = match (
+            Self::command(&binding, &values),
+            binding.editing.try_write(),
+        ) {
+            (Some(command), Ok(mut editing)) => editing_commands::execute(
+                &mut editing,
+                &document,
+                &binding.clipboard,
+                command,
+                &values[1],
+            ),
+            _ => false,
+        };
+        retval.set(v8::Boolean::new(scope, done).into());
+    }
+
+    /// `clipboardEntries()`: the `{ type, data }` of each representation of
+    /// the first clipboard item, as a JSON array for a `paste` event.
+    pub fn clipboard_entries(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::prepare(scope, &args, 0, "clipboardEntries") {
+            Some((binding, _, _)) => binding,
+            None => return,
+        };
+        let item = binding
+            .clipboard
+            .read()
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let mut entries = Vec::new();
+        for data in item.representations() {
+            match data.to_web() {
+                Ok(bytes) => {
+                    let bytes: String = bytes.iter().map(|&byte| byte as char).collect();
+                    entries.push(json!({ "type": data.mime_type(), "data": bytes }));
+                }
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &e.to_string());
+                    return;
+                }
+            }
+        }
+        DomCallbacks::set_string(scope, &mut retval, &json!(entries).to_string());
+    }
+
+    /// `focus(id)`: give `id` keyboard focus; whether it took it, which only
+    /// editable elements do.
+    pub fn focus(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (binding, document, values) = match Self::prepare(scope, &args, 1, "focus") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node = match DomCallbacks::node_id(scope, &values[0]) {
+            Some(node) => node,
+            None => return,
+        };
+        let focused = binding.editing.try_write().is_ok_and(|mut editing| {
+            editing.focused() == Some(node) || editing.focus(&document, node)
+        });
+        retval.set(v8::Boolean::new(scope, focused).into());
+    }
+
+    /// `blur(id)`: drop focus if `id` has it.
+    pub fn blur(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let (binding, _, values) = match Self::prepare(scope, &args, 1, "blur") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node = match DomCallbacks::node_id(scope, &values[0]) {
+            Some(node) => node,
+            None => return,
+        };
+        let Ok(mut editing) = binding.editing.try_write() else {
+            return;
+        };
+        if editing.focused() == Some(node) {
+            editing.blur();
+        }
+    }
+
+    /// `focused()`: the id of the element with keyboard focus, or `null`.
+    pub fn focused(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some((binding, _, _)) = Self::prepare(scope, &args, 0, "focused") {
+            let focused = binding
+                .editing
+                .try_read()
+                .ok()
+                .and_then(|editing| editing.focused())
+                .map(|node| node.0.to_string());
+            DomCallbacks::set_optional_string(scope, &mut retval, focused);
+        }
+    }
+}
+
+pub struct SerialCallbacks;
+
+impl SerialCallbacks {
+    pub fn request_port(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        let port_object = V8CallbackHelper::create_empty_object(scope);
+
+        if let Err(e) =
+            V8CallbackHelper::bind_method_to_object(scope, port_object, "open", Self::port_open)
+        {
+            error!("Failed to bind port.open: {}", e);
+        }
+
+        if let Err(e) =
+            V8CallbackHelper::bind_method_to_object(scope, port_object, "write", Self::port_write)
+        {
+            error!("Failed to bind port.write: {}", e);
+        }
+
+        if let Err(e) =
+            V8CallbackHelper::bind_method_to_object(scope, port_object, "close", Self::port_close)
+        {
+            error!("Failed to bind port.close: {}", e);
+        }
+
+        debug!("Serial port object created");
+        resolver.resolve(scope, port_object.into());
+        retval.set(promise.into());
+    }
+
+    pub fn port_open(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        info!("Serial port opened");
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        retval.set(promise.into());
+    }
+
+    pub fn port_write(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        if let Ok(data) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            info!("Serial port write: {}", data);
+        }
+
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        retval.set(promise.into());
+    }
+
+    pub fn port_close(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        info!("Serial port closed");
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        retval.set(promise.into());
+    }
+}
+
+pub struct TimerCallbacks;
+
+impl TimerCallbacks {
+    pub fn set_timeout(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if args.length() < 2 {
+            V8CallbackHelper::throw_error(scope, "setTimeout requires callback and delay");
+            return;
+        }
+
+        let timer_id = v8::Number::new(scope, 1.0);
+        debug!("setTimeout called");
+        retval.set(timer_id.into());
+    }
+
+    pub fn set_interval(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if args.length() < 2 {
+            V8CallbackHelper::throw_error(scope, "setInterval requires callback and delay");
+            return;
+        }
+
+        let timer_id = v8::Number::new(scope, 2.0);
+        debug!("setInterval called");
+        retval.set(timer_id.into());
+    }
+
+    pub fn clear_timeout(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        debug!("clearTimeout called");
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+
+    pub fn clear_interval(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        debug!("clearInterval called");
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+}
+
+pub struct CacheCallbacks;
+
+impl CacheCallbacks {
+    pub fn open(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let cache_name = match V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            Ok(name) => name,
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Cache name required");
+                return;
+            }
+        };
+
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        let cache_object = V8CallbackHelper::create_empty_object(scope);
+
+        if let Err(e) =
+            V8CallbackHelper::bind_method_to_object(scope, cache_object, "add", Self::cache_add)
+        {
+            error!("Failed to bind cache.add: {}", e);
+        }
+
+        if let Err(e) =
+            V8CallbackHelper::bind_method_to_object(scope, cache_object, "match", Self::cache_match)
+        {
+            error!("Failed to bind cache.match: {}", e);
+        }
+
+        debug!("Cache opened: {}", cache_name);
+        resolver.resolve(scope, cache_object.into());
+        retval.set(promise.into());
+    }
+
+    pub fn cache_add(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        if let Ok(url) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            debug!("Cache add: {}", url);
+        }
+
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        retval.set(promise.into());
+    }
+
+    pub fn cache_match(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
+            Ok((p, r)) => (p, r),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                return;
+            }
+        };
+
+        if let Ok(url) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
+            debug!("Cache match: {}", url);
+        }
+
+        let undefined = v8::undefined(scope);
+        resolver.resolve(scope, undefined.into());
+        retval.set(promise.into());
+    }
+}
+
+/// Native half of the DOM bindings. Nodes cross into JS as decimal `NodeId`
+/// strings; the `Document` handle lives in an isolate slot set by
+/// `V8Runtime::bind_dom_api`.
+pub struct DomCallbacks;
+
+impl DomCallbacks {
+    /// Fetch the bound document and the first `count` arguments as strings,
+    /// throwing a JS error named after `method` when anything is missing.
+    fn prepare(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        count: i32,
+        method: &str,
+    ) -> Option<(Document, Vec<String>)> {
+        let document = scope.get_slot::<Document>().cloned();
+        let document = match document {
+            Some(document) => document,
+            None => {
+                V8CallbackHelper::throw_error(scope, "No document is bound to this context");
+                return None;
+            }
+        };
+
+        let mut values = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            match V8CallbackHelper::extract_string_argument(scope, args, index) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("{}: {}", method, e));
+                    return None;
+                }
+            }
+        }
+        Some((document, values))
+    }
+
+    fn node_id(scope: &mut v8::HandleScope, raw: &str) -> Option<NodeId> {
+        match raw.parse::<u64>() {
+            Ok(id) => Some(NodeId(id)),
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, &format!("Invalid node handle: {}", raw));
+                None
+            }
+        }
+    }
+
+    fn set_optional_string(
+        scope: &mut v8::HandleScope,
+        retval: &mut v8::ReturnValue,
+        value: Option<String>,
+    ) {
+        match value.map(|v| V8CallbackHelper::create_v8_string(scope, &v)) {
+            Some(Ok(string)) => retval.set(string.into()),
+            _ => V8CallbackHelper::set_null_return(scope, retval),
+        }
+    }
+
+    fn set_string(scope: &mut v8::HandleScope, retval: &mut v8::ReturnValue, value: &str) {
+        match V8CallbackHelper::create_v8_string(scope, value) {
+            Ok(string) => retval.set(string.into()),
+            Err(_) => V8CallbackHelper::set_undefined_return(scope, retval),
+        }
+    }
+
+    pub fn get_element_by_id(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "getElementById") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let found = document
+            .get_element_by_id(&values[0])
+            .map(|id| id.0.to_string());
+        Self::set_optional_string(scope, &mut retval, found);
+    }
+
+    /// `getElementsByClassName(names)`: a JSON array of the ids of the
+    /// elements with every class in `names`, in document order.
+    pub fn get_elements_by_class_name(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "getElementsByClassName") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let found = document.get_elements_by_class_name(&values[0]);
+        Self::set_node_list(scope, &mut retval, &found);
+    }
+
+    pub fn query_selector(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(found) = Self::query(scope, &args, "querySelector", false) {
+            let first = found.first().map(|id| id.0.to_string());
+            Self::set_optional_string(scope, &mut retval, first);
+        }
+    }
+
+    /// `querySelectorAll(selector)`: a JSON array of the matching node ids,
+    /// in document order.
+    pub fn query_selector_all(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(found) = Self::query(scope, &args, "querySelectorAll", false) {
+            Self::set_node_list(scope, &mut retval, &found);
+        }
+    }
+
+    /// `querySelectorWithin(id, selector)`: the first element under `id`
+    /// matching the selector.
+    pub fn query_selector_within(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(found) = Self::query(scope, &args, "querySelectorWithin", true) {
+            let first = found.first().map(|id| id.0.to_string());
+            Self::set_optional_string(scope, &mut retval, first);
+        }
+    }
+
+    /// `querySelectorAllWithin(id, selector)`: a JSON array of the elements
+    /// under `id` matching the selector, in document order.
+    pub fn query_selector_all_within(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(found) = Self::query(scope, &args, "querySelectorAllWithin", true) {
+            Self::set_node_list(scope, &mut retval, &found);
+        }
+    }
+
+    /// The elements a query finds, over the document or, when `scoped`,
+    /// under the node of the first argument. An invalid selector throws a
+    /// `SyntaxError`.
+    fn query(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        method: &str,
+        scoped: bool,
+    ) -> Option<Vec<NodeId>> {
+        let count = if scoped { 2 } else { 1 };
+        let (document, values) = Self::prepare(scope, args, count, method)?;
+        let result = if scoped {
+            let root = Self::node_id(scope, &values[0])?;
+            document.query_selector_all_within(root, &values[1])
+        } else {
+            document.query_selector_all(&values[0])
+        };
+        match result {
+            Ok(found) => Some(found),
+            Err(DocumentError::Query(message)) => {
+                let message = format!("{}: not a valid selector: {}", method, message);
+                if let Ok(message) = V8CallbackHelper::create_v8_string(scope, &message) {
+                    let exception = v8::Exception::syntax_error(scope, message);
+                    scope.throw_exception(exception);
+                }
+                None
+            }
+            Err(e) => {
+                V8CallbackHelper::throw_error(scope, &e.to_string());
+                None
+            }
+        }
+    }
+
+    fn set_node_list(scope: &mut v8::HandleScope, retval: &mut v8::ReturnValue, nodes: &[NodeId]) {
+        let ids: Vec<String> = nodes.iter().map(|id| id.0.to_string()).collect();
+        Self::set_string(scope, retval, &serde_json::json!(ids).to_string())
+    }
+
+    pub fn get_attribute(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "getAttribute") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let value = document
+            .get_node(node_id)
+            .and_then(|node| node.read().get_attribute(&values[1]));
+        Self::set_optional_string(scope, &mut retval, value);
+    }
+
+    pub fn set_attribute(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 3, "setAttribute") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.set_attribute(node_id, &values[1], &values[2]) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn remove_attribute(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "removeAttribute") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.remove_attribute(node_id, &values[1]) {
+            Ok(removed) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                if removed.is_some() {
+                    EventLoopCallbacks::schedule_mutation_delivery(scope);
+                }
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn get_style_property(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "getPropertyValue") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let value = document
+            .get_inline_style(node_id, false)
+            .and_then(|style| style.get_property_value(&values[1]))
+            .unwrap_or_default();
+        Self::set_string(scope, &mut retval, &value);
+    }
+
+    pub fn get_style_priority(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "getPropertyPriority") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let priority = document
+            .get_inline_style(node_id, false)
+            .map(|style| style.get_property_priority(&values[1]))
+            .unwrap_or_default();
+        Self::set_string(scope, &mut retval, &priority);
+    }
+
+    pub fn set_style_property(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 4, "setProperty") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.set_style_property(node_id, &values[1], &values[2], &values[3]) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn remove_style_property(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "removeProperty") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.remove_style_property(node_id, &values[1]) {
+            Ok(previous) => {
+                Self::set_string(scope, &mut retval, &previous);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn get_css_text(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "cssText") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let css_text = document
+            .get_inline_style(node_id, false)
+            .map(|style| style.get_css_text())
+            .unwrap_or_default();
+        Self::set_string(scope, &mut retval, &css_text);
+    }
+
+    pub fn set_css_text(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "cssText") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.set_style_css_text(node_id, &values[1]) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// `createElement(tag)`: a new element owned by script until inserted.
+    pub fn create_element(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "createElement") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        match document.create_node(NodeType::Element, values[0].to_ascii_lowercase()) {
+            Ok(node_id) => {
+                document.mark_detached(node_id);
+                Self::set_string(scope, &mut retval, &node_id.0.to_string());
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// The document node, parent of the top-level elements.
+    pub fn root_node(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, _) = match Self::prepare(scope, &args, 0, "rootNode") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let root = document.get_root_node().map(|id| id.0.to_string());
+        Self::set_optional_string(scope, &mut retval, root);
+    }
+
+    /// `documentElement()`: the root's first element child, or `null`.
+    pub fn document_element(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, _) = match Self::prepare(scope, &args, 0, "documentElement") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let element = document.document_element().map(|id| id.0.to_string());
+        Self::set_optional_string(scope, &mut retval, element);
+    }
+
+    /// `location()`: the parts of the document's URL `window.location`
+    /// shows, as JSON. A document without one is `about:blank`.
+    pub fn location(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, _) = match Self::prepare(scope, &args, 0, "location") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let url = document
+            .get_url()
+            .and_then(|url| url::Url::parse(&url).ok())
+            .unwrap_or_else(|| url::Url::parse("about:blank").expect("valid URL"));
+        // An empty fragment or query shows as nothing, as an absent one.
+        let prefixed = |prefix: &str, part: Option<&str>| match part {
+            Some(part) if !part.is_empty() => format!("{}{}", prefix, part),
+            _ => String::new(),
+        };
+        let parts = json!({
+            "href": url.as_str(),
+            "origin": url.origin().ascii_serialization(),
+            "protocol": format!("{}:", url.scheme()),
+            "host": match (url.host_str(), url.port()) {
+                (Some(host), Some(port)) => format!("{}:{}", host, port),
+                (host, _) => host.unwrap_or_default().to_string(),
+            },
+            "hostname": url.host_str().unwrap_or_default(),
+            "port": url.port().map(|port| port.to_string()).unwrap_or_default(),
+            "pathname": url.path(),
+            "search": prefixed("?", url.query()),
+            "hash": prefixed("#", url.fragment()),
+        });
+        Self::set_string(scope, &mut retval, &parts.to_string());
+    }
+
+    /// `serialize(id, kind)`: the node as markup, where `kind` is `outer`
+    /// (`outerHTML`), `inner` (`innerHTML`) or `xml` (`XMLSerializer`).
+    pub fn serialize(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "serialize") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let markup = match values[1].as_str() {
+            "outer" => document.outer_html(node_id),
+            "inner" => document.inner_html(node_id),
+            "xml" => document.serialize_xml(node_id),
+            kind => {
+                V8CallbackHelper::throw_error(scope, &format!("serialize: unknown kind {}", kind));
+                return;
+            }
+        };
+        match markup {
+            Ok(markup) => Self::set_string(scope, &mut retval, &markup),
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn text_content(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "textContent") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        Self::set_optional_string(scope, &mut retval, document.text_content(node_id));
+    }
+
+    pub fn set_text_content(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "textContent") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.set_text_content(node_id, &values[1]) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn parent_node(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "parentNode") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let parent = document.get_parent(node_id).map(|id| id.0.to_string());
+        Self::set_optional_string(scope, &mut retval, parent);
+    }
+
+    /// `appendChild(parent, child)`, moving `child` if it is already in the tree.
+    pub fn append_child(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "appendChild") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let (parent, child) = match (
+            Self::node_id(scope, &values[0]),
+            Self::node_id(scope, &values[1]),
+        ) {
+            (Some(parent), Some(child)) => (parent, child),
+            _ => return,
+        };
+        if document.get_node(parent).is_none() || document.get_node(child).is_none() {
+            V8CallbackHelper::throw_error(scope, "appendChild: node not found");
+            return;
+        }
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            if id == child {
+                V8CallbackHelper::throw_error(
+                    scope,
+                    "appendChild: the new child is an ancestor of the parent",
+                );
+                return;
+            }
+            ancestor = document.get_parent(id);
+        }
+        match document.append_child(parent, child) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    pub fn remove_child(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "removeChild") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let (parent, child) = match (
+            Self::node_id(scope, &values[0]),
+            Self::node_id(scope, &values[1]),
+        ) {
+            (Some(parent), Some(child)) => (parent, child),
+            _ => return,
+        };
+        if document.get_parent(child) != Some(parent) {
+            V8CallbackHelper::throw_error(
+                scope,
+                "removeChild: the node is not a child of this node",
+            );
+            return;
+        }
+        match document.remove_child(parent, child) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// `appendText(parent, text)`: append `text`, extending the parent's
+    /// last child when that is text.
+    pub fn append_text(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "appendText") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let parent = match Self::node_id(scope, &values[0]) {
+            Some(parent) => parent,
+            None => return,
+        };
+        match document.append_text(parent, &values[1]) {
+            Ok(_) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// `normalize(id)`: merge the adjacent text nodes below the node and
+    /// drop the empty ones.
+    pub fn normalize(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "normalize") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        match document.normalize(node_id) {
+            Ok(()) => {
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+                EventLoopCallbacks::schedule_mutation_delivery(scope);
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+
+    /// `trackWrapper(id, wrapper)`: hold `wrapper` weakly and keep its node
+    /// alive until V8 collects it.
+    pub fn track_wrapper(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 1, "trackWrapper") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let node_id = match Self::node_id(scope, &values[0]) {
+            Some(node_id) => node_id,
+            None => return,
+        };
+        let wrapper = match v8::Local::<v8::Object>::try_from(args.get(1)) {
+            Ok(wrapper) => wrapper,
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "trackWrapper: wrapper must be an object");
+                return;
+            }
+        };
+
+        if scope.get_slot::<DomWrappers>().is_none() {
+            V8CallbackHelper::throw_error(scope, "trackWrapper: no wrapper registry is bound");
+            return;
+        }
+
+        // The finalizer runs inside GC, so it only queues the release.
+        document.retain_wrapper(node_id);
+        let released = document.clone();
+        let weak = v8::Weak::with_finalizer(
+            scope,
+            wrapper,
+            Box::new(move |_| released.release_wrapper(node_id)),
+        );
+        if let Some(wrappers) = scope.get_slot_mut::<DomWrappers>() {
+            wrappers.track(weak);
+        }
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+
+    /// `contains(ancestor, node)`: whether `node` is `ancestor` or below it.
+    pub fn contains(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (document, values) = match Self::prepare(scope, &args, 2, "contains") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+        let (ancestor, node) = match (
+            Self::node_id(scope, &values[0]),
+            Self::node_id(scope, &values[1]),
+        ) {
+            (Some(ancestor), Some(node)) => (ancestor, node),
+            _ => return,
+        };
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == ancestor {
+                break;
+            }
+            current = document.get_parent(id);
+        }
+        retval.set(v8::Boolean::new(scope, current.is_some()).into());
+    }
+
+    /// Hand the mutation records queued since the last call to JS, as a JSON
+    /// array, and clear the queue.
+    pub fn take_mutation_records(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let records = match scope.get_slot::<PendingMutations>() {
+            Some(pending) => pending.take(),
+            None => Vec::new(),
+        };
+        let id = |node: &NodeId| node.0.to_string();
+        let records: Vec<_> = records
+            .iter()
+            .map(|record| {
+                json!({
+                    "type": match record.mutation_type {
+                        MutationType::ChildList => "childList",
+                        MutationType::Attributes => "attributes",
+                        MutationType::CharacterData => "characterData",
+                    },
+                    "target": id(&record.target),
+                    "addedNodes": record.added_nodes.iter().map(id).collect::<Vec<_>>(),
+                    "removedNodes": record.removed_nodes.iter().map(id).collect::<Vec<_>>(),
+                    "previousSibling": record.previous_sibling.as_ref().map(id),
+                    "nextSibling": record.next_sibling.as_ref().map(id),
+                    "attributeName": record.attribute_name,
+                    "attributeNamespace": record.attribute_namespace,
+                    "oldValue": record.old_value,
+                })
+            })
+            .collect();
+        Self::set_string(
+            scope,
+            &mut retval,
+            &serde_json::Value::Array(records).to_string(),
+        );
+    }
+}
+
+/// Isolate slot payload for the network bindings: which manager to queue on,
+/// which document the requests are made for and where its fetches settle.
+#[derive(Clone)]
+pub struct NetworkBinding {
+    pub network: Arc<NetworkManager>,
+    pub initiator: RequestInitiator,
+    pub fetches: Arc<ScriptFetches>,
+}
+
+/// Native half of `fetch`, `navigator.sendBeacon` and keepalive `fetch`.
+pub struct NetworkCallbacks;
+
+impl NetworkCallbacks {
+    /// Read `(method, url, body, binary, contentType, headers)` into a
+    /// request. `body` is a UTF-16 string, or a byte string (one char per
+    /// byte) when `binary` is true; `headers` is JSON `[name, value]` pairs.
+    /// Headers script may not set are dropped.
+    fn request(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        name: &str,
+    ) -> Option<(NetworkBinding, FetchRequest)> {
+        let binding = match scope.get_slot::<NetworkBinding>().cloned() {
+            Some(binding) => binding,
+            None => {
+                V8CallbackHelper::throw_error(scope, "Network access is not bound to this context");
+                return None;
+            }
+        };
+
+        let mut values = Vec::with_capacity(3);
+        for index in 0..3 {
+            match V8CallbackHelper::extract_string_argument(scope, args, index) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("{}: {}", name, e));
+                    return None;
+                }
+            }
+        }
+        let binary = args.get(3).is_true();
+        let content_type = V8CallbackHelper::extract_string_argument(scope, args, 4)
+            .ok()
+            .filter(|value| !value.is_empty());
+
+        let body = if binary {
+            values[2].chars().map(|c| c as u32 as u8).collect()
+        } else {
+            values[2].clone().into_bytes()
+        };
+
+        let mut headers: HashMap<String, String> =
+            V8CallbackHelper::extract_string_argument(scope, args, 5)
+                .ok()
+                .and_then(|json| serde_json::from_str::<Vec<(String, String)>>(&json).ok())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(name, _)| !is_forbidden_request_header(name))
+                .collect();
+        if let Some(content_type) = content_type {
+            headers.insert("Content-Type".to_string(), content_type);
+        }
+        let request = FetchRequest {
+            url: values[1].clone(),
+            method: values[0].to_ascii_uppercase(),
+            headers,
+            body: if body.is_empty() && values[0] != "POST" {
+                None
+            } else {
+                Some(body)
+            },
+            timeout_ms: None,
+            follow_redirects: true,
+            cache_policy: None,
+            priority: Priority::Low,
+            idempotent: false,
+        };
+        Some((binding, request))
+    }
+
+    /// `queueKeepalive(method, url, body, binary, contentType, headers)`.
+    /// Returns `false` when the request was refused or over quota.
+    pub fn queue_keepalive(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (binding, request) = match Self::request(scope, &args, "queueKeepalive") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+
+        let queued = match binding.network.send_keepalive(request, &binding.initiator) {
+            Ok(queued) => queued,
+            Err(e) => {
+                warn!("Keepalive request refused: {}", e);
+                false
+            }
+        };
+        retval.set(v8::Boolean::new(scope, queued).into());
+    }
+
+    /// `fetch(method, url, body, binary, contentType, headers)`: start a
+    /// fetch and return its id; the result arrives through
+    /// `__vbeFetchSettle`. Throws when the request is refused outright, or
+    /// the page has too many fetches in flight.
+    pub fn fetch(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let (binding, request) = match Self::request(scope, &args, "fetch") {
+            Some(prepared) => prepared,
+            None => return,
+        };
+
+        match binding
+            .fetches
+            .start(binding.network.clone(), request, binding.initiator.clone())
+        {
+            Ok(id) => {
+                if let Ok(id) = V8CallbackHelper::create_v8_string(scope, &id.to_string()) {
+                    retval.set(id.into());
+                }
+            }
+            Err(e) => V8CallbackHelper::throw_error(scope, &e.to_string()),
+        }
+    }
+}
 
-        if let Ok(url) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            debug!("Cache add: {}", url);
+/// Isolate slot payload: the clock `performance.now()`, `Date` and timers
+/// read, and the time origin `performance.now()` counts from.
+#[derive(Clone)]
+pub enum EventLoopClock {
+    System(Instant),
+    Virtual {
+        clock: Arc<VirtualClock>,
+        origin_ms: f64,
+    },
+}
+
+impl EventLoopClock {
+    /// Milliseconds since the time origin.
+    pub fn now_ms(&self) -> f64 {
+        match self {
+            Self::System(origin) => origin.elapsed().as_secs_f64() * 1000.0,
+            Self::Virtual { clock, origin_ms } => clock.now_ms() - origin_ms,
         }
+    }
 
-        let undefined = v8::undefined(scope);
-        resolver.resolve(scope, undefined.into());
-        retval.set(promise.into());
+    /// Milliseconds since the Unix epoch.
+    pub fn wall_ms(&self) -> f64 {
+        match self {
+            Self::System(_) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |since| since.as_secs_f64() * 1000.0),
+            Self::Virtual { clock, .. } => clock.wall_ms(),
+        }
     }
+}
 
-    pub fn cache_match(
+/// A task that ran long, and the script whose code it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongTask {
+    /// The URL of the script that ran, or scheduled the callback that ran;
+    /// `None` for inline script.
+    pub script_url: Option<String>,
+    pub duration: Duration,
+}
+
+/// Isolate slot payload, shared by every context: the script whose code is
+/// running, and the longest event loop task since it was last taken.
+#[derive(Clone, Default)]
+pub struct ScriptTasks(Arc<Mutex<ScriptTaskState>>);
+
+#[derive(Default)]
+struct ScriptTaskState {
+    current: Option<String>,
+    task_started: Option<Instant>,
+    longest: Option<LongTask>,
+}
+
+impl ScriptTasks {
+    /// Note that the script at `url` is being run, or no script when
+    /// `None`.
+    pub fn set_current(&self, url: Option<&str>) {
+        self.0.lock().current = url.map(str::to_string);
+    }
+
+    pub fn current(&self) -> Option<String> {
+        self.0.lock().current.clone()
+    }
+
+    fn begin_task(&self, url: Option<String>) {
+        let mut state = self.0.lock();
+        state.current = url;
+        state.task_started = Some(Instant::now());
+    }
+
+    fn end_task(&self) {
+        let mut state = self.0.lock();
+        let script_url = state.current.take();
+        let Some(started) = state.task_started.take() else {
+            return;
+        };
+        let duration = started.elapsed();
+        if state
+            .longest
+            .as_ref()
+            .map_or(true, |longest| duration > longest.duration)
+        {
+            state.longest = Some(LongTask {
+                script_url,
+                duration,
+            });
+        }
+    }
+
+    /// The longest task since the last call.
+    pub fn take_longest(&self) -> Option<LongTask> {
+        self.0.lock().longest.take()
+    }
+}
+
+/// Isolate slot payload: mutations of the bound document waiting to be
+/// delivered to JS `MutationObserver`s.
+#[derive(Clone, Default)]
+pub struct PendingMutations {
+    records: Arc<Mutex<Vec<MutationRecord>>>,
+    /// Set while a delivery microtask is queued but has not run yet.
+    scheduled: Arc<AtomicBool>,
+}
+
+impl PendingMutations {
+    pub fn push(&self, records: &[MutationRecord]) {
+        self.records.lock().extend_from_slice(records);
+    }
+
+    pub fn take(&self) -> Vec<MutationRecord> {
+        self.scheduled.store(false, Ordering::SeqCst);
+        std::mem::take(&mut *self.records.lock())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.lock().is_empty()
+    }
+}
+
+/// Isolate slot payload: the weak handles behind `trackWrapper`. Dropping the
+/// registry (on rebind or teardown) cancels the finalizers of every wrapper
+/// it still tracks.
+#[derive(Default)]
+pub struct DomWrappers {
+    handles: Vec<v8::Weak<v8::Object>>,
+}
+
+impl DomWrappers {
+    pub fn track(&mut self, handle: v8::Weak<v8::Object>) {
+        self.handles.push(handle);
+    }
+
+    /// Forget handles whose wrapper has been collected.
+    pub fn prune(&mut self) {
+        self.handles.retain(|handle| !handle.is_empty());
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+/// Native half of timers, animation frames and microtasks. Scheduling lives
+/// in the JS prelude; these give it a clock and control over the microtask
+/// queue, which the isolate never drains on its own.
+pub struct EventLoopCallbacks;
+
+impl EventLoopCallbacks {
+    /// Milliseconds since the context was created.
+    pub fn now(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let elapsed = scope
+            .get_slot::<EventLoopClock>()
+            .map_or(0.0, EventLoopClock::now_ms);
+        retval.set(v8::Number::new(scope, elapsed).into());
+    }
+
+    /// Milliseconds since the Unix epoch, by the context's clock; what a
+    /// virtual `Date` reads.
+    pub fn wall_now(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let now = scope
+            .get_slot::<EventLoopClock>()
+            .map_or(0.0, EventLoopClock::wall_ms);
+        retval.set(v8::Number::new(scope, now).into());
+    }
+
+    /// `enqueueMicrotask(callback)`, the native half of `queueMicrotask`.
+    pub fn enqueue_microtask(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
         mut retval: v8::ReturnValue,
     ) {
-        let (promise, resolver) = match V8CallbackHelper::create_promise_with_resolver(scope) {
-            Ok((p, r)) => (p, r),
+        match Local::<Function>::try_from(args.get(0)) {
+            Ok(callback) => {
+                scope.enqueue_microtask(callback);
+                V8CallbackHelper::set_undefined_return(scope, &mut retval);
+            }
+            Err(_) => V8CallbackHelper::throw_error(scope, "queueMicrotask requires a function"),
+        }
+    }
+
+    /// `scriptUrl()`: the URL of the script whose code is running, kept
+    /// with the callbacks it schedules; `null` for inline script.
+    pub fn script_url(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let url = scope
+            .get_slot::<ScriptTasks>()
+            .and_then(ScriptTasks::current);
+        match url.map(|url| V8CallbackHelper::create_v8_string(scope, &url)) {
+            Some(Ok(url)) => retval.set(url.into()),
+            _ => V8CallbackHelper::set_null_return(scope, &mut retval),
+        }
+    }
+
+    /// `beginTask(scriptUrl)`: a timer or animation frame callback the
+    /// script at `scriptUrl` scheduled starts running.
+    pub fn begin_task(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let url = args.get(0);
+        let url = if url.is_null_or_undefined() {
+            None
+        } else {
+            url.to_string(scope)
+                .map(|url| url.to_rust_string_lossy(scope))
+        };
+        if let Some(tasks) = scope.get_slot::<ScriptTasks>() {
+            tasks.begin_task(url);
+        }
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+
+    /// `endTask()`: the task begun last is over, microtasks included.
+    pub fn end_task(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(tasks) = scope.get_slot::<ScriptTasks>() {
+            tasks.end_task();
+        }
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+
+    /// `runMicrotasks()`: the checkpoint after each task the prelude runs.
+    pub fn run_microtasks(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        Self::checkpoint(scope);
+        V8CallbackHelper::set_undefined_return(scope, &mut retval);
+    }
+
+    /// Perform a microtask checkpoint: queue delivery of pending mutation
+    /// records, then run microtasks until the queue is empty.
+    pub fn checkpoint(scope: &mut v8::HandleScope) {
+        Self::schedule_mutation_delivery(scope);
+        scope.perform_microtask_checkpoint();
+    }
+
+    /// Queue the mutation observer microtask unless one is already queued or
+    /// there is nothing to deliver.
+    pub fn schedule_mutation_delivery(scope: &mut v8::HandleScope) {
+        let pending = match scope.get_slot::<PendingMutations>().cloned() {
+            Some(pending) => pending,
+            None => return,
+        };
+        if pending.is_empty() || pending.scheduled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let deliver = V8CallbackHelper::create_v8_string(scope, "__vbeDeliverMutations")
+            .ok()
+            .and_then(|name| {
+                let global = scope.get_current_context().global(scope);
+                global.get(scope, name.into())
+            })
+            .and_then(|value| Local::<Function>::try_from(value).ok());
+        match deliver {
+            Some(deliver) => scope.enqueue_microtask(deliver),
+            None => pending.scheduled.store(false, Ordering::SeqCst),
+        }
+    }
+}
+
+/// Isolate slot payload for the font bindings: the document's face set and
+/// the loader its fetches go through.
+#[derive(Clone)]
+pub struct FontBinding {
+    pub fonts: Arc<FontFaceSet>,
+    pub loader: Arc<FontLoader>,
+}
+
+/// Native half of `FontFace` and `document.fonts`. Faces are identified by
+/// their id in the [`FontFaceSet`]; state comes back as JSON descriptions.
+pub struct FontCallbacks;
+
+impl FontCallbacks {
+    fn binding(scope: &mut v8::HandleScope) -> Option<FontBinding> {
+        let binding = scope.get_slot::<FontBinding>().cloned();
+        if binding.is_none() {
+            V8CallbackHelper::throw_error(scope, "Fonts are not bound to this context");
+        }
+        binding
+    }
+
+    fn face_id(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments) -> u64 {
+        args.get(0)
+            .number_value(scope)
+            .filter(|id| *id >= 0.0)
+            .map_or(0, |id| id as u64)
+    }
+
+    fn set_json(scope: &mut v8::HandleScope, retval: &mut v8::ReturnValue, value: &str) {
+        match V8CallbackHelper::create_v8_string(scope, value) {
+            Ok(string) => retval.set(string.into()),
+            Err(_) => V8CallbackHelper::set_undefined_return(scope, retval),
+        }
+    }
+
+    /// `addFace(family, source, descriptors, binary)`: register a face built
+    /// by the `FontFace` constructor. `source` is a CSS `src` value, or a byte
+    /// string (one char per byte) of font data when `binary` is true.
+    /// `descriptors` is a JSON object. Returns the face's description.
+    pub fn add_face(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+
+        let mut values = Vec::with_capacity(3);
+        for index in 0..3 {
+            match V8CallbackHelper::extract_string_argument(scope, &args, index) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &format!("addFace: {}", e));
+                    return;
+                }
+            }
+        }
+        let binary = args.get(3).is_true();
+
+        let mut descriptor = FontFaceDescriptor::new(&values[0]);
+        let descriptors: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&values[2]).unwrap_or_default();
+        for (name, value) in &descriptors {
+            if let Some(value) = value.as_str() {
+                if let Err(e) = descriptor.set(name, value) {
+                    V8CallbackHelper::throw_error(scope, &e.to_string());
+                    return;
+                }
+            }
+        }
+
+        let id = if binary {
+            let data: Vec<u8> = values[1].chars().map(|c| c as u32 as u8).collect();
+            binding.fonts.add_data(descriptor, &data)
+        } else {
+            let base = &binding.loader.initiator().document_url;
+            match parse_src(&values[1], base) {
+                Ok(sources) => {
+                    descriptor.sources = sources;
+                    binding.fonts.add(descriptor, false)
+                }
+                Err(e) => {
+                    V8CallbackHelper::throw_error(scope, &e.to_string());
+                    return;
+                }
+            }
+        };
+
+        let description = binding.fonts.describe(id).unwrap_or_default();
+        Self::set_json(scope, &mut retval, &description.to_string());
+    }
+
+    /// `load(id)`: start loading the face. Returns `false` if it is unknown.
+    pub fn load(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let id = Self::face_id(scope, &args);
+        let started = binding.fonts.start_load(id, &binding.loader);
+        retval.set(v8::Boolean::new(scope, started).into());
+    }
+
+    /// `describe(id)`: the face's current state as JSON, or `undefined`.
+    pub fn describe(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let id = Self::face_id(scope, &args);
+        match binding.fonts.describe(id) {
+            Some(description) => Self::set_json(scope, &mut retval, &description.to_string()),
+            None => V8CallbackHelper::set_undefined_return(scope, &mut retval),
+        }
+    }
+
+    /// `members()`: ids of the faces in `document.fonts`, as a JSON array.
+    pub fn members(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let members = json!(binding.fonts.members());
+        Self::set_json(scope, &mut retval, &members.to_string());
+    }
+
+    /// `setMember(id, member)`: add the face to or remove it from
+    /// `document.fonts`. Returns `false` if it is unknown.
+    pub fn set_member(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let id = Self::face_id(scope, &args);
+        let changed = binding.fonts.set_membership(id, args.get(1).is_true());
+        retval.set(v8::Boolean::new(scope, changed).into());
+    }
+
+    /// `isLoading()`: whether any face in `document.fonts` is loading.
+    pub fn is_loading(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        retval.set(v8::Boolean::new(scope, binding.fonts.is_loading()).into());
+    }
+}
+
+/// A `postMessage` from one agent to another, waiting for the engine to
+/// deliver it.
+#[derive(Debug, Clone)]
+pub struct PostedMessage {
+    pub source: u64,
+    /// The sender's origin as `MessageEvent.origin` spells it.
+    pub origin: String,
+    pub target: u64,
+    /// `*`, `/` for the sender's own origin, or the origin the target must
+    /// have for the message to arrive.
+    pub target_origin: String,
+    /// The message, cloned as JSON.
+    pub data: String,
+}
+
+/// Isolate slot payload for cross-agent messaging: the agent the context
+/// belongs to and the runtime's outgoing messages.
+#[derive(Clone)]
+pub struct AgentBinding {
+    pub agent: u64,
+    pub origin: String,
+    pub mailbox: Arc<Mutex<Vec<PostedMessage>>>,
+}
+
+/// Native half of `postMessage` and cross-agent window proxies. Agents are
+/// named by their ids, as strings.
+pub struct AgentCallbacks;
+
+impl AgentCallbacks {
+    fn binding(scope: &mut v8::HandleScope) -> Option<AgentBinding> {
+        match scope.get_slot::<AgentBinding>().cloned() {
+            Some(binding) => Some(binding),
+            None => {
+                V8CallbackHelper::throw_error(scope, "agents are not bound to this context");
+                None
+            }
+        }
+    }
+
+    /// `self()`: the id of the agent this context belongs to.
+    pub fn self_id(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(binding) = Self::binding(scope) {
+            if let Ok(id) = V8CallbackHelper::create_v8_string(scope, &binding.agent.to_string()) {
+                retval.set(id.into());
+            }
+        }
+    }
+
+    /// `postMessage(target, data, targetOrigin)`: queue `data`, already
+    /// serialized, for agent `target`.
+    pub fn post_message(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let arguments = (0..3)
+            .map(|index| V8CallbackHelper::extract_string_argument(scope, &args, index))
+            .collect::<Result<Vec<_>, _>>();
+        let (target, data, target_origin) = match arguments.as_deref() {
+            Ok([target, data, target_origin]) => (target, data, target_origin),
+            _ => {
+                V8CallbackHelper::throw_error(scope, "postMessage needs a target, data and origin");
+                return;
+            }
+        };
+        let target = match target.parse::<u64>() {
+            Ok(target) => target,
             Err(_) => {
-                V8CallbackHelper::throw_error(scope, "Failed to create promise");
+                V8CallbackHelper::throw_error(scope, "postMessage target is not an agent");
                 return;
             }
         };
+        debug!(
+            "Agent {} posted a message to agent {}",
+            binding.agent, target
+        );
+        binding.mailbox.lock().push(PostedMessage {
+            source: binding.agent,
+            origin: binding.origin,
+            target,
+            target_origin: target_origin.clone(),
+            data: data.clone(),
+        });
+    }
+}
 
-        if let Ok(url) = V8CallbackHelper::extract_string_argument(scope, &args, 0) {
-            debug!("Cache match: {}", url);
+/// Isolate slot payload for WebAssembly: the context's policy and the
+/// runtime's wasm statistics.
+#[derive(Clone)]
+pub struct WasmBinding {
+    pub agent: u64,
+    pub policy: WasmPolicy,
+    pub stats: Arc<WasmStats>,
+}
+
+/// Native half of the `WebAssembly` gate.
+pub struct WasmCallbacks;
+
+impl WasmCallbacks {
+    fn binding(scope: &mut v8::HandleScope) -> Option<WasmBinding> {
+        match scope.get_slot::<WasmBinding>().cloned() {
+            Some(binding) => Some(binding),
+            None => {
+                V8CallbackHelper::throw_error(scope, "WebAssembly is not bound to this context");
+                None
+            }
         }
+    }
 
-        let undefined = v8::undefined(scope);
-        resolver.resolve(scope, undefined.into());
-        retval.set(promise.into());
+    /// `enabled()`: whether the context keeps `WebAssembly` at all.
+    pub fn enabled(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(binding) = Self::binding(scope) {
+            retval.set(v8::Boolean::new(scope, binding.policy.enabled).into());
+        }
+    }
+
+    /// `check(bytes)`: vet a module, given as a `Uint8Array`, before it is
+    /// compiled. Returns JSON: `{ memoryBytes }` with the linear memory it
+    /// declares, or `{ error: { name, message } }`.
+    pub fn check(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let view = match v8::Local::<v8::ArrayBufferView>::try_from(args.get(0)) {
+            Ok(view) => view,
+            Err(_) => {
+                V8CallbackHelper::throw_error(scope, "check needs the module's bytes");
+                return;
+            }
+        };
+        let mut module = vec![0; view.byte_length()];
+        view.copy_contents(&mut module);
+
+        let report = match binding.policy.check_module(&module) {
+            Ok(memory_bytes) => json!({ "memoryBytes": memory_bytes }),
+            Err((name, message)) => {
+                debug!("WebAssembly module refused: {}", message);
+                json!({ "error": { "name": name, "message": message } })
+            }
+        };
+        if let Ok(report) = V8CallbackHelper::create_v8_string(scope, &report.to_string()) {
+            retval.set(report.into());
+        }
+    }
+
+    /// `memoryLimit()`: the most linear memory the context may hold, in
+    /// bytes.
+    pub fn memory_limit(
+        scope: &mut v8::HandleScope,
+        _args: v8::FunctionCallbackArguments,
+        mut retval: v8::ReturnValue,
+    ) {
+        if let Some(binding) = Self::binding(scope) {
+            let limit = binding.policy.max_memory_bytes as f64;
+            retval.set(v8::Number::new(scope, limit).into());
+        }
+    }
+
+    /// `record(kind, micros)`: add to the compile (`"compile"`) or
+    /// instantiate (`"instantiate"`) time.
+    pub fn record(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        let binding = match Self::binding(scope) {
+            Some(binding) => binding,
+            None => return,
+        };
+        let kind = V8CallbackHelper::extract_string_argument(scope, &args, 0).unwrap_or_default();
+        let micros = args
+            .get(1)
+            .number_value(scope)
+            .filter(|micros| micros.is_finite() && *micros > 0.0)
+            .unwrap_or(0.0) as u64;
+        match kind.as_str() {
+            "compile" => binding.stats.record_compile(micros),
+            "instantiate" => binding.stats.record_instantiate(micros),
+            _ => {}
+        }
+    }
+
+    /// `reportMemory(bytes)`: the context's live linear memory now.
+    pub fn report_memory(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _retval: v8::ReturnValue,
+    ) {
+        if let Some(binding) = Self::binding(scope) {
+            let bytes = args.get(0).number_value(scope).unwrap_or(0.0).max(0.0) as u64;
+            binding.stats.set_memory(binding.agent, bytes);
+        }
     }
 }
@@ -2,14 +2,2240 @@ pub mod callbacks;
 
 pub use callbacks::*;
 
+use crate::core::clock::VirtualTime;
+use crate::core::dom::document::MutationObserver;
+use crate::core::dom::Document;
 use crate::js_engine::gc::GarbageCollector;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
 use v8::{HandleScope, Local, TryCatch};
 
+/// JS half of the DOM bindings: wraps the native `__vbeDom` functions in
+/// `document`/`Element` objects. `element.style` is a proxy mapping camelCase
+/// properties onto the inline declaration. `outerHTML`, `innerHTML` and
+/// `XMLSerializer` serialize natively, and `textContent` reads and replaces
+/// text natively. Strings passed to `append()` extend the text already last,
+/// and `normalize()` merges adjacent text. Events raised by the engine arrive
+/// through `__vbeFireEvent` and bubble from the element to `window`;
+/// `__vbeFireCancelableEvent` also reports whether a listener canceled them.
+/// `MutationObserver` callbacks run from `__vbeDeliverMutations`, which the
+/// native side queues as a microtask when records are pending.
+/// `__vbeWrapNode` hands embedders the wrapper of a node id.
+///
+/// Wrappers are cached through `WeakRef`s and registered with
+/// `native.trackWrapper`, so a wrapper script has dropped can be collected and
+/// its node freed once it is also out of the tree. `__vbeDomSweep` forgets the
+/// cache entries and listeners of what was collected; `__vbeDomTeardown`
+/// swaps `native` for a stub that throws, so wrappers of a replaced document
+/// fail loudly instead of reaching into the new one.
+const DOM_PRELUDE: &str = r#"
+(function (native) {
+  const toKebab = (name) =>
+    name.startsWith('--')
+      ? name
+      : name.replace(/[A-Z]/g, (c) => '-' + c.toLowerCase()).replace(/^(webkit|moz|ms)-/, '-$1-');
+  const text = (value) => (value === null || value === undefined ? '' : String(value));
+
+  function styleFor(id) {
+    const methods = {
+      getPropertyValue: (name) => native.getStyleProperty(id, String(name)),
+      getPropertyPriority: (name) => native.getStylePriority(id, String(name)),
+      setProperty: (name, value, priority) =>
+        native.setStyleProperty(id, String(name), text(value), text(priority)),
+      removeProperty: (name) => native.removeStyleProperty(id, String(name)),
+    };
+    return new Proxy(methods, {
+      get(target, prop) {
+        if (prop === 'cssText') return native.getCssText(id);
+        if (typeof prop !== 'string' || prop in target) return target[prop];
+        return native.getStyleProperty(id, toKebab(prop));
+      },
+      set(target, prop, value) {
+        if (typeof prop !== 'string') return false;
+        if (prop === 'cssText') native.setCssText(id, text(value));
+        else native.setStyleProperty(id, toKebab(prop), text(value), '');
+        return true;
+      },
+    });
+  }
+
+  // Event listeners belong to the document, so every bind starts from an
+  // empty set. Window listeners are keyed by type, element ones by `id type`
+  // and the document's by `#document type`.
+  const listeners = new Map();
+  const listen = (key, listener) => {
+    if (typeof listener !== 'function') return;
+    const list = listeners.get(key) || [];
+    if (!list.includes(listener)) list.push(listener);
+    listeners.set(key, list);
+  };
+  const unlisten = (key, listener) => {
+    const list = listeners.get(key);
+    if (list) listeners.set(key, list.filter((l) => l !== listener));
+  };
+  const invoke = (key, thisArg, event) => {
+    for (const listener of (listeners.get(key) || []).slice()) {
+      try {
+        listener.call(thisArg, event);
+      } catch (e) {
+        // A throwing listener must not keep the others from running.
+      }
+    }
+  };
+  const fireAt = (id, event) => {
+    const target = wrap(id);
+    event.target = target;
+    invoke(id + ' ' + event.type, target, event);
+    if (event.bubbles) invoke(event.type, globalThis, event);
+    return true;
+  };
+
+  class Element {
+    constructor(id) {
+      Object.defineProperty(this, '__nodeId', { value: id });
+    }
+    get style() {
+      return styleFor(this.__nodeId);
+    }
+    getAttribute(name) {
+      return native.getAttribute(this.__nodeId, String(name));
+    }
+    setAttribute(name, value) {
+      native.setAttribute(this.__nodeId, String(name), String(value));
+    }
+    removeAttribute(name) {
+      native.removeAttribute(this.__nodeId, String(name));
+    }
+    get lang() {
+      const lang = this.getAttribute('lang');
+      return lang === null ? '' : lang;
+    }
+    set lang(value) {
+      this.setAttribute('lang', value);
+    }
+    addEventListener(type, listener) {
+      listen(this.__nodeId + ' ' + String(type), listener);
+    }
+    removeEventListener(type, listener) {
+      unlisten(this.__nodeId + ' ' + String(type), listener);
+    }
+    querySelector(selector) {
+      return wrap(native.querySelectorWithin(this.__nodeId, String(selector)));
+    }
+    querySelectorAll(selector) {
+      return JSON.parse(native.querySelectorAllWithin(this.__nodeId, String(selector))).map(wrap);
+    }
+    dispatchEvent(event) {
+      return fireAt(this.__nodeId, event);
+    }
+    get parentNode() {
+      return parentOf(this.__nodeId);
+    }
+    get outerHTML() {
+      return native.serialize(this.__nodeId, 'outer');
+    }
+    get innerHTML() {
+      return native.serialize(this.__nodeId, 'inner');
+    }
+    get textContent() {
+      return native.textContent(this.__nodeId);
+    }
+    set textContent(value) {
+      native.setTextContent(this.__nodeId, text(value));
+    }
+    appendChild(child) {
+      native.appendChild(this.__nodeId, nodeIdOf(child));
+      return child;
+    }
+    // Strings join the text already last rather than adding a node each.
+    append(...nodes) {
+      for (const node of nodes) {
+        if (node instanceof Element) native.appendChild(this.__nodeId, node.__nodeId);
+        else native.appendText(this.__nodeId, String(node));
+      }
+    }
+    removeChild(child) {
+      native.removeChild(this.__nodeId, nodeIdOf(child));
+      return child;
+    }
+    normalize() {
+      native.normalize(this.__nodeId);
+    }
+    remove() {
+      const parent = native.parentNode(this.__nodeId);
+      if (parent !== null) native.removeChild(parent, this.__nodeId);
+    }
+  }
+
+  const nodeIdOf = (node) => {
+    if (!(node instanceof Element)) throw new TypeError('Argument is not an Element');
+    return node.__nodeId;
+  };
+
+  const wrappers = new Map();
+  const wrap = (id) => {
+    if (id === null) return null;
+    const cached = wrappers.get(id);
+    const existing = cached && cached.deref();
+    if (existing) return existing;
+    const element = new Element(id);
+    native.trackWrapper(id, element);
+    wrappers.set(id, new WeakRef(element));
+    return element;
+  };
+  const parentOf = (id) => {
+    const parent = native.parentNode(id);
+    return parent !== null && parent === native.rootNode() ? globalThis.document : wrap(parent);
+  };
+  const rootId = () => {
+    const root = native.rootNode();
+    if (root === null) throw new Error('The document has no root node');
+    return root;
+  };
+
+  // MutationObserver. Records are queued natively and handed over by a
+  // microtask, so callbacks interleave with promise jobs in queue order.
+  const observers = [];
+  const observerState = new WeakMap();
+  const interested = (options, targetId, raw) => {
+    if (targetId !== raw.target && !(options.subtree && native.contains(targetId, raw.target))) {
+      return false;
+    }
+    if (raw.type === 'attributes') {
+      return options.attributes && (!options.attributeFilter || options.attributeFilter.includes(raw.attributeName));
+    }
+    return raw.type === 'childList' ? options.childList : options.characterData;
+  };
+  const toRecord = (raw, options) => ({
+    type: raw.type,
+    target: wrap(raw.target),
+    addedNodes: raw.addedNodes.map(wrap),
+    removedNodes: raw.removedNodes.map(wrap),
+    previousSibling: wrap(raw.previousSibling),
+    nextSibling: wrap(raw.nextSibling),
+    attributeName: raw.attributeName,
+    attributeNamespace: raw.attributeNamespace,
+    oldValue:
+      (raw.type === 'attributes' && options.attributeOldValue) ||
+      (raw.type === 'characterData' && options.characterDataOldValue)
+        ? raw.oldValue
+        : null,
+  });
+
+  class MutationObserver {
+    constructor(callback) {
+      if (typeof callback !== 'function') throw new TypeError('MutationObserver requires a function');
+      observerState.set(this, { callback, targets: new Map(), records: [] });
+    }
+    observe(target, options) {
+      options = Object.assign({}, options);
+      if (options.attributeOldValue || options.attributeFilter) options.attributes = options.attributes !== false;
+      if (options.characterDataOldValue) options.characterData = options.characterData !== false;
+      if (!options.childList && !options.attributes && !options.characterData) {
+        throw new TypeError('observe() needs childList, attributes or characterData');
+      }
+      if (options.attributeFilter) options.attributeFilter = Array.from(options.attributeFilter, String);
+      observerState.get(this).targets.set(target.__nodeId, options);
+      if (!observers.includes(this)) observers.push(this);
+    }
+    disconnect() {
+      const state = observerState.get(this);
+      state.targets.clear();
+      state.records = [];
+      const index = observers.indexOf(this);
+      if (index >= 0) observers.splice(index, 1);
+    }
+    takeRecords() {
+      const state = observerState.get(this);
+      const records = state.records;
+      state.records = [];
+      return records;
+    }
+  }
+  Object.defineProperty(globalThis, '__vbeDeliverMutations', {
+    value: () => {
+      for (const raw of JSON.parse(native.takeMutationRecords())) {
+        for (const observer of observers) {
+          const state = observerState.get(observer);
+          for (const [targetId, options] of state.targets) {
+            if (interested(options, targetId, raw)) {
+              state.records.push(toRecord(raw, options));
+              break;
+            }
+          }
+        }
+      }
+      for (const observer of observers.slice()) {
+        const records = observer.takeRecords();
+        if (records.length === 0) continue;
+        try {
+          observerState.get(observer).callback.call(observer, records, observer);
+        } catch (e) {
+          // Same as listeners: one failing observer must not starve the rest.
+        }
+      }
+    },
+    configurable: true,
+    writable: true,
+  });
+
+  class XMLSerializer {
+    serializeToString(node) {
+      const id = node === globalThis.document ? rootId() : nodeIdOf(node);
+      return native.serialize(id, 'xml');
+    }
+  }
+
+  // Read-only: navigating by assigning to it is not supported.
+  const location = {
+    toString() {
+      return this.href;
+    },
+  };
+  for (const part of ['href', 'origin', 'protocol', 'host', 'hostname', 'port', 'pathname', 'search', 'hash']) {
+    Object.defineProperty(location, part, {
+      get: () => JSON.parse(native.location())[part],
+      enumerable: true,
+    });
+  }
+
+  globalThis.Element = Element;
+  globalThis.MutationObserver = MutationObserver;
+  globalThis.XMLSerializer = XMLSerializer;
+  globalThis.location = location;
+  // Set by the engine through `__vbePageState`; a prerendered page is
+  // hidden until it is activated.
+  let visibilityState = 'visible';
+  let prerendering = false;
+  globalThis.document = {
+    location,
+    get visibilityState() {
+      return visibilityState;
+    },
+    get hidden() {
+      return visibilityState === 'hidden';
+    },
+    get prerendering() {
+      return prerendering;
+    },
+    addEventListener: (type, listener) => listen('#document ' + String(type), listener),
+    removeEventListener: (type, listener) => unlisten('#document ' + String(type), listener),
+    dispatchEvent: (event) => {
+      event.target = globalThis.document;
+      invoke('#document ' + event.type, globalThis.document, event);
+      if (event.bubbles) invoke(event.type, globalThis, event);
+      return true;
+    },
+    get URL() {
+      return location.href;
+    },
+    get documentElement() {
+      return wrap(native.documentElement());
+    },
+    getElementById: (id) => wrap(native.getElementById(String(id))),
+    getElementsByClassName: (names) =>
+      JSON.parse(native.getElementsByClassName(String(names))).map(wrap),
+    querySelector: (selector) => wrap(native.querySelector(String(selector))),
+    querySelectorAll: (selector) =>
+      JSON.parse(native.querySelectorAll(String(selector))).map(wrap),
+    createElement: (tag) => wrap(native.createElement(String(tag))),
+    appendChild: (child) => {
+      native.appendChild(rootId(), nodeIdOf(child));
+      return child;
+    },
+    removeChild: (child) => {
+      native.removeChild(rootId(), nodeIdOf(child));
+      return child;
+    },
+  };
+
+  Object.defineProperty(globalThis, '__vbeDomSweep', {
+    value: (reclaimed) => {
+      for (const [id, ref] of wrappers) {
+        if (!ref.deref()) wrappers.delete(id);
+      }
+      if (reclaimed.length === 0) return;
+      const gone = new Set(reclaimed);
+      for (const key of listeners.keys()) {
+        if (gone.has(key.split(' ')[0])) listeners.delete(key);
+      }
+    },
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeDomTeardown', {
+    value: () => {
+      native = new Proxy({}, {
+        get() {
+          throw new Error('node belongs to a destroyed document');
+        },
+      });
+      wrappers.clear();
+      listeners.clear();
+      observers.length = 0;
+    },
+    configurable: true,
+    writable: true,
+  });
+
+  // Window events: lifecycle (`pagehide`, ...) and whatever bubbles up.
+  globalThis.window = globalThis;
+  globalThis.addEventListener = (type, listener) => listen(String(type), listener);
+  globalThis.removeEventListener = (type, listener) => unlisten(String(type), listener);
+  globalThis.dispatchEvent = (event) => {
+    invoke(event.type, globalThis, event);
+    return true;
+  };
+  Object.defineProperty(globalThis, '__vbeFireEvent', {
+    value: (id, init) => fireAt(id, Object.assign({ bubbles: true }, init)),
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbePageState', {
+    value: (visibility, nextPrerendering) => {
+      const visibilityChanged = visibility !== visibilityState;
+      const activated = prerendering && !nextPrerendering;
+      visibilityState = visibility;
+      prerendering = nextPrerendering;
+      const fire = (type, bubbles) => globalThis.document.dispatchEvent({ type, bubbles });
+      if (visibilityChanged) fire('visibilitychange', true);
+      if (activated) fire('prerenderingchange', false);
+    },
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeWrapNode', {
+    value: (id) => wrap(String(id)),
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeFireCancelableEvent', {
+    value: (id, init) => {
+      const event = Object.assign({ bubbles: true, defaultPrevented: false }, init);
+      event.preventDefault = () => {
+        if (event.cancelable) event.defaultPrevented = true;
+      };
+      fireAt(id, event);
+      return !event.defaultPrevented;
+    },
+    configurable: true,
+    writable: true,
+  });
+})(globalThis.__vbeDom);
+delete globalThis.__vbeDom;
+"#;
+
+/// JS half of `document.elementFromPoint()` and `elementsFromPoint()`.
+/// Coordinates are viewport CSS pixels; non-finite ones throw, as WebIDL
+/// `double` arguments do.
+const HIT_TEST_PRELUDE: &str = r#"
+(function (native) {
+  const stack = (method, x, y) => {
+    x = Number(x);
+    y = Number(y);
+    if (!Number.isFinite(x) || !Number.isFinite(y)) {
+      throw new TypeError(method + ': coordinates must be finite numbers');
+    }
+    return JSON.parse(native.elementsFromPoint(x, y)).map(globalThis.__vbeWrapNode);
+  };
+  globalThis.document.elementFromPoint = (x, y) => stack('elementFromPoint', x, y)[0] || null;
+  globalThis.document.elementsFromPoint = (x, y) => stack('elementsFromPoint', x, y);
+})(globalThis.__vbeHitTest);
+delete globalThis.__vbeHitTest;
+"#;
+
+/// JS half of window scrolling. `scrollX` and `scrollY` (and their
+/// `pageXOffset`/`pageYOffset` aliases) read the engine's position;
+/// `scrollTo()`, `scroll()` and `scrollBy()` move it at once, and the page
+/// is drawn there on the next frame. `behavior: 'smooth'` scrolls
+/// instantly.
+const SCROLL_PRELUDE: &str = r#"
+(function (native) {
+  for (const [name, axis] of [['scrollX', 0], ['scrollY', 1], ['pageXOffset', 0], ['pageYOffset', 1]]) {
+    Object.defineProperty(globalThis, name, { get: () => native.position()[axis], configurable: true });
+  }
+  // (x, y), or a ScrollToOptions dictionary whose missing members leave
+  // their axis alone.
+  const options = (method, args) => {
+    if (args.length >= 2) return { left: Number(args[0]), top: Number(args[1]) };
+    const dict = args[0];
+    if (dict === undefined || dict === null) return {};
+    if (typeof dict !== 'object') {
+      throw new TypeError(method + ": parameter 1 is not of type 'ScrollToOptions'");
+    }
+    return {
+      left: dict.left === undefined ? undefined : Number(dict.left),
+      top: dict.top === undefined ? undefined : Number(dict.top),
+    };
+  };
+  const finite = (value) => (Number.isFinite(value) ? value : 0);
+  globalThis.scrollTo = globalThis.scroll = (...args) => {
+    const { left, top } = options('scrollTo', args);
+    const [x, y] = native.position();
+    native.scrollTo(left === undefined ? x : finite(left), top === undefined ? y : finite(top));
+  };
+  globalThis.scrollBy = (...args) => {
+    const { left, top } = options('scrollBy', args);
+    const [x, y] = native.position();
+    native.scrollTo(x + finite(left ?? 0), y + finite(top ?? 0));
+  };
+})(globalThis.__vbeScroll);
+delete globalThis.__vbeScroll;
+"#;
+
+/// JS half of `window.print()`: `beforeprint` fires at once and the request
+/// waits on the embedder, which fires `afterprint` when it settles. Calls
+/// while a request is pending are ignored.
+const PRINT_PRELUDE: &str = r#"
+(function (native) {
+  globalThis.print = () => {
+    if (native.pending()) return;
+    globalThis.dispatchEvent({ type: 'beforeprint' });
+    native.request();
+  };
+})(globalThis.__vbePrint);
+delete globalThis.__vbePrint;
+"#;
+
+/// JS half of `document.write()` and `writeln()`, which join their
+/// arguments and hand them to `__vbeDocumentWrite`. A write from a script
+/// without an insertion point is dropped with a console warning.
+const DOCUMENT_WRITE_PRELUDE: &str = r#"
+(function (native) {
+  const write = (...text) => {
+    if (!native.write(text.join(''))) {
+      globalThis.console?.warn?.(
+        'A call to document.write() from an asynchronously-loaded external script was ignored.'
+      );
+    }
+  };
+  Object.assign(globalThis.document, {
+    write,
+    writeln: (...text) => write(...text, '\n'),
+  });
+})(globalThis.__vbeDocumentWrite);
+delete globalThis.__vbeDocumentWrite;
+"#;
+
+/// JS half of install prompts. `__vbeBeforeInstallPrompt` fires
+/// `beforeinstallprompt`, whose `prompt()` asks the engine to install once;
+/// the engine reports how that went through `__vbeInstallSettled`, which
+/// settles `userChoice` and fires `appinstalled` on success. Canceling the
+/// event only lets the page hold on to it: the engine shows no install UI
+/// of its own either way.
+const INSTALL_PRELUDE: &str = r#"
+(function (native) {
+  let settle = null;
+  const invalidState = (message) => {
+    if (typeof DOMException === 'function') return new DOMException(message, 'InvalidStateError');
+    const error = new Error(message);
+    error.name = 'InvalidStateError';
+    return error;
+  };
+
+  Object.defineProperty(globalThis, '__vbeBeforeInstallPrompt', {
+    value: (platforms) => {
+      let prompted = false;
+      const event = { type: 'beforeinstallprompt', platforms, cancelable: true, defaultPrevented: false };
+      event.preventDefault = () => {
+        event.defaultPrevented = true;
+      };
+      event.userChoice = new Promise((resolve) => {
+        settle = resolve;
+      });
+      event.prompt = () => {
+        if (prompted || !native.prompt()) {
+          return Promise.reject(invalidState('The prompt() method can only be called once.'));
+        }
+        prompted = true;
+        return event.userChoice;
+      };
+      globalThis.dispatchEvent(event);
+    },
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeInstallSettled', {
+    value: (accepted) => {
+      if (settle !== null) {
+        settle({ outcome: accepted ? 'accepted' : 'dismissed', platform: accepted ? 'web' : '' });
+        settle = null;
+      }
+      if (accepted) globalThis.dispatchEvent({ type: 'appinstalled' });
+    },
+    configurable: true,
+    writable: true,
+  });
+})(globalThis.__vbeInstall);
+delete globalThis.__vbeInstall;
+"#;
+
+/// JS half of `performance.timing`, read afresh on every access: fields
+/// of what has not happened yet are 0 until it has.
+const NAVIGATION_TIMING_PRELUDE: &str = r#"
+(function (native) {
+  class PerformanceTiming {
+    toJSON() {
+      return Object.assign({}, this);
+    }
+  }
+  globalThis.performance = globalThis.performance || {};
+  Object.defineProperty(globalThis.performance, 'timing', {
+    get() {
+      return Object.freeze(Object.assign(new PerformanceTiming(), JSON.parse(native.timing())));
+    },
+    configurable: true,
+  });
+  globalThis.PerformanceTiming = PerformanceTiming;
+})(globalThis.__vbeNavigationTiming);
+delete globalThis.__vbeNavigationTiming;
+"#;
+
+/// JS half of `<video>` and `<audio>`: `HTMLMediaElement` state on every
+/// `Element`, read from `__vbeMedia` and `undefined` on other elements.
+/// There is no playback here: `play()` rejects with `NotAllowedError` when
+/// the autoplay policy wants a gesture, and with `NotSupportedError` unless
+/// the embedder's playback handler takes it. `muted` reads the tab's mute
+/// too; setting it, or `volume`, only changes the element's own.
+const MEDIA_PRELUDE: &str = r#"
+(function (native) {
+  const state = (element) => {
+    const raw = native.state(element.__nodeId);
+    return raw === null ? null : JSON.parse(raw);
+  };
+  const domError = (name, message) => {
+    if (typeof DOMException === 'function') return new DOMException(message, name);
+    const error = new Error(message);
+    error.name = name;
+    return error;
+  };
+  const refusals = {
+    NotAllowedError: 'play() can only be initiated by a user gesture.',
+    NotSupportedError: 'The element has no supported source to play.',
+  };
+
+  class MediaError {}
+  const errorCodes = {
+    MEDIA_ERR_ABORTED: 1,
+    MEDIA_ERR_NETWORK: 2,
+    MEDIA_ERR_DECODE: 3,
+    MEDIA_ERR_SRC_NOT_SUPPORTED: 4,
+  };
+  Object.assign(MediaError, errorCodes);
+  Object.assign(MediaError.prototype, errorCodes);
+  const mediaError = (info) =>
+    Object.assign(Object.create(MediaError.prototype), { code: info.code, message: info.message });
+
+  const readOnly = {
+    readyState: (s) => s.readyState,
+    networkState: (s) => s.networkState,
+    currentSrc: (s) => s.src,
+    duration: (s) => (s.duration === null ? NaN : s.duration),
+    videoWidth: (s) => s.videoWidth,
+    videoHeight: (s) => s.videoHeight,
+    paused: (s) => s.paused,
+    ended: () => false,
+    error: (s) => (s.error === null ? null : mediaError(s.error)),
+  };
+  for (const [name, read] of Object.entries(readOnly)) {
+    Object.defineProperty(Element.prototype, name, {
+      get() {
+        const s = state(this);
+        return s === null ? undefined : read(s);
+      },
+      configurable: true,
+    });
+  }
+  Object.defineProperty(Element.prototype, 'currentTime', {
+    get() {
+      const s = state(this);
+      return s === null ? undefined : s.currentTime;
+    },
+    set(value) {
+      if (state(this) !== null) native.setCurrentTime(this.__nodeId, Number(value));
+    },
+    configurable: true,
+  });
+  Object.defineProperty(Element.prototype, 'muted', {
+    get() {
+      const s = state(this);
+      return s === null ? undefined : s.muted;
+    },
+    set(value) {
+      if (state(this) !== null) native.setMuted(this.__nodeId, Boolean(value));
+    },
+    configurable: true,
+  });
+  Object.defineProperty(Element.prototype, 'volume', {
+    get() {
+      const s = state(this);
+      return s === null ? undefined : s.volume;
+    },
+    set(value) {
+      if (state(this) === null) return;
+      const volume = Number(value);
+      if (!(volume >= 0 && volume <= 1)) {
+        throw domError('IndexSizeError', 'The volume must be between 0 and 1.');
+      }
+      native.setVolume(this.__nodeId, volume);
+    },
+    configurable: true,
+  });
+
+  const methods = {
+    play() {
+      if (state(this) === null) throw new TypeError('play() needs a media element');
+      const refused = native.play(this.__nodeId);
+      return refused === null ? Promise.resolve() : Promise.reject(domError(refused, refusals[refused]));
+    },
+    pause() {
+      if (state(this) !== null) native.pause(this.__nodeId);
+    },
+    load() {
+      native.load(this.__nodeId);
+    },
+  };
+  for (const [name, method] of Object.entries(methods)) {
+    Object.defineProperty(Element.prototype, name, {
+      value: method,
+      configurable: true,
+      writable: true,
+    });
+  }
+
+  Object.assign(Element.prototype, {
+    HAVE_NOTHING: 0,
+    HAVE_METADATA: 1,
+    HAVE_CURRENT_DATA: 2,
+    HAVE_FUTURE_DATA: 3,
+    HAVE_ENOUGH_DATA: 4,
+    NETWORK_EMPTY: 0,
+    NETWORK_IDLE: 1,
+    NETWORK_LOADING: 2,
+    NETWORK_NO_SOURCE: 3,
+  });
+  globalThis.MediaError = MediaError;
+})(globalThis.__vbeMedia);
+delete globalThis.__vbeMedia;
+"#;
+
+/// JS half of `speechSynthesis` and `SpeechSynthesisUtterance`. The queue
+/// lives natively (`__vbeSpeech`), which hands out an id per `speak()`; the
+/// engine reports what happened to each through `__vbeSpeechEvents`, which
+/// fires the events at the utterance it was spoken for.
+const SPEECH_PRELUDE: &str = r#"
+(function (native) {
+  const listenersOf = new WeakMap();
+  const listeners = (target, type) => {
+    if (!listenersOf.has(target)) listenersOf.set(target, new Map());
+    const byType = listenersOf.get(target);
+    if (!byType.has(type)) byType.set(type, []);
+    return byType.get(type);
+  };
+  const fire = (target, event) => {
+    const handlers = listeners(target, event.type).slice();
+    if (typeof target['on' + event.type] === 'function') handlers.unshift(target['on' + event.type]);
+    for (const handler of handlers) {
+      try {
+        handler.call(target, event);
+      } catch (e) {
+        // A failing listener must not keep the others from hearing it.
+      }
+    }
+  };
+  const eventTarget = {
+    addEventListener(type, listener) {
+      const list = listeners(this, String(type));
+      if (!list.includes(listener)) list.push(listener);
+    },
+    removeEventListener(type, listener) {
+      const list = listeners(this, String(type));
+      const index = list.indexOf(listener);
+      if (index >= 0) list.splice(index, 1);
+    },
+    dispatchEvent(event) {
+      fire(this, event);
+      return true;
+    },
+  };
+
+  class SpeechSynthesisVoice {}
+  class SpeechSynthesisUtterance {
+    constructor(text) {
+      this.text = text === undefined ? '' : String(text);
+      this.lang = '';
+      this.voice = null;
+      this.volume = 1;
+      this.rate = 1;
+      this.pitch = 1;
+      for (const type of ['start', 'end', 'error', 'pause', 'resume', 'mark', 'boundary']) {
+        this['on' + type] = null;
+      }
+    }
+  }
+  Object.assign(SpeechSynthesisUtterance.prototype, eventTarget);
+
+  // Utterances by the id `speak()` got for them, until their end or error.
+  const spoken = new Map();
+  const status = () => JSON.parse(native.status());
+  const speechSynthesis = {
+    get pending() { return status().pending; },
+    get speaking() { return status().speaking; },
+    get paused() { return status().paused; },
+    onvoiceschanged: null,
+    speak(utterance) {
+      if (!(utterance instanceof SpeechSynthesisUtterance)) {
+        throw new TypeError('speak() needs a SpeechSynthesisUtterance');
+      }
+      const voice = utterance.voice ? String(utterance.voice.voiceURI) : null;
+      const id = native.speak(
+        String(utterance.text),
+        String(utterance.lang),
+        voice,
+        Number(utterance.rate),
+        Number(utterance.pitch),
+        Number(utterance.volume),
+      );
+      spoken.set(id, utterance);
+    },
+    cancel() { native.cancel(); },
+    pause() { native.pause(); },
+    resume() { native.resume(); },
+    getVoices() {
+      return JSON.parse(native.voices()).map((voice) =>
+        Object.freeze(Object.assign(Object.create(SpeechSynthesisVoice.prototype), voice)));
+    },
+  };
+  Object.assign(speechSynthesis, eventTarget);
+
+  Object.defineProperty(globalThis, '__vbeSpeechEvents', {
+    value(events) {
+      for (const info of events) {
+        const utterance = spoken.get(info.id);
+        if (utterance === undefined) continue;
+        if (info.type === 'end' || info.type === 'error') spoken.delete(info.id);
+        const event = {
+          type: info.type,
+          target: utterance,
+          utterance,
+          charIndex: info.charIndex,
+          charLength: info.charLength,
+          elapsedTime: info.elapsedTime,
+          name: info.name,
+        };
+        if (info.type === 'error') event.error = info.error;
+        fire(utterance, event);
+      }
+    },
+    configurable: true,
+    writable: true,
+  });
+
+  globalThis.SpeechSynthesisUtterance = SpeechSynthesisUtterance;
+  globalThis.SpeechSynthesisVoice = SpeechSynthesisVoice;
+  globalThis.speechSynthesis = speechSynthesis;
+})(globalThis.__vbeSpeech);
+delete globalThis.__vbeSpeech;
+"#;
+
+/// JS half of constraint validation: `validity`, `checkValidity()` and
+/// friends on every `Element`, read from `__vbeForms` and `undefined` on
+/// elements that are no form controls. Forms get `requestSubmit()`, which
+/// fires `invalid` at each failing control and reports the first one no
+/// listener canceled, unless `novalidate` or the submitter's
+/// `formnovalidate` is set. A submission that passes ends at the `submit`
+/// event; nothing is navigated to yet.
+const FORM_PRELUDE: &str = r#"
+(function (native) {
+  const state = (element) => {
+    const raw = native.state(element.__nodeId);
+    return raw === null ? null : JSON.parse(raw);
+  };
+  // Fire at `id`; true unless a listener canceled it.
+  const fire = (id, type, bubbles) => {
+    let canceled = false;
+    globalThis.__vbeFireEvent(id, {
+      type,
+      bubbles,
+      cancelable: true,
+      preventDefault() {
+        canceled = true;
+      },
+    });
+    return !canceled;
+  };
+  // `null` for anything but a form.
+  const invalidControls = (element) => {
+    const raw = native.invalidControls(element.__nodeId);
+    return raw === null ? null : JSON.parse(raw);
+  };
+  const checkControl = (element, report) => {
+    const s = state(element);
+    if (s === null) throw new TypeError('The element is not a form control');
+    if (!s.willValidate || s.validity.valid) return true;
+    if (fire(element.__nodeId, 'invalid', false) && report) native.report(element.__nodeId);
+    return false;
+  };
+  const checkForm = (invalid, report) => {
+    const unhandled = invalid.filter((id) => fire(id, 'invalid', false));
+    if (report && unhandled.length > 0) native.report(unhandled[0]);
+    return invalid.length === 0;
+  };
+
+  class ValidityState {}
+  const validityState = (flags) =>
+    Object.freeze(Object.assign(Object.create(ValidityState.prototype), flags));
+
+  const readOnly = {
+    validity: (s) => validityState(s.validity),
+    willValidate: (s) => s.willValidate,
+    validationMessage: (s) => s.validationMessage,
+  };
+  for (const [name, read] of Object.entries(readOnly)) {
+    Object.defineProperty(Element.prototype, name, {
+      get() {
+        const s = state(this);
+        return s === null ? undefined : read(s);
+      },
+      configurable: true,
+    });
+  }
+  Object.defineProperty(Element.prototype, 'value', {
+    get() {
+      const s = state(this);
+      return s === null ? undefined : s.value;
+    },
+    set(value) {
+      const s = state(this);
+      if (s !== null && s.editable) this.setAttribute('value', value === null ? '' : String(value));
+    },
+    configurable: true,
+  });
+
+  const methods = {
+    checkValidity() {
+      const invalid = invalidControls(this);
+      return invalid === null ? checkControl(this, false) : checkForm(invalid, false);
+    },
+    reportValidity() {
+      const invalid = invalidControls(this);
+      return invalid === null ? checkControl(this, true) : checkForm(invalid, true);
+    },
+    setCustomValidity(message) {
+      if (state(this) === null) throw new TypeError('The element is not a form control');
+      native.setCustomValidity(this.__nodeId, String(message));
+    },
+    requestSubmit(submitter) {
+      const invalid = invalidControls(this);
+      if (invalid === null) throw new TypeError('requestSubmit() needs a form element');
+      const noValidate =
+        this.getAttribute('novalidate') !== null ||
+        (submitter != null && submitter.getAttribute('formnovalidate') !== null);
+      if (!noValidate && !checkForm(invalid, true)) return;
+      fire(this.__nodeId, 'submit', true);
+    },
+  };
+  for (const [name, method] of Object.entries(methods)) {
+    Object.defineProperty(Element.prototype, name, {
+      value: method,
+      configurable: true,
+      writable: true,
+    });
+  }
+  globalThis.ValidityState = ValidityState;
+})(globalThis.__vbeForms);
+delete globalThis.__vbeForms;
+"#;
+
+/// JS half of `document.execCommand()` and the `queryCommand*` methods over
+/// `__vbeEditing`, which runs commands on the focused `contenteditable`
+/// element. A command that changes the text fires a cancelable
+/// `beforeinput` first and `input` after; `copy` and `cut` fire their
+/// clipboard event and `paste` fires `paste` with what is on the clipboard,
+/// and a listener canceling those has handled the clipboard itself.
+/// Unsupported commands return `false` rather than throwing. `focus()` and
+/// `blur()` move the engine's keyboard focus, which only editable elements
+/// take; `document.activeElement` is `<body>` without it.
+const EDITING_PRELUDE: &str = r#"
+(function (native) {
+  const fire = (id, init) => globalThis.__vbeFireCancelableEvent(id, init);
+  const execCommand = (command, showUI, value) => {
+    command = String(command);
+    if (!native.enabled(command)) return false;
+    const target = native.host();
+    const name = command.toLowerCase();
+    if (name === 'copy' || name === 'cut') {
+      if (!fire(target, { type: name, cancelable: true })) return true;
+    } else if (name === 'paste' && typeof globalThis.__vbeFirePasteEvent === 'function') {
+      if (!globalThis.__vbeFirePasteEvent(target, JSON.parse(native.clipboardEntries()))) return true;
+    }
+    const inputType = native.inputType(command);
+    const data = inputType === 'insertText' ? (value === undefined ? '' : String(value)) : null;
+    if (inputType !== null) {
+      const init = { type: 'beforeinput', inputType, data, isComposing: false, cancelable: true };
+      if (!fire(target, init)) return false;
+    }
+    if (!native.execute(command, data === null ? '' : data)) return false;
+    if (inputType !== null) {
+      globalThis.__vbeFireEvent(target, { type: 'input', inputType, data, isComposing: false });
+    }
+    return true;
+  };
+  Object.assign(globalThis.document, {
+    execCommand,
+    queryCommandEnabled: (command) => native.enabled(String(command)),
+    queryCommandState: (command) => native.state(String(command)),
+    queryCommandSupported: (command) => native.supported(String(command)),
+  });
+  Object.assign(globalThis.Element.prototype, {
+    focus() {
+      native.focus(this.__nodeId);
+    },
+    blur() {
+      native.blur(this.__nodeId);
+    },
+  });
+  Object.defineProperty(globalThis.document, 'activeElement', {
+    get: () => {
+      const focused = native.focused();
+      return focused === null
+        ? globalThis.document.querySelector('body')
+        : globalThis.__vbeWrapNode(focused);
+    },
+    configurable: true,
+  });
+})(globalThis.__vbeEditing);
+delete globalThis.__vbeEditing;
+"#;
+
+/// JS half of drag-and-drop: `DataTransfer` over `__vbeDrag`, which works on
+/// the engine's drag in progress, and `File` for dropped files, read through
+/// their grant. The engine fires drag events with
+/// `__vbeFireDragEvent(id, init)`, which is false when a listener canceled
+/// the event.
+const DRAG_PRELUDE: &str = r#"
+(function (native) {
+  const notReadable = (cause) => {
+    const message = 'The file could not be read: ' + (cause && cause.message ? cause.message : cause);
+    if (typeof DOMException === 'function') return new DOMException(message, 'NotReadableError');
+    const error = new Error(message);
+    error.name = 'NotReadableError';
+    return error;
+  };
+
+  const grants = new WeakMap();
+  class File {
+    get name() { return grants.get(this).name; }
+    get size() { return grants.get(this).size; }
+    get type() { return grants.get(this).type; }
+    get lastModified() { return grants.get(this).lastModified; }
+    arrayBuffer() {
+      try {
+        const data = native.readFile(grants.get(this).grant, false);
+        const bytes = new Uint8Array(data.length);
+        for (let i = 0; i < data.length; i++) bytes[i] = data.charCodeAt(i);
+        return Promise.resolve(bytes.buffer);
+      } catch (e) {
+        return Promise.reject(notReadable(e));
+      }
+    }
+    text() {
+      try {
+        return Promise.resolve(native.readFile(grants.get(this).grant, true));
+      } catch (e) {
+        return Promise.reject(notReadable(e));
+      }
+    }
+  }
+  // One `File` per grant, so every `files` read hands out the same objects.
+  const files = new Map();
+  const fileFor = (info) => {
+    if (!files.has(info.grant)) {
+      const file = Object.create(File.prototype);
+      grants.set(file, info);
+      files.set(info.grant, file);
+    }
+    return files.get(info.grant);
+  };
+
+  class DataTransfer {
+    getData(format) { return native.getData(String(format)); }
+    setData(format, data) { native.setData(String(format), String(data)); }
+    clearData(format) { native.clearData(format === undefined ? null : String(format)); }
+    get types() { return Object.freeze(JSON.parse(native.types())); }
+    get files() {
+      const list = JSON.parse(native.files()).map(fileFor);
+      list.item = (index) => list[index] || null;
+      return list;
+    }
+    get effectAllowed() { return native.effectAllowed(); }
+    set effectAllowed(value) { native.setEffectAllowed(String(value)); }
+    get dropEffect() { return native.dropEffect(); }
+    set dropEffect(value) { native.setDropEffect(String(value)); }
+    setDragImage(element, x, y) {
+      native.setDragImage(element.__nodeId, Number(x) || 0, Number(y) || 0);
+    }
+  }
+  const dataTransfer = new DataTransfer();
+
+  Object.defineProperty(globalThis, '__vbeFireDragEvent', {
+    value: (id, init) =>
+      globalThis.__vbeFireCancelableEvent(id, Object.assign({}, init, { dataTransfer })),
+    configurable: true,
+    writable: true,
+  });
+  globalThis.DataTransfer = DataTransfer;
+  globalThis.File = File;
+})(globalThis.__vbeDrag);
+delete globalThis.__vbeDrag;
+"#;
+
+/// JS half of the network bindings: `fetch` with `Headers` and `Response`,
+/// `navigator.sendBeacon` and the `keepalive` flag on `fetch`. Keepalive
+/// requests are queued through `__vbeNet.queueKeepalive`; the rest start
+/// through `__vbeNet.fetch` and settle when the engine calls
+/// `__vbeFetchSettle`. Bodies cross as byte strings (one char per byte),
+/// headers besides `Content-Type` as JSON `[name, value]` pairs.
+const NETWORK_PRELUDE: &str = r#"
+(function (native) {
+  const toBody = (data) => {
+    if (data === null || data === undefined) return { body: '', binary: false, type: '' };
+    if (typeof URLSearchParams === 'function' && data instanceof URLSearchParams) {
+      return {
+        body: data.toString(),
+        binary: false,
+        type: 'application/x-www-form-urlencoded;charset=UTF-8',
+      };
+    }
+    const blobBytes = typeof __vbeBlobBytes === 'function' ? __vbeBlobBytes(data) : undefined;
+    if (blobBytes !== undefined) return { body: blobBytes, binary: true, type: data.type };
+    let bytes = null;
+    if (data instanceof ArrayBuffer) bytes = new Uint8Array(data);
+    else if (ArrayBuffer.isView(data)) bytes = new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+    if (bytes !== null) {
+      let body = '';
+      for (let i = 0; i < bytes.length; i += 0x8000) {
+        body += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+      }
+      return { body, binary: true, type: '' };
+    }
+    return { body: String(data), binary: false, type: 'text/plain;charset=UTF-8' };
+  };
+  const toBuffer = (bytes) => {
+    const view = new Uint8Array(bytes.length);
+    for (let i = 0; i < bytes.length; i++) view[i] = bytes.charCodeAt(i);
+    return view.buffer;
+  };
+  const decodeUtf8 = (buffer) => {
+    const { body } = toBody(buffer);
+    try {
+      return decodeURIComponent(escape(body));
+    } catch (_) {
+      return body;
+    }
+  };
+
+  class Headers {
+    constructor(init) {
+      this.__map = new Map();
+      let entries;
+      if (init instanceof Headers) entries = init.__map.entries();
+      else if (init && typeof init[Symbol.iterator] === 'function') entries = init;
+      else entries = Object.entries(init || {});
+      for (const [name, value] of entries) this.append(name, value);
+    }
+    get(name) {
+      const value = this.__map.get(String(name).toLowerCase());
+      return value === undefined ? null : value;
+    }
+    has(name) {
+      return this.__map.has(String(name).toLowerCase());
+    }
+    set(name, value) {
+      this.__map.set(String(name).toLowerCase(), String(value));
+    }
+    append(name, value) {
+      const key = String(name).toLowerCase();
+      const old = this.__map.get(key);
+      this.__map.set(key, old === undefined ? String(value) : old + ', ' + String(value));
+    }
+    delete(name) {
+      this.__map.delete(String(name).toLowerCase());
+    }
+    forEach(callback, thisArg) {
+      for (const [name, value] of this.__map) callback.call(thisArg, value, name, this);
+    }
+    entries() {
+      return [...this.__map.entries()].sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0))[Symbol.iterator]();
+    }
+    keys() {
+      return [...this.entries()].map(([name]) => name)[Symbol.iterator]();
+    }
+    values() {
+      return [...this.entries()].map(([, value]) => value)[Symbol.iterator]();
+    }
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  const bodies = new WeakMap();
+  class Response {
+    constructor(body, init) {
+      init = init || {};
+      const { body: bytes, type } = toBody(body);
+      this.status = init.status === undefined ? 200 : init.status;
+      this.statusText = init.statusText || '';
+      this.ok = this.status >= 200 && this.status < 300;
+      this.headers = new Headers(init.headers);
+      if (type && !this.headers.has('content-type')) this.headers.set('content-type', type);
+      this.url = '';
+      this.redirected = false;
+      this.type = 'default';
+      bodies.set(this, toBuffer(bytes));
+    }
+    get bodyUsed() {
+      return !bodies.has(this);
+    }
+    arrayBuffer() {
+      const buffer = bodies.get(this);
+      if (buffer === undefined) return Promise.reject(new TypeError('Body has already been consumed.'));
+      bodies.delete(this);
+      return Promise.resolve(buffer);
+    }
+    text() {
+      return this.arrayBuffer().then(decodeUtf8);
+    }
+    json() {
+      return this.text().then(JSON.parse);
+    }
+  }
+
+  const pending = new Map();
+  Object.defineProperty(globalThis, '__vbeFetchSettle', {
+    value: (results) => {
+      for (const result of results) {
+        const request = pending.get(result.id);
+        if (!request) continue;
+        pending.delete(result.id);
+        if (result.error !== undefined) {
+          request.reject(new TypeError('Failed to fetch: ' + result.error));
+          continue;
+        }
+        const response = new Response(null, { status: result.status, headers: result.headers });
+        bodies.set(response, toBuffer(result.body));
+        response.url = result.url;
+        response.redirected = result.redirected;
+        response.type = 'basic';
+        request.resolve(response);
+      }
+    },
+    configurable: true,
+    writable: true,
+  });
+
+  globalThis.navigator = globalThis.navigator || {};
+  globalThis.navigator.sendBeacon = (url, data) => {
+    const { body, binary, type } = toBody(data);
+    return native.queueKeepalive('POST', String(url), body, binary, type);
+  };
+
+  globalThis.fetch = (input, init) => {
+    init = init || {};
+    const method = String(init.method || 'GET').toUpperCase();
+    const { body, binary, type } = toBody(init.body);
+    const headers = new Headers(init.headers);
+    const contentType = headers.get('content-type') || type;
+    headers.delete('content-type');
+    const others = JSON.stringify([...headers]);
+    if (init.keepalive) {
+      if (!native.queueKeepalive(method, String(input), body, binary, contentType, others)) {
+        return Promise.reject(new TypeError('keepalive request was refused'));
+      }
+      return Promise.resolve({ ok: true, status: 0, type: 'opaque' });
+    }
+    return new Promise((resolve, reject) => {
+      let id;
+      try {
+        id = native.fetch(method, String(input), body, binary, contentType, others);
+      } catch (e) {
+        reject(new TypeError('Failed to fetch: ' + String(e)));
+        return;
+      }
+      pending.set(id, { resolve, reject });
+    });
+  };
+  globalThis.Headers = Headers;
+  globalThis.Response = Response;
+})(globalThis.__vbeNet);
+delete globalThis.__vbeNet;
+"#;
+
+/// JS half of the clipboard bindings: `Blob`, `URL.createObjectURL` and
+/// `revokeObjectURL` over the engine's blob store, `ClipboardItem` and
+/// `navigator.clipboard` over `__vbeClipboard`. A `Blob` keeps its bytes as
+/// a byte string; `__vbeBlobBytes` hands them to `fetch` and `Response`.
+/// The engine fires `paste` with `__vbeFirePasteEvent(id, entries)`, where
+/// each entry is a `{ type, data }` representation of what is pasted, and
+/// learns whether a listener canceled it.
+const CLIPBOARD_PRELUDE: &str = r#"
+(function (native) {
+  const domException = (message, name) => {
+    if (typeof DOMException === 'function') return new DOMException(message, name);
+    const error = new Error(message);
+    error.name = name;
+    return error;
+  };
+  const encodeUtf8 = (text) => unescape(encodeURIComponent(text));
+  const decodeUtf8 = (bytes) => {
+    try {
+      return decodeURIComponent(escape(bytes));
+    } catch (_) {
+      return bytes;
+    }
+  };
+  const toBuffer = (bytes) => {
+    const view = new Uint8Array(bytes.length);
+    for (let i = 0; i < bytes.length; i++) view[i] = bytes.charCodeAt(i);
+    return view.buffer;
+  };
+
+  const contents = new WeakMap();
+  const partBytes = (part) => {
+    if (contents.has(part)) return contents.get(part);
+    let view = null;
+    if (part instanceof ArrayBuffer) view = new Uint8Array(part);
+    else if (ArrayBuffer.isView(part)) view = new Uint8Array(part.buffer, part.byteOffset, part.byteLength);
+    if (view === null) return encodeUtf8(String(part));
+    let bytes = '';
+    for (let i = 0; i < view.length; i += 0x8000) {
+      bytes += String.fromCharCode.apply(null, view.subarray(i, i + 0x8000));
+    }
+    return bytes;
+  };
+  class Blob {
+    constructor(parts, options) {
+      const type = options && options.type !== undefined ? String(options.type) : '';
+      contents.set(this, Array.from(parts || [], partBytes).join(''));
+      Object.defineProperty(this, 'type', {
+        value: /^[\x20-\x7e]*$/.test(type) ? type.toLowerCase() : '',
+      });
+    }
+    get size() { return contents.get(this).length; }
+    slice(start, end, type) {
+      const bytes = contents.get(this);
+      const blob = new Blob([], { type: type === undefined ? '' : type });
+      contents.set(blob, bytes.slice(start, end));
+      return blob;
+    }
+    arrayBuffer() { return Promise.resolve(toBuffer(contents.get(this))); }
+    text() { return Promise.resolve(decodeUtf8(contents.get(this))); }
+  }
+  const blobOf = (bytes, type) => {
+    const blob = new Blob([], { type });
+    contents.set(blob, bytes);
+    return blob;
+  };
+
+  const URL = globalThis.URL || {};
+  URL.createObjectURL = (blob) => {
+    if (!contents.has(blob)) throw new TypeError('createObjectURL takes a Blob');
+    return native.createObjectURL(contents.get(blob), blob.type);
+  };
+  URL.revokeObjectURL = (url) => native.revokeObjectURL(String(url));
+
+  if (typeof globalThis.Response === 'function') {
+    globalThis.Response.prototype.blob = function () {
+      return this.arrayBuffer().then((buffer) =>
+        blobOf(partBytes(buffer), this.headers.get('content-type') || ''));
+    };
+  }
+
+  const items = new WeakMap();
+  class ClipboardItem {
+    constructor(data) {
+      items.set(this, Object.assign({}, data));
+    }
+    get types() { return Object.freeze(Object.keys(items.get(this))); }
+    getType(type) {
+      const data = items.get(this);
+      if (!Object.prototype.hasOwnProperty.call(data, type)) {
+        return Promise.reject(domException('The type ' + type + ' was not found', 'NotFoundError'));
+      }
+      return Promise.resolve(data[type]).then((value) =>
+        contents.has(value) ? value : new Blob([value], { type }));
+    }
+  }
+
+  const notAllowed = (what) => domException(what + ' the clipboard is not allowed', 'NotAllowedError');
+  const clipboard = {
+    read() {
+      let read;
+      try {
+        read = native.read();
+      } catch (e) {
+        return Promise.reject(domException(String(e), 'DataError'));
+      }
+      if (read === null) return Promise.reject(notAllowed('Reading'));
+      return Promise.resolve(JSON.parse(read).map((representations) => {
+        const data = {};
+        for (const type of Object.keys(representations)) data[type] = blobOf(representations[type], type);
+        return new ClipboardItem(data);
+      }));
+    },
+    readText() {
+      return clipboard.read().then((read) => {
+        const item = read.find((candidate) => candidate.types.includes('text/plain'));
+        return item ? item.getType('text/plain').then((blob) => blob.text()) : '';
+      });
+    },
+    write(written) {
+      return Promise.all(Array.from(written || [], (item) =>
+        Promise.all(item.types.map((type) =>
+          item.getType(type).then((blob) => [type, contents.get(blob)])))))
+        .then((resolved) => {
+          const representations = resolved.map((pairs) => Object.fromEntries(pairs));
+          let allowed;
+          try {
+            allowed = native.write(JSON.stringify(representations));
+          } catch (e) {
+            throw domException(String(e), 'DataError');
+          }
+          if (!allowed) throw notAllowed('Writing');
+        });
+    },
+    writeText(text) {
+      return clipboard.write([new ClipboardItem({ 'text/plain': String(text) })]);
+    },
+  };
+
+  // What a paste carries: readable while its listeners run, never writable.
+  const clipboardDataOf = (entries) => {
+    const strings = entries.filter((entry) => entry.type.startsWith('text/'));
+    const files = entries
+      .filter((entry) => !entry.type.startsWith('text/'))
+      .map((entry) => {
+        const file = blobOf(entry.data, entry.type);
+        Object.defineProperty(file, 'name', { value: 'image.' + entry.type.split('/')[1] });
+        Object.defineProperty(file, 'lastModified', { value: Date.now() });
+        return file;
+      });
+    files.item = (index) => files[index] || null;
+    const items = strings
+      .map((entry) => ({
+        kind: 'string',
+        type: entry.type,
+        getAsString: (callback) => callback && setTimeout(() => callback(decodeUtf8(entry.data)), 0),
+        getAsFile: () => null,
+      }))
+      .concat(files.map((file) => ({
+        kind: 'file',
+        type: file.type,
+        getAsString: () => {},
+        getAsFile: () => file,
+      })));
+    const types = strings.map((entry) => entry.type).concat(files.length ? ['Files'] : []);
+    return {
+      types: Object.freeze(types),
+      files,
+      items,
+      getData(format) {
+        format = String(format).toLowerCase();
+        if (format === 'text') format = 'text/plain';
+        const entry = strings.find((candidate) => candidate.type === format);
+        return entry ? decodeUtf8(entry.data) : '';
+      },
+      setData() {},
+      clearData() {},
+    };
+  };
+
+  Object.defineProperty(globalThis, '__vbeBlobBytes', {
+    value: (blob) => contents.get(blob),
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeFirePasteEvent', {
+    value: (id, entries) =>
+      globalThis.__vbeFireCancelableEvent(id, {
+        type: 'paste',
+        cancelable: true,
+        clipboardData: clipboardDataOf(entries),
+      }),
+    configurable: true,
+    writable: true,
+  });
+  globalThis.Blob = Blob;
+  globalThis.URL = URL;
+  globalThis.ClipboardItem = ClipboardItem;
+  globalThis.navigator = globalThis.navigator || {};
+  globalThis.navigator.clipboard = clipboard;
+})(globalThis.__vbeClipboard);
+delete globalThis.__vbeClipboard;
+"#;
+
+/// JS half of Web Storage: `localStorage` and `sessionStorage` as proxies
+/// over `__vbeStorage`, so both `getItem`/`setItem` and property access reach
+/// the document's [`StorageArea`](crate::core::storage::StorageArea)s. Private
+/// mode is invisible here: the areas are merely memory-only.
+const STORAGE_PRELUDE: &str = r#"
+(function (native) {
+  const quotaError = () => {
+    const message = 'The quota has been exceeded.';
+    if (typeof DOMException === 'function') return new DOMException(message, 'QuotaExceededError');
+    const error = new Error(message);
+    error.name = 'QuotaExceededError';
+    return error;
+  };
+
+  const makeStorage = (session) => {
+    const api = {
+      getItem: (key) => native.getItem(session, String(key)),
+      setItem: (key, value) => {
+        if (!native.setItem(session, String(key), String(value))) throw quotaError();
+      },
+      removeItem: (key) => { native.removeItem(session, String(key)); },
+      clear: () => { native.clear(session); },
+      key: (index) => native.key(session, Number(index)),
+    };
+    const isName = (prop) => typeof prop === 'string' && !(prop in api) && prop !== 'length';
+    return new Proxy({}, {
+      get(target, prop) {
+        if (prop === 'length') return native.length(session);
+        if (typeof prop === 'string' && prop in api) return api[prop];
+        if (!isName(prop)) return undefined;
+        const value = native.getItem(session, prop);
+        return value === null ? undefined : value;
+      },
+      set(target, prop, value) {
+        if (!isName(prop)) return false;
+        api.setItem(prop, value);
+        return true;
+      },
+      deleteProperty(target, prop) {
+        if (isName(prop)) native.removeItem(session, prop);
+        return true;
+      },
+      has(target, prop) {
+        if (typeof prop !== 'string') return false;
+        return !isName(prop) || native.getItem(session, prop) !== null;
+      },
+      ownKeys() {
+        const keys = [];
+        for (let i = 0, n = native.length(session); i < n; i++) keys.push(native.key(session, i));
+        return keys;
+      },
+      getOwnPropertyDescriptor(target, prop) {
+        if (!isName(prop)) return undefined;
+        const value = native.getItem(session, prop);
+        if (value === null) return undefined;
+        return { value, writable: true, enumerable: true, configurable: true };
+      },
+    });
+  };
+
+  Object.defineProperty(globalThis, 'localStorage', { value: makeStorage(false), configurable: true });
+  Object.defineProperty(globalThis, 'sessionStorage', { value: makeStorage(true), configurable: true });
+  globalThis.navigator = globalThis.navigator || {};
+  globalThis.navigator.cookieEnabled = true;
+})(globalThis.__vbeStorage);
+delete globalThis.__vbeStorage;
+"#;
+
+/// JS half of the font bindings: `FontFace` and `document.fonts`. Face state
+/// lives natively (`__vbeFonts`); the engine reports finished loads through
+/// `__vbeFontSet.settle`, which settles `loaded` promises and, once nothing
+/// in the set is loading, fires `loadingdone` and resolves `ready`.
+const FONT_PRELUDE: &str = r#"
+(function (native) {
+  const ids = new WeakMap();
+  const faces = new Map();
+  const waiters = new Map();
+  const describe = (id) => JSON.parse(native.describe(id));
+  const idOf = (face) => {
+    if (!ids.has(face)) throw new TypeError('Not a FontFace');
+    return ids.get(face);
+  };
+
+  const waiter = (id) => {
+    if (!waiters.has(id)) {
+      const entry = {};
+      entry.promise = new Promise((resolve, reject) => {
+        entry.resolve = resolve;
+        entry.reject = reject;
+      });
+      // Rejections surface through `loaded`; an unobserved one is not an error.
+      entry.promise.catch(() => {});
+      waiters.set(id, entry);
+    }
+    return waiters.get(id);
+  };
+  const settleWaiter = (info) => {
+    if (info.status === 'loaded') waiter(info.id).resolve(faces.get(info.id));
+    if (info.status === 'error') {
+      const error = new Error(info.error || 'Font failed to load');
+      error.name = 'NetworkError';
+      waiter(info.id).reject(error);
+    }
+  };
+
+  const adopt = (face, info) => {
+    ids.set(face, info.id);
+    faces.set(info.id, face);
+    settleWaiter(info);
+    return face;
+  };
+  const faceFor = (id) => faces.get(id) || adopt(Object.create(FontFace.prototype), describe(id));
+
+  const toBytes = (source) => {
+    const bytes =
+      source instanceof ArrayBuffer
+        ? new Uint8Array(source)
+        : new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+    let data = '';
+    for (let i = 0; i < bytes.length; i += 0x8000) {
+      data += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+    }
+    return data;
+  };
+
+  class FontFace {
+    constructor(family, source, descriptors) {
+      const fields = {};
+      for (const name of ['style', 'weight', 'display']) {
+        if (descriptors && descriptors[name] !== undefined) fields[name] = String(descriptors[name]);
+      }
+      const binary = source instanceof ArrayBuffer || ArrayBuffer.isView(source);
+      const data = binary ? toBytes(source) : String(source);
+      adopt(this, JSON.parse(native.addFace(String(family), data, JSON.stringify(fields), binary)));
+    }
+    get family() { return describe(idOf(this)).family; }
+    get style() { return describe(idOf(this)).style; }
+    get weight() { return describe(idOf(this)).weight; }
+    get display() { return describe(idOf(this)).display; }
+    get status() { return describe(idOf(this)).status; }
+    get loaded() { return waiter(idOf(this)).promise; }
+    load() {
+      const id = idOf(this);
+      if (describe(id).status === 'unloaded') {
+        native.load(id);
+        if (fonts.has(this)) loadingStarted();
+      }
+      return this.loaded;
+    }
+  }
+
+  // One loading session runs from the first load until nothing is loading.
+  const listeners = new Map();
+  let session = null;
+  const newSession = () => {
+    const next = { loaded: [], failed: [], done: false };
+    next.ready = new Promise((resolve) => (next.resolve = resolve));
+    return next;
+  };
+  const dispatch = (type, fontfaces) => {
+    const event = { type, fontfaces, target: fonts };
+    const handlers = (listeners.get(type) || []).slice();
+    if (typeof fonts['on' + type] === 'function') handlers.unshift(fonts['on' + type]);
+    for (const handler of handlers) {
+      try {
+        handler.call(fonts, event);
+      } catch (e) {
+        // A failing listener must not keep `ready` from resolving.
+      }
+    }
+  };
+  const loadingStarted = () => {
+    if (!session.done) return;
+    session = newSession();
+    dispatch('loading', []);
+  };
+  const finishIfIdle = () => {
+    if (session.done || native.isLoading()) return;
+    const finished = session;
+    finished.done = true;
+    dispatch('loadingdone', finished.loaded);
+    if (finished.failed.length > 0) dispatch('loadingerror', finished.failed);
+    finished.resolve(fonts);
+  };
+
+  const familiesOf = (font) => {
+    const match = /(?:^|\s)[+-]?[\d.]+[a-z%]*(?:\/\S+)?\s+(.+)$/i.exec(String(font).trim());
+    if (!match) throw new SyntaxError('Invalid font: ' + font);
+    return match[1].split(',').map((family) => family.trim().replace(/^(['"])(.*)\1$/, '$2').toLowerCase());
+  };
+  const members = () => JSON.parse(native.members()).map(faceFor);
+  const matching = (font) => {
+    const families = familiesOf(font);
+    return members().filter((face) => families.includes(face.family.toLowerCase()));
+  };
+
+  const fonts = {
+    get status() { return native.isLoading() ? 'loading' : 'loaded'; },
+    get ready() { return session.ready; },
+    get size() { return members().length; },
+    add(face) {
+      native.setMember(idOf(face), true);
+      if (face.status === 'loading') loadingStarted();
+      return fonts;
+    },
+    delete(face) {
+      const had = fonts.has(face);
+      native.setMember(idOf(face), false);
+      finishIfIdle();
+      return had;
+    },
+    has(face) { return ids.has(face) && JSON.parse(native.members()).includes(ids.get(face)); },
+    clear() {
+      for (const face of members()) native.setMember(idOf(face), false);
+      finishIfIdle();
+    },
+    forEach(callback, thisArg) {
+      for (const face of members()) callback.call(thisArg, face, face, fonts);
+    },
+    values() { return members()[Symbol.iterator](); },
+    [Symbol.iterator]() { return fonts.values(); },
+    check(font) { return matching(font).every((face) => face.status === 'loaded'); },
+    load(font) {
+      const matches = matching(font);
+      return Promise.all(matches.map((face) => face.load()));
+    },
+    addEventListener(type, listener) {
+      if (!listeners.has(type)) listeners.set(type, []);
+      listeners.get(type).push(listener);
+    },
+    removeEventListener(type, listener) {
+      const list = listeners.get(type) || [];
+      const index = list.indexOf(listener);
+      if (index >= 0) list.splice(index, 1);
+    },
+  };
+
+  session = newSession();
+  if (!native.isLoading()) {
+    session.done = true;
+    session.resolve(fonts);
+  }
+
+  Object.defineProperty(globalThis, '__vbeFontSet', {
+    value: {
+      settle(events) {
+        for (const info of events) {
+          if (!faces.has(info.id)) faceFor(info.id);
+          else settleWaiter(info);
+          const face = faces.get(info.id);
+          if (!fonts.has(face)) continue;
+          (info.status === 'loaded' ? session.loaded : session.failed).push(face);
+        }
+        finishIfIdle();
+      },
+    },
+    configurable: true,
+    writable: true,
+  });
+
+  globalThis.FontFace = FontFace;
+  globalThis.document = globalThis.document || {};
+  Object.defineProperty(globalThis.document, 'fonts', { value: fonts, configurable: true });
+})(globalThis.__vbeFonts);
+delete globalThis.__vbeFonts;
+"#;
+
+/// JS half of the event loop. Timers and animation frame callbacks are kept
+/// here; the engine runs them through `__vbeEventLoop`, and every callback is
+/// its own task followed by a microtask checkpoint (`__vbeLoop.runMicrotasks`).
+const EVENT_LOOP_PRELUDE: &str = r#"
+(function (native) {
+  let nextHandle = 1;
+  let nextSeq = 0;
+  // handle -> { callback, args, interval, due, seq, script }; `interval` is null for
+  // timeouts, `script` the URL of the script that set the timer.
+  const timers = new Map();
+  // Callbacks for the next frame, and those of the frame being run, with the
+  // URL of the script that requested each.
+  let frameCallbacks = new Map();
+  let runningFrame = new Map();
+
+  const runTask = (callback, thisArg, args, script) => {
+    native.beginTask(script);
+    try {
+      if (typeof callback === 'function') callback.apply(thisArg, args);
+      else (0, eval)(String(callback));
+    } catch (e) {
+      // An exception ends the task, not the event loop.
+    }
+    native.runMicrotasks();
+    native.endTask();
+  };
+
+  const addTimer = (callback, delay, args, repeat) => {
+    const handle = nextHandle++;
+    delay = Math.max(0, Number(delay) || 0);
+    timers.set(handle, {
+      callback,
+      args,
+      interval: repeat ? delay : null,
+      due: native.now() + delay,
+      seq: nextSeq++,
+      script: native.scriptUrl(),
+    });
+    return handle;
+  };
+  const clearTimer = (handle) => {
+    timers.delete(Number(handle));
+  };
+
+  globalThis.setTimeout = (callback, delay, ...args) => addTimer(callback, delay, args, false);
+  globalThis.setInterval = (callback, delay, ...args) => addTimer(callback, delay, args, true);
+  globalThis.clearTimeout = clearTimer;
+  globalThis.clearInterval = clearTimer;
+
+  globalThis.requestAnimationFrame = (callback) => {
+    if (typeof callback !== 'function') {
+      throw new TypeError('requestAnimationFrame requires a function');
+    }
+    const handle = nextHandle++;
+    frameCallbacks.set(handle, { callback, script: native.scriptUrl() });
+    return handle;
+  };
+  globalThis.cancelAnimationFrame = (handle) => {
+    frameCallbacks.delete(Number(handle));
+    runningFrame.delete(Number(handle));
+  };
+
+  globalThis.queueMicrotask = (callback) => native.enqueueMicrotask(callback);
+
+  globalThis.performance = globalThis.performance || {};
+  globalThis.performance.now = () => native.now();
+
+  const loop = {
+    // Timers due now, oldest deadline first. Ones scheduled while these run
+    // wait for the next turn even with a zero delay.
+    runTimers() {
+      const now = native.now();
+      const due = [...timers]
+        .filter(([, timer]) => timer.due <= now)
+        .sort(([, a], [, b]) => a.due - b.due || a.seq - b.seq);
+      let ran = 0;
+      for (const [handle, timer] of due) {
+        if (timers.get(handle) !== timer) continue;
+        if (timer.interval === null) {
+          timers.delete(handle);
+        } else {
+          timer.due = now + timer.interval;
+          timer.seq = nextSeq++;
+        }
+        runTask(timer.callback, globalThis, timer.args, timer.script);
+        ran++;
+      }
+      return ran;
+    },
+    // One frame's batch. Callbacks requested during it run next frame.
+    runAnimationFrames() {
+      runningFrame = frameCallbacks;
+      frameCallbacks = new Map();
+      const timestamp = native.now();
+      let ran = 0;
+      for (const { callback, script } of runningFrame.values()) {
+        runTask(callback, globalThis, [timestamp], script);
+        ran++;
+      }
+      runningFrame = new Map();
+      return ran;
+    },
+    reset() {
+      timers.clear();
+      frameCallbacks = new Map();
+      runningFrame = new Map();
+    },
+  };
+  Object.defineProperty(globalThis, '__vbeEventLoop', {
+    value: loop,
+    configurable: true,
+    writable: true,
+  });
+})(globalThis.__vbeLoop);
+delete globalThis.__vbeLoop;
+"#;
+
+/// `Date` and `Math.random` under virtual time. `Date` reads the time from
+/// `__vbeTime.wallNow` and is otherwise the real one: instances, `parse`,
+/// `UTC` and subclasses all behave the same. `Math.random` is an sfc32
+/// generator seeded from the two 32-bit halves passed in.
+const VIRTUAL_TIME_PRELUDE: &str = r#"
+(function (native, seedLow, seedHigh) {
+  const SystemDate = Date;
+  const VirtualDate = function Date(...args) {
+    if (new.target === undefined) {
+      return new SystemDate(native.wallNow()).toString();
+    }
+    return Reflect.construct(SystemDate, args.length ? args : [native.wallNow()], new.target);
+  };
+  VirtualDate.prototype = SystemDate.prototype;
+  VirtualDate.now = () => Math.floor(native.wallNow());
+  VirtualDate.parse = SystemDate.parse;
+  VirtualDate.UTC = SystemDate.UTC;
+  Object.defineProperty(SystemDate.prototype, 'constructor', {
+    value: VirtualDate,
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, 'Date', {
+    value: VirtualDate,
+    configurable: true,
+    writable: true,
+  });
+
+  let a = seedLow >>> 0;
+  let b = seedHigh >>> 0;
+  let c = (seedLow ^ 0x9e3779b9) >>> 0;
+  let d = 1;
+  const next = () => {
+    const t = (((a + b) | 0) + d) | 0;
+    d = (d + 1) | 0;
+    a = b ^ (b >>> 9);
+    b = (c + (c << 3)) | 0;
+    c = (c << 21) | (c >>> 11);
+    c = (c + t) | 0;
+    return (t >>> 0) / 4294967296;
+  };
+  // The first outputs of a fresh state are poorly mixed.
+  for (let i = 0; i < 12; i++) next();
+  Object.defineProperty(Math, 'random', {
+    value: function random() {
+      return next();
+    },
+    configurable: true,
+    writable: true,
+  });
+})(globalThis.__vbeTime, SEED_LOW, SEED_HIGH);
+delete globalThis.__vbeTime;
+"#;
+
+/// JS half of cross-agent messaging. Another agent's window is only ever
+/// seen through a proxy whose one usable member is `postMessage`; touching
+/// anything else throws a `SecurityError`. Messages are cloned as JSON and
+/// arrive as `message` events through `__vbeDeliverMessage`; `__vbeSetOpener`
+/// points `window.opener` at the agent that opened the document.
+const AGENT_PRELUDE: &str = r#"
+(function (native) {
+  const self = native.self();
+  const securityError = (name) => {
+    const message = 'Blocked access to ' + String(name) + ' of a cross-origin window';
+    if (typeof DOMException === 'function') return new DOMException(message, 'SecurityError');
+    const error = new Error(message);
+    error.name = 'SecurityError';
+    return error;
+  };
+  const poster = (target) => (message, targetOrigin) => {
+    const data = JSON.stringify(message);
+    native.postMessage(
+      target,
+      data === undefined ? 'null' : data,
+      targetOrigin === undefined ? '/' : String(targetOrigin)
+    );
+  };
+  const proxies = new Map();
+  const windowOf = (agent) => {
+    if (agent === self) return globalThis;
+    let proxy = proxies.get(agent);
+    if (!proxy) {
+      const postMessage = poster(agent);
+      const deny = (_target, name) => {
+        throw securityError(name);
+      };
+      proxy = new Proxy(Object.create(null), {
+        get: (_target, name) => {
+          if (name === 'postMessage') return postMessage;
+          throw securityError(name);
+        },
+        set: deny,
+        has: deny,
+        deleteProperty: deny,
+        defineProperty: deny,
+        getOwnPropertyDescriptor: deny,
+        ownKeys: () => {
+          throw securityError('the properties');
+        },
+      });
+      proxies.set(agent, proxy);
+    }
+    return proxy;
+  };
+
+  globalThis.postMessage = poster(self);
+  globalThis.opener = null;
+  Object.defineProperty(globalThis, '__vbeSetOpener', {
+    value: (agent) => {
+      globalThis.opener = windowOf(agent);
+    },
+    configurable: true,
+    writable: true,
+  });
+  Object.defineProperty(globalThis, '__vbeDeliverMessage', {
+    value: (source, origin, data) => {
+      const event = { type: 'message', data: JSON.parse(data), origin, source: windowOf(source) };
+      if (typeof globalThis.dispatchEvent === 'function') globalThis.dispatchEvent(event);
+      if (typeof globalThis.onmessage === 'function') globalThis.onmessage(event);
+    },
+    configurable: true,
+    writable: true,
+  });
+})(globalThis.__vbeAgent);
+delete globalThis.__vbeAgent;
+"#;
+
+/// JS half of the `WebAssembly` gate. Every way of compiling or
+/// instantiating goes through `native.check` first, and the instantiations,
+/// `Memory` constructions and `grow` calls that would take the context's live
+/// linear memory over `native.memoryLimit()` throw a `RangeError`.
+/// `compileStreaming`/`instantiateStreaming` take a `Response` (or a promise
+/// of one) served as `application/wasm` and compile its bytes once they are
+/// all in. Running it again in a context already gated only drops the
+/// native object; with WebAssembly disabled it removes the global instead.
+const WASM_PRELUDE: &str = r#"
+(function (native) {
+  const wasm = globalThis.WebAssembly;
+  if (!native.enabled()) {
+    delete globalThis.WebAssembly;
+    return;
+  }
+  if (!wasm || wasm.compile.__vbeGated) return;
+
+  const PAGE_BYTES = 65536;
+  const original = {
+    compile: wasm.compile,
+    instantiate: wasm.instantiate,
+    Module: wasm.Module,
+    Instance: wasm.Instance,
+    Memory: wasm.Memory,
+    grow: wasm.Memory.prototype.grow,
+    buffer: Object.getOwnPropertyDescriptor(wasm.Memory.prototype, 'buffer').get,
+  };
+  const now = () =>
+    typeof performance === 'object' && typeof performance.now === 'function'
+      ? performance.now()
+      : Date.now();
+  const timed = (kind, start) => native.record(kind, (now() - start) * 1000);
+
+  const memories = new Set();
+  const tracked = new WeakSet();
+  const liveBytes = () => {
+    let total = 0;
+    for (const ref of memories) {
+      const memory = ref.deref();
+      if (memory === undefined) memories.delete(ref);
+      else total += original.buffer.call(memory).byteLength;
+    }
+    return total;
+  };
+  const track = (memory) => {
+    if (!tracked.has(memory)) {
+      tracked.add(memory);
+      memories.add(new WeakRef(memory));
+    }
+    native.reportMemory(liveBytes());
+  };
+  const checkRoom = (bytes) => {
+    const limit = native.memoryLimit();
+    if (bytes > 0 && liveBytes() + bytes > limit) {
+      throw new RangeError(
+        'WebAssembly memory of ' + bytes + ' more bytes would go over the ' + limit + ' byte limit'
+      );
+    }
+  };
+
+  const declared = new WeakMap();
+  const vet = (source) => {
+    let bytes;
+    if (ArrayBuffer.isView(source)) {
+      bytes = new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+    } else if (source instanceof ArrayBuffer) {
+      bytes = new Uint8Array(source);
+    } else {
+      throw new TypeError('WebAssembly source must be an ArrayBuffer or a typed array');
+    }
+    const report = JSON.parse(native.check(bytes));
+    if (report.error) {
+      const Kind = report.error.name === 'RangeError' ? RangeError : wasm.CompileError;
+      throw new Kind(report.error.message);
+    }
+    return report.memoryBytes;
+  };
+  const trackExports = (instance) => {
+    for (const value of Object.values(instance.exports)) {
+      if (value instanceof original.Memory) track(value);
+    }
+    return instance;
+  };
+
+  const compile = async (source) => {
+    const memoryBytes = vet(source);
+    const start = now();
+    try {
+      const module = await original.compile.call(wasm, source);
+      declared.set(module, memoryBytes);
+      return module;
+    } finally {
+      timed('compile', start);
+    }
+  };
+  const instantiateModule = async (module, imports) => {
+    checkRoom(declared.get(module) || 0);
+    const start = now();
+    try {
+      return trackExports(await original.instantiate.call(wasm, module, imports));
+    } finally {
+      timed('instantiate', start);
+    }
+  };
+  const instantiate = async (source, imports) => {
+    if (source instanceof original.Module) return instantiateModule(source, imports);
+    const module = await compile(source);
+    return { module, instance: await instantiateModule(module, imports) };
+  };
+
+  function Module(source) {
+    if (new.target === undefined) throw new TypeError("WebAssembly.Module must be invoked with 'new'");
+    const memoryBytes = vet(source);
+    const start = now();
+    try {
+      const module = new original.Module(source);
+      declared.set(module, memoryBytes);
+      return module;
+    } finally {
+      timed('compile', start);
+    }
+  }
+  function Instance(module, imports) {
+    if (new.target === undefined) throw new TypeError("WebAssembly.Instance must be invoked with 'new'");
+    checkRoom(declared.get(module) || 0);
+    const start = now();
+    try {
+      return trackExports(new original.Instance(module, imports));
+    } finally {
+      timed('instantiate', start);
+    }
+  }
+  function Memory(descriptor) {
+    if (new.target === undefined) throw new TypeError("WebAssembly.Memory must be invoked with 'new'");
+    checkRoom(Number(descriptor && descriptor.initial) * PAGE_BYTES || 0);
+    const memory = new original.Memory(descriptor);
+    track(memory);
+    return memory;
+  }
+  for (const [wrapper, wrapped] of [
+    [Module, original.Module],
+    [Instance, original.Instance],
+    [Memory, original.Memory],
+  ]) {
+    wrapper.prototype = wrapped.prototype;
+    Object.defineProperty(wrapped.prototype, 'constructor', {
+      value: wrapper,
+      configurable: true,
+      writable: true,
+    });
+  }
+  Module.exports = original.Module.exports;
+  Module.imports = original.Module.imports;
+  Module.customSections = original.Module.customSections;
+  original.Memory.prototype.grow = function grow(delta) {
+    checkRoom(Number(delta) * PAGE_BYTES || 0);
+    const pages = original.grow.call(this, delta);
+    track(this);
+    return pages;
+  };
+
+  const responseBytes = async (source) => {
+    const response = await source;
+    if (typeof Response !== 'function' || !(response instanceof Response)) {
+      throw new TypeError('WebAssembly streaming needs a Response');
+    }
+    const type = (response.headers.get('content-type') || '').split(';')[0].trim().toLowerCase();
+    if (type !== 'application/wasm') {
+      throw new TypeError("Incorrect response MIME type. Expected 'application/wasm'.");
+    }
+    if (!response.ok) throw new TypeError('HTTP status code is not ok');
+    return response.arrayBuffer();
+  };
+
+  Object.defineProperty(compile, '__vbeGated', { value: true });
+  wasm.compile = compile;
+  wasm.instantiate = instantiate;
+  wasm.compileStreaming = async (source) => compile(await responseBytes(source));
+  wasm.instantiateStreaming = async (source, imports) =>
+    instantiate(await responseBytes(source), imports);
+  wasm.Module = Module;
+  wasm.Instance = Instance;
+  wasm.Memory = Memory;
+})(globalThis.__vbeWasm);
+delete globalThis.__vbeWasm;
+"#;
+
 // Global V8 initialization state
 static INIT_V8: Once = Once::new();
 static DISPOSE_V8: Once = Once::new();
 static V8_STATE: Mutex<V8State> = Mutex::new(V8State::Uninitialized);
+static V8_JITLESS: AtomicBool = AtomicBool::new(false);
+static NEXT_ISOLATE_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static ENTERED_ISOLATES: RefCell<EnteredIsolates> = RefCell::new(EnteredIsolates::default());
+}
+
+/// An isolate enters its thread when created and leaves it when disposed,
+/// and V8 wants them to leave in the reverse order. A runtime dropped while
+/// a newer one on its thread lives keeps its isolate here until the newer
+/// ones are gone.
+#[derive(Default)]
+struct EnteredIsolates {
+    /// This thread's live isolates, oldest first.
+    ids: Vec<u64>,
+    retired: HashMap<u64, v8::OwnedIsolate>,
+}
+
+impl EnteredIsolates {
+    fn enter(id: u64) {
+        ENTERED_ISOLATES.with(|entered| entered.borrow_mut().ids.push(id));
+    }
+
+    fn is_current(id: u64) -> bool {
+        ENTERED_ISOLATES.with(|entered| entered.borrow().ids.last() == Some(&id))
+    }
+
+    /// Dispose of `isolate` now if no newer isolate is entered, along with
+    /// any retired ones that were waiting on it; otherwise retire it.
+    fn dispose(id: u64, isolate: v8::OwnedIsolate) {
+        let disposable = ENTERED_ISOLATES.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if entered.ids.last() != Some(&id) && entered.ids.contains(&id) {
+                entered.retired.insert(id, isolate);
+                return Vec::new();
+            }
+            entered.ids.retain(|&other| other != id);
+            let mut disposable = vec![isolate];
+            while let Some(older) = entered.ids.last().copied() {
+                match entered.retired.remove(&older) {
+                    Some(isolate) => {
+                        entered.ids.pop();
+                        disposable.push(isolate);
+                    }
+                    None => break,
+                }
+            }
+            disposable
+        });
+        // Newest first, outside the borrow: slots dropped with an isolate
+        // may drop other runtimes.
+        for isolate in disposable {
+            drop(isolate);
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum V8State {
@@ -18,18 +2244,147 @@ enum V8State {
     Disposed,
 }
 
+/// The isolate slot payloads that belong to one context. Only the current
+/// context's are in the isolate; switching contexts stashes them here.
+#[derive(Default)]
+struct ContextSlots {
+    pending_mutations: Option<PendingMutations>,
+    document: Option<Document>,
+    wrappers: Option<DomWrappers>,
+    network: Option<NetworkBinding>,
+    storage: Option<StorageBinding>,
+    form: Option<FormBinding>,
+    editing: Option<EditingBinding>,
+    drag: Option<DragBinding>,
+    clipboard: Option<ClipboardBinding>,
+    media: Option<MediaBinding>,
+    print: Option<PrintBinding>,
+    document_write: Option<DocumentWriteBinding>,
+    scroll: Option<ScrollBinding>,
+    install: Option<InstallBinding>,
+    speech: Option<SpeechBinding>,
+    font: Option<FontBinding>,
+    clock: Option<EventLoopClock>,
+    agent: Option<AgentBinding>,
+    wasm: Option<WasmBinding>,
+    gated_globals: Option<GatedGlobals>,
+}
+
+impl ContextSlots {
+    fn take(isolate: &mut v8::Isolate) -> Self {
+        Self {
+            pending_mutations: isolate.remove_slot(),
+            document: isolate.remove_slot(),
+            wrappers: isolate.remove_slot(),
+            network: isolate.remove_slot(),
+            storage: isolate.remove_slot(),
+            form: isolate.remove_slot(),
+            editing: isolate.remove_slot(),
+            drag: isolate.remove_slot(),
+            clipboard: isolate.remove_slot(),
+            media: isolate.remove_slot(),
+            print: isolate.remove_slot(),
+            document_write: isolate.remove_slot(),
+            scroll: isolate.remove_slot(),
+            install: isolate.remove_slot(),
+            speech: isolate.remove_slot(),
+            font: isolate.remove_slot(),
+            clock: isolate.remove_slot(),
+            agent: isolate.remove_slot(),
+            wasm: isolate.remove_slot(),
+            gated_globals: isolate.remove_slot(),
+        }
+    }
+
+    fn restore(self, isolate: &mut v8::Isolate) {
+        fn put<T: 'static>(isolate: &mut v8::Isolate, slot: Option<T>) {
+            if let Some(value) = slot {
+                isolate.set_slot(value);
+            }
+        }
+        put(isolate, self.pending_mutations);
+        put(isolate, self.document);
+        put(isolate, self.wrappers);
+        put(isolate, self.network);
+        put(isolate, self.storage);
+        put(isolate, self.form);
+        put(isolate, self.editing);
+        put(isolate, self.drag);
+        put(isolate, self.clipboard);
+        put(isolate, self.media);
+        put(isolate, self.print);
+        put(isolate, self.document_write);
+        put(isolate, self.scroll);
+        put(isolate, self.install);
+        put(isolate, self.speech);
+        put(isolate, self.font);
+        put(isolate, self.clock);
+        put(isolate, self.agent);
+        put(isolate, self.wasm);
+        put(isolate, self.gated_globals);
+    }
+}
+
+/// Globals of disabled features a context had taken away, kept to be put
+/// back once a document with the feature on runs in it.
+#[derive(Default)]
+struct GatedGlobals(HashMap<String, v8::Global<v8::Value>>);
+
+/// What a compile did with V8 code cache data.
+#[derive(Debug, Default)]
+pub struct CodeCacheOutcome {
+    /// Data was supplied and V8 refused it, so the script compiled in full.
+    pub rejected: bool,
+    /// Fresh data for the script, when asked for and none was consumed.
+    pub produced: Option<Vec<u8>>,
+}
+
+/// A context that is not the current one, with its bindings.
+struct ParkedContext {
+    context: v8::Global<v8::Context>,
+    slots: ContextSlots,
+}
+
+/// One isolate running any number of contexts, each created under a caller
+/// chosen id. Script, bindings and event loop steps go to the current
+/// context. Every task ends in a microtask checkpoint, so the isolate-wide
+/// microtask queue is empty whenever the current context changes.
 pub struct V8Runtime {
-    isolate: v8::OwnedIsolate,
+    isolate: ManuallyDrop<v8::OwnedIsolate>,
+    isolate_id: u64,
     context: v8::Global<v8::Context>,
+    context_id: u64,
+    parked: HashMap<u64, ParkedContext>,
+    // Isolated worlds of user scripts, by the id of the context whose
+    // document they share.
+    isolated_worlds: HashMap<u64, v8::Global<v8::Context>>,
     gc: Arc<Mutex<GarbageCollector>>,
+    // Spent compiling scripts, cached or not, since the runtime started.
+    compile_time: Duration,
+    // What contexts bound from now on tell the time and draw random
+    // numbers by; the system's when `None`.
+    virtual_time: Option<VirtualTime>,
 }
 
 impl V8Runtime {
+    /// The id of the context a runtime starts in.
+    pub const INITIAL_CONTEXT: u64 = 0;
+
     pub fn new() -> Result<Self, V8Error> {
+        Self::with_jitless(false)
+    }
+
+    /// Create a runtime, requesting interpreter-only (`--jitless`) execution.
+    ///
+    /// V8 flags are process-global and can only be set before initialization, so the
+    /// first runtime created in the process decides the mode for all later ones.
+    pub fn with_jitless(jitless: bool) -> Result<Self, V8Error> {
         // Initialize V8 only once per process
-        Self::ensure_v8_initialized()?;
+        Self::ensure_v8_initialized(jitless)?;
 
         let mut isolate = v8::Isolate::new(v8::CreateParams::default());
+        // Microtasks run only at the checkpoints the event loop performs.
+        isolate.set_microtasks_policy(v8::MicrotasksPolicy::Explicit);
 
         let context = {
             let scope = &mut v8::HandleScope::new(&mut isolate);
@@ -38,285 +2393,1767 @@ impl V8Runtime {
         };
 
         let gc = Arc::new(Mutex::new(GarbageCollector::new()));
+        let isolate_id = NEXT_ISOLATE_ID.fetch_add(1, Ordering::Relaxed);
+        EnteredIsolates::enter(isolate_id);
 
         Ok(Self {
-            isolate,
+            isolate: ManuallyDrop::new(isolate),
+            isolate_id,
             context,
+            context_id: Self::INITIAL_CONTEXT,
+            parked: HashMap::new(),
+            isolated_worlds: HashMap::new(),
             gc,
+            compile_time: Duration::ZERO,
+            virtual_time: None,
         })
     }
 
-    fn ensure_v8_initialized() -> Result<(), V8Error> {
-        let mut init_result = Ok(());
+    /// Have the contexts bound after this read `time`'s clock and seed
+    /// `Math.random` from it. Contexts already bound keep what they had.
+    pub fn set_virtual_time(&mut self, time: Option<VirtualTime>) {
+        self.virtual_time = time;
+    }
+
+    fn ensure_v8_initialized(jitless: bool) -> Result<(), V8Error> {
+        let mut init_result = Ok(());
+
+        INIT_V8.call_once(|| match Self::initialize_v8(jitless) {
+            Ok(_) => {
+                if let Ok(mut state) = V8_STATE.lock() {
+                    *state = V8State::Initialized;
+                } else {
+                    init_result = Err(V8Error::InitializationFailed);
+                }
+            }
+            Err(e) => {
+                init_result = Err(e);
+            }
+        });
+
+        // Check if initialization was successful
+        if init_result.is_ok() {
+            let state = V8_STATE.lock().map_err(|_| V8Error::InitializationFailed)?;
+            if *state != V8State::Initialized {
+                return Err(V8Error::InitializationFailed);
+            }
+        }
+
+        init_result
+    }
+
+    fn initialize_v8(jitless: bool) -> Result<(), V8Error> {
+        if jitless {
+            v8::V8::set_flags_from_string("--jitless");
+            V8_JITLESS.store(true, Ordering::SeqCst);
+        }
+        let platform = v8::new_default_platform(0, false).make_shared();
+        v8::V8::initialize_platform(platform);
+        v8::V8::initialize();
+        Ok(())
+    }
+
+    pub fn dispose_v8() {
+        DISPOSE_V8.call_once(|| {
+            if let Ok(mut state) = V8_STATE.lock() {
+                if *state == V8State::Initialized {
+                    unsafe {
+                        v8::V8::dispose();
+                    }
+                    v8::V8::dispose_platform();
+                    *state = V8State::Disposed;
+                }
+            }
+        });
+    }
+
+    /// The version of V8 compiling scripts; code cache data only fits the
+    /// version that produced it.
+    pub fn v8_version() -> &'static str {
+        v8::V8::get_version()
+    }
+
+    pub fn is_jitless() -> bool {
+        V8_JITLESS.load(Ordering::SeqCst)
+    }
+
+    pub fn is_v8_initialized() -> bool {
+        V8_STATE
+            .lock()
+            .map(|state| *state == V8State::Initialized)
+            .unwrap_or(false)
+    }
+
+    /// The id of the current context.
+    pub fn context_id(&self) -> u64 {
+        self.context_id
+    }
+
+    /// Create a context under `id` with the event loop bound, without
+    /// switching to it.
+    pub fn create_context(&mut self, id: u64) -> Result<(), V8Error> {
+        if id == self.context_id || self.parked.contains_key(&id) {
+            return Err(V8Error::ContextExists(id));
+        }
+        let context = {
+            let scope = &mut v8::HandleScope::new(&mut *self.isolate);
+            let context = v8::Context::new(scope);
+            v8::Global::new(scope, context)
+        };
+        self.parked.insert(
+            id,
+            ParkedContext {
+                context,
+                slots: ContextSlots::default(),
+            },
+        );
+
+        let previous = self.context_id;
+        self.switch_context(id)?;
+        let bound = self.bind_event_loop();
+        self.switch_context(previous)?;
+        bound
+    }
+
+    /// Make the context created under `id` the current one. Its bindings
+    /// come back into the isolate and the previous context's are stashed.
+    pub fn switch_context(&mut self, id: u64) -> Result<(), V8Error> {
+        if id == self.context_id {
+            return Ok(());
+        }
+        let next = self.parked.remove(&id).ok_or(V8Error::UnknownContext(id))?;
+        let slots = ContextSlots::take(&mut self.isolate);
+        let previous = std::mem::replace(&mut self.context, next.context);
+        self.parked.insert(
+            self.context_id,
+            ParkedContext {
+                context: previous,
+                slots,
+            },
+        );
+        next.slots.restore(&mut self.isolate);
+        self.context_id = id;
+        Ok(())
+    }
+
+    /// Drop the context created under `id` and its bindings. The current
+    /// context cannot be disposed.
+    pub fn dispose_context(&mut self, id: u64) -> Result<(), V8Error> {
+        if id == self.context_id {
+            return Err(V8Error::ContextInUse(id));
+        }
+        self.isolated_worlds.remove(&id);
+        match self.parked.remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(V8Error::UnknownContext(id)),
+        }
+    }
+
+    fn with_context_scope<T, F>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut v8::ContextScope<v8::HandleScope>) -> T,
+    {
+        let scope = &mut v8::HandleScope::new(&mut *self.isolate);
+        let context = v8::Local::new(scope, &self.context);
+        let scope = &mut v8::ContextScope::new(scope, context);
+        f(scope)
+    }
+
+    /// Run `source` as a task: the script, then a microtask checkpoint. The
+    /// result is the script's completion value, taken before the checkpoint.
+    pub fn execute(&mut self, source: &str) -> Result<serde_json::Value, V8Error> {
+        self.execute_with_code_cache(source, None, false)
+            .map(|(value, _)| value)
+    }
+
+    /// Run `source` as [`execute`](Self::execute) does, compiling it from
+    /// `cached_data` when V8 accepts that. With `produce_cache`, a script
+    /// compiled in full that runs to completion also yields data for the
+    /// next compile; taken after the run, it covers the functions the run
+    /// compiled too.
+    pub fn execute_with_code_cache(
+        &mut self,
+        source: &str,
+        cached_data: Option<&[u8]>,
+        produce_cache: bool,
+    ) -> Result<(serde_json::Value, CodeCacheOutcome), V8Error> {
+        let mut compile_time = Duration::ZERO;
+        let result = self.with_context_scope(|scope| {
+            let result =
+                Self::run_script(scope, source, cached_data, produce_cache, &mut compile_time);
+            EventLoopCallbacks::checkpoint(scope);
+            result
+        });
+        self.compile_time += compile_time;
+        result
+    }
+
+    /// Microseconds spent compiling scripts since the runtime started.
+    pub fn compile_time_us(&self) -> u64 {
+        self.compile_time.as_micros() as u64
+    }
+
+    fn run_script(
+        scope: &mut HandleScope,
+        source: &str,
+        cached_data: Option<&[u8]>,
+        produce_cache: bool,
+        compile_time: &mut Duration,
+    ) -> Result<(serde_json::Value, CodeCacheOutcome), V8Error> {
+        let code = v8::String::new(scope, source).ok_or(V8Error::InvalidSource)?;
+
+        let mut try_catch = v8::TryCatch::new(scope);
+        let mut outcome = CodeCacheOutcome::default();
+        let started = Instant::now();
+        let script = match cached_data {
+            Some(data) => {
+                let mut source = v8::script_compiler::Source::new_with_cached_data(
+                    code,
+                    None,
+                    v8::CachedData::new(data),
+                );
+                let script = v8::script_compiler::compile(
+                    &mut try_catch,
+                    &mut source,
+                    v8::script_compiler::CompileOptions::ConsumeCodeCache,
+                    v8::script_compiler::NoCacheReason::NoReason,
+                );
+                outcome.rejected = source
+                    .get_cached_data()
+                    .map_or(true, |data| data.rejected());
+                script
+            }
+            None => v8::Script::compile(&mut try_catch, code, None),
+        };
+        *compile_time += started.elapsed();
+        let script = script.ok_or_else(|| Self::extract_exception(&mut try_catch))?;
+
+        let result = script
+            .run(&mut try_catch)
+            .ok_or_else(|| Self::extract_exception(&mut try_catch))?;
+
+        if produce_cache && (cached_data.is_none() || outcome.rejected) {
+            outcome.produced = script
+                .get_unbound_script(&mut try_catch)
+                .create_code_cache()
+                .map(|data| data.to_vec());
+        }
+
+        Ok((Self::value_to_json(&mut try_catch, result)?, outcome))
+    }
+
+    fn extract_exception(try_catch: &mut TryCatch<HandleScope>) -> V8Error {
+        if let Some(exception) = try_catch.exception() {
+            if let Some(message) = try_catch.message() {
+                let msg_str = message.get(try_catch);
+                return V8Error::ExecutionError(msg_str.to_rust_string_lossy(try_catch));
+            }
+            if let Some(exception_str) = exception.to_string(try_catch) {
+                return V8Error::ExecutionError(exception_str.to_rust_string_lossy(try_catch));
+            }
+        }
+        V8Error::ExecutionError("Unknown execution error".to_string())
+    }
+
+    fn value_to_json(
+        scope: &mut HandleScope,
+        value: Local<v8::Value>,
+    ) -> Result<serde_json::Value, V8Error> {
+        if value.is_null() || value.is_undefined() {
+            Ok(serde_json::Value::Null)
+        } else if value.is_boolean() {
+            Ok(serde_json::Value::Bool(value.boolean_value(scope)))
+        } else if value.is_number() {
+            let num = value.number_value(scope).unwrap_or(0.0);
+            Ok(serde_json::Value::Number(
+                serde_json::Number::from_f64(num).unwrap_or_else(|| serde_json::Number::from(0)),
+            ))
+        } else if value.is_string() {
+            let string = value.to_string(scope).unwrap();
+            Ok(serde_json::Value::String(
+                string.to_rust_string_lossy(scope),
+            ))
+        } else if value.is_array() {
+            let array = v8::Local::<v8::Array>::try_from(value).unwrap();
+            let mut result = Vec::with_capacity(array.length() as usize);
+            for i in 0..array.length() {
+                if let Some(element) = array.get_index(scope, i) {
+                    result.push(Self::value_to_json(scope, element)?);
+                }
+            }
+            Ok(serde_json::Value::Array(result))
+        } else if value.is_object() {
+            let object = v8::Local::<v8::Object>::try_from(value).unwrap();
+            let mut result = serde_json::Map::new();
+            if let Some(property_names) =
+                object.get_own_property_names(scope, v8::GetPropertyNamesArgs::default())
+            {
+                for i in 0..property_names.length() {
+                    if let Some(key) = property_names.get_index(scope, i) {
+                        if let Some(key_str) = key.to_string(scope) {
+                            let key_string = key_str.to_rust_string_lossy(scope);
+                            if let Some(prop_value) = object.get(scope, key) {
+                                result.insert(key_string, Self::value_to_json(scope, prop_value)?);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(serde_json::Value::Object(result))
+        } else {
+            Ok(serde_json::Value::Null)
+        }
+    }
+
+    pub fn bind_function(&mut self, name: &str) -> Result<(), V8Error> {
+        self.with_context_scope(|scope| {
+            let function_name = v8::String::new(scope, name).ok_or(V8Error::InvalidFunctionName)?;
+
+            let function_template = v8::FunctionTemplate::new(
+                scope,
+                |scope: &mut HandleScope,
+                 _args: v8::FunctionCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    let undefined = v8::undefined(scope);
+                    rv.set(undefined.into());
+                },
+            );
+
+            let function = function_template
+                .get_function(scope)
+                .ok_or(V8Error::FunctionCreationFailed)?;
+
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, function_name.into(), function.into())
+                .ok_or(V8Error::BindingFailed)?;
+
+            Ok(())
+        })
+    }
+
+    pub fn bind_console_log(&mut self) -> Result<(), V8Error> {
+        self.with_context_scope(|scope| {
+            let console_obj = v8::Object::new(scope);
+            let console_str =
+                v8::String::new(scope, "console").ok_or(V8Error::InvalidFunctionName)?;
+
+            let log_template = v8::FunctionTemplate::new(
+                scope,
+                |scope: &mut HandleScope,
+                 args: v8::FunctionCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    for i in 0..args.length() {
+                        let arg = args.get(i);
+                        if let Some(str_val) = arg.to_string(scope) {
+                            let rust_string = str_val.to_rust_string_lossy(scope);
+                            println!("{}", rust_string);
+                        }
+                    }
+                    let undefined = v8::undefined(scope);
+                    rv.set(undefined.into());
+                },
+            );
+
+            let log_function = log_template
+                .get_function(scope)
+                .ok_or(V8Error::FunctionCreationFailed)?;
+
+            let log_str = v8::String::new(scope, "log").ok_or(V8Error::InvalidFunctionName)?;
+
+            console_obj
+                .set(scope, log_str.into(), log_function.into())
+                .ok_or(V8Error::BindingFailed)?;
+
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, console_str.into(), console_obj.into())
+                .ok_or(V8Error::BindingFailed)?;
+
+            Ok(())
+        })
+    }
+
+    pub fn bind_arithmetic_functions(&mut self) -> Result<(), V8Error> {
+        self.with_context_scope(|scope| {
+            let global = scope.get_current_context().global(scope);
+
+            let add_template = v8::FunctionTemplate::new(
+                scope,
+                |scope: &mut HandleScope,
+                 args: v8::FunctionCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    if args.length() >= 2 {
+                        let a = args.get(0).number_value(scope).unwrap_or(0.0);
+                        let b = args.get(1).number_value(scope).unwrap_or(0.0);
+                        let result = v8::Number::new(scope, a + b);
+                        rv.set(result.into());
+                    } else {
+                        let undefined = v8::undefined(scope);
+                        rv.set(undefined.into());
+                    }
+                },
+            );
+
+            let multiply_template = v8::FunctionTemplate::new(
+                scope,
+                |scope: &mut HandleScope,
+                 args: v8::FunctionCallbackArguments,
+                 mut rv: v8::ReturnValue| {
+                    if args.length() >= 2 {
+                        let a = args.get(0).number_value(scope).unwrap_or(0.0);
+                        let b = args.get(1).number_value(scope).unwrap_or(0.0);
+                        let result = v8::Number::new(scope, a * b);
+                        rv.set(result.into());
+                    } else {
+                        let undefined = v8::undefined(scope);
+                        rv.set(undefined.into());
+                    }
+                },
+            );
+
+            let add_function = add_template
+                .get_function(scope)
+                .ok_or(V8Error::FunctionCreationFailed)?;
+            let multiply_function = multiply_template
+                .get_function(scope)
+                .ok_or(V8Error::FunctionCreationFailed)?;
+
+            let add_name = v8::String::new(scope, "add").ok_or(V8Error::InvalidFunctionName)?;
+            let multiply_name =
+                v8::String::new(scope, "multiply").ok_or(V8Error::InvalidFunctionName)?;
+
+            global
+                .set(scope, add_name.into(), add_function.into())
+                .ok_or(V8Error::BindingFailed)?;
+            global
+                .set(scope, multiply_name.into(), multiply_function.into())
+                .ok_or(V8Error::BindingFailed)?;
+
+            Ok(())
+        })
+    }
+
+    /// Expose `document` and element wrappers backed by `document`. Re-binding
+    /// replaces the previous document for this isolate.
+    pub fn bind_dom_api(&mut self, document: Document) -> Result<(), V8Error> {
+        self.unbind_dom_api()?;
+        // Mutations reach JS observers at the next microtask checkpoint,
+        // whether script or the engine made them.
+        let pending = PendingMutations::default();
+        let sink = pending.clone();
+        document.add_mutation_observer(MutationObserver {
+            callback: Arc::new(move |records| sink.push(records)),
+            observe_child_list: true,
+            observe_attributes: true,
+            observe_character_data: true,
+            observe_subtree: true,
+            attribute_filter: None,
+        });
+        self.isolate.set_slot(pending);
+        self.isolate.set_slot(document);
+        self.isolate.set_slot(DomWrappers::default());
+
+        self.with_context_scope(Self::bind_dom_natives)?;
+        // A user script's world saw the previous document.
+        self.isolated_worlds.remove(&self.context_id);
+
+        // Timers and frame callbacks belonged to the previous document.
+        self.execute("globalThis.__vbeEventLoop && __vbeEventLoop.reset()")?;
+        self.execute(DOM_PRELUDE).map(|_| ())
+    }
+
+    /// Put the natives `DOM_PRELUDE` builds the DOM API from on the global
+    /// of the context `scope` is in, as `__vbeDom`.
+    fn bind_dom_natives(scope: &mut v8::ContextScope<v8::HandleScope>) -> Result<(), V8Error> {
+        let native = v8::Object::new(scope);
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getElementById",
+            DomCallbacks::get_element_by_id,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getElementsByClassName",
+            DomCallbacks::get_elements_by_class_name,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "querySelector",
+            DomCallbacks::query_selector,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "querySelectorAll",
+            DomCallbacks::query_selector_all,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "querySelectorWithin",
+            DomCallbacks::query_selector_within,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "querySelectorAllWithin",
+            DomCallbacks::query_selector_all_within,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getAttribute",
+            DomCallbacks::get_attribute,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "setAttribute",
+            DomCallbacks::set_attribute,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "removeAttribute",
+            DomCallbacks::remove_attribute,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getStyleProperty",
+            DomCallbacks::get_style_property,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getStylePriority",
+            DomCallbacks::get_style_priority,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "setStyleProperty",
+            DomCallbacks::set_style_property,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "removeStyleProperty",
+            DomCallbacks::remove_style_property,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "getCssText",
+            DomCallbacks::get_css_text,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "setCssText",
+            DomCallbacks::set_css_text,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(scope, native, "contains", DomCallbacks::contains)
+            .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "takeMutationRecords",
+            DomCallbacks::take_mutation_records,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "createElement",
+            DomCallbacks::create_element,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(scope, native, "rootNode", DomCallbacks::root_node)
+            .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "parentNode",
+            DomCallbacks::parent_node,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "documentElement",
+            DomCallbacks::document_element,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(scope, native, "location", DomCallbacks::location)
+            .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "serialize",
+            DomCallbacks::serialize,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "textContent",
+            DomCallbacks::text_content,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "setTextContent",
+            DomCallbacks::set_text_content,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "appendChild",
+            DomCallbacks::append_child,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "removeChild",
+            DomCallbacks::remove_child,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "appendText",
+            DomCallbacks::append_text,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "normalize",
+            DomCallbacks::normalize,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+        V8CallbackHelper::bind_method_to_object(
+            scope,
+            native,
+            "trackWrapper",
+            DomCallbacks::track_wrapper,
+        )
+        .map_err(|_| V8Error::BindingFailed)?;
+
+        let native_name = v8::String::new(scope, "__vbeDom").ok_or(V8Error::InvalidFunctionName)?;
+        let global = scope.get_current_context().global(scope);
+        global
+            .set(scope, native_name.into(), native.into())
+            .ok_or(V8Error::BindingFailed)?;
+        Ok(())
+    }
+
+    /// Run `source` as a task in the current context's isolated world: a
+    /// context of its own over the same document, so the script works on
+    /// the page's DOM without seeing the page's globals, nor the page its.
+    /// The world is made on first use and lasts until a document is bound
+    /// again. Events the engine dispatches reach only the page's world.
+    pub fn execute_isolated(&mut self, source: &str) -> Result<serde_json::Value, V8Error> {
+        if self.isolate.get_slot::<Document>().is_none() {
+            return Err(V8Error::ExecutionError(
+                "No document is bound for an isolated world".to_string(),
+            ));
+        }
+        let world = match self.isolated_worlds.get(&self.context_id) {
+            Some(world) => world.clone(),
+            None => {
+                let world = {
+                    let scope = &mut v8::HandleScope::new(&mut *self.isolate);
+                    let context = v8::Context::new(scope);
+                    v8::Global::new(scope, context)
+                };
+                let page = std::mem::replace(&mut self.context, world.clone());
+                let bound = self
+                    .with_context_scope(Self::bind_dom_natives)
+                    .and_then(|_| self.execute(DOM_PRELUDE));
+                self.context = page;
+                bound?;
+                self.isolated_worlds.insert(self.context_id, world.clone());
+                world
+            }
+        };
+        let page = std::mem::replace(&mut self.context, world);
+        let result = self.execute(source);
+        self.context = page;
+        result
+    }
+
+    /// Invalidate every wrapper of the bound document, so script touching one
+    /// gets a "destroyed document" error, and cancel their GC finalizers.
+    /// Call before the document is torn down.
+    pub fn unbind_dom_api(&mut self) -> Result<(), V8Error> {
+        self.execute("globalThis.__vbeDomTeardown && __vbeDomTeardown()")?;
+        self.isolate.set_slot(DomWrappers::default());
+        Ok(())
+    }
+
+    /// Apply wrapper releases reported by GC and free detached nodes nothing
+    /// refers to any more. Returns how many nodes were freed.
+    pub fn reclaim_dom_nodes(&mut self) -> Result<usize, V8Error> {
+        let document = match self.isolate.get_slot::<Document>() {
+            Some(document) => document.clone(),
+            None => return Ok(0),
+        };
+        let report = document.reclaim_detached();
+        if report.released_wrappers == 0 && report.reclaimed.is_empty() {
+            return Ok(0);
+        }
+        if let Some(wrappers) = self.isolate.get_slot_mut::<DomWrappers>() {
+            wrappers.prune();
+        }
+        let reclaimed: Vec<_> = report
+            .reclaimed
+            .iter()
+            .map(|id| serde_json::Value::String(id.0.to_string()))
+            .collect();
+        self.execute(&format!(
+            "globalThis.__vbeDomSweep && __vbeDomSweep({})",
+            serde_json::Value::Array(reclaimed)
+        ))?;
+        Ok(report.reclaimed.len())
+    }
+
+    /// Run a full V8 collection, then [`Self::reclaim_dom_nodes`].
+    pub fn collect_dom_garbage(&mut self) -> Result<usize, V8Error> {
+        // `WeakRef` targets stay alive until kept objects are cleared.
+        self.isolate.clear_kept_objects();
+        self.isolate.low_memory_notification();
+        self.reclaim_dom_nodes()
+    }
+
+    /// Expose `fetch`, `navigator.sendBeacon` and keepalive `fetch` for the
+    /// document described by `binding`. Re-binding replaces the previous
+    /// initiator.
+    pub fn bind_network_api(&mut self, binding: NetworkBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "queueKeepalive",
+                NetworkCallbacks::queue_keepalive,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "fetch",
+                NetworkCallbacks::fetch,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeNet").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(NETWORK_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `localStorage` and `sessionStorage` over `binding`'s areas.
+    /// Re-binding on navigation switches to the new document's areas.
+    pub fn bind_storage_api(&mut self, binding: StorageBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "getItem",
+                StorageCallbacks::get_item,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setItem",
+                StorageCallbacks::set_item,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "removeItem",
+                StorageCallbacks::remove_item,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "clear",
+                StorageCallbacks::clear,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "key", StorageCallbacks::key)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "length",
+                StorageCallbacks::length,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeStorage").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(STORAGE_PRELUDE).map(|_| ())
+    }
+
+    /// Expose constraint validation, reporting into `binding`'s queue. Bind
+    /// after the document, whose prelude defines `Element`.
+    pub fn bind_form_api(&mut self, binding: FormBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "state", FormCallbacks::state)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setCustomValidity",
+                FormCallbacks::set_custom_validity,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "invalidControls",
+                FormCallbacks::invalid_controls,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "report", FormCallbacks::report)
+                .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeForms").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(FORM_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `execCommand` over `binding`'s editing session. Bind after the
+    /// document, whose prelude defines `document` and `__vbeFireEvent`.
+    pub fn bind_editing_api(&mut self, binding: EditingBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "host", EditingCallbacks::host)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "supported",
+                EditingCallbacks::supported,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "enabled",
+                EditingCallbacks::enabled,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "state",
+                EditingCallbacks::state,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "inputType",
+                EditingCallbacks::input_type,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "execute",
+                EditingCallbacks::execute,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "clipboardEntries",
+                EditingCallbacks::clipboard_entries,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "focus",
+                EditingCallbacks::focus,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "blur", EditingCallbacks::blur)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "focused",
+                EditingCallbacks::focused,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeEditing").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(EDITING_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `DataTransfer` and dropped `File`s over `binding`'s drag.
+    /// Bind after the document, whose prelude defines `__vbeFireEvent`.
+    pub fn bind_drag_api(&mut self, binding: DragBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "getData",
+                DragCallbacks::get_data,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setData",
+                DragCallbacks::set_data,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "clearData",
+                DragCallbacks::clear_data,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "types", DragCallbacks::types)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "files", DragCallbacks::files)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "effectAllowed",
+                DragCallbacks::effect_allowed,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setEffectAllowed",
+                DragCallbacks::set_effect_allowed,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "dropEffect",
+                DragCallbacks::drop_effect,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setDropEffect",
+                DragCallbacks::set_drop_effect,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setDragImage",
+                DragCallbacks::set_drag_image,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "readFile",
+                DragCallbacks::read_file,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeDrag").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(DRAG_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `Blob`, object URLs and `navigator.clipboard` over `binding`.
+    /// Bind after the network API, whose `Response` gains `blob()`.
+    pub fn bind_clipboard_api(&mut self, binding: ClipboardBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "read",
+                ClipboardCallbacks::read,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "write",
+                ClipboardCallbacks::write,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "createObjectURL",
+                ClipboardCallbacks::create_object_url,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "revokeObjectURL",
+                ClipboardCallbacks::revoke_object_url,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeClipboard").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(CLIPBOARD_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `<video>`/`<audio>` state and methods over `binding`'s media
+    /// elements. Bind after the document, whose prelude defines `Element`.
+    pub fn bind_media_api(&mut self, binding: MediaBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "state", MediaCallbacks::state)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setCurrentTime",
+                MediaCallbacks::set_current_time,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "play", MediaCallbacks::play)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "pause", MediaCallbacks::pause)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setMuted",
+                MediaCallbacks::set_muted,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setVolume",
+                MediaCallbacks::set_volume,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "load", MediaCallbacks::load)
+                .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeMedia").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(MEDIA_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `performance.timing` over `binding`'s timings.
+    pub fn bind_navigation_timing_api(
+        &mut self,
+        binding: NavigationTimingBinding,
+    ) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "timing",
+                NavigationTimingCallbacks::timing,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name = v8::String::new(scope, "__vbeNavigationTiming")
+                .ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(NAVIGATION_TIMING_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `document.elementFromPoint()` and `elementsFromPoint()` over
+    /// `binding`'s regions. Bind after the document, whose prelude defines
+    /// `document`.
+    pub fn bind_hit_test_api(&mut self, binding: HitTestBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "elementsFromPoint",
+                HitTestCallbacks::elements_from_point,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeHitTest").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(HIT_TEST_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `window.print()` over `binding`'s request. Bind after the
+    /// document, whose prelude defines `dispatchEvent`.
+    pub fn bind_print_api(&mut self, binding: PrintBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "pending",
+                PrintCallbacks::pending,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "request",
+                PrintCallbacks::request,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbePrint").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(PRINT_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `window.scrollX`, `scrollY`, `scrollTo()` and `scrollBy()`
+    /// over `binding`'s position.
+    pub fn bind_scroll_api(&mut self, binding: ScrollBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "position",
+                ScrollCallbacks::position,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "scrollTo",
+                ScrollCallbacks::scroll_to,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeScroll").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(SCROLL_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `document.write()` and `writeln()` over `binding`. Bind after
+    /// the DOM API, whose `document` they are added to.
+    pub fn bind_document_write_api(
+        &mut self,
+        binding: DocumentWriteBinding,
+    ) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "write",
+                DocumentWriteCallbacks::write,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeDocumentWrite").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(DOCUMENT_WRITE_PRELUDE).map(|_| ())
+    }
+
+    /// Let the page prompt for `binding`'s install offer. Bind after the
+    /// document, whose prelude defines `dispatchEvent`.
+    pub fn bind_install_api(&mut self, binding: InstallBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "prompt",
+                InstallCallbacks::prompt,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeInstall").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(INSTALL_PRELUDE).map(|_| ())
+    }
+
+    /// Expose `speechSynthesis` and `SpeechSynthesisUtterance` over
+    /// `binding`'s queue.
+    pub fn bind_speech_api(&mut self, binding: SpeechBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
+
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "speak", SpeechCallbacks::speak)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "cancel",
+                SpeechCallbacks::cancel,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "pause", SpeechCallbacks::pause)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "resume",
+                SpeechCallbacks::resume,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "status",
+                SpeechCallbacks::status,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "voices",
+                SpeechCallbacks::voices,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeSpeech").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
+
+        self.execute(SPEECH_PRELUDE).map(|_| ())
+    }
+
+    /// Fire speech events at their utterances, each event being the JSON
+    /// form of a [`SpeechEvent`](crate::core::speech::SpeechEvent). Runs as
+    /// one task followed by a microtask checkpoint.
+    pub fn deliver_speech_events(&mut self, events: &serde_json::Value) -> Result<(), V8Error> {
+        self.execute(&format!(
+            "globalThis.__vbeSpeechEvents && __vbeSpeechEvents({})",
+            events
+        ))
+        .map(|_| ())
+    }
 
-        INIT_V8.call_once(|| match Self::initialize_v8() {
-            Ok(_) => {
-                if let Ok(mut state) = V8_STATE.lock() {
-                    *state = V8State::Initialized;
-                } else {
-                    init_result = Err(V8Error::InitializationFailed);
-                }
-            }
-            Err(e) => {
-                init_result = Err(e);
-            }
-        });
+    /// Expose `FontFace` and `document.fonts` over `binding`'s face set.
+    /// Bind after the document, and after starting the loads of CSS-declared
+    /// faces so that `document.fonts.ready` waits for them.
+    pub fn bind_font_api(&mut self, binding: FontBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
 
-        // Check if initialization was successful
-        if init_result.is_ok() {
-            let state = V8_STATE.lock().map_err(|_| V8Error::InitializationFailed)?;
-            if *state != V8State::Initialized {
-                return Err(V8Error::InitializationFailed);
-            }
-        }
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "addFace",
+                FontCallbacks::add_face,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "load", FontCallbacks::load)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "describe",
+                FontCallbacks::describe,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "members",
+                FontCallbacks::members,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "setMember",
+                FontCallbacks::set_member,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "isLoading",
+                FontCallbacks::is_loading,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
 
-        init_result
-    }
+            let native_name =
+                v8::String::new(scope, "__vbeFonts").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
 
-    fn initialize_v8() -> Result<(), V8Error> {
-        let platform = v8::new_default_platform(0, false).make_shared();
-        v8::V8::initialize_platform(platform);
-        v8::V8::initialize();
-        Ok(())
+        self.execute(FONT_PRELUDE).map(|_| ())
     }
 
-    pub fn dispose_v8() {
-        DISPOSE_V8.call_once(|| {
-            if let Ok(mut state) = V8_STATE.lock() {
-                if *state == V8State::Initialized {
-                    unsafe {
-                        v8::V8::dispose();
-                    }
-                    v8::V8::dispose_platform();
-                    *state = V8State::Disposed;
-                }
-            }
-        });
+    /// Tell `document.fonts` about loads that settled, each event being the
+    /// JSON form of a [`FontLoadEvent`](crate::core::fonts::FontLoadEvent).
+    /// Runs as one task followed by a microtask checkpoint.
+    pub fn deliver_font_events(&mut self, events: &serde_json::Value) -> Result<(), V8Error> {
+        self.execute(&format!(
+            "globalThis.__vbeFontSet && __vbeFontSet.settle({})",
+            events
+        ))
+        .map(|_| ())
     }
 
-    pub fn is_v8_initialized() -> bool {
-        V8_STATE
-            .lock()
-            .map(|state| *state == V8State::Initialized)
-            .unwrap_or(false)
+    /// Settle script fetches, each result being the JSON form of a
+    /// [`SettledFetch`](crate::core::network::SettledFetch). Runs as one
+    /// task followed by a microtask checkpoint.
+    pub fn deliver_fetches(&mut self, results: &serde_json::Value) -> Result<(), V8Error> {
+        self.execute(&format!(
+            "globalThis.__vbeFetchSettle && __vbeFetchSettle({})",
+            results
+        ))
+        .map(|_| ())
     }
 
-    fn with_context_scope<T, F>(&mut self, f: F) -> T
-    where
-        F: FnOnce(&mut v8::ContextScope<v8::HandleScope>) -> T,
-    {
-        let scope = &mut v8::HandleScope::new(&mut self.isolate);
-        let context = v8::Local::new(scope, &self.context);
-        let scope = &mut v8::ContextScope::new(scope, context);
-        f(scope)
-    }
+    /// Expose `postMessage`, `window.opener` and `message` events over
+    /// `binding`'s mailbox. Bind once per context, after the event loop.
+    pub fn bind_agent_api(&mut self, binding: AgentBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
 
-    pub fn execute(&mut self, source: &str) -> Result<serde_json::Value, V8Error> {
         self.with_context_scope(|scope| {
-            let code = v8::String::new(scope, source).ok_or(V8Error::InvalidSource)?;
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "self", AgentCallbacks::self_id)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "postMessage",
+                AgentCallbacks::post_message,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
 
-            let mut try_catch = v8::TryCatch::new(scope);
-            let script = v8::Script::compile(&mut try_catch, code, None)
-                .ok_or_else(|| Self::extract_exception(&mut try_catch))?;
+            let native_name =
+                v8::String::new(scope, "__vbeAgent").ok_or(V8Error::InvalidFunctionName)?;
+            let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
 
-            let result = script
-                .run(&mut try_catch)
-                .ok_or_else(|| Self::extract_exception(&mut try_catch))?;
+        self.execute(AGENT_PRELUDE).map(|_| ())
+    }
 
-            Self::value_to_json(&mut try_catch, result)
-        })
+    /// Point `window.opener` at agent `opener`.
+    pub fn set_opener(&mut self, opener: u64) -> Result<(), V8Error> {
+        self.execute(&format!(
+            "globalThis.__vbeSetOpener && __vbeSetOpener({})",
+            serde_json::Value::String(opener.to_string())
+        ))
+        .map(|_| ())
     }
 
-    fn extract_exception(try_catch: &mut TryCatch<HandleScope>) -> V8Error {
-        if let Some(exception) = try_catch.exception() {
-            if let Some(message) = try_catch.message() {
-                let msg_str = message.get(try_catch);
-                return V8Error::ExecutionError(msg_str.to_rust_string_lossy(try_catch));
-            }
-            if let Some(exception_str) = exception.to_string(try_catch) {
-                return V8Error::ExecutionError(exception_str.to_rust_string_lossy(try_catch));
-            }
-        }
-        V8Error::ExecutionError("Unknown execution error".to_string())
+    /// Fire a `message` event carrying `data`, a JSON clone, from agent
+    /// `source` of `origin`. Runs as one task followed by a microtask
+    /// checkpoint.
+    pub fn deliver_message(
+        &mut self,
+        source: u64,
+        origin: &str,
+        data: &str,
+    ) -> Result<(), V8Error> {
+        self.execute(&format!(
+            "globalThis.__vbeDeliverMessage && __vbeDeliverMessage({}, {}, {})",
+            serde_json::Value::String(source.to_string()),
+            serde_json::Value::String(origin.to_string()),
+            serde_json::Value::String(data.to_string())
+        ))
+        .map(|_| ())
     }
 
-    fn value_to_json(
-        scope: &mut HandleScope,
-        value: Local<v8::Value>,
-    ) -> Result<serde_json::Value, V8Error> {
-        if value.is_null() || value.is_undefined() {
-            Ok(serde_json::Value::Null)
-        } else if value.is_boolean() {
-            Ok(serde_json::Value::Bool(value.boolean_value(scope)))
-        } else if value.is_number() {
-            let num = value.number_value(scope).unwrap_or(0.0);
-            Ok(serde_json::Value::Number(
-                serde_json::Number::from_f64(num).unwrap_or_else(|| serde_json::Number::from(0)),
-            ))
-        } else if value.is_string() {
-            let string = value.to_string(scope).unwrap();
-            Ok(serde_json::Value::String(
-                string.to_rust_string_lossy(scope),
-            ))
-        } else if value.is_array() {
-            let array = v8::Local::<v8::Array>::try_from(value).unwrap();
-            let mut result = Vec::with_capacity(array.length() as usize);
-            for i in 0..array.length() {
-                if let Some(element) = array.get_index(scope, i) {
-                    result.push(Self::value_to_json(scope, element)?);
+    /// Remove the globals named in `disabled` from the current context and
+    /// put back those removed before that `disabled` no longer names. Run
+    /// after binding a document's APIs, which may define them again.
+    pub fn gate_globals(&mut self, disabled: &[&str]) -> Result<(), V8Error> {
+        let mut stash = self
+            .isolate
+            .remove_slot::<GatedGlobals>()
+            .unwrap_or_default();
+        let gated = self.with_context_scope(|scope| {
+            let global = scope.get_current_context().global(scope);
+            let restored: Vec<String> = stash
+                .0
+                .keys()
+                .filter(|name| !disabled.contains(&name.as_str()))
+                .cloned()
+                .collect();
+            for name in restored {
+                let value = stash.0.remove(&name).ok_or(V8Error::BindingFailed)?;
+                let key = v8::String::new(scope, &name).ok_or(V8Error::InvalidFunctionName)?;
+                if global.has_own_property(scope, key.into()) != Some(true) {
+                    let value = v8::Local::new(scope, &value);
+                    global
+                        .define_own_property(
+                            scope,
+                            key.into(),
+                            value,
+                            v8::PropertyAttribute::DONT_ENUM,
+                        )
+                        .ok_or(V8Error::BindingFailed)?;
                 }
             }
-            Ok(serde_json::Value::Array(result))
-        } else if value.is_object() {
-            let object = v8::Local::<v8::Object>::try_from(value).unwrap();
-            let mut result = serde_json::Map::new();
-            if let Some(property_names) =
-                object.get_own_property_names(scope, v8::GetPropertyNamesArgs::default())
-            {
-                for i in 0..property_names.length() {
-                    if let Some(key) = property_names.get_index(scope, i) {
-                        if let Some(key_str) = key.to_string(scope) {
-                            let key_string = key_str.to_rust_string_lossy(scope);
-                            if let Some(prop_value) = object.get(scope, key) {
-                                result.insert(key_string, Self::value_to_json(scope, prop_value)?);
-                            }
-                        }
-                    }
+            for &name in disabled {
+                let key = v8::String::new(scope, name).ok_or(V8Error::InvalidFunctionName)?;
+                if global.has_own_property(scope, key.into()) != Some(true) {
+                    continue;
+                }
+                if let Some(value) = global.get(scope, key.into()) {
+                    let value = v8::Global::new(scope, value);
+                    stash.0.insert(name.to_string(), value);
                 }
+                global
+                    .delete(scope, key.into())
+                    .ok_or(V8Error::BindingFailed)?;
             }
-            Ok(serde_json::Value::Object(result))
-        } else {
-            Ok(serde_json::Value::Null)
-        }
+            Ok(())
+        });
+        self.isolate.set_slot(stash);
+        gated
     }
 
-    pub fn bind_function(&mut self, name: &str) -> Result<(), V8Error> {
-        self.with_context_scope(|scope| {
-            let function_name = v8::String::new(scope, name).ok_or(V8Error::InvalidFunctionName)?;
+    /// Gate `WebAssembly` on `binding`'s policy, or remove it when the
+    /// policy disables it. Bind once per context after the event loop, and
+    /// again whenever the policy changes.
+    pub fn bind_wasm_api(&mut self, binding: WasmBinding) -> Result<(), V8Error> {
+        self.isolate.set_slot(binding);
 
-            let function_template = v8::FunctionTemplate::new(
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
                 scope,
-                |scope: &mut HandleScope,
-                 _args: v8::FunctionCallbackArguments,
-                 mut rv: v8::ReturnValue| {
-                    let undefined = v8::undefined(scope);
-                    rv.set(undefined.into());
-                },
-            );
-
-            let function = function_template
-                .get_function(scope)
-                .ok_or(V8Error::FunctionCreationFailed)?;
+                native,
+                "enabled",
+                WasmCallbacks::enabled,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "check", WasmCallbacks::check)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "memoryLimit",
+                WasmCallbacks::memory_limit,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(scope, native, "record", WasmCallbacks::record)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "reportMemory",
+                WasmCallbacks::report_memory,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
 
+            let native_name =
+                v8::String::new(scope, "__vbeWasm").ok_or(V8Error::InvalidFunctionName)?;
             let global = scope.get_current_context().global(scope);
             global
-                .set(scope, function_name.into(), function.into())
+                .set(scope, native_name.into(), native.into())
                 .ok_or(V8Error::BindingFailed)?;
-
             Ok(())
-        })
+        })?;
+
+        self.execute(WASM_PRELUDE).map(|_| ())
     }
 
-    pub fn bind_console_log(&mut self) -> Result<(), V8Error> {
-        self.with_context_scope(|scope| {
-            let console_obj = v8::Object::new(scope);
-            let console_str =
-                v8::String::new(scope, "console").ok_or(V8Error::InvalidFunctionName)?;
+    /// Expose `setTimeout`/`setInterval`, `requestAnimationFrame`,
+    /// `queueMicrotask` and `performance.now`. Nothing runs until the engine
+    /// calls [`run_timers`](Self::run_timers) and
+    /// [`run_animation_frames`](Self::run_animation_frames).
+    pub fn bind_event_loop(&mut self) -> Result<(), V8Error> {
+        let clock = match &self.virtual_time {
+            Some(time) => EventLoopClock::Virtual {
+                origin_ms: time.clock.now_ms(),
+                clock: time.clock.clone(),
+            },
+            None => EventLoopClock::System(Instant::now()),
+        };
+        self.isolate.set_slot(clock);
+        if self.isolate.get_slot::<ScriptTasks>().is_none() {
+            self.isolate.set_slot(ScriptTasks::default());
+        }
 
-            let log_template = v8::FunctionTemplate::new(
+        self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(scope, native, "now", EventLoopCallbacks::now)
+                .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
                 scope,
-                |scope: &mut HandleScope,
-                 args: v8::FunctionCallbackArguments,
-                 mut rv: v8::ReturnValue| {
-                    for i in 0..args.length() {
-                        let arg = args.get(i);
-                        if let Some(str_val) = arg.to_string(scope) {
-                            let rust_string = str_val.to_rust_string_lossy(scope);
-                            println!("{}", rust_string);
-                        }
-                    }
-                    let undefined = v8::undefined(scope);
-                    rv.set(undefined.into());
-                },
-            );
-
-            let log_function = log_template
-                .get_function(scope)
-                .ok_or(V8Error::FunctionCreationFailed)?;
-
-            let log_str = v8::String::new(scope, "log").ok_or(V8Error::InvalidFunctionName)?;
-
-            console_obj
-                .set(scope, log_str.into(), log_function.into())
-                .ok_or(V8Error::BindingFailed)?;
+                native,
+                "enqueueMicrotask",
+                EventLoopCallbacks::enqueue_microtask,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "runMicrotasks",
+                EventLoopCallbacks::run_microtasks,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "scriptUrl",
+                EventLoopCallbacks::script_url,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "beginTask",
+                EventLoopCallbacks::begin_task,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "endTask",
+                EventLoopCallbacks::end_task,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
 
+            let native_name =
+                v8::String::new(scope, "__vbeLoop").ok_or(V8Error::InvalidFunctionName)?;
             let global = scope.get_current_context().global(scope);
             global
-                .set(scope, console_str.into(), console_obj.into())
+                .set(scope, native_name.into(), native.into())
                 .ok_or(V8Error::BindingFailed)?;
-
             Ok(())
-        })
+        })?;
+
+        self.execute(EVENT_LOOP_PRELUDE)?;
+        match self.virtual_time.as_ref().map(|time| time.random_seed) {
+            Some(seed) => self.bind_virtual_time(seed),
+            None => Ok(()),
+        }
     }
 
-    pub fn bind_arithmetic_functions(&mut self) -> Result<(), V8Error> {
+    /// Swap `Date` and `Math.random` for ones run by the context's virtual
+    /// clock and `seed`.
+    fn bind_virtual_time(&mut self, seed: u64) -> Result<(), V8Error> {
         self.with_context_scope(|scope| {
+            let native = v8::Object::new(scope);
+            V8CallbackHelper::bind_method_to_object(
+                scope,
+                native,
+                "wallNow",
+                EventLoopCallbacks::wall_now,
+            )
+            .map_err(|_| V8Error::BindingFailed)?;
+
+            let native_name =
+                v8::String::new(scope, "__vbeTime").ok_or(V8Error::InvalidFunctionName)?;
             let global = scope.get_current_context().global(scope);
+            global
+                .set(scope, native_name.into(), native.into())
+                .ok_or(V8Error::BindingFailed)?;
+            Ok(())
+        })?;
 
-            let add_template = v8::FunctionTemplate::new(
-                scope,
-                |scope: &mut HandleScope,
-                 args: v8::FunctionCallbackArguments,
-                 mut rv: v8::ReturnValue| {
-                    if args.length() >= 2 {
-                        let a = args.get(0).number_value(scope).unwrap_or(0.0);
-                        let b = args.get(1).number_value(scope).unwrap_or(0.0);
-                        let result = v8::Number::new(scope, a + b);
-                        rv.set(result.into());
-                    } else {
-                        let undefined = v8::undefined(scope);
-                        rv.set(undefined.into());
-                    }
-                },
-            );
+        let prelude = VIRTUAL_TIME_PRELUDE
+            .replace("SEED_LOW", &(seed as u32).to_string())
+            .replace("SEED_HIGH", &((seed >> 32) as u32).to_string());
+        self.execute(&prelude).map(|_| ())
+    }
 
-            let multiply_template = v8::FunctionTemplate::new(
-                scope,
-                |scope: &mut HandleScope,
-                 args: v8::FunctionCallbackArguments,
-                 mut rv: v8::ReturnValue| {
-                    if args.length() >= 2 {
-                        let a = args.get(0).number_value(scope).unwrap_or(0.0);
-                        let b = args.get(1).number_value(scope).unwrap_or(0.0);
-                        let result = v8::Number::new(scope, a * b);
-                        rv.set(result.into());
-                    } else {
-                        let undefined = v8::undefined(scope);
-                        rv.set(undefined.into());
-                    }
-                },
-            );
+    /// Run every timer that is due, each as its own task followed by a
+    /// microtask checkpoint. Returns how many ran.
+    pub fn run_timers(&mut self) -> Result<usize, V8Error> {
+        self.run_event_loop_step("runTimers()")
+    }
 
-            let add_function = add_template
-                .get_function(scope)
-                .ok_or(V8Error::FunctionCreationFailed)?;
-            let multiply_function = multiply_template
-                .get_function(scope)
-                .ok_or(V8Error::FunctionCreationFailed)?;
+    /// Run the animation frame callbacks registered before this frame, with a
+    /// microtask checkpoint after each. Returns how many ran.
+    pub fn run_animation_frames(&mut self) -> Result<usize, V8Error> {
+        self.run_event_loop_step("runAnimationFrames()")
+    }
 
-            let add_name = v8::String::new(scope, "add").ok_or(V8Error::InvalidFunctionName)?;
-            let multiply_name =
-                v8::String::new(scope, "multiply").ok_or(V8Error::InvalidFunctionName)?;
+    /// Note that the script at `url` is about to run, or that none is when
+    /// `None`; the callbacks it schedules are put down to it.
+    pub fn set_current_script(&mut self, url: Option<&str>) {
+        if let Some(tasks) = self.isolate.get_slot::<ScriptTasks>() {
+            tasks.set_current(url);
+        }
+    }
 
-            global
-                .set(scope, add_name.into(), add_function.into())
-                .ok_or(V8Error::BindingFailed)?;
-            global
-                .set(scope, multiply_name.into(), multiply_function.into())
-                .ok_or(V8Error::BindingFailed)?;
+    /// The longest timer or animation frame task since the last call.
+    pub fn take_longest_task(&mut self) -> Option<LongTask> {
+        self.isolate
+            .get_slot::<ScriptTasks>()
+            .and_then(ScriptTasks::take_longest)
+    }
 
-            Ok(())
-        })
+    fn run_event_loop_step(&mut self, step: &str) -> Result<usize, V8Error> {
+        let ran = self.execute(&format!(
+            "globalThis.__vbeEventLoop ? __vbeEventLoop.{} : 0",
+            step
+        ))?;
+        Ok(ran.as_f64().unwrap_or(0.0) as usize)
     }
 
     pub fn create_object(&mut self) -> Result<v8::Global<v8::Object>, V8Error> {
@@ -384,7 +4221,15 @@ unsafe impl Sync for V8Runtime {}
 
 impl Drop for V8Runtime {
     fn drop(&mut self) {
-        self.force_gc();
+        // Parked contexts hold handles into the isolate; release them first.
+        self.parked.clear();
+        self.isolated_worlds.clear();
+        if EnteredIsolates::is_current(self.isolate_id) {
+            self.force_gc();
+        }
+        // SAFETY: the field is never used again.
+        let isolate = unsafe { ManuallyDrop::take(&mut self.isolate) };
+        EnteredIsolates::dispose(self.isolate_id, isolate);
         // Note: We don't dispose V8 here as it should only be disposed once per process
         // V8 disposal should happen at application shutdown via V8Runtime::dispose_v8()
     }
@@ -408,4 +4253,10 @@ pub enum V8Error {
     GarbageCollectionFailed,
     #[error("V8 initialization failed")]
     InitializationFailed,
+    #[error("No context {0}")]
+    UnknownContext(u64),
+    #[error("Context {0} already exists")]
+    ContextExists(u64),
+    #[error("Context {0} is the current context")]
+    ContextInUse(u64),
 }
@@ -0,0 +1,177 @@
+//! WebAssembly policy: whether a context may compile modules and how much
+//! linear memory they may hold.
+//!
+//! The `WebAssembly` global is V8's own; the engine wraps its entry points
+//! in JS so every compile is checked here first. A document's CSP must allow
+//! `'wasm-unsafe-eval'` or `'unsafe-eval'` in `script-src` (or
+//! `default-src`). Modules larger than an agent's share of the JS memory
+//! budget are refused, and so is instantiating a module, constructing a
+//! `WebAssembly.Memory` or growing one when the agent's live wasm memory
+//! would exceed that share. `memory.grow` executed by wasm code itself is
+//! bounded only by the module's declared maximum.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+/// Bytes in one page of wasm linear memory.
+pub const WASM_PAGE_BYTES: u64 = 64 * 1024;
+
+const SECTION_MEMORY: u8 = 5;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum WasmError {
+    #[error("Malformed WebAssembly module: {0}")]
+    Malformed(&'static str),
+}
+
+/// What a context may do with WebAssembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmPolicy {
+    /// Off removes the `WebAssembly` global altogether.
+    pub enabled: bool,
+    /// Whether the document's CSP lets it compile code.
+    pub allow_compile: bool,
+    pub max_module_bytes: u64,
+    pub max_memory_bytes: u64,
+}
+
+impl WasmPolicy {
+    /// Check `module` before it is compiled. Gives the linear memory it
+    /// declares, or why it is refused as a JS error name and message.
+    pub fn check_module(&self, module: &[u8]) -> Result<u64, (&'static str, String)> {
+        if !self.allow_compile {
+            return Err((
+                "CompileError",
+                "Refused to compile WebAssembly: the Content-Security-Policy allows neither \
+                 'wasm-unsafe-eval' nor 'unsafe-eval'"
+                    .to_string(),
+            ));
+        }
+        if module.len() as u64 > self.max_module_bytes {
+            return Err((
+                "RangeError",
+                format!(
+                    "WebAssembly module of {} bytes is over the {} byte limit",
+                    module.len(),
+                    self.max_module_bytes
+                ),
+            ));
+        }
+        declared_memory_bytes(module).map_err(|e| ("CompileError", e.to_string()))
+    }
+}
+
+/// Linear memory a module allocates when instantiated: the initial size of
+/// every memory it defines. Imported memories belong to whoever made them.
+pub fn declared_memory_bytes(module: &[u8]) -> Result<u64, WasmError> {
+    if module.len() < 8 || &module[..4] != b"\0asm" {
+        return Err(WasmError::Malformed("missing the \\0asm header"));
+    }
+    let mut reader = Reader {
+        bytes: module,
+        offset: 8,
+    };
+    let mut total = 0u64;
+    while !reader.is_done() {
+        let id = reader.byte()?;
+        let size = reader.leb()? as usize;
+        let end = reader
+            .offset
+            .checked_add(size)
+            .filter(|end| *end <= module.len())
+            .ok_or(WasmError::Malformed("section runs past the end"))?;
+        if id == SECTION_MEMORY {
+            let count = reader.leb()?;
+            for _ in 0..count {
+                let flags = reader.byte()?;
+                let initial = reader.leb()?;
+                if flags & 0x01 != 0 {
+                    reader.leb()?;
+                }
+                total = total.saturating_add(initial.saturating_mul(WASM_PAGE_BYTES));
+            }
+        }
+        reader.offset = end;
+    }
+    Ok(total)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn is_done(&self) -> bool {
+        self.offset >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, WasmError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(WasmError::Malformed("unexpected end"))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    /// Unsigned LEB128, up to 64 bits.
+    fn leb(&mut self) -> Result<u64, WasmError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(WasmError::Malformed("integer is too long"))
+    }
+}
+
+/// Compile and instantiate time across the runtime, and the live linear
+/// memory of each agent as its context last reported it.
+#[derive(Debug, Default)]
+pub struct WasmStats {
+    compile_time_us: AtomicU64,
+    instantiate_time_us: AtomicU64,
+    memory_bytes: Mutex<HashMap<u64, u64>>,
+}
+
+impl WasmStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_compile(&self, micros: u64) {
+        self.compile_time_us.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn record_instantiate(&self, micros: u64) {
+        self.instantiate_time_us
+            .fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn set_memory(&self, agent: u64, bytes: u64) {
+        self.memory_bytes.lock().insert(agent, bytes);
+    }
+
+    /// Forget an agent that was torn down.
+    pub fn forget(&self, agent: u64) {
+        self.memory_bytes.lock().remove(&agent);
+    }
+
+    pub fn compile_time_us(&self) -> u64 {
+        self.compile_time_us.load(Ordering::Relaxed)
+    }
+
+    pub fn instantiate_time_us(&self) -> u64 {
+        self.instantiate_time_us.load(Ordering::Relaxed)
+    }
+
+    pub fn memory_bytes(&self) -> u64 {
+        self.memory_bytes.lock().values().sum()
+    }
+}
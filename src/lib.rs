@@ -8,42 +8,141 @@
 //!    JIT/FFI/raw-pointer heavy subsystems off of cross-thread moves.
 
 use base64::Engine;
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 use serde::{Deserialize, Serialize};
 use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 // For secure data: URL handling
 use percent_encoding::percent_decode_str;
 
 // For panic-to-Result guard on async futures
-use futures::FutureExt;
+use futures::{FutureExt, StreamExt};
 
 pub mod core;
 pub mod js_engine;
 pub mod pwa;
 pub mod renderer;
+#[cfg(feature = "replay")]
+pub mod replay;
 pub mod sandbox;
+#[cfg(feature = "webdriver")]
+pub mod webdriver;
 
 use crate::core::{
-    css::{Color, ComputedStyles, ComputedValue, StyleEngine},
-    dom::{document::NodeType as DomNodeType, Document, NodeId},
-    events::EventSystem,
-    layout::LayoutEngine,
-    network::{NetworkError, NetworkManager},
+    accelerators::{actions, AcceleratorError, AcceleratorTable, Keystroke, Modifiers},
+    archive::{self, ArchiveError, ArchiveFormat, ArchiveOptions, PageArchive, PageArchiveConfig},
+    audio::AudioSession,
+    clipboard::{self, Clipboard, ClipboardBitmap, ClipboardData, ClipboardError, ClipboardItem},
+    clock::VirtualTime,
+    content_limits::{ContentLimits, LimitBreach, LimitBreaches},
+    css::{
+        Color, ComputedStyles, ComputedValue, FoucControl, LinkedStylesheets, MediaType,
+        StyleEngine, StylesheetLoader, StylesheetLoadingConfig,
+    },
+    devtools::{self, overlay, Command as DevtoolsCommand, Inspector},
+    document_write::{DocumentWrites, MAX_WRITE_DEPTH},
+    dom::{
+        document::NodeType as DomNodeType, view_source::build_view_source, CompactionReport,
+        Document, NodeId, SerializeOptions, DEFAULT_COMPACTION_RATIO,
+    },
+    drag::{
+        self, DataTransfer, DataTransferMode, DragAndDrop, DragImage, DragSession, DropEffect,
+        DroppedFile,
+    },
+    editing::{self, CaretMovement, EditingEvent, EditingSession},
+    event_log::{
+        EventKindMask, EventLog, LoggedEvent, NavigationPhase, TimestampedEvent,
+        DEFAULT_EVENT_LOG_CAPACITY,
+    },
+    features::{DocumentFeatures, FeatureOverrides, FeatureRegistry},
+    find::{
+        text_fragment::{self, TextDirective, TextHighlight},
+        PageText,
+    },
+    fonts::{FontFaceSet, FontLoader, FontMetrics, FontRequest, ShapedRun, ShapingObserver},
+    forms::ValidationReports,
+    frame_budget::{FrameBudgetConfig, FramePhase, FrameStatistics, FrameWatchdog},
+    layout::{Containment, LayoutBox, LayoutEngine, ViewportScroll},
+    live_regions::{Announcement, AnnouncementHandler, LiveRegionTracker, Politeness},
+    media::{MediaConfig, MediaElements, MediaKind, MediaLoader, PlaybackHandler, PlaybackRequest},
+    metadata::{FaviconLoader, PageMetadata, PageMetadataTracker, DEFAULT_FAVICON_SIZE},
+    navigation_timing::{
+        NavigationMark, NavigationTiming, NavigationTimings, ResponseStartObserver, ScriptSource,
+        DEFAULT_NAVIGATION_TIMING_HISTORY,
+    },
+    network::{
+        image_source, AuthChallenge, AuthHandler, Blob, BodyObserver, ContentSecurityPolicy,
+        Credentials, Destination, DiskCacheConfig, FetchRequest, NetworkError, NetworkManager,
+        PolitenessConfig, Preload, Preloader, Priority, RequestInitiator, ResourceLogEntry,
+        RetryConfig, ScriptFetches, SecurityState, TlsConfig, DEFAULT_MAX_SCRIPT_FETCHES,
+        DEFAULT_MAX_SPECULATIVE_FETCHES,
+    },
+    permissions::{Permission, PermissionState, PermissionStore},
+    prerender::{PrerenderConfig, PrerenderDiscardReason, PrerenderHandle, PrerenderSet},
+    print::{
+        pdf::{self, PdfPage},
+        PageMargins, PageRule, PageSize, PrintDecision, PrintError, PrintOptions, PrintRequests,
+    },
+    spatial_navigation::{self, SpatialNavigation},
+    speech::{NullTtsBackend, SpeechSynthesis, TtsBackend},
+    storage::{StorageArea, StorageConfig, StorageKey, WebStorage},
+    user_activation::UserActivation,
+    user_content::{
+        RunAt, ScriptWorld, UserContent, UserContentError, UserContentId, UserScriptOptions,
+        UserStyleOptions,
+    },
 };
+use crate::js_engine::agents::AgentMetrics;
+use crate::js_engine::code_cache::CodeCacheConfig;
 use crate::js_engine::{JSError, JSRuntime};
-use crate::pwa::PwaError;
+use crate::pwa::install::{InstallOffer, InstallPrompts};
+use crate::pwa::push::PushSubscription;
+use crate::pwa::service_worker::{ClientId, Notification, ServiceWorkerRegistration};
 use crate::pwa::PwaRuntime as PwaManager;
+use crate::pwa::{is_potentially_trustworthy, FetchResponse, InstalledApp, PwaError};
+use crate::renderer::custom_paint::{
+    PaintSource, PaintSources, DEFAULT_PAINT_BUDGET, PAINT_FUNCTION,
+};
+use crate::renderer::image::animation::DEFAULT_ANIMATION_BUDGET_BYTES;
+use crate::renderer::image::{DecodedImage, ImageAnimations, ImageCache, LoadedImage};
+use crate::renderer::vulkan;
 use crate::renderer::{
-    ElementType, LayoutNode, LayoutTree, Rect, RenderError, Style, VulkanRenderer,
+    decoration, parse_color, BoxOutline, ClipChain, ClipRect, CornerRadii, DebugOverlayFlags,
+    DebugOverlaySettings, DrawQuad, EdgeWidths, ElementType, HitRegions, LayoutNode, LayoutTree,
+    Rect, RenderBackend, RenderError, Snapshot, Style, TextDecoration, TextShadow, VulkanRenderer,
 };
+use crate::sandbox::files::FileGrants;
 use crate::sandbox::{SandboxError, SandboxManager};
 
 /// Short alias to reduce trait-object verbosity in signatures/fields.
 type ErrorCallback = Arc<dyn Fn(&BrowserError) + Send + Sync>;
 
+/// A touch point that is down: the element it started on and where it is now.
+type TouchPoint = (NodeId, (f32, f32));
+
+/// Tab ids are unique within the process.
+static NEXT_TAB_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How often a devtools session sends notifications while the client is
+/// quiet, and checks for shutdown.
+const DEVTOOLS_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Average size of a DOM node with its attributes and text, for estimating
+/// a document's footprint from its node count.
+const ESTIMATED_DOM_NODE_BYTES: u64 = 256;
+
+/// Events a subscriber may fall behind by before it misses the oldest.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Share of `max_memory_mb` a window's device memory takes before it is
+/// warned about.
+pub const DEFAULT_GPU_MEMORY_WARNING_FRACTION: f64 = 0.5;
+
 #[derive(Error, Debug, Clone)]
 pub enum BrowserError {
     #[error("Renderer initialization failed: {0}")]
@@ -68,16 +167,40 @@ pub enum BrowserError {
     Platform(String),
     #[error("Security policy: {0}")]
     Security(String),
+    #[error("Feature disabled: {0}")]
+    FeatureDisabled(&'static str),
+    #[error("Disallowed by robots.txt: {0}")]
+    RobotsDisallowed(String),
+    #[error("Print error: {0}")]
+    Print(String),
+    #[error("Input error: {0}")]
+    Input(String),
+    #[error("User content error: {0}")]
+    UserContent(String),
+    #[error("Clipboard error: {0}")]
+    Clipboard(String),
+    #[error("Not prerenderable: {0}")]
+    NotPrerenderable(String),
+    #[error("Archive error: {0}")]
+    Archive(String),
+    #[error("Navigation cancelled: {0}")]
+    NavigationCancelled(String),
 }
 
 impl From<JSError> for BrowserError {
     fn from(e: JSError) -> Self {
-        BrowserError::JSEngine(e.to_string())
+        match e {
+            JSError::FeatureDisabled(feature) => BrowserError::FeatureDisabled(feature),
+            other => BrowserError::JSEngine(other.to_string()),
+        }
     }
 }
 impl From<NetworkError> for BrowserError {
     fn from(e: NetworkError) -> Self {
-        BrowserError::Network(e.to_string())
+        match e {
+            NetworkError::RobotsDisallowed(target) => BrowserError::RobotsDisallowed(target),
+            other => BrowserError::Network(other.to_string()),
+        }
     }
 }
 impl From<SandboxError> for BrowserError {
@@ -95,6 +218,31 @@ impl From<RenderError> for BrowserError {
         BrowserError::Render(e.to_string())
     }
 }
+impl From<AcceleratorError> for BrowserError {
+    fn from(e: AcceleratorError) -> Self {
+        BrowserError::Input(e.to_string())
+    }
+}
+impl From<UserContentError> for BrowserError {
+    fn from(e: UserContentError) -> Self {
+        BrowserError::UserContent(e.to_string())
+    }
+}
+impl From<ClipboardError> for BrowserError {
+    fn from(e: ClipboardError) -> Self {
+        BrowserError::Clipboard(e.to_string())
+    }
+}
+impl From<PrintError> for BrowserError {
+    fn from(e: PrintError) -> Self {
+        BrowserError::Print(e.to_string())
+    }
+}
+impl From<ArchiveError> for BrowserError {
+    fn from(e: ArchiveError) -> Self {
+        BrowserError::Archive(e.to_string())
+    }
+}
 
 pub type Result<T> = std::result::Result<T, BrowserError>;
 
@@ -111,12 +259,134 @@ pub struct BrowserConfig {
     pub enable_pwa: bool,
     pub enable_chrome_apis: bool,
     pub max_memory_mb: usize,
+    // Share of `max_memory_mb` a window's device memory may take before a
+    // `PerformanceWarning` is sent.
+    pub gpu_memory_warning_fraction: f64,
+    // JS heap the agents of an engine share; each gets an even share.
+    pub js_memory_budget_mb: usize,
+    // Off removes the `WebAssembly` global from every context.
+    pub enable_webassembly: bool,
     pub max_processes: usize,
     pub user_agent: String,
     pub viewport_width: u32,
     pub viewport_height: u32,
     pub enable_dev_tools: bool,
     pub enable_security_features: bool,
+
+    // Crawler politeness (rate limits, robots.txt); off by default.
+    pub politeness: PolitenessConfig,
+
+    // Certificate pins and hosts whose self-signed certificate is accepted.
+    pub tls: TlsConfig,
+
+    // Persistent HTTP cache tier; memory-only unless a directory is set.
+    pub disk_cache: DiskCacheConfig,
+
+    // Responses with a larger body fail as soon as they get past it.
+    pub max_response_size_mb: usize,
+
+    // How often and how patiently requests that failed in passing are sent
+    // again.
+    pub network_retry: RetryConfig,
+
+    // Script `fetch()` requests a page may have in flight at once.
+    pub max_script_fetches: usize,
+
+    // Compiled page scripts kept across sessions; off unless a directory is set.
+    pub code_cache: CodeCacheConfig,
+
+    // Entries kept by the event flight recorder; zero disables it.
+    pub event_log_capacity: usize,
+
+    // Navigations whose timing breakdown `get_navigation_timings` returns.
+    pub navigation_timing_history: usize,
+
+    // localStorage persistence and third-party storage partitioning.
+    pub storage: StorageConfig,
+
+    // Private browsing: storage, the HTTP cache and Cache Storage stay in
+    // memory and are dropped on shutdown; configured directories are never
+    // touched. Permission grants are session-only in every mode.
+    pub private_mode: bool,
+
+    // How long first paint waits on `<link rel="stylesheet">`s.
+    pub stylesheet_loading: StylesheetLoadingConfig,
+
+    // `<video>`/`<audio>` have no playback of their own; this lets an
+    // embedder take `play()` calls.
+    pub media: MediaConfig,
+
+    // Fetches the preload scanner may start per page while the markup is
+    // still downloading; zero turns it off.
+    pub max_speculative_fetches: usize,
+
+    // Repaint every node into new vertex and index buffers each frame
+    // instead of patching the last frame's; for checking the two agree.
+    pub full_frame_rebuild: bool,
+
+    // Debug overlays drawn over the page from the first frame;
+    // `set_debug_overlays` switches them at run time.
+    pub debug_overlays: DebugOverlayFlags,
+
+    // Device pixels per CSS pixel, as `-vk-paint()` sources are told.
+    pub device_pixel_ratio: f32,
+
+    // How long a frame waits on a paint source before drawing its last
+    // output instead.
+    pub paint_source_budget_ms: u64,
+
+    // Dead node-arena slots per live node past which freeing nodes compacts
+    // the arena; `None` leaves it to `BrowserEngine::compact_dom`.
+    pub dom_compaction_ratio: Option<f64>,
+
+    // Matches `(prefers-reduced-motion: reduce)` and holds animated images
+    // on their first frame.
+    pub prefers_reduced_motion: bool,
+
+    // Decoded frames the page's animated images may hold; past it, frames
+    // of animations out of view are dropped until they come back.
+    pub animated_image_budget_bytes: usize,
+
+    // Scroll to and highlight the passage a `#:~:text=` link names. Off,
+    // the directive stays in the URL as a plain fragment.
+    pub enable_text_fragments: bool,
+
+    // Send `<a ping>` requests as links are followed. A denied
+    // `Permission::HyperlinkAuditing` turns them off for one origin.
+    pub enable_hyperlink_auditing: bool,
+
+    // Arrow keys move focus to the element that lies that way on screen,
+    // Enter clicks it, and it always shows a focus ring; for D-pads and
+    // remote controls. `set_spatial_navigation` switches it at run time.
+    pub enable_spatial_navigation: bool,
+
+    // Bounds on DOM depth and size, text and attribute lengths, stylesheet
+    // rules and selector complexity. Content past them is flattened, cut
+    // short or dropped, and reported as a `PerformanceWarning`.
+    pub content_limits: ContentLimits,
+
+    // How many pages a tab may prerender, and the memory they may take.
+    pub prerender: PrerenderConfig,
+
+    // The frame rate `tick` is to keep up. Frames over the budget it leaves
+    // are reported as `FrameJank`.
+    pub frame_budget: FrameBudgetConfig,
+
+    // Engine features turned on or off for every origin;
+    // `set_origin_features` overrides them for one.
+    pub feature_overrides: FeatureOverrides,
+
+    // A clock script reads instead of the system's, and the seed of its
+    // `Math.random`; for runs that must come out the same every time.
+    pub virtual_time: Option<VirtualTime>,
+
+    // Caps on the subresource bodies a page keeps for
+    // `save_page_archive`, and so on what an archive holds.
+    pub page_archive: PageArchiveConfig,
+
+    // File the Vulkan pipeline cache is loaded from at startup and saved
+    // to on shutdown; `None` compiles every pipeline afresh each run.
+    pub pipeline_cache_path: Option<PathBuf>,
 }
 
 impl Default for BrowserConfig {
@@ -128,6 +398,9 @@ impl Default for BrowserConfig {
             enable_pwa: true,
             enable_chrome_apis: true,
             max_memory_mb: 2048,
+            gpu_memory_warning_fraction: DEFAULT_GPU_MEMORY_WARNING_FRACTION,
+            js_memory_budget_mb: 512,
+            enable_webassembly: true,
             max_processes: 16,
 
             // --- Developer-friendly defaults for tests/demos ---
@@ -150,10 +423,43 @@ impl Default for BrowserConfig {
             viewport_height: 1080,
             enable_dev_tools: false,
             enable_security_features: true,
+            politeness: PolitenessConfig::default(),
+            tls: TlsConfig::default(),
+            disk_cache: DiskCacheConfig::default(),
+            max_response_size_mb: 100,
+            network_retry: RetryConfig::default(),
+            max_script_fetches: DEFAULT_MAX_SCRIPT_FETCHES,
+            code_cache: CodeCacheConfig::default(),
+            event_log_capacity: DEFAULT_EVENT_LOG_CAPACITY,
+            navigation_timing_history: DEFAULT_NAVIGATION_TIMING_HISTORY,
+            storage: StorageConfig::default(),
+            private_mode: false,
+            stylesheet_loading: StylesheetLoadingConfig::default(),
+            media: MediaConfig::default(),
+            max_speculative_fetches: DEFAULT_MAX_SPECULATIVE_FETCHES,
+            full_frame_rebuild: false,
+            debug_overlays: DebugOverlayFlags::NONE,
+            device_pixel_ratio: 1.0,
+            paint_source_budget_ms: DEFAULT_PAINT_BUDGET.as_millis() as u64,
+            dom_compaction_ratio: Some(DEFAULT_COMPACTION_RATIO),
+            prefers_reduced_motion: false,
+            animated_image_budget_bytes: DEFAULT_ANIMATION_BUDGET_BYTES,
+            enable_text_fragments: true,
+            enable_hyperlink_auditing: true,
+            enable_spatial_navigation: false,
+            content_limits: ContentLimits::default(),
+            prerender: PrerenderConfig::default(),
+            frame_budget: FrameBudgetConfig::default(),
+            feature_overrides: FeatureOverrides::new(),
+            virtual_time: None,
+            page_archive: PageArchiveConfig::default(),
+            pipeline_cache_path: None,
         }
     }
 }
 
+/// Metrics of one engine. The JS, DOM, layout-box and heap totals are the
+/// sums of `contexts`.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PerformanceMetrics {
     pub renderer: RendererMetrics,
@@ -161,6 +467,48 @@ pub struct PerformanceMetrics {
     pub layout: LayoutMetrics,
     pub memory_usage: MemoryMetrics,
     pub network: NetworkMetrics,
+    #[serde(default)]
+    pub contexts: Vec<ContextMetrics>,
+}
+
+/// Identifies one engine among those an embedder runs side by side, each
+/// being a tab.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TabId(pub u64);
+
+/// What runs in an execution context. Workers and sandboxed processes get
+/// a kind of their own once they run their own isolates.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextKind {
+    Document,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContextId {
+    pub tab: TabId,
+    pub kind: ContextKind,
+    /// ASCII serialization of the context's origin; `None` before anything
+    /// was loaded.
+    pub origin: Option<String>,
+}
+
+/// One execution context's resource use, sampled from counters the engine
+/// keeps anyway; taking a sample never walks the DOM.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContextMetrics {
+    pub context: ContextId,
+    pub js_heap_bytes: u64,
+    pub dom_nodes: u64,
+    /// The node arena's storage plus `dom_nodes` times an average node
+    /// size; [`Document::memory_usage`] counts exactly but walks the tree.
+    pub dom_bytes_estimate: u64,
+    /// Decoded frames of the context's animated images; still images are
+    /// not cached decoded.
+    pub image_cache_bytes: u64,
+    pub layout_boxes: u64,
+    /// Script and layout time spent since the previous sample.
+    pub cpu_time_ms: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -170,6 +518,32 @@ pub struct RendererMetrics {
     pub gpu_utilization: f64,
     pub draw_calls: u64,
     pub triangles_rendered: u64,
+    // Nodes of the last frame painted again into the buffers they had, and
+    // painted into new buffers.
+    #[serde(default)]
+    pub nodes_patched: u32,
+    #[serde(default)]
+    pub nodes_rebuilt: u32,
+    // Allocations of the frame's vertex and index buffers so far.
+    #[serde(default)]
+    pub vertex_buffer_version: u64,
+    #[serde(default)]
+    pub index_buffer_version: u64,
+    // Frames rendered since the engine started.
+    #[serde(default)]
+    pub frames_rendered: u64,
+    // Ticks over the frame budget since the engine started, and the
+    // percentiles of recent tick times; see `get_frame_statistics`.
+    #[serde(default)]
+    pub jank_frames: u64,
+    #[serde(default)]
+    pub frame_time_p95_ms: f64,
+    #[serde(default)]
+    pub frame_time_p99_ms: f64,
+    // Of the last frame presented to the window, the time spent waiting on
+    // the GPU to finish earlier ones.
+    #[serde(default)]
+    pub gpu_wait_ms: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -177,8 +551,25 @@ pub struct JSMetrics {
     pub execution_time_ms: f64,
     pub heap_size_mb: f64,
     pub gc_count: u64,
+    // V8 compiling scripts, cached or not, plus JIT compiles.
     pub compile_time_ms: f64,
+    // Scripts compiled from the code cache, and those looked up there that
+    // compiled in full.
+    pub code_cache_hits: u64,
+    pub code_cache_misses: u64,
+    // Live agents: contexts of one origin with their own heap account.
     pub active_isolates: u32,
+    pub jit_enabled: bool,
+    // DOM nodes in the document's arena, attached or not.
+    pub dom_nodes: u64,
+    pub live_dom_wrappers: u64,
+    pub detached_referenced_nodes: u64,
+    pub reclaimed_dom_nodes: u64,
+    pub agents: Vec<AgentMetrics>,
+    pub wasm_compile_time_ms: f64,
+    pub wasm_instantiate_time_ms: f64,
+    // Live WebAssembly linear memory across agents.
+    pub wasm_memory_bytes: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -187,6 +578,15 @@ pub struct LayoutMetrics {
     pub nodes_count: usize,
     pub reflow_count: u64,
     pub style_recalc_time_ms: f64,
+    /// Nodes the last style pass styled: the subtrees mutations touched,
+    /// unless stylesheets or the viewport changed.
+    #[serde(default)]
+    pub nodes_restyled: usize,
+    /// Boxes the last layout laid out: those inside the relayout
+    /// boundaries around what mutations touched, when they could be kept
+    /// to.
+    #[serde(default)]
+    pub nodes_relaid_out: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -203,9 +603,42 @@ pub struct NetworkMetrics {
     pub bytes_downloaded: u64,
     pub bytes_uploaded: u64,
     pub average_response_time_ms: f64,
+    pub speculative_fetches_issued: u64,
+    pub speculative_fetches_used: u64,
+    pub speculative_fetches_wasted: u64,
+    #[serde(default)]
+    pub pings_sent: u64,
+    #[serde(default)]
+    pub pings_blocked: u64,
+    #[serde(default)]
+    pub cache_hits: u64,
+    #[serde(default)]
+    pub cache_misses: u64,
+    #[serde(default)]
+    pub failed_requests: u64,
 }
 
-#[derive(Debug, Clone)]
+impl From<crate::core::network::NetworkMetrics> for NetworkMetrics {
+    fn from(network: crate::core::network::NetworkMetrics) -> Self {
+        Self {
+            requests_total: network.total_requests,
+            bytes_downloaded: network.total_bytes_downloaded,
+            bytes_uploaded: network.total_bytes_uploaded,
+            average_response_time_ms: network.average_request_time_ms,
+            speculative_fetches_issued: network.speculative_fetches_issued,
+            speculative_fetches_used: network.speculative_fetches_used,
+            speculative_fetches_wasted: network.speculative_fetches_wasted,
+            pings_sent: network.pings_sent,
+            pings_blocked: network.pings_blocked,
+            cache_hits: network.cache_hits,
+            cache_misses: network.cache_misses,
+            failed_requests: network.failed_requests,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum InputEvent {
     MouseMove {
         x: i32,
@@ -216,12 +649,25 @@ pub enum InputEvent {
         y: i32,
         button: u8,
     },
+    /// A button went down; `button` numbers as in DOM `MouseEvent.button`.
+    MouseDown {
+        x: i32,
+        y: i32,
+        button: u8,
+    },
+    MouseUp {
+        x: i32,
+        y: i32,
+        button: u8,
+    },
     MouseWheel {
         x: i32,
         y: i32,
         delta_x: f64,
         delta_y: f64,
     },
+    /// `key` as in DOM `KeyboardEvent.key`; `modifiers` holds
+    /// [`Modifiers`] bits.
     KeyPress {
         key: String,
         modifiers: u8,
@@ -244,9 +690,35 @@ pub enum InputEvent {
         width: u32,
         height: u32,
     },
+    ImeComposition {
+        state: ImeCompositionState,
+    },
+    /// Files dropped on the page from outside, at `(x, y)`.
+    FileDrop {
+        paths: Vec<PathBuf>,
+        x: i32,
+        y: i32,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// IME composition transitions, as reported by the windowing layer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ImeCompositionState {
+    Start,
+    Update {
+        text: String,
+        /// IME cursor or selection within `text`, in bytes.
+        cursor_range: Option<(usize, usize)>,
+    },
+    Commit {
+        text: String,
+    },
+    Cancel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum BrowserEvent {
     PageLoaded {
         url: String,
@@ -255,6 +727,26 @@ pub enum BrowserEvent {
     NavigationStarted {
         url: String,
     },
+    /// Script wrote to the document at `url` after it had loaded, which
+    /// replaces it with what was written: the old document is torn down
+    /// and the new one loads as a navigation to the same URL would.
+    DocumentReopened {
+        url: String,
+    },
+    /// The navigation to `url` was stopped before it loaded, by
+    /// [`BrowserEngine::stop`] or a newer navigation; no `PageLoaded`
+    /// follows for it.
+    NavigationCancelled {
+        url: String,
+    },
+    /// More of the document being loaded arrived: `bytes` of it so far, out
+    /// of `total` when the response said how long it is. Sent to
+    /// subscribers only, never kept in the event log.
+    LoadProgress {
+        url: String,
+        bytes: u64,
+        total: Option<u64>,
+    },
     JavaScriptError {
         message: String,
         line: u32,
@@ -264,17 +756,149 @@ pub enum BrowserEvent {
         url: String,
         error: String,
     },
+    /// A security check failed, as a certificate matching none of its
+    /// host's pins; the request it was for fails.
     SecurityViolation {
         description: String,
     },
+    /// The page's security state changed; also read by
+    /// [`BrowserEngine::get_security_state`].
+    SecurityStateChanged {
+        tab: TabId,
+        state: SecurityState,
+    },
     PerformanceWarning {
         metric: String,
         value: f64,
         threshold: f64,
+        /// The resource responsible, when there is one.
+        url: Option<String>,
+        /// The execution context that crossed the threshold.
+        context: Option<ContextId>,
     },
     ErrorHandled {
         message: String,
     }, // emitted by error handler
+    /// The page called `window.print()`; settle it with
+    /// [`BrowserEngine::resolve_print_request`].
+    PrintRequested {
+        request_id: u64,
+    },
+    /// Interactive validation stopped a form submission or
+    /// `reportValidity()` call at `node`, which now has focus when it takes
+    /// text; show `message` by it.
+    ValidationMessage {
+        node: NodeId,
+        message: String,
+    },
+    /// A key the page left alone matched an accelerator. Built-in reload,
+    /// back and forward have been carried out; other actions are the
+    /// embedder's to perform.
+    AcceleratorTriggered {
+        action: String,
+        keystroke: String,
+    },
+    /// The page's favicon resolved to a different icon, or went away;
+    /// fetch it with [`BrowserEngine::get_favicon`].
+    FaviconChanged {
+        tab: TabId,
+    },
+    /// The page's `<meta name="theme-color">` changed; `None` when it has
+    /// none any more.
+    ThemeColorChanged {
+        tab: TabId,
+        color: Option<String>,
+    },
+    /// The page meets the install criteria and got `beforeinstallprompt`;
+    /// install it with [`BrowserEngine::accept_install_prompt`].
+    PwaInstallable {
+        manifest_url: String,
+        app_name: String,
+    },
+    /// A user script added with [`BrowserEngine::add_user_script`] threw.
+    /// The page went on loading.
+    UserScriptError {
+        script_id: u64,
+        url: String,
+        message: String,
+    },
+    /// Where the time of a navigation went, after its `PageLoaded`. Also
+    /// read by [`BrowserEngine::get_navigation_timings`].
+    NavigationTiming {
+        tab: TabId,
+        timing: Box<NavigationTiming>,
+    },
+    /// The tab started or stopped making sound; muting it makes it
+    /// inaudible. Also read by [`BrowserEngine::is_tab_audible`].
+    AudibleStateChanged {
+        tab: TabId,
+        audible: bool,
+    },
+    /// A service worker called `showNotification()`. Showing it is the
+    /// embedder's.
+    NotificationRequested {
+        notification: Notification,
+    },
+    /// A page prerendered with [`BrowserEngine::prerender`] went without
+    /// being shown; activating its handle navigates to `url` as usual.
+    PrerenderDiscarded {
+        tab: TabId,
+        url: String,
+        reason: PrerenderDiscardReason,
+    },
+    /// Something changed in an ARIA live region; see
+    /// [`BrowserEngine::set_announcement_handler`].
+    LiveRegionAnnouncement {
+        tab: TabId,
+        politeness: Politeness,
+        text: String,
+    },
+    /// A frame took longer than the budget
+    /// [`BrowserConfig::frame_budget`] leaves; `phases` add up to
+    /// `total_ms`. When script took the most, `script_url` names the
+    /// script of the longest callback, unless that was inline. Also
+    /// counted by [`BrowserEngine::get_frame_statistics`].
+    FrameJank {
+        total_ms: f64,
+        phases: Vec<(FramePhase, f64)>,
+        worst_offender: FramePhase,
+        script_url: Option<String>,
+    },
+    /// The page used a feature disabled for its origin, as a CSS property
+    /// whose declarations were dropped. Reported once per page.
+    DisabledFeatureUsed {
+        feature: String,
+        usage: String,
+        url: Option<String>,
+    },
+}
+
+/// What [`BrowserEngine::clear_cache`] drops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// Every cache below.
+    All,
+    /// Network responses, in memory and on disk.
+    Http,
+    /// Compiled code of page scripts.
+    ScriptCache,
+    /// Every cookie, session and persistent.
+    Cookies,
+}
+
+/// Who a key press went to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRoute {
+    /// The page: an editable element took it or a `keydown` listener
+    /// canceled it.
+    Page,
+    /// The accelerator bound to this action id.
+    Accelerator(String),
+    /// Spatial navigation: focus moved, or Enter clicked the focused
+    /// element.
+    SpatialNavigation,
+    /// Nobody.
+    Unhandled,
 }
 
 /// The main engine. Intentionally uses `Arc<…>` around non-`Send` components,
@@ -282,25 +906,251 @@ pub enum BrowserEvent {
 /// scope clippy allows to this type to avoid muting the lints globally.
 #[allow(clippy::arc_with_non_send_sync)]
 pub struct BrowserEngine {
-    config: BrowserConfig,
+    // Written only by setters such as `set_user_agent`.
+    config: parking_lot::RwLock<BrowserConfig>,
     renderer: Arc<RwLock<VulkanRenderer>>,
     js_runtime: Arc<RwLock<JSRuntime>>,
     document: Arc<RwLock<Document>>,
-    style_engine: Arc<StyleEngine>,
     layout_engine: Arc<RwLock<LayoutEngine>>,
-    event_system: Arc<EventSystem>,
-    network_manager: Arc<NetworkManager>,
     sandbox_manager: Option<Arc<SandboxManager>>,
     pwa_manager: Option<Arc<PwaManager>>,
     is_shutdown: Arc<RwLock<bool>>,
 
+    // What belongs to the current document; activating a prerender swaps
+    // it for the prerendered page's.
+    page: parking_lot::RwLock<Arc<Page>>,
+
     // Simple history and loading state
     history: Arc<RwLock<Vec<String>>>,
     history_index: Arc<RwLock<Option<usize>>>,
     is_loading_flag: Arc<RwLock<bool>>,
+    // The navigation under way, which `stop` or a newer one cancels.
+    navigation: parking_lot::Mutex<NavigationSlot>,
+
+    // `<link rel="manifest">` of the current page; only tracked when PWA is enabled.
+    manifest_url: Arc<RwLock<Option<String>>>,
+    // The current page as a service worker client; only tracked when PWA is enabled.
+    service_worker_client: Arc<RwLock<Option<ClientId>>>,
+    // The embedder's user stylesheets and scripts, kept across navigations.
+    user_content: Arc<UserContent>,
+
+    // Focused editable element, caret and any in-progress IME composition.
+    editing: Arc<RwLock<EditingSession>>,
+    // Whether arrow keys move focus spatially, and the element they focused.
+    spatial_navigation: Arc<SpatialNavigation>,
+
+    // Recent events and navigation milestones, for post-mortem debugging.
+    event_log: Arc<EventLog>,
+    // Every event as it happens, to whoever subscribed.
+    event_sender: broadcast::Sender<BrowserEvent>,
+    // The current navigation's phases, and breakdowns of the last ones.
+    navigation_timings: Arc<NavigationTimings>,
+
+    // localStorage / sessionStorage areas, keyed by StorageKey.
+    web_storage: Arc<WebStorage>,
+
+    // The embedder's `-vk-paint()` sources, drawn by the renderer.
+    paint_sources: Arc<PaintSources>,
+
+    // What each origin was allowed or refused this session.
+    permissions: Arc<PermissionStore>,
+
+    // Engine features on for each origin; documents take theirs as they load.
+    features: Arc<FeatureRegistry>,
+
+    // Element the primary button went down on; releasing over it clicks it.
+    pressed: Arc<RwLock<Option<NodeId>>>,
+
+    // What was copied, for pages and paste; it outlives navigations.
+    clipboard: Arc<Clipboard>,
+
+    // Keyboard shortcuts for keys the page does not consume.
+    accelerators: Arc<AcceleratorTable>,
+
+    // Highlight, inspect mode and notifications of the devtools protocol.
+    devtools: Arc<Inspector>,
+
+    // Pages loaded in the background for this tab to switch to, and
+    // whether this engine is itself one of them.
+    prerenders: Arc<PrerenderSet<Arc<BrowserEngine>>>,
+    prerendering: bool,
+
+    // This engine's tab, and the script and layout microseconds it had used
+    // at the last metrics sample.
+    tab_id: TabId,
+    sampled_cpu_us: Arc<AtomicU64>,
 
     // Error handler callback; defaults to logging and swallow.
     error_handler: Arc<RwLock<Option<ErrorCallback>>>,
+    // Who live region announcements are handed to.
+    announcement_handler: parking_lot::RwLock<Option<AnnouncementHandler>>,
+
+    // Where the time of each frame went, and how many went over budget.
+    frame_watchdog: Arc<FrameWatchdog>,
+
+    // Debug overlays on, shared with prerendered pages as the renderer is.
+    debug_overlays: Arc<parking_lot::RwLock<DebugOverlaySettings>>,
+
+    // The window frames are presented to, once the embedder attached one.
+    window: RwLock<Option<WindowSurface>>,
+
+    // Draws screenshots when there is no window; opened for the first one,
+    // and `Some(None)` when there is no device to open.
+    offscreen: RwLock<Option<Option<vulkan::VulkanRenderer>>>,
+}
+
+/// A window presented to through a Vulkan swapchain.
+struct WindowSurface {
+    presenter: vulkan::VulkanRenderer,
+    // The renderer's frame on screen, so an unchanged one is not copied
+    // in again.
+    presented_frame: Option<u64>,
+    // Device memory is past its share of `max_memory_mb` and was reported.
+    gpu_memory_warned: bool,
+}
+
+/// What one document has of its own besides its tree, script and layout.
+/// Components script reaches are bound to the page's, so a page is
+/// swapped as a whole.
+struct Page {
+    style_engine: Arc<StyleEngine>,
+    // The tab's network stack, with this page's security state, speculative
+    // fetches, `blob:` URLs and subresource counts.
+    network_manager: Arc<NetworkManager>,
+
+    // What the page offers to install once it meets the install criteria.
+    install_prompts: Arc<InstallPrompts>,
+    // The passage a text fragment scrolled to, until the user moves on.
+    text_highlight: Arc<TextHighlight>,
+    // The content limits the current page went past.
+    content_limit_breaches: Arc<LimitBreaches>,
+    // The features the current document loaded with.
+    features: Arc<DocumentFeatures>,
+    // Where the last paint list drew each node; page script hit tests it.
+    hit_regions: Arc<HitRegions>,
+    // The scroll position page script reads and sets.
+    scroll: Arc<ViewportScroll>,
+
+    // `@font-face` and `FontFace` faces of the current document.
+    fonts: Arc<FontFaceSet>,
+
+    // `fetch()` calls of the current document still in flight or unclaimed.
+    script_fetches: Arc<ScriptFetches>,
+
+    // The current document's `window.print()` request, if any.
+    print_requests: Arc<PrintRequests>,
+    // What the current document's scripts passed `document.write()`.
+    document_writes: Arc<DocumentWrites>,
+
+    // `<link rel="stylesheet">`s of the current document and their loads.
+    stylesheets: Arc<LinkedStylesheets>,
+
+    // `<video>`/`<audio>` of the current document: metadata, posters, events.
+    media: Arc<MediaElements>,
+    // What plays sound in the page, and whether the user muted the tab.
+    audio: Arc<AudioSession>,
+    // When the user last interacted with the current document.
+    user_activation: Arc<UserActivation>,
+
+    // Frames and playback of the current document's animated GIFs and APNGs.
+    image_animations: Arc<ImageAnimations>,
+    // Each `<img>` source of the current document once its fetch is over.
+    images: Arc<ImageCache>,
+
+    // `<meta>` values and icons of the current document.
+    page_metadata: Arc<PageMetadataTracker>,
+
+    // The current document's `speechSynthesis` queue and the backend it
+    // speaks through.
+    speech: Arc<SpeechSynthesis>,
+
+    // Changes inside the current document's live regions not announced yet.
+    live_regions: Arc<LiveRegionTracker>,
+
+    // Invalid form controls script asked to be shown to the user.
+    validation_reports: Arc<ValidationReports>,
+
+    // The drag in progress, and the files drops granted the current document.
+    drag: Arc<DragAndDrop>,
+    file_grants: Arc<FileGrants>,
+
+    // The current document's URL and CSP, for what it requests once loaded.
+    request_initiator: parking_lot::RwLock<Option<RequestInitiator>>,
+
+    // Touch points down on the current document, by id: the element each
+    // started on and where it is now.
+    touches: parking_lot::Mutex<std::collections::BTreeMap<u32, TouchPoint>>,
+}
+
+impl Page {
+    fn new(
+        config: &BrowserConfig,
+        network_manager: Arc<NetworkManager>,
+        permissions: &Arc<PermissionStore>,
+    ) -> Self {
+        let style_engine = Arc::new(StyleEngine::new());
+        style_engine.set_prefers_reduced_motion(config.prefers_reduced_motion);
+        let image_animations = Arc::new(ImageAnimations::new(config.animated_image_budget_bytes));
+        image_animations.set_reduced_motion(config.prefers_reduced_motion);
+        let audio = Arc::new(AudioSession::new());
+        let user_activation = Arc::new(UserActivation::new());
+        let media = Arc::new(MediaElements::new(
+            audio.clone(),
+            permissions.clone(),
+            user_activation.clone(),
+        ));
+        Self {
+            style_engine,
+            network_manager,
+            install_prompts: Arc::new(InstallPrompts::default()),
+            text_highlight: Arc::new(TextHighlight::default()),
+            content_limit_breaches: Arc::new(LimitBreaches::default()),
+            features: Arc::new(DocumentFeatures::default()),
+            hit_regions: Arc::new(HitRegions::default()),
+            scroll: Arc::new(ViewportScroll::default()),
+            fonts: Arc::new(FontFaceSet::new()),
+            script_fetches: Arc::new(ScriptFetches::new(config.max_script_fetches)),
+            print_requests: Arc::new(PrintRequests::default()),
+            document_writes: Arc::new(DocumentWrites::new()),
+            stylesheets: Arc::new(LinkedStylesheets::new()),
+            media,
+            audio,
+            user_activation,
+            image_animations,
+            images: Arc::new(ImageCache::new()),
+            page_metadata: Arc::new(PageMetadataTracker::new()),
+            speech: SpeechSynthesis::new(Arc::new(NullTtsBackend::new()), permissions.clone()),
+            live_regions: Arc::new(LiveRegionTracker::new()),
+            validation_reports: Arc::new(ValidationReports::default()),
+            drag: Arc::new(DragAndDrop::default()),
+            file_grants: Arc::new(FileGrants::new()),
+            request_initiator: parking_lot::RwLock::new(None),
+            touches: parking_lot::Mutex::new(std::collections::BTreeMap::new()),
+        }
+    }
+
+    /// A layout engine for this page's documents.
+    fn layout_engine(&self, config: &BrowserConfig) -> LayoutEngine {
+        LayoutEngine::new(config.viewport_width, config.viewport_height)
+            .with_media_elements(self.media.clone())
+            .with_fonts(self.fonts.clone())
+            .with_images(self.images.clone())
+    }
+
+    /// Take over the embedder's hooks and the tab's mute from `other`.
+    fn adopt_tab_settings(&self, other: &Page) {
+        self.media
+            .set_playback_handler(other.media.playback_handler());
+        self.fonts
+            .set_shaping_observer(other.fonts.shaping_observer());
+        let backend = other.speech.backend();
+        if !Arc::ptr_eq(&backend, &self.speech.backend()) {
+            self.speech.set_backend(backend);
+        }
+        if self.audio.set_muted(other.audio.is_muted()) {
+            self.media.tab_muted_changed();
+        }
+    }
 }
 
 impl BrowserEngine {
@@ -319,7 +1169,10 @@ impl BrowserEngine {
         match res {
             Ok(outcome) => {
                 if let Err(ref err) = outcome {
-                    self.handle_error(err.clone()).await;
+                    // A stopped navigation is not a failure to report.
+                    if !matches!(err, BrowserError::NavigationCancelled(_)) {
+                        self.handle_error(err.clone()).await;
+                    }
                 }
                 outcome
             }
@@ -359,26 +1212,176 @@ impl BrowserEngine {
         *self.error_handler.write().await = arc_cb;
     }
 
+    /// Install the credential prompt used when a server (401) or proxy (407)
+    /// asks for authentication and nothing is stored for that origin and
+    /// realm. Resolving to `None` cancels and the challenge response is
+    /// loaded as is. Only navigations and same-origin subresources prompt.
+    pub fn set_auth_handler<F, Fut>(&self, handler: Option<F>)
+    where
+        F: Fn(AuthChallenge) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Option<Credentials>> + Send + 'static,
+    {
+        let handler: Option<AuthHandler> = handler
+            .map(|f| Arc::new(move |challenge: AuthChallenge| f(challenge).boxed()) as AuthHandler);
+        self.page().network_manager.set_auth_handler(handler);
+    }
+
+    /// Forget the credentials remembered this session, so the next challenge
+    /// prompts again.
+    pub fn clear_auth_credentials(&self) {
+        self.page().network_manager.clear_credentials();
+    }
+
+    /// Install the handler `play()` on a `<video>` or `<audio>` is handed
+    /// to, with the element and its source; it returns whether it plays
+    /// it. Without one, or when it refuses, `play()` rejects with
+    /// `NotSupportedError`. It is also told when what it plays pauses, goes
+    /// away, or changes muted state or volume, the tab's mute included.
+    /// Requires [`MediaConfig::external_playback`].
+    pub fn set_media_playback_handler<F>(&self, handler: Option<F>) -> Result<()>
+    where
+        F: Fn(PlaybackRequest) -> bool + Send + Sync + 'static,
+    {
+        if !self.config.read().media.external_playback {
+            return Err(BrowserError::FeatureDisabled("external media playback"));
+        }
+        let handler: Option<PlaybackHandler> = handler.map(|f| Arc::new(f) as PlaybackHandler);
+        self.page().media.set_playback_handler(handler);
+        Ok(())
+    }
+
+    /// Paint backgrounds declared `background-image: -vk-paint(name)` with
+    /// `source`, in place of any registered under `name`. Pages already
+    /// showing such backgrounds pick it up on the next frame.
+    pub fn register_paint_source(&self, name: &str, source: Box<dyn PaintSource>) {
+        self.paint_sources.register(name, Arc::from(source));
+    }
+
+    /// Mute or unmute `tab`. Its media goes on playing, silently: pages
+    /// read `muted` as true and the playback handler is told to stop its
+    /// output. The tab stays muted across navigations.
+    pub async fn set_tab_muted(&self, tab: TabId, muted: bool) -> Result<()> {
+        self.run_safe(async move {
+            if tab != self.tab_id {
+                return Err(BrowserError::Platform(format!("No tab {}", tab.0)));
+            }
+            if self.page().audio.set_muted(muted) {
+                self.page().media.tab_muted_changed();
+                self.announce_audible_change().await;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Whether `tab` plays sound that is not muted.
+    pub fn is_tab_audible(&self, tab: TabId) -> bool {
+        tab == self.tab_id && self.page().audio.is_audible()
+    }
+
+    /// Have `observer` called with every run of text as it is shaped with a
+    /// web font, with the run's content language; `None` stops. Meant for
+    /// tests and diagnostics: it runs during layout, under its locks.
+    pub fn set_shaping_observer<F>(&self, observer: Option<F>)
+    where
+        F: Fn(&ShapedRun) + Send + Sync + 'static,
+    {
+        let observer: Option<ShapingObserver> = observer.map(|f| Arc::new(f) as ShapingObserver);
+        self.page().fonts.set_shaping_observer(observer);
+    }
+
+    /// Speak `speechSynthesis` utterances through `backend`, such as a
+    /// host's bridge to the platform's speech service; `None` goes back to
+    /// the silent [`NullTtsBackend`]. An utterance the previous backend was
+    /// speaking gets an `interrupted` error.
+    pub fn set_tts_backend(&self, backend: Option<Arc<dyn TtsBackend>>) {
+        let backend = backend.unwrap_or_else(|| Arc::new(NullTtsBackend::new()));
+        self.page().speech.set_backend(backend);
+    }
+
+    /// Hand what changes in the page's ARIA live regions to `handler`, for
+    /// a screen reader or a [`TtsBackend`], after the script run or tick
+    /// that changed it; assertive announcements come first. Each is also
+    /// emitted as a [`BrowserEvent::LiveRegionAnnouncement`].
+    pub fn set_announcement_handler<F>(&self, handler: Option<F>)
+    where
+        F: Fn(Announcement) + Send + Sync + 'static,
+    {
+        *self.announcement_handler.write() = handler.map(|f| Arc::new(f) as AnnouncementHandler);
+    }
+
+    /// Allow or refuse `permission` to the origin of `url` for the rest of
+    /// the session; `None` forgets the decision. Opaque origins only ever
+    /// get the default.
+    pub fn set_permission(
+        &self,
+        url: &url::Url,
+        permission: Permission,
+        state: Option<PermissionState>,
+    ) {
+        self.permissions.set(url, permission, state);
+    }
+
+    /// What origins without a decision of their own get for `permission`.
+    pub fn set_default_permission(&self, permission: Permission, state: PermissionState) {
+        self.permissions.set_default(permission, state);
+    }
+
+    pub fn permission_state(&self, url: &url::Url, permission: Permission) -> PermissionState {
+        self.permissions.state(url, permission)
+    }
+
+    /// Turn features on or off for the origin of `url` over the configured
+    /// ones, replacing what was set for it before; empty `overrides` forget
+    /// them. Documents already loaded keep their features: this applies
+    /// from the origin's next navigation. Opaque origins only ever get the
+    /// configured features.
+    pub fn set_origin_features(&self, url: &url::Url, overrides: FeatureOverrides) {
+        self.features.set_origin(url, overrides);
+    }
+
     // -------- Construction --------
 
     pub async fn new(config: BrowserConfig) -> Result<Self> {
-        let renderer = Arc::new(RwLock::new(
-            VulkanRenderer::new()
-                .await
-                .map_err(|e| BrowserError::RendererInit(e.to_string()))?,
-        ));
+        let network_manager = NetworkManager::new(&config).await?;
+        Self::with_network(config, network_manager).await
+    }
+
+    /// An engine that fetches through `network_manager`, such as one a test
+    /// built over a mock with [`NetworkManager::with_transport`].
+    pub async fn with_network(
+        config: BrowserConfig,
+        network_manager: NetworkManager,
+    ) -> Result<Self> {
+        let render_backend = if config.enable_gpu_acceleration {
+            RenderBackend::Vulkan
+        } else {
+            RenderBackend::Software
+        };
+        let mut renderer = VulkanRenderer::with_backend(render_backend)
+            .await
+            .map_err(|e| BrowserError::RendererInit(e.to_string()))?;
+        renderer.set_full_rebuild(config.full_frame_rebuild);
+        renderer.set_debug_overlays(config.debug_overlays);
+        renderer.set_device_pixel_ratio(config.device_pixel_ratio);
+        let paint_sources = Arc::new(PaintSources::new(std::time::Duration::from_millis(
+            config.paint_source_budget_ms,
+        )));
+        renderer.set_paint_sources(paint_sources.clone());
+        let renderer = Arc::new(RwLock::new(renderer));
 
         #[allow(clippy::arc_with_non_send_sync)]
         let js_runtime = Arc::new(RwLock::new(JSRuntime::new(&config).await?));
 
-        let document = Arc::new(RwLock::new(Document::new()));
-        let style_engine = Arc::new(StyleEngine::new());
-        let layout_engine = Arc::new(RwLock::new(LayoutEngine::new(
-            config.viewport_width,
-            config.viewport_height,
-        )));
-        let event_system = Arc::new(EventSystem::new());
-        let network_manager = Arc::new(NetworkManager::new(&config).await?);
+        let document = Document::new();
+        document.set_compaction_ratio(config.dom_compaction_ratio);
+        let document = Arc::new(RwLock::new(document));
+        let permissions = Arc::new(PermissionStore::new());
+        let page = Page::new(&config, Arc::new(network_manager), &permissions);
+        let layout_engine = Arc::new(RwLock::new(page.layout_engine(&config)));
+        let event_log = Arc::new(EventLog::new(config.event_log_capacity));
+        let navigation_timings = Arc::new(NavigationTimings::new(config.navigation_timing_history));
+        let web_storage = Arc::new(WebStorage::new(&config.storage, config.private_mode));
 
         let sandbox_manager = if config.enable_sandbox {
             Some(Arc::new(SandboxManager::new().await?))
@@ -388,27 +1391,57 @@ impl BrowserEngine {
 
         let pwa_manager = if config.enable_pwa {
             #[allow(clippy::arc_with_non_send_sync)]
-            Some(Arc::new(PwaManager::new().await?))
+            Some(Arc::new(
+                PwaManager::with_storage(web_storage.clone()).await?,
+            ))
         } else {
             None
         };
 
         Ok(Self {
-            config,
             renderer,
             js_runtime,
             document,
-            style_engine,
             layout_engine,
-            event_system,
-            network_manager,
             sandbox_manager,
             pwa_manager,
             is_shutdown: Arc::new(RwLock::new(false)),
+            page: parking_lot::RwLock::new(Arc::new(page)),
             history: Arc::new(RwLock::new(Vec::new())),
             history_index: Arc::new(RwLock::new(None)),
             is_loading_flag: Arc::new(RwLock::new(false)),
+            navigation: parking_lot::Mutex::new(NavigationSlot::default()),
+            manifest_url: Arc::new(RwLock::new(None)),
+            service_worker_client: Arc::new(RwLock::new(None)),
+            user_content: Arc::new(UserContent::new()),
+            editing: Arc::new(RwLock::new(EditingSession::new())),
+            spatial_navigation: Arc::new(SpatialNavigation::new(config.enable_spatial_navigation)),
+            event_log,
+            event_sender: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            navigation_timings,
+            web_storage,
+            paint_sources,
+            permissions,
+            features: Arc::new(FeatureRegistry::new(config.feature_overrides.clone())),
+            pressed: Arc::new(RwLock::new(None)),
+            clipboard: Arc::new(Clipboard::new()),
+            accelerators: Arc::new(AcceleratorTable::new()),
+            devtools: Arc::new(Inspector::new()),
+            #[allow(clippy::arc_with_non_send_sync)]
+            prerenders: Arc::new(PrerenderSet::new(&config.prerender)),
+            prerendering: false,
+            frame_watchdog: Arc::new(FrameWatchdog::new(&config.frame_budget)),
+            debug_overlays: Arc::new(parking_lot::RwLock::new(DebugOverlaySettings {
+                flags: config.debug_overlays,
+                outline_root: None,
+            })),
+            window: RwLock::new(None),
+            offscreen: RwLock::new(None),
+            config: parking_lot::RwLock::new(config),
+            tab_id: TabId(NEXT_TAB_ID.fetch_add(1, Ordering::Relaxed)),
+            sampled_cpu_us: Arc::new(AtomicU64::new(0)),
             error_handler: Arc::new(RwLock::new(None)),
+            announcement_handler: parking_lot::RwLock::new(None),
         })
     }
 
@@ -422,6 +1455,39 @@ impl BrowserEngine {
         self.run_safe(self.load_url_inner(url.to_string())).await
     }
 
+    /// Show the raw markup of `url` instead of rendering it; same as loading `view-source:<url>`.
+    pub async fn view_source(&self, url: &str) -> Result<()> {
+        self.run_safe(self.load_url_inner(format!("view-source:{url}")))
+            .await
+    }
+
+    /// Load `url` hidden, alongside the current page, so that navigating to
+    /// it later is near instant: through [`Self::activate_prerender`], or
+    /// any navigation to the same URL. The page runs its scripts seeing
+    /// `document.prerendering`; its timers run throttled and animation
+    /// frames, sound, printing and notifications wait until it is shown.
+    /// A page that fails to load, or says `Cache-Control: no-store`, is
+    /// discarded with a [`BrowserEvent::PrerenderDiscarded`], as are the
+    /// oldest once there are more than [`BrowserConfig::prerender`] allows.
+    pub async fn prerender(&self, url: &str) -> Result<PrerenderHandle> {
+        self.run_safe(self.prerender_inner(url.to_string())).await
+    }
+
+    /// Show the page prerendered for `handle` in place of the current one.
+    /// A discarded prerender is navigated to as usual.
+    pub async fn activate_prerender(&self, handle: &PrerenderHandle) -> Result<()> {
+        self.run_safe(async {
+            match self.prerenders.take(handle) {
+                Some(prerendered) => {
+                    self.activate_prerendered(&prerendered, handle.url().to_string())
+                        .await
+                }
+                None => self.load_url_inner(handle.url().to_string()).await,
+            }
+        })
+        .await
+    }
+
     pub async fn navigate_back(&self) -> Result<()> {
         self.run_safe(self.navigate_back_inner()).await
     }
@@ -435,50 +1501,284 @@ impl BrowserEngine {
             .await
     }
 
-    pub async fn reload(&self) -> Result<()> {
-        self.run_safe(self.reload_inner()).await
+    /// Restyle, lay out and paint what the DOM changed now, rather than at
+    /// the next [`Self::tick`]. Script run through
+    /// [`Self::execute_javascript`] needs no call: its mutations are
+    /// rendered as it returns.
+    pub async fn request_frame(&self) -> Result<()> {
+        self.run_safe(self.update_pipeline()).await
     }
 
-    pub async fn resize_viewport(&self, width: u32, height: u32) -> Result<()> {
-        self.run_safe(self.resize_viewport_inner(width, height))
+    /// Run one turn of the page's event loop: due timers, then this frame's
+    /// animation frame callbacks, then a repaint if script dirtied styles.
+    /// Call once per frame.
+    pub async fn tick(&self) -> Result<()> {
+        self.run_safe(self.tick_inner()).await
+    }
+
+    /// Settle the `window.print()` request announced as
+    /// [`BrowserEvent::PrintRequested`]. Printing returns the PDF; either way
+    /// the page then gets `afterprint`. Each request settles once.
+    pub async fn resolve_print_request(
+        &self,
+        request_id: u64,
+        decision: PrintDecision,
+    ) -> Result<Option<Vec<u8>>> {
+        self.run_safe(self.resolve_print_request_inner(request_id, decision))
             .await
     }
 
-    pub async fn get_performance_metrics(&self) -> PerformanceMetrics {
-        // metrics collection should never panic; return directly
-        let renderer_metrics = RendererMetrics {
-            frame_rate: 60.0,
-            render_time_ms: 16.7,
-            gpu_utilization: 0.0,
-            draw_calls: 0,
-            triangles_rendered: 0,
-        };
+    /// Lay the current document out for print media on `options`' pages,
+    /// honoring its `@page` rules, and return it as a PDF. The screen layout
+    /// is restored afterwards.
+    pub async fn print_to_pdf(&self, options: PrintOptions) -> Result<Vec<u8>> {
+        self.run_safe(self.print_to_pdf_inner(options)).await
+    }
 
-        // Use read() where possible to avoid exclusive locks
-        let js_perf = self.js_runtime.read().await.get_metrics().await;
-        let js_metrics = JSMetrics {
-            execution_time_ms: js_perf.execution_time_us as f64 / 1000.0,
-            heap_size_mb: js_perf.heap_size_bytes as f64 / (1024.0 * 1024.0),
-            gc_count: 0,
-            compile_time_ms: 0.0,
-            active_isolates: 1,
-        };
+    /// The page laid out and painted at `width` by `height` into an
+    /// offscreen image, as a PNG. See-through areas are flattened onto
+    /// white. Needs no window, and leaves the viewport and what is on
+    /// screen as they are.
+    pub async fn capture_screenshot(&self, width: u32, height: u32) -> Result<Vec<u8>> {
+        self.run_safe(self.capture_screenshot_inner(width, height))
+            .await
+    }
 
-        let layout_perf = self.layout_engine.read().await.get_metrics().await;
-        let layout_metrics = LayoutMetrics {
-            layout_time_ms: layout_perf.average_layout_time_us as f64 / 1000.0,
-            nodes_count: 0,
-            reflow_count: layout_perf.total_layouts,
-            style_recalc_time_ms: 0.0,
+    /// The current document, as script has left it, saved in one file with
+    /// the stylesheets, images and fonts it loaded. Scripts are neutered.
+    pub async fn save_page_archive(&self, format: ArchiveFormat) -> Result<Vec<u8>> {
+        let options = ArchiveOptions {
+            format,
+            ..Default::default()
         };
+        Ok(self.save_page_archive_with(options).await?.data)
+    }
 
-        let memory_metrics = self.get_memory_usage().await;
-        let network_metrics = NetworkMetrics {
-            requests_total: 0,
-            bytes_downloaded: 0,
-            bytes_uploaded: 0,
-            average_response_time_ms: 0.0,
-        };
+    /// [`save_page_archive`](Self::save_page_archive) with `options`, and
+    /// the manifest of what went in and what was left out.
+    pub async fn save_page_archive_with(&self, options: ArchiveOptions) -> Result<PageArchive> {
+        self.run_safe(async {
+            let page = self.page();
+            let document = self.document.read().await;
+            let saved = archive::save_page(
+                &document,
+                |url| page.network_manager.page_response(url),
+                options,
+                &self.config.read().page_archive,
+            )?;
+            Ok(saved)
+        })
+        .await
+    }
+
+    /// Run a full JS garbage collection and free the DOM nodes it left
+    /// unreachable. Returns how many nodes were freed.
+    pub async fn collect_garbage(&self) -> Result<usize> {
+        self.run_safe(async {
+            let rt = self.js_runtime.read().await;
+            Ok(rt.collect_garbage().await?)
+        })
+        .await
+    }
+
+    /// Free what script no longer reaches, then pack the DOM's node arena
+    /// and drop layout and style entries of freed nodes; for an embedder to
+    /// call while idle. Node ids, and so script's references, are kept.
+    pub async fn compact_dom(&self) -> Result<CompactionReport> {
+        self.run_safe(async {
+            self.js_runtime.read().await.reclaim_dom_nodes().await?;
+            let document = self.document.read().await;
+            let report = document.compact();
+            document.take_compaction();
+            self.retain_node_tables(&document).await;
+            Ok(report)
+        })
+        .await
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        self.run_safe(self.reload_inner()).await
+    }
+
+    /// Scroll the page so document point `(x, y)` is at the viewport's top
+    /// left, as far as the document reaches.
+    pub async fn scroll_to(&self, x: f32, y: f32) -> Result<()> {
+        self.run_safe(self.scroll_to_inner(x, y)).await
+    }
+
+    /// Where the viewport's top left is in the document.
+    pub async fn scroll_position(&self) -> (f32, f32) {
+        self.layout_engine.read().await.scroll_position()
+    }
+
+    /// Where the passage a `#:~:text=` link scrolled to is highlighted, in
+    /// viewport coordinates; empty once the user clicked or scrolled away,
+    /// or when the link matched nothing.
+    pub async fn text_fragment_highlight(&self) -> Vec<Rect> {
+        self.text_fragment_rects().await
+    }
+
+    /// Lay the page out for a viewport of `width` by `height`, and size
+    /// the attached window's swapchain to match.
+    pub async fn resize_viewport(&self, width: u32, height: u32) -> Result<()> {
+        self.run_safe(async {
+            self.resize_viewport_inner(width, height).await?;
+            self.resize_window_inner(width, height).await
+        })
+        .await
+    }
+
+    /// Present frames to `window` on `display` from now on, through a
+    /// Vulkan surface and swapchain; [`Self::render_frame`] draws to it.
+    /// Replaces the window attached before, if any. Fails with
+    /// [`BrowserError::FeatureDisabled`] without touching Vulkan when
+    /// [`BrowserConfig::enable_gpu_acceleration`] is off.
+    ///
+    /// # Safety
+    ///
+    /// The handles must be of a live window that outlives this engine or
+    /// the next window attached.
+    pub async unsafe fn attach_window(
+        &self,
+        display: RawDisplayHandle,
+        window: RawWindowHandle,
+    ) -> Result<()> {
+        self.run_safe(self.attach_window_inner(display, window))
+            .await
+    }
+
+    /// Show the last painted frame in the attached window. Does nothing
+    /// without one. Call once per frame, after [`Self::tick`].
+    pub async fn render_frame(&self) -> Result<()> {
+        self.run_safe(self.render_frame_inner()).await
+    }
+
+    /// How many frames `tick` ran and how many went over budget, and the
+    /// percentiles of their times over the last
+    /// [`FrameBudgetConfig::window`] frames.
+    pub fn get_frame_statistics(&self) -> FrameStatistics {
+        self.frame_watchdog.statistics()
+    }
+
+    /// The last subresources the tab fetched and pings it sent, oldest
+    /// first; unlike the per-page counts it spans navigations.
+    pub fn resource_log(&self) -> Vec<ResourceLogEntry> {
+        self.page().network_manager.resource_log()
+    }
+
+    pub async fn get_performance_metrics(&self) -> PerformanceMetrics {
+        // metrics collection should never panic; return directly
+        let (frame, frames_rendered) = {
+            let renderer = self.renderer.read().await;
+            (*renderer.get_frame_stats(), renderer.frame_count())
+        };
+        let frame_statistics = self.frame_watchdog.statistics();
+        let presented = self
+            .window
+            .read()
+            .await
+            .as_ref()
+            .map(|window| window.presenter.stats());
+        // Painting the last frame, and presenting it when there is a window.
+        let render_time_ms = frame.frame_time_ms() as f64
+            + presented.map_or(0.0, |stats| stats.frame_time_ms as f64);
+        let renderer_metrics = RendererMetrics {
+            frame_rate: if render_time_ms > 0.0 {
+                1000.0 / render_time_ms
+            } else {
+                0.0
+            },
+            render_time_ms,
+            gpu_utilization: 0.0,
+            draw_calls: 0,
+            triangles_rendered: 0,
+            nodes_patched: frame.nodes_patched(),
+            nodes_rebuilt: frame.nodes_rebuilt(),
+            vertex_buffer_version: frame.vertex_buffer_version(),
+            index_buffer_version: frame.index_buffer_version(),
+            frames_rendered,
+            jank_frames: frame_statistics.jank_frames,
+            frame_time_p95_ms: frame_statistics.p95_ms,
+            frame_time_p99_ms: frame_statistics.p99_ms,
+            gpu_wait_ms: presented.map_or(0.0, |stats| stats.gpu_wait_ms as f64),
+        };
+
+        // Use read() where possible to avoid exclusive locks
+        let (js_perf, current_agent) = {
+            let rt = self.js_runtime.read().await;
+            (rt.get_metrics().await, rt.current_agent())
+        };
+        let js_heap_bytes = js_perf
+            .agents
+            .iter()
+            .find(|agent| agent.agent == current_agent)
+            .map_or(0, |agent| agent.heap_used_bytes);
+        let (layout_perf, layout_boxes) = {
+            let layout_engine = self.layout_engine.read().await;
+            (layout_engine.get_metrics().await, layout_engine.box_count())
+        };
+        let (context, wrapper_stats, dom_nodes, arena_bytes) = {
+            let document = self.document.read().await;
+            (
+                self.document_context(&document),
+                document.wrapper_stats(),
+                document.node_count() as u64,
+                document.arena_bytes() as u64,
+            )
+        };
+
+        // Cumulative counters; the sample gets what was added since the last.
+        let cpu_us = js_perf.execution_time_us + layout_perf.total_layout_time_us as u64;
+        let previous_cpu_us = self.sampled_cpu_us.swap(cpu_us, Ordering::Relaxed);
+        let contexts = vec![ContextMetrics {
+            context,
+            js_heap_bytes,
+            dom_nodes,
+            dom_bytes_estimate: arena_bytes + dom_nodes * ESTIMATED_DOM_NODE_BYTES,
+            image_cache_bytes: (self.page().image_animations.decoded_bytes()
+                + self.page().images.decoded_bytes()) as u64,
+            layout_boxes: layout_boxes as u64,
+            cpu_time_ms: cpu_us.saturating_sub(previous_cpu_us) as f64 / 1000.0,
+        }];
+
+        let js_metrics = JSMetrics {
+            execution_time_ms: js_perf.execution_time_us as f64 / 1000.0,
+            heap_size_mb: contexts.iter().map(|c| c.js_heap_bytes).sum::<u64>() as f64
+                / (1024.0 * 1024.0),
+            gc_count: 0,
+            compile_time_ms: (js_perf.compilation_time_us + js_perf.jit_compilation_time_us) as f64
+                / 1000.0,
+            code_cache_hits: js_perf.code_cache_hits,
+            code_cache_misses: js_perf.code_cache_misses,
+            active_isolates: js_perf.agents.len() as u32,
+            jit_enabled: js_perf.jit_enabled,
+            dom_nodes: contexts.iter().map(|c| c.dom_nodes).sum(),
+            live_dom_wrappers: wrapper_stats.live_wrappers,
+            detached_referenced_nodes: wrapper_stats.detached_referenced,
+            reclaimed_dom_nodes: wrapper_stats.reclaimed,
+            agents: js_perf.agents,
+            wasm_compile_time_ms: js_perf.wasm_compile_time_us as f64 / 1000.0,
+            wasm_instantiate_time_ms: js_perf.wasm_instantiate_time_us as f64 / 1000.0,
+            wasm_memory_bytes: js_perf.wasm_memory_bytes,
+        };
+
+        let layout_metrics = LayoutMetrics {
+            layout_time_ms: layout_perf.average_layout_time_us / 1000.0,
+            nodes_count: contexts.iter().map(|c| c.layout_boxes as usize).sum(),
+            reflow_count: layout_perf.total_layouts,
+            style_recalc_time_ms: 0.0,
+            nodes_restyled: self.page().style_engine.styled_node_count(),
+            nodes_relaid_out: layout_perf.last_layout_boxes,
+        };
+
+        let gpu_bytes = self
+            .window
+            .read()
+            .await
+            .as_ref()
+            .map_or(0, |window| window.presenter.memory_report().reserved_bytes);
+        let memory_metrics = self.get_memory_usage(&contexts, gpu_bytes);
+        let network_metrics = NetworkMetrics::from(self.page().network_manager.get_metrics());
 
         PerformanceMetrics {
             renderer: renderer_metrics,
@@ -486,29 +1786,171 @@ impl BrowserEngine {
             layout: layout_metrics,
             memory_usage: memory_metrics,
             network: network_metrics,
+            contexts,
         }
     }
 
+    /// Where the time of the last navigations went, oldest first; as many
+    /// as [`BrowserConfig::navigation_timing_history`] says to keep.
+    pub fn get_navigation_timings(&self) -> Vec<NavigationTiming> {
+        self.navigation_timings.recent()
+    }
+
+    /// The [`BrowserConfig::content_limits`] the current page went past,
+    /// one entry per limit with the most it asked for.
+    pub fn get_content_limit_breaches(&self) -> Vec<LimitBreach> {
+        self.page().content_limit_breaches.all()
+    }
+
+    /// The tab this engine is, for telling engines' metrics apart.
+    pub fn tab_id(&self) -> TabId {
+        self.tab_id
+    }
+
     pub async fn handle_input_event(&self, event: InputEvent) -> Result<()> {
-        self.run_safe(async move {
-            match event {
-                InputEvent::Resize { width, height } => {
-                    self.resize_viewport_inner(width, height).await
+        let started = std::time::Instant::now();
+        let result = self
+            .run_safe(async move {
+                match event {
+                    InputEvent::Resize { width, height } => {
+                        self.resize_viewport_inner(width, height).await
+                    }
+                    InputEvent::ImeComposition { state } => self.compose_inner(state).await,
+                    InputEvent::KeyPress { key, modifiers } => self
+                        .key_press_inner(&key, Modifiers::from_bits(modifiers))
+                        .await
+                        .map(|_| ()),
+                    InputEvent::KeyRelease { key, modifiers } => {
+                        self.key_release_inner(&key, Modifiers::from_bits(modifiers))
+                            .await
+                    }
+                    InputEvent::MouseDown { x, y, button } => {
+                        self.pointer_down_inner(x, y, button).await
+                    }
+                    InputEvent::MouseMove { x, y } => self.pointer_move_inner(x, y).await,
+                    InputEvent::MouseUp { x, y, button } => {
+                        self.pointer_up_inner(x, y, button).await
+                    }
+                    InputEvent::MouseClick { x, y, button } => {
+                        self.pointer_down_inner(x, y, button).await?;
+                        self.pointer_up_inner(x, y, button).await
+                    }
+                    InputEvent::MouseWheel {
+                        x,
+                        y,
+                        delta_x,
+                        delta_y,
+                    } => self.wheel_inner(x, y, delta_x, delta_y).await,
+                    InputEvent::Touch { x, y, pressure, id } => {
+                        self.touch_inner(x, y, pressure, id).await
+                    }
+                    InputEvent::FileDrop { paths, x, y } => self.file_drop_inner(paths, x, y).await,
+                    InputEvent::Scroll { delta_x, delta_y } => {
+                        let (x, y) = self.layout_engine.read().await.scroll_position();
+                        self.scroll_to_inner(x + delta_x as f32, y + delta_y as f32)
+                            .await
+                    }
                 }
-                _ => Ok(()),
-            }
-        })
-        .await
+            })
+            .await;
+        self.frame_watchdog
+            .record(FramePhase::InputDispatch, started.elapsed());
+        result
+    }
+
+    /// Deliver a key press and report who handled it. The page gets a
+    /// cancelable `keydown` at the focused element (or `<body>`), then the
+    /// focused editable element takes text, Backspace and arrows, then
+    /// spatial navigation arrows and Enter; only what is left reaches the
+    /// accelerators.
+    pub async fn press_key(&self, key: &str, modifiers: Modifiers) -> Result<KeyRoute> {
+        self.run_safe(self.key_press_inner(key, modifiers)).await
+    }
+
+    /// Bind `keystroke` (such as `Ctrl+Shift+R`) to `action_id`, replacing
+    /// what it was bound to, built-ins included. Triggering it emits
+    /// [`BrowserEvent::AcceleratorTriggered`].
+    pub fn register_accelerator(&self, keystroke: &str, action_id: &str) -> Result<()> {
+        self.accelerators.register(keystroke, action_id)?;
+        Ok(())
+    }
+
+    /// Unbind `keystroke`; keys it matched go unhandled.
+    pub fn unregister_accelerator(&self, keystroke: &str) -> Result<()> {
+        self.accelerators.unregister(keystroke)?;
+        Ok(())
+    }
+
+    /// Add `css` to every document whose URL matches one of
+    /// `options.origins`, from the next one loaded on. See
+    /// [`crate::core::user_content`] for the patterns and cascade levels.
+    pub fn add_user_stylesheet(
+        &self,
+        css: &str,
+        options: UserStyleOptions,
+    ) -> Result<UserContentId> {
+        Ok(self.user_content.add_stylesheet(css, options)?)
+    }
+
+    /// Run `js` in every document whose URL matches one of
+    /// `options.origins`, from the next one loaded on, at `options.run_at`
+    /// and in `options.world`. A script that throws is reported as
+    /// [`BrowserEvent::UserScriptError`] and does not fail the load.
+    pub fn add_user_script(&self, js: &str, options: UserScriptOptions) -> Result<UserContentId> {
+        Ok(self.user_content.add_script(js, options)?)
+    }
+
+    /// Remove a user stylesheet or script. `false` when `id` names none.
+    pub fn remove_user_content(&self, id: UserContentId) -> bool {
+        self.user_content.remove(id)
+    }
+
+    /// Replace what is on the clipboard, as the platform's clipboard
+    /// changing does. See [`crate::core::clipboard`] for what pages see.
+    pub fn write_clipboard(&self, items: Vec<ClipboardItem>) {
+        self.clipboard.write(items);
+    }
+
+    /// What is on the clipboard, such as what a page wrote, for the
+    /// embedder to hand the platform's clipboard.
+    pub fn read_clipboard(&self) -> Vec<ClipboardItem> {
+        self.clipboard.read()
+    }
+
+    /// Put `bitmap` on the clipboard by itself.
+    pub fn write_clipboard_image(&self, bitmap: ClipboardBitmap) -> Result<()> {
+        if bitmap.rgba.len() as u64 != u64::from(bitmap.width) * u64::from(bitmap.height) * 4 {
+            return Err(BrowserError::Clipboard(format!(
+                "{} bytes is not {}x{} RGBA",
+                bitmap.rgba.len(),
+                bitmap.width,
+                bitmap.height
+            )));
+        }
+        self.clipboard.write(vec![ClipboardItem::image(bitmap)]);
+        Ok(())
+    }
+
+    /// The first image on the clipboard.
+    pub fn read_clipboard_image(&self) -> Option<ClipboardBitmap> {
+        match self.clipboard.find(clipboard::IMAGE_PNG) {
+            Some(ClipboardData::Image(bitmap)) => Some(bitmap),
+            _ => None,
+        }
+    }
+
+    /// Paste what is on the clipboard, as Ctrl+V does. See
+    /// [`paste_inner`](Self::paste_inner).
+    pub async fn paste(&self) -> Result<()> {
+        self.run_safe(self.paste_inner()).await
     }
 
     pub async fn enable_chrome_api(&self, api_name: &str) -> Result<()> {
         // Use a read lock (assume API injectors take &self). If they require &mut,
         // consider redesigning JSRuntime to split mutable/async parts.
         self.run_safe(async move {
-            if !self.config.enable_chrome_apis {
-                return Err(BrowserError::Platform(
-                    "Chrome APIs not enabled".to_string(),
-                ));
+            if !self.config.read().enable_chrome_apis {
+                return Err(BrowserError::FeatureDisabled("chrome_apis"));
             }
             let rt = self.js_runtime.read().await;
             match api_name {
@@ -529,6 +1971,12 @@ impl BrowserEngine {
         .await
     }
 
+    /// Whether `api_name` was enabled with [`Self::enable_chrome_api`] for
+    /// the current page; a navigation drops them all.
+    pub async fn is_chrome_api_enabled(&self, api_name: &str) -> bool {
+        self.js_runtime.read().await.is_api_injected(api_name)
+    }
+
     pub async fn set_user_agent(&self, user_agent: &str) -> Result<()> {
         self.run_safe(async move {
             if user_agent.trim().is_empty() {
@@ -536,289 +1984,4245 @@ impl BrowserEngine {
                     "user_agent must not be empty".to_string(),
                 ));
             }
-            // Persist for future requests by updating NetworkManager if it exposes setter.
-            // For now, accept and no-op (avoids lying).
+            // It goes out as a header value.
+            if user_agent.chars().any(char::is_control) {
+                return Err(BrowserError::Platform(
+                    "user_agent must not contain control characters".to_string(),
+                ));
+            }
+            self.page().network_manager.set_user_agent(user_agent);
+            self.config.write().user_agent = user_agent.to_string();
             Ok(())
         })
         .await
     }
 
-    pub async fn clear_cache(&self) -> Result<()> {
-        // No caches exposed; succeed deterministically.
-        Ok(())
+    /// The `User-Agent` requests are sent with: [`BrowserConfig::user_agent`]
+    /// until [`set_user_agent`](Self::set_user_agent) replaces it.
+    pub fn user_agent(&self) -> String {
+        self.page().network_manager.user_agent()
     }
 
-    pub async fn get_current_url(&self) -> Option<String> {
-        let document = self.document.read().await;
-        document.get_url().map(|s| s.to_string())
+    /// Drop the cached data `kind` names, from memory and from disk.
+    pub async fn clear_cache(&self, kind: CacheKind) -> Result<()> {
+        self.run_safe(async move {
+            if matches!(kind, CacheKind::All | CacheKind::Http) {
+                self.page().network_manager.clear_cache();
+            }
+            if matches!(kind, CacheKind::All | CacheKind::ScriptCache) {
+                self.js_runtime.read().await.clear_code_cache()?;
+            }
+            if matches!(kind, CacheKind::All | CacheKind::Cookies) {
+                self.page().network_manager.clear_cookies();
+            }
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn get_page_title(&self) -> Option<String> {
-        let document = self.document.read().await;
-        Some(document.get_title())
+    /// The node painted at viewport point `(x, y)`, skipping content
+    /// clipped away by an overflow clip, including rounded-off corners,
+    /// and nodes with `pointer-events: none`. Text nodes are hits too; see
+    /// [`Self::element_at`] for the element.
+    pub async fn hit_test(&self, x: f32, y: f32) -> Result<Option<NodeId>> {
+        self.run_safe(async move {
+            self.create_layout_tree().await?;
+            Ok(self.page().hit_regions.nodes_at(x, y).into_iter().next())
+        })
+        .await
     }
 
-    pub async fn is_loading(&self) -> bool {
-        *self.is_loading_flag.read().await
+    /// The element [`Self::element_at`] finds at `(x, y)` followed by its
+    /// ancestors up to the document, the path an event dispatched there
+    /// bubbles along. Empty when nothing is hit.
+    pub async fn hit_test_path(&self, x: f32, y: f32) -> Result<Vec<NodeId>> {
+        self.run_safe(async move {
+            let hit = self.element_at_inner(x, y).await?;
+            let document = self.document.read().await;
+            Ok(std::iter::successors(hit, |&node| document.get_parent(node)).collect())
+        })
+        .await
     }
 
-    pub async fn install_pwa(&self, manifest_url: &str) -> Result<()> {
+    /// The topmost element at viewport point `(x, y)`, the one a click
+    /// there is dispatched to; text counts as its element. `None` outside
+    /// the viewport.
+    pub async fn element_at(&self, x: f32, y: f32) -> Result<Option<NodeId>> {
+        self.run_safe(async move { self.element_at_inner(x, y).await })
+            .await
+    }
+
+    /// Every element at viewport point `(x, y)`, topmost first, as
+    /// `document.elementsFromPoint` lists them.
+    pub async fn elements_at(&self, x: f32, y: f32) -> Result<Vec<NodeId>> {
+        self.run_safe(async move { self.elements_at_inner(x, y).await })
+            .await
+    }
+
+    /// Give keyboard and IME focus to the first element matching `selector`.
+    /// Returns `false` when nothing matches or the match is not editable.
+    pub async fn focus_element(&self, selector: &str) -> Result<bool> {
         self.run_safe(async move {
-            if let Some(pwa_manager) = &self.pwa_manager {
-                let manifest_content = self.network_manager.fetch(manifest_url).await?;
-                let manifest: crate::pwa::manifest::Manifest =
-                    serde_json::from_str(&manifest_content).map_err(|e| {
-                        BrowserError::Platform(format!("Failed to parse manifest: {e}"))
-                    })?;
-                let _ = pwa_manager.install_app(&manifest).await?;
-                Ok(())
-            } else {
-                Err(BrowserError::Platform(
-                    "PWA functionality not enabled".to_string(),
-                ))
-            }
+            let document = self.document.read().await;
+            let node = document
+                .query_selector(selector)
+                .map_err(|e| BrowserError::Document(e.to_string()))?;
+            let mut editing = self.editing.write().await;
+            Ok(match node {
+                Some(node) => editing.focus(&document, node),
+                None => false,
+            })
         })
         .await
     }
 
-    pub async fn register_service_worker(&self, script_url: &str) -> Result<()> {
+    /// Select chars `anchor` to `focus` of the text in the focused
+    /// `contenteditable` element, as dragging across them does; editing
+    /// commands and typing act on the selection. Returns `false` without
+    /// such an element.
+    pub async fn select_text(&self, anchor: usize, focus: usize) -> Result<bool> {
         self.run_safe(async move {
-            if let Some(pwa_manager) = &self.pwa_manager {
-                let _ = pwa_manager
-                    .register_service_worker(script_url, None)
-                    .await?;
-                Ok(())
-            } else {
-                Err(BrowserError::Platform(
-                    "PWA functionality not enabled".to_string(),
-                ))
-            }
+            let document = self.document.read().await;
+            Ok(self.editing.write().await.select(&document, anchor, focus))
         })
         .await
     }
 
-    pub async fn shutdown(&self) -> Result<()> {
-        self.run_safe(async {
-            {
-                let mut shutdown_guard = self.is_shutdown.write().await;
-                if *shutdown_guard {
-                    return Ok(());
-                }
-                *shutdown_guard = true;
+    /// The text selected in the focused `contenteditable` element.
+    pub async fn selected_text(&self) -> String {
+        let document = self.document.read().await;
+        self.editing.read().await.selected_text(&document)
+    }
+
+    /// Switch spatial navigation on or off, as
+    /// [`BrowserConfig::enable_spatial_navigation`] does at startup.
+    /// Switching it off drops the focus it gave and its focus ring.
+    pub async fn set_spatial_navigation(&self, enabled: bool) -> Result<()> {
+        self.spatial_navigation.set_enabled(enabled);
+        self.run_safe(self.repaint_overlay()).await
+    }
+
+    /// The element keys go to: the one spatial navigation moved to, else
+    /// the focused editable element.
+    pub async fn focused_element(&self) -> Option<NodeId> {
+        match self.spatial_navigation.focused() {
+            Some(focused) => Some(focused),
+            None => self.editing.read().await.focused(),
+        }
+    }
+
+    /// The element an arrow key moving `direction` would focus in spatial
+    /// navigation, without moving there, as `spatialNavigationSearch`
+    /// answers; `None` when nothing lies that way. It answers with the mode
+    /// off too.
+    pub async fn spatial_navigation_search(
+        &self,
+        direction: spatial_navigation::Direction,
+    ) -> Option<NodeId> {
+        self.spatial_move(direction).await.map(|step| step.target)
+    }
+
+    /// Where the spatial navigation focus ring is painted: around the
+    /// focused element's border box, in viewport coordinates. `None` with
+    /// the mode off or nothing focused.
+    pub async fn focus_ring(&self) -> Option<Rect> {
+        if !self.spatial_navigation.is_enabled() {
+            return None;
+        }
+        let focused = self.focused_element().await?;
+        if !self.document.read().await.is_connected(focused) {
+            return None;
+        }
+        let layout_box = self.layout_engine.read().await.get_layout_box(focused)?;
+        Some(spatial_navigation::border_box(&layout_box))
+    }
+
+    /// Viewport rect of the caret in the focused element, for placing IME
+    /// candidate windows.
+    pub async fn caret_rect(&self) -> Option<Rect> {
+        let document = self.document.read().await;
+        let editing = self.editing.read().await;
+        let target = editing.focused()?;
+        let layout_box = self.layout_engine.read().await.get_layout_box(target)?;
+
+        let style = self.extract_style(
+            self.page()
+                .style_engine
+                .get_computed_styles(target)
+                .as_deref(),
+        );
+        let line_height = style.font_size * 1.2;
+        let before = editing.text_before_caret(&document);
+        let offset = self.text_width(&style, &before);
+
+        Some(Rect {
+            x: layout_box.content_x + offset,
+            y: layout_box.content_y,
+            width: 1.0,
+            height: line_height,
+        })
+    }
+
+    /// Where to draw the ghost of the element being dragged: that element
+    /// and its border box, kept at the offset it was grabbed at from the
+    /// pointer. `None` unless an element of the page is being dragged.
+    pub async fn drag_image(&self) -> Option<(NodeId, Rect)> {
+        let session = self.page().drag.session()?;
+        let image = session.image?;
+        let layout_box = self.layout_engine.read().await.get_layout_box(image.node)?;
+        Some((
+            image.node,
+            Rect {
+                x: session.pointer.0 - image.offset_x,
+                y: session.pointer.1 - image.offset_y,
+                width: layout_box.border_box_width(),
+                height: layout_box.border_box_height(),
+            },
+        ))
+    }
+
+    pub async fn get_current_url(&self) -> Option<String> {
+        let document = self.document.read().await;
+        document.get_url().map(|s| s.to_string())
+    }
+
+    pub async fn get_page_title(&self) -> Option<String> {
+        let document = self.document.read().await;
+        Some(document.get_title())
+    }
+
+    /// Whether the current page came over HTTPS, and whether everything it
+    /// loaded since did with certificates that verified.
+    pub fn get_security_state(&self) -> SecurityState {
+        self.page().network_manager.security_state()
+    }
+
+    /// The current page's description, theme color and Open Graph fields.
+    pub async fn get_page_metadata(&self) -> PageMetadata {
+        self.announce_metadata_changes().await;
+        self.page().page_metadata.metadata()
+    }
+
+    /// The current page's icon closest to `preferred_size` pixels, fitted
+    /// to that square; SVG icons are drawn at it. Falls back to the
+    /// origin's `/favicon.ico` when no declared icon loads.
+    pub async fn get_favicon(&self, preferred_size: u32) -> Option<DecodedImage> {
+        self.announce_metadata_changes().await;
+        let (candidates, loader) = self
+            .page()
+            .page_metadata
+            .ranked_icons(preferred_size, false);
+        let (_, image) = loader?.load_first(&candidates, preferred_size).await?;
+        Some(image)
+    }
+
+    /// The current document as markup: as it is now, with script changes,
+    /// or as it was parsed.
+    pub async fn serialize_document(&self, options: SerializeOptions) -> Result<String> {
+        let document = self.document.read().await;
+        document
+            .serialize_with(options)
+            .map_err(|e| BrowserError::Document(e.to_string()))
+    }
+
+    /// Deterministic JSON dump of the current layout tree, for golden tests.
+    ///
+    /// Nodes appear in document order and are addressed by child-index path
+    /// (`"0/1/2"`) rather than `NodeId`; coordinates are rounded to two decimals
+    /// and only the painted style subset is included.
+    pub async fn dump_layout_tree(&self) -> serde_json::Value {
+        let document = self.document.read().await;
+        let layout_engine = self.layout_engine.read().await;
+
+        let mut nodes = Vec::new();
+        if let Some(root) = document.get_root_node() {
+            self.dump_layout_node(&document, &layout_engine, root, "0", &mut nodes);
+        }
+
+        serde_json::json!({ "nodes": nodes })
+    }
+
+    /// Computed styles of every element matching `selector`, in document order,
+    /// with properties sorted by name.
+    pub async fn dump_computed_styles(&self, selector: &str) -> Result<serde_json::Value> {
+        let document = self.document.read().await;
+        let matches: std::collections::HashSet<NodeId> = document
+            .query_selector_all(selector)
+            .map_err(|e| BrowserError::Document(e.to_string()))?
+            .into_iter()
+            .collect();
+
+        let mut ordered = Vec::new();
+        if let Some(root) = document.get_root_node() {
+            Self::collect_paths(&document, root, "0".to_string(), &mut ordered);
+        }
+
+        let elements: Vec<serde_json::Value> = ordered
+            .into_iter()
+            .filter(|(_, node_id)| matches.contains(node_id))
+            .map(|(path, node_id)| {
+                let tag = document
+                    .get_node(node_id)
+                    .map(|node| node.read().tag_name.to_ascii_lowercase())
+                    .unwrap_or_default();
+                let properties: std::collections::BTreeMap<String, String> = self
+                    .page()
+                    .style_engine
+                    .get_computed_styles(node_id)
+                    .map(|styles| {
+                        styles
+                            .get_all_properties()
+                            .into_iter()
+                            .map(|(name, value)| (name, Self::computed_value_to_dump(&value)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                serde_json::json!({ "path": path, "tag": tag, "styles": properties })
+            })
+            .collect();
+
+        Ok(serde_json::Value::Array(elements))
+    }
+
+    /// The viewport as the software rasterizer draws the last frame, with
+    /// the devtools overlay.
+    pub async fn snapshot(&self) -> Snapshot {
+        self.renderer.read().await.snapshot()
+    }
+
+    /// Draw the debug overlays `flags` names over the page, repainting
+    /// now; [`DebugOverlayFlags::NONE`] turns them off.
+    pub async fn set_debug_overlays(&self, flags: DebugOverlayFlags) -> Result<()> {
+        let settings = DebugOverlaySettings {
+            flags,
+            ..*self.debug_overlays.read()
+        };
+        self.run_safe(self.update_debug_overlays(settings)).await
+    }
+
+    pub fn debug_overlays(&self) -> DebugOverlayFlags {
+        self.debug_overlays.read().flags
+    }
+
+    /// Outline only `root` and the boxes under it; `None` outlines every
+    /// box.
+    pub async fn set_debug_outline_root(&self, root: Option<NodeId>) -> Result<()> {
+        let settings = DebugOverlaySettings {
+            outline_root: root,
+            ..*self.debug_overlays.read()
+        };
+        self.run_safe(self.update_debug_overlays(settings)).await
+    }
+
+    /// What the debug overlays drew over the last frame, in paint order.
+    pub async fn debug_overlay_quads(&self) -> Vec<DrawQuad> {
+        self.renderer.read().await.debug_overlay_quads().to_vec()
+    }
+
+    /// Run one devtools protocol command, as a client of
+    /// [`Self::serve_devtools`] would.
+    pub async fn devtools_command(&self, command: DevtoolsCommand) -> Result<serde_json::Value> {
+        self.run_safe(self.devtools_command_inner(command)).await
+    }
+
+    /// Devtools notifications since the last call, for embedders that run
+    /// commands with [`Self::devtools_command`] rather than serving them.
+    pub async fn take_devtools_notifications(&self) -> Vec<serde_json::Value> {
+        let document = self.document.read().await;
+        self.devtools.take_notifications(&document)
+    }
+
+    /// Serve the devtools protocol over WebSocket on `listener`, which must
+    /// be bound to a loopback address. Clients are served one at a time.
+    /// Runs until the engine shuts down, so drive it alongside
+    /// [`Self::tick`].
+    pub async fn serve_devtools(&self, listener: tokio::net::TcpListener) -> Result<()> {
+        self.run_safe(self.serve_devtools_inner(listener)).await
+    }
+
+    /// Events from now on, as they happen. A receiver that falls more than
+    /// a thousand or so behind misses the oldest and is told how many with
+    /// [`broadcast::error::RecvError::Lagged`]; the engine never waits for
+    /// one, and one dropped is simply no longer sent to.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<BrowserEvent> {
+        self.event_sender.subscribe()
+    }
+
+    /// Recorded events newer than `since_seq`, oldest first, optionally
+    /// restricted to the kinds in `filter`. Pass the last `seq` seen to poll.
+    pub fn get_recent_events(
+        &self,
+        since_seq: Option<u64>,
+        filter: Option<EventKindMask>,
+    ) -> Vec<TimestampedEvent> {
+        self.event_log.recent(since_seq, filter)
+    }
+
+    /// The event log as JSON, for bug reports.
+    pub fn dump_recent_events(&self) -> serde_json::Value {
+        self.event_log.to_json()
+    }
+
+    pub fn clear_recent_events(&self) {
+        self.event_log.clear();
+    }
+
+    /// Shared handle to the event log, e.g. for writing it out from a panic hook.
+    pub fn event_log(&self) -> Arc<EventLog> {
+        self.event_log.clone()
+    }
+
+    /// The engine's Web Storage, e.g. to inspect or clear an origin's
+    /// `localStorage`, or to derive the key of an embedded frame.
+    pub fn web_storage(&self) -> Arc<WebStorage> {
+        self.web_storage.clone()
+    }
+
+    /// The configuration the engine was made with, as setters such as
+    /// [`set_user_agent`](Self::set_user_agent) have since changed it.
+    pub fn config(&self) -> BrowserConfig {
+        self.config.read().clone()
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.config.read().private_mode
+    }
+
+    pub async fn is_loading(&self) -> bool {
+        *self.is_loading_flag.read().await
+    }
+
+    /// Stop the navigation under way, if any: its document fetch is cancelled
+    /// and it ends with a [`BrowserEvent::NavigationCancelled`] rather than a
+    /// `PageLoaded`, its `load_url` failing with
+    /// [`BrowserError::NavigationCancelled`].
+    pub async fn stop(&self) {
+        let cancelled = self.navigation.lock().current.take();
+        if let Some(cancelled) = cancelled {
+            self.cancel_navigation(cancelled).await;
+        }
+    }
+
+    /// Manifest URL advertised by the current page, if PWA support is enabled.
+    pub async fn get_manifest_url(&self) -> Option<String> {
+        self.manifest_url.read().await.clone()
+    }
+
+    pub async fn install_pwa(&self, manifest_url: &str) -> Result<()> {
+        self.run_safe(async move {
+            if let Some(pwa_manager) = &self.pwa_manager {
+                // The page's own offer goes through its prompt, so the page
+                // hears how it went.
+                if let Some(offer) = self.page().install_prompts.take_for(manifest_url) {
+                    self.install_offered(offer).await?;
+                    return Ok(());
+                }
+                let manifest_content = self.page().network_manager.fetch(manifest_url).await?;
+                let manifest = pwa_manager.parse_manifest(&manifest_content, manifest_url)?;
+                let _ = pwa_manager.install_app(&manifest).await?;
+                Ok(())
+            } else {
+                Err(BrowserError::FeatureDisabled("pwa"))
+            }
+        })
+        .await
+    }
+
+    /// Install the app the page offered, as if the page had prompted for
+    /// it. Returns the new app's id.
+    pub async fn accept_install_prompt(&self) -> Result<String> {
+        self.run_safe(async move {
+            if self.pwa_manager.is_none() {
+                return Err(BrowserError::FeatureDisabled("pwa"));
+            }
+            match self.page().install_prompts.take() {
+                Some(offer) => self.install_offered(offer).await,
+                None => Err(BrowserError::PWA(
+                    "The page has no install offer".to_string(),
+                )),
+            }
+        })
+        .await
+    }
+
+    /// Installed apps related to the current page: those whose scope
+    /// covers it, or whose start URL is the one its manifest names.
+    pub async fn get_installed_related_apps(&self) -> Vec<InstalledApp> {
+        let pwa_manager = match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager,
+            None => return Vec::new(),
+        };
+        let page_url = match self.get_current_url().await {
+            Some(url) => url,
+            None => return Vec::new(),
+        };
+        let start_url = self
+            .page()
+            .install_prompts
+            .current()
+            .map(|offer| offer.manifest.start_url);
+        pwa_manager
+            .get_installed_apps()
+            .await
+            .into_iter()
+            .filter(|app| {
+                let scope = app
+                    .manifest
+                    .scope
+                    .as_deref()
+                    .unwrap_or(&app.manifest.start_url);
+                page_url.starts_with(scope) || start_url.as_ref() == Some(&app.manifest.start_url)
+            })
+            .collect()
+    }
+
+    pub async fn register_service_worker(&self, script_url: &str) -> Result<()> {
+        self.run_safe(async move {
+            if let Some(pwa_manager) = &self.pwa_manager {
+                let _ = pwa_manager
+                    .register_service_worker(script_url, None)
+                    .await?;
+                self.announce_notifications().await;
+                // The worker may be what the page was missing.
+                self.evaluate_install_criteria().await;
+                Ok(())
+            } else {
+                Err(BrowserError::FeatureDisabled("pwa"))
+            }
+        })
+        .await
+    }
+
+    /// The service worker registrations, those restored from a previous
+    /// run included.
+    pub async fn get_service_worker_registrations(&self) -> Vec<ServiceWorkerRegistration> {
+        match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager.service_worker_registrations().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Unregister the service worker registration of `scope`, an absolute
+    /// URL. It is not restored on the next start; the pages it controls
+    /// stay controlled until they unload. Returns whether there was one.
+    pub async fn unregister_service_worker(&self, scope: &str) -> Result<bool> {
+        self.run_safe(async move {
+            match &self.pwa_manager {
+                Some(pwa_manager) => Ok(pwa_manager.unregister_service_worker(scope).await?),
+                None => Err(BrowserError::FeatureDisabled("pwa")),
+            }
+        })
+        .await
+    }
+
+    /// The push subscriptions service workers made with
+    /// `registration.pushManager.subscribe()`, those restored from a
+    /// previous run included.
+    pub async fn get_push_subscriptions(&self) -> Vec<PushSubscription> {
+        match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager.push_subscriptions().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Hand over a push message the embedder's push service connection
+    /// received. `origin_or_subscription_id` is the id of one of
+    /// [`BrowserEngine::get_push_subscriptions`], or an origin whose
+    /// subscriptions are each tried. `payload` is the message body,
+    /// encrypted as RFC 8291 describes; it is decrypted and given to the
+    /// subscription's service worker as a `push` event, starting the
+    /// worker if need be. Notifications it shows come out as
+    /// [`BrowserEvent::NotificationRequested`]. Returns whether the worker
+    /// handled the message successfully.
+    pub async fn deliver_push_message(
+        &self,
+        origin_or_subscription_id: &str,
+        payload: &[u8],
+    ) -> Result<bool> {
+        self.run_safe(async move {
+            let pwa_manager = self
+                .pwa_manager
+                .as_ref()
+                .ok_or(BrowserError::FeatureDisabled("pwa"))?;
+            let handled = pwa_manager
+                .deliver_push_message(origin_or_subscription_id, payload)
+                .await;
+            self.announce_notifications().await;
+            Ok(handled?)
+        })
+        .await
+    }
+
+    /// The push service invalidated the subscription `subscription_id`. It
+    /// is forgotten and its service worker gets `pushsubscriptionchange`.
+    /// Returns the subscription the worker made in its place, if any.
+    pub async fn expire_push_subscription(
+        &self,
+        subscription_id: &str,
+    ) -> Result<Option<PushSubscription>> {
+        self.run_safe(async move {
+            let pwa_manager = self
+                .pwa_manager
+                .as_ref()
+                .ok_or(BrowserError::FeatureDisabled("pwa"))?;
+            let renewed = pwa_manager.expire_push_subscription(subscription_id).await;
+            self.announce_notifications().await;
+            Ok(renewed?)
+        })
+        .await
+    }
+
+    /// Fire the background sync the service worker of `scope` registered
+    /// as `tag`, when the embedder judges the connection it waits for is
+    /// back. Returns whether the worker's `sync` handler succeeded; a tag
+    /// that failed stays registered for another attempt, unless this was
+    /// its `last_chance`. The tags waiting are in the registration's
+    /// `sync_tags`.
+    pub async fn fire_background_sync(
+        &self,
+        scope: &str,
+        tag: &str,
+        last_chance: bool,
+    ) -> Result<bool> {
+        self.run_safe(async move {
+            let pwa_manager = self
+                .pwa_manager
+                .as_ref()
+                .ok_or(BrowserError::FeatureDisabled("pwa"))?;
+            let synced = pwa_manager.dispatch_sync(scope, tag, last_chance).await;
+            self.announce_notifications().await;
+            Ok(synced?)
+        })
+        .await
+    }
+
+    pub async fn shutdown(&self) -> Result<()> {
+        self.run_safe(async {
+            {
+                let mut shutdown_guard = self.is_shutdown.write().await;
+                if *shutdown_guard {
+                    return Ok(());
+                }
+                *shutdown_guard = true;
+            }
+
+            if let Some(pwa) = &self.pwa_manager {
+                pwa.shutdown().await?;
+            }
+
+            if let Some(_sandbox) = &self.sandbox_manager {
+                // Add sandbox shutdown when API is available.
+            }
+
+            self.page().speech.reset(None);
+            for (_, prerendered) in self.prerenders.take_all() {
+                prerendered.discard_page().await;
+            }
+
+            // Shutdown JS runtime first (drops isolates/contexts)
+            {
+                let rt = self.js_runtime.read().await;
+                rt.shutdown().await?;
+            }
+
+            // Shutdown network manager
+            self.page().network_manager.shutdown().await?;
+
+            self.web_storage.end_session();
+
+            if let Some(window) = self.window.write().await.take() {
+                window
+                    .presenter
+                    .shutdown()
+                    .await
+                    .map_err(|e| BrowserError::Render(e.to_string()))?;
+            }
+            if let Some(Some(offscreen)) = self.offscreen.write().await.take() {
+                offscreen
+                    .shutdown()
+                    .await
+                    .map_err(|e| BrowserError::Render(e.to_string()))?;
+            }
+
+            // Dispose V8 global state exactly once (handled internally with Once)
+            crate::js_engine::v8_binding::V8Runtime::dispose_v8();
+
+            Ok(())
+        })
+        .await
+    }
+
+    // -------- Internal implementations (unsafeguarded; always call via run_safe) --------
+
+    async fn load_url_inner(&self, url: String) -> Result<()> {
+        let navigation = self.begin_navigation(&url).await;
+        let result = self.run_navigation(url.clone(), navigation, None).await;
+        self.end_navigation(navigation, &url, result).await
+    }
+
+    /// Replace the document with what script wrote to it after its scripts
+    /// ran, as `document.open()` does: it is torn down and the written
+    /// markup loaded in its place as a navigation to the same URL, without
+    /// a history entry. Announced first with a
+    /// [`BrowserEvent::DocumentReopened`].
+    async fn reopen_written_document(&self) -> Result<()> {
+        let Some(markup) = self.page().document_writes.take_reopened() else {
+            return Ok(());
+        };
+        let url = self
+            .document
+            .read()
+            .await
+            .get_url()
+            .unwrap_or_else(|| "about:blank".to_string());
+        self.emit_event(BrowserEvent::DocumentReopened { url: url.clone() })
+            .await;
+        let navigation = self.begin_navigation(&url).await;
+        let result = self
+            .run_navigation(url.clone(), navigation, Some(markup))
+            .await;
+        self.end_navigation(navigation, &url, result).await
+    }
+
+    /// Make the navigation to `url` the one under way, cancelling the one
+    /// it supersedes.
+    async fn begin_navigation(&self, url: &str) -> u64 {
+        let (navigation, superseded) = {
+            let mut slot = self.navigation.lock();
+            slot.next_id += 1;
+            let navigation = slot.next_id;
+            (
+                navigation,
+                slot.current.replace(CurrentNavigation {
+                    id: navigation,
+                    url: url.to_string(),
+                    request: None,
+                }),
+            )
+        };
+        if let Some(superseded) = superseded {
+            self.cancel_navigation(superseded).await;
+        }
+        navigation
+    }
+
+    /// Stop the fetch of `cancelled`'s document, if under way; the page's
+    /// other requests carry on.
+    async fn cancel_navigation(&self, cancelled: CurrentNavigation) {
+        if let Some(request) = &cancelled.request {
+            self.page().network_manager.cancel_request(request).await;
+        }
+        *self.is_loading_flag.write().await = false;
+        self.emit_event(BrowserEvent::NavigationCancelled { url: cancelled.url })
+            .await;
+    }
+
+    /// Fails once `navigation` is no longer the one under way.
+    fn check_navigation(&self, navigation: u64, url: &str) -> Result<()> {
+        let current = self
+            .navigation
+            .lock()
+            .current
+            .as_ref()
+            .map(|current| current.id);
+        if current == Some(navigation) {
+            Ok(())
+        } else {
+            Err(BrowserError::NavigationCancelled(url.to_string()))
+        }
+    }
+
+    /// `navigation` ended with `result`; whatever it was, a navigation
+    /// cancelled meanwhile ends cancelled.
+    async fn end_navigation(&self, navigation: u64, url: &str, result: Result<()>) -> Result<()> {
+        let current = {
+            let mut slot = self.navigation.lock();
+            let current = slot
+                .current
+                .as_ref()
+                .is_some_and(|current| current.id == navigation);
+            if current {
+                slot.current = None;
+            }
+            current
+        };
+        if !current {
+            return Err(BrowserError::NavigationCancelled(url.to_string()));
+        }
+        if result.is_err() {
+            *self.is_loading_flag.write().await = false;
+        }
+        result
+    }
+
+    /// Load `url` as `navigation`; with `written`, the markup a reopened
+    /// document was given, instead of fetching it.
+    async fn run_navigation(
+        &self,
+        url: String,
+        navigation: u64,
+        written: Option<String>,
+    ) -> Result<()> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        let reopening = written.is_some();
+        if !reopening {
+            if let Some((_, prerendered)) = self.prerenders.take_url(&url) {
+                return self.activate_prerendered(&prerendered, url).await;
+            }
+        }
+        let page = self.page();
+
+        // The fragment directive is the browser's: the document, its
+        // history entry and its scripts get the URL without it.
+        let (url, text_directives) = if self.config.read().enable_text_fragments {
+            text_fragment::split_fragment_directive(&url)
+        } else {
+            (url, Vec::new())
+        };
+
+        self.emit_event(BrowserEvent::NavigationStarted { url: url.clone() })
+            .await;
+        *self.is_loading_flag.write().await = true;
+
+        let start_time = std::time::Instant::now();
+        self.navigation_timings.begin(&url);
+
+        self.dispatch_pagehide().await;
+
+        // `view-source:` wraps another URL; fetch that and list its markup instead of parsing it.
+        let (is_view_source, target) = match url.strip_prefix("view-source:") {
+            Some(target) if target.starts_with("view-source:") => {
+                return Err(BrowserError::Platform(
+                    "Nested view-source: URLs are not supported".to_string(),
+                ));
+            }
+            Some(target) => (true, target),
+            None => (false, url.as_str()),
+        };
+
+        let mut document_url = target.to_string();
+        let mut csp_header = None;
+        let mut content_language = None;
+        self.record_phase(&url, NavigationPhase::Fetch);
+        self.navigation_timings.mark(NavigationMark::FetchStart);
+        page.network_manager.begin_page();
+        // A document that arrives whole is reported once, in full.
+        let arrived = |content: &str| {
+            let event = BrowserEvent::LoadProgress {
+                url: url.clone(),
+                bytes: content.len() as u64,
+                total: Some(content.len() as u64),
+            };
+            // Sending fails only when nobody is subscribed.
+            let _ = self.event_sender.send(event);
+        };
+        let content = if let Some(markup) = written {
+            arrived(&markup);
+            markup
+        } else if let Some(rest) = target.strip_prefix("data:") {
+            let content = self.decode_data_url_document(rest)?;
+            arrived(&content);
+            content
+        } else if let Some(response) = self.service_worker_response(target, is_view_source).await {
+            let header = |name: &str| {
+                response
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.clone())
+            };
+            if self.prerendering
+                && header("cache-control").is_some_and(|value| cache_control_no_store(&value))
+            {
+                return Err(BrowserError::NotPrerenderable(format!(
+                    "{} is Cache-Control: no-store",
+                    target
+                )));
+            }
+            csp_header = header("content-security-policy");
+            content_language = header("content-language");
+            let content = String::from_utf8(response.body)
+                .map_err(|e| NetworkError::Protocol(format!("Invalid UTF-8: {}", e)))?;
+            arrived(&content);
+            content
+        } else {
+            // The preload scanner starts subresource fetches as the markup
+            // arrives; a source listing loads nothing.
+            let mut preloader = Preloader::new(page.network_manager.clone());
+            let mut timing = ResponseStartObserver::new(&self.navigation_timings, &mut preloader);
+            let mut observer = LoadProgressObserver {
+                inner: (!is_view_source).then_some(&mut timing as &mut dyn BodyObserver),
+                sender: &self.event_sender,
+                url: url.clone(),
+                bytes: 0,
+                reported: 0,
+                total: None,
+            };
+            let fetch = page
+                .network_manager
+                .fetch_navigation_with_handle(target, &mut observer);
+            if !self.navigation.lock().set_request(navigation, &fetch.id) {
+                return Err(BrowserError::NavigationCancelled(url.clone()));
+            }
+            let response = fetch.future.await;
+            self.check_navigation(navigation, &url)?;
+            // A pin failure is reported whether or not it failed the load.
+            if !self.prerendering {
+                self.announce_security_changes().await;
+            }
+            let response = response?;
+            let no_store = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("cache-control"))
+                .is_some_and(|(_, value)| cache_control_no_store(value));
+            if self.prerendering && no_store {
+                return Err(BrowserError::NotPrerenderable(format!(
+                    "{} is Cache-Control: no-store",
+                    response.url
+                )));
+            }
+            document_url = response.url.clone();
+            csp_header = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-security-policy"))
+                .map(|(_, value)| value.clone());
+            content_language = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-language"))
+                .map(|(_, value)| value.clone());
+            if is_view_source {
+                // Re-decode the raw bytes with the detected charset so the listing matches the wire.
+                response.decode_text()
+            } else {
+                String::from_utf8(response.body)
+                    .map_err(|e| NetworkError::Protocol(format!("Invalid UTF-8: {}", e)))?
+            }
+        };
+
+        // A newer navigation may have taken over while the document arrived;
+        // the page it loads is not to be replaced by this one's.
+        self.check_navigation(navigation, &url)?;
+
+        // Parse HTML (or build the source listing) and update document
+        self.record_phase(&url, NavigationPhase::Parse);
+        self.navigation_timings.mark(NavigationMark::ResponseEnd);
+        {
+            // Wrappers of the old tree must start throwing before its nodes go.
+            let rt = self.js_runtime.read().await;
+            rt.teardown_document_api().await?;
+            // The new document runs in its origin's agent. A source listing
+            // runs no script and is opaque, as is a document whose address
+            // does not parse.
+            let agent_url = url::Url::parse(&document_url)
+                .ok()
+                .filter(|_| !is_view_source);
+            rt.navigate_agent(agent_url.as_ref())?;
+            page.features
+                .reset(self.features.for_document(agent_url.as_ref()));
+            self.navigation_timings
+                .set_document_features(page.features.current().names());
+            let document = self.document.write().await;
+            document.teardown();
+            page.content_limit_breaches.clear();
+            if is_view_source {
+                build_view_source(&document, &content, &url)
+                    .map_err(|e| BrowserError::Document(e.to_string()))?;
+            } else {
+                let breaches = document
+                    .parse_html_with_limits(&content, self.config.read().content_limits)
+                    .map_err(|e| BrowserError::Document(e.to_string()))?;
+                page.content_limit_breaches.record(breaches);
+            }
+            document.set_url(url.clone());
+            document.set_content_language(content_language.as_deref());
+        }
+        self.editing.write().await.blur();
+        self.spatial_navigation.blur();
+        page.fonts.clear();
+        page.script_fetches.reset();
+        // A request of the old document goes unanswered; nobody is left to
+        // receive `afterprint`.
+        page.print_requests.cancel();
+        page.document_writes.reset();
+        // Nothing the old document queued is spoken on the new one's behalf.
+        page.speech.reset(
+            url::Url::parse(&document_url)
+                .ok()
+                .filter(|_| !is_view_source),
+        );
+        page.image_animations.clear();
+        page.images.clear();
+        page.install_prompts.reset();
+        self.switch_service_worker_client(&document_url, is_view_source)
+            .await;
+        page.text_highlight.clear();
+        page.validation_reports.take();
+        page.drag.reset();
+        page.touches.lock().clear();
+        page.scroll.take_request();
+        page.user_activation.reset();
+        page.file_grants.revoke_all();
+        self.layout_engine.read().await.forget_document();
+        if !self.prerendering {
+            self.devtools
+                .document_replaced(&*self.document.read().await);
+            self.update_overlay().await;
+        }
+        // A source listing's links are markup, not stylesheets, and its
+        // `<video>`s are text.
+        // User content applies to the page, not to a listing of its source.
+        let user_content_url = url::Url::parse(&document_url)
+            .ok()
+            .filter(|_| !is_view_source);
+        let initiator = match url::Url::parse(&document_url) {
+            Ok(base) if !is_view_source => Some(
+                RequestInitiator::new(base)
+                    .with_csp(csp_header.as_deref().map(ContentSecurityPolicy::parse)),
+            ),
+            _ => None,
+        };
+        *page.request_initiator.write() = initiator.clone();
+        page.stylesheets.reset(initiator.clone().map(|initiator| {
+            Arc::new(
+                StylesheetLoader::new(page.network_manager.clone(), initiator)
+                    .with_content_limits(
+                        self.config.read().content_limits,
+                        page.content_limit_breaches.clone(),
+                    )
+                    .with_features(page.features.clone()),
+            )
+        }));
+        page.media.reset(
+            initiator.clone().map(|initiator| {
+                Arc::new(MediaLoader::new(page.network_manager.clone(), initiator))
+            }),
+        );
+        page.page_metadata.reset(
+            &*self.document.read().await,
+            initiator.clone().map(|initiator| {
+                Arc::new(FaviconLoader::new(page.network_manager.clone(), initiator))
+            }),
+        );
+        page.live_regions.reset(&*self.document.read().await);
+
+        // Manifest discovery is part of the PWA subsystem; skip it entirely when disabled.
+        *self.manifest_url.write().await = if self.pwa_manager.is_some() && !is_view_source {
+            let document = self.document.read().await;
+            find_manifest_link(&document)
+        } else {
+            None
+        };
+
+        // A reopened document keeps the history entry it had.
+        if !reopening {
+            self.push_history(&url).await;
+        }
+
+        // Style and layout
+        self.navigation_timings.mark(NavigationMark::DomInteractive);
+        self.record_phase(&url, NavigationPhase::StyleAndLayout);
+        {
+            let document_guard = self.document.read().await;
+            self.start_stylesheet_loads(&document_guard, true);
+            self.start_media_loads(&document_guard);
+            let loading = self.config.read().stylesheet_loading.clone();
+            if loading.fouc_control == FoucControl::BlockUntilBudget {
+                let threshold = loading
+                    .stylesheet_timeout_ms
+                    .min(loading.render_blocking_budget_ms);
+                for late in page.stylesheets.wait_for_render_blocking(&loading).await {
+                    tracing::warn!(
+                        "Painting {} without {} after {:?}",
+                        url,
+                        late.url,
+                        late.waited
+                    );
+                    self.emit_event(BrowserEvent::PerformanceWarning {
+                        metric: "render_blocking_stylesheet_ms".to_string(),
+                        value: late.waited.as_secs_f64() * 1000.0,
+                        threshold: threshold as f64,
+                        url: Some(late.url.to_string()),
+                        context: Some(self.document_context(&document_guard)),
+                    })
+                    .await;
+                }
+            }
+            let (blocking, fetch_time) = page.stylesheets.render_blocking_loads();
+            self.navigation_timings
+                .set_blocking_stylesheets(blocking, fetch_time);
+            self.navigation_timings
+                .mark(NavigationMark::StylesheetsReady);
+            // Whatever arrived so far is applied below.
+            page.stylesheets.take_restyle_needed();
+            self.report_stylesheet_failures().await;
+            let author_rules = self.collect_style_rules(&document_guard);
+            self.report_content_limit_breaches(&document_guard).await;
+            self.report_disabled_features(&document_guard).await;
+            page.style_engine.set_stylesheets(author_rules.clone());
+            let (user_rules, injected_rules) = match &user_content_url {
+                Some(url) => self.user_content.stylesheets_for(url),
+                None => Default::default(),
+            };
+            page.style_engine
+                .set_user_stylesheets(user_rules, injected_rules);
+
+            // Compute styles (sync)
+            page.style_engine
+                .compute_styles(&document_guard)
+                .map_err(|e| BrowserError::Style(e.to_string()))?;
+            self.navigation_timings.mark(NavigationMark::StyleDone);
+
+            // Compute layout (async)
+            {
+                let layout_engine = self.layout_engine.write().await;
+                layout_engine
+                    .compute_layout(&document_guard, &page.style_engine)
+                    .await
+                    .map_err(|e| BrowserError::Layout(e.to_string()))?;
+                self.navigation_timings
+                    .set_layout_node_count(layout_engine.box_count());
+            }
+            self.navigation_timings.mark(NavigationMark::LayoutDone);
+
+            // Execute JavaScript (async); a source listing never runs the viewed page's scripts.
+            if !is_view_source {
+                self.record_phase(&url, NavigationPhase::Scripts);
+                // Script may hit test before the first frame is painted.
+                {
+                    let layout_engine = self.layout_engine.read().await;
+                    let mut layout_tree = LayoutTree::new();
+                    if let Some(root) = document_guard.get_root_node() {
+                        self.build_layout_tree(
+                            &document_guard,
+                            &layout_engine,
+                            root,
+                            &ClipChain::new(),
+                            &mut layout_tree,
+                        );
+                    }
+                    self.update_hit_regions(&layout_tree, &layout_engine);
+                    self.update_script_scroll(&document_guard, &layout_engine);
+                }
+                let rt = self.js_runtime.read().await;
+                rt.inject_document_api(&document_guard).await?;
+                rt.inject_document_write_api(page.document_writes.clone())
+                    .await?;
+                if self.prerendering {
+                    rt.set_page_state(true, true).await?;
+                }
+                rt.inject_hit_test_api(page.hit_regions.clone()).await?;
+                rt.inject_scroll_api(page.scroll.clone()).await?;
+                rt.inject_form_api(page.validation_reports.clone()).await?;
+                rt.inject_editing_api(
+                    self.editing.clone(),
+                    self.clipboard.clone(),
+                    page.user_activation.clone(),
+                )
+                .await?;
+                rt.inject_drag_api(page.drag.clone(), page.file_grants.clone())
+                    .await?;
+                rt.inject_navigation_timing_api(self.navigation_timings.clone())
+                    .await?;
+                if let Ok(document_url) = url::Url::parse(&document_url) {
+                    let initiator = RequestInitiator::new(document_url.clone())
+                        .with_csp(csp_header.as_deref().map(ContentSecurityPolicy::parse));
+                    rt.inject_network_api(
+                        page.network_manager.clone(),
+                        initiator.clone(),
+                        page.script_fetches.clone(),
+                    )
+                    .await?;
+                    rt.inject_wasm_api(initiator.csp.as_deref()).await?;
+                    rt.inject_clipboard_api(
+                        self.clipboard.clone(),
+                        self.permissions.clone(),
+                        page.network_manager.blob_store().clone(),
+                        document_url.clone(),
+                    )
+                    .await?;
+
+                    // A top-level document is always first-party; opaque
+                    // origins get throwaway areas nobody else can reach.
+                    let storage_key = StorageKey::for_url(&document_url);
+                    let (local, session) = if document_url.origin().is_tuple() {
+                        (
+                            self.web_storage.local(&storage_key),
+                            self.web_storage.session(&storage_key),
+                        )
+                    } else {
+                        (
+                            Arc::new(StorageArea::in_memory(storage_key.clone())),
+                            Arc::new(StorageArea::in_memory(storage_key)),
+                        )
+                    };
+                    rt.inject_storage_api(local, session).await?;
+                    rt.inject_print_api(page.print_requests.clone()).await?;
+                    rt.inject_media_api(page.media.clone()).await?;
+                    rt.inject_speech_api(page.speech.clone()).await?;
+                    if self.pwa_manager.is_some() {
+                        rt.inject_install_api(page.install_prompts.clone()).await?;
+                    }
+
+                    // Start web font loads before binding `document.fonts`
+                    // so its `ready` promise waits for them.
+                    let loader = Arc::new(FontLoader::new(page.network_manager.clone(), initiator));
+                    page.fonts.add_rules(&author_rules, &document_url);
+                    page.fonts.load_all(&loader);
+                    rt.inject_font_api(page.fonts.clone(), loader).await?;
+                }
+                rt.apply_features(&page.features.current()).await?;
+                self.run_user_scripts(&rt, user_content_url.as_ref(), RunAt::DocumentStart)
+                    .await;
+                self.run_document_scripts(&rt, &document_guard, initiator.as_ref())
+                    .await;
+                self.run_user_scripts(&rt, user_content_url.as_ref(), RunAt::DocumentEnd)
+                    .await;
+                if let Err(e) = rt.fire_dom_content_loaded().await {
+                    tracing::debug!("DOMContentLoaded dispatch failed: {}", e);
+                }
+                // What the page's own scripts put in its live regions as it
+                // loaded is not news.
+                page.live_regions.clear();
+            }
+            self.navigation_timings
+                .mark(NavigationMark::DomContentLoaded);
+
+            // Render the page; a prerendered one renders once activated.
+            self.record_phase(&url, NavigationPhase::Render);
+            if !self.prerendering {
+                let layout_tree = self.create_layout_tree().await?;
+                self.navigation_timings
+                    .mark(NavigationMark::LayoutTreeBuilt);
+                {
+                    let mut renderer = self.renderer.write().await;
+                    renderer.render(&document_guard, &layout_tree).await?;
+                }
+                self.navigation_timings.mark(NavigationMark::FirstRender);
+            }
+        }
+        if !is_view_source && !text_directives.is_empty() && !self.prerendering {
+            self.scroll_to_text_fragment(&text_directives).await?;
+        }
+
+        // `PageLoaded` waits for the document's images, as `load` does.
+        if let Some(initiator) = initiator {
+            self.load_images(&initiator).await;
+            // Loaded and broken images have their size now.
+            if page.images.take_layout_dirty() && !self.prerendering {
+                self.relayout().await?;
+            }
+        }
+        {
+            let rt = self.js_runtime.read().await;
+            self.run_user_scripts(&rt, user_content_url.as_ref(), RunAt::DocumentIdle)
+                .await;
+        }
+
+        self.check_navigation(navigation, &url)?;
+        *self.is_loading_flag.write().await = false;
+
+        let load_time = start_time.elapsed().as_millis() as u64;
+        let timing = self
+            .navigation_timings
+            .finish(page.network_manager.page_resource_counts());
+        self.emit_event(BrowserEvent::PageLoaded {
+            url,
+            load_time_ms: load_time,
+        })
+        .await;
+        if let Some(timing) = timing {
+            self.emit_event(BrowserEvent::NavigationTiming {
+                tab: self.tab_id,
+                timing: Box::new(timing),
+            })
+            .await;
+        }
+        // What a prerendered page tells the embedder waits for activation.
+        if self.prerendering {
+            return Ok(());
+        }
+        self.announce_security_changes().await;
+        self.announce_audible_change().await;
+        // Icons load after the page, as they don't hold up `load`.
+        self.announce_metadata_changes().await;
+        // Navigating is what checks the controlling worker for updates.
+        if let (Some(pwa_manager), false) = (&self.pwa_manager, is_view_source) {
+            if let Ok(document_url) = url::Url::parse(&document_url) {
+                pwa_manager.update_for_navigation(&document_url).await;
+            }
+        }
+        self.announce_notifications().await;
+        self.evaluate_install_criteria().await;
+
+        Ok(())
+    }
+
+    /// What a service worker answers a navigation to `target` with. A
+    /// source listing shows what the network has.
+    async fn service_worker_response(
+        &self,
+        target: &str,
+        is_view_source: bool,
+    ) -> Option<FetchResponse> {
+        let pwa_manager = self.pwa_manager.as_ref().filter(|_| !is_view_source)?;
+        let url = url::Url::parse(target).ok()?;
+        pwa_manager.handle_navigation(&url).await
+    }
+
+    /// Make the new document a service worker client, then let go of the
+    /// old one. In that order, a page replaced by another in the same
+    /// scope never leaves its registration without a client, so a waiting
+    /// worker waits for the scope's pages to go rather than for a reload.
+    async fn switch_service_worker_client(&self, document_url: &str, is_view_source: bool) {
+        let pwa_manager = match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager,
+            None => return,
+        };
+        let client = match url::Url::parse(document_url) {
+            Ok(url) if !is_view_source => pwa_manager.open_client(&url).await,
+            _ => None,
+        };
+        let previous = std::mem::replace(&mut *self.service_worker_client.write().await, client);
+        if let Some(previous) = previous {
+            pwa_manager.close_client(previous).await;
+        }
+    }
+
+    /// Let the outgoing page queue its beacons; those outlive the navigation.
+    async fn dispatch_pagehide(&self) {
+        if self.document.read().await.get_url().is_none() {
+            return;
+        }
+        let rt = self.js_runtime.read().await;
+        if let Err(e) = rt
+            .execute(
+                "typeof dispatchEvent === 'function' && \
+                 dispatchEvent({ type: 'pagehide', persisted: false })",
+            )
+            .await
+        {
+            tracing::debug!("pagehide dispatch failed: {}", e);
+        }
+    }
+
+    async fn push_history(&self, url: &str) {
+        let mut history = self.history.write().await;
+        let mut idx = self.history_index.write().await;
+        match *idx {
+            Some(i) if i + 1 < history.len() => {
+                history.truncate(i + 1);
+                history.push(url.to_string());
+                *idx = Some(i + 1);
+            }
+            Some(i) if i + 1 == history.len() => {
+                history.push(url.to_string());
+                *idx = Some(i + 1);
+            }
+            Some(_) => {}
+            None => {
+                history.push(url.to_string());
+                *idx = Some(0);
+            }
+        }
+    }
+
+    async fn prerender_inner(&self, url: String) -> Result<PrerenderHandle> {
+        if !self.prerenders.is_enabled() {
+            return Err(BrowserError::FeatureDisabled("prerendering"));
+        }
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        if let Some(handle) = self.prerenders.find(&url) {
+            return Ok(handle);
+        }
+        let handle = self.prerenders.new_handle(&url);
+        #[allow(clippy::arc_with_non_send_sync)]
+        let prerendered = Arc::new(self.prerender_engine().await?);
+        let loaded = AssertUnwindSafe(prerendered.load_url_inner(url.clone()))
+            .catch_unwind()
+            .await;
+        let reason = match loaded {
+            Ok(Ok(())) => {
+                let bytes = prerendered.page_bytes().await;
+                let evicted = self
+                    .prerenders
+                    .insert(handle.clone(), prerendered, bytes as usize);
+                for (evicted, prerendered) in evicted {
+                    prerendered.discard_page().await;
+                    self.emit_event(BrowserEvent::PrerenderDiscarded {
+                        tab: self.tab_id,
+                        url: evicted.url().to_string(),
+                        reason: PrerenderDiscardReason::Evicted,
+                    })
+                    .await;
+                }
+                return Ok(handle);
+            }
+            Ok(Err(BrowserError::NotPrerenderable(_))) => PrerenderDiscardReason::NoStore,
+            Ok(Err(e)) => {
+                tracing::debug!("Prerendering {} failed: {}", url, e);
+                PrerenderDiscardReason::LoadFailed
+            }
+            Err(_) => PrerenderDiscardReason::LoadFailed,
+        };
+        prerendered.discard_page().await;
+        self.emit_event(BrowserEvent::PrerenderDiscarded {
+            tab: self.tab_id,
+            url,
+            reason,
+        })
+        .await;
+        Ok(handle)
+    }
+
+    /// A hidden engine to prerender a page of this tab in. It draws with
+    /// the tab's renderer and shares its storage, permissions, user content
+    /// and embedder hooks, with a document, runtime and page of its own. Its
+    /// events go to a log of its own, which activation passes on to the tab.
+    async fn prerender_engine(&self) -> Result<BrowserEngine> {
+        let config = self.config.read().clone();
+        let current = self.page();
+        let page = Page::new(
+            &config,
+            Arc::new(current.network_manager.for_other_page()),
+            &self.permissions,
+        );
+        // Sound and speech wait for activation; the page's media still
+        // goes to the embedder, muted.
+        page.media
+            .set_playback_handler(current.media.playback_handler());
+        page.fonts
+            .set_shaping_observer(current.fonts.shaping_observer());
+        page.audio.set_muted(true);
+        let layout_engine = page.layout_engine(&config);
+        let (width, height) = self.layout_engine.read().await.viewport_size();
+        layout_engine
+            .resize_viewport(width as u32, height as u32)
+            .await
+            .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        #[allow(clippy::arc_with_non_send_sync)]
+        let js_runtime = Arc::new(RwLock::new(JSRuntime::new(&config).await?));
+        let document = Document::new();
+        document.set_compaction_ratio(config.dom_compaction_ratio);
+        Ok(BrowserEngine {
+            renderer: self.renderer.clone(),
+            js_runtime,
+            document: Arc::new(RwLock::new(document)),
+            layout_engine: Arc::new(RwLock::new(layout_engine)),
+            sandbox_manager: self.sandbox_manager.clone(),
+            pwa_manager: self.pwa_manager.clone(),
+            is_shutdown: self.is_shutdown.clone(),
+            page: parking_lot::RwLock::new(Arc::new(page)),
+            history: Arc::new(RwLock::new(Vec::new())),
+            history_index: Arc::new(RwLock::new(None)),
+            is_loading_flag: Arc::new(RwLock::new(false)),
+            navigation: parking_lot::Mutex::new(NavigationSlot::default()),
+            manifest_url: Arc::new(RwLock::new(None)),
+            service_worker_client: Arc::new(RwLock::new(None)),
+            user_content: self.user_content.clone(),
+            editing: Arc::new(RwLock::new(EditingSession::new())),
+            spatial_navigation: Arc::new(SpatialNavigation::new(config.enable_spatial_navigation)),
+            event_log: Arc::new(EventLog::new(config.event_log_capacity)),
+            event_sender: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            navigation_timings: Arc::new(NavigationTimings::new(config.navigation_timing_history)),
+            web_storage: self.web_storage.clone(),
+            paint_sources: self.paint_sources.clone(),
+            permissions: self.permissions.clone(),
+            features: self.features.clone(),
+            pressed: Arc::new(RwLock::new(None)),
+            clipboard: self.clipboard.clone(),
+            accelerators: self.accelerators.clone(),
+            devtools: Arc::new(Inspector::new()),
+            // A prerendered page prerenders nothing itself.
+            #[allow(clippy::arc_with_non_send_sync)]
+            prerenders: Arc::new(PrerenderSet::new(&PrerenderConfig {
+                max_prerenders: 0,
+                ..config.prerender.clone()
+            })),
+            prerendering: true,
+            frame_watchdog: Arc::new(FrameWatchdog::new(&config.frame_budget)),
+            config: parking_lot::RwLock::new(config),
+            tab_id: self.tab_id,
+            sampled_cpu_us: Arc::new(AtomicU64::new(0)),
+            error_handler: Arc::new(RwLock::new(None)),
+            announcement_handler: parking_lot::RwLock::new(None),
+            debug_overlays: self.debug_overlays.clone(),
+            window: RwLock::new(None),
+            offscreen: RwLock::new(None),
+        })
+    }
+
+    /// What the current page takes: script heap, DOM and decoded images.
+    async fn page_bytes(&self) -> u64 {
+        self.get_performance_metrics()
+            .await
+            .contexts
+            .iter()
+            .map(|context| {
+                context.js_heap_bytes + context.dom_bytes_estimate + context.image_cache_bytes
+            })
+            .sum()
+    }
+
+    /// Let go of the current page as navigating away does, for an engine
+    /// that goes away with it: one whose prerender was discarded, or that
+    /// was left the page its activation replaced.
+    async fn discard_page(&self) {
+        {
+            let rt = self.js_runtime.read().await;
+            if let Err(e) = rt.teardown_document_api().await {
+                tracing::debug!("Tearing down a discarded page's API failed: {}", e);
+            }
+        }
+        self.document.read().await.teardown();
+        let page = self.page();
+        page.fonts.clear();
+        page.script_fetches.reset();
+        page.print_requests.cancel();
+        page.document_writes.reset();
+        page.speech.reset(None);
+        page.image_animations.clear();
+        page.images.clear();
+        page.media.reset(None);
+        page.stylesheets.reset(None);
+        page.network_manager.begin_page();
+        let client = self.service_worker_client.write().await.take();
+        if let (Some(pwa_manager), Some(client)) = (&self.pwa_manager, client) {
+            pwa_manager.close_client(client).await;
+        }
+    }
+
+    /// Show the page `prerendered` loaded for `url`: swap its document,
+    /// runtime, layout and page in, and the current ones out to be torn
+    /// down. Only the first render is left to do, so the navigation's
+    /// timing has no fetch, parse, style, layout or script phases.
+    async fn activate_prerendered(&self, prerendered: &BrowserEngine, url: String) -> Result<()> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        let (url, text_directives) = if self.config.read().enable_text_fragments {
+            text_fragment::split_fragment_directive(&url)
+        } else {
+            (url, Vec::new())
+        };
+        self.emit_event(BrowserEvent::NavigationStarted { url: url.clone() })
+            .await;
+        // What the page reported while hidden reaches the tab now; its
+        // navigation is reported again as the activation's own.
+        let navigation = EventKindMask::NAVIGATION | EventKindMask::NAVIGATION_TIMING;
+        for entry in prerendered.event_log.recent(None, None) {
+            if navigation.intersects(entry.event.kind()) {
+                continue;
+            }
+            if let LoggedEvent::Browser { event } = entry.event {
+                self.emit_event(event).await;
+            }
+        }
+        *self.is_loading_flag.write().await = true;
+        let start_time = std::time::Instant::now();
+        self.navigation_timings.begin(&url);
+        self.dispatch_pagehide().await;
+        self.navigation_timings.mark(NavigationMark::FetchStart);
+        self.record_phase(&url, NavigationPhase::Render);
+
+        let viewport = self.layout_engine.read().await.viewport_size();
+        std::mem::swap(
+            &mut *self.js_runtime.write().await,
+            &mut *prerendered.js_runtime.write().await,
+        );
+        std::mem::swap(
+            &mut *self.document.write().await,
+            &mut *prerendered.document.write().await,
+        );
+        std::mem::swap(
+            &mut *self.layout_engine.write().await,
+            &mut *prerendered.layout_engine.write().await,
+        );
+        std::mem::swap(
+            &mut *self.editing.write().await,
+            &mut *prerendered.editing.write().await,
+        );
+        self.spatial_navigation.blur();
+        std::mem::swap(
+            &mut *self.pressed.write().await,
+            &mut *prerendered.pressed.write().await,
+        );
+        std::mem::swap(
+            &mut *self.manifest_url.write().await,
+            &mut *prerendered.manifest_url.write().await,
+        );
+        std::mem::swap(
+            &mut *self.service_worker_client.write().await,
+            &mut *prerendered.service_worker_client.write().await,
+        );
+        std::mem::swap(&mut *self.page.write(), &mut *prerendered.page.write());
+        self.page().adopt_tab_settings(&prerendered.page());
+        // Its live regions changed while no one could hear them.
+        self.page().live_regions.clear();
+        prerendered.discard_page().await;
+
+        self.push_history(&url).await;
+        self.devtools
+            .document_replaced(&*self.document.read().await);
+        if self.layout_engine.read().await.viewport_size() != viewport {
+            let document = self.document.read().await;
+            let layout_engine = self.layout_engine.write().await;
+            layout_engine
+                .resize_viewport(viewport.0 as u32, viewport.1 as u32)
+                .await
+                .map_err(|e| BrowserError::Layout(e.to_string()))?;
+            layout_engine
+                .compute_layout(&document, &self.page().style_engine)
+                .await
+                .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        }
+        {
+            let rt = self.js_runtime.read().await;
+            if let Err(e) = rt.set_page_state(false, false).await {
+                tracing::debug!("Activating the prerendered page's script failed: {}", e);
+            }
+        }
+
+        let layout_tree = self.create_layout_tree().await?;
+        self.navigation_timings
+            .mark(NavigationMark::LayoutTreeBuilt);
+        {
+            let document = self.document.read().await;
+            let mut renderer = self.renderer.write().await;
+            renderer.render(&document, &layout_tree).await?;
+        }
+        self.navigation_timings.mark(NavigationMark::FirstRender);
+        self.update_overlay().await;
+        if !text_directives.is_empty() {
+            self.scroll_to_text_fragment(&text_directives).await?;
+        }
+        *self.is_loading_flag.write().await = false;
+
+        let timing = self
+            .navigation_timings
+            .finish(self.page().network_manager.page_resource_counts());
+        self.emit_event(BrowserEvent::PageLoaded {
+            url,
+            load_time_ms: start_time.elapsed().as_millis() as u64,
+        })
+        .await;
+        if let Some(timing) = timing {
+            self.emit_event(BrowserEvent::NavigationTiming {
+                tab: self.tab_id,
+                timing: Box::new(timing),
+            })
+            .await;
+        }
+        self.announce_security_changes().await;
+        self.announce_audible_change().await;
+        self.announce_metadata_changes().await;
+        let document_url = self.document.read().await.get_url();
+        if let (Some(pwa_manager), Some(document_url)) = (&self.pwa_manager, document_url) {
+            if let Ok(document_url) = url::Url::parse(&document_url) {
+                pwa_manager.update_for_navigation(&document_url).await;
+            }
+        }
+        self.announce_notifications().await;
+        self.evaluate_install_criteria().await;
+        Ok(())
+    }
+
+    /// Run the timers and deliver the fetches and messages of prerendered
+    /// pages, each at most once per
+    /// [`PRERENDER_TIMER_INTERVAL`](crate::core::prerender::PRERENDER_TIMER_INTERVAL).
+    /// Nothing of theirs is painted, and animation frames wait for
+    /// activation.
+    async fn tick_prerenders(&self) {
+        for prerendered in self.prerenders.timers_due(std::time::Instant::now()) {
+            let rt = prerendered.js_runtime.read().await;
+            let page = prerendered.page();
+            let ran = async {
+                let font_events = page.fonts.take_events();
+                if !font_events.is_empty() {
+                    rt.deliver_font_events(&font_events).await?;
+                }
+                for event in page.media.take_events() {
+                    rt.dispatch_element_event(event.node, &event.to_json())
+                        .await?;
+                }
+                let fetches = page.script_fetches.take_settled();
+                if !fetches.is_empty() {
+                    rt.deliver_fetches(&fetches).await?;
+                }
+                rt.deliver_messages()?;
+                rt.run_timers().await?;
+                rt.reclaim_dom_nodes().await.map(drop)
+            };
+            if let Err(e) = ran.await {
+                tracing::debug!("Prerendered page's timers failed: {}", e);
+            }
+        }
+    }
+
+    fn decode_data_url_document(&self, rest: &str) -> Result<String> {
+        if !self.config.read().allow_data_urls {
+            return Err(BrowserError::Security("Scheme 'data' not allowed".into()));
+        }
+        let (mime, bytes) = parse_data_url(rest)
+            .map_err(|e| BrowserError::Security(format!("Invalid data: URL - {e}")))?;
+        if bytes.len() > self.config.read().max_data_url_bytes {
+            return Err(BrowserError::Security("data: payload too large".into()));
+        }
+        let allowed = self
+            .config
+            .read()
+            .allowed_data_mime_prefixes
+            .iter()
+            .any(|p| mime.starts_with(p));
+        if !allowed {
+            return Err(BrowserError::Security(format!("Blocked data: MIME {mime}")));
+        }
+        if mime.starts_with("text/html")
+            || mime.starts_with("text/plain")
+            || mime == "application/xhtml+xml"
+        {
+            Ok(String::from_utf8(bytes)
+                .unwrap_or_else(|_| "<!doctype html><title>Invalid UTF-8</title>".to_string()))
+        } else {
+            Err(BrowserError::Security(format!(
+                "Top-level data: MIME not renderable: {mime}"
+            )))
+        }
+    }
+
+    /// The bytes of an `<img>`'s `data:` source, under the same limits as
+    /// `data:` documents.
+    fn decode_data_url_image(&self, rest: &str) -> Result<Vec<u8>> {
+        if !self.config.read().allow_data_urls {
+            return Err(BrowserError::Security("Scheme 'data' not allowed".into()));
+        }
+        let (mime, bytes) = parse_data_url(rest)
+            .map_err(|e| BrowserError::Security(format!("Invalid data: URL - {e}")))?;
+        if bytes.len() > self.config.read().max_data_url_bytes {
+            return Err(BrowserError::Security("data: payload too large".into()));
+        }
+        let allowed = self
+            .config
+            .read()
+            .allowed_data_mime_prefixes
+            .iter()
+            .any(|p| mime.starts_with(p));
+        if !allowed || !mime.starts_with("image/") {
+            return Err(BrowserError::Security(format!("Blocked data: MIME {mime}")));
+        }
+        Ok(bytes)
+    }
+
+    async fn navigate_back_inner(&self) -> Result<()> {
+        let mut idx_guard = self.history_index.write().await;
+        let history = self.history.read().await;
+
+        match *idx_guard {
+            Some(i) if i > 0 && i < history.len() => {
+                let new_i = i - 1;
+                let target = history[new_i].clone();
+                *idx_guard = Some(new_i);
+                drop(history);
+                drop(idx_guard);
+                self.load_url_inner(target).await
+            }
+            _ => Err(BrowserError::Platform("No back history".to_string())),
+        }
+    }
+
+    async fn navigate_forward_inner(&self) -> Result<()> {
+        let mut idx_guard = self.history_index.write().await;
+        let history = self.history.read().await;
+
+        match *idx_guard {
+            Some(i) if i + 1 < history.len() => {
+                let new_i = i + 1;
+                let target = history[new_i].clone();
+                *idx_guard = Some(new_i);
+                drop(history);
+                drop(idx_guard);
+                self.load_url_inner(target).await
+            }
+            _ => Err(BrowserError::Platform("No forward history".to_string())),
+        }
+    }
+
+    async fn execute_javascript_inner(&self, script: String) -> Result<serde_json::Value> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        // Use read lock; assume JSRuntime::execute takes &self
+        let result = {
+            let rt = self.js_runtime.read().await;
+            rt.execute(&script).await?
+        };
+        self.reopen_written_document().await?;
+        self.announce_print_request().await;
+        self.announce_validation_messages().await;
+        self.announce_metadata_changes().await;
+        self.announce_audible_change().await;
+        self.complete_install_prompt().await;
+        self.update_pipeline().await?;
+        self.apply_script_scroll().await?;
+        self.announce_live_regions().await;
+        Ok(result)
+    }
+
+    async fn tick_inner(&self) -> Result<()> {
+        if *self.is_shutdown.read().await {
+            return Ok(());
+        }
+        let started = std::time::Instant::now();
+        self.frame_watchdog.begin_frame();
+        let result = self.run_frame().await;
+        self.end_frame(started.elapsed()).await;
+        result
+    }
+
+    /// Report the frame `tick` just ran when it went over budget.
+    async fn end_frame(&self, elapsed: std::time::Duration) {
+        // Taken every frame, so a long task is put down to its own frame.
+        let longest = self.js_runtime.read().await.take_longest_task();
+        let Some(jank) = self.frame_watchdog.end_frame(elapsed) else {
+            return;
+        };
+        let script_url = longest
+            .filter(|_| jank.worst_offender.runs_script())
+            .and_then(|task| task.script_url);
+        self.emit_event(BrowserEvent::FrameJank {
+            total_ms: jank.total_ms,
+            phases: jank.phases,
+            worst_offender: jank.worst_offender,
+            script_url,
+        })
+        .await;
+    }
+
+    async fn run_frame(&self) -> Result<()> {
+        let page = self.page();
+        {
+            let rt = self.js_runtime.read().await;
+            let timers = std::time::Instant::now();
+            let font_events = page.fonts.take_events();
+            if !font_events.is_empty() {
+                rt.deliver_font_events(&font_events).await?;
+            }
+            for event in page.media.take_events() {
+                rt.dispatch_element_event(event.node, &event.to_json())
+                    .await?;
+            }
+            let speech_events = page.speech.take_events();
+            if !speech_events.is_empty() {
+                rt.deliver_speech_events(&speech_events).await?;
+            }
+            let fetches = page.script_fetches.take_settled();
+            if !fetches.is_empty() {
+                rt.deliver_fetches(&fetches).await?;
+            }
+            rt.deliver_messages()?;
+            rt.run_timers().await?;
+            self.frame_watchdog
+                .record(FramePhase::Timers, timers.elapsed());
+            let animation_frames = std::time::Instant::now();
+            rt.run_animation_frames().await?;
+            self.frame_watchdog
+                .record(FramePhase::AnimationFrames, animation_frames.elapsed());
+            rt.reclaim_dom_nodes().await?;
+        }
+        // A task that wrote after load replaced the page.
+        self.reopen_written_document().await?;
+        let page = self.page();
+        self.tick_prerenders().await;
+        {
+            let document = self.document.read().await;
+            if document.take_compaction().is_some() {
+                self.retain_node_tables(&document).await;
+            }
+        }
+        self.announce_print_request().await;
+        self.announce_validation_messages().await;
+        self.announce_metadata_changes().await;
+        self.announce_security_changes().await;
+        self.announce_audible_change().await;
+        self.complete_install_prompt().await;
+        if let Some(request_id) = page.print_requests.take_expired(std::time::Instant::now()) {
+            tracing::warn!("Print request {} timed out; dismissing it", request_id);
+            self.fire_afterprint().await;
+        }
+
+        self.update_rendering(&page).await?;
+        self.apply_script_scroll().await?;
+        self.announce_live_regions().await;
+        Ok(())
+    }
+
+    /// Style, lay out and paint what changed since the last frame.
+    async fn update_rendering(&self, page: &Page) -> Result<()> {
+        // Links and media script inserted, then sheets that arrived since
+        // last frame.
+        {
+            let document = self.document.read().await;
+            self.start_stylesheet_loads(&document, false);
+            self.start_media_loads(&document);
+        }
+        self.report_stylesheet_failures().await;
+        let loading = self.config.read().stylesheet_loading.clone();
+        if loading.fouc_control == FoucControl::BlockUntilBudget
+            && page.stylesheets.blocks_rendering(&loading)
+        {
+            return Ok(());
+        }
+        // Animated images stepped, or a paint source wants drawing again.
+        let frames_changed =
+            self.advance_image_animations().await | self.paint_sources.take_frame_request();
+        if page.stylesheets.take_restyle_needed() {
+            {
+                let document = self.document.read().await;
+                page.style_engine
+                    .set_stylesheets(self.collect_style_rules(&document));
+                self.report_content_limit_breaches(&document).await;
+                self.report_disabled_features(&document).await;
+            }
+            return self.relayout().await;
+        }
+        // A web font swapped in or out: text advances changed. A poster or
+        // video size arrived: media elements resize. So do images that
+        // loaded or broke.
+        if page.fonts.take_layout_dirty()
+            | page.media.take_layout_dirty()
+            | page.images.take_layout_dirty()
+        {
+            return self.relayout().await;
+        }
+        if frames_changed && !self.document.read().await.has_style_dirty_nodes() {
+            return self.repaint().await;
+        }
+        self.update_pipeline().await
+    }
+
+    /// Step the animated images an element shows in the viewport; the
+    /// others hold their frame until scrolled back into view. True when a
+    /// frame changed, so the elements showing it need painting again.
+    async fn advance_image_animations(&self) -> bool {
+        if self.page().image_animations.is_empty() {
+            return false;
+        }
+        let visible = {
+            let document = self.document.read().await;
+            let layout_engine = self.layout_engine.read().await;
+            animated_images_in_view(&document, &layout_engine, &self.page().image_animations)
+        };
+        let changed = self
+            .page()
+            .image_animations
+            .advance(std::time::Instant::now(), &visible);
+        !changed.is_empty()
+    }
+
+    /// Start loading the document's `<link rel="stylesheet">`s not seen yet.
+    /// Parser-inserted ones in `<head>` are render-blocking; otherwise only
+    /// `blocking="render"` makes them so.
+    fn start_stylesheet_loads(&self, document: &Document, parser_inserted: bool) {
+        let base = match document.base_url() {
+            Some(base) => base,
+            None => return,
+        };
+        for node_id in style_elements(document) {
+            if self.page().stylesheets.contains(node_id) {
+                continue;
+            }
+            let (href, blocking, media) = match document.get_node(node_id) {
+                Some(node) => {
+                    let node = node.read();
+                    match stylesheet_link_href(&node) {
+                        Some(href) => (
+                            href,
+                            node.get_attribute("blocking"),
+                            node.get_attribute("media"),
+                        ),
+                        None => continue,
+                    }
+                }
+                None => continue,
+            };
+            let url = match base.join(&href) {
+                Ok(url) => url,
+                Err(e) => {
+                    tracing::debug!("Ignoring stylesheet link '{}': {}", href, e);
+                    continue;
+                }
+            };
+            let explicitly_blocking = blocking.is_some_and(|tokens| {
+                tokens
+                    .split_ascii_whitespace()
+                    .any(|token| token.eq_ignore_ascii_case("render"))
+            });
+            // A sheet for other media, as `media=print`, loads without
+            // holding up the screen.
+            let media = media
+                .map(|media| crate::core::css::CSSParser::new().parse_media_list(&media))
+                .unwrap_or_default();
+            let render_blocking = self.page().style_engine.matches_media_list(&media)
+                && (explicitly_blocking || (parser_inserted && is_in_head(document, node_id)));
+            self.page().stylesheets.start(node_id, url, render_blocking);
+        }
+    }
+
+    /// Run the document's classic scripts in the order the parser reaches
+    /// them: inline and plain external ones in turn, each `async` one
+    /// between those as soon as it has loaded, then the `defer` ones in
+    /// document order, then any `async` one still loading. Scripts of
+    /// another type, `module` among them, are skipped. One that fails to
+    /// load is reported and skipped, as is one that throws. Documents
+    /// without a URL run only inline scripts.
+    ///
+    /// A script run in turn has an insertion point right after its end tag:
+    /// what it `document.write()`s is parsed there before the parser goes
+    /// on, so the scripts in it come next, nesting up to
+    /// [`MAX_WRITE_DEPTH`] deep. `async` and `defer` ones have none. The
+    /// document is closed to writes after.
+    async fn run_document_scripts(
+        &self,
+        rt: &JSRuntime,
+        document: &Document,
+        initiator: Option<&RequestInitiator>,
+    ) {
+        let page = self.page();
+        let mut deferred = Vec::new();
+        let mut loading = futures::stream::FuturesUnordered::new();
+        let mut next = 0;
+        while let Some(parser_script) = document.parser_script(next) {
+            let index = next;
+            next += 1;
+            let Some(script) = document.script(parser_script.node) else {
+                continue;
+            };
+            if !script.is_classic() {
+                tracing::debug!("Skipping a script of type {:?}", script.script_type);
+                continue;
+            }
+            let url = match (&script.src, initiator) {
+                (Some(src), Some(_)) => match document.resolve_url(src) {
+                    Some(url) => Some(url),
+                    None => {
+                        tracing::debug!("Script src {:?} is not a URL", src);
+                        continue;
+                    }
+                },
+                (Some(_), None) => continue,
+                (None, _) => None,
+            };
+            // `defer` and `async` only mean something with a `src`.
+            if let (Some(url), Some(initiator)) = (&url, initiator) {
+                if script.async_loading || script.defer_execution {
+                    // Both load while the parser goes on.
+                    page.network_manager.preload(
+                        Preload {
+                            url: url.clone(),
+                            destination: Destination::Script,
+                        },
+                        initiator,
+                    );
+                    let url = url.clone();
+                    if script.async_loading {
+                        loading.push(async move {
+                            let source = self.fetch_script(&url, initiator).await;
+                            (url, source)
+                        });
+                    } else {
+                        deferred.push(url);
+                    }
+                    continue;
+                }
+            }
+
+            while let Some(Some((url, source))) = loading.next().now_or_never() {
+                if let Some(source) = source {
+                    self.run_script(rt, &source, Some(&url), initiator).await;
+                }
+            }
+            let source = match (&url, initiator) {
+                (Some(url), Some(initiator)) => match self.fetch_script(url, initiator).await {
+                    Some(source) => source,
+                    None => continue,
+                },
+                _ => script.content,
+            };
+            page.document_writes.set_insertion_point(true);
+            self.run_script(rt, &source, url.as_ref(), initiator).await;
+            page.document_writes.set_insertion_point(false);
+            self.parse_written(document, index, parser_script.write_depth);
+        }
+        if let Some(initiator) = initiator {
+            for url in deferred {
+                if let Some(source) = self.fetch_script(&url, initiator).await {
+                    self.run_script(rt, &source, Some(&url), Some(initiator))
+                        .await;
+                }
+            }
+        }
+        while let Some((url, source)) = loading.next().await {
+            if let Some(source) = source {
+                self.run_script(rt, &source, Some(&url), initiator).await;
+            }
+        }
+        page.document_writes.close();
+        document.finish_parsing();
+    }
+
+    /// Parse what the `index`th script the parser reached wrote, right
+    /// after it; `write_depth` writes deep, it is dropped instead.
+    fn parse_written(&self, document: &Document, index: usize, write_depth: usize) {
+        let page = self.page();
+        let written = page.document_writes.take_written();
+        if written.is_empty() {
+            return;
+        }
+        if write_depth >= MAX_WRITE_DEPTH {
+            tracing::warn!(
+                "Dropped a document.write() nested {} deep, past the limit of {}",
+                write_depth + 1,
+                MAX_WRITE_DEPTH
+            );
+            return;
+        }
+        match document.write_after_script(index, &written) {
+            Ok(breaches) => page.content_limit_breaches.record(breaches),
+            Err(e) => tracing::warn!("Failed to parse written markup: {}", e),
+        }
+    }
+
+    /// Run one of the document's scripts, `script_url` naming an external
+    /// one, timing it for the navigation. A script that throws is reported.
+    async fn run_script(
+        &self,
+        rt: &JSRuntime,
+        source: &str,
+        script_url: Option<&url::Url>,
+        initiator: Option<&RequestInitiator>,
+    ) {
+        // V8 counts compile time; the rest of the run is execution.
+        let compiled_before = rt.compile_time_us();
+        let started = std::time::Instant::now();
+        let result = match initiator {
+            Some(initiator) => {
+                rt.execute_document_script(source, script_url, &initiator.document_url)
+                    .await
+            }
+            None => rt.execute(source).await,
+        };
+        let elapsed = started.elapsed();
+        let compile =
+            std::time::Duration::from_micros(rt.compile_time_us().saturating_sub(compiled_before))
+                .min(elapsed);
+        let kind = match script_url {
+            Some(_) => ScriptSource::External,
+            None => ScriptSource::Inline,
+        };
+        self.navigation_timings
+            .add_script(kind, compile, elapsed - compile);
+        if let Err(e) = result {
+            tracing::warn!("Failed to execute script: {}", e);
+            self.emit_event(BrowserEvent::JavaScriptError {
+                message: e.to_string(),
+                line: 0,
+                column: 0,
+            })
+            .await;
+        }
+    }
+
+    /// The text of the external script at `url`, or `None` when the
+    /// document's policy blocks it or the fetch fails. A failed fetch is
+    /// reported.
+    async fn fetch_script(&self, url: &url::Url, initiator: &RequestInitiator) -> Option<String> {
+        if !initiator.allows_script(url) {
+            tracing::debug!("{} violates the document's script-src policy", url);
+            return None;
+        }
+        let request = FetchRequest {
+            url: url.to_string(),
+            method: "GET".to_string(),
+            headers: std::collections::HashMap::new(),
+            body: None,
+            timeout_ms: None,
+            follow_redirects: true,
+            cache_policy: None,
+            priority: Priority::High,
+            idempotent: false,
+        };
+        let error = match self
+            .page()
+            .network_manager
+            .fetch_subresource(request, initiator)
+            .await
+        {
+            Ok(response) if (200..300).contains(&response.status) => {
+                return Some(String::from_utf8_lossy(&response.body).into_owned())
+            }
+            Ok(response) => format!("HTTP {}", response.status),
+            Err(e) => e.to_string(),
+        };
+        tracing::debug!("Script {} failed: {}", url, error);
+        self.emit_event(BrowserEvent::NetworkError {
+            url: url.to_string(),
+            error,
+        })
+        .await;
+        None
+    }
+
+    /// Fetch the document's images one after another, in the order parsing
+    /// reaches them. Fetches the preload scanner started are picked up
+    /// rather than made again; that is what overlaps them. Each ends up
+    /// decoded or broken in the page's image cache.
+    async fn load_images(&self, initiator: &RequestInitiator) {
+        let sources = image_sources(&*self.document.read().await);
+        for url in sources {
+            if self.page().images.contains(url.as_str()) {
+                continue;
+            }
+            if !initiator.allows_image(&url) {
+                tracing::debug!("{} violates the document's img-src policy", url);
+                self.page().images.set_broken(url.as_str());
+                continue;
+            }
+            if url.scheme() == "data" {
+                match self.decode_data_url_image(&url.as_str()["data:".len()..]) {
+                    Ok(bytes) => self.image_loaded(&url, &bytes).await,
+                    Err(e) => {
+                        tracing::debug!("Image {} blocked: {}", url, e);
+                        self.page().images.set_broken(url.as_str());
+                    }
+                }
+                continue;
+            }
+            let request = FetchRequest {
+                url: url.to_string(),
+                method: "GET".to_string(),
+                headers: std::collections::HashMap::new(),
+                body: None,
+                timeout_ms: None,
+                follow_redirects: true,
+                cache_policy: None,
+                priority: Priority::Low,
+                idempotent: false,
+            };
+            match self
+                .page()
+                .network_manager
+                .fetch_subresource(request, initiator)
+                .await
+            {
+                Ok(response) if !(200..300).contains(&response.status) => {
+                    tracing::debug!("Image {} failed with HTTP {}", url, response.status);
+                    self.page().images.set_broken(url.as_str());
+                }
+                Ok(response) => self.image_loaded(&url, &response.body).await,
+                Err(e) => {
+                    tracing::debug!("Image {} failed: {}", url, e);
+                    self.page().images.set_broken(url.as_str());
+                }
+            }
+        }
+    }
+
+    /// Decode the fetched bytes of `url`; an image that does not decode is
+    /// broken, and reported as a network error.
+    async fn image_loaded(&self, url: &url::Url, bytes: &[u8]) {
+        if let Err(e) = self.page().images.insert(url.as_str(), bytes) {
+            self.emit_event(BrowserEvent::NetworkError {
+                url: url.to_string(),
+                error: format!("Image failed to decode: {e}"),
+            })
+            .await;
+            return;
+        }
+        if let Err(e) = self.page().image_animations.insert(url.as_str(), bytes) {
+            tracing::debug!("Image {} failed to decode: {}", url, e);
+        }
+    }
+
+    /// Start loading the document's `<video>`s and `<audio>`s not seen yet.
+    fn start_media_loads(&self, document: &Document) {
+        for node_id in media_elements(document) {
+            if !self.page().media.contains(node_id) {
+                self.page().media.load_element(document, node_id);
+            }
+        }
+    }
+
+    /// Drop layout and style entries of nodes `document` has freed.
+    async fn retain_node_tables(&self, document: &Document) {
+        self.layout_engine.read().await.retain_nodes(document);
+        self.page().style_engine.retain_nodes(document);
+    }
+
+    /// Tell the embedder about a `window.print()` call it has not seen yet.
+    async fn announce_print_request(&self) {
+        if let Some(request_id) = self.page().print_requests.take_unannounced() {
+            self.emit_event(BrowserEvent::PrintRequested { request_id })
+                .await;
+        }
+    }
+
+    /// Focus each control interactive validation reported and tell the
+    /// embedder what to show by it.
+    async fn announce_validation_messages(&self) {
+        for report in self.page().validation_reports.take() {
+            {
+                let document = self.document.read().await;
+                self.editing.write().await.focus(&document, report.node);
+            }
+            self.emit_event(BrowserEvent::ValidationMessage {
+                node: report.node,
+                message: report.message,
+            })
+            .await;
+        }
+    }
+
+    /// Report certificate pin failures since the last call, then the page's
+    /// security state if it changed.
+    async fn announce_security_changes(&self) {
+        for description in self.page().network_manager.take_security_violations() {
+            self.emit_event(BrowserEvent::SecurityViolation { description })
+                .await;
+        }
+        if let Some(state) = self.page().network_manager.take_security_state_change() {
+            self.emit_event(BrowserEvent::SecurityStateChanged {
+                tab: self.tab_id,
+                state,
+            })
+            .await;
+        }
+    }
+
+    /// Tell the embedder the tab started or stopped being audible.
+    async fn announce_audible_change(&self) {
+        if let Some(audible) = self.page().audio.take_audible_change() {
+            self.emit_event(BrowserEvent::AudibleStateChanged {
+                tab: self.tab_id,
+                audible,
+            })
+            .await;
+        }
+    }
+
+    /// Re-read the document's metadata if script or the parser touched it,
+    /// reporting a new theme color and resolving the favicon again when its
+    /// links changed. Only declared icons are tried here; the guessed
+    /// `/favicon.ico` waits for [`Self::get_favicon`].
+    async fn announce_live_regions(&self) {
+        let announcements = {
+            let document = self.document.read().await;
+            let page = self.page();
+            page.live_regions
+                .take_announcements(&document, &page.style_engine)
+        };
+        if announcements.is_empty() {
+            return;
+        }
+        let handler = self.announcement_handler.read().clone();
+        for announcement in announcements {
+            if let Some(handler) = &handler {
+                handler(announcement.clone());
+            }
+            self.emit_event(BrowserEvent::LiveRegionAnnouncement {
+                tab: self.tab_id,
+                politeness: announcement.politeness,
+                text: announcement.text,
+            })
+            .await;
+        }
+    }
+
+    async fn announce_metadata_changes(&self) {
+        let changes = {
+            let document = self.document.read().await;
+            self.page().page_metadata.refresh(&document)
+        };
+        if let Some(color) = changes.theme_color {
+            self.emit_event(BrowserEvent::ThemeColorChanged {
+                tab: self.tab_id,
+                color,
+            })
+            .await;
+        }
+        if !changes.icons {
+            return;
+        }
+        let (candidates, loader) = self
+            .page()
+            .page_metadata
+            .ranked_icons(DEFAULT_FAVICON_SIZE, true);
+        let favicon = match loader {
+            Some(loader) => loader
+                .load_first(&candidates, DEFAULT_FAVICON_SIZE)
+                .await
+                .map(|(url, _)| url),
+            None => None,
+        };
+        if self.page().page_metadata.announce_favicon(favicon) {
+            self.emit_event(BrowserEvent::FaviconChanged { tab: self.tab_id })
+                .await;
+        }
+    }
+
+    /// Run the user scripts due at `run_at` for the document at `url`, in
+    /// the order they were added. One that throws is reported and the rest
+    /// still run.
+    async fn run_user_scripts(&self, rt: &JSRuntime, url: Option<&url::Url>, run_at: RunAt) {
+        let url = match url {
+            Some(url) => url,
+            None => return,
+        };
+        for script in self.user_content.scripts_for(url, run_at) {
+            let result = match script.world {
+                ScriptWorld::Main => rt.execute(&script.source).await,
+                ScriptWorld::Isolated => rt.execute_isolated(&script.source).await,
+            };
+            if let Err(e) = result {
+                tracing::warn!("User script {} failed on {}: {}", script.id.0, url, e);
+                self.emit_event(BrowserEvent::UserScriptError {
+                    script_id: script.id.0,
+                    url: url.to_string(),
+                    message: e.to_string(),
+                })
+                .await;
+            }
+        }
+    }
+
+    /// Offer the page for install if it meets the install criteria and
+    /// has not been offered since it loaded: fire `beforeinstallprompt` and
+    /// tell the embedder.
+    async fn evaluate_install_criteria(&self) {
+        let pwa_manager = match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager,
+            None => return,
+        };
+        if self.page().install_prompts.was_offered() {
+            return;
+        }
+        let href = match self.manifest_url.read().await.clone() {
+            Some(href) => href,
+            None => return,
+        };
+        let page_url = self.get_current_url().await;
+        let page_url = match page_url.map(|url| url::Url::parse(&url)) {
+            Some(Ok(url)) if is_potentially_trustworthy(&url) => url,
+            _ => return,
+        };
+        if !pwa_manager.is_controlled(&page_url).await {
+            return;
+        }
+        let manifest_url = match page_url.join(&href) {
+            Ok(url) => url.to_string(),
+            Err(_) => return,
+        };
+        let manifest = match self.page().network_manager.fetch(&manifest_url).await {
+            Ok(content) => match pwa_manager.parse_manifest(&content, &manifest_url) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    tracing::debug!("Manifest {} is not installable: {}", manifest_url, e);
+                    return;
+                }
+            },
+            Err(e) => {
+                tracing::debug!("Manifest {} failed to load: {}", manifest_url, e);
+                return;
+            }
+        };
+        if pwa_manager.is_installed(&manifest).await {
+            return;
+        }
+
+        let app_name = manifest.name.clone();
+        if !self.page().install_prompts.offer(InstallOffer {
+            manifest_url: manifest_url.clone(),
+            manifest,
+        }) {
+            return;
+        }
+        {
+            let rt = self.js_runtime.read().await;
+            if let Err(e) = rt.fire_before_install_prompt().await {
+                tracing::debug!("beforeinstallprompt dispatch failed: {}", e);
+            }
+        }
+        self.emit_event(BrowserEvent::PwaInstallable {
+            manifest_url,
+            app_name,
+        })
+        .await;
+    }
+
+    /// Install what the page's `prompt()` asked for.
+    async fn complete_install_prompt(&self) {
+        if let Some(offer) = self.page().install_prompts.take_requested() {
+            if let Err(e) = self.install_offered(offer).await {
+                tracing::warn!("Prompted install failed: {}", e);
+            }
+        }
+    }
+
+    /// Install `offer` and tell the page how it went.
+    async fn install_offered(&self, offer: InstallOffer) -> Result<String> {
+        let installed = match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager
+                .install_app(&offer.manifest)
+                .await
+                .map_err(BrowserError::from),
+            None => Err(BrowserError::FeatureDisabled("pwa")),
+        };
+        let rt = self.js_runtime.read().await;
+        if let Err(e) = rt.settle_install_prompt(installed.is_ok()).await {
+            tracing::debug!("Install prompt settlement failed: {}", e);
+        }
+        installed
+    }
+
+    async fn fire_afterprint(&self) {
+        let rt = self.js_runtime.read().await;
+        if let Err(e) = rt
+            .execute("typeof dispatchEvent === 'function' && dispatchEvent({ type: 'afterprint' })")
+            .await
+        {
+            tracing::debug!("afterprint dispatch failed: {}", e);
+        }
+    }
+
+    async fn resolve_print_request_inner(
+        &self,
+        request_id: u64,
+        decision: PrintDecision,
+    ) -> Result<Option<Vec<u8>>> {
+        self.page().print_requests.resolve(request_id)?;
+        let printed = match decision {
+            PrintDecision::Print(options) => self.print_to_pdf_inner(options).await.map(Some),
+            PrintDecision::Dismiss => Ok(None),
+        };
+        self.fire_afterprint().await;
+        let printed = printed?;
+        self.update_pipeline().await?;
+        Ok(printed)
+    }
+
+    async fn print_to_pdf_inner(&self, options: PrintOptions) -> Result<Vec<u8>> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+
+        let page_rule = {
+            let document = self.document.read().await;
+            PageRule::from_rules(&self.collect_style_rules(&document))
+        };
+        let (page_size, margins) = options.page_layout(page_rule.as_ref());
+        let (screen_width, screen_height) = self.layout_engine.read().await.viewport_size();
+
+        self.page().style_engine.set_media_type(MediaType::Print);
+        self.layout_engine.read().await.set_printing(true);
+        let pages = self.paginate(&options, page_size, margins).await;
+        self.layout_engine.read().await.set_printing(false);
+        self.page().style_engine.set_media_type(MediaType::Screen);
+        {
+            let layout_engine = self.layout_engine.write().await;
+            layout_engine
+                .resize_viewport(screen_width as u32, screen_height as u32)
+                .await
+                .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        }
+        self.relayout().await?;
+
+        Ok(pdf::write_pdf(&pages?))
+    }
+
+    async fn capture_screenshot_inner(&self, width: u32, height: u32) -> Result<Vec<u8>> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        if width == 0 || height == 0 {
+            return Err(BrowserError::Render(format!(
+                "Cannot capture a {}x{} screenshot",
+                width, height
+            )));
+        }
+
+        // The page is laid out and painted again at the screenshot's size,
+        // by a layout engine and a renderer of its own.
+        let config = self.config.read().clone();
+        let layout_engine = self.page().layout_engine(&config);
+        layout_engine
+            .resize_viewport(width, height)
+            .await
+            .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        let document = self.document.read().await;
+        layout_engine
+            .compute_layout(&document, &self.page().style_engine)
+            .await
+            .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        let (scroll_x, scroll_y) = self.layout_engine.read().await.scroll_position();
+        layout_engine.scroll_to(scroll_x, scroll_y, &document);
+        let layout_tree = self.layout_tree_of(&document, &layout_engine).await;
+
+        let mut renderer = VulkanRenderer::with_backend(self.renderer.read().await.backend())
+            .await
+            .map_err(|e| BrowserError::RendererInit(e.to_string()))?;
+        renderer.set_device_pixel_ratio(config.device_pixel_ratio);
+        renderer.set_debug_overlays(self.debug_overlays.read().flags);
+        renderer.set_paint_sources(self.paint_sources.clone());
+        renderer.resize(width, height).await?;
+        renderer.render(&document, &layout_tree).await?;
+        drop(document);
+
+        let quads = renderer.frame_quads();
+        let mut snapshot = match self.capture_on_gpu(&quads, width, height).await {
+            Some(snapshot) => snapshot,
+            None => renderer.snapshot(),
+        };
+        snapshot.flatten([255, 255, 255]);
+        snapshot
+            .to_png()
+            .map_err(|e| BrowserError::Render(e.to_string()))
+    }
+
+    /// `quads` drawn into an offscreen image on the GPU: the window's device
+    /// when there is a window, else one opened for screenshots. `None` when
+    /// there is no device or the draw failed, for the CPU to paint instead.
+    async fn capture_on_gpu(
+        &self,
+        quads: &[DrawQuad],
+        width: u32,
+        height: u32,
+    ) -> Option<Snapshot> {
+        let window = self.window.read().await;
+        let captured = match window.as_ref() {
+            Some(surface) => surface.presenter.capture(quads, width, height).await,
+            None => {
+                if !self.config.read().enable_gpu_acceleration {
+                    return None;
+                }
+                let mut offscreen = self.offscreen.write().await;
+                if offscreen.is_none() {
+                    let config = self.config.read().clone();
+                    let opened = vulkan::VulkanRenderer::new(&config).await;
+                    if let Err(e) = &opened {
+                        tracing::warn!("No device for offscreen screenshots: {}", e);
+                    }
+                    *offscreen = Some(opened.ok());
+                }
+                offscreen
+                    .as_ref()
+                    .and_then(Option::as_ref)?
+                    .capture(quads, width, height)
+                    .await
+            }
+        };
+        captured
+            .map_err(|e| tracing::warn!("Screenshot not drawn on the GPU: {}", e))
+            .ok()
+    }
+
+    /// Lay the document out in the page content box and cut it into pages
+    /// at content-box heights. Clips are not applied; a fragment goes on
+    /// the page its top falls on.
+    async fn paginate(
+        &self,
+        options: &PrintOptions,
+        page_size: PageSize,
+        margins: PageMargins,
+    ) -> Result<Vec<PdfPage>> {
+        let content_width = (page_size.width - margins.left - margins.right).max(1.0);
+        let content_height = (page_size.height - margins.top - margins.bottom).max(1.0);
+        {
+            let layout_engine = self.layout_engine.write().await;
+            layout_engine
+                .resize_viewport(content_width as u32, content_height as u32)
+                .await
+                .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        }
+        {
+            let document_guard = self.document.read().await;
+            self.page()
+                .style_engine
+                .compute_styles(&document_guard)
+                .map_err(|e| BrowserError::Style(e.to_string()))?;
+            let layout_engine = self.layout_engine.write().await;
+            layout_engine
+                .compute_layout(&document_guard, &self.page().style_engine)
+                .await
+                .map_err(|e| BrowserError::Layout(e.to_string()))?;
+        }
+        let layout_tree = self.create_layout_tree().await?;
+
+        let mut pages = vec![PdfPage::new(page_size.width, page_size.height)];
+        if options.print_background {
+            for node in layout_tree.get_render_nodes() {
+                let [r, g, b, a] = match node.style.background_color.as_deref() {
+                    Some(color) => parse_color(color),
+                    None => continue,
+                };
+                if a <= 0.0 {
+                    continue;
+                }
+                // A box taller than what is left of the page continues on
+                // the next one.
+                let bottom = node.bounds.y + node.bounds.height;
+                let mut y = node.bounds.y.max(0.0);
+                while y < bottom {
+                    let index = (y / content_height).floor() as usize;
+                    let page_top = index as f32 * content_height;
+                    let slice_bottom = bottom.min(page_top + content_height);
+                    print_page(&mut pages, index, page_size).fill_rect(
+                        margins.left + node.bounds.x,
+                        margins.top + y - page_top,
+                        node.bounds.width,
+                        slice_bottom - y,
+                        [r, g, b],
+                    );
+                    y = slice_bottom;
+                }
+            }
+        }
+        for node in layout_tree.get_text_nodes() {
+            let text = match node.text_content.as_deref() {
+                Some(text) => text,
+                None => continue,
+            };
+            let index = (node.bounds.y.max(0.0) / content_height).floor() as usize;
+            let baseline = decoration::baseline(&node.bounds, &node.style.font_metrics);
+            let [r, g, b, _] = parse_color(node.style.color.as_deref().unwrap_or("#000000"));
+            print_page(&mut pages, index, page_size).text(
+                margins.left + node.bounds.x,
+                margins.top + baseline - index as f32 * content_height,
+                node.style.font_size,
+                [r, g, b],
+                text,
+            );
+        }
+        Ok(pages)
+    }
+
+    /// Bring the rendering up to date with the DOM mutations made since
+    /// the last frame, however many: restyle the subtrees they touched,
+    /// lay out within the relayout boundaries around them and repaint.
+    /// Nothing happens when there were none.
+    async fn update_pipeline(&self) -> Result<()> {
+        let document_guard = self.document.read().await;
+        let dirty = document_guard.style_dirty_nodes();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        let style = std::time::Instant::now();
+        self.page()
+            .style_engine
+            .restyle(&document_guard, &dirty)
+            .map_err(|e| BrowserError::Style(e.to_string()))?;
+        self.frame_watchdog
+            .record(FramePhase::Style, style.elapsed());
+        self.lay_out_and_paint(&document_guard, Some(&dirty)).await
+    }
+
+    /// Recompute styles and layout for the current document and repaint.
+    async fn relayout(&self) -> Result<()> {
+        let document_guard = self.document.read().await;
+        let style = std::time::Instant::now();
+        self.page()
+            .style_engine
+            .compute_styles(&document_guard)
+            .map_err(|e| BrowserError::Style(e.to_string()))?;
+        self.frame_watchdog
+            .record(FramePhase::Style, style.elapsed());
+        self.lay_out_and_paint(&document_guard, None).await
+    }
+
+    /// Lay the styled document out and paint it. With `changed`, layout
+    /// keeps to what changing those nodes can move.
+    async fn lay_out_and_paint(
+        &self,
+        document_guard: &Document,
+        changed: Option<&[NodeId]>,
+    ) -> Result<()> {
+        {
+            let layout = std::time::Instant::now();
+            let layout_engine = self.layout_engine.write().await;
+            let page = self.page();
+            let style_engine = &page.style_engine;
+            match changed {
+                Some(changed) => {
+                    layout_engine
+                        .compute_layout_for_changes(document_guard, style_engine, changed)
+                        .await
+                }
+                None => {
+                    layout_engine
+                        .compute_layout(document_guard, style_engine)
+                        .await
+                }
+            }
+            .map_err(|e| BrowserError::Layout(e.to_string()))?;
+            self.frame_watchdog
+                .record(FramePhase::Layout, layout.elapsed());
+        }
+
+        let paint_started = std::time::Instant::now();
+        self.update_overlay().await;
+        self.paint(document_guard, paint_started).await
+    }
+
+    /// Build the layout tree and render it, the frame's paint having
+    /// started at `started`.
+    async fn paint(&self, document: &Document, started: std::time::Instant) -> Result<()> {
+        let layout_tree = self.create_layout_tree().await?;
+        let mut renderer = self.renderer.write().await;
+        renderer.render(document, &layout_tree).await?;
+        let submit = std::time::Duration::from_secs_f32(
+            renderer.get_frame_stats().submit_time_ms() / 1000.0,
+        );
+        self.frame_watchdog
+            .record(FramePhase::Paint, started.elapsed().saturating_sub(submit));
+        self.frame_watchdog.record(FramePhase::Submit, submit);
+        Ok(())
+    }
+
+    /// Paint over the page the text fragment highlight, the spatial
+    /// navigation focus ring and, above them, the outline of the node
+    /// devtools point at.
+    async fn update_overlay(&self) {
+        let mut quads = text_fragment::highlight_quads(&self.text_fragment_rects().await);
+        if let Some(rect) = self.focus_ring().await {
+            quads.extend(spatial_navigation::focus_ring_quads(&rect));
+        }
+        if let Some(node_id) = self.devtools.outlined() {
+            if let Some(layout_box) = self.layout_engine.read().await.get_layout_box(node_id) {
+                quads.extend(overlay::box_model_quads(&layout_box));
+            }
+        }
+        self.renderer.write().await.set_overlay(quads);
+    }
+
+    /// Where the text fragment highlight is painted, in viewport
+    /// coordinates.
+    async fn text_fragment_rects(&self) -> Vec<Rect> {
+        let nodes = self.page().text_highlight.nodes();
+        if nodes.is_empty() {
+            return Vec::new();
+        }
+        let document = self.document.read().await;
+        let layout_engine = self.layout_engine.read().await;
+        text_fragment::highlight_rects(&nodes, &document, &layout_engine)
+    }
+
+    /// Scroll the first of `directives` that matches to the middle of the
+    /// viewport and highlight it. When none does the page stays where it
+    /// is, as for a fragment naming no element.
+    async fn scroll_to_text_fragment(&self, directives: &[TextDirective]) -> Result<()> {
+        let (nodes, rect) = {
+            let document = self.document.read().await;
+            let text = PageText::collect(&document, &self.page().style_engine);
+            let nodes = match text_fragment::find_first(directives, &text) {
+                Some(nodes) => nodes,
+                None => return Ok(()),
+            };
+            let layout_engine = self.layout_engine.read().await;
+            let rects = text_fragment::highlight_rects(&nodes, &document, &layout_engine);
+            (nodes, rects.first().cloned())
+        };
+        if let Some(rect) = rect {
+            let ((x, y), (_, height)) = {
+                let layout_engine = self.layout_engine.read().await;
+                (
+                    layout_engine.scroll_position(),
+                    layout_engine.viewport_size(),
+                )
+            };
+            self.scroll_to_inner(x, y + rect.y + (rect.height - height) / 2.0)
+                .await?;
+        }
+        let scroll = self.layout_engine.read().await.scroll_position();
+        self.page().text_highlight.set(nodes, scroll);
+        self.repaint_overlay().await
+    }
+
+    /// Paint the boxes as they are laid out, as when only an image's frame
+    /// changed; the retained scene repaints just the nodes that differ.
+    async fn repaint(&self) -> Result<()> {
+        let started = std::time::Instant::now();
+        let document_guard = self.document.read().await;
+        self.paint(&document_guard, started).await
+    }
+
+    /// Switch to the debug overlays `settings` names and repaint with them.
+    async fn update_debug_overlays(&self, settings: DebugOverlaySettings) -> Result<()> {
+        *self.debug_overlays.write() = settings;
+        self.renderer
+            .write()
+            .await
+            .set_debug_overlays(settings.flags);
+        self.repaint().await
+    }
+
+    /// Repaint after the overlay changed; styles and layout have not.
+    async fn repaint_overlay(&self) -> Result<()> {
+        self.update_overlay().await;
+        self.repaint().await
+    }
+
+    /// `node_id` if it is an element of the current document.
+    fn devtools_element(document: &Document, node_id: NodeId) -> Result<NodeId> {
+        match document.get_node(node_id) {
+            Some(node) if node.read().node_type == DomNodeType::Element => Ok(node_id),
+            _ => Err(BrowserError::Document(format!(
+                "No element with node id {}",
+                devtools::wire_id(node_id)
+            ))),
+        }
+    }
+
+    async fn devtools_command_inner(&self, command: DevtoolsCommand) -> Result<serde_json::Value> {
+        let document_error = |e: core::dom::DocumentError| BrowserError::Document(e.to_string());
+        match command {
+            DevtoolsCommand::GetProtocol {} => serde_json::from_str(devtools::PROTOCOL_SCHEMA)
+                .map_err(|e| BrowserError::Platform(e.to_string())),
+            DevtoolsCommand::GetDocument {} => {
+                let document = self.document.read().await;
+                let root = document
+                    .get_root_node()
+                    .map(|root| devtools::describe_node(&document, root, None));
+                Ok(serde_json::json!({ "root": root }))
+            }
+            DevtoolsCommand::QuerySelector { selector } => {
+                let found = self
+                    .document
+                    .read()
+                    .await
+                    .query_selector(&selector)
+                    .map_err(document_error)?;
+                Ok(serde_json::json!({ "nodeId": found.map(devtools::wire_id) }))
+            }
+            DevtoolsCommand::SetAttributeValue {
+                node_id,
+                name,
+                value,
+            } => {
+                {
+                    let document = self.document.read().await;
+                    Self::devtools_element(&document, node_id)?;
+                    document
+                        .set_attribute(node_id, &name, &value)
+                        .map_err(document_error)?;
+                }
+                self.update_pipeline().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::SetTextContent { node_id, text } => {
+                self.document
+                    .read()
+                    .await
+                    .set_text_content(node_id, &text)
+                    .map_err(document_error)?;
+                self.update_pipeline().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::EnableMutations {} | DevtoolsCommand::DisableMutations {} => {
+                let enabled = matches!(command, DevtoolsCommand::EnableMutations {});
+                let document = self.document.read().await;
+                self.devtools.set_mutations_enabled(enabled, &document);
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::GetMatchedStyles { node_id } => {
+                let document = self.document.read().await;
+                Self::devtools_element(&document, node_id)?;
+                let mut inline: Vec<serde_json::Value> = document
+                    .get_inline_style(node_id, false)
+                    .map(|declaration| declaration.get_all_properties())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(name, value)| {
+                        serde_json::json!({
+                            "name": name,
+                            "value": value.raw,
+                            "important": value.important,
+                            "enabled": true,
+                        })
+                    })
+                    .collect();
+                inline.extend(self.devtools.disabled_inline(node_id).into_iter().map(
+                    |(name, value, important)| {
+                        serde_json::json!({
+                            "name": name,
+                            "value": value,
+                            "important": important,
+                            "enabled": false,
+                        })
+                    },
+                ));
+                let rules: Vec<serde_json::Value> = self
+                    .page()
+                    .style_engine
+                    .matched_rules(node_id, &document)
+                    .into_iter()
+                    .map(|rule| {
+                        let declarations: Vec<serde_json::Value> = rule
+                            .declarations
+                            .into_iter()
+                            .map(|declaration| {
+                                serde_json::json!({
+                                    "name": declaration.name,
+                                    "value": declaration.value,
+                                    "important": declaration.important,
+                                    "enabled": declaration.enabled,
+                                })
+                            })
+                            .collect();
+                        serde_json::json!({
+                            "ruleIndex": rule.index,
+                            "specificity": rule.specificity,
+                            "declarations": declarations,
+                        })
+                    })
+                    .collect();
+                Ok(serde_json::json!({ "inline": inline, "rules": rules }))
+            }
+            DevtoolsCommand::GetComputedStyle { node_id } => {
+                Self::devtools_element(&*self.document.read().await, node_id)?;
+                let properties: std::collections::BTreeMap<String, String> = self
+                    .page()
+                    .style_engine
+                    .get_computed_styles(node_id)
+                    .map(|styles| {
+                        styles
+                            .get_all_properties()
+                            .into_iter()
+                            .map(|(name, value)| (name, Self::computed_value_to_dump(&value)))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(serde_json::json!({ "properties": properties }))
+            }
+            DevtoolsCommand::SetDeclarationEnabled {
+                node_id,
+                rule_index: Some(index),
+                name,
+                enabled,
+            } => {
+                Self::devtools_element(&*self.document.read().await, node_id)?;
+                self.page()
+                    .style_engine
+                    .set_declaration_enabled(index, &name, enabled);
+                self.relayout().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::SetDeclarationEnabled {
+                node_id,
+                rule_index: None,
+                name,
+                enabled,
+            } => {
+                {
+                    let document = self.document.read().await;
+                    Self::devtools_element(&document, node_id)?;
+                    if enabled {
+                        if let Some((value, important)) =
+                            self.devtools.take_disabled_inline(node_id, &name)
+                        {
+                            let priority = if important { "important" } else { "" };
+                            document
+                                .set_style_property(node_id, &name, &value, priority)
+                                .map_err(document_error)?;
+                        }
+                    } else if let Some(declaration) = document.get_inline_style(node_id, false) {
+                        if let Some(value) = declaration.get_property_value(&name) {
+                            let important = declaration.get_property_priority(&name) == "important";
+                            document
+                                .remove_style_property(node_id, &name)
+                                .map_err(document_error)?;
+                            self.devtools
+                                .disable_inline(node_id, &name, value, important);
+                        }
+                    }
+                }
+                self.update_pipeline().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::AddInlineDeclaration {
+                node_id,
+                name,
+                value,
+                important,
+            } => {
+                {
+                    let document = self.document.read().await;
+                    Self::devtools_element(&document, node_id)?;
+                    let priority = if important { "important" } else { "" };
+                    document
+                        .set_style_property(node_id, &name, &value, priority)
+                        .map_err(document_error)?;
+                }
+                // A declaration typed in replaces one switched off.
+                self.devtools.take_disabled_inline(node_id, &name);
+                self.update_pipeline().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::HighlightNode { node_id } => {
+                Self::devtools_element(&*self.document.read().await, node_id)?;
+                self.devtools.highlight(Some(node_id));
+                self.repaint_overlay().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::HideHighlight {} => {
+                self.devtools.highlight(None);
+                self.repaint_overlay().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::SetInspectMode { enabled } => {
+                self.devtools.set_picking(enabled);
+                self.repaint_overlay().await?;
+                Ok(serde_json::json!({}))
+            }
+            DevtoolsCommand::SetDebugOverlays { overlays, node_id } => {
+                let flags = DebugOverlayFlags::from_names(overlays.iter().map(String::as_str))
+                    .map_err(BrowserError::Document)?;
+                if let Some(node_id) = node_id {
+                    Self::devtools_element(&*self.document.read().await, node_id)?;
+                }
+                self.update_debug_overlays(DebugOverlaySettings {
+                    flags,
+                    outline_root: node_id,
+                })
+                .await?;
+                Ok(serde_json::json!({ "overlays": flags.names() }))
+            }
+            DevtoolsCommand::CaptureSnapshot {} => {
+                let snapshot = self.snapshot().await;
+                Ok(serde_json::json!({
+                    "width": snapshot.width,
+                    "height": snapshot.height,
+                    "data": base64::engine::general_purpose::STANDARD.encode(&snapshot.data),
+                }))
+            }
+        }
+    }
+
+    async fn serve_devtools_inner(&self, listener: tokio::net::TcpListener) -> Result<()> {
+        let addr = listener
+            .local_addr()
+            .map_err(|e| BrowserError::Platform(e.to_string()))?;
+        if !addr.ip().is_loopback() {
+            return Err(BrowserError::Security(format!(
+                "Devtools only listen on loopback addresses, not {}",
+                addr
+            )));
+        }
+        tracing::info!("Devtools listening on ws://{}", addr);
+        loop {
+            if *self.is_shutdown.read().await {
+                return Ok(());
+            }
+            let stream = match tokio::time::timeout(DEVTOOLS_POLL_INTERVAL, listener.accept()).await
+            {
+                Ok(Ok((stream, _))) => stream,
+                Ok(Err(e)) => {
+                    tracing::warn!("Devtools accept failed: {}", e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            match tokio_tungstenite::accept_async(stream).await {
+                Ok(socket) => {
+                    if let Err(e) = self.devtools_session(socket).await {
+                        tracing::debug!("Devtools client went away: {}", e);
+                    }
+                }
+                Err(e) => tracing::debug!("Devtools handshake failed: {}", e),
+            }
+        }
+    }
+
+    /// Answer one client's commands in order, sending notifications as
+    /// they come, until it disconnects or the engine shuts down.
+    async fn devtools_session(
+        &self,
+        mut socket: tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>,
+    ) -> std::result::Result<(), tokio_tungstenite::tungstenite::Error> {
+        use futures::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let mut poll = tokio::time::interval(DEVTOOLS_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                message = socket.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return Err(e),
+                    };
+                    let reply = self.devtools_reply(text.as_str()).await;
+                    socket.send(Message::Text(reply.to_string().into())).await?;
+                }
+                _ = poll.tick() => {}
+            }
+            if *self.is_shutdown.read().await {
+                return socket.close(None).await;
+            }
+            for notification in self.take_devtools_notifications().await {
+                socket
+                    .send(Message::Text(notification.to_string().into()))
+                    .await?;
+            }
+        }
+    }
+
+    /// The reply to one client message.
+    async fn devtools_reply(&self, text: &str) -> serde_json::Value {
+        let request = match devtools::Request::parse(text) {
+            Ok(request) => request,
+            Err(message) => return serde_json::json!({ "error": { "message": message } }),
+        };
+        let outcome = match DevtoolsCommand::parse(&request.method, request.params) {
+            Ok(command) => self
+                .devtools_command(command)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(message) => Err(message),
+        };
+        devtools::response(request.id, outcome)
+    }
+
+    async fn compose_inner(&self, state: ImeCompositionState) -> Result<()> {
+        let events = {
+            let document = self.document.read().await;
+            self.editing.write().await.compose(&document, state)
+        };
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        self.dispatch_editing_events(&events).await;
+        self.relayout().await
+    }
+
+    async fn key_press_inner(&self, key: &str, modifiers: Modifiers) -> Result<KeyRoute> {
+        if key != "Escape" {
+            self.page().user_activation.activate();
+        }
+        if !self.fire_keydown(key, modifiers).await? {
+            return Ok(KeyRoute::Page);
+        }
+        if let Some(route) = self.edit_with_key(key, modifiers).await? {
+            return Ok(route);
+        }
+        if let Some(route) = self.navigate_spatially(key, modifiers).await? {
+            return Ok(route);
+        }
+
+        let keystroke = match Keystroke::from_key(key, modifiers) {
+            Some(keystroke) => keystroke,
+            None => return Ok(KeyRoute::Unhandled),
+        };
+        let action = match self.accelerators.lookup(&keystroke) {
+            Some(action) => action,
+            None => return Ok(KeyRoute::Unhandled),
+        };
+        tracing::debug!("{} triggered accelerator {}", keystroke, action);
+        self.emit_event(BrowserEvent::AcceleratorTriggered {
+            action: action.clone(),
+            keystroke: keystroke.to_string(),
+        })
+        .await;
+        match action.as_str() {
+            actions::RELOAD => self.reload_inner().await?,
+            actions::BACK => self.navigate_back_inner().await?,
+            actions::FORWARD => self.navigate_forward_inner().await?,
+            actions::PASTE => self.paste_inner().await?,
+            _ => {}
+        }
+        Ok(KeyRoute::Accelerator(action))
+    }
+
+    /// Where keyboard events go: the focused element, or `<body>` without
+    /// focus.
+    async fn keyboard_target(&self) -> Option<NodeId> {
+        let document = self.document.read().await;
+        match self.focused_element().await {
+            Some(focused) => Some(focused),
+            None => document
+                .query_selector("body")
+                .ok()
+                .flatten()
+                .or_else(|| document.get_root_node()),
+        }
+    }
+
+    /// Fire `keydown` at the focused element, or `<body>` without focus.
+    /// False when a listener canceled it; a listener that throws does not.
+    async fn fire_keydown(&self, key: &str, modifiers: Modifiers) -> Result<bool> {
+        let target = match self.keyboard_target().await {
+            Some(target) => target,
+            None => return Ok(true),
+        };
+        self.fire_cancelable_event(target, &key_event_init("keydown", key, modifiers))
+            .await
+    }
+
+    /// A key release fires `keyup` where `keydown` goes. Nothing is done
+    /// on release, so canceling it changes nothing.
+    async fn key_release_inner(&self, key: &str, modifiers: Modifiers) -> Result<()> {
+        if let Some(target) = self.keyboard_target().await {
+            self.fire_cancelable_event(target, &key_event_init("keyup", key, modifiers))
+                .await?;
+        }
+        self.update_pipeline().await
+    }
+
+    /// Fire `paste` at the focused element, or `<body>` without focus, with
+    /// the first clipboard item as its `clipboardData`. Unless a listener
+    /// cancels it, a focused `contenteditable` element takes an image as an
+    /// `<img>` with a `blob:` URL of its PNG, and any editable element takes
+    /// text at the caret. Pasting is the user's doing, so it needs no
+    /// clipboard permission.
+    async fn paste_inner(&self) -> Result<()> {
+        let target = match self.keyboard_target().await {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let item = self.clipboard.read().into_iter().next().unwrap_or_default();
+        let mut entries = Vec::new();
+        for data in item.representations() {
+            let bytes: String = data.to_web()?.iter().map(|&byte| byte as char).collect();
+            entries.push(serde_json::json!({ "type": data.mime_type(), "data": bytes }));
+        }
+        let outcome = {
+            let rt = self.js_runtime.read().await;
+            rt.dispatch_paste_event(target, &serde_json::Value::Array(entries))
+                .await
+        };
+        let not_canceled = match outcome {
+            Ok(not_canceled) => not_canceled,
+            Err(e) => {
+                self.emit_event(BrowserEvent::JavaScriptError {
+                    message: e.to_string(),
+                    line: 0,
+                    column: 0,
+                })
+                .await;
+                true
+            }
+        };
+        if !not_canceled || self.editing.read().await.focused() != Some(target) {
+            return Ok(());
+        }
+
+        let document = self.document.read().await;
+        let image = match item.get(clipboard::IMAGE_PNG) {
+            Some(image) if !editing::is_form_control(&document, target) => Some(image),
+            _ => None,
+        };
+        let events = match (image, item.get(clipboard::TEXT_PLAIN)) {
+            (Some(image), _) => {
+                let png = image.to_web()?;
+                let document_url = document
+                    .get_url()
+                    .and_then(|url| url::Url::parse(&url).ok())
+                    .ok_or_else(|| BrowserError::Clipboard("The document has no URL".into()))?;
+                let url = self.page().network_manager.blob_store().register(
+                    &document_url,
+                    Blob {
+                        content_type: clipboard::IMAGE_PNG.to_string(),
+                        data: Arc::new(png.clone()),
+                    },
+                );
+                if let Err(e) = self.page().image_animations.insert(&url, &png) {
+                    tracing::debug!("Pasted image failed to decode: {}", e);
+                }
+                let _ = self.page().images.insert(&url, &png);
+                let img = document
+                    .create_node(DomNodeType::Element, "img".to_string())
+                    .map_err(|e| BrowserError::Document(e.to_string()))?;
+                document
+                    .set_attribute(img, "src", &url)
+                    .map_err(|e| BrowserError::Document(e.to_string()))?;
+                self.editing.write().await.paste_element(&document, img)
+            }
+            (None, Some(ClipboardData::Text(text))) => {
+                self.editing.write().await.paste_text(&document, text)
+            }
+            _ => Vec::new(),
+        };
+        drop(document);
+
+        if !events.is_empty() {
+            self.dispatch_editing_events(&events).await;
+            self.relayout().await?;
+        }
+        Ok(())
+    }
+
+    /// Fire a mouse event of `kind` at `target`, at viewport point `(x, y)`.
+    /// False when a listener canceled it.
+    async fn fire_mouse_event(
+        &self,
+        target: NodeId,
+        kind: &str,
+        (x, y): (f32, f32),
+        button: u8,
+    ) -> Result<bool> {
+        let init = serde_json::json!({
+            "type": kind,
+            "cancelable": true,
+            "clientX": x,
+            "clientY": y,
+            "button": button,
+            "detail": u8::from(kind == "click"),
+        });
+        self.fire_cancelable_event(target, &init).await
+    }
+
+    /// Fire the event `init` describes at `target`; false when a listener
+    /// canceled it. A script error is reported, and counts as not canceled.
+    async fn fire_cancelable_event(
+        &self,
+        target: NodeId,
+        init: &serde_json::Value,
+    ) -> Result<bool> {
+        let outcome = {
+            let rt = self.js_runtime.read().await;
+            rt.dispatch_cancelable_event(target, init).await
+        };
+        match outcome {
+            Ok(not_canceled) => Ok(not_canceled),
+            Err(e) => {
+                self.emit_event(BrowserEvent::JavaScriptError {
+                    message: e.to_string(),
+                    line: 0,
+                    column: 0,
+                })
+                .await;
+                Ok(true)
+            }
+        }
+    }
+
+    /// What the focused editable element does with a key: IME keys while
+    /// composing, typed characters, Backspace and caret movement. `None`
+    /// when it does not take the key.
+    async fn edit_with_key(&self, key: &str, modifiers: Modifiers) -> Result<Option<KeyRoute>> {
+        let composing = {
+            let editing = self.editing.read().await;
+            if editing.focused().is_none() || modifiers.is_command() {
+                return Ok(None);
+            }
+            editing.composition().is_some()
+        };
+        if composing && key == "Escape" {
+            self.compose_inner(ImeCompositionState::Cancel).await?;
+            return Ok(Some(KeyRoute::Page));
+        }
+
+        let document = self.document.read().await;
+        let events = match key {
+            "ArrowLeft" | "ArrowRight" => {
+                let movement = if key == "ArrowLeft" {
+                    CaretMovement::Left
+                } else {
+                    CaretMovement::Right
+                };
+                self.editing.write().await.move_caret(&document, movement);
+                return Ok(Some(KeyRoute::Page));
+            }
+            "Backspace" => self.editing.write().await.delete_backward(&document),
+            _ if key.chars().count() == 1 => self.editing.write().await.insert_text(&document, key),
+            _ if composing => Vec::new(),
+            _ => return Ok(None),
+        };
+        drop(document);
+
+        if !events.is_empty() {
+            self.dispatch_editing_events(&events).await;
+            self.relayout().await?;
+        }
+        Ok(Some(KeyRoute::Page))
+    }
+
+    /// Where moving `direction` from the focused element goes.
+    async fn spatial_move(
+        &self,
+        direction: spatial_navigation::Direction,
+    ) -> Option<spatial_navigation::SpatialMove> {
+        let from = self.focused_element().await;
+        let document = self.document.read().await;
+        let layout_engine = self.layout_engine.read().await;
+        let areas = spatial_navigation::focusable_areas(&document, &layout_engine);
+        spatial_navigation::search(&areas, from, direction, layout_engine.viewport_size())
+    }
+
+    /// In spatial navigation, an arrow moves focus that way, scrolling
+    /// first when what lies that way is off screen, and Enter clicks the
+    /// focused element. `None` for other keys, or when nothing lies that
+    /// way.
+    async fn navigate_spatially(
+        &self,
+        key: &str,
+        modifiers: Modifiers,
+    ) -> Result<Option<KeyRoute>> {
+        if !self.spatial_navigation.is_enabled() || modifiers.is_command() {
+            return Ok(None);
+        }
+        if key == "Enter" {
+            let target = match self.spatial_navigation.focused() {
+                Some(target) => target,
+                None => return Ok(None),
+            };
+            let center = match self.layout_engine.read().await.get_layout_box(target) {
+                Some(layout_box) => (
+                    layout_box.border_box_x() + layout_box.border_box_width() / 2.0,
+                    layout_box.border_box_y() + layout_box.border_box_height() / 2.0,
+                ),
+                None => return Ok(None),
+            };
+            if self.fire_mouse_event(target, "click", center, 0).await?
+                && self.follow_hyperlink(target).await?
+            {
+                return Ok(Some(KeyRoute::SpatialNavigation));
+            }
+            self.update_pipeline().await?;
+            return Ok(Some(KeyRoute::SpatialNavigation));
+        }
+
+        let direction = match spatial_navigation::Direction::from_key(key) {
+            Some(direction) => direction,
+            None => return Ok(None),
+        };
+        let step = match self.spatial_move(direction).await {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        if step.scroll_by != (0.0, 0.0) {
+            let (x, y) = self.layout_engine.read().await.scroll_position();
+            self.scroll_to_inner(x + step.scroll_by.0, y + step.scroll_by.1)
+                .await?;
+        }
+        self.spatial_navigation.focus(step.target);
+        {
+            // An editable element takes the caret too; anything else
+            // leaves nothing to edit.
+            let document = self.document.read().await;
+            let mut editing = self.editing.write().await;
+            if !editing.focus(&document, step.target) {
+                editing.blur();
+            }
+        }
+        self.repaint_overlay().await?;
+        Ok(Some(KeyRoute::SpatialNavigation))
+    }
+
+    async fn dispatch_editing_events(&self, events: &[EditingEvent]) {
+        let rt = self.js_runtime.read().await;
+        for event in events {
+            if let Err(e) = rt
+                .dispatch_element_event(event.target, &event.to_json())
+                .await
+            {
+                self.emit_event(BrowserEvent::JavaScriptError {
+                    message: e.to_string(),
+                    line: 0,
+                    column: 0,
+                })
+                .await;
+            }
+        }
+    }
+
+    /// The element under viewport point `(x, y)`: the node painted there,
+    /// or the element holding the text painted there.
+    async fn element_at_inner(&self, x: f32, y: f32) -> Result<Option<NodeId>> {
+        Ok(self.elements_at_inner(x, y).await?.into_iter().next())
+    }
+
+    /// The elements under viewport point `(x, y)`, topmost first, hit
+    /// tested against a paint list made afresh.
+    async fn elements_at_inner(&self, x: f32, y: f32) -> Result<Vec<NodeId>> {
+        self.create_layout_tree().await?;
+        let document = self.document.read().await;
+        Ok(self.page().hit_regions.elements_at(&document, x, y))
+    }
+
+    /// A button press fires `mousedown` at the element under the pointer.
+    /// Unless a listener cancels it, a primary press arms a drag of the
+    /// nearest draggable element there. In devtools inspect mode a primary
+    /// press picks the element instead, and the page never sees it.
+    async fn pointer_down_inner(&self, x: i32, y: i32, button: u8) -> Result<()> {
+        let (x, y) = (x as f32, y as f32);
+        let mut current = self.element_at_inner(x, y).await?;
+        // Any click puts the text fragment highlight away.
+        if self.page().text_highlight.clear() {
+            self.repaint_overlay().await?;
+        }
+        if button == 0 && self.devtools.is_picking() {
+            self.devtools.pick(current);
+            return self.repaint_overlay().await;
+        }
+        self.page().user_activation.activate();
+        let target = match current {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let not_canceled = self
+            .fire_mouse_event(target, "mousedown", (x, y), button)
+            .await?;
+        if button != 0 {
+            return self.update_pipeline().await;
+        }
+        *self.pressed.write().await = Some(target);
+        if !not_canceled {
+            return self.update_pipeline().await;
+        }
+        let document = self.document.read().await;
+        while let Some(node_id) = current {
+            let draggable = document
+                .get_node(node_id)
+                .is_some_and(|node| drag::is_draggable(&node.read()));
+            if draggable {
+                self.page().drag.press(node_id, x, y);
+                break;
+            }
+            current = document.get_parent(node_id);
+        }
+        drop(document);
+        self.update_pipeline().await
+    }
+
+    async fn pointer_move_inner(&self, x: i32, y: i32) -> Result<()> {
+        let pointer = (x as f32, y as f32);
+        if self.devtools.is_picking() {
+            let hit = self.element_at_inner(pointer.0, pointer.1).await?;
+            if self.devtools.hover(hit) {
+                self.repaint_overlay().await?;
             }
-
-            if let Some(pwa) = &self.pwa_manager {
-                pwa.shutdown().await?;
+            return Ok(());
+        }
+        if let Some((source, pressed_x, pressed_y)) =
+            self.page().drag.crosses_threshold(pointer.0, pointer.1)
+        {
+            if !self.start_drag(source, (pressed_x, pressed_y)).await? {
+                return Ok(());
             }
+        }
+        if self.page().drag.is_dragging() {
+            return self.drag_over(pointer).await;
+        }
+        if let Some(target) = self.element_at_inner(pointer.0, pointer.1).await? {
+            self.fire_mouse_event(target, "mousemove", pointer, 0)
+                .await?;
+            self.update_pipeline().await?;
+        }
+        Ok(())
+    }
 
-            if let Some(_sandbox) = &self.sandbox_manager {
-                // Add sandbox shutdown when API is available.
+    /// A wheel turn fires `wheel` at the element under the pointer. Unless
+    /// a listener cancels it, the viewport scrolls by the deltas, in CSS
+    /// pixels.
+    async fn wheel_inner(&self, x: i32, y: i32, delta_x: f64, delta_y: f64) -> Result<()> {
+        let pointer = (x as f32, y as f32);
+        if let Some(target) = self.element_at_inner(pointer.0, pointer.1).await? {
+            let init = serde_json::json!({
+                "type": "wheel",
+                "cancelable": true,
+                "clientX": pointer.0,
+                "clientY": pointer.1,
+                "deltaX": delta_x,
+                "deltaY": delta_y,
+                "deltaZ": 0,
+                "deltaMode": 0,
+            });
+            if !self.fire_cancelable_event(target, &init).await? {
+                return self.update_pipeline().await;
             }
+        }
+        let (scroll_x, scroll_y) = self.layout_engine.read().await.scroll_position();
+        self.scroll_to_inner(scroll_x + delta_x as f32, scroll_y + delta_y as f32)
+            .await
+    }
 
-            // Shutdown JS runtime first (drops isolates/contexts)
-            {
-                let rt = self.js_runtime.read().await;
-                rt.shutdown().await?;
+    /// A touch point with `pressure` fires `touchstart` at the element
+    /// under it, or `touchmove` when it was down already; without pressure
+    /// it is lifted and fires `touchend`. A touch's events all go to the
+    /// element it started on, wherever it has moved since.
+    async fn touch_inner(&self, x: i32, y: i32, pressure: f64, id: u32) -> Result<()> {
+        let point = (x as f32, y as f32);
+        let started = self
+            .page()
+            .touches
+            .lock()
+            .get(&id)
+            .map(|&(target, _)| target);
+        let (kind, target) = match (started, pressure > 0.0) {
+            (Some(target), true) => {
+                self.page().touches.lock().insert(id, (target, point));
+                ("touchmove", target)
             }
+            (Some(target), false) => {
+                self.page().touches.lock().remove(&id);
+                ("touchend", target)
+            }
+            (None, true) => match self.element_at_inner(point.0, point.1).await? {
+                Some(target) => {
+                    self.page().user_activation.activate();
+                    self.page().touches.lock().insert(id, (target, point));
+                    ("touchstart", target)
+                }
+                None => return Ok(()),
+            },
+            (None, false) => return Ok(()),
+        };
 
-            // Shutdown network manager
-            self.network_manager.shutdown().await?;
-
-            // Dispose V8 global state exactly once (handled internally with Once)
-            crate::js_engine::v8_binding::V8Runtime::dispose_v8();
-
-            Ok(())
-        })
-        .await
+        let touch = |id: u32, (x, y): (f32, f32)| serde_json::json!({ "identifier": id, "clientX": x, "clientY": y });
+        // The points still down, the lifted one no longer among them.
+        let touches: Vec<serde_json::Value> = self
+            .page()
+            .touches
+            .lock()
+            .iter()
+            .map(|(&id, &(_, point))| touch(id, point))
+            .collect();
+        let mut changed = touch(id, point);
+        changed["force"] = serde_json::json!(pressure.clamp(0.0, 1.0));
+        let init = serde_json::json!({
+            "type": kind,
+            "cancelable": true,
+            "clientX": point.0,
+            "clientY": point.1,
+            "touches": touches,
+            "changedTouches": [changed],
+        });
+        self.fire_cancelable_event(target, &init).await?;
+        self.update_pipeline().await
     }
 
-    // -------- Internal implementations (unsafeguarded; always call via run_safe) --------
-
-    async fn load_url_inner(&self, url: String) -> Result<()> {
-        if *self.is_shutdown.read().await {
-            return Err(BrowserError::Platform(
-                "Browser engine has been shut down".to_string(),
-            ));
+    /// A release ends a drag if one is going on. Otherwise the element
+    /// under the pointer gets `mouseup`, then `click` when a primary press
+    /// went down on it too.
+    async fn pointer_up_inner(&self, x: i32, y: i32, button: u8) -> Result<()> {
+        let (x, y) = (x as f32, y as f32);
+        if button == 0 {
+            self.page().drag.release();
+            if self.page().drag.is_dragging() {
+                self.pressed.write().await.take();
+                return self.finish_drag((x, y)).await.map(|_| ());
+            }
         }
+        let pressed = match button {
+            0 => self.pressed.write().await.take(),
+            _ => None,
+        };
+        let target = match self.element_at_inner(x, y).await? {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        self.fire_mouse_event(target, "mouseup", (x, y), button)
+            .await?;
+        if pressed == Some(target)
+            && self
+                .fire_mouse_event(target, "click", (x, y), button)
+                .await?
+            && button == 0
+            && self.follow_hyperlink(target).await?
+        {
+            return Ok(());
+        }
+        self.update_pipeline().await
+    }
 
-        self.emit_event(BrowserEvent::NavigationStarted { url: url.clone() })
-            .await;
-        *self.is_loading_flag.write().await = true;
-
-        let start_time = std::time::Instant::now();
-
-        // Handle data: URLs (size & MIME-capped)
-        let content = if let Some(rest) = url.strip_prefix("data:") {
-            if !self.config.allow_data_urls {
-                return Err(BrowserError::Security("Scheme 'data' not allowed".into()));
+    /// Follow the link `target` is in, sending its pings first; false when
+    /// it is in none.
+    async fn follow_hyperlink(&self, target: NodeId) -> Result<bool> {
+        let (href, pings, base) = {
+            let document = self.document.read().await;
+            match hyperlink_of(&document, target) {
+                Some((href, pings)) => (href, pings, document.base_url()),
+                None => return Ok(false),
             }
-            let (mime, bytes) = parse_data_url(rest)
-                .map_err(|e| BrowserError::Security(format!("Invalid data: URL - {e}")))?;
-            if bytes.len() > self.config.max_data_url_bytes {
-                return Err(BrowserError::Security("data: payload too large".into()));
+        };
+        let resolved = match &base {
+            Some(base) => base.join(&href),
+            None => url::Url::parse(&href),
+        };
+        // `javascript:` URLs run script rather than navigate; there is no
+        // script to run them with here.
+        let url = match resolved {
+            Ok(url) if url.scheme() != "javascript" => url,
+            _ => return Ok(false),
+        };
+        if let Some(pings) = pings {
+            self.send_pings(&pings, base.as_ref(), &url);
+        }
+        self.load_url_inner(url.to_string()).await?;
+        Ok(true)
+    }
+
+    /// Queue the hyperlink auditing pings of a link to `target`. They go
+    /// out beside the navigation: one that cannot be sent is skipped, and
+    /// none holds up or fails it.
+    fn send_pings(&self, pings: &str, base: Option<&url::Url>, target: &url::Url) {
+        let page = self.page();
+        let initiator = match page.request_initiator.read().clone() {
+            Some(initiator) => initiator,
+            None => return,
+        };
+        if !self.config.read().enable_hyperlink_auditing
+            || !self
+                .permissions
+                .is_granted(&initiator.document_url, Permission::HyperlinkAuditing)
+        {
+            return;
+        }
+        for ping in pings.split_ascii_whitespace() {
+            let ping = match base {
+                Some(base) => base.join(ping),
+                None => url::Url::parse(ping),
+            };
+            let ping = match ping {
+                Ok(ping) => ping,
+                Err(_) => continue,
+            };
+            match page.network_manager.send_ping(&ping, target, &initiator) {
+                Ok(true) => {}
+                Ok(false) => tracing::debug!("Ping to {} over the keepalive quota", ping),
+                Err(e) => tracing::debug!("Ping to {} not sent: {}", ping, e),
             }
-            let allowed = self
-                .config
-                .allowed_data_mime_prefixes
-                .iter()
-                .any(|p| mime.starts_with(p));
-            if !allowed {
-                return Err(BrowserError::Security(format!("Blocked data: MIME {mime}")));
+        }
+    }
+
+    /// Files dropped from outside at `(x, y)`. The element there gets
+    /// `dragenter` and `dragover`, then `drop` if it canceled `dragover`.
+    /// The document keeps read access to the files only when it took them.
+    async fn file_drop_inner(&self, paths: Vec<PathBuf>, x: i32, y: i32) -> Result<()> {
+        let pointer = (x as f32, y as f32);
+        let files: Vec<DroppedFile> = paths
+            .iter()
+            .filter_map(|path| match self.page().file_grants.grant(path) {
+                Ok(grant) => Some(DroppedFile::from(grant)),
+                Err(e) => {
+                    tracing::warn!("Ignoring dropped {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        let revoke = |files: &[DroppedFile]| {
+            for file in files {
+                self.page().file_grants.revoke(file.grant);
             }
-            if mime.starts_with("text/html")
-                || mime.starts_with("text/plain")
-                || mime == "application/xhtml+xml"
-            {
-                String::from_utf8(bytes)
-                    .unwrap_or_else(|_| "<!doctype html><title>Invalid UTF-8</title>".to_string())
-            } else {
-                return Err(BrowserError::Security(format!(
-                    "Top-level data: MIME not renderable: {mime}"
-                )));
+        };
+        let target = match self.element_at_inner(pointer.0, pointer.1).await? {
+            Some(target) if !files.is_empty() => target,
+            _ => {
+                revoke(&files);
+                return Ok(());
             }
-        } else {
-            // Normal fetch path
-            self.network_manager.fetch(&url).await?
         };
 
-        // Parse HTML and update document
-        {
-            let document = self.document.write().await;
+        self.page().drag.start(DragSession::new(
+            None,
+            DataTransfer::with_files(files.clone()),
+            pointer,
+        ));
+        self.page()
+            .drag
+            .update(|session| session.target = Some(target));
+        self.reset_drop_effect();
+        self.fire_drag_event(target, "dragenter", pointer, DataTransferMode::Protected)
+            .await?;
+        let operation = self.drag_over_target(target, pointer).await?;
+        self.page()
+            .drag
+            .update(|session| session.operation = operation);
+        if self.finish_drag(pointer).await? == DropEffect::None {
+            revoke(&files);
+        }
+        Ok(())
+    }
+
+    /// Fire `dragstart` at `source`, pressed at `pressed`. The drag starts
+    /// carrying the address of a link or image and the source as its image,
+    /// unless a listener cancels it.
+    async fn start_drag(&self, source: NodeId, pressed: (f32, f32)) -> Result<bool> {
+        let items = {
+            let document = self.document.read().await;
+            let base = document.base_url();
             document
-                .parse_html(&content)
-                .map_err(|e| BrowserError::Document(e.to_string()))?;
-            document.set_url(url.clone());
+                .get_node(source)
+                .map(|node| drag::default_items(&node.read(), base.as_ref()))
+                .unwrap_or_default()
+        };
+        let mut transfer = DataTransfer::new("uninitialized");
+        transfer.set_mode(DataTransferMode::ReadWrite);
+        for (format, data) in &items {
+            transfer.set_data(format, data);
         }
+        let mut session = DragSession::new(Some(source), transfer, pressed);
+        if let Some(layout_box) = self.layout_engine.read().await.get_layout_box(source) {
+            session.image = Some(DragImage {
+                node: source,
+                offset_x: pressed.0 - layout_box.border_box_x(),
+                offset_y: pressed.1 - layout_box.border_box_y(),
+            });
+        }
+        self.page().drag.start(session);
 
-        // Update history
-        {
-            let mut history = self.history.write().await;
-            let mut idx = self.history_index.write().await;
-            match *idx {
-                Some(i) if i + 1 < history.len() => {
-                    history.truncate(i + 1);
-                    history.push(url.clone());
-                    *idx = Some(i + 1);
-                }
-                Some(i) if i + 1 == history.len() => {
-                    history.push(url.clone());
-                    *idx = Some(i + 1);
-                }
-                Some(_) => {}
-                None => {
-                    history.push(url.clone());
-                    *idx = Some(0);
+        let started = self
+            .fire_drag_event(source, "dragstart", pressed, DataTransferMode::ReadWrite)
+            .await?;
+        if !started {
+            self.page().drag.end();
+        }
+        Ok(started)
+    }
+
+    /// The pointer moved to `pointer` during a drag: `drag` at the source,
+    /// `dragenter` and `dragleave` when the element under it changed, then
+    /// `dragover` at that element. Canceling `drag` cancels the drag.
+    async fn drag_over(&self, pointer: (f32, f32)) -> Result<()> {
+        let session = match self.page().drag.update(|session| {
+            session.pointer = pointer;
+            session.clone()
+        }) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if let Some(source) = session.source {
+            let go_on = self
+                .fire_drag_event(source, "drag", pointer, DataTransferMode::Protected)
+                .await?;
+            if !go_on {
+                if let Some(target) = session.target {
+                    self.fire_drag_event(target, "dragleave", pointer, DataTransferMode::Protected)
+                        .await?;
                 }
+                return self
+                    .end_drag(session.source, DropEffect::None, pointer)
+                    .await;
             }
         }
 
-        // Style and layout
-        {
-            let document_guard = self.document.read().await;
-
-            // Compute styles (sync)
-            self.style_engine
-                .compute_styles(&document_guard)
-                .map_err(|e| BrowserError::Style(e.to_string()))?;
+        let under = self.element_at_inner(pointer.0, pointer.1).await?;
+        if under != session.target {
+            if let Some(entered) = under {
+                self.reset_drop_effect();
+                self.fire_drag_event(entered, "dragenter", pointer, DataTransferMode::Protected)
+                    .await?;
+            }
+            if let Some(left) = session.target {
+                self.fire_drag_event(left, "dragleave", pointer, DataTransferMode::Protected)
+                    .await?;
+            }
+            self.page().drag.update(|session| session.target = under);
+        }
+        let operation = match under {
+            Some(target) => self.drag_over_target(target, pointer).await?,
+            None => DropEffect::None,
+        };
+        self.page()
+            .drag
+            .update(|session| session.operation = operation);
+        Ok(())
+    }
 
-            // Compute layout (async)
-            {
-                let layout_engine = self.layout_engine.write().await;
-                layout_engine
-                    .compute_layout(&document_guard, &self.style_engine)
-                    .await
-                    .map_err(|e| BrowserError::Layout(e.to_string()))?;
+    /// Fire `dragover` at `target`. Only a canceled `dragover` accepts the
+    /// drop, with the `dropEffect` it left, if the source allows that.
+    async fn drag_over_target(&self, target: NodeId, pointer: (f32, f32)) -> Result<DropEffect> {
+        self.reset_drop_effect();
+        let uncanceled = self
+            .fire_drag_event(target, "dragover", pointer, DataTransferMode::Protected)
+            .await?;
+        if uncanceled {
+            return Ok(DropEffect::None);
+        }
+        let operation = self.page().drag.with_transfer(|transfer| {
+            let effect = transfer.drop_effect();
+            if effect.allowed_by(transfer.effect_allowed()) {
+                effect
+            } else {
+                DropEffect::None
             }
+        });
+        Ok(operation.unwrap_or(DropEffect::None))
+    }
 
-            // Execute JavaScript (async)
-            {
-                let rt = self.js_runtime.read().await;
-                rt.inject_document_api(&document_guard).await?;
-                if let Err(e) = rt.execute_inline_scripts(&document_guard).await {
-                    self.emit_event(BrowserEvent::JavaScriptError {
-                        message: e.to_string(),
-                        line: 0,
-                        column: 0,
-                    })
-                    .await;
+    /// The drag ends at `pointer`: `drop` at a target that accepted it,
+    /// `dragleave` at one that did not, then `dragend` at the source. A
+    /// `drop` no listener canceled does nothing. Returns the operation
+    /// performed.
+    async fn finish_drag(&self, pointer: (f32, f32)) -> Result<DropEffect> {
+        let session = match self.page().drag.session() {
+            Some(session) => session,
+            None => return Ok(DropEffect::None),
+        };
+        let mut operation = session.operation;
+        if let Some(target) = session.target {
+            if operation == DropEffect::None {
+                self.fire_drag_event(target, "dragleave", pointer, DataTransferMode::Protected)
+                    .await?;
+            } else {
+                self.page()
+                    .drag
+                    .with_transfer(|transfer| transfer.set_drop_effect(operation));
+                let uncanceled = self
+                    .fire_drag_event(target, "drop", pointer, DataTransferMode::ReadOnly)
+                    .await?;
+                if uncanceled {
+                    operation = DropEffect::None;
                 }
             }
+        }
+        self.end_drag(session.source, operation, pointer).await?;
+        Ok(operation)
+    }
 
-            // Render the page
-            let layout_tree = self.create_layout_tree().await?;
-            {
-                let mut renderer = self.renderer.write().await;
-                renderer.render(&document_guard, &layout_tree).await?;
-            }
+    async fn end_drag(
+        &self,
+        source: Option<NodeId>,
+        operation: DropEffect,
+        pointer: (f32, f32),
+    ) -> Result<()> {
+        if let Some(source) = source {
+            self.page()
+                .drag
+                .with_transfer(|transfer| transfer.set_drop_effect(operation));
+            self.fire_drag_event(source, "dragend", pointer, DataTransferMode::Protected)
+                .await?;
         }
+        self.page().drag.end();
+        Ok(())
+    }
 
-        *self.is_loading_flag.write().await = false;
+    /// Before `dragenter` and `dragover`, `dropEffect` is what the source's
+    /// `effectAllowed` suggests.
+    fn reset_drop_effect(&self) {
+        self.page().drag.with_transfer(|transfer| {
+            transfer.set_drop_effect(DropEffect::default_for(transfer.effect_allowed()))
+        });
+    }
 
-        let load_time = start_time.elapsed().as_millis() as u64;
-        self.emit_event(BrowserEvent::PageLoaded {
-            url,
-            load_time_ms: load_time,
-        })
-        .await;
+    /// Fire drag event `kind` at `node`, with the data store in `mode` for
+    /// the duration. False when a listener canceled it.
+    async fn fire_drag_event(
+        &self,
+        node: NodeId,
+        kind: &str,
+        (x, y): (f32, f32),
+        mode: DataTransferMode,
+    ) -> Result<bool> {
+        self.page()
+            .drag
+            .with_transfer(|transfer| transfer.set_mode(mode));
+        let init = serde_json::json!({
+            "type": kind,
+            "bubbles": true,
+            "cancelable": !matches!(kind, "dragleave" | "dragend"),
+            "clientX": x,
+            "clientY": y,
+            "button": 0,
+        });
+        let outcome = self
+            .js_runtime
+            .read()
+            .await
+            .dispatch_drag_event(node, &init)
+            .await;
+        self.page()
+            .drag
+            .with_transfer(|transfer| transfer.set_mode(DataTransferMode::Protected));
+        outcome.map_err(BrowserError::from)
+    }
 
-        Ok(())
+    /// Advance width of `text` in `style`: shaped with a loaded web font
+    /// when one applies, otherwise the layout engine's approximation.
+    fn text_width(&self, style: &Style, text: &str) -> f32 {
+        style
+            .font_family
+            .as_deref()
+            .and_then(|family| {
+                self.page().fonts.shape(
+                    family,
+                    &style.font,
+                    style.font_size,
+                    text,
+                    style.language.as_deref(),
+                )
+            })
+            .map(|shaped| shaped.width())
+            .unwrap_or_else(|| {
+                crate::core::layout::text::measure_text(
+                    text,
+                    style.font_size,
+                    style.font_size * 1.2,
+                    None,
+                )
+                .width
+            })
     }
 
-    async fn navigate_back_inner(&self) -> Result<()> {
-        let mut idx_guard = self.history_index.write().await;
-        let history = self.history.read().await;
+    async fn reload_inner(&self) -> Result<()> {
+        let url = {
+            let document = self.document.read().await;
+            document.get_url().map(|s| s.to_string())
+        };
+        if let Some(url) = url {
+            self.load_url_inner(url).await
+        } else {
+            Err(BrowserError::Platform("No URL to reload".to_string()))
+        }
+    }
 
-        match *idx_guard {
-            Some(i) if i > 0 && i < history.len() => {
-                let new_i = i - 1;
-                let target = history[new_i].clone();
-                *idx_guard = Some(new_i);
-                drop(history);
-                drop(idx_guard);
-                self.load_url_inner(target).await
+    /// Boxes are laid out where the viewport sees them, so scrolling moves
+    /// them; only when `content-visibility: auto` contents come into range
+    /// or leave it is the document laid out again.
+    async fn scroll_to_inner(&self, x: f32, y: f32) -> Result<()> {
+        let document_guard = self.document.read().await;
+        {
+            let layout_engine = self.layout_engine.write().await;
+            if layout_engine.scroll_to(x, y, &document_guard) {
+                layout_engine
+                    .compute_layout(&document_guard, &self.page().style_engine)
+                    .await
+                    .map_err(|e| BrowserError::Layout(e.to_string()))?;
             }
-            _ => Err(BrowserError::Platform("No back history".to_string())),
+            self.page()
+                .text_highlight
+                .scrolled(layout_engine.scroll_position());
         }
+
+        self.update_overlay().await;
+        let layout_tree = self.create_layout_tree().await?;
+        let mut renderer = self.renderer.write().await;
+        renderer.render(&document_guard, &layout_tree).await?;
+        Ok(())
     }
 
-    async fn navigate_forward_inner(&self) -> Result<()> {
-        let mut idx_guard = self.history_index.write().await;
-        let history = self.history.read().await;
+    async unsafe fn attach_window_inner(
+        &self,
+        display: RawDisplayHandle,
+        window: RawWindowHandle,
+    ) -> Result<()> {
+        if *self.is_shutdown.read().await {
+            return Err(BrowserError::Platform(
+                "Browser engine has been shut down".to_string(),
+            ));
+        }
+        if !self.config.read().enable_gpu_acceleration {
+            return Err(BrowserError::FeatureDisabled("gpu_acceleration"));
+        }
 
-        match *idx_guard {
-            Some(i) if i + 1 < history.len() => {
-                let new_i = i + 1;
-                let target = history[new_i].clone();
-                *idx_guard = Some(new_i);
-                drop(history);
-                drop(idx_guard);
-                self.load_url_inner(target).await
-            }
-            _ => Err(BrowserError::Platform("No forward history".to_string())),
+        let config = self.config.read().clone();
+        let presenter = vulkan::VulkanRenderer::new(&config)
+            .await
+            .map_err(|e| BrowserError::RendererInit(e.to_string()))?;
+        if let Err(e) = presenter.attach_window(display, window).await {
+            let _ = presenter.shutdown().await;
+            return Err(BrowserError::RendererInit(e.to_string()));
         }
+        let previous = self.window.write().await.replace(WindowSurface {
+            presenter,
+            presented_frame: None,
+            gpu_memory_warned: false,
+        });
+        if let Some(previous) = previous {
+            let _ = previous.presenter.shutdown().await;
+        }
+        Ok(())
     }
 
-    async fn execute_javascript_inner(&self, script: String) -> Result<serde_json::Value> {
+    async fn render_frame_inner(&self) -> Result<()> {
         if *self.is_shutdown.read().await {
             return Err(BrowserError::Platform(
                 "Browser engine has been shut down".to_string(),
             ));
         }
-        // Use read lock; assume JSRuntime::execute takes &self
-        let rt = self.js_runtime.read().await;
-        rt.execute(&script).await.map_err(Into::into)
+
+        let mut window = self.window.write().await;
+        let Some(surface) = window.as_mut() else {
+            return Ok(());
+        };
+        let (frame, quads) = {
+            let renderer = self.renderer.read().await;
+            let frame = renderer.frame_count();
+            if surface.presented_frame == Some(frame) {
+                return Ok(());
+            }
+            (frame, renderer.frame_quads())
+        };
+        let presented = surface
+            .presenter
+            .render(&quads)
+            .await
+            .map_err(|e| BrowserError::Render(e.to_string()))?;
+        if presented {
+            surface.presented_frame = Some(frame);
+        }
+        let warning = self.gpu_memory_warning(surface);
+        drop(window);
+        if let Some(warning) = warning {
+            self.emit_event(warning).await;
+        }
+        Ok(())
     }
 
-    async fn reload_inner(&self) -> Result<()> {
-        let url = {
-            let document = self.document.read().await;
-            document.get_url().map(|s| s.to_string())
+    /// A `PerformanceWarning` when the window's device memory first goes
+    /// past its share of `max_memory_mb`; again only once it went back
+    /// under.
+    fn gpu_memory_warning(&self, surface: &mut WindowSurface) -> Option<BrowserEvent> {
+        let threshold = {
+            let config = self.config.read();
+            config.max_memory_mb as f64 * config.gpu_memory_warning_fraction
         };
-        if let Some(url) = url {
-            self.load_url_inner(url).await
-        } else {
-            Err(BrowserError::Platform("No URL to reload".to_string()))
+        let used = surface.presenter.memory_report().reserved_bytes as f64 / (1024.0 * 1024.0);
+        let over = used > threshold;
+        let warn = over && !surface.gpu_memory_warned;
+        surface.gpu_memory_warned = over;
+        if warn {
+            tracing::warn!("GPU memory at {:.1} MB, past {:.1} MB", used, threshold);
         }
+        warn.then(|| BrowserEvent::PerformanceWarning {
+            metric: "gpu_memory_mb".to_string(),
+            value: used,
+            threshold,
+            url: None,
+            context: None,
+        })
+    }
+
+    async fn resize_window_inner(&self, width: u32, height: u32) -> Result<()> {
+        let mut window = self.window.write().await;
+        let Some(surface) = window.as_mut() else {
+            return Ok(());
+        };
+        surface
+            .presenter
+            .resize_surface(width, height)
+            .await
+            .map_err(|e| BrowserError::Render(e.to_string()))?;
+        surface.presented_frame = None;
+        Ok(())
     }
 
     async fn resize_viewport_inner(&self, width: u32, height: u32) -> Result<()> {
@@ -841,48 +6245,228 @@ impl BrowserEngine {
             {
                 let layout_engine = self.layout_engine.write().await;
                 layout_engine
-                    .compute_layout(&document_guard, &self.style_engine)
+                    .compute_layout(&document_guard, &self.page().style_engine)
                     .await
                     .map_err(|e| BrowserError::Layout(e.to_string()))?;
             }
 
+            self.update_overlay().await;
             let layout_tree = self.create_layout_tree().await?;
             let mut renderer = self.renderer.write().await;
             renderer.render(&document_guard, &layout_tree).await?;
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    async fn create_layout_tree(&self) -> Result<LayoutTree> {
+        let document = self.document.read().await;
+        let layout_engine = self.layout_engine.read().await;
+        let layout_tree = self.layout_tree_of(&document, &layout_engine).await;
+        self.update_hit_regions(&layout_tree, &layout_engine);
+        self.update_script_scroll(&document, &layout_engine);
+        Ok(layout_tree)
+    }
+
+    /// The paint of `document` as `layout_engine` laid it out.
+    async fn layout_tree_of(
+        &self,
+        document: &Document,
+        layout_engine: &LayoutEngine,
+    ) -> LayoutTree {
+        let mut layout_tree = LayoutTree::new();
+
+        if let Some(root) = document.get_root_node() {
+            self.build_layout_tree(
+                document,
+                layout_engine,
+                root,
+                &ClipChain::new(),
+                &mut layout_tree,
+            );
+        }
+
+        let debug_overlays = *self.debug_overlays.read();
+        if debug_overlays
+            .flags
+            .contains(DebugOverlayFlags::BOX_OUTLINES)
+        {
+            let root = debug_overlays
+                .outline_root
+                .or_else(|| document.get_root_node());
+            layout_tree.set_box_outlines(debug_box_outlines(document, layout_engine, root));
+        }
+
+        // An in-progress IME composition is drawn at the caret, underlined,
+        // without being part of the DOM.
+        let editing = self.editing.read().await;
+        if let (Some(target), Some(composition)) = (editing.focused(), editing.composition()) {
+            if let Some(layout_box) = layout_engine.get_layout_box(target) {
+                let mut style = self.extract_style(
+                    self.page()
+                        .style_engine
+                        .get_computed_styles(target)
+                        .as_deref(),
+                );
+                style.background_color = None;
+                style.background_paint = None;
+                style.text_decoration = TextDecoration::underline();
+                let line_height = style.font_size * 1.2;
+                let before = editing.text_before_composition(document);
+                let measure = |text: &str| self.text_width(&style, text);
+
+                layout_tree.add_node(LayoutNode {
+                    node_id: target,
+                    bounds: Rect {
+                        x: layout_box.content_x + measure(&before),
+                        y: layout_box.content_y,
+                        width: measure(&composition.text),
+                        height: line_height,
+                    },
+                    element_type: ElementType::Text,
+                    text_content: Some(composition.text.clone()),
+                    style,
+                    image_url: None,
+                    image_frame: 0,
+                    clip: ClipChain::new(),
+                });
+            }
+        }
+
+        layout_tree
     }
 
-    async fn create_layout_tree(&self) -> Result<LayoutTree> {
-        let document = self.document.read().await;
-        let layout_engine = self.layout_engine.read().await;
-
-        let mut layout_tree = LayoutTree::new();
+    fn update_script_scroll(&self, document: &Document, layout_engine: &LayoutEngine) {
+        self.page().scroll.update(
+            layout_engine.scroll_position(),
+            layout_engine.max_scroll(document),
+        );
+    }
 
-        if let Some(root) = document.get_root_node() {
-            self.build_layout_tree(&document, &layout_engine, root, &mut layout_tree);
+    /// Scroll to where page script last asked to, now that what it changed
+    /// is laid out.
+    async fn apply_script_scroll(&self) -> Result<()> {
+        match self.page().scroll.take_request() {
+            Some((x, y)) => self.scroll_to_inner(x, y).await,
+            None => Ok(()),
         }
+    }
 
-        Ok(layout_tree)
+    fn update_hit_regions(&self, layout_tree: &LayoutTree, layout_engine: &LayoutEngine) {
+        self.page()
+            .hit_regions
+            .update(layout_tree, layout_engine.viewport_size(), |node_id| {
+                !matches!(
+                    self.page().style_engine
+                        .get_computed_styles(node_id)
+                        .and_then(|styles| styles.get_computed_value("pointer-events").ok()),
+                    Some(ComputedValue::Keyword(keyword)) if keyword.eq_ignore_ascii_case("none")
+                )
+            });
     }
 
+    /// Adds the subtree at `root` in paint order, which is tree order. The
+    /// walk keeps its own stack: however deep the document, it is not the
+    /// thread's.
     fn build_layout_tree(
         &self,
         document: &Document,
         layout_engine: &LayoutEngine,
-        node_id: NodeId,
+        root: NodeId,
+        clip: &ClipChain,
         tree: &mut LayoutTree,
     ) {
-        if let Some(layout_node) = self.create_layout_node(document, layout_engine, node_id) {
-            tree.add_node(layout_node);
+        let mut stack = vec![(root, clip.clone())];
+        while let Some((node_id, clip)) = stack.pop() {
+            let mut child_clip = None;
+            if let Some(mut layout_node) = self.create_layout_node(document, layout_engine, node_id)
+            {
+                if layout_node.style.clips_overflow {
+                    if let Some(layout_box) = layout_engine.get_layout_box(node_id) {
+                        child_clip = Some(clip.push(Self::overflow_clip(
+                            &layout_box,
+                            layout_node.style.border_radius,
+                        )));
+                    }
+                }
+                layout_node.clip = clip.clone();
+                // Text paints line by line, each line in the room floats leave.
+                let lines = match layout_node.element_type {
+                    ElementType::Text => layout_engine
+                        .get_layout_result(node_id)
+                        .map(|result| result.line_boxes)
+                        .unwrap_or_default(),
+                    _ => Vec::new(),
+                };
+                if let (ElementType::Image, Some(url)) =
+                    (&layout_node.element_type, &layout_node.image_url)
+                {
+                    if let Some(image) = self.loaded_image(url) {
+                        tree.set_image(url, layout_node.image_frame, image);
+                    }
+                }
+                if lines.is_empty() {
+                    tree.add_node(layout_node);
+                } else {
+                    for line in lines {
+                        tree.add_node(LayoutNode {
+                            bounds: Rect {
+                                x: line.x,
+                                y: line.y,
+                                width: line.width,
+                                height: line.height,
+                            },
+                            text_content: Some(line.text),
+                            ..layout_node.clone()
+                        });
+                    }
+                }
+            }
+
+            // Contents skipped by `content-visibility` are not painted.
+            if layout_engine.is_skipped(node_id) {
+                continue;
+            }
+            let child_clip = child_clip.unwrap_or(clip);
+            // Reversed, so the first child is popped first.
+            for child in document.get_children(node_id).into_iter().rev() {
+                stack.push((child, child_clip.clone()));
+            }
         }
+    }
 
-        for child in document.get_children(node_id) {
-            self.build_layout_tree(document, layout_engine, child, tree);
+    /// What `url` shows: an animated image's current frame, else what the
+    /// image cache holds; `None` while it loads.
+    fn loaded_image(&self, url: &str) -> Option<LoadedImage> {
+        match self.page().image_animations.current_frame(url) {
+            Some(frame) => Some(LoadedImage::Decoded(frame)),
+            None => self.page().images.get(url),
         }
     }
 
+    /// Descendants are clipped to the padding box, whose corners are the
+    /// border-box radii shrunk by the border widths.
+    fn overflow_clip(layout_box: &LayoutBox, radius: CornerRadii) -> ClipRect {
+        let padding_box = Rect {
+            x: layout_box.content_x - layout_box.padding_left,
+            y: layout_box.content_y - layout_box.padding_top,
+            width: layout_box.content_width + layout_box.padding_left + layout_box.padding_right,
+            height: layout_box.content_height + layout_box.padding_top + layout_box.padding_bottom,
+        };
+        let radius = radius
+            .fit(
+                padding_box.width + layout_box.border_left + layout_box.border_right,
+                padding_box.height + layout_box.border_top + layout_box.border_bottom,
+            )
+            .inset(
+                layout_box.border_top,
+                layout_box.border_right,
+                layout_box.border_bottom,
+                layout_box.border_left,
+            );
+        ClipRect::new(padding_box, radius)
+    }
+
     fn create_layout_node(
         &self,
         document: &Document,
@@ -905,12 +6489,16 @@ impl BrowserEngine {
             _ => {}
         }
 
-        let computed_styles = self.style_engine.get_computed_styles(node_id);
+        let computed_styles = self.page().style_engine.get_computed_styles(node_id);
         let computed_ref = computed_styles.as_deref();
 
         let mut element_type = self.determine_element_type(node.node_type, computed_ref)?;
 
-        if node.node_type == DomNodeType::Element && node.tag_name.eq_ignore_ascii_case("img") {
+        // A `<video>` paints its poster once it has loaded.
+        let poster = self.page().media.poster(node_id);
+        if node.node_type == DomNodeType::Element && node.tag_name.eq_ignore_ascii_case("img")
+            || poster.is_some()
+        {
             element_type = ElementType::Image;
         }
 
@@ -930,15 +6518,35 @@ impl BrowserEngine {
         if matches!(element_type, ElementType::Text) {
             // For text nodes, prefer inheriting color/family while keeping transparent background.
             style.background_color = None;
+            style.background_paint = None;
+            style.border_width = EdgeWidths::default();
+            style.border_color = None;
+        } else {
+            style.padding = EdgeWidths {
+                top: layout_box.padding_top,
+                right: layout_box.padding_right,
+                bottom: layout_box.padding_bottom,
+                left: layout_box.padding_left,
+            };
         }
 
         let image_url = if node.node_type == DomNodeType::Element
             && node.tag_name.eq_ignore_ascii_case("img")
         {
-            node.get_attribute("src")
+            // The source `load_images` fetched; one that does not resolve is
+            // kept as written.
+            document
+                .base_url()
+                .and_then(|base| image_source(&node, &base))
+                .map(String::from)
+                .or_else(|| {
+                    node.get_attribute("src")
+                        .map(|src| document.resolve_url(&src).map_or(src, String::from))
+                })
         } else {
-            None
+            poster.map(String::from)
         };
+        let image_frame = self.animated_image_frame(document, &node);
 
         Some(LayoutNode {
             node_id,
@@ -952,9 +6560,156 @@ impl BrowserEngine {
             style,
             text_content,
             image_url,
+            image_frame,
+            clip: ClipChain::new(),
         })
     }
 
+    /// The frame an `<img>` of an animated image is on; 0 otherwise.
+    fn animated_image_frame(
+        &self,
+        document: &Document,
+        node: &crate::core::dom::document::Node,
+    ) -> u32 {
+        if self.page().image_animations.is_empty() {
+            return 0;
+        }
+        let base = match document.base_url() {
+            Some(base) => base,
+            None => return 0,
+        };
+        image_source(node, &base)
+            .and_then(|url| self.page().image_animations.frame_index(url.as_str()))
+            .map_or(0, |frame| frame as u32)
+    }
+
+    /// Dumps the subtree at `root` in tree order, with a stack of its own
+    /// as [`Self::build_layout_tree`] does.
+    fn dump_layout_node(
+        &self,
+        document: &Document,
+        layout_engine: &LayoutEngine,
+        root: NodeId,
+        root_path: &str,
+        out: &mut Vec<serde_json::Value>,
+    ) {
+        let mut stack = vec![(root, root_path.to_string())];
+        while let Some((node_id, path)) = stack.pop() {
+            self.dump_one_layout_node(document, layout_engine, node_id, &path, out);
+            // Paths track the DOM, so a skipped node still consumes its index.
+            let children = document.get_children(node_id);
+            for (index, child) in children.into_iter().enumerate().rev() {
+                stack.push((child, format!("{path}/{index}")));
+            }
+        }
+    }
+
+    fn dump_one_layout_node(
+        &self,
+        document: &Document,
+        layout_engine: &LayoutEngine,
+        node_id: NodeId,
+        path: &str,
+        out: &mut Vec<serde_json::Value>,
+    ) {
+        if let Some(layout_node) = self.create_layout_node(document, layout_engine, node_id) {
+            let mut entry = serde_json::json!({
+                "path": path,
+                "type": match layout_node.element_type {
+                    ElementType::Block => "block",
+                    ElementType::Inline => "inline",
+                    ElementType::Image => "image",
+                    ElementType::Text => "text",
+                },
+                "bounds": {
+                    "x": round_for_dump(layout_node.bounds.x),
+                    "y": round_for_dump(layout_node.bounds.y),
+                    "width": round_for_dump(layout_node.bounds.width),
+                    "height": round_for_dump(layout_node.bounds.height),
+                },
+                "style": {
+                    "background_color": layout_node.style.background_color,
+                    "color": layout_node.style.color,
+                    "font_family": layout_node.style.font_family,
+                    "font_size": round_for_dump(layout_node.style.font_size),
+                },
+            });
+
+            if let Some(node) = document.get_node(node_id) {
+                let node = node.read();
+                if node.node_type == DomNodeType::Element {
+                    entry["tag"] = node.tag_name.to_ascii_lowercase().into();
+                }
+            }
+            if let Some(text) = layout_node.text_content {
+                entry["text"] = text.into();
+                if let Some(result) = layout_engine.get_layout_result(node_id) {
+                    let lines: Vec<serde_json::Value> = result
+                        .line_boxes
+                        .iter()
+                        .map(|line| {
+                            serde_json::json!({
+                                "x": round_for_dump(line.x),
+                                "y": round_for_dump(line.y),
+                                "width": round_for_dump(line.width),
+                                "height": round_for_dump(line.height),
+                            })
+                        })
+                        .collect();
+                    entry["lines"] = lines.into();
+                }
+            }
+            if let Some(src) = layout_node.image_url {
+                entry["image"] = src.into();
+            }
+            out.push(entry);
+        }
+    }
+
+    fn collect_paths(
+        document: &Document,
+        root: NodeId,
+        root_path: String,
+        out: &mut Vec<(String, NodeId)>,
+    ) {
+        let mut stack = vec![(root, root_path)];
+        while let Some((node_id, path)) = stack.pop() {
+            let children = document.get_children(node_id);
+            for (index, child) in children.into_iter().enumerate().rev() {
+                stack.push((child, format!("{path}/{index}")));
+            }
+            out.push((path, node_id));
+        }
+    }
+
+    /// CSS-like text for a computed value with floats rounded, for dumps.
+    fn computed_value_to_dump(value: &ComputedValue) -> String {
+        match value {
+            ComputedValue::Length(v) => format!("{}px", round_for_dump(*v)),
+            ComputedValue::Percentage(v) => format!("{}%", round_for_dump(*v)),
+            ComputedValue::Number(v) => round_for_dump(*v).to_string(),
+            ComputedValue::Integer(v) => v.to_string(),
+            ComputedValue::String(s) => format!("\"{}\"", s),
+            ComputedValue::Keyword(s) => s.clone(),
+            ComputedValue::Color(color) => Self::color_to_css(color),
+            ComputedValue::Url(url) => format!("url(\"{}\")", url),
+            ComputedValue::Function { name, args } => {
+                let args: Vec<_> = args.iter().map(Self::computed_value_to_dump).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            ComputedValue::List(values) => {
+                let values: Vec<_> = values.iter().map(Self::computed_value_to_dump).collect();
+                values.join(", ")
+            }
+            ComputedValue::None => "none".to_string(),
+            ComputedValue::Auto => "auto".to_string(),
+            ComputedValue::Initial => "initial".to_string(),
+            ComputedValue::Inherit => "inherit".to_string(),
+            ComputedValue::Unset => "unset".to_string(),
+            ComputedValue::Revert => "revert".to_string(),
+        }
+    }
+
     fn determine_element_type(
         &self,
         node_type: DomNodeType,
@@ -995,6 +6750,8 @@ impl BrowserEngine {
                     }
                 }
             }
+            style.background_paint = Self::extract_background_paint(computed)
+                .filter(|name| self.paint_sources.is_registered(name));
 
             if let Ok(value) = computed.get_computed_value("color") {
                 if let Some(color) = Self::computed_value_to_color(&value) {
@@ -1013,11 +6770,229 @@ impl BrowserEngine {
                     style.font_family = Some(family);
                 }
             }
+            style.font = FontRequest::from_styles(computed);
+
+            style.text_decoration = Self::extract_text_decoration(computed);
+            style.text_shadows = Self::extract_text_shadows(computed);
+            style.border_radius = Self::extract_border_radius(computed);
+            style.border_width = Self::extract_border_width(computed);
+            if !style.border_width.is_zero() {
+                style.border_color = match computed
+                    .get_computed_value("border-color")
+                    .ok()
+                    .and_then(|value| Self::computed_value_to_color(&value))
+                {
+                    Some(color) if color == "transparent" => None,
+                    Some(color) if !color.eq_ignore_ascii_case("currentcolor") => Some(color),
+                    _ => style.color.clone(),
+                };
+            }
+            style.clips_overflow = ["overflow", "overflow-x", "overflow-y"].iter().any(|name| {
+                match computed.get_computed_value(name) {
+                    Ok(ComputedValue::Keyword(keyword)) => !keyword.eq_ignore_ascii_case("visible"),
+                    _ => false,
+                }
+            }) || Containment::of(computed).paint;
+            style.language = computed.language();
         }
 
+        style.font_metrics = style
+            .font_family
+            .as_deref()
+            .and_then(|family| {
+                self.page()
+                    .fonts
+                    .metrics(family, &style.font, style.font_size)
+            })
+            .unwrap_or_else(|| FontMetrics::fallback(style.font_size));
         style
     }
 
+    /// The paint source `background-image: -vk-paint(name)` names.
+    fn extract_background_paint(computed: &ComputedStyles) -> Option<String> {
+        match computed.get_computed_value("background-image") {
+            Ok(ComputedValue::Function { name, args }) if name == PAINT_FUNCTION => {
+                match args.first() {
+                    Some(ComputedValue::Keyword(name) | ComputedValue::String(name)) => {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The `text-decoration` shorthand, then its `-line`, `-style`, `-color`
+    /// and `-thickness` longhands.
+    fn extract_text_decoration(computed: &ComputedStyles) -> TextDecoration {
+        let mut decoration = TextDecoration::default();
+        let values = |value: ComputedValue| match value {
+            ComputedValue::List(values) => values,
+            value => vec![value],
+        };
+
+        if let Ok(value) = computed.get_computed_value("text-decoration") {
+            for part in values(value) {
+                let applied = match &part {
+                    ComputedValue::Keyword(keyword) => decoration.apply_keyword(keyword),
+                    ComputedValue::None => decoration.apply_keyword("none"),
+                    _ => false,
+                };
+                if !applied {
+                    if let Some(length) = Self::computed_value_to_length(&part) {
+                        decoration.thickness = Some(length);
+                    } else if let Some(color) = Self::computed_value_to_color(&part) {
+                        decoration.color = Some(color);
+                    }
+                }
+            }
+        }
+        if let Ok(value) = computed.get_computed_value("text-decoration-line") {
+            decoration.lines = Default::default();
+            for part in values(value) {
+                if let ComputedValue::Keyword(keyword) = part {
+                    decoration.apply_keyword(&keyword);
+                }
+            }
+        }
+        if let Ok(ComputedValue::Keyword(keyword)) =
+            computed.get_computed_value("text-decoration-style")
+        {
+            decoration.apply_keyword(&keyword);
+        }
+        if let Ok(value) = computed.get_computed_value("text-decoration-color") {
+            if let Some(color) = Self::computed_value_to_color(&value) {
+                decoration.color = Some(color);
+            }
+        }
+        if let Ok(value) = computed.get_computed_value("text-decoration-thickness") {
+            decoration.thickness = Self::computed_value_to_length(&value);
+        }
+        decoration
+    }
+
+    /// `text-shadow` layers. Layers are split on a trailing comma of their
+    /// last token, which is how the value parser leaves them.
+    fn extract_text_shadows(computed: &ComputedStyles) -> Vec<TextShadow> {
+        let parts = match computed.get_computed_value("text-shadow") {
+            Ok(ComputedValue::List(values)) => values,
+            Ok(ComputedValue::None) | Err(_) => return Vec::new(),
+            Ok(value) => vec![value],
+        };
+
+        let mut shadows = Vec::new();
+        let mut lengths = Vec::new();
+        let mut color = None;
+        let count = parts.len();
+        for (index, part) in parts.into_iter().enumerate() {
+            let (part, ends_layer) = match part {
+                ComputedValue::Keyword(keyword) if keyword.ends_with(',') => (
+                    ComputedValue::Keyword(keyword.trim_end_matches(',').to_string()),
+                    true,
+                ),
+                part => (part, index + 1 == count),
+            };
+            if let Some(length) = Self::computed_value_to_length(&part) {
+                lengths.push(length);
+            } else if let Some(value) = Self::computed_value_to_color(&part) {
+                color = Some(value);
+            }
+            if ends_layer {
+                shadows.extend(TextShadow::from_parts(&lengths, color.take()));
+                lengths.clear();
+            }
+        }
+        shadows
+    }
+
+    /// A length in pixels; unitless numbers count as pixels, as the value
+    /// parser reads `2px` as an integer.
+    fn computed_value_to_length(value: &ComputedValue) -> Option<f32> {
+        match value {
+            ComputedValue::Length(v) | ComputedValue::Number(v) => Some(*v),
+            ComputedValue::Integer(v) => Some(*v as f32),
+            ComputedValue::Keyword(keyword) => keyword
+                .strip_suffix("px")
+                .and_then(|number| number.parse().ok()),
+            _ => None,
+        }
+    }
+
+    /// The `border-*-width` longhands, which layout reserves room for.
+    /// `border-style` and its per-side longhands of `none` or `hidden` leave
+    /// a side without a border.
+    fn extract_border_width(computed: &ComputedStyles) -> EdgeWidths {
+        let hidden = |name: &str| match computed.get_computed_value(name) {
+            Ok(ComputedValue::Keyword(keyword)) => {
+                keyword.eq_ignore_ascii_case("none") || keyword.eq_ignore_ascii_case("hidden")
+            }
+            Ok(ComputedValue::None) => true,
+            _ => false,
+        };
+        let all_hidden = hidden("border-style");
+
+        let mut widths = EdgeWidths::default();
+        for (side, width) in [
+            ("top", &mut widths.top),
+            ("right", &mut widths.right),
+            ("bottom", &mut widths.bottom),
+            ("left", &mut widths.left),
+        ] {
+            if all_hidden || hidden(&format!("border-{side}-style")) {
+                continue;
+            }
+            if let Ok(value) = computed.get_computed_value(&format!("border-{side}-width")) {
+                *width = Self::computed_value_to_length(&value)
+                    .unwrap_or(0.0)
+                    .max(0.0);
+            }
+        }
+        widths
+    }
+
+    /// `border-radius` (one to four lengths) overridden by the per-corner
+    /// longhands. Percentages are not resolved and count as zero.
+    fn extract_border_radius(computed: &ComputedStyles) -> CornerRadii {
+        let length = |value: &ComputedValue| match value {
+            ComputedValue::Length(v) => v.max(0.0),
+            _ => 0.0,
+        };
+
+        let mut radii = CornerRadii::default();
+        if let Ok(value) = computed.get_computed_value("border-radius") {
+            let values: Vec<f32> = match &value {
+                ComputedValue::List(values) => values.iter().map(length).collect(),
+                value => vec![length(value)],
+            };
+            let [top_left, top_right, bottom_right, bottom_left] = match values[..] {
+                [all] => [all; 4],
+                [a, b] => [a, b, a, b],
+                [a, b, c] => [a, b, c, b],
+                [a, b, c, d, ..] => [a, b, c, d],
+                [] => [0.0; 4],
+            };
+            radii = CornerRadii {
+                top_left,
+                top_right,
+                bottom_right,
+                bottom_left,
+            };
+        }
+
+        for (name, corner) in [
+            ("border-top-left-radius", &mut radii.top_left),
+            ("border-top-right-radius", &mut radii.top_right),
+            ("border-bottom-right-radius", &mut radii.bottom_right),
+            ("border-bottom-left-radius", &mut radii.bottom_left),
+        ] {
+            if let Ok(value) = computed.get_computed_value(name) {
+                *corner = length(&value);
+            }
+        }
+        radii
+    }
+
     fn computed_value_to_string(value: &ComputedValue) -> Option<String> {
         match value {
             ComputedValue::String(s) | ComputedValue::Keyword(s) => Some(s.clone()),
@@ -1057,17 +7032,120 @@ impl BrowserEngine {
     }
 
     async fn emit_event(&self, event: BrowserEvent) {
-        println!("[BrowserEvent] {:?}", event);
-        let _ = &self.event_system;
+        publish_event(&self.event_sender, &self.event_log, event);
+    }
+
+    /// Pass on the notifications service workers showed.
+    async fn announce_notifications(&self) {
+        let pwa_manager = match &self.pwa_manager {
+            Some(pwa_manager) => pwa_manager,
+            None => return,
+        };
+        for notification in pwa_manager.take_notifications().await {
+            self.emit_event(BrowserEvent::NotificationRequested { notification })
+                .await;
+        }
+    }
+
+    /// What belongs to the current document.
+    fn page(&self) -> Arc<Page> {
+        self.page.read().clone()
+    }
+
+    fn record_phase(&self, url: &str, phase: NavigationPhase) {
+        self.event_log.record(LoggedEvent::NavigationPhase {
+            url: url.to_string(),
+            phase,
+        });
+    }
+
+    /// The document's execution context.
+    /// The author rules of `document`, parsed under the content limits.
+    fn collect_style_rules(&self, document: &Document) -> Vec<crate::core::css::CSSRule> {
+        collect_style_rules(
+            document,
+            &self.page().stylesheets,
+            &self.config.read().content_limits,
+            &self.page().content_limit_breaches,
+            &self.page().features,
+        )
+    }
+
+    /// Report the linked stylesheets that failed to load since last time.
+    /// The page renders without them.
+    async fn report_stylesheet_failures(&self) {
+        for failure in self.page().stylesheets.take_failures() {
+            tracing::warn!("Stylesheet {} failed: {}", failure.url, failure.error);
+            self.emit_event(BrowserEvent::NetworkError {
+                url: failure.url.to_string(),
+                error: failure.error,
+            })
+            .await;
+        }
+    }
+
+    /// Warn once per page of each content limit it went past.
+    async fn report_content_limit_breaches(&self, document: &Document) {
+        for breach in self.page().content_limit_breaches.take_unreported() {
+            tracing::warn!(
+                "{} exceeds the {} limit: {} for {}",
+                document.get_url().unwrap_or_default(),
+                breach.limit.metric(),
+                breach.value,
+                breach.threshold
+            );
+            self.emit_event(BrowserEvent::PerformanceWarning {
+                metric: breach.limit.metric().to_string(),
+                value: breach.value as f64,
+                threshold: breach.threshold as f64,
+                url: document.get_url(),
+                context: Some(self.document_context(document)),
+            })
+            .await;
+        }
+    }
+
+    /// Report once per page each disabled feature it tried to use.
+    async fn report_disabled_features(&self, document: &Document) {
+        for used in self.page().features.take_unreported() {
+            tracing::warn!(
+                "{} uses {}, but the {} feature is disabled for it",
+                document.get_url().unwrap_or_default(),
+                used.usage,
+                used.feature.name()
+            );
+            self.emit_event(BrowserEvent::DisabledFeatureUsed {
+                feature: used.feature.name().to_string(),
+                usage: used.usage,
+                url: document.get_url(),
+            })
+            .await;
+        }
+    }
+
+    fn document_context(&self, document: &Document) -> ContextId {
+        let origin = document
+            .get_url()
+            .and_then(|url| url::Url::parse(&url).ok())
+            .map(|url| url.origin().ascii_serialization());
+        ContextId {
+            tab: self.tab_id,
+            kind: ContextKind::Document,
+            origin,
+        }
     }
 
-    // Placeholder for memory metric gathering
-    async fn get_memory_usage(&self) -> MemoryMetrics {
-        // Provide deterministic stub values until wired to a real sampler
+    /// Heap totals over `contexts`, and the device memory of the window's
+    /// presenter; system memory is not sampled yet.
+    fn get_memory_usage(&self, contexts: &[ContextMetrics], gpu_bytes: u64) -> MemoryMetrics {
+        let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        let heap: u64 = contexts.iter().map(|c| c.js_heap_bytes).sum();
+        let dom: u64 = contexts.iter().map(|c| c.dom_bytes_estimate).sum();
+        let images: u64 = contexts.iter().map(|c| c.image_cache_bytes).sum();
         MemoryMetrics {
-            heap_size_mb: 0.0,
-            used_heap_mb: 0.0,
-            gpu_memory_mb: 0.0,
+            heap_size_mb: mb(heap),
+            used_heap_mb: mb(heap + dom + images),
+            gpu_memory_mb: mb(gpu_bytes),
             system_memory_mb: 0.0,
         }
     }
@@ -1079,8 +7157,406 @@ impl Drop for BrowserEngine {
     }
 }
 
+/// Send `event` to the subscribers, if any, and log it.
+fn publish_event(sender: &broadcast::Sender<BrowserEvent>, log: &EventLog, event: BrowserEvent) {
+    // Sending fails only when nobody is subscribed.
+    let _ = sender.send(event.clone());
+    log.record(LoggedEvent::Browser { event });
+}
+
+/// The navigation under way, and the id the next one takes.
+#[derive(Default)]
+struct NavigationSlot {
+    next_id: u64,
+    current: Option<CurrentNavigation>,
+}
+
+impl NavigationSlot {
+    /// Record `request` as the fetch of `navigation`'s document. False when
+    /// `navigation` is no longer the one under way.
+    fn set_request(&mut self, navigation: u64, request: &str) -> bool {
+        match &mut self.current {
+            Some(current) if current.id == navigation => {
+                current.request = Some(request.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A navigation under way: its id, its url and, while its document is
+/// being fetched, the network request doing so, which cancelling the
+/// navigation stops.
+struct CurrentNavigation {
+    id: u64,
+    url: String,
+    request: Option<String>,
+}
+
+/// Bytes of a document of unknown length between two progress reports.
+const LOAD_PROGRESS_STEP: u64 = 64 * 1024;
+
+/// Passes a document's body on to `inner`, if any, reporting how much of
+/// it has arrived as [`BrowserEvent::LoadProgress`]: on the first chunk,
+/// then every percent of a known length or every [`LOAD_PROGRESS_STEP`]
+/// bytes of an unknown one, and on the last. Progress goes to subscribers
+/// only; it would crowd everything else out of the event log.
+struct LoadProgressObserver<'a> {
+    inner: Option<&'a mut dyn BodyObserver>,
+    sender: &'a broadcast::Sender<BrowserEvent>,
+    url: String,
+    bytes: u64,
+    /// `bytes` as of the last report.
+    reported: u64,
+    total: Option<u64>,
+}
+
+impl BodyObserver for LoadProgressObserver<'_> {
+    fn start(&mut self, url: &url::Url, headers: &std::collections::HashMap<String, String>) {
+        let header = |wanted: &str| {
+            headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| value.trim())
+        };
+        // A compressed body's length says nothing of the decoded bytes
+        // counted here.
+        let encoded = header("content-encoding")
+            .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity"));
+        self.total = header("content-length")
+            .filter(|_| !encoded)
+            .and_then(|value| value.parse().ok());
+        if let Some(inner) = self.inner.as_deref_mut() {
+            inner.start(url, headers);
+        }
+    }
+
+    fn chunk(&mut self, bytes: &[u8]) {
+        if let Some(inner) = self.inner.as_deref_mut() {
+            inner.chunk(bytes);
+        }
+        if bytes.is_empty() {
+            return;
+        }
+        let first = self.bytes == 0;
+        self.bytes += bytes.len() as u64;
+        let (step, done) = match self.total {
+            Some(total) => ((total / 100).max(1), self.bytes >= total),
+            None => (LOAD_PROGRESS_STEP, false),
+        };
+        if !first && !done && self.bytes - self.reported < step {
+            return;
+        }
+        self.reported = self.bytes;
+        let event = BrowserEvent::LoadProgress {
+            url: self.url.clone(),
+            bytes: self.bytes,
+            total: self.total,
+        };
+        // Sending fails only when nobody is subscribed.
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Whether a `Cache-Control` value forbids keeping the response.
+fn cache_control_no_store(value: &str) -> bool {
+    value
+        .split(',')
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+}
+
+/// Return the `href` of the first `<link rel="manifest">` in the document.
+fn find_manifest_link(document: &Document) -> Option<String> {
+    document
+        .get_elements_by_tag_name("link")
+        .into_iter()
+        .filter_map(|id| document.get_node(id))
+        .find_map(|node| {
+            let node = node.read();
+            let is_manifest = node
+                .get_attribute("rel")
+                .map(|rel| {
+                    rel.split_whitespace()
+                        .any(|r| r.eq_ignore_ascii_case("manifest"))
+                })
+                .unwrap_or(false);
+            if is_manifest {
+                node.get_attribute("href")
+            } else {
+                None
+            }
+        })
+}
+
+/// Page `index` of a printout, adding blank pages up to it.
+fn print_page(pages: &mut Vec<PdfPage>, index: usize, size: PageSize) -> &mut PdfPage {
+    while pages.len() <= index {
+        pages.push(PdfPage::new(size.width, size.height));
+    }
+    &mut pages[index]
+}
+
+/// The `<style>` and `<link>` elements of the document tree, in tree order.
+/// `<video>` and `<audio>` elements, in tree order.
+fn media_elements(document: &Document) -> Vec<NodeId> {
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            if MediaKind::of(&node.read()).is_some() {
+                found.push(node_id);
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    found
+}
+
+/// The box model of each element laid out in `root`'s subtree, in tree
+/// order, for the debug overlay to outline.
+fn debug_box_outlines(
+    document: &Document,
+    layout_engine: &LayoutEngine,
+    root: Option<NodeId>,
+) -> Vec<BoxOutline> {
+    let mut outlines = Vec::new();
+    let mut stack: Vec<NodeId> = root.into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        let is_element = document
+            .get_node(node_id)
+            .is_some_and(|node| node.read().node_type == DomNodeType::Element);
+        if is_element {
+            if let Some(layout_box) = layout_engine.get_layout_box(node_id) {
+                outlines.push(BoxOutline::from_layout_box(&layout_box));
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    outlines
+}
+
+/// Where the document's `<img>`s load from, in tree order; `<template>`
+/// contents are inert and other schemes need no fetch.
+fn image_sources(document: &Document) -> Vec<url::Url> {
+    let base = match document.base_url() {
+        Some(base) => base,
+        None => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("template") {
+                continue;
+            }
+            if let Some(url) = image_source(&node, &base) {
+                found.push(url);
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    found
+}
+
+/// URLs of the animated images shown by an `<img>` that overlaps the
+/// viewport. Contents `content-visibility` skips are out of view.
+fn animated_images_in_view(
+    document: &Document,
+    layout_engine: &LayoutEngine,
+    animations: &ImageAnimations,
+) -> std::collections::HashSet<String> {
+    let mut found = std::collections::HashSet::new();
+    let base = match document.base_url() {
+        Some(base) => base,
+        None => return found,
+    };
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("template") {
+                continue;
+            }
+            match image_source(&node, &base) {
+                Some(url)
+                    if animations.contains(url.as_str())
+                        && layout_engine.is_in_viewport(node_id) =>
+                {
+                    found.insert(url.into());
+                }
+                _ => {}
+            }
+        }
+        if !layout_engine.is_skipped(node_id) {
+            stack.extend(document.get_children(node_id));
+        }
+    }
+    found
+}
+
+fn style_elements(document: &Document) -> Vec<NodeId> {
+    let mut found = Vec::new();
+    let mut stack: Vec<NodeId> = document.get_root_node().into_iter().collect();
+    while let Some(node_id) = stack.pop() {
+        if let Some(node) = document.get_node(node_id) {
+            let node = node.read();
+            if node.tag_name.eq_ignore_ascii_case("style")
+                || node.tag_name.eq_ignore_ascii_case("link")
+            {
+                found.push(node_id);
+            }
+        }
+        stack.extend(document.get_children(node_id).into_iter().rev());
+    }
+    found
+}
+
+/// The `href` of a `<link rel="stylesheet">`; alternate stylesheets are
+/// never loaded.
+fn stylesheet_link_href(node: &crate::core::dom::document::Node) -> Option<String> {
+    if !node.tag_name.eq_ignore_ascii_case("link") {
+        return None;
+    }
+    let rel = node.get_attribute("rel")?;
+    let mut tokens = rel.split_ascii_whitespace();
+    if !tokens
+        .clone()
+        .any(|token| token.eq_ignore_ascii_case("stylesheet"))
+        || tokens.any(|token| token.eq_ignore_ascii_case("alternate"))
+    {
+        return None;
+    }
+    node.get_attribute("href")
+        .map(|href| href.trim().to_string())
+        .filter(|href| !href.is_empty())
+}
+
+/// What a `keydown` or `keyup` of `key` is initialized with.
+fn key_event_init(kind: &str, key: &str, modifiers: Modifiers) -> serde_json::Value {
+    serde_json::json!({
+        "type": kind,
+        "key": key,
+        "shiftKey": modifiers.contains(Modifiers::SHIFT),
+        "ctrlKey": modifiers.contains(Modifiers::CTRL),
+        "altKey": modifiers.contains(Modifiers::ALT),
+        "metaKey": modifiers.contains(Modifiers::META),
+        "cancelable": true,
+    })
+}
+
+/// The `href` and `ping` of the link `node_id` is in: the nearest `<a>` or
+/// `<area>` with an `href` at or above it.
+fn hyperlink_of(document: &Document, node_id: NodeId) -> Option<(String, Option<String>)> {
+    let mut current = Some(node_id);
+    while let Some(id) = current {
+        if let Some(node) = document.get_node(id) {
+            let node = node.read();
+            let is_link = node.tag_name.eq_ignore_ascii_case("a")
+                || node.tag_name.eq_ignore_ascii_case("area");
+            if let Some(href) = node.get_attribute("href").filter(|_| is_link) {
+                return Some((href.trim().to_string(), node.get_attribute("ping")));
+            }
+        }
+        current = document.get_parent(id);
+    }
+    None
+}
+
+fn is_in_head(document: &Document, node_id: NodeId) -> bool {
+    let mut current = document.get_parent(node_id);
+    while let Some(parent) = current {
+        let is_head = document
+            .get_node(parent)
+            .is_some_and(|node| node.read().tag_name.eq_ignore_ascii_case("head"));
+        if is_head {
+            return true;
+        }
+        current = document.get_parent(parent);
+    }
+    false
+}
+
+/// The author rules of the document: its `<style>` elements and loaded
+/// `<link>` stylesheets, in tree order, each under its `media` attribute.
+fn collect_style_rules(
+    document: &Document,
+    stylesheets: &LinkedStylesheets,
+    limits: &ContentLimits,
+    breaches: &LimitBreaches,
+    features: &DocumentFeatures,
+) -> Vec<crate::core::css::CSSRule> {
+    let mut rules = Vec::new();
+    let mut parser =
+        crate::core::css::CSSParser::with_limits(limits).with_features(features.current());
+    for node_id in style_elements(document) {
+        let (is_style, media) = match document.get_node(node_id) {
+            Some(node) => {
+                let node = node.read();
+                (
+                    node.tag_name.eq_ignore_ascii_case("style"),
+                    node.get_attribute("media"),
+                )
+            }
+            None => continue,
+        };
+        let sheet = if is_style {
+            let text: String = document
+                .get_children(node_id)
+                .into_iter()
+                .filter_map(|child| document.get_node(child))
+                .map(|child| child.read().get_text_content())
+                .collect();
+            match parser.parse(&text) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    tracing::debug!("Skipping unparsable <style>: {}", e);
+                    continue;
+                }
+            }
+        } else {
+            stylesheets.rules(node_id).unwrap_or_default()
+        };
+        rules.extend(under_media(sheet, media.as_deref()));
+    }
+    breaches.record(parser.take_breaches());
+    features.record(parser.take_disabled_uses());
+    rules
+}
+
+/// `rules` as they are without a `media` attribute; with one, inside an
+/// `@media` block for each query of its list, so they apply while any
+/// matches.
+fn under_media(
+    rules: Vec<crate::core::css::CSSRule>,
+    media: Option<&str>,
+) -> Vec<crate::core::css::CSSRule> {
+    let queries = match media {
+        Some(media) => crate::core::css::CSSParser::new().parse_media_list(media),
+        None => return rules,
+    };
+    if queries.is_empty() || rules.is_empty() {
+        return rules;
+    }
+    queries
+        .into_iter()
+        .map(|media_query| {
+            crate::core::css::CSSRule::Media(crate::core::css::parser::CSSMediaRule {
+                media_query,
+                rules: rules.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Round to two decimals for layout dumps, folding `-0.0` into `0.0`.
+fn round_for_dump(value: f32) -> f64 {
+    (value as f64 * 100.0).round() / 100.0 + 0.0
+}
+
 /// Parse the part after "data:" in a data URL. Returns (mime, bytes).
-fn parse_data_url(rest: &str) -> std::result::Result<(String, Vec<u8>), String> {
+pub(crate) fn parse_data_url(rest: &str) -> std::result::Result<(String, Vec<u8>), String> {
     // RFC 2397: data:[<mediatype>][;base64],<data>
     let idx = rest
         .find(',')
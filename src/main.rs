@@ -5,9 +5,12 @@
 //! No nested runtimes, no `block_on` inside another runtime.
 
 use std::env;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Instant;
 
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use tokio::{
     runtime::{Builder, Runtime},
     signal,
@@ -15,14 +18,19 @@ use tokio::{
 use tracing::{error, info, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+use vulkan_browser_engine::core::accelerators::Modifiers;
+use vulkan_browser_engine::core::event_log::EventLog;
+use vulkan_browser_engine::renderer::DebugOverlayFlags;
+use vulkan_browser_engine::{
+    BrowserConfig, BrowserEngine, ImeCompositionState, InputEvent, KeyRoute,
+};
 
 use winit::{
-    dpi::LogicalSize,
-    event::{ElementState, Event, KeyEvent, WindowEvent},
+    dpi::{LogicalPosition, LogicalSize},
+    event::{ElementState, Event, Ime, KeyEvent, MouseButton, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Fullscreen, WindowBuilder},
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::{Fullscreen, Window, WindowBuilder},
 };
 
 #[derive(Debug, Clone)]
@@ -33,6 +41,9 @@ struct AppConfig {
     enable_tracy: bool,
     log_level: Level,
     profile_startup: bool,
+    dump_events_on_exit: Option<PathBuf>,
+    private: bool,
+    debug_overlays: DebugOverlayFlags,
 }
 
 impl AppConfig {
@@ -55,7 +66,21 @@ impl AppConfig {
                 "--debug" => config.log_level = Level::DEBUG,
                 "--trace" => config.log_level = Level::TRACE,
                 "--profile" => config.profile_startup = true,
-                _ => {}
+                "--private" => config.private = true,
+                "--dump-events-on-exit" => {
+                    if i + 1 < args.len() {
+                        config.dump_events_on_exit = Some(PathBuf::from(&args[i + 1]));
+                        i += 1;
+                    }
+                }
+                arg => {
+                    if let Some(list) = arg.strip_prefix("--debug-overlays=") {
+                        match DebugOverlayFlags::parse(list) {
+                            Ok(flags) => config.debug_overlays = flags,
+                            Err(e) => eprintln!("Ignoring --debug-overlays: {}", e),
+                        }
+                    }
+                }
             }
             i += 1;
         }
@@ -73,6 +98,48 @@ impl Default for AppConfig {
             enable_tracy: false,
             log_level: Level::INFO,
             profile_startup: false,
+            dump_events_on_exit: None,
+            private: false,
+            debug_overlays: DebugOverlayFlags::NONE,
+        }
+    }
+}
+
+/// Writes the engine's event log to the `--dump-events-on-exit` path.
+#[derive(Clone)]
+struct EventDump {
+    log: Arc<EventLog>,
+    path: PathBuf,
+}
+
+impl EventDump {
+    fn new(engine: &BrowserEngine, path: Option<&PathBuf>) -> Option<Self> {
+        let dump = Self {
+            log: engine.event_log(),
+            path: path?.clone(),
+        };
+        dump.install_panic_hook();
+        Some(dump)
+    }
+
+    /// Dump on panic too, after the default hook has reported it.
+    fn install_panic_hook(&self) {
+        let dump = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous(info);
+            dump.write();
+        }));
+    }
+
+    fn write(&self) {
+        match self.log.dump_to_file(&self.path) {
+            Ok(()) => eprintln!("Event log written to {}", self.path.display()),
+            Err(e) => eprintln!(
+                "Failed to write event log to {}: {}",
+                self.path.display(),
+                e
+            ),
         }
     }
 }
@@ -171,25 +238,46 @@ impl PerformanceMonitor {
     }
 }
 
-/// Executes a tiny placeholder rendering workload to keep the loop ticking.
-/// Replace with real engine-driven pipeline once available.
-fn do_render_tick() -> std::time::Duration {
-    let t0 = Instant::now();
-    std::thread::sleep(std::time::Duration::from_millis(1));
-    t0.elapsed()
-}
-
-fn setup_signal_handlers(rt: &Runtime) {
+fn setup_signal_handlers(rt: &Runtime, event_dump: Option<EventDump>) {
     // Fire-and-forget task on the same runtime
-    let signal_task = rt.spawn(async {
+    let signal_task = rt.spawn(async move {
         if let Ok(()) = signal::ctrl_c().await {
             info!("Received SIGINT, shutting down gracefully");
+            if let Some(dump) = &event_dump {
+                dump.write();
+            }
             std::process::exit(0);
         }
     });
     drop(signal_task);
 }
 
+/// Present the engine's frames in `window`. Without a Vulkan surface the
+/// window stays blank; the page still runs.
+fn attach_window(engine: &BrowserEngine, window: &Window, rt: &Runtime) {
+    let handles = window
+        .display_handle()
+        .and_then(|display| Ok((display.as_raw(), window.window_handle()?.as_raw())));
+    let (display, window_handle) = match handles {
+        Ok(handles) => handles,
+        Err(e) => {
+            error!("No window handle to render to: {}", e);
+            return;
+        }
+    };
+    // SAFETY: the window lives until the event loop exits, which shuts the
+    // engine down first.
+    let attached = rt.block_on(unsafe { engine.attach_window(display, window_handle) });
+    if let Err(e) = attached {
+        error!("Cannot present to the window: {}", e);
+        return;
+    }
+    let size = window.inner_size();
+    if let Err(e) = rt.block_on(engine.resize_viewport(size.width, size.height)) {
+        error!("Resize failed: {}", e);
+    }
+}
+
 async fn handle_window_resize(
     engine: &BrowserEngine,
     new_w: u32,
@@ -200,13 +288,40 @@ async fn handle_window_resize(
     Ok(())
 }
 
-#[allow(dead_code)]
-async fn execute_render_frame(
-    _engine: &BrowserEngine,
-    start_time: Instant,
-) -> Result<std::time::Duration, vulkan_browser_engine::BrowserError> {
-    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
-    Ok(start_time.elapsed())
+/// Window-level shortcuts, on top of the engine's built-in ones. Like those,
+/// they only fire for keys the page did not consume.
+const WINDOW_ACCELERATORS: &[(&str, &str)] = &[
+    ("F11", "toggle_fullscreen"),
+    ("Escape", "exit_fullscreen"),
+    ("F12", "devtools"),
+    ("Ctrl+L", "focus_address_bar"),
+    ("Ctrl+T", "new_tab"),
+    ("Ctrl+W", "close_tab"),
+];
+
+/// `KeyboardEvent.key` for a winit key; winit names keys the same way.
+fn dom_key(key: &Key) -> Option<String> {
+    match key {
+        Key::Character(text) => Some(text.to_string()),
+        Key::Named(NamedKey::Space) => Some(" ".to_string()),
+        Key::Named(named) => Some(format!("{:?}", named)),
+        Key::Unidentified(_) | Key::Dead(_) => None,
+    }
+}
+
+fn to_modifiers(state: ModifiersState) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    for (held, modifier) in [
+        (state.shift_key(), Modifiers::SHIFT),
+        (state.control_key(), Modifiers::CTRL),
+        (state.alt_key(), Modifiers::ALT),
+        (state.super_key(), Modifiers::META),
+    ] {
+        if held {
+            modifiers = modifiers | modifier;
+        }
+    }
+    modifiers
 }
 
 fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::Result<()> {
@@ -217,6 +332,7 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
         .with_inner_size(LogicalSize::new(1920, 1080))
         .build(&event_loop)
         .expect("Failed to create window");
+    window.set_ime_allowed(true);
     let window = Rc::new(window);
 
     let browser_config = BrowserConfig {
@@ -227,11 +343,18 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
         enable_chrome_apis: true,
         viewport_width: 1920,
         viewport_height: 1080,
+        private_mode: app_config.private,
+        debug_overlays: app_config.debug_overlays,
         ..Default::default()
     };
 
     // Engine lives on this thread only.
     let engine = Rc::new(rt.block_on(BrowserEngine::new(browser_config))?);
+    let event_dump = EventDump::new(&engine, app_config.dump_events_on_exit.as_ref());
+    for (keystroke, action) in WINDOW_ACCELERATORS {
+        engine.register_accelerator(keystroke, action)?;
+    }
+    attach_window(&engine, &window, rt);
 
     // Initial navigation
     if let Some(url) = app_config.url {
@@ -247,6 +370,12 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
     let mut frame_count: u64 = 0;
     let mut is_fullscreen = false;
     let mut perf_monitor = PerformanceMonitor::new();
+    let mut ime_composing = false;
+    let mut modifiers = ModifiersState::empty();
+    // Last pointer position in CSS pixels, and files dropped since the last
+    // frame; winit reports one `DroppedFile` per file.
+    let mut cursor = (0, 0);
+    let mut dropped_files: Vec<PathBuf> = Vec::new();
 
     // Capture in the closure (Rc clones are cheap and single-threaded).
     let window_for_loop = Rc::clone(&window);
@@ -265,6 +394,9 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
                     if let Err(e) = rt.block_on(async { engine_for_loop.shutdown().await }) {
                         error!("Error during shutdown: {}", e);
                     }
+                    if let Some(dump) = &event_dump {
+                        dump.write();
+                    }
                     elwt.exit();
                 }
 
@@ -281,68 +413,90 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
                     }
                 }
 
+                Event::WindowEvent {
+                    event: WindowEvent::ModifiersChanged(state),
+                    ..
+                } => {
+                    modifiers = state.state();
+                }
+
                 Event::WindowEvent {
                     event:
                         WindowEvent::KeyboardInput {
                             event:
                                 KeyEvent {
-                                    physical_key: PhysicalKey::Code(keycode),
+                                    logical_key,
                                     state: ElementState::Pressed,
                                     ..
                                 },
                             ..
                         },
                     ..
-                } => match keycode {
-                    KeyCode::F5 | KeyCode::KeyR => {
-                        if let Err(e) = rt.block_on(async { engine_for_loop.reload().await }) {
-                            error!("Failed to reload: {}", e);
-                        } else {
-                            info!("Reloaded page");
+                } => {
+                    let key = match dom_key(&logical_key) {
+                        Some(key) => key,
+                        None => return,
+                    };
+                    let route =
+                        rt.block_on(engine_for_loop.press_key(&key, to_modifiers(modifiers)));
+                    let action = match route {
+                        Ok(KeyRoute::Accelerator(action)) => action,
+                        Ok(_) => return,
+                        Err(e) => {
+                            error!("Key press failed: {}", e);
+                            return;
                         }
-                    }
-                    KeyCode::F11 => {
-                        is_fullscreen = !is_fullscreen;
-                        if is_fullscreen {
-                            let monitor = window_for_loop
-                                .current_monitor()
-                                .or_else(|| window_for_loop.available_monitors().next());
-                            window_for_loop.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
-                            info!("Entered fullscreen mode");
-                        } else {
-                            window_for_loop.set_fullscreen(None);
-                            info!("Exited fullscreen mode");
+                    };
+                    match action.as_str() {
+                        "toggle_fullscreen" => {
+                            is_fullscreen = !is_fullscreen;
+                            if is_fullscreen {
+                                let monitor = window_for_loop
+                                    .current_monitor()
+                                    .or_else(|| window_for_loop.available_monitors().next());
+                                window_for_loop
+                                    .set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+                                info!("Entered fullscreen mode");
+                            } else {
+                                window_for_loop.set_fullscreen(None);
+                                info!("Exited fullscreen mode");
+                            }
                         }
-                    }
-                    KeyCode::F12 => {
-                        info!("Developer tools toggle requested (engine API required)");
-                    }
-                    KeyCode::Escape => {
-                        if is_fullscreen {
-                            is_fullscreen = false;
-                            window_for_loop.set_fullscreen(None);
-                            info!("Exited fullscreen mode with Escape");
+                        "exit_fullscreen" => {
+                            if is_fullscreen {
+                                is_fullscreen = false;
+                                window_for_loop.set_fullscreen(None);
+                                info!("Exited fullscreen mode with Escape");
+                            }
                         }
+                        "devtools" => {
+                            info!("Developer tools toggle requested (engine API required)");
+                        }
+                        "focus_address_bar" => {
+                            info!("Focus address bar requested (engine API required)");
+                        }
+                        "new_tab" => {
+                            info!("New tab requested (engine API required)");
+                        }
+                        "close_tab" => {
+                            info!("Close tab requested (engine API required)");
+                        }
+                        other => info!("Accelerator {} handled by the engine", other),
                     }
-                    KeyCode::ArrowLeft => {
-                        info!("Navigate back requested (engine API required)");
-                    }
-                    KeyCode::ArrowRight => {
-                        info!("Navigate forward requested (engine API required)");
-                    }
-                    KeyCode::KeyL => {
-                        info!("Focus address bar requested (engine API required)");
-                    }
-                    KeyCode::KeyT => {
-                        info!("New tab requested (engine API required)");
-                    }
-                    KeyCode::KeyW => {
-                        info!("Close tab requested (engine API required)");
-                    }
-                    _ => {}
-                },
+                }
 
                 Event::AboutToWait => {
+                    // Drops carry no position; they land where the pointer was last seen.
+                    if !dropped_files.is_empty() {
+                        let event = InputEvent::FileDrop {
+                            paths: std::mem::take(&mut dropped_files),
+                            x: cursor.0,
+                            y: cursor.1,
+                        };
+                        if let Err(e) = rt.block_on(engine_for_loop.handle_input_event(event)) {
+                            error!("File drop failed: {}", e);
+                        }
+                    }
                     window_for_loop.request_redraw();
                 }
 
@@ -356,11 +510,15 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
                     last_frame_time = now;
                     frame_count = frame_count.wrapping_add(1);
 
-                    // Tiny render tick; swap for real pipeline later.
+                    // Timers and animation frames run before the frame is drawn.
+                    if let Err(e) = rt.block_on(engine_for_loop.tick()) {
+                        error!("Event loop tick failed: {}", e);
+                    }
+
                     let render_start = Instant::now();
-                    let _ = do_render_tick();
-                    // Example when ready:
-                    // let _ = rt.block_on(execute_render_frame(&engine_for_loop, render_start));
+                    if let Err(e) = rt.block_on(engine_for_loop.render_frame()) {
+                        error!("Render failed: {}", e);
+                    }
 
                     let render_time = render_start.elapsed();
                     if render_time.as_millis() > 16 {
@@ -378,13 +536,42 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
                     event: WindowEvent::CursorMoved { position, .. },
                     ..
                 } => {
-                    info!("Mouse moved to {:?}", position);
+                    let position = position.to_logical::<f64>(window_for_loop.scale_factor());
+                    cursor = (position.x as i32, position.y as i32);
+                    let event = InputEvent::MouseMove {
+                        x: cursor.0,
+                        y: cursor.1,
+                    };
+                    if let Err(e) = rt.block_on(engine_for_loop.handle_input_event(event)) {
+                        error!("Mouse move failed: {}", e);
+                    }
                 }
                 Event::WindowEvent {
                     event: WindowEvent::MouseInput { state, button, .. },
                     ..
                 } => {
-                    info!("Mouse button {:?} {:?}", button, state);
+                    let button = match button {
+                        MouseButton::Left => 0,
+                        MouseButton::Middle => 1,
+                        MouseButton::Right => 2,
+                        MouseButton::Back => 3,
+                        MouseButton::Forward => 4,
+                        MouseButton::Other(_) => return,
+                    };
+                    let (x, y) = cursor;
+                    let event = match state {
+                        ElementState::Pressed => InputEvent::MouseDown { x, y, button },
+                        ElementState::Released => InputEvent::MouseUp { x, y, button },
+                    };
+                    if let Err(e) = rt.block_on(engine_for_loop.handle_input_event(event)) {
+                        error!("Mouse button failed: {}", e);
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => {
+                    dropped_files.push(path);
                 }
                 Event::WindowEvent {
                     event: WindowEvent::MouseWheel { delta, .. },
@@ -396,7 +583,44 @@ fn run_windowed(app_config: AppConfig, rt: &Runtime) -> vulkan_browser_engine::R
                     event: WindowEvent::Ime(ime_input),
                     ..
                 } => {
-                    info!("IME input: {:?}", ime_input);
+                    // winit has no explicit start; the first non-empty preedit opens a composition.
+                    let states = match ime_input {
+                        Ime::Preedit(text, cursor_range) => {
+                            let mut states = Vec::new();
+                            if !ime_composing && !text.is_empty() {
+                                ime_composing = true;
+                                states.push(ImeCompositionState::Start);
+                            }
+                            if ime_composing {
+                                states.push(ImeCompositionState::Update { text, cursor_range });
+                            }
+                            states
+                        }
+                        Ime::Commit(text) => {
+                            ime_composing = false;
+                            vec![ImeCompositionState::Commit { text }]
+                        }
+                        Ime::Disabled if ime_composing => {
+                            ime_composing = false;
+                            vec![ImeCompositionState::Cancel]
+                        }
+                        _ => Vec::new(),
+                    };
+
+                    for state in states {
+                        let event = InputEvent::ImeComposition { state };
+                        if let Err(e) = rt.block_on(engine_for_loop.handle_input_event(event)) {
+                            error!("IME input failed: {}", e);
+                        }
+                    }
+
+                    // Keep the candidate window next to the caret.
+                    if let Some(caret) = rt.block_on(engine_for_loop.caret_rect()) {
+                        window_for_loop.set_ime_cursor_area(
+                            LogicalPosition::new(caret.x as f64, caret.y as f64),
+                            LogicalSize::new(caret.width as f64, caret.height as f64),
+                        );
+                    }
                 }
                 Event::WindowEvent {
                     event: WindowEvent::Focused(focused),
@@ -428,15 +652,25 @@ fn main() -> vulkan_browser_engine::Result<()> {
 
     let startup_start = app_config.profile_startup.then_some(Instant::now());
 
-    let browser_config = BrowserConfig::default();
+    let browser_config = BrowserConfig {
+        private_mode: app_config.private,
+        debug_overlays: app_config.debug_overlays,
+        ..Default::default()
+    };
 
     if app_config.headless && app_config.benchmark {
         let engine = rt.block_on(BrowserEngine::new(browser_config))?;
-        setup_signal_handlers(&rt);
-        rt.block_on(run_headless_benchmark(&engine))?;
+        let event_dump = EventDump::new(&engine, app_config.dump_events_on_exit.as_ref());
+        setup_signal_handlers(&rt, event_dump.clone());
+        let result = rt.block_on(run_headless_benchmark(&engine));
+        if let Some(dump) = &event_dump {
+            dump.write();
+        }
+        result?;
     } else if app_config.headless {
         let engine = rt.block_on(BrowserEngine::new(browser_config))?;
-        setup_signal_handlers(&rt);
+        let event_dump = EventDump::new(&engine, app_config.dump_events_on_exit.as_ref());
+        setup_signal_handlers(&rt, event_dump.clone());
 
         if let Some(url) = &app_config.url {
             rt.block_on(engine.load_url(url))?;
@@ -449,6 +683,9 @@ fn main() -> vulkan_browser_engine::Result<()> {
         if let Err(e) = rt.block_on(async { engine.shutdown().await }) {
             error!("Shutdown error: {}", e);
         }
+        if let Some(dump) = &event_dump {
+            dump.write();
+        }
     } else {
         run_windowed(app_config, &rt)?;
     }
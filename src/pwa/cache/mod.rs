@@ -12,7 +12,8 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::fs;
 
 pub struct CacheManager {
-    cache_root: PathBuf,
+    /// `None` for an ephemeral (private mode) manager that never touches disk.
+    cache_root: Option<PathBuf>,
     caches: HashMap<String, Cache>,
     global_quota: u64,
     used_space: u64,
@@ -60,13 +61,23 @@ impl CacheManager {
             .map_err(|e| CacheError::IoError(e.to_string()))?;
 
         Ok(Self {
-            cache_root,
+            cache_root: Some(cache_root),
             caches: HashMap::new(),
             global_quota: 50 * 1024 * 1024 * 1024,
             used_space: 0,
         })
     }
 
+    /// A manager whose caches live only in memory.
+    pub fn ephemeral() -> Self {
+        Self {
+            cache_root: None,
+            caches: HashMap::new(),
+            global_quota: 50 * 1024 * 1024 * 1024,
+            used_space: 0,
+        }
+    }
+
     fn get_cache_directory() -> PathBuf {
         std::env::var("CACHE_DIR")
             .map(PathBuf::from)
@@ -102,11 +113,12 @@ impl CacheManager {
         if let Some(cache) = self.caches.remove(name) {
             self.used_space = self.used_space.saturating_sub(cache.current_size);
 
-            let cache_dir = self.cache_root.join(name);
-            if cache_dir.exists() {
-                fs::remove_dir_all(cache_dir)
-                    .await
-                    .map_err(|e| CacheError::IoError(e.to_string()))?;
+            if let Some(cache_dir) = self.cache_root.as_ref().map(|root| root.join(name)) {
+                if cache_dir.exists() {
+                    fs::remove_dir_all(cache_dir)
+                        .await
+                        .map_err(|e| CacheError::IoError(e.to_string()))?;
+                }
             }
             Ok(true)
         } else {
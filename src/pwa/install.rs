@@ -0,0 +1,96 @@
+//! Install prompts: when a page may be installed and who asked to.
+//!
+//! After a page loads the engine checks the install criteria: a manifest
+//! link whose manifest parses, a service worker whose scope covers the page,
+//! an origin that is `https` or localhost, and no installed app with the
+//! manifest's start URL. When they hold the page gets `beforeinstallprompt`
+//! and the embedder [`BrowserEvent::PwaInstallable`](crate::BrowserEvent).
+//! Either side may then take the offer: the page by calling the event's
+//! `prompt()`, the embedder through
+//! [`BrowserEngine::accept_install_prompt`](crate::BrowserEngine). A page is
+//! offered at most once per load.
+
+use super::manifest::Manifest;
+use parking_lot::Mutex;
+
+/// What a page offers to install.
+#[derive(Debug, Clone)]
+pub struct InstallOffer {
+    /// Absolute URL of the manifest.
+    pub manifest_url: String,
+    pub manifest: Manifest,
+}
+
+#[derive(Default)]
+struct PromptState {
+    offer: Option<InstallOffer>,
+    offered: bool,
+    prompted: bool,
+}
+
+/// The current document's install offer.
+#[derive(Default)]
+pub struct InstallPrompts {
+    state: Mutex<PromptState>,
+}
+
+impl InstallPrompts {
+    /// Make `offer` the page's. `false` when the page was offered already.
+    pub fn offer(&self, offer: InstallOffer) -> bool {
+        let mut state = self.state.lock();
+        if state.offered {
+            return false;
+        }
+        state.offered = true;
+        state.offer = Some(offer);
+        true
+    }
+
+    /// Whether the page was offered since it loaded, taken or not.
+    pub fn was_offered(&self) -> bool {
+        self.state.lock().offered
+    }
+
+    pub fn current(&self) -> Option<InstallOffer> {
+        self.state.lock().offer.clone()
+    }
+
+    /// The page called `prompt()`. `false` when there is nothing to prompt
+    /// for or the page already did.
+    pub fn request(&self) -> bool {
+        let mut state = self.state.lock();
+        if state.offer.is_none() || state.prompted {
+            return false;
+        }
+        state.prompted = true;
+        true
+    }
+
+    /// The offer, if the page prompted for it since the last call.
+    pub fn take_requested(&self) -> Option<InstallOffer> {
+        let mut state = self.state.lock();
+        if !state.prompted {
+            return None;
+        }
+        state.offer.take()
+    }
+
+    /// The offer, for the embedder to act on.
+    pub fn take(&self) -> Option<InstallOffer> {
+        self.state.lock().offer.take()
+    }
+
+    /// The offer, if it is of the manifest at `manifest_url`.
+    pub fn take_for(&self, manifest_url: &str) -> Option<InstallOffer> {
+        let mut state = self.state.lock();
+        match &state.offer {
+            Some(offer) if offer.manifest_url == manifest_url => state.offer.take(),
+            _ => None,
+        }
+    }
+
+    /// Forget the offer, as when its document goes away.
+    pub fn reset(&self) {
+        *self.state.lock() = PromptState::default();
+    }
+}
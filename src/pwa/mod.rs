@@ -1,14 +1,22 @@
 #![allow(dead_code)]
 
 pub mod cache;
+pub mod install;
 pub mod manifest;
+pub mod push;
 pub mod service_worker;
 pub mod storage;
 
+use crate::core::storage::WebStorage;
 use cache::{CacheError, CacheManager};
 use manifest::{Manifest, ManifestError, ManifestParser};
-use service_worker::{ServiceWorkerError, ServiceWorkerManager};
+use push::PushSubscription;
+use service_worker::{
+    ClientId, Notification, RegistrationStore, ServiceWorkerConfig, ServiceWorkerError,
+    ServiceWorkerManager, ServiceWorkerRegistration,
+};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::SystemTime;
 use storage::{StorageError, StorageManager};
 use tokio::sync::{Mutex, RwLock};
@@ -57,9 +65,28 @@ pub struct StorageUsage {
 
 impl PwaRuntime {
     pub async fn new() -> Result<Self, PwaError> {
-        let cache_manager = Mutex::new(CacheManager::new().await?);
-        let storage_manager = Mutex::new(StorageManager::new().await?);
-        let service_worker_manager = Mutex::new(ServiceWorkerManager::new().await?);
+        Self::with_storage(Arc::new(WebStorage::default())).await
+    }
+
+    /// A runtime whose app storage goes through `web_storage`. When that is
+    /// memory-only (private mode), caches and service worker registrations
+    /// stay in memory too.
+    pub async fn with_storage(web_storage: Arc<WebStorage>) -> Result<Self, PwaError> {
+        let cache_manager = Mutex::new(if web_storage.is_ephemeral() {
+            CacheManager::ephemeral()
+        } else {
+            CacheManager::new().await?
+        });
+        let storage_manager = Mutex::new(StorageManager::new(web_storage.clone()).await?);
+        let registration_store = RegistrationStore::new(
+            web_storage
+                .directory()
+                .map(|directory| directory.join("service-workers")),
+        );
+        let service_worker_manager = Mutex::new(
+            ServiceWorkerManager::with_store(ServiceWorkerConfig::default(), registration_store)
+                .await?,
+        );
         let installed_apps = RwLock::new(HashMap::new());
         let manifest_parser = ManifestParser::new();
         let is_shutdown = RwLock::new(false);
@@ -87,16 +114,11 @@ impl PwaRuntime {
             *shutdown_flag = true;
         }
 
-        // Shutdown service workers - using existing methods
+        // Stop service workers; their registrations stay stored for the
+        // next start.
         {
             let sw_manager = self.service_worker_manager.lock().await;
-            let workers = sw_manager.list_active_workers().await;
-
-            for worker_id in workers {
-                if let Err(e) = sw_manager.unregister(&worker_id).await {
-                    warn!("Error shutting down service worker {}: {}", worker_id, e);
-                }
-            }
+            sw_manager.shutdown().await;
         }
 
         // Clear cache - using existing clear methods if available
@@ -224,6 +246,30 @@ impl PwaRuntime {
         apps.values().cloned().collect()
     }
 
+    /// Whether an installed app has `manifest`'s start URL, the identity
+    /// apps are told apart by.
+    pub async fn is_installed(&self, manifest: &Manifest) -> bool {
+        let apps = self.installed_apps.read().await;
+        apps.values()
+            .any(|app| app.manifest.start_url == manifest.start_url)
+    }
+
+    /// Parse and validate the manifest `json`, resolving its URLs against
+    /// `base_url`.
+    pub fn parse_manifest(&self, json: &str, base_url: &str) -> Result<Manifest, PwaError> {
+        Ok(self.manifest_parser.parse(json, Some(base_url))?)
+    }
+
+    /// Whether an active service worker's scope covers `page_url`.
+    pub async fn is_controlled(&self, page_url: &url::Url) -> bool {
+        if self.is_shutdown().await {
+            return false;
+        }
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        sw_manager.find_controller(page_url).await.is_some()
+    }
+
     pub async fn update_app(&self, app_id: &str, new_manifest: &Manifest) -> Result<(), PwaError> {
         self.check_not_shutdown().await?;
 
@@ -285,6 +331,123 @@ impl PwaRuntime {
         }
     }
 
+    /// Unregister the service worker registration of `scope`, an absolute
+    /// URL. Returns whether there was one.
+    pub async fn unregister_service_worker(&self, scope: &str) -> Result<bool, PwaError> {
+        self.check_not_shutdown().await?;
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        Ok(sw_manager.unregister_scope(scope).await)
+    }
+
+    pub async fn service_worker_registrations(&self) -> Vec<ServiceWorkerRegistration> {
+        if self.is_shutdown().await {
+            return Vec::new();
+        }
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        sw_manager.registrations().await
+    }
+
+    /// A page at `url` opened; see [`ServiceWorkerManager::open_client`].
+    pub async fn open_client(&self, url: &url::Url) -> Option<ClientId> {
+        if self.is_shutdown().await {
+            return None;
+        }
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        Some(sw_manager.open_client(url).await)
+    }
+
+    /// A page went away; see [`ServiceWorkerManager::close_client`].
+    pub async fn close_client(&self, client: ClientId) {
+        let sw_manager = self.service_worker_manager.lock().await;
+        sw_manager.close_client(client).await;
+    }
+
+    /// What the service worker controlling `url` answers a navigation to
+    /// it with. `None` leaves the navigation to the network: no worker
+    /// covers `url`, the worker did not respond, or it failed.
+    pub async fn handle_navigation(&self, url: &url::Url) -> Option<FetchResponse> {
+        if self.is_shutdown().await {
+            return None;
+        }
+
+        let request = FetchRequest {
+            url: url.to_string(),
+            method: "GET".to_string(),
+            headers: HashMap::from([("Accept".to_string(), "text/html".to_string())]),
+            body: None,
+        };
+        let sw_manager = self.service_worker_manager.lock().await;
+        match sw_manager.handle_fetch(&request).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Service worker failed to handle {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    /// Run the update check a navigation to `url` calls for.
+    pub async fn update_for_navigation(&self, url: &url::Url) {
+        if self.is_shutdown().await {
+            return;
+        }
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        if let Err(e) = sw_manager.update_for_navigation(url).await {
+            warn!("Service worker update check for {} failed: {}", url, e);
+        }
+    }
+
+    pub async fn push_subscriptions(&self) -> Vec<PushSubscription> {
+        if self.is_shutdown().await {
+            return Vec::new();
+        }
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        sw_manager.push_subscriptions().await
+    }
+
+    /// See [`ServiceWorkerManager::deliver_push`].
+    pub async fn deliver_push_message(&self, target: &str, body: &[u8]) -> Result<bool, PwaError> {
+        self.check_not_shutdown().await?;
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        Ok(sw_manager.deliver_push(target, body).await?)
+    }
+
+    /// See [`ServiceWorkerManager::expire_push_subscription`].
+    pub async fn expire_push_subscription(
+        &self,
+        id: &str,
+    ) -> Result<Option<PushSubscription>, PwaError> {
+        self.check_not_shutdown().await?;
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        Ok(sw_manager.expire_push_subscription(id).await?)
+    }
+
+    /// See [`ServiceWorkerManager::dispatch_sync`].
+    pub async fn dispatch_sync(
+        &self,
+        scope: &str,
+        tag: &str,
+        last_chance: bool,
+    ) -> Result<bool, PwaError> {
+        self.check_not_shutdown().await?;
+
+        let sw_manager = self.service_worker_manager.lock().await;
+        Ok(sw_manager.dispatch_sync(scope, tag, last_chance).await?)
+    }
+
+    /// The notifications service workers showed since the last call.
+    pub async fn take_notifications(&self) -> Vec<Notification> {
+        let sw_manager = self.service_worker_manager.lock().await;
+        sw_manager.take_notifications()
+    }
+
     pub async fn handle_fetch_request(
         &self,
         request: &FetchRequest,
@@ -534,6 +697,24 @@ impl PwaRuntime {
     }
 }
 
+/// Whether `url`'s origin is potentially trustworthy, as service workers
+/// and installed apps require: `https`, or `http` on a loopback host.
+pub fn is_potentially_trustworthy(url: &url::Url) -> bool {
+    match url.scheme() {
+        "https" => true,
+        "http" => match url.host() {
+            Some(url::Host::Domain(domain)) => {
+                domain.eq_ignore_ascii_case("localhost")
+                    || domain.to_ascii_lowercase().ends_with(".localhost")
+            }
+            Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PwaError {
     #[error("Cache error: {0}")]
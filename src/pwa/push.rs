@@ -0,0 +1,219 @@
+//! Push subscriptions and the decryption of push messages.
+//!
+//! The engine is the user agent end of Web Push: a subscription's P-256
+//! key pair and auth secret are generated here, and an application server
+//! encrypts each message to them with the `aes128gcm` content coding, as
+//! RFC 8291 describes. Holding a connection to a push service is the
+//! embedder's job; whatever receives a message hands its body to
+//! `BrowserEngine::deliver_push_message`, which decrypts it here and
+//! dispatches it to the subscription's service worker.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand::RngCore;
+use ring::{aead, hkdf};
+use serde::{Deserialize, Serialize};
+
+/// Where subscription endpoints point by default; the subscription id is
+/// appended. An embedder relaying a real push service maps these to its
+/// own.
+pub const DEFAULT_PUSH_ENDPOINT: &str = "https://push.invalid/";
+
+/// Bytes of an uncompressed P-256 public key.
+const PUBLIC_KEY_LEN: usize = 65;
+const SALT_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum PushError {
+    #[error("Malformed push message: {0}")]
+    Malformed(&'static str),
+    #[error("Push message could not be decrypted")]
+    Decryption,
+    #[error("No push subscription matches {0}")]
+    NoSubscription(String),
+}
+
+/// A subscription as `PushSubscription.toJSON()` and the application
+/// server see it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PushSubscription {
+    /// What `BrowserEngine::deliver_push_message` knows it by; the last
+    /// segment of `endpoint`.
+    pub id: String,
+    /// Scope of the service worker registration it belongs to.
+    pub scope: String,
+    pub endpoint: String,
+    /// The public key, uncompressed, in unpadded base64url.
+    pub p256dh: String,
+    /// The auth secret, in unpadded base64url.
+    pub auth: String,
+    /// The key the application server signs its requests with, in
+    /// unpadded base64url, when `subscribe()` was given one.
+    pub application_server_key: Option<String>,
+}
+
+/// The key pair and auth secret messages to a subscription are encrypted
+/// to.
+#[derive(Clone)]
+pub struct PushKeys {
+    id: String,
+    secret: p256::SecretKey,
+    auth: [u8; 16],
+}
+
+impl std::fmt::Debug for PushKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PushKeys").field("id", &self.id).finish()
+    }
+}
+
+impl PushKeys {
+    pub fn generate() -> Self {
+        let mut auth = [0u8; 16];
+        rand::rngs::OsRng.fill_bytes(&mut auth);
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            secret: p256::SecretKey::random(&mut rand::rngs::OsRng),
+            auth,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The public key, uncompressed.
+    pub fn public_key(&self) -> Vec<u8> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+        self.secret
+            .public_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    pub fn auth(&self) -> &[u8; 16] {
+        &self.auth
+    }
+
+    /// The subscription these keys make for the registration of `scope`.
+    pub fn subscription(
+        &self,
+        scope: &str,
+        endpoint_base: &str,
+        application_server_key: Option<String>,
+    ) -> PushSubscription {
+        PushSubscription {
+            id: self.id.clone(),
+            scope: scope.to_string(),
+            endpoint: format!("{}{}", endpoint_base, self.id),
+            p256dh: URL_SAFE_NO_PAD.encode(self.public_key()),
+            auth: URL_SAFE_NO_PAD.encode(self.auth),
+            application_server_key,
+        }
+    }
+
+    /// Decrypt an `aes128gcm` message body: a header of salt, record size
+    /// and the sender's public key, then a single record.
+    pub fn decrypt(&self, body: &[u8]) -> Result<Vec<u8>, PushError> {
+        let header_len = SALT_LEN + 4 + 1 + PUBLIC_KEY_LEN;
+        if body.len() < header_len {
+            return Err(PushError::Malformed("truncated header"));
+        }
+        let (salt, rest) = body.split_at(SALT_LEN);
+        let record_size = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest[4] as usize != PUBLIC_KEY_LEN {
+            return Err(PushError::Malformed("key id is not a P-256 public key"));
+        }
+        let sender_key = &rest[5..5 + PUBLIC_KEY_LEN];
+        let record = &rest[5 + PUBLIC_KEY_LEN..];
+        if record_size < TAG_LEN + 2 {
+            return Err(PushError::Malformed("record size too small"));
+        }
+        // Push messages are always a single record.
+        if record.len() < TAG_LEN + 1 || record.len() > record_size {
+            return Err(PushError::Malformed("not a single record"));
+        }
+
+        let sender = p256::PublicKey::from_sec1_bytes(sender_key)
+            .map_err(|_| PushError::Malformed("invalid sender key"))?;
+        let shared =
+            p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), sender.as_affine());
+        let user_agent_key = self.public_key();
+        let ikm = hkdf_sha256(
+            &self.auth,
+            shared.raw_secret_bytes(),
+            &[b"WebPush: info\0", &user_agent_key, sender_key],
+            32,
+        );
+        let cek = hkdf_sha256(salt, &ikm, &[b"Content-Encoding: aes128gcm\0"], 16);
+        let nonce = hkdf_sha256(salt, &ikm, &[b"Content-Encoding: nonce\0"], 12);
+
+        let key = aead::UnboundKey::new(&aead::AES_128_GCM, &cek)
+            .map(aead::LessSafeKey::new)
+            .map_err(|_| PushError::Decryption)?;
+        let nonce =
+            aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| PushError::Decryption)?;
+        let mut buffer = record.to_vec();
+        let plaintext = key
+            .open_in_place(nonce, aead::Aad::empty(), &mut buffer)
+            .map_err(|_| PushError::Decryption)?;
+
+        // The content, then the last-record delimiter, then zero padding.
+        let end = plaintext
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .ok_or(PushError::Malformed("no record delimiter"))?;
+        if plaintext[end] != 2 {
+            return Err(PushError::Malformed("no last-record delimiter"));
+        }
+        Ok(plaintext[..end].to_vec())
+    }
+
+    pub fn to_stored(&self, application_server_key: Option<String>) -> StoredSubscription {
+        StoredSubscription {
+            id: self.id.clone(),
+            private_key: URL_SAFE_NO_PAD.encode(self.secret.to_bytes()),
+            auth: URL_SAFE_NO_PAD.encode(self.auth),
+            application_server_key,
+        }
+    }
+
+    pub fn from_stored(stored: &StoredSubscription) -> Option<Self> {
+        let private_key = URL_SAFE_NO_PAD.decode(&stored.private_key).ok()?;
+        let auth = URL_SAFE_NO_PAD.decode(&stored.auth).ok()?;
+        Some(Self {
+            id: stored.id.clone(),
+            secret: p256::SecretKey::from_slice(&private_key).ok()?,
+            auth: auth.try_into().ok()?,
+        })
+    }
+}
+
+/// A subscription as persisted with its registration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredSubscription {
+    pub id: String,
+    /// The private key's scalar, in unpadded base64url.
+    pub private_key: String,
+    pub auth: String,
+    pub application_server_key: Option<String>,
+}
+
+/// HKDF-SHA-256 of `ikm` under `salt`, `len` bytes of it.
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[&[u8]], len: usize) -> Vec<u8> {
+    struct Len(usize);
+    impl hkdf::KeyType for Len {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+
+    let mut output = vec![0; len];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(ikm)
+        .expand(info, Len(len))
+        .and_then(|okm| okm.fill(&mut output))
+        .expect("HKDF-SHA-256 output of at most 32 bytes");
+    output
+}
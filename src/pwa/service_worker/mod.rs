@@ -1,16 +1,62 @@
+//! Service worker registrations and their lifecycle.
+//!
+//! A registration is identified by its absolute scope URL and holds up to
+//! three workers: one installing, one installed and waiting, and the
+//! active one, which is the only one fetches are routed to. An update
+//! check fetches the script again; different bytes are installed as a new
+//! worker, which then waits until no page the registration controls is
+//! left, unless it calls `skipWaiting()`. Navigations run an update check
+//! unless the script was checked within its `max-age`, and always when
+//! that was more than [`MAX_SCRIPT_AGE`] ago.
+//!
+//! Registrations with an active worker are kept in a [`RegistrationStore`]
+//! with that worker's script, and come back when the engine restarts.
+//!
+//! A registration also holds what its workers asked for through it: a
+//! push subscription, background sync tags still to be synced, and
+//! notifications to show. Worker script cannot call into the engine while
+//! it runs, so it leaves these requests in an outbox the manager collects
+//! after every event, and is told what the registration holds before its
+//! next one. A push message or sync is dispatched to the active worker,
+//! which is started for it when it is not running.
+
 pub mod runtime;
+pub mod store;
 
 pub use runtime::*;
+pub use store::{RegistrationStore, StoredRegistration};
 
+use crate::core::network::disk_cache::content_hash;
+use crate::pwa::push::{PushError, PushKeys, PushSubscription};
+use parking_lot::Mutex;
+use runtime::subscription_record;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+use store::epoch_seconds;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
+/// The longest a worker script goes without an update check on
+/// navigation, whatever its response allowed.
+pub const MAX_SCRIPT_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A page a registration may control.
+pub type ClientId = u64;
+
 pub struct ServiceWorkerManager {
-    workers: Arc<RwLock<HashMap<String, ServiceWorker>>>,
+    /// By absolute scope URL.
+    registrations: Arc<RwLock<HashMap<String, Registration>>>,
+    clients: Arc<RwLock<HashMap<ClientId, Client>>>,
+    next_client: AtomicU64,
+    store: RegistrationStore,
     runtime: ServiceWorkerRuntime,
+    /// Shown by workers and not yet taken by the engine.
+    notifications: Mutex<Vec<Notification>>,
+    push_endpoint: String,
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +67,8 @@ pub struct ServiceWorker {
     pub state: ServiceWorkerState,
     pub installation_time: SystemTime,
     pub last_update_check: SystemTime,
+    /// SHA-256 of the worker's script, in hex.
+    pub script_hash: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -32,140 +80,674 @@ pub enum ServiceWorkerState {
     Redundant,
 }
 
+/// A registration as it stands.
+#[derive(Debug, Clone)]
+pub struct ServiceWorkerRegistration {
+    pub scope: String,
+    pub script_url: String,
+    pub installing: Option<ServiceWorker>,
+    pub waiting: Option<ServiceWorker>,
+    pub active: Option<ServiceWorker>,
+    /// When an update check last reached the network.
+    pub last_update_check: SystemTime,
+    /// `unregister` was called. The registration keeps the pages it
+    /// controls until they go, and takes no new ones.
+    pub uninstalling: bool,
+    /// Background sync tags registered and not yet synced.
+    pub sync_tags: Vec<String>,
+}
+
+/// A notification a worker asked to show with
+/// `registration.showNotification()`. Showing it is up to the embedder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Scope of the registration of the worker that showed it.
+    #[serde(default)]
+    pub scope: String,
+    pub title: String,
+    pub body: String,
+    /// Replaces a notification of the same tag, when not empty.
+    pub tag: String,
+    pub icon: Option<String>,
+    pub data: serde_json::Value,
+}
+
+struct Registration {
+    info: ServiceWorkerRegistration,
+    /// How long after `last_update_check` the script is reused without a
+    /// check; at most [`MAX_SCRIPT_AGE`].
+    max_age: Duration,
+    /// Each worker's script, by worker id.
+    scripts: HashMap<String, Arc<str>>,
+    push: Option<Subscription>,
+    /// The keys the next `pushManager.subscribe()` takes.
+    push_offer: PushKeys,
+}
+
+struct Subscription {
+    keys: PushKeys,
+    /// In unpadded base64url.
+    application_server_key: Option<String>,
+}
+
+impl Registration {
+    fn new(scope: &str, script_url: &str) -> Self {
+        Self {
+            info: ServiceWorkerRegistration {
+                scope: scope.to_string(),
+                script_url: script_url.to_string(),
+                installing: None,
+                waiting: None,
+                active: None,
+                last_update_check: SystemTime::UNIX_EPOCH,
+                uninstalling: false,
+                sync_tags: Vec::new(),
+            },
+            max_age: Duration::ZERO,
+            scripts: HashMap::new(),
+            push: None,
+            push_offer: PushKeys::generate(),
+        }
+    }
+
+    /// A stored registration, its worker active but not yet started.
+    fn restore(stored: StoredRegistration, worker_id: String) -> Self {
+        let last_update_check = stored.last_update_check();
+        let worker = ServiceWorker {
+            id: worker_id.clone(),
+            script_url: stored.script_url.clone(),
+            scope: stored.scope.clone(),
+            state: ServiceWorkerState::Activated,
+            installation_time: last_update_check,
+            last_update_check,
+            script_hash: stored.script_hash,
+        };
+        let mut registration = Self::new(&stored.scope, &stored.script_url);
+        registration.info.active = Some(worker);
+        registration.info.last_update_check = last_update_check;
+        registration.max_age = Duration::from_secs(stored.max_age).min(MAX_SCRIPT_AGE);
+        registration.info.sync_tags = stored.sync_tags;
+        registration.push = stored.push_subscription.and_then(|subscription| {
+            Some(Subscription {
+                keys: PushKeys::from_stored(&subscription)?,
+                application_server_key: subscription.application_server_key,
+            })
+        });
+        registration
+            .scripts
+            .insert(worker_id, Arc::from(stored.script));
+        registration
+    }
+
+    fn workers(&self) -> impl Iterator<Item = &ServiceWorker> {
+        [&self.info.installing, &self.info.waiting, &self.info.active]
+            .into_iter()
+            .flatten()
+    }
+
+    /// The worker the next update is compared against.
+    fn newest(&self) -> Option<&ServiceWorker> {
+        self.workers().next()
+    }
+
+    /// Whether the script was checked recently enough to skip a check.
+    fn is_fresh(&self) -> bool {
+        SystemTime::now()
+            .duration_since(self.info.last_update_check)
+            .is_ok_and(|age| age < self.max_age)
+    }
+
+    fn push_subscription(&self, push_endpoint: &str) -> Option<PushSubscription> {
+        self.push.as_ref().map(|subscription| {
+            subscription.keys.subscription(
+                &self.info.scope,
+                push_endpoint,
+                subscription.application_server_key.clone(),
+            )
+        })
+    }
+
+    /// What its workers are told the registration holds.
+    fn host_state(&self, push_endpoint: &str) -> serde_json::Value {
+        let offer = self
+            .push_offer
+            .subscription(&self.info.scope, push_endpoint, None);
+        json!({
+            "push": {
+                "subscription": subscription_record(self.push_subscription(push_endpoint).as_ref()),
+                "offer": subscription_record(Some(&offer)),
+            },
+            "syncTags": self.info.sync_tags,
+        })
+    }
+}
+
+struct Client {
+    url: url::Url,
+    /// Scope of the registration controlling the page.
+    controller: Option<String>,
+}
+
+/// The registration that would control a page at `url`: the one with an
+/// active worker and the longest scope covering it.
+fn matching<'a>(
+    registrations: &'a HashMap<String, Registration>,
+    url: &url::Url,
+) -> Option<&'a Registration> {
+    registrations
+        .values()
+        .filter(|registration| {
+            !registration.info.uninstalling
+                && registration.info.active.is_some()
+                && url.as_str().starts_with(&registration.info.scope)
+        })
+        .max_by_key(|registration| registration.info.scope.len())
+}
+
+/// The origin of a scope URL, serialized.
+fn scope_origin(scope: &str) -> Option<String> {
+    url::Url::parse(scope)
+        .ok()
+        .map(|scope| scope.origin().ascii_serialization())
+}
+
+/// `scope` resolved against the script URL, so `/` is the root of the
+/// script's origin.
+fn resolve_scope(script_url: &str, scope: &str) -> Result<String, ServiceWorkerError> {
+    url::Url::parse(script_url)
+        .and_then(|script| script.join(scope))
+        .map(String::from)
+        .map_err(|e| ServiceWorkerError::ScriptError(format!("Invalid scope {}: {}", scope, e)))
+}
+
 impl ServiceWorkerManager {
     pub async fn new() -> Result<Self, ServiceWorkerError> {
         Self::with_config(ServiceWorkerConfig::default()).await
     }
 
     pub async fn with_config(config: ServiceWorkerConfig) -> Result<Self, ServiceWorkerError> {
+        Self::with_store(config, RegistrationStore::in_memory()).await
+    }
+
+    /// A manager starting with the registrations `store` kept. Their
+    /// workers start when first needed.
+    pub async fn with_store(
+        config: ServiceWorkerConfig,
+        store: RegistrationStore,
+    ) -> Result<Self, ServiceWorkerError> {
+        let push_endpoint = config.push_endpoint.clone();
         let runtime = ServiceWorkerRuntime::with_config(config).await?;
 
+        let mut registrations = HashMap::new();
+        for stored in store.load_all() {
+            let worker_id = Self::generate_worker_id(&stored.script_url, &stored.scope);
+            info!("Restored service worker registration: {}", stored.scope);
+            registrations.insert(
+                stored.scope.clone(),
+                Registration::restore(stored, worker_id),
+            );
+        }
+
         Ok(Self {
-            workers: Arc::new(RwLock::new(HashMap::new())),
+            registrations: Arc::new(RwLock::new(registrations)),
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            next_client: AtomicU64::new(1),
+            store,
             runtime,
+            notifications: Mutex::new(Vec::new()),
+            push_endpoint,
         })
     }
 
+    /// Register `script_url` for `scope`, or update the registration
+    /// already there, and return the id of its newest worker.
     pub async fn register(
         &self,
         script_url: &str,
         scope: &str,
     ) -> Result<String, ServiceWorkerError> {
-        let worker_id = self.generate_worker_id(script_url, scope);
+        let scope = resolve_scope(script_url, scope)?;
+        {
+            let mut registrations = self.registrations.write().await;
+            let registration = registrations
+                .entry(scope.clone())
+                .or_insert_with(|| Registration::new(&scope, script_url));
+            registration.info.uninstalling = false;
+            registration.info.script_url = script_url.to_string();
+        }
+
+        if let Err(e) = self.update(&scope, true).await {
+            // A registration that never got a worker goes with its install.
+            let mut registrations = self.registrations.write().await;
+            if registrations
+                .get(&scope)
+                .is_some_and(|registration| registration.newest().is_none())
+            {
+                registrations.remove(&scope);
+            }
+            error!("Failed to register service worker {}: {}", script_url, e);
+            return Err(e);
+        }
+
+        let registrations = self.registrations.read().await;
+        let worker_id = registrations
+            .get(&scope)
+            .and_then(|registration| registration.newest())
+            .map(|worker| worker.id.clone())
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(scope.clone()))?;
+        info!(
+            "Service Worker registered successfully: {} ({})",
+            script_url, worker_id
+        );
+        Ok(worker_id)
+    }
+
+    /// Check the registration of `scope` for a new script; unless
+    /// `force`, not while the last check is fresh. A changed script is
+    /// installed as a new worker, which activates once nothing holds it
+    /// back. Returns whether a new worker was installed.
+    pub async fn update(&self, scope: &str, force: bool) -> Result<bool, ServiceWorkerError> {
+        let mut registrations = self.registrations.write().await;
+        let registration = registrations
+            .get_mut(scope)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(scope.to_string()))?;
+        if registration.info.uninstalling || (!force && registration.is_fresh()) {
+            return Ok(false);
+        }
+
+        let script_url = registration.info.script_url.clone();
+        let fetched = self.runtime.fetch_script(&script_url).await?;
+        let now = SystemTime::now();
+        registration.info.last_update_check = now;
+        registration.max_age = fetched.max_age.min(MAX_SCRIPT_AGE);
+
+        let script_hash = content_hash(fetched.source.as_bytes());
+        let unchanged = registration.newest().is_some_and(|worker| {
+            worker.script_url == script_url && worker.script_hash == script_hash
+        });
+        if unchanged {
+            self.persist(registration);
+            return Ok(false);
+        }
 
         let worker = ServiceWorker {
-            id: worker_id.clone(),
-            script_url: script_url.to_string(),
+            id: Self::generate_worker_id(&script_url, scope),
+            script_url: script_url.clone(),
             scope: scope.to_string(),
             state: ServiceWorkerState::Installing,
-            installation_time: SystemTime::now(),
-            last_update_check: SystemTime::now(),
+            installation_time: now,
+            last_update_check: now,
+            script_hash,
         };
-
-        self.insert_worker(worker_id.clone(), worker).await;
-
-        match self.runtime.install_worker(script_url, scope).await {
-            Ok(runtime_worker_id) => {
-                self.update_worker_state(&worker_id, ServiceWorkerState::Installed)
-                    .await;
-
-                match self.runtime.activate_worker(&runtime_worker_id).await {
-                    Ok(()) => {
-                        self.update_worker_state(&worker_id, ServiceWorkerState::Activated)
-                            .await;
-                        info!(
-                            "Service Worker registered successfully: {} ({})",
-                            script_url, worker_id
-                        );
-                        Ok(worker_id)
-                    }
-                    Err(e) => {
-                        self.cleanup_failed_worker(&worker_id).await;
-                        error!("Failed to activate worker {}: {}", worker_id, e);
-                        Err(e)
-                    }
+        registration.info.installing = Some(worker.clone());
+        if let Some(active) = registration.info.active.clone() {
+            if self.ensure_running(registration, &active).await {
+                if let Err(e) = self.runtime.notify_update_found(&active.id).await {
+                    warn!("Failed to fire updatefound at {}: {}", active.id, e);
                 }
             }
-            Err(e) => {
-                self.cleanup_failed_worker(&worker_id).await;
-                error!("Failed to install worker {}: {}", worker_id, e);
-                Err(e)
-            }
         }
+
+        if let Err(e) = self
+            .runtime
+            .install_worker(
+                &worker.id,
+                &script_url,
+                scope,
+                &fetched.source,
+                &registration.host_state(&self.push_endpoint),
+            )
+            .await
+        {
+            registration.info.installing = None;
+            error!("Failed to install worker {}: {}", worker.id, e);
+            return Err(e);
+        }
+        self.absorb_outbox(registration, &worker.id).await;
+        registration
+            .scripts
+            .insert(worker.id.clone(), Arc::from(fetched.source));
+
+        // A worker already waiting makes way for the newer one.
+        if let Some(replaced) = registration.info.waiting.take() {
+            self.retire(registration, &replaced).await;
+        }
+        registration.info.installing = None;
+        registration.info.waiting = Some(ServiceWorker {
+            state: ServiceWorkerState::Installed,
+            ..worker
+        });
+        self.try_activate(registration).await;
+        Ok(true)
+    }
+
+    /// The update check a navigation to `url` runs, on the registration
+    /// that controls it.
+    pub async fn update_for_navigation(&self, url: &url::Url) -> Result<bool, ServiceWorkerError> {
+        let scope = {
+            let registrations = self.registrations.read().await;
+            match matching(&registrations, url) {
+                Some(registration) => registration.info.scope.clone(),
+                None => return Ok(false),
+            }
+        };
+        self.update(&scope, false).await
     }
 
+    /// Unregister the registration holding `worker_id`.
     pub async fn unregister(&self, worker_id: &str) -> Result<(), ServiceWorkerError> {
-        self.update_worker_state(worker_id, ServiceWorkerState::Redundant)
-            .await;
-        self.runtime.terminate_worker(worker_id).await?;
-        self.remove_worker(worker_id).await;
-        info!("Service Worker unregistered: {}", worker_id);
+        let scope = self
+            .get_worker_by_id(worker_id)
+            .await
+            .map(|worker| worker.scope)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
+        self.unregister_scope(&scope).await;
         Ok(())
     }
 
+    /// Unregister the registration of `scope`. It is forgotten at once, so
+    /// it does not come back on restart, but goes on controlling the pages
+    /// it controls until they are gone. Returns whether there was one.
+    pub async fn unregister_scope(&self, scope: &str) -> bool {
+        let mut registrations = self.registrations.write().await;
+        let registration = match registrations.get_mut(scope) {
+            Some(registration) => registration,
+            None => return false,
+        };
+        registration.info.uninstalling = true;
+        self.store.remove(scope);
+        if self.controlled_clients(scope).await == 0 {
+            if let Some(registration) = registrations.remove(scope) {
+                self.clear(registration).await;
+            }
+        }
+        info!("Service Worker registration unregistered: {}", scope);
+        true
+    }
+
     pub async fn update_worker(&self, worker_id: &str) -> Result<(), ServiceWorkerError> {
-        let worker_info = self
-            .get_worker_info(worker_id)
+        let scope = self
+            .get_worker_by_id(worker_id)
             .await
+            .map(|worker| worker.scope)
             .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
+        self.update(&scope, true).await?;
+        info!("Service Worker updated: {}", worker_id);
+        Ok(())
+    }
 
-        let (script_url, scope) = worker_info;
+    /// The active worker of the registration of `scope`.
+    pub async fn get_registration(&self, scope: &str) -> Option<ServiceWorker> {
+        let registrations = self.registrations.read().await;
+        registrations
+            .get(scope)
+            .and_then(|registration| registration.info.active.clone())
+    }
 
-        self.runtime.terminate_worker(worker_id).await?;
+    pub async fn registrations(&self) -> Vec<ServiceWorkerRegistration> {
+        let registrations = self.registrations.read().await;
+        registrations
+            .values()
+            .map(|registration| registration.info.clone())
+            .collect()
+    }
 
-        let new_worker_id = self.runtime.install_worker(&script_url, &scope).await?;
-        self.runtime.activate_worker(&new_worker_id).await?;
+    /// The active worker whose scope covers `page_url`, the longest scope
+    /// winning.
+    pub async fn find_controller(&self, page_url: &url::Url) -> Option<ServiceWorker> {
+        let registrations = self.registrations.read().await;
+        matching(&registrations, page_url).and_then(|registration| registration.info.active.clone())
+    }
 
-        self.update_worker_timestamp(worker_id).await;
+    /// A page at `url` opened. The registration covering it, if any,
+    /// controls it from now on.
+    pub async fn open_client(&self, url: &url::Url) -> ClientId {
+        let controller = {
+            let registrations = self.registrations.read().await;
+            matching(&registrations, url).map(|registration| registration.info.scope.clone())
+        };
+        let client = self.next_client.fetch_add(1, Ordering::Relaxed);
+        self.clients.write().await.insert(
+            client,
+            Client {
+                url: url.clone(),
+                controller,
+            },
+        );
+        client
+    }
 
-        info!("Service Worker updated: {}", worker_id);
-        Ok(())
+    /// A page went away. When it was the last one of its registration, a
+    /// waiting worker activates, and an unregistered registration is
+    /// cleared.
+    pub async fn close_client(&self, client: ClientId) {
+        let mut registrations = self.registrations.write().await;
+        let scope = match self.clients.write().await.remove(&client) {
+            Some(Client {
+                controller: Some(scope),
+                ..
+            }) => scope,
+            _ => return,
+        };
+        if self.controlled_clients(&scope).await > 0 {
+            return;
+        }
+        let uninstalling = match registrations.get(&scope) {
+            Some(registration) => registration.info.uninstalling,
+            None => return,
+        };
+        if uninstalling {
+            if let Some(registration) = registrations.remove(&scope) {
+                self.clear(registration).await;
+            }
+        } else if let Some(registration) = registrations.get_mut(&scope) {
+            self.try_activate(registration).await;
+        }
     }
 
-    pub async fn get_registration(&self, scope: &str) -> Option<ServiceWorker> {
-        let workers = self.workers.read().await;
-        workers
-            .values()
-            .find(|worker| worker.scope == scope && worker.state == ServiceWorkerState::Activated)
-            .cloned()
+    /// The active worker controlling `client`.
+    pub async fn controller_of(&self, client: ClientId) -> Option<ServiceWorker> {
+        let registrations = self.registrations.read().await;
+        let clients = self.clients.read().await;
+        let scope = clients.get(&client)?.controller.as_ref()?;
+        registrations.get(scope)?.info.active.clone()
     }
 
+    /// Give `request` to the active worker of the registration covering
+    /// its URL.
     pub async fn handle_fetch(
         &self,
         request: &crate::pwa::FetchRequest,
     ) -> Result<Option<crate::pwa::FetchResponse>, ServiceWorkerError> {
-        let matching_worker_id = self.find_matching_worker(&request.url).await;
-
-        if let Some(worker_id) = matching_worker_id {
-            self.runtime.handle_fetch_event(&worker_id, request).await
-        } else {
-            Ok(None)
+        let url = match url::Url::parse(&request.url) {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let mut registrations = self.registrations.write().await;
+        let scope = match matching(&registrations, &url) {
+            Some(registration) => registration.info.scope.clone(),
+            None => return Ok(None),
+        };
+        let registration = match registrations.get_mut(&scope) {
+            Some(registration) => registration,
+            None => return Ok(None),
+        };
+        let active = match registration.info.active.clone() {
+            Some(active) => active,
+            None => return Ok(None),
+        };
+        if !self.ensure_running(registration, &active).await {
+            return Ok(None);
         }
+        let response = self.runtime.handle_fetch_event(&active.id, request).await;
+        self.absorb_outbox(registration, &active.id).await;
+        response
     }
 
-    pub async fn get_all_registrations(&self) -> Vec<ServiceWorker> {
-        let workers = self.workers.read().await;
-        workers.values().cloned().collect()
+    /// The push subscription of every registration that has one.
+    pub async fn push_subscriptions(&self) -> Vec<PushSubscription> {
+        let registrations = self.registrations.read().await;
+        registrations
+            .values()
+            .filter(|registration| !registration.info.uninstalling)
+            .filter_map(|registration| registration.push_subscription(&self.push_endpoint))
+            .collect()
     }
 
-    pub async fn cleanup_redundant_workers(&self) -> Result<usize, ServiceWorkerError> {
-        let redundant_worker_ids = self.collect_redundant_workers().await;
-        let cleanup_count = redundant_worker_ids.len();
+    /// Decrypt a push message `body` and dispatch it as a `push` event to
+    /// the active worker of the registration it was sent to. `target` is a
+    /// subscription id, or an origin whose subscriptions are each tried;
+    /// an empty body is a push without data. Returns whether the worker
+    /// handled it successfully.
+    pub async fn deliver_push(
+        &self,
+        target: &str,
+        body: &[u8],
+    ) -> Result<bool, ServiceWorkerError> {
+        let origin = url::Url::parse(target)
+            .ok()
+            .map(|url| url.origin().ascii_serialization());
+        let mut registrations = self.registrations.write().await;
 
-        for worker_id in redundant_worker_ids {
-            if let Err(e) = self.unregister(&worker_id).await {
-                warn!("Failed to cleanup redundant worker {}: {}", worker_id, e);
+        let mut failure = PushError::NoSubscription(target.to_string());
+        let mut delivery = None;
+        for registration in registrations.values() {
+            let subscription = match &registration.push {
+                Some(subscription) if !registration.info.uninstalling => subscription,
+                _ => continue,
+            };
+            let addressed = subscription.keys.id() == target
+                || (origin.is_some() && origin == scope_origin(&registration.info.scope));
+            if !addressed {
+                continue;
+            }
+            if body.is_empty() {
+                delivery = Some((registration.info.scope.clone(), None));
+                break;
+            }
+            match subscription.keys.decrypt(body) {
+                Ok(data) => {
+                    delivery = Some((registration.info.scope.clone(), Some(data)));
+                    break;
+                }
+                Err(e) => failure = e,
             }
         }
+        let (scope, data) = delivery.ok_or(failure)?;
+
+        let registration = registrations
+            .get_mut(&scope)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(scope.clone()))?;
+        let active = self.running_active(registration).await?;
+        let handled = self
+            .runtime
+            .dispatch_push_event(&active.id, data.as_deref())
+            .await;
+        self.absorb_outbox(registration, &active.id).await;
+        handled
+    }
 
+    /// The push service gave up the subscription `id`. It is forgotten,
+    /// and the registration's active worker gets `pushsubscriptionchange`,
+    /// in which it may subscribe again. Returns the subscription it made,
+    /// if any.
+    pub async fn expire_push_subscription(
+        &self,
+        id: &str,
+    ) -> Result<Option<PushSubscription>, ServiceWorkerError> {
+        let mut registrations = self.registrations.write().await;
+        let registration = registrations
+            .values_mut()
+            .find(|registration| {
+                registration
+                    .push
+                    .as_ref()
+                    .is_some_and(|subscription| subscription.keys.id() == id)
+            })
+            .ok_or_else(|| PushError::NoSubscription(id.to_string()))?;
+        let expired = registration.push_subscription(&self.push_endpoint);
+        registration.push = None;
+        self.persist(registration);
+        self.share_host_state(registration).await;
+
+        if !registration.info.uninstalling {
+            let active = self.running_active(registration).await?;
+            if let Err(e) = self
+                .runtime
+                .dispatch_push_subscription_change(&active.id, expired.as_ref(), None)
+                .await
+            {
+                warn!(
+                    "Failed to fire pushsubscriptionchange at {}: {}",
+                    active.id, e
+                );
+            }
+            self.absorb_outbox(registration, &active.id).await;
+        }
+        Ok(registration.push_subscription(&self.push_endpoint))
+    }
+
+    /// Fire `sync` for `tag` at the active worker of the registration of
+    /// `scope`, as when the connection it waited for came back. A tag
+    /// whose handler succeeds is done with; one whose handler fails stays
+    /// registered for another attempt, unless this was the `last_chance`.
+    /// Returns whether the handler succeeded.
+    pub async fn dispatch_sync(
+        &self,
+        scope: &str,
+        tag: &str,
+        last_chance: bool,
+    ) -> Result<bool, ServiceWorkerError> {
+        let mut registrations = self.registrations.write().await;
+        let registration = registrations
+            .get_mut(scope)
+            .filter(|registration| {
+                !registration.info.uninstalling
+                    && registration.info.sync_tags.iter().any(|held| held == tag)
+            })
+            .ok_or_else(|| {
+                ServiceWorkerError::NoSyncRegistration(format!("{} in {}", tag, scope))
+            })?;
+        let active = self.running_active(registration).await?;
+        let synced = self
+            .runtime
+            .dispatch_sync_event(&active.id, tag, last_chance)
+            .await;
+        self.absorb_outbox(registration, &active.id).await;
+
+        if matches!(synced, Ok(true)) || last_chance {
+            registration.info.sync_tags.retain(|held| held != tag);
+            self.persist(registration);
+            self.share_host_state(registration).await;
+        }
+        synced
+    }
+
+    /// The notifications workers showed since the last call.
+    pub fn take_notifications(&self) -> Vec<Notification> {
+        std::mem::take(&mut *self.notifications.lock())
+    }
+
+    /// Every worker of every registration.
+    pub async fn get_all_registrations(&self) -> Vec<ServiceWorker> {
+        let registrations = self.registrations.read().await;
+        registrations
+            .values()
+            .flat_map(|registration| registration.workers().cloned())
+            .collect()
+    }
+
+    /// Stop workers idle for too long. Redundant workers are dropped as
+    /// they become redundant; stopped ones start again when needed.
+    pub async fn cleanup_redundant_workers(&self) -> Result<usize, ServiceWorkerError> {
         let runtime_cleanup_count = self.runtime.cleanup_inactive_workers().await;
 
         info!(
-            "Cleaned up {} redundant workers, {} inactive runtime workers",
-            cleanup_count, runtime_cleanup_count
+            "Cleaned up {} inactive runtime workers",
+            runtime_cleanup_count
         );
 
-        Ok(cleanup_count + runtime_cleanup_count)
+        Ok(runtime_cleanup_count)
     }
 
     pub async fn get_worker_stats(
@@ -189,33 +771,36 @@ impl ServiceWorkerManager {
     }
 
     pub async fn list_active_workers(&self) -> Vec<String> {
-        let workers = self.workers.read().await;
-        workers
+        let registrations = self.registrations.read().await;
+        registrations
             .values()
-            .filter(|w| w.state == ServiceWorkerState::Activated)
-            .map(|w| w.id.clone())
+            .filter_map(|registration| registration.info.active.as_ref())
+            .map(|worker| worker.id.clone())
             .collect()
     }
 
+    /// The newest worker of the registration of `scope`.
     pub async fn get_worker_by_scope(&self, scope: &str) -> Option<ServiceWorker> {
-        let workers = self.workers.read().await;
-        workers
-            .values()
-            .find(|worker| worker.scope == scope)
-            .cloned()
+        let registrations = self.registrations.read().await;
+        registrations
+            .get(scope)
+            .and_then(|registration| registration.newest().cloned())
     }
 
+    /// Check every registration for a new script, fresh or not. Returns
+    /// the scopes that got a new worker.
     pub async fn force_update_all(&self) -> Result<Vec<String>, ServiceWorkerError> {
-        let worker_ids = self.collect_all_worker_ids().await;
-        let mut updated_workers = Vec::new();
+        let scopes: Vec<String> = self.registrations.read().await.keys().cloned().collect();
+        let mut updated_scopes = Vec::new();
         let mut errors = Vec::new();
 
-        for worker_id in worker_ids {
-            match self.update_worker(&worker_id).await {
-                Ok(()) => updated_workers.push(worker_id),
+        for scope in scopes {
+            match self.update(&scope, true).await {
+                Ok(true) => updated_scopes.push(scope),
+                Ok(false) => {}
                 Err(e) => {
-                    warn!("Failed to update worker {}: {}", worker_id, e);
-                    errors.push(format!("{}: {}", worker_id, e));
+                    warn!("Failed to update registration {}: {}", scope, e);
+                    errors.push(format!("{}: {}", scope, e));
                 }
             }
         }
@@ -227,82 +812,247 @@ impl ServiceWorkerManager {
             )));
         }
 
-        Ok(updated_workers)
+        Ok(updated_scopes)
     }
 
-    async fn insert_worker(&self, worker_id: String, worker: ServiceWorker) {
-        let mut workers = self.workers.write().await;
-        workers.insert(worker_id, worker);
+    /// Stop every worker and let go of every page, leaving the stored
+    /// registrations for the next start.
+    pub async fn shutdown(&self) {
+        let registrations = self.registrations.read().await;
+        self.clients.write().await.clear();
+        for worker in registrations
+            .values()
+            .flat_map(|registration| registration.workers())
+        {
+            self.runtime.stop_worker(&worker.id).await;
+        }
     }
 
-    async fn remove_worker(&self, worker_id: &str) {
-        let mut workers = self.workers.write().await;
-        workers.remove(worker_id);
+    /// Promote the waiting worker unless something holds it back: it may
+    /// go first when no worker is active, when it called `skipWaiting()`,
+    /// or when no page is controlled by the registration any more. The
+    /// pages it controls are then routed to the new worker.
+    async fn try_activate(&self, registration: &mut Registration) {
+        let waiting = match &registration.info.waiting {
+            Some(waiting) => waiting.clone(),
+            None => return,
+        };
+        let ready = registration.info.active.is_none()
+            || self.runtime.skip_waiting_requested(&waiting.id).await
+            || self.controlled_clients(&registration.info.scope).await == 0;
+        if !ready {
+            info!("Service Worker {} is waiting", waiting.id);
+            return;
+        }
+
+        registration.info.waiting = None;
+        if let Some(previous) = registration.info.active.take() {
+            self.retire(registration, &previous).await;
+        }
+        registration.info.active = Some(ServiceWorker {
+            state: ServiceWorkerState::Activating,
+            ..waiting.clone()
+        });
+        // An activate handler that fails does not keep the worker from
+        // being the active one.
+        if let Err(e) = self.runtime.activate_worker(&waiting.id).await {
+            error!("Failed to activate worker {}: {}", waiting.id, e);
+        }
+        if let Some(active) = &mut registration.info.active {
+            active.state = ServiceWorkerState::Activated;
+        }
+        self.absorb_outbox(registration, &waiting.id).await;
+        self.persist(registration);
+
+        if self.runtime.claim_requested(&waiting.id).await {
+            self.claim(&registration.info.scope).await;
+        }
+        info!("Service Worker activated: {}", waiting.id);
     }
 
-    async fn update_worker_state(&self, worker_id: &str, new_state: ServiceWorkerState) {
-        let mut workers = self.workers.write().await;
-        if let Some(worker) = workers.get_mut(worker_id) {
-            worker.state = new_state;
+    /// `clients.claim()`: every page in `scope` that this registration
+    /// would control if it opened now is controlled by it.
+    async fn claim(&self, scope: &str) {
+        let mut clients = self.clients.write().await;
+        for client in clients.values_mut() {
+            let covered = client.url.as_str().starts_with(scope);
+            let closer = client.controller.as_ref().is_some_and(|current| {
+                current.len() > scope.len() && client.url.as_str().starts_with(current.as_str())
+            });
+            if covered && !closer {
+                client.controller = Some(scope.to_string());
+            }
         }
     }
 
-    async fn update_worker_timestamp(&self, worker_id: &str) {
-        let mut workers = self.workers.write().await;
-        if let Some(worker) = workers.get_mut(worker_id) {
-            worker.last_update_check = SystemTime::now();
-            worker.state = ServiceWorkerState::Activated;
+    async fn controlled_clients(&self, scope: &str) -> usize {
+        self.clients
+            .read()
+            .await
+            .values()
+            .filter(|client| client.controller.as_deref() == Some(scope))
+            .count()
+    }
+
+    /// Start the stored `worker` when it is not running. Returns whether it
+    /// is running.
+    async fn ensure_running(&self, registration: &Registration, worker: &ServiceWorker) -> bool {
+        if self.runtime.is_running(&worker.id).await {
+            return true;
+        }
+        let script = match registration.scripts.get(&worker.id) {
+            Some(script) => script.clone(),
+            None => return false,
+        };
+        match self
+            .runtime
+            .start_worker(
+                &worker.id,
+                &worker.script_url,
+                &worker.scope,
+                &script,
+                &registration.host_state(&self.push_endpoint),
+            )
+            .await
+        {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Failed to start service worker {}: {}", worker.id, e);
+                false
+            }
         }
     }
 
-    async fn cleanup_failed_worker(&self, worker_id: &str) {
-        self.update_worker_state(worker_id, ServiceWorkerState::Redundant)
-            .await;
-        let _ = self.runtime.terminate_worker(worker_id).await;
+    /// The active worker of `registration`, started if it was not running.
+    async fn running_active(
+        &self,
+        registration: &Registration,
+    ) -> Result<ServiceWorker, ServiceWorkerError> {
+        let active =
+            registration.info.active.clone().ok_or_else(|| {
+                ServiceWorkerError::WorkerNotFound(registration.info.scope.clone())
+            })?;
+        if !self.ensure_running(registration, &active).await {
+            return Err(ServiceWorkerError::ExecutionError(format!(
+                "Service worker {} could not be started",
+                active.id
+            )));
+        }
+        Ok(active)
+    }
+
+    /// Carry out what `worker_id` asked for while handling an event: a
+    /// push subscription made or given up and sync tags registered are
+    /// kept with the registration, notifications queued for the engine.
+    async fn absorb_outbox(&self, registration: &mut Registration, worker_id: &str) {
+        let outbox = self.runtime.take_outbox(worker_id).await;
+        let mut changed = false;
+        if outbox.unsubscribe {
+            changed |= registration.push.take().is_some();
+        }
+        if let Some(request) = outbox.subscribe.filter(|_| registration.push.is_none()) {
+            registration.push = Some(Subscription {
+                keys: std::mem::replace(&mut registration.push_offer, PushKeys::generate()),
+                application_server_key: request.application_server_key,
+            });
+            changed = true;
+        }
+        for tag in outbox.sync_tags {
+            if !registration.info.sync_tags.contains(&tag) {
+                registration.info.sync_tags.push(tag);
+                changed = true;
+            }
+        }
+        let scope = &registration.info.scope;
+        self.notifications
+            .lock()
+            .extend(
+                outbox
+                    .notifications
+                    .into_iter()
+                    .map(|notification| Notification {
+                        scope: scope.clone(),
+                        ..notification
+                    }),
+            );
+
+        if changed {
+            self.persist(registration);
+            self.share_host_state(registration).await;
+        }
     }
 
-    async fn get_worker_info(&self, worker_id: &str) -> Option<(String, String)> {
-        let workers = self.workers.read().await;
-        workers
-            .get(worker_id)
-            .map(|w| (w.script_url.clone(), w.scope.clone()))
+    /// Tell the running workers of `registration` what it now holds.
+    async fn share_host_state(&self, registration: &Registration) {
+        let host_state = registration.host_state(&self.push_endpoint);
+        for worker in registration.workers() {
+            if !self.runtime.is_running(&worker.id).await {
+                continue;
+            }
+            if let Err(e) = self.runtime.set_host_state(&worker.id, &host_state).await {
+                warn!("Failed to update worker {}: {}", worker.id, e);
+            }
+        }
     }
 
-    async fn get_worker_by_id(&self, worker_id: &str) -> Option<ServiceWorker> {
-        let workers = self.workers.read().await;
-        workers.get(worker_id).cloned()
+    /// `worker` became redundant.
+    async fn retire(&self, registration: &mut Registration, worker: &ServiceWorker) {
+        registration.scripts.remove(&worker.id);
+        if self.runtime.is_running(&worker.id).await {
+            if let Err(e) = self.runtime.terminate_worker(&worker.id).await {
+                warn!("Failed to terminate worker {}: {}", worker.id, e);
+            }
+        }
     }
 
-    async fn find_matching_worker(&self, url: &str) -> Option<String> {
-        let workers = self.workers.read().await;
-        workers
-            .values()
-            .find(|worker| {
-                worker.state == ServiceWorkerState::Activated && url.starts_with(&worker.scope)
-            })
-            .map(|worker| worker.id.clone())
+    /// Make every worker of an unregistered `registration` redundant.
+    async fn clear(&self, mut registration: Registration) {
+        let workers: Vec<ServiceWorker> = registration.workers().cloned().collect();
+        for worker in workers {
+            self.retire(&mut registration, &worker).await;
+        }
     }
 
-    async fn collect_redundant_workers(&self) -> Vec<String> {
-        let workers = self.workers.read().await;
-        workers
-            .iter()
-            .filter_map(|(id, worker)| {
-                if worker.state == ServiceWorkerState::Redundant {
-                    Some(id.clone())
-                } else {
-                    None
-                }
-            })
-            .collect()
+    /// Store the registration with its active worker's script.
+    fn persist(&self, registration: &Registration) {
+        if registration.info.uninstalling {
+            return;
+        }
+        let active = match &registration.info.active {
+            Some(active) => active,
+            None => return,
+        };
+        let script = match registration.scripts.get(&active.id) {
+            Some(script) => script,
+            None => return,
+        };
+        self.store.save(&StoredRegistration {
+            scope: registration.info.scope.clone(),
+            script_url: active.script_url.clone(),
+            updated_at: epoch_seconds(registration.info.last_update_check),
+            max_age: registration.max_age.as_secs(),
+            script: script.to_string(),
+            script_hash: active.script_hash.clone(),
+            push_subscription: registration.push.as_ref().map(|subscription| {
+                subscription
+                    .keys
+                    .to_stored(subscription.application_server_key.clone())
+            }),
+            sync_tags: registration.info.sync_tags.clone(),
+        });
     }
 
-    async fn collect_all_worker_ids(&self) -> Vec<String> {
-        let workers = self.workers.read().await;
-        workers.keys().cloned().collect()
+    async fn get_worker_by_id(&self, worker_id: &str) -> Option<ServiceWorker> {
+        let registrations = self.registrations.read().await;
+        registrations.values().find_map(|registration| {
+            registration
+                .workers()
+                .find(|worker| worker.id == worker_id)
+                .cloned()
+        })
     }
 
-    fn generate_worker_id(&self, script_url: &str, scope: &str) -> String {
+    fn generate_worker_id(script_url: &str, scope: &str) -> String {
         use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
 
@@ -333,6 +1083,10 @@ pub enum ServiceWorkerError {
     NetworkError(String),
     #[error("Script error: {0}")]
     ScriptError(String),
+    #[error(transparent)]
+    Push(#[from] PushError),
+    #[error("No background sync registration: {0}")]
+    NoSyncRegistration(String),
 }
 
 impl Default for ServiceWorkerManager {
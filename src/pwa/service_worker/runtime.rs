@@ -1,10 +1,12 @@
 #![allow(dead_code)]
 
-use super::ServiceWorkerError;
+use super::{Notification, ServiceWorkerError};
 use crate::js_engine::JSRuntime as JsEngine;
+use crate::pwa::push::{PushSubscription, DEFAULT_PUSH_ENDPOINT};
 use crate::BrowserConfig;
 use base64::{engine::general_purpose, Engine as _};
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
@@ -19,6 +21,41 @@ pub struct ServiceWorkerConfig {
     pub max_script_size: usize,
     pub max_idle_time: Duration,
     pub enable_https_only: bool,
+    /// Where push subscription endpoints point; the subscription id is
+    /// appended.
+    pub push_endpoint: String,
+}
+
+/// A worker script as fetched for an install or an update check.
+#[derive(Debug, Clone)]
+pub struct FetchedScript {
+    pub source: String,
+    /// How long the response said it could be reused for; zero unless it
+    /// gave a `max-age`.
+    pub max_age: Duration,
+}
+
+/// What a worker asked of the engine while handling events, since it was
+/// last collected.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WorkerOutbox {
+    /// `pushManager.subscribe()` took the keys on offer.
+    pub subscribe: Option<SubscribeRequest>,
+    /// The subscription was given up with `unsubscribe()`. When the worker
+    /// subscribed again after that, `subscribe` is set too.
+    pub unsubscribe: bool,
+    /// Background sync tags registered with `sync.register()`.
+    pub sync_tags: Vec<String>,
+    /// Calls to `registration.showNotification()`.
+    pub notifications: Vec<Notification>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscribeRequest {
+    /// In unpadded base64url.
+    pub application_server_key: Option<String>,
 }
 
 pub struct ServiceWorkerRuntime {
@@ -47,6 +84,19 @@ enum WorkerState {
     Redundant,
 }
 
+impl WorkerState {
+    /// The state as the worker's `ServiceWorker.state` spells it.
+    fn as_str(&self) -> &'static str {
+        match self {
+            WorkerState::Installing => "installing",
+            WorkerState::Installed => "installed",
+            WorkerState::Activating => "activating",
+            WorkerState::Activated => "activated",
+            WorkerState::Redundant => "redundant",
+        }
+    }
+}
+
 #[derive(Default)]
 struct EventHandlerRegistry {
     install: bool,
@@ -92,54 +142,121 @@ impl ServiceWorkerRuntime {
         BrowserConfig::default()
     }
 
+    /// Start `script`, fetched from `script_url`, as worker `worker_id`
+    /// and run its install event. The worker is left installed; one whose
+    /// install fails is dropped. `host_state` is what the registration
+    /// holds, as [`ServiceWorkerRuntime::set_host_state`] takes it.
     pub async fn install_worker(
         &self,
+        worker_id: &str,
         script_url: &str,
         scope: &str,
-    ) -> Result<String, ServiceWorkerError> {
-        let worker_id = self.generate_worker_id(script_url, scope);
+        script: &str,
+        host_state: &Value,
+    ) -> Result<(), ServiceWorkerError> {
+        self.start_instance(
+            worker_id,
+            script_url,
+            scope,
+            script,
+            host_state,
+            WorkerState::Installing,
+        )
+        .await?;
+
+        if let Err(e) = self.fire_install_event(worker_id).await {
+            self.workers.write().await.remove(worker_id);
+            return Err(e);
+        }
+
+        info!("Service Worker installed successfully: {}", script_url);
+        Ok(())
+    }
+
+    /// Run an activated worker again from its stored `script`, as after a
+    /// restart or once it was stopped for being idle. It gets no install
+    /// or activate event; it had those when it was first started.
+    pub async fn start_worker(
+        &self,
+        worker_id: &str,
+        script_url: &str,
+        scope: &str,
+        script: &str,
+        host_state: &Value,
+    ) -> Result<(), ServiceWorkerError> {
+        self.start_instance(
+            worker_id,
+            script_url,
+            scope,
+            script,
+            host_state,
+            WorkerState::Activated,
+        )
+        .await?;
+        info!("Service Worker started: {}", script_url);
+        Ok(())
+    }
 
+    pub async fn is_running(&self, worker_id: &str) -> bool {
+        self.workers.read().await.contains_key(worker_id)
+    }
+
+    async fn start_instance(
+        &self,
+        worker_id: &str,
+        script_url: &str,
+        scope: &str,
+        script: &str,
+        host_state: &Value,
+        state: WorkerState,
+    ) -> Result<(), ServiceWorkerError> {
         if self.workers.read().await.len() >= self.config.max_workers {
             return Err(ServiceWorkerError::ExecutionError(
                 "Maximum workers reached".to_string(),
             ));
         }
 
-        let script_content = self.fetch_script_with_validation(script_url).await?;
-
         let js_config = Self::create_service_worker_js_config();
 
         let mut js_engine = JsEngine::new(&js_config)
             .await
             .map_err(|e| ServiceWorkerError::ScriptError(e.to_string()))?;
 
-        self.setup_service_worker_environment(&mut js_engine, scope, &worker_id)
+        self.setup_service_worker_environment(&mut js_engine, scope, script_url, worker_id)
             .await?;
 
-        self.execute_script_safely(&mut js_engine, &script_content, "worker_installation")
+        // The worker is in its state before its script runs; nothing is
+        // listening yet to hear it change.
+        self.execute_script_safely(
+            &mut js_engine,
+            &format!(
+                "self.__vbeSetState('{}', true); self.__vbeSetHostState({})",
+                state.as_str(),
+                host_state
+            ),
+            "worker_state",
+        )
+        .await?;
+
+        self.execute_script_safely(&mut js_engine, script, "worker_installation")
             .await?;
 
         let event_handlers = self.extract_event_handlers(&mut js_engine).await?;
 
         let worker_instance = WorkerInstance {
-            id: worker_id.clone(),
+            id: worker_id.to_string(),
             js_engine,
             scope: scope.to_string(),
             script_url: script_url.to_string(),
-            state: WorkerState::Installing,
+            state,
             event_handlers,
             last_activity: Instant::now(),
             execution_stats: ExecutionStats::default(),
         };
 
         let mut workers = self.workers.write().await;
-        workers.insert(worker_id.clone(), worker_instance);
-        drop(workers);
-
-        self.fire_install_event(&worker_id).await?;
-
-        info!("Service Worker installed successfully: {}", script_url);
-        Ok(worker_id)
+        workers.insert(worker_id.to_string(), worker_instance);
+        Ok(())
     }
 
     pub async fn activate_worker(&self, worker_id: &str) -> Result<(), ServiceWorkerError> {
@@ -155,14 +272,14 @@ impl ServiceWorkerRuntime {
             )));
         }
 
-        worker.state = WorkerState::Activating;
+        self.set_state(worker, WorkerState::Activating).await?;
         drop(workers);
 
         self.fire_activate_event(worker_id).await?;
 
         let mut workers = self.workers.write().await;
         if let Some(worker) = workers.get_mut(worker_id) {
-            worker.state = WorkerState::Activated;
+            self.set_state(worker, WorkerState::Activated).await?;
         }
 
         info!("Service Worker activated: {}", worker_id);
@@ -172,7 +289,12 @@ impl ServiceWorkerRuntime {
     pub async fn terminate_worker(&self, worker_id: &str) -> Result<(), ServiceWorkerError> {
         let mut workers = self.workers.write().await;
         if let Some(mut worker) = workers.remove(worker_id) {
-            worker.state = WorkerState::Redundant;
+            if let Err(e) = self.set_state(&mut worker, WorkerState::Redundant).await {
+                warn!(
+                    "Worker {} did not hear it became redundant: {}",
+                    worker_id, e
+                );
+            }
             info!("Service Worker terminated: {}", worker_id);
             Ok(())
         } else {
@@ -180,6 +302,12 @@ impl ServiceWorkerRuntime {
         }
     }
 
+    /// Stop a worker without it becoming redundant, as when the engine
+    /// shuts down; it can be started again from its script.
+    pub async fn stop_worker(&self, worker_id: &str) -> bool {
+        self.workers.write().await.remove(worker_id).is_some()
+    }
+
     pub async fn handle_fetch_event(
         &self,
         worker_id: &str,
@@ -201,22 +329,17 @@ impl ServiceWorkerRuntime {
         let fetch_event_script = self.create_fetch_event_script(request)?;
 
         match self
-            .execute_with_timeout(
+            .run_event(
                 &mut worker.js_engine,
                 &fetch_event_script,
                 self.config.execution_timeout,
             )
             .await
         {
-            Ok(result) => {
+            Ok(response_data) => {
                 let duration = start_time.elapsed();
                 self.update_execution_stats(&mut worker.execution_stats, duration, true);
-
-                if let Some(response_data) = result {
-                    self.parse_fetch_response(response_data)
-                } else {
-                    Ok(Some(self.create_default_response()))
-                }
+                self.parse_fetch_response(response_data)
             }
             Err(e) => {
                 let duration = start_time.elapsed();
@@ -230,6 +353,214 @@ impl ServiceWorkerRuntime {
         }
     }
 
+    /// Whether the worker called `skipWaiting()`.
+    pub async fn skip_waiting_requested(&self, worker_id: &str) -> bool {
+        self.read_flag(worker_id, "__vbeSkipWaiting").await
+    }
+
+    /// Whether the worker called `clients.claim()`.
+    pub async fn claim_requested(&self, worker_id: &str) -> bool {
+        self.read_flag(worker_id, "__vbeClaim").await
+    }
+
+    /// Fire `updatefound` at the worker's registration: a new worker for
+    /// it started installing.
+    pub async fn notify_update_found(&self, worker_id: &str) -> Result<(), ServiceWorkerError> {
+        let mut workers = self.workers.write().await;
+        let worker = workers
+            .get_mut(worker_id)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
+        self.execute_script_safely(
+            &mut worker.js_engine,
+            "self.__vbeUpdateFound()",
+            "updatefound",
+        )
+        .await
+    }
+
+    /// Tell the worker what its registration holds: `{"push":
+    /// {"subscription", "offer"}, "syncTags"}`, the subscription and the
+    /// keys `subscribe()` would take as `{"endpoint", "p256dh", "auth",
+    /// "applicationServerKey"}`, or null.
+    pub async fn set_host_state(
+        &self,
+        worker_id: &str,
+        host_state: &Value,
+    ) -> Result<(), ServiceWorkerError> {
+        let mut workers = self.workers.write().await;
+        let worker = workers
+            .get_mut(worker_id)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
+        self.execute_script_safely(
+            &mut worker.js_engine,
+            &format!("self.__vbeSetHostState({})", host_state),
+            "host_state",
+        )
+        .await
+    }
+
+    /// Collect what the worker asked for since the last call.
+    pub async fn take_outbox(&self, worker_id: &str) -> WorkerOutbox {
+        let mut workers = self.workers.write().await;
+        let worker = match workers.get_mut(worker_id) {
+            Some(worker) => worker,
+            None => return WorkerOutbox::default(),
+        };
+        match self
+            .execute_with_timeout(
+                &mut worker.js_engine,
+                "self.__vbeTakeOutbox()",
+                Duration::from_secs(2),
+            )
+            .await
+        {
+            Ok(Some(Value::String(outbox))) => serde_json::from_str(&outbox).unwrap_or_else(|e| {
+                warn!("Unreadable outbox from worker {}: {}", worker_id, e);
+                WorkerOutbox::default()
+            }),
+            Ok(_) => WorkerOutbox::default(),
+            Err(e) => {
+                warn!(
+                    "Failed to collect the outbox of worker {}: {}",
+                    worker_id, e
+                );
+                WorkerOutbox::default()
+            }
+        }
+    }
+
+    /// Fire `push` at an activated worker, with `data` as the decrypted
+    /// payload. Returns whether the handler and what it passed to
+    /// `waitUntil()` succeeded.
+    pub async fn dispatch_push_event(
+        &self,
+        worker_id: &str,
+        data: Option<&[u8]>,
+    ) -> Result<bool, ServiceWorkerError> {
+        let data = match data {
+            Some(data) => format!(
+                "new PushMessageData({}, {})",
+                json!(data),
+                json!(String::from_utf8_lossy(data))
+            ),
+            None => "null".to_string(),
+        };
+        self.dispatch_extendable_event(
+            worker_id,
+            &format!("new PushEvent('push', {{ data: {} }})", data),
+        )
+        .await
+    }
+
+    /// Fire `sync` for `tag` at an activated worker. Returns whether the
+    /// handler and what it passed to `waitUntil()` succeeded.
+    pub async fn dispatch_sync_event(
+        &self,
+        worker_id: &str,
+        tag: &str,
+        last_chance: bool,
+    ) -> Result<bool, ServiceWorkerError> {
+        self.dispatch_extendable_event(
+            worker_id,
+            &format!(
+                "new SyncEvent('sync', {{ tag: {}, lastChance: {} }})",
+                json!(tag),
+                last_chance
+            ),
+        )
+        .await
+    }
+
+    /// Fire `pushsubscriptionchange` at an activated worker: `old` is no
+    /// longer valid, `new` replaces it if there is one.
+    pub async fn dispatch_push_subscription_change(
+        &self,
+        worker_id: &str,
+        old: Option<&PushSubscription>,
+        new: Option<&PushSubscription>,
+    ) -> Result<bool, ServiceWorkerError> {
+        self.dispatch_extendable_event(
+            worker_id,
+            &format!(
+                "new PushSubscriptionChangeEvent('pushsubscriptionchange', {{ \
+                 oldSubscription: self.__vbePushSubscription({}), \
+                 newSubscription: self.__vbePushSubscription({}) }})",
+                subscription_record(old),
+                subscription_record(new)
+            ),
+        )
+        .await
+    }
+
+    /// Dispatch `event`, an expression for an `ExtendableEvent`, to its
+    /// handler and wait for what was passed to `waitUntil()`.
+    async fn dispatch_extendable_event(
+        &self,
+        worker_id: &str,
+        event: &str,
+    ) -> Result<bool, ServiceWorkerError> {
+        let start_time = Instant::now();
+        let mut workers = self.workers.write().await;
+        let worker = workers
+            .get_mut(worker_id)
+            .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
+        if worker.state != WorkerState::Activated {
+            return Err(ServiceWorkerError::ExecutionError(format!(
+                "Worker {} is not activated",
+                worker_id
+            )));
+        }
+        worker.last_activity = Instant::now();
+
+        let outcome = self
+            .run_event(
+                &mut worker.js_engine,
+                &format!("self.__vbeDispatchExtendable({})", event),
+                self.config.execution_timeout,
+            )
+            .await;
+        let success = matches!(&outcome, Ok(outcome) if outcome["success"] == Value::Bool(true));
+        self.update_execution_stats(&mut worker.execution_stats, start_time.elapsed(), success);
+        if let Some(error) = outcome
+            .as_ref()
+            .ok()
+            .and_then(|outcome| outcome["error"].as_str())
+        {
+            warn!("Event handler of {} failed: {}", worker_id, error);
+        }
+        outcome.map(|_| success)
+    }
+
+    async fn read_flag(&self, worker_id: &str, flag: &str) -> bool {
+        let mut workers = self.workers.write().await;
+        let worker = match workers.get_mut(worker_id) {
+            Some(worker) => worker,
+            None => return false,
+        };
+        let script = format!("self.{} === true", flag);
+        matches!(
+            self.execute_with_timeout(&mut worker.js_engine, &script, Duration::from_secs(2))
+                .await,
+            Ok(Some(Value::Bool(true)))
+        )
+    }
+
+    /// Move `worker` to `state`, firing `statechange` in it.
+    async fn set_state(
+        &self,
+        worker: &mut WorkerInstance,
+        state: WorkerState,
+    ) -> Result<(), ServiceWorkerError> {
+        self.execute_script_safely(
+            &mut worker.js_engine,
+            &format!("self.__vbeSetState('{}')", state.as_str()),
+            "statechange",
+        )
+        .await?;
+        worker.state = state;
+        Ok(())
+    }
+
     pub async fn get_worker_stats(
         &self,
         worker_id: &str,
@@ -275,23 +606,300 @@ impl ServiceWorkerRuntime {
         &self,
         js_engine: &mut JsEngine,
         scope: &str,
+        script_url: &str,
         worker_id: &str,
     ) -> Result<(), ServiceWorkerError> {
+        let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
         let globals_script = format!(
             r#"
             const self = globalThis;
-            const __WORKER_ID__ = '{}';
-            const __WORKER_SCOPE__ = '{}';
-            
-            self.registration = {{
-                scope: __WORKER_SCOPE__,
-                active: null,
-                installing: null,
-                waiting: null,
-                addEventListener: () => {{}},
-                update: () => Promise.resolve(),
-                unregister: () => Promise.resolve(true)
-            }};
+            const __WORKER_ID__ = {};
+            const __WORKER_SCOPE__ = {};
+            const __WORKER_SCRIPT_URL__ = {};
+            
+            (() => {{
+                const eventTarget = (target) => {{
+                    const listeners = new Map();
+                    target.addEventListener = (type, listener) => {{
+                        if (!listeners.has(type)) {{
+                            listeners.set(type, []);
+                        }}
+                        listeners.get(type).push(listener);
+                    }};
+                    target.removeEventListener = (type, listener) => {{
+                        const list = listeners.get(type) || [];
+                        const index = list.indexOf(listener);
+                        if (index !== -1) {{
+                            list.splice(index, 1);
+                        }}
+                    }};
+                    target.dispatchEvent = (event) => {{
+                        event.target = target;
+                        const handler = target['on' + event.type];
+                        if (typeof handler === 'function') {{
+                            handler.call(target, event);
+                        }}
+                        for (const listener of [...(listeners.get(event.type) || [])]) {{
+                            listener.call(target, event);
+                        }}
+                        return true;
+                    }};
+                    return target;
+                }};
+            
+                self.serviceWorker = eventTarget({{
+                    scriptURL: __WORKER_SCRIPT_URL__,
+                    state: 'parsed',
+                    onstatechange: null,
+                    postMessage: () => {{}}
+                }});
+            
+                self.registration = eventTarget({{
+                    scope: __WORKER_SCOPE__,
+                    active: null,
+                    installing: null,
+                    waiting: null,
+                    onupdatefound: null,
+                    update: () => Promise.resolve(),
+                    unregister: () => Promise.resolve(true)
+                }});
+            
+                // Where a worker in each state sits on its registration.
+                const slots = {{
+                    installing: 'installing',
+                    installed: 'waiting',
+                    activating: 'active',
+                    activated: 'active'
+                }};
+            
+                Object.defineProperty(self, '__vbeSetState', {{
+                    value: (state, quiet) => {{
+                        for (const slot of ['installing', 'waiting', 'active']) {{
+                            if (self.registration[slot] === self.serviceWorker) {{
+                                self.registration[slot] = null;
+                            }}
+                        }}
+                        if (slots[state]) {{
+                            self.registration[slots[state]] = self.serviceWorker;
+                        }}
+                        self.serviceWorker.state = state;
+                        if (!quiet) {{
+                            self.serviceWorker.dispatchEvent({{ type: 'statechange' }});
+                        }}
+                    }}
+                }});
+            
+                Object.defineProperty(self, '__vbeUpdateFound', {{
+                    value: () => self.registration.dispatchEvent({{ type: 'updatefound' }})
+                }});
+            
+                // What the registration holds, as the engine last said, and what the
+                // worker asked of the engine since it last looked.
+                let hostState = {{ push: {{ subscription: null, offer: null }}, syncTags: [] }};
+                let outbox;
+                const resetOutbox = () => {{
+                    outbox = {{ subscribe: null, unsubscribe: false, syncTags: [], notifications: [] }};
+                }};
+                resetOutbox();
+            
+                Object.defineProperty(self, '__vbeSetHostState', {{
+                    value: (state) => {{ hostState = state; }}
+                }});
+            
+                Object.defineProperty(self, '__vbeTakeOutbox', {{
+                    value: () => {{
+                        const taken = JSON.stringify(outbox);
+                        resetOutbox();
+                        return taken;
+                    }}
+                }});
+            
+                Object.defineProperty(self, '__vbeDispatchExtendable', {{
+                    value: async (event) => {{
+                        const handler = self['on' + event.type];
+                        if (typeof handler !== 'function') {{
+                            return {{ success: true }};
+                        }}
+                        try {{
+                            await handler.call(self, event);
+                            await Promise.all(event.promises);
+                            return {{ success: true }};
+                        }} catch (error) {{
+                            return {{ success: false, error: String((error && error.message) || error) }};
+                        }}
+                    }}
+                }});
+            
+                const domError = (name, message) => {{
+                    const error = new Error(message);
+                    error.name = name;
+                    return error;
+                }};
+            
+                const ALPHABET = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_';
+                const encodeKey = (bytes) => {{
+                    let text = '';
+                    let value = 0;
+                    let bits = 0;
+                    for (const byte of bytes) {{
+                        value = (value << 8) | byte;
+                        bits += 8;
+                        while (bits >= 6) {{
+                            bits -= 6;
+                            text += ALPHABET[(value >> bits) & 63];
+                        }}
+                        value &= (1 << bits) - 1;
+                    }}
+                    if (bits > 0) {{
+                        text += ALPHABET[(value << (6 - bits)) & 63];
+                    }}
+                    return text;
+                }};
+                const decodeKey = (text) => {{
+                    const bytes = [];
+                    let value = 0;
+                    let bits = 0;
+                    for (const char of text.replace(/=+$/, '').replace(/\+/g, '-').replace(/\//g, '_')) {{
+                        const index = ALPHABET.indexOf(char);
+                        if (index === -1) {{
+                            throw domError('InvalidCharacterError', 'Invalid base64url key');
+                        }}
+                        value = (value << 6) | index;
+                        bits += 6;
+                        if (bits >= 8) {{
+                            bits -= 8;
+                            bytes.push((value >> bits) & 255);
+                        }}
+                        value &= (1 << bits) - 1;
+                    }}
+                    return new Uint8Array(bytes);
+                }};
+                // An applicationServerKey in unpadded base64url; it must be a P-256
+                // public key.
+                const serverKey = (key) => {{
+                    if (key == null) {{
+                        return null;
+                    }}
+                    let bytes;
+                    if (typeof key === 'string') {{
+                        bytes = decodeKey(key);
+                    }} else if (key instanceof ArrayBuffer) {{
+                        bytes = new Uint8Array(key);
+                    }} else if (ArrayBuffer.isView(key)) {{
+                        bytes = new Uint8Array(key.buffer, key.byteOffset, key.byteLength);
+                    }} else {{
+                        throw new TypeError('applicationServerKey must be a string or a BufferSource');
+                    }}
+                    if (bytes.length !== 65 || bytes[0] !== 4) {{
+                        throw domError('InvalidAccessError', 'applicationServerKey is not a P-256 public key');
+                    }}
+                    return encodeKey(bytes);
+                }};
+            
+                const pushSubscription = (record) => record && {{
+                    endpoint: record.endpoint,
+                    expirationTime: null,
+                    options: {{
+                        userVisibleOnly: true,
+                        applicationServerKey: record.applicationServerKey
+                            ? decodeKey(record.applicationServerKey).buffer
+                            : null
+                    }},
+                    getKey: (name) =>
+                        name === 'p256dh' || name === 'auth' ? decodeKey(record[name]).buffer : null,
+                    toJSON: () => ({{
+                        endpoint: record.endpoint,
+                        expirationTime: null,
+                        keys: {{ p256dh: record.p256dh, auth: record.auth }}
+                    }}),
+                    unsubscribe: () => {{
+                        const current = hostState.push.subscription;
+                        if (!current || current.endpoint !== record.endpoint) {{
+                            return Promise.resolve(false);
+                        }}
+                        hostState.push.subscription = null;
+                        if (outbox.subscribe) {{
+                            // Made during this event: the engine never heard of it.
+                            outbox.subscribe = null;
+                            hostState.push.offer = current;
+                        }} else {{
+                            outbox.unsubscribe = true;
+                        }}
+                        return Promise.resolve(true);
+                    }}
+                }};
+                Object.defineProperty(self, '__vbePushSubscription', {{ value: pushSubscription }});
+            
+                self.registration.pushManager = {{
+                    subscribe: (options = {{}}) => {{
+                        try {{
+                            if (options.userVisibleOnly !== true) {{
+                                throw domError('NotAllowedError', 'Push subscriptions must be userVisibleOnly');
+                            }}
+                            const applicationServerKey = serverKey(options.applicationServerKey);
+                            const current = hostState.push.subscription;
+                            if (current) {{
+                                if (applicationServerKey && current.applicationServerKey
+                                    && applicationServerKey !== current.applicationServerKey) {{
+                                    throw domError('InvalidStateError',
+                                        'Subscribed with a different applicationServerKey');
+                                }}
+                                return Promise.resolve(pushSubscription(current));
+                            }}
+                            const offer = hostState.push.offer;
+                            if (!offer) {{
+                                throw domError('AbortError', 'No push keys available');
+                            }}
+                            hostState.push.subscription = Object.assign({{}}, offer, {{ applicationServerKey }});
+                            hostState.push.offer = null;
+                            outbox.subscribe = {{ applicationServerKey }};
+                            return Promise.resolve(pushSubscription(hostState.push.subscription));
+                        }} catch (error) {{
+                            return Promise.reject(error);
+                        }}
+                    }},
+                    getSubscription: () => Promise.resolve(pushSubscription(hostState.push.subscription)),
+                    permissionState: () => Promise.resolve('granted')
+                }};
+            
+                self.registration.sync = {{
+                    register: (tag) => {{
+                        tag = String(tag);
+                        if (!hostState.syncTags.includes(tag)) {{
+                            hostState.syncTags.push(tag);
+                            outbox.syncTags.push(tag);
+                        }}
+                        return Promise.resolve();
+                    }},
+                    getTags: () => Promise.resolve([...hostState.syncTags])
+                }};
+            
+                // Notifications go to the engine, which leaves showing them to the
+                // embedder.
+                self.registration.showNotification = (title, options = {{}}) => {{
+                    outbox.notifications.push({{
+                        title: String(title),
+                        body: options.body === undefined ? '' : String(options.body),
+                        tag: options.tag === undefined ? '' : String(options.tag),
+                        icon: options.icon === undefined ? null : String(options.icon),
+                        data: options.data === undefined ? null : options.data
+                    }});
+                    return Promise.resolve();
+                }};
+                self.registration.getNotifications = () => Promise.resolve([]);
+            
+                if (typeof self.Response !== 'function') {{
+                    self.Response = class Response {{
+                        constructor(body, init = {{}}) {{
+                            this.status = init.status === undefined ? 200 : init.status;
+                            this.ok = this.status >= 200 && this.status < 300;
+                            this.headers = Object.assign({{}}, init.headers || {{}});
+                            this.body = body == null ? '' : String(body);
+                        }}
+                        text() {{ return Promise.resolve(this.body); }}
+                    }};
+                }}
+            }})();
             
             self.caches = {{
                 open: (name) => Promise.resolve(new Cache()),
@@ -359,10 +967,48 @@ impl ServiceWorkerRuntime {
                 constructor() {{ super('activate'); }}
             }}
             
+            class PushMessageData {{
+                constructor(bytes, text) {{
+                    this.__bytes = bytes;
+                    this.__text = text;
+                }}
+                text() {{ return this.__text; }}
+                json() {{ return JSON.parse(this.__text); }}
+                bytes() {{ return new Uint8Array(this.__bytes); }}
+                arrayBuffer() {{ return new Uint8Array(this.__bytes).buffer; }}
+            }}
+            
+            class PushEvent extends ExtendableEvent {{
+                constructor(type, eventInitDict = {{}}) {{
+                    super(type, eventInitDict);
+                    this.data = eventInitDict.data || null;
+                }}
+            }}
+            
+            class PushSubscriptionChangeEvent extends ExtendableEvent {{
+                constructor(type, eventInitDict = {{}}) {{
+                    super(type, eventInitDict);
+                    this.oldSubscription = eventInitDict.oldSubscription || null;
+                    this.newSubscription = eventInitDict.newSubscription || null;
+                }}
+            }}
+            
+            class SyncEvent extends ExtendableEvent {{
+                constructor(type, eventInitDict) {{
+                    super(type, eventInitDict);
+                    this.tag = eventInitDict.tag;
+                    this.lastChance = eventInitDict.lastChance || false;
+                }}
+            }}
+            
             self.ExtendableEvent = ExtendableEvent;
             self.FetchEvent = FetchEvent;
             self.InstallEvent = InstallEvent;
             self.ActivateEvent = ActivateEvent;
+            self.PushMessageData = PushMessageData;
+            self.PushEvent = PushEvent;
+            self.PushSubscriptionChangeEvent = PushSubscriptionChangeEvent;
+            self.SyncEvent = SyncEvent;
             
             const eventListeners = new Map();
             
@@ -384,10 +1030,16 @@ impl ServiceWorkerRuntime {
                 }}
             }};
             
-            self.skipWaiting = () => Promise.resolve();
+            self.skipWaiting = () => {{
+                self.__vbeSkipWaiting = true;
+                return Promise.resolve();
+            }};
             
             self.clients = {{
-                claim: () => Promise.resolve(),
+                claim: () => {{
+                    self.__vbeClaim = true;
+                    return Promise.resolve();
+                }},
                 matchAll: (options = {{}}) => Promise.resolve([]),
                 openWindow: (url) => Promise.resolve(null),
                 get: (id) => Promise.resolve(null)
@@ -397,7 +1049,9 @@ impl ServiceWorkerRuntime {
                 throw new Error('importScripts not supported in this environment');
             }};
             "#,
-            worker_id, scope
+            quote(worker_id),
+            quote(scope),
+            quote(script_url)
         );
 
         self.execute_script_safely(js_engine, &globals_script, "globals_setup")
@@ -419,6 +1073,29 @@ impl ServiceWorkerRuntime {
         Ok(())
     }
 
+    /// Run `event_script`, an expression for a promise, and return what
+    /// the promise settles to once the microtasks it queued have run;
+    /// null if it is still pending.
+    async fn run_event(
+        &self,
+        js_engine: &mut JsEngine,
+        event_script: &str,
+        timeout_duration: Duration,
+    ) -> Result<Value, ServiceWorkerError> {
+        let script = format!(
+            "globalThis.__vbeEventResult = null; \
+             Promise.resolve({}).then((result) => {{ globalThis.__vbeEventResult = result; }}); \
+             undefined",
+            event_script
+        );
+        self.execute_with_timeout(js_engine, &script, timeout_duration)
+            .await?;
+        Ok(self
+            .execute_with_timeout(js_engine, "globalThis.__vbeEventResult", timeout_duration)
+            .await?
+            .unwrap_or(Value::Null))
+    }
+
     async fn execute_with_timeout(
         &self,
         js_engine: &mut JsEngine,
@@ -496,13 +1173,22 @@ impl ServiceWorkerRuntime {
             .get_mut(worker_id)
             .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
 
-        self.execute_with_timeout(
-            &mut worker.js_engine,
-            install_script,
-            Duration::from_secs(30),
-        )
-        .await?;
-        worker.state = WorkerState::Installed;
+        let outcome = self
+            .run_event(
+                &mut worker.js_engine,
+                install_script,
+                Duration::from_secs(30),
+            )
+            .await?;
+        if outcome["success"] == Value::Bool(false) {
+            return Err(ServiceWorkerError::InstallationFailed(
+                outcome["error"]
+                    .as_str()
+                    .unwrap_or("install handler failed")
+                    .to_string(),
+            ));
+        }
+        self.set_state(worker, WorkerState::Installed).await?;
 
         Ok(())
     }
@@ -531,12 +1217,18 @@ impl ServiceWorkerRuntime {
             .get_mut(worker_id)
             .ok_or_else(|| ServiceWorkerError::WorkerNotFound(worker_id.to_string()))?;
 
-        self.execute_with_timeout(
-            &mut worker.js_engine,
-            activate_script,
-            Duration::from_secs(30),
-        )
-        .await?;
+        // A failed activate handler does not keep the worker from
+        // activating.
+        let outcome = self
+            .run_event(
+                &mut worker.js_engine,
+                activate_script,
+                Duration::from_secs(30),
+            )
+            .await?;
+        if let Some(error) = outcome["error"].as_str() {
+            warn!("Activate handler of {} failed: {}", worker_id, error);
+        }
 
         Ok(())
     }
@@ -572,11 +1264,19 @@ impl ServiceWorkerRuntime {
                         
                         if (event.handled && event.response) {{
                             const response = await event.response;
+                            const headers = {{}};
+                            if (response.headers && typeof response.headers.forEach === 'function') {{
+                                response.headers.forEach((value, name) => {{ headers[name] = value; }});
+                            }} else {{
+                                Object.assign(headers, response.headers || {{}});
+                            }}
                             return {{
                                 handled: true,
                                 status: response.status || 200,
-                                headers: response.headers || {{}},
-                                body: response.body || ''
+                                headers,
+                                body: typeof response.text === 'function'
+                                    ? await response.text()
+                                    : String(response.body || '')
                             }};
                         }}
                         
@@ -631,21 +1331,15 @@ impl ServiceWorkerRuntime {
         Ok(None)
     }
 
-    fn create_default_response(&self) -> crate::pwa::FetchResponse {
-        crate::pwa::FetchResponse {
-            status: 200,
-            headers: HashMap::new(),
-            body: b"Service Worker handled request".to_vec(),
-        }
-    }
-
-    async fn fetch_script_with_validation(
+    /// Fetch the worker script at `script_url` from the network, checking
+    /// where it comes from, its type and its size.
+    pub async fn fetch_script(
         &self,
         script_url: &str,
-    ) -> Result<String, ServiceWorkerError> {
+    ) -> Result<FetchedScript, ServiceWorkerError> {
         if self.config.enable_https_only
-            && !script_url.starts_with("https://")
-            && !script_url.starts_with("http://localhost")
+            && !url::Url::parse(script_url)
+                .is_ok_and(|url| crate::pwa::is_potentially_trustworthy(&url))
         {
             return Err(ServiceWorkerError::NetworkError(
                 "Service Worker scripts must be served over HTTPS".to_string(),
@@ -679,6 +1373,12 @@ impl ServiceWorkerRuntime {
             ));
         }
 
+        let max_age = response
+            .headers()
+            .get("cache-control")
+            .and_then(|v| v.to_str().ok())
+            .map_or(Duration::ZERO, max_age);
+
         let script_content = response
             .text()
             .await
@@ -691,17 +1391,10 @@ impl ServiceWorkerRuntime {
             )));
         }
 
-        Ok(script_content)
-    }
-
-    fn generate_worker_id(&self, script_url: &str, scope: &str) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-        script_url.hash(&mut hasher);
-        scope.hash(&mut hasher);
-        format!("sw_{:x}", hasher.finish())
+        Ok(FetchedScript {
+            source: script_content,
+            max_age,
+        })
     }
 
     fn update_execution_stats(
@@ -724,6 +1417,39 @@ impl ServiceWorkerRuntime {
     }
 }
 
+/// A subscription as the worker's push state holds it, or null.
+pub(crate) fn subscription_record(subscription: Option<&PushSubscription>) -> Value {
+    match subscription {
+        Some(subscription) => json!({
+            "endpoint": subscription.endpoint,
+            "p256dh": subscription.p256dh,
+            "auth": subscription.auth,
+            "applicationServerKey": subscription.application_server_key,
+        }),
+        None => Value::Null,
+    }
+}
+
+/// The `max-age` of a `Cache-Control` value; zero when it has none, or
+/// forbids reuse.
+fn max_age(cache_control: &str) -> Duration {
+    let mut max_age = Duration::ZERO;
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        {
+            return Duration::ZERO;
+        }
+        if let Some((name, value)) = directive.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                if let Ok(seconds) = value.trim().trim_matches('"').parse() {
+                    max_age = Duration::from_secs(seconds);
+                }
+            }
+        }
+    }
+    max_age
+}
+
 impl Default for ServiceWorkerConfig {
     fn default() -> Self {
         Self {
@@ -733,6 +1459,7 @@ impl Default for ServiceWorkerConfig {
             max_script_size: 5 * 1024 * 1024,
             max_idle_time: Duration::from_secs(300),
             enable_https_only: true,
+            push_endpoint: DEFAULT_PUSH_ENDPOINT.to_string(),
         }
     }
 }
@@ -0,0 +1,161 @@
+//! Service worker registrations kept across engine restarts.
+//!
+//! Each origin's registrations live in `<directory>/<origin digest>.json`,
+//! rewritten whole on every change. A registration is stored once it has
+//! an active worker, together with that worker's script, so a restarted
+//! engine can run it again before the network is asked anything, along
+//! with its push subscription's keys and pending sync tags. Without a
+//! directory (private mode) nothing is written and nothing is restored.
+
+use crate::core::storage::write_atomically;
+use crate::pwa::push::StoredSubscription;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A registration as persisted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredRegistration {
+    /// Absolute scope URL; the registration's identity within its origin.
+    pub scope: String,
+    pub script_url: String,
+    /// Seconds since the epoch of the last update check that reached the
+    /// network.
+    pub updated_at: u64,
+    /// Seconds the script response said it could be reused for.
+    pub max_age: u64,
+    /// The active worker's script.
+    pub script: String,
+    /// SHA-256 of `script`, in hex.
+    pub script_hash: String,
+    #[serde(default)]
+    pub push_subscription: Option<StoredSubscription>,
+    /// Background sync tags not yet synced.
+    #[serde(default)]
+    pub sync_tags: Vec<String>,
+}
+
+impl StoredRegistration {
+    pub fn last_update_check(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.updated_at)
+    }
+}
+
+/// Seconds since the epoch of `time`, as stored.
+pub(crate) fn epoch_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Serialize, Deserialize)]
+struct OriginFile {
+    origin: String,
+    registrations: Vec<StoredRegistration>,
+}
+
+pub struct RegistrationStore {
+    directory: Option<PathBuf>,
+}
+
+impl RegistrationStore {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Self { directory }
+    }
+
+    /// A store that keeps nothing.
+    pub fn in_memory() -> Self {
+        Self::new(None)
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        self.directory.is_some()
+    }
+
+    /// Every stored registration. Unreadable files are skipped.
+    pub fn load_all(&self) -> Vec<StoredRegistration> {
+        let directory = match &self.directory {
+            Some(directory) => directory,
+            None => return Vec::new(),
+        };
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .flat_map(|path| match Self::read(&path) {
+                Some(file) => file.registrations,
+                None => {
+                    tracing::warn!("Discarding unreadable {}", path.display());
+                    Vec::new()
+                }
+            })
+            .collect()
+    }
+
+    /// Store `registration`, replacing the one of the same scope.
+    pub fn save(&self, registration: &StoredRegistration) {
+        self.update(&registration.scope, |registrations| {
+            registrations.retain(|stored| stored.scope != registration.scope);
+            registrations.push(registration.clone());
+        });
+    }
+
+    /// Forget the registration of `scope`.
+    pub fn remove(&self, scope: &str) {
+        self.update(scope, |registrations| {
+            registrations.retain(|stored| stored.scope != scope);
+        });
+    }
+
+    fn update(&self, scope: &str, change: impl FnOnce(&mut Vec<StoredRegistration>)) {
+        let origin = match url::Url::parse(scope) {
+            Ok(scope) => scope.origin().ascii_serialization(),
+            Err(_) => return,
+        };
+        let path = match &self.directory {
+            Some(directory) => directory.join(format!("{}.json", file_stem(&origin))),
+            None => return,
+        };
+        let mut registrations = Self::read(&path)
+            .filter(|file| file.origin == origin)
+            .map(|file| file.registrations)
+            .unwrap_or_default();
+        change(&mut registrations);
+
+        let result = if registrations.is_empty() {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        } else {
+            serde_json::to_vec(&OriginFile {
+                origin,
+                registrations,
+            })
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| write_atomically(&path, &bytes))
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to persist {}: {}", path.display(), e);
+        }
+    }
+
+    fn read(path: &std::path::Path) -> Option<OriginFile> {
+        let bytes = fs::read(path).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// Stable file-name-safe digest of an origin.
+fn file_stem(origin: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, origin.as_bytes());
+    digest.as_ref()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
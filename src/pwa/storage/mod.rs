@@ -4,15 +4,18 @@ pub mod quota;
 
 pub use quota::*;
 
+use crate::core::storage::{StorageKey, WebStorage};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::fs;
 
 pub struct StorageManager {
-    storage_root: PathBuf,
+    /// `None` when storage is memory-only (private mode).
+    storage_root: Option<PathBuf>,
     databases: HashMap<String, IndexedDatabase>,
-    local_storage: HashMap<String, HashMap<String, String>>,
-    session_storage: HashMap<String, HashMap<String, String>>,
+    /// `localStorage`/`sessionStorage`, shared with the pages' `Storage` objects.
+    web_storage: Arc<WebStorage>,
     quota_manager: QuotaManager,
 }
 
@@ -38,21 +41,19 @@ pub struct Index {
 }
 
 impl StorageManager {
-    pub async fn new() -> Result<Self, StorageError> {
-        let storage_root = dirs::data_dir()
-            .unwrap_or_else(|| PathBuf::from("./data"))
-            .join("vulkan-renderer")
-            .join("storage");
+    pub async fn new(web_storage: Arc<WebStorage>) -> Result<Self, StorageError> {
+        let storage_root = web_storage.directory().map(PathBuf::from);
 
-        fs::create_dir_all(&storage_root)
-            .await
-            .map_err(|e| StorageError::IoError(e.to_string()))?;
+        if let Some(storage_root) = &storage_root {
+            fs::create_dir_all(storage_root)
+                .await
+                .map_err(|e| StorageError::IoError(e.to_string()))?;
+        }
 
         Ok(Self {
             storage_root,
             databases: HashMap::new(),
-            local_storage: HashMap::new(),
-            session_storage: HashMap::new(),
+            web_storage,
             quota_manager: QuotaManager::new(),
         })
     }
@@ -88,11 +89,16 @@ impl StorageManager {
             self.databases.remove(&key);
         }
 
-        let db_path = self.storage_root.join(format!("{}.db", name));
-        if db_path.exists() {
-            fs::remove_file(db_path)
-                .await
-                .map_err(|e| StorageError::IoError(e.to_string()))?;
+        if let Some(db_path) = self
+            .storage_root
+            .as_ref()
+            .map(|root| root.join(format!("{}.db", name)))
+        {
+            if db_path.exists() {
+                fs::remove_file(db_path)
+                    .await
+                    .map_err(|e| StorageError::IoError(e.to_string()))?;
+            }
         }
 
         Ok(())
@@ -180,14 +186,16 @@ impl StorageManager {
         key: &str,
         value: &str,
     ) -> Result<(), StorageError> {
-        let storage = self.local_storage.entry(origin.to_string()).or_default();
-        storage.insert(key.to_string(), value.to_string());
-        self.persist_local_storage(origin).await?;
-        Ok(())
+        self.web_storage
+            .local(&StorageKey::first_party(origin))
+            .set(key, value)
+            .map_err(|_| StorageError::QuotaExceeded)
     }
 
     pub async fn get_local_storage(&self, origin: &str, key: &str) -> Option<String> {
-        self.local_storage.get(origin)?.get(key).cloned()
+        self.web_storage
+            .local(&StorageKey::first_party(origin))
+            .get(key)
     }
 
     pub async fn remove_local_storage(
@@ -195,23 +203,15 @@ impl StorageManager {
         origin: &str,
         key: &str,
     ) -> Result<(), StorageError> {
-        if let Some(storage) = self.local_storage.get_mut(origin) {
-            storage.remove(key);
-            self.persist_local_storage(origin).await?;
-        }
+        self.web_storage
+            .local(&StorageKey::first_party(origin))
+            .remove(key);
         Ok(())
     }
 
     pub async fn clear_local_storage(&mut self, origin: &str) -> Result<(), StorageError> {
-        self.local_storage.remove(origin);
-        let storage_file = self
-            .storage_root
-            .join(format!("{}_localStorage.json", origin));
-        if storage_file.exists() {
-            fs::remove_file(storage_file)
-                .await
-                .map_err(|e| StorageError::IoError(e.to_string()))?;
-        }
+        self.web_storage
+            .clear_local(&StorageKey::first_party(origin));
         Ok(())
     }
 
@@ -221,13 +221,16 @@ impl StorageManager {
         key: &str,
         value: &str,
     ) -> Result<(), StorageError> {
-        let storage = self.session_storage.entry(origin.to_string()).or_default();
-        storage.insert(key.to_string(), value.to_string());
-        Ok(())
+        self.web_storage
+            .session(&StorageKey::first_party(origin))
+            .set(key, value)
+            .map_err(|_| StorageError::QuotaExceeded)
     }
 
     pub async fn get_session_storage(&self, origin: &str, key: &str) -> Option<String> {
-        self.session_storage.get(origin)?.get(key).cloned()
+        self.web_storage
+            .session(&StorageKey::first_party(origin))
+            .get(key)
     }
 
     pub async fn clear_app_storage(&mut self, app_id: &str) -> Result<(), StorageError> {
@@ -242,15 +245,15 @@ impl StorageManager {
             self.databases.remove(&db_key);
         }
 
-        self.local_storage.remove(&format!("app_{}", app_id));
-        self.session_storage.remove(&format!("app_{}", app_id));
+        let key = StorageKey::first_party(format!("app_{}", app_id));
+        self.web_storage.clear_local(&key);
+        self.web_storage.clear_session(&key);
 
         Ok(())
     }
 
     pub async fn get_usage(&self, app_id: &str) -> Result<crate::pwa::StorageUsage, StorageError> {
         let mut indexeddb_size = 0u64;
-        let mut local_storage_size = 0u64;
 
         for (db_key, db) in &self.databases {
             if db_key.contains(&format!("app_{}", app_id)) {
@@ -262,11 +265,12 @@ impl StorageManager {
             }
         }
 
-        if let Some(local_data) = self.local_storage.get(&format!("app_{}", app_id)) {
-            for (key, value) in local_data {
-                local_storage_size += (key.len() + value.len()) as u64;
-            }
-        }
+        // Quota usage counts UTF-16 code units; report bytes.
+        let local_storage_size = self
+            .web_storage
+            .local(&StorageKey::first_party(format!("app_{}", app_id)))
+            .usage() as u64
+            * 2;
 
         Ok(crate::pwa::StorageUsage {
             cache_size: 0, // This would be calculated by the cache manager
@@ -275,40 +279,6 @@ impl StorageManager {
             total_size: indexeddb_size + local_storage_size,
         })
     }
-
-    async fn persist_local_storage(&self, origin: &str) -> Result<(), StorageError> {
-        if let Some(data) = self.local_storage.get(origin) {
-            let storage_file = self
-                .storage_root
-                .join(format!("{}_localStorage.json", origin));
-            let json_data = serde_json::to_string_pretty(data)
-                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
-
-            fs::write(storage_file, json_data)
-                .await
-                .map_err(|e| StorageError::IoError(e.to_string()))?;
-        }
-        Ok(())
-    }
-
-    pub async fn load_persistent_data(&mut self, origin: &str) -> Result<(), StorageError> {
-        let storage_file = self
-            .storage_root
-            .join(format!("{}_localStorage.json", origin));
-
-        if storage_file.exists() {
-            let content = fs::read_to_string(storage_file)
-                .await
-                .map_err(|e| StorageError::IoError(e.to_string()))?;
-
-            let data: HashMap<String, String> = serde_json::from_str(&content)
-                .map_err(|e| StorageError::SerializationError(e.to_string()))?;
-
-            self.local_storage.insert(origin.to_string(), data);
-        }
-
-        Ok(())
-    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -331,6 +301,8 @@ pub enum StorageError {
 
 impl Default for StorageManager {
     fn default() -> Self {
-        futures::executor::block_on(async { Self::new().await.unwrap() })
+        futures::executor::block_on(async {
+            Self::new(Arc::new(WebStorage::in_memory())).await.unwrap()
+        })
     }
 }
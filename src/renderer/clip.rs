@@ -0,0 +1,293 @@
+//! Overflow clipping, including rounded clips for `border-radius` with
+//! `overflow: hidden`.
+//!
+//! Every draw carries the [`ClipChain`] of its clipping ancestors. The
+//! rectangular part of all clips intersects into one scissor rect; rounded
+//! clips additionally go to the fragment shader as push constants
+//! ([`ClipPushConstants`]) and are tested there with a signed-distance check
+//! ([`ROUNDED_CLIP_GLSL`]). Hit-testing and the software rasterizer use
+//! [`ClipChain::contains`], which applies the same geometry.
+
+use super::Rect;
+
+/// Rounded clips a single draw can be subject to at once. A rounded card
+/// inside a rounded modal inside a rounded panel still clips exactly; a
+/// fourth rounded ancestor only clips to its rectangle. Sized so
+/// [`ClipPushConstants`] fits the 128 bytes of push constants every Vulkan
+/// implementation guarantees.
+pub const MAX_ROUNDED_CLIP_DEPTH: usize = 3;
+
+/// Fragment shader helper matching [`ClipPushConstants`]. Shaders drawing
+/// clipped content include it and multiply their output alpha by
+/// `rounded_clip_coverage(gl_FragCoord.xy)`.
+pub const ROUNDED_CLIP_GLSL: &str = r#"
+layout(push_constant) uniform RoundedClips {
+    vec4 rects[3];
+    vec4 radii[3];
+    uint count;
+} clip;
+
+float rounded_clip_distance(vec2 p, vec4 rect, vec4 radii) {
+    vec2 half_size = rect.zw * 0.5;
+    vec2 q = p - (rect.xy + half_size);
+    // radii: top-left, top-right, bottom-right, bottom-left.
+    float r = q.x < 0.0 ? (q.y < 0.0 ? radii.x : radii.w)
+                        : (q.y < 0.0 ? radii.y : radii.z);
+    vec2 d = abs(q) - half_size + r;
+    return min(max(d.x, d.y), 0.0) + length(max(d, 0.0)) - r;
+}
+
+float rounded_clip_coverage(vec2 p) {
+    float coverage = 1.0;
+    for (uint i = 0u; i < clip.count; i++) {
+        float distance = rounded_clip_distance(p, clip.rects[i], clip.radii[i]);
+        coverage *= clamp(0.5 - distance, 0.0, 1.0);
+    }
+    return coverage;
+}
+"#;
+
+/// Corner radii of a rounded rect, in pixels. Corners are circular;
+/// elliptical radii are not supported.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CornerRadii {
+    pub top_left: f32,
+    pub top_right: f32,
+    pub bottom_right: f32,
+    pub bottom_left: f32,
+}
+
+impl CornerRadii {
+    pub fn uniform(radius: f32) -> Self {
+        Self {
+            top_left: radius,
+            top_right: radius,
+            bottom_right: radius,
+            bottom_left: radius,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.top_left <= 0.0
+            && self.top_right <= 0.0
+            && self.bottom_right <= 0.0
+            && self.bottom_left <= 0.0
+    }
+
+    /// Radii for a box inset by the given widths, as for the padding edge
+    /// inside a border.
+    pub fn inset(&self, top: f32, right: f32, bottom: f32, left: f32) -> Self {
+        Self {
+            top_left: (self.top_left - top.max(left)).max(0.0),
+            top_right: (self.top_right - top.max(right)).max(0.0),
+            bottom_right: (self.bottom_right - bottom.max(right)).max(0.0),
+            bottom_left: (self.bottom_left - bottom.max(left)).max(0.0),
+        }
+    }
+
+    /// Scale all radii down together until adjacent ones fit along each
+    /// side of a `width` x `height` box, as CSS does for oversized radii.
+    pub fn fit(&self, width: f32, height: f32) -> Self {
+        let ratio = |side: f32, a: f32, b: f32| {
+            if a + b > side {
+                side.max(0.0) / (a + b)
+            } else {
+                1.0
+            }
+        };
+        let scale = ratio(width, self.top_left, self.top_right)
+            .min(ratio(width, self.bottom_left, self.bottom_right))
+            .min(ratio(height, self.top_left, self.bottom_left))
+            .min(ratio(height, self.top_right, self.bottom_right));
+        Self {
+            top_left: self.top_left.max(0.0) * scale,
+            top_right: self.top_right.max(0.0) * scale,
+            bottom_right: self.bottom_right.max(0.0) * scale,
+            bottom_left: self.bottom_left.max(0.0) * scale,
+        }
+    }
+
+    fn to_array(self) -> [f32; 4] {
+        [
+            self.top_left,
+            self.top_right,
+            self.bottom_right,
+            self.bottom_left,
+        ]
+    }
+}
+
+/// A clip region: a rect with optionally rounded corners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipRect {
+    pub rect: Rect,
+    pub radii: CornerRadii,
+}
+
+impl ClipRect {
+    pub fn new(rect: Rect, radii: CornerRadii) -> Self {
+        let radii = radii.fit(rect.width, rect.height);
+        Self { rect, radii }
+    }
+
+    pub fn is_rounded(&self) -> bool {
+        !self.radii.is_zero()
+    }
+
+    /// Whether `(x, y)` lies inside, corners included.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let Rect {
+            x: left,
+            y: top,
+            width,
+            height,
+        } = self.rect;
+        let (right, bottom) = (left + width, top + height);
+        if x < left || x >= right || y < top || y >= bottom {
+            return false;
+        }
+
+        let left_half = x < left + width / 2.0;
+        let top_half = y < top + height / 2.0;
+        let radius = match (left_half, top_half) {
+            (true, true) => self.radii.top_left,
+            (false, true) => self.radii.top_right,
+            (false, false) => self.radii.bottom_right,
+            (true, false) => self.radii.bottom_left,
+        };
+        if radius <= 0.0 {
+            return true;
+        }
+        let cx = if left_half {
+            left + radius
+        } else {
+            right - radius
+        };
+        let cy = if top_half {
+            top + radius
+        } else {
+            bottom - radius
+        };
+        // Only the square between the corner and its arc's center is cut.
+        let beyond_x = if left_half { x < cx } else { x > cx };
+        let beyond_y = if top_half { y < cy } else { y > cy };
+        if !(beyond_x && beyond_y) {
+            return true;
+        }
+        let (dx, dy) = (x - cx, y - cy);
+        dx * dx + dy * dy <= radius * radius
+    }
+}
+
+/// The clips in effect for one draw, outermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClipChain {
+    /// Intersection of every clip's rect; `None` when unclipped.
+    scissor: Option<Rect>,
+    /// Rounded clips, up to [`MAX_ROUNDED_CLIP_DEPTH`].
+    rounded: Vec<ClipRect>,
+}
+
+impl ClipChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A chain exactly as another one was built, as received from the
+    /// process that laid it out.
+    pub(crate) fn from_parts(scissor: Option<Rect>, rounded: Vec<ClipRect>) -> Self {
+        Self { scissor, rounded }
+    }
+
+    /// The chain for descendants of an element that clips to `clip`.
+    pub fn push(&self, clip: ClipRect) -> Self {
+        let mut chain = self.clone();
+        chain.scissor = Some(match &chain.scissor {
+            Some(scissor) => intersect(scissor, &clip.rect),
+            None => clip.rect.clone(),
+        });
+        if clip.is_rounded() {
+            if chain.rounded.len() < MAX_ROUNDED_CLIP_DEPTH {
+                chain.rounded.push(clip);
+            } else {
+                tracing::debug!(
+                    "Rounded clip nesting deeper than {}; clipping to the rectangle",
+                    MAX_ROUNDED_CLIP_DEPTH
+                );
+            }
+        }
+        chain
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scissor.is_none()
+    }
+
+    pub fn scissor(&self) -> Option<&Rect> {
+        self.scissor.as_ref()
+    }
+
+    pub fn rounded(&self) -> &[ClipRect] {
+        &self.rounded
+    }
+
+    /// Whether `(x, y)` survives every clip in the chain.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let in_scissor = match &self.scissor {
+            Some(scissor) => {
+                x >= scissor.x
+                    && x < scissor.x + scissor.width
+                    && y >= scissor.y
+                    && y < scissor.y + scissor.height
+            }
+            None => true,
+        };
+        in_scissor && self.rounded.iter().all(|clip| clip.contains(x, y))
+    }
+
+    pub fn push_constants(&self) -> ClipPushConstants {
+        let mut constants = ClipPushConstants::default();
+        for (index, clip) in self.rounded.iter().enumerate() {
+            constants.rects[index] = [clip.rect.x, clip.rect.y, clip.rect.width, clip.rect.height];
+            constants.radii[index] = clip.radii.to_array();
+        }
+        constants.count = self.rounded.len() as u32;
+        constants
+    }
+}
+
+fn intersect(a: &Rect, b: &Rect) -> Rect {
+    let x = a.x.max(b.x);
+    let y = a.y.max(b.y);
+    let right = (a.x + a.width).min(b.x + b.width);
+    let bottom = (a.y + a.height).min(b.y + b.height);
+    Rect {
+        x,
+        y,
+        width: (right - x).max(0.0),
+        height: (bottom - y).max(0.0),
+    }
+}
+
+/// Push constant block read by [`ROUNDED_CLIP_GLSL`] (112 bytes, std430).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClipPushConstants {
+    pub rects: [[f32; 4]; MAX_ROUNDED_CLIP_DEPTH],
+    pub radii: [[f32; 4]; MAX_ROUNDED_CLIP_DEPTH],
+    pub count: u32,
+    pub _padding: [u32; 3],
+}
+
+impl ClipPushConstants {
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: `repr(C)` plain data made of `f32`/`u32` with no padding
+        // between fields; every byte is initialized.
+        unsafe {
+            std::slice::from_raw_parts(
+                (self as *const Self).cast::<u8>(),
+                std::mem::size_of::<Self>(),
+            )
+        }
+    }
+}
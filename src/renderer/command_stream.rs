@@ -0,0 +1,1476 @@
+//! Paint commands streamed from a content process to the GPU process.
+//!
+//! [`wire`](super::wire) ships layout trees, which leaves all paint work to
+//! the process that owns the GPU. This is the other split: the content
+//! process paints, turning its layout tree into a list of [`PaintCommand`]s
+//! with a [`CommandRecorder`], and the GPU process only checks the list with
+//! a [`CommandReceiver`] and draws it with
+//! [`VulkanRenderer::render_commands`](super::VulkanRenderer::render_commands).
+//! Commands name no Vulkan objects: quads are solid or drawn from a resource
+//! by [`ResourceId`], glyph runs from an atlas page, and clips and
+//! transforms nest by push and pop.
+//!
+//! Decoded images and glyph atlas pages are resources. A frame carries the
+//! resource updates its commands need ahead of them: a resource is defined
+//! with its pixels the first time it is drawn, atlas pages are patched as
+//! glyphs are added to them, and images no longer drawn are released. Every
+//! other frame refers to them by id alone. A frame flagged as a reset starts
+//! from no resources, as the first one does and as the recorder sends again
+//! once asked to after the receiver refused a frame.
+//!
+//! Numbers are little-endian and fixed-width, `f32`s as their bits. A frame
+//! is laid out as
+//!
+//! ```text
+//! magic "VBPC", version u16, flags u8 (1 reset), reserved u8 (0), seq u64
+//! updates:  count u32, then each as a tag u8 and
+//!           0 define     id u32, kind u8 (0 image, 1 glyph atlas),
+//!                        width u32, height u32, pixel bytes
+//!           1 patch      id u32, x u32, y u32, width u32, height u32,
+//!                        length u32, pixel bytes
+//!           2 release    id u32
+//! commands: count u32, then each as a tag u8 and
+//!           0 quad            bounds, color, blur radius
+//!           1 textured quad   bounds, resource id u32, source rect
+//!           2 push clip       rect, corner radii
+//!           3 pop clip
+//!           4 push transform  scale x, scale y, translate x, translate y
+//!           5 pop transform
+//!           6 glyph run       page id u32, color, count u32, then each
+//!                             glyph as bounds and source rect
+//! ```
+//!
+//! Rects are four `f32`s, x, y, width and height, and colors four, sRGB
+//! RGBA. Images are RGBA8 and atlas pages one coverage byte per pixel, row
+//! by row; source rects are in their pixels.
+//!
+//! The receiver trusts nothing in a frame. It decodes it with bounds-checked
+//! reads, checking counts against the bytes left before allocating, and then
+//! checks, before any of it takes effect, that every number is finite and
+//! every rect within [`MAX_COORDINATE`] of the origin once transformed; that
+//! each resource drawn from exists, is of the kind drawn and holds the
+//! source rect; that patches fall inside their resource; that resources stay
+//! within [`MAX_RESOURCE_SIDE`] and, together, [`MAX_RESOURCE_BYTES`]; and
+//! that clips and transforms nest at most [`MAX_STACK_DEPTH`] deep and are
+//! all popped by the end. A frame failing any of it is refused whole, the
+//! resources left as they were.
+
+use ahash::{AHashMap, AHashSet};
+use std::sync::Arc;
+use thiserror::Error;
+
+use super::glyphs::{GlyphCache, GlyphKey, PlacedGlyph};
+use super::image::{broken_image_quads, DecodedImage, LoadedImage};
+use super::retained::{frame_entries, PaintSource};
+use super::{
+    decoration, parse_color, ClipChain, ClipRect, CornerRadii, ElementType, LayoutNode, LayoutTree,
+    Rect,
+};
+
+/// Largest frame either side accepts, and the cap on
+/// [`MessageType::PaintCommands`](crate::sandbox::ipc::MessageType::PaintCommands)
+/// payloads. Room for a few images defined at once.
+pub const MAX_COMMAND_FRAME_BYTES: usize = 32 * 1024 * 1024;
+
+pub const COMMAND_STREAM_VERSION: u16 = 1;
+
+/// Farthest from the origin, in pixels, any drawn or clipping rect may
+/// reach once transformed.
+pub const MAX_COORDINATE: f32 = 1_000_000.0;
+
+/// Clips, and transforms, a frame may have pushed at once.
+pub const MAX_STACK_DEPTH: usize = 64;
+
+/// Widest and tallest a resource may be.
+pub const MAX_RESOURCE_SIDE: u32 = 8192;
+
+/// Pixel bytes the resources of one content process may hold together.
+pub const MAX_RESOURCE_BYTES: usize = 256 * 1024 * 1024;
+
+/// Width and height of a glyph atlas page.
+pub const ATLAS_PAGE_SIZE: u32 = 512;
+
+/// Largest blur radius a quad may have, in pixels.
+const MAX_BLUR_RADIUS: f32 = 1_000.0;
+
+/// Largest factor transforms may scale by, together.
+const MAX_SCALE: f32 = 1_000.0;
+
+/// Largest side of a glyph atlas cell; bigger glyphs are stretched from it.
+const MAX_GLYPH_CELL: u32 = 128;
+
+const MAGIC: &[u8; 4] = b"VBPC";
+
+const RESET: u8 = 1;
+
+/// Bytes before the resource updates.
+const HEADER_LEN: usize = 4 + 2 + 1 + 1 + 8;
+
+const RECT_LEN: usize = 16;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Paint command frame ends early")]
+    Truncated,
+    #[error("Not a paint command frame")]
+    BadMagic,
+    #[error("Unsupported paint command frame version {0}")]
+    UnsupportedVersion(u16),
+    #[error("Invalid {0} tag {1}")]
+    InvalidTag(&'static str, u8),
+    #[error("Paint command frame of {0} bytes exceeds the cap")]
+    TooLarge(usize),
+    #[error("{0} bytes past the end of the paint command frame")]
+    TrailingBytes(usize),
+    #[error("Frame {seq} does not follow frame {last:?}")]
+    OutOfOrder { seq: u64, last: Option<u64> },
+    #[error("{0} out of bounds")]
+    OutOfBounds(&'static str),
+    #[error("Resource {0:?} does not exist")]
+    UnknownResource(ResourceId),
+    #[error("Resource {0:?} already exists")]
+    DuplicateResource(ResourceId),
+    #[error("Resource {0:?} is not {1}")]
+    WrongResourceKind(ResourceId, &'static str),
+    #[error("Resources would take {0} bytes, over the budget")]
+    ResourceBudget(usize),
+    #[error("Unbalanced {0} stack")]
+    Unbalanced(&'static str),
+    #[error("{0} stack deeper than {max}", max = MAX_STACK_DEPTH)]
+    TooDeep(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    /// RGBA8, straight alpha.
+    Image,
+    /// One byte of glyph coverage per pixel.
+    GlyphAtlas,
+}
+
+impl ResourceKind {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            ResourceKind::Image => 4,
+            ResourceKind::GlyphAtlas => 1,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ResourceKind::Image => "an image",
+            ResourceKind::GlyphAtlas => "a glyph atlas page",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceUpdate {
+    Define {
+        id: ResourceId,
+        kind: ResourceKind,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    /// New pixels for part of a resource, as glyphs are added to an atlas
+    /// page.
+    Patch {
+        id: ResourceId,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    Release(ResourceId),
+}
+
+/// A scale, then a translation. Without rotation or skew quads stay
+/// axis-aligned, as scissors and the rasterizer need them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub translate_x: f32,
+    pub translate_y: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        scale_x: 1.0,
+        scale_y: 1.0,
+        translate_x: 0.0,
+        translate_y: 0.0,
+    };
+
+    pub fn scale(factor: f32) -> Self {
+        Self {
+            scale_x: factor,
+            scale_y: factor,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// `inner`, then this transform.
+    pub fn then(&self, inner: &Transform) -> Transform {
+        Transform {
+            scale_x: self.scale_x * inner.scale_x,
+            scale_y: self.scale_y * inner.scale_y,
+            translate_x: self.scale_x * inner.translate_x + self.translate_x,
+            translate_y: self.scale_y * inner.translate_y + self.translate_y,
+        }
+    }
+
+    /// `rect` transformed, its width and height kept positive when a scale
+    /// flips it.
+    pub fn apply(&self, rect: &Rect) -> Rect {
+        let x0 = rect.x * self.scale_x + self.translate_x;
+        let x1 = (rect.x + rect.width) * self.scale_x + self.translate_x;
+        let y0 = rect.y * self.scale_y + self.translate_y;
+        let y1 = (rect.y + rect.height) * self.scale_y + self.translate_y;
+        Rect {
+            x: x0.min(x1),
+            y: y0.min(y1),
+            width: (x1 - x0).abs(),
+            height: (y1 - y0).abs(),
+        }
+    }
+
+    /// `clip` transformed; corners scale by the smaller of the two factors.
+    pub fn apply_clip(&self, clip: &ClipRect) -> ClipRect {
+        let factor = self.scale_x.abs().min(self.scale_y.abs());
+        let radii = &clip.radii;
+        ClipRect::new(
+            self.apply(&clip.rect),
+            CornerRadii {
+                top_left: radii.top_left * factor,
+                top_right: radii.top_right * factor,
+                bottom_right: radii.bottom_right * factor,
+                bottom_left: radii.bottom_left * factor,
+            },
+        )
+    }
+
+    /// What a blur radius scales by.
+    pub fn blur_scale(&self) -> f32 {
+        (self.scale_x.abs() + self.scale_y.abs()) / 2.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphQuad {
+    pub bounds: Rect,
+    /// Where the glyph's coverage is on its atlas page.
+    pub source: Rect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaintCommand {
+    Quad {
+        bounds: Rect,
+        /// sRGB, as [`parse_color`] gives it.
+        color: [f32; 4],
+        /// `0.0` for a sharp quad.
+        blur_radius: f32,
+    },
+    TexturedQuad {
+        bounds: Rect,
+        /// An image.
+        resource: ResourceId,
+        source: Rect,
+    },
+    /// Clip what follows, until the matching pop, to `clip` as well.
+    PushClip(ClipRect),
+    PopClip,
+    /// Transform what follows, until the matching pop, by this and then by
+    /// the transforms already pushed.
+    PushTransform(Transform),
+    PopTransform,
+    GlyphRun {
+        /// A glyph atlas page.
+        page: ResourceId,
+        color: [f32; 4],
+        glyphs: Vec<GlyphQuad>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandFrame {
+    pub seq: u64,
+    /// Drop every resource before the updates.
+    pub reset: bool,
+    pub updates: Vec<ResourceUpdate>,
+    pub commands: Vec<PaintCommand>,
+}
+
+struct Writer {
+    body: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.body.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn rect(&mut self, rect: &Rect) {
+        for value in [rect.x, rect.y, rect.width, rect.height] {
+            self.f32(value);
+        }
+    }
+
+    fn color(&mut self, color: &[f32; 4]) {
+        for &value in color {
+            self.f32(value);
+        }
+    }
+
+    fn update(&mut self, update: &ResourceUpdate) {
+        match update {
+            ResourceUpdate::Define {
+                id,
+                kind,
+                width,
+                height,
+                pixels,
+            } => {
+                self.u8(0);
+                self.u32(id.0);
+                self.u8(match kind {
+                    ResourceKind::Image => 0,
+                    ResourceKind::GlyphAtlas => 1,
+                });
+                self.u32(*width);
+                self.u32(*height);
+                self.body.extend_from_slice(pixels);
+            }
+            ResourceUpdate::Patch {
+                id,
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            } => {
+                self.u8(1);
+                for value in [id.0, *x, *y, *width, *height, pixels.len() as u32] {
+                    self.u32(value);
+                }
+                self.body.extend_from_slice(pixels);
+            }
+            ResourceUpdate::Release(id) => {
+                self.u8(2);
+                self.u32(id.0);
+            }
+        }
+    }
+
+    fn command(&mut self, command: &PaintCommand) {
+        match command {
+            PaintCommand::Quad {
+                bounds,
+                color,
+                blur_radius,
+            } => {
+                self.u8(0);
+                self.rect(bounds);
+                self.color(color);
+                self.f32(*blur_radius);
+            }
+            PaintCommand::TexturedQuad {
+                bounds,
+                resource,
+                source,
+            } => {
+                self.u8(1);
+                self.rect(bounds);
+                self.u32(resource.0);
+                self.rect(source);
+            }
+            PaintCommand::PushClip(clip) => {
+                self.u8(2);
+                self.rect(&clip.rect);
+                let radii = &clip.radii;
+                for value in [
+                    radii.top_left,
+                    radii.top_right,
+                    radii.bottom_right,
+                    radii.bottom_left,
+                ] {
+                    self.f32(value);
+                }
+            }
+            PaintCommand::PopClip => self.u8(3),
+            PaintCommand::PushTransform(transform) => {
+                self.u8(4);
+                for value in [
+                    transform.scale_x,
+                    transform.scale_y,
+                    transform.translate_x,
+                    transform.translate_y,
+                ] {
+                    self.f32(value);
+                }
+            }
+            PaintCommand::PopTransform => self.u8(5),
+            PaintCommand::GlyphRun {
+                page,
+                color,
+                glyphs,
+            } => {
+                self.u8(6);
+                self.u32(page.0);
+                self.color(color);
+                self.u32(glyphs.len() as u32);
+                for glyph in glyphs {
+                    self.rect(&glyph.bounds);
+                    self.rect(&glyph.source);
+                }
+            }
+        }
+    }
+}
+
+/// Encode `frame`, refusing it when it is over the cap.
+pub fn encode_frame(frame: &CommandFrame) -> Result<Vec<u8>, CommandError> {
+    let pixels: usize = frame
+        .updates
+        .iter()
+        .map(|update| match update {
+            ResourceUpdate::Define { pixels, .. } | ResourceUpdate::Patch { pixels, .. } => {
+                pixels.len()
+            }
+            ResourceUpdate::Release(_) => 0,
+        })
+        .sum();
+    let mut writer = Writer {
+        body: Vec::with_capacity(
+            HEADER_LEN + 8 + pixels + frame.updates.len() * 25 + frame.commands.len() * 40,
+        ),
+    };
+    writer.body.extend_from_slice(MAGIC);
+    writer.u16(COMMAND_STREAM_VERSION);
+    writer.u8(if frame.reset { RESET } else { 0 });
+    writer.u8(0);
+    writer.u64(frame.seq);
+    writer.u32(frame.updates.len() as u32);
+    for update in &frame.updates {
+        writer.update(update);
+    }
+    writer.u32(frame.commands.len() as u32);
+    for command in &frame.commands {
+        writer.command(command);
+    }
+    match writer.body.len() {
+        len if len > MAX_COMMAND_FRAME_BYTES => Err(CommandError::TooLarge(len)),
+        _ => Ok(writer.body),
+    }
+}
+
+/// Bounds-checked little-endian reads over a received frame.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CommandError> {
+        if len > self.remaining() {
+            return Err(CommandError::Truncated);
+        }
+        let taken = &self.bytes[self.at..self.at + len];
+        self.at += len;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CommandError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, CommandError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, CommandError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, CommandError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, CommandError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, CommandError> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    /// A count of items at least `item_len` bytes each, refused when the
+    /// rest of the frame cannot hold that many.
+    fn count(&mut self, item_len: usize) -> Result<usize, CommandError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(item_len) > self.remaining() {
+            return Err(CommandError::Truncated);
+        }
+        Ok(count)
+    }
+
+    fn rect(&mut self) -> Result<Rect, CommandError> {
+        Ok(Rect {
+            x: self.f32()?,
+            y: self.f32()?,
+            width: self.f32()?,
+            height: self.f32()?,
+        })
+    }
+
+    fn color(&mut self) -> Result<[f32; 4], CommandError> {
+        Ok([self.f32()?, self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    /// A resource side, refused past [`MAX_RESOURCE_SIDE`] so pixel counts
+    /// cannot overflow.
+    fn side(&mut self) -> Result<u32, CommandError> {
+        match self.u32()? {
+            side if side > MAX_RESOURCE_SIDE => Err(CommandError::OutOfBounds("resource size")),
+            side => Ok(side),
+        }
+    }
+
+    fn update(&mut self) -> Result<ResourceUpdate, CommandError> {
+        Ok(match self.u8()? {
+            0 => {
+                let id = ResourceId(self.u32()?);
+                let kind = match self.u8()? {
+                    0 => ResourceKind::Image,
+                    1 => ResourceKind::GlyphAtlas,
+                    tag => return Err(CommandError::InvalidTag("resource kind", tag)),
+                };
+                let width = self.side()?;
+                let height = self.side()?;
+                let len = width as usize * height as usize * kind.bytes_per_pixel();
+                ResourceUpdate::Define {
+                    id,
+                    kind,
+                    width,
+                    height,
+                    pixels: self.take(len)?.to_vec(),
+                }
+            }
+            1 => {
+                let id = ResourceId(self.u32()?);
+                let x = self.u32()?;
+                let y = self.u32()?;
+                let width = self.side()?;
+                let height = self.side()?;
+                let len = self.u32()? as usize;
+                ResourceUpdate::Patch {
+                    id,
+                    x,
+                    y,
+                    width,
+                    height,
+                    pixels: self.take(len)?.to_vec(),
+                }
+            }
+            2 => ResourceUpdate::Release(ResourceId(self.u32()?)),
+            tag => return Err(CommandError::InvalidTag("resource update", tag)),
+        })
+    }
+
+    fn command(&mut self) -> Result<PaintCommand, CommandError> {
+        Ok(match self.u8()? {
+            0 => PaintCommand::Quad {
+                bounds: self.rect()?,
+                color: self.color()?,
+                blur_radius: self.f32()?,
+            },
+            1 => PaintCommand::TexturedQuad {
+                bounds: self.rect()?,
+                resource: ResourceId(self.u32()?),
+                source: self.rect()?,
+            },
+            2 => PaintCommand::PushClip(ClipRect {
+                rect: self.rect()?,
+                radii: CornerRadii {
+                    top_left: self.f32()?,
+                    top_right: self.f32()?,
+                    bottom_right: self.f32()?,
+                    bottom_left: self.f32()?,
+                },
+            }),
+            3 => PaintCommand::PopClip,
+            4 => PaintCommand::PushTransform(Transform {
+                scale_x: self.f32()?,
+                scale_y: self.f32()?,
+                translate_x: self.f32()?,
+                translate_y: self.f32()?,
+            }),
+            5 => PaintCommand::PopTransform,
+            6 => {
+                let page = ResourceId(self.u32()?);
+                let color = self.color()?;
+                let count = self.count(2 * RECT_LEN)?;
+                let mut glyphs = Vec::with_capacity(count);
+                for _ in 0..count {
+                    glyphs.push(GlyphQuad {
+                        bounds: self.rect()?,
+                        source: self.rect()?,
+                    });
+                }
+                PaintCommand::GlyphRun {
+                    page,
+                    color,
+                    glyphs,
+                }
+            }
+            tag => return Err(CommandError::InvalidTag("paint command", tag)),
+        })
+    }
+}
+
+/// Decode a frame, checking only that it is well-formed;
+/// [`CommandReceiver::apply`] checks what it asks for.
+pub fn decode_frame(bytes: &[u8]) -> Result<CommandFrame, CommandError> {
+    if bytes.len() > MAX_COMMAND_FRAME_BYTES {
+        return Err(CommandError::TooLarge(bytes.len()));
+    }
+    let mut reader = Reader { bytes, at: 0 };
+    if reader.take(4).map_err(|_| CommandError::BadMagic)? != MAGIC {
+        return Err(CommandError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if version != COMMAND_STREAM_VERSION {
+        return Err(CommandError::UnsupportedVersion(version));
+    }
+    let reset = match reader.u8()? {
+        0 => false,
+        RESET => true,
+        flags => return Err(CommandError::InvalidTag("flags", flags)),
+    };
+    match reader.u8()? {
+        0 => {}
+        reserved => return Err(CommandError::InvalidTag("reserved", reserved)),
+    }
+    let seq = reader.u64()?;
+
+    // The smallest update is a release, the smallest command a pop.
+    let count = reader.count(5)?;
+    let mut updates = Vec::with_capacity(count);
+    for _ in 0..count {
+        updates.push(reader.update()?);
+    }
+    let count = reader.count(1)?;
+    let mut commands = Vec::with_capacity(count);
+    for _ in 0..count {
+        commands.push(reader.command()?);
+    }
+    match reader.remaining() {
+        0 => Ok(CommandFrame {
+            seq,
+            reset,
+            updates,
+            commands,
+        }),
+        extra => Err(CommandError::TrailingBytes(extra)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// The resources a content process defined, as the GPU process holds them.
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    resources: AHashMap<ResourceId, Resource>,
+    bytes: usize,
+}
+
+impl ResourceRegistry {
+    pub fn get(&self, id: ResourceId) -> Option<&Resource> {
+        self.resources.get(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.resources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    /// Pixel bytes held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Apply updates [`check_frame`] accepted.
+    fn apply(&mut self, updates: Vec<ResourceUpdate>) {
+        for update in updates {
+            match update {
+                ResourceUpdate::Define {
+                    id,
+                    kind,
+                    width,
+                    height,
+                    pixels,
+                } => {
+                    self.bytes += pixels.len();
+                    self.resources.insert(
+                        id,
+                        Resource {
+                            kind,
+                            width,
+                            height,
+                            pixels,
+                        },
+                    );
+                }
+                ResourceUpdate::Patch {
+                    id,
+                    x,
+                    y,
+                    width,
+                    height,
+                    pixels,
+                } => {
+                    let Some(resource) = self.resources.get_mut(&id) else {
+                        continue;
+                    };
+                    let bpp = resource.kind.bytes_per_pixel();
+                    let row = width as usize * bpp;
+                    for line in 0..height as usize {
+                        let at = ((y as usize + line) * resource.width as usize + x as usize) * bpp;
+                        resource.pixels[at..at + row]
+                            .copy_from_slice(&pixels[line * row..(line + 1) * row]);
+                    }
+                }
+                ResourceUpdate::Release(id) => {
+                    if let Some(resource) = self.resources.remove(&id) {
+                        self.bytes -= resource.pixels.len();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What a frame's checks know of a resource.
+#[derive(Clone, Copy)]
+struct Extent {
+    kind: ResourceKind,
+    width: u32,
+    height: u32,
+}
+
+fn check_finite(what: &'static str, values: &[f32]) -> Result<(), CommandError> {
+    match values.iter().all(|value| value.is_finite()) {
+        true => Ok(()),
+        false => Err(CommandError::OutOfBounds(what)),
+    }
+}
+
+/// A drawn or clipping rect, as transformed.
+fn check_rect(what: &'static str, rect: &Rect) -> Result<(), CommandError> {
+    check_finite(what, &[rect.x, rect.y, rect.width, rect.height])?;
+    let within = rect.width >= 0.0
+        && rect.height >= 0.0
+        && rect.x.abs() <= MAX_COORDINATE
+        && rect.y.abs() <= MAX_COORDINATE
+        && (rect.x + rect.width).abs() <= MAX_COORDINATE
+        && (rect.y + rect.height).abs() <= MAX_COORDINATE;
+    match within {
+        true => Ok(()),
+        false => Err(CommandError::OutOfBounds(what)),
+    }
+}
+
+fn check_color(color: &[f32; 4]) -> Result<(), CommandError> {
+    match color.iter().all(|value| (0.0..=1.0).contains(value)) {
+        true => Ok(()),
+        false => Err(CommandError::OutOfBounds("color")),
+    }
+}
+
+/// A rect read from resource `id`, which must be of `kind`.
+fn check_source(
+    resources: &AHashMap<ResourceId, Extent>,
+    id: ResourceId,
+    kind: ResourceKind,
+    source: &Rect,
+) -> Result<(), CommandError> {
+    let extent = resources
+        .get(&id)
+        .ok_or(CommandError::UnknownResource(id))?;
+    if extent.kind != kind {
+        return Err(CommandError::WrongResourceKind(id, kind.name()));
+    }
+    check_finite(
+        "source rect",
+        &[source.x, source.y, source.width, source.height],
+    )?;
+    let within = source.x >= 0.0
+        && source.y >= 0.0
+        && source.width >= 0.0
+        && source.height >= 0.0
+        && source.x + source.width <= extent.width as f32
+        && source.y + source.height <= extent.height as f32;
+    match within {
+        true => Ok(()),
+        false => Err(CommandError::OutOfBounds("source rect")),
+    }
+}
+
+/// Check `frame` against `registry` as it would be after its updates,
+/// without changing anything.
+fn check_frame(registry: &ResourceRegistry, frame: &CommandFrame) -> Result<(), CommandError> {
+    let mut resources: AHashMap<ResourceId, Extent> = AHashMap::new();
+    let mut bytes = 0;
+    if !frame.reset {
+        resources.extend(registry.resources.iter().map(|(&id, resource)| {
+            (
+                id,
+                Extent {
+                    kind: resource.kind,
+                    width: resource.width,
+                    height: resource.height,
+                },
+            )
+        }));
+        bytes = registry.bytes;
+    }
+    for update in &frame.updates {
+        match update {
+            ResourceUpdate::Define {
+                id,
+                kind,
+                width,
+                height,
+                pixels,
+            } => {
+                if resources.contains_key(id) {
+                    return Err(CommandError::DuplicateResource(*id));
+                }
+                bytes += pixels.len();
+                if bytes > MAX_RESOURCE_BYTES {
+                    return Err(CommandError::ResourceBudget(bytes));
+                }
+                resources.insert(
+                    *id,
+                    Extent {
+                        kind: *kind,
+                        width: *width,
+                        height: *height,
+                    },
+                );
+            }
+            ResourceUpdate::Patch {
+                id,
+                x,
+                y,
+                width,
+                height,
+                pixels,
+            } => {
+                let extent = resources
+                    .get(id)
+                    .ok_or(CommandError::UnknownResource(*id))?;
+                let inside = x.checked_add(*width).is_some_and(|r| r <= extent.width)
+                    && y.checked_add(*height).is_some_and(|b| b <= extent.height);
+                let len = *width as usize * *height as usize * extent.kind.bytes_per_pixel();
+                if !inside || pixels.len() != len {
+                    return Err(CommandError::OutOfBounds("resource patch"));
+                }
+            }
+            ResourceUpdate::Release(id) => {
+                let extent = resources
+                    .remove(id)
+                    .ok_or(CommandError::UnknownResource(*id))?;
+                bytes -=
+                    extent.width as usize * extent.height as usize * extent.kind.bytes_per_pixel();
+            }
+        }
+    }
+
+    let mut transforms = vec![Transform::IDENTITY];
+    let mut clips = 0;
+    for command in &frame.commands {
+        let transform = *transforms.last().unwrap_or(&Transform::IDENTITY);
+        match command {
+            PaintCommand::Quad {
+                bounds,
+                color,
+                blur_radius,
+            } => {
+                check_rect("quad bounds", &transform.apply(bounds))?;
+                check_color(color)?;
+                if !(0.0..=MAX_BLUR_RADIUS).contains(blur_radius) {
+                    return Err(CommandError::OutOfBounds("blur radius"));
+                }
+            }
+            PaintCommand::TexturedQuad {
+                bounds,
+                resource,
+                source,
+            } => {
+                check_rect("quad bounds", &transform.apply(bounds))?;
+                check_source(&resources, *resource, ResourceKind::Image, source)?;
+            }
+            PaintCommand::PushClip(clip) => {
+                check_rect("clip", &transform.apply(&clip.rect))?;
+                let radii = &clip.radii;
+                let radii = [
+                    radii.top_left,
+                    radii.top_right,
+                    radii.bottom_right,
+                    radii.bottom_left,
+                ];
+                if !radii.iter().all(|r| (0.0..=MAX_COORDINATE).contains(r)) {
+                    return Err(CommandError::OutOfBounds("clip radii"));
+                }
+                clips += 1;
+                if clips > MAX_STACK_DEPTH {
+                    return Err(CommandError::TooDeep("clip"));
+                }
+            }
+            PaintCommand::PopClip => {
+                if clips == 0 {
+                    return Err(CommandError::Unbalanced("clip"));
+                }
+                clips -= 1;
+            }
+            PaintCommand::PushTransform(inner) => {
+                let combined = transform.then(inner);
+                check_finite(
+                    "transform",
+                    &[
+                        inner.scale_x,
+                        inner.scale_y,
+                        inner.translate_x,
+                        inner.translate_y,
+                    ],
+                )?;
+                let within = combined.scale_x.abs() <= MAX_SCALE
+                    && combined.scale_y.abs() <= MAX_SCALE
+                    && combined.translate_x.abs() <= MAX_COORDINATE
+                    && combined.translate_y.abs() <= MAX_COORDINATE;
+                if !within {
+                    return Err(CommandError::OutOfBounds("transform"));
+                }
+                if transforms.len() > MAX_STACK_DEPTH {
+                    return Err(CommandError::TooDeep("transform"));
+                }
+                transforms.push(combined);
+            }
+            PaintCommand::PopTransform => {
+                if transforms.len() == 1 {
+                    return Err(CommandError::Unbalanced("transform"));
+                }
+                transforms.pop();
+            }
+            PaintCommand::GlyphRun {
+                page,
+                color,
+                glyphs,
+            } => {
+                check_color(color)?;
+                for glyph in glyphs {
+                    check_rect("glyph bounds", &transform.apply(&glyph.bounds))?;
+                    check_source(&resources, *page, ResourceKind::GlyphAtlas, &glyph.source)?;
+                }
+                if glyphs.is_empty() && !resources.contains_key(page) {
+                    return Err(CommandError::UnknownResource(*page));
+                }
+            }
+        }
+    }
+    if clips != 0 {
+        return Err(CommandError::Unbalanced("clip"));
+    }
+    if transforms.len() != 1 {
+        return Err(CommandError::Unbalanced("transform"));
+    }
+    Ok(())
+}
+
+/// The GPU process's end of the stream: takes frames in order, keeping the
+/// resources they define and the commands of the last one.
+#[derive(Debug, Default)]
+pub struct CommandReceiver {
+    resources: ResourceRegistry,
+    commands: Vec<PaintCommand>,
+    seq: Option<u64>,
+}
+
+impl CommandReceiver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the last frame applied.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn resources(&self) -> &ResourceRegistry {
+        &self.resources
+    }
+
+    /// The commands of the last frame applied, checked.
+    pub fn commands(&self) -> &[PaintCommand] {
+        &self.commands
+    }
+
+    /// Take the frame in `bytes`: a reset, or the frame following the last
+    /// one applied. A frame that fails to decode or to check leaves the
+    /// receiver as it was; the sender should then be asked for a reset.
+    pub fn apply(&mut self, bytes: &[u8]) -> Result<&[PaintCommand], CommandError> {
+        let frame = decode_frame(bytes)?;
+        if !frame.reset && self.seq.and_then(|seq| seq.checked_add(1)) != Some(frame.seq) {
+            return Err(CommandError::OutOfOrder {
+                seq: frame.seq,
+                last: self.seq,
+            });
+        }
+        check_frame(&self.resources, &frame)?;
+
+        if frame.reset {
+            self.resources = ResourceRegistry::default();
+        }
+        self.resources.apply(frame.updates);
+        self.commands = frame.commands;
+        self.seq = Some(frame.seq);
+        Ok(&self.commands)
+    }
+}
+
+/// Where the glyphs met so far sit on atlas pages. Cells are handed out in
+/// rows, and a page is added when the last one is full. A cell holds the
+/// glyph's mask, shrunk to fit when it is larger.
+#[derive(Default)]
+struct GlyphAtlas {
+    pages: Vec<ResourceId>,
+    cells: AHashMap<GlyphKey, (ResourceId, Rect)>,
+    /// Where the next cell goes on the last page, and the height of its row.
+    cursor: (u32, u32, u32),
+}
+
+impl GlyphAtlas {
+    /// The cell of `glyph`, placed and its updates queued when it is new.
+    fn cell(
+        &mut self,
+        glyph: &PlacedGlyph,
+        next_id: &mut u32,
+        updates: &mut Vec<ResourceUpdate>,
+    ) -> (ResourceId, Rect) {
+        if let Some(cell) = self.cells.get(&glyph.key) {
+            return cell.clone();
+        }
+        let mask = &glyph.mask;
+        let width = mask.width.clamp(1, MAX_GLYPH_CELL);
+        let height = mask.height.clamp(1, MAX_GLYPH_CELL);
+        let (mut x, mut y, mut row) = self.cursor;
+        if x + width > ATLAS_PAGE_SIZE {
+            (x, y, row) = (0, y + row, 0);
+        }
+        if self.pages.is_empty() || y + height > ATLAS_PAGE_SIZE {
+            let id = ResourceId(*next_id);
+            *next_id += 1;
+            updates.push(ResourceUpdate::Define {
+                id,
+                kind: ResourceKind::GlyphAtlas,
+                width: ATLAS_PAGE_SIZE,
+                height: ATLAS_PAGE_SIZE,
+                pixels: vec![0; (ATLAS_PAGE_SIZE * ATLAS_PAGE_SIZE) as usize],
+            });
+            self.pages.push(id);
+            (x, y, row) = (0, 0, 0);
+        }
+        let page = *self.pages.last().expect("a page was just added");
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for cell_y in 0..height {
+            for cell_x in 0..width {
+                let u = (cell_x as f32 + 0.5) / width as f32;
+                let v = (cell_y as f32 + 0.5) / height as f32;
+                pixels.push((mask.sample(u, v) * 255.0).round() as u8);
+            }
+        }
+        updates.push(ResourceUpdate::Patch {
+            id: page,
+            x,
+            y,
+            width,
+            height,
+            pixels,
+        });
+        let source = Rect {
+            x: x as f32,
+            y: y as f32,
+            width: width as f32,
+            height: height as f32,
+        };
+        self.cursor = (x + width, y, row.max(height));
+        self.cells.insert(glyph.key, (page, source.clone()));
+        (page, source)
+    }
+}
+
+/// The content process's end of the stream: paints successive layout trees
+/// into frames of commands, sending each image and glyph once.
+///
+/// It paints what
+/// [`VulkanRenderer::render`](super::VulkanRenderer::render) does, but for
+/// `-vk-paint()` backgrounds, whose sources live with the embedder's
+/// renderer.
+pub struct CommandRecorder {
+    seq: u64,
+    reset_due: bool,
+    next_id: u32,
+    /// Images the receiver holds, with their size, by URL and frame.
+    images: AHashMap<(String, u32), (ResourceId, u32, u32)>,
+    atlas: GlyphAtlas,
+    glyphs: GlyphCache,
+    device_pixel_ratio: f32,
+}
+
+impl Default for CommandRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandRecorder {
+    pub fn new() -> Self {
+        Self {
+            seq: 0,
+            reset_due: true,
+            next_id: 1,
+            images: AHashMap::new(),
+            atlas: GlyphAtlas::default(),
+            glyphs: GlyphCache::new(),
+            device_pixel_ratio: 1.0,
+        }
+    }
+
+    /// Sequence number of the last frame recorded; 0 before the first.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Send the next frame as a reset with every resource it draws, as when
+    /// the receiver refused a frame.
+    pub fn request_reset(&mut self) {
+        self.reset_due = true;
+    }
+
+    /// Device pixels per CSS pixel: frames are scaled by it on the GPU.
+    pub fn set_device_pixel_ratio(&mut self, ratio: f32) {
+        self.device_pixel_ratio = ratio.max(f32::EPSILON);
+    }
+
+    /// Paint `tree` into the next frame. `images` gives the pixels of an
+    /// image URL's frame; images it has none for are left out, as before
+    /// they load.
+    pub fn record(
+        &mut self,
+        tree: &LayoutTree,
+        images: impl Fn(&str, u32) -> Option<Arc<DecodedImage>>,
+    ) -> CommandFrame {
+        let reset = std::mem::take(&mut self.reset_due);
+        if reset {
+            self.images.clear();
+            self.atlas = GlyphAtlas::default();
+        }
+        self.seq += 1;
+        let mut recording = Recording {
+            updates: Vec::new(),
+            commands: Vec::new(),
+            clip: ClipChain::new(),
+            clips_pushed: 0,
+        };
+        let scaled = self.device_pixel_ratio != 1.0;
+        if scaled {
+            recording
+                .commands
+                .push(PaintCommand::PushTransform(Transform::scale(
+                    self.device_pixel_ratio,
+                )));
+        }
+
+        let mut drawn_images = AHashSet::new();
+        for entry in frame_entries(tree, &[]) {
+            let PaintSource::Node(node) = entry.source else {
+                continue;
+            };
+            recording.set_clip(&node.clip);
+            match node.element_type {
+                ElementType::Block | ElementType::Inline => {
+                    if let Some(color) = &node.style.background_color {
+                        recording.commands.push(PaintCommand::Quad {
+                            bounds: node.padding_box(),
+                            color: color_of(color),
+                            blur_radius: 0.0,
+                        });
+                    }
+                    if let Some(color) = &node.style.border_color {
+                        for side in node.border_sides() {
+                            recording.commands.push(PaintCommand::Quad {
+                                bounds: side,
+                                color: color_of(color),
+                                blur_radius: 0.0,
+                            });
+                        }
+                    }
+                }
+                ElementType::Image => {
+                    let Some(url) = &node.image_url else {
+                        continue;
+                    };
+                    if tree.image(url, node.image_frame) == Some(&LoadedImage::Broken) {
+                        for (bounds, color) in broken_image_quads(&node.bounds) {
+                            recording.commands.push(PaintCommand::Quad {
+                                bounds,
+                                color,
+                                blur_radius: 0.0,
+                            });
+                        }
+                        continue;
+                    }
+                    let key = (url.clone(), node.image_frame);
+                    let sent = match self.images.get(&key) {
+                        Some(&sent) => Some(sent),
+                        None => images(url, node.image_frame)
+                            .filter(|image| {
+                                image.width <= MAX_RESOURCE_SIDE
+                                    && image.height <= MAX_RESOURCE_SIDE
+                                    && image.data.len()
+                                        == image.width as usize * image.height as usize * 4
+                            })
+                            .map(|image| {
+                                let id = ResourceId(self.next_id);
+                                self.next_id += 1;
+                                recording.updates.push(ResourceUpdate::Define {
+                                    id,
+                                    kind: ResourceKind::Image,
+                                    width: image.width,
+                                    height: image.height,
+                                    pixels: image.data.clone(),
+                                });
+                                let sent = (id, image.width, image.height);
+                                self.images.insert(key.clone(), sent);
+                                sent
+                            }),
+                    };
+                    if let Some((id, width, height)) = sent {
+                        recording.commands.push(PaintCommand::TexturedQuad {
+                            bounds: node.bounds.clone(),
+                            resource: id,
+                            source: Rect {
+                                x: 0.0,
+                                y: 0.0,
+                                width: width as f32,
+                                height: height as f32,
+                            },
+                        });
+                        drawn_images.insert(key);
+                    }
+                }
+                ElementType::Text => self.record_text(node, &mut recording),
+            }
+        }
+        recording.set_clip(&ClipChain::new());
+        if scaled {
+            recording.commands.push(PaintCommand::PopTransform);
+        }
+
+        // Images no longer drawn are let go of on both sides.
+        self.images.retain(|key, &mut (id, _, _)| {
+            let keep = drawn_images.contains(key);
+            if !keep {
+                recording.updates.push(ResourceUpdate::Release(id));
+            }
+            keep
+        });
+
+        CommandFrame {
+            seq: self.seq,
+            reset,
+            updates: recording.updates,
+            commands: recording.commands,
+        }
+    }
+
+    /// [`record`](Self::record) `tree` and encode the frame. A frame over
+    /// the cap is not sent, and the next one is a reset.
+    pub fn encode(
+        &mut self,
+        tree: &LayoutTree,
+        images: impl Fn(&str, u32) -> Option<Arc<DecodedImage>>,
+    ) -> Result<Vec<u8>, CommandError> {
+        let frame = self.record(tree, images);
+        encode_frame(&frame).inspect_err(|_| self.reset_due = true)
+    }
+
+    /// Text as [`VulkanRenderer`](super::VulkanRenderer) paints it:
+    /// shadows, decorations under the text, the glyphs, then decorations
+    /// over them.
+    fn record_text(&mut self, node: &LayoutNode, recording: &mut Recording) {
+        let Some(text) = &node.text_content else {
+            return;
+        };
+        let style = &node.style;
+        let text_color = style.color.as_deref().unwrap_or("#000000");
+        let glyphs = self.glyphs.place(text, &node.bounds, style);
+        let strips = decoration::decoration_strips(
+            &node.bounds,
+            &style.font_metrics,
+            &style.text_decoration,
+        );
+        let decoration_color = style.text_decoration.color.as_deref().unwrap_or(text_color);
+        let quad = |bounds: &Rect, color: &str, blur_radius: f32| PaintCommand::Quad {
+            bounds: bounds.clone(),
+            color: color_of(color),
+            blur_radius: blur_radius.clamp(0.0, MAX_BLUR_RADIUS),
+        };
+
+        for shadow in style.text_shadows.iter().rev() {
+            let color = shadow.color.as_deref().unwrap_or(text_color);
+            let shapes = glyphs
+                .iter()
+                .map(|glyph| &glyph.bounds)
+                .chain(strips.iter().map(|strip| &strip.rect));
+            for shape in shapes {
+                let offset = Rect {
+                    x: shape.x + shadow.offset_x,
+                    y: shape.y + shadow.offset_y,
+                    ..shape.clone()
+                };
+                recording
+                    .commands
+                    .push(quad(&offset, color, shadow.blur_radius));
+            }
+        }
+        for strip in strips.iter().filter(|strip| !strip.kind.paints_over_text()) {
+            recording
+                .commands
+                .push(quad(&strip.rect, decoration_color, 0.0));
+        }
+
+        // One run per atlas page the glyphs are on.
+        let color = color_of(text_color);
+        let mut run: Option<(ResourceId, Vec<GlyphQuad>)> = None;
+        for glyph in glyphs {
+            let (page, source) = self
+                .atlas
+                .cell(&glyph, &mut self.next_id, &mut recording.updates);
+            let bounds = glyph.bounds;
+            match &mut run {
+                Some((current, glyphs)) if *current == page => {
+                    glyphs.push(GlyphQuad { bounds, source })
+                }
+                _ => {
+                    if let Some((page, glyphs)) = run.take() {
+                        recording.commands.push(PaintCommand::GlyphRun {
+                            page,
+                            color,
+                            glyphs,
+                        });
+                    }
+                    run = Some((page, vec![GlyphQuad { bounds, source }]));
+                }
+            }
+        }
+        if let Some((page, glyphs)) = run {
+            recording.commands.push(PaintCommand::GlyphRun {
+                page,
+                color,
+                glyphs,
+            });
+        }
+
+        for strip in strips.iter().filter(|strip| strip.kind.paints_over_text()) {
+            recording
+                .commands
+                .push(quad(&strip.rect, decoration_color, 0.0));
+        }
+    }
+}
+
+/// `css` as [`parse_color`] reads it, within what the receiver accepts.
+fn color_of(css: &str) -> [f32; 4] {
+    parse_color(css).map(|channel| match channel.is_nan() {
+        true => 0.0,
+        false => channel.clamp(0.0, 1.0),
+    })
+}
+
+/// A frame being recorded.
+struct Recording {
+    updates: Vec<ResourceUpdate>,
+    commands: Vec<PaintCommand>,
+    /// The clips pushed so far, as the chain they make.
+    clip: ClipChain,
+    clips_pushed: usize,
+}
+
+impl Recording {
+    /// Clip what follows to `clip`: its scissor, unless a rounded clip has
+    /// the same rect, then its rounded clips, after popping those of the
+    /// last chain when it differs.
+    fn set_clip(&mut self, clip: &ClipChain) {
+        if self.clip == *clip {
+            return;
+        }
+        for _ in 0..self.clips_pushed {
+            self.commands.push(PaintCommand::PopClip);
+        }
+        self.clips_pushed = 0;
+        let scissor = clip
+            .scissor()
+            .filter(|scissor| !clip.rounded().iter().any(|r| r.rect == **scissor));
+        if let Some(scissor) = scissor {
+            self.commands.push(PaintCommand::PushClip(ClipRect {
+                rect: scissor.clone(),
+                radii: CornerRadii::default(),
+            }));
+            self.clips_pushed += 1;
+        }
+        for rounded in clip.rounded() {
+            self.commands.push(PaintCommand::PushClip(rounded.clone()));
+            self.clips_pushed += 1;
+        }
+        self.clip = clip.clone();
+    }
+}
@@ -0,0 +1,453 @@
+//! Backgrounds the embedder paints: `background-image: -vk-paint(name)`.
+//!
+//! The embedder registers a [`PaintSource`] under a name; boxes whose
+//! background names it are painted by calling it with their size, the
+//! device pixel ratio and the time since it was registered. It answers with
+//! RGBA pixels or a short list of rects and gradients, drawn over the
+//! background color.
+//!
+//! A static source paints the same thing for the same size, so its output
+//! is cached by name and size in device pixels and shared by every box of
+//! that size. An animated one is called again for each frame that shows
+//! it, once per frame and size. Either way a call gets the paint budget: a
+//! source slower than that leaves the frame with its last output for the
+//! size, or nothing the first time, and the frame it finishes in is
+//! requested for when it does.
+
+use super::Rect;
+use crate::core::css::color_space;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a frame waits on a paint source by default.
+pub const DEFAULT_PAINT_BUDGET: Duration = Duration::from_millis(4);
+
+/// The function `background-image` names a paint source with.
+pub const PAINT_FUNCTION: &str = "-vk-paint";
+
+/// Outputs kept across sources and sizes; past it the least recently
+/// painted go.
+const MAX_CACHED_OUTPUTS: usize = 64;
+
+/// What a paint source is asked to paint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaintInput {
+    /// The box, in CSS pixels.
+    pub width: f32,
+    pub height: f32,
+    pub device_pixel_ratio: f32,
+    /// Since the source was registered.
+    pub elapsed: Duration,
+}
+
+impl PaintInput {
+    /// The box in device pixels, which pixel output should match.
+    pub fn device_size(&self) -> (u32, u32) {
+        (
+            (self.width * self.device_pixel_ratio).round().max(0.0) as u32,
+            (self.height * self.device_pixel_ratio).round().max(0.0) as u32,
+        )
+    }
+}
+
+/// The direction a [`PaintCommand::LinearGradient`] runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientAxis {
+    /// Left to right.
+    Horizontal,
+    /// Top to bottom.
+    Vertical,
+}
+
+/// The space a [`PaintCommand::LinearGradient`] mixes its colors in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorInterpolation {
+    /// Linear light, where halfway between black and white is as bright
+    /// as the two lights averaged, as blending is.
+    #[default]
+    LinearSrgb,
+    /// Oklab, whose steps look even and whose hues do not drift through
+    /// gray between saturated ends.
+    Oklab,
+}
+
+impl ColorInterpolation {
+    /// The color `t` of the way from `from` to `to`, both sRGB as given to
+    /// a command. Alpha mixes as it is.
+    pub fn mix(self, from: [f32; 4], to: [f32; 4], t: f32) -> [f32; 4] {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let [from_rgb, to_rgb] =
+            [from, to].map(|[r, g, b, _]| [r, g, b].map(color_space::srgb_to_linear));
+        let linear = match self {
+            ColorInterpolation::LinearSrgb => [0, 1, 2].map(|c| lerp(from_rgb[c], to_rgb[c])),
+            ColorInterpolation::Oklab => {
+                let from = color_space::linear_srgb_to_oklab(from_rgb);
+                let to = color_space::linear_srgb_to_oklab(to_rgb);
+                color_space::oklab_to_linear_srgb([0, 1, 2].map(|c| lerp(from[c], to[c])))
+            }
+        };
+        let [r, g, b] = color_space::gamut_map(linear).map(color_space::linear_to_srgb);
+        [r, g, b, lerp(from[3], to[3])]
+    }
+}
+
+/// A shape of a display list, in CSS pixels from the box's top left
+/// corner. Colors are sRGB RGBA in 0..=1; what falls outside the box is
+/// cut off.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaintCommand {
+    Rect {
+        bounds: Rect,
+        color: [f32; 4],
+    },
+    LinearGradient {
+        bounds: Rect,
+        from: [f32; 4],
+        to: [f32; 4],
+        axis: GradientAxis,
+        interpolation: ColorInterpolation,
+    },
+}
+
+/// What a paint source painted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaintOutput {
+    /// RGBA8, row-major, stretched over the box; normally
+    /// [`PaintInput::device_size`].
+    Pixels {
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+    },
+    Commands(Vec<PaintCommand>),
+}
+
+impl PaintOutput {
+    /// The output over `target` as solid rects in page coordinates: runs
+    /// of same-colored pixels, commands cut to the box, gradients in one
+    /// CSS pixel bands.
+    pub fn quads(&self, target: &Rect) -> Vec<(Rect, [f32; 4])> {
+        let mut quads = Vec::new();
+        match self {
+            PaintOutput::Pixels {
+                width,
+                height,
+                data,
+            } => {
+                let (width, height) = (*width as usize, *height as usize);
+                if width == 0 || height == 0 || data.len() < width * height * 4 {
+                    return quads;
+                }
+                let pixel_width = target.width / width as f32;
+                let pixel_height = target.height / height as f32;
+                for (y, row) in data.chunks_exact(width * 4).take(height).enumerate() {
+                    let mut x = 0;
+                    while x < width {
+                        let color = &row[x * 4..x * 4 + 4];
+                        let run = row[x * 4..]
+                            .chunks_exact(4)
+                            .take_while(|pixel| *pixel == color)
+                            .count();
+                        if color[3] > 0 {
+                            quads.push((
+                                Rect {
+                                    x: target.x + x as f32 * pixel_width,
+                                    y: target.y + y as f32 * pixel_height,
+                                    width: run as f32 * pixel_width,
+                                    height: pixel_height,
+                                },
+                                [0, 1, 2, 3].map(|channel| color[channel] as f32 / 255.0),
+                            ));
+                        }
+                        x += run;
+                    }
+                }
+            }
+            PaintOutput::Commands(commands) => {
+                for command in commands {
+                    match command {
+                        PaintCommand::Rect { bounds, color } => {
+                            if let Some(bounds) = place(bounds, target) {
+                                quads.push((bounds, *color));
+                            }
+                        }
+                        PaintCommand::LinearGradient {
+                            bounds,
+                            from,
+                            to,
+                            axis,
+                            interpolation,
+                        } => {
+                            let length = match axis {
+                                GradientAxis::Horizontal => bounds.width,
+                                GradientAxis::Vertical => bounds.height,
+                            };
+                            let bands = length.ceil().max(1.0) as usize;
+                            for band in 0..bands {
+                                let start = band as f32;
+                                let end = (start + 1.0).min(length);
+                                let t = if length > 0.0 {
+                                    ((start + end) / 2.0 / length).clamp(0.0, 1.0)
+                                } else {
+                                    0.0
+                                };
+                                let color = interpolation.mix(*from, *to, t);
+                                let band = match axis {
+                                    GradientAxis::Horizontal => Rect {
+                                        x: bounds.x + start,
+                                        width: end - start,
+                                        ..bounds.clone()
+                                    },
+                                    GradientAxis::Vertical => Rect {
+                                        y: bounds.y + start,
+                                        height: end - start,
+                                        ..bounds.clone()
+                                    },
+                                };
+                                if let Some(band) = place(&band, target) {
+                                    quads.push((band, color));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        quads
+    }
+}
+
+/// `bounds`, relative to `target`, in page coordinates and cut to it.
+fn place(bounds: &Rect, target: &Rect) -> Option<Rect> {
+    let x = (target.x + bounds.x).max(target.x);
+    let y = (target.y + bounds.y).max(target.y);
+    let right = (target.x + bounds.x + bounds.width).min(target.x + target.width);
+    let bottom = (target.y + bounds.y + bounds.height).min(target.y + target.height);
+    (right > x && bottom > y).then_some(Rect {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    })
+}
+
+/// Paints backgrounds for the embedder. Called off the render thread, so
+/// it must not block on the engine.
+pub trait PaintSource: Send + Sync {
+    fn paint(&self, input: &PaintInput) -> PaintOutput;
+
+    /// Whether the output changes over time, so every frame showing it
+    /// calls [`Self::paint`] again. Asked once, when registered.
+    fn is_animated(&self) -> bool {
+        false
+    }
+}
+
+struct Registered {
+    source: Arc<dyn PaintSource>,
+    animated: bool,
+    registered_at: Instant,
+}
+
+/// Source name and size in device pixels.
+type OutputKey = (String, u32, u32);
+
+struct CachedOutput {
+    output: Arc<PaintOutput>,
+    /// The frame it was painted for.
+    frame: u64,
+    /// The last frame it was drawn in.
+    used: u64,
+}
+
+#[derive(Default)]
+struct PaintState {
+    sources: HashMap<String, Registered>,
+    outputs: HashMap<OutputKey, CachedOutput>,
+    in_flight: HashSet<OutputKey>,
+    /// Calls the frame stopped waiting for.
+    overdue: HashSet<OutputKey>,
+    /// A frame is wanted: a source was registered, or an overdue call
+    /// finished.
+    frame_requested: bool,
+    /// The last frame drew an animated source.
+    animating: bool,
+}
+
+/// The embedder's paint sources and what they painted.
+pub struct PaintSources {
+    state: Arc<Mutex<PaintState>>,
+    budget: Duration,
+}
+
+impl Default for PaintSources {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAINT_BUDGET)
+    }
+}
+
+impl PaintSources {
+    /// Sources waited on for at most `budget` a call.
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PaintState::default())),
+            budget,
+        }
+    }
+
+    /// Paint `-vk-paint(name)` backgrounds with `source`, replacing the
+    /// one registered under `name` and its outputs.
+    pub fn register(&self, name: &str, source: Arc<dyn PaintSource>) {
+        let mut state = self.state.lock();
+        state.outputs.retain(|(other, _, _), _| other != name);
+        let animated = source.is_animated();
+        state.sources.insert(
+            name.to_string(),
+            Registered {
+                source,
+                animated,
+                registered_at: Instant::now(),
+            },
+        );
+        state.frame_requested = true;
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.state.lock().sources.contains_key(name)
+    }
+
+    pub fn is_animated(&self, name: &str) -> bool {
+        self.state
+            .lock()
+            .sources
+            .get(name)
+            .is_some_and(|registered| registered.animated)
+    }
+
+    /// Record whether the frame just rendered drew an animated source.
+    pub fn set_animating(&self, animating: bool) {
+        self.state.lock().animating = animating;
+    }
+
+    /// Whether a frame should be rendered: one drew an animated source, a
+    /// source was registered, or a slow source finished since.
+    pub fn take_frame_request(&self) -> bool {
+        let mut state = self.state.lock();
+        std::mem::take(&mut state.frame_requested) || state.animating
+    }
+
+    /// What `name` paints for `width` by `height` CSS pixels in `frame`:
+    /// the cached output when it still holds, otherwise a new one if the
+    /// source delivers it within the budget, otherwise the last one for
+    /// that size. `None` for sources not registered, empty boxes, and
+    /// first calls over budget.
+    pub async fn paint(
+        &self,
+        name: &str,
+        width: f32,
+        height: f32,
+        device_pixel_ratio: f32,
+        frame: u64,
+    ) -> Option<Arc<PaintOutput>> {
+        let (key, source, input) = {
+            let mut state = self.state.lock();
+            let registered = state.sources.get(name)?;
+            let input = PaintInput {
+                width,
+                height,
+                device_pixel_ratio,
+                elapsed: registered.registered_at.elapsed(),
+            };
+            let (device_width, device_height) = input.device_size();
+            if device_width == 0 || device_height == 0 {
+                return None;
+            }
+            let key = (name.to_string(), device_width, device_height);
+            let (source, animated) = (registered.source.clone(), registered.animated);
+            let in_flight = state.in_flight.contains(&key);
+            if let Some(cached) = state.outputs.get_mut(&key) {
+                cached.used = frame;
+                if !animated || cached.frame == frame || in_flight {
+                    return Some(cached.output.clone());
+                }
+            } else if in_flight {
+                return None;
+            }
+            state.in_flight.insert(key.clone());
+            (key, source, input)
+        };
+
+        let state = self.state.clone();
+        let finish_key = key.clone();
+        let call = move || {
+            let painted =
+                std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| source.paint(&input)));
+            let mut state = state.lock();
+            state.in_flight.remove(&finish_key);
+            if state.overdue.remove(&finish_key) {
+                state.frame_requested = true;
+            }
+            match painted {
+                Ok(output) => {
+                    let output = Arc::new(output);
+                    // A source replaced meanwhile must not leave its output.
+                    let current = state
+                        .sources
+                        .get(&finish_key.0)
+                        .is_some_and(|registered| Arc::ptr_eq(&registered.source, &source));
+                    if current {
+                        Self::cache(&mut state, finish_key, output.clone(), frame);
+                    }
+                    Some(output)
+                }
+                Err(_) => {
+                    tracing::warn!("Paint source '{}' panicked", finish_key.0);
+                    None
+                }
+            }
+        };
+        // Outside a runtime there is nothing to wait with; the call runs
+        // inline.
+        if tokio::runtime::Handle::try_current().is_err() {
+            return call();
+        }
+        match tokio::time::timeout(self.budget, tokio::task::spawn_blocking(call)).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                tracing::warn!("Paint source '{}' failed: {}", key.0, e);
+                None
+            }
+            Err(_) => {
+                let mut state = self.state.lock();
+                if state.in_flight.contains(&key) {
+                    tracing::debug!("Paint source '{}' is over budget", key.0);
+                    state.overdue.insert(key.clone());
+                }
+                state.outputs.get(&key).map(|cached| cached.output.clone())
+            }
+        }
+    }
+
+    fn cache(state: &mut PaintState, key: OutputKey, output: Arc<PaintOutput>, frame: u64) {
+        if state.outputs.len() >= MAX_CACHED_OUTPUTS && !state.outputs.contains_key(&key) {
+            let stalest = state
+                .outputs
+                .iter()
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(key, _)| key.clone());
+            if let Some(stalest) = stalest {
+                state.outputs.remove(&stalest);
+            }
+        }
+        state.outputs.insert(
+            key,
+            CachedOutput {
+                output,
+                frame,
+                used: frame,
+            },
+        );
+    }
+}
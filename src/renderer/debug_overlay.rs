@@ -0,0 +1,469 @@
+//! Debug overlays: what the renderer draws over everything else, the
+//! inspector's highlight included, to show how a frame was laid out and
+//! painted.
+//!
+//! Each overlay has a flag of [`DebugOverlayFlags`]. With none set the
+//! renderer does no work for them. Box outlines need the box model, which
+//! the paint list does not keep: the engine puts them on the
+//! [`LayoutTree`] only while they are on. Snapshots leave the overlays out
+//! unless [`DebugOverlayFlags::IN_SNAPSHOTS`] is set.
+
+use super::{decoration, ClipChain, DrawQuad, LayoutTree, Rect};
+use crate::core::dom::NodeId;
+use crate::core::layout::LayoutBox;
+use std::collections::VecDeque;
+use std::ops::{BitOr, BitOrAssign};
+use std::time::Instant;
+
+pub const MARGIN_OUTLINE_COLOR: [f32; 4] = [1.0, 0.55, 0.0, 0.9];
+pub const BORDER_OUTLINE_COLOR: [f32; 4] = [1.0, 0.85, 0.0, 0.9];
+pub const PADDING_OUTLINE_COLOR: [f32; 4] = [0.2, 0.75, 0.2, 0.9];
+pub const CONTENT_OUTLINE_COLOR: [f32; 4] = [0.15, 0.45, 1.0, 0.9];
+pub const CLIP_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 0.9];
+pub const SCROLL_CONTAINER_COLOR: [f32; 4] = [0.0, 0.8, 0.8, 0.9];
+pub const FLASH_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 0.4];
+const GRID_COLOR: [f32; 4] = [0.0, 0.6, 1.0, 0.2];
+const BASELINE_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 0.8];
+const HUD_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+const HUD_TEXT_COLOR: &str = "#FFFFFF";
+const HUD_WITHIN_BUDGET: [f32; 4] = [0.2, 0.8, 0.2, 1.0];
+const HUD_OVER_BUDGET: [f32; 4] = [0.9, 0.2, 0.2, 1.0];
+
+/// How wide outlines are drawn.
+pub const OUTLINE_WIDTH: f32 = 1.0;
+/// Frames a repainted region stays tinted, fading, after its repaint.
+pub const FLASH_FRAMES: u32 = 4;
+/// Distance between the lines of the baseline grid.
+pub const GRID_STEP: f32 = 8.0;
+/// The frame time the HUD measures frames against: 60 frames a second.
+pub const FRAME_BUDGET_MS: f32 = 1000.0 / 60.0;
+/// Frames the HUD's frame rate is averaged over.
+const HUD_FRAMES: usize = 60;
+const HUD_MARGIN: f32 = 8.0;
+const HUD_WIDTH: f32 = 176.0;
+const HUD_HEIGHT: f32 = 30.0;
+const HUD_FONT_SIZE: f32 = 12.0;
+const HUD_BAR_HEIGHT: f32 = 4.0;
+
+/// A set of debug overlays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DebugOverlayFlags(u32);
+
+impl DebugOverlayFlags {
+    /// The margin, border, padding and content box of every box, or of
+    /// those under [`DebugOverlaySettings::outline_root`].
+    pub const BOX_OUTLINES: Self = Self(1 << 0);
+    /// The region each frame repainted, tinted and fading out.
+    pub const REPAINT_FLASHING: Self = Self(1 << 1);
+    /// Every clip rectangle, and the box of each element clipping its
+    /// overflow.
+    pub const CLIP_REGIONS: Self = Self(1 << 2);
+    /// A grid of horizontal lines and the baseline of each text fragment.
+    pub const BASELINE_GRID: Self = Self(1 << 3);
+    /// The frame rate and the last frame's share of its budget, in the
+    /// top left corner.
+    pub const FRAME_HUD: Self = Self(1 << 4);
+    /// Snapshots show the other overlays too.
+    pub const IN_SNAPSHOTS: Self = Self(1 << 5);
+
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self((1 << 6) - 1);
+
+    const NAMES: [(&'static str, Self); 6] = [
+        ("box-outlines", Self::BOX_OUTLINES),
+        ("repaint-flashing", Self::REPAINT_FLASHING),
+        ("clip-regions", Self::CLIP_REGIONS),
+        ("baseline-grid", Self::BASELINE_GRID),
+        ("frame-hud", Self::FRAME_HUD),
+        ("in-snapshots", Self::IN_SNAPSHOTS),
+    ];
+
+    /// The flags a comma-separated list of names sets, as
+    /// `box-outlines,frame-hud`; `all` and `none` are understood too.
+    pub fn parse(list: &str) -> Result<Self, String> {
+        Self::from_names(
+            list.split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty()),
+        )
+    }
+
+    /// The flags `names` set, each one of [`Self::names`] or `all`/`none`.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut flags = Self::NONE;
+        for name in names {
+            flags |= match name {
+                "all" => Self::ALL,
+                "none" => Self::NONE,
+                _ => Self::NAMES
+                    .iter()
+                    .find(|(known, _)| known.eq_ignore_ascii_case(name))
+                    .map(|(_, flag)| *flag)
+                    .ok_or_else(|| format!("Unknown debug overlay '{}'", name))?,
+            };
+        }
+        Ok(flags)
+    }
+
+    /// The names of the flags set.
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(_, flag)| self.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for DebugOverlayFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for DebugOverlayFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The debug overlays that are on, and the subtree box outlines are
+/// limited to; `None` outlines every box.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DebugOverlaySettings {
+    pub flags: DebugOverlayFlags,
+    pub outline_root: Option<NodeId>,
+}
+
+/// The four areas of one box, outermost first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxOutline {
+    pub margin: Rect,
+    pub border: Rect,
+    pub padding: Rect,
+    pub content: Rect,
+}
+
+impl BoxOutline {
+    pub fn from_layout_box(layout_box: &LayoutBox) -> Self {
+        Self {
+            margin: Rect {
+                x: layout_box.margin_box_x(),
+                y: layout_box.margin_box_y(),
+                width: layout_box.margin_box_width(),
+                height: layout_box.margin_box_height(),
+            },
+            border: Rect {
+                x: layout_box.border_box_x(),
+                y: layout_box.border_box_y(),
+                width: layout_box.border_box_width(),
+                height: layout_box.border_box_height(),
+            },
+            padding: Rect {
+                x: layout_box.padding_box_x(),
+                y: layout_box.padding_box_y(),
+                width: layout_box.padding_box_width(),
+                height: layout_box.padding_box_height(),
+            },
+            content: Rect {
+                x: layout_box.content_x,
+                y: layout_box.content_y,
+                width: layout_box.content_width,
+                height: layout_box.content_height,
+            },
+        }
+    }
+
+    /// Four edges for each of the areas that has any size, each area in
+    /// its own color.
+    pub fn quads(&self) -> Vec<DrawQuad> {
+        [
+            (&self.margin, MARGIN_OUTLINE_COLOR),
+            (&self.border, BORDER_OUTLINE_COLOR),
+            (&self.padding, PADDING_OUTLINE_COLOR),
+            (&self.content, CONTENT_OUTLINE_COLOR),
+        ]
+        .into_iter()
+        .flat_map(|(rect, color)| outline_quads(rect, color))
+        .collect()
+    }
+}
+
+/// Text the renderer draws for the HUD with the text pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HudLabel {
+    pub text: String,
+    pub bounds: Rect,
+    pub color: String,
+    pub font_size: f32,
+}
+
+/// The debug overlays of one renderer: which are on, what repaint
+/// flashing still shows and the frame times the HUD reports.
+#[derive(Debug, Default)]
+pub struct DebugOverlay {
+    flags: DebugOverlayFlags,
+    /// Repainted regions still tinted, with the frames since.
+    flashes: Vec<(Rect, u32)>,
+    /// When the last frames were drawn, oldest first.
+    frame_starts: VecDeque<Instant>,
+    last_frame_ms: f32,
+    quads: Vec<DrawQuad>,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn flags(&self) -> DebugOverlayFlags {
+        self.flags
+    }
+
+    /// Switch overlays on and off; those switched off forget what they
+    /// showed.
+    pub fn set_flags(&mut self, flags: DebugOverlayFlags) {
+        self.flags = flags;
+        if !flags.contains(DebugOverlayFlags::REPAINT_FLASHING) {
+            self.flashes.clear();
+        }
+        if !flags.contains(DebugOverlayFlags::FRAME_HUD) {
+            self.frame_starts.clear();
+            self.last_frame_ms = 0.0;
+        }
+        if flags.is_empty() {
+            self.quads.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.flags.is_empty()
+    }
+
+    /// Whether snapshots show the overlays.
+    pub fn in_snapshots(&self) -> bool {
+        self.flags.contains(DebugOverlayFlags::IN_SNAPSHOTS)
+    }
+
+    /// What the overlays draw over the frame of `tree` that started at
+    /// `started` and repainted `damage`, in paint order. The HUD's text is
+    /// returned for the renderer to draw; its glyphs go in with
+    /// [`Self::add_quads`].
+    pub fn update(
+        &mut self,
+        tree: &LayoutTree,
+        damage: Option<&Rect>,
+        viewport: (f32, f32),
+        started: Instant,
+    ) -> Option<HudLabel> {
+        let flags = self.flags;
+        self.quads.clear();
+        if flags.contains(DebugOverlayFlags::BASELINE_GRID) {
+            self.quads.extend(baseline_grid(tree, viewport));
+        }
+        if flags.contains(DebugOverlayFlags::BOX_OUTLINES) {
+            self.quads
+                .extend(tree.box_outlines().iter().flat_map(BoxOutline::quads));
+        }
+        if flags.contains(DebugOverlayFlags::CLIP_REGIONS) {
+            self.quads.extend(clip_regions(tree));
+        }
+        if flags.contains(DebugOverlayFlags::REPAINT_FLASHING) {
+            for (_, age) in &mut self.flashes {
+                *age += 1;
+            }
+            self.flashes.retain(|(_, age)| *age < FLASH_FRAMES);
+            if let Some(damage) = damage {
+                self.flashes.push((damage.clone(), 0));
+            }
+            self.quads.extend(self.flashes.iter().map(|(rect, age)| {
+                let fade = (FLASH_FRAMES - age) as f32 / FLASH_FRAMES as f32;
+                let [r, g, b, a] = FLASH_COLOR;
+                solid(rect.clone(), [r, g, b, a * fade])
+            }));
+        }
+        if flags.contains(DebugOverlayFlags::FRAME_HUD) {
+            return Some(self.hud(started));
+        }
+        None
+    }
+
+    /// The HUD's panel and budget bar, and its label: the frame rate over
+    /// the last frames and the last frame's time against the budget.
+    fn hud(&mut self, started: Instant) -> HudLabel {
+        self.frame_starts.push_back(started);
+        while self.frame_starts.len() > HUD_FRAMES {
+            self.frame_starts.pop_front();
+        }
+        let fps = match (self.frame_starts.front(), self.frame_starts.back()) {
+            (Some(first), Some(last)) if self.frame_starts.len() > 1 => {
+                let span = last.duration_since(*first).as_secs_f32();
+                if span > 0.0 {
+                    (self.frame_starts.len() - 1) as f32 / span
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+        let panel = Rect {
+            x: HUD_MARGIN,
+            y: HUD_MARGIN,
+            width: HUD_WIDTH,
+            height: HUD_HEIGHT,
+        };
+        let share = self.last_frame_ms / FRAME_BUDGET_MS;
+        let bar_color = if share > 1.0 {
+            HUD_OVER_BUDGET
+        } else {
+            HUD_WITHIN_BUDGET
+        };
+        self.quads.push(solid(panel.clone(), HUD_BACKGROUND));
+        self.quads.push(solid(
+            Rect {
+                x: panel.x,
+                y: panel.y + panel.height - HUD_BAR_HEIGHT,
+                width: panel.width * share.min(1.0),
+                height: HUD_BAR_HEIGHT,
+            },
+            bar_color,
+        ));
+        HudLabel {
+            text: format!(
+                "{:.0} fps {:.1}/{:.1} ms",
+                fps, self.last_frame_ms, FRAME_BUDGET_MS
+            ),
+            bounds: Rect {
+                x: panel.x + 6.0,
+                y: panel.y + 4.0,
+                width: panel.width - 12.0,
+                height: HUD_FONT_SIZE * 1.2,
+            },
+            color: HUD_TEXT_COLOR.to_string(),
+            font_size: HUD_FONT_SIZE,
+        }
+    }
+
+    /// Draw `quads` over the rest, as the HUD's glyphs.
+    pub fn add_quads(&mut self, quads: impl IntoIterator<Item = DrawQuad>) {
+        self.quads.extend(quads);
+    }
+
+    /// The frame just drawn took `frame_ms`; the HUD shows it next frame.
+    pub fn frame_done(&mut self, frame_ms: f32) {
+        if self.flags.contains(DebugOverlayFlags::FRAME_HUD) {
+            self.last_frame_ms = frame_ms;
+        }
+    }
+
+    /// What the overlays drew over the last frame, in paint order.
+    pub fn quads(&self) -> &[DrawQuad] {
+        &self.quads
+    }
+}
+
+/// Lines every [`GRID_STEP`] down the viewport, and each text fragment's
+/// baseline over it.
+fn baseline_grid(tree: &LayoutTree, (width, height): (f32, f32)) -> Vec<DrawQuad> {
+    let rows = (height / GRID_STEP).ceil() as usize;
+    let grid = (0..rows).map(|row| {
+        solid(
+            Rect {
+                x: 0.0,
+                y: row as f32 * GRID_STEP,
+                width,
+                height: OUTLINE_WIDTH,
+            },
+            GRID_COLOR,
+        )
+    });
+    let baselines = tree.get_text_nodes().iter().map(|node| {
+        solid(
+            Rect {
+                x: node.bounds.x,
+                y: decoration::baseline(&node.bounds, &node.style.font_metrics),
+                width: node.bounds.width,
+                height: OUTLINE_WIDTH,
+            },
+            BASELINE_COLOR,
+        )
+    });
+    grid.chain(baselines).collect()
+}
+
+/// Each distinct clip rectangle nodes are drawn with, then the box of
+/// each element clipping its overflow.
+fn clip_regions(tree: &LayoutTree) -> Vec<DrawQuad> {
+    let nodes = || tree.get_render_nodes().iter().chain(tree.get_text_nodes());
+    let mut clips: Vec<&Rect> = Vec::new();
+    for scissor in nodes().filter_map(|node| node.clip.scissor()) {
+        if !clips.contains(&scissor) {
+            clips.push(scissor);
+        }
+    }
+    let containers = nodes()
+        .filter(|node| node.style.clips_overflow)
+        .flat_map(|node| outline_quads(&node.bounds, SCROLL_CONTAINER_COLOR));
+    clips
+        .into_iter()
+        .flat_map(|rect| outline_quads(rect, CLIP_COLOR))
+        .chain(containers)
+        .collect()
+}
+
+/// The four edges of `rect`, inside it; none for an empty one.
+fn outline_quads(rect: &Rect, color: [f32; 4]) -> Vec<DrawQuad> {
+    if rect.width <= 0.0 || rect.height <= 0.0 {
+        return Vec::new();
+    }
+    let thickness_x = OUTLINE_WIDTH.min(rect.width);
+    let thickness_y = OUTLINE_WIDTH.min(rect.height);
+    [
+        Rect {
+            height: thickness_y,
+            ..rect.clone()
+        },
+        Rect {
+            y: rect.y + rect.height - thickness_y,
+            height: thickness_y,
+            ..rect.clone()
+        },
+        Rect {
+            width: thickness_x,
+            ..rect.clone()
+        },
+        Rect {
+            x: rect.x + rect.width - thickness_x,
+            width: thickness_x,
+            ..rect.clone()
+        },
+    ]
+    .into_iter()
+    .map(|bounds| solid(bounds, color))
+    .collect()
+}
+
+fn solid(bounds: Rect, color: [f32; 4]) -> DrawQuad {
+    DrawQuad {
+        bounds,
+        color,
+        clip: ClipChain::new(),
+        blur_radius: 0.0,
+        mask: None,
+        image: None,
+    }
+}
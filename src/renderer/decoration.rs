@@ -0,0 +1,224 @@
+//! `text-decoration` and `text-shadow` for a text fragment.
+//!
+//! Geometry only: [`decoration_strips`] places underline, overline and
+//! line-through strips from the font's [`FontMetrics`], and a [`TextShadow`]
+//! describes a copy of the glyphs and their decorations painted offset (and
+//! blurred) behind them. Per fragment the renderer paints shadows first,
+//! then underlines and overlines, the glyphs, and line-throughs last.
+//! Neither affects layout. Skip-ink is not implemented.
+
+use super::Rect;
+use crate::core::fonts::FontMetrics;
+
+/// The lines of `text-decoration-line`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecorationLines {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+}
+
+impl DecorationLines {
+    pub fn is_empty(&self) -> bool {
+        !(self.underline || self.overline || self.line_through)
+    }
+}
+
+/// `text-decoration-style`. `wavy` is drawn solid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecorationStyle {
+    #[default]
+    Solid,
+    Double,
+    Dotted,
+    Dashed,
+    Wavy,
+}
+
+/// The computed `text-decoration` of a fragment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextDecoration {
+    pub lines: DecorationLines,
+    pub style: DecorationStyle,
+    /// `None` draws in the text color.
+    pub color: Option<String>,
+    /// `None` (`auto`, `from-font`) takes the thickness from the font.
+    pub thickness: Option<f32>,
+}
+
+impl TextDecoration {
+    /// A solid underline in the text color.
+    pub fn underline() -> Self {
+        Self {
+            lines: DecorationLines {
+                underline: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Apply one keyword of `text-decoration`, `text-decoration-line` or
+    /// `text-decoration-style`. `false` when the keyword is none of those,
+    /// which in the shorthand makes it the color.
+    pub fn apply_keyword(&mut self, keyword: &str) -> bool {
+        match keyword.to_ascii_lowercase().as_str() {
+            "none" => self.lines = DecorationLines::default(),
+            "underline" => self.lines.underline = true,
+            "overline" => self.lines.overline = true,
+            "line-through" => self.lines.line_through = true,
+            "solid" => self.style = DecorationStyle::Solid,
+            "double" => self.style = DecorationStyle::Double,
+            "dotted" => self.style = DecorationStyle::Dotted,
+            "dashed" => self.style = DecorationStyle::Dashed,
+            "wavy" => self.style = DecorationStyle::Wavy,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecorationKind {
+    Underline,
+    Overline,
+    LineThrough,
+}
+
+impl DecorationKind {
+    /// Painted after the glyphs rather than before.
+    pub fn paints_over_text(&self) -> bool {
+        matches!(self, DecorationKind::LineThrough)
+    }
+}
+
+/// One filled piece of a decoration line; dashed and dotted lines have
+/// several per kind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecorationStrip {
+    pub kind: DecorationKind,
+    pub rect: Rect,
+}
+
+/// The baseline of a single-line fragment: the font's ascent plus descent
+/// is centered in the fragment's height, as in its line box.
+pub fn baseline(bounds: &Rect, metrics: &FontMetrics) -> f32 {
+    bounds.y + (bounds.height - (metrics.ascent + metrics.descent)) / 2.0 + metrics.ascent
+}
+
+/// The strips `decoration` paints for a fragment at `bounds`.
+pub fn decoration_strips(
+    bounds: &Rect,
+    metrics: &FontMetrics,
+    decoration: &TextDecoration,
+) -> Vec<DecorationStrip> {
+    let mut strips = Vec::new();
+    if decoration.is_none() || bounds.width <= 0.0 {
+        return strips;
+    }
+    let baseline = baseline(bounds, metrics);
+
+    let mut lines = Vec::with_capacity(3);
+    if decoration.lines.underline {
+        let thickness = decoration.thickness.unwrap_or(metrics.underline_thickness);
+        lines.push((
+            DecorationKind::Underline,
+            baseline + metrics.underline_offset,
+            thickness,
+        ));
+    }
+    if decoration.lines.overline {
+        let thickness = decoration.thickness.unwrap_or(metrics.underline_thickness);
+        lines.push((
+            DecorationKind::Overline,
+            baseline - metrics.ascent,
+            thickness,
+        ));
+    }
+    if decoration.lines.line_through {
+        let thickness = decoration.thickness.unwrap_or(metrics.strikeout_thickness);
+        lines.push((
+            DecorationKind::LineThrough,
+            baseline - metrics.strikeout_offset,
+            thickness,
+        ));
+    }
+
+    for (kind, top, thickness) in lines {
+        let thickness = thickness.max(1.0);
+        let strip = |x: f32, y: f32, width: f32| DecorationStrip {
+            kind,
+            rect: Rect {
+                x,
+                y,
+                width: width.min(bounds.x + bounds.width - x),
+                height: thickness,
+            },
+        };
+        match decoration.style {
+            DecorationStyle::Solid | DecorationStyle::Wavy => {
+                strips.push(strip(bounds.x, top, bounds.width));
+            }
+            DecorationStyle::Double => {
+                // The second line goes away from the text.
+                let gap = if kind == DecorationKind::Overline {
+                    -2.0 * thickness
+                } else {
+                    2.0 * thickness
+                };
+                strips.push(strip(bounds.x, top, bounds.width));
+                strips.push(strip(bounds.x, top + gap, bounds.width));
+            }
+            DecorationStyle::Dotted | DecorationStyle::Dashed => {
+                let dash = if decoration.style == DecorationStyle::Dotted {
+                    thickness
+                } else {
+                    thickness * 3.0
+                };
+                let mut x = bounds.x;
+                while x < bounds.x + bounds.width {
+                    strips.push(strip(x, top, dash));
+                    x += dash * 2.0;
+                }
+            }
+        }
+    }
+    strips
+}
+
+/// One `text-shadow` layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextShadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    /// CSS blur radius; the gaussian's standard deviation is half of it.
+    pub blur_radius: f32,
+    /// `None` draws in the text color.
+    pub color: Option<String>,
+}
+
+impl TextShadow {
+    /// A layer from the lengths and color split out of its CSS value.
+    /// `None` unless there are two or three lengths.
+    pub fn from_parts(lengths: &[f32], color: Option<String>) -> Option<Self> {
+        match *lengths {
+            [offset_x, offset_y] => Some(Self {
+                offset_x,
+                offset_y,
+                blur_radius: 0.0,
+                color,
+            }),
+            [offset_x, offset_y, blur_radius] if blur_radius >= 0.0 => Some(Self {
+                offset_x,
+                offset_y,
+                blur_radius,
+                color,
+            }),
+            _ => None,
+        }
+    }
+}
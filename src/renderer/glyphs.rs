@@ -0,0 +1,351 @@
+//! Glyphs of text drawn with the installed fonts, and the atlas the GPU
+//! samples them from.
+//!
+//! [`GlyphCache`] picks a face by the text's `font-family` list, falling
+//! back to a default face, lays the text out on its baseline and rasterizes
+//! each glyph once. Without any installed face text gets ink boxes: a solid
+//! cell stretched over each character's share of the fragment.
+//!
+//! [`GlyphAtlas`] packs the coverage into one texture. A full atlas doubles
+//! in height, which keeps its cells where they are but changes every
+//! texture coordinate; at its largest it is emptied instead. Either way its
+//! generation changes, and whatever was drawn from it is drawn again.
+
+use super::{decoration, Rect, Style};
+use crate::core::fonts::{self, FontMetrics, FontRequest};
+use rusttype::{point, Font, GlyphId, Scale};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Width of the atlas; it grows in height only.
+const ATLAS_WIDTH: u32 = 1024;
+const ATLAS_MIN_HEIGHT: u32 = 256;
+const ATLAS_MAX_HEIGHT: u32 = 8192;
+
+/// Empty pixels right of and below each cell, so that filtering does not
+/// pick up the neighbors.
+const CELL_PADDING: u32 = 1;
+
+/// A glyph's coverage, one byte per pixel, row-major.
+#[derive(Debug, PartialEq)]
+pub struct GlyphMask {
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<u8>,
+}
+
+impl GlyphMask {
+    /// Full coverage, stretched over an ink box.
+    fn solid() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            coverage: vec![u8::MAX],
+        }
+    }
+
+    /// The `source` rect of an atlas `width` pixels wide.
+    pub fn from_atlas(pixels: &[u8], width: u32, source: &Rect) -> Self {
+        let height = pixels.len() as u32 / width.max(1);
+        let x0 = (source.x.max(0.0) as u32).min(width);
+        let y0 = (source.y.max(0.0) as u32).min(height);
+        let x1 = ((source.x + source.width).max(0.0) as u32).clamp(x0, width);
+        let y1 = ((source.y + source.height).max(0.0) as u32).clamp(y0, height);
+        let mut coverage = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+        for y in y0..y1 {
+            let row = (y * width) as usize;
+            coverage.extend_from_slice(&pixels[row + x0 as usize..row + x1 as usize]);
+        }
+        Self {
+            width: x1 - x0,
+            height: y1 - y0,
+            coverage,
+        }
+    }
+
+    /// Coverage from 0 to 1 at `(u, v)`, with the mask stretched over the
+    /// unit square; nothing outside it.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let inside = (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v);
+        if !inside || self.width == 0 || self.height == 0 {
+            return 0.0;
+        }
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        self.coverage[(y * self.width + x) as usize] as f32 / 255.0
+    }
+}
+
+/// What a glyph's coverage is cached under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GlyphKey {
+    /// Glyph `glyph` of the installed face `face`, `size` pixels per em as
+    /// its bits.
+    Outline {
+        face: fontdb::ID,
+        glyph: u16,
+        size: u32,
+    },
+    /// The cell ink boxes stretch.
+    Solid,
+}
+
+/// A glyph laid out for drawing, its mask stretched over `bounds`.
+#[derive(Debug, Clone)]
+pub struct PlacedGlyph {
+    pub key: GlyphKey,
+    pub bounds: Rect,
+    pub mask: Arc<GlyphMask>,
+}
+
+/// A rasterized glyph, and where its mask sits from the pen on the
+/// baseline.
+#[derive(Clone)]
+struct Rasterized {
+    offset: (i32, i32),
+    mask: Arc<GlyphMask>,
+}
+
+#[derive(Default)]
+pub struct GlyphCache {
+    /// The face picked for a `font-family` list, weight and style.
+    families: HashMap<(String, u32, u8), Option<fontdb::ID>>,
+    faces: HashMap<fontdb::ID, Option<Font<'static>>>,
+    /// `None` for glyphs with nothing to draw, such as spaces.
+    glyphs: HashMap<GlyphKey, Option<Rasterized>>,
+}
+
+impl GlyphCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The glyphs of a text fragment at `bounds` styled `style`, laid out
+    /// from its left edge on its baseline.
+    pub fn place(&mut self, text: &str, bounds: &Rect, style: &Style) -> Vec<PlacedGlyph> {
+        let families = style.font_family.as_deref().unwrap_or("sans-serif");
+        let Some((face, font)) = self.face(families, &style.font) else {
+            let solid = Arc::new(GlyphMask::solid());
+            return ink_boxes(text, bounds, &style.font_metrics)
+                .into_iter()
+                .map(|bounds| PlacedGlyph {
+                    key: GlyphKey::Solid,
+                    bounds,
+                    mask: solid.clone(),
+                })
+                .collect();
+        };
+
+        let scale = Scale::uniform(style.font_size);
+        let baseline = decoration::baseline(bounds, &style.font_metrics);
+        let mut placed = Vec::new();
+        for glyph in font.layout(text, scale, point(bounds.x, baseline)) {
+            let key = GlyphKey::Outline {
+                face,
+                glyph: glyph.id().0,
+                size: style.font_size.to_bits(),
+            };
+            let Some(rasterized) = self.rasterize(&font, key, scale) else {
+                continue;
+            };
+            let pen = glyph.position();
+            let mask = rasterized.mask;
+            placed.push(PlacedGlyph {
+                key,
+                bounds: Rect {
+                    x: pen.x.round() + rasterized.offset.0 as f32,
+                    y: pen.y.round() + rasterized.offset.1 as f32,
+                    width: mask.width as f32,
+                    height: mask.height as f32,
+                },
+                mask,
+            });
+        }
+        placed
+    }
+
+    fn face(&mut self, families: &str, font: &FontRequest) -> Option<(fontdb::ID, Font<'static>)> {
+        let request = (
+            families.to_string(),
+            font.weight.to_bits(),
+            font.style as u8,
+        );
+        let face = (*self
+            .families
+            .entry(request)
+            .or_insert_with(|| fonts::system_face(families, font)))?;
+        let loaded = self.faces.entry(face).or_insert_with(|| {
+            let loaded = fonts::system_face_data(face)?;
+            Font::try_from_vec_and_index(loaded.data, loaded.index)
+        });
+        loaded.clone().map(|font| (face, font))
+    }
+
+    fn rasterize(
+        &mut self,
+        font: &Font<'static>,
+        key: GlyphKey,
+        scale: Scale,
+    ) -> Option<Rasterized> {
+        if let Some(rasterized) = self.glyphs.get(&key) {
+            return rasterized.clone();
+        }
+        let GlyphKey::Outline { glyph, .. } = key else {
+            return None;
+        };
+        let glyph = font
+            .glyph(GlyphId(glyph))
+            .scaled(scale)
+            .positioned(point(0.0, 0.0));
+        let rasterized = glyph.pixel_bounding_box().and_then(|bounds| {
+            let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+            if width == 0 || height == 0 {
+                return None;
+            }
+            let mut coverage = vec![0; (width * height) as usize];
+            glyph.draw(|x, y, v| {
+                coverage[(y * width + x) as usize] = (v * 255.0).round() as u8;
+            });
+            Some(Rasterized {
+                offset: (bounds.min.x, bounds.min.y),
+                mask: Arc::new(GlyphMask {
+                    width,
+                    height,
+                    coverage,
+                }),
+            })
+        });
+        self.glyphs.insert(key, rasterized.clone());
+        rasterized
+    }
+}
+
+/// Ink boxes standing in for glyphs: each non-blank character's share of
+/// the fragment width, inset a little, from cap height to the baseline.
+pub fn ink_boxes(text: &str, bounds: &Rect, metrics: &FontMetrics) -> Vec<Rect> {
+    let count = text.chars().count();
+    if count == 0 {
+        return Vec::new();
+    }
+    let advance = bounds.width / count as f32;
+    let baseline = decoration::baseline(bounds, metrics);
+    let cap_height = metrics.ascent * 0.875;
+    text.chars()
+        .enumerate()
+        .filter(|(_, c)| !c.is_whitespace())
+        .map(|(index, _)| Rect {
+            x: bounds.x + advance * (index as f32 + 0.1),
+            y: baseline - cap_height,
+            width: advance * 0.8,
+            height: cap_height,
+        })
+        .collect()
+}
+
+/// Glyph coverage packed into one texture, in rows, each cell under the
+/// key it was packed for.
+pub struct GlyphAtlas<K = GlyphKey> {
+    height: u32,
+    pixels: Vec<u8>,
+    cells: HashMap<K, Rect>,
+    /// Where the next cell goes, and the height of its row.
+    cursor: (u32, u32, u32),
+    generation: u64,
+}
+
+impl GlyphAtlas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Where `glyph`'s coverage is, in pixels, packed if it is new. `None`
+    /// for a glyph larger than the atlas can be.
+    pub fn cell(&mut self, glyph: &PlacedGlyph) -> Option<Rect> {
+        self.pack(glyph.key, &glyph.mask)
+    }
+}
+
+impl<K: Eq + Hash> GlyphAtlas<K> {
+    /// Where the coverage packed under `key` is, in pixels; `mask` is
+    /// packed there if nothing is yet. `None` for a mask larger than the
+    /// atlas can be.
+    pub fn pack(&mut self, key: K, mask: &GlyphMask) -> Option<Rect> {
+        if let Some(cell) = self.cells.get(&key) {
+            return Some(cell.clone());
+        }
+        let (x, y) = self.allocate(mask.width, mask.height)?;
+        for (row, coverage) in mask.coverage.chunks(mask.width as usize).enumerate() {
+            let start = ((y + row as u32) * ATLAS_WIDTH + x) as usize;
+            self.pixels[start..start + coverage.len()].copy_from_slice(coverage);
+        }
+        let cell = Rect {
+            x: x as f32,
+            y: y as f32,
+            width: mask.width as f32,
+            height: mask.height as f32,
+        };
+        self.cells.insert(key, cell.clone());
+        Some(cell)
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (width, height) = (width + CELL_PADDING, height + CELL_PADDING);
+        if width > ATLAS_WIDTH || height > ATLAS_MAX_HEIGHT {
+            return None;
+        }
+        let (mut x, mut y, mut row) = self.cursor;
+        if x + width > ATLAS_WIDTH {
+            (x, y, row) = (0, y + row, 0);
+        }
+        if y + height > self.height {
+            self.generation += 1;
+            if y + height <= ATLAS_MAX_HEIGHT {
+                while y + height > self.height {
+                    self.height *= 2;
+                }
+                self.pixels.resize((ATLAS_WIDTH * self.height) as usize, 0);
+            } else {
+                self.cells.clear();
+                self.pixels.fill(0);
+                (x, y, row) = (0, 0, 0);
+            }
+        }
+        self.cursor = (x + width, y, row.max(height));
+        Some((x, y))
+    }
+
+    /// Width and height in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (ATLAS_WIDTH, self.height)
+    }
+
+    /// One coverage byte per pixel, row-major.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Changes whenever cells handed out before no longer have the texture
+    /// coordinates they had: the atlas grew, or was emptied.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// How many cells are packed; with the generation, this tells whether
+    /// the pixels changed.
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+impl<K> Default for GlyphAtlas<K> {
+    fn default() -> Self {
+        Self {
+            height: ATLAS_MIN_HEIGHT,
+            pixels: vec![0; (ATLAS_WIDTH * ATLAS_MIN_HEIGHT) as usize],
+            cells: HashMap::new(),
+            cursor: (0, 0, 0),
+            generation: 0,
+        }
+    }
+}
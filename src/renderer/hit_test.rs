@@ -0,0 +1,122 @@
+//! Which nodes are drawn at a point.
+//!
+//! There is one hit test, and it reads the paint list: pointer dispatch,
+//! `BrowserEngine::element_at`, WebDriver and `document.elementFromPoint`
+//! all agree with what was drawn because they ask what was drawn. Later
+//! nodes paint over earlier ones and text over boxes, so the topmost hit
+//! is the last one painted; a point a clip cuts away, rounded corners
+//! included, misses what it clips. Nodes with `pointer-events: none` are
+//! left out of the regions, so hits fall through to what is under them.
+//!
+//! Script cannot wait for a frame, so the engine keeps the regions of the
+//! last paint list in a [`HitRegions`] shared with the JS side, and page
+//! script hit tests the page as last laid out.
+
+use super::{ClipChain, LayoutNode, LayoutTree, Rect};
+use crate::core::dom::document::NodeType;
+use crate::core::dom::{Document, NodeId};
+use parking_lot::RwLock;
+
+/// Whether `(x, y)` falls in `bounds` and survives `clip`.
+pub(crate) fn hits(bounds: &Rect, clip: &ClipChain, x: f32, y: f32) -> bool {
+    x >= bounds.x
+        && x < bounds.x + bounds.width
+        && y >= bounds.y
+        && y < bounds.y + bounds.height
+        && clip.contains(x, y)
+}
+
+/// Where a node was drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HitRegion {
+    pub node_id: NodeId,
+    pub bounds: Rect,
+    pub clip: ClipChain,
+}
+
+impl HitRegion {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        hits(&self.bounds, &self.clip, x, y)
+    }
+}
+
+impl From<&LayoutNode> for HitRegion {
+    fn from(node: &LayoutNode) -> Self {
+        Self {
+            node_id: node.node_id,
+            bounds: node.bounds.clone(),
+            clip: node.clip.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct HitState {
+    /// Topmost first.
+    regions: Vec<HitRegion>,
+    viewport: (f32, f32),
+}
+
+/// The hit regions of the last paint list.
+#[derive(Default)]
+pub struct HitRegions {
+    state: RwLock<HitState>,
+}
+
+impl HitRegions {
+    /// Take the regions of `tree`, painted into a `viewport`-sized
+    /// viewport, leaving out the nodes `targetable` turns down.
+    pub fn update(
+        &self,
+        tree: &LayoutTree,
+        viewport: (f32, f32),
+        targetable: impl Fn(NodeId) -> bool,
+    ) {
+        let regions = tree
+            .hit_order()
+            .filter(|node| targetable(node.node_id))
+            .map(HitRegion::from)
+            .collect();
+        *self.state.write() = HitState { regions, viewport };
+    }
+
+    /// The nodes drawn at viewport point `(x, y)`, topmost first. A text
+    /// node broken into lines is there once per line hit. Nothing is
+    /// outside the viewport.
+    pub fn nodes_at(&self, x: f32, y: f32) -> Vec<NodeId> {
+        let state = self.state.read();
+        let (width, height) = state.viewport;
+        if !(0.0..width).contains(&x) || !(0.0..height).contains(&y) {
+            return Vec::new();
+        }
+        state
+            .regions
+            .iter()
+            .filter(|region| region.contains(x, y))
+            .map(|region| region.node_id)
+            .collect()
+    }
+
+    /// The elements at `(x, y)`, topmost first, as `elementsFromPoint`
+    /// lists them: text stands for the element holding it, and each
+    /// element is listed once, where it is topmost.
+    pub fn elements_at(&self, document: &Document, x: f32, y: f32) -> Vec<NodeId> {
+        let mut elements: Vec<NodeId> = Vec::new();
+        for node_id in self.nodes_at(x, y) {
+            let mut current = Some(node_id);
+            while let Some(node_id) = current {
+                let is_element = document
+                    .get_node(node_id)
+                    .is_some_and(|node| node.read().node_type == NodeType::Element);
+                if is_element {
+                    break;
+                }
+                current = document.get_parent(node_id);
+            }
+            if let Some(element) = current.filter(|element| !elements.contains(element)) {
+                elements.push(element);
+            }
+        }
+        elements
+    }
+}
@@ -0,0 +1,379 @@
+//! Animated GIF and APNG playback.
+//!
+//! An image with more than one frame is decoded whole, each frame
+//! composited onto the full canvas with the previous frame's disposal
+//! applied. Delays of 10ms or less play at 100ms, as in other browsers:
+//! GIFs of the era that wrote 0 meant "as fast as the browser goes". A GIF
+//! without a `NETSCAPE2.0` loop extension plays once, one with a loop
+//! count of n plays n + 1 times and with 0 forever; an APNG plays its
+//! `num_plays` times, 0 being forever.
+//!
+//! [`ImageAnimations`] holds the animations of the current document by
+//! URL, so elements showing the same image show the same frame. Playback
+//! advances on the engine's frame tick and only for animations an element
+//! shows in the viewport; time spent out of view does not count. Decoded
+//! frames of every animation share one byte budget: past it, the frames of
+//! animations out of view are dropped, and decoded again from the fetched
+//! bytes when one comes back into view. With reduced motion every
+//! animation stays on its first frame.
+
+use super::{DecodedImage, ImageError};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, Frame, ImageFormat};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Frame delays at or below this play at [`CLAMPED_FRAME_DELAY`].
+pub const MIN_FRAME_DELAY: Duration = Duration::from_millis(10);
+
+pub const CLAMPED_FRAME_DELAY: Duration = Duration::from_millis(100);
+
+/// Bytes of decoded frames the animations of a document keep by default.
+pub const DEFAULT_ANIMATION_BUDGET_BYTES: usize = 64 * 1024 * 1024;
+
+/// How many times an animation plays through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Forever,
+    Times(u32),
+}
+
+/// Every frame of an animated image.
+#[derive(Debug, Clone)]
+pub struct AnimatedImage {
+    /// Each frame as it is shown, on the whole canvas.
+    pub frames: Vec<Arc<DecodedImage>>,
+    /// How long each frame is shown, clamped.
+    pub delays: Vec<Duration>,
+    pub repeat: Repeat,
+}
+
+impl AnimatedImage {
+    /// Decode every frame of a GIF or APNG. `None` for other formats and
+    /// for images of a single frame, which decode as still images.
+    pub fn decode(data: &[u8]) -> Result<Option<Self>, ImageError> {
+        let decode_error = |e: image::ImageError| ImageError::DecodeError(e.to_string());
+        let (frames, repeat) = match image::guess_format(data) {
+            Ok(ImageFormat::Gif) => {
+                let decoder = GifDecoder::new(Cursor::new(data)).map_err(decode_error)?;
+                let frames = decoder.into_frames().collect_frames();
+                (frames.map_err(decode_error)?, gif_repeat(data))
+            }
+            Ok(ImageFormat::Png) => {
+                let decoder = PngDecoder::new(Cursor::new(data)).map_err(decode_error)?;
+                if !decoder.is_apng() {
+                    return Ok(None);
+                }
+                let frames = decoder.apng().into_frames().collect_frames();
+                (frames.map_err(decode_error)?, apng_repeat(data))
+            }
+            _ => return Ok(None),
+        };
+        if frames.len() < 2 {
+            return Ok(None);
+        }
+        let delays = frames
+            .iter()
+            .map(|frame| clamp_delay(frame.delay().into()))
+            .collect();
+        let frames = frames.into_iter().map(Frame::into_buffer).map(|buffer| {
+            Arc::new(DecodedImage {
+                width: buffer.width(),
+                height: buffer.height(),
+                data: buffer.into_raw(),
+            })
+        });
+        Ok(Some(Self {
+            frames: frames.collect(),
+            delays,
+            repeat,
+        }))
+    }
+
+    pub fn byte_size(&self) -> usize {
+        self.frames.iter().map(|frame| frame.data.len()).sum()
+    }
+}
+
+/// A delay as browsers play it.
+pub fn clamp_delay(delay: Duration) -> Duration {
+    if delay <= MIN_FRAME_DELAY {
+        CLAMPED_FRAME_DELAY
+    } else {
+        delay
+    }
+}
+
+/// The loop count of a GIF's `NETSCAPE2.0` (or `ANIMEXTS1.0`) application
+/// extension.
+fn gif_repeat(data: &[u8]) -> Repeat {
+    for identifier in [b"NETSCAPE2.0", b"ANIMEXTS1.0"] {
+        let mut marker = vec![0x21, 0xFF, 0x0B];
+        marker.extend_from_slice(identifier);
+        let at = match data
+            .windows(marker.len())
+            .position(|window| window == marker)
+        {
+            Some(at) => at + marker.len(),
+            None => continue,
+        };
+        if let Some(&[3, 1, low, high]) = data.get(at..at + 4) {
+            return match u16::from_le_bytes([low, high]) {
+                0 => Repeat::Forever,
+                count => Repeat::Times(u32::from(count) + 1),
+            };
+        }
+    }
+    Repeat::Times(1)
+}
+
+/// `num_plays` of an APNG's `acTL` chunk.
+fn apng_repeat(data: &[u8]) -> Repeat {
+    let mut at = 8;
+    while let Some(header) = data.get(at..at + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        match &header[4..] {
+            b"acTL" => {
+                return match data.get(at + 12..at + 16) {
+                    Some(&[a, b, c, d]) => match u32::from_be_bytes([a, b, c, d]) {
+                        0 => Repeat::Forever,
+                        plays => Repeat::Times(plays),
+                    },
+                    _ => Repeat::Times(1),
+                };
+            }
+            // `acTL` comes before the image data.
+            b"IDAT" => break,
+            _ => at = at.saturating_add(12).saturating_add(length),
+        }
+    }
+    Repeat::Times(1)
+}
+
+/// Where an animation is in its frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Playback {
+    frame: usize,
+    /// How long the current frame has been shown.
+    shown: Duration,
+    /// Times the animation has played through.
+    plays: u32,
+    /// Stopped on the last frame after the last play.
+    finished: bool,
+}
+
+impl Playback {
+    /// Move on by `elapsed`. True when that changed the frame.
+    fn advance(&mut self, elapsed: Duration, delays: &[Duration], repeat: Repeat) -> bool {
+        let start = self.frame;
+        self.shown += elapsed;
+        while !self.finished && self.shown >= delays[self.frame] {
+            if self.frame + 1 < delays.len() {
+                self.shown -= delays[self.frame];
+                self.frame += 1;
+                continue;
+            }
+            self.plays += 1;
+            match repeat {
+                Repeat::Times(times) if self.plays >= times => self.finished = true,
+                _ => {
+                    self.shown -= delays[self.frame];
+                    self.frame = 0;
+                }
+            }
+        }
+        self.frame != start
+    }
+}
+
+struct Entry {
+    /// The image as fetched, to decode the frames from again.
+    encoded: Arc<[u8]>,
+    delays: Vec<Duration>,
+    repeat: Repeat,
+    /// `None` while evicted.
+    frames: Option<Vec<Arc<DecodedImage>>>,
+    playback: Playback,
+    /// When playback last advanced; `None` while out of view.
+    advanced_at: Option<Instant>,
+}
+
+impl Entry {
+    fn decoded_bytes(&self) -> usize {
+        self.frames
+            .iter()
+            .flatten()
+            .map(|frame| frame.data.len())
+            .sum()
+    }
+}
+
+/// The animated images of the current document and where each is in its
+/// playback.
+pub struct ImageAnimations {
+    budget_bytes: usize,
+    reduced_motion: AtomicBool,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ImageAnimations {
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget_bytes,
+            reduced_motion: AtomicBool::new(false),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hold every animation on its first frame, or let them play.
+    pub fn set_reduced_motion(&self, reduce: bool) {
+        self.reduced_motion.store(reduce, Ordering::Relaxed);
+        if reduce {
+            for entry in self.entries.lock().values_mut() {
+                entry.playback = Playback::default();
+                entry.advanced_at = None;
+            }
+        }
+    }
+
+    pub fn reduced_motion(&self) -> bool {
+        self.reduced_motion.load(Ordering::Relaxed)
+    }
+
+    /// Decode `data`, fetched from `url`, and keep it if it is animated.
+    /// True when it is. An image already kept keeps its playback.
+    pub fn insert(&self, url: &str, data: &[u8]) -> Result<bool, ImageError> {
+        if self.contains(url) {
+            return Ok(true);
+        }
+        let image = match AnimatedImage::decode(data)? {
+            Some(image) => image,
+            None => return Ok(false),
+        };
+        let mut entries = self.entries.lock();
+        entries.insert(
+            url.to_string(),
+            Entry {
+                encoded: data.into(),
+                delays: image.delays,
+                repeat: image.repeat,
+                frames: Some(image.frames),
+                playback: Playback::default(),
+                advanced_at: None,
+            },
+        );
+        self.evict(&mut entries, Some(url));
+        Ok(true)
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.entries.lock().contains_key(url)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+
+    /// Forget every animation, as for a new document.
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    /// Advance to `now` the animations of the URLs in `visible`; the
+    /// others pause where they are. Returns the URLs whose frame changed.
+    pub fn advance(&self, now: Instant, visible: &HashSet<String>) -> Vec<String> {
+        let reduced_motion = self.reduced_motion();
+        let mut entries = self.entries.lock();
+        let mut changed = Vec::new();
+        let mut failed = Vec::new();
+        for (url, entry) in entries.iter_mut() {
+            if reduced_motion || !visible.contains(url) {
+                entry.advanced_at = None;
+                continue;
+            }
+            if entry.frames.is_none() {
+                match AnimatedImage::decode(&entry.encoded) {
+                    Ok(Some(image)) => entry.frames = Some(image.frames),
+                    Ok(None) => {
+                        failed.push(url.clone());
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Failed to decode {} again: {}", url, e);
+                        failed.push(url.clone());
+                        continue;
+                    }
+                }
+            }
+            let elapsed = entry
+                .advanced_at
+                .map_or(Duration::ZERO, |then| now.saturating_duration_since(then));
+            entry.advanced_at = Some(now);
+            if entry.playback.advance(elapsed, &entry.delays, entry.repeat) {
+                changed.push(url.clone());
+            }
+        }
+        for url in failed {
+            entries.remove(&url);
+        }
+        self.evict(&mut entries, None);
+        changed
+    }
+
+    /// Index of the frame `url` shows; `None` when it is not animated.
+    pub fn frame_index(&self, url: &str) -> Option<usize> {
+        self.entries
+            .lock()
+            .get(url)
+            .map(|entry| entry.playback.frame)
+    }
+
+    /// The frame `url` shows, unless it is not animated or its frames were
+    /// evicted.
+    pub fn current_frame(&self, url: &str) -> Option<Arc<DecodedImage>> {
+        let entries = self.entries.lock();
+        let entry = entries.get(url)?;
+        entry.frames.as_ref()?.get(entry.playback.frame).cloned()
+    }
+
+    /// Bytes of decoded frames held across every animation.
+    pub fn decoded_bytes(&self) -> usize {
+        self.entries.lock().values().map(Entry::decoded_bytes).sum()
+    }
+
+    /// Drop the frames of animations out of view, largest first, until
+    /// the decoded frames fit the budget. `keep` is spared, as an image
+    /// just inserted has not been in view yet.
+    fn evict(&self, entries: &mut HashMap<String, Entry>, keep: Option<&str>) {
+        let mut total: usize = entries.values().map(Entry::decoded_bytes).sum();
+        if total <= self.budget_bytes {
+            return;
+        }
+        let mut candidates: Vec<(&String, &mut Entry)> = entries
+            .iter_mut()
+            .filter(|(url, entry)| {
+                entry.advanced_at.is_none() && entry.frames.is_some() && Some(url.as_str()) != keep
+            })
+            .collect();
+        candidates.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.decoded_bytes()));
+        for (url, entry) in candidates {
+            if total <= self.budget_bytes {
+                break;
+            }
+            total -= entry.decoded_bytes();
+            entry.frames = None;
+            tracing::debug!("Evicted the decoded frames of {}", url);
+        }
+    }
+}
+
+impl Default for ImageAnimations {
+    fn default() -> Self {
+        Self::new(DEFAULT_ANIMATION_BUDGET_BYTES)
+    }
+}
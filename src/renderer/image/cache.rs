@@ -0,0 +1,135 @@
+//! The current document's images, decoded once their fetch is over.
+//!
+//! [`ImageCache`] keeps each `<img>` URL's pixels, or that it is broken: the
+//! fetch failed or was blocked, or the bytes did not decode. Animated images
+//! are kept at their first frame here; [`super::ImageAnimations`] has the
+//! rest. An image arriving or breaking changes its element's natural size,
+//! so the cache flags that layout is due, as media elements and web fonts
+//! do.
+
+use super::{DecodedImage, ImageError, ImageLoader};
+use crate::renderer::Rect;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// An image whose fetch is over.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadedImage {
+    Decoded(Arc<DecodedImage>),
+    /// Drawn as a placeholder.
+    Broken,
+}
+
+#[derive(Default)]
+pub struct ImageCache {
+    entries: Mutex<HashMap<String, LoadedImage>>,
+    layout_dirty: AtomicBool,
+}
+
+impl ImageCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode `data`, fetched from `url`; an image that does not decode is
+    /// kept as broken.
+    pub fn insert(&self, url: &str, data: &[u8]) -> Result<Arc<DecodedImage>, ImageError> {
+        let decoded = ImageLoader::new()
+            .load_image_data_from_bytes(data)
+            .map(|image| Arc::new(DecodedImage::from(image)));
+        let loaded = match &decoded {
+            Ok(image) => LoadedImage::Decoded(image.clone()),
+            Err(_) => LoadedImage::Broken,
+        };
+        self.set(url, loaded);
+        decoded
+    }
+
+    /// `url` failed to load and shows as broken.
+    pub fn set_broken(&self, url: &str) {
+        self.set(url, LoadedImage::Broken);
+    }
+
+    fn set(&self, url: &str, loaded: LoadedImage) {
+        self.entries.lock().insert(url.to_string(), loaded);
+        self.layout_dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// `None` until the fetch of `url` is over.
+    pub fn get(&self, url: &str) -> Option<LoadedImage> {
+        self.entries.lock().get(url).cloned()
+    }
+
+    /// Width and height of `url`'s pixels, once decoded.
+    pub fn natural_size(&self, url: &str) -> Option<(u32, u32)> {
+        match self.entries.lock().get(url)? {
+            LoadedImage::Decoded(image) => Some((image.width, image.height)),
+            LoadedImage::Broken => None,
+        }
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.entries.lock().contains_key(url)
+    }
+
+    /// Forget every image, as for a new document.
+    pub fn clear(&self) {
+        self.entries.lock().clear();
+    }
+
+    /// Bytes of decoded pixels held.
+    pub fn decoded_bytes(&self) -> usize {
+        self.entries
+            .lock()
+            .values()
+            .map(|loaded| match loaded {
+                LoadedImage::Decoded(image) => image.data.len(),
+                LoadedImage::Broken => 0,
+            })
+            .sum()
+    }
+
+    /// Whether an image loaded or broke since the last call. Clears the
+    /// flag.
+    pub fn take_layout_dirty(&self) -> bool {
+        self.layout_dirty.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// What a broken image shows over `bounds`: a light box in a gray frame
+/// one pixel wide.
+pub fn broken_image_quads(bounds: &Rect) -> Vec<(Rect, [f32; 4])> {
+    const FILL: [f32; 4] = [0.94, 0.94, 0.94, 1.0];
+    const FRAME: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+    let frame = 1.0_f32.min(bounds.width / 2.0).min(bounds.height / 2.0);
+    let side = |x, y, width, height| {
+        (
+            Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            FRAME,
+        )
+    };
+    let (right, bottom) = (bounds.x + bounds.width, bounds.y + bounds.height);
+    let inner_height = bounds.height - 2.0 * frame;
+    vec![
+        (
+            Rect {
+                x: bounds.x + frame,
+                y: bounds.y + frame,
+                width: bounds.width - 2.0 * frame,
+                height: inner_height,
+            },
+            FILL,
+        ),
+        side(bounds.x, bounds.y, bounds.width, frame),
+        side(right - frame, bounds.y + frame, frame, inner_height),
+        side(bounds.x, bottom - frame, bounds.width, frame),
+        side(bounds.x, bounds.y + frame, frame, inner_height),
+    ]
+}
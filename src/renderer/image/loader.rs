@@ -1,10 +1,68 @@
 use super::ImageError;
 use crate::renderer::gpu::{GpuContext, Texture};
+use crate::renderer::Rect;
 use ash::vk;
 use base64::engine::Engine;
 use image::{DynamicImage, ImageFormat};
+use resvg::usvg::{self, TreeParsing};
 use std::io::Cursor; // Import the trait for decode method
 
+/// A decoded image as rows of straight-alpha RGBA8 pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl DecodedImage {
+    /// The `source` rect of RGBA8 `pixels` rows `width` pixels wide.
+    pub fn from_pixels(pixels: &[u8], width: u32, source: &Rect) -> Self {
+        let height = pixels.len() as u32 / (width.max(1) * 4);
+        let x0 = (source.x.max(0.0) as u32).min(width);
+        let y0 = (source.y.max(0.0) as u32).min(height);
+        let x1 = ((source.x + source.width).max(0.0) as u32).clamp(x0, width);
+        let y1 = ((source.y + source.height).max(0.0) as u32).clamp(y0, height);
+        let mut data = Vec::with_capacity(((x1 - x0) * (y1 - y0) * 4) as usize);
+        for y in y0..y1 {
+            let row = (y * width * 4) as usize;
+            data.extend_from_slice(&pixels[row + x0 as usize * 4..row + x1 as usize * 4]);
+        }
+        Self {
+            width: x1 - x0,
+            height: y1 - y0,
+            data,
+        }
+    }
+
+    /// The pixel at `(u, v)` as channels from 0 to 1, with the image
+    /// stretched over the unit square; transparent outside it.
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let inside = (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v);
+        if !inside || self.width == 0 || self.height == 0 {
+            return [0.0; 4];
+        }
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        let offset = ((y * self.width + x) * 4) as usize;
+        match self.data.get(offset..offset + 4) {
+            Some(pixel) => [0, 1, 2, 3].map(|channel| pixel[channel] as f32 / 255.0),
+            None => [0.0; 4],
+        }
+    }
+}
+
+impl From<DynamicImage> for DecodedImage {
+    fn from(image: DynamicImage) -> Self {
+        let rgba = image.into_rgba8();
+        Self {
+            width: rgba.width(),
+            height: rgba.height(),
+            data: rgba.into_raw(),
+        }
+    }
+}
+
 pub struct ImageLoader {
     supported_formats: Vec<ImageFormat>,
 }
@@ -19,6 +77,7 @@ impl ImageLoader {
                 ImageFormat::Gif,
                 ImageFormat::Bmp,
                 ImageFormat::Tiff,
+                ImageFormat::Ico,
             ],
         }
     }
@@ -97,7 +156,26 @@ impl ImageLoader {
             .map_err(|e| ImageError::LoadError(e.to_string()))
     }
 
+    /// Decode `data` scaled to fit a `size` pixel square, keeping its
+    /// aspect ratio. SVG is drawn at that size rather than scaled.
+    pub fn decode_image_to_fit(&self, data: &[u8], size: u32) -> Result<DynamicImage, ImageError> {
+        let size = size.max(1);
+        if is_svg(data) {
+            return rasterize_svg(data, Some(size));
+        }
+        let image = self.decode_image(data)?;
+        if image.width() == size && image.height() <= size
+            || image.height() == size && image.width() <= size
+        {
+            return Ok(image);
+        }
+        Ok(image.resize(size, size, image::imageops::FilterType::Triangle))
+    }
+
     fn decode_image(&self, data: &[u8]) -> Result<DynamicImage, ImageError> {
+        if is_svg(data) {
+            return rasterize_svg(data, None);
+        }
         let format =
             image::guess_format(data).map_err(|e| ImageError::DecodeError(e.to_string()))?;
 
@@ -235,6 +313,58 @@ impl ImageLoader {
     }
 }
 
+/// Whether `data` is SVG markup rather than an encoded bitmap.
+pub fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(1024)];
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    text.starts_with("<svg")
+        || ((text.starts_with("<?xml") || text.starts_with("<!")) && text.contains("<svg"))
+}
+
+/// Draw an SVG document at its own size, or fitted to a `size` square.
+fn rasterize_svg(data: &[u8], size: Option<u32>) -> Result<DynamicImage, ImageError> {
+    let mut tree = usvg::Tree::from_data(data, &usvg::Options::default())
+        .map_err(|e| ImageError::DecodeError(e.to_string()))?;
+    tree.calculate_abs_transforms();
+    tree.calculate_bounding_boxes();
+
+    let natural = tree.size.to_int_size();
+    let (width, height) = match size {
+        Some(size) => {
+            let scale = size as f32 / tree.size.width().max(tree.size.height());
+            (
+                ((tree.size.width() * scale).round() as u32).max(1),
+                ((tree.size.height() * scale).round() as u32).max(1),
+            )
+        }
+        None => (natural.width(), natural.height()),
+    };
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| ImageError::DecodeError("SVG has no area".to_string()))?;
+    let transform = resvg::tiny_skia::Transform::from_scale(
+        width as f32 / tree.size.width(),
+        height as f32 / tree.size.height(),
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    // tiny-skia pixels are premultiplied.
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    image::RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| ImageError::DecodeError("SVG raster size mismatch".to_string()))
+}
+
 impl Default for ImageLoader {
     fn default() -> Self {
         Self::new()
@@ -1,5 +1,9 @@
+pub mod animation;
+pub mod cache;
 pub mod loader;
 
+pub use animation::{AnimatedImage, ImageAnimations, Repeat};
+pub use cache::{broken_image_quads, ImageCache, LoadedImage};
 pub use loader::*;
 
 use crate::renderer::gpu::Texture;
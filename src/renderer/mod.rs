@@ -1,17 +1,45 @@
+pub mod clip;
+pub mod command_stream;
+pub mod custom_paint;
+pub mod debug_overlay;
+pub mod decoration;
+pub mod glyphs;
 pub mod gpu;
+pub mod hit_test;
 pub mod image;
 pub mod pipeline;
+pub mod raster;
+pub mod retained;
 pub mod text;
 pub mod vulkan;
-
+pub mod wire;
+
+pub use clip::{ClipChain, ClipRect, CornerRadii, MAX_ROUNDED_CLIP_DEPTH};
+pub use debug_overlay::{BoxOutline, DebugOverlay, DebugOverlayFlags, DebugOverlaySettings};
+pub use decoration::{
+    DecorationKind, DecorationLines, DecorationStrip, DecorationStyle, TextDecoration, TextShadow,
+};
+pub use hit_test::{HitRegion, HitRegions};
+pub use raster::{DrawQuad, Snapshot};
+pub use retained::{ChangeSet, FrameUpdate, PaintKey, RetainedScene};
+
+use self::image::{broken_image_quads, DecodedImage, LoadedImage};
+use crate::core::css::color_space::srgb_to_linear;
 use crate::core::dom::Document;
 use crate::core::dom::NodeId;
+use crate::core::fonts::{FontMetrics, FontRequest};
 use crate::core::layout::LayoutBox;
 use ash::vk;
+use command_stream::{CommandReceiver, PaintCommand, Transform};
+use custom_paint::{PaintOutput, PaintSources};
+use glyphs::{GlyphAtlas, GlyphCache, GlyphMask, PlacedGlyph};
+use retained::{NodePaint, PaintSource};
+use std::collections::HashMap;
+use std::sync::Arc;
 use thiserror::Error;
 
 // Unified, self-contained types - no external dependencies
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -19,7 +47,7 @@ pub struct Rect {
     pub height: f32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ElementType {
     Block,
     Inline,
@@ -27,26 +55,86 @@ pub enum ElementType {
     Text,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Style {
     pub background_color: Option<String>,
+    /// The registered paint source `background-image: -vk-paint(name)`
+    /// names, drawn over the background color.
+    pub background_paint: Option<String>,
     pub color: Option<String>,
     pub font_family: Option<String>,
     pub font_size: f32,
+    /// Weight, width, slant and axis settings the text's face is drawn at.
+    pub font: FontRequest,
+    /// Metrics of the face the text is drawn with, at `font_size`.
+    pub font_metrics: FontMetrics,
+    /// Content language of the text, a BCP 47 tag; `None` when unknown.
+    pub language: Option<String>,
+    pub text_decoration: TextDecoration,
+    /// `text-shadow` layers, frontmost first.
+    pub text_shadows: Vec<TextShadow>,
+    pub border_radius: CornerRadii,
+    /// The `border-*-width` longhands; a side with a `border-style` of
+    /// `none` or `hidden` has none.
+    pub border_width: EdgeWidths,
+    /// `border-color`, or the text color.
+    pub border_color: Option<String>,
+    /// Padding between `bounds`, the content box, and the border.
+    pub padding: EdgeWidths,
+    /// `overflow` is not `visible` on either axis, or the box has paint
+    /// containment: descendants are clipped to the padding box, rounded by
+    /// `border_radius`.
+    pub clips_overflow: bool,
 }
 
 impl Default for Style {
     fn default() -> Self {
         Self {
             background_color: None,
+            background_paint: None,
             color: Some("#000000".to_string()),
             font_family: Some("Arial".to_string()),
             font_size: 16.0,
+            font: FontRequest::default(),
+            font_metrics: FontMetrics::fallback(16.0),
+            language: None,
+            text_decoration: TextDecoration::default(),
+            text_shadows: Vec::new(),
+            border_radius: CornerRadii::default(),
+            border_width: EdgeWidths::default(),
+            border_color: None,
+            padding: EdgeWidths::default(),
+            clips_overflow: false,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// Widths of the four sides of a box edge, in pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EdgeWidths {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl EdgeWidths {
+    pub fn is_zero(&self) -> bool {
+        self.top <= 0.0 && self.right <= 0.0 && self.bottom <= 0.0 && self.left <= 0.0
+    }
+
+    /// `rect` grown by the widths.
+    pub fn outset(&self, rect: &Rect) -> Rect {
+        Rect {
+            x: rect.x - self.left,
+            y: rect.y - self.top,
+            width: rect.width + self.left + self.right,
+            height: rect.height + self.top + self.bottom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LayoutNode {
     pub node_id: NodeId,
     pub bounds: Rect,
@@ -54,12 +142,64 @@ pub struct LayoutNode {
     pub style: Style,
     pub text_content: Option<String>,
     pub image_url: Option<String>,
+    /// Frame of an animated image to show; 0 for still images.
+    pub image_frame: u32,
+    /// Clips of the node's overflow-clipping ancestors.
+    pub clip: ClipChain,
+}
+
+impl LayoutNode {
+    /// Where the background is drawn: the content box and its padding.
+    pub fn padding_box(&self) -> Rect {
+        self.style.padding.outset(&self.bounds)
+    }
+
+    /// The border's sides around the padding box, those that have a width.
+    /// Top and bottom span the corners, so no pixel is drawn twice.
+    pub fn border_sides(&self) -> Vec<Rect> {
+        let widths = &self.style.border_width;
+        let inner = self.padding_box();
+        let outer = widths.outset(&inner);
+        [
+            Rect {
+                x: outer.x,
+                y: outer.y,
+                width: outer.width,
+                height: widths.top,
+            },
+            Rect {
+                x: inner.x + inner.width,
+                y: inner.y,
+                width: widths.right,
+                height: inner.height,
+            },
+            Rect {
+                x: outer.x,
+                y: inner.y + inner.height,
+                width: outer.width,
+                height: widths.bottom,
+            },
+            Rect {
+                x: outer.x,
+                y: inner.y,
+                width: widths.left,
+                height: inner.height,
+            },
+        ]
+        .into_iter()
+        .filter(|side| side.width > 0.0 && side.height > 0.0)
+        .collect()
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct LayoutTree {
     nodes: Vec<LayoutNode>,
     text_nodes: Vec<LayoutNode>,
+    /// Boxes the debug overlay outlines; only filled in while it does.
+    box_outlines: Vec<BoxOutline>,
+    /// What image nodes show, by URL and frame, once their fetch is over.
+    images: HashMap<(String, u32), LoadedImage>,
 }
 
 impl LayoutTree {
@@ -67,6 +207,8 @@ impl LayoutTree {
         Self {
             nodes: Vec::with_capacity(256),
             text_nodes: Vec::with_capacity(128),
+            box_outlines: Vec::new(),
+            images: HashMap::new(),
         }
     }
 
@@ -78,6 +220,39 @@ impl LayoutTree {
         &self.text_nodes
     }
 
+    /// The topmost node drawn at `(x, y)`, honoring the clips it is drawn
+    /// with, so points in a rounded-off corner miss the clipped content.
+    /// The paint list does not know `pointer-events`; [`HitRegions`] does.
+    pub fn hit_test(&self, x: f32, y: f32) -> Option<NodeId> {
+        self.hit_order()
+            .find(|node| hit_test::hits(&node.bounds, &node.clip, x, y))
+            .map(|node| node.node_id)
+    }
+
+    /// Every node, topmost first: text paints over boxes, later nodes over
+    /// earlier ones.
+    pub fn hit_order(&self) -> impl Iterator<Item = &LayoutNode> {
+        self.text_nodes.iter().rev().chain(self.nodes.iter().rev())
+    }
+
+    pub fn box_outlines(&self) -> &[BoxOutline] {
+        &self.box_outlines
+    }
+
+    pub fn set_box_outlines(&mut self, outlines: Vec<BoxOutline>) {
+        self.box_outlines = outlines;
+    }
+
+    /// What image nodes showing frame `frame` of `url` draw.
+    pub fn set_image(&mut self, url: &str, frame: u32, image: LoadedImage) {
+        self.images.insert((url.to_string(), frame), image);
+    }
+
+    /// `None` while the image is loading: nothing is drawn for it.
+    pub fn image(&self, url: &str, frame: u32) -> Option<&LoadedImage> {
+        self.images.get(&(url.to_string(), frame))
+    }
+
     pub fn add_node(&mut self, node: LayoutNode) {
         if matches!(node.element_type, ElementType::Text) {
             self.text_nodes.push(node);
@@ -104,15 +279,29 @@ impl LayoutTree {
             style: Style::default(),
             text_content: None,
             image_url: None,
+            image_frame: 0,
+            clip: ClipChain::new(),
         };
         self.add_node(layout_node);
     }
 }
 
+/// Which rasterization path a `VulkanRenderer` drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// GPU path: pipelines, textures and vertex uploads go through Vulkan.
+    Vulkan,
+    /// Null/software path used when GPU acceleration is disabled; no GPU
+    /// pipelines or textures are created.
+    Software,
+}
+
 #[derive(Debug, Clone)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub tex_coord: [f32; 2],
+    /// Linear light with straight alpha, see [`linearize`]; the sRGB
+    /// framebuffer encodes what the fragment shader writes.
     pub color: [f32; 4],
 }
 
@@ -268,7 +457,11 @@ mod stubs {
             Self { cache_size: 0 }
         }
 
-        pub async fn load_image(&mut self, _url: &str) -> Result<DummyTexture, RenderError> {
+        pub async fn load_image(
+            &mut self,
+            _url: &str,
+            _frame: u32,
+        ) -> Result<DummyTexture, RenderError> {
             self.cache_size += 1;
             let texture = DummyTexture::new();
             texture.touch();
@@ -294,11 +487,37 @@ mod stubs {
 use stubs::*;
 
 pub struct VulkanRenderer {
+    backend: RenderBackend,
     context: RenderContext,
     pipeline_cache: PipelineCache,
     text_renderer: TextRenderer,
+    glyphs: GlyphCache,
+    /// Where glyph quads sample their coverage.
+    glyph_atlas: GlyphAtlas,
     image_loader: ImageLoader,
-    vertex_buffer: Vec<Vertex>,
+    /// What each image node was last painted showing.
+    drawn_images: HashMap<PaintKey, Option<LoadedImage>>,
+    /// Vertices, indices and solid quads of the last frame, patched by the
+    /// next one; the quads, with their clips, are what
+    /// [`VulkanRenderer::snapshot`] draws.
+    scene: RetainedScene,
+    /// Solid quads of the last frame when it was drawn from paint commands
+    /// rather than a layout tree.
+    streamed: Vec<DrawQuad>,
+    /// Paint every frame into new buffers instead of patching the last
+    /// frame's.
+    full_rebuild: bool,
+    /// Where the last frame differs from the one before it.
+    damage: Option<Rect>,
+    /// Quads painted over the page until replaced, such as the inspector's
+    /// node highlight.
+    overlay: Vec<DrawQuad>,
+    /// Drawn over everything, the overlay included; not part of the
+    /// retained scene, so it never counts as damage.
+    debug_overlay: DebugOverlay,
+    /// The embedder's `-vk-paint()` sources.
+    paint_sources: Arc<PaintSources>,
+    device_pixel_ratio: f32,
     frame_stats: FrameStats,
 }
 
@@ -308,23 +527,99 @@ pub struct FrameStats {
     draw_calls: u32,
     texture_binds: u32,
     frame_time_ms: f32,
+    submit_time_ms: f32,
+    nodes_patched: u32,
+    nodes_rebuilt: u32,
+    vertices_uploaded: u32,
+    vertex_buffer_version: u64,
+    index_buffer_version: u64,
+}
+
+impl FrameStats {
+    /// Nodes painted again into the buffers they already had.
+    pub fn nodes_patched(&self) -> u32 {
+        self.nodes_patched
+    }
+
+    /// Nodes painted into new buffers: all of them on a full rebuild, and
+    /// the unchanged ones moved along when the buffers had to grow.
+    pub fn nodes_rebuilt(&self) -> u32 {
+        self.nodes_rebuilt
+    }
+
+    /// Time the frame took, from recording it to presenting it.
+    pub fn frame_time_ms(&self) -> f32 {
+        self.frame_time_ms
+    }
+
+    /// The part of [`frame_time_ms`](Self::frame_time_ms) spent uploading
+    /// vertices, submitting and waiting to present.
+    pub fn submit_time_ms(&self) -> f32 {
+        self.submit_time_ms
+    }
+
+    /// Vertices copied to the GPU this frame.
+    pub fn vertices_uploaded(&self) -> u32 {
+        self.vertices_uploaded
+    }
+
+    /// Allocations of the vertex buffer so far.
+    pub fn vertex_buffer_version(&self) -> u64 {
+        self.vertex_buffer_version
+    }
+
+    /// Allocations of the index buffer so far.
+    pub fn index_buffer_version(&self) -> u64 {
+        self.index_buffer_version
+    }
 }
 
 impl VulkanRenderer {
     pub async fn new() -> Result<Self, RenderError> {
+        Self::with_backend(RenderBackend::Vulkan).await
+    }
+
+    pub async fn with_backend(backend: RenderBackend) -> Result<Self, RenderError> {
         let mut context = RenderContext::new();
         context.initialize()?;
 
         Ok(Self {
+            backend,
             context,
             pipeline_cache: PipelineCache::new(),
             text_renderer: TextRenderer::new(),
+            glyphs: GlyphCache::new(),
+            glyph_atlas: GlyphAtlas::new(),
             image_loader: ImageLoader::new(),
-            vertex_buffer: Vec::with_capacity(4096),
+            drawn_images: HashMap::new(),
+            scene: RetainedScene::new(),
+            streamed: Vec::new(),
+            full_rebuild: false,
+            damage: None,
+            overlay: Vec::new(),
+            debug_overlay: DebugOverlay::new(),
+            paint_sources: Arc::new(PaintSources::default()),
+            device_pixel_ratio: 1.0,
             frame_stats: FrameStats::default(),
         })
     }
 
+    /// Paint every frame from scratch into new buffers, for comparing the
+    /// retained path against.
+    pub fn set_full_rebuild(&mut self, full_rebuild: bool) {
+        self.full_rebuild = full_rebuild;
+    }
+
+    /// Draw `-vk-paint()` backgrounds with `sources`.
+    pub fn set_paint_sources(&mut self, sources: Arc<PaintSources>) {
+        self.paint_sources = sources;
+    }
+
+    /// Device pixels per CSS pixel, as paint sources are told.
+    pub fn set_device_pixel_ratio(&mut self, ratio: f32) {
+        self.device_pixel_ratio = ratio.max(f32::EPSILON);
+    }
+
     pub async fn render(
         &mut self,
         _document: &Document,
@@ -336,12 +631,236 @@ impl VulkanRenderer {
         let command_buffer = self.context.begin_frame()?;
 
         self.render_background(command_buffer).await?;
-        self.render_elements(command_buffer, layout_tree).await?;
-        self.render_text(command_buffer, layout_tree).await?;
+        self.streamed.clear();
+
+        // Only nodes that differ from the last frame are painted again,
+        // unless every frame is rebuilt.
+        let overlay = self.overlay.clone();
+        let entries = retained::frame_entries(layout_tree, &overlay);
+        let mut changes = (!self.full_rebuild).then(|| self.scene.diff(&entries));
+        // An animated paint source paints anew each frame it shows in.
+        let mut animating = false;
+        for entry in &entries {
+            if let PaintSource::Node(node) = entry.source {
+                let animated = node
+                    .style
+                    .background_paint
+                    .as_deref()
+                    .is_some_and(|name| self.paint_sources.is_animated(name));
+                if animated {
+                    animating = true;
+                    if let Some(changes) = changes.as_mut() {
+                        changes.force_repaint(entry.key);
+                    }
+                }
+            }
+        }
+        self.paint_sources.set_animating(animating);
+        // An image that loaded or broke since its node was painted.
+        let mut drawn_images = HashMap::new();
+        for entry in &entries {
+            let PaintSource::Node(node) = entry.source else {
+                continue;
+            };
+            let Some(url) = &node.image_url else {
+                continue;
+            };
+            let image = layout_tree.image(url, node.image_frame).cloned();
+            if self.drawn_images.get(&entry.key) != Some(&image) {
+                if let Some(changes) = changes.as_mut() {
+                    changes.force_repaint(entry.key);
+                }
+            }
+            drawn_images.insert(entry.key, image);
+        }
+        self.drawn_images = drawn_images;
+        let atlas_generation = self.glyph_atlas.generation();
+        let mut painted = HashMap::new();
+        for entry in &entries {
+            if changes.as_ref().map_or(true, |c| c.repaints(entry.key)) {
+                let paint = self
+                    .paint_entry(command_buffer, layout_tree, entry.source)
+                    .await?;
+                painted.insert(entry.key, paint);
+            }
+        }
+        // The glyph atlas grew or was emptied: every glyph quad, kept from
+        // the last frame or not, samples the wrong cell.
+        if self.glyph_atlas.generation() != atlas_generation {
+            changes = None;
+            for entry in &entries {
+                let paint = self
+                    .paint_entry(command_buffer, layout_tree, entry.source)
+                    .await?;
+                painted.insert(entry.key, paint);
+            }
+        }
+        let update = match &changes {
+            Some(changes) => self.scene.apply(&entries, changes, painted),
+            None => {
+                let mut update = self.scene.rebuild(&entries, painted);
+                let config = self.context.get_config();
+                update.damage = Some(Rect {
+                    x: 0.0,
+                    y: 0.0,
+                    width: config.viewport_width as f32,
+                    height: config.viewport_height as f32,
+                });
+                update
+            }
+        };
+        self.damage = update.damage;
+        self.frame_stats.nodes_patched = update.nodes_patched;
+        self.frame_stats.nodes_rebuilt = update.nodes_rebuilt;
+
+        let submit_start = std::time::Instant::now();
         self.flush_vertices(command_buffer).await?;
+        // The top layer draws last, over the scene.
+        if self.debug_overlay.is_enabled() {
+            self.render_debug_overlay(command_buffer, layout_tree, frame_start)
+                .await?;
+        }
+
+        self.context.end_frame(command_buffer)?;
+
+        self.frame_stats.submit_time_ms = submit_start.elapsed().as_secs_f32() * 1000.0;
+        self.frame_stats.frame_time_ms = frame_start.elapsed().as_secs_f32() * 1000.0;
+        self.debug_overlay
+            .frame_done(self.frame_stats.frame_time_ms);
+
+        Ok(())
+    }
+
+    /// Draw the commands `stream` last took, in place of a layout tree: the
+    /// GPU process's end of [`command_stream`]. Nothing is retained between
+    /// such frames: each frame's commands are drawn whole.
+    pub async fn render_commands(&mut self, stream: &CommandReceiver) -> Result<(), RenderError> {
+        let frame_start = std::time::Instant::now();
+        self.frame_stats = FrameStats::default();
 
+        let command_buffer = self.context.begin_frame()?;
+
+        self.render_background(command_buffer).await?;
+        // A layout tree drawn next starts from nothing retained.
+        self.scene = RetainedScene::new();
+        self.drawn_images.clear();
+        self.streamed.clear();
+
+        let resources = stream.resources();
+        let mut vertices = Vec::new();
+        let mut clips = vec![ClipChain::new()];
+        let mut transforms = vec![Transform::IDENTITY];
+        for command in stream.commands() {
+            let transform = transforms.last().copied().unwrap_or(Transform::IDENTITY);
+            let clip = clips.last().cloned().unwrap_or_default();
+            match command {
+                PaintCommand::Quad {
+                    bounds,
+                    color,
+                    blur_radius,
+                } => {
+                    let bounds = transform.apply(bounds);
+                    vertices.extend(Self::solid_vertices(&bounds, *color));
+                    self.streamed.push(DrawQuad {
+                        bounds,
+                        color: *color,
+                        clip,
+                        blur_radius: blur_radius * transform.blur_scale(),
+                        mask: None,
+                        image: None,
+                    });
+                }
+                PaintCommand::TexturedQuad {
+                    bounds,
+                    resource,
+                    source,
+                } => {
+                    let Some(image) = resources.get(*resource) else {
+                        continue;
+                    };
+                    if self.backend == RenderBackend::Vulkan {
+                        let _pipeline = self.pipeline_cache.get_image_pipeline()?;
+                        self.frame_stats.texture_binds += 1;
+                    }
+                    let bounds = transform.apply(bounds);
+                    vertices.extend(Self::textured_vertices(
+                        &bounds,
+                        source,
+                        (image.width, image.height),
+                        [1.0; 4],
+                    ));
+                    let pixels = DecodedImage::from_pixels(&image.pixels, image.width, source);
+                    self.streamed.push(DrawQuad {
+                        bounds,
+                        color: [1.0; 4],
+                        clip,
+                        blur_radius: 0.0,
+                        mask: None,
+                        image: Some(Arc::new(pixels)),
+                    });
+                    self.frame_stats.draw_calls += 1;
+                }
+                PaintCommand::PushClip(pushed) => {
+                    clips.push(clip.push(transform.apply_clip(pushed)));
+                }
+                PaintCommand::PopClip => {
+                    if clips.len() > 1 {
+                        clips.pop();
+                    }
+                }
+                PaintCommand::PushTransform(pushed) => transforms.push(transform.then(pushed)),
+                PaintCommand::PopTransform => {
+                    if transforms.len() > 1 {
+                        transforms.pop();
+                    }
+                }
+                PaintCommand::GlyphRun {
+                    page,
+                    color,
+                    glyphs,
+                } => {
+                    let Some(page) = resources.get(*page) else {
+                        continue;
+                    };
+                    for glyph in glyphs {
+                        let bounds = transform.apply(&glyph.bounds);
+                        vertices.extend(Self::textured_vertices(
+                            &bounds,
+                            &glyph.source,
+                            (page.width, page.height),
+                            linearize(*color),
+                        ));
+                        let mask = GlyphMask::from_atlas(&page.pixels, page.width, &glyph.source);
+                        self.streamed.push(DrawQuad {
+                            bounds,
+                            color: *color,
+                            clip: clip.clone(),
+                            blur_radius: 0.0,
+                            mask: Some(Arc::new(mask)),
+                            image: None,
+                        });
+                    }
+                    self.frame_stats.draw_calls += 1;
+                }
+            }
+        }
+
+        let vertex_count = vertices.len() as u32;
+        self.frame_stats.vertices_rendered = vertex_count;
+        self.frame_stats.vertices_uploaded = vertex_count;
+        self.frame_stats.draw_calls += if vertex_count > 0 { 1 } else { 0 };
+        let config = self.context.get_config();
+        self.damage = Some(Rect {
+            x: 0.0,
+            y: 0.0,
+            width: config.viewport_width as f32,
+            height: config.viewport_height as f32,
+        });
+
+        let submit_start = std::time::Instant::now();
         self.context.end_frame(command_buffer)?;
 
+        self.frame_stats.submit_time_ms = submit_start.elapsed().as_secs_f32() * 1000.0;
         self.frame_stats.frame_time_ms = frame_start.elapsed().as_secs_f32() * 1000.0;
 
         Ok(())
@@ -354,108 +873,479 @@ impl VulkanRenderer {
         Ok(())
     }
 
-    async fn render_elements(
+    async fn paint_entry(
         &mut self,
-        _command_buffer: vk::CommandBuffer,
+        command_buffer: vk::CommandBuffer,
         layout_tree: &LayoutTree,
-    ) -> Result<(), RenderError> {
-        self.vertex_buffer.clear();
-
-        for node in layout_tree.get_render_nodes() {
-            match node.element_type {
-                ElementType::Block => {
-                    self.render_block_element(node)?;
-                }
-                ElementType::Inline => {
-                    self.render_inline_element(node)?;
-                }
+        source: PaintSource<'_>,
+    ) -> Result<NodePaint, RenderError> {
+        let mut paint = NodePaint::default();
+        match source {
+            PaintSource::Node(node) => match node.element_type {
+                ElementType::Block => self.render_block_element(node, &mut paint).await?,
+                ElementType::Inline => self.render_inline_element(node, &mut paint)?,
                 ElementType::Image => {
-                    self.render_image_element(node).await?;
+                    let image = node
+                        .image_url
+                        .as_deref()
+                        .and_then(|url| layout_tree.image(url, node.image_frame));
+                    self.render_image_element(node, image, &mut paint).await?
                 }
-                _ => {}
-            }
+                ElementType::Text => self.render_text(command_buffer, node, &mut paint).await?,
+            },
+            PaintSource::Overlay(quads) => Self::render_overlay(quads, &mut paint),
         }
+        Ok(paint)
+    }
 
-        Ok(())
+    pub fn backend(&self) -> RenderBackend {
+        self.backend
     }
 
-    fn render_block_element(&mut self, node: &LayoutNode) -> Result<(), RenderError> {
-        let _pipeline = self.pipeline_cache.get_rect_pipeline()?;
-        let vertices = self.create_rect_vertices(&node.bounds, &node.style.background_color);
+    async fn render_block_element(
+        &mut self,
+        node: &LayoutNode,
+        paint: &mut NodePaint,
+    ) -> Result<(), RenderError> {
+        self.render_box(node, paint)?;
 
-        self.vertex_buffer.extend(vertices);
-        self.frame_stats.vertices_rendered += 4;
-        self.frame_stats.draw_calls += 1;
+        if let Some(name) = &node.style.background_paint {
+            self.render_paint_source(node, name, paint).await?;
+        }
 
         Ok(())
     }
 
-    fn render_inline_element(&mut self, _node: &LayoutNode) -> Result<(), RenderError> {
+    /// Draws what the paint source `name` painted for the node: pixels as
+    /// a texture, shapes as solid quads.
+    async fn render_paint_source(
+        &mut self,
+        node: &LayoutNode,
+        name: &str,
+        paint: &mut NodePaint,
+    ) -> Result<(), RenderError> {
+        let bounds = &node.bounds;
+        let output = match self
+            .paint_sources
+            .paint(
+                name,
+                bounds.width,
+                bounds.height,
+                self.device_pixel_ratio,
+                self.context.frame_index as u64,
+            )
+            .await
+        {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+
+        let quads = output.quads(bounds);
+        match output.as_ref() {
+            PaintOutput::Pixels { .. } => {
+                if self.backend == RenderBackend::Vulkan {
+                    let _pipeline = self.pipeline_cache.get_image_pipeline()?;
+                    self.frame_stats.texture_binds += 1;
+                }
+                paint.vertices.extend(self.create_image_vertices(bounds));
+            }
+            PaintOutput::Commands(_) => {
+                for (quad, color) in &quads {
+                    paint.vertices.extend(Self::solid_vertices(quad, *color));
+                }
+            }
+        }
+        for (quad, color) in quads {
+            paint.quads.push(DrawQuad {
+                bounds: quad,
+                color,
+                clip: node.clip.clone(),
+                blur_radius: 0.0,
+                mask: None,
+                image: None,
+            });
+        }
+        paint.draw_calls += 1;
         Ok(())
     }
 
-    async fn render_image_element(&mut self, node: &LayoutNode) -> Result<(), RenderError> {
-        if let Some(image_url) = &node.image_url {
-            let _texture = self.image_loader.load_image(image_url).await?;
-            let _pipeline = self.pipeline_cache.get_image_pipeline()?;
+    /// The background over the padding box, then the border around it, in
+    /// one draw: the colors are per vertex.
+    fn render_box(&mut self, node: &LayoutNode, paint: &mut NodePaint) -> Result<(), RenderError> {
+        let background = node.style.background_color.as_ref();
+        let border = node
+            .style
+            .border_color
+            .as_ref()
+            .map(|color| (color, node.border_sides()))
+            .filter(|(_, sides)| !sides.is_empty());
+        if background.is_none() && border.is_none() {
+            return Ok(());
+        }
+        if self.backend == RenderBackend::Vulkan {
+            let _pipeline = self.pipeline_cache.get_rect_pipeline()?;
+        }
 
-            let vertices = self.create_image_vertices(&node.bounds);
-            self.vertex_buffer.extend(vertices);
+        if let Some(color) = background {
+            let padding_box = node.padding_box();
+            paint
+                .vertices
+                .extend(Self::solid_vertices(&padding_box, parse_color(color)));
+            Self::record_quad(paint, &padding_box, color, &node.clip, 0.0);
+        }
+        if let Some((color, sides)) = border {
+            for side in &sides {
+                paint
+                    .vertices
+                    .extend(Self::solid_vertices(side, parse_color(color)));
+                Self::record_quad(paint, side, color, &node.clip, 0.0);
+            }
+        }
+        paint.draw_calls += 1;
+        Ok(())
+    }
 
-            self.frame_stats.vertices_rendered += 4;
-            self.frame_stats.texture_binds += 1;
-            self.frame_stats.draw_calls += 1;
+    fn render_inline_element(
+        &mut self,
+        node: &LayoutNode,
+        paint: &mut NodePaint,
+    ) -> Result<(), RenderError> {
+        self.render_box(node, paint)
+    }
+
+    /// Binds the texture of the image's frame; nodes not painted again
+    /// keep theirs, so an animation repaints only the boxes showing it.
+    /// Nothing is drawn while `image` is loading, and a placeholder once
+    /// it is broken.
+    async fn render_image_element(
+        &mut self,
+        node: &LayoutNode,
+        image: Option<&LoadedImage>,
+        paint: &mut NodePaint,
+    ) -> Result<(), RenderError> {
+        let Some(image_url) = &node.image_url else {
+            return Ok(());
+        };
+        match image {
+            Some(LoadedImage::Decoded(image)) => {
+                if self.backend == RenderBackend::Vulkan {
+                    let _texture = self
+                        .image_loader
+                        .load_image(image_url, node.image_frame)
+                        .await?;
+                    let _pipeline = self.pipeline_cache.get_image_pipeline()?;
+                    self.frame_stats.texture_binds += 1;
+                }
+                paint
+                    .vertices
+                    .extend(self.create_image_vertices(&node.bounds));
+                paint.quads.push(DrawQuad {
+                    bounds: node.bounds.clone(),
+                    color: [1.0; 4],
+                    clip: node.clip.clone(),
+                    blur_radius: 0.0,
+                    mask: None,
+                    image: Some(image.clone()),
+                });
+            }
+            Some(LoadedImage::Broken) => {
+                if self.backend == RenderBackend::Vulkan {
+                    let _pipeline = self.pipeline_cache.get_rect_pipeline()?;
+                }
+                for (bounds, color) in broken_image_quads(&node.bounds) {
+                    paint.vertices.extend(Self::solid_vertices(&bounds, color));
+                    paint.quads.push(DrawQuad {
+                        bounds,
+                        color,
+                        clip: node.clip.clone(),
+                        blur_radius: 0.0,
+                        mask: None,
+                        image: None,
+                    });
+                }
+            }
+            None => return Ok(()),
         }
+        paint.draw_calls += 1;
         Ok(())
     }
 
     async fn render_text(
         &mut self,
         command_buffer: vk::CommandBuffer,
-        layout_tree: &LayoutTree,
+        node: &LayoutNode,
+        paint: &mut NodePaint,
     ) -> Result<(), RenderError> {
-        for node in layout_tree.get_text_nodes() {
-            if let Some(text_content) = &node.text_content {
-                self.text_renderer
-                    .render_text(
-                        command_buffer,
-                        text_content,
-                        &node.bounds,
-                        &node.style.color,
-                        &node.style.font_family,
-                        node.style.font_size,
-                    )
-                    .await?;
+        if let Some(text_content) = &node.text_content {
+            let style = &node.style;
+            let text_color = style.color.as_deref().unwrap_or("#000000");
+            let glyphs = self.glyphs.place(text_content, &node.bounds, style);
+            let strips = decoration::decoration_strips(
+                &node.bounds,
+                &style.font_metrics,
+                &style.text_decoration,
+            );
+            let decoration_color = style.text_decoration.color.as_deref().unwrap_or(text_color);
+
+            // Shadows paint back to front, under everything of the text.
+            // Glyphs cast the shadows of their boxes.
+            for shadow in style.text_shadows.iter().rev() {
+                let color = shadow.color.as_deref().unwrap_or(text_color);
+                let shapes = glyphs
+                    .iter()
+                    .map(|glyph| &glyph.bounds)
+                    .chain(strips.iter().map(|strip| &strip.rect));
+                for shape in shapes {
+                    let offset = Rect {
+                        x: shape.x + shadow.offset_x,
+                        y: shape.y + shadow.offset_y,
+                        ..shape.clone()
+                    };
+                    self.paint_text_quad(paint, &offset, color, &node.clip, shadow.blur_radius);
+                }
+            }
+            for strip in strips.iter().filter(|strip| !strip.kind.paints_over_text()) {
+                self.paint_text_quad(paint, &strip.rect, decoration_color, &node.clip, 0.0);
+            }
+
+            self.text_renderer
+                .render_text(
+                    command_buffer,
+                    text_content,
+                    &node.bounds,
+                    &style.color,
+                    &style.font_family,
+                    style.font_size,
+                )
+                .await?;
+            for glyph in &glyphs {
+                self.paint_glyph(paint, glyph, text_color, &node.clip);
+            }
+            paint.draw_calls += 1;
 
-                self.frame_stats.draw_calls += 1;
+            for strip in strips.iter().filter(|strip| strip.kind.paints_over_text()) {
+                self.paint_text_quad(paint, &strip.rect, decoration_color, &node.clip, 0.0);
             }
         }
         Ok(())
     }
 
-    async fn flush_vertices(
+    /// A glyph: a quad sampling its atlas cell in the node's vertices, and
+    /// its coverage in the snapshot quads.
+    fn paint_glyph(
         &mut self,
-        _command_buffer: vk::CommandBuffer,
-    ) -> Result<(), RenderError> {
-        if self.vertex_buffer.is_empty() {
-            return Ok(());
+        paint: &mut NodePaint,
+        glyph: &PlacedGlyph,
+        color: &str,
+        clip: &ClipChain,
+    ) {
+        let color = parse_color(color);
+        if let Some(cell) = self.glyph_atlas.cell(glyph) {
+            paint.vertices.extend(Self::textured_vertices(
+                &glyph.bounds,
+                &cell,
+                self.glyph_atlas.size(),
+                linearize(color),
+            ));
+        }
+        paint.quads.push(DrawQuad {
+            bounds: glyph.bounds.clone(),
+            color,
+            clip: clip.clone(),
+            blur_radius: 0.0,
+            mask: Some(glyph.mask.clone()),
+            image: None,
+        });
+    }
+
+    /// A decoration strip or shadow shape: a solid quad in the node's
+    /// vertices and its snapshot quads.
+    fn paint_text_quad(
+        &self,
+        paint: &mut NodePaint,
+        bounds: &Rect,
+        color: &str,
+        clip: &ClipChain,
+        blur_radius: f32,
+    ) {
+        let vertices = self.create_rect_vertices(bounds, &Some(color.to_string()));
+        paint.vertices.extend(vertices);
+        Self::record_quad(paint, bounds, color, clip, blur_radius);
+    }
+
+    fn render_overlay(quads: &[DrawQuad], paint: &mut NodePaint) {
+        for quad in quads {
+            let color = linearize(quad.color);
+            let vertices = [
+                [quad.bounds.x, quad.bounds.y],
+                [quad.bounds.x + quad.bounds.width, quad.bounds.y],
+                [
+                    quad.bounds.x + quad.bounds.width,
+                    quad.bounds.y + quad.bounds.height,
+                ],
+                [quad.bounds.x, quad.bounds.y + quad.bounds.height],
+            ]
+            .map(|[x, y]| Vertex {
+                position: [x, y, 0.0],
+                tex_coord: [0.0, 0.0],
+                color,
+            });
+            paint.vertices.extend(vertices);
         }
+    }
+
+    /// Replace the overlay; it shows from the next frame, and in snapshots
+    /// at once.
+    pub fn set_overlay(&mut self, quads: Vec<DrawQuad>) {
+        self.overlay = quads;
+    }
 
-        // Simulate GPU vertex buffer upload
-        let vertex_count = self.vertex_buffer.len();
-        self.vertex_buffer.clear();
+    /// Switch debug overlays on and off; they show from the next frame.
+    pub fn set_debug_overlays(&mut self, flags: DebugOverlayFlags) {
+        self.debug_overlay.set_flags(flags);
+    }
+
+    pub fn debug_overlays(&self) -> DebugOverlayFlags {
+        self.debug_overlay.flags()
+    }
 
+    /// What the debug overlays drew over the last frame, in paint order.
+    pub fn debug_overlay_quads(&self) -> &[DrawQuad] {
+        self.debug_overlay.quads()
+    }
+
+    /// Draw the debug overlays over the frame of `layout_tree`, the HUD's
+    /// text with the text pipeline.
+    async fn render_debug_overlay(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        layout_tree: &LayoutTree,
+        frame_start: std::time::Instant,
+    ) -> Result<(), RenderError> {
+        let config = self.context.get_config();
+        let viewport = (config.viewport_width as f32, config.viewport_height as f32);
+        let label =
+            self.debug_overlay
+                .update(layout_tree, self.damage.as_ref(), viewport, frame_start);
+        if let Some(label) = label {
+            let color = Some(label.color.clone());
+            self.text_renderer
+                .render_text(
+                    command_buffer,
+                    &label.text,
+                    &label.bounds,
+                    &color,
+                    &None,
+                    label.font_size,
+                )
+                .await?;
+            let metrics = FontMetrics::fallback(label.font_size);
+            let color = parse_color(&label.color);
+            let glyphs = glyphs::ink_boxes(&label.text, &label.bounds, &metrics);
+            self.debug_overlay
+                .add_quads(glyphs.into_iter().map(|bounds| DrawQuad {
+                    bounds,
+                    color,
+                    clip: ClipChain::new(),
+                    blur_radius: 0.0,
+                    mask: None,
+                    image: None,
+                }));
+            self.frame_stats.draw_calls += 1;
+        }
+        let mut paint = NodePaint::default();
+        Self::render_overlay(self.debug_overlay.quads(), &mut paint);
+        self.frame_stats.vertices_rendered += paint.vertices.len() as u32;
+        self.frame_stats.draw_calls += 1;
+        Ok(())
+    }
+
+    /// Upload what the frame changed: the slot ranges of patched nodes, and
+    /// the index buffer when the paint order changed.
+    async fn flush_vertices(
+        &mut self,
+        _command_buffer: vk::CommandBuffer,
+    ) -> Result<(), RenderError> {
+        let (vertex_ranges, _indices_changed) = self.scene.take_uploads();
+        self.frame_stats.vertices_uploaded = vertex_ranges
+            .iter()
+            .map(|range| (range.end - range.start) * 4)
+            .sum();
+        let (vertex_version, index_version) = self.scene.buffer_versions();
+        self.frame_stats.vertex_buffer_version = vertex_version;
+        self.frame_stats.index_buffer_version = index_version;
+
+        // Every node draws each frame, patched or not.
+        let vertex_count = self.scene.vertex_count();
+        self.frame_stats.vertices_rendered = vertex_count;
+        self.frame_stats.draw_calls += self.scene.draw_calls();
         self.frame_stats.draw_calls += if vertex_count > 0 { 1 } else { 0 };
 
         Ok(())
     }
 
+    fn record_quad(
+        paint: &mut NodePaint,
+        bounds: &Rect,
+        color: &str,
+        clip: &ClipChain,
+        blur_radius: f32,
+    ) {
+        let color = parse_color(color);
+        paint.quads.push(DrawQuad {
+            bounds: bounds.clone(),
+            color,
+            clip: clip.clone(),
+            blur_radius,
+            mask: None,
+            image: None,
+        });
+    }
+
+    /// Where the last frame differs from the one before it; `None` when it
+    /// does not. A full rebuild damages the whole viewport.
+    pub fn damage(&self) -> Option<&Rect> {
+        self.damage.as_ref()
+    }
+
+    /// Rasterize [`frame_quads`](Self::frame_quads) on the CPU, clips
+    /// included.
+    pub fn snapshot(&self) -> Snapshot {
+        let config = self.context.get_config();
+        Snapshot::rasterize(
+            config.viewport_width,
+            config.viewport_height,
+            &self.frame_quads(),
+        )
+    }
+
+    /// The quads of the last frame in paint order, glyphs and images
+    /// included, then the overlay, and the debug overlays when they ask to
+    /// be in snapshots.
+    pub fn frame_quads(&self) -> Vec<DrawQuad> {
+        let debug_quads = match self.debug_overlay.in_snapshots() {
+            true => self.debug_overlay.quads(),
+            false => &[],
+        };
+        self.scene
+            .page_quads()
+            .chain(&self.streamed)
+            .chain(&self.overlay)
+            .chain(debug_quads)
+            .cloned()
+            .collect()
+    }
+
     fn create_rect_vertices(&self, bounds: &Rect, color: &Option<String>) -> Vec<Vertex> {
         let rgba = color
             .as_ref()
-            .map(|c| self.parse_color(c))
+            .map(|c| parse_color(c))
             .unwrap_or([0.2, 0.2, 0.2, 1.0]); // Default gray
+        Self::solid_vertices(bounds, rgba)
+    }
 
+    fn solid_vertices(bounds: &Rect, rgba: [f32; 4]) -> Vec<Vertex> {
+        let rgba = linearize(rgba);
         vec![
             Vertex {
                 position: [bounds.x, bounds.y, 0.0],
@@ -507,27 +1397,40 @@ impl VulkanRenderer {
         ]
     }
 
-    fn parse_color(&self, color_str: &str) -> [f32; 4] {
-        if color_str.starts_with('#') && color_str.len() == 7 {
-            let parse_hex = |s: &str| u8::from_str_radix(s, 16).unwrap_or(0) as f32 / 255.0;
+    /// A quad showing `source`, in pixels of a texture `size` large,
+    /// tinted by the linear `color`.
+    fn textured_vertices(
+        bounds: &Rect,
+        source: &Rect,
+        size: (u32, u32),
+        color: [f32; 4],
+    ) -> [Vertex; 4] {
+        let (width, height) = (size.0.max(1) as f32, size.1.max(1) as f32);
+        let (u0, v0) = (source.x / width, source.y / height);
+        let (u1, v1) = (
+            (source.x + source.width) / width,
+            (source.y + source.height) / height,
+        );
+        [
+            ([bounds.x, bounds.y], [u0, v0]),
+            ([bounds.x + bounds.width, bounds.y], [u1, v0]),
+            (
+                [bounds.x + bounds.width, bounds.y + bounds.height],
+                [u1, v1],
+            ),
+            ([bounds.x, bounds.y + bounds.height], [u0, v1]),
+        ]
+        .map(|([x, y], tex_coord)| Vertex {
+            position: [x, y, 0.0],
+            tex_coord,
+            color,
+        })
+    }
 
-            [
-                parse_hex(&color_str[1..3]),
-                parse_hex(&color_str[3..5]),
-                parse_hex(&color_str[5..7]),
-                1.0,
-            ]
-        } else {
-            // Named colors
-            match color_str.to_lowercase().as_str() {
-                "red" => [1.0, 0.0, 0.0, 1.0],
-                "green" => [0.0, 1.0, 0.0, 1.0],
-                "blue" => [0.0, 0.0, 1.0, 1.0],
-                "white" => [1.0, 1.0, 1.0, 1.0],
-                "black" => [0.0, 0.0, 0.0, 1.0],
-                _ => [0.0, 0.0, 0.0, 1.0],
-            }
-        }
+    /// The size of the surface frames are drawn to.
+    pub fn viewport_size(&self) -> (u32, u32) {
+        let config = self.context.get_config();
+        (config.viewport_width, config.viewport_height)
     }
 
     pub async fn resize(&mut self, width: u32, height: u32) -> Result<(), RenderError> {
@@ -544,8 +1447,14 @@ impl VulkanRenderer {
         &self.frame_stats
     }
 
+    /// Frames rendered so far.
+    pub fn frame_count(&self) -> u64 {
+        self.context.frame_index as u64
+    }
+
     pub fn get_metrics(&self) -> serde_json::Value {
         serde_json::json!({
+            "backend": format!("{:?}", self.backend),
             "vertices_rendered": self.frame_stats.vertices_rendered,
             "draw_calls": self.frame_stats.draw_calls,
             "texture_binds": self.frame_stats.texture_binds,
@@ -555,7 +1464,12 @@ impl VulkanRenderer {
             } else {
                 0.0
             },
-            "vertex_buffer_size": self.vertex_buffer.len(),
+            "vertex_buffer_size": self.scene.vertex_count(),
+            "nodes_patched": self.frame_stats.nodes_patched,
+            "nodes_rebuilt": self.frame_stats.nodes_rebuilt,
+            "vertices_uploaded": self.frame_stats.vertices_uploaded,
+            "vertex_buffer_version": self.frame_stats.vertex_buffer_version,
+            "index_buffer_version": self.frame_stats.index_buffer_version,
             "frame_index": self.context.frame_index,
         })
     }
@@ -588,3 +1502,45 @@ impl Default for VulkanRenderer {
         })
     }
 }
+
+/// An sRGB color from [`parse_color`] as the linear light vertices carry.
+/// Alpha is not encoded and stays as it is.
+pub fn linearize([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+}
+
+/// RGBA in 0..=1 from a computed color: `#rrggbb`, `rgba(r,g,b,a)` or a
+/// basic named color. Anything else is opaque black.
+pub fn parse_color(color_str: &str) -> [f32; 4] {
+    if color_str.starts_with('#') && color_str.len() == 7 {
+        let parse_hex = |s: &str| u8::from_str_radix(s, 16).unwrap_or(0) as f32 / 255.0;
+
+        [
+            parse_hex(&color_str[1..3]),
+            parse_hex(&color_str[3..5]),
+            parse_hex(&color_str[5..7]),
+            1.0,
+        ]
+    } else if let Some(args) = color_str
+        .strip_prefix("rgba(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        // As written by computed styles: `rgba(r,g,b,a)`.
+        let mut channels = [0.0, 0.0, 0.0, 1.0];
+        for (index, part) in args.split(',').take(4).enumerate() {
+            let value = part.trim().parse::<f32>().unwrap_or(0.0);
+            channels[index] = if index < 3 { value / 255.0 } else { value };
+        }
+        channels
+    } else {
+        // Named colors
+        match color_str.to_lowercase().as_str() {
+            "red" => [1.0, 0.0, 0.0, 1.0],
+            "green" => [0.0, 1.0, 0.0, 1.0],
+            "blue" => [0.0, 0.0, 1.0, 1.0],
+            "white" => [1.0, 1.0, 1.0, 1.0],
+            "black" => [0.0, 0.0, 0.0, 1.0],
+            _ => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! CPU reference rasterizer for the solid quads of a frame.
+//!
+//! Fills each quad by sampling pixel centers against its bounds and
+//! [`ClipChain`], which is what the GPU path computes with scissor rects and
+//! the rounded-clip fragment test. Blurred quads (text shadows) get the
+//! coverage of a gaussian-blurred rect, which is separable into one `erf`
+//! term per axis. Glyphs take their coverage from their mask, and images
+//! their pixels, stretched over their bounds as the GPU samples the atlas
+//! cell or texture. Colors mix in linear light, as the GPU's blending does
+//! on the sRGB framebuffer. Used for screenshots on the software backend
+//! and in tests.
+
+use super::clip::ClipChain;
+use super::glyphs::GlyphMask;
+use super::image::DecodedImage;
+use super::Rect;
+use crate::core::css::color_space::{linear_to_srgb, srgb_to_linear};
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use std::sync::Arc;
+
+/// A filled rect as recorded during a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawQuad {
+    pub bounds: Rect,
+    /// sRGB as CSS gives it; blending decodes it.
+    pub color: [f32; 4],
+    pub clip: ClipChain,
+    /// CSS blur radius; `0.0` for a sharp quad.
+    pub blur_radius: f32,
+    /// A glyph's coverage over `bounds`; `None` for a solid quad. Blurred
+    /// quads are solid.
+    pub mask: Option<Arc<GlyphMask>>,
+    /// Pixels stretched over `bounds`, tinted by `color`; `None` for an
+    /// untextured quad.
+    pub image: Option<Arc<DecodedImage>>,
+}
+
+impl DrawQuad {
+    /// The area the quad can touch: its bounds grown by the blur, cut to
+    /// the scissor. `None` when nothing is left.
+    pub fn painted_area(&self) -> Option<Rect> {
+        let bounds = &self.bounds;
+        // Blur spreads coverage about three standard deviations out.
+        let spread = self.blur_radius.max(0.0) / 2.0 * 3.0;
+        let mut area = Rect {
+            x: bounds.x - spread,
+            y: bounds.y - spread,
+            width: bounds.width + spread * 2.0,
+            height: bounds.height + spread * 2.0,
+        };
+        if let Some(scissor) = self.clip.scissor() {
+            let right = (area.x + area.width).min(scissor.x + scissor.width);
+            let bottom = (area.y + area.height).min(scissor.y + scissor.height);
+            area.x = area.x.max(scissor.x);
+            area.y = area.y.max(scissor.y);
+            area.width = right - area.x;
+            area.height = bottom - area.y;
+        }
+        (area.width > 0.0 && area.height > 0.0).then_some(area)
+    }
+}
+
+/// RGBA8 pixels, row-major, starting out fully transparent.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn rasterize(width: u32, height: u32, quads: &[DrawQuad]) -> Self {
+        let mut snapshot = Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        };
+        for quad in quads {
+            snapshot.fill(quad);
+        }
+        snapshot
+    }
+
+    /// The pixel at `(x, y)`; transparent outside the image.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        if x >= self.width || y >= self.height {
+            return [0; 4];
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 4;
+        [
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+            self.data[offset + 3],
+        ]
+    }
+
+    /// Composite the image onto an opaque `background`, mixing in linear
+    /// light as [`Self::rasterize`] does, so no pixel is left see-through.
+    pub fn flatten(&mut self, background: [u8; 3]) {
+        let background = background.map(|c| srgb_to_linear(c as f32 / 255.0));
+        for pixel in self.data.chunks_exact_mut(4) {
+            let a = pixel[3] as f32 / 255.0;
+            if a < 1.0 {
+                for (channel, bg) in pixel.iter_mut().take(3).zip(background) {
+                    let src = srgb_to_linear(*channel as f32 / 255.0);
+                    let out = src * a + bg * (1.0 - a);
+                    *channel = (linear_to_srgb(out) * 255.0).round() as u8;
+                }
+            }
+            pixel[3] = 255;
+        }
+    }
+
+    /// The image encoded as a PNG.
+    pub fn to_png(&self) -> Result<Vec<u8>, image::ImageError> {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).write_image(
+            &self.data,
+            self.width,
+            self.height,
+            ColorType::Rgba8,
+        )?;
+        Ok(png)
+    }
+
+    fn fill(&mut self, quad: &DrawQuad) {
+        let bounds = &quad.bounds;
+        let sigma = quad.blur_radius.max(0.0) / 2.0;
+        let area = match quad.painted_area() {
+            Some(area) => area,
+            None => return,
+        };
+
+        // Pixels whose centers fall inside the area.
+        let x0 = (area.x - 0.5).ceil().max(0.0) as u32;
+        let y0 = (area.y - 0.5).ceil().max(0.0) as u32;
+        let x1 = ((area.x + area.width - 0.5).ceil().max(0.0) as u32).min(self.width);
+        let y1 = ((area.y + area.height - 0.5).ceil().max(0.0) as u32).min(self.height);
+
+        let [r, g, b, alpha] = quad.color;
+        let linear = [r, g, b].map(srgb_to_linear);
+        let opaque = [r, g, b].map(|c| (c * 255.0).round() as u8);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                if !quad.clip.contains(px, py) {
+                    continue;
+                }
+                let (u, v) = (
+                    (px - bounds.x) / bounds.width,
+                    (py - bounds.y) / bounds.height,
+                );
+                let coverage = if sigma > 0.0 {
+                    axis_coverage(px, bounds.x, bounds.x + bounds.width, sigma)
+                        * axis_coverage(py, bounds.y, bounds.y + bounds.height, sigma)
+                } else if let Some(mask) = &quad.mask {
+                    mask.sample(u, v)
+                } else {
+                    1.0
+                };
+                let offset = (y as usize * self.width as usize + x as usize) * 4;
+                let dst = &mut self.data[offset..offset + 4];
+                match &quad.image {
+                    Some(image) if sigma <= 0.0 => {
+                        let [ir, ig, ib, ia] = image.sample(u, v);
+                        let tinted = [r * ir, g * ig, b * ib];
+                        blend(
+                            dst,
+                            tinted.map(|c| (c * 255.0).round() as u8),
+                            tinted.map(srgb_to_linear),
+                            alpha * ia * coverage,
+                        );
+                    }
+                    _ => blend(dst, opaque, linear, alpha * coverage),
+                }
+            }
+        }
+    }
+}
+
+/// Source-over of `a` coverage onto the `dst` pixel, `opaque` when it
+/// covers it whole, on straight alpha, in linear light: the pixels are
+/// sRGB, decoded to mix and encoded again after.
+fn blend(dst: &mut [u8], opaque: [u8; 3], linear: [f32; 3], a: f32) {
+    if a <= 0.0 {
+        return;
+    }
+    if a >= 1.0 {
+        dst[..3].copy_from_slice(&opaque);
+        dst[3] = 255;
+        return;
+    }
+    let dst_a = dst[3] as f32 / 255.0;
+    let out_a = a + dst_a * (1.0 - a);
+    for (channel, src) in dst.iter_mut().take(3).zip(linear) {
+        let dst_c = srgb_to_linear(*channel as f32 / 255.0);
+        let out = if out_a > 0.0 {
+            (src * a + dst_c * dst_a * (1.0 - a)) / out_a
+        } else {
+            0.0
+        };
+        *channel = (linear_to_srgb(out) * 255.0).round() as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+/// Share of a unit gaussian centered at `p` that falls within
+/// `start..end`.
+fn axis_coverage(p: f32, start: f32, end: f32, sigma: f32) -> f32 {
+    let scale = sigma * std::f32::consts::SQRT_2;
+    0.5 * (erf((p - start) / scale) - erf((p - end) / scale))
+}
+
+/// Abramowitz and Stegun 7.1.26; absolute error below 1.5e-7.
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t
+        * (0.254_829_6
+            + t * (-0.284_496_74 + t * (1.421_413_7 + t * (-1.453_152_1 + t * 1.061_405_4))));
+    let value = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
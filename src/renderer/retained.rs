@@ -0,0 +1,496 @@
+//! Paint state retained from one frame to the next.
+//!
+//! Each entry of a frame (a box, a line of text, the overlay) keeps the
+//! quad slots it was painted into in one persistent vertex buffer; the
+//! index buffer lists those slots in paint order. A new frame is diffed
+//! against the entries of the last one, and only added, moved or restyled
+//! entries are painted again, their vertices written over their own slots.
+//! The index buffer is rewritten only when the paint order or an entry's
+//! slot count changed. Freed slots wait on a free list for the next entry
+//! that needs room, and the buffers are reallocated only when they run out
+//! of it, which is what their versions count.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+use super::{DrawQuad, LayoutNode, LayoutTree, Rect, Vertex};
+use crate::core::dom::NodeId;
+
+/// Vertices per quad slot.
+const QUAD_VERTICES: usize = 4;
+
+/// Slots the vertex buffer starts with, and grows by at least.
+const MIN_QUAD_SLOTS: u32 = 256;
+
+const EMPTY_VERTEX: Vertex = Vertex {
+    position: [0.0; 3],
+    tex_coord: [0.0; 2],
+    color: [0.0; 4],
+};
+
+/// Names what one frame entry paints across frames. A node painted by
+/// several entries, a text node with a line box each, has them told apart
+/// by their order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaintKey {
+    Box(NodeId, u32),
+    Text(NodeId, u32),
+    /// Quads drawn over the page, such as the inspector's highlight.
+    Overlay,
+}
+
+/// What a frame entry paints from.
+#[derive(Debug, Clone, Copy)]
+pub enum PaintSource<'a> {
+    Node(&'a LayoutNode),
+    Overlay(&'a [DrawQuad]),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameEntry<'a> {
+    pub key: PaintKey,
+    pub source: PaintSource<'a>,
+}
+
+/// The entries of a frame in paint order: boxes, then text, then the
+/// overlay when there is one.
+pub fn frame_entries<'a>(tree: &'a LayoutTree, overlay: &'a [DrawQuad]) -> Vec<FrameEntry<'a>> {
+    let boxes = tree.get_render_nodes();
+    let texts = tree.get_text_nodes();
+    let mut entries = Vec::with_capacity(boxes.len() + texts.len() + 1);
+    let mut seen: HashMap<NodeId, u32> = HashMap::new();
+    for node in boxes {
+        let ordinal = seen.entry(node.node_id).or_default();
+        entries.push(FrameEntry {
+            key: PaintKey::Box(node.node_id, *ordinal),
+            source: PaintSource::Node(node),
+        });
+        *ordinal += 1;
+    }
+    seen.clear();
+    for node in texts {
+        let ordinal = seen.entry(node.node_id).or_default();
+        entries.push(FrameEntry {
+            key: PaintKey::Text(node.node_id, *ordinal),
+            source: PaintSource::Node(node),
+        });
+        *ordinal += 1;
+    }
+    if !overlay.is_empty() {
+        entries.push(FrameEntry {
+            key: PaintKey::Overlay,
+            source: PaintSource::Overlay(overlay),
+        });
+    }
+    entries
+}
+
+/// What painting one entry produced.
+#[derive(Debug, Clone, Default)]
+pub struct NodePaint {
+    /// Whole quads, four vertices each.
+    pub vertices: Vec<Vertex>,
+    /// The solid quads among them, for snapshots.
+    pub quads: Vec<DrawQuad>,
+    pub draw_calls: u32,
+}
+
+/// How a frame differs from the last one.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub added: Vec<PaintKey>,
+    pub removed: Vec<PaintKey>,
+    /// Entries that only changed bounds.
+    pub moved: Vec<PaintKey>,
+    /// Entries whose style, text, image or clip changed.
+    pub restyled: Vec<PaintKey>,
+    /// The entries are not in the last frame's order.
+    pub reordered: bool,
+    repaint: HashSet<PaintKey>,
+}
+
+impl ChangeSet {
+    /// Whether `key` is painted again this frame.
+    pub fn repaints(&self, key: PaintKey) -> bool {
+        self.repaint.contains(&key)
+    }
+
+    /// Paint `key` again though it did not change, as what an animated
+    /// paint source draws for it did.
+    pub fn force_repaint(&mut self, key: PaintKey) {
+        if self.repaint.insert(key) {
+            self.restyled.push(key);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repaint.is_empty() && self.removed.is_empty() && !self.reordered
+    }
+}
+
+/// What bringing the scene up to a frame cost.
+#[derive(Debug, Clone, Default)]
+pub struct FrameUpdate {
+    /// Entries painted into slots they kept or took from the free list.
+    pub nodes_patched: u32,
+    /// Entries painted into freshly allocated buffers.
+    pub nodes_rebuilt: u32,
+    /// Where the frame can differ from the last one; `None` when nowhere.
+    pub damage: Option<Rect>,
+}
+
+/// Vertex storage in quad-sized slots, as mirrored into the GPU buffer.
+#[derive(Debug, Default)]
+struct QuadSlots {
+    vertices: Vec<Vertex>,
+    /// Slots handed out at least once; the rest of the buffer is unused.
+    used: u32,
+    free: Vec<u32>,
+    version: u64,
+    /// Slot ranges written since the last upload.
+    dirty: Vec<Range<u32>>,
+}
+
+impl QuadSlots {
+    fn capacity(&self) -> u32 {
+        (self.vertices.len() / QUAD_VERTICES) as u32
+    }
+
+    fn allocate(&mut self) -> u32 {
+        if let Some(slot) = self.free.pop() {
+            return slot;
+        }
+        if self.used == self.capacity() {
+            // A new buffer: everything in use is uploaded into it.
+            let capacity = (self.capacity() * 2).max(MIN_QUAD_SLOTS);
+            self.vertices
+                .resize(capacity as usize * QUAD_VERTICES, EMPTY_VERTEX);
+            self.version += 1;
+            self.dirty.clear();
+            if self.used > 0 {
+                self.dirty.push(0..self.used);
+            }
+        }
+        self.used += 1;
+        self.used - 1
+    }
+
+    fn release(&mut self, slot: u32) {
+        self.free.push(slot);
+    }
+
+    fn write(&mut self, slot: u32, quad: &[Vertex]) {
+        let start = slot as usize * QUAD_VERTICES;
+        self.vertices[start..start + QUAD_VERTICES].clone_from_slice(quad);
+        match self.dirty.last_mut() {
+            Some(range) if range.start <= slot && slot <= range.end => {
+                range.end = range.end.max(slot + 1);
+            }
+            _ => self.dirty.push(slot..slot + 1),
+        }
+    }
+}
+
+/// Six indices per slot, two triangles, in paint order.
+#[derive(Debug, Default)]
+struct QuadIndices {
+    indices: Vec<u32>,
+    capacity: usize,
+    version: u64,
+    dirty: bool,
+}
+
+impl QuadIndices {
+    fn rewrite(&mut self, slots: impl Iterator<Item = u32>) {
+        self.indices.clear();
+        for slot in slots {
+            let base = slot * QUAD_VERTICES as u32;
+            self.indices
+                .extend([base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        if self.indices.len() > self.capacity {
+            self.capacity = (self.capacity * 2)
+                .max(self.indices.len())
+                .max(MIN_QUAD_SLOTS as usize * 6);
+            self.version += 1;
+        }
+        self.dirty = true;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Painted {
+    Node(Box<LayoutNode>),
+    Overlay(Vec<DrawQuad>),
+}
+
+impl Painted {
+    fn from_source(source: PaintSource<'_>) -> Self {
+        match source {
+            PaintSource::Node(node) => Self::Node(Box::new(node.clone())),
+            PaintSource::Overlay(quads) => Self::Overlay(quads.to_vec()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    painted: Painted,
+    slots: Vec<u32>,
+    quads: Vec<DrawQuad>,
+    draw_calls: u32,
+}
+
+/// The last frame's entries and the buffers they are painted into.
+#[derive(Debug)]
+pub struct RetainedScene {
+    entries: HashMap<PaintKey, Entry>,
+    order: Vec<PaintKey>,
+    slots: QuadSlots,
+    indices: QuadIndices,
+}
+
+impl Default for RetainedScene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetainedScene {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: Vec::new(),
+            slots: QuadSlots::default(),
+            indices: QuadIndices::default(),
+        }
+    }
+
+    /// Compare a frame against the retained one.
+    pub fn diff(&self, frame: &[FrameEntry<'_>]) -> ChangeSet {
+        let mut changes = ChangeSet {
+            reordered: frame.len() != self.order.len()
+                || frame.iter().zip(&self.order).any(|(e, key)| e.key != *key),
+            ..Default::default()
+        };
+        for entry in frame {
+            let old = match self.entries.get(&entry.key) {
+                Some(old) => old,
+                None => {
+                    changes.added.push(entry.key);
+                    changes.repaint.insert(entry.key);
+                    continue;
+                }
+            };
+            match (&old.painted, entry.source) {
+                (Painted::Node(old), PaintSource::Node(new)) if **old == *new => {}
+                (Painted::Node(old), PaintSource::Node(new)) if same_but_bounds(old, new) => {
+                    changes.moved.push(entry.key);
+                    changes.repaint.insert(entry.key);
+                }
+                (Painted::Overlay(old), PaintSource::Overlay(new)) if old.as_slice() == new => {}
+                _ => {
+                    changes.restyled.push(entry.key);
+                    changes.repaint.insert(entry.key);
+                }
+            }
+        }
+        // A frame with entries added or removed is never in the old order.
+        if changes.reordered {
+            let current: HashSet<PaintKey> = frame.iter().map(|entry| entry.key).collect();
+            changes.removed = self
+                .order
+                .iter()
+                .filter(|key| !current.contains(key))
+                .copied()
+                .collect();
+        }
+        changes
+    }
+
+    /// Bring the scene up to `frame`, given the paint of every entry
+    /// `changes` repaints.
+    pub fn apply(
+        &mut self,
+        frame: &[FrameEntry<'_>],
+        changes: &ChangeSet,
+        mut painted: HashMap<PaintKey, NodePaint>,
+    ) -> FrameUpdate {
+        let version = self.slots.version;
+        let mut update = FrameUpdate::default();
+        let mut relinked = changes.reordered;
+
+        // Removed entries go first so that their slots are reused.
+        for key in &changes.removed {
+            if let Some(entry) = self.entries.remove(key) {
+                for slot in entry.slots {
+                    self.slots.release(slot);
+                }
+                update.damage = union_quads(update.damage, &entry.quads);
+            }
+        }
+        for entry in frame {
+            let paint = match painted.remove(&entry.key) {
+                Some(paint) => paint,
+                None => continue,
+            };
+            let needed = paint.vertices.len() / QUAD_VERTICES;
+            let mut slots = match self.entries.remove(&entry.key) {
+                Some(old) => {
+                    update.damage = union_quads(update.damage, &old.quads);
+                    old.slots
+                }
+                None => Vec::new(),
+            };
+            if slots.len() != needed {
+                for slot in slots.drain(..) {
+                    self.slots.release(slot);
+                }
+                slots.extend((0..needed).map(|_| self.slots.allocate()));
+                relinked = true;
+            }
+            for (slot, quad) in slots.iter().zip(paint.vertices.chunks(QUAD_VERTICES)) {
+                self.slots.write(*slot, quad);
+            }
+            update.damage = union_quads(update.damage, &paint.quads);
+            update.nodes_patched += 1;
+            self.entries.insert(
+                entry.key,
+                Entry {
+                    painted: Painted::from_source(entry.source),
+                    slots,
+                    quads: paint.quads,
+                    draw_calls: paint.draw_calls,
+                },
+            );
+        }
+
+        if relinked {
+            self.order = frame.iter().map(|entry| entry.key).collect();
+            self.relink();
+        }
+        // Growing the vertex buffer re-uploaded every entry in it.
+        if self.slots.version != version {
+            update.nodes_rebuilt = self.entries.len() as u32 - update.nodes_patched;
+        }
+        update
+    }
+
+    /// Drop everything retained and paint `frame` into new buffers; the
+    /// reference the patched path is checked against.
+    pub fn rebuild(
+        &mut self,
+        frame: &[FrameEntry<'_>],
+        mut painted: HashMap<PaintKey, NodePaint>,
+    ) -> FrameUpdate {
+        let mut update = FrameUpdate::default();
+        for entry in self.entries.values() {
+            update.damage = union_quads(update.damage, &entry.quads);
+        }
+        let (vertex_version, index_version) = self.buffer_versions();
+        self.entries.clear();
+        self.slots = QuadSlots::default();
+        self.indices = QuadIndices::default();
+
+        for entry in frame {
+            let paint = painted.remove(&entry.key).unwrap_or_default();
+            let slots: Vec<u32> = paint
+                .vertices
+                .chunks(QUAD_VERTICES)
+                .map(|quad| {
+                    let slot = self.slots.allocate();
+                    self.slots.write(slot, quad);
+                    slot
+                })
+                .collect();
+            update.damage = union_quads(update.damage, &paint.quads);
+            update.nodes_rebuilt += 1;
+            self.entries.insert(
+                entry.key,
+                Entry {
+                    painted: Painted::from_source(entry.source),
+                    slots,
+                    quads: paint.quads,
+                    draw_calls: paint.draw_calls,
+                },
+            );
+        }
+        self.order = frame.iter().map(|entry| entry.key).collect();
+        self.relink();
+        // Sized while filled, each buffer is one new allocation.
+        self.slots.version = vertex_version + 1;
+        self.indices.version = index_version + 1;
+        update
+    }
+
+    fn relink(&mut self) {
+        let entries = &self.entries;
+        let slots = self
+            .order
+            .iter()
+            .filter_map(|key| entries.get(key))
+            .flat_map(|entry| entry.slots.iter().copied());
+        self.indices.rewrite(slots);
+    }
+
+    /// The solid quads of the page in paint order, without the overlay.
+    pub fn page_quads(&self) -> impl Iterator<Item = &DrawQuad> {
+        self.order
+            .iter()
+            .filter(|key| **key != PaintKey::Overlay)
+            .filter_map(|key| self.entries.get(key))
+            .flat_map(|entry| entry.quads.iter())
+    }
+
+    /// Vertices the index buffer draws.
+    pub fn vertex_count(&self) -> u32 {
+        (self.indices.indices.len() / 6 * QUAD_VERTICES) as u32
+    }
+
+    pub fn draw_calls(&self) -> u32 {
+        self.entries.values().map(|entry| entry.draw_calls).sum()
+    }
+
+    /// Versions of the vertex and index buffers; each reallocation bumps
+    /// its buffer's.
+    pub fn buffer_versions(&self) -> (u64, u64) {
+        (self.slots.version, self.indices.version)
+    }
+
+    /// Hand over what changed since the last upload: the vertex slot
+    /// ranges to copy, and whether the index buffer is to be copied.
+    pub fn take_uploads(&mut self) -> (Vec<Range<u32>>, bool) {
+        let indices = std::mem::take(&mut self.indices.dirty);
+        (std::mem::take(&mut self.slots.dirty), indices)
+    }
+}
+
+/// Everything about the node but where it is.
+fn same_but_bounds(old: &LayoutNode, new: &LayoutNode) -> bool {
+    old.node_id == new.node_id
+        && old.element_type == new.element_type
+        && old.style == new.style
+        && old.text_content == new.text_content
+        && old.image_url == new.image_url
+        && old.image_frame == new.image_frame
+        && old.clip == new.clip
+}
+
+fn union_quads(damage: Option<Rect>, quads: &[DrawQuad]) -> Option<Rect> {
+    quads
+        .iter()
+        .filter_map(DrawQuad::painted_area)
+        .fold(damage, |damage, area| match damage {
+            Some(damage) => {
+                let x = damage.x.min(area.x);
+                let y = damage.y.min(area.y);
+                Some(Rect {
+                    x,
+                    y,
+                    width: (damage.x + damage.width).max(area.x + area.width) - x,
+                    height: (damage.y + damage.height).max(area.y + area.height) - y,
+                })
+            }
+            None => Some(area),
+        })
+}
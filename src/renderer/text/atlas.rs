@@ -1,16 +1,55 @@
+use crate::core::fonts::{FontInstance, VariationCoords};
 use crate::renderer::gpu::Texture;
 use rusttype::{point, Glyph, Scale};
 use std::collections::HashMap;
+use swash::scale::{Render, ScaleContext, Source};
 
 pub struct FontAtlas {
     width: u32,
     height: u32,
     texture: Option<Texture>,
-    glyph_cache: HashMap<char, GlyphCoords>,
+    glyph_cache: HashMap<GlyphKey, GlyphCoords>,
     current_x: u32,
     current_y: u32,
     row_height: u32,
     data: Vec<u8>,
+    scale_context: ScaleContext,
+}
+
+/// What a rasterized glyph is cached under. A face drawn at two sizes or at
+/// two positions on its variation axes, such as two weights of a variable
+/// font, keeps an entry for each.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    /// [`FontInstance::face`] of a web font, or [`GlyphKey::SYSTEM_FACE`].
+    pub face: u64,
+    pub glyph: u16,
+    /// Pixels per em, as its bits.
+    size: u32,
+    pub coords: VariationCoords,
+}
+
+impl GlyphKey {
+    /// The face of the renderer's own fonts. Web font faces count from 1.
+    pub const SYSTEM_FACE: u64 = 0;
+
+    pub fn new(face: u64, glyph: u16, font_size: f32, coords: VariationCoords) -> Self {
+        Self {
+            face,
+            glyph,
+            size: font_size.to_bits(),
+            coords,
+        }
+    }
+
+    /// Glyph `glyph` of `instance` at `font_size`.
+    pub fn for_instance(instance: &FontInstance, glyph: u16, font_size: f32) -> Self {
+        Self::new(instance.face, glyph, font_size, instance.coords.clone())
+    }
+
+    pub fn font_size(&self) -> f32 {
+        f32::from_bits(self.size)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,16 +75,28 @@ impl FontAtlas {
             current_y: 0,
             row_height: 0,
             data,
+            scale_context: ScaleContext::new(),
         })
     }
 
+    /// Key of `glyph` of the renderer's own font at `scale`.
+    pub fn system_key(glyph: &Glyph<'_>, scale: Scale) -> GlyphKey {
+        GlyphKey::new(
+            GlyphKey::SYSTEM_FACE,
+            glyph.id().0,
+            scale.y,
+            VariationCoords::default(),
+        )
+    }
+
     pub fn get_or_cache_glyph(
         &mut self,
         character: char,
         glyph: &Glyph<'_>,
         scale: Scale,
     ) -> Result<GlyphCoords, AtlasError> {
-        if let Some(coords) = self.glyph_cache.get(&character) {
+        let key = Self::system_key(glyph, scale);
+        if let Some(coords) = self.glyph_cache.get(&key) {
             return Ok(coords.clone());
         }
 
@@ -66,7 +117,7 @@ impl FontAtlas {
                     width: 0,
                     height: 0,
                 };
-                self.glyph_cache.insert(character, coords.clone());
+                self.glyph_cache.insert(key, coords.clone());
                 return Ok(coords);
             }
 
@@ -82,13 +133,69 @@ impl FontAtlas {
                 height: glyph_height,
             };
 
-            self.glyph_cache.insert(character, coords.clone());
+            self.glyph_cache.insert(key, coords.clone());
             Ok(coords)
         } else {
             Err(AtlasError::GlyphRasterizationFailed(character))
         }
     }
 
+    /// Rasterize glyph `glyph` of a web font `instance` at `font_size`
+    /// unless it is cached. Variations are applied as the outline is
+    /// generated, so each weight of a variable face gets its own bitmap.
+    pub fn get_or_cache_instance_glyph(
+        &mut self,
+        instance: &FontInstance,
+        glyph: u16,
+        font_size: f32,
+    ) -> Result<GlyphCoords, AtlasError> {
+        let key = GlyphKey::for_instance(instance, glyph, font_size);
+        if let Some(coords) = self.glyph_cache.get(&key) {
+            return Ok(coords.clone());
+        }
+
+        let font = swash::FontRef::from_index(&instance.font.data, instance.font.index as usize)
+            .ok_or(AtlasError::OutlineRasterizationFailed(glyph))?;
+        let mut scaler = self
+            .scale_context
+            .builder(font)
+            .size(font_size)
+            .variations(instance.coords.iter())
+            .build();
+        let image = Render::new(&[Source::Outline])
+            .render(&mut scaler, glyph)
+            .ok_or(AtlasError::OutlineRasterizationFailed(glyph))?;
+
+        let (glyph_width, glyph_height) = (image.placement.width, image.placement.height);
+        let coords = if glyph_width == 0 || glyph_height == 0 {
+            GlyphCoords {
+                u_min: 0.0,
+                v_min: 0.0,
+                u_max: 0.0,
+                v_max: 0.0,
+                width: 0,
+                height: 0,
+            }
+        } else {
+            let (atlas_x, atlas_y) = self.allocate_space(glyph_width, glyph_height)?;
+            for (row, alpha) in image.data.chunks(glyph_width as usize).enumerate() {
+                let start = ((atlas_y + row as u32) * self.width + atlas_x) as usize;
+                self.data[start..start + alpha.len()].copy_from_slice(alpha);
+            }
+            GlyphCoords {
+                u_min: atlas_x as f32 / self.width as f32,
+                v_min: atlas_y as f32 / self.height as f32,
+                u_max: (atlas_x + glyph_width) as f32 / self.width as f32,
+                v_max: (atlas_y + glyph_height) as f32 / self.height as f32,
+                width: glyph_width,
+                height: glyph_height,
+            }
+        };
+
+        self.glyph_cache.insert(key, coords.clone());
+        Ok(coords)
+    }
+
     fn allocate_space(&mut self, width: u32, height: u32) -> Result<(u32, u32), AtlasError> {
         if self.current_x + width > self.width {
             self.current_x = 0;
@@ -128,8 +235,8 @@ impl FontAtlas {
         Ok(())
     }
 
-    pub fn get_glyph_coords(&self, character: char) -> Option<&GlyphCoords> {
-        self.glyph_cache.get(&character)
+    pub fn get_glyph_coords(&self, key: &GlyphKey) -> Option<&GlyphCoords> {
+        self.glyph_cache.get(key)
     }
 
     pub fn get_texture(&self) -> &Texture {
@@ -184,6 +291,112 @@ impl FontAtlas {
     }
 }
 
+/// RGBA8 atlas for color glyphs, kept apart from the alpha-only text atlas
+/// so emoji are sampled with their own colors rather than tinted.
+pub struct ColorAtlas {
+    width: u32,
+    height: u32,
+    entries: HashMap<String, GlyphCoords>,
+    current_x: u32,
+    current_y: u32,
+    row_height: u32,
+    data: Vec<u8>,
+}
+
+impl ColorAtlas {
+    pub fn new(width: u32, height: u32) -> Result<Self, AtlasError> {
+        if width == 0 || height == 0 {
+            return Err(AtlasError::InvalidDimensions);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            entries: HashMap::new(),
+            current_x: 0,
+            current_y: 0,
+            row_height: 0,
+            data: vec![0u8; (width * height * 4) as usize],
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&GlyphCoords> {
+        self.entries.get(key)
+    }
+
+    /// Copy a `width * height` RGBA8 image into the atlas under `key`.
+    pub fn insert(
+        &mut self,
+        key: &str,
+        width: u32,
+        height: u32,
+        rgba: &[u8],
+    ) -> Result<GlyphCoords, AtlasError> {
+        if let Some(coords) = self.entries.get(key) {
+            return Ok(coords.clone());
+        }
+        if rgba.len() < (width * height * 4) as usize {
+            return Err(AtlasError::InvalidDimensions);
+        }
+
+        if self.current_x + width > self.width {
+            self.current_x = 0;
+            self.current_y += self.row_height;
+            self.row_height = 0;
+        }
+        if width > self.width || self.current_y + height > self.height {
+            return Err(AtlasError::AtlasFull);
+        }
+
+        let (x, y) = (self.current_x, self.current_y);
+        self.current_x += width;
+        self.row_height = self.row_height.max(height);
+
+        let row_bytes = (width * 4) as usize;
+        for row in 0..height {
+            let src = (row * width * 4) as usize;
+            let dst = (((y + row) * self.width + x) * 4) as usize;
+            self.data[dst..dst + row_bytes].copy_from_slice(&rgba[src..src + row_bytes]);
+        }
+
+        let coords = GlyphCoords {
+            u_min: x as f32 / self.width as f32,
+            v_min: y as f32 / self.height as f32,
+            u_max: (x + width) as f32 / self.width as f32,
+            v_max: (y + height) as f32 / self.height as f32,
+            width,
+            height,
+        };
+        self.entries.insert(key.to_string(), coords.clone());
+        Ok(coords)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Raw RGBA8 pixels, row-major, for upload to the color glyph texture.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get_atlas_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.current_x = 0;
+        self.current_y = 0;
+        self.row_height = 0;
+        self.data.fill(0);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AtlasUsageStats {
     pub used_pixels: u32,
@@ -198,6 +411,8 @@ pub enum AtlasError {
     AtlasFull,
     #[error("Glyph rasterization failed for character: {0}")]
     GlyphRasterizationFailed(char),
+    #[error("Glyph rasterization failed for glyph id: {0}")]
+    OutlineRasterizationFailed(u16),
     #[error("Texture creation failed")]
     TextureCreationFailed,
     #[error("Invalid atlas dimensions")]
@@ -0,0 +1,354 @@
+//! Color glyphs for emoji and other codepoints the primary font lacks.
+//!
+//! Clusters are shaped with rustybuzz so ZWJ and flag sequences collapse to
+//! the font's ligature glyph, then rasterized from an embedded PNG strike
+//! (CBDT/sbix) or from COLRv0 layers into the RGBA [`ColorAtlas`].
+
+use super::atlas::{AtlasError, ColorAtlas, GlyphCoords};
+use super::TextError;
+use rusttype::{point, Scale};
+use std::collections::HashMap;
+
+/// One font in the color fallback chain.
+pub struct ColorFont {
+    data: Vec<u8>,
+    outlines: rusttype::Font<'static>,
+}
+
+/// A rasterized cluster in straight-alpha RGBA8.
+#[derive(Debug, Clone)]
+pub struct ColorGlyphImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+    /// Distance from the top of the image to the baseline.
+    pub ascent: f32,
+    pub advance: f32,
+}
+
+/// A color cluster placed in the atlas.
+#[derive(Debug, Clone)]
+pub struct ColorGlyph {
+    pub key: String,
+    pub coords: GlyphCoords,
+    pub width: f32,
+    pub height: f32,
+    pub ascent: f32,
+    pub advance: f32,
+}
+
+struct ShapedGlyph {
+    glyph_id: u16,
+    x: f32,
+    y: f32,
+}
+
+/// COLRv0 layers in paint order; `None` means the foreground color.
+#[derive(Default)]
+struct LayerCollector {
+    current: Option<ttf_parser::GlyphId>,
+    layers: Vec<(ttf_parser::GlyphId, Option<ttf_parser::RgbaColor>)>,
+}
+
+impl ttf_parser::colr::Painter for LayerCollector {
+    fn outline(&mut self, glyph_id: ttf_parser::GlyphId) {
+        self.current = Some(glyph_id);
+    }
+
+    fn paint_foreground(&mut self) {
+        if let Some(glyph_id) = self.current.take() {
+            self.layers.push((glyph_id, None));
+        }
+    }
+
+    fn paint_color(&mut self, color: ttf_parser::RgbaColor) {
+        if let Some(glyph_id) = self.current.take() {
+            self.layers.push((glyph_id, Some(color)));
+        }
+    }
+}
+
+impl ColorFont {
+    pub fn from_data(data: Vec<u8>) -> Result<Self, TextError> {
+        if rustybuzz::Face::from_slice(&data, 0).is_none() {
+            return Err(TextError::FontLoadError(
+                "Unparseable color font".to_string(),
+            ));
+        }
+        let outlines = rusttype::Font::try_from_vec(data.clone())
+            .ok_or_else(|| TextError::FontLoadError("Unparseable color font".to_string()))?;
+
+        Ok(Self { data, outlines })
+    }
+
+    /// Whether the font carries COLR or bitmap color tables.
+    pub fn has_color_tables(&self) -> bool {
+        rustybuzz::Face::from_slice(&self.data, 0)
+            .map(|face| {
+                let tables = face.tables();
+                tables.colr.is_some() || tables.cbdt.is_some() || tables.sbix.is_some()
+            })
+            .unwrap_or(false)
+    }
+
+    /// Shape `cluster` and return its glyphs in font units plus the total
+    /// advance, or `None` when any codepoint is missing from the font.
+    fn shape(&self, cluster: &str) -> Option<(Vec<ShapedGlyph>, f32)> {
+        let face = rustybuzz::Face::from_slice(&self.data, 0)?;
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(cluster);
+        buffer.guess_segment_properties();
+        let shaped = rustybuzz::shape(&face, &[], buffer);
+
+        let mut glyphs = Vec::new();
+        let mut pen = 0.0f32;
+        for (info, pos) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+            if info.glyph_id == 0 {
+                return None;
+            }
+            glyphs.push(ShapedGlyph {
+                glyph_id: info.glyph_id as u16,
+                x: pen + pos.x_offset as f32,
+                y: pos.y_offset as f32,
+            });
+            pen += pos.x_advance as f32;
+        }
+
+        if glyphs.is_empty() {
+            None
+        } else {
+            Some((glyphs, pen))
+        }
+    }
+
+    /// Advance of `cluster` at `size` pixels per em.
+    pub fn advance(&self, cluster: &str, size: f32) -> Option<f32> {
+        let face = rustybuzz::Face::from_slice(&self.data, 0)?;
+        let (_, advance) = self.shape(cluster)?;
+        Some(advance * size / face.units_per_em() as f32)
+    }
+
+    /// Rasterize `cluster` at `size` pixels per em, baseline at `ascent`.
+    pub fn rasterize(&self, cluster: &str, size: f32) -> Option<ColorGlyphImage> {
+        let face = rustybuzz::Face::from_slice(&self.data, 0)?;
+        let (glyphs, advance) = self.shape(cluster)?;
+
+        let units = size / face.units_per_em() as f32;
+        let ascent = face.ascender() as f32 * units;
+        let descent = face.descender() as f32 * units;
+        let width = (advance * units).ceil().max(1.0) as u32;
+        let height = (ascent - descent).ceil().max(1.0) as u32;
+        let mut canvas = vec![0u8; (width * height * 4) as usize];
+
+        for glyph in &glyphs {
+            let origin_x = glyph.x * units;
+            let baseline = ascent - glyph.y * units;
+            let glyph_id = ttf_parser::GlyphId(glyph.glyph_id);
+
+            if let Some(image) = face.glyph_raster_image(glyph_id, size.round().max(1.0) as u16) {
+                if image.format == ttf_parser::RasterImageFormat::PNG {
+                    let decoded = match image::load_from_memory_with_format(
+                        image.data,
+                        image::ImageFormat::Png,
+                    ) {
+                        Ok(decoded) => decoded.to_rgba8(),
+                        Err(_) => continue,
+                    };
+                    // Strikes come in fixed sizes; scale to the requested em.
+                    let scale = size / image.pixels_per_em.max(1) as f32;
+                    let target_w = (decoded.width() as f32 * scale).round().max(1.0) as u32;
+                    let target_h = (decoded.height() as f32 * scale).round().max(1.0) as u32;
+                    let resized = image::imageops::resize(
+                        &decoded,
+                        target_w,
+                        target_h,
+                        image::imageops::FilterType::Triangle,
+                    );
+                    // `image.y` is the bottom edge relative to the baseline, y-up.
+                    let left = (origin_x + image.x as f32 * scale).round() as i32;
+                    let top = (baseline - image.y as f32 * scale - target_h as f32).round() as i32;
+                    for (px, py, pixel) in resized.enumerate_pixels() {
+                        blend(
+                            &mut canvas,
+                            width,
+                            height,
+                            left + px as i32,
+                            top + py as i32,
+                            pixel.0,
+                            1.0,
+                        );
+                    }
+                    continue;
+                }
+            }
+
+            let mut layers = LayerCollector::default();
+            if face.is_color_glyph(glyph_id) {
+                face.paint_color_glyph(glyph_id, 0, &mut layers);
+            } else {
+                layers.layers.push((glyph_id, None));
+            }
+
+            // rusttype scales by ascent - descent, not by the em square.
+            let scale = Scale::uniform(ascent - descent);
+            for (layer_id, color) in layers.layers {
+                // Foreground layers use opaque black: emoji ignore CSS `color`.
+                let color = color.map_or([0, 0, 0, 255], |c| [c.red, c.green, c.blue, c.alpha]);
+                let outline = self
+                    .outlines
+                    .glyph(rusttype::GlyphId(layer_id.0))
+                    .scaled(scale)
+                    .positioned(point(origin_x, baseline));
+                if let Some(bounds) = outline.pixel_bounding_box() {
+                    outline.draw(|x, y, coverage| {
+                        blend(
+                            &mut canvas,
+                            width,
+                            height,
+                            bounds.min.x + x as i32,
+                            bounds.min.y + y as i32,
+                            color,
+                            coverage,
+                        );
+                    });
+                }
+            }
+        }
+
+        Some(ColorGlyphImage {
+            width,
+            height,
+            rgba: canvas,
+            ascent,
+            advance: advance * units,
+        })
+    }
+}
+
+/// Source-over blend of a straight-alpha pixel scaled by `coverage`.
+fn blend(canvas: &mut [u8], width: u32, height: u32, x: i32, y: i32, src: [u8; 4], coverage: f32) {
+    if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+        return;
+    }
+    let index = ((y as u32 * width + x as u32) * 4) as usize;
+    let src_a = src[3] as f32 / 255.0 * coverage.clamp(0.0, 1.0);
+    if src_a <= 0.0 {
+        return;
+    }
+    let dst_a = canvas[index + 3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+
+    for (dst, src_c) in canvas[index..index + 3].iter_mut().zip(src) {
+        let blended = (src_c as f32 * src_a + *dst as f32 * dst_a * (1.0 - src_a)) / out_a;
+        *dst = blended.round() as u8;
+    }
+    canvas[index + 3] = (out_a * 255.0).round() as u8;
+}
+
+/// Color font fallback chain plus the atlas its clusters are cached in.
+pub struct ColorGlyphCache {
+    fonts: Vec<ColorFont>,
+    glyphs: HashMap<String, ColorGlyph>,
+    atlas: ColorAtlas,
+}
+
+impl ColorGlyphCache {
+    pub fn new() -> Self {
+        Self {
+            fonts: Vec::new(),
+            glyphs: HashMap::new(),
+            atlas: ColorAtlas::new(512, 512).expect("Failed to create color atlas"),
+        }
+    }
+
+    /// A cache preloaded with whichever platform emoji font is installed.
+    pub fn with_system_fonts() -> Self {
+        let mut cache = Self::new();
+
+        let font_paths = if cfg!(target_os = "windows") {
+            vec!["C:/Windows/Fonts/seguiemj.ttf"]
+        } else if cfg!(target_os = "macos") {
+            vec!["/System/Library/Fonts/Apple Color Emoji.ttc"]
+        } else {
+            vec![
+                "/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf",
+                "/usr/share/fonts/noto/NotoColorEmoji.ttf",
+                "/usr/share/fonts/google-noto-emoji/NotoColorEmoji.ttf",
+                "/usr/share/fonts/TTF/Twemoji.ttf",
+            ]
+        };
+
+        for path in font_paths {
+            if let Ok(data) = std::fs::read(path) {
+                if let Ok(font) = ColorFont::from_data(data) {
+                    cache.add_font(font);
+                }
+            }
+        }
+
+        cache
+    }
+
+    /// Append a font to the fallback chain; earlier fonts win.
+    pub fn add_font(&mut self, font: ColorFont) {
+        self.fonts.push(font);
+    }
+
+    pub fn has_fonts(&self) -> bool {
+        !self.fonts.is_empty()
+    }
+
+    /// Advance of `cluster` from the first font that covers it.
+    pub fn advance(&self, cluster: &str, size: f32) -> Option<f32> {
+        self.fonts
+            .iter()
+            .find_map(|font| font.advance(cluster, size))
+    }
+
+    /// Rasterize `cluster` at `size` into the atlas, or reuse the cached entry.
+    /// `Ok(None)` means no font in the chain covers it.
+    pub fn glyph(&mut self, cluster: &str, size: f32) -> Result<Option<ColorGlyph>, AtlasError> {
+        let key = format!("{}@{}", cluster, size.round() as u32);
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Ok(Some(glyph.clone()));
+        }
+
+        let image = match self
+            .fonts
+            .iter()
+            .find_map(|font| font.rasterize(cluster, size))
+        {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+
+        let coords = self
+            .atlas
+            .insert(&key, image.width, image.height, &image.rgba)?;
+        let glyph = ColorGlyph {
+            key: key.clone(),
+            coords,
+            width: image.width as f32,
+            height: image.height as f32,
+            ascent: image.ascent,
+            advance: image.advance,
+        };
+        self.glyphs.insert(key, glyph.clone());
+        Ok(Some(glyph))
+    }
+
+    pub fn atlas(&self) -> &ColorAtlas {
+        &self.atlas
+    }
+
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+        self.atlas.clear();
+    }
+}
+
+impl Default for ColorGlyphCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,137 @@
+//! Cluster segmentation for emoji sequences.
+//!
+//! Text is split into clusters before glyph lookup so that ZWJ sequences,
+//! flags, keycaps, skin-tone modifiers and variation selectors reach the
+//! color font as one unit instead of several independent codepoints.
+
+const ZWJ: char = '\u{200D}';
+const TEXT_PRESENTATION: char = '\u{FE0E}';
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+const KEYCAP: char = '\u{20E3}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextCluster<'a> {
+    pub text: &'a str,
+    /// Whether the cluster should be drawn from a color font.
+    pub emoji: bool,
+}
+
+/// Codepoints that default to emoji presentation.
+pub fn is_emoji_presentation(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1F0FF
+            | 0x1F180..=0x1F1FF
+            | 0x1F200..=0x1F2FF
+            | 0x1F300..=0x1F5FF
+            | 0x1F600..=0x1F64F
+            | 0x1F680..=0x1F6FF
+            | 0x1F900..=0x1F9FF
+            | 0x1FA70..=0x1FAFF
+            | 0x231A..=0x231B
+            | 0x23E9..=0x23EC
+            | 0x23F0
+            | 0x23F3
+            | 0x25FD..=0x25FE
+            | 0x2614..=0x2615
+            | 0x2648..=0x2653
+            | 0x267F
+            | 0x2693
+            | 0x26A1
+            | 0x26AA..=0x26AB
+            | 0x26BD..=0x26BE
+            | 0x26C4..=0x26C5
+            | 0x26CE
+            | 0x26D4
+            | 0x26EA
+            | 0x26F2..=0x26F5
+            | 0x26FA
+            | 0x26FD
+            | 0x2705
+            | 0x270A..=0x270B
+            | 0x2728
+            | 0x274C
+            | 0x274E
+            | 0x2753..=0x2755
+            | 0x2757
+            | 0x2795..=0x2797
+            | 0x27B0
+            | 0x27BF
+            | 0x2B1B..=0x2B1C
+            | 0x2B50
+            | 0x2B55
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone_modifier(c: char) -> bool {
+    ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// Tag characters used by subdivision flags such as England's.
+fn is_tag(c: char) -> bool {
+    ('\u{E0020}'..='\u{E007F}').contains(&c)
+}
+
+fn is_combining_mark(c: char) -> bool {
+    matches!(c as u32, 0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F)
+}
+
+/// Split `text` into clusters: a base character plus anything that extends
+/// it (marks, selectors, modifiers, tags, ZWJ-joined characters), and
+/// regional-indicator pairs. Concatenating the cluster texts yields `text`.
+pub fn segment_clusters(text: &str) -> Vec<TextCluster<'_>> {
+    let mut clusters = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, base)) = chars.next() {
+        let mut end = start + base.len_utf8();
+        let mut emoji = is_emoji_presentation(base);
+        let mut joins = false;
+        let mut text_style = false;
+
+        if is_regional_indicator(base) {
+            if let Some(&(i, next)) = chars.peek() {
+                if is_regional_indicator(next) {
+                    chars.next();
+                    end = i + next.len_utf8();
+                }
+            }
+        }
+
+        while let Some(&(i, next)) = chars.peek() {
+            let extends = if joins {
+                joins = false;
+                emoji |= is_emoji_presentation(next);
+                true
+            } else if next == ZWJ {
+                joins = true;
+                true
+            } else if next == EMOJI_PRESENTATION || next == KEYCAP {
+                emoji = true;
+                true
+            } else if next == TEXT_PRESENTATION {
+                text_style = true;
+                true
+            } else {
+                is_skin_tone_modifier(next) || is_tag(next) || is_combining_mark(next)
+            };
+
+            if !extends {
+                break;
+            }
+            chars.next();
+            end = i + next.len_utf8();
+        }
+
+        clusters.push(TextCluster {
+            text: &text[start..end],
+            emoji: emoji && !text_style,
+        });
+    }
+
+    clusters
+}
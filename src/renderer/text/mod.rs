@@ -1,6 +1,10 @@
 pub mod atlas;
+pub mod color;
+pub mod emoji;
 
 pub use atlas::*;
+pub use color::*;
+pub use emoji::*;
 
 use crate::renderer::gpu::{Buffer, GpuContext, Texture};
 use ash::vk;
@@ -11,6 +15,7 @@ use std::sync::Arc;
 pub struct TextRenderer {
     gpu_context: Arc<GpuContext>,
     font_atlas: FontAtlas,
+    color_glyphs: ColorGlyphCache,
     vertex_buffer: Option<Buffer>,
     fonts: HashMap<String, Font<'static>>,
     default_font_size: f32,
@@ -20,6 +25,7 @@ pub struct TextRenderer {
 pub struct TextVertex {
     pub position: [f32; 2],
     pub tex_coord: [f32; 2],
+    /// Linear light, as [`Vertex::color`](super::Vertex::color).
     pub color: [f32; 4],
 }
 
@@ -33,6 +39,10 @@ pub struct GlyphInfo {
     pub advance: f32,
     pub bearing_x: f32,
     pub bearing_y: f32,
+    /// Color atlas key for emoji clusters; `None` for outline glyphs.
+    pub color_key: Option<String>,
+    /// Alpha atlas key for outline glyphs; `None` for emoji clusters.
+    pub atlas_key: Option<GlyphKey>,
 }
 
 // Simple Rect struct if not available from core::layout
@@ -61,6 +71,7 @@ impl TextRenderer {
         Ok(Self {
             gpu_context,
             font_atlas,
+            color_glyphs: ColorGlyphCache::with_system_fonts(),
             vertex_buffer: None,
             fonts,
             default_font_size: 16.0,
@@ -107,6 +118,12 @@ impl TextRenderer {
         Ok(())
     }
 
+    /// Add a color font to the emoji fallback chain, after any already loaded.
+    pub fn load_color_font(&mut self, font_data: Vec<u8>) -> Result<(), TextError> {
+        self.color_glyphs.add_font(ColorFont::from_data(font_data)?);
+        Ok(())
+    }
+
     pub async fn render_text(
         &mut self,
         command_buffer: &vk::CommandBuffer,
@@ -125,7 +142,8 @@ impl TextRenderer {
             font_ref.clone()
         };
 
-        let rgba_color = self.parse_color(color.as_ref().unwrap_or(&"#000000".to_string()));
+        let rgba_color =
+            super::linearize(self.parse_color(color.as_ref().unwrap_or(&"#000000".to_string())));
         let effective_font_size = if font_size.is_finite() && font_size > 0.0 {
             font_size
         } else {
@@ -134,15 +152,25 @@ impl TextRenderer {
         let scale = Scale::uniform(effective_font_size);
 
         let glyphs = self.layout_text(text, &font, scale, bounds)?;
-        let vertices = self.create_text_vertices(&glyphs, rgba_color)?;
+        let mut vertices = self.create_text_vertices(&glyphs, rgba_color)?;
+        let outline_count = vertices.len();
+        vertices.extend(self.create_color_vertices(&glyphs)?);
 
         if vertices.is_empty() {
             return Ok(());
         }
 
         self.update_vertex_buffer(&vertices).await?;
-        self.draw_text_vertices(command_buffer, vertices.len())
+        // Outline glyphs sample the alpha atlas tinted by `color`; the color
+        // range that follows samples the RGBA atlas untinted.
+        self.draw_text_vertices(command_buffer, 0, outline_count)
             .await?;
+        self.draw_text_vertices(
+            command_buffer,
+            outline_count,
+            vertices.len() - outline_count,
+        )
+        .await?;
 
         Ok(())
     }
@@ -161,63 +189,90 @@ impl TextRenderer {
         let v_metrics = font.v_metrics(scale);
         let line_height = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
 
-        for character in text.chars() {
-            if character == '\n' {
+        for cluster in segment_clusters(text) {
+            if cluster.text == "\n" {
                 x = bounds.x;
                 y += line_height;
                 continue;
             }
 
-            if character == '\r' {
+            if cluster.text == "\r" {
                 continue;
             }
 
-            // Get the base glyph
-            let base_glyph = font.glyph(character);
-
-            // Clone glyph before scaling to avoid move issues
-            let glyph_for_atlas = base_glyph.clone();
-            let glyph_for_scaling = base_glyph.clone();
-
-            // Scale and position for metrics and layout
-            let scaled_glyph = glyph_for_scaling.scaled(scale);
-            let h_metrics = scaled_glyph.h_metrics();
-            let positioned_glyph = scaled_glyph.positioned(rusttype::point(x, y));
-            let bounding_box = positioned_glyph.pixel_bounding_box();
-
-            // Now cache in atlas (this requires mutable borrow of self)
-            let _atlas_coords =
-                self.font_atlas
-                    .get_or_cache_glyph(character, &glyph_for_atlas, scale)?;
-
-            let (glyph_x, glyph_y, glyph_width, glyph_height) = if let Some(bb) = bounding_box {
-                (
-                    bb.min.x as f32,
-                    bb.min.y as f32,
-                    (bb.max.x - bb.min.x) as f32,
-                    (bb.max.y - bb.min.y) as f32,
-                )
-            } else {
-                (x, y, 0.0, 0.0)
-            };
+            if let Some(color_glyph) = self.color_glyph_for(&cluster, font, scale.y)? {
+                glyphs.push(GlyphInfo {
+                    character: cluster.text.chars().next().unwrap_or(' '),
+                    x,
+                    y: y - color_glyph.ascent,
+                    width: color_glyph.width,
+                    height: color_glyph.height,
+                    advance: color_glyph.advance,
+                    bearing_x: 0.0,
+                    bearing_y: color_glyph.ascent,
+                    color_key: Some(color_glyph.key),
+                    atlas_key: None,
+                });
+
+                x += color_glyph.advance;
+                if x > bounds.x + bounds.width {
+                    x = bounds.x;
+                    y += line_height;
+                }
+                continue;
+            }
 
-            glyphs.push(GlyphInfo {
-                character,
-                x: glyph_x,
-                y: glyph_y,
-                width: glyph_width,
-                height: glyph_height,
-                advance: h_metrics.advance_width,
-                bearing_x: h_metrics.left_side_bearing,
-                bearing_y: v_metrics.ascent,
-            });
-
-            x += h_metrics.advance_width;
-
-            // Simple line wrapping
-            if x > bounds.x + bounds.width {
-                x = bounds.x;
-                y += line_height;
+            for character in cluster.text.chars() {
+                // Get the base glyph
+                let base_glyph = font.glyph(character);
+
+                // Clone glyph before scaling to avoid move issues
+                let glyph_for_atlas = base_glyph.clone();
+                let glyph_for_scaling = base_glyph.clone();
+
+                // Scale and position for metrics and layout
+                let scaled_glyph = glyph_for_scaling.scaled(scale);
+                let h_metrics = scaled_glyph.h_metrics();
+                let positioned_glyph = scaled_glyph.positioned(rusttype::point(x, y));
+                let bounding_box = positioned_glyph.pixel_bounding_box();
+
+                // Now cache in atlas (this requires mutable borrow of self)
+                let atlas_key = FontAtlas::system_key(&glyph_for_atlas, scale);
+                let _atlas_coords =
+                    self.font_atlas
+                        .get_or_cache_glyph(character, &glyph_for_atlas, scale)?;
+
+                let (glyph_x, glyph_y, glyph_width, glyph_height) = if let Some(bb) = bounding_box {
+                    (
+                        bb.min.x as f32,
+                        bb.min.y as f32,
+                        (bb.max.x - bb.min.x) as f32,
+                        (bb.max.y - bb.min.y) as f32,
+                    )
+                } else {
+                    (x, y, 0.0, 0.0)
+                };
+
+                glyphs.push(GlyphInfo {
+                    character,
+                    x: glyph_x,
+                    y: glyph_y,
+                    width: glyph_width,
+                    height: glyph_height,
+                    advance: h_metrics.advance_width,
+                    bearing_x: h_metrics.left_side_bearing,
+                    bearing_y: v_metrics.ascent,
+                    color_key: None,
+                    atlas_key: Some(atlas_key),
+                });
+
+                x += h_metrics.advance_width;
+
+                // Simple line wrapping
+                if x > bounds.x + bounds.width {
+                    x = bounds.x;
+                    y += line_height;
+                }
             }
         }
 
@@ -232,13 +287,14 @@ impl TextRenderer {
         let mut vertices = Vec::new();
 
         for glyph in glyphs {
-            if glyph.width == 0.0 || glyph.height == 0.0 {
-                continue; // Skip whitespace characters
+            if glyph.width == 0.0 || glyph.height == 0.0 || glyph.color_key.is_some() {
+                continue; // Skip whitespace and color glyphs
             }
 
-            let atlas_coords = self
-                .font_atlas
-                .get_glyph_coords(glyph.character)
+            let atlas_coords = glyph
+                .atlas_key
+                .as_ref()
+                .and_then(|key| self.font_atlas.get_glyph_coords(key))
                 .ok_or(TextError::GlyphNotFound(glyph.character))?;
 
             let quad_vertices = [
@@ -270,6 +326,72 @@ impl TextRenderer {
         Ok(vertices)
     }
 
+    /// Route a cluster to the color fallback chain when it is emoji or the
+    /// primary font has no glyph for it.
+    fn color_glyph_for(
+        &mut self,
+        cluster: &TextCluster<'_>,
+        font: &Font,
+        font_size: f32,
+    ) -> Result<Option<ColorGlyph>, TextError> {
+        if !self.color_glyphs.has_fonts() || !Self::needs_color_font(cluster, font) {
+            return Ok(None);
+        }
+        Ok(self.color_glyphs.glyph(cluster.text, font_size)?)
+    }
+
+    fn needs_color_font(cluster: &TextCluster<'_>, font: &Font) -> bool {
+        cluster.emoji
+            || cluster
+                .text
+                .chars()
+                .next()
+                .is_some_and(|c| !c.is_whitespace() && font.glyph(c).id().0 == 0)
+    }
+
+    fn create_color_vertices(&self, glyphs: &[GlyphInfo]) -> Result<Vec<TextVertex>, TextError> {
+        // White vertex color: emoji keep their own colors.
+        let color = [1.0, 1.0, 1.0, 1.0];
+        let mut vertices = Vec::new();
+
+        for glyph in glyphs {
+            let key = match &glyph.color_key {
+                Some(key) => key,
+                None => continue,
+            };
+            let atlas_coords = self
+                .color_glyphs
+                .atlas()
+                .get(key)
+                .ok_or(TextError::GlyphNotFound(glyph.character))?;
+
+            vertices.extend_from_slice(&[
+                TextVertex {
+                    position: [glyph.x, glyph.y],
+                    tex_coord: [atlas_coords.u_min, atlas_coords.v_min],
+                    color,
+                },
+                TextVertex {
+                    position: [glyph.x + glyph.width, glyph.y],
+                    tex_coord: [atlas_coords.u_max, atlas_coords.v_min],
+                    color,
+                },
+                TextVertex {
+                    position: [glyph.x + glyph.width, glyph.y + glyph.height],
+                    tex_coord: [atlas_coords.u_max, atlas_coords.v_max],
+                    color,
+                },
+                TextVertex {
+                    position: [glyph.x, glyph.y + glyph.height],
+                    tex_coord: [atlas_coords.u_min, atlas_coords.v_max],
+                    color,
+                },
+            ]);
+        }
+
+        Ok(vertices)
+    }
+
     async fn update_vertex_buffer(&mut self, vertices: &[TextVertex]) -> Result<(), TextError> {
         let buffer_size = std::mem::size_of_val(vertices) as u64;
 
@@ -292,8 +414,13 @@ impl TextRenderer {
     async fn draw_text_vertices(
         &self,
         command_buffer: &vk::CommandBuffer,
+        first_vertex: usize,
         vertex_count: usize,
     ) -> Result<(), TextError> {
+        if vertex_count == 0 {
+            return Ok(());
+        }
+
         if let Some(ref vertex_buffer) = self.vertex_buffer {
             let vertex_buffers = [vertex_buffer.get_buffer()];
             let offsets = [0];
@@ -310,7 +437,7 @@ impl TextRenderer {
                     *command_buffer,
                     vertex_count as u32,
                     1,
-                    0,
+                    first_vertex as u32,
                     0,
                 );
             }
@@ -349,17 +476,29 @@ impl TextRenderer {
         let mut max_width = 0.0f32;
         let mut lines = 1;
 
-        for character in text.chars() {
-            if character == '\n' {
+        for cluster in segment_clusters(text) {
+            if cluster.text == "\n" {
                 max_width = max_width.max(width);
                 width = 0.0;
                 lines += 1;
                 continue;
             }
 
-            let glyph = font.glyph(character).scaled(scale);
-            let h_metrics = glyph.h_metrics();
-            width += h_metrics.advance_width;
+            let color_advance = if Self::needs_color_font(&cluster, font) {
+                self.color_glyphs.advance(cluster.text, font_size)
+            } else {
+                None
+            };
+            if let Some(advance) = color_advance {
+                width += advance;
+                continue;
+            }
+
+            for character in cluster.text.chars() {
+                let glyph = font.glyph(character).scaled(scale);
+                let h_metrics = glyph.h_metrics();
+                width += h_metrics.advance_width;
+            }
         }
 
         max_width = max_width.max(width);
@@ -372,8 +511,13 @@ impl TextRenderer {
         self.font_atlas.get_texture()
     }
 
+    pub fn get_color_atlas(&self) -> &ColorAtlas {
+        self.color_glyphs.atlas()
+    }
+
     pub async fn regenerate_atlas(&mut self) -> Result<(), TextError> {
         self.font_atlas.clear();
+        self.color_glyphs.clear();
         Ok(())
     }
 }
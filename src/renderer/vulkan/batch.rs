@@ -0,0 +1,238 @@
+//! A frame's quads as GPU draws.
+//!
+//! Every quad of a frame goes into one vertex and one index buffer, in
+//! paint order, so what paints later blends over what painted before it:
+//! children over their parents, as the document orders them. Consecutive
+//! quads drawn with the same pipeline and texture under the same clip
+//! share a draw.
+//!
+//! Glyph masks are packed into the presenter's own [`GlyphAtlas`], under
+//! the mask itself: the paint hands out the same mask for a glyph from
+//! frame to frame. Each image is a texture of its own, identified the same
+//! way.
+
+use crate::renderer::clip::ClipChain;
+use crate::renderer::glyphs::{GlyphAtlas, GlyphMask};
+use crate::renderer::image::DecodedImage;
+use crate::renderer::linearize;
+use crate::renderer::raster::DrawQuad;
+use crate::renderer::Rect;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// The pipeline solid quads are drawn with, blurred ones included.
+pub const SOLID_PIPELINE: u64 = 1;
+/// The pipeline glyphs are drawn with, their coverage from the atlas.
+pub const GLYPH_PIPELINE: u64 = 2;
+/// The pipeline images are drawn with, tinted by the quad's color.
+pub const IMAGE_PIPELINE: u64 = 3;
+
+/// The largest image every device can hold as a texture; larger ones are
+/// drawn on the CPU.
+pub const MAX_IMAGE_SIZE: u32 = 4096;
+
+/// The texture the glyph atlas is uploaded to.
+pub const GLYPH_ATLAS_TEXTURE: u64 = 0;
+
+/// A glyph mask as the presenter's atlas keys it: by identity. The key
+/// holds the mask, so no other mask can take its address.
+#[derive(Debug, Clone)]
+pub struct MaskKey(pub Arc<GlyphMask>);
+
+impl PartialEq for MaskKey {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MaskKey {}
+
+impl Hash for MaskKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state);
+    }
+}
+
+/// One corner of a quad, as the quad shaders read it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuadVertex {
+    /// Clip space.
+    pub position: [f32; 2],
+    /// In pixels of the quad's texture, which the shader divides by its
+    /// size: the glyph atlas grows without moving its cells.
+    pub tex_coord: [f32; 2],
+    /// Linear light with straight alpha; the sRGB framebuffer encodes what
+    /// the fragment shader writes.
+    pub color: [f32; 4],
+    /// The quad's bounds in framebuffer pixels, which a blur fades out
+    /// from.
+    pub bounds: [f32; 4],
+    /// Standard deviation of the blur in pixels; `0.0` for sharp edges.
+    pub sigma: f32,
+}
+
+/// Quads drawn with one pipeline and texture under one clip:
+/// `index_count` indices from `first_index`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuadDraw {
+    pub pipeline_id: u64,
+    pub texture: Option<u64>,
+    pub clip: ClipChain,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct QuadBatch {
+    pub vertices: Vec<QuadVertex>,
+    pub indices: Vec<u32>,
+    pub draws: Vec<QuadDraw>,
+    /// The images the draws sample, once each, under their texture id.
+    pub images: Vec<(u64, Arc<DecodedImage>)>,
+}
+
+impl QuadBatch {
+    /// `quads` drawn to a framebuffer `width` by `height` pixels, their
+    /// glyph masks packed into `atlas`. `None` when one of them needs what
+    /// the GPU does not draw: a mask the atlas cannot hold, or an image no
+    /// texture can.
+    pub fn build(
+        quads: &[DrawQuad],
+        width: u32,
+        height: u32,
+        atlas: &mut GlyphAtlas<MaskKey>,
+    ) -> Option<Self> {
+        // An atlas that fills up is emptied, and the cells handed out
+        // before are gone; the frame is batched again, once.
+        for _ in 0..2 {
+            let generation = atlas.generation();
+            let batch = Self::batch(quads, width, height, atlas)?;
+            if atlas.generation() == generation {
+                return Some(batch);
+            }
+        }
+        None
+    }
+
+    fn batch(
+        quads: &[DrawQuad],
+        width: u32,
+        height: u32,
+        atlas: &mut GlyphAtlas<MaskKey>,
+    ) -> Option<Self> {
+        let mut batch = Self::default();
+        let size = (width.max(1) as f32, height.max(1) as f32);
+        for quad in quads {
+            if quad.painted_area().is_none() {
+                continue;
+            }
+            // Blurred quads are solid, as the software rasterizer has them.
+            let sharp = quad.blur_radius <= 0.0;
+            match (&quad.image, &quad.mask) {
+                // Either covers nothing.
+                (Some(image), _) if sharp && (image.width == 0 || image.height == 0) => {}
+                (_, Some(mask)) if sharp && (mask.width == 0 || mask.height == 0) => {}
+                (Some(image), _) if sharp => {
+                    let texture_size = image.width.max(image.height) <= MAX_IMAGE_SIZE;
+                    let complete =
+                        image.data.len() == image.width as usize * image.height as usize * 4;
+                    if !texture_size || !complete {
+                        return None;
+                    }
+                    // The batch holds the image, so no other can take its
+                    // address while the texture is about.
+                    let id = Arc::as_ptr(image) as usize as u64;
+                    if !batch.images.iter().any(|(known, _)| *known == id) {
+                        batch.images.push((id, image.clone()));
+                    }
+                    let source = Rect {
+                        x: 0.0,
+                        y: 0.0,
+                        width: image.width as f32,
+                        height: image.height as f32,
+                    };
+                    batch.push(quad, IMAGE_PIPELINE, Some((id, source)), size);
+                }
+                (_, Some(mask)) if sharp => {
+                    let cell = atlas.pack(MaskKey(mask.clone()), mask)?;
+                    batch.push(
+                        quad,
+                        GLYPH_PIPELINE,
+                        Some((GLYPH_ATLAS_TEXTURE, cell)),
+                        size,
+                    );
+                }
+                _ => batch.push(quad, SOLID_PIPELINE, None, size),
+            }
+        }
+        Some(batch)
+    }
+
+    /// Push `quad`, stretching the `source` pixels of a texture over it if
+    /// it has one.
+    fn push(
+        &mut self,
+        quad: &DrawQuad,
+        pipeline_id: u64,
+        texture: Option<(u64, Rect)>,
+        (width, height): (f32, f32),
+    ) {
+        // Blur spreads coverage about three standard deviations out, as
+        // the software rasterizer has it.
+        let sigma = quad.blur_radius.max(0.0) / 2.0;
+        let spread = sigma * 3.0;
+        let bounds = &quad.bounds;
+        let (x0, y0) = (bounds.x - spread, bounds.y - spread);
+        let (x1, y1) = (
+            bounds.x + bounds.width + spread,
+            bounds.y + bounds.height + spread,
+        );
+        let color = linearize(quad.color);
+        let (u0, v0, u1, v1) = match &texture {
+            Some((_, source)) => (
+                source.x,
+                source.y,
+                source.x + source.width,
+                source.y + source.height,
+            ),
+            None => (0.0, 0.0, 1.0, 1.0),
+        };
+        let base = self.vertices.len() as u32;
+        for ([x, y], tex_coord) in [
+            ([x0, y0], [u0, v0]),
+            ([x1, y0], [u1, v0]),
+            ([x1, y1], [u1, v1]),
+            ([x0, y1], [u0, v1]),
+        ] {
+            self.vertices.push(QuadVertex {
+                position: [x / width * 2.0 - 1.0, y / height * 2.0 - 1.0],
+                tex_coord,
+                color,
+                bounds: [bounds.x, bounds.y, bounds.width, bounds.height],
+                sigma,
+            });
+        }
+
+        let first_index = self.indices.len() as u32;
+        self.indices
+            .extend([0, 1, 2, 2, 3, 0].map(|corner| base + corner));
+        let texture = texture.map(|(id, _)| id);
+        match self.draws.last_mut() {
+            Some(draw)
+                if draw.pipeline_id == pipeline_id
+                    && draw.texture == texture
+                    && draw.clip == quad.clip =>
+            {
+                draw.index_count += 6;
+            }
+            _ => self.draws.push(QuadDraw {
+                pipeline_id,
+                texture,
+                clip: quad.clip.clone(),
+                first_index,
+                index_count: 6,
+            }),
+        }
+    }
+}
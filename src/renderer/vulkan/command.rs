@@ -1,4 +1,5 @@
 use super::device::VulkanDevice;
+use super::sync::{FrameSignals, FrameSync};
 use ash::{vk, Device};
 use crossbeam::channel::{unbounded, Sender};
 use dashmap::DashMap;
@@ -6,6 +7,7 @@ use parking_lot::{Mutex, RwLock};
 use smallvec::SmallVec;
 use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -96,6 +98,14 @@ impl CommandPool {
         Ok(buffer)
     }
 
+    /// Take `buffer` back for the next allocation; the GPU is done with it.
+    fn release_buffer(&mut self, buffer: vk::CommandBuffer) {
+        if let Some(index) = self.in_use_buffers.iter().position(|b| *b == buffer) {
+            self.in_use_buffers.swap_remove(index);
+            self.available_buffers.push_back(buffer);
+        }
+    }
+
     fn reset_pool(&mut self, device: &Device) -> Result<()> {
         unsafe {
             device
@@ -118,45 +128,17 @@ impl CommandPool {
 
 pub struct FrameData {
     pub command_buffers: SmallVec<[CommandBufferInfo; 16]>,
-    pub submission_fence: vk::Fence,
-    pub render_finished_semaphore: vk::Semaphore,
-    pub image_available_semaphore: vk::Semaphore,
     pub frame_index: u32,
     pub is_submitted: bool,
 }
 
 impl FrameData {
-    fn new(device: &Device, frame_index: u32) -> Result<Self> {
-        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-
-        let submission_fence = unsafe {
-            device
-                .create_fence(&fence_info, None)
-                .map_err(|e| CommandError::Synchronization(e.to_string()))?
-        };
-
-        let semaphore_info = vk::SemaphoreCreateInfo::builder();
-
-        let render_finished_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_info, None)
-                .map_err(|e| CommandError::Synchronization(e.to_string()))?
-        };
-
-        let image_available_semaphore = unsafe {
-            device
-                .create_semaphore(&semaphore_info, None)
-                .map_err(|e| CommandError::Synchronization(e.to_string()))?
-        };
-
-        Ok(Self {
+    fn new(frame_index: u32) -> Self {
+        Self {
             command_buffers: SmallVec::new(),
-            submission_fence,
-            render_finished_semaphore,
-            image_available_semaphore,
             frame_index,
             is_submitted: false,
-        })
+        }
     }
 }
 
@@ -167,6 +149,8 @@ pub struct CommandManager {
     transfer_pools: Arc<Mutex<Vec<CommandPool>>>,
     thread_local_pools: Arc<DashMap<std::thread::ThreadId, usize>>,
     frames_in_flight: Arc<RwLock<VecDeque<FrameData>>>,
+    sync: FrameSync,
+    // The frame being recorded.
     current_frame: Arc<RwLock<u32>>,
     max_frames_in_flight: u32,
     command_buffer_registry: Arc<DashMap<vk::CommandBuffer, CommandBufferInfo>>,
@@ -199,10 +183,8 @@ impl CommandManager {
             4,
         )?));
 
-        let mut frames_in_flight = VecDeque::with_capacity(max_frames_in_flight as usize);
-        for i in 0..max_frames_in_flight {
-            frames_in_flight.push_back(FrameData::new(device.logical_device(), i)?);
-        }
+        let frames_in_flight = (0..max_frames_in_flight).map(FrameData::new).collect();
+        let sync = FrameSync::new(device.logical_device(), max_frames_in_flight)?;
 
         let (shutdown_tx, _shutdown_rx) = unbounded();
 
@@ -213,6 +195,7 @@ impl CommandManager {
             transfer_pools,
             thread_local_pools: Arc::new(DashMap::new()),
             frames_in_flight: Arc::new(RwLock::new(frames_in_flight)),
+            sync,
             current_frame: Arc::new(RwLock::new(0)),
             max_frames_in_flight,
             command_buffer_registry: Arc::new(DashMap::new()),
@@ -235,30 +218,20 @@ impl CommandManager {
         Ok(pools)
     }
 
-    pub async fn begin_frame(&self) -> Result<vk::CommandBuffer> {
-        let current_frame = {
-            let mut frame = self.current_frame.write();
-            let f = *frame;
-            *frame = (*frame + 1) % self.max_frames_in_flight;
-            f
-        };
+    /// The next frame's semaphores and fence, once the GPU is done with
+    /// the frame that last used them, and how long that wait took.
+    pub async fn next_frame(&self) -> Result<(FrameSignals, Duration)> {
+        self.sync.wait_for_next(self.device.logical_device())
+    }
+
+    /// Start recording `frame`, from [`Self::next_frame`].
+    pub async fn begin_frame(&self, frame: &FrameSignals) -> Result<vk::CommandBuffer> {
+        self.sync.advance(self.device.logical_device(), frame)?;
+        *self.current_frame.write() = frame.index;
 
         {
             let mut frames = self.frames_in_flight.write();
-            let frame_data = &mut frames[current_frame as usize];
-
-            unsafe {
-                self.device
-                    .logical_device()
-                    .wait_for_fences(&[frame_data.submission_fence], true, u64::MAX)
-                    .map_err(|e| CommandError::Synchronization(e.to_string()))?;
-
-                self.device
-                    .logical_device()
-                    .reset_fences(&[frame_data.submission_fence])
-                    .map_err(|e| CommandError::Synchronization(e.to_string()))?;
-            }
-
+            let frame_data = &mut frames[frame.index as usize];
             for buffer_info in &frame_data.command_buffers {
                 self.command_buffer_registry.remove(&buffer_info.buffer);
             }
@@ -362,6 +335,54 @@ impl CommandManager {
         wait_semaphores: &[vk::Semaphore],
         wait_stages: &[vk::PipelineStageFlags],
         signal_semaphores: &[vk::Semaphore],
+    ) -> Result<()> {
+        // The frame's fence is for `end_frame` to signal.
+        self.submit(
+            buffer,
+            wait_semaphores,
+            wait_stages,
+            signal_semaphores,
+            vk::Fence::null(),
+        )
+    }
+
+    /// End `buffer`, from [`Self::allocate_command_buffer`], submit it on
+    /// its own and wait for the GPU to finish it; the buffer then goes back
+    /// to its pool. For work outside any frame, such as reading an image
+    /// back.
+    pub async fn submit_and_wait(&self, buffer: vk::CommandBuffer) -> Result<()> {
+        self.end_command_buffer(buffer).await?;
+
+        let device = self.device.logical_device();
+        let fence = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }
+            .map_err(|e| CommandError::Synchronization(e.to_string()))?;
+        let finished = self.submit(buffer, &[], &[], &[], fence).and_then(|()| {
+            unsafe { device.wait_for_fences(&[fence], true, u64::MAX) }
+                .map_err(|e| CommandError::Synchronization(e.to_string()))
+        });
+        unsafe { device.destroy_fence(fence, None) };
+        finished?;
+
+        if let Some((_, info)) = self.command_buffer_registry.remove(&buffer) {
+            let pools = match info.buffer_type {
+                CommandBufferType::Graphics => &self.graphics_pools,
+                CommandBufferType::Compute => &self.compute_pools,
+                CommandBufferType::Transfer => &self.transfer_pools,
+            };
+            if let Some(pool) = pools.lock().iter_mut().find(|pool| pool.pool == info.pool) {
+                pool.release_buffer(buffer);
+            }
+        }
+        Ok(())
+    }
+
+    fn submit(
+        &self,
+        buffer: vk::CommandBuffer,
+        wait_semaphores: &[vk::Semaphore],
+        wait_stages: &[vk::PipelineStageFlags],
+        signal_semaphores: &[vk::Semaphore],
+        fence: vk::Fence,
     ) -> Result<()> {
         let buffer_info = self
             .command_buffer_registry
@@ -392,15 +413,6 @@ impl CommandManager {
             }
         };
 
-        // Get the submission fence, ensuring proper lifetime management
-        let submission_fence = {
-            let current_frame = *self.current_frame.read();
-            let frame_index = ((current_frame + self.max_frames_in_flight - 1)
-                % self.max_frames_in_flight) as usize;
-            let frames = self.frames_in_flight.read();
-            frames[frame_index].submission_fence
-        };
-
         let command_buffers = [buffer];
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(&command_buffers)
@@ -412,39 +424,39 @@ impl CommandManager {
         unsafe {
             self.device
                 .logical_device()
-                .queue_submit(queue, &[submit_info], submission_fence)
+                .queue_submit(queue, &[submit_info], fence)
                 .map_err(|e| CommandError::Submission(e.to_string()))?;
         }
 
         Ok(())
     }
 
-    pub async fn end_frame(&self, primary_buffer: vk::CommandBuffer) -> Result<()> {
+    /// Submit `frame`'s primary buffer. It runs from `wait_stage` once the
+    /// swapchain image is available, and signals the frame's
+    /// render-finished semaphore and fence when done.
+    pub async fn end_frame(
+        &self,
+        primary_buffer: vk::CommandBuffer,
+        frame: &FrameSignals,
+        wait_stage: vk::PipelineStageFlags,
+    ) -> Result<()> {
         self.end_command_buffer(primary_buffer).await?;
 
-        // Get the frame index and extract semaphore/fence values, ensuring proper lifetime management
-        let (render_finished_semaphore, submission_fence) = {
-            let current_frame = *self.current_frame.read();
-            let frame_index = ((current_frame + self.max_frames_in_flight - 1)
-                % self.max_frames_in_flight) as usize;
+        if let Some(buffer_info) = self.command_buffer_registry.get(&primary_buffer) {
             let mut frames = self.frames_in_flight.write();
-            let frame_data = &mut frames[frame_index];
-
-            if let Some(buffer_info) = self.command_buffer_registry.get(&primary_buffer) {
-                frame_data.command_buffers.push(*buffer_info.value());
-            }
-
-            let semaphore = frame_data.render_finished_semaphore;
-            let fence = frame_data.submission_fence;
-
-            // Extract values before releasing the lock
-            (semaphore, fence)
-        };
+            frames[frame.index as usize]
+                .command_buffers
+                .push(*buffer_info.value());
+        }
 
         let command_buffers = [primary_buffer];
-        let signal_semaphores = [render_finished_semaphore];
+        let wait_semaphores = [frame.image_available];
+        let wait_stages = [wait_stage];
+        let signal_semaphores = [frame.render_finished];
         let submit_info = vk::SubmitInfo::builder()
             .command_buffers(&command_buffers)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
             .signal_semaphores(&signal_semaphores)
             .build();
 
@@ -454,32 +466,27 @@ impl CommandManager {
                 .queue_submit(
                     self.device.graphics_queue(),
                     &[submit_info],
-                    submission_fence,
+                    frame.in_flight,
                 )
                 .map_err(|e| CommandError::Submission(e.to_string()))?;
         }
 
-        // Update the submission status
-        {
-            let current_frame = *self.current_frame.read();
-            let frame_index = ((current_frame + self.max_frames_in_flight - 1)
-                % self.max_frames_in_flight) as usize;
-            let mut frames = self.frames_in_flight.write();
-            frames[frame_index].is_submitted = true;
-        }
-
+        self.frames_in_flight.write()[frame.index as usize].is_submitted = true;
         Ok(())
     }
 
     pub async fn wait_for_frame(&self, frame_index: u32) -> Result<()> {
-        let frames = self.frames_in_flight.read();
-        let frame_data = &frames[(frame_index % self.max_frames_in_flight) as usize];
+        let submitted = {
+            let frames = self.frames_in_flight.read();
+            frames[(frame_index % self.max_frames_in_flight) as usize].is_submitted
+        };
 
-        if frame_data.is_submitted {
+        if submitted {
+            let fence = self.sync.frame(frame_index).in_flight;
             unsafe {
                 self.device
                     .logical_device()
-                    .wait_for_fences(&[frame_data.submission_fence], true, u64::MAX)
+                    .wait_for_fences(&[fence], true, u64::MAX)
                     .map_err(|e| CommandError::Synchronization(e.to_string()))?;
             }
         }
@@ -506,15 +513,6 @@ impl CommandManager {
         Ok(())
     }
 
-    pub fn get_frame_semaphores(&self, frame_index: u32) -> (vk::Semaphore, vk::Semaphore) {
-        let frames = self.frames_in_flight.read();
-        let frame_data = &frames[(frame_index % self.max_frames_in_flight) as usize];
-        (
-            frame_data.image_available_semaphore,
-            frame_data.render_finished_semaphore,
-        )
-    }
-
     pub async fn shutdown(&self) -> Result<()> {
         if let Some(sender) = self.shutdown_signal.lock().take() {
             let _ = sender.send(());
@@ -525,19 +523,8 @@ impl CommandManager {
             .await
             .map_err(|e| CommandError::Synchronization(e.to_string()))?;
 
-        let frames = self.frames_in_flight.read();
-        for frame_data in frames.iter() {
-            unsafe {
-                self.device
-                    .logical_device()
-                    .destroy_fence(frame_data.submission_fence, None);
-                self.device
-                    .logical_device()
-                    .destroy_semaphore(frame_data.render_finished_semaphore, None);
-                self.device
-                    .logical_device()
-                    .destroy_semaphore(frame_data.image_available_semaphore, None);
-            }
+        unsafe {
+            self.sync.destroy(self.device.logical_device());
         }
 
         let graphics_pools = self.graphics_pools.lock();
@@ -0,0 +1,74 @@
+//! Accounting of the device memory a renderer holds.
+//!
+//! The renderer allocates each buffer and image its own `VkDeviceMemory`,
+//! so every allocation is a block of its own; [`MemoryTracker`] counts
+//! them as they are made and freed, keyed by their handle.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct MemoryTracker {
+    // Bytes of each live allocation, by its raw handle.
+    live: Mutex<HashMap<u64, u64>>,
+    allocated_bytes: std::sync::atomic::AtomicU64,
+    peak_bytes: std::sync::atomic::AtomicU64,
+}
+
+impl MemoryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `bytes` were allocated as `handle`.
+    pub fn allocate(&self, handle: u64, bytes: u64) {
+        let mut live = self.live.lock();
+        if let Some(previous) = live.insert(handle, bytes) {
+            self.allocated_bytes
+                .fetch_sub(previous, std::sync::atomic::Ordering::Relaxed);
+        }
+        let allocated = self
+            .allocated_bytes
+            .fetch_add(bytes, std::sync::atomic::Ordering::Relaxed)
+            + bytes;
+        self.peak_bytes
+            .fetch_max(allocated, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// `handle` was freed; unknown handles are ignored.
+    pub fn deallocate(&self, handle: u64) {
+        if let Some(bytes) = self.live.lock().remove(&handle) {
+            self.allocated_bytes
+                .fetch_sub(bytes, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    pub fn current_usage(&self) -> u64 {
+        self.allocated_bytes
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn peak_usage(&self) -> u64 {
+        self.peak_bytes.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub fn report(&self) -> GpuMemoryReport {
+        let live = self.live.lock();
+        GpuMemoryReport {
+            blocks: live.len(),
+            reserved_bytes: live.values().sum(),
+            peak_bytes: self.peak_usage(),
+        }
+    }
+}
+
+/// The device memory a renderer holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GpuMemoryReport {
+    /// Live `VkDeviceMemory` objects.
+    pub blocks: usize,
+    /// Bytes they take together.
+    pub reserved_bytes: u64,
+    /// The most `reserved_bytes` has been.
+    pub peak_bytes: u64,
+}
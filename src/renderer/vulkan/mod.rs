@@ -1,22 +1,51 @@
 use ash::vk;
 use ash::{Device, Entry, Instance};
 use dashmap::DashMap;
-use parking_lot::RwLock;
+use gpu_allocator::vulkan::{Allocator, AllocatorCreateDesc};
+use gpu_allocator::MemoryLocation;
+use parking_lot::{Mutex, RwLock};
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 use smallvec::SmallVec;
+use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
 
+pub mod batch;
 pub mod command;
 pub mod device;
+pub mod memory;
+pub mod pipeline_cache;
+mod pipelines;
 pub mod shaders;
+mod surface;
+pub mod sync;
 
-use command::{CommandError, CommandManager};
+use batch::{
+    MaskKey, QuadBatch, GLYPH_ATLAS_TEXTURE, GLYPH_PIPELINE, IMAGE_PIPELINE, SOLID_PIPELINE,
+};
+use command::{CommandBufferType, CommandError, CommandManager};
 use device::{DeviceError, VulkanDevice};
-use shaders::ShaderError;
-
-use crate::core::{dom::Document, layout::LayoutEngine};
+use memory::{GpuMemoryReport, MemoryTracker};
+use pipeline_cache::{CacheIdentity, PipelineCacheStats};
+use shaders::{CompiledShader, ShaderError, ShaderManager};
+
+use crate::renderer::clip::ClipChain;
+use crate::renderer::glyphs::GlyphAtlas;
+use crate::renderer::gpu::{Buffer, Texture};
+use crate::renderer::image::DecodedImage;
+use crate::renderer::raster::{DrawQuad, Snapshot};
 use crate::BrowserConfig;
 
+/// The smallest vertex or index buffer made; they grow by doubling.
+const MIN_BUFFER_SIZE: vk::DeviceSize = 64 * 1024;
+
+/// The descriptor set layout of pipelines sampling one texture.
+const TEXTURE_SET_LAYOUT: &str = "texture";
+
+/// Frames with more images than this are drawn on the CPU; each image
+/// takes a descriptor set of the pool.
+const MAX_FRAME_IMAGES: usize = 512;
+
 #[derive(Error, Debug)]
 pub enum VulkanError {
     #[error("Device creation failed: {0}")]
@@ -43,6 +72,35 @@ pub enum VulkanError {
 
 pub type Result<T> = std::result::Result<T, VulkanError>;
 
+static VALIDATION_ERRORS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Errors the validation layers reported so far, in any renderer. Only
+/// debug builds enable the layers; release builds always report none.
+pub fn validation_errors() -> u64 {
+    VALIDATION_ERRORS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+unsafe extern "system" fn on_validation_message(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    _message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _user_data: *mut std::ffi::c_void,
+) -> vk::Bool32 {
+    let message = match data.as_ref() {
+        Some(data) if !data.p_message.is_null() => {
+            std::ffi::CStr::from_ptr(data.p_message).to_string_lossy()
+        }
+        _ => "".into(),
+    };
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        VALIDATION_ERRORS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        tracing::error!("Vulkan validation: {}", message);
+    } else {
+        tracing::warn!("Vulkan validation: {}", message);
+    }
+    vk::FALSE
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct RenderStats {
     pub frame_time_ms: f32,
@@ -51,6 +109,9 @@ pub struct RenderStats {
     pub vertices: u32,
     pub memory_used_mb: u32,
     pub pipeline_switches: u32,
+    /// Of `frame_time_ms`, the time spent waiting on the GPU to finish
+    /// earlier frames.
+    pub gpu_wait_ms: f32,
 }
 
 impl Default for RenderStats {
@@ -62,6 +123,7 @@ impl Default for RenderStats {
             vertices: 0,
             memory_used_mb: 0,
             pipeline_switches: 0,
+            gpu_wait_ms: 0.0,
         }
     }
 }
@@ -69,12 +131,16 @@ impl Default for RenderStats {
 #[derive(Debug, Clone)]
 pub struct RenderCommand {
     pub pipeline_id: u64,
+    pub layout: vk::PipelineLayout,
     pub vertex_buffer: vk::Buffer,
     pub index_buffer: vk::Buffer,
     pub descriptor_sets: SmallVec<[vk::DescriptorSet; 4]>,
+    pub first_index: u32,
     pub index_count: u32,
     pub vertex_offset: u32,
     pub instance_count: u32,
+    /// Overflow clips: the scissor rect plus rounded clips as push constants.
+    pub clip: ClipChain,
 }
 
 #[derive(Debug, Clone)]
@@ -86,70 +152,122 @@ pub struct SwapchainData {
     pub format: vk::Format,
     pub extent: vk::Extent2D,
     pub current_index: u32,
+    /// The depth attachment the framebuffers share.
+    pub depth_image: vk::Image,
+    pub depth_memory: vk::DeviceMemory,
+    pub depth_view: vk::ImageView,
 }
 
-struct MemoryTracker {
-    allocated_bytes: std::sync::atomic::AtomicU64,
-    peak_bytes: std::sync::atomic::AtomicU64,
+/// Host-visible memory frames are copied into the swapchain from.
+struct StagingBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
 }
 
-impl MemoryTracker {
-    fn new() -> Self {
-        Self {
-            allocated_bytes: std::sync::atomic::AtomicU64::new(0),
-            peak_bytes: std::sync::atomic::AtomicU64::new(0),
-        }
-    }
-
-    fn current_usage(&self) -> u64 {
-        self.allocated_bytes
-            .load(std::sync::atomic::Ordering::Relaxed)
-    }
-
-    fn peak_usage(&self) -> u64 {
-        self.peak_bytes.load(std::sync::atomic::Ordering::Relaxed)
-    }
+/// Textures a frame fills before it draws, each from its offset in the
+/// staging buffer.
+#[derive(Default)]
+struct TextureUploads {
+    staging: vk::Buffer,
+    textures: Vec<(u64, vk::DeviceSize)>,
 }
 
 struct ResourceManager {
     pipelines: DashMap<u64, vk::Pipeline>,
+    pipeline_layouts: DashMap<u64, vk::PipelineLayout>,
     descriptor_set_layouts: DashMap<String, vk::DescriptorSetLayout>,
+    /// Host-visible buffers frames are drawn from, by what they hold.
+    buffers: DashMap<&'static str, Buffer>,
+    /// Textures quads sample, and the descriptor set each is bound with.
+    textures: DashMap<u64, Texture>,
+    descriptor_sets: DashMap<u64, vk::DescriptorSet>,
+    /// Nearest and clamped, as the software rasterizer samples.
+    sampler: vk::Sampler,
+    device: Arc<Device>,
+    allocator: Arc<std::sync::Mutex<Allocator>>,
 }
 
 impl ResourceManager {
-    fn new() -> Self {
-        Self {
+    fn new(instance: &Instance, device: &VulkanDevice) -> Result<Self> {
+        let allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: instance.clone(),
+            device: device.logical_device().clone(),
+            physical_device: device.physical_device(),
+            debug_settings: Default::default(),
+            buffer_device_address: false,
+            allocation_sizes: Default::default(),
+        })
+        .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+        let sampler = Texture::create_nearest_sampler(device.logical_device())
+            .map_err(|e| VulkanError::PipelineCreation(e.to_string()))?;
+        Ok(Self {
             pipelines: DashMap::with_capacity(64),
+            pipeline_layouts: DashMap::with_capacity(64),
             descriptor_set_layouts: DashMap::with_capacity(32),
-        }
+            buffers: DashMap::new(),
+            textures: DashMap::new(),
+            descriptor_sets: DashMap::new(),
+            sampler,
+            device: Arc::new(device.logical_device().clone()),
+            allocator: Arc::new(std::sync::Mutex::new(allocator)),
+        })
     }
 
     unsafe fn cleanup(&self, device: &Device) {
         for entry in self.pipelines.iter() {
             device.destroy_pipeline(*entry.value(), None);
         }
+        for entry in self.pipeline_layouts.iter() {
+            device.destroy_pipeline_layout(*entry.value(), None);
+        }
         for entry in self.descriptor_set_layouts.iter() {
             device.destroy_descriptor_set_layout(*entry.value(), None);
         }
+        // Buffers and textures give their memory back to the allocator as
+        // they drop; descriptor sets go with their pool.
+        self.buffers.clear();
+        self.textures.clear();
+        device.destroy_sampler(self.sampler, None);
     }
 }
 
 pub struct VulkanRenderer {
+    entry: Entry,
     instance: Instance,
     device: Arc<VulkanDevice>,
-    surface: vk::SurfaceKHR,
+    surface: RwLock<vk::SurfaceKHR>,
     surface_loader: ash::extensions::khr::Surface,
     swapchain_loader: ash::extensions::khr::Swapchain,
     swapchain_data: Arc<RwLock<SwapchainData>>,
+    // An image was acquired from a swapchain that no longer matches the
+    // surface exactly; it is rebuilt once that image is presented.
+    swapchain_suboptimal: std::sync::atomic::AtomicBool,
     command_manager: Arc<CommandManager>,
     render_pass: vk::RenderPass,
+    // Compatible with `render_pass`, so the same pipelines draw in it, but
+    // leaves the image to be copied out of.
+    offscreen_pass: vk::RenderPass,
     pipeline_cache: vk::PipelineCache,
+    // Where the pipeline cache is loaded from and saved to, if anywhere.
+    pipeline_cache_path: Option<std::path::PathBuf>,
+    pipeline_cache_stats: Mutex<PipelineCacheStats>,
     descriptor_pool: vk::DescriptorPool,
     resources: ResourceManager,
     memory_tracker: MemoryTracker,
     frame_index: std::sync::atomic::AtomicU32,
     stats: Arc<RwLock<RenderStats>>,
     command_batches: Arc<RwLock<Vec<RenderCommand>>>,
+    staging: Mutex<Option<StagingBuffer>>,
+    // The glyph masks of the frames drawn so far, and which of its
+    // contents the atlas texture has: its generation and cell count.
+    glyph_atlas: Mutex<GlyphAtlas<MaskKey>>,
+    glyph_atlas_uploaded: Mutex<Option<(u64, usize)>>,
+    // The images that have a texture, under its id; held so that no other
+    // image takes the address the id is made from.
+    image_textures: Mutex<HashMap<u64, Arc<DecodedImage>>>,
+    // Counts what the validation layers report, in debug builds.
+    debug_messenger: Option<(ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
 }
 
 impl VulkanRenderer {
@@ -158,19 +276,36 @@ impl VulkanRenderer {
             unsafe { Entry::load() }.map_err(|e| VulkanError::DeviceCreation(e.to_string()))?;
 
         let instance = Self::create_instance(&entry)?;
-        let surface = vk::SurfaceKHR::null();
+        let debug_messenger = cfg!(debug_assertions)
+            .then(|| Self::create_debug_messenger(&entry, &instance))
+            .transpose()?;
         let surface_loader = ash::extensions::khr::Surface::new(&entry, &instance);
 
-        let device =
-            Arc::new(VulkanDevice::new(&entry, &instance, surface, &surface_loader).await?);
+        // The device is picked before there is a window; attaching one
+        // checks that it can present to it.
+        let device = Arc::new(
+            VulkanDevice::new(&entry, &instance, vk::SurfaceKHR::null(), &surface_loader).await?,
+        );
 
         let swapchain_loader =
             ash::extensions::khr::Swapchain::new(&instance, device.logical_device());
         let command_manager = Arc::new(CommandManager::new(device.clone()).await?);
 
-        let render_pass = Self::create_render_pass(device.logical_device())?;
-        let pipeline_cache = Self::create_pipeline_cache(device.logical_device())?;
+        let render_pass =
+            Self::create_render_pass(device.logical_device(), vk::ImageLayout::PRESENT_SRC_KHR)?;
+        let offscreen_pass = Self::create_render_pass(
+            device.logical_device(),
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )?;
+        let identity = CacheIdentity::of(device.device_properties());
+        let restored = config
+            .pipeline_cache_path
+            .as_deref()
+            .and_then(|path| pipeline_cache::load(path, &identity));
+        let (pipeline_cache, pipeline_cache_stats) =
+            Self::create_pipeline_cache(device.logical_device(), restored.as_deref())?;
         let descriptor_pool = Self::create_descriptor_pool(device.logical_device())?;
+        let resources = ResourceManager::new(&instance, &device)?;
 
         let swapchain_data = Arc::new(RwLock::new(SwapchainData {
             swapchain: vk::SwapchainKHR::null(),
@@ -183,25 +318,43 @@ impl VulkanRenderer {
                 height: config.viewport_height.max(1),
             },
             current_index: 0,
+            depth_image: vk::Image::null(),
+            depth_memory: vk::DeviceMemory::null(),
+            depth_view: vk::ImageView::null(),
         }));
 
-        Ok(Self {
+        let renderer = Self {
+            entry,
             instance,
             device,
-            surface,
+            surface: RwLock::new(vk::SurfaceKHR::null()),
             surface_loader,
             swapchain_loader,
             swapchain_data,
+            swapchain_suboptimal: std::sync::atomic::AtomicBool::new(false),
             command_manager,
             render_pass,
+            offscreen_pass,
             pipeline_cache,
+            pipeline_cache_path: config.pipeline_cache_path.clone(),
+            pipeline_cache_stats: Mutex::new(pipeline_cache_stats),
             descriptor_pool,
-            resources: ResourceManager::new(),
+            resources,
             memory_tracker: MemoryTracker::new(),
             frame_index: std::sync::atomic::AtomicU32::new(0),
             stats: Arc::new(RwLock::new(RenderStats::default())),
             command_batches: Arc::new(RwLock::new(Vec::with_capacity(1024))),
-        })
+            staging: Mutex::new(None),
+            glyph_atlas: Mutex::new(GlyphAtlas::default()),
+            glyph_atlas_uploaded: Mutex::new(None),
+            image_textures: Mutex::new(HashMap::new()),
+            debug_messenger,
+        };
+        // Without them, frames are painted on the CPU and copied in.
+        if let Err(e) = renderer.create_quad_pipelines().await {
+            tracing::warn!("Drawing frames on the CPU: {}", e);
+        }
+        Ok(renderer)
     }
 
     fn create_instance(entry: &Entry) -> Result<Instance> {
@@ -215,7 +368,7 @@ impl VulkanRenderer {
             .engine_version(vk::make_api_version(0, 1, 0, 0))
             .api_version(vk::API_VERSION_1_3);
 
-        let mut extension_names = vec![ash::extensions::khr::Surface::name().as_ptr()];
+        let mut extension_names = surface::instance_extensions(entry);
 
         let mut layer_names = Vec::new();
 
@@ -236,7 +389,35 @@ impl VulkanRenderer {
         }
     }
 
-    fn create_render_pass(device: &Device) -> Result<vk::RenderPass> {
+    fn create_debug_messenger(
+        entry: &Entry,
+        instance: &Instance,
+    ) -> Result<(ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT)> {
+        let debug_utils = ash::extensions::ext::DebugUtils::new(entry, instance);
+        let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(
+                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            )
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(on_validation_message));
+        let messenger = unsafe {
+            debug_utils
+                .create_debug_utils_messenger(&create_info, None)
+                .map_err(|e| VulkanError::DeviceCreation(e.to_string()))?
+        };
+        Ok((debug_utils, messenger))
+    }
+
+    /// The pass frames are drawn in, leaving the color attachment in
+    /// `final_layout`: to present, or to copy out of.
+    fn create_render_pass(
+        device: &Device,
+        final_layout: vk::ImageLayout,
+    ) -> Result<vk::RenderPass> {
         let attachments = [
             vk::AttachmentDescription::builder()
                 .format(vk::Format::B8G8R8A8_SRGB)
@@ -246,7 +427,7 @@ impl VulkanRenderer {
                 .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                 .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                 .initial_layout(vk::ImageLayout::UNDEFINED)
-                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .final_layout(final_layout)
                 .build(),
             vk::AttachmentDescription::builder()
                 .format(vk::Format::D32_SFLOAT)
@@ -276,7 +457,7 @@ impl VulkanRenderer {
             .depth_stencil_attachment(&depth_attachment_ref)
             .build()];
 
-        let dependencies = [vk::SubpassDependency::builder()
+        let mut dependencies = vec![vk::SubpassDependency::builder()
             .src_subpass(vk::SUBPASS_EXTERNAL)
             .dst_subpass(0)
             .src_stage_mask(
@@ -293,6 +474,19 @@ impl VulkanRenderer {
                     | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
             )
             .build()];
+        // A copy out of the image waits for the drawing.
+        if final_layout == vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+            dependencies.push(
+                vk::SubpassDependency::builder()
+                    .src_subpass(0)
+                    .dst_subpass(vk::SUBPASS_EXTERNAL)
+                    .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .build(),
+            );
+        }
 
         let render_pass_info = vk::RenderPassCreateInfo::builder()
             .attachments(&attachments)
@@ -306,16 +500,60 @@ impl VulkanRenderer {
         }
     }
 
-    fn create_pipeline_cache(device: &Device) -> Result<vk::PipelineCache> {
+    /// A pipeline cache seeded with `initial`, a blob the device accepts;
+    /// one the driver still rejects is dropped for an empty cache.
+    fn create_pipeline_cache(
+        device: &Device,
+        initial: Option<&[u8]>,
+    ) -> Result<(vk::PipelineCache, PipelineCacheStats)> {
+        if let Some(initial) = initial {
+            let cache_info = vk::PipelineCacheCreateInfo::builder().initial_data(initial);
+            match unsafe { device.create_pipeline_cache(&cache_info, None) } {
+                Ok(cache) => {
+                    let stats = PipelineCacheStats {
+                        blob_bytes: initial.len(),
+                        restored: true,
+                    };
+                    return Ok((cache, stats));
+                }
+                Err(e) => tracing::debug!("Driver rejected the saved pipeline cache: {}", e),
+            }
+        }
         let cache_info = vk::PipelineCacheCreateInfo::builder();
 
         unsafe {
             device
                 .create_pipeline_cache(&cache_info, None)
+                .map(|cache| (cache, PipelineCacheStats::default()))
                 .map_err(|e| VulkanError::PipelineCreation(e.to_string()))
         }
     }
 
+    pub fn pipeline_cache_stats(&self) -> PipelineCacheStats {
+        *self.pipeline_cache_stats.lock()
+    }
+
+    /// Write the pipeline cache to its path. Failing to is logged; the
+    /// next run starts with an empty cache.
+    fn save_pipeline_cache(&self) {
+        let Some(path) = &self.pipeline_cache_path else {
+            return;
+        };
+        let data = unsafe {
+            self.device
+                .logical_device()
+                .get_pipeline_cache_data(self.pipeline_cache)
+        };
+        let saved = match data {
+            Ok(data) => pipeline_cache::save(path, &data).map(|_| data.len()),
+            Err(e) => Err(std::io::Error::other(e.to_string())),
+        };
+        match saved {
+            Ok(bytes) => self.pipeline_cache_stats.lock().blob_bytes = bytes,
+            Err(e) => tracing::warn!("Could not save the pipeline cache: {}", e),
+        }
+    }
+
     fn create_descriptor_pool(device: &Device) -> Result<vk::DescriptorPool> {
         let pool_sizes = [
             vk::DescriptorPoolSize::builder()
@@ -344,33 +582,109 @@ impl VulkanRenderer {
         }
     }
 
-    pub async fn render(&self, document: &Document, layout_engine: &LayoutEngine) -> Result<()> {
+    /// Compile the quad shaders and build a pipeline for each kind of
+    /// quad, registered under its id in [`batch`].
+    async fn create_quad_pipelines(&self) -> Result<()> {
+        let texture_layout = pipelines::create_texture_set_layout(self.device.logical_device())?;
+        self.resources
+            .descriptor_set_layouts
+            .insert(TEXTURE_SET_LAYOUT.to_string(), texture_layout);
+
+        let shaders = ShaderManager::new(self.device.clone()).await?;
+        let vertex = shaders.compile_shader(pipelines::vertex_shader()).await?;
+        let built = async {
+            self.create_quad_pipeline(&shaders, &vertex, SOLID_PIPELINE, &[])
+                .await?;
+            self.create_quad_pipeline(&shaders, &vertex, GLYPH_PIPELINE, &[texture_layout])
+                .await?;
+            self.create_quad_pipeline(&shaders, &vertex, IMAGE_PIPELINE, &[texture_layout])
+                .await
+        }
+        .await;
+        shaders.cleanup_shader(&vertex);
+        built
+    }
+
+    async fn create_quad_pipeline(
+        &self,
+        shaders: &ShaderManager,
+        vertex: &CompiledShader,
+        pipeline_id: u64,
+        set_layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<()> {
+        let source = pipelines::fragment_shader(pipeline_id).ok_or_else(|| {
+            VulkanError::PipelineCreation(format!("pipeline {} has no shader", pipeline_id))
+        })?;
+        let fragment = shaders.compile_shader(source).await?;
+        let device = self.device.logical_device();
+        let built = pipelines::create_layout(device, set_layouts).and_then(|layout| {
+            self.resources.pipeline_layouts.insert(pipeline_id, layout);
+            pipelines::create_pipeline(
+                device,
+                self.pipeline_cache,
+                self.render_pass,
+                layout,
+                vertex.module,
+                fragment.module,
+            )
+        });
+        shaders.cleanup_shader(&fragment);
+        self.resources.pipelines.insert(pipeline_id, built?);
+        Ok(())
+    }
+
+    /// Draw `quads` into the next swapchain image, over white. Returns
+    /// whether the frame was presented; not when there is no window or its
+    /// swapchain had to be recreated, so the caller draws again. A frame
+    /// the pipelines cannot draw is painted on the CPU and copied in.
+    pub async fn render(&self, quads: &[DrawQuad]) -> Result<bool> {
         let frame_start = std::time::Instant::now();
 
         let swapchain_data = {
             let swapchain = self.swapchain_data.read();
             if swapchain.swapchain == vk::SwapchainKHR::null() {
-                return Ok(());
+                return Ok(false);
             }
             swapchain.clone()
         };
+        let extent = swapchain_data.extent;
 
-        let image_index = self.acquire_next_image(&swapchain_data)?;
-        let command_buffer = self.command_manager.begin_frame().await?;
-
-        self.begin_render_pass(command_buffer, &swapchain_data, image_index)?;
-
-        let mut stats = RenderStats::default();
-        let mut batch = {
+        let batch = {
             let mut guard = self.command_batches.write();
             std::mem::take(&mut *guard)
         };
+        let Some((batch, uploads)) = self.build_render_commands(quads, extent, batch)? else {
+            let mut snapshot = Snapshot::rasterize(extent.width, extent.height, quads);
+            snapshot.flatten([255, 255, 255]);
+            return self.present(&snapshot).await;
+        };
 
-        batch = self
-            .build_render_commands(document, layout_engine, batch)
-            .await?;
-        stats.draw_calls = batch.len() as u32;
-
+        let (frame, gpu_wait) = self.command_manager.next_frame().await?;
+        let Some(image_index) = self
+            .acquire_next_image(&swapchain_data, frame.image_available)
+            .await?
+        else {
+            // Textures that were not filled are made again next time.
+            for (texture, _) in &uploads.textures {
+                self.remove_texture(*texture);
+            }
+            *self.command_batches.write() = batch;
+            return Ok(false);
+        };
+        let command_buffer = self.command_manager.begin_frame(&frame).await?;
+        self.record_uploads(command_buffer, &uploads)?;
+
+        self.begin_render_pass(
+            command_buffer,
+            self.render_pass,
+            swapchain_data.framebuffers[image_index as usize],
+            extent,
+        )?;
+
+        let mut stats = RenderStats {
+            draw_calls: batch.len() as u32,
+            ..Default::default()
+        };
         for command in batch.iter() {
             self.execute_render_command(command_buffer, command, &mut stats)?;
         }
@@ -381,52 +695,499 @@ impl VulkanRenderer {
         }
 
         self.end_render_pass(command_buffer)?;
-        self.command_manager.end_frame(command_buffer).await?;
-        self.present_frame(&swapchain_data, image_index)?;
+        self.command_manager
+            .end_frame(
+                command_buffer,
+                &frame,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .await?;
+        let presented = self
+            .present_frame(&swapchain_data, image_index, frame.render_finished)
+            .await?;
+        // The next frame writes the vertex and index buffers again.
+        let draw_start = std::time::Instant::now();
+        self.command_manager.wait_for_frame(frame.index).await?;
+        let gpu_wait = gpu_wait + draw_start.elapsed();
 
+        stats.gpu_wait_ms = gpu_wait.as_secs_f32() * 1000.0;
         stats.frame_time_ms = frame_start.elapsed().as_secs_f32() * 1000.0;
         stats.memory_used_mb = (self.get_memory_usage().await / (1024 * 1024)) as u32;
         *self.stats.write() = stats;
 
         self.frame_index
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        Ok(())
+        Ok(presented)
+    }
+
+    /// Draw `quads` over white into an offscreen `width` by `height` image
+    /// and read it back as RGBA. Needs no window, and leaves the
+    /// swapchain alone. A frame the pipelines cannot draw is painted on the
+    /// CPU instead.
+    pub async fn capture(&self, quads: &[DrawQuad], width: u32, height: u32) -> Result<Snapshot> {
+        let extent = vk::Extent2D { width, height };
+        let batch = std::mem::take(&mut *self.command_batches.write());
+        let Some((batch, uploads)) = self.build_render_commands(quads, extent, batch)? else {
+            let mut snapshot = Snapshot::rasterize(width, height, quads);
+            snapshot.flatten([255, 255, 255]);
+            return Ok(snapshot);
+        };
+
+        let captured = self.draw_offscreen(&batch, &uploads, extent).await;
+        if captured.is_err() {
+            // Textures that were not filled are made again next time.
+            for (texture, _) in &uploads.textures {
+                self.remove_texture(*texture);
+            }
+        }
+        *self.command_batches.write() = batch;
+        captured
+    }
+
+    /// Record `batch` into a color image `extent` large, through the
+    /// offscreen pass, wait for it and read the image back.
+    async fn draw_offscreen(
+        &self,
+        batch: &[RenderCommand],
+        uploads: &TextureUploads,
+        extent: vk::Extent2D,
+    ) -> Result<Snapshot> {
+        let vk::Extent2D { width, height } = extent;
+        let allocation_failed =
+            |e: crate::renderer::gpu::GpuError| VulkanError::MemoryAllocation(e.to_string());
+        let device = self.resources.device.clone();
+        let allocator = self.resources.allocator.clone();
+        let color = Texture::new_with_mips(
+            device.clone(),
+            allocator.clone(),
+            width,
+            height,
+            vk::Format::B8G8R8A8_SRGB,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            Some(1),
+        )
+        .map_err(allocation_failed)?;
+        let depth = Texture::new_depth_texture(
+            device.clone(),
+            allocator.clone(),
+            width,
+            height,
+            vk::Format::D32_SFLOAT,
+        )
+        .map_err(allocation_failed)?;
+        let readback = Buffer::new(
+            device.clone(),
+            allocator,
+            width as vk::DeviceSize * height as vk::DeviceSize * 4,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        )
+        .map_err(allocation_failed)?;
+
+        let attachments = [color.get_image_view(), depth.get_image_view()];
+        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(self.offscreen_pass)
+            .attachments(&attachments)
+            .width(width)
+            .height(height)
+            .layers(1);
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None) }
+            .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+
+        let drawn = async {
+            let command_buffer = self
+                .command_manager
+                .allocate_command_buffer(
+                    CommandBufferType::Graphics,
+                    vk::CommandBufferLevel::PRIMARY,
+                )
+                .await?;
+            self.record_uploads(command_buffer, uploads)?;
+            self.begin_render_pass(command_buffer, self.offscreen_pass, framebuffer, extent)?;
+            let mut stats = RenderStats::default();
+            for command in batch {
+                self.execute_render_command(command_buffer, command, &mut stats)?;
+            }
+            self.end_render_pass(command_buffer)?;
+
+            // The pass left the image ready to copy out of.
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .build();
+            let to_host = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build();
+            unsafe {
+                device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    color.get_image(),
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    readback.get_buffer(),
+                    &[region],
+                );
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[to_host],
+                    &[],
+                    &[],
+                );
+            }
+            self.command_manager.submit_and_wait(command_buffer).await?;
+            Ok::<_, VulkanError>(())
+        }
+        .await;
+        unsafe { device.destroy_framebuffer(framebuffer, None) };
+        drawn?;
+
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+        readback.read_data(&mut data).map_err(allocation_failed)?;
+        // The image is BGRA; snapshots are RGBA.
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        Ok(Snapshot {
+            width,
+            height,
+            data,
+        })
     }
 
-    async fn build_render_commands(
+    /// `quads` as draws from one vertex and one index buffer, written for
+    /// a framebuffer `extent` large, and the textures to fill before them.
+    /// `None` when a quad needs a pipeline there is not.
+    fn build_render_commands(
         &self,
-        _document: &Document,
-        _layout_engine: &LayoutEngine,
+        quads: &[DrawQuad],
+        extent: vk::Extent2D,
         mut batch: Vec<RenderCommand>,
-    ) -> Result<Vec<RenderCommand>> {
+    ) -> Result<Option<(Vec<RenderCommand>, TextureUploads)>> {
         batch.clear();
-        Ok(batch)
+        let mut atlas = self.glyph_atlas.lock();
+        let Some(quads) = QuadBatch::build(quads, extent.width, extent.height, &mut atlas) else {
+            return Ok(None);
+        };
+        let drawable = quads
+            .draws
+            .iter()
+            .all(|draw| self.resources.pipelines.contains_key(&draw.pipeline_id));
+        if !drawable || quads.images.len() > MAX_FRAME_IMAGES {
+            return Ok(None);
+        }
+        let mut uploads = TextureUploads::default();
+        if quads.draws.is_empty() {
+            return Ok(Some((batch, uploads)));
+        }
+
+        let mut staging = Vec::new();
+        let samples_atlas = quads
+            .draws
+            .iter()
+            .any(|draw| draw.texture == Some(GLYPH_ATLAS_TEXTURE));
+        if samples_atlas {
+            let (width, height) = atlas.size();
+            let contents = Some((atlas.generation(), atlas.cell_count()));
+            let created =
+                self.ensure_texture(GLYPH_ATLAS_TEXTURE, width, height, vk::Format::R8_UNORM)?;
+            let mut uploaded = self.glyph_atlas_uploaded.lock();
+            if created || *uploaded != contents {
+                uploads
+                    .textures
+                    .push((GLYPH_ATLAS_TEXTURE, staging.len() as vk::DeviceSize));
+                staging.extend_from_slice(atlas.pixels());
+                *uploaded = contents;
+            }
+        }
+        drop(atlas);
+
+        // Textures of images the frame no longer shows go; no frame is
+        // drawing from them.
+        let mut images = self.image_textures.lock();
+        images.retain(|id, _| {
+            let shown = quads.images.iter().any(|(shown, _)| shown == id);
+            if !shown {
+                self.remove_texture(*id);
+            }
+            shown
+        });
+        for (id, image) in &quads.images {
+            let format = vk::Format::R8G8B8A8_SRGB;
+            if self.ensure_texture(*id, image.width, image.height, format)? {
+                uploads
+                    .textures
+                    .push((*id, staging.len() as vk::DeviceSize));
+                staging.extend_from_slice(&image.data);
+            }
+            images.insert(*id, image.clone());
+        }
+        drop(images);
+
+        if !uploads.textures.is_empty() {
+            uploads.staging =
+                self.write_buffer("staging", vk::BufferUsageFlags::TRANSFER_SRC, &staging)?;
+        }
+
+        let vertex_buffer = self.write_buffer(
+            "vertices",
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &quads.vertices,
+        )?;
+        let index_buffer = self.write_buffer(
+            "indices",
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            &quads.indices,
+        )?;
+        for draw in quads.draws {
+            let layout = self
+                .resources
+                .pipeline_layouts
+                .get(&draw.pipeline_id)
+                .map_or(vk::PipelineLayout::null(), |layout| *layout);
+            let descriptor_sets = draw
+                .texture
+                .and_then(|texture| self.resources.descriptor_sets.get(&texture))
+                .map(|set| SmallVec::from_slice(&[*set]))
+                .unwrap_or_default();
+            batch.push(RenderCommand {
+                pipeline_id: draw.pipeline_id,
+                layout,
+                vertex_buffer,
+                index_buffer,
+                descriptor_sets,
+                first_index: draw.first_index,
+                index_count: draw.index_count,
+                vertex_offset: 0,
+                instance_count: 1,
+                clip: draw.clip,
+            });
+        }
+        Ok(Some((batch, uploads)))
+    }
+
+    /// Make sure there is a `width` by `height` texture `id`, with a
+    /// descriptor set binding it. Whether it was made anew, and has yet to
+    /// be filled.
+    fn ensure_texture(&self, id: u64, width: u32, height: u32, format: vk::Format) -> Result<bool> {
+        let fits = self.resources.textures.get(&id).is_some_and(|texture| {
+            texture.width() == width && texture.height() == height && texture.format() == format
+        });
+        if fits {
+            return Ok(false);
+        }
+
+        let texture = Texture::new_with_mips(
+            self.resources.device.clone(),
+            self.resources.allocator.clone(),
+            width,
+            height,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            Some(1),
+        )
+        .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+        let device = self.device.logical_device();
+        let set = match self.resources.descriptor_sets.get(&id) {
+            Some(set) => *set,
+            None => {
+                let layout = self
+                    .resources
+                    .descriptor_set_layouts
+                    .get(TEXTURE_SET_LAYOUT)
+                    .map(|layout| *layout)
+                    .ok_or_else(|| {
+                        VulkanError::PipelineCreation("no texture set layout".to_string())
+                    })?;
+                let layouts = [layout];
+                let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                    .descriptor_pool(self.descriptor_pool)
+                    .set_layouts(&layouts);
+                let set = unsafe { device.allocate_descriptor_sets(&allocate_info) }
+                    .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?[0];
+                self.resources.descriptor_sets.insert(id, set);
+                set
+            }
+        };
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: self.resources.sampler,
+            image_view: texture.get_image_view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build();
+        // No frame is drawing from the set: each is waited for.
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+
+        self.memory_tracker.allocate(
+            vk::Handle::as_raw(texture.get_image()),
+            texture.calculate_required_bytes(),
+        );
+        if let Some(old) = self.resources.textures.insert(id, texture) {
+            self.memory_tracker
+                .deallocate(vk::Handle::as_raw(old.get_image()));
+        }
+        Ok(true)
+    }
+
+    /// Drop texture `id` and give its descriptor set back to the pool.
+    fn remove_texture(&self, id: u64) {
+        if let Some((_, texture)) = self.resources.textures.remove(&id) {
+            self.memory_tracker
+                .deallocate(vk::Handle::as_raw(texture.get_image()));
+        }
+        if let Some((_, set)) = self.resources.descriptor_sets.remove(&id) {
+            let freed = unsafe {
+                self.device
+                    .logical_device()
+                    .free_descriptor_sets(self.descriptor_pool, &[set])
+            };
+            if let Err(e) = freed {
+                tracing::warn!("Failed to free a texture's descriptor set: {}", e);
+            }
+        }
+    }
+
+    /// Fill the textures of `uploads` from their staging buffer, ready for
+    /// the fragment shaders to sample.
+    fn record_uploads(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        uploads: &TextureUploads,
+    ) -> Result<()> {
+        for (id, offset) in &uploads.textures {
+            let Some(texture) = self.resources.textures.get(id) else {
+                continue;
+            };
+            texture
+                .transition_layout(
+                    command_buffer,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                )
+                .and_then(|()| {
+                    texture.copy_from_buffer_with_offset(
+                        command_buffer,
+                        uploads.staging,
+                        *offset,
+                        0,
+                    )
+                })
+                .and_then(|()| {
+                    texture.transition_layout(
+                        command_buffer,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    )
+                })
+                .map_err(|e| VulkanError::CommandBuffer(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Write `data` to the host-visible buffer `name`, replaced by a larger
+    /// one when it does not fit. No frame may be drawing from it.
+    fn write_buffer<T: Copy>(
+        &self,
+        name: &'static str,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<vk::Buffer> {
+        let size = std::mem::size_of_val(data).max(1) as vk::DeviceSize;
+        let fits = self
+            .resources
+            .buffers
+            .get(name)
+            .is_some_and(|buffer| buffer.size() >= size);
+        if !fits {
+            let capacity = size.next_power_of_two().max(MIN_BUFFER_SIZE);
+            let buffer = Buffer::new(
+                self.resources.device.clone(),
+                self.resources.allocator.clone(),
+                capacity,
+                usage,
+                MemoryLocation::CpuToGpu,
+            )
+            .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+            self.memory_tracker
+                .allocate(vk::Handle::as_raw(buffer.get_buffer()), capacity);
+            if let Some(old) = self.resources.buffers.insert(name, buffer) {
+                self.memory_tracker
+                    .deallocate(vk::Handle::as_raw(old.get_buffer()));
+            }
+        }
+
+        let mut buffer = self
+            .resources
+            .buffers
+            .get_mut(name)
+            .expect("buffer was just made");
+        buffer
+            .write_data(data)
+            .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+        Ok(buffer.get_buffer())
     }
 
-    fn acquire_next_image(&self, swapchain_data: &SwapchainData) -> Result<u32> {
-        let (image_index, _) = unsafe {
+    /// The swapchain image to draw the frame to, ready once `semaphore`
+    /// is signaled. `None` when the swapchain no longer matches the
+    /// surface; it is recreated and the frame skipped.
+    async fn acquire_next_image(
+        &self,
+        swapchain_data: &SwapchainData,
+        semaphore: vk::Semaphore,
+    ) -> Result<Option<u32>> {
+        let acquired = unsafe {
             self.swapchain_loader.acquire_next_image(
                 swapchain_data.swapchain,
                 u64::MAX,
-                vk::Semaphore::null(),
+                semaphore,
                 vk::Fence::null(),
             )
+        };
+        match acquired {
+            Ok((image_index, suboptimal)) => {
+                if suboptimal {
+                    self.swapchain_suboptimal
+                        .store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                Ok(Some(image_index))
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.recreate_swapchain().await?;
+                Ok(None)
+            }
+            Err(e) => Err(VulkanError::SwapchainCreation(e.to_string())),
         }
-        .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?;
-
-        Ok(image_index)
     }
 
+    /// Begin `render_pass` on `framebuffer`, cleared to white, with the
+    /// viewport covering it.
     fn begin_render_pass(
         &self,
         command_buffer: vk::CommandBuffer,
-        swapchain_data: &SwapchainData,
-        image_index: u32,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
     ) -> Result<()> {
         let clear_values = [
             vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 1.0],
+                    float32: [1.0, 1.0, 1.0, 1.0],
                 },
             },
             vk::ClearValue {
@@ -438,20 +1199,30 @@ impl VulkanRenderer {
         ];
 
         let render_pass_info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.render_pass)
-            .framebuffer(swapchain_data.framebuffers[image_index as usize])
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
-                extent: swapchain_data.extent,
+                extent,
             })
             .clear_values(&clear_values);
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
 
         unsafe {
-            self.device.logical_device().cmd_begin_render_pass(
+            let device = self.device.logical_device();
+            device.cmd_begin_render_pass(
                 command_buffer,
                 &render_pass_info,
                 vk::SubpassContents::INLINE,
             );
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
         }
 
         Ok(())
@@ -475,13 +1246,15 @@ impl VulkanRenderer {
                     self.device.logical_device().cmd_bind_descriptor_sets(
                         command_buffer,
                         vk::PipelineBindPoint::GRAPHICS,
-                        vk::PipelineLayout::null(),
+                        command.layout,
                         0,
                         &command.descriptor_sets,
                         &[],
                     );
                 }
 
+                self.apply_clip(command_buffer, command);
+
                 self.device.logical_device().cmd_bind_vertex_buffers(
                     command_buffer,
                     0,
@@ -500,7 +1273,7 @@ impl VulkanRenderer {
                     command_buffer,
                     command.index_count,
                     command.instance_count,
-                    0,
+                    command.first_index,
                     0,
                     0,
                 );
@@ -514,6 +1287,47 @@ impl VulkanRenderer {
         Ok(())
     }
 
+    /// Scissor to the clip chain's rect and hand its rounded clips to the
+    /// fragment shader. Unclipped draws get an unbounded scissor and zero
+    /// rounded clips.
+    unsafe fn apply_clip(&self, command_buffer: vk::CommandBuffer, command: &RenderCommand) {
+        let scissor = match command.clip.scissor() {
+            Some(rect) => {
+                let x = rect.x.floor().max(0.0);
+                let y = rect.y.floor().max(0.0);
+                vk::Rect2D {
+                    offset: vk::Offset2D {
+                        x: x as i32,
+                        y: y as i32,
+                    },
+                    extent: vk::Extent2D {
+                        width: ((rect.x + rect.width).ceil() - x).max(0.0) as u32,
+                        height: ((rect.y + rect.height).ceil() - y).max(0.0) as u32,
+                    },
+                }
+            }
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: i32::MAX as u32,
+                    height: i32::MAX as u32,
+                },
+            },
+        };
+        let device = self.device.logical_device();
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+
+        if command.layout != vk::PipelineLayout::null() {
+            device.cmd_push_constants(
+                command_buffer,
+                command.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                command.clip.push_constants().as_bytes(),
+            );
+        }
+    }
+
     fn end_render_pass(&self, command_buffer: vk::CommandBuffer) -> Result<()> {
         unsafe {
             self.device
@@ -523,33 +1337,576 @@ impl VulkanRenderer {
         Ok(())
     }
 
-    fn present_frame(&self, swapchain_data: &SwapchainData, image_index: u32) -> Result<()> {
+    /// Present `image_index` once `render_finished` is signaled. Returns
+    /// whether the swapchain is still current; an out of date or suboptimal
+    /// one is recreated, to be drawn again.
+    async fn present_frame(
+        &self,
+        swapchain_data: &SwapchainData,
+        image_index: u32,
+        render_finished: vk::Semaphore,
+    ) -> Result<bool> {
+        let wait_semaphores = [render_finished];
         let swapchains = [swapchain_data.swapchain];
         let image_indices = [image_index];
 
         let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&wait_semaphores)
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        unsafe {
+        let presented = unsafe {
             self.swapchain_loader
                 .queue_present(self.device.graphics_queue(), &present_info)
-                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?;
+        };
+        match presented {
+            Ok(false)
+                if !self
+                    .swapchain_suboptimal
+                    .load(std::sync::atomic::Ordering::Relaxed) =>
+            {
+                Ok(true)
+            }
+            Ok(_) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.recreate_swapchain().await?;
+                Ok(false)
+            }
+            Err(e) => Err(VulkanError::SwapchainCreation(e.to_string())),
         }
+    }
 
-        Ok(())
+    /// Present to `window` on `display` from now on, replacing any window
+    /// attached before.
+    ///
+    /// # Safety
+    ///
+    /// The handles must be of a live window that outlives this renderer or
+    /// the next window attached.
+    pub async unsafe fn attach_window(
+        &self,
+        display: RawDisplayHandle,
+        window: RawWindowHandle,
+    ) -> Result<()> {
+        let surface = surface::create_surface(&self.entry, &self.instance, display, window)?;
+        let supports_present = self
+            .surface_loader
+            .get_physical_device_surface_support(
+                self.device.physical_device(),
+                self.device.queue_families().graphics,
+                surface,
+            )
+            .unwrap_or(false);
+        if !supports_present {
+            self.surface_loader.destroy_surface(surface, None);
+            return Err(VulkanError::SurfaceCreation(
+                "the graphics queue cannot present to this window".to_string(),
+            ));
+        }
+
+        self.device.wait_idle().await?;
+        self.destroy_swapchain();
+        let previous = std::mem::replace(&mut *self.surface.write(), surface);
+        if previous != vk::SurfaceKHR::null() {
+            self.surface_loader.destroy_surface(previous, None);
+        }
+        self.recreate_swapchain().await
     }
 
+    /// Rebuild the swapchain at `width` by `height`, or where the window
+    /// system sizes it to the window. Before a window is attached, only
+    /// remembers the size.
     pub async fn resize_surface(&self, width: u32, height: u32) -> Result<()> {
+        {
+            let mut swapchain_data = self.swapchain_data.write();
+            swapchain_data.extent.width = width.max(1);
+            swapchain_data.extent.height = height.max(1);
+        }
+        self.recreate_swapchain().await
+    }
+
+    /// Replace the swapchain with one made for the surface as it is now,
+    /// and its views and framebuffers with ones for the new images.
+    async fn recreate_swapchain(&self) -> Result<()> {
+        let surface = *self.surface.read();
+        if surface == vk::SurfaceKHR::null() {
+            return Ok(());
+        }
         self.device.wait_idle().await?;
+        self.swapchain_suboptimal
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+
+        let physical_device = self.device.physical_device();
+        let (capabilities, formats) = unsafe {
+            let capabilities = self
+                .surface_loader
+                .get_physical_device_surface_capabilities(physical_device, surface)
+                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?;
+            let formats = self
+                .surface_loader
+                .get_physical_device_surface_formats(physical_device, surface)
+                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?;
+            (capabilities, formats)
+        };
+        // The render pass is built for this format.
+        let format = formats
+            .iter()
+            .find(|format| format.format == vk::Format::B8G8R8A8_SRGB)
+            .ok_or_else(|| {
+                VulkanError::SwapchainCreation("the window has no B8G8R8A8_SRGB format".to_string())
+            })?;
+
+        let requested = self.swapchain_data.read().extent;
+        let extent = if capabilities.current_extent.width != u32::MAX {
+            capabilities.current_extent
+        } else {
+            vk::Extent2D {
+                width: requested.width.clamp(
+                    capabilities.min_image_extent.width,
+                    capabilities.max_image_extent.width,
+                ),
+                height: requested.height.clamp(
+                    capabilities.min_image_extent.height,
+                    capabilities.max_image_extent.height,
+                ),
+            }
+        };
+        // A minimized window has nothing to present to until it is shown.
+        if extent.width == 0 || extent.height == 0 {
+            self.destroy_swapchain();
+            return Ok(());
+        }
+
+        let mut image_count = capabilities.min_image_count + 1;
+        if capabilities.max_image_count > 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+        let composite_alpha = [
+            vk::CompositeAlphaFlagsKHR::OPAQUE,
+            vk::CompositeAlphaFlagsKHR::INHERIT,
+            vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+            vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        ]
+        .into_iter()
+        .find(|mode| capabilities.supported_composite_alpha.contains(*mode))
+        .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+
+        let old_swapchain = self.swapchain_data.read().swapchain;
+        let create_info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(surface)
+            .min_image_count(image_count)
+            .image_format(format.format)
+            .image_color_space(format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            // Frames painted on the CPU are copied in.
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(capabilities.current_transform)
+            .composite_alpha(composite_alpha)
+            .present_mode(vk::PresentModeKHR::FIFO)
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+        let swapchain = unsafe {
+            self.swapchain_loader
+                .create_swapchain(&create_info, None)
+                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?
+        };
+        self.destroy_swapchain();
+
+        let mut data = SwapchainData {
+            swapchain,
+            images: Vec::new(),
+            image_views: Vec::new(),
+            framebuffers: Vec::new(),
+            format: format.format,
+            extent,
+            current_index: 0,
+            depth_image: vk::Image::null(),
+            depth_memory: vk::DeviceMemory::null(),
+            depth_view: vk::ImageView::null(),
+        };
+        // Store it first so what was made is destroyed if a step fails.
+        let built = self.create_swapchain_images(&mut data);
+        *self.swapchain_data.write() = data;
+        if built.is_err() {
+            self.destroy_swapchain();
+        }
+        built
+    }
 
-        let mut swapchain_data = self.swapchain_data.write();
-        swapchain_data.extent.width = width.max(1);
-        swapchain_data.extent.height = height.max(1);
+    fn create_swapchain_images(&self, data: &mut SwapchainData) -> Result<()> {
+        let device = self.device.logical_device();
+        data.images = unsafe {
+            self.swapchain_loader
+                .get_swapchain_images(data.swapchain)
+                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?
+        };
+        for &image in &data.images {
+            let view =
+                Self::create_image_view(device, image, data.format, vk::ImageAspectFlags::COLOR)?;
+            data.image_views.push(view);
+        }
 
+        let depth_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::D32_SFLOAT)
+            .extent(vk::Extent3D {
+                width: data.extent.width,
+                height: data.extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        data.depth_image = unsafe {
+            device
+                .create_image(&depth_info, None)
+                .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?
+        };
+        let requirements = self.device.get_image_memory_requirements(data.depth_image);
+        data.depth_memory =
+            self.allocate_memory(requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+        unsafe {
+            device
+                .bind_image_memory(data.depth_image, data.depth_memory, 0)
+                .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+        }
+        data.depth_view = Self::create_image_view(
+            device,
+            data.depth_image,
+            vk::Format::D32_SFLOAT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        for &view in &data.image_views {
+            let attachments = [view, data.depth_view];
+            let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(data.extent.width)
+                .height(data.extent.height)
+                .layers(1);
+            let framebuffer = unsafe {
+                device
+                    .create_framebuffer(&framebuffer_info, None)
+                    .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))?
+            };
+            data.framebuffers.push(framebuffer);
+        }
         Ok(())
     }
 
+    fn create_image_view(
+        device: &Device,
+        image: vk::Image,
+        format: vk::Format,
+        aspect_mask: vk::ImageAspectFlags,
+    ) -> Result<vk::ImageView> {
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            });
+        unsafe {
+            device
+                .create_image_view(&view_info, None)
+                .map_err(|e| VulkanError::SwapchainCreation(e.to_string()))
+        }
+    }
+
+    fn allocate_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<vk::DeviceMemory> {
+        let memory_type = self
+            .device
+            .find_memory_type(requirements.memory_type_bits, properties)
+            .ok_or_else(|| {
+                VulkanError::MemoryAllocation(format!("no memory type is {:?}", properties))
+            })?;
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type);
+        let memory = unsafe {
+            self.device
+                .logical_device()
+                .allocate_memory(&allocate_info, None)
+                .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?
+        };
+        self.memory_tracker
+            .allocate(vk::Handle::as_raw(memory), requirements.size);
+        Ok(memory)
+    }
+
+    /// Free memory [`Self::allocate_memory`] gave out.
+    unsafe fn free_memory(&self, memory: vk::DeviceMemory) {
+        self.device.logical_device().free_memory(memory, None);
+        self.memory_tracker.deallocate(vk::Handle::as_raw(memory));
+    }
+
+    /// Destroy the swapchain and what was made for its images. The device
+    /// must be idle.
+    fn destroy_swapchain(&self) {
+        let device = self.device.logical_device();
+        let mut data = self.swapchain_data.write();
+        unsafe {
+            for framebuffer in data.framebuffers.drain(..) {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            for view in data.image_views.drain(..) {
+                device.destroy_image_view(view, None);
+            }
+            if data.depth_view != vk::ImageView::null() {
+                device.destroy_image_view(data.depth_view, None);
+            }
+            if data.depth_image != vk::Image::null() {
+                device.destroy_image(data.depth_image, None);
+            }
+            if data.depth_memory != vk::DeviceMemory::null() {
+                self.free_memory(data.depth_memory);
+            }
+            if data.swapchain != vk::SwapchainKHR::null() {
+                self.swapchain_loader
+                    .destroy_swapchain(data.swapchain, None);
+            }
+        }
+        data.images.clear();
+        data.swapchain = vk::SwapchainKHR::null();
+        data.depth_view = vk::ImageView::null();
+        data.depth_image = vk::Image::null();
+        data.depth_memory = vk::DeviceMemory::null();
+    }
+
+    /// Show `snapshot` in the window, copied into the next swapchain image
+    /// and the rest of the image cleared to white. Returns whether it was
+    /// presented; not when there is no window or its swapchain had to be
+    /// recreated, so the caller presents again.
+    pub async fn present(&self, snapshot: &Snapshot) -> Result<bool> {
+        let frame_start = std::time::Instant::now();
+        let swapchain_data = {
+            let swapchain = self.swapchain_data.read();
+            if swapchain.swapchain == vk::SwapchainKHR::null() {
+                return Ok(false);
+            }
+            swapchain.clone()
+        };
+
+        let (frame, gpu_wait) = self.command_manager.next_frame().await?;
+        let Some(image_index) = self
+            .acquire_next_image(&swapchain_data, frame.image_available)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        let staging = self.upload(snapshot)?;
+        let command_buffer = self.command_manager.begin_frame(&frame).await?;
+        let image = swapchain_data.images[image_index as usize];
+        let device = self.device.logical_device();
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        unsafe {
+            let to_transfer = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(color_range)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+
+            device.cmd_clear_color_image(
+                command_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: [1.0, 1.0, 1.0, 1.0],
+                },
+                &[color_range],
+            );
+            let between = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[between],
+                &[],
+                &[],
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .buffer_offset(0)
+                .buffer_row_length(snapshot.width)
+                .buffer_image_height(snapshot.height)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: snapshot.width.min(swapchain_data.extent.width),
+                    height: snapshot.height.min(swapchain_data.extent.height),
+                    depth: 1,
+                })
+                .build();
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            let to_present = vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::empty())
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(color_range)
+                .build();
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+        }
+
+        self.command_manager
+            .end_frame(command_buffer, &frame, vk::PipelineStageFlags::TRANSFER)
+            .await?;
+        let presented = self
+            .present_frame(&swapchain_data, image_index, frame.render_finished)
+            .await?;
+        // The next frame reuses the staging buffer.
+        let copy_start = std::time::Instant::now();
+        self.command_manager.wait_for_frame(frame.index).await?;
+        let gpu_wait = gpu_wait + copy_start.elapsed();
+
+        *self.stats.write() = RenderStats {
+            frame_time_ms: frame_start.elapsed().as_secs_f32() * 1000.0,
+            gpu_wait_ms: gpu_wait.as_secs_f32() * 1000.0,
+            memory_used_mb: (self.get_memory_usage().await / (1024 * 1024)) as u32,
+            ..Default::default()
+        };
+        self.frame_index
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(presented)
+    }
+
+    /// Copy `snapshot` into the staging buffer as BGRA, the swapchain's
+    /// byte order, growing the buffer to fit.
+    fn upload(&self, snapshot: &Snapshot) -> Result<vk::Buffer> {
+        let device = self.device.logical_device();
+        let size = snapshot.data.len().max(4) as vk::DeviceSize;
+        let mut staging = self.staging.lock();
+        if staging.as_ref().is_some_and(|staging| staging.size < size) {
+            if let Some(old) = staging.take() {
+                unsafe {
+                    device.destroy_buffer(old.buffer, None);
+                    self.free_memory(old.memory);
+                }
+            }
+        }
+        if staging.is_none() {
+            let buffer_info = vk::BufferCreateInfo::builder()
+                .size(size)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let buffer = unsafe {
+                device
+                    .create_buffer(&buffer_info, None)
+                    .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?
+            };
+            let requirements = self.device.get_buffer_memory_requirements(buffer);
+            let memory = match self.allocate_memory(
+                requirements,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    unsafe { device.destroy_buffer(buffer, None) };
+                    return Err(e);
+                }
+            };
+            unsafe {
+                device
+                    .bind_buffer_memory(buffer, memory, 0)
+                    .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+            }
+            *staging = Some(StagingBuffer {
+                buffer,
+                memory,
+                size,
+            });
+        }
+        let staging = staging.as_ref().expect("staging buffer was just made");
+
+        unsafe {
+            let mapped = device
+                .map_memory(
+                    staging.memory,
+                    0,
+                    snapshot.data.len() as vk::DeviceSize,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .map_err(|e| VulkanError::MemoryAllocation(e.to_string()))?;
+            let mapped = std::slice::from_raw_parts_mut(mapped.cast::<u8>(), snapshot.data.len());
+            for (bgra, rgba) in mapped
+                .chunks_exact_mut(4)
+                .zip(snapshot.data.chunks_exact(4))
+            {
+                bgra.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+            }
+            device.unmap_memory(staging.memory);
+        }
+        Ok(staging.buffer)
+    }
+
+    /// What the last frame took.
+    pub fn stats(&self) -> RenderStats {
+        *self.stats.read()
+    }
+
     pub async fn get_metrics(&self) -> serde_json::Value {
         let stats = self.stats.read();
         serde_json::json!({
@@ -560,6 +1917,7 @@ impl VulkanRenderer {
             "vertices": stats.vertices,
             "memory_used_mb": stats.memory_used_mb,
             "pipeline_switches": stats.pipeline_switches,
+            "gpu_wait_ms": stats.gpu_wait_ms,
             "frame_index": self.frame_index.load(std::sync::atomic::Ordering::Relaxed),
         })
     }
@@ -572,9 +1930,23 @@ impl VulkanRenderer {
         self.memory_tracker.peak_usage()
     }
 
+    pub fn memory_report(&self) -> GpuMemoryReport {
+        self.memory_tracker.report()
+    }
+
     pub async fn shutdown(&self) -> Result<()> {
         self.device.wait_idle().await?;
+        self.command_manager.shutdown().await?;
+        self.save_pipeline_cache();
 
+        for entry in self.resources.buffers.iter() {
+            self.memory_tracker
+                .deallocate(vk::Handle::as_raw(entry.value().get_buffer()));
+        }
+        for entry in self.resources.textures.iter() {
+            self.memory_tracker
+                .deallocate(vk::Handle::as_raw(entry.value().get_image()));
+        }
         unsafe {
             self.resources.cleanup(self.device.logical_device());
             self.device
@@ -586,11 +1958,25 @@ impl VulkanRenderer {
             self.device
                 .logical_device()
                 .destroy_render_pass(self.render_pass, None);
+            self.device
+                .logical_device()
+                .destroy_render_pass(self.offscreen_pass, None);
 
-            if self.surface != vk::SurfaceKHR::null() {
-                self.surface_loader.destroy_surface(self.surface, None);
+            if let Some(staging) = self.staging.lock().take() {
+                self.device
+                    .logical_device()
+                    .destroy_buffer(staging.buffer, None);
+                self.free_memory(staging.memory);
+            }
+            self.destroy_swapchain();
+            let surface = std::mem::replace(&mut *self.surface.write(), vk::SurfaceKHR::null());
+            if surface != vk::SurfaceKHR::null() {
+                self.surface_loader.destroy_surface(surface, None);
             }
 
+            if let Some((debug_utils, messenger)) = &self.debug_messenger {
+                debug_utils.destroy_debug_utils_messenger(*messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
 
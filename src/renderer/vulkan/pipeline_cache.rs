@@ -0,0 +1,85 @@
+//! The pipeline cache kept on disk between runs.
+//!
+//! A driver only takes back a cache blob it wrote itself, which its header
+//! says: the vendor, the device and the driver's cache UUID. A blob written
+//! by another device or driver, or cut short, is dropped and the cache
+//! starts empty; it is rewritten on shutdown either way.
+
+use crate::core::storage::write_atomically;
+use ash::vk;
+use std::path::Path;
+
+/// Bytes of a `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header.
+pub const HEADER_LEN: usize = 32;
+const HEADER_VERSION_ONE: u32 = vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32;
+
+/// What a cache blob must say about the device it is loaded on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheIdentity {
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub cache_uuid: [u8; vk::UUID_SIZE],
+}
+
+impl CacheIdentity {
+    pub fn of(properties: &vk::PhysicalDeviceProperties) -> Self {
+        Self {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    /// Whether `blob` is a cache this device and driver wrote.
+    pub fn accepts(&self, blob: &[u8]) -> bool {
+        if blob.len() < HEADER_LEN {
+            return false;
+        }
+        let word = |at: usize| u32::from_le_bytes(blob[at..at + 4].try_into().unwrap());
+        let header_len = word(0) as usize;
+        header_len >= HEADER_LEN
+            && header_len <= blob.len()
+            && word(4) == HEADER_VERSION_ONE
+            && word(8) == self.vendor_id
+            && word(12) == self.device_id
+            && blob[16..HEADER_LEN] == self.cache_uuid
+    }
+
+    /// A header naming this device, as a driver writes one; for tests.
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&(HEADER_LEN as u32).to_le_bytes());
+        header.extend_from_slice(&HEADER_VERSION_ONE.to_le_bytes());
+        header.extend_from_slice(&self.vendor_id.to_le_bytes());
+        header.extend_from_slice(&self.device_id.to_le_bytes());
+        header.extend_from_slice(&self.cache_uuid);
+        header
+    }
+}
+
+/// The size of the cache and where it came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineCacheStats {
+    /// Bytes of the blob loaded at startup, or written at shutdown once
+    /// saved.
+    pub blob_bytes: usize,
+    /// Whether the cache started from a blob on disk.
+    pub restored: bool,
+}
+
+/// The blob at `path` if `identity` accepts it; a missing, unreadable or
+/// foreign file gives `None`.
+pub fn load(path: &Path, identity: &CacheIdentity) -> Option<Vec<u8>> {
+    let blob = std::fs::read(path).ok()?;
+    if identity.accepts(&blob) {
+        Some(blob)
+    } else {
+        tracing::debug!("Discarding pipeline cache {}", path.display());
+        None
+    }
+}
+
+/// Replace the blob at `path` with `data`.
+pub fn save(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    write_atomically(path, data)
+}
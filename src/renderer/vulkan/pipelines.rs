@@ -0,0 +1,286 @@
+//! The pipelines frames are drawn with, and their shaders.
+//!
+//! The shaders are GLSL, compiled by [`ShaderManager`](super::shaders::ShaderManager)
+//! when the renderer starts. Every pipeline reads [`QuadVertex`] and the
+//! clip push constants, blends source-over and leaves depth alone: quads
+//! paint in the order they come. Those that sample a texture bind it at
+//! set 0, binding 0.
+
+use super::batch::{QuadVertex, GLYPH_PIPELINE, IMAGE_PIPELINE, SOLID_PIPELINE};
+use super::shaders::{OptimizationLevel, ShaderSource, ShaderStage};
+use super::{Result, VulkanError};
+use crate::renderer::clip::{ClipPushConstants, ROUNDED_CLIP_GLSL};
+use ash::{vk, Device};
+use std::collections::HashMap;
+use std::mem::{offset_of, size_of};
+
+const QUAD_VERTEX_GLSL: &str = r#"
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in vec4 color;
+layout(location = 3) in vec4 bounds;
+layout(location = 4) in float sigma;
+
+layout(location = 0) out vec2 frag_tex_coord;
+layout(location = 1) out vec4 frag_color;
+layout(location = 2) flat out vec4 frag_bounds;
+layout(location = 3) flat out float frag_sigma;
+
+void main() {
+    gl_Position = vec4(position, 0.0, 1.0);
+    frag_tex_coord = tex_coord;
+    frag_color = color;
+    frag_bounds = bounds;
+    frag_sigma = sigma;
+}
+"#;
+
+/// What every fragment shader starts with: its inputs, and the coverage of
+/// the rounded clips and of a blurred quad's edges.
+const FRAGMENT_PRELUDE_GLSL: &str = r#"
+layout(location = 0) in vec2 frag_tex_coord;
+layout(location = 1) in vec4 frag_color;
+layout(location = 2) flat in vec4 frag_bounds;
+layout(location = 3) flat in float frag_sigma;
+
+layout(location = 0) out vec4 out_color;
+
+// Abramowitz and Stegun 7.1.26, as the software rasterizer has it.
+float erf_approx(float x) {
+    float t = 1.0 / (1.0 + 0.3275911 * abs(x));
+    float poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741
+        + t * (-1.453152027 + t * 1.061405429))));
+    return sign(x) * (1.0 - poly * exp(-x * x));
+}
+
+// Share of a gaussian centered at p that falls within start..end.
+float axis_coverage(float p, float start, float end) {
+    float scale = frag_sigma * 1.41421356;
+    return 0.5 * (erf_approx((p - start) / scale) - erf_approx((p - end) / scale));
+}
+
+float blur_coverage(vec2 p) {
+    if (frag_sigma <= 0.0) {
+        return 1.0;
+    }
+    vec2 end = frag_bounds.xy + frag_bounds.zw;
+    return axis_coverage(p.x, frag_bounds.x, end.x)
+        * axis_coverage(p.y, frag_bounds.y, end.y);
+}
+"#;
+
+const SOLID_FRAGMENT_GLSL: &str = r#"
+void main() {
+    float coverage = blur_coverage(gl_FragCoord.xy) * rounded_clip_coverage(gl_FragCoord.xy);
+    out_color = vec4(frag_color.rgb, frag_color.a * coverage);
+}
+"#;
+
+/// Glyphs tint their coverage, a single channel of the atlas, with their
+/// color. Texture coordinates are in atlas pixels.
+const GLYPH_FRAGMENT_GLSL: &str = r#"
+layout(set = 0, binding = 0) uniform sampler2D quad_texture;
+
+void main() {
+    vec2 uv = frag_tex_coord / vec2(textureSize(quad_texture, 0));
+    float coverage = texture(quad_texture, uv).r * rounded_clip_coverage(gl_FragCoord.xy);
+    out_color = vec4(frag_color.rgb, frag_color.a * coverage);
+}
+"#;
+
+/// Images multiply their texels by their color. The texture is sRGB, so
+/// the texels arrive in linear light, as the color does.
+const IMAGE_FRAGMENT_GLSL: &str = r#"
+layout(set = 0, binding = 0) uniform sampler2D quad_texture;
+
+void main() {
+    vec4 texel = texture(quad_texture, frag_tex_coord / vec2(textureSize(quad_texture, 0)));
+    float coverage = rounded_clip_coverage(gl_FragCoord.xy);
+    out_color = vec4(frag_color.rgb * texel.rgb, frag_color.a * texel.a * coverage);
+}
+"#;
+
+/// The vertex shader every pipeline shares.
+pub fn vertex_shader() -> ShaderSource {
+    source(ShaderStage::Vertex, QUAD_VERTEX_GLSL.to_string())
+}
+
+/// The fragment shader of the pipeline `pipeline_id`.
+pub fn fragment_shader(pipeline_id: u64) -> Option<ShaderSource> {
+    let main = match pipeline_id {
+        SOLID_PIPELINE => SOLID_FRAGMENT_GLSL,
+        GLYPH_PIPELINE => GLYPH_FRAGMENT_GLSL,
+        IMAGE_PIPELINE => IMAGE_FRAGMENT_GLSL,
+        _ => return None,
+    };
+    let glsl = [FRAGMENT_PRELUDE_GLSL, ROUNDED_CLIP_GLSL, main].concat();
+    Some(source(ShaderStage::Fragment, glsl))
+}
+
+fn source(stage: ShaderStage, glsl_code: String) -> ShaderSource {
+    ShaderSource {
+        glsl_code,
+        entry_point: "main".to_string(),
+        stage,
+        include_paths: Vec::new(),
+        defines: HashMap::new(),
+        optimization_level: OptimizationLevel::Performance,
+    }
+}
+
+/// The set a texture is bound with: one combined image sampler, for the
+/// fragment shader.
+pub fn create_texture_set_layout(device: &Device) -> Result<vk::DescriptorSetLayout> {
+    let bindings = [vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .build()];
+    let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .map_err(|e| VulkanError::PipelineCreation(e.to_string()))
+    }
+}
+
+/// A layout with the clip push constants, binding `set_layouts`.
+pub fn create_layout(
+    device: &Device,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> Result<vk::PipelineLayout> {
+    let push_constant_ranges = [vk::PushConstantRange {
+        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        offset: 0,
+        size: size_of::<ClipPushConstants>() as u32,
+    }];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&push_constant_ranges);
+    unsafe {
+        device
+            .create_pipeline_layout(&layout_info, None)
+            .map_err(|e| VulkanError::PipelineCreation(e.to_string()))
+    }
+}
+
+/// A pipeline drawing quads in subpass 0 of `render_pass`. Viewport and
+/// scissor are set while recording.
+pub fn create_pipeline(
+    device: &Device,
+    cache: vk::PipelineCache,
+    render_pass: vk::RenderPass,
+    layout: vk::PipelineLayout,
+    vertex: vk::ShaderModule,
+    fragment: vk::ShaderModule,
+) -> Result<vk::Pipeline> {
+    let entry_point = c"main";
+    let stages = [
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vertex)
+            .name(entry_point)
+            .build(),
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(fragment)
+            .name(entry_point)
+            .build(),
+    ];
+
+    let bindings = [vk::VertexInputBindingDescription {
+        binding: 0,
+        stride: size_of::<QuadVertex>() as u32,
+        input_rate: vk::VertexInputRate::VERTEX,
+    }];
+    let attribute = |location, format, offset: usize| vk::VertexInputAttributeDescription {
+        location,
+        binding: 0,
+        format,
+        offset: offset as u32,
+    };
+    let attributes = [
+        attribute(
+            0,
+            vk::Format::R32G32_SFLOAT,
+            offset_of!(QuadVertex, position),
+        ),
+        attribute(
+            1,
+            vk::Format::R32G32_SFLOAT,
+            offset_of!(QuadVertex, tex_coord),
+        ),
+        attribute(
+            2,
+            vk::Format::R32G32B32A32_SFLOAT,
+            offset_of!(QuadVertex, color),
+        ),
+        attribute(
+            3,
+            vk::Format::R32G32B32A32_SFLOAT,
+            offset_of!(QuadVertex, bounds),
+        ),
+        attribute(4, vk::Format::R32_SFLOAT, offset_of!(QuadVertex, sigma)),
+    ];
+    let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(&bindings)
+        .vertex_attribute_descriptions(&attributes);
+
+    let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+    // Quads are wound either way once mapped to clip space.
+    let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::CLOCKWISE);
+    let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
+        .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+    let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false);
+
+    // Source-over. The target is sRGB, so the hardware decodes what is
+    // there, blends in linear light and encodes the result.
+    let blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build()];
+    let color_blending =
+        vk::PipelineColorBlendStateCreateInfo::builder().attachments(&blend_attachments);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
+
+    let pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input)
+        .input_assembly_state(&input_assembly)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterizer)
+        .multisample_state(&multisampling)
+        .depth_stencil_state(&depth_stencil)
+        .color_blend_state(&color_blending)
+        .dynamic_state(&dynamic_state)
+        .layout(layout)
+        .render_pass(render_pass)
+        .subpass(0)
+        .build();
+    let pipelines = unsafe {
+        device
+            .create_graphics_pipelines(cache, &[pipeline_info], None)
+            .map_err(|(_, e)| VulkanError::PipelineCreation(e.to_string()))?
+    };
+    Ok(pipelines[0])
+}
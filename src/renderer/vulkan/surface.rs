@@ -0,0 +1,92 @@
+//! Vulkan surfaces for native windows, from their raw handles.
+//!
+//! Covers the window systems with a plain `VK_KHR_*_surface` extension:
+//! Xlib, XCB, Wayland and Win32. AppKit needs a `CAMetalLayer` set up on the
+//! view first, which the engine does not do.
+
+use super::{Result, VulkanError};
+use ash::extensions::khr;
+use ash::vk;
+use ash::{Entry, Instance};
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+use std::ffi::{c_char, CStr};
+
+/// `VK_KHR_surface` and whichever window system surface extensions the
+/// loader offers, to enable on the instance before there is a window.
+pub(super) fn instance_extensions(entry: &Entry) -> Vec<*const c_char> {
+    let available = entry
+        .enumerate_instance_extension_properties(None)
+        .unwrap_or_default();
+    let offered = |name: &CStr| {
+        available
+            .iter()
+            .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == name)
+    };
+    let mut extensions = vec![khr::Surface::name().as_ptr()];
+    for name in [
+        khr::XlibSurface::name(),
+        khr::XcbSurface::name(),
+        khr::WaylandSurface::name(),
+        khr::Win32Surface::name(),
+    ] {
+        if offered(name) {
+            extensions.push(name.as_ptr());
+        }
+    }
+    extensions
+}
+
+/// A surface for `window` on `display`.
+///
+/// # Safety
+///
+/// The handles must be of a live window, which must outlive the surface.
+pub(super) unsafe fn create_surface(
+    entry: &Entry,
+    instance: &Instance,
+    display: RawDisplayHandle,
+    window: RawWindowHandle,
+) -> Result<vk::SurfaceKHR> {
+    let surface = match (display, window) {
+        (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
+            let display = display.display.ok_or_else(|| {
+                VulkanError::SurfaceCreation("Xlib window without a display".to_string())
+            })?;
+            let create_info = vk::XlibSurfaceCreateInfoKHR::builder()
+                .dpy(display.as_ptr().cast())
+                .window(window.window);
+            khr::XlibSurface::new(entry, instance).create_xlib_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
+            let connection = display.connection.ok_or_else(|| {
+                VulkanError::SurfaceCreation("XCB window without a connection".to_string())
+            })?;
+            let create_info = vk::XcbSurfaceCreateInfoKHR::builder()
+                .connection(connection.as_ptr())
+                .window(window.window.get());
+            khr::XcbSurface::new(entry, instance).create_xcb_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
+            let create_info = vk::WaylandSurfaceCreateInfoKHR::builder()
+                .display(display.display.as_ptr())
+                .surface(window.surface.as_ptr());
+            khr::WaylandSurface::new(entry, instance).create_wayland_surface(&create_info, None)
+        }
+        (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
+            let hinstance = window
+                .hinstance
+                .map_or(std::ptr::null(), |hinstance| hinstance.get() as *const _);
+            let create_info = vk::Win32SurfaceCreateInfoKHR::builder()
+                .hinstance(hinstance)
+                .hwnd(window.hwnd.get() as *const _);
+            khr::Win32Surface::new(entry, instance).create_win32_surface(&create_info, None)
+        }
+        (display, window) => {
+            return Err(VulkanError::SurfaceCreation(format!(
+                "unsupported window system: {:?} on {:?}",
+                window, display
+            )))
+        }
+    };
+    surface.map_err(|e| VulkanError::SurfaceCreation(e.to_string()))
+}
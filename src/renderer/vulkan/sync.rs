@@ -0,0 +1,105 @@
+//! Semaphores and fences for the frames in flight.
+//!
+//! Each frame acquires its swapchain image signaling `image_available`,
+//! submits waiting on it and signaling `render_finished` and `in_flight`,
+//! and presents waiting on `render_finished`. Frames take the slots in
+//! turn; a slot is reused once its fence says the GPU is done with it.
+
+use super::command::{CommandError, Result};
+use ash::{vk, Device};
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// The synchronization objects of one frame in flight.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameSignals {
+    pub index: u32,
+    pub image_available: vk::Semaphore,
+    pub render_finished: vk::Semaphore,
+    pub in_flight: vk::Fence,
+}
+
+pub struct FrameSync {
+    frames: Vec<FrameSignals>,
+    next: Mutex<u32>,
+}
+
+impl FrameSync {
+    pub fn new(device: &Device, frames_in_flight: u32) -> Result<Self> {
+        let mut frames = Vec::with_capacity(frames_in_flight as usize);
+        for index in 0..frames_in_flight {
+            frames.push(Self::create_frame(device, index)?);
+        }
+        Ok(Self {
+            frames,
+            next: Mutex::new(0),
+        })
+    }
+
+    fn create_frame(device: &Device, index: u32) -> Result<FrameSignals> {
+        // Signaled, so the first use of the slot does not wait.
+        let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        unsafe {
+            Ok(FrameSignals {
+                index,
+                image_available: device
+                    .create_semaphore(&semaphore_info, None)
+                    .map_err(|e| CommandError::Synchronization(e.to_string()))?,
+                render_finished: device
+                    .create_semaphore(&semaphore_info, None)
+                    .map_err(|e| CommandError::Synchronization(e.to_string()))?,
+                in_flight: device
+                    .create_fence(&fence_info, None)
+                    .map_err(|e| CommandError::Synchronization(e.to_string()))?,
+            })
+        }
+    }
+
+    /// How many frames can be in flight at once.
+    pub fn frames_in_flight(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    /// The slot the next frame takes, once the GPU is done with what was
+    /// last submitted in it, and how long that took. The slot is only
+    /// taken by [`Self::advance`], so a frame given up before it submits
+    /// anything leaves the fence signaled for the next try.
+    pub fn wait_for_next(&self, device: &Device) -> Result<(FrameSignals, Duration)> {
+        let frame = self.frames[*self.next.lock() as usize];
+        let started = Instant::now();
+        unsafe {
+            device
+                .wait_for_fences(&[frame.in_flight], true, u64::MAX)
+                .map_err(|e| CommandError::Synchronization(e.to_string()))?;
+        }
+        Ok((frame, started.elapsed()))
+    }
+
+    /// Take `frame`'s slot: its fence is reset for the submission about to
+    /// signal it, and the next frame gets the following slot.
+    pub fn advance(&self, device: &Device, frame: &FrameSignals) -> Result<()> {
+        unsafe {
+            device
+                .reset_fences(&[frame.in_flight])
+                .map_err(|e| CommandError::Synchronization(e.to_string()))?;
+        }
+        *self.next.lock() = (frame.index + 1) % self.frames_in_flight();
+        Ok(())
+    }
+
+    pub fn frame(&self, index: u32) -> FrameSignals {
+        self.frames[(index % self.frames_in_flight()) as usize]
+    }
+
+    /// # Safety
+    ///
+    /// The device must be idle, and the objects are not to be used after.
+    pub unsafe fn destroy(&self, device: &Device) {
+        for frame in &self.frames {
+            device.destroy_semaphore(frame.image_available, None);
+            device.destroy_semaphore(frame.render_finished, None);
+            device.destroy_fence(frame.in_flight, None);
+        }
+    }
+}
@@ -0,0 +1,1107 @@
+//! Binary encoding of layout trees for the out-of-process renderer.
+//!
+//! A content process sends the renderer one frame per layout: a keyframe
+//! carrying the whole [`LayoutTree`], or a delta carrying only the nodes
+//! that changed since the frame it names as its base, keyed by the same
+//! [`PaintKey`]s the retained scene diffs by. [`FrameEncoder`] decides which
+//! to send; [`FrameDecoder`] rebuilds the tree on the other side.
+//!
+//! Numbers are little-endian and fixed-width, `f32`s as their bits. A frame
+//! is laid out as
+//!
+//! ```text
+//! magic "VBLT", version u16, kind u8 (0 keyframe, 1 delta), flags u8 (0)
+//! seq u64, base u64 (the frame a delta applies to; 0 for keyframes)
+//! strings_at u32, the offset of the string table
+//! keyframe: node count u32, then the boxes and the text nodes in order
+//! delta:    change count u32, then each as ordinal u32 and node;
+//!           order u8, and when 1 the ids of the frame's boxes and of its
+//!           text nodes, each list as count u32 and u64s
+//! strings:  count u32, then each as length u32 and UTF-8 bytes
+//! ```
+//!
+//! A node record is its id, element type, flags u16, bounds, colors,
+//! paint source, font, font properties, font metrics and language; the
+//! flags say which of decoration, shadows, corner radii, text, image URL and
+//! frame, scissor, rounded clips and the box edges (padding, border widths
+//! and border color) follow, so the common node without them costs 89
+//! bytes. Font properties are the weight, stretch, style byte and
+//! oblique angle, then a count u8 of variation settings, each as its tag and
+//! value.
+//!
+//! Colors, paint source names, font families, languages and image URLs are
+//! interned into the frame's string table and written as an index,
+//! `u32::MAX` standing for `None`: a page repeats a handful of them across
+//! thousands of nodes. Text content rarely repeats and is written inline.
+//! The table goes last so records are written straight into the frame as
+//! the strings are met.
+//!
+//! A delta without an order section patches nodes in place. One that adds
+//! or removes nodes, or moves them in paint order, carries the order of the
+//! whole frame; its nodes missing from the changes are taken from the base.
+//!
+//! Decoding is a hand-rolled, bounds-checked reader over the received bytes
+//! rather than bytemuck or rkyv. Layout nodes are not plain old data, so
+//! bytemuck could only cover their numeric fields and would want aligned
+//! buffers, which bytes off a pipe are not; rkyv would bring an archived
+//! twin of every type that still has to be validated and converted before
+//! the renderer can use it. The reader reads unaligned fields where they
+//! lie, validates the string table once and resolves references to `&str`s
+//! borrowed from the buffer, and copies only what the rebuilt nodes own.
+//! Every count is checked against the bytes left before anything is
+//! allocated for it, so a corrupt frame fails with a [`WireError`] rather
+//! than panicking or reserving gigabytes.
+
+use ahash::{AHashMap, AHashSet};
+use thiserror::Error;
+
+use super::retained::{frame_entries, ChangeSet, FrameEntry, PaintSource};
+use super::{
+    ClipChain, ClipRect, CornerRadii, DecorationLines, DecorationStyle, EdgeWidths, ElementType,
+    LayoutNode, LayoutTree, PaintKey, Rect, Style, TextDecoration, TextShadow,
+    MAX_ROUNDED_CLIP_DEPTH,
+};
+use crate::core::dom::NodeId;
+use crate::core::fonts::{FontMetrics, FontRequest, FontStyle};
+
+/// Largest frame either side accepts, and the cap on
+/// [`MessageType::LayoutFrame`](crate::sandbox::ipc::MessageType::LayoutFrame)
+/// payloads.
+pub const MAX_LAYOUT_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+pub const WIRE_VERSION: u16 = 6;
+
+const MAGIC: &[u8; 4] = b"VBLT";
+
+/// Written where a string reference or inline string is `None`.
+const NONE: u32 = u32::MAX;
+
+/// Bytes before the first record.
+const HEADER_LEN: usize = 4 + 2 + 1 + 1 + 8 + 8 + 4;
+
+/// Bytes of a node record before its optional parts: id, element type,
+/// flags, bounds, background, background paint, color, font family, font
+/// size, font properties without variation settings, metrics and language.
+const MIN_NODE_LEN: usize = 8 + 1 + 2 + 16 + 4 * 4 + 4 + 14 + 6 * 4 + 4;
+
+// Flags of a node record, most naming an optional part that follows its
+// fixed fields, in this order.
+const HAS_DECORATION: u16 = 1;
+const HAS_SHADOWS: u16 = 1 << 1;
+const HAS_RADII: u16 = 1 << 2;
+const HAS_TEXT: u16 = 1 << 3;
+const HAS_IMAGE: u16 = 1 << 4;
+const HAS_SCISSOR: u16 = 1 << 5;
+const HAS_ROUNDED_CLIPS: u16 = 1 << 6;
+const CLIPS_OVERFLOW: u16 = 1 << 7;
+const HAS_EDGES: u16 = 1 << 8;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum WireError {
+    #[error("Layout frame ends early")]
+    Truncated,
+    #[error("Not a layout frame")]
+    BadMagic,
+    #[error("Unsupported layout frame version {0}")]
+    UnsupportedVersion(u16),
+    #[error("Invalid {0} tag {1}")]
+    InvalidTag(&'static str, u8),
+    #[error("String is not UTF-8")]
+    InvalidUtf8,
+    #[error("String reference {0} is past the string table")]
+    StringOutOfRange(u32),
+    #[error("Delta on frame {base} cannot apply to frame {current:?}")]
+    BaseMismatch { base: u64, current: Option<u64> },
+    #[error("Layout frame of {0} bytes exceeds the cap")]
+    TooLarge(usize),
+    #[error("{0} bytes past the end of the layout frame")]
+    TrailingBytes(usize),
+    #[error("Delta names {0:?}, which is not in the frame")]
+    UnknownKey(PaintKey),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Keyframe,
+    Delta,
+}
+
+/// What a frame is, read without decoding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub kind: FrameKind,
+    pub seq: u64,
+    /// The frame a delta applies to; 0 for keyframes.
+    pub base: u64,
+}
+
+/// Read the header of `bytes`, checking magic and version.
+pub fn read_header(bytes: &[u8]) -> Result<FrameHeader, WireError> {
+    Reader::new(bytes).header().map(|(header, _)| header)
+}
+
+/// Where a string is referenced from. The last string seen in each place
+/// is remembered, as neighbouring nodes mostly share their colors and font.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Background,
+    BackgroundPaint,
+    Color,
+    FontFamily,
+    Language,
+    DecorationColor,
+    ShadowColor,
+    ImageUrl,
+    BorderColor,
+}
+
+/// Interns strings as the frame's records are written.
+#[derive(Default)]
+struct StringTable<'a> {
+    index: AHashMap<&'a str, u32>,
+    strings: Vec<&'a str>,
+    recent: [Option<(&'a str, u32)>; 9],
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, field: Field, string: Option<&'a String>) -> u32 {
+        let string = match string {
+            Some(string) => string.as_str(),
+            None => return NONE,
+        };
+        let recent = &mut self.recent[field as usize];
+        match *recent {
+            Some((last, index)) if last == string => index,
+            _ => {
+                let index = *self.index.entry(string).or_insert_with(|| {
+                    self.strings.push(string);
+                    (self.strings.len() - 1) as u32
+                });
+                *recent = Some((string, index));
+                index
+            }
+        }
+    }
+}
+
+struct Writer<'a> {
+    body: Vec<u8>,
+    strings: StringTable<'a>,
+}
+
+impl<'a> Writer<'a> {
+    /// A frame with its header written, sized for about `nodes` records.
+    fn new(kind: FrameKind, seq: u64, base: u64, nodes: usize) -> Self {
+        let mut writer = Self {
+            body: Vec::with_capacity(HEADER_LEN + nodes * (MIN_NODE_LEN + 16) + 64),
+            strings: StringTable::default(),
+        };
+        writer.body.extend_from_slice(MAGIC);
+        writer.u16(WIRE_VERSION);
+        writer.u8(match kind {
+            FrameKind::Keyframe => 0,
+            FrameKind::Delta => 1,
+        });
+        writer.u8(0);
+        writer.u64(seq);
+        writer.u64(base);
+        writer.u32(0);
+        writer
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.body.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.body.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string_ref(&mut self, field: Field, string: Option<&'a String>) {
+        let index = self.strings.intern(field, string);
+        self.u32(index);
+    }
+
+    fn rect(&mut self, rect: &Rect) {
+        self.f32(rect.x);
+        self.f32(rect.y);
+        self.f32(rect.width);
+        self.f32(rect.height);
+    }
+
+    fn radii(&mut self, radii: &CornerRadii) {
+        self.f32(radii.top_left);
+        self.f32(radii.top_right);
+        self.f32(radii.bottom_right);
+        self.f32(radii.bottom_left);
+    }
+
+    fn edges(&mut self, edges: &EdgeWidths) {
+        self.f32(edges.top);
+        self.f32(edges.right);
+        self.f32(edges.bottom);
+        self.f32(edges.left);
+    }
+
+    fn font(&mut self, font: &FontRequest) {
+        self.f32(font.weight);
+        self.f32(font.stretch);
+        self.u8(match font.style {
+            FontStyle::Normal => 0,
+            FontStyle::Italic => 1,
+            FontStyle::Oblique => 2,
+        });
+        self.f32(font.oblique_angle);
+        let settings = &font.variation_settings[..font.variation_settings.len().min(255)];
+        self.u8(settings.len() as u8);
+        for (tag, value) in settings {
+            self.body.extend_from_slice(tag);
+            self.f32(*value);
+        }
+    }
+
+    fn node(&mut self, node: &'a LayoutNode) {
+        let style = &node.style;
+        let decorated = style.text_decoration != TextDecoration::default();
+        let rounded = node.clip.rounded();
+        let edged = !style.padding.is_zero()
+            || !style.border_width.is_zero()
+            || style.border_color.is_some();
+        let flags = [
+            (node.text_content.is_some(), HAS_TEXT),
+            (node.image_url.is_some(), HAS_IMAGE),
+            (decorated, HAS_DECORATION),
+            (!style.text_shadows.is_empty(), HAS_SHADOWS),
+            (style.border_radius != CornerRadii::default(), HAS_RADII),
+            (style.clips_overflow, CLIPS_OVERFLOW),
+            (node.clip.scissor().is_some(), HAS_SCISSOR),
+            (!rounded.is_empty(), HAS_ROUNDED_CLIPS),
+            (edged, HAS_EDGES),
+        ]
+        .into_iter()
+        .fold(
+            0,
+            |flags, (set, flag)| if set { flags | flag } else { flags },
+        );
+
+        self.u64(node.node_id.0);
+        self.u8(match node.element_type {
+            ElementType::Block => 0,
+            ElementType::Inline => 1,
+            ElementType::Image => 2,
+            ElementType::Text => 3,
+        });
+        self.u16(flags);
+        self.rect(&node.bounds);
+        self.string_ref(Field::Background, style.background_color.as_ref());
+        self.string_ref(Field::BackgroundPaint, style.background_paint.as_ref());
+        self.string_ref(Field::Color, style.color.as_ref());
+        self.string_ref(Field::FontFamily, style.font_family.as_ref());
+        self.f32(style.font_size);
+        self.font(&style.font);
+        let metrics = &style.font_metrics;
+        for value in [
+            metrics.ascent,
+            metrics.descent,
+            metrics.underline_offset,
+            metrics.underline_thickness,
+            metrics.strikeout_offset,
+            metrics.strikeout_thickness,
+        ] {
+            self.f32(value);
+        }
+        self.string_ref(Field::Language, style.language.as_ref());
+
+        if decorated {
+            let decoration = &style.text_decoration;
+            let lines = &decoration.lines;
+            self.u8(lines.underline as u8
+                | (lines.overline as u8) << 1
+                | (lines.line_through as u8) << 2);
+            self.u8(match decoration.style {
+                DecorationStyle::Solid => 0,
+                DecorationStyle::Double => 1,
+                DecorationStyle::Dotted => 2,
+                DecorationStyle::Dashed => 3,
+                DecorationStyle::Wavy => 4,
+            });
+            self.string_ref(Field::DecorationColor, decoration.color.as_ref());
+            match decoration.thickness {
+                Some(thickness) => {
+                    self.u8(1);
+                    self.f32(thickness);
+                }
+                None => self.u8(0),
+            }
+        }
+        if flags & HAS_SHADOWS != 0 {
+            let shadows = &style.text_shadows[..style.text_shadows.len().min(u16::MAX as usize)];
+            self.u16(shadows.len() as u16);
+            for shadow in shadows {
+                self.f32(shadow.offset_x);
+                self.f32(shadow.offset_y);
+                self.f32(shadow.blur_radius);
+                self.string_ref(Field::ShadowColor, shadow.color.as_ref());
+            }
+        }
+        if flags & HAS_RADII != 0 {
+            self.radii(&style.border_radius);
+        }
+        if edged {
+            self.edges(&style.padding);
+            self.edges(&style.border_width);
+            self.string_ref(Field::BorderColor, style.border_color.as_ref());
+        }
+        if let Some(text) = &node.text_content {
+            self.u32(text.len() as u32);
+            self.body.extend_from_slice(text.as_bytes());
+        }
+        if flags & HAS_IMAGE != 0 {
+            self.string_ref(Field::ImageUrl, node.image_url.as_ref());
+            self.u32(node.image_frame);
+        }
+        if let Some(scissor) = node.clip.scissor() {
+            self.rect(scissor);
+        }
+        if !rounded.is_empty() {
+            self.u8(rounded.len() as u8);
+            for clip in rounded {
+                self.rect(&clip.rect);
+                self.radii(&clip.radii);
+            }
+        }
+    }
+
+    fn ids<'n>(&mut self, nodes: impl ExactSizeIterator<Item = &'n LayoutNode>) {
+        self.u32(nodes.len() as u32);
+        for node in nodes {
+            self.u64(node.node_id.0);
+        }
+    }
+
+    /// The frame, once the string table is appended.
+    fn finish(mut self) -> Result<Vec<u8>, WireError> {
+        let strings_at = self.body.len();
+        let strings_len: usize = self.strings.strings.iter().map(|s| 4 + s.len()).sum();
+        let len = strings_at + 4 + strings_len;
+        if len > MAX_LAYOUT_FRAME_BYTES {
+            return Err(WireError::TooLarge(len));
+        }
+        self.body[HEADER_LEN - 4..HEADER_LEN].copy_from_slice(&(strings_at as u32).to_le_bytes());
+        let strings = std::mem::take(&mut self.strings.strings);
+        self.u32(strings.len() as u32);
+        for string in strings {
+            self.u32(string.len() as u32);
+            self.body.extend_from_slice(string.as_bytes());
+        }
+        Ok(self.body)
+    }
+}
+
+/// Encode the whole of `tree` as frame `seq`.
+pub fn encode_keyframe(seq: u64, tree: &LayoutTree) -> Result<Vec<u8>, WireError> {
+    let count = tree.nodes.len() + tree.text_nodes.len();
+    let mut writer = Writer::new(FrameKind::Keyframe, seq, 0, count);
+    writer.u32(count as u32);
+    for node in tree.nodes.iter().chain(&tree.text_nodes) {
+        writer.node(node);
+    }
+    writer.finish()
+}
+
+/// Encode `changes`, the added and altered nodes of frame `seq`, as a delta
+/// on frame `base`. `order` is the whole new frame, needed whenever nodes
+/// were added, removed or reordered; the receiver adopts its paint order.
+/// Overlay keys are skipped, as the overlay is not part of the tree.
+pub fn encode_delta(
+    seq: u64,
+    base: u64,
+    changes: &[(PaintKey, &LayoutNode)],
+    order: Option<&LayoutTree>,
+) -> Result<Vec<u8>, WireError> {
+    let mut writer = Writer::new(FrameKind::Delta, seq, base, changes.len());
+    let count_at = writer.body.len();
+    writer.u32(0);
+    let mut count = 0u32;
+    for &(key, node) in changes {
+        let ordinal = match key {
+            PaintKey::Box(_, ordinal) | PaintKey::Text(_, ordinal) => ordinal,
+            PaintKey::Overlay => continue,
+        };
+        writer.u32(ordinal);
+        writer.node(node);
+        count += 1;
+    }
+    writer.body[count_at..count_at + 4].copy_from_slice(&count.to_le_bytes());
+    match order {
+        Some(tree) => {
+            writer.u8(1);
+            writer.ids(tree.nodes.iter());
+            writer.ids(tree.text_nodes.iter());
+        }
+        None => writer.u8(0),
+    }
+    writer.finish()
+}
+
+/// The nodes of `frame` that `changes`, from diffing it against the retained
+/// scene, repaints: what a delta for it carries.
+pub fn changed_nodes<'a>(
+    frame: &[FrameEntry<'a>],
+    changes: &ChangeSet,
+) -> Vec<(PaintKey, &'a LayoutNode)> {
+    frame
+        .iter()
+        .filter(|entry| changes.repaints(entry.key))
+        .filter_map(|entry| match entry.source {
+            PaintSource::Node(node) => Some((entry.key, node)),
+            PaintSource::Overlay(_) => None,
+        })
+        .collect()
+}
+
+/// Turns successive layout trees into frames, each a delta on the last one
+/// encoded unless a keyframe is due.
+pub struct FrameEncoder {
+    seq: u64,
+    /// The nodes of the last frame encoded, as the receiver has them.
+    sent: AHashMap<PaintKey, LayoutNode>,
+    order: Vec<PaintKey>,
+    keyframe_due: bool,
+}
+
+impl Default for FrameEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self {
+            seq: 0,
+            sent: AHashMap::new(),
+            order: Vec::new(),
+            keyframe_due: true,
+        }
+    }
+
+    /// Sequence number of the last frame encoded; 0 before the first.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Send the next frame whole, as when the receiver lost track.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_due = true;
+    }
+
+    /// Encode `tree` as the next frame. A delta changing more than half the
+    /// nodes is sent as a keyframe instead, which is no larger. A frame
+    /// over the cap is not sent and leaves the encoder where it was.
+    pub fn encode(&mut self, tree: &LayoutTree) -> Result<Vec<u8>, WireError> {
+        let seq = self.seq + 1;
+        let entries = frame_entries(tree, &[]);
+        let changes: Vec<(PaintKey, &LayoutNode)> = entries
+            .iter()
+            .filter_map(|entry| match entry.source {
+                PaintSource::Node(node) if self.sent.get(&entry.key) != Some(node) => {
+                    Some((entry.key, node))
+                }
+                _ => None,
+            })
+            .collect();
+        let reordered = entries.len() != self.order.len()
+            || entries
+                .iter()
+                .zip(&self.order)
+                .any(|(entry, key)| entry.key != *key);
+
+        let keyframe = self.keyframe_due || changes.len() * 2 > entries.len();
+        let frame = if keyframe {
+            encode_keyframe(seq, tree)?
+        } else {
+            encode_delta(seq, self.seq, &changes, reordered.then_some(tree))?
+        };
+
+        for (key, node) in changes {
+            self.sent.insert(key, node.clone());
+        }
+        if reordered {
+            let current: AHashSet<PaintKey> = entries.iter().map(|entry| entry.key).collect();
+            self.sent.retain(|key, _| current.contains(key));
+            self.order = entries.iter().map(|entry| entry.key).collect();
+        }
+        self.seq = seq;
+        self.keyframe_due = false;
+        Ok(frame)
+    }
+}
+
+/// Bounds-checked little-endian reads over a received frame.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, at: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        if len > self.remaining() {
+            return Err(WireError::Truncated);
+        }
+        let taken = &self.bytes[self.at..self.at + len];
+        self.at += len;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, WireError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, WireError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> Result<f32, WireError> {
+        self.array().map(f32::from_le_bytes)
+    }
+
+    fn flag(&mut self, what: &'static str) -> Result<bool, WireError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(WireError::InvalidTag(what, tag)),
+        }
+    }
+
+    /// A count of items at least `item_len` bytes each, refused when the
+    /// rest of the frame cannot hold that many.
+    fn count(&mut self, item_len: usize) -> Result<usize, WireError> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(item_len) > self.remaining() {
+            return Err(WireError::Truncated);
+        }
+        Ok(count)
+    }
+
+    fn str(&mut self, len: usize) -> Result<&'a str, WireError> {
+        std::str::from_utf8(self.take(len)?).map_err(|_| WireError::InvalidUtf8)
+    }
+
+    fn rect(&mut self) -> Result<Rect, WireError> {
+        Ok(Rect {
+            x: self.f32()?,
+            y: self.f32()?,
+            width: self.f32()?,
+            height: self.f32()?,
+        })
+    }
+
+    fn radii(&mut self) -> Result<CornerRadii, WireError> {
+        Ok(CornerRadii {
+            top_left: self.f32()?,
+            top_right: self.f32()?,
+            bottom_right: self.f32()?,
+            bottom_left: self.f32()?,
+        })
+    }
+
+    fn edges(&mut self) -> Result<EdgeWidths, WireError> {
+        Ok(EdgeWidths {
+            top: self.f32()?,
+            right: self.f32()?,
+            bottom: self.f32()?,
+            left: self.f32()?,
+        })
+    }
+
+    /// The header, and where the string table starts.
+    fn header(&mut self) -> Result<(FrameHeader, usize), WireError> {
+        if self.bytes.len() > MAX_LAYOUT_FRAME_BYTES {
+            return Err(WireError::TooLarge(self.bytes.len()));
+        }
+        if self.take(4).map_err(|_| WireError::BadMagic)? != MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        let version = self.u16()?;
+        if version != WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion(version));
+        }
+        let kind = match self.u8()? {
+            0 => FrameKind::Keyframe,
+            1 => FrameKind::Delta,
+            tag => return Err(WireError::InvalidTag("frame kind", tag)),
+        };
+        match self.u8()? {
+            0 => {}
+            flags => return Err(WireError::InvalidTag("flags", flags)),
+        }
+        let header = FrameHeader {
+            kind,
+            seq: self.u64()?,
+            base: self.u64()?,
+        };
+        let strings_at = self.u32()? as usize;
+        if strings_at < HEADER_LEN || strings_at > self.bytes.len() {
+            return Err(WireError::Truncated);
+        }
+        Ok((header, strings_at))
+    }
+
+    /// The string table, which runs to the end of the frame.
+    fn strings(&mut self) -> Result<Vec<&'a str>, WireError> {
+        let count = self.count(4)?;
+        let mut strings = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = self.u32()? as usize;
+            strings.push(self.str(len)?);
+        }
+        match self.remaining() {
+            0 => Ok(strings),
+            extra => Err(WireError::TrailingBytes(extra)),
+        }
+    }
+}
+
+/// Reads node records against the frame's string table.
+struct NodeReader<'a> {
+    reader: Reader<'a>,
+    strings: Vec<&'a str>,
+}
+
+impl NodeReader<'_> {
+    fn string_ref(&mut self) -> Result<Option<String>, WireError> {
+        match self.reader.u32()? {
+            NONE => Ok(None),
+            index => match self.strings.get(index as usize) {
+                Some(string) => Ok(Some(string.to_string())),
+                None => Err(WireError::StringOutOfRange(index)),
+            },
+        }
+    }
+
+    fn node(&mut self) -> Result<LayoutNode, WireError> {
+        let node_id = NodeId(self.reader.u64()?);
+        let element_type = match self.reader.u8()? {
+            0 => ElementType::Block,
+            1 => ElementType::Inline,
+            2 => ElementType::Image,
+            3 => ElementType::Text,
+            tag => return Err(WireError::InvalidTag("element type", tag)),
+        };
+        let flags = self.reader.u16()?;
+        let bounds = self.reader.rect()?;
+        let background_color = self.string_ref()?;
+        let background_paint = self.string_ref()?;
+        let color = self.string_ref()?;
+        let font_family = self.string_ref()?;
+        let font_size = self.reader.f32()?;
+        let font = self.font()?;
+        let font_metrics = FontMetrics {
+            ascent: self.reader.f32()?,
+            descent: self.reader.f32()?,
+            underline_offset: self.reader.f32()?,
+            underline_thickness: self.reader.f32()?,
+            strikeout_offset: self.reader.f32()?,
+            strikeout_thickness: self.reader.f32()?,
+        };
+        let language = self.string_ref()?;
+
+        let text_decoration = match flags & HAS_DECORATION {
+            0 => TextDecoration::default(),
+            _ => self.decoration()?,
+        };
+        let mut text_shadows = Vec::new();
+        if flags & HAS_SHADOWS != 0 {
+            let count = self.reader.u16()? as usize;
+            if count * 16 > self.reader.remaining() {
+                return Err(WireError::Truncated);
+            }
+            text_shadows.reserve_exact(count);
+            for _ in 0..count {
+                text_shadows.push(TextShadow {
+                    offset_x: self.reader.f32()?,
+                    offset_y: self.reader.f32()?,
+                    blur_radius: self.reader.f32()?,
+                    color: self.string_ref()?,
+                });
+            }
+        }
+        let border_radius = match flags & HAS_RADII {
+            0 => CornerRadii::default(),
+            _ => self.reader.radii()?,
+        };
+        let (padding, border_width, border_color) = match flags & HAS_EDGES {
+            0 => (EdgeWidths::default(), EdgeWidths::default(), None),
+            _ => (
+                self.reader.edges()?,
+                self.reader.edges()?,
+                self.string_ref()?,
+            ),
+        };
+        let text_content = match flags & HAS_TEXT {
+            0 => None,
+            _ => {
+                let len = self.reader.u32()? as usize;
+                Some(self.reader.str(len)?.to_string())
+            }
+        };
+        let (image_url, image_frame) = match flags & HAS_IMAGE {
+            0 => (None, 0),
+            _ => (self.string_ref()?, self.reader.u32()?),
+        };
+        let scissor = match flags & HAS_SCISSOR {
+            0 => None,
+            _ => Some(self.reader.rect()?),
+        };
+        let mut rounded = Vec::new();
+        if flags & HAS_ROUNDED_CLIPS != 0 {
+            let depth = self.reader.u8()?;
+            if depth as usize > MAX_ROUNDED_CLIP_DEPTH {
+                return Err(WireError::InvalidTag("rounded clip depth", depth));
+            }
+            for _ in 0..depth {
+                rounded.push(ClipRect {
+                    rect: self.reader.rect()?,
+                    radii: self.reader.radii()?,
+                });
+            }
+        }
+
+        Ok(LayoutNode {
+            node_id,
+            bounds,
+            element_type,
+            style: Style {
+                background_color,
+                background_paint,
+                color,
+                font_family,
+                font_size,
+                font,
+                font_metrics,
+                language,
+                text_decoration,
+                text_shadows,
+                border_radius,
+                border_width,
+                border_color,
+                padding,
+                clips_overflow: flags & CLIPS_OVERFLOW != 0,
+            },
+            text_content,
+            image_url,
+            image_frame,
+            clip: ClipChain::from_parts(scissor, rounded),
+        })
+    }
+
+    fn font(&mut self) -> Result<FontRequest, WireError> {
+        let weight = self.reader.f32()?;
+        let stretch = self.reader.f32()?;
+        let style = match self.reader.u8()? {
+            0 => FontStyle::Normal,
+            1 => FontStyle::Italic,
+            2 => FontStyle::Oblique,
+            tag => return Err(WireError::InvalidTag("font style", tag)),
+        };
+        let oblique_angle = self.reader.f32()?;
+        let count = self.reader.u8()? as usize;
+        if count * 8 > self.reader.remaining() {
+            return Err(WireError::Truncated);
+        }
+        let mut variation_settings = Vec::with_capacity(count);
+        for _ in 0..count {
+            variation_settings.push((self.reader.array()?, self.reader.f32()?));
+        }
+        Ok(FontRequest {
+            weight,
+            stretch,
+            style,
+            oblique_angle,
+            variation_settings,
+        })
+    }
+
+    fn decoration(&mut self) -> Result<TextDecoration, WireError> {
+        let lines = match self.reader.u8()? {
+            bits if bits < 8 => DecorationLines {
+                underline: bits & 1 != 0,
+                overline: bits & 2 != 0,
+                line_through: bits & 4 != 0,
+            },
+            bits => return Err(WireError::InvalidTag("decoration lines", bits)),
+        };
+        let style = match self.reader.u8()? {
+            0 => DecorationStyle::Solid,
+            1 => DecorationStyle::Double,
+            2 => DecorationStyle::Dotted,
+            3 => DecorationStyle::Dashed,
+            4 => DecorationStyle::Wavy,
+            tag => return Err(WireError::InvalidTag("decoration style", tag)),
+        };
+        Ok(TextDecoration {
+            lines,
+            style,
+            color: self.string_ref()?,
+            thickness: match self.reader.flag("decoration thickness")? {
+                true => Some(self.reader.f32()?),
+                false => None,
+            },
+        })
+    }
+
+    fn ids(&mut self) -> Result<Vec<NodeId>, WireError> {
+        let count = self.reader.count(8)?;
+        (0..count).map(|_| self.reader.u64().map(NodeId)).collect()
+    }
+
+    fn end(&self) -> Result<(), WireError> {
+        match self.reader.remaining() {
+            0 => Ok(()),
+            extra => Err(WireError::TrailingBytes(extra)),
+        }
+    }
+}
+
+/// A frame's content, fully read and checked before it touches the tree.
+enum Body {
+    Keyframe(Vec<LayoutNode>),
+    Delta {
+        changes: AHashMap<PaintKey, LayoutNode>,
+        order: Option<(Vec<NodeId>, Vec<NodeId>)>,
+    },
+}
+
+fn key_of(node: &LayoutNode, ordinal: u32) -> PaintKey {
+    match node.element_type {
+        ElementType::Text => PaintKey::Text(node.node_id, ordinal),
+        _ => PaintKey::Box(node.node_id, ordinal),
+    }
+}
+
+/// Keys of the boxes with `box_ids` and text nodes with `text_ids`, in
+/// paint order, numbered as [`frame_entries`] numbers them.
+fn keys_of(box_ids: &[NodeId], text_ids: &[NodeId]) -> Vec<PaintKey> {
+    let mut keys = Vec::with_capacity(box_ids.len() + text_ids.len());
+    let mut seen: AHashMap<NodeId, u32> = AHashMap::new();
+    for &id in box_ids {
+        let ordinal = seen.entry(id).or_default();
+        keys.push(PaintKey::Box(id, *ordinal));
+        *ordinal += 1;
+    }
+    seen.clear();
+    for &id in text_ids {
+        let ordinal = seen.entry(id).or_default();
+        keys.push(PaintKey::Text(id, *ordinal));
+        *ordinal += 1;
+    }
+    keys
+}
+
+/// Rebuilds the layout tree from the frames of a [`FrameEncoder`].
+#[derive(Default)]
+pub struct FrameDecoder {
+    tree: LayoutTree,
+    /// Position of each node in the tree's box or text list.
+    index: AHashMap<PaintKey, usize>,
+    seq: Option<u64>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tree(&self) -> &LayoutTree {
+        &self.tree
+    }
+
+    /// Sequence number of the last frame applied.
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    /// Bring the tree up to the frame in `bytes`. A frame that fails to
+    /// decode, or a delta on a frame other than the last one applied,
+    /// leaves the tree as it was; the sender should then be asked for a
+    /// keyframe.
+    pub fn apply(&mut self, bytes: &[u8]) -> Result<&LayoutTree, WireError> {
+        let (header, strings_at) = Reader::new(bytes).header()?;
+        if header.kind == FrameKind::Delta && self.seq != Some(header.base) {
+            return Err(WireError::BaseMismatch {
+                base: header.base,
+                current: self.seq,
+            });
+        }
+        let strings = Reader {
+            bytes,
+            at: strings_at,
+        }
+        .strings()?;
+        let reader = Reader {
+            bytes: &bytes[..strings_at],
+            at: HEADER_LEN,
+        };
+        let mut nodes = NodeReader { reader, strings };
+        let body = match header.kind {
+            FrameKind::Keyframe => {
+                let count = nodes.reader.count(MIN_NODE_LEN)?;
+                let mut list = Vec::with_capacity(count);
+                for _ in 0..count {
+                    list.push(nodes.node()?);
+                }
+                Body::Keyframe(list)
+            }
+            FrameKind::Delta => {
+                let count = nodes.reader.count(4 + MIN_NODE_LEN)?;
+                let mut changes = AHashMap::with_capacity(count);
+                for _ in 0..count {
+                    let ordinal = nodes.reader.u32()?;
+                    let node = nodes.node()?;
+                    changes.insert(key_of(&node, ordinal), node);
+                }
+                let order = match nodes.reader.flag("order")? {
+                    true => Some((nodes.ids()?, nodes.ids()?)),
+                    false => None,
+                };
+                Body::Delta { changes, order }
+            }
+        };
+        nodes.end()?;
+
+        match body {
+            Body::Keyframe(list) => {
+                let mut tree = LayoutTree::new();
+                for node in list {
+                    tree.add_node(node);
+                }
+                self.tree = tree;
+                self.reindex();
+            }
+            Body::Delta {
+                changes,
+                order: None,
+            } => self.patch(changes)?,
+            Body::Delta {
+                changes,
+                order: Some((box_ids, text_ids)),
+            } => self.reorder(changes, &box_ids, &text_ids)?,
+        }
+        self.seq = Some(header.seq);
+        Ok(&self.tree)
+    }
+
+    fn reindex(&mut self) {
+        // Positions follow from paint order: boxes, then text nodes.
+        let boxes = self.tree.nodes.len();
+        self.index.clear();
+        for (position, entry) in frame_entries(&self.tree, &[]).iter().enumerate() {
+            let position = match entry.key {
+                PaintKey::Text(..) => position - boxes,
+                _ => position,
+            };
+            self.index.insert(entry.key, position);
+        }
+    }
+
+    /// Replace nodes in place; every key must already be in the tree.
+    fn patch(&mut self, changes: AHashMap<PaintKey, LayoutNode>) -> Result<(), WireError> {
+        if let Some(key) = changes.keys().find(|key| !self.index.contains_key(key)) {
+            return Err(WireError::UnknownKey(*key));
+        }
+        for (key, node) in changes {
+            let position = self.index[&key];
+            match key {
+                PaintKey::Text(..) => self.tree.text_nodes[position] = node,
+                _ => self.tree.nodes[position] = node,
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the tree in the frame's order from `changes` and the nodes
+    /// of the last frame.
+    fn reorder(
+        &mut self,
+        mut changes: AHashMap<PaintKey, LayoutNode>,
+        box_ids: &[NodeId],
+        text_ids: &[NodeId],
+    ) -> Result<(), WireError> {
+        let keys = keys_of(box_ids, text_ids);
+        if let Some(key) = keys
+            .iter()
+            .find(|key| !changes.contains_key(key) && !self.index.contains_key(key))
+        {
+            return Err(WireError::UnknownKey(*key));
+        }
+        let wanted: AHashSet<&PaintKey> = keys.iter().collect();
+        if let Some(key) = changes.keys().find(|key| !wanted.contains(key)) {
+            return Err(WireError::UnknownKey(*key));
+        }
+
+        let mut boxes: Vec<Option<LayoutNode>> = std::mem::take(&mut self.tree.nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut texts: Vec<Option<LayoutNode>> = std::mem::take(&mut self.tree.text_nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut tree = LayoutTree::new();
+        for key in &keys {
+            let node = match changes.remove(key) {
+                Some(node) => node,
+                None => {
+                    let position = self.index[key];
+                    let old = match key {
+                        PaintKey::Text(..) => &mut texts[position],
+                        _ => &mut boxes[position],
+                    };
+                    old.take().expect("each key of a frame is taken once")
+                }
+            };
+            match key {
+                PaintKey::Text(..) => tree.text_nodes.push(node),
+                _ => tree.nodes.push(node),
+            }
+        }
+        self.tree = tree;
+        self.reindex();
+        Ok(())
+    }
+}
@@ -0,0 +1,639 @@
+//! Recording a browsing session and replaying it exactly, to reproduce bugs
+//! that depend on timing.
+//!
+//! A [`Recorder`] drives an engine the way an embedder would and writes down
+//! everything that could come out differently another time: each call made
+//! on the engine and the time it was made at, every network exchange with
+//! its full body, the viewport, and the seed of `Math.random`. Script runs
+//! on a [`VirtualClock`] that only moves as each step begins, to the time
+//! the step began at, so `Date`, `performance.now()` and the order timers
+//! fire in all follow from the recorded times. The result is a
+//! [`ReplayBundle`].
+//!
+//! A [`Replayer`] builds an engine from a bundle and makes the same calls at
+//! the same virtual times, answering requests from the recording instead of
+//! the network. A request the recording does not have next, a call that
+//! fails one way but not the other, or a script that returns something else
+//! stops the replay with a [`ReplayError`] saying where. Checkpoints keep
+//! the layout tree as recorded, and replaying one compares it to the new
+//! layout.
+//!
+//! Both run in private mode, so no cache or storage carries over from disk
+//! into the session. Replayed responses carry no TLS details.
+
+mod transport;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::core::clock::{VirtualClock, VirtualTime};
+use crate::core::network::{NetworkError, NetworkManager, RawResponse, Transport};
+use crate::{BrowserConfig, BrowserEngine, BrowserError, InputEvent, Result};
+use transport::{Playback, RecordingLog, RecordingTransport, ReplayTransport};
+
+/// The bundle format [`Replayer`] reads.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// How long a replayed step may take before the replay gives up on it.
+const STEP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A recorded session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayBundle {
+    pub version: u32,
+    pub random_seed: u64,
+    /// Wall clock time when the session started, in milliseconds since the
+    /// Unix epoch.
+    pub epoch_ms: f64,
+    pub viewport: (u32, u32),
+    pub device_pixel_ratio: f32,
+    pub steps: Vec<ReplayStep>,
+    /// Every request the engine made, in the order it made them.
+    pub exchanges: Vec<RecordedExchange>,
+}
+
+impl ReplayBundle {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a bundle always serializes")
+    }
+
+    pub fn from_json(json: &str) -> std::result::Result<Self, ReplayError> {
+        let bundle: Self =
+            serde_json::from_str(json).map_err(|e| ReplayError::Bundle(e.to_string()))?;
+        if bundle.version != BUNDLE_VERSION {
+            return Err(ReplayError::UnsupportedVersion(bundle.version));
+        }
+        Ok(bundle)
+    }
+}
+
+/// One call on the engine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayStep {
+    /// Virtual time the step began at, in milliseconds since the session
+    /// started.
+    pub at_ms: f64,
+    pub action: ReplayAction,
+    /// What the call failed with, if it did.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplayAction {
+    Navigate {
+        url: String,
+    },
+    Input {
+        event: InputEvent,
+    },
+    Tick,
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    /// Script the embedder ran, and what it returned.
+    Script {
+        source: String,
+        result: Value,
+    },
+    /// A point to replay to, with the layout tree dump at it.
+    Checkpoint {
+        name: String,
+        layout: Value,
+    },
+}
+
+impl ReplayAction {
+    /// A line naming the action, for errors.
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Navigate { url } => format!("navigate to {}", url),
+            Self::Input { event } => format!("input {:?}", event),
+            Self::Tick => "tick".to_string(),
+            Self::Resize { width, height } => format!("resize to {}x{}", width, height),
+            Self::Script { source, .. } => {
+                let mut line: String = source.chars().take(60).collect();
+                if line.len() < source.len() {
+                    line.push('…');
+                }
+                format!("script `{}`", line)
+            }
+            Self::Checkpoint { name, .. } => format!("checkpoint '{}'", name),
+        }
+    }
+}
+
+/// A request and what came back for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    pub method: String,
+    pub url: String,
+    /// The step the response arrived in, counting from one; a replay holds
+    /// it back until then.
+    pub step: usize,
+    /// Neither is set when the request was given up on first.
+    pub response: Option<RecordedResponse>,
+    pub error: Option<RecordedError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    /// Where the response came from, after redirects.
+    pub url: String,
+    pub headers: std::collections::HashMap<String, String>,
+    /// As much of the body as the engine read.
+    #[serde(with = "base64_bytes")]
+    pub body: Vec<u8>,
+    /// What cut the body short, if anything.
+    pub body_error: Option<RecordedError>,
+}
+
+impl RecordedResponse {
+    fn into_raw(self) -> crate::core::network::Result<RawResponse> {
+        let url = url::Url::parse(&self.url)
+            .map_err(|e| NetworkError::RequestFailed(format!("Recorded URL: {}", e)))?;
+        let mut chunks = Vec::new();
+        if !self.body.is_empty() {
+            chunks.push(Ok(self.body));
+        }
+        if let Some(error) = self.body_error {
+            chunks.push(Err(error.to_error()));
+        }
+        Ok(RawResponse {
+            status: self.status,
+            url,
+            headers: self.headers,
+            tls: None,
+            body: Box::pin(futures::stream::iter(chunks)),
+        })
+    }
+}
+
+/// A network error, kept as the kind the engine tells apart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedError {
+    pub kind: String,
+    pub message: String,
+}
+
+impl RecordedError {
+    fn from_error(error: &NetworkError) -> Self {
+        let (kind, message) = match error {
+            NetworkError::Timeout(message) => ("timeout", message.clone()),
+            NetworkError::DnsResolution(message) => ("dns", message.clone()),
+            NetworkError::SslError(message) => ("tls", message.clone()),
+            NetworkError::Connection(message) => ("connection", message.clone()),
+            NetworkError::Protocol(message) => ("protocol", message.clone()),
+            other => ("other", other.to_string()),
+        };
+        Self {
+            kind: kind.to_string(),
+            message,
+        }
+    }
+
+    fn to_error(&self) -> NetworkError {
+        let message = self.message.clone();
+        match self.kind.as_str() {
+            "timeout" => NetworkError::Timeout(message),
+            "dns" => NetworkError::DnsResolution(message),
+            "tls" => NetworkError::SslError(message),
+            "connection" => NetworkError::Connection(message),
+            "protocol" => NetworkError::Protocol(message),
+            _ => NetworkError::RequestFailed(message),
+        }
+    }
+}
+
+mod base64_bytes {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error(transparent)]
+    Browser(#[from] BrowserError),
+    #[error("Replay diverged at step {step} ({action}): {detail}")]
+    Diverged {
+        step: usize,
+        action: String,
+        detail: String,
+    },
+    #[error("Layout at checkpoint '{checkpoint}' differs from the recording at {path}")]
+    LayoutDiverged { checkpoint: String, path: String },
+    #[error("Step {step} ({action}) did not finish")]
+    Stalled { step: usize, action: String },
+    #[error("No checkpoint '{0}' ahead in the recording")]
+    NoSuchCheckpoint(String),
+    #[error("Replay bundle version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("Invalid replay bundle: {0}")]
+    Bundle(String),
+}
+
+/// The config both a recording and its replay run on: private, on the
+/// bundle's viewport and virtual time.
+fn session_config(
+    mut config: BrowserConfig,
+    viewport: (u32, u32),
+    device_pixel_ratio: f32,
+    time: VirtualTime,
+) -> BrowserConfig {
+    config.private_mode = true;
+    config.viewport_width = viewport.0;
+    config.viewport_height = viewport.1;
+    config.device_pixel_ratio = device_pixel_ratio;
+    config.virtual_time = Some(time);
+    config
+}
+
+/// An engine whose session is recorded. Make every call that should be in
+/// the recording through it; reading state through [`Self::engine`] is
+/// fine, changing it there is not.
+pub struct Recorder {
+    engine: BrowserEngine,
+    log: Arc<RecordingLog>,
+    time: VirtualTime,
+    started: Instant,
+    viewport: (u32, u32),
+    device_pixel_ratio: f32,
+    steps: parking_lot::Mutex<Vec<ReplayStep>>,
+}
+
+impl Recorder {
+    /// Start recording an engine on `config`. Requests go through
+    /// `transport`, or the network when `None`. The config's virtual time,
+    /// if any, is kept; otherwise the clock starts now with a seed drawn
+    /// from the system clock.
+    pub async fn start(
+        config: BrowserConfig,
+        transport: Option<Arc<dyn Transport>>,
+    ) -> Result<Self> {
+        let time = config.virtual_time.clone().unwrap_or_else(|| {
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos() as u64);
+            VirtualTime::new(Arc::new(VirtualClock::starting_now()), seed)
+        });
+        let viewport = (config.viewport_width, config.viewport_height);
+        let device_pixel_ratio = config.device_pixel_ratio;
+        let config = session_config(config, viewport, device_pixel_ratio, time.clone());
+
+        let log = Arc::new(RecordingLog::default());
+        let record = |inner: Arc<dyn Transport>| -> Arc<dyn Transport> {
+            Arc::new(RecordingTransport::new(inner, log.clone()))
+        };
+        let network = match transport {
+            Some(transport) => NetworkManager::with_transport(&config, record(transport)).await?,
+            None => NetworkManager::wrapping_transport(&config, record).await?,
+        };
+        let engine = BrowserEngine::with_network(config, network).await?;
+        Ok(Self {
+            engine,
+            log,
+            time,
+            started: Instant::now(),
+            viewport,
+            device_pixel_ratio,
+            steps: parking_lot::Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn engine(&self) -> &BrowserEngine {
+        &self.engine
+    }
+
+    pub async fn load_url(&self, url: &str) -> Result<()> {
+        let action = ReplayAction::Navigate {
+            url: url.to_string(),
+        };
+        self.record(action, self.engine.load_url(url)).await
+    }
+
+    pub async fn handle_input_event(&self, event: InputEvent) -> Result<()> {
+        let action = ReplayAction::Input {
+            event: event.clone(),
+        };
+        self.record(action, self.engine.handle_input_event(event))
+            .await
+    }
+
+    pub async fn tick(&self) -> Result<()> {
+        self.record(ReplayAction::Tick, self.engine.tick()).await
+    }
+
+    pub async fn resize_viewport(&self, width: u32, height: u32) -> Result<()> {
+        let action = ReplayAction::Resize { width, height };
+        self.record(action, self.engine.resize_viewport(width, height))
+            .await
+    }
+
+    pub async fn execute_javascript(&self, source: &str) -> Result<Value> {
+        let index = self.begin_step();
+        let result = self.engine.execute_javascript(source).await;
+        let action = ReplayAction::Script {
+            source: source.to_string(),
+            result: result.as_ref().cloned().unwrap_or(Value::Null),
+        };
+        self.end_step(index, action, result.as_ref().err());
+        result
+    }
+
+    /// Mark a point to replay to, keeping the layout tree as it is now;
+    /// returns its dump.
+    pub async fn checkpoint(&self, name: &str) -> Value {
+        let index = self.begin_step();
+        let layout = self.engine.dump_layout_tree().await;
+        let action = ReplayAction::Checkpoint {
+            name: name.to_string(),
+            layout: layout.clone(),
+        };
+        self.end_step(index, action, None);
+        layout
+    }
+
+    /// Stop recording and hand over the session. The engine stays usable
+    /// through the returned one, unrecorded.
+    pub fn finish(self) -> (ReplayBundle, BrowserEngine) {
+        let bundle = ReplayBundle {
+            version: BUNDLE_VERSION,
+            random_seed: self.time.random_seed,
+            epoch_ms: self.time.clock.epoch_ms(),
+            viewport: self.viewport,
+            device_pixel_ratio: self.device_pixel_ratio,
+            steps: self.steps.into_inner(),
+            exchanges: self.log.take_exchanges(),
+        };
+        (bundle, self.engine)
+    }
+
+    async fn record<T>(
+        &self,
+        action: ReplayAction,
+        call: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let index = self.begin_step();
+        let result = call.await;
+        self.end_step(index, action, result.as_ref().err());
+        result
+    }
+
+    /// Move the clock to now and reserve the step's place, so steps are
+    /// kept in the order they began.
+    fn begin_step(&self) -> usize {
+        let mut steps = self.steps.lock();
+        let at_ms = self.started.elapsed().as_secs_f64() * 1000.0;
+        self.time.clock.set(at_ms);
+        self.log.begin_step();
+        steps.push(ReplayStep {
+            at_ms: self.time.clock.now_ms(),
+            action: ReplayAction::Tick,
+            error: None,
+        });
+        steps.len() - 1
+    }
+
+    fn end_step(&self, index: usize, action: ReplayAction, error: Option<&BrowserError>) {
+        self.log.end_step();
+        let mut steps = self.steps.lock();
+        steps[index].action = action;
+        steps[index].error = error.map(ToString::to_string);
+    }
+}
+
+/// An engine that plays a recorded session back.
+pub struct Replayer {
+    engine: BrowserEngine,
+    clock: Arc<VirtualClock>,
+    playback: Arc<Playback>,
+    steps: Vec<ReplayStep>,
+    next_step: usize,
+}
+
+impl Replayer {
+    /// An engine on `config`, with the viewport and virtual time of
+    /// `bundle`, at the start of its session.
+    pub async fn start(
+        config: BrowserConfig,
+        bundle: ReplayBundle,
+    ) -> std::result::Result<Self, ReplayError> {
+        if bundle.version != BUNDLE_VERSION {
+            return Err(ReplayError::UnsupportedVersion(bundle.version));
+        }
+        let clock = Arc::new(VirtualClock::new(bundle.epoch_ms));
+        let time = VirtualTime::new(clock.clone(), bundle.random_seed);
+        let config = session_config(config, bundle.viewport, bundle.device_pixel_ratio, time);
+        let playback = Arc::new(Playback::new(bundle.exchanges));
+        let network = NetworkManager::with_transport(
+            &config,
+            Arc::new(ReplayTransport::new(playback.clone())),
+        )
+        .await
+        .map_err(BrowserError::from)?;
+        let engine = BrowserEngine::with_network(config, network).await?;
+        Ok(Self {
+            engine,
+            clock,
+            playback,
+            steps: bundle.steps,
+            next_step: 0,
+        })
+    }
+
+    pub fn engine(&self) -> &BrowserEngine {
+        &self.engine
+    }
+
+    /// Steps replayed so far.
+    pub fn position(&self) -> usize {
+        self.next_step
+    }
+
+    /// Replay up to and including the checkpoint `name`, and return the
+    /// layout tree dump there, which matched the recording's.
+    pub async fn run_to_checkpoint(
+        &mut self,
+        name: &str,
+    ) -> std::result::Result<Value, ReplayError> {
+        let found = self.steps[self.next_step..].iter().any(|step| {
+            matches!(&step.action, ReplayAction::Checkpoint { name: candidate, .. } if candidate == name)
+        });
+        if !found {
+            return Err(ReplayError::NoSuchCheckpoint(name.to_string()));
+        }
+        loop {
+            if let Some(layout) = self.run_step().await? {
+                if matches!(&self.steps[self.next_step - 1].action, ReplayAction::Checkpoint { name: candidate, .. } if candidate == name)
+                {
+                    return Ok(layout);
+                }
+            }
+        }
+    }
+
+    /// Replay the rest of the session. Recorded requests the replay never
+    /// made count as a divergence too.
+    pub async fn run_to_end(&mut self) -> std::result::Result<(), ReplayError> {
+        while self.next_step < self.steps.len() {
+            self.run_step().await?;
+        }
+        let unclaimed = self.playback.unclaimed();
+        if let Some(first) = unclaimed.first() {
+            return Err(ReplayError::Diverged {
+                step: self.steps.len(),
+                action: "end of session".to_string(),
+                detail: format!(
+                    "{} recorded request(s) never made, from {} {}",
+                    unclaimed.len(),
+                    first.method,
+                    first.url
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replay the next step; a checkpoint's layout dump when it was one.
+    async fn run_step(&mut self) -> std::result::Result<Option<Value>, ReplayError> {
+        let step = self.steps[self.next_step].clone();
+        self.next_step += 1;
+        let number = self.next_step;
+        self.clock.set(step.at_ms);
+        self.playback.begin_step();
+
+        let outcome = tokio::time::timeout(STEP_TIMEOUT, self.perform(&step.action)).await;
+        let diverged = |detail: String| ReplayError::Diverged {
+            step: number,
+            action: step.action.describe(),
+            detail,
+        };
+        let (result, layout) = match outcome {
+            Ok(outcome) => outcome,
+            Err(_) => {
+                return Err(match self.playback.take_divergence() {
+                    Some(detail) => diverged(detail),
+                    None => ReplayError::Stalled {
+                        step: number,
+                        action: step.action.describe(),
+                    },
+                })
+            }
+        };
+        if let Some(detail) = self.playback.take_divergence() {
+            return Err(diverged(detail));
+        }
+        match (&result, &step.error) {
+            (Ok(_), Some(recorded)) => {
+                return Err(diverged(format!(
+                    "succeeded, where the recording failed with: {}",
+                    recorded
+                )))
+            }
+            (Err(e), None) => return Err(diverged(format!("failed with: {}", e))),
+            _ => {}
+        }
+        match (&step.action, result) {
+            (
+                ReplayAction::Script {
+                    result: recorded, ..
+                },
+                Ok(Some(value)),
+            ) if value != *recorded => Err(diverged(format!(
+                "returned {}, where the recording returned {}",
+                value, recorded
+            ))),
+            (
+                ReplayAction::Checkpoint {
+                    name,
+                    layout: recorded,
+                },
+                _,
+            ) => {
+                let layout = layout.unwrap_or(Value::Null);
+                match first_difference(recorded, &layout, "$") {
+                    Some(path) => Err(ReplayError::LayoutDiverged {
+                        checkpoint: name.clone(),
+                        path,
+                    }),
+                    None => Ok(Some(layout)),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Make the call `action` stands for: its result, with what a script
+    /// returned, and the layout tree dump at a checkpoint.
+    async fn perform(&self, action: &ReplayAction) -> (Result<Option<Value>>, Option<Value>) {
+        let engine = &self.engine;
+        match action {
+            ReplayAction::Navigate { url } => (engine.load_url(url).await.map(|_| None), None),
+            ReplayAction::Input { event } => (
+                engine.handle_input_event(event.clone()).await.map(|_| None),
+                None,
+            ),
+            ReplayAction::Tick => (engine.tick().await.map(|_| None), None),
+            ReplayAction::Resize { width, height } => (
+                engine.resize_viewport(*width, *height).await.map(|_| None),
+                None,
+            ),
+            ReplayAction::Script { source, .. } => {
+                (engine.execute_javascript(source).await.map(Some), None)
+            }
+            ReplayAction::Checkpoint { .. } => (Ok(None), Some(engine.dump_layout_tree().await)),
+        }
+    }
+}
+
+/// Where `actual` first differs from `expected`, as a JSON path from `path`.
+fn first_difference(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                let path = format!("{}.{}", path, key);
+                match actual.get(key) {
+                    Some(other) => {
+                        if let Some(found) = first_difference(value, other, &path) {
+                            return Some(found);
+                        }
+                    }
+                    None => return Some(path),
+                }
+            }
+            actual
+                .keys()
+                .find(|key| !expected.contains_key(*key))
+                .map(|key| format!("{}.{}", path, key))
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (index, (value, other)) in expected.iter().zip(actual).enumerate() {
+                if let Some(found) = first_difference(value, other, &format!("{}[{}]", path, index))
+                {
+                    return Some(found);
+                }
+            }
+            (expected.len() != actual.len())
+                .then(|| format!("{}[{}]", path, expected.len().min(actual.len())))
+        }
+        _ => (expected != actual).then(|| path.to_string()),
+    }
+}
@@ -0,0 +1,236 @@
+//! The transports a session is recorded through and replayed from.
+//!
+//! A recording keeps every exchange in the order its request was made, with
+//! all of the body the engine read. A replay answers the n-th request with
+//! the n-th exchange, holding the response back until the replay reaches
+//! the step it first arrived in, and reports a request the recording does
+//! not have next as a divergence.
+
+use super::{RecordedError, RecordedExchange, RecordedResponse};
+use crate::core::network::{NetworkError, PreparedRequest, RawResponse, Result, Transport};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The steps and exchanges of a session being recorded.
+#[derive(Default)]
+pub(super) struct RecordingLog {
+    state: Mutex<RecordingState>,
+}
+
+#[derive(Default)]
+struct RecordingState {
+    steps_begun: usize,
+    in_step: bool,
+    exchanges: Vec<RecordedExchange>,
+}
+
+impl RecordingState {
+    /// The step a response arriving now comes in at when replayed: the one
+    /// running, or the next when none is.
+    fn arrival_step(&self) -> usize {
+        if self.in_step {
+            self.steps_begun
+        } else {
+            self.steps_begun + 1
+        }
+    }
+}
+
+impl RecordingLog {
+    pub(super) fn begin_step(&self) {
+        let mut state = self.state.lock();
+        state.steps_begun += 1;
+        state.in_step = true;
+    }
+
+    pub(super) fn end_step(&self) {
+        self.state.lock().in_step = false;
+    }
+
+    pub(super) fn take_exchanges(&self) -> Vec<RecordedExchange> {
+        std::mem::take(&mut self.state.lock().exchanges)
+    }
+
+    fn open(&self, request: &PreparedRequest) -> usize {
+        let mut state = self.state.lock();
+        state.exchanges.push(RecordedExchange {
+            method: request.method.clone(),
+            url: request.url.to_string(),
+            step: 0,
+            response: None,
+            error: None,
+        });
+        state.exchanges.len() - 1
+    }
+
+    fn answered(&self, index: usize, response: &RawResponse) {
+        let mut state = self.state.lock();
+        let step = state.arrival_step();
+        let exchange = &mut state.exchanges[index];
+        exchange.step = step;
+        exchange.response = Some(RecordedResponse {
+            status: response.status,
+            url: response.url.to_string(),
+            headers: response.headers.clone(),
+            body: Vec::new(),
+            body_error: None,
+        });
+    }
+
+    fn failed(&self, index: usize, error: &NetworkError) {
+        let mut state = self.state.lock();
+        let step = state.arrival_step();
+        let exchange = &mut state.exchanges[index];
+        exchange.step = step;
+        exchange.error = Some(RecordedError::from_error(error));
+    }
+
+    fn body_read(&self, index: usize, chunk: &Result<Vec<u8>>) {
+        let mut state = self.state.lock();
+        if let Some(response) = state.exchanges[index].response.as_mut() {
+            match chunk {
+                Ok(bytes) => response.body.extend_from_slice(bytes),
+                Err(e) => response.body_error = Some(RecordedError::from_error(e)),
+            }
+        }
+    }
+}
+
+/// Passes requests on to another transport and logs what comes back.
+pub(super) struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    log: Arc<RecordingLog>,
+}
+
+impl RecordingTransport {
+    pub(super) fn new(inner: Arc<dyn Transport>, log: Arc<RecordingLog>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn execute(&self, request: PreparedRequest) -> BoxFuture<'static, Result<RawResponse>> {
+        let index = self.log.open(&request);
+        let send = self.inner.execute(request);
+        let log = self.log.clone();
+        async move {
+            let response = match send.await {
+                Ok(response) => response,
+                Err(e) => {
+                    log.failed(index, &e);
+                    return Err(e);
+                }
+            };
+            log.answered(index, &response);
+            let body = response
+                .body
+                .map(move |chunk| {
+                    log.body_read(index, &chunk);
+                    chunk
+                })
+                .boxed();
+            Ok(RawResponse { body, ..response })
+        }
+        .boxed()
+    }
+}
+
+/// The exchanges of a recording, handed out in turn as a replay makes its
+/// requests.
+pub(super) struct Playback {
+    exchanges: Vec<RecordedExchange>,
+    next: Mutex<usize>,
+    steps_begun: watch::Sender<usize>,
+    divergence: Mutex<Option<String>>,
+}
+
+impl Playback {
+    pub(super) fn new(exchanges: Vec<RecordedExchange>) -> Self {
+        Self {
+            exchanges,
+            next: Mutex::new(0),
+            steps_begun: watch::channel(0).0,
+            divergence: Mutex::new(None),
+        }
+    }
+
+    /// Responses held back for the step about to run may now arrive.
+    pub(super) fn begin_step(&self) {
+        self.steps_begun.send_modify(|begun| *begun += 1);
+    }
+
+    /// What first went differently from the recording since the last call.
+    pub(super) fn take_divergence(&self) -> Option<String> {
+        self.divergence.lock().take()
+    }
+
+    /// The recorded exchanges no request has asked for yet.
+    pub(super) fn unclaimed(&self) -> &[RecordedExchange] {
+        let next = *self.next.lock();
+        &self.exchanges[next.min(self.exchanges.len())..]
+    }
+
+    fn claim(&self, request: &PreparedRequest) -> std::result::Result<RecordedExchange, String> {
+        let mut next = self.next.lock();
+        let index = *next;
+        let detail = match self.exchanges.get(index) {
+            Some(exchange)
+                if exchange.method == request.method && exchange.url == request.url.as_str() =>
+            {
+                *next += 1;
+                return Ok(exchange.clone());
+            }
+            Some(exchange) => format!(
+                "request {} was {} {}, where the recording has {} {}",
+                index + 1,
+                request.method,
+                request.url,
+                exchange.method,
+                exchange.url
+            ),
+            None => format!(
+                "request {} was {} {}, past the {} the recording has",
+                index + 1,
+                request.method,
+                request.url,
+                self.exchanges.len()
+            ),
+        };
+        self.divergence.lock().get_or_insert_with(|| detail.clone());
+        Err(detail)
+    }
+}
+
+/// Answers requests from a [`Playback`]; nothing goes on the wire.
+pub(super) struct ReplayTransport {
+    playback: Arc<Playback>,
+}
+
+impl ReplayTransport {
+    pub(super) fn new(playback: Arc<Playback>) -> Self {
+        Self { playback }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn execute(&self, request: PreparedRequest) -> BoxFuture<'static, Result<RawResponse>> {
+        let claimed = self.playback.claim(&request);
+        let mut steps_begun = self.playback.steps_begun.subscribe();
+        async move {
+            let exchange = claimed.map_err(|detail| {
+                NetworkError::RequestFailed(format!("Replay diverged: {}", detail))
+            })?;
+            let _ = steps_begun.wait_for(|begun| *begun >= exchange.step).await;
+            match (exchange.response, exchange.error) {
+                (Some(response), _) => response.into_raw(),
+                (None, Some(error)) => Err(error.to_error()),
+                // The request was given up on before anything came back.
+                (None, None) => futures::future::pending().await,
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,154 @@
+//! Files the user handed to a page, which are the only ones it may read.
+//!
+//! Dropping a file on a page grants its document read access to exactly
+//! that file. Each read resolves the path again and refuses it unless it
+//! still names the same regular file, unmodified since the drop and no
+//! larger than [`MAX_READ_BYTES`]; a symlink pointed elsewhere or a file
+//! replaced in the meantime cannot be read through an old grant.
+
+use super::SandboxError;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The largest file a page may read in one go.
+pub const MAX_READ_BYTES: u64 = 256 * 1024 * 1024;
+
+/// What a page learns about a granted file without reading it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileGrant {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+    /// Milliseconds since the Unix epoch.
+    pub last_modified: u64,
+}
+
+#[derive(Debug)]
+struct Granted {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Default)]
+pub struct FileGrants {
+    next_id: AtomicU64,
+    granted: Mutex<HashMap<u64, Granted>>,
+}
+
+impl FileGrants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grant read access to the regular file at `path`.
+    pub fn grant(&self, path: &Path) -> Result<FileGrant, SandboxError> {
+        let path = fs::canonicalize(path).map_err(|e| io_error(path, e))?;
+        let metadata = fs::metadata(&path).map_err(|e| io_error(&path, e))?;
+        if !metadata.is_file() {
+            return Err(SandboxError::PermissionDenied(format!(
+                "{} is not a regular file",
+                path.display()
+            )));
+        }
+
+        let modified = metadata.modified().ok();
+        let grant = FileGrant {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            mime_type: mime_type_for(&path).to_string(),
+            last_modified: modified
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_millis() as u64),
+        };
+        self.granted
+            .lock()
+            .insert(grant.id, Granted { path, modified });
+        Ok(grant)
+    }
+
+    /// The contents of the file behind grant `id`.
+    pub fn read(&self, id: u64) -> Result<Vec<u8>, SandboxError> {
+        let (path, modified) = match self.granted.lock().get(&id) {
+            Some(granted) => (granted.path.clone(), granted.modified),
+            None => {
+                return Err(SandboxError::PermissionDenied(format!(
+                    "no file is granted as {}",
+                    id
+                )))
+            }
+        };
+        let changed = || {
+            SandboxError::PermissionDenied(format!(
+                "{} changed since it was dropped",
+                path.display()
+            ))
+        };
+
+        if fs::canonicalize(&path).ok().as_deref() != Some(path.as_path()) {
+            return Err(changed());
+        }
+        let metadata = fs::metadata(&path).map_err(|e| io_error(&path, e))?;
+        if !metadata.is_file() || metadata.modified().ok() != modified {
+            return Err(changed());
+        }
+        if metadata.len() > MAX_READ_BYTES {
+            return Err(SandboxError::ResourceExhausted(format!(
+                "{} is larger than {} bytes",
+                path.display(),
+                MAX_READ_BYTES
+            )));
+        }
+        fs::read(&path).map_err(|e| io_error(&path, e))
+    }
+
+    pub fn revoke(&self, id: u64) {
+        self.granted.lock().remove(&id);
+    }
+
+    /// Forget every grant; the document they were made to is gone.
+    pub fn revoke_all(&self) {
+        self.granted.lock().clear();
+    }
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> SandboxError {
+    SandboxError::SystemError(format!("{}: {}", path.display(), error))
+}
+
+/// `File.type` for common extensions; empty when unknown, as browsers do.
+fn mime_type_for(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" => "text/plain",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "",
+    }
+}
@@ -2,6 +2,8 @@ pub mod channel;
 
 pub use channel::*;
 
+use crate::renderer::command_stream::MAX_COMMAND_FRAME_BYTES;
+use crate::renderer::wire::MAX_LAYOUT_FRAME_BYTES;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -38,9 +40,59 @@ pub enum MessageType {
     DomUpdate,
     JavaScriptExecution,
     ResourceRequest,
+    /// A `renderer::wire` layout frame from a content process, at most
+    /// [`MAX_LAYOUT_FRAME_BYTES`].
+    LayoutFrame,
+    /// A `renderer::command_stream` frame of paint commands from a content
+    /// process, at most [`MAX_COMMAND_FRAME_BYTES`].
+    PaintCommands,
     Custom(String),
 }
 
+impl IpcMessage {
+    /// A layout frame bound for the renderer; sender, recipient and
+    /// timestamp are filled in by [`IpcManager::send_message`].
+    pub fn layout_frame(frame: Vec<u8>) -> Result<Self, IpcError> {
+        if frame.len() > MAX_LAYOUT_FRAME_BYTES {
+            return Err(IpcError::SecurityViolation(format!(
+                "Layout frame size {} exceeds limit {}",
+                frame.len(),
+                MAX_LAYOUT_FRAME_BYTES
+            )));
+        }
+        Ok(Self {
+            priority: MessagePriority::High,
+            timestamp: 0,
+            id: Uuid::new_v4(),
+            sender: 0,
+            recipient: 0,
+            message_type: MessageType::LayoutFrame,
+            payload: frame,
+        })
+    }
+
+    /// A frame of paint commands bound for the GPU process, filled in like
+    /// [`IpcMessage::layout_frame`].
+    pub fn paint_commands(frame: Vec<u8>) -> Result<Self, IpcError> {
+        if frame.len() > MAX_COMMAND_FRAME_BYTES {
+            return Err(IpcError::SecurityViolation(format!(
+                "Paint command frame size {} exceeds limit {}",
+                frame.len(),
+                MAX_COMMAND_FRAME_BYTES
+            )));
+        }
+        Ok(Self {
+            priority: MessagePriority::High,
+            timestamp: 0,
+            id: Uuid::new_v4(),
+            sender: 0,
+            recipient: 0,
+            message_type: MessageType::PaintCommands,
+            payload: frame,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MessagePriority {
     Critical = 0,
@@ -348,6 +400,21 @@ impl SecurityFilter {
                 max_per_second: 500,
             },
         );
+        // One frame per layout, with headroom for high refresh rates.
+        message_limits.insert(
+            MessageType::LayoutFrame,
+            MessageLimits {
+                max_size: MAX_LAYOUT_FRAME_BYTES,
+                max_per_second: 240,
+            },
+        );
+        message_limits.insert(
+            MessageType::PaintCommands,
+            MessageLimits {
+                max_size: MAX_COMMAND_FRAME_BYTES,
+                max_per_second: 240,
+            },
+        );
 
         Self {
             message_limits,
@@ -1,3 +1,4 @@
+pub mod files;
 pub mod ipc;
 pub mod permissions;
 pub mod process;
@@ -6,7 +7,7 @@ pub mod security;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, error, info, warn};
 
 use crate::sandbox::security::policy::{
@@ -41,6 +42,9 @@ pub struct SecurityAuditReport {
     pub compliance_status: ComplianceStatus,
     pub security_status: SecurityStatus,
     pub policy_engine_report: PolicyEngineAuditReport,
+    /// How the audited processes' resource limits are held to: the weakest
+    /// of them, or what a new process would get when there are none.
+    pub resource_enforcement: process::ResourceEnforcement,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +98,8 @@ pub struct SandboxManager {
     security_framework: Arc<SecurityFramework>,
     policy_engine: Arc<RwLock<SecurityPolicyEngine>>,
     max_processes: u32,
+    cgroup_parent: Option<String>,
+    process_events: mpsc::UnboundedSender<process::ProcessEvent>,
 }
 
 impl SandboxManager {
@@ -106,6 +112,8 @@ impl SandboxManager {
         let ipc_manager = Arc::new(ipc::IpcManager::new());
         let security_framework = Arc::new(SecurityFramework::new());
         let policy_engine = Arc::new(RwLock::new(SecurityPolicyEngine::new()));
+        let (process_events, receiver) = mpsc::unbounded_channel();
+        Self::supervise(receiver, security_framework.clone());
 
         Ok(Self {
             processes: Arc::new(RwLock::new(HashMap::with_capacity(config.initial_capacity))),
@@ -115,9 +123,39 @@ impl SandboxManager {
             security_framework,
             policy_engine,
             max_processes: config.max_processes,
+            cgroup_parent: config.cgroup_parent,
+            process_events,
         })
     }
 
+    /// Turn what sandboxed processes report about themselves into security
+    /// events.
+    fn supervise(
+        mut receiver: mpsc::UnboundedReceiver<process::ProcessEvent>,
+        security_framework: Arc<SecurityFramework>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                match event {
+                    process::ProcessEvent::OutOfMemoryKilled(process_id, kills) => {
+                        warn!(
+                            target: "sandbox::security",
+                            "Process {} OOM-killed by its cgroup ({} kills)",
+                            process_id,
+                            kills
+                        );
+                        let event = Self::build_oom_event(process_id, kills);
+                        security_framework.analyze_security_event(event).await;
+                    }
+                    process::ProcessEvent::ProcessFailed(process_id, reason) => {
+                        error!("Sandboxed process {} failed: {}", process_id, reason);
+                    }
+                    other => debug!("Sandboxed process event: {:?}", other),
+                }
+            }
+        });
+    }
+
     pub async fn create_sandboxed_process(
         &self,
         config: process::ProcessConfig,
@@ -166,7 +204,12 @@ impl SandboxManager {
             );
         }
 
-        let sandboxed_process = process::SandboxedProcess::new(process_id, config).await?;
+        let mut config = config;
+        if config.cgroup_parent.is_none() {
+            config.cgroup_parent = self.cgroup_parent.clone();
+        }
+        let mut sandboxed_process = process::SandboxedProcess::new(process_id, config).await?;
+        sandboxed_process.report_events_to(self.process_events.clone());
 
         {
             let mut processes = self.processes.write().await;
@@ -182,6 +225,16 @@ impl SandboxManager {
         Ok(process_id)
     }
 
+    pub async fn start_process(&self, process_id: ProcessId) -> Result<(), SandboxError> {
+        let mut processes = self.processes.write().await;
+        let process = processes
+            .get_mut(&process_id)
+            .ok_or(SandboxError::ProcessNotFound(process_id))?;
+        process.start().await?;
+        info!("Started sandboxed process: {}", process_id);
+        Ok(())
+    }
+
     pub async fn terminate_process(&self, process_id: ProcessId) -> Result<(), SandboxError> {
         let mut process = {
             let mut processes = self.processes.write().await;
@@ -266,6 +319,7 @@ impl SandboxManager {
         let mut total_threads = 0u32;
         let mut total_file_handles = 0u32;
         let total_processes;
+        let mut resource_enforcement = None;
 
         {
             let processes = self.processes.read().await;
@@ -273,6 +327,11 @@ impl SandboxManager {
 
             for (process_id, process) in processes.iter() {
                 let stats = process.get_stats().await;
+                if stats.resource_enforcement == process::ResourceEnforcement::Sampled
+                    || resource_enforcement.is_none()
+                {
+                    resource_enforcement = Some(stats.resource_enforcement);
+                }
                 total_memory += stats.memory_usage_bytes;
                 total_cpu_usage += stats.cpu_usage_percent;
                 total_threads += stats.thread_count;
@@ -321,9 +380,22 @@ impl SandboxManager {
             compliance_status,
             security_status,
             policy_engine_report,
+            resource_enforcement: resource_enforcement.unwrap_or_else(|| {
+                let parent = self.cgroup_parent.as_deref().map(std::path::Path::new);
+                if process::cgroup::Cgroup::usable_parent(parent).is_some() {
+                    process::ResourceEnforcement::Kernel
+                } else {
+                    process::ResourceEnforcement::Sampled
+                }
+            }),
         })
     }
 
+    /// Security events recorded so far, oldest first.
+    pub async fn get_security_events(&self) -> Vec<SecurityEvent> {
+        self.security_framework.get_security_events().await
+    }
+
     pub async fn get_process_stats(&self) -> Vec<process::ProcessStats> {
         let processes = self.processes.read().await;
         let mut stats = Vec::with_capacity(processes.len());
@@ -651,6 +723,22 @@ impl SandboxManager {
         }
     }
 
+    fn build_oom_event(process_id: ProcessId, kills: u64) -> SecurityEvent {
+        let mut details = HashMap::new();
+        details.insert("resource_type".to_string(), "memory".to_string());
+        details.insert("oom_kills".to_string(), kills.to_string());
+
+        SecurityEvent {
+            timestamp: Self::current_timestamp(),
+            event_type: SecurityEventType::ResourceAbuse,
+            severity: SecuritySeverity::High,
+            source_process: process_id,
+            target_resource: format!("process://{process_id}"),
+            details,
+            threat_score: 0.7,
+        }
+    }
+
     fn build_process_creation_event(
         &self,
         process_id: ProcessId,
@@ -870,6 +958,8 @@ pub struct SandboxConfig {
     pub max_processes: u32,
     pub initial_capacity: usize,
     pub security_policy: SecurityPolicy,
+    /// Cgroup v2 directory for processes whose `ProcessConfig` names none.
+    pub cgroup_parent: Option<String>,
 }
 
 impl Default for SandboxConfig {
@@ -878,6 +968,7 @@ impl Default for SandboxConfig {
             max_processes: 1000,
             initial_capacity: 64,
             security_policy: SecurityPolicy::default(),
+            cgroup_parent: None,
         }
     }
 }
@@ -148,7 +148,7 @@ impl<T> CircularBuffer<T> {
         }
     }
 
-    fn iter(&self) -> CircularBufferIterator<T> {
+    fn iter(&self) -> CircularBufferIterator<'_, T> {
         let start = if self.size == self.capacity {
             self.head
         } else {
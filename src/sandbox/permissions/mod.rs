@@ -9,7 +9,7 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::sync::{Notify, RwLock};
 use tokio::time::interval;
-use tracing::{error, info};
+use tracing::info;
 
 type PolicyStore = HashMap<u32, Arc<ProcessPermissions>>;
 type CapabilityKey = (u32, Capability);
@@ -0,0 +1,225 @@
+//! Kernel-enforced resource limits through cgroup v2.
+//!
+//! Each sandboxed process gets a cgroup of its own under a parent the
+//! engine is allowed to manage: `ProcessConfig::cgroup_parent` when set,
+//! otherwise the cgroup the engine itself runs in. `memory.max`,
+//! `memory.high` and `cpu.max` are written from the process's
+//! `ResourceLimits`, and the child joins the cgroup between fork and exec,
+//! so nothing it runs is ever outside them. The cgroup's own accounting then
+//! stands in for sampling `/proc`.
+//!
+//! Where the unified hierarchy isn't mounted, the parent can't hand the
+//! memory and cpu controllers down, or the engine may not write to it,
+//! processes run without kernel limits and their usage is only sampled;
+//! `ResourceEnforcement` records which of the two a process got.
+
+use super::ResourceLimits;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// `cpu.max` period, in microseconds.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// How a process's resource limits are held to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResourceEnforcement {
+    /// The kernel enforces them through the process's cgroup.
+    Kernel,
+    /// Usage is sampled and reported; nothing stops a process going over
+    /// between samples.
+    #[default]
+    Sampled,
+}
+
+/// What a cgroup has accounted so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CgroupUsage {
+    pub memory_bytes: u64,
+    /// CPU time of every task that ran in the cgroup, in microseconds.
+    pub cpu_usage_us: u64,
+    /// Tasks the OOM killer has killed for going over `memory.max`.
+    pub oom_kills: u64,
+    /// Whether any task is still in the cgroup.
+    pub populated: bool,
+}
+
+/// A process's cgroup. Dropping it kills whatever is left inside and
+/// removes it.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// The directory to create cgroups in, with the memory and cpu
+    /// controllers enabled for its children, or `None` when cgroups can't
+    /// be used here.
+    pub fn usable_parent(configured: Option<&Path>) -> Option<PathBuf> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        let parent = match configured {
+            Some(parent) => parent.to_path_buf(),
+            None => own_cgroup()?,
+        };
+        let available = fs::read_to_string(parent.join("cgroup.controllers")).ok()?;
+        let enabled = fs::read_to_string(parent.join("cgroup.subtree_control")).ok()?;
+        for controller in ["memory", "cpu"] {
+            if !available.split_whitespace().any(|c| c == controller) {
+                return None;
+            }
+            if !enabled.split_whitespace().any(|c| c == controller) {
+                // Refused when the parent still holds processes of its own
+                // (other than at the root) or isn't delegated to us.
+                fs::write(
+                    parent.join("cgroup.subtree_control"),
+                    format!("+{}", controller),
+                )
+                .ok()?;
+            }
+        }
+        Some(parent)
+    }
+
+    /// Create the cgroup `name` under `parent` with `limits` applied.
+    pub fn create(parent: &Path, name: &str, limits: &ResourceLimits) -> io::Result<Self> {
+        let path = parent.join(name);
+        if let Err(e) = fs::create_dir(&path) {
+            // Left behind by an engine that didn't get to clean up.
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                return Err(e);
+            }
+            fs::remove_dir(&path)?;
+            fs::create_dir(&path)?;
+        }
+        let cgroup = Self { path };
+        cgroup.set_limits(limits)?;
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_limits(&self, limits: &ResourceLimits) -> io::Result<()> {
+        let memory_max = limits.max_memory_mb.saturating_mul(1024 * 1024);
+        if memory_max == 0 {
+            self.write("memory.max", "max")?;
+            self.write("memory.high", "max")?;
+        } else {
+            // Reclaim starts pressing an eighth below the hard limit.
+            self.write("memory.high", &(memory_max - memory_max / 8).to_string())?;
+            self.write("memory.max", &memory_max.to_string())?;
+            // Going over must mean the OOM killer, not swapping out; the
+            // file is absent without swap accounting.
+            let _ = self.write("memory.swap.max", "0");
+        }
+        let cpu_max = match limits.max_cpu_percent {
+            0 => format!("max {}", CPU_PERIOD_US),
+            percent => format!("{} {}", CPU_PERIOD_US * percent as u64 / 100, CPU_PERIOD_US),
+        };
+        self.write("cpu.max", &cpu_max)
+    }
+
+    /// Make the spawned child join this cgroup before it execs, so it and
+    /// everything it starts are limited from the first instruction.
+    pub fn join_on_exec(&self, command: &mut Command) {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let procs =
+                std::ffi::CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes())
+                    .expect("cgroup path without NUL bytes");
+            // Only async-signal-safe calls between fork and exec.
+            unsafe {
+                command.pre_exec(move || {
+                    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    // "0" is whoever writes it.
+                    let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                    let error = io::Error::last_os_error();
+                    libc::close(fd);
+                    if written == 1 {
+                        Ok(())
+                    } else {
+                        Err(error)
+                    }
+                });
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = command;
+    }
+
+    pub fn usage(&self) -> io::Result<CgroupUsage> {
+        usage(&self.path)
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(file), value)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // cgroup.kill needs Linux 5.14; the child has usually been reaped by
+        // now anyway.
+        let _ = fs::write(self.path.join("cgroup.kill"), "1");
+        for _ in 0..50 {
+            match fs::remove_dir(&self.path) {
+                Ok(()) => return,
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    std::thread::sleep(std::time::Duration::from_millis(2))
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+                Err(e) => {
+                    tracing::warn!("Failed to remove cgroup {}: {}", self.path.display(), e);
+                    return;
+                }
+            }
+        }
+        tracing::warn!("Cgroup {} still busy; leaving it", self.path.display());
+    }
+}
+
+/// What the cgroup at `path` has accounted.
+pub fn usage(path: &Path) -> io::Result<CgroupUsage> {
+    let read = |file: &str| fs::read_to_string(path.join(file));
+    Ok(CgroupUsage {
+        memory_bytes: read("memory.current")?.trim().parse().unwrap_or(0),
+        cpu_usage_us: keyed_value(&read("cpu.stat")?, "usage_usec"),
+        oom_kills: keyed_value(&read("memory.events")?, "oom_kill"),
+        populated: keyed_value(&read("cgroup.events")?, "populated") != 0,
+    })
+}
+
+/// The value of `key` in a flat-keyed cgroup file of `key value` lines.
+fn keyed_value(content: &str, key: &str) -> u64 {
+    content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .find(|(name, _)| *name == key)
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// The engine's own cgroup in the unified hierarchy.
+fn own_cgroup() -> Option<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    let mount_point = mountinfo.lines().find_map(|line| {
+        let (mount, filesystem) = line.split_once(" - ")?;
+        if filesystem.split_whitespace().next()? != "cgroup2" {
+            return None;
+        }
+        mount.split_whitespace().nth(4)
+    })?;
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let own = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(Path::new(mount_point).join(own.trim_start_matches('/')))
+}
@@ -20,6 +20,9 @@ pub enum ProcessEvent {
     ProcessFailed(u32, String),
     ResourceLimitExceeded(u32, String),
     HealthCheckFailed(u32),
+    /// The kernel killed a task of the process for exceeding its memory
+    /// limit; carries the cgroup's OOM kill count so far.
+    OutOfMemoryKilled(u32, u64),
 }
 
 struct ResourceMonitor {
@@ -62,7 +65,8 @@ impl ProcessManager {
 
     pub async fn create_process(&self, config: ProcessConfig) -> Result<u32, ProcessError> {
         let process_id = self.generate_process_id().await;
-        let process = SandboxedProcess::new(process_id, config).await?;
+        let mut process = SandboxedProcess::new(process_id, config).await?;
+        process.report_events_to(self.process_events.clone());
 
         {
             let mut processes = self.processes.write().await;
@@ -294,6 +298,9 @@ impl ProcessManager {
                     ProcessEvent::HealthCheckFailed(id) => {
                         log::warn!("Health check failed for process {}", id);
                     }
+                    ProcessEvent::OutOfMemoryKilled(id, kills) => {
+                        log::error!("Process {} OOM-killed ({} so far)", id, kills);
+                    }
                 }
             }
         });
@@ -1,7 +1,11 @@
+pub mod cgroup;
 pub mod manager;
 
+pub use cgroup::ResourceEnforcement;
 pub use manager::*;
 
+use cgroup::Cgroup;
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::process::Stdio;
@@ -18,6 +22,8 @@ pub struct SandboxedProcess {
     pub command_sender: mpsc::UnboundedSender<ProcessCommand>,
     pub status: Arc<RwLock<ProcessStatus>>,
     isolation_manager: IsolationManager,
+    cgroup: Option<Cgroup>,
+    event_sender: Option<mpsc::UnboundedSender<ProcessEvent>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +36,10 @@ pub struct ProcessConfig {
     pub resource_limits: ResourceLimits,
     pub network_restrictions: NetworkRestrictions,
     pub file_system_restrictions: FileSystemRestrictions,
+    /// Cgroup v2 directory to put the process's cgroup in; the engine's own
+    /// cgroup when unset. See `cgroup`.
+    #[serde(default)]
+    pub cgroup_parent: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -77,6 +87,8 @@ pub struct ProcessStats {
     pub start_time: Option<std::time::Instant>,
     pub execution_time: std::time::Duration,
     pub exit_code: Option<i32>,
+    pub oom_kills: u64,
+    pub resource_enforcement: ResourceEnforcement,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -146,12 +158,19 @@ impl SandboxedProcess {
             command_sender,
             status: status.clone(),
             isolation_manager,
+            cgroup: None,
+            event_sender: None,
         };
 
         Self::spawn_command_handler(id, command_receiver, stats, status).await;
         Ok(process)
     }
 
+    /// Send this process's `ProcessEvent`s, such as OOM kills, to `sender`.
+    pub fn report_events_to(&mut self, sender: mpsc::UnboundedSender<ProcessEvent>) {
+        self.event_sender = Some(sender);
+    }
+
     pub async fn start(&mut self) -> Result<(), ProcessError> {
         {
             let mut status = self.status.write().await;
@@ -178,6 +197,12 @@ impl SandboxedProcess {
             .apply_restrictions(&mut command)
             .await?;
 
+        // Dropped, and so removed, on any early return below.
+        let cgroup = self.create_cgroup();
+        if let Some(ref cgroup) = cgroup {
+            cgroup.join_on_exec(&mut command);
+        }
+
         let child = command
             .spawn()
             .map_err(|e| ProcessError::SpawnFailed(e.to_string()))?;
@@ -188,9 +213,15 @@ impl SandboxedProcess {
             let mut stats = self.stats.write().await;
             stats.pid = Some(pid);
             stats.start_time = Some(std::time::Instant::now());
+            stats.resource_enforcement = if cgroup.is_some() {
+                ResourceEnforcement::Kernel
+            } else {
+                ResourceEnforcement::Sampled
+            };
         }
 
         self.handle = Some(child);
+        self.cgroup = cgroup;
 
         {
             let mut status = self.status.write().await;
@@ -207,6 +238,9 @@ impl SandboxedProcess {
             let mut status = self.status.write().await;
             *status = ProcessStatus::Terminating;
         }
+        // Removed once this returns, whether or not the child could be
+        // reaped; anything left in it is killed then.
+        let _cgroup = self.cgroup.take();
 
         if let Some(ref mut child) = self.handle {
             child
@@ -233,6 +267,35 @@ impl SandboxedProcess {
         Ok(())
     }
 
+    /// A cgroup holding this process to its limits, when the kernel can.
+    fn create_cgroup(&self) -> Option<Cgroup> {
+        let configured = self
+            .config
+            .cgroup_parent
+            .as_deref()
+            .map(std::path::Path::new);
+        let Some(parent) = Cgroup::usable_parent(configured) else {
+            log::debug!(
+                "Cgroups unavailable; sampling resource usage of process {}",
+                self.id
+            );
+            return None;
+        };
+        let name = format!("vbe-sandbox-{}-{}", std::process::id(), self.id);
+        match Cgroup::create(&parent, &name, &self.config.resource_limits) {
+            Ok(cgroup) => Some(cgroup),
+            Err(e) => {
+                log::warn!(
+                    "Failed to create a cgroup for process {} under {}: {}",
+                    self.id,
+                    parent.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
     pub async fn suspend(&mut self) -> Result<(), ProcessError> {
         if let Some(ref child) = self.handle {
             let _pid = child.id().ok_or(ProcessError::InvalidPid)?;
@@ -270,10 +333,17 @@ impl SandboxedProcess {
             let stats_guard = stats.read().await;
             stats_guard.pid
         };
+        let id = self.id;
+        let cgroup_path = self
+            .cgroup
+            .as_ref()
+            .map(|cgroup| cgroup.path().to_path_buf());
+        let event_sender = self.event_sender.clone();
 
         if let Some(pid) = pid {
             tokio::spawn(async move {
                 let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+                let mut last_cpu_sample: Option<(u64, std::time::Instant)> = None;
 
                 loop {
                     interval.tick().await;
@@ -286,6 +356,57 @@ impl SandboxedProcess {
                         break;
                     }
 
+                    if let Some(ref path) = cgroup_path {
+                        let Ok(usage) = cgroup::usage(path) else {
+                            continue;
+                        };
+                        let now = std::time::Instant::now();
+                        let mut stats_guard = stats.write().await;
+                        stats_guard.memory_usage_bytes = usage.memory_bytes;
+                        if let Some((last_usage, last_time)) = last_cpu_sample {
+                            let elapsed_us = now.duration_since(last_time).as_micros() as f64;
+                            if elapsed_us > 0.0 {
+                                stats_guard.cpu_usage_percent =
+                                    usage.cpu_usage_us.saturating_sub(last_usage) as f64
+                                        / elapsed_us
+                                        * 100.0;
+                            }
+                        }
+                        last_cpu_sample = Some((usage.cpu_usage_us, now));
+                        if let Some(start_time) = stats_guard.start_time {
+                            stats_guard.execution_time = start_time.elapsed();
+                        }
+
+                        if usage.oom_kills > stats_guard.oom_kills {
+                            stats_guard.oom_kills = usage.oom_kills;
+                            log::warn!("Process {} was OOM-killed", id);
+                            if let Some(ref sender) = event_sender {
+                                let _ = sender
+                                    .send(ProcessEvent::OutOfMemoryKilled(id, usage.oom_kills));
+                            }
+                        }
+                        if !usage.populated {
+                            // Everything in the cgroup has exited.
+                            let oom_killed = stats_guard.oom_kills > 0;
+                            drop(stats_guard);
+                            *status.write().await = if oom_killed {
+                                ProcessStatus::Failed
+                            } else {
+                                ProcessStatus::Terminated
+                            };
+                            if oom_killed {
+                                if let Some(ref sender) = event_sender {
+                                    let _ = sender.send(ProcessEvent::ProcessFailed(
+                                        id,
+                                        "killed for exceeding its memory limit".to_string(),
+                                    ));
+                                }
+                            }
+                            break;
+                        }
+                        continue;
+                    }
+
                     if let Ok(process_info) = Self::get_process_info(pid).await {
                         let mut stats_guard = stats.write().await;
                         stats_guard.memory_usage_bytes = process_info.memory_bytes;
@@ -540,6 +661,7 @@ impl Default for ProcessConfig {
             resource_limits: ResourceLimits::default(),
             network_restrictions: NetworkRestrictions::default(),
             file_system_restrictions: FileSystemRestrictions::default(),
+            cgroup_parent: None,
         }
     }
 }
@@ -389,6 +389,11 @@ impl SecurityFramework {
             .collect()
     }
 
+    /// Every event analyzed so far, oldest first.
+    pub async fn get_security_events(&self) -> Vec<SecurityEvent> {
+        self.active_threats.read().await.clone()
+    }
+
     pub async fn get_security_status(&self) -> SecurityStatus {
         let active_threats = self.active_threats.read().await;
         let quarantined_count = self.incident_responder.get_quarantined_count().await;
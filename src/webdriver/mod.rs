@@ -0,0 +1,1068 @@
+//! WebDriver automation endpoint, so existing browser-automation suites
+//! can drive pages in the engine.
+//!
+//! [`WebDriverServer`] speaks a subset of W3C WebDriver over HTTP:
+//! sessions, navigation, finding elements by CSS selector, reading,
+//! clicking and typing into them, scripts, screenshots and the window
+//! size. Each session owns a [`BrowserEngine`] built from the server's
+//! base config and the session's capabilities. Element references are
+//! node ids; one goes stale once its node leaves the document, which
+//! happens to every node when the page navigates.
+
+pub mod http;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use crate::core::accelerators::Modifiers;
+use crate::core::dom::{document::NodeType, NodeId};
+use crate::{BrowserConfig, BrowserEngine, BrowserError, InputEvent};
+
+/// Key under which element references travel, as in the spec.
+pub const ELEMENT_KEY: &str = "element-6066-11e4-a52e-4f735466cecf";
+
+/// `browserName` this engine answers to.
+pub const BROWSER_NAME: &str = "vulkan_browser_engine";
+
+/// Capability holding the engine's own settings.
+pub const VENDOR_OPTIONS: &str = "vbe:options";
+
+/// How often waits check back, and how often idle sessions are ticked.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Errors as the wire protocol names them; each maps to a status code.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum WebDriverError {
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("No active session with id {0}")]
+    InvalidSessionId(String),
+    #[error("{0}")]
+    InvalidSelector(String),
+    #[error("No element matches {0}")]
+    NoSuchElement(String),
+    #[error("Element {0} is no longer in the document")]
+    StaleElementReference(String),
+    #[error("{0}")]
+    ElementClickIntercepted(String),
+    #[error("{0}")]
+    ElementNotInteractable(String),
+    #[error("{0}")]
+    JavascriptError(String),
+    #[error("Script did not finish within {0} ms")]
+    ScriptTimeout(u64),
+    #[error("Navigation did not finish within {0} ms")]
+    Timeout(u64),
+    #[error("{0}")]
+    SessionNotCreated(String),
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("{0} is not supported here")]
+    UnknownMethod(String),
+    #[error("{0}")]
+    UnknownError(String),
+}
+
+impl WebDriverError {
+    /// The `error` code of the response.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidArgument(_) => "invalid argument",
+            Self::InvalidSessionId(_) => "invalid session id",
+            Self::InvalidSelector(_) => "invalid selector",
+            Self::NoSuchElement(_) => "no such element",
+            Self::StaleElementReference(_) => "stale element reference",
+            Self::ElementClickIntercepted(_) => "element click intercepted",
+            Self::ElementNotInteractable(_) => "element not interactable",
+            Self::JavascriptError(_) => "javascript error",
+            Self::ScriptTimeout(_) => "script timeout",
+            Self::Timeout(_) => "timeout",
+            Self::SessionNotCreated(_) => "session not created",
+            Self::UnknownCommand(_) => "unknown command",
+            Self::UnknownMethod(_) => "unknown method",
+            Self::UnknownError(_) => "unknown error",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::InvalidArgument(_)
+            | Self::InvalidSelector(_)
+            | Self::ElementClickIntercepted(_)
+            | Self::ElementNotInteractable(_) => 400,
+            Self::InvalidSessionId(_)
+            | Self::NoSuchElement(_)
+            | Self::StaleElementReference(_)
+            | Self::UnknownCommand(_) => 404,
+            Self::UnknownMethod(_) => 405,
+            Self::JavascriptError(_)
+            | Self::ScriptTimeout(_)
+            | Self::Timeout(_)
+            | Self::SessionNotCreated(_)
+            | Self::UnknownError(_) => 500,
+        }
+    }
+
+    /// The response body.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "value": {
+                "error": self.code(),
+                "message": self.to_string(),
+                "stacktrace": "",
+            }
+        })
+    }
+}
+
+impl From<BrowserError> for WebDriverError {
+    fn from(e: BrowserError) -> Self {
+        match e {
+            BrowserError::JSEngine(message) => Self::JavascriptError(message),
+            other => Self::UnknownError(other.to_string()),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, WebDriverError>;
+
+/// Session timeouts in milliseconds; a `None` script timeout waits forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub script: Option<u64>,
+    pub page_load: u64,
+    pub implicit: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            script: Some(30_000),
+            page_load: 300_000,
+            implicit: 0,
+        }
+    }
+}
+
+impl Timeouts {
+    fn to_json(self) -> Value {
+        json!({
+            "script": self.script,
+            "pageLoad": self.page_load,
+            "implicit": self.implicit,
+        })
+    }
+
+    /// Apply the fields `value` has, all or nothing.
+    fn update(&mut self, value: &Value) -> Result<()> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| WebDriverError::InvalidArgument("timeouts must be an object".into()))?;
+        let mut updated = *self;
+        for (name, value) in object {
+            let millis = match value {
+                Value::Null if name == "script" => None,
+                value => Some(value.as_u64().ok_or_else(|| {
+                    WebDriverError::InvalidArgument(format!("{name} must be a whole number"))
+                })?),
+            };
+            match (name.as_str(), millis) {
+                ("script", millis) => updated.script = millis,
+                ("pageLoad", Some(millis)) => updated.page_load = millis,
+                ("implicit", Some(millis)) => updated.implicit = millis,
+                _ => {
+                    return Err(WebDriverError::InvalidArgument(format!(
+                        "unknown timeout {name}"
+                    )))
+                }
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+}
+
+struct Session {
+    engine: BrowserEngine,
+    timeouts: Timeouts,
+}
+
+/// Serves WebDriver sessions over HTTP. Engine futures are not `Send`, so
+/// the server runs on the task that polls [`Self::serve`], one request at
+/// a time.
+pub struct WebDriverServer {
+    config: BrowserConfig,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl WebDriverServer {
+    /// Sessions start from `config`, adjusted by their capabilities.
+    pub fn new(config: BrowserConfig) -> Self {
+        Self {
+            config,
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Answer requests on `listener`, which must be bound to a loopback
+    /// address, ticking every session's engine while no client is talking.
+    /// Runs until the future is dropped.
+    pub async fn serve(
+        &self,
+        listener: tokio::net::TcpListener,
+    ) -> std::result::Result<(), BrowserError> {
+        let addr = listener
+            .local_addr()
+            .map_err(|e| BrowserError::Platform(e.to_string()))?;
+        if !addr.ip().is_loopback() {
+            return Err(BrowserError::Security(format!(
+                "WebDriver only listens on loopback addresses, not {}",
+                addr
+            )));
+        }
+        tracing::info!("WebDriver listening on http://{}", addr);
+        loop {
+            let mut stream = match tokio::time::timeout(POLL_INTERVAL, listener.accept()).await {
+                Ok(Ok((stream, _))) => stream,
+                Ok(Err(e)) => {
+                    tracing::warn!("WebDriver accept failed: {}", e);
+                    continue;
+                }
+                Err(_) => {
+                    self.tick_sessions().await;
+                    continue;
+                }
+            };
+            let request = match tokio::time::timeout(
+                REQUEST_TIMEOUT,
+                http::read_request(&mut stream),
+            )
+            .await
+            {
+                Ok(Some(request)) => request,
+                _ => continue,
+            };
+            let segments: Vec<&str> = request.segments.iter().map(String::as_str).collect();
+            let (status, body) = match self.handle(&request.method, &segments, &request.body).await
+            {
+                Ok(value) => (200, json!({ "value": value })),
+                Err(e) => {
+                    tracing::debug!(
+                        "WebDriver {} /{}: {}",
+                        request.method,
+                        segments.join("/"),
+                        e
+                    );
+                    (e.status(), e.to_json())
+                }
+            };
+            if let Err(e) = http::write_response(&mut stream, status, &body).await {
+                tracing::debug!("WebDriver client went away: {}", e);
+            }
+        }
+    }
+
+    /// Run one command, as an HTTP client of [`Self::serve`] would, and
+    /// return the response's `value`. `segments` is the request path split
+    /// at slashes, such as `["session", id, "url"]`.
+    pub async fn handle(&self, method: &str, segments: &[&str], body: &[u8]) -> Result<Value> {
+        let params: Value = if body.iter().all(u8::is_ascii_whitespace) {
+            json!({})
+        } else {
+            serde_json::from_slice(body)
+                .map_err(|e| WebDriverError::InvalidArgument(format!("body is not JSON: {e}")))?
+        };
+        match segments {
+            ["status"] => {
+                expect_method(method, "GET", segments)?;
+                Ok(json!({ "ready": true, "message": "Ready for new sessions" }))
+            }
+            ["session"] => {
+                expect_method(method, "POST", segments)?;
+                self.new_session(&params).await
+            }
+            ["session", id] => {
+                expect_method(method, "DELETE", segments)?;
+                let session = self.sessions.write().await.remove(*id);
+                match session {
+                    Some(session) => {
+                        session.engine.shutdown().await?;
+                        Ok(Value::Null)
+                    }
+                    None => Err(WebDriverError::InvalidSessionId(id.to_string())),
+                }
+            }
+            ["session", id, command @ ..] => {
+                let mut sessions = self.sessions.write().await;
+                let session = sessions
+                    .get_mut(*id)
+                    .ok_or_else(|| WebDriverError::InvalidSessionId(id.to_string()))?;
+                session.command(method, command, &params).await
+            }
+            _ => Err(WebDriverError::UnknownCommand(format!(
+                "{method} /{}",
+                segments.join("/")
+            ))),
+        }
+    }
+
+    async fn tick_sessions(&self) {
+        for session in self.sessions.read().await.values() {
+            if let Err(e) = session.engine.tick().await {
+                tracing::debug!("WebDriver session tick failed: {}", e);
+            }
+        }
+    }
+
+    /// Pick the first `firstMatch` entry, merged over `alwaysMatch`, this
+    /// engine can satisfy, and start a session with it.
+    async fn new_session(&self, params: &Value) -> Result<Value> {
+        let capabilities = params.get("capabilities").unwrap_or(&Value::Null);
+        let always = match capabilities.get("alwaysMatch") {
+            None | Some(Value::Null) => serde_json::Map::new(),
+            Some(Value::Object(always)) => always.clone(),
+            Some(_) => {
+                return Err(WebDriverError::InvalidArgument(
+                    "alwaysMatch must be an object".into(),
+                ))
+            }
+        };
+        let first_match = match capabilities.get("firstMatch") {
+            None | Some(Value::Null) => vec![json!({})],
+            Some(Value::Array(entries)) if !entries.is_empty() => entries.clone(),
+            Some(_) => {
+                return Err(WebDriverError::InvalidArgument(
+                    "firstMatch must be a non-empty array".into(),
+                ))
+            }
+        };
+
+        let mut rejection = None;
+        for entry in first_match {
+            let mut merged = always.clone();
+            match entry {
+                Value::Object(entry) => {
+                    for (name, value) in entry {
+                        if merged.contains_key(&name) {
+                            return Err(WebDriverError::InvalidArgument(format!(
+                                "{name} is in both alwaysMatch and firstMatch"
+                            )));
+                        }
+                        merged.insert(name, value);
+                    }
+                }
+                _ => {
+                    return Err(WebDriverError::InvalidArgument(
+                        "firstMatch entries must be objects".into(),
+                    ))
+                }
+            }
+            match self.session_settings(&merged) {
+                Ok((config, timeouts)) => return self.start_session(config, timeouts).await,
+                Err(WebDriverError::SessionNotCreated(reason)) => rejection = Some(reason),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(WebDriverError::SessionNotCreated(
+            rejection.unwrap_or_else(|| "no capabilities matched".into()),
+        ))
+    }
+
+    /// The config and timeouts `capabilities` ask for, or why they cannot
+    /// be met.
+    fn session_settings(
+        &self,
+        capabilities: &serde_json::Map<String, Value>,
+    ) -> Result<(BrowserConfig, Timeouts)> {
+        let mut config = self.config.clone();
+        let mut timeouts = Timeouts::default();
+        for (name, value) in capabilities {
+            match name.as_str() {
+                "browserName" if value.as_str() != Some(BROWSER_NAME) => {
+                    return Err(WebDriverError::SessionNotCreated(format!(
+                        "browserName {value} is not {BROWSER_NAME}"
+                    )));
+                }
+                "platformName" if value.as_str() != Some(std::env::consts::OS) => {
+                    return Err(WebDriverError::SessionNotCreated(format!(
+                        "platformName {value} is not {}",
+                        std::env::consts::OS
+                    )));
+                }
+                "acceptInsecureCerts" if value.as_bool() == Some(true) => {
+                    return Err(WebDriverError::SessionNotCreated(
+                        "insecure certificates are never accepted".into(),
+                    ));
+                }
+                "pageLoadStrategy" if value.as_str() != Some("normal") => {
+                    return Err(WebDriverError::SessionNotCreated(format!(
+                        "pageLoadStrategy {value} is not supported; pages load fully"
+                    )));
+                }
+                "timeouts" => timeouts.update(value)?,
+                VENDOR_OPTIONS => apply_vendor_options(&mut config, value)?,
+                // Other standard and extension capabilities are ignored.
+                _ => {}
+            }
+        }
+        Ok((config, timeouts))
+    }
+
+    async fn start_session(&self, config: BrowserConfig, timeouts: Timeouts) -> Result<Value> {
+        let capabilities = json!({
+            "browserName": BROWSER_NAME,
+            "browserVersion": env!("CARGO_PKG_VERSION"),
+            "platformName": std::env::consts::OS,
+            "acceptInsecureCerts": false,
+            "pageLoadStrategy": "normal",
+            "setWindowRect": true,
+            "timeouts": timeouts.to_json(),
+            VENDOR_OPTIONS: {
+                "viewportWidth": config.viewport_width,
+                "viewportHeight": config.viewport_height,
+                "userAgent": config.user_agent,
+                "privateMode": config.private_mode,
+                "jit": config.enable_jit,
+                "gpu": config.enable_gpu_acceleration,
+            },
+        });
+        let engine = BrowserEngine::new(config)
+            .await
+            .map_err(|e| WebDriverError::SessionNotCreated(e.to_string()))?;
+        let id = uuid::Uuid::new_v4().to_string();
+        tracing::info!("WebDriver session {} started", id);
+        self.sessions
+            .write()
+            .await
+            .insert(id.clone(), Session { engine, timeouts });
+        Ok(json!({ "sessionId": id, "capabilities": capabilities }))
+    }
+}
+
+/// Apply `vbe:options`, the engine settings a session may choose.
+fn apply_vendor_options(config: &mut BrowserConfig, options: &Value) -> Result<()> {
+    let options = options.as_object().ok_or_else(|| {
+        WebDriverError::InvalidArgument(format!("{VENDOR_OPTIONS} must be an object"))
+    })?;
+    let invalid = |name: &str, expected: &str| {
+        WebDriverError::InvalidArgument(format!("{VENDOR_OPTIONS}.{name} must be {expected}"))
+    };
+    for (name, value) in options {
+        match name.as_str() {
+            "viewportWidth" | "viewportHeight" => {
+                let size = value
+                    .as_u64()
+                    .and_then(|size| u32::try_from(size).ok())
+                    .filter(|&size| size > 0)
+                    .ok_or_else(|| invalid(name, "a positive whole number"))?;
+                if name == "viewportWidth" {
+                    config.viewport_width = size;
+                } else {
+                    config.viewport_height = size;
+                }
+            }
+            "userAgent" => {
+                config.user_agent = value
+                    .as_str()
+                    .ok_or_else(|| invalid(name, "a string"))?
+                    .to_string();
+            }
+            "privateMode" | "jit" | "gpu" => {
+                let flag = value.as_bool().ok_or_else(|| invalid(name, "a boolean"))?;
+                match name.as_str() {
+                    "privateMode" => config.private_mode = flag,
+                    "jit" => config.enable_jit = flag,
+                    _ => config.enable_gpu_acceleration = flag,
+                }
+            }
+            _ => {
+                return Err(WebDriverError::InvalidArgument(format!(
+                    "unknown option {VENDOR_OPTIONS}.{name}"
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn expect_method(method: &str, expected: &str, segments: &[&str]) -> Result<()> {
+    if method == expected {
+        Ok(())
+    } else {
+        Err(WebDriverError::UnknownMethod(format!(
+            "{method} /{}",
+            segments.join("/")
+        )))
+    }
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| WebDriverError::InvalidArgument(format!("{name} must be a string")))
+}
+
+fn element_reference(node_id: NodeId) -> Value {
+    json!({ ELEMENT_KEY: node_id.0.to_string() })
+}
+
+/// What one character of Element Send Keys stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeyInput {
+    Key(String),
+    /// A modifier key, held until pressed again or [`KeyInput::Release`].
+    Modifier(Modifiers),
+    Release,
+}
+
+/// Characters in the spec's private-use range name keys; the rest type
+/// themselves.
+fn key_input(c: char) -> KeyInput {
+    let key = match c {
+        '\u{E000}' => return KeyInput::Release,
+        '\u{E008}' | '\u{E050}' => return KeyInput::Modifier(Modifiers::SHIFT),
+        '\u{E009}' | '\u{E051}' => return KeyInput::Modifier(Modifiers::CTRL),
+        '\u{E00A}' | '\u{E052}' => return KeyInput::Modifier(Modifiers::ALT),
+        '\u{E03D}' | '\u{E053}' => return KeyInput::Modifier(Modifiers::META),
+        '\u{E003}' => "Backspace",
+        '\u{E004}' => "Tab",
+        '\u{E006}' | '\u{E007}' => "Enter",
+        '\u{E00C}' => "Escape",
+        '\u{E00D}' => " ",
+        '\u{E00E}' => "PageUp",
+        '\u{E00F}' => "PageDown",
+        '\u{E010}' => "End",
+        '\u{E011}' => "Home",
+        '\u{E012}' => "ArrowLeft",
+        '\u{E013}' => "ArrowUp",
+        '\u{E014}' => "ArrowRight",
+        '\u{E015}' => "ArrowDown",
+        '\u{E016}' => "Insert",
+        '\u{E017}' => "Delete",
+        c => return KeyInput::Key(c.to_string()),
+    };
+    KeyInput::Key(key.to_string())
+}
+
+/// Run the body of an Execute Script command as a function of `args`,
+/// settling into a slot the engine polls. Sync scripts settle with what
+/// they return, awaited when it is a promise; async ones when they call
+/// the callback appended to their arguments. Elements go out as
+/// references.
+fn script_runner(body: &str, args: &Value, asynchronous: bool) -> String {
+    let call = if asynchronous {
+        "args.push((value) => settle(true, value));\n    run.apply(null, args);"
+    } else {
+        "Promise.resolve(run.apply(null, args)).then(\n      (value) => settle(true, value),\n      (e) => settle(false, e),\n    );"
+    };
+    format!(
+        r#"(() => {{
+  const slot = {{ done: false }};
+  globalThis.__vbeWebDriverResult = slot;
+  const settle = (ok, value) => {{
+    if (slot.done) return;
+    slot.done = true;
+    try {{
+      slot.ok = ok;
+      slot.value = ok
+        ? JSON.stringify(value, (key, v) =>
+            v instanceof Element ? {{ {key}: v.__nodeId }} : v)
+        : String(value && value.message !== undefined ? value.message : value);
+    }} catch (e) {{
+      slot.ok = false;
+      slot.value = String(e && e.message);
+    }}
+  }};
+  try {{
+    const args = JSON.parse({args}, (key, v) =>
+      v !== null && typeof v === 'object' && {key} in v ? __vbeWrapNode(v[{key}]) : v);
+    const run = function () {{
+{body}
+    }};
+    {call}
+  }} catch (e) {{
+    settle(false, e);
+  }}
+}})();"#,
+        key = Value::String(ELEMENT_KEY.to_string()),
+        args = Value::String(args.to_string()),
+    )
+}
+
+/// Hands over the settled slot once: `{ok, value}`, or `null` while the
+/// script is still running.
+const TAKE_SCRIPT_RESULT: &str = r#"(() => {
+  const slot = globalThis.__vbeWebDriverResult;
+  if (!slot || !slot.done) return null;
+  delete globalThis.__vbeWebDriverResult;
+  return { ok: slot.ok, value: slot.value === undefined ? null : slot.value };
+})()"#;
+
+impl Session {
+    async fn command(&mut self, method: &str, command: &[&str], params: &Value) -> Result<Value> {
+        match command {
+            ["timeouts"] => match method {
+                "GET" => Ok(self.timeouts.to_json()),
+                "POST" => {
+                    self.timeouts.update(params)?;
+                    Ok(Value::Null)
+                }
+                _ => Err(unknown_method(method, command)),
+            },
+            ["url"] => match method {
+                "GET" => Ok(json!(self
+                    .engine
+                    .get_current_url()
+                    .await
+                    .unwrap_or_else(|| "about:blank".to_string()))),
+                "POST" => {
+                    let url = string_param(params, "url")?;
+                    self.with_page_load_timeout(self.engine.navigate(url)).await
+                }
+                _ => Err(unknown_method(method, command)),
+            },
+            ["back"] => {
+                only(method, "POST", command)?;
+                self.with_page_load_timeout(self.engine.navigate_back())
+                    .await
+            }
+            ["forward"] => {
+                only(method, "POST", command)?;
+                self.with_page_load_timeout(self.engine.navigate_forward())
+                    .await
+            }
+            ["refresh"] => {
+                only(method, "POST", command)?;
+                self.with_page_load_timeout(self.engine.reload()).await
+            }
+            ["title"] => {
+                only(method, "GET", command)?;
+                Ok(json!(self
+                    .engine
+                    .get_page_title()
+                    .await
+                    .unwrap_or_default()))
+            }
+            ["element"] | ["elements"] => {
+                only(method, "POST", command)?;
+                let found = self.find(None, params).await?;
+                found_elements(command[0] == "element", found, params)
+            }
+            ["element", reference, kind @ ("element" | "elements")] => {
+                only(method, "POST", command)?;
+                let scope = self.node(reference).await?;
+                let found = self.find(Some(scope), params).await?;
+                found_elements(*kind == "element", found, params)
+            }
+            ["element", reference, "text"] => {
+                only(method, "GET", command)?;
+                let node_id = self.node(reference).await?;
+                let text = self
+                    .engine
+                    .document
+                    .read()
+                    .await
+                    .text_content(node_id)
+                    .unwrap_or_default();
+                Ok(json!(text.split_whitespace().collect::<Vec<_>>().join(" ")))
+            }
+            ["element", reference, "name"] => {
+                only(method, "GET", command)?;
+                let node_id = self.node(reference).await?;
+                let document = self.engine.document.read().await;
+                let tag = document
+                    .get_node(node_id)
+                    .map(|node| node.read().tag_name.to_ascii_lowercase())
+                    .unwrap_or_default();
+                Ok(json!(tag))
+            }
+            ["element", reference, "attribute", name] => {
+                only(method, "GET", command)?;
+                let node_id = self.node(reference).await?;
+                let document = self.engine.document.read().await;
+                let value = document
+                    .get_node(node_id)
+                    .and_then(|node| node.read().get_attribute(name));
+                Ok(json!(value))
+            }
+            ["element", reference, "property", name] => {
+                only(method, "GET", command)?;
+                self.node(reference).await?;
+                let args = json!([{ ELEMENT_KEY: reference }, name]);
+                self.execute("return arguments[0][arguments[1]];", &args, false)
+                    .await
+            }
+            ["element", reference, "rect"] => {
+                only(method, "GET", command)?;
+                let node_id = self.node(reference).await?;
+                let (scroll_x, scroll_y) = self.engine.scroll_position().await;
+                let layout_box = self
+                    .engine
+                    .layout_engine
+                    .read()
+                    .await
+                    .get_layout_box(node_id);
+                let (x, y, width, height) = layout_box
+                    .map(|b| {
+                        (
+                            b.border_box_x() + scroll_x,
+                            b.border_box_y() + scroll_y,
+                            b.border_box_width(),
+                            b.border_box_height(),
+                        )
+                    })
+                    .unwrap_or_default();
+                Ok(json!({ "x": x, "y": y, "width": width, "height": height }))
+            }
+            ["element", reference, "click"] => {
+                only(method, "POST", command)?;
+                let node_id = self.node(reference).await?;
+                self.click(node_id).await?;
+                Ok(Value::Null)
+            }
+            ["element", reference, "value"] => {
+                only(method, "POST", command)?;
+                let node_id = self.node(reference).await?;
+                let text = string_param(params, "text")?;
+                self.send_keys(node_id, text).await?;
+                Ok(Value::Null)
+            }
+            ["execute", kind @ ("sync" | "async")] => {
+                only(method, "POST", command)?;
+                let script = string_param(params, "script")?;
+                let args = match params.get("args") {
+                    Some(args @ Value::Array(_)) => args.clone(),
+                    _ => {
+                        return Err(WebDriverError::InvalidArgument(
+                            "args must be an array".into(),
+                        ))
+                    }
+                };
+                self.execute(script, &args, *kind == "async").await
+            }
+            ["screenshot"] => {
+                only(method, "GET", command)?;
+                self.screenshot().await
+            }
+            ["window", "rect"] => match method {
+                "GET" => Ok(self.window_rect().await),
+                "POST" => {
+                    let size = |name: &str| match params.get(name) {
+                        None | Some(Value::Null) => Ok(None),
+                        Some(value) => value
+                            .as_u64()
+                            .and_then(|size| u32::try_from(size).ok())
+                            .filter(|&size| size > 0)
+                            .map(Some)
+                            .ok_or_else(|| {
+                                WebDriverError::InvalidArgument(format!(
+                                    "{name} must be a positive whole number"
+                                ))
+                            }),
+                    };
+                    let (width, height) = (size("width")?, size("height")?);
+                    if width.is_some() || height.is_some() {
+                        let (current_width, current_height) = self.viewport().await;
+                        self.engine
+                            .resize_viewport(
+                                width.unwrap_or(current_width as u32),
+                                height.unwrap_or(current_height as u32),
+                            )
+                            .await?;
+                    }
+                    Ok(self.window_rect().await)
+                }
+                _ => Err(unknown_method(method, command)),
+            },
+            _ => Err(WebDriverError::UnknownCommand(format!(
+                "{method} /session/<id>/{}",
+                command.join("/")
+            ))),
+        }
+    }
+
+    async fn with_page_load_timeout(
+        &self,
+        navigation: impl std::future::Future<Output = crate::Result<()>>,
+    ) -> Result<Value> {
+        let limit = self.timeouts.page_load;
+        match tokio::time::timeout(Duration::from_millis(limit), navigation).await {
+            Ok(outcome) => outcome.map(|_| Value::Null).map_err(WebDriverError::from),
+            Err(_) => Err(WebDriverError::Timeout(limit)),
+        }
+    }
+
+    /// The node `reference` names, if it is still in the document.
+    async fn node(&self, reference: &str) -> Result<NodeId> {
+        let node_id = reference
+            .parse()
+            .map(NodeId)
+            .map_err(|_| WebDriverError::NoSuchElement(format!("element {reference}")))?;
+        let document = self.engine.document.read().await;
+        let is_element = document
+            .get_node(node_id)
+            .is_some_and(|node| node.read().node_type == NodeType::Element);
+        if is_element && document.is_connected(node_id) {
+            Ok(node_id)
+        } else {
+            Err(WebDriverError::StaleElementReference(reference.to_string()))
+        }
+    }
+
+    /// Elements matching the `using`/`value` locator, under `scope` when
+    /// given, retrying until the implicit wait is up.
+    async fn find(&self, scope: Option<NodeId>, params: &Value) -> Result<Vec<NodeId>> {
+        let value = string_param(params, "value")?;
+        let selector = match string_param(params, "using")? {
+            "css selector" | "tag name" => value,
+            other => {
+                return Err(WebDriverError::InvalidArgument(format!(
+                    "locator strategy {other} is not supported; use css selector"
+                )))
+            }
+        };
+        let deadline = Instant::now() + Duration::from_millis(self.timeouts.implicit);
+        loop {
+            let found: Vec<NodeId> = {
+                let document = self.engine.document.read().await;
+                document
+                    .query_selector_all(selector)
+                    .map_err(|e| WebDriverError::InvalidSelector(e.to_string()))?
+                    .into_iter()
+                    .filter(|&node_id| match scope {
+                        Some(scope) => {
+                            let mut current = document.get_parent(node_id);
+                            while let Some(ancestor) = current {
+                                if ancestor == scope {
+                                    return true;
+                                }
+                                current = document.get_parent(ancestor);
+                            }
+                            false
+                        }
+                        None => true,
+                    })
+                    .collect()
+            };
+            if !found.is_empty() || Instant::now() >= deadline {
+                return Ok(found);
+            }
+            self.engine.tick().await?;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    async fn viewport(&self) -> (f32, f32) {
+        self.engine.layout_engine.read().await.viewport_size()
+    }
+
+    async fn window_rect(&self) -> Value {
+        let (width, height) = self.viewport().await;
+        json!({ "x": 0, "y": 0, "width": width, "height": height })
+    }
+
+    /// The center of `node_id`'s border box in the viewport, scrolling it
+    /// into view first when it is outside.
+    async fn in_view_center(&self, node_id: NodeId) -> Result<(f32, f32)> {
+        let center = || async {
+            let layout_box = self
+                .engine
+                .layout_engine
+                .read()
+                .await
+                .get_layout_box(node_id)?;
+            if layout_box.border_box_width() <= 0.0 || layout_box.border_box_height() <= 0.0 {
+                return None;
+            }
+            Some((
+                layout_box.border_box_x() + layout_box.border_box_width() / 2.0,
+                layout_box.border_box_y() + layout_box.border_box_height() / 2.0,
+            ))
+        };
+        let not_rendered = || {
+            WebDriverError::ElementNotInteractable("element has no size or is not rendered".into())
+        };
+        let (width, height) = self.viewport().await;
+        let in_view = |(x, y): (f32, f32)| x >= 0.0 && x < width && y >= 0.0 && y < height;
+
+        let (x, y) = center().await.ok_or_else(not_rendered)?;
+        if in_view((x, y)) {
+            return Ok((x, y));
+        }
+        let (scroll_x, scroll_y) = self.engine.scroll_position().await;
+        self.engine
+            .scroll_to(scroll_x + x - width / 2.0, scroll_y + y - height / 2.0)
+            .await?;
+        let point = center().await.ok_or_else(not_rendered)?;
+        if in_view(point) {
+            Ok(point)
+        } else {
+            Err(WebDriverError::ElementNotInteractable(
+                "element cannot be scrolled into view".into(),
+            ))
+        }
+    }
+
+    /// Press and release the primary button at the center of `node_id`,
+    /// unless something else is painted there.
+    async fn click(&self, node_id: NodeId) -> Result<()> {
+        let (x, y) = self.in_view_center(node_id).await?;
+        let hit = self.engine.hit_test(x, y).await?;
+        {
+            let document = self.engine.document.read().await;
+            let mut current = hit;
+            while current.is_some_and(|id| id != node_id) {
+                current = current.and_then(|id| document.get_parent(id));
+            }
+            if current.is_none() {
+                let receiver = hit
+                    .and_then(|id| document.get_node(id))
+                    .map(|node| node.read().tag_name.to_ascii_lowercase())
+                    .unwrap_or_else(|| "nothing".to_string());
+                return Err(WebDriverError::ElementClickIntercepted(format!(
+                    "element is not clickable at ({x}, {y}); <{receiver}> would receive the click"
+                )));
+            }
+        }
+        let (x, y) = (x.round() as i32, y.round() as i32);
+        for event in [
+            InputEvent::MouseMove { x, y },
+            InputEvent::MouseDown { x, y, button: 0 },
+            InputEvent::MouseUp { x, y, button: 0 },
+        ] {
+            self.engine.handle_input_event(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Focus `node_id` and type `text` into it, key by key.
+    async fn send_keys(&self, node_id: NodeId, text: &str) -> Result<()> {
+        let focused = {
+            let document = self.engine.document.read().await;
+            self.engine.editing.write().await.focus(&document, node_id)
+        };
+        if !focused {
+            return Err(WebDriverError::ElementNotInteractable(
+                "element cannot take keyboard input".into(),
+            ));
+        }
+        let mut held = Modifiers::NONE;
+        for c in text.chars() {
+            match key_input(c) {
+                KeyInput::Key(key) => {
+                    self.engine.press_key(&key, held).await?;
+                }
+                KeyInput::Modifier(modifier) if held.contains(modifier) => {
+                    held = Modifiers::from_bits(held.bits() & !modifier.bits());
+                }
+                KeyInput::Modifier(modifier) => held = held | modifier,
+                KeyInput::Release => held = Modifiers::NONE,
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `script` with `args` and wait for it to settle, up to the
+    /// script timeout.
+    async fn execute(&self, script: &str, args: &Value, asynchronous: bool) -> Result<Value> {
+        self.check_references(args).await?;
+        self.engine
+            .execute_javascript(&script_runner(script, args, asynchronous))
+            .await?;
+        let deadline = self
+            .timeouts
+            .script
+            .map(|millis| Instant::now() + Duration::from_millis(millis));
+        let slot = loop {
+            let slot = self.engine.execute_javascript(TAKE_SCRIPT_RESULT).await?;
+            if !slot.is_null() {
+                break slot;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(WebDriverError::ScriptTimeout(
+                    self.timeouts.script.unwrap_or_default(),
+                ));
+            }
+            self.engine.tick().await?;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        };
+        let value = slot["value"].as_str().unwrap_or("null");
+        if slot["ok"] != Value::Bool(true) {
+            return Err(WebDriverError::JavascriptError(value.to_string()));
+        }
+        serde_json::from_str(value).map_err(|e| WebDriverError::JavascriptError(e.to_string()))
+    }
+
+    /// Fail with the spec's errors for element references in script
+    /// arguments that name no live element.
+    async fn check_references(&self, value: &Value) -> Result<()> {
+        let mut pending = vec![value];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Array(items) => pending.extend(items),
+                Value::Object(object) => match object.get(ELEMENT_KEY) {
+                    Some(Value::String(reference)) => {
+                        self.node(reference).await?;
+                    }
+                    Some(_) => {
+                        return Err(WebDriverError::InvalidArgument(
+                            "element references must be strings".into(),
+                        ))
+                    }
+                    None => pending.extend(object.values()),
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The viewport as a base64 PNG.
+    async fn screenshot(&self) -> Result<Value> {
+        let snapshot = self.engine.snapshot().await;
+        let image = image::RgbaImage::from_raw(snapshot.width, snapshot.height, snapshot.data)
+            .ok_or_else(|| WebDriverError::UnknownError("snapshot has the wrong size".into()))?;
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .map_err(|e| WebDriverError::UnknownError(e.to_string()))?;
+        Ok(json!(
+            base64::engine::general_purpose::STANDARD.encode(png.into_inner())
+        ))
+    }
+}
+
+/// Find Element answers with the first match or fails; Find Elements
+/// with all of them.
+fn found_elements(single: bool, found: Vec<NodeId>, params: &Value) -> Result<Value> {
+    if !single {
+        return Ok(found.into_iter().map(element_reference).collect());
+    }
+    match found.first() {
+        Some(&node_id) => Ok(element_reference(node_id)),
+        None => Err(WebDriverError::NoSuchElement(
+            string_param(params, "value")?.to_string(),
+        )),
+    }
+}
+
+fn only(method: &str, expected: &str, command: &[&str]) -> Result<()> {
+    if method == expected {
+        Ok(())
+    } else {
+        Err(unknown_method(method, command))
+    }
+}
+
+fn unknown_method(method: &str, command: &[&str]) -> WebDriverError {
+    WebDriverError::UnknownMethod(format!("{method} /session/<id>/{}", command.join("/")))
+}
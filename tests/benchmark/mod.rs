@@ -4,7 +4,14 @@ pub mod dom_bench;
 pub mod js_bench; 
 pub mod render_bench;
 
-criterion_group!(benches, dom_bench::bench_dom, js_bench::bench_js, render_bench::bench_render);
+criterion_group!(
+    benches,
+    dom_bench::bench_dom,
+    js_bench::bench_js,
+    render_bench::bench_render,
+    render_bench::bench_layout_wire,
+    render_bench::bench_paint_commands
+);
 criterion_main!(benches);
 
 tests/benchmark/dom_bench.rs
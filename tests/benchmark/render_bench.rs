@@ -12,4 +12,167 @@ pub fn bench_render(c: &mut Criterion) {
                 .collect();
         })
     });
-}
\ No newline at end of file
+}
+
+pub fn bench_layout_wire(c: &mut Criterion) {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::renderer::wire::{encode_delta, encode_keyframe, FrameDecoder};
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ElementType, LayoutNode, LayoutTree, PaintKey, Rect, Style,
+    };
+
+    for size in [1_000u64, 50_000] {
+        let mut tree = LayoutTree::new();
+        for id in 0..size {
+            tree.add_node(LayoutNode {
+                node_id: NodeId(id),
+                bounds: Rect {
+                    x: 0.0,
+                    y: id as f32 * 20.0,
+                    width: 200.0,
+                    height: 18.0,
+                },
+                element_type: ElementType::Block,
+                style: Style::default(),
+                text_content: None,
+                image_url: None,
+                image_frame: 0,
+                clip: ClipChain::new(),
+            });
+        }
+        let frame = encode_keyframe(1, &tree).unwrap();
+        c.bench_with_input(
+            BenchmarkId::new("layout_keyframe_encode", size),
+            &tree,
+            |b, tree| b.iter(|| encode_keyframe(1, tree).unwrap()),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("layout_keyframe_decode", size),
+            &frame,
+            |b, frame| b.iter(|| FrameDecoder::new().apply(frame).map(|_| ()).unwrap()),
+        );
+
+        let changes: Vec<_> = tree
+            .get_render_nodes()
+            .iter()
+            .take(50)
+            .map(|node| (PaintKey::Box(node.node_id, 0), node))
+            .collect();
+        c.bench_with_input(
+            BenchmarkId::new("layout_delta_encode", size),
+            &changes,
+            |b, changes| b.iter(|| encode_delta(2, 1, changes, None).unwrap()),
+        );
+    }
+}
+
+/// Shipping layout trees against shipping paint commands, on a page of
+/// clipped cards with shadowed, underlined text: bytes per frame, and a
+/// frame from the content process's tree to drawn on the other side.
+pub fn bench_paint_commands(c: &mut Criterion) {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::dom::{Document, NodeId};
+    use vulkan_browser_engine::renderer::command_stream::{CommandReceiver, CommandRecorder};
+    use vulkan_browser_engine::renderer::image::DecodedImage;
+    use vulkan_browser_engine::renderer::wire::{encode_keyframe, FrameDecoder};
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ClipRect, CornerRadii, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend,
+        Style, TextDecoration, TextShadow, VulkanRenderer,
+    };
+
+    let mut tree = LayoutTree::new();
+    for card in 0..2_000u64 {
+        let bounds = Rect {
+            x: (card % 4) as f32 * 300.0,
+            y: (card / 4) as f32 * 120.0,
+            width: 280.0,
+            height: 100.0,
+        };
+        let clip = ClipChain::new().push(ClipRect::new(bounds.clone(), CornerRadii::uniform(8.0)));
+        tree.add_node(LayoutNode {
+            node_id: NodeId(card * 4),
+            bounds: bounds.clone(),
+            element_type: ElementType::Block,
+            style: Style {
+                background_color: Some("#f0f0f0".to_string()),
+                ..Default::default()
+            },
+            text_content: None,
+            image_url: None,
+            image_frame: 0,
+            clip: ClipChain::new(),
+        });
+        for line in 0..3 {
+            tree.add_node(LayoutNode {
+                node_id: NodeId(card * 4 + 1 + line),
+                bounds: Rect {
+                    x: bounds.x + 8.0,
+                    y: bounds.y + 8.0 + line as f32 * 28.0,
+                    width: 264.0,
+                    height: 24.0,
+                },
+                element_type: ElementType::Text,
+                style: Style {
+                    color: Some("#202020".to_string()),
+                    text_decoration: TextDecoration::underline(),
+                    text_shadows: vec![TextShadow {
+                        offset_x: 1.0,
+                        offset_y: 1.0,
+                        blur_radius: 2.0,
+                        color: Some("#808080".to_string()),
+                    }],
+                    ..Default::default()
+                },
+                text_content: Some(format!("Card {} line {}: lorem ipsum", card, line)),
+                image_url: None,
+                image_frame: 0,
+                clip: clip.clone(),
+            });
+        }
+    }
+    let no_images = |_: &str, _: u32| -> Option<Arc<DecodedImage>> { None };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let layout_frame = encode_keyframe(1, &tree).unwrap();
+    let mut recorder = CommandRecorder::new();
+    let first = recorder.encode(&tree, no_images).unwrap();
+    let steady = recorder.encode(&tree, no_images).unwrap();
+    println!(
+        "8000 nodes: layout keyframe {} bytes; paint commands {} bytes, {} with the atlas",
+        layout_frame.len(),
+        steady.len(),
+        first.len()
+    );
+
+    c.bench_function("frame_via_layout_tree", |b| {
+        let mut renderer = runtime
+            .block_on(VulkanRenderer::with_backend(RenderBackend::Software))
+            .unwrap();
+        // Painted whole, as the commands are.
+        renderer.set_full_rebuild(true);
+        let document = Document::new();
+        b.iter(|| {
+            let frame = encode_keyframe(1, &tree).unwrap();
+            let mut decoder = FrameDecoder::new();
+            let tree = decoder.apply(&frame).unwrap();
+            runtime.block_on(renderer.render(&document, tree)).unwrap();
+        })
+    });
+    c.bench_function("frame_via_paint_commands", |b| {
+        let mut renderer = runtime
+            .block_on(VulkanRenderer::with_backend(RenderBackend::Software))
+            .unwrap();
+        let mut recorder = CommandRecorder::new();
+        let mut receiver = CommandReceiver::new();
+        receiver
+            .apply(&recorder.encode(&tree, no_images).unwrap())
+            .unwrap();
+        b.iter(|| {
+            let frame = recorder.encode(&tree, no_images).unwrap();
+            receiver.apply(&frame).unwrap();
+            runtime
+                .block_on(renderer.render_commands(&receiver))
+                .unwrap();
+        })
+    });
+}
@@ -0,0 +1,175 @@
+//! Layout regression suite.
+//!
+//! Every `tests/golden/fixtures/<name>.html` is loaded through a `data:` URL on
+//! the software backend, dumped with `BrowserEngine::dump_layout_tree`, and
+//! compared against `tests/golden/expected/<name>.json`. A missing golden fails
+//! the suite; set `UPDATE_GOLDEN=1` to write it, or to regenerate existing ones
+//! after an intended layout change.
+
+use base64::Engine;
+use std::fs;
+use std::path::{Path, PathBuf};
+use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn golden_config() -> BrowserConfig {
+    BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_jit: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        enable_chrome_apis: false,
+        viewport_width: 800,
+        viewport_height: 600,
+        ..Default::default()
+    }
+}
+
+async fn dump_fixture(html: &str) -> serde_json::Value {
+    let engine = BrowserEngine::new(golden_config()).await.unwrap();
+    let url = format!(
+        "data:text/html;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(html)
+    );
+    engine.load_url(&url).await.unwrap();
+    let dump = engine.dump_layout_tree().await;
+    engine.shutdown().await.unwrap();
+    dump
+}
+
+fn fixtures() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(golden_dir().join("fixtures"))
+        .unwrap()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "html"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Compare `actual` with the golden file, returning a readable diff on mismatch
+/// or the reason there is nothing to compare with.
+fn check_golden(name: &str, actual: &serde_json::Value) -> Option<String> {
+    let path = golden_dir().join("expected").join(format!("{name}.json"));
+    let actual = serde_json::to_string_pretty(actual).unwrap() + "\n";
+
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        eprintln!("wrote golden {}", path.display());
+        return None;
+    }
+    let expected = match fs::read_to_string(&path) {
+        Ok(expected) => expected,
+        Err(e) => {
+            return Some(format!(
+                "{name}: cannot read golden {} ({e}); run with {UPDATE_ENV}=1 to write it",
+                path.display()
+            ))
+        }
+    };
+
+    if expected == actual {
+        None
+    } else {
+        Some(format!(
+            "{name}: layout dump differs from {} (rerun with {UPDATE_ENV}=1 if intended)\n{}",
+            path.display(),
+            pretty_assertions::StrComparison::new(&expected, &actual)
+        ))
+    }
+}
+
+#[tokio::test]
+async fn test_layout_matches_golden_dumps() {
+    let mut failures = Vec::new();
+
+    for fixture in fixtures() {
+        let name = fixture.file_stem().unwrap().to_string_lossy().into_owned();
+        let html = fs::read_to_string(&fixture).unwrap();
+        let dump = dump_fixture(&html).await;
+        if let Some(diff) = check_golden(&name, &dump) {
+            failures.push(diff);
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[tokio::test]
+async fn test_layout_dump_is_deterministic() {
+    let html = fs::read_to_string(golden_dir().join("fixtures/flex_justify.html")).unwrap();
+
+    let first = dump_fixture(&html).await;
+    let second = dump_fixture(&html).await;
+
+    assert_eq!(first, second);
+    let text = serde_json::to_string(&first).unwrap();
+    assert!(!text.contains("node_id"));
+}
+
+fn node_at<'a>(dump: &'a serde_json::Value, tag: &str, nth: usize) -> &'a serde_json::Value {
+    dump["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|node| node["tag"] == tag)
+        .nth(nth)
+        .unwrap_or_else(|| panic!("no <{tag}> #{nth} in dump"))
+}
+
+#[tokio::test]
+async fn test_text_wraps_beside_a_float() {
+    let html = fs::read_to_string(golden_dir().join("fixtures/float_wrap.html")).unwrap();
+    let dump = dump_fixture(&html).await;
+
+    let float = &node_at(&dump, "img", 0)["bounds"];
+    assert_eq!(
+        (float["x"].as_f64(), float["width"].as_f64()),
+        (Some(0.0), Some(100.0))
+    );
+    let lines: Vec<&serde_json::Value> = dump["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|node| node["lines"].as_array())
+        .flatten()
+        .collect();
+    assert!(lines.len() > 5, "{lines:?}");
+    for line in &lines {
+        let (x, y, width) = (
+            line["x"].as_f64().unwrap(),
+            line["y"].as_f64().unwrap(),
+            line["width"].as_f64().unwrap(),
+        );
+        if y < 60.0 {
+            assert_eq!((x, width), (100.0, 300.0), "{line}");
+        } else {
+            assert_eq!((x, width), (0.0, 400.0), "{line}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_clear_and_bfc_roots_contain_floats() {
+    let html = fs::read_to_string(golden_dir().join("fixtures/float_clear.html")).unwrap();
+    let dump = dump_fixture(&html).await;
+
+    // overflow: hidden grows to the tallest float.
+    let clearfix = &node_at(&dump, "div", 0)["bounds"];
+    assert_eq!(clearfix["height"].as_f64(), Some(80.0));
+    let right = &node_at(&dump, "div", 2)["bounds"];
+    assert_eq!(right["x"].as_f64(), Some(280.0));
+
+    let float = &node_at(&dump, "div", 4)["bounds"];
+    let cleared = &node_at(&dump, "div", 5)["bounds"];
+    assert!(
+        cleared["y"].as_f64().unwrap() >= float["y"].as_f64().unwrap() + 50.0,
+        "{cleared} vs {float}"
+    );
+}
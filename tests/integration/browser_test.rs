@@ -1,153 +1,6383 @@
-use vulkan_renderer::{BrowserEngine, Config};
-use tokio_test;
+#[cfg(feature = "test-util")]
+mod support;
 
 #[tokio::test]
 async fn test_full_page_rendering() {
-    let config = Config::default();
-    let mut engine = BrowserEngine::new(config, None).unwrap();
-    
-    let html = r#"
-        <!DOCTYPE html>
-        <html>
-        <head>
-            <title>Test Page</title>
-            <style>
-                body { font-family: Arial; margin: 20px; }
-                .container { background: #f0f0f0; padding: 20px; }
-                h1 { color: #333; }
-            </style>
-        </head>
-        <body>
-            <div class="container">
-                <h1>Hello World</h1>
-                <p>This is a test page for integration testing.</p>
-                <button onclick="alert('Clicked!')">Click Me</button>
-            </div>
-        </body>
-        </html>
-    "#;
-    
-    engine.load_html(html).await.unwrap();
-    
-    let render_result = engine.render_frame().await;
-    assert!(render_result.is_ok());
-    
-    let document = engine.get_document();
-    assert!(document.query_selector("h1").is_some());
-    assert!(document.query_selector(".container").is_some());
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<!DOCTYPE html><html><head><title>Test Page</title>\
+             <style>body { font-family: Arial; margin: 20px; } \
+             .container { background: rgb(240, 240, 240); padding: 20px; } h1 { color: rgb(51, 51, 51); }</style>\
+             </head><body><div class=container><h1>Hello World</h1>\
+             <p>This is a test page for integration testing.</p>\
+             <button onclick=\"alert('Clicked!')\">Click Me</button></div></body></html>",
+        )
+        .await
+        .unwrap();
+
+    assert!(engine.render_frame().await.is_ok());
+    assert_eq!(engine.get_page_title().await.as_deref(), Some("Test Page"));
+    let found = engine
+        .execute_javascript(
+            "[document.querySelector('h1') !== null, document.querySelector('.container') !== null]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(found, serde_json::json!([true, true]));
 }
 
 #[tokio::test]
 async fn test_javascript_execution() {
-    let config = Config::default();
-    let mut engine = BrowserEngine::new(config, None).unwrap();
-    
-    engine.load_html(r#"
-        <html>
-        <body>
-            <div id="target">Original</div>
-            <script>
-                document.getElementById('target').textContent = 'Modified by JS';
-                window.testVariable = 'Hello from JavaScript';
-            </script>
-        </body>
-        </html>
-    "#).await.unwrap();
-    
-    let result = engine.execute_script("window.testVariable").await.unwrap();
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body><div id=target>Original</div><script>\
+             document.getElementById('target').textContent = 'Modified by JS';\
+             window.testVariable = 'Hello from JavaScript';\
+             </script></body>",
+        )
+        .await
+        .unwrap();
+
+    let result = engine
+        .execute_javascript("window.testVariable")
+        .await
+        .unwrap();
     assert_eq!(result.as_str(), Some("Hello from JavaScript"));
-    
-    let target_element = engine.get_document().query_selector("#target").unwrap();
-    assert!(target_element.get_text_content().contains("Modified by JS"));
+    let text = engine
+        .execute_javascript("document.getElementById('target').textContent")
+        .await
+        .unwrap();
+    assert_eq!(text.as_str(), Some("Modified by JS"));
 }
 
 #[tokio::test]
 async fn test_css_styling_application() {
-    let config = Config::default();
-    let mut engine = BrowserEngine::new(config, None).unwrap();
-    
-    engine.load_html(r#"
-        <html>
-        <head>
-            <style>
-                .red { color: red; }
-                .large { font-size: 24px; }
-                #special { background: yellow; }
-            </style>
-        </head>
-        <body>
-            <div class="red large" id="special">Styled Text</div>
-        </body>
-        </html>
-    "#).await.unwrap();
-    
-    let styled_element = engine.get_document().query_selector("#special").unwrap();
-    let computed_style = engine.get_computed_style(&styled_element);
-    
-    assert_eq!(computed_style.get_property("color"), Some("red"));
-    assert_eq!(computed_style.get_property("font-size"), Some("24px"));
-    assert_eq!(computed_style.get_property("background"), Some("yellow"));
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>.red { color: red } .large { font-size: 24px } \
+             #special { background-color: yellow }</style>\
+             <div class=\"red large\" id=special>Styled Text</div>",
+        )
+        .await
+        .unwrap();
+
+    let styles = engine.dump_computed_styles("#special").await.unwrap();
+    assert_eq!(styles[0]["styles"]["color"], "#FF0000");
+    assert_eq!(styles[0]["styles"]["font-size"], "24px");
+    assert_eq!(styles[0]["styles"]["background-color"], "#FFFF00");
 }
 
 #[tokio::test]
 async fn test_form_interactions() {
-    let config = Config::default();
-    let mut engine = BrowserEngine::new(config, None).unwrap();
-    
-    engine.load_html(r#"
-        <html>
-        <body>
-            <form id="testForm">
-                <input type="text" id="username" name="username" value="">
-                <input type="password" id="password" name="password" value="">
-                <button type="submit">Submit</button>
-            </form>
-            <div id="result"></div>
-            <script>
-                document.getElementById('testForm').addEventListener('submit', function(e) {
-                    e.preventDefault();
-                    document.getElementById('result').textContent = 'Form submitted';
-                });
-            </script>
-        </body>
-        </html>
-    "#).await.unwrap();
-    
-    engine.simulate_input("#username", "testuser").await.unwrap();
-    engine.simulate_input("#password", "testpass").await.unwrap();
-    engine.simulate_click("button[type=submit]").await.unwrap();
-    
-    let result = engine.get_document().query_selector("#result").unwrap();
-    assert!(result.get_text_content().contains("Form submitted"));
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<form id=testForm>\
+             <input type=text id=username name=username value=\"\">\
+             <input type=password id=password name=password value=\"\">\
+             <button type=submit>Submit</button></form><div id=result></div><script>\
+             document.getElementById('testForm').addEventListener('submit', function (e) {\
+               e.preventDefault();\
+               document.getElementById('result').textContent = 'Form submitted';\
+             });</script>",
+        )
+        .await
+        .unwrap();
+
+    assert!(engine.focus_element("#username").await.unwrap());
+    engine
+        .execute_javascript(
+            "document.getElementById('username').value = 'testuser';\
+             document.getElementById('password').value = 'testpass';\
+             document.getElementById('testForm').requestSubmit(); 0",
+        )
+        .await
+        .unwrap();
+
+    let result = engine
+        .execute_javascript("document.getElementById('result').textContent")
+        .await
+        .unwrap();
+    assert_eq!(result.as_str(), Some("Form submitted"));
 }
 
+#[cfg(feature = "test-util")]
 #[tokio::test]
 async fn test_network_requests() {
-    let config = Config::default();
-    let mut engine = BrowserEngine::new(config, None).unwrap();
-    
-    engine.load_html(r#"
-        <html>
-        <body>
-            <div id="content">Loading...</div>
-            <script>
-                fetch('/api/data')
-                    .then(response => response.json())
-                    .then(data => {
-                        document.getElementById('content').textContent = data.message;
-                    })
-                    .catch(error => {
-                        document.getElementById('content').textContent = 'Error: ' + error.message;
-                    });
-            </script>
-        </body>
-        </html>
-    "#).await.unwrap();
-    
-    engine.mock_network_response("/api/data", 200, r#"{"message":"Hello from API"}"#).await;
-    
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-    
-    let content = engine.get_document().query_selector("#content").unwrap();
-    assert!(content.get_text_content().contains("Hello from API"));
-}
\ No newline at end of file
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::NetworkManager;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let config = BrowserConfig::default();
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://example.test/",
+        MockResponse::ok(
+            "text/html",
+            "<div id=content>Loading...</div><script>\
+             fetch('/api/data')\
+               .then((response) => response.json())\
+               .then((data) => { document.getElementById('content').textContent = data.message; })\
+               .catch((error) => { document.getElementById('content').textContent = 'Error: ' + error.message; })\
+               .finally(() => { globalThis.result = document.getElementById('content').textContent; });\
+             </script>",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://example.test/api/data",
+        MockResponse::ok("application/json", r#"{"message":"Hello from API"}"#),
+    );
+    let network = NetworkManager::with_transport(&config, mock).await.unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://example.test/").await.unwrap();
+
+    assert_eq!(
+        wait_for_result(&engine).await,
+        serde_json::json!("Hello from API")
+    );
+}
+
+#[tokio::test]
+async fn test_disabled_pwa_is_reported_as_feature_disabled() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        engine.install_pwa("https://example.com/manifest.json").await,
+        Err(BrowserError::FeatureDisabled("pwa"))
+    ));
+    assert!(engine.get_manifest_url().await.is_none());
+}
+
+#[tokio::test]
+async fn test_disabled_chrome_apis_and_jit() {
+    use vulkan_browser_engine::js_engine::v8_binding::V8Runtime;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_chrome_apis: false,
+        enable_jit: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        engine.enable_chrome_api("serial").await,
+        Err(BrowserError::FeatureDisabled("chrome_apis"))
+    ));
+    assert!(!engine.is_chrome_api_enabled("serial").await);
+    // V8 is set up once per process, by whichever engine came first; the
+    // metrics tell how it was.
+    assert_eq!(
+        engine.get_performance_metrics().await.javascript.jit_enabled,
+        !V8Runtime::is_jitless()
+    );
+}
+
+#[tokio::test]
+async fn test_chrome_apis_do_not_outlive_the_page() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url("data:text/html,<p>one</p>").await.unwrap();
+    engine.enable_chrome_api("serial").await.unwrap();
+    assert!(engine.is_chrome_api_enabled("serial").await);
+
+    engine.load_url("data:text/html,<p>two</p>").await.unwrap();
+    assert!(!engine.is_chrome_api_enabled("serial").await);
+}
+
+#[tokio::test]
+async fn test_disabled_gpu_acceleration_never_initializes_vulkan() {
+    use raw_window_handle::{
+        RawDisplayHandle, RawWindowHandle, XlibDisplayHandle, XlibWindowHandle,
+    };
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine.load_url("data:text/html,<p>cpu</p>").await.unwrap();
+
+    // Refused before the handles are looked at, so made-up ones do.
+    let attached = unsafe {
+        engine
+            .attach_window(
+                RawDisplayHandle::Xlib(XlibDisplayHandle::new(None, 0)),
+                RawWindowHandle::Xlib(XlibWindowHandle::new(1)),
+            )
+            .await
+    };
+    assert!(matches!(
+        attached,
+        Err(BrowserError::FeatureDisabled("gpu_acceleration"))
+    ));
+    // With no window, frames are painted in software alone.
+    engine.render_frame().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_view_source_does_not_execute_scripts() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .view_source("data:text/html,<script>globalThis.ran = 1</script>")
+        .await
+        .unwrap();
+
+    let ran = engine
+        .execute_javascript("typeof globalThis.ran")
+        .await
+        .unwrap();
+    assert_eq!(ran, serde_json::json!("undefined"));
+    assert_eq!(
+        engine.get_current_url().await.as_deref(),
+        Some("view-source:data:text/html,<script>globalThis.ran = 1</script>")
+    );
+}
+
+#[tokio::test]
+async fn test_event_log_evicts_oldest_and_filters_by_seq() {
+    use vulkan_browser_engine::core::event_log::{
+        EventKindMask, EventLog, LoggedEvent, NavigationPhase, TimestampedEvent,
+    };
+    use vulkan_browser_engine::BrowserEvent;
+
+    let log = EventLog::new(4);
+    for i in 0..10 {
+        let event = if i % 2 == 0 {
+            LoggedEvent::Browser {
+                event: BrowserEvent::NavigationStarted {
+                    url: format!("https://example.com/{i}"),
+                },
+            }
+        } else {
+            LoggedEvent::NavigationPhase {
+                url: format!("https://example.com/{i}"),
+                phase: NavigationPhase::Fetch,
+            }
+        };
+        assert_eq!(log.record(event), i + 1);
+    }
+
+    let seqs = |events: Vec<TimestampedEvent>| events.iter().map(|e| e.seq).collect::<Vec<_>>();
+    assert_eq!(log.len(), 4);
+    assert_eq!(seqs(log.recent(None, None)), vec![7, 8, 9, 10]);
+    assert_eq!(seqs(log.recent(Some(8), None)), vec![9, 10]);
+    assert_eq!(seqs(log.recent(Some(2), None)), vec![7, 8, 9, 10]);
+    assert!(log.recent(Some(10), None).is_empty());
+    assert_eq!(
+        seqs(log.recent(None, Some(EventKindMask::NAVIGATION_STARTED))),
+        vec![7, 9]
+    );
+
+    // Clearing keeps numbering going so stale cursors don't replay.
+    log.clear();
+    assert!(log.is_empty());
+    let next = LoggedEvent::NavigationPhase {
+        url: "https://example.com/".to_string(),
+        phase: NavigationPhase::Render,
+    };
+    assert_eq!(log.record(next), 11);
+}
+
+#[tokio::test]
+async fn test_navigation_is_recorded_in_event_log() {
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p>hello</p>")
+        .await
+        .unwrap();
+
+    let events = engine.get_recent_events(None, Some(EventKindMask::NAVIGATION));
+    let json = serde_json::to_value(&events).unwrap();
+    let phases: Vec<_> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            e["phase"]
+                .as_str()
+                .or_else(|| e["event"]["type"].as_str())
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(
+        phases,
+        [
+            "navigation_started",
+            "fetch",
+            "parse",
+            "style_and_layout",
+            "scripts",
+            "render",
+            "page_loaded"
+        ]
+    );
+
+    let last = events.last().unwrap().seq;
+    assert!(engine.get_recent_events(Some(last), None).is_empty());
+    engine.clear_recent_events();
+    assert!(engine.dump_recent_events()["events"]
+        .as_array()
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_subscribers_receive_events_as_they_happen() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let mut rx = engine.subscribe_events();
+    // Nobody listening must not hold the engine up.
+    drop(engine.subscribe_events());
+    engine
+        .load_url("data:text/html,<p>hello</p>")
+        .await
+        .unwrap();
+
+    let first = rx.recv().await.unwrap();
+    assert!(
+        matches!(&first, BrowserEvent::NavigationStarted { url } if url == "data:text/html,<p>hello</p>"),
+        "{first:?}"
+    );
+    let mut progress = Vec::new();
+    loop {
+        match rx.recv().await.unwrap() {
+            BrowserEvent::LoadProgress { bytes, total, .. } => progress.push((bytes, total)),
+            BrowserEvent::PageLoaded { url, .. } => {
+                assert_eq!(url, "data:text/html,<p>hello</p>");
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(progress, [(12, Some(12))]);
+
+    // A late subscriber sees only what happens after it subscribed.
+    let mut late = engine.subscribe_events();
+    assert!(late.try_recv().is_err());
+    engine
+        .load_url("data:text/html,<p>again</p>")
+        .await
+        .unwrap();
+    assert!(matches!(
+        late.recv().await.unwrap(),
+        BrowserEvent::NavigationStarted { .. }
+    ));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_load_progress_is_throttled_and_kept_out_of_the_log() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::broadcast::Receiver;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let page = format!("<p>{}</p>", "x".repeat(9_993));
+    let response = MockResponse::ok("text/html", page.clone())
+        .header("Cache-Control", "no-store")
+        .chunked(10, Duration::ZERO);
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://progress.test/plain",
+        response
+            .clone()
+            .header("Content-Length", &page.len().to_string()),
+    );
+    mock.route(
+        "GET",
+        "http://progress.test/gzip",
+        response
+            .header("Content-Length", "512")
+            .header("Content-Encoding", "gzip"),
+    );
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+
+    let mut rx = engine.subscribe_events();
+    async fn progress(
+        engine: &BrowserEngine,
+        rx: &mut Receiver<BrowserEvent>,
+        url: &str,
+    ) -> Vec<(u64, Option<u64>)> {
+        engine.load_url(url).await.unwrap();
+        std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|event| match event {
+                BrowserEvent::LoadProgress { bytes, total, .. } => Some((bytes, total)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // A thousand chunks: the first, one per percent after it, and the last.
+    let plain = progress(&engine, &mut rx, "http://progress.test/plain").await;
+    assert_eq!(plain.len(), 101, "{plain:?}");
+    assert_eq!(plain[0], (10, Some(10_000)));
+    assert_eq!(plain.last(), Some(&(10_000, Some(10_000))));
+
+    // The encoded length is not the length of what is counted.
+    let gzip = progress(&engine, &mut rx, "http://progress.test/gzip").await;
+    assert_eq!(gzip, [(10, None)]);
+
+    assert!(engine
+        .get_recent_events(None, Some(EventKindMask::LOAD_PROGRESS))
+        .is_empty());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_slow_animation_frames_are_reported_as_jank() {
+    use vulkan_browser_engine::core::frame_budget::FramePhase;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let host = spawn_page_host(&[
+        (
+            "/",
+            "text/html",
+            b"<p>spin</p><script src=/spin.js></script>",
+        ),
+        (
+            "/spin.js",
+            "text/javascript",
+            b"requestAnimationFrame(() => {\
+                const end = performance.now() + 40;\
+                while (performance.now() < end) {}\
+              });",
+        ),
+    ])
+    .await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    let mut events = engine.subscribe_events();
+    engine.tick().await.unwrap();
+
+    let jank = std::iter::from_fn(|| events.try_recv().ok())
+        .find_map(|event| match event {
+            BrowserEvent::FrameJank {
+                total_ms,
+                phases,
+                worst_offender,
+                script_url,
+            } => Some((total_ms, phases, worst_offender, script_url)),
+            _ => None,
+        })
+        .expect("no FrameJank");
+    let (total_ms, phases, worst_offender, script_url) = jank;
+    assert!(total_ms >= 40.0, "{total_ms}");
+    assert_eq!(worst_offender, FramePhase::AnimationFrames);
+    assert_eq!(script_url, Some(format!("{}/spin.js", host)));
+    let sum: f64 = phases.iter().map(|(_, ms)| ms).sum();
+    assert!((sum - total_ms).abs() < 0.01, "{phases:?} vs {total_ms}");
+
+    // A quiet frame is not jank.
+    engine.tick().await.unwrap();
+    let stats = engine.get_frame_statistics();
+    assert_eq!(stats.frames, 2);
+    assert_eq!(stats.jank_frames, 1);
+    assert!(stats.p99_ms >= 40.0);
+    let metrics = engine.get_performance_metrics().await;
+    assert_eq!(metrics.renderer.jank_frames, 1);
+}
+
+#[test]
+fn test_frame_statistics_aggregate_over_the_window() {
+    use std::time::Duration;
+    use vulkan_browser_engine::core::frame_budget::{FrameBudgetConfig, FramePhase, FrameWatchdog};
+
+    let watchdog = FrameWatchdog::new(&FrameBudgetConfig {
+        target_fps: 50.0,
+        window: 100,
+    });
+    assert_eq!(watchdog.budget(), Duration::from_millis(20));
+    // Layout has frames of 1 to 100ms take 1 to 100ms; only those past
+    // 20ms are jank.
+    let mut janks = Vec::new();
+    for ms in 1..=100u64 {
+        watchdog.begin_frame();
+        watchdog.record(FramePhase::Layout, Duration::from_millis(ms));
+        janks.extend(watchdog.end_frame(Duration::from_millis(ms)));
+    }
+    assert_eq!(janks.len(), 80);
+    assert!(janks
+        .iter()
+        .all(|jank| jank.worst_offender == FramePhase::Layout));
+    assert_eq!(janks[0].phases, [(FramePhase::Layout, 21.0)]);
+
+    let stats = watchdog.statistics();
+    assert_eq!(stats.frames, 100);
+    assert_eq!(stats.jank_frames, 80);
+    assert_eq!(stats.budget_ms, 20.0);
+    assert_eq!(stats.p95_ms, 95.0);
+    assert_eq!(stats.p99_ms, 99.0);
+    let p95 = |phase| {
+        stats
+            .phase_p95_ms
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map(|(_, ms)| *ms)
+            .unwrap()
+    };
+    assert_eq!(p95(FramePhase::Layout), 95.0);
+    assert_eq!(p95(FramePhase::Style), 0.0);
+
+    // Input between frames counts toward the next; layout outside a frame
+    // does not.
+    watchdog.record(FramePhase::InputDispatch, Duration::from_millis(30));
+    watchdog.record(FramePhase::Layout, Duration::from_millis(500));
+    watchdog.begin_frame();
+    watchdog.record(FramePhase::Timers, Duration::from_millis(2));
+    let jank = watchdog.end_frame(Duration::from_millis(5)).unwrap();
+    assert_eq!(jank.worst_offender, FramePhase::InputDispatch);
+    assert_eq!(jank.total_ms, 35.0);
+    assert_eq!(
+        jank.phases,
+        [
+            (FramePhase::InputDispatch, 30.0),
+            (FramePhase::Timers, 2.0),
+            (FramePhase::Other, 3.0)
+        ]
+    );
+    // The window keeps the last 100 frames.
+    assert_eq!(watchdog.statistics().frames, 101);
+    assert_eq!(watchdog.statistics().p99_ms, 99.0);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_user_agent_set_mid_session_is_sent() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    // The client sets the header itself, so this needs a real server.
+    let mock = Arc::new(MockTransport::new());
+    let host = support::serve(mock.clone()).await;
+    mock.route_fn("GET", &format!("{host}/*"), |request| {
+        let user_agent = request.header("User-Agent").unwrap_or_default();
+        MockResponse::ok("text/html", format!("<p id=ua>{}</p>", user_agent))
+            .header("Cache-Control", "no-store")
+    });
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        user_agent: "FirstAgent/1.0".to_string(),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let engine = &engine;
+    let shown = move || async move {
+        engine
+            .execute_javascript("document.getElementById('ua').textContent")
+            .await
+            .unwrap()
+    };
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    assert_eq!(shown().await, serde_json::json!("FirstAgent/1.0"));
+
+    engine.set_user_agent("SecondAgent/2.0").await.unwrap();
+    assert_eq!(engine.user_agent(), "SecondAgent/2.0");
+    assert_eq!(engine.config().user_agent, "SecondAgent/2.0");
+    engine.load_url(&format!("{}/again", host)).await.unwrap();
+    assert_eq!(shown().await, serde_json::json!("SecondAgent/2.0"));
+
+    assert!(engine.set_user_agent(" ").await.is_err());
+    assert!(engine.set_user_agent("Bad\r\nAgent").await.is_err());
+    assert_eq!(engine.user_agent(), "SecondAgent/2.0");
+}
+
+#[tokio::test]
+async fn test_removed_nodes_are_reclaimed_after_gc() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url("data:text/html,<p>gc</p>").await.unwrap();
+    engine.collect_garbage().await.unwrap();
+    let baseline = engine.get_performance_metrics().await.javascript;
+
+    engine
+        .execute_javascript(
+            "for (let i = 0; i < 100000; i++) {
+               const div = document.createElement('div');
+               div.setAttribute('data-index', String(i));
+               document.appendChild(div);
+               document.removeChild(div);
+             }",
+        )
+        .await
+        .unwrap();
+    let grown = engine.get_performance_metrics().await.javascript;
+    assert!(grown.dom_nodes >= baseline.dom_nodes + 100_000);
+
+    engine.collect_garbage().await.unwrap();
+    let after = engine.get_performance_metrics().await.javascript;
+    assert_eq!(after.dom_nodes, baseline.dom_nodes);
+    assert_eq!(after.live_dom_wrappers, baseline.live_dom_wrappers);
+    assert_eq!(after.detached_referenced_nodes, 0);
+    assert_eq!(
+        after.reclaimed_dom_nodes - baseline.reclaimed_dom_nodes,
+        100_000
+    );
+}
+
+#[tokio::test]
+async fn test_detached_node_held_by_script_survives_gc_and_reinserts() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url("data:text/html,<p>keep</p>").await.unwrap();
+    engine
+        .execute_javascript(
+            "globalThis.kept = document.createElement('section');
+             kept.setAttribute('data-state', 'detached');
+             document.appendChild(kept);
+             kept.remove();",
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(engine.collect_garbage().await.unwrap(), 0);
+    let metrics = engine.get_performance_metrics().await.javascript;
+    assert_eq!(metrics.detached_referenced_nodes, 1);
+    assert!(metrics.live_dom_wrappers >= 1);
+
+    let state = engine
+        .execute_javascript(
+            "document.appendChild(kept);
+             [kept.getAttribute('data-state'), kept.parentNode === document]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(state, serde_json::json!(["detached", true]));
+}
+
+/// Serve `pages`, each a path, its content type and body, on a local port;
+/// other paths get an empty HTML page. Returns the origin to load them from.
+#[cfg(feature = "test-util")]
+async fn spawn_page_host(pages: &[(&str, &str, &[u8])]) -> String {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = std::sync::Arc::new(MockTransport::new());
+    let host = support::serve(mock.clone()).await;
+    mock.route(
+        "*",
+        &format!("{host}/*"),
+        MockResponse::ok("text/html", "<html></html>").header("Cache-Control", "no-store"),
+    );
+    for (path, content_type, body) in pages {
+        mock.route(
+            "*",
+            &format!("{host}{path}"),
+            MockResponse::ok(content_type, body.to_vec()).header("Cache-Control", "no-store"),
+        );
+    }
+    host
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_wrappers_of_previous_document_throw_after_navigation() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(&[
+        ("/first", "text/html", b"<p>first</p>"),
+        ("/second", "text/html", b"<p>second</p>"),
+    ])
+    .await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/first", host)).await.unwrap();
+    engine
+        .execute_javascript("globalThis.stale = document.createElement('div'); 0")
+        .await
+        .unwrap();
+
+    // Same origin, so the same agent and globals.
+    engine.load_url(&format!("{}/second", host)).await.unwrap();
+    let message = engine
+        .execute_javascript("try { stale.getAttribute('id'); 'no error' } catch (e) { e.message }")
+        .await
+        .unwrap();
+    assert_eq!(
+        message,
+        serde_json::json!("node belongs to a destroyed document")
+    );
+
+    let fresh = engine
+        .execute_javascript("document.createElement('div').getAttribute('id')")
+        .await
+        .unwrap();
+    assert_eq!(fresh, serde_json::Value::Null);
+
+    // Another origin gets another agent, which never saw `stale`.
+    engine
+        .load_url("data:text/html,<p>third</p>")
+        .await
+        .unwrap();
+    let leftover = engine.execute_javascript("typeof stale").await.unwrap();
+    assert_eq!(leftover, serde_json::json!("undefined"));
+}
+
+#[tokio::test]
+async fn test_private_mode_local_storage_does_not_outlive_the_engine() {
+    use vulkan_browser_engine::core::storage::{StorageConfig, StorageKey};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = |private_mode| BrowserConfig {
+        storage: StorageConfig {
+            directory: Some(dir.path().to_path_buf()),
+            partition_third_party: false,
+        },
+        private_mode,
+        ..Default::default()
+    };
+    let key = StorageKey::first_party("https://example.com");
+
+    let engine = BrowserEngine::new(config(false)).await.unwrap();
+    engine
+        .web_storage()
+        .local(&key)
+        .set("theme", "dark")
+        .unwrap();
+    engine.shutdown().await.unwrap();
+
+    let engine = BrowserEngine::new(config(true)).await.unwrap();
+    assert!(engine.is_private());
+    assert_eq!(engine.web_storage().local(&key).get("theme"), None);
+    engine
+        .web_storage()
+        .local(&key)
+        .set("session", "abc")
+        .unwrap();
+    engine
+        .load_url("data:text/html,<p>private</p>")
+        .await
+        .unwrap();
+    let result = engine
+        .execute_javascript(
+            "localStorage.setItem('a', '1'); \
+             [localStorage.getItem('a'), localStorage.a, localStorage.length, navigator.cookieEnabled]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(result, serde_json::json!(["1", "1", 1, true]));
+    engine.shutdown().await.unwrap();
+
+    let engine = BrowserEngine::new(config(false)).await.unwrap();
+    let local = engine.web_storage().local(&key);
+    assert_eq!(local.get("theme").as_deref(), Some("dark"));
+    assert_eq!(local.get("session"), None);
+}
+
+#[tokio::test]
+async fn test_third_party_storage_is_partitioned_by_top_level_site() {
+    use vulkan_browser_engine::core::storage::{StorageConfig, WebStorage};
+
+    let top_a = url::Url::parse("https://news.example/article").unwrap();
+    let top_b = url::Url::parse("https://shop.example/cart").unwrap();
+    let frame = url::Url::parse("https://tracker.example/frame.html").unwrap();
+
+    let storage = |partition_third_party| {
+        WebStorage::new(
+            &StorageConfig {
+                directory: None,
+                partition_third_party,
+            },
+            false,
+        )
+    };
+
+    let partitioned = storage(true);
+    let under_a = partitioned.key_for(&top_a, &frame);
+    let under_b = partitioned.key_for(&top_b, &frame);
+    assert!(under_a.is_partitioned());
+    assert_ne!(under_a, under_b);
+    partitioned.local(&under_a).set("id", "42").unwrap();
+    // Another page on the same top-level site shares the bucket.
+    let again = url::Url::parse("https://news.example/other").unwrap();
+    let under_a_again = partitioned.key_for(&again, &frame);
+    assert_eq!(
+        partitioned.local(&under_a_again).get("id").as_deref(),
+        Some("42")
+    );
+    assert_eq!(partitioned.local(&under_b).get("id"), None);
+    assert_eq!(partitioned.session(&under_b).get("id"), None);
+
+    let shared = storage(false);
+    let under_a = shared.key_for(&top_a, &frame);
+    let under_b = shared.key_for(&top_b, &frame);
+    assert!(!under_a.is_partitioned());
+    shared.local(&under_a).set("id", "42").unwrap();
+    assert_eq!(shared.local(&under_b).get("id").as_deref(), Some("42"));
+}
+
+#[tokio::test]
+async fn test_print_to_pdf_applies_print_media_and_page_size() {
+    use vulkan_browser_engine::core::print::PrintOptions;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>@page { size: A4; margin: 1cm } \
+             @media print { .screen-only { display: none } }</style>\
+             <p>Printed paragraph</p><nav class=\"screen-only\">Site menu</nav>",
+        )
+        .await
+        .unwrap();
+
+    let pdf = engine.print_to_pdf(PrintOptions::default()).await.unwrap();
+    let pdf = String::from_utf8_lossy(&pdf);
+    assert!(pdf.starts_with("%PDF-1.4"));
+    assert!(pdf.contains("/MediaBox [0 0 595.28 841.89]"));
+    assert!(pdf.contains("(Printed paragraph)"));
+    assert!(!pdf.contains("Site menu"));
+
+    // Screen styles are back once printing is done.
+    let nav = engine.dump_computed_styles(".screen-only").await.unwrap();
+    assert_ne!(nav[0]["styles"]["display"], "none");
+}
+
+#[tokio::test]
+async fn test_window_print_fires_beforeprint_and_afterprint_around_the_embedder() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::print::{PrintDecision, PrintOptions};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p>receipt</p>")
+        .await
+        .unwrap();
+    engine
+        .execute_javascript(
+            "globalThis.seen = []; \
+             addEventListener('beforeprint', () => seen.push('before')); \
+             addEventListener('afterprint', () => seen.push('after')); \
+             print(); print(); 0",
+        )
+        .await
+        .unwrap();
+
+    let requests: Vec<u64> = engine
+        .get_recent_events(None, Some(EventKindMask::PRINT_REQUESTED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::PrintRequested { request_id },
+            } => Some(request_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(requests.len(), 1);
+    let seen = engine.execute_javascript("seen").await.unwrap();
+    assert_eq!(seen, serde_json::json!(["before"]));
+
+    let pdf = engine
+        .resolve_print_request(requests[0], PrintDecision::Print(PrintOptions::default()))
+        .await
+        .unwrap();
+    assert!(pdf.unwrap().starts_with(b"%PDF"));
+    let seen = engine.execute_javascript("seen").await.unwrap();
+    assert_eq!(seen, serde_json::json!(["before", "after"]));
+    assert!(engine
+        .resolve_print_request(requests[0], PrintDecision::Dismiss)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_outer_html_and_xml_serializer_reflect_script_changes() {
+    use vulkan_browser_engine::core::dom::{DomSource, SerializeOptions};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<!DOCTYPE html><html><body><p id=greeting>hi</p></body></html>")
+        .await
+        .unwrap();
+    let markup = engine
+        .execute_javascript(
+            "const p = document.getElementById('greeting'); \
+             p.setAttribute('class', 'a<b'); \
+             [p.outerHTML, document.documentElement.outerHTML, \
+              new XMLSerializer().serializeToString(p)]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        markup,
+        serde_json::json!([
+            "<p class=\"a&lt;b\" id=\"greeting\">hi</p>",
+            "<html><body><p class=\"a&lt;b\" id=\"greeting\">hi</p></body></html>",
+            "<p xmlns=\"http://www.w3.org/1999/xhtml\" class=\"a&lt;b\" id=\"greeting\">hi</p>",
+        ])
+    );
+
+    let current = engine
+        .serialize_document(SerializeOptions::default())
+        .await
+        .unwrap();
+    assert!(current.contains("class=\"a&lt;b\""));
+    let as_parsed = engine
+        .serialize_document(SerializeOptions {
+            source: DomSource::AsParsed,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        as_parsed,
+        "<!DOCTYPE html><html><body><p id=\"greeting\">hi</p></body></html>"
+    );
+}
+
+#[tokio::test]
+async fn test_serialization_leaves_out_ime_composition() {
+    use vulkan_browser_engine::core::dom::SerializeOptions;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, ImeCompositionState, InputEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<div id=box contenteditable>ab</div>")
+        .await
+        .unwrap();
+    assert!(engine.focus_element("#box").await.unwrap());
+    for state in [
+        ImeCompositionState::Start,
+        ImeCompositionState::Update {
+            text: "か".to_string(),
+            cursor_range: Some((3, 3)),
+        },
+    ] {
+        engine
+            .handle_input_event(InputEvent::ImeComposition { state })
+            .await
+            .unwrap();
+    }
+
+    let markup = engine
+        .serialize_document(SerializeOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(markup, "<div contenteditable=\"\" id=\"box\">ab</div>");
+}
+
+/// An 8×4 PNG.
+const POSTER_PNG: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAgAAAAECAIAAAA8r+mnAAAAEklEQVR4nGP4z8CAFWEXJUsCAFpeH+EeQoQoAAAAAElFTkSuQmCC";
+
+fn video_boxes(tree: &serde_json::Value) -> Vec<serde_json::Value> {
+    tree.as_array()
+        .unwrap()
+        .iter()
+        .filter(|node| node["tag"] == "video")
+        .cloned()
+        .collect()
+}
+
+#[tokio::test]
+async fn test_video_sizes_from_attributes_then_poster_then_default() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(&format!(
+            "data:text/html,<video></video><video width=200></video>\
+             <video poster=\"{POSTER_PNG}\"></video>\
+             <video width=40 height=30 poster=\"{POSTER_PNG}\"></video><audio src=a.mp3></audio>"
+        ))
+        .await
+        .unwrap();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    let videos = loop {
+        engine.tick().await.unwrap();
+        let videos = video_boxes(&engine.dump_layout_tree().await);
+        if videos[2]["type"] == "image" || std::time::Instant::now() > deadline {
+            break videos;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    };
+    let sizes: Vec<(serde_json::Value, serde_json::Value)> = videos
+        .iter()
+        .map(|video| {
+            (
+                video["bounds"]["width"].clone(),
+                video["bounds"]["height"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        sizes,
+        vec![
+            (300.0.into(), 150.0.into()),
+            (200.0.into(), 100.0.into()),
+            (8.0.into(), 4.0.into()),
+            (40.0.into(), 30.0.into()),
+        ]
+    );
+
+    // The poster paints in the box; without one nothing is drawn there.
+    assert_eq!(videos[0]["type"], "block");
+    assert_eq!(videos[2]["type"], "image");
+    assert_eq!(videos[2]["image"], POSTER_PNG);
+    assert_eq!(videos[3]["image"], POSTER_PNG);
+    // `<audio>` without controls takes no space.
+    let tree = engine.dump_layout_tree().await;
+    assert!(tree
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|node| node["tag"] != "audio"));
+}
+
+#[tokio::test]
+async fn test_media_play_rejects_without_a_playback_handler() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    assert!(matches!(
+        engine.set_media_playback_handler(Some(|_| true)),
+        Err(BrowserError::FeatureDisabled(_))
+    ));
+    engine
+        .load_url(
+            "data:text/html,<video id=v></video><script>\
+             const v = document.getElementById('v');\
+             globalThis.before = [v.paused, v.readyState, v.networkState, v.error];\
+             v.play().then(() => { globalThis.outcome = 'played'; },\
+               (e) => { globalThis.outcome = [e.name, v.paused]; });\
+             v.currentTime = -4;\
+             </script>",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    let seen = engine
+        .execute_javascript("[before, outcome, document.getElementById('v').currentTime]")
+        .await
+        .unwrap();
+    assert_eq!(
+        seen,
+        serde_json::json!([[true, 0, 0, null], ["NotSupportedError", true], 0])
+    );
+}
+
+#[tokio::test]
+async fn test_media_play_goes_to_the_embedder_when_enabled() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::media::{MediaConfig, PlaybackRequest};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        media: MediaConfig {
+            external_playback: true,
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    engine
+        .set_media_playback_handler(Some(move |request: PlaybackRequest| {
+            seen.lock().unwrap().push(request.src.to_string());
+            true
+        }))
+        .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<audio id=a src=\"http://127.0.0.1:9/song.mp3\"></audio><script>\
+             document.getElementById('a').play().then(() => { globalThis.outcome = 'handed off'; });\
+             </script>",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    assert_eq!(
+        engine.execute_javascript("outcome").await.unwrap(),
+        "handed off"
+    );
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec!["http://127.0.0.1:9/song.mp3".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn test_required_input_blocks_submit_and_matches_invalid_until_filled() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>input:invalid { color: red } input:valid { color: green }</style>\
+             <form id=f><input id=email name=email required></form><script>\
+             globalThis.submitted = 0;\
+             document.getElementById('f').addEventListener('submit', () => submitted++);\
+             document.getElementById('email').addEventListener('invalid', (e) => { globalThis.invalidFired = e.type; });\
+             </script>",
+        )
+        .await
+        .unwrap();
+    let styles = engine.dump_computed_styles("#email").await.unwrap();
+    assert_eq!(styles[0]["styles"]["color"], "#FF0000");
+
+    let outcome = engine
+        .execute_javascript(
+            "document.getElementById('f').requestSubmit(); \
+             [submitted, invalidFired, document.getElementById('email').validity.valueMissing, \
+              document.getElementById('f').checkValidity()]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome, serde_json::json!([0, "invalid", true, false]));
+    let messages: Vec<String> = engine
+        .get_recent_events(None, Some(EventKindMask::VALIDATION_MESSAGE))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::ValidationMessage { message, .. },
+            } => Some(message),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(messages, vec!["Please fill out this field.".to_string()]);
+    // The control is focused for the user to fix.
+    assert!(engine.caret_rect().await.is_some());
+
+    let outcome = engine
+        .execute_javascript(
+            "document.getElementById('email').value = 'me@example.com'; \
+             document.getElementById('f').requestSubmit(); \
+             [submitted, document.getElementById('email').validity.valid]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome, serde_json::json!([1, true]));
+    let styles = engine.dump_computed_styles("#email").await.unwrap();
+    assert_eq!(styles[0]["styles"]["color"], "#008000");
+}
+
+#[tokio::test]
+async fn test_custom_validity_forces_invalid_until_cleared() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>input:invalid { color: red }</style>\
+             <form id=f><input id=name value=taken></form><script>\
+             globalThis.submitted = 0;\
+             document.getElementById('f').addEventListener('submit', () => submitted++);\
+             </script>",
+        )
+        .await
+        .unwrap();
+
+    let outcome = engine
+        .execute_javascript(
+            "document.getElementById('name').setCustomValidity('x'); \
+             document.getElementById('f').requestSubmit(); \
+             [submitted, document.getElementById('name').validity.customError, \
+              document.getElementById('name').validationMessage, \
+              document.getElementById('name').checkValidity()]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome, serde_json::json!([0, true, "x", false]));
+    let styles = engine.dump_computed_styles("#name").await.unwrap();
+    assert_eq!(styles[0]["styles"]["color"], "#FF0000");
+
+    let outcome = engine
+        .execute_javascript(
+            "document.getElementById('name').setCustomValidity(''); \
+             document.getElementById('f').requestSubmit(); \
+             [submitted, document.getElementById('name').checkValidity()]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(outcome, serde_json::json!([1, true]));
+    let styles = engine.dump_computed_styles("#name").await.unwrap();
+    assert_ne!(styles[0]["styles"]["color"], "#FF0000");
+}
+
+#[tokio::test]
+async fn test_form_values_are_sanitized_and_novalidate_skips_validation() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<form id=f>\
+             <input id=n type=number value=12abc>\
+             <input id=q type=number min=1 max=10 step=2 value=4>\
+             <input id=e type=email value=\" me@example.com \">\
+             <input id=p pattern=\"[a-z]+\" value=abc1>\
+             <button id=skip formnovalidate>Save draft</button></form><script>\
+             globalThis.submitted = 0;\
+             document.getElementById('f').addEventListener('submit', () => submitted++);\
+             </script>",
+        )
+        .await
+        .unwrap();
+
+    let outcome = engine
+        .execute_javascript(
+            "const byId = (id) => document.getElementById(id); \
+             [byId('n').value, byId('n').validity.badInput, byId('q').validity.stepMismatch, \
+              byId('e').value, byId('e').validity.typeMismatch, byId('p').validity.patternMismatch]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        outcome,
+        serde_json::json!(["", true, true, "me@example.com", false, true])
+    );
+
+    let submitted = engine
+        .execute_javascript(
+            "byId('f').requestSubmit(); byId('f').requestSubmit(byId('skip')); submitted",
+        )
+        .await
+        .unwrap();
+    assert_eq!(submitted, 1);
+}
+
+#[tokio::test]
+async fn test_performance_metrics_break_down_per_tab() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, ContextKind};
+
+    let heavy = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let light = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let paragraphs = "<p>filler</p>".repeat(500);
+    heavy
+        .load_url(&format!("data:text/html,<body>{}</body>", paragraphs))
+        .await
+        .unwrap();
+    light
+        .load_url("data:text/html,<body><p>hello</p></body>")
+        .await
+        .unwrap();
+
+    let heavy_metrics = heavy.get_performance_metrics().await;
+    let light_metrics = light.get_performance_metrics().await;
+    assert_ne!(heavy.tab_id(), light.tab_id());
+
+    for metrics in [&heavy_metrics, &light_metrics] {
+        assert_eq!(metrics.contexts.len(), 1);
+        assert_eq!(metrics.contexts[0].context.kind, ContextKind::Document);
+        let dom_nodes: u64 = metrics.contexts.iter().map(|c| c.dom_nodes).sum();
+        let layout_boxes: u64 = metrics.contexts.iter().map(|c| c.layout_boxes).sum();
+        assert_eq!(metrics.javascript.dom_nodes, dom_nodes);
+        assert_eq!(metrics.layout.nodes_count as u64, layout_boxes);
+    }
+    assert_eq!(heavy_metrics.contexts[0].context.tab, heavy.tab_id());
+    assert_eq!(light_metrics.contexts[0].context.tab, light.tab_id());
+    assert!(heavy_metrics.contexts[0].dom_nodes > light_metrics.contexts[0].dom_nodes + 400);
+    assert!(
+        heavy_metrics.contexts[0].dom_bytes_estimate > light_metrics.contexts[0].dom_bytes_estimate
+    );
+}
+
+#[tokio::test]
+async fn test_dragging_between_elements_carries_the_data_transfer() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=src draggable=true style=\"height:50px\">Drag me</div>\
+             <div id=dst style=\"height:50px\">Drop here</div><script>\
+             globalThis.seen = [];\
+             const src = document.getElementById('src');\
+             const dst = document.getElementById('dst');\
+             src.addEventListener('dragstart', (e) => {\
+               e.dataTransfer.setData('text/plain', 'moved text');\
+               e.dataTransfer.effectAllowed = 'move';\
+               seen.push('dragstart');\
+             });\
+             dst.addEventListener('dragenter', () => seen.push('dragenter'));\
+             dst.addEventListener('dragover', (e) => {\
+               seen.push('dragover:' + e.dataTransfer.getData('text/plain') + ':' + e.dataTransfer.types);\
+               e.preventDefault();\
+             });\
+             dst.addEventListener('drop', (e) => {\
+               e.preventDefault();\
+               seen.push('drop:' + e.dataTransfer.getData('text/plain') + ':' + e.dataTransfer.dropEffect);\
+             });\
+             src.addEventListener('dragend', (e) => seen.push('dragend:' + e.dataTransfer.dropEffect));\
+             </script>",
+        )
+        .await
+        .unwrap();
+
+    // Under the threshold nothing starts; past it the drag runs to `dst`.
+    for event in [
+        InputEvent::MouseDown {
+            x: 20,
+            y: 20,
+            button: 0,
+        },
+        InputEvent::MouseMove { x: 22, y: 21 },
+    ] {
+        engine.handle_input_event(event).await.unwrap();
+    }
+    assert!(engine.drag_image().await.is_none());
+    engine
+        .handle_input_event(InputEvent::MouseMove { x: 20, y: 75 })
+        .await
+        .unwrap();
+    let (_, ghost) = engine.drag_image().await.unwrap();
+    assert!(ghost.width > 0.0);
+    engine
+        .handle_input_event(InputEvent::MouseUp {
+            x: 20,
+            y: 75,
+            button: 0,
+        })
+        .await
+        .unwrap();
+    assert!(engine.drag_image().await.is_none());
+
+    let seen = engine.execute_javascript("seen").await.unwrap();
+    assert_eq!(
+        seen,
+        serde_json::json!([
+            "dragstart",
+            "dragenter",
+            "dragover::text/plain",
+            "drop:moved text:move",
+            "dragend:move"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_dropped_files_are_readable_by_the_page() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let dir = std::env::temp_dir().join(format!("vbe-file-drop-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "dropped contents").unwrap();
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=zone style=\"height:100px\">Drop files here</div><script>\
+             const zone = document.getElementById('zone');\
+             zone.addEventListener('dragover', (e) => e.preventDefault());\
+             zone.addEventListener('drop', (e) => {\
+               e.preventDefault();\
+               const file = e.dataTransfer.files[0];\
+               globalThis.meta = [file.name, file.size, file.type, e.dataTransfer.types.join()];\
+               file.text().then((text) => { globalThis.contents = text; });\
+             });\
+             </script>",
+        )
+        .await
+        .unwrap();
+
+    engine
+        .handle_input_event(InputEvent::FileDrop {
+            paths: vec![path.clone(), dir.clone()],
+            x: 20,
+            y: 20,
+        })
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    let outcome = engine.execute_javascript("[meta, contents]").await.unwrap();
+    assert_eq!(
+        outcome,
+        serde_json::json!([["notes.txt", 16, "text/plain", "Files"], "dropped contents"])
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_typing_into_focused_input_does_not_trigger_accelerators() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, KeyRoute};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.register_accelerator("R", "reload").unwrap();
+    engine
+        .load_url(
+            "data:text/html,<input id=name><script>\
+             globalThis.inputs = [];\
+             document.getElementById('name').addEventListener('input', (e) => inputs.push(e.inputType + ':' + e.data));\
+             </script>",
+        )
+        .await
+        .unwrap();
+    assert!(engine.focus_element("#name").await.unwrap());
+
+    let route = engine.press_key("r", Modifiers::NONE).await.unwrap();
+    assert_eq!(route, KeyRoute::Page);
+
+    let outcome = engine
+        .execute_javascript("[document.getElementById('name').value, inputs]")
+        .await
+        .unwrap();
+    assert_eq!(outcome, serde_json::json!(["r", ["insertText:r"]]));
+    assert!(engine
+        .get_recent_events(None, Some(EventKindMask::ACCELERATOR_TRIGGERED))
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_keys_the_page_leaves_alone_trigger_accelerators() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent, KeyRoute};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.register_accelerator("R", "reload").unwrap();
+    engine
+        .load_url(
+            "data:text/html,<input id=name><script>\
+             document.body.addEventListener('keydown', (e) => { if (e.key === 'F5') e.preventDefault(); });\
+             </script>",
+        )
+        .await
+        .unwrap();
+    let navigations = || {
+        engine
+            .get_recent_events(None, Some(EventKindMask::NAVIGATION_STARTED))
+            .len()
+    };
+    let loaded = navigations();
+
+    // A canceled keydown keeps the key from the built-in reload.
+    let route = engine.press_key("F5", Modifiers::NONE).await.unwrap();
+    assert_eq!(route, KeyRoute::Page);
+    assert_eq!(navigations(), loaded);
+
+    let route = engine.press_key("r", Modifiers::NONE).await.unwrap();
+    assert_eq!(route, KeyRoute::Accelerator("reload".to_string()));
+    assert_eq!(navigations(), loaded + 1);
+    let triggered: Vec<(String, String)> = engine
+        .get_recent_events(None, Some(EventKindMask::ACCELERATOR_TRIGGERED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::AcceleratorTriggered { action, keystroke },
+            } => Some((action, keystroke)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(triggered, vec![("reload".to_string(), "R".to_string())]);
+
+    assert_eq!(
+        engine.press_key("q", Modifiers::NONE).await.unwrap(),
+        KeyRoute::Unhandled
+    );
+}
+
+#[cfg(feature = "test-util")]
+const ADD_WASM: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/add.wasm"
+));
+
+/// Tick until `globalThis.result` is set, giving up after five seconds.
+#[cfg(feature = "test-util")]
+async fn wait_for_result(engine: &vulkan_browser_engine::BrowserEngine) -> serde_json::Value {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        engine.tick().await.unwrap();
+        let result = engine
+            .execute_javascript("globalThis.result === undefined ? null : globalThis.result")
+            .await
+            .unwrap();
+        if !result.is_null() || std::time::Instant::now() > deadline {
+            return result;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_wasm_served_as_application_wasm_instantiates_streaming() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(&[("/add.wasm", "application/wasm", ADD_WASM)]).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    engine
+        .execute_javascript(
+            "WebAssembly.instantiateStreaming(fetch('/add.wasm')).then(\
+               (r) => { globalThis.result = r.instance.exports.add(2, 3); },\
+               (e) => { globalThis.result = e.name + ': ' + e.message; }); 0",
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(wait_for_result(&engine).await, serde_json::json!(5));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_wasm_streaming_rejects_the_wrong_mime_type() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(&[("/add.wasm", "text/plain", ADD_WASM)]).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    engine
+        .execute_javascript(
+            "WebAssembly.instantiateStreaming(fetch('/add.wasm')).then(\
+               () => { globalThis.result = 'instantiated'; },\
+               (e) => { globalThis.result = e.name; }); 0",
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        wait_for_result(&engine).await,
+        serde_json::json!("TypeError")
+    );
+}
+
+#[tokio::test]
+async fn test_disabling_webassembly_removes_the_global() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_webassembly: false,
+        ..BrowserConfig::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url("data:text/html,<p>no wasm</p>")
+        .await
+        .unwrap();
+    let kind = engine
+        .execute_javascript("typeof WebAssembly")
+        .await
+        .unwrap();
+    assert_eq!(kind, serde_json::json!("undefined"));
+}
+
+type DevtoolsSocket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Send a devtools command and wait for its reply, keeping the
+/// notifications that arrive first.
+async fn devtools_call(
+    socket: &mut DevtoolsSocket,
+    id: u64,
+    method: &str,
+    params: serde_json::Value,
+    notifications: &mut Vec<serde_json::Value>,
+) -> serde_json::Value {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let request = serde_json::json!({ "id": id, "method": method, "params": params });
+    socket
+        .send(Message::Text(request.to_string().into()))
+        .await
+        .unwrap();
+    loop {
+        let message = socket.next().await.unwrap().unwrap();
+        let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        if message["id"] == serde_json::json!(id) {
+            return message;
+        }
+        notifications.push(message);
+    }
+}
+
+/// A `Page.captureSnapshot` result as a snapshot.
+fn decode_snapshot(result: &serde_json::Value) -> vulkan_browser_engine::renderer::Snapshot {
+    use base64::Engine;
+
+    vulkan_browser_engine::renderer::Snapshot {
+        width: result["width"].as_u64().unwrap() as u32,
+        height: result["height"].as_u64().unwrap() as u32,
+        data: base64::engine::general_purpose::STANDARD
+            .decode(result["data"].as_str().unwrap())
+            .unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn test_devtools_socket_highlights_nodes_and_streams_mutations() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>.red { color: red }</style><body style=\"margin:0\">\
+             <div id=box style=\"width:100px;height:40px;padding:10px\">Box</div></body>",
+        )
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let client = async {
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        let mut notifications = Vec::new();
+        let reply = devtools_call(
+            &mut socket,
+            1,
+            "DOM.querySelector",
+            serde_json::json!({ "selector": "#box" }),
+            &mut notifications,
+        )
+        .await;
+        let node_id = reply["result"]["nodeId"].clone();
+        assert!(node_id.is_string());
+
+        let before = devtools_call(
+            &mut socket,
+            2,
+            "Page.captureSnapshot",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        devtools_call(
+            &mut socket,
+            3,
+            "Overlay.highlightNode",
+            serde_json::json!({ "nodeId": node_id }),
+            &mut notifications,
+        )
+        .await;
+        let highlighted = devtools_call(
+            &mut socket,
+            4,
+            "Page.captureSnapshot",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        devtools_call(
+            &mut socket,
+            5,
+            "Overlay.hideHighlight",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        let hidden = devtools_call(
+            &mut socket,
+            6,
+            "Page.captureSnapshot",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        let before = decode_snapshot(&before["result"]);
+        let highlighted = decode_snapshot(&highlighted["result"]);
+        let hidden = decode_snapshot(&hidden["result"]);
+        // Inside the padding, then inside the content.
+        for (x, y) in [(5, 5), (50, 30)] {
+            assert_ne!(highlighted.pixel(x, y), before.pixel(x, y));
+            assert_eq!(hidden.pixel(x, y), before.pixel(x, y));
+        }
+
+        devtools_call(
+            &mut socket,
+            7,
+            "DOM.enableMutations",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        let reply = devtools_call(
+            &mut socket,
+            8,
+            "DOM.setAttributeValue",
+            serde_json::json!({ "nodeId": node_id, "name": "class", "value": "red" }),
+            &mut notifications,
+        )
+        .await;
+        assert_eq!(reply["result"], serde_json::json!({}));
+        let styles = devtools_call(
+            &mut socket,
+            9,
+            "CSS.getComputedStyle",
+            serde_json::json!({ "nodeId": node_id }),
+            &mut notifications,
+        )
+        .await;
+        assert_eq!(
+            styles["result"]["properties"]["color"],
+            serde_json::json!("#FF0000")
+        );
+        // Notifications go out after the reply to the command causing them.
+        devtools_call(
+            &mut socket,
+            10,
+            "DOM.disableMutations",
+            serde_json::json!({}),
+            &mut notifications,
+        )
+        .await;
+        assert_eq!(
+            notifications,
+            vec![serde_json::json!({
+                "method": "DOM.mutation",
+                "params": {
+                    "type": "attributes",
+                    "target": node_id,
+                    "attributeName": "class",
+                    "value": "red",
+                    "oldValue": null,
+                },
+            })]
+        );
+
+        let reply = devtools_call(
+            &mut socket,
+            11,
+            "DOM.setAttributeValue",
+            serde_json::json!({ "nodeId": 7 }),
+            &mut notifications,
+        )
+        .await;
+        assert!(reply["error"]["message"].is_string());
+    };
+
+    tokio::select! {
+        result = engine.serve_devtools(listener) => panic!("server stopped: {:?}", result),
+        _ = client => {}
+    }
+}
+
+#[tokio::test]
+async fn test_devtools_edits_restyle_and_declarations_toggle() {
+    use vulkan_browser_engine::core::devtools::Command;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>p { color: blue } .red { color: red }</style>\
+             <p id=text style=\"font-weight: bold\">Text</p>",
+        )
+        .await
+        .unwrap();
+    let command = |method: &str, params: serde_json::Value| Command::parse(method, params).unwrap();
+    let found = engine
+        .devtools_command(command(
+            "DOM.querySelector",
+            serde_json::json!({ "selector": "#text" }),
+        ))
+        .await
+        .unwrap();
+    let node_id = found["nodeId"].clone();
+    let computed = |name: &'static str| {
+        let engine = &engine;
+        let node_id = node_id.clone();
+        async move {
+            let styles = engine
+                .devtools_command(command(
+                    "CSS.getComputedStyle",
+                    serde_json::json!({ "nodeId": node_id }),
+                ))
+                .await
+                .unwrap();
+            styles["properties"][name].clone()
+        }
+    };
+    assert_eq!(computed("color").await, serde_json::json!("#0000FF"));
+
+    engine
+        .devtools_command(command(
+            "DOM.setAttributeValue",
+            serde_json::json!({ "nodeId": node_id, "name": "class", "value": "red" }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(computed("color").await, serde_json::json!("#FF0000"));
+
+    let matched = engine
+        .devtools_command(command(
+            "CSS.getMatchedStyles",
+            serde_json::json!({ "nodeId": node_id }),
+        ))
+        .await
+        .unwrap();
+    let last_rule = matched["rules"].as_array().unwrap().last().unwrap();
+    let red_rule = last_rule["ruleIndex"].clone();
+    assert_eq!(
+        last_rule["declarations"],
+        serde_json::json!([
+            { "name": "color", "value": "red", "important": false, "enabled": true }
+        ])
+    );
+
+    // Switching `.red`'s declaration off lets `p`'s show through.
+    engine
+        .devtools_command(command(
+            "CSS.setDeclarationEnabled",
+            serde_json::json!({
+                "nodeId": node_id, "ruleIndex": red_rule, "name": "color", "enabled": false
+            }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(computed("color").await, serde_json::json!("#0000FF"));
+    engine
+        .devtools_command(command(
+            "CSS.setDeclarationEnabled",
+            serde_json::json!({
+                "nodeId": node_id, "ruleIndex": red_rule, "name": "color", "enabled": true
+            }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(computed("color").await, serde_json::json!("#FF0000"));
+
+    // Inline declarations come off the style attribute and go back on.
+    let inline_toggle = |enabled: bool| {
+        command(
+            "CSS.setDeclarationEnabled",
+            serde_json::json!({ "nodeId": node_id, "name": "font-weight", "enabled": enabled }),
+        )
+    };
+    let font_weight =
+        || engine.execute_javascript("document.getElementById('text').style.fontWeight");
+    engine.devtools_command(inline_toggle(false)).await.unwrap();
+    assert_eq!(font_weight().await.unwrap(), serde_json::json!(""));
+    engine.devtools_command(inline_toggle(true)).await.unwrap();
+    assert_eq!(font_weight().await.unwrap(), serde_json::json!("bold"));
+
+    engine
+        .devtools_command(command(
+            "CSS.addInlineDeclaration",
+            serde_json::json!({ "nodeId": node_id, "name": "color", "value": "#00ff00" }),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(computed("color").await, serde_json::json!("#00FF00"));
+
+    engine
+        .devtools_command(command(
+            "DOM.setTextContent",
+            serde_json::json!({ "nodeId": node_id, "text": "Edited" }),
+        ))
+        .await
+        .unwrap();
+    let text = engine
+        .execute_javascript("document.getElementById('text').textContent")
+        .await
+        .unwrap();
+    assert_eq!(text, serde_json::json!("Edited"));
+}
+
+#[tokio::test]
+async fn test_devtools_inspect_mode_picks_the_clicked_element() {
+    use vulkan_browser_engine::core::devtools::Command;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=target draggable=true style=\"height:50px\">Pick me</div>",
+        )
+        .await
+        .unwrap();
+    engine
+        .devtools_command(
+            Command::parse(
+                "Overlay.setInspectMode",
+                serde_json::json!({ "enabled": true }),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    let before = engine.snapshot().await.pixel(10, 10);
+    engine
+        .handle_input_event(InputEvent::MouseMove { x: 10, y: 10 })
+        .await
+        .unwrap();
+    assert_ne!(engine.snapshot().await.pixel(10, 10), before);
+    engine
+        .handle_input_event(InputEvent::MouseDown {
+            x: 10,
+            y: 10,
+            button: 0,
+        })
+        .await
+        .unwrap();
+
+    let target = engine
+        .devtools_command(
+            Command::parse(
+                "DOM.querySelector",
+                serde_json::json!({ "selector": "#target" }),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        engine.take_devtools_notifications().await,
+        vec![serde_json::json!({
+            "method": "Overlay.inspectNodeRequested",
+            "params": { "nodeId": target["nodeId"] },
+        })]
+    );
+
+    // The press picked rather than arming a drag of the draggable target.
+    engine
+        .handle_input_event(InputEvent::MouseMove { x: 10, y: 40 })
+        .await
+        .unwrap();
+    assert!(engine.drag_image().await.is_none());
+}
+
+#[tokio::test]
+async fn test_devtools_refuse_non_loopback_listeners() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+    assert!(matches!(
+        engine.serve_devtools(listener).await,
+        Err(BrowserError::Security(_))
+    ));
+}
+
+fn feed_page(section_style: &str) -> String {
+    let mut page = String::from("data:text/html,<body style=\"margin:0;font-size:4px\">");
+    for section in 0..1000 {
+        let background = if section % 2 == 0 { "red" } else { "blue" };
+        page.push_str(&format!(
+            "<section style=\"background-color:{background};{section_style}\">"
+        ));
+        for item in 0..12 {
+            page.push_str(&format!("<div>Section {section} item {item}</div>"));
+        }
+        page.push_str("</section>");
+    }
+    page.push_str("</body>");
+    page
+}
+
+fn visible_text(tree: &serde_json::Value, height: f64) -> Vec<(String, f64)> {
+    tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|node| node["type"] == "text")
+        .map(|node| {
+            (
+                node["text"].as_str().unwrap().to_string(),
+                node["bounds"]["y"].as_f64().unwrap(),
+            )
+        })
+        .filter(|(_, y)| *y < height)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_content_visibility_auto_skips_offscreen_sections() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let config = || BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 800,
+        viewport_height: 600,
+        ..Default::default()
+    };
+    let plain = BrowserEngine::new(config()).await.unwrap();
+    let skipping = BrowserEngine::new(config()).await.unwrap();
+    plain.load_url(&feed_page("")).await.unwrap();
+    skipping
+        .load_url(&feed_page(
+            "content-visibility:auto;contain-intrinsic-size:auto 58px",
+        ))
+        .await
+        .unwrap();
+
+    // Relayout both with nothing changed, timing the fastest of a few runs.
+    async fn relayout(engine: &BrowserEngine) -> (u64, std::time::Duration) {
+        let reflows = engine.get_performance_metrics().await.layout.reflow_count;
+        let mut fastest = std::time::Duration::MAX;
+        for _ in 0..3 {
+            let started = std::time::Instant::now();
+            engine.resize_viewport(800, 600).await.unwrap();
+            fastest = fastest.min(started.elapsed());
+        }
+        let reflows = engine.get_performance_metrics().await.layout.reflow_count - reflows;
+        (reflows / 3, fastest)
+    }
+    let (plain_nodes, plain_time) = relayout(&plain).await;
+    let (skipping_nodes, skipping_time) = relayout(&skipping).await;
+    assert!(
+        skipping_nodes * 10 < plain_nodes,
+        "{skipping_nodes} vs {plain_nodes} nodes laid out"
+    );
+    assert!(
+        skipping_time * 10 < plain_time,
+        "{skipping_time:?} vs {plain_time:?}"
+    );
+    let plain_boxes = plain.get_performance_metrics().await.layout.nodes_count;
+    let skipping_boxes = skipping.get_performance_metrics().await.layout.nodes_count;
+    assert!(skipping_boxes * 10 < plain_boxes);
+
+    // The visible region renders the same.
+    assert_eq!(plain.snapshot().await.data, skipping.snapshot().await.data);
+    let visible = visible_text(&skipping.dump_layout_tree().await, 600.0);
+    assert!(visible.iter().any(|(text, _)| text == "Section 0 item 0"));
+    assert_eq!(visible, visible_text(&plain.dump_layout_tree().await, 600.0));
+    // Placeholders keep the document as tall as it would be.
+    let plain_height = plain.dump_layout_tree().await["nodes"][0]["bounds"]["height"].clone();
+    let skipping_height =
+        skipping.dump_layout_tree().await["nodes"][0]["bounds"]["height"].clone();
+    let (plain_height, skipping_height) = (
+        plain_height.as_f64().unwrap(),
+        skipping_height.as_f64().unwrap(),
+    );
+    assert!((plain_height - skipping_height).abs() < 1000.0 * 0.5);
+}
+
+#[tokio::test]
+async fn test_content_visibility_lays_out_sections_scrolled_near() {
+    use vulkan_browser_engine::core::devtools::Command;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        viewport_width: 800,
+        viewport_height: 600,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(&feed_page(
+            "content-visibility:auto;contain-intrinsic-size:auto 58px",
+        ))
+        .await
+        .unwrap();
+    let far = |tree: &serde_json::Value| {
+        visible_text(tree, 600.0)
+            .iter()
+            .any(|(text, _)| text == "Section 500 item 0")
+    };
+    assert!(!far(&engine.dump_layout_tree().await));
+
+    engine.scroll_to(0.0, 500.0 * 58.0).await.unwrap();
+    assert!(far(&engine.dump_layout_tree().await));
+    assert!(engine.scroll_position().await.1 > 0.0);
+
+    // Hidden contents are never laid out, so hits land on the container.
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=shut style=\"content-visibility:hidden;contain-intrinsic-size:100px\">\
+             <p>Hidden</p></div>",
+        )
+        .await
+        .unwrap();
+    let shut = engine
+        .devtools_command(
+            Command::parse(
+                "DOM.querySelector",
+                serde_json::json!({ "selector": "#shut" }),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    let hit = engine.hit_test(10.0, 10.0).await.unwrap();
+    assert_eq!(
+        hit.map(|node_id| node_id.0.to_string()),
+        shut["nodeId"].as_str().map(str::to_string)
+    );
+    assert!(visible_text(&engine.dump_layout_tree().await, 600.0).is_empty());
+}
+
+/// A list with a spinner whose background steps through reds, one shade
+/// per animation frame.
+fn spinner_page() -> String {
+    let mut page = String::from(
+        "data:text/html,<body style=\"margin:0\">\
+         <div id=spinner style=\"width:20px;height:20px;background-color:rgb(0,0,0)\"></div>",
+    );
+    for item in 0..40 {
+        let background = if item % 2 == 0 { "white" } else { "blue" };
+        page.push_str(&format!(
+            "<div style=\"background-color:{background}\">Item {item}</div>"
+        ));
+    }
+    page.push_str(
+        "<script>let shade = 0;\
+         function step() {\
+           shade += 4;\
+           document.getElementById('spinner').style.backgroundColor = `rgb(${shade},0,0)`;\
+           requestAnimationFrame(step);\
+         }\
+         requestAnimationFrame(step);</script></body>",
+    );
+    page
+}
+
+#[tokio::test]
+async fn test_animated_frames_patch_only_the_changed_node() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let config = |full_frame_rebuild| BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 400,
+        viewport_height: 600,
+        full_frame_rebuild,
+        ..Default::default()
+    };
+    let retained = BrowserEngine::new(config(false)).await.unwrap();
+    let rebuilt = BrowserEngine::new(config(true)).await.unwrap();
+    retained.load_url(&spinner_page()).await.unwrap();
+    rebuilt.load_url(&spinner_page()).await.unwrap();
+
+    let before = retained.get_performance_metrics().await.renderer;
+    for frame in 0..60 {
+        retained.tick().await.unwrap();
+        rebuilt.tick().await.unwrap();
+
+        let patched = retained.get_performance_metrics().await.renderer;
+        assert_eq!(patched.nodes_patched, 1, "frame {frame}");
+        assert_eq!(patched.nodes_rebuilt, 0, "frame {frame}");
+        assert_eq!(
+            (patched.vertex_buffer_version, patched.index_buffer_version),
+            (before.vertex_buffer_version, before.index_buffer_version),
+            "frame {frame}"
+        );
+        let full = rebuilt.get_performance_metrics().await.renderer;
+        assert_eq!(full.nodes_patched, 0);
+        assert!(full.nodes_rebuilt > 40);
+    }
+    assert_eq!(
+        retained
+            .execute_javascript("document.getElementById('spinner').style.backgroundColor")
+            .await
+            .unwrap(),
+        "rgb(240,0,0)"
+    );
+
+    // Patching draws what painting everything again draws.
+    let snapshot = retained.snapshot().await;
+    assert_eq!(snapshot.pixel(10, 10), [240, 0, 0, 255]);
+    assert_eq!(snapshot.data, rebuilt.snapshot().await.data);
+}
+
+/// A `size` pixel square PNG of one color, as a data URL.
+fn png_icon(size: u32, rgb: [u8; 3]) -> String {
+    use base64::Engine;
+
+    let image =
+        image::RgbaImage::from_pixel(size, size, image::Rgba([rgb[0], rgb[1], rgb[2], 255]));
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .unwrap();
+    format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png.into_inner())
+    )
+}
+
+#[tokio::test]
+async fn test_favicon_picks_the_closest_size() {
+    use base64::Engine;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    assert!(engine.get_favicon(32).await.is_none());
+
+    engine
+        .load_url(&format!(
+            "data:text/html,<head>\
+             <link rel=icon sizes=16x16 href=\"{}\">\
+             <link rel=icon sizes=40x40 href=\"{}\">\
+             <link rel=icon sizes=96x96 href=\"{}\">\
+             </head><p>Icons</p>",
+            png_icon(16, [255, 0, 0]),
+            png_icon(40, [0, 255, 0]),
+            png_icon(96, [0, 0, 255]),
+        ))
+        .await
+        .unwrap();
+    let favicon = engine.get_favicon(32).await.unwrap();
+    assert_eq!((favicon.width, favicon.height), (32, 32));
+    assert_eq!(&favicon.data[..4], &[0, 255, 0, 255]);
+    let large = engine.get_favicon(128).await.unwrap();
+    assert_eq!(
+        (large.width, &large.data[..4]),
+        (128, &[0, 0, 255, 255][..])
+    );
+
+    // SVG icons are drawn at the size asked for.
+    let svg = "<svg xmlns='http://www.w3.org/2000/svg' width='10' height='10'>\
+               <rect width='10' height='10' fill='yellow'/></svg>";
+    engine
+        .load_url(&format!(
+            "data:text/html,<link rel=icon type=image/svg+xml href=\"data:image/svg+xml;base64,{}\">",
+            base64::engine::general_purpose::STANDARD.encode(svg)
+        ))
+        .await
+        .unwrap();
+    let drawn = engine.get_favicon(64).await.unwrap();
+    assert_eq!((drawn.width, drawn.height), (64, 64));
+    assert_eq!(&drawn.data[..4], &[255, 255, 0, 255]);
+    assert_eq!(
+        engine
+            .get_recent_events(None, Some(EventKindMask::FAVICON_CHANGED))
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_page_metadata_reads_open_graph_fields() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<head><title>Card</title>\
+             <meta name=description content=\"A plain description\">\
+             <meta property=og:title content=\" Shared title \">\
+             <meta property=og:description content=\"Shared description\">\
+             <meta property=og:image content=\"https://example.com/card.png\">\
+             <meta property=og:title content=\"Ignored second title\">\
+             </head><p>Body</p>",
+        )
+        .await
+        .unwrap();
+
+    let metadata = engine.get_page_metadata().await;
+    assert_eq!(metadata.description.as_deref(), Some("A plain description"));
+    assert_eq!(metadata.og_title.as_deref(), Some("Shared title"));
+    assert_eq!(
+        metadata.og_description.as_deref(),
+        Some("Shared description")
+    );
+    assert_eq!(
+        metadata.og_image.as_deref(),
+        Some("https://example.com/card.png")
+    );
+    assert_eq!(metadata.theme_color, None);
+}
+
+#[tokio::test]
+async fn test_theme_color_change_from_script_fires_event() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<head><meta id=theme name=theme-color content=red></head>")
+        .await
+        .unwrap();
+    engine
+        .execute_javascript("document.getElementById('theme').setAttribute('content', 'blue')")
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    let colors: Vec<Option<String>> = engine
+        .get_recent_events(None, Some(EventKindMask::THEME_COLOR_CHANGED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::ThemeColorChanged { tab, color },
+            } if tab == engine.tab_id() => Some(color),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(colors, [Some("red".to_string()), Some("blue".to_string())]);
+    assert_eq!(
+        engine.get_page_metadata().await.theme_color.as_deref(),
+        Some("blue")
+    );
+}
+
+/// A TTS backend that speaks nothing on its own: it records what the queue
+/// asks of it and keeps each utterance's progress for the test to report.
+#[derive(Default)]
+struct RecordingTts {
+    calls: std::sync::Mutex<Vec<String>>,
+    spoken: std::sync::Mutex<Vec<vulkan_browser_engine::core::speech::SpeechProgress>>,
+}
+
+impl RecordingTts {
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn progress(&self, index: usize) -> vulkan_browser_engine::core::speech::SpeechProgress {
+        self.spoken.lock().unwrap()[index].clone()
+    }
+}
+
+impl vulkan_browser_engine::core::speech::TtsBackend for RecordingTts {
+    fn voices(&self) -> Vec<vulkan_browser_engine::core::speech::Voice> {
+        vec![vulkan_browser_engine::core::speech::Voice {
+            voice_uri: "test:voice".to_string(),
+            name: "Test".to_string(),
+            lang: "en-GB".to_string(),
+            local_service: true,
+            default: true,
+        }]
+    }
+
+    fn speak(
+        &self,
+        request: vulkan_browser_engine::core::speech::SpeechRequest,
+        progress: vulkan_browser_engine::core::speech::SpeechProgress,
+    ) {
+        self.calls.lock().unwrap().push(format!(
+            "speak {} rate={} voice={}",
+            request.text,
+            request.rate,
+            request.voice.as_deref().unwrap_or("none")
+        ));
+        self.spoken.lock().unwrap().push(progress);
+    }
+
+    fn pause(&self) {
+        self.calls.lock().unwrap().push("pause".to_string());
+    }
+
+    fn resume(&self) {
+        self.calls.lock().unwrap().push("resume".to_string());
+    }
+
+    fn cancel(&self) {
+        self.calls.lock().unwrap().push("cancel".to_string());
+    }
+}
+
+/// Script defining `say(text)`, which speaks an utterance that logs its
+/// events into `speechLog`.
+const SPEECH_LOG_SCRIPT: &str = "globalThis.speechLog = [];\
+     globalThis.say = (text, rate) => {\
+       const u = new SpeechSynthesisUtterance(text);\
+       if (rate !== undefined) u.rate = rate;\
+       for (const type of ['start', 'boundary', 'pause', 'resume', 'end', 'error'])\
+         u.addEventListener(type, (e) => speechLog.push(text + ':' + e.type + (e.error ? '=' + e.error : '')));\
+       speechSynthesis.speak(u);\
+     };";
+
+#[tokio::test]
+async fn test_speech_queue_speaks_in_order_and_cancel_flushes() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::speech::Boundary;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let tts = Arc::new(RecordingTts::default());
+    engine.set_tts_backend(Some(tts.clone()));
+    engine
+        .load_url(&format!(
+            "data:text/html,<script>{}\
+             say('first', 20); say('second');\
+             globalThis.voices = speechSynthesis.getVoices().map((v) => v.voiceURI);\
+             globalThis.busy = [speechSynthesis.speaking, speechSynthesis.pending];\
+             </script>",
+            SPEECH_LOG_SCRIPT
+        ))
+        .await
+        .unwrap();
+
+    // The second utterance waits for the first; rates clamp to 10.
+    assert_eq!(tts.calls(), vec!["speak first rate=10 voice=none"]);
+    assert_eq!(
+        engine.execute_javascript("[voices, busy]").await.unwrap(),
+        serde_json::json!([["test:voice"], [true, true]])
+    );
+
+    let first = tts.progress(0);
+    first.started();
+    first.boundary(Boundary::Word, 0, 5);
+    first.ended();
+    engine.tick().await.unwrap();
+    assert_eq!(
+        tts.calls(),
+        vec![
+            "speak first rate=10 voice=none",
+            "speak second rate=1 voice=none"
+        ]
+    );
+
+    tts.progress(1).started();
+    engine
+        .execute_javascript(
+            "speechSynthesis.pause(); speechSynthesis.resume();\
+             say('third'); speechSynthesis.cancel();\
+             [speechSynthesis.speaking, speechSynthesis.pending, speechSynthesis.paused]",
+        )
+        .await
+        .unwrap();
+    // A report for an utterance that was cancelled goes nowhere.
+    tts.progress(1).ended();
+    engine.tick().await.unwrap();
+
+    assert_eq!(
+        engine.execute_javascript("speechLog").await.unwrap(),
+        serde_json::json!([
+            "first:start",
+            "first:boundary",
+            "first:end",
+            "second:start",
+            "second:pause",
+            "second:resume",
+            "second:error=interrupted",
+            "third:error=canceled"
+        ])
+    );
+    assert_eq!(
+        &tts.calls()[2..],
+        ["pause", "resume", "cancel"].map(String::from)
+    );
+}
+
+#[tokio::test]
+async fn test_speech_pause_holds_the_queue_until_resume() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let tts = Arc::new(RecordingTts::default());
+    engine.set_tts_backend(Some(tts.clone()));
+    engine
+        .load_url(&format!(
+            "data:text/html,<script>{}\
+             speechSynthesis.pause(); say('held');\
+             globalThis.held = [speechSynthesis.speaking, speechSynthesis.pending, speechSynthesis.paused];\
+             </script>",
+            SPEECH_LOG_SCRIPT
+        ))
+        .await
+        .unwrap();
+    assert!(tts.calls().is_empty());
+    assert_eq!(
+        engine.execute_javascript("held").await.unwrap(),
+        serde_json::json!([false, true, true])
+    );
+
+    engine
+        .execute_javascript("speechSynthesis.resume()")
+        .await
+        .unwrap();
+    assert_eq!(tts.calls(), vec!["speak held rate=1 voice=none"]);
+    // Ending without reporting a start still starts first.
+    tts.progress(0).ended();
+    engine.tick().await.unwrap();
+    assert_eq!(
+        engine.execute_javascript("speechLog").await.unwrap(),
+        serde_json::json!(["held:start", "held:end"])
+    );
+}
+
+#[tokio::test]
+async fn test_navigation_cancels_queued_speech() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let tts = Arc::new(RecordingTts::default());
+    engine.set_tts_backend(Some(tts.clone()));
+    engine
+        .load_url(&format!(
+            "data:text/html,<script>{}say('one'); say('two');</script>",
+            SPEECH_LOG_SCRIPT
+        ))
+        .await
+        .unwrap();
+    let one = tts.progress(0);
+    one.started();
+
+    engine.load_url("data:text/html,<p>next</p>").await.unwrap();
+    assert_eq!(tts.calls(), vec!["speak one rate=1 voice=none", "cancel"]);
+
+    // The old utterance finishing neither reaches the new page nor starts
+    // the one queued behind it.
+    one.ended();
+    engine.tick().await.unwrap();
+    assert_eq!(tts.calls().len(), 2);
+    assert_eq!(
+        engine
+            .execute_javascript("[speechSynthesis.speaking, speechSynthesis.pending]")
+            .await
+            .unwrap(),
+        serde_json::json!([false, false])
+    );
+}
+
+#[tokio::test]
+async fn test_speech_refused_to_an_origin_without_permission() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::permissions::{Permission, PermissionState};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let tts = Arc::new(RecordingTts::default());
+    engine.set_tts_backend(Some(tts.clone()));
+    let kiosk = url::Url::parse("https://kiosk.example/").unwrap();
+    engine.set_default_permission(Permission::SpeechSynthesis, PermissionState::Denied);
+    engine.set_permission(
+        &kiosk,
+        Permission::SpeechSynthesis,
+        Some(PermissionState::Granted),
+    );
+    assert_eq!(
+        engine.permission_state(
+            &url::Url::parse("https://kiosk.example/help").unwrap(),
+            Permission::SpeechSynthesis
+        ),
+        PermissionState::Granted
+    );
+
+    engine
+        .load_url(&format!(
+            "data:text/html,<script>{}say('hello');</script>",
+            SPEECH_LOG_SCRIPT
+        ))
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    assert!(tts.calls().is_empty());
+    assert_eq!(
+        engine.execute_javascript("speechLog").await.unwrap(),
+        serde_json::json!(["hello:error=not-allowed"])
+    );
+}
+
+#[tokio::test]
+async fn test_null_tts_backend_finishes_utterances() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(&format!(
+            "data:text/html,<script>{}say('quick one', 10);</script>",
+            SPEECH_LOG_SCRIPT
+        ))
+        .await
+        .unwrap();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        engine.tick().await.unwrap();
+        let log = engine.execute_javascript("speechLog").await.unwrap();
+        if log.as_array().is_some_and(|log| log.len() == 4) {
+            assert_eq!(
+                log,
+                serde_json::json!([
+                    "quick one:start",
+                    "quick one:boundary",
+                    "quick one:boundary",
+                    "quick one:end"
+                ])
+            );
+            break;
+        }
+        assert!(std::time::Instant::now() < deadline, "speech never ended");
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_lang_reflects_attribute_and_drives_lang_selector() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<html lang=en><body><p id=p>hi</p><div id=d lang=fr></div></body></html>")
+        .await
+        .unwrap();
+    let result = engine
+        .execute_javascript(
+            "const p = document.getElementById('p');
+             const before = p.lang;
+             p.lang = 'de';
+             [document.documentElement.lang, before, p.lang, p.getAttribute('lang'),
+              document.getElementById('d').lang, document.querySelector(':lang(de)') === p]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        serde_json::json!(["en", "", "de", "de", "fr", true])
+    );
+}
+
+#[tokio::test]
+async fn test_prefers_reduced_motion_media_feature_follows_config() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let page = "data:text/html,<style>p { color: red } \
+                @media (prefers-reduced-motion: reduce) { p { color: green } }</style><p>Still</p>";
+    for (reduce, color) in [(false, "#FF0000"), (true, "#008000")] {
+        let engine = BrowserEngine::new(BrowserConfig {
+            prefers_reduced_motion: reduce,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        engine.load_url(page).await.unwrap();
+        let styles = engine.dump_computed_styles("p").await.unwrap();
+        assert_eq!(styles[0]["styles"]["color"], color);
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_animated_image_frames_count_toward_image_memory() {
+    use image::codecs::gif::{GifEncoder, Repeat};
+    use image::{Delay, Frame, Rgba, RgbaImage};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        for color in [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]] {
+            let frame = RgbaImage::from_pixel(4, 4, Rgba(color));
+            encoder
+                .encode_frame(Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(50, 1),
+                ))
+                .unwrap();
+        }
+    }
+    let gif: &'static [u8] = Box::leak(gif.into_boxed_slice());
+    let pages: &'static [(&str, &str, &[u8])] = Box::leak(Box::new([
+        (
+            "/",
+            "text/html",
+            &b"<img src=/spinner.gif width=4 height=4>"[..],
+        ),
+        ("/spinner.gif", "image/gif", gif),
+    ]));
+    let host = spawn_page_host(pages).await;
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    engine.tick().await.unwrap();
+    let metrics = engine.get_performance_metrics().await;
+    // Three 4x4 RGBA frames.
+    assert_eq!(metrics.contexts[0].image_cache_bytes, 3 * 4 * 4 * 4);
+
+    // A new document drops them.
+    engine.load_url("data:text/html,<p>none</p>").await.unwrap();
+    let metrics = engine.get_performance_metrics().await;
+    assert_eq!(metrics.contexts[0].image_cache_bytes, 0);
+}
+
+/// An installable app: a page linking its manifest, which names a service
+/// worker. The page keeps its `beforeinstallprompt` to prompt later.
+#[cfg(feature = "test-util")]
+const PWA_PAGES: &[(&str, &str, &[u8])] = &[
+    (
+        "/",
+        "text/html",
+        b"<link rel=manifest href=/manifest.json>\
+          <script>\
+          globalThis.prompts = 0;\
+          addEventListener('beforeinstallprompt', (e) => {\
+            e.preventDefault();\
+            globalThis.prompts += 1;\
+            globalThis.deferred = e;\
+          });\
+          addEventListener('appinstalled', () => { globalThis.installed = true; });\
+          </script>",
+    ),
+    (
+        "/manifest.json",
+        "application/manifest+json",
+        br#"{"name": "Notes", "start_url": "/", "display": "standalone"}"#,
+    ),
+    (
+        "/sw.js",
+        "text/javascript",
+        b"self.addEventListener('fetch', () => {});",
+    ),
+];
+
+#[cfg(feature = "test-util")]
+fn installable_events(engine: &vulkan_browser_engine::BrowserEngine) -> Vec<(String, String)> {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::BrowserEvent;
+
+    engine
+        .get_recent_events(None, Some(EventKindMask::PWA_INSTALLABLE))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event:
+                    BrowserEvent::PwaInstallable {
+                        manifest_url,
+                        app_name,
+                    },
+            } => Some((manifest_url, app_name)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_installable_page_is_offered_once() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(PWA_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    engine.tick().await.unwrap();
+    engine.tick().await.unwrap();
+
+    assert_eq!(
+        installable_events(&engine),
+        vec![(format!("{}/manifest.json", host), "Notes".to_string())]
+    );
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.prompts")
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_install_prompt_installs_the_app() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(PWA_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    assert!(engine.get_installed_related_apps().await.is_empty());
+
+    engine
+        .execute_javascript(
+            "globalThis.deferred.prompt().then((choice) => { globalThis.outcome = choice.outcome; })",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.outcome")
+            .await
+            .unwrap(),
+        "accepted"
+    );
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.installed")
+            .await
+            .unwrap(),
+        true
+    );
+    let apps = engine.get_installed_related_apps().await;
+    assert_eq!(apps.len(), 1);
+    assert_eq!(apps[0].manifest.name, "Notes");
+
+    // A second prompt is refused, and the installed app is not offered
+    // again.
+    assert_eq!(
+        engine
+            .execute_javascript(
+                "globalThis.deferred.prompt().catch((e) => { globalThis.refused = e.name; }); 0",
+            )
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.refused")
+            .await
+            .unwrap(),
+        "InvalidStateError"
+    );
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    assert_eq!(installable_events(&engine).len(), 1);
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.prompts")
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_page_without_service_worker_is_not_offered() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(PWA_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    engine.tick().await.unwrap();
+
+    assert!(installable_events(&engine).is_empty());
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.prompts")
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(engine.accept_install_prompt().await.is_err());
+}
+
+#[cfg(feature = "test-util")]
+static USER_CONTENT_PAGES: &[(&str, &str, &[u8])] = &[(
+    "/",
+    "text/html",
+    b"<style>.brand { display: inline } .banner { display: block !important }</style>\
+      <p id=brand class=brand>brand</p><p class=banner>banner</p>\
+      <script>(globalThis.order = globalThis.order || []).push('page')</script>",
+)];
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_user_stylesheet_overrides_author_styles_only_when_important() {
+    use vulkan_browser_engine::core::user_content::{CascadeLevel, UserStyleOptions};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(USER_CONTENT_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let user_origin = || UserStyleOptions {
+        origins: vec!["http://127.0.0.1:*".to_string()],
+        cascade_level: CascadeLevel::User,
+    };
+    engine
+        .add_user_stylesheet(".brand { display: none }", user_origin())
+        .unwrap();
+    engine
+        .add_user_stylesheet(".banner { display: none !important }", user_origin())
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    let brand = engine.dump_computed_styles(".brand").await.unwrap();
+    assert_eq!(brand[0]["styles"]["display"], "inline");
+    let banner = engine.dump_computed_styles(".banner").await.unwrap();
+    assert_eq!(banner[0]["styles"]["display"], "none");
+
+    // At the author level it cascades after the page's own sheets instead.
+    let author = engine
+        .add_user_stylesheet(
+            ".brand { display: block }",
+            UserStyleOptions {
+                cascade_level: CascadeLevel::Author,
+                ..user_origin()
+            },
+        )
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    let brand = engine.dump_computed_styles(".brand").await.unwrap();
+    assert_eq!(brand[0]["styles"]["display"], "block");
+
+    // Removal applies from the next document on.
+    assert!(engine.remove_user_content(author));
+    assert!(!engine.remove_user_content(author));
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    let brand = engine.dump_computed_styles(".brand").await.unwrap();
+    assert_eq!(brand[0]["styles"]["display"], "inline");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_user_scripts_run_around_page_scripts_in_their_world() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::user_content::{RunAt, ScriptWorld, UserScriptOptions};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let host = spawn_page_host(USER_CONTENT_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let at = |run_at, world| UserScriptOptions {
+        origins: vec!["*".to_string()],
+        run_at,
+        world,
+    };
+    let push = |step: &str| {
+        format!(
+            "(globalThis.order = globalThis.order || []).push('{}')",
+            step
+        )
+    };
+    engine
+        .add_user_script(&push("idle"), at(RunAt::DocumentIdle, ScriptWorld::Main))
+        .unwrap();
+    engine
+        .add_user_script(&push("end"), at(RunAt::DocumentEnd, ScriptWorld::Main))
+        .unwrap();
+    let broken = engine
+        .add_user_script(
+            "throw new Error('shim broke')",
+            at(RunAt::DocumentStart, ScriptWorld::Main),
+        )
+        .unwrap();
+    engine
+        .add_user_script(&push("start"), at(RunAt::DocumentStart, ScriptWorld::Main))
+        .unwrap();
+    engine
+        .add_user_script(
+            "globalThis.secret = 1; \
+             document.getElementById('brand').setAttribute('data-seen', typeof order)",
+            at(RunAt::DocumentEnd, ScriptWorld::Isolated),
+        )
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    let order = engine.execute_javascript("order.join(',')").await.unwrap();
+    assert_eq!(order, "start,page,end,idle");
+
+    // The isolated script shared the DOM but none of the page's globals.
+    let seen = engine
+        .execute_javascript("document.getElementById('brand').getAttribute('data-seen')")
+        .await
+        .unwrap();
+    assert_eq!(seen, "undefined");
+    let secret = engine.execute_javascript("typeof secret").await.unwrap();
+    assert_eq!(secret, "undefined");
+
+    // The throwing script is reported under its own id, and the page loaded.
+    let errors = engine.get_recent_events(None, Some(EventKindMask::USER_SCRIPT_ERROR));
+    assert_eq!(errors.len(), 1);
+    match &errors[0].event {
+        LoggedEvent::Browser {
+            event:
+                BrowserEvent::UserScriptError {
+                    script_id, message, ..
+                },
+        } => {
+            assert_eq!(*script_id, broken.0);
+            assert!(message.contains("shim broke"));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_user_content_skips_pages_outside_its_origins() {
+    use vulkan_browser_engine::core::user_content::{
+        RunAt, ScriptWorld, UserScriptOptions, UserStyleOptions,
+    };
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(USER_CONTENT_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let elsewhere = vec![
+        "https://*.example.com".to_string(),
+        "http://127.0.0.1:1".to_string(),
+    ];
+    engine
+        .add_user_stylesheet(
+            ".banner { display: none !important }",
+            UserStyleOptions {
+                origins: elsewhere.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    engine
+        .add_user_script(
+            "globalThis.injected = true",
+            UserScriptOptions {
+                origins: elsewhere,
+                run_at: RunAt::DocumentStart,
+                world: ScriptWorld::Main,
+            },
+        )
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    let banner = engine.dump_computed_styles(".banner").await.unwrap();
+    assert_eq!(banner[0]["styles"]["display"], "block");
+    let injected = engine.execute_javascript("typeof injected").await.unwrap();
+    assert_eq!(injected, "undefined");
+
+    // A pattern that is not an origin is refused up front.
+    assert!(engine
+        .add_user_script(
+            "0",
+            UserScriptOptions {
+                origins: vec!["https://example.com/kiosk".to_string()],
+                ..Default::default()
+            },
+        )
+        .is_err());
+}
+
+#[cfg(feature = "test-util")]
+const TEXT_FRAGMENT_PAGES: &[(&str, &str, &[u8])] = &[(
+    "/article",
+    "text/html",
+    b"<body style=\"margin:0\">\
+      <div style=\"height:3000px\">Intro</div>\
+      <p>The quick <b>brown</b>\n   fox jumps over the lazy dog.</p>\
+      <div style=\"height:3000px\">Outro</div></body>",
+)];
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_text_fragment_scrolls_to_and_highlights_match_across_elements() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let host = spawn_page_host(TEXT_FRAGMENT_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig {
+        viewport_width: 800,
+        viewport_height: 600,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(&format!(
+            "{}/article#:~:text=quick-,brown%20fox,-jumps",
+            host
+        ))
+        .await
+        .unwrap();
+
+    assert!(engine.scroll_position().await.1 > 2000.0);
+    let highlight = engine.text_fragment_highlight().await;
+    assert!(!highlight.is_empty());
+    for rect in &highlight {
+        assert!(rect.y >= 0.0 && rect.y + rect.height <= 600.0, "{:?}", rect);
+    }
+
+    // Scripts see the URL without the directive.
+    let hash = engine.execute_javascript("location.hash").await.unwrap();
+    assert_eq!(hash, "");
+    let href = engine.execute_javascript("location.href").await.unwrap();
+    assert_eq!(href, format!("{}/article#", host).as_str());
+
+    // A click puts the highlight away.
+    engine
+        .handle_input_event(InputEvent::MouseClick {
+            x: 10,
+            y: 10,
+            button: 0,
+        })
+        .await
+        .unwrap();
+    assert!(engine.text_fragment_highlight().await.is_empty());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_text_fragment_without_match_loads_at_top() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(TEXT_FRAGMENT_PAGES).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    // The words are there, but not in this order.
+    engine
+        .load_url(&format!("{}/article#:~:text=fox%20brown", host))
+        .await
+        .unwrap();
+
+    assert_eq!(engine.scroll_position().await, (0.0, 0.0));
+    assert!(engine.text_fragment_highlight().await.is_empty());
+    let hash = engine.execute_javascript("location.hash").await.unwrap();
+    assert_eq!(hash, "");
+
+    // Turned off, the directive is an ordinary fragment.
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_text_fragments: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(&format!("{}/article#:~:text=brown%20fox", host))
+        .await
+        .unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 0.0));
+    assert!(engine.text_fragment_highlight().await.is_empty());
+    let hash = engine.execute_javascript("location.hash").await.unwrap();
+    assert_eq!(hash, "#:~:text=brown%20fox");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_clipboard_image_from_engine_reads_back_as_png_in_js() {
+    use vulkan_browser_engine::core::clipboard::ClipboardBitmap;
+    use vulkan_browser_engine::core::permissions::{Permission, PermissionState};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(&[("/", "text/html", b"<body></body>")]).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .write_clipboard_image(ClipboardBitmap {
+            width: 5,
+            height: 3,
+            rgba: vec![200; 5 * 3 * 4],
+        })
+        .unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    // Reading takes the permission.
+    engine
+        .execute_javascript(
+            "navigator.clipboard.read().then(\
+               () => { globalThis.result = 'read'; },\
+               (e) => { globalThis.result = e.name; })",
+        )
+        .await
+        .unwrap();
+    assert_eq!(wait_for_result(&engine).await, "NotAllowedError");
+
+    engine.set_permission(
+        &url::Url::parse(&host).unwrap(),
+        Permission::ClipboardRead,
+        Some(PermissionState::Granted),
+    );
+    engine
+        .execute_javascript(
+            "globalThis.result = undefined;\
+             navigator.clipboard.read().then(async (items) => {\
+               const blob = await items[0].getType('image/png');\
+               const header = new DataView(await blob.arrayBuffer());\
+               globalThis.result = [items[0].types, blob.type,\
+                 header.getUint32(16), header.getUint32(20)];\
+             })",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        wait_for_result(&engine).await,
+        serde_json::json!([["image/png"], "image/png", 5, 3])
+    );
+
+    // And what the page writes back arrives as a bitmap.
+    engine
+        .execute_javascript(
+            "globalThis.result = undefined;\
+             navigator.clipboard.read()\
+               .then((items) => items[0].getType('image/png'))\
+               .then((blob) => blob.slice(0, blob.size, 'image/png'))\
+               .then((png) => navigator.clipboard.write([\
+                 new ClipboardItem({ 'image/png': png, 'text/plain': 'caption' })]))\
+               .then(() => { globalThis.result = 'written'; },\
+                     (e) => { globalThis.result = e.name; })",
+        )
+        .await
+        .unwrap();
+    assert_eq!(wait_for_result(&engine).await, "written");
+    let bitmap = engine.read_clipboard_image().unwrap();
+    assert_eq!((bitmap.width, bitmap.height), (5, 3));
+    assert_eq!(engine.read_clipboard()[0].representations().len(), 2);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_paste_into_contenteditable_inserts_image_with_blob_url() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::core::clipboard::{ClipboardBitmap, ClipboardItem};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, KeyRoute};
+
+    let host = spawn_page_host(&[(
+        "/",
+        "text/html",
+        b"<div id=editor contenteditable>ab</div>\
+          <script>\
+            globalThis.pastes = [];\
+            document.getElementById('editor').addEventListener('paste', (e) => {\
+              const data = e.clipboardData;\
+              globalThis.pastes.push([data.types, data.getData('text/plain'),\
+                data.files.length ? data.files[0].type : null]);\
+            });\
+          </script>",
+    )])
+    .await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    assert!(engine.focus_element("#editor").await.unwrap());
+
+    engine
+        .write_clipboard_image(ClipboardBitmap {
+            width: 2,
+            height: 7,
+            rgba: vec![0; 2 * 7 * 4],
+        })
+        .unwrap();
+    assert_eq!(
+        engine.press_key("v", Modifiers::CTRL).await.unwrap(),
+        KeyRoute::Accelerator("paste".to_string())
+    );
+    // Typing goes on after the image.
+    engine.press_key("c", Modifiers::NONE).await.unwrap();
+
+    let src = engine
+        .execute_javascript("document.querySelector('img').getAttribute('src')")
+        .await
+        .unwrap();
+    let src = src.as_str().unwrap().to_string();
+    assert!(src.starts_with(&format!("blob:{}/", host)), "{}", src);
+    assert_eq!(
+        engine
+            .execute_javascript("document.getElementById('editor').innerHTML")
+            .await
+            .unwrap(),
+        format!("ab<img src=\"{}\">c", src).as_str()
+    );
+
+    // The blob URL resolves to the PNG through fetch.
+    engine
+        .execute_javascript(&format!(
+            "fetch('{}').then((r) => r.blob()).then(async (blob) => {{\
+               const header = new DataView(await blob.arrayBuffer());\
+               globalThis.result = [blob.type, header.getUint32(16), header.getUint32(20)];\
+             }}, (e) => {{ globalThis.result = String(e); }})",
+            src
+        ))
+        .await
+        .unwrap();
+    assert_eq!(
+        wait_for_result(&engine).await,
+        serde_json::json!(["image/png", 2, 7])
+    );
+
+    // Text goes in at the caret.
+    engine.write_clipboard(vec![ClipboardItem::text("xy")]);
+    engine.paste().await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("document.getElementById('editor').innerHTML")
+            .await
+            .unwrap(),
+        format!("ab<img src=\"{}\">cxy", src).as_str()
+    );
+    assert_eq!(
+        engine.execute_javascript("pastes").await.unwrap(),
+        serde_json::json!([[["Files"], "", "image/png"], [["text/plain"], "xy", null]])
+    );
+}
+
+/// A page running one external script big enough for its compile time to
+/// dwarf the engine's own preludes.
+#[cfg(feature = "test-util")]
+fn sizable_script_pages() -> &'static [(&'static str, &'static str, &'static [u8])] {
+    let mut script = String::from("globalThis.total = 0;\n");
+    for i in 0..20_000 {
+        script.push_str(&format!(
+            "(function f{i}(x) {{ var a = x * 3; for (var j = 0; j < 4; j++) {{ a += j; }} globalThis.total += a % 7; }})({i});\n"
+        ));
+    }
+    script.push_str("globalThis.result = globalThis.total;\n");
+    let script: &'static [u8] = Box::leak(script.into_bytes().into_boxed_slice());
+    Box::leak(Box::new([
+        (
+            "/",
+            "text/html",
+            &b"<html><body><script src=\"/big.js\"></script></body></html>"[..],
+        ),
+        ("/big.js", "text/javascript", script),
+    ]))
+}
+
+#[cfg(feature = "test-util")]
+fn code_cache_config(directory: &std::path::Path) -> vulkan_browser_engine::BrowserConfig {
+    use vulkan_browser_engine::js_engine::code_cache::CodeCacheConfig;
+
+    vulkan_browser_engine::BrowserConfig {
+        code_cache: CodeCacheConfig {
+            directory: Some(directory.to_path_buf()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_code_cache_warm_load_skips_compiling_external_script() {
+    use vulkan_browser_engine::{BrowserEngine, CacheKind};
+
+    let dir = tempfile::tempdir().unwrap();
+    let host = spawn_page_host(sizable_script_pages()).await;
+    let page = format!("{}/", host);
+
+    let cold = BrowserEngine::new(code_cache_config(dir.path()))
+        .await
+        .unwrap();
+    let before = cold.get_performance_metrics().await.javascript;
+    cold.load_url(&page).await.unwrap();
+    let after = cold.get_performance_metrics().await.javascript;
+    let expected = cold.execute_javascript("globalThis.result").await.unwrap();
+    assert!(!expected.is_null());
+    assert_eq!(after.code_cache_hits, 0);
+    assert_eq!(after.code_cache_misses, 1);
+    let cold_compile_ms = after.compile_time_ms - before.compile_time_ms;
+    assert!(cold_compile_ms > 0.0);
+
+    // A new session over the same directory.
+    let warm = BrowserEngine::new(code_cache_config(dir.path()))
+        .await
+        .unwrap();
+    let before = warm.get_performance_metrics().await.javascript;
+    warm.load_url(&page).await.unwrap();
+    let after = warm.get_performance_metrics().await.javascript;
+    assert_eq!(
+        warm.execute_javascript("globalThis.result").await.unwrap(),
+        expected
+    );
+    assert_eq!(after.code_cache_hits, 1);
+    assert_eq!(after.code_cache_misses, 0);
+    let warm_compile_ms = after.compile_time_ms - before.compile_time_ms;
+    assert!(
+        warm_compile_ms < cold_compile_ms * 0.7,
+        "warm compile {}ms, cold {}ms",
+        warm_compile_ms,
+        cold_compile_ms
+    );
+
+    warm.clear_cache(CacheKind::ScriptCache).await.unwrap();
+    assert!(!dir.path().join("data").exists());
+    assert!(!dir.path().join("index.json").exists());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_corrupt_code_cache_entry_falls_back_to_full_compile() {
+    use vulkan_browser_engine::BrowserEngine;
+
+    let dir = tempfile::tempdir().unwrap();
+    let host = spawn_page_host(sizable_script_pages()).await;
+    let page = format!("{}/", host);
+
+    let first = BrowserEngine::new(code_cache_config(dir.path()))
+        .await
+        .unwrap();
+    first.load_url(&page).await.unwrap();
+    let expected = first.execute_javascript("globalThis.result").await.unwrap();
+
+    let mut corrupted = 0;
+    for entry in std::fs::read_dir(dir.path().join("data")).unwrap() {
+        std::fs::write(entry.unwrap().path(), b"not V8 code cache data").unwrap();
+        corrupted += 1;
+    }
+    assert_eq!(corrupted, 1);
+
+    let second = BrowserEngine::new(code_cache_config(dir.path()))
+        .await
+        .unwrap();
+    second.load_url(&page).await.unwrap();
+    assert_eq!(
+        second
+            .execute_javascript("globalThis.result")
+            .await
+            .unwrap(),
+        expected
+    );
+    let metrics = second.get_performance_metrics().await.javascript;
+    assert_eq!(metrics.code_cache_hits, 0);
+    assert_eq!(metrics.code_cache_misses, 1);
+}
+
+fn external_playback_engine_config() -> vulkan_browser_engine::BrowserConfig {
+    use vulkan_browser_engine::core::media::MediaConfig;
+
+    vulkan_browser_engine::BrowserConfig {
+        media: MediaConfig {
+            external_playback: true,
+        },
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_muting_a_tab_mutes_its_media_and_reports_audibility() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::media::{PlaybackAction, PlaybackRequest};
+    use vulkan_browser_engine::{BrowserEngine, BrowserEvent, TabId};
+
+    let engine = BrowserEngine::new(external_playback_engine_config())
+        .await
+        .unwrap();
+    let outputs = Arc::new(Mutex::new(Vec::new()));
+    let seen = outputs.clone();
+    engine
+        .set_media_playback_handler(Some(move |request: PlaybackRequest| {
+            if request.action == PlaybackAction::SetOutput {
+                seen.lock().unwrap().push(request.muted);
+            }
+            true
+        }))
+        .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<video id=v src=\"http://127.0.0.1:9/clip.mp4\"></video><script>\
+             document.getElementById('v').play().then(() => { globalThis.outcome = 'playing'; });\
+             </script>",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+
+    let tab = engine.tab_id();
+    let state =
+        "[outcome, document.getElementById('v').paused, document.getElementById('v').muted]";
+    assert_eq!(
+        engine.execute_javascript(state).await.unwrap(),
+        serde_json::json!(["playing", false, false])
+    );
+    assert!(engine.is_tab_audible(tab));
+
+    engine.set_tab_muted(tab, true).await.unwrap();
+    assert_eq!(
+        engine.execute_javascript(state).await.unwrap(),
+        serde_json::json!(["playing", false, true])
+    );
+    assert!(!engine.is_tab_audible(tab));
+    assert_eq!(*outputs.lock().unwrap(), vec![true]);
+    assert!(engine.set_tab_muted(TabId(u64::MAX), false).await.is_err());
+
+    let audible: Vec<bool> = engine
+        .get_recent_events(None, Some(EventKindMask::AUDIBLE_STATE_CHANGED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::AudibleStateChanged { tab: from, audible },
+            } if from == tab => Some(audible),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(audible, vec![true, false]);
+}
+
+#[tokio::test]
+async fn test_autoplay_policy_needs_a_user_gesture_for_unmuted_media() {
+    use vulkan_browser_engine::core::media::PlaybackRequest;
+    use vulkan_browser_engine::core::permissions::{Permission, PermissionState};
+    use vulkan_browser_engine::{BrowserEngine, InputEvent};
+
+    let engine = BrowserEngine::new(external_playback_engine_config())
+        .await
+        .unwrap();
+    engine
+        .set_media_playback_handler(Some(|_: PlaybackRequest| true))
+        .unwrap();
+    engine.set_default_permission(Permission::Autoplay, PermissionState::Denied);
+    engine
+        .load_url(
+            "data:text/html,<video id=v src=\"http://127.0.0.1:9/clip.mp4\"></video>\
+             <audio id=a muted src=\"http://127.0.0.1:9/song.mp3\"></audio><script>\
+             const v = document.getElementById('v');\
+             v.play().then(() => { globalThis.outcome = 'played'; },\
+               (e) => { globalThis.outcome = [e.name, v.paused]; });\
+             document.getElementById('a').play().then(() => { globalThis.muted = 'played'; });\
+             v.addEventListener('click', () => v.play().then(() => { globalThis.clicked = 'played'; },\
+               (e) => { globalThis.clicked = e.name; }));\
+             </script>",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+    assert_eq!(
+        engine.execute_javascript("[outcome, muted]").await.unwrap(),
+        serde_json::json!([["NotAllowedError", true], "played"])
+    );
+
+    engine
+        .handle_input_event(InputEvent::MouseClick {
+            x: 20,
+            y: 20,
+            button: 0,
+        })
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("[clicked, document.getElementById('v').paused]")
+            .await
+            .unwrap(),
+        serde_json::json!(["played", false])
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_navigation_timing_breaks_down_a_load() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let host = spawn_page_host(&[
+        (
+            "/",
+            "text/html",
+            b"<link rel=stylesheet href=/style.css><p>timed</p>\
+              <script src=/app.js></script>\
+              <script>globalThis.started = performance.timing.navigationStart;\
+              globalThis.loadedDuring = performance.timing.loadEventEnd;</script>",
+        ),
+        ("/style.css", "text/css", b"p { color: green }"),
+        ("/app.js", "text/javascript", b"globalThis.app = 1;"),
+    ])
+    .await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    let timings = engine.get_navigation_timings();
+    assert_eq!(timings.len(), 1);
+    let timing = &timings[0];
+    let mut fields = vec![
+        timing.navigation_start,
+        timing.total_ms,
+        timing.fetch.time_to_first_byte_ms,
+        timing.stylesheets.blocking_fetch_ms,
+        timing.scripts.inline.compile_ms,
+        timing.scripts.inline.execute_ms,
+        timing.scripts.external.compile_ms,
+        timing.scripts.external.execute_ms,
+    ];
+    fields.extend(timing.phases());
+    assert!(
+        fields.iter().all(|ms| ms.is_finite() && *ms >= 0.0),
+        "{:?}",
+        timing
+    );
+    assert!(timing.fetch.time_to_first_byte_ms <= timing.fetch.total_ms);
+    let sum: f64 = timing.phases().iter().sum();
+    assert!(
+        (sum - timing.total_ms).abs() < 0.01,
+        "{} vs {}",
+        sum,
+        timing.total_ms
+    );
+
+    assert_eq!(timing.stylesheets.blocking_count, 1);
+    assert_eq!(timing.scripts.inline.count, 1);
+    assert_eq!(timing.scripts.external.count, 1);
+    assert!(timing.layout_node_count > 0);
+    let resources = &timing.subresources;
+    assert!(resources.stylesheet.count >= 1 && resources.script.count >= 1);
+    assert_eq!(
+        resources.total.count,
+        resources.stylesheet.count + resources.script.count
+    );
+    assert!((0.0..=1.0).contains(&resources.total.cache_hit_ratio));
+
+    // `performance.timing` reads the same clock.
+    let read = engine
+        .execute_javascript("[started, loadedDuring, performance.timing.loadEventEnd]")
+        .await
+        .unwrap();
+    assert_eq!(read[0].as_f64().unwrap(), timing.navigation_start.round());
+    assert_eq!(read[1], serde_json::json!(0));
+    let load_end = read[2].as_f64().unwrap();
+    assert!((load_end - (timing.navigation_start + timing.total_ms)).abs() <= 1.0);
+
+    let events = engine.get_recent_events(None, Some(EventKindMask::NAVIGATION_TIMING));
+    assert!(matches!(
+        &events[..],
+        [e] if matches!(&e.event, LoggedEvent::Browser {
+            event: BrowserEvent::NavigationTiming { timing: logged, .. },
+        } if **logged == *timing)
+    ));
+}
+
+/// A two-by-two checkerboard, black at the top left, counting its calls.
+struct Checkerboard(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+impl vulkan_browser_engine::renderer::custom_paint::PaintSource for Checkerboard {
+    fn paint(
+        &self,
+        input: &vulkan_browser_engine::renderer::custom_paint::PaintInput,
+    ) -> vulkan_browser_engine::renderer::custom_paint::PaintOutput {
+        use vulkan_browser_engine::renderer::custom_paint::{PaintCommand, PaintOutput};
+        use vulkan_browser_engine::renderer::Rect;
+
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let (w, h) = (input.width / 2.0, input.height / 2.0);
+        let cell = |col: f32, row: f32, color: [f32; 4]| PaintCommand::Rect {
+            bounds: Rect {
+                x: col * w,
+                y: row * h,
+                width: w,
+                height: h,
+            },
+            color,
+        };
+        let (black, white) = ([0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]);
+        PaintOutput::Commands(vec![
+            cell(0.0, 0.0, black),
+            cell(1.0, 0.0, white),
+            cell(0.0, 1.0, white),
+            cell(1.0, 1.0, black),
+        ])
+    }
+}
+
+fn paint_source_config() -> vulkan_browser_engine::BrowserConfig {
+    vulkan_browser_engine::BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 400,
+        viewport_height: 600,
+        // Slow CI machines still paint inside the frame.
+        paint_source_budget_ms: 5_000,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_paint_source_draws_and_caches_a_static_background() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use vulkan_browser_engine::BrowserEngine;
+
+    let engine = BrowserEngine::new(paint_source_config()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0} \
+             .p{width:40px;height:40px;background-image:-vk-paint(checker)}</style>\
+             <div class=p></div><div class=p></div>",
+        )
+        .await
+        .unwrap();
+    let unpainted = engine.snapshot().await.pixel(5, 5);
+
+    // Registered after the load, the source shows on the next frame.
+    let calls = Arc::new(AtomicUsize::new(0));
+    engine.register_paint_source("checker", Box::new(Checkerboard(calls.clone())));
+    engine.tick().await.unwrap();
+    let snapshot = engine.snapshot().await;
+    assert_ne!(snapshot.pixel(5, 5), unpainted);
+    for top in [0, 40] {
+        assert_eq!(snapshot.pixel(5, top + 5), [0, 0, 0, 255]);
+        assert_eq!(snapshot.pixel(25, top + 5), [255, 255, 255, 255]);
+        assert_eq!(snapshot.pixel(5, top + 25), [255, 255, 255, 255]);
+        assert_eq!(snapshot.pixel(25, top + 25), [0, 0, 0, 255]);
+    }
+    // Both boxes are the same size, so one call paints them.
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // Nor does painting the page again call a static source.
+    engine.tick().await.unwrap();
+    engine.resize_viewport(500, 600).await.unwrap();
+    engine.tick().await.unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(engine.snapshot().await.pixel(25, 25), [0, 0, 0, 255]);
+}
+
+#[tokio::test]
+async fn test_animated_paint_source_paints_once_per_frame() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use vulkan_browser_engine::renderer::custom_paint::{PaintInput, PaintOutput, PaintSource};
+    use vulkan_browser_engine::BrowserEngine;
+
+    struct Pulse(Arc<AtomicUsize>);
+
+    impl PaintSource for Pulse {
+        fn paint(&self, input: &PaintInput) -> PaintOutput {
+            let call = self.0.fetch_add(1, Ordering::SeqCst);
+            let (width, height) = input.device_size();
+            let shade = (call * 40 % 256) as u8;
+            PaintOutput::Pixels {
+                width,
+                height,
+                data: [shade, 0, 255 - shade, 255].repeat((width * height) as usize),
+            }
+        }
+
+        fn is_animated(&self) -> bool {
+            true
+        }
+    }
+
+    let engine = BrowserEngine::new(paint_source_config()).await.unwrap();
+    let calls = Arc::new(AtomicUsize::new(0));
+    engine.register_paint_source("pulse", Box::new(Pulse(calls.clone())));
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0} \
+             .p{width:30px;height:30px;background-image:-vk-paint(pulse)}</style>\
+             <div class=p></div>",
+        )
+        .await
+        .unwrap();
+
+    let frames = || async {
+        engine
+            .get_performance_metrics()
+            .await
+            .renderer
+            .frames_rendered
+    };
+    let (calls_before, frames_before) = (calls.load(Ordering::SeqCst), frames().await);
+    let first = engine.snapshot().await.pixel(5, 5);
+    for _ in 0..3 {
+        engine.tick().await.unwrap();
+    }
+    let rendered = frames().await - frames_before;
+    assert!(rendered >= 3, "ticks render while a source animates");
+    assert_eq!(
+        (calls.load(Ordering::SeqCst) - calls_before) as u64,
+        rendered
+    );
+    assert_ne!(engine.snapshot().await.pixel(5, 5), first);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_page_load_runs_against_a_mock_transport() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::{Credentials, NetworkManager, Priority};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let mock = Arc::new(MockTransport::new());
+    // base64("user:secret")
+    mock.route_fn("GET", "http://members.test/", |request| {
+        match request.header("Authorization") {
+            Some("Basic dXNlcjpzZWNyZXQ=") => MockResponse::ok(
+                "text/html",
+                "<link rel=stylesheet href=/site.css><p>Hello</p><img src=/hero.png>",
+            ),
+            _ => MockResponse::new(401).header("WWW-Authenticate", "Basic realm=\"members\""),
+        }
+    });
+    mock.route(
+        "GET",
+        "http://members.test/site.css",
+        MockResponse::ok("text/css", "p{color:green}").chunked(8, Default::default()),
+    );
+    let png = {
+        let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 255]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    };
+    mock.route(
+        "GET",
+        "http://members.test/hero.png",
+        MockResponse::ok("image/png", png),
+    );
+
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, mock.clone())
+        .await
+        .unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.set_auth_handler(Some(|_| async {
+        Some(Credentials::Basic {
+            username: "user".to_string(),
+            password: "secret".to_string(),
+        })
+    }));
+    // `.test` never resolves, so only the mock can have answered.
+    engine.load_url("http://members.test/").await.unwrap();
+    let timing = engine.get_navigation_timings().pop().unwrap();
+    assert_eq!(timing.subresources.stylesheet.count, 1);
+    assert_eq!(timing.subresources.image.count, 1);
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].url.as_str(), "http://members.test/");
+    assert_eq!(requests[0].header("Authorization"), None);
+    assert_eq!(requests[1].url.as_str(), "http://members.test/");
+    assert_eq!(
+        requests[1].header("Authorization"),
+        Some("Basic dXNlcjpzZWNyZXQ=")
+    );
+    assert_eq!(requests[1].priority, Priority::High);
+
+    let stylesheet = mock.requests_to("http://members.test/site.css");
+    assert_eq!(stylesheet.len(), 1);
+    assert_eq!(stylesheet[0].priority, Priority::High);
+    assert_eq!(stylesheet[0].header("Authorization"), None);
+    let image = mock.requests_to("http://members.test/hero.png");
+    assert_eq!(image.len(), 1);
+    assert_eq!(image[0].priority, Priority::Low);
+    assert!(requests.iter().all(|request| request.method == "GET"));
+    assert_eq!(
+        engine
+            .get_performance_metrics()
+            .await
+            .network
+            .failed_requests,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_translucent_colors_blend_in_linear_light() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0;background-color:black} \
+             div{width:40px;height:40px;background-color:rgba(255,255,255,0.5)}</style>\
+             <div></div>",
+        )
+        .await
+        .unwrap();
+
+    // Half of white's light, not half its encoded value, which is 128.
+    let [r, g, b, a] = engine.snapshot().await.pixel(20, 20);
+    assert_eq!([r, g, b], [188; 3]);
+    assert_eq!(a, 255);
+}
+
+#[tokio::test]
+async fn test_wide_gamut_and_oklab_colors_map_into_srgb() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0} div{width:20px;height:20px}</style>\
+             <div style='background-color:color(display-p3 1 0 0)'></div>\
+             <div style='background-color:oklch(0.628 0.2577 29.23)'></div>\
+             <div style='background-color:oklch(0.7 0.1 180deg)'></div>\
+             <div style='background-color:oklab(1 0 0 / 0.5)'></div>",
+        )
+        .await
+        .unwrap();
+
+    let snapshot = engine.snapshot().await;
+    // Redder than sRGB can show, so clipped to its red.
+    assert_eq!(snapshot.pixel(10, 10), [255, 0, 0, 255]);
+    assert_eq!(snapshot.pixel(10, 30), [255, 0, 0, 255]);
+    assert_eq!(snapshot.pixel(10, 50), [75, 179, 161, 255]);
+    assert_eq!(snapshot.pixel(10, 70), [255, 255, 255, 128]);
+}
+
+#[tokio::test]
+async fn test_pathological_pages_load_within_content_limits() {
+    use std::collections::BTreeSet;
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::core::content_limits::ContentLimits;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        content_limits: ContentLimits {
+            max_dom_depth: 64,
+            max_nodes: 20_000,
+            max_attribute_length: 1024,
+            max_text_length: 4096,
+            max_inline_script_length: 1024,
+            max_stylesheet_rules: 500,
+            max_selector_components: 16,
+        },
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+
+    // A fixed seed, so a failure reproduces.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = |bound: u64| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) % bound
+    };
+    let mut css = format!("{} {{ color: red }}", vec!["div"; 10_000].join(" "));
+    for i in 0..5_000 {
+        css.push_str(&format!(".c{} {{ margin: {}px }}", i, next(9)));
+    }
+    let mut html = format!(
+        "<style>{}</style><div title={}>{}</div><script>{}</script>",
+        css,
+        "a".repeat(100_000),
+        "word ".repeat(10_000),
+        "1;".repeat(10_000),
+    );
+    // Mostly opening tags, some stray closing ones: nesting far past the
+    // depth limit and nodes past the node limit.
+    let tags = ["div", "span", "p", "b", "ul", "li", "section", "em"];
+    for _ in 0..100_000 {
+        match next(10) {
+            0..=6 => html.push_str(&format!(
+                "<{} class=c{}>",
+                tags[next(8) as usize],
+                next(5_000)
+            )),
+            7 => html.push_str(&format!("</{}>", tags[next(8) as usize])),
+            _ => html.push_str("text "),
+        }
+    }
+
+    let started = Instant::now();
+    engine
+        .load_url(&format!("data:text/html,{html}"))
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(30));
+
+    let breached: BTreeSet<&str> = engine
+        .get_content_limit_breaches()
+        .iter()
+        .map(|breach| breach.limit.metric())
+        .collect();
+    let all = BTreeSet::from([
+        "dom_depth",
+        "dom_nodes",
+        "attribute_length",
+        "text_length",
+        "inline_script_length",
+        "stylesheet_rules",
+        "selector_components",
+    ]);
+    assert_eq!(breached, all);
+
+    // Each is warned of once, however often the page restyles.
+    let warnings = engine.get_recent_events(None, Some(EventKindMask::PERFORMANCE_WARNING));
+    let warnings = serde_json::to_value(&warnings).unwrap();
+    let warnings = warnings.as_array().unwrap();
+    assert_eq!(warnings.len(), all.len());
+    let warned: BTreeSet<&str> = warnings
+        .iter()
+        .map(|warning| warning["event"]["metric"].as_str().unwrap())
+        .collect();
+    assert_eq!(warned, all);
+
+    // The next page starts with a clean slate.
+    engine
+        .load_url("data:text/html,<p>plain</p>")
+        .await
+        .unwrap();
+    assert!(engine.get_content_limit_breaches().is_empty());
+}
+
+#[tokio::test]
+async fn test_element_from_point_follows_paint_order_clips_and_pointer_events() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 400,
+        viewport_height: 300,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    // `a` holds `b` over its top left and a `pointer-events: none` overlay
+    // over its bottom half; `clip` cuts `wide` off at 60px.
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0} p{margin:0}</style>\
+             <body id=body>\
+             <div id=a style=\"width:100px;height:100px;background-color:red\">\
+             <div id=b style=\"width:50px;height:50px;background-color:blue\"></div>\
+             <div id=overlay style=\"width:100px;height:50px;pointer-events:none;\
+             background-color:green\"><div id=under style=\"width:20px;height:20px\"></div></div>\
+             </div>\
+             <div id=clip style=\"width:60px;height:30px;overflow:hidden\">\
+             <div id=wide style=\"width:200px;height:30px;background-color:black\"></div></div>\
+             <p id=text>Words</p></body>",
+        )
+        .await
+        .unwrap();
+
+    // Point, then the stack `elementsFromPoint` lists, topmost first.
+    let probes: &[((f32, f32), &[&str])] = &[
+        ((10.0, 10.0), &["b", "a", "body"]),
+        ((75.0, 10.0), &["a", "body"]),
+        // The overlay and what it holds let hits through.
+        ((10.0, 60.0), &["a", "body"]),
+        ((75.0, 90.0), &["a", "body"]),
+        ((10.0, 115.0), &["wide", "clip", "body"]),
+        ((100.0, 115.0), &["body"]),
+        // Text is hit as the element holding it, listed once.
+        ((3.0, 135.0), &["text", "body"]),
+    ];
+    for ((x, y), expected) in probes {
+        let stack = engine
+            .execute_javascript(&format!(
+                "document.elementsFromPoint({x}, {y}).map((e) => e.getAttribute('id'))"
+            ))
+            .await
+            .unwrap();
+        let stack: Vec<String> = serde_json::from_value(stack).unwrap();
+        assert_eq!(&stack, expected, "stack at ({x}, {y})");
+
+        let top = engine
+            .execute_javascript(&format!(
+                "document.elementFromPoint({x}, {y}).getAttribute('id')"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(top, expected[0], "element at ({x}, {y})");
+
+        // The engine's answer, which clicks go to, is the same element.
+        let node_id = engine
+            .execute_javascript(&format!(
+                "document.getElementById('{}').__nodeId",
+                expected[0]
+            ))
+            .await
+            .unwrap();
+        let hit = engine.element_at(*x, *y).await.unwrap();
+        assert_eq!(
+            hit.map(|node_id| node_id.0.to_string()),
+            node_id.as_str().map(str::to_string)
+        );
+        assert_eq!(
+            engine.elements_at(*x, *y).await.unwrap().len(),
+            expected.len()
+        );
+    }
+
+    // Nothing is hit outside the viewport.
+    for (x, y) in [(-1.0, 10.0), (10.0, -1.0), (400.0, 10.0), (10.0, 300.0)] {
+        assert_eq!(engine.element_at(x, y).await.unwrap(), None);
+        let top = engine
+            .execute_javascript(&format!("document.elementFromPoint({x}, {y})"))
+            .await
+            .unwrap();
+        assert!(top.is_null());
+    }
+    let thrown = engine
+        .execute_javascript(
+            "try { document.elementFromPoint(NaN, 0); 'no error' } catch (e) { e.name }",
+        )
+        .await
+        .unwrap();
+    assert_eq!(thrown, "TypeError");
+}
+
+/// A worker that answers every navigation with a page recording its
+/// version and what it heard of its lifecycle in `globalThis.worker`.
+#[cfg(feature = "test-util")]
+fn logging_service_worker(version: u32, on_install: &str) -> String {
+    format!(
+        "const log = [];\
+         self.serviceWorker.addEventListener('statechange', () => log.push(self.serviceWorker.state));\
+         self.registration.addEventListener('updatefound', () => log.push('updatefound'));\
+         self.addEventListener('install', () => {{ {} }});\
+         self.addEventListener('fetch', (event) => {{\
+           const page = {{ version: {}, log }};\
+           event.respondWith(new Response('<script>globalThis.worker = ' + JSON.stringify(page) + '</script>',\
+             {{ headers: {{ 'Content-Type': 'text/html' }} }}));\
+         }});",
+        on_install, version
+    )
+}
+
+/// Serves `/sw.js` from `script` as it is at the time of the request, and a
+/// page from the network anywhere else.
+#[cfg(feature = "test-util")]
+async fn spawn_service_worker_host(script: std::sync::Arc<std::sync::Mutex<String>>) -> String {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = std::sync::Arc::new(MockTransport::new());
+    let host = support::serve(mock.clone()).await;
+    mock.route(
+        "*",
+        &format!("{host}/*"),
+        MockResponse::ok("text/html", "<p id=source>network</p>")
+            .header("Cache-Control", "no-store"),
+    );
+    mock.route_fn("*", &format!("{host}/sw.js"), move |_| {
+        MockResponse::ok("text/javascript", script.lock().unwrap().clone())
+            .header("Cache-Control", "no-store")
+    });
+    host
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_service_worker_registration_survives_restart_and_controls_navigation() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::storage::StorageConfig;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = || BrowserConfig {
+        storage: StorageConfig {
+            directory: Some(dir.path().to_path_buf()),
+            partition_third_party: false,
+        },
+        ..Default::default()
+    };
+    let script = Arc::new(Mutex::new(logging_service_worker(1, "")));
+    let host = spawn_service_worker_host(script.clone()).await;
+    let scope = format!("{}/", host);
+
+    let engine = BrowserEngine::new(config()).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+    engine.shutdown().await.unwrap();
+    drop(engine);
+
+    // The network no longer has the worker; the stored copy runs.
+    *script.lock().unwrap() = String::new();
+    let engine = BrowserEngine::new(config()).await.unwrap();
+    let registrations = engine.get_service_worker_registrations().await;
+    assert_eq!(registrations.len(), 1);
+    assert_eq!(registrations[0].scope, scope);
+    assert!(registrations[0].active.is_some());
+    engine.load_url(&format!("{}/app", host)).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker.version")
+            .await
+            .unwrap(),
+        1
+    );
+
+    // Unregistering forgets it for the next start; the page it controls
+    // stays controlled until it goes.
+    assert!(engine.unregister_service_worker(&scope).await.unwrap());
+    assert!(!engine.unregister_service_worker(&scope).await.unwrap());
+    engine.shutdown().await.unwrap();
+    drop(engine);
+
+    let engine = BrowserEngine::new(config()).await.unwrap();
+    assert!(engine.get_service_worker_registrations().await.is_empty());
+    engine.load_url(&format!("{}/app", host)).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("document.getElementById('source').textContent")
+            .await
+            .unwrap(),
+        "network"
+    );
+}
+
+#[cfg(feature = "test-util")]
+async fn active_script_hash(engine: &vulkan_browser_engine::BrowserEngine) -> String {
+    let registrations = engine.get_service_worker_registrations().await;
+    registrations[0]
+        .active
+        .as_ref()
+        .unwrap()
+        .script_hash
+        .clone()
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_updated_service_worker_waits_for_clients_unless_it_skips_waiting() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::storage::StorageConfig;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = BrowserConfig {
+        storage: StorageConfig {
+            directory: Some(dir.path().to_path_buf()),
+            partition_third_party: false,
+        },
+        ..Default::default()
+    };
+    let script = Arc::new(Mutex::new(logging_service_worker(1, "")));
+    let host = spawn_service_worker_host(script.clone()).await;
+    let app = format!("{}/app", host);
+    let engine = BrowserEngine::new(config).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+
+    engine.load_url(&app).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker")
+            .await
+            .unwrap(),
+        serde_json::json!({"version": 1, "log": ["installed", "activating", "activated"]})
+    );
+    let first = active_script_hash(&engine).await;
+
+    // The navigation finds the new script and installs it, but this page
+    // is still the first worker's.
+    *script.lock().unwrap() = logging_service_worker(2, "");
+    engine.load_url(&app).await.unwrap();
+    let registrations = engine.get_service_worker_registrations().await;
+    assert!(registrations[0].waiting.is_some());
+    assert_eq!(active_script_hash(&engine).await, first);
+
+    // A page replacing it in the scope keeps the new worker waiting.
+    engine.load_url(&app).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker")
+            .await
+            .unwrap(),
+        serde_json::json!({
+            "version": 1,
+            "log": ["installed", "activating", "activated", "updatefound"]
+        })
+    );
+    assert!(engine.get_service_worker_registrations().await[0]
+        .waiting
+        .is_some());
+
+    // Once the scope has no page left, it activates.
+    engine
+        .load_url("data:text/html,<p>elsewhere</p>")
+        .await
+        .unwrap();
+    let registrations = engine.get_service_worker_registrations().await;
+    assert!(registrations[0].waiting.is_none());
+    let second = active_script_hash(&engine).await;
+    assert_ne!(second, first);
+    engine.load_url(&app).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker")
+            .await
+            .unwrap(),
+        serde_json::json!({"version": 2, "log": ["installed", "activating", "activated"]})
+    );
+
+    // A worker that skips waiting takes over the page at once.
+    *script.lock().unwrap() = logging_service_worker(3, "self.skipWaiting();");
+    engine.load_url(&app).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker.version")
+            .await
+            .unwrap(),
+        2
+    );
+    let registrations = engine.get_service_worker_registrations().await;
+    assert!(registrations[0].waiting.is_none());
+    assert_ne!(active_script_hash(&engine).await, second);
+    engine.load_url(&app).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("globalThis.worker.version")
+            .await
+            .unwrap(),
+        3
+    );
+}
+
+#[cfg(feature = "test-util")]
+const PUSH_WORKER: &[(&str, &str, &[u8])] = &[(
+    "/sw.js",
+    "text/javascript",
+    b"self.addEventListener('activate', (event) => {\
+        event.waitUntil(self.registration.pushManager.subscribe({ userVisibleOnly: true }));\
+      });\
+      self.addEventListener('push', (event) => {\
+        const message = event.data.json();\
+        event.waitUntil(self.registration.showNotification(message.title, {\
+          body: message.body,\
+          tag: 'build'\
+        }));\
+      });\
+      self.addEventListener('pushsubscriptionchange', (event) => {\
+        event.waitUntil(self.registration.pushManager.subscribe({ userVisibleOnly: true }));\
+      });\
+      self.addEventListener('sync', (event) => {\
+        event.waitUntil(self.registration.showNotification('Synced ' + event.tag));\
+      });\
+      self.registration.sync.register('outbox');",
+)];
+
+/// The application server side of RFC 8291: `plaintext` encrypted to a
+/// subscription's `p256dh` and `auth`, as a single `aes128gcm` record.
+#[cfg(feature = "test-util")]
+fn encrypt_push_message(p256dh: &str, auth: &str, plaintext: &[u8]) -> Vec<u8> {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+    use rand::RngCore;
+    use ring::{aead, hkdf};
+
+    struct Len(usize);
+    impl hkdf::KeyType for Len {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+    let derive = |salt: &[u8], ikm: &[u8], info: &[&[u8]], len: usize| {
+        let mut output = vec![0; len];
+        hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+            .extract(ikm)
+            .expand(info, Len(len))
+            .unwrap()
+            .fill(&mut output)
+            .unwrap();
+        output
+    };
+
+    let user_agent_key = URL_SAFE_NO_PAD.decode(p256dh).unwrap();
+    let auth = URL_SAFE_NO_PAD.decode(auth).unwrap();
+    let sender = p256::SecretKey::random(&mut rand::rngs::OsRng);
+    let sender_key = sender
+        .public_key()
+        .to_encoded_point(false)
+        .as_bytes()
+        .to_vec();
+    let shared = p256::ecdh::diffie_hellman(
+        sender.to_nonzero_scalar(),
+        p256::PublicKey::from_sec1_bytes(&user_agent_key)
+            .unwrap()
+            .as_affine(),
+    );
+    let mut salt = [0u8; 16];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+
+    let ikm = derive(
+        &auth,
+        shared.raw_secret_bytes(),
+        &[b"WebPush: info\0", &user_agent_key, &sender_key],
+        32,
+    );
+    let cek = derive(&salt, &ikm, &[b"Content-Encoding: aes128gcm\0"], 16);
+    let nonce = derive(&salt, &ikm, &[b"Content-Encoding: nonce\0"], 12);
+    let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+    let mut record = plaintext.to_vec();
+    record.push(2);
+    key.seal_in_place_append_tag(
+        aead::Nonce::try_assume_unique_for_key(&nonce).unwrap(),
+        aead::Aad::empty(),
+        &mut record,
+    )
+    .unwrap();
+
+    let mut body = salt.to_vec();
+    body.extend_from_slice(&4096u32.to_be_bytes());
+    body.push(sender_key.len() as u8);
+    body.extend_from_slice(&sender_key);
+    body.extend_from_slice(&record);
+    body
+}
+
+#[cfg(feature = "test-util")]
+fn requested_notifications(
+    engine: &vulkan_browser_engine::BrowserEngine,
+) -> Vec<vulkan_browser_engine::pwa::service_worker::Notification> {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::BrowserEvent;
+
+    engine
+        .get_recent_events(None, Some(EventKindMask::NOTIFICATION_REQUESTED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::NotificationRequested { notification },
+            } => Some(notification),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_push_message_is_decrypted_for_the_worker_which_shows_a_notification() {
+    use vulkan_browser_engine::core::storage::StorageConfig;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = || BrowserConfig {
+        storage: StorageConfig {
+            directory: Some(dir.path().to_path_buf()),
+            partition_third_party: false,
+        },
+        ..Default::default()
+    };
+    let host = spawn_page_host(PUSH_WORKER).await;
+    let engine = BrowserEngine::new(config()).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+
+    let subscriptions = engine.get_push_subscriptions().await;
+    assert_eq!(subscriptions.len(), 1);
+    let subscription = subscriptions[0].clone();
+    assert_eq!(subscription.scope, format!("{}/", host));
+    assert!(subscription.endpoint.ends_with(&subscription.id));
+
+    let payload = encrypt_push_message(
+        &subscription.p256dh,
+        &subscription.auth,
+        br#"{"title":"Build finished","body":"All green"}"#,
+    );
+    assert!(engine
+        .deliver_push_message(&subscription.id, &payload)
+        .await
+        .unwrap());
+    let notifications = requested_notifications(&engine);
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].title, "Build finished");
+    assert_eq!(notifications[0].body, "All green");
+    assert_eq!(notifications[0].tag, "build");
+    assert_eq!(notifications[0].scope, subscription.scope);
+
+    // A message not encrypted to the subscription is turned away.
+    let mut tampered = payload.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(engine
+        .deliver_push_message(&subscription.id, &tampered)
+        .await
+        .is_err());
+
+    // The keys outlive the engine; the worker is started for the message,
+    // addressed by origin this time.
+    engine.shutdown().await.unwrap();
+    drop(engine);
+    let engine = BrowserEngine::new(config()).await.unwrap();
+    assert_eq!(
+        engine.get_push_subscriptions().await,
+        vec![subscription.clone()]
+    );
+    let payload = encrypt_push_message(
+        &subscription.p256dh,
+        &subscription.auth,
+        br#"{"title":"Deployed","body":"v2 is live"}"#,
+    );
+    assert!(engine.deliver_push_message(&host, &payload).await.unwrap());
+    assert_eq!(requested_notifications(&engine)[0].title, "Deployed");
+
+    // An invalidated subscription is replaced by the worker.
+    let renewed = engine
+        .expire_push_subscription(&subscription.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_ne!(renewed.id, subscription.id);
+    assert_ne!(renewed.p256dh, subscription.p256dh);
+    assert_eq!(engine.get_push_subscriptions().await, vec![renewed]);
+    assert!(engine
+        .deliver_push_message(&subscription.id, &payload)
+        .await
+        .is_err());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_background_sync_fires_once_for_a_registered_tag() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(PUSH_WORKER).await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .register_service_worker(&format!("{}/sw.js", host))
+        .await
+        .unwrap();
+    let scope = format!("{}/", host);
+    assert_eq!(
+        engine.get_service_worker_registrations().await[0].sync_tags,
+        vec!["outbox".to_string()]
+    );
+
+    assert!(engine
+        .fire_background_sync(&scope, "outbox", false)
+        .await
+        .unwrap());
+    assert_eq!(requested_notifications(&engine)[0].title, "Synced outbox");
+    assert!(engine.get_service_worker_registrations().await[0]
+        .sync_tags
+        .is_empty());
+    assert!(engine
+        .fire_background_sync(&scope, "outbox", false)
+        .await
+        .is_err());
+}
+
+#[test]
+fn test_push_keys_decrypt_the_rfc_8291_example() {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    use vulkan_browser_engine::pwa::push::{PushError, PushKeys, StoredSubscription};
+
+    let keys = PushKeys::from_stored(&StoredSubscription {
+        id: "example".to_string(),
+        private_key: "q1dXpw3UpT5VOmu_cf_v6ih07Aems3njxI-JWgLcM94".to_string(),
+        auth: "BTBZMqHH6r4Tts7J_aSIgg".to_string(),
+        application_server_key: None,
+    })
+    .unwrap();
+    assert_eq!(
+        URL_SAFE_NO_PAD.encode(keys.public_key()),
+        "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4"
+    );
+
+    let mut body = URL_SAFE_NO_PAD
+        .decode(
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A8",
+        )
+        .unwrap();
+    body.extend(
+        URL_SAFE_NO_PAD
+            .decode(
+                "8pfeW0KbunFT06SuDKoJH9Ql87S1QUrdirN6GcG7sFz1y1sqLgVi1VhjVkHsUoEsbI_0LpXMuGvnzQ",
+            )
+            .unwrap(),
+    );
+    assert_eq!(
+        keys.decrypt(&body).unwrap(),
+        b"When I grow up, I want to be a watermelon"
+    );
+    assert_eq!(
+        keys.decrypt(&body[..40]),
+        Err(PushError::Malformed("truncated header"))
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_prerendered_page_activates_without_loading_again() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::NetworkManager;
+    use vulkan_browser_engine::core::prerender::PrerenderDiscardReason;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://shop.test/",
+        MockResponse::ok("text/html", "<p>Home</p>"),
+    );
+    mock.route(
+        "GET",
+        "http://shop.test/cart",
+        MockResponse::ok(
+            "text/html",
+            "<p>Cart</p><script>\
+             globalThis.seen = [];\
+             document.addEventListener('DOMContentLoaded', () => seen.push(\
+               'DOMContentLoaded ' + document.visibilityState + ' ' + document.prerendering));\
+             document.addEventListener('visibilitychange', () => seen.push(\
+               'visibilitychange ' + document.visibilityState));\
+             document.addEventListener('prerenderingchange', () => seen.push(\
+               'prerenderingchange ' + document.prerendering));\
+             </script>",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://shop.test/account",
+        MockResponse::ok("text/html", "<p>Account</p>")
+            .header("Cache-Control", "private, no-store"),
+    );
+
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, mock.clone())
+        .await
+        .unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://shop.test/").await.unwrap();
+
+    let cart = engine.prerender("http://shop.test/cart").await.unwrap();
+    engine.prerender("http://shop.test/account").await.unwrap();
+    let discarded = engine.get_recent_events(None, Some(EventKindMask::PRERENDER_DISCARDED));
+    assert!(matches!(
+        &discarded[..],
+        [e] if matches!(&e.event, LoggedEvent::Browser {
+            event: BrowserEvent::PrerenderDiscarded { url, reason: PrerenderDiscardReason::NoStore, .. },
+        } if url == "http://shop.test/account")
+    ));
+    // Still showing the page it was showing.
+    assert_eq!(
+        engine.execute_javascript("document.URL").await.unwrap(),
+        serde_json::json!("http://shop.test/")
+    );
+
+    engine.activate_prerender(&cart).await.unwrap();
+    assert_eq!(mock.requests_to("http://shop.test/cart").len(), 1);
+    assert_eq!(
+        engine.execute_javascript("seen").await.unwrap(),
+        serde_json::json!([
+            "DOMContentLoaded hidden true",
+            "visibilitychange visible",
+            "prerenderingchange false",
+        ])
+    );
+    assert_eq!(
+        engine.get_current_url().await.as_deref(),
+        Some("http://shop.test/cart")
+    );
+
+    let timing = engine.get_navigation_timings().pop().unwrap();
+    assert_eq!(timing.url, "http://shop.test/cart");
+    assert_eq!(timing.fetch.total_ms, 0.0);
+    assert_eq!(timing.parse_ms, 0.0);
+    assert_eq!(timing.style_ms, 0.0);
+    assert_eq!(timing.layout_ms, 0.0);
+    assert_eq!(timing.scripts.total_ms, 0.0);
+    let sum: f64 = timing.phases().iter().sum();
+    assert!((sum - timing.total_ms).abs() < 0.01);
+
+    // A discarded prerender's handle just navigates.
+    let account = engine.prerender("http://shop.test/account").await.unwrap();
+    engine.activate_prerender(&account).await.unwrap();
+    assert_eq!(
+        engine
+            .execute_javascript("document.prerendering")
+            .await
+            .unwrap(),
+        serde_json::json!(false)
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_prerendered_page_events_reach_the_tab_on_activation() {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEvent};
+
+    let mock = std::sync::Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://shop.test/cart",
+        MockResponse::ok(
+            "text/html",
+            "<p>Cart</p><script>throw new Error('cart broke')</script>",
+        ),
+    );
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let engine = support::mock_engine(config, &mock).await;
+    engine.load_url("data:text/html,<p>Home</p>").await.unwrap();
+    let mut events = engine.subscribe_events();
+
+    let cart = engine.prerender("http://shop.test/cart").await.unwrap();
+    // Nothing of the hidden page is told while it stays hidden.
+    assert!(events.try_recv().is_err());
+
+    engine.activate_prerender(&cart).await.unwrap();
+    let mut received = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        received.push(event);
+    }
+    let errors = received
+        .iter()
+        .filter(|event| match event {
+            BrowserEvent::JavaScriptError { message, .. } => message.contains("cart broke"),
+            _ => false,
+        })
+        .count();
+    assert_eq!(errors, 1);
+    // The navigation is the activation's, reported once.
+    let started = received
+        .iter()
+        .filter(|event| matches!(event, BrowserEvent::NavigationStarted { .. }))
+        .count();
+    assert_eq!(started, 1);
+}
+
+#[tokio::test]
+async fn test_live_region_changes_are_announced_once_per_region() {
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::live_regions::{Announcement, Politeness};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body id='page'>\
+             <div id='status' aria-live='polite'>Idle</div>\
+             <div id='offscreen' aria-live='assertive' style='display: none'></div>\
+             <div aria-hidden='true'><p id='muted' role='status'></p></div>\
+             <script>\
+             function say(parent, text) {\
+               const span = document.createElement('span');\
+               span.textContent = text;\
+               parent.appendChild(span);\
+             }\
+             </script></body>",
+        )
+        .await
+        .unwrap();
+    let heard: Arc<Mutex<Vec<Announcement>>> = Arc::default();
+    let sink = heard.clone();
+    engine.set_announcement_handler(Some(move |announcement| sink.lock().push(announcement)));
+    let said = || {
+        heard
+            .lock()
+            .drain(..)
+            .map(|a: Announcement| (a.politeness, a.text))
+            .collect::<Vec<_>>()
+    };
+
+    engine
+        .execute_javascript(
+            "const region = document.getElementById('status');\
+             say(region, 'Saved'); say(region, '3   items');",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        said(),
+        vec![(Politeness::Polite, "Saved 3 items".to_string())]
+    );
+
+    // The alert goes first though the polite change came before it.
+    engine
+        .execute_javascript(
+            "say(region, 'Syncing');\
+             const banner = document.createElement('div');\
+             banner.setAttribute('role', 'alert');\
+             banner.textContent = 'Connection lost';\
+             document.getElementById('page').appendChild(banner);",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        said(),
+        vec![
+            (Politeness::Assertive, "Connection lost".to_string()),
+            (Politeness::Polite, "Syncing".to_string()),
+        ]
+    );
+
+    engine
+        .execute_javascript(
+            "say(document.getElementById('offscreen'), 'Hidden');\
+             document.getElementById('muted').textContent = 'Muted';",
+        )
+        .await
+        .unwrap();
+    engine.tick().await.unwrap();
+    assert!(said().is_empty());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_document_write_is_parsed_at_the_writing_script() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::NetworkManager;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://write.test/",
+        MockResponse::ok(
+            "text/html",
+            "<body><script>globalThis.order = ['first'];\
+             globalThis.warned = [];\
+             console.warn = (message) => warned.push(message);\
+             document.write('<p id=written>w</p><script src=/written.js></' + 'script>');\
+             </script><p id=after>a</p>\
+             <script>order.push('second:' + !!document.getElementById('written'));</script>\
+             <script async src=/async.js></script></body>",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://write.test/written.js",
+        MockResponse::ok(
+            "text/javascript",
+            "order.push('written'); document.write('<i id=nested></i>');",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://write.test/async.js",
+        MockResponse::ok(
+            "text/javascript",
+            "globalThis.asyncRan = true; document.write('<b id=late></b>');",
+        ),
+    );
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, mock).await.unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://write.test/").await.unwrap();
+
+    // The written script ran before the one after it in the markup.
+    assert_eq!(
+        engine.execute_javascript("order").await.unwrap(),
+        serde_json::json!(["first", "written", "second:true"])
+    );
+    let ids = engine
+        .execute_javascript("Array.from(document.body.querySelectorAll('[id]'), (e) => e.id)")
+        .await
+        .unwrap();
+    assert_eq!(ids, serde_json::json!(["written", "nested", "after"]));
+    // The async script ran, but had nowhere to write, and the page was told.
+    assert_eq!(
+        engine.execute_javascript("asyncRan").await.unwrap(),
+        serde_json::json!(true)
+    );
+    assert_eq!(
+        engine.execute_javascript("warned.length").await.unwrap(),
+        serde_json::json!(1)
+    );
+}
+
+#[tokio::test]
+async fn test_an_element_left_open_by_document_write_takes_in_what_follows() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body><script>document.write('<div id=wrap>')</script>\
+             <p id=inside>x</p></body>",
+        )
+        .await
+        .unwrap();
+
+    let parent = engine
+        .execute_javascript("document.getElementById('inside').parentElement.id")
+        .await
+        .unwrap();
+    assert_eq!(parent, serde_json::json!("wrap"));
+}
+
+#[tokio::test]
+async fn test_document_write_after_load_replaces_the_document() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p id=old>old</p>")
+        .await
+        .unwrap();
+    engine
+        .execute_javascript("document.write('<p id=new>new</p>'); 0")
+        .await
+        .unwrap();
+
+    let ids = engine
+        .execute_javascript("[!!document.getElementById('new'), !!document.getElementById('old')]")
+        .await
+        .unwrap();
+    assert_eq!(ids, serde_json::json!([true, false]));
+    let kinds: Vec<_> = engine
+        .get_recent_events(
+            None,
+            Some(
+                EventKindMask::DOCUMENT_REOPENED
+                    | EventKindMask::NAVIGATION_STARTED
+                    | EventKindMask::PAGE_LOADED,
+            ),
+        )
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser { event } => Some(event),
+            _ => None,
+        })
+        .map(|event| match event {
+            BrowserEvent::DocumentReopened { .. } => "reopened",
+            BrowserEvent::NavigationStarted { .. } => "started",
+            BrowserEvent::PageLoaded { .. } => "loaded",
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        ["started", "loaded", "reopened", "started", "loaded"]
+    );
+}
+
+#[tokio::test]
+async fn test_spatial_navigation_moves_focus_across_a_grid_of_cards() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::core::spatial_navigation::Direction;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, KeyRoute};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 400,
+        viewport_height: 300,
+        enable_spatial_navigation: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    // Three rows of cards 100px wide, 120px apart: B2 is disabled, C1 has a
+    // negative tabindex and C2 is inert. D1 is far below the fold.
+    engine
+        .load_url(
+            "data:text/html,<style>body{margin:0} .row{display:flex;height:80px}\
+             .card{display:block;width:100px;height:60px;margin:10px}</style>\
+             <div class=row><a class=card id=a1 href=%23a1>A1</a>\
+             <div class=card id=a2 tabindex=0>A2</div><button class=card id=a3>A3</button></div>\
+             <div class=row><div class=card id=b1 tabindex=0>B1</div>\
+             <button class=card id=b2 disabled>B2</button><div class=card id=b3 tabindex=0>B3</div></div>\
+             <div class=row><div class=card id=c1 tabindex=-1>C1</div>\
+             <div inert><div class=card id=c2 tabindex=0>C2</div></div></div>\
+             <div style=\"height:400px\"></div>\
+             <div class=row><div class=card id=d1 tabindex=0 onclick=\"window.clicked = this.id\">D1</div></div>",
+        )
+        .await
+        .unwrap();
+
+    let mut cards = Vec::new();
+    for id in ["a1", "a2", "a3", "b1", "b2", "b3", "c1", "c2", "d1"] {
+        let node_id = engine
+            .execute_javascript(&format!("document.getElementById('{id}').__nodeId"))
+            .await
+            .unwrap();
+        let node_id: u64 = node_id.as_str().unwrap().parse().unwrap();
+        cards.push((id, NodeId(node_id)));
+    }
+    let press = |key: &'static str| engine.press_key(key, Modifiers::NONE);
+    let name = |node: Option<NodeId>| {
+        cards
+            .iter()
+            .find(|(_, id)| Some(*id) == node)
+            .map(|(name, _)| *name)
+    };
+
+    // Without focus, a move starts from the viewport's edge.
+    assert_eq!(
+        name(engine.spatial_navigation_search(Direction::Down).await),
+        Some("a1")
+    );
+    assert_eq!(engine.focused_element().await, None);
+
+    let presses = [
+        ("ArrowDown", "a1"),
+        ("ArrowRight", "a2"),
+        ("ArrowRight", "a3"),
+        ("ArrowDown", "b3"),
+        // Past the disabled B2.
+        ("ArrowLeft", "b1"),
+    ];
+    for (key, expected) in presses {
+        let route = press(key).await.unwrap();
+        assert_eq!(route, KeyRoute::SpatialNavigation, "{key} to {expected}");
+        assert_eq!(name(engine.focused_element().await), Some(expected));
+        let ring = engine.focus_ring().await.unwrap();
+        assert!(ring.y >= 0.0 && ring.y + ring.height <= 300.0);
+    }
+    assert_eq!(engine.scroll_position().await, (0.0, 0.0));
+
+    // Nothing on screen lies below B1, as C1 and C2 cannot take focus: the
+    // page scrolls to D1, then focuses it.
+    assert_eq!(
+        press("ArrowDown").await.unwrap(),
+        KeyRoute::SpatialNavigation
+    );
+    assert_eq!(name(engine.focused_element().await), Some("d1"));
+    assert!(engine.scroll_position().await.1 > 0.0);
+    let ring = engine.focus_ring().await.unwrap();
+    assert!(ring.y >= 0.0 && ring.y + ring.height <= 300.0);
+
+    // Nothing lies to the right of D1; Enter clicks it.
+    assert_eq!(
+        engine.spatial_navigation_search(Direction::Right).await,
+        None
+    );
+    assert_eq!(press("ArrowRight").await.unwrap(), KeyRoute::Unhandled);
+    assert_eq!(press("Enter").await.unwrap(), KeyRoute::SpatialNavigation);
+    assert_eq!(
+        engine.execute_javascript("window.clicked").await.unwrap(),
+        "d1"
+    );
+
+    // Off, arrows are the page's again and no ring is shown.
+    engine.set_spatial_navigation(false).await.unwrap();
+    assert_eq!(engine.focus_ring().await, None);
+    assert_eq!(press("ArrowUp").await.unwrap(), KeyRoute::Unhandled);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_exec_command_formats_and_cuts_and_pastes_in_contenteditable() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::core::clipboard::{ClipboardData, TEXT_PLAIN};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let host = spawn_page_host(&[(
+        "/",
+        "text/html",
+        b"<div id=editor contenteditable>hello world</div>\
+          <script>\
+            globalThis.inputs = [];\
+            const editor = document.getElementById('editor');\
+            for (const type of ['beforeinput', 'input']) {\
+              editor.addEventListener(type, (e) => globalThis.inputs.push(e.type + ' ' + e.inputType));\
+            }\
+          </script>",
+    )])
+    .await;
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+    assert!(engine.focus_element("#editor").await.unwrap());
+    let run = |script: &'static str| engine.execute_javascript(script);
+    let html = || engine.execute_javascript("document.getElementById('editor').innerHTML");
+
+    // Bold wraps the selected word, and unwraps it again.
+    assert!(engine.select_text(6, 11).await.unwrap());
+    assert_eq!(run("document.execCommand('bold')").await.unwrap(), true);
+    assert_eq!(html().await.unwrap(), "hello <b>world</b>");
+    assert_eq!(
+        run("document.queryCommandState('bold')").await.unwrap(),
+        true
+    );
+    assert_eq!(run("document.execCommand('bold')").await.unwrap(), true);
+    assert_eq!(html().await.unwrap(), "hello world");
+    assert_eq!(
+        run("globalThis.inputs").await.unwrap(),
+        serde_json::json!([
+            "beforeinput formatBold",
+            "input formatBold",
+            "beforeinput formatBold",
+            "input formatBold"
+        ])
+    );
+
+    // Bolding next to bold text joins it.
+    engine.select_text(0, 5).await.unwrap();
+    run("document.execCommand('bold')").await.unwrap();
+    engine.select_text(5, 11).await.unwrap();
+    run("document.execCommand('bold')").await.unwrap();
+    assert_eq!(html().await.unwrap(), "<b>hello world</b>");
+
+    // State follows the text before the caret.
+    engine.select_text(8, 8).await.unwrap();
+    assert_eq!(
+        run("document.queryCommandState('bold')").await.unwrap(),
+        true
+    );
+    assert_eq!(
+        run("document.queryCommandState('italic')").await.unwrap(),
+        false
+    );
+    engine.select_text(0, 11).await.unwrap();
+    run("document.execCommand('removeFormat')").await.unwrap();
+    assert_eq!(html().await.unwrap(), "hello world");
+    engine.select_text(8, 8).await.unwrap();
+    assert_eq!(
+        run("document.queryCommandState('bold')").await.unwrap(),
+        false
+    );
+
+    assert_eq!(
+        run("document.queryCommandSupported('insertParagraph')")
+            .await
+            .unwrap(),
+        true
+    );
+    assert_eq!(
+        run("document.queryCommandSupported('justifyCenter')")
+            .await
+            .unwrap(),
+        false
+    );
+    assert_eq!(
+        run("document.execCommand('justifyCenter')").await.unwrap(),
+        false
+    );
+
+    // The clipboard needs a user gesture; then cut and paste round-trip.
+    engine.select_text(0, 6).await.unwrap();
+    assert_eq!(run("document.execCommand('cut')").await.unwrap(), false);
+    engine.press_key("Shift", Modifiers::SHIFT).await.unwrap();
+    assert_eq!(run("document.execCommand('cut')").await.unwrap(), true);
+    assert_eq!(html().await.unwrap(), "world");
+    assert_eq!(
+        engine.read_clipboard()[0].get(TEXT_PLAIN),
+        Some(&ClipboardData::Text("hello ".to_string()))
+    );
+    engine.select_text(5, 5).await.unwrap();
+    assert_eq!(run("document.execCommand('paste')").await.unwrap(), true);
+    assert_eq!(html().await.unwrap(), "worldhello ");
+    assert_eq!(
+        run("globalThis.inputs.slice(-4)").await.unwrap(),
+        serde_json::json!([
+            "beforeinput deleteByCut",
+            "input deleteByCut",
+            "beforeinput insertFromPaste",
+            "input insertFromPaste"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_debug_box_outlines_cover_the_selected_subtree() {
+    use vulkan_browser_engine::core::devtools::Command;
+    use vulkan_browser_engine::renderer::DebugOverlayFlags;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=outer style=\"margin:4px;padding:3px;height:40px\">\
+             <div style=\"margin:1px;padding:1px;height:10px\"></div></div>\
+             <div style=\"height:20px\"></div>",
+        )
+        .await
+        .unwrap();
+    let plain = engine.snapshot().await;
+    let outer = engine
+        .devtools_command(
+            Command::parse(
+                "DOM.querySelector",
+                serde_json::json!({ "selector": "#outer" }),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Two boxes, each with four areas of four edges.
+    let result = engine
+        .devtools_command(
+            Command::parse(
+                "Overlay.setDebugOverlays",
+                serde_json::json!({ "overlays": ["box-outlines"], "nodeId": outer["nodeId"] }),
+            )
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(result["overlays"], serde_json::json!(["box-outlines"]));
+    assert_eq!(engine.debug_overlay_quads().await.len(), 2 * 4 * 4);
+    // The page's own frame, without the overlay, is what snapshots show.
+    assert_eq!(engine.snapshot().await.data, plain.data);
+
+    engine.set_debug_outline_root(None).await.unwrap();
+    assert!(engine.debug_overlay_quads().await.len() > 2 * 4 * 4);
+    engine
+        .set_debug_overlays(DebugOverlayFlags::BOX_OUTLINES | DebugOverlayFlags::IN_SNAPSHOTS)
+        .await
+        .unwrap();
+    assert_ne!(engine.snapshot().await.data, plain.data);
+
+    engine
+        .set_debug_overlays(DebugOverlayFlags::NONE)
+        .await
+        .unwrap();
+    assert!(engine.debug_overlay_quads().await.is_empty());
+    assert_eq!(engine.snapshot().await.data, plain.data);
+}
+
+#[tokio::test]
+async fn test_element_query_selector_is_scoped_and_rejects_bad_selectors() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<div id=a class=item><span>1</span><span>2</span></div>\
+             <div id=b class=item><span>3</span></div><span>4</span>",
+        )
+        .await
+        .unwrap();
+    let result = engine
+        .execute_javascript(
+            "const a = document.getElementById('a');
+             let error = null;
+             try { document.querySelector('div['); } catch (e) { error = e; }
+             let scoped = null;
+             try { a.querySelectorAll('span:'); } catch (e) { scoped = e; }
+             [document.querySelectorAll('div.item > span').length,
+              a.querySelectorAll('span').map(s => s.textContent),
+              a.querySelector('span').textContent,
+              a.querySelector('div'),
+              error instanceof SyntaxError, scoped instanceof SyntaxError]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        result,
+        serde_json::json!([3, ["1", "2"], "1", null, true, true])
+    );
+}
+
+#[tokio::test]
+async fn test_script_mutations_are_rendered_in_one_partial_restyle() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    let items: String = (0..50).map(|i| format!("<li>item {i}</li>")).collect();
+    engine
+        .load_url(&format!(
+            "data:text/html,<ul>{items}</ul><p id=x>old</p><p>after</p>"
+        ))
+        .await
+        .unwrap();
+    let texts = |tree: serde_json::Value| -> Vec<String> {
+        tree["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|node| node["text"].as_str().map(str::to_string))
+            .collect()
+    };
+    assert!(texts(engine.dump_layout_tree().await).contains(&"old".to_string()));
+    let before = engine.get_performance_metrics().await;
+
+    engine
+        .execute_javascript(
+            "const x = document.getElementById('x');
+             x.textContent = 'h';
+             x.textContent = 'hi';",
+        )
+        .await
+        .unwrap();
+
+    let after = engine.get_performance_metrics().await;
+    assert_eq!(
+        after.renderer.frames_rendered,
+        before.renderer.frames_rendered + 1
+    );
+    // The paragraph, its text and the sibling after it; not the list.
+    assert!(
+        (2..10).contains(&after.layout.nodes_restyled),
+        "{} nodes restyled",
+        after.layout.nodes_restyled
+    );
+    let texts = texts(engine.dump_layout_tree().await);
+    assert!(texts.contains(&"hi".to_string()), "{texts:?}");
+    assert!(!texts.contains(&"old".to_string()));
+
+    // Nothing changed since: no frame is painted.
+    engine.request_frame().await.unwrap();
+    assert_eq!(
+        engine
+            .get_performance_metrics()
+            .await
+            .renderer
+            .frames_rendered,
+        after.renderer.frames_rendered
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_following_a_link_sends_its_pings_beside_the_navigation() {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::{NetworkManager, ResourceType};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let mock = Arc::new(MockTransport::new());
+    // `connect-src` lets pings reach the page's own origin and the tracker
+    // only; the `ftp:` ping is refused by the security policy.
+    mock.route(
+        "GET",
+        "http://news.test/",
+        MockResponse::ok(
+            "text/html",
+            "<style>body{margin:0} a{display:block;width:300px;height:50px}</style>\
+             <a href=\"/story\" ping=\"/audit http://blocked.test/hit \
+             http://tracker.test/hit ftp://tracker.test/hit\">Story</a>",
+        )
+        .header(
+            "Content-Security-Policy",
+            "connect-src 'self' http://tracker.test",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://news.test/story",
+        MockResponse::ok("text/html", "<p>Story</p>"),
+    );
+    mock.route(
+        "POST",
+        "http://news.test/audit",
+        MockResponse::new(204).latency(Duration::from_secs(2)),
+    );
+    mock.route(
+        "POST",
+        "http://tracker.test/hit",
+        MockResponse::new(204).latency(Duration::from_secs(2)),
+    );
+
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, mock.clone())
+        .await
+        .unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://news.test/").await.unwrap();
+
+    let start = Instant::now();
+    engine
+        .handle_input_event(InputEvent::MouseClick {
+            x: 250,
+            y: 25,
+            button: 0,
+        })
+        .await
+        .unwrap();
+    assert!(
+        start.elapsed() < Duration::from_secs(1),
+        "navigation waited for the pings"
+    );
+    assert_eq!(
+        engine.get_current_url().await.as_deref(),
+        Some("http://news.test/story")
+    );
+
+    let pings: Vec<_> = mock
+        .requests()
+        .into_iter()
+        .filter(|request| request.method == "POST")
+        .collect();
+    let pinged: Vec<_> = pings.iter().map(|ping| ping.url.as_str()).collect();
+    assert_eq!(
+        pinged,
+        ["http://news.test/audit", "http://tracker.test/hit"]
+    );
+    for ping in &pings {
+        assert_eq!(ping.body.as_deref(), Some(&b"PING"[..]));
+        assert_eq!(ping.header("Content-Type"), Some("text/ping"));
+        assert_eq!(ping.header("Ping-To"), Some("http://news.test/story"));
+        // An http page tells every pinged server where the link was.
+        assert_eq!(ping.header("Ping-From"), Some("http://news.test/"));
+    }
+
+    let metrics = engine.get_performance_metrics().await.network;
+    assert_eq!((metrics.pings_sent, metrics.pings_blocked), (2, 2));
+    let logged: Vec<_> = engine
+        .resource_log()
+        .into_iter()
+        .filter(|entry| entry.resource_type == ResourceType::Ping)
+        .map(|entry| entry.url)
+        .collect();
+    assert_eq!(
+        logged,
+        ["http://news.test/audit", "http://tracker.test/hit"]
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_hyperlink_auditing_off_follows_links_without_pings() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::NetworkManager;
+    use vulkan_browser_engine::core::permissions::{Permission, PermissionState};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://news.test/",
+        MockResponse::ok(
+            "text/html",
+            "<style>body{margin:0} a{display:block;width:300px;height:50px}</style>\
+             <a href=\"/story\" ping=\"/audit\"><span>Story</span></a>",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://news.test/story",
+        MockResponse::ok("text/html", "<p>Story</p>"),
+    );
+    mock.route("POST", "http://news.test/audit", MockResponse::new(204));
+
+    let click = InputEvent::MouseClick {
+        x: 10,
+        y: 10,
+        button: 0,
+    };
+    for auditing in [false, true] {
+        let config = BrowserConfig {
+            enable_gpu_acceleration: false,
+            enable_sandbox: false,
+            enable_pwa: false,
+            enable_hyperlink_auditing: auditing,
+            ..Default::default()
+        };
+        let network = NetworkManager::with_transport(&config, mock.clone())
+            .await
+            .unwrap();
+        let engine = BrowserEngine::with_network(config, network).await.unwrap();
+        // With the switch on, the site is denied instead.
+        engine.set_permission(
+            &url::Url::parse("http://news.test/").unwrap(),
+            Permission::HyperlinkAuditing,
+            Some(PermissionState::Denied),
+        );
+        engine.load_url("http://news.test/").await.unwrap();
+
+        // The click lands on the text inside the link.
+        engine.handle_input_event(click.clone()).await.unwrap();
+        assert_eq!(
+            engine.get_current_url().await.as_deref(),
+            Some("http://news.test/story")
+        );
+        assert!(mock.requests_to("http://news.test/audit").is_empty());
+        assert_eq!(engine.get_performance_metrics().await.network.pings_sent, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_hit_test_path_runs_from_the_hit_element_up_to_the_document() {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let engine = BrowserEngine::new(config).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=outer style=\"width:200px;height:100px\">\
+             <div id=inner style=\"width:50px;height:50px\">Hi</div></div>\
+             <script>window.__clicks = [];\
+             for (const id of ['outer', 'inner']) {\
+               document.getElementById(id).addEventListener('click',\
+                 (e) => __clicks.push(id + ' ' + e.target.id));\
+             }</script>",
+        )
+        .await
+        .unwrap();
+    let mut nodes = Vec::new();
+    for id in ["outer", "inner"] {
+        let node_id = engine
+            .execute_javascript(&format!("document.getElementById('{id}').__nodeId"))
+            .await
+            .unwrap();
+        nodes.push(NodeId(node_id.as_str().unwrap().parse().unwrap()));
+    }
+    let (outer, inner) = (nodes[0], nodes[1]);
+
+    // Inner sits on top of outer; the path bubbles out through outer, then
+    // body, html and the document.
+    let path = engine.hit_test_path(10.0, 10.0).await.unwrap();
+    assert_eq!(path[..2], [inner, outer]);
+    assert!(path.len() > 3);
+    // Over its text, the path still starts at the element.
+    let text = engine.hit_test(4.0, 8.0).await.unwrap();
+    assert!(text.is_some() && text != Some(inner));
+    assert_eq!(
+        engine.hit_test_path(4.0, 8.0).await.unwrap()[..2],
+        [inner, outer]
+    );
+    let path = engine.hit_test_path(150.0, 10.0).await.unwrap();
+    assert_eq!(path[0], outer);
+    assert_eq!(engine.hit_test(150.0, 10.0).await.unwrap(), Some(outer));
+    assert!(engine.hit_test_path(10.0, -5.0).await.unwrap().is_empty());
+
+    for x in [10, 150] {
+        engine
+            .handle_input_event(InputEvent::MouseClick {
+                x,
+                y: 10,
+                button: 0,
+            })
+            .await
+            .unwrap();
+    }
+    let clicks = engine
+        .execute_javascript("__clicks.join(',')")
+        .await
+        .unwrap();
+    assert_eq!(clicks, "inner inner,outer inner,outer outer");
+}
+
+#[tokio::test]
+async fn test_input_events_reach_page_listeners_with_their_coordinates_and_keys() {
+    use vulkan_browser_engine::core::accelerators::Modifiers;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let engine = BrowserEngine::new(config).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div id=pad style=\"width:200px;height:100px\"></div>\
+             <input id=field>\
+             <div style=\"height:3000px\"></div>\
+             <script>window.__seen = [];\
+             const pad = document.getElementById('pad');\
+             const field = document.getElementById('field');\
+             const log = (e) => __seen.push(e.type + ' ' + e.clientX + ',' + e.clientY);\
+             pad.addEventListener('mousemove', log);\
+             pad.addEventListener('touchstart', log);\
+             pad.addEventListener('touchmove', (e) =>\
+               __seen.push('touchmove ' + e.touches.length + ' ' + e.changedTouches[0].clientX));\
+             pad.addEventListener('touchend', (e) =>\
+               __seen.push('touchend ' + e.touches.length));\
+             let wheels = 0;\
+             pad.addEventListener('wheel', (e) => {\
+               __seen.push('wheel ' + e.deltaY);\
+               if (wheels++ === 0) e.preventDefault();\
+             });\
+             for (const type of ['keydown', 'keyup']) {\
+               field.addEventListener(type, (e) =>\
+                 __seen.push(type + ' ' + e.key + (e.shiftKey ? ' shift' : '')));\
+             }\
+             </script>",
+        )
+        .await
+        .unwrap();
+
+    let input = [
+        InputEvent::MouseMove { x: 30, y: 40 },
+        // Nothing is under the pointer out here.
+        InputEvent::MouseMove { x: 500, y: 40 },
+        InputEvent::Touch {
+            x: 10,
+            y: 20,
+            pressure: 0.5,
+            id: 7,
+        },
+        // The touch moved off the pad but still reports there.
+        InputEvent::Touch {
+            x: 300,
+            y: 20,
+            pressure: 0.5,
+            id: 7,
+        },
+        InputEvent::Touch {
+            x: 300,
+            y: 20,
+            pressure: 0.0,
+            id: 7,
+        },
+    ];
+    for event in input {
+        engine.handle_input_event(event).await.unwrap();
+    }
+
+    // The first wheel turn is canceled and scrolls nothing; the second
+    // scrolls the page.
+    let wheel = InputEvent::MouseWheel {
+        x: 10,
+        y: 10,
+        delta_x: 0.0,
+        delta_y: 120.0,
+    };
+    engine.handle_input_event(wheel.clone()).await.unwrap();
+    assert_eq!(engine.scroll_position().await.1, 0.0);
+    engine.handle_input_event(wheel).await.unwrap();
+    assert_eq!(engine.scroll_position().await.1, 120.0);
+
+    // Keys go to the element script focused, modifiers and all.
+    let active = engine
+        .execute_javascript(
+            "document.getElementById('field').focus();\
+             document.activeElement.getAttribute('id')",
+        )
+        .await
+        .unwrap();
+    assert_eq!(active, "field");
+    engine
+        .handle_input_event(InputEvent::KeyPress {
+            key: "A".to_string(),
+            modifiers: Modifiers::SHIFT.bits(),
+        })
+        .await
+        .unwrap();
+    engine
+        .handle_input_event(InputEvent::KeyRelease {
+            key: "A".to_string(),
+            modifiers: 0,
+        })
+        .await
+        .unwrap();
+    let blurred = engine
+        .execute_javascript(
+            "document.getElementById('field').blur();\
+             document.activeElement === document.querySelector('body')",
+        )
+        .await
+        .unwrap();
+    assert_eq!(blurred, true);
+
+    let seen = engine.execute_javascript("__seen").await.unwrap();
+    assert_eq!(
+        seen,
+        serde_json::json!([
+            "mousemove 30,40",
+            "touchstart 10,20",
+            "touchmove 1 300",
+            "touchend 0",
+            "wheel 120",
+            "wheel 120",
+            "keydown A shift",
+            "keyup A",
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_scoped_relayout_matches_a_layout_from_scratch() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let fixtures = [
+        // Fixed-size boxes: what changes inside one stays inside it.
+        "<style>.card { height: 40px; overflow: hidden }</style>\
+         <div class=card id=a><p id=a1>one</p><p id=a2>two</p></div>\
+         <div class=card id=b><span id=b1>three</span></div><p id=c>after</p>",
+        // A boundary inside a box that grows with its contents.
+        "<div style=\"contain: layout\" id=a><p id=a1>one</p>\
+         <div style=\"height: 30px; overflow: auto\" id=b><p id=b1>two</p><p id=b2>x</p></div>\
+         </div><p id=c>after</p>",
+        // Boundaries beside a float, nested in one another.
+        "<div style=\"float: left; width: 50px; height: 80px\" id=f></div>\
+         <div style=\"overflow: hidden; height: 60px\" id=a>\
+         <div style=\"display: flow-root; height: 20px\" id=b><p id=b1>inner</p></div>\
+         <p id=a1>text beside the float</p></div><p id=c>more text</p>",
+    ];
+    let mutations = [
+        "e.textContent = 'changed to text long enough to wrap onto a good few more lines than it had'",
+        "e.style.height = '75px'",
+        "e.style.width = '120px'",
+        "e.style.padding = '7px'",
+        "e.appendChild(document.createElement('div')).textContent = 'added'",
+        "e.remove()",
+    ];
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine.resize_viewport(400, 300).await.unwrap();
+    let mut seed = 0x2545_f491_u64;
+    let mut scoped = 0;
+    for fixture in fixtures {
+        let ids = fixture
+            .split(" id=")
+            .skip(1)
+            .map(|rest| rest.split(['>', ' ']).next().unwrap());
+        for id in ids {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let mutation = mutations[(seed >> 33) as usize % mutations.len()];
+            engine
+                .load_url(&format!("data:text/html,{fixture}"))
+                .await
+                .unwrap();
+            let loaded = engine.get_performance_metrics().await.layout;
+            engine
+                .execute_javascript(&format!(
+                    "{{ const e = document.getElementById('{id}'); {mutation}; }}"
+                ))
+                .await
+                .unwrap();
+            let changed = engine.get_performance_metrics().await.layout;
+            if changed.nodes_relaid_out * 2 < loaded.nodes_relaid_out {
+                scoped += 1;
+            }
+            let relaid = engine.dump_layout_tree().await;
+
+            // The same size again lays the whole document out afresh.
+            engine.resize_viewport(400, 300).await.unwrap();
+            assert_eq!(
+                relaid,
+                engine.dump_layout_tree().await,
+                "{mutation} on #{id} of {fixture}"
+            );
+        }
+    }
+    assert!(scoped > 0);
+}
+
+#[tokio::test]
+async fn test_a_text_change_in_a_large_document_relays_out_only_its_card() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    // Three nodes a card: over 10,000 in all.
+    let cards: String = (0..3400)
+        .map(|i| format!("<div class=card><p id=p{i}>card {i}</p></div>"))
+        .collect();
+    engine
+        .load_url(&format!(
+            "data:text/html,<style>.card {{ height: 24px; overflow: hidden }}</style>{cards}"
+        ))
+        .await
+        .unwrap();
+    let before = engine.get_performance_metrics().await.layout;
+    assert!(before.nodes_relaid_out > 6800, "{before:?}");
+
+    engine
+        .execute_javascript("document.getElementById('p1700').textContent = 'changed'")
+        .await
+        .unwrap();
+
+    let after = engine.get_performance_metrics().await.layout;
+    assert!(
+        (1..300).contains(&after.nodes_relaid_out),
+        "{} boxes laid out",
+        after.nodes_relaid_out
+    );
+    let tree = engine.dump_layout_tree().await;
+    let nodes = tree["nodes"].as_array().unwrap();
+    let changed = nodes
+        .iter()
+        .find(|node| node["text"] == "changed")
+        .expect("the new text is laid out");
+    let neighbour = nodes
+        .iter()
+        .find(|node| node["text"] == "card 1699")
+        .unwrap();
+    assert_eq!(
+        changed["bounds"]["y"].as_f64().unwrap() - neighbour["bounds"]["y"].as_f64().unwrap(),
+        24.0
+    );
+}
+
+#[tokio::test]
+async fn test_wheel_and_script_scrolling_move_the_layout() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        viewport_width: 800,
+        viewport_height: 600,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div style=\"height:1000px\">first</div><div style=\"height:1600px\">second</div>\
+             <script>window.atLoad = [scrollX, scrollY];</script></body>",
+        )
+        .await
+        .unwrap();
+    let text_y = |tree: &serde_json::Value, text: &str| {
+        tree["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|node| node["type"] == "text" && node["text"] == text)
+            .map(|node| node["bounds"]["y"].as_f64().unwrap())
+            .unwrap()
+    };
+    let script_position = || async {
+        engine
+            .execute_javascript("[scrollX, scrollY, pageXOffset, pageYOffset].join()")
+            .await
+            .unwrap()
+    };
+    let wheel = |delta_y| InputEvent::MouseWheel {
+        x: 10,
+        y: 10,
+        delta_x: 0.0,
+        delta_y,
+    };
+    assert_eq!(
+        engine.execute_javascript("atLoad.join()").await.unwrap(),
+        "0,0"
+    );
+    let second = text_y(&engine.dump_layout_tree().await, "second");
+
+    engine.handle_input_event(wheel(250.0)).await.unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 250.0));
+    assert_eq!(script_position().await, "0,250,0,250");
+    let tree = engine.dump_layout_tree().await;
+    assert_eq!(text_y(&tree, "second"), second - 250.0);
+
+    // Scrolling back up past the top stops there, as does scrolling past
+    // the bottom: the page is 2600px tall in a 600px viewport.
+    engine.handle_input_event(wheel(-400.0)).await.unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 0.0));
+    engine.handle_input_event(wheel(5000.0)).await.unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 2000.0));
+    engine
+        .handle_input_event(InputEvent::Scroll {
+            delta_x: 0.0,
+            delta_y: -500.0,
+        })
+        .await
+        .unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 1500.0));
+
+    // Script reads back where it scrolled to at once; the layout follows
+    // once it is done.
+    let read_back = engine
+        .execute_javascript(
+            "scrollTo(0, 100); const a = scrollY;\
+             scrollBy({ top: 50 }); const b = scrollY;\
+             scrollBy(0, -1000); const c = scrollY;\
+             scroll({ top: 1e9 }); const d = scrollY;\
+             scrollTo({ top: 300, behavior: 'smooth' }); [a, b, c, d, scrollY].join()",
+        )
+        .await
+        .unwrap();
+    assert_eq!(read_back, "100,150,0,2000,300");
+    assert_eq!(engine.scroll_position().await, (0.0, 300.0));
+    let tree = engine.dump_layout_tree().await;
+    assert_eq!(text_y(&tree, "second"), second - 300.0);
+
+    // A scroll past what the page then reached lands where script grew it
+    // to, and a script scroll from a timer applies on the next frame.
+    engine
+        .execute_javascript(
+            "document.body.insertAdjacentHTML('beforeend', '<div style=\"height:1000px\"></div>');\
+             scrollTo(0, 2800); setTimeout(() => scrollTo(0, 40), 0);",
+        )
+        .await
+        .unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 2800.0));
+    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    engine.tick().await.unwrap();
+    assert_eq!(engine.scroll_position().await, (0.0, 40.0));
+    assert_eq!(script_position().await, "0,40,0,40");
+    assert!(engine
+        .execute_javascript("try { scrollTo(5); 'scrolled' } catch (e) { e.name }")
+        .await
+        .unwrap()
+        .as_str()
+        .is_some_and(|name| name == "TypeError"));
+}
+
+#[tokio::test]
+async fn test_headless_screenshot_is_a_png_of_the_page() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0; height:100px; background:rgb(255,0,0)\"></body>",
+        )
+        .await
+        .unwrap();
+    let before = engine.get_performance_metrics().await;
+
+    let png = engine.capture_screenshot(200, 150).await.unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(image.dimensions(), (200, 150));
+    assert_eq!(image.get_pixel(100, 50).0, [255, 0, 0, 255]);
+    // Below the body nothing is painted, and that comes out white.
+    assert_eq!(image.get_pixel(100, 125).0, [255, 255, 255, 255]);
+
+    // The engine's own viewport is left as it was, and not painted again.
+    assert_eq!(engine.snapshot().await.width, 1920);
+    let after = engine.get_performance_metrics().await;
+    assert_eq!(
+        after.renderer.frames_rendered,
+        before.renderer.frames_rendered
+    );
+    assert!(engine.capture_screenshot(0, 10).await.is_err());
+}
+
+#[tokio::test]
+async fn test_render_frame_without_a_window_does_nothing() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p>headless</p>")
+        .await
+        .unwrap();
+    engine.render_frame().await.unwrap();
+    // Resizing sizes the window's swapchain only once there is one.
+    engine.resize_viewport(800, 600).await.unwrap();
+    engine.render_frame().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_headless_screenshot_draws_the_glyphs_of_a_heading() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        viewport_width: 400,
+        viewport_height: 200,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url("data:text/html,<body style=\"margin:0\"><h1>Heading</h1></body>")
+        .await
+        .unwrap();
+    let tree = engine.dump_layout_tree().await;
+    let heading = tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|node| node["type"] == "text" && node["text"] == "Heading")
+        .unwrap();
+    let bound = |key: &str| heading["bounds"][key].as_f64().unwrap() as u32;
+    let (x, y, width, height) = (bound("x"), bound("y"), bound("width"), bound("height"));
+
+    let png = engine.capture_screenshot(400, 200).await.unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+    let inked = (y..y + height)
+        .flat_map(|py| (x..x + width).map(move |px| (px, py)))
+        .filter(|&(px, py)| image.get_pixel(px, py).0 != [255, 255, 255, 255])
+        .count();
+    assert!(inked > 0, "nothing drawn where the heading is");
+    // Glyphs leave the background showing between and inside them.
+    assert!(inked < (width * height) as usize);
+    assert_eq!(image.get_pixel(390, 190).0, [255, 255, 255, 255]);
+    engine.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_headless_screenshot_paints_nested_backgrounds_and_borders() {
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let engine = BrowserEngine::new(BrowserConfig {
+        viewport_width: 400,
+        viewport_height: 300,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    engine
+        .load_url(
+            "data:text/html,<body style=\"margin:0\">\
+             <div style=\"width:200px; height:100px; padding-top:10px; padding-left:10px; \
+             border-top-width:4px; border-right-width:4px; border-bottom-width:4px; \
+             border-left-width:4px; border-style:solid; border-color:rgb(0,0,255); \
+             background-color:rgb(255,0,0)\">\
+             <div style=\"width:100px; height:40px; background-color:rgb(0,255,0)\"></div>\
+             </div></body>",
+        )
+        .await
+        .unwrap();
+    let tree = engine.dump_layout_tree().await;
+    let divs: Vec<_> = tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|node| node["tag"] == "div")
+        .map(|node| {
+            let bound = |key: &str| node["bounds"][key].as_f64().unwrap() as u32;
+            (bound("x"), bound("y"), bound("width"), bound("height"))
+        })
+        .collect();
+    let [(outer_x, outer_y, outer_width, _), (inner_x, inner_y, inner_width, inner_height)] =
+        divs[..]
+    else {
+        panic!("expected two divs, got {:?}", divs);
+    };
+
+    let png = engine.capture_screenshot(400, 300).await.unwrap();
+    let image = image::load_from_memory(&png).unwrap().to_rgba8();
+    let pixel = |x: u32, y: u32| image.get_pixel(x, y).0;
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    // The child paints over its parent.
+    assert_eq!(
+        pixel(inner_x + inner_width / 2, inner_y + inner_height / 2),
+        GREEN
+    );
+    // The parent shows around the child, padding included.
+    assert_eq!(
+        pixel(inner_x + inner_width + 10, inner_y + inner_height / 2),
+        RED
+    );
+    assert_eq!(pixel(outer_x - 5, outer_y - 5), RED);
+    // The border rings the padding box.
+    assert_eq!(pixel(outer_x - 12, outer_y + 20), BLUE);
+    assert_eq!(pixel(outer_x + 20, outer_y - 12), BLUE);
+    assert_eq!(pixel(outer_x + outer_width + 2, outer_y + 20), BLUE);
+    assert_eq!(
+        pixel(outer_x + outer_width + 10, outer_y + 20),
+        [255, 255, 255, 255]
+    );
+    engine.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_img_elements_draw_their_decoded_pixels_at_their_natural_size() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::NetworkManager;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://images.test/",
+        MockResponse::ok(
+            "text/html",
+            "<body style=\"margin:0\"><img src=/red.png><br>\
+             <img src=/missing.png width=30 height=30></body>",
+        ),
+    );
+    let png = {
+        let image = image::RgbaImage::from_pixel(20, 10, image::Rgba([255, 0, 0, 255]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    };
+    mock.route(
+        "GET",
+        "http://images.test/red.png",
+        MockResponse::ok("image/png", png),
+    );
+    mock.route(
+        "GET",
+        "http://images.test/missing.png",
+        MockResponse::new(404),
+    );
+
+    let config = BrowserConfig {
+        viewport_width: 200,
+        viewport_height: 100,
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, mock.clone())
+        .await
+        .unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://images.test/").await.unwrap();
+
+    let tree = engine.dump_layout_tree().await;
+    let images: Vec<_> = tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|node| node["tag"] == "img")
+        .map(|node| {
+            let bound = |key: &str| node["bounds"][key].as_f64().unwrap() as u32;
+            (bound("x"), bound("y"), bound("width"), bound("height"))
+        })
+        .collect();
+    let [(x, y, 20, 10), (broken_x, broken_y, 30, 30)] = images[..] else {
+        panic!("expected a 20x10 and a 30x30 image, got {:?}", images);
+    };
+
+    let png = engine.capture_screenshot(200, 100).await.unwrap();
+    let screenshot = image::load_from_memory(&png).unwrap().to_rgba8();
+    let pixel = |x: u32, y: u32| screenshot.get_pixel(x, y).0;
+    assert_eq!(pixel(x + 10, y + 5), [255, 0, 0, 255]);
+    assert_eq!(pixel(x + 21, y + 5), [255, 255, 255, 255]);
+    // The broken image is a light box in a darker gray frame.
+    let [fill, _, _, _] = pixel(broken_x + 15, broken_y + 15);
+    let [frame, _, _, _] = pixel(broken_x, broken_y + 15);
+    assert_eq!(pixel(broken_x + 15, broken_y + 15), [fill, fill, fill, 255]);
+    assert_eq!(pixel(broken_x, broken_y + 15), [frame, frame, frame, 255]);
+    assert!(frame < fill && fill < 255);
+    engine.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_performance_metrics_count_network_requests_but_not_data_urls() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::BrowserConfig;
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://metrics.test/*",
+        MockResponse::ok("text/html", "<p>served over the network</p>")
+            .header("Cache-Control", "no-store"),
+    );
+    let host = "http://metrics.test";
+
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    engine.load_url("data:text/html,<p>one</p>").await.unwrap();
+    engine.load_url("data:text/html,<p>two</p>").await.unwrap();
+    engine.load_url(&format!("{}/", host)).await.unwrap();
+
+    let network = engine.get_performance_metrics().await.network;
+    assert_eq!(network.requests_total, 1);
+    assert!(network.bytes_downloaded > 0);
+    assert_eq!(network.failed_requests, 0);
+    assert_eq!(network.bytes_uploaded, 0);
+    engine.shutdown().await.unwrap();
+}
+
+/// Answers every request to `http://slow.test` after `delay`.
+#[cfg(feature = "test-util")]
+fn slow_host(
+    delay: std::time::Duration,
+) -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route(
+        "GET",
+        "http://slow.test/*",
+        MockResponse::ok("text/html", "<p>slow</p>").latency(delay),
+    );
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_stop_cancels_the_navigation_under_way() {
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserError, BrowserEvent};
+
+    let mock = slow_host(Duration::from_secs(10));
+    let host = "http://slow.test";
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    let url = format!("{}/", host);
+
+    let started = Instant::now();
+    let (loaded, ()) = tokio::join!(engine.load_url(&url), async {
+        while !engine.is_loading().await {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        engine.stop().await;
+        assert!(!engine.is_loading().await);
+    });
+    assert!(matches!(loaded, Err(BrowserError::NavigationCancelled(_))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!engine.is_loading().await);
+
+    let navigation = engine.get_recent_events(None, Some(EventKindMask::NAVIGATION));
+    assert!(navigation.iter().any(|entry| matches!(
+        &entry.event,
+        LoggedEvent::Browser {
+            event: BrowserEvent::NavigationCancelled { url: cancelled },
+        } if *cancelled == url
+    )));
+    assert!(!navigation.iter().any(|entry| matches!(
+        entry.event,
+        LoggedEvent::Browser {
+            event: BrowserEvent::PageLoaded { .. },
+        }
+    )));
+    // Stopping on purpose is not reported as an error.
+    assert!(engine
+        .get_recent_events(None, Some(EventKindMask::ERROR_HANDLED))
+        .is_empty());
+    // With nothing under way, there is nothing to stop.
+    engine.stop().await;
+    assert_eq!(
+        engine
+            .get_recent_events(None, Some(EventKindMask::NAVIGATION_CANCELLED))
+            .len(),
+        1
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_a_newer_navigation_cancels_the_older_one() {
+    use std::time::Duration;
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::{BrowserConfig, BrowserError, BrowserEvent};
+
+    let mock = slow_host(Duration::from_secs(10));
+    let slow_url = "http://slow.test/";
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+
+    let (slow, fast) = tokio::join!(engine.load_url(slow_url), async {
+        while !engine.is_loading().await {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        engine.load_url("data:text/html,<p>fast</p>").await
+    });
+    assert!(matches!(slow, Err(BrowserError::NavigationCancelled(_))));
+    fast.unwrap();
+    assert!(!engine.is_loading().await);
+
+    let loaded: Vec<_> = engine
+        .get_recent_events(None, Some(EventKindMask::PAGE_LOADED))
+        .into_iter()
+        .filter_map(|entry| match entry.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::PageLoaded { url, .. },
+            } => Some(url),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(loaded, vec!["data:text/html,<p>fast</p>".to_string()]);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_a_slow_first_navigation_never_replaces_the_newer_page() {
+    use std::time::Duration;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine, BrowserError};
+
+    // The first page answers while the second is still on its way.
+    let page = |text: &str, delay: u64| {
+        MockResponse::ok("text/html", format!("<p id=\"page\">{}</p>", text))
+            .latency(Duration::from_millis(delay))
+    };
+    let mock = std::sync::Arc::new(MockTransport::new());
+    mock.route("GET", "http://slow.test/*", page("slow", 300));
+    mock.route("GET", "http://fast.test/*", page("fast", 600));
+    let config = BrowserConfig::default();
+    let network = support::mock_network(&config, &mock).await;
+    // Shares the engine's requests in flight, as the page's own fetches do.
+    let other = network.for_other_page();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+
+    let (slow, fast, unrelated) = tokio::join!(
+        engine.load_url("http://slow.test/"),
+        async {
+            while !engine.is_loading().await {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            engine.load_url("http://fast.test/").await
+        },
+        other.fetch_response("http://slow.test/data"),
+    );
+    assert!(matches!(slow, Err(BrowserError::NavigationCancelled(_))));
+    fast.unwrap();
+    // Only the superseded navigation's own fetch was stopped.
+    assert_eq!(unrelated.unwrap().status, 200);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        engine.get_current_url().await.as_deref(),
+        Some("http://fast.test/")
+    );
+    let text = engine
+        .execute_javascript("document.getElementById('page').textContent")
+        .await
+        .unwrap();
+    assert_eq!(text, serde_json::json!("fast"));
+}
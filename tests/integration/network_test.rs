@@ -0,0 +1,2431 @@
+use std::time::Duration;
+#[cfg(feature = "test-util")]
+use std::time::Instant;
+#[cfg(feature = "test-util")]
+use vulkan_browser_engine::core::network::{NetworkError, NetworkManager, PolitenessConfig};
+use vulkan_browser_engine::BrowserConfig;
+
+#[cfg(feature = "test-util")]
+mod support;
+
+#[cfg(feature = "test-util")]
+fn polite_config(politeness: PolitenessConfig) -> BrowserConfig {
+    BrowserConfig {
+        politeness,
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_navigations_to_same_host_are_spaced() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://polite.test/*",
+        MockResponse::ok("text/html", "<html></html>").header("Cache-Control", "no-store"),
+    );
+    let host = "http://polite.test";
+    let network = support::mock_network(
+        &polite_config(PolitenessConfig {
+            enabled: true,
+            requests_per_second: 0.0,
+            min_navigation_delay_ms: 300,
+            respect_robots_txt: false,
+            ..Default::default()
+        }),
+        &mock,
+    )
+    .await;
+
+    let start = Instant::now();
+    network
+        .fetch_navigation(&format!("{host}/a"))
+        .await
+        .unwrap();
+    network
+        .fetch_navigation(&format!("{host}/b"))
+        .await
+        .unwrap();
+
+    assert!(start.elapsed() >= Duration::from_millis(300));
+    assert_eq!(network.get_metrics().throttled_requests, 1);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_robots_disallow_is_refused_unless_overridden() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://crawl.test/*",
+        MockResponse::ok("text/html", "<html></html>").header("Cache-Control", "no-store"),
+    );
+    mock.route(
+        "GET",
+        "http://crawl.test/robots.txt",
+        MockResponse::ok("text/plain", "User-agent: *\nDisallow: /private\n"),
+    );
+    let host = "http://crawl.test";
+    let config = PolitenessConfig {
+        enabled: true,
+        requests_per_second: 0.0,
+        min_navigation_delay_ms: 0,
+        ..Default::default()
+    };
+
+    let strict = NetworkManager::with_transport(&polite_config(config.clone()), mock.clone())
+        .await
+        .unwrap();
+    let refused = strict.fetch(&format!("{host}/private/page")).await;
+    assert!(matches!(refused, Err(NetworkError::RobotsDisallowed(_))));
+    assert!(strict.fetch(&format!("{host}/public")).await.is_ok());
+    assert_eq!(strict.get_metrics().robots_denied_requests, 1);
+    // robots.txt is asked for once per origin; the refused page never is.
+    assert_eq!(mock.requests_to("http://crawl.test/robots.txt").len(), 1);
+    assert!(mock.requests_to("http://crawl.test/private/*").is_empty());
+
+    let lenient = NetworkManager::with_transport(
+        &polite_config(PolitenessConfig {
+            override_robots: true,
+            ..config
+        }),
+        mock.clone(),
+    )
+    .await
+    .unwrap();
+    assert!(!lenient
+        .robots_decision(&format!("{host}/private/page"))
+        .await
+        .unwrap()
+        .is_allowed());
+    assert!(lenient.fetch(&format!("{host}/private/page")).await.is_ok());
+}
+
+#[test]
+fn test_robots_rules_pick_most_specific_group() {
+    use vulkan_browser_engine::core::network::RobotsRules;
+
+    let rules = RobotsRules::parse(
+        "User-agent: *\nDisallow: /private\nAllow: /private/open\n\n\
+         User-agent: VulkanBrowser\nDisallow: /no-bots$\nCrawl-delay: 2\n",
+    );
+
+    assert!(rules.decide("OtherBot", "/private/open/page").is_allowed());
+    assert!(!rules.decide("OtherBot", "/private/x").is_allowed());
+    assert!(rules.decide("VulkanBrowser/1.0", "/private/x").is_allowed());
+    assert!(!rules.decide("VulkanBrowser/1.0", "/no-bots").is_allowed());
+    assert_eq!(
+        rules.crawl_delay("VulkanBrowser/1.0"),
+        Some(Duration::from_secs(2))
+    );
+}
+
+/// The first request to a URL matching `pattern`, waiting up to `timeout`
+/// for one.
+#[cfg(feature = "test-util")]
+async fn wait_for_request(
+    mock: &vulkan_browser_engine::core::network::mock::MockTransport,
+    pattern: &str,
+    timeout: Duration,
+) -> Option<vulkan_browser_engine::core::network::mock::RecordedRequest> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(request) = mock.requests_to(pattern).into_iter().next() {
+            return Some(request);
+        }
+        if Instant::now() > deadline {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "test-util")]
+fn beacon_engine_config() -> BrowserConfig {
+    BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_beacon_from_pagehide_survives_navigation() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    // Answers late, so the test can tell whether navigation waited for it.
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "POST",
+        "http://beacon.test/*",
+        MockResponse::new(204).latency(Duration::from_millis(500)),
+    );
+    let host = "http://beacon.test";
+    let engine = support::mock_engine(beacon_engine_config(), &mock).await;
+    engine
+        .load_url("data:text/html,<p>first</p>")
+        .await
+        .unwrap();
+    engine
+        .execute_javascript(&format!(
+            "addEventListener('pagehide', () => navigator.sendBeacon('{host}/collect', 'bye'))"
+        ))
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    engine
+        .load_url("data:text/html,<p>second</p>")
+        .await
+        .unwrap();
+    assert!(
+        start.elapsed() < Duration::from_millis(300),
+        "navigation waited for the beacon"
+    );
+
+    let beacon = wait_for_request(&mock, "http://beacon.test/collect", Duration::from_secs(5))
+        .await
+        .expect("beacon never arrived");
+    assert_eq!(beacon.method, "POST");
+    assert_eq!(beacon.body.as_deref(), Some(&b"bye"[..]));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_beacon_over_quota_is_refused() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route("POST", "http://beacon.test/*", MockResponse::new(204));
+    let host = "http://beacon.test";
+    let engine = support::mock_engine(beacon_engine_config(), &mock).await;
+    engine.load_url("data:text/html,<p>page</p>").await.unwrap();
+
+    let queued = engine
+        .execute_javascript(&format!(
+            "navigator.sendBeacon('{host}/big', 'x'.repeat(70000))"
+        ))
+        .await
+        .unwrap();
+    assert_eq!(queued, serde_json::Value::Bool(false));
+    assert!(
+        wait_for_request(&mock, "http://beacon.test/*", Duration::from_millis(300))
+            .await
+            .is_none()
+    );
+}
+
+/// A transport answering every request with a cacheable page.
+#[cfg(feature = "test-util")]
+fn cacheable_transport() -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport>
+{
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route(
+        "GET",
+        "*",
+        MockResponse::ok("text/html", "<html>cached</html>")
+            .header("Cache-Control", "max-age=3600"),
+    );
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+fn disk_cache_config(directory: &std::path::Path) -> BrowserConfig {
+    use vulkan_browser_engine::core::network::DiskCacheConfig;
+
+    BrowserConfig {
+        disk_cache: DiskCacheConfig {
+            directory: Some(directory.to_path_buf()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_disk_cache_serves_after_restart() {
+    let mock = cacheable_transport();
+    let dir = tempfile::tempdir().unwrap();
+    let url = "http://cache.test/page";
+
+    let first = NetworkManager::with_transport(&disk_cache_config(dir.path()), mock.clone())
+        .await
+        .unwrap();
+    assert_eq!(first.fetch(url).await.unwrap(), "<html>cached</html>");
+    first.shutdown().await.unwrap();
+    drop(first);
+    assert_eq!(mock.requests().len(), 1);
+
+    let second = NetworkManager::with_transport(&disk_cache_config(dir.path()), mock.clone())
+        .await
+        .unwrap();
+    assert_eq!(second.fetch(url).await.unwrap(), "<html>cached</html>");
+    assert_eq!(mock.requests().len(), 1);
+    assert_eq!(second.get_metrics().cache_hits, 1);
+
+    second.clear_cache();
+    assert_eq!(second.get_disk_cache_stats().unwrap().entry_count, 0);
+    assert!(!dir.path().join("index.json").exists());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_corrupt_disk_cache_blob_is_refetched() {
+    let mock = cacheable_transport();
+    let dir = tempfile::tempdir().unwrap();
+    let url = "http://cache.test/page";
+
+    let first = NetworkManager::with_transport(&disk_cache_config(dir.path()), mock.clone())
+        .await
+        .unwrap();
+    first.fetch(url).await.unwrap();
+    drop(first);
+
+    // Simulate a write torn by a power cut.
+    for blob in std::fs::read_dir(dir.path().join("blobs")).unwrap() {
+        std::fs::write(blob.unwrap().path(), b"<html>cach").unwrap();
+    }
+
+    let second = NetworkManager::with_transport(&disk_cache_config(dir.path()), mock.clone())
+        .await
+        .unwrap();
+    assert_eq!(second.fetch(url).await.unwrap(), "<html>cached</html>");
+    assert_eq!(mock.requests().len(), 2);
+    assert_eq!(second.get_disk_cache_stats().unwrap().entry_count, 1);
+}
+
+/// A minimal TrueType font: 'a'..='z' map to glyphs that advance `advance`
+/// units of a 1000-unit em. Glyphs past the last metric reuse its advance.
+#[cfg(feature = "test-util")]
+fn tiny_font(advance: u16) -> Vec<u8> {
+    let be16 =
+        |values: &[u16]| -> Vec<u8> { values.iter().flat_map(|v| v.to_be_bytes()).collect() };
+
+    let mut head = vec![0u8; 54];
+    head[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    head[12..16].copy_from_slice(&0x5F0F_3CF5u32.to_be_bytes());
+    head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+    let mut hhea = vec![0u8; 36];
+    hhea[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    hhea[4..6].copy_from_slice(&800u16.to_be_bytes());
+    hhea[34..36].copy_from_slice(&2u16.to_be_bytes());
+    let maxp = [&0x0000_5000u32.to_be_bytes()[..], &27u16.to_be_bytes()].concat();
+    let mut hmtx = be16(&[500, 0, advance, 0]);
+    hmtx.resize(hmtx.len() + 25 * 2, 0);
+    // One (3, 1) format 4 subtable mapping 'a'..='z' onto glyphs 1..=26.
+    let mut cmap = be16(&[0, 1, 3, 1, 0, 12]);
+    cmap.extend(be16(&[4, 32, 0, 4, 4, 1, 0]));
+    cmap.extend(be16(&[0x7A, 0xFFFF, 0, 0x61, 0xFFFF]));
+    cmap.extend(be16(&[1u16.wrapping_sub(0x61), 1, 0, 0]));
+
+    let tables = [
+        (b"cmap", cmap),
+        (b"head", head),
+        (b"hhea", hhea),
+        (b"hmtx", hmtx),
+        (b"maxp", maxp),
+    ];
+    let mut font = [&0x0001_0000u32.to_be_bytes()[..], &be16(&[5, 0, 0, 0])].concat();
+    let mut offset = 12 + 16 * tables.len();
+    for (tag, table) in &tables {
+        font.extend_from_slice(*tag);
+        font.extend_from_slice(&[0; 4]);
+        font.extend_from_slice(&(offset as u32).to_be_bytes());
+        font.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    for (_, table) in &tables {
+        font.extend_from_slice(table);
+        font.resize((font.len() + 3) & !3, 0);
+    }
+    font
+}
+
+/// Serves `font` at every path of `http://fonts.test` after `delay`, shared
+/// with any origin.
+#[cfg(feature = "test-util")]
+fn font_host(
+    font: Vec<u8>,
+    delay: Duration,
+) -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route(
+        "GET",
+        "http://fonts.test/*",
+        MockResponse::ok("font/ttf", font)
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Cache-Control", "no-store")
+            .latency(delay),
+    );
+    std::sync::Arc::new(mock)
+}
+
+/// Declare family "Wide" from `fonts.test` with `font-display: display`, as
+/// a cross-origin document would, and start loading it through `mock`.
+#[cfg(feature = "test-util")]
+async fn load_wide_font(
+    mock: &std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport>,
+    display: &str,
+) -> std::sync::Arc<vulkan_browser_engine::core::fonts::FontFaceSet> {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::css::CSSParser;
+    use vulkan_browser_engine::core::fonts::{FontFaceSet, FontLoader};
+    use vulkan_browser_engine::core::network::RequestInitiator;
+
+    let document_url = url::Url::parse("http://document.test/page").unwrap();
+    let css = format!(
+        "@font-face {{ font-family: \"Wide\"; src: url(\"http://fonts.test/wide.ttf\") format(\"truetype\"); font-display: {display}; }}"
+    );
+    let rules = CSSParser::new().parse(&css).unwrap();
+
+    let network = Arc::new(support::mock_network(&BrowserConfig::default(), mock).await);
+    let loader = Arc::new(FontLoader::new(
+        network,
+        RequestInitiator::new(document_url.clone()),
+    ));
+    let fonts = Arc::new(FontFaceSet::new());
+    assert_eq!(fonts.add_rules(&rules, &document_url).len(), 1);
+    fonts.load_all(&loader);
+    fonts
+}
+
+#[cfg(feature = "test-util")]
+async fn wait_for_font_loads(
+    fonts: &vulkan_browser_engine::core::fonts::FontFaceSet,
+) -> Vec<vulkan_browser_engine::core::fonts::FontLoadEvent> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let events = fonts.take_events();
+        if !events.is_empty() || Instant::now() > deadline {
+            return events;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_web_font_changes_text_advances_once_loaded() {
+    use vulkan_browser_engine::core::fonts::FontFaceStatus;
+
+    let mock = font_host(tiny_font(1000), Duration::ZERO);
+    let fonts = load_wide_font(&mock, "swap").await;
+
+    // Nothing to shape with yet: callers keep their fallback metrics.
+    assert!(fonts.is_loading());
+    assert_eq!(fonts.measure_text("'Wide', sans-serif", 10.0, "abc"), None);
+
+    let events = wait_for_font_loads(&fonts).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(
+        events[0].status,
+        FontFaceStatus::Loaded,
+        "{:?}",
+        events[0].error
+    );
+    assert!(!fonts.is_loading());
+    assert!(fonts.take_layout_dirty());
+    assert_eq!(
+        fonts.measure_text("'Wide', sans-serif", 10.0, "abc"),
+        Some(30.0)
+    );
+    assert_eq!(fonts.measure_text("Serif", 10.0, "abc"), None);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_font_display_optional_keeps_fallback_when_load_is_late() {
+    use vulkan_browser_engine::core::fonts::{FontFaceStatus, BLOCK_PERIOD};
+
+    let mock = font_host(tiny_font(1000), BLOCK_PERIOD * 4);
+    let fonts = load_wide_font(&mock, "optional").await;
+
+    let events = wait_for_font_loads(&fonts).await;
+    assert_eq!(events.len(), 1);
+    // The face itself loads, but the page keeps the text it already showed.
+    assert_eq!(
+        events[0].status,
+        FontFaceStatus::Loaded,
+        "{:?}",
+        events[0].error
+    );
+    assert!(!fonts.take_layout_dirty());
+    assert_eq!(fonts.measure_text("Wide", 10.0, "abc"), None);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_text_runs_are_shaped_in_their_content_language() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::fonts::ShapedRun;
+
+    let mock = font_host(tiny_font(1000), Duration::ZERO);
+    let host = "http://fonts.test";
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    let runs = Arc::new(Mutex::new(HashMap::new()));
+    let seen = runs.clone();
+    engine.set_shaping_observer(Some(move |run: &ShapedRun| {
+        seen.lock()
+            .unwrap()
+            .insert(run.text.clone(), (run.family.clone(), run.language.clone()));
+    }));
+    engine
+        .load_url(&format!(
+            "data:text/html,<html lang=en><head><style>\
+             @font-face {{ font-family: Tiny; src: url(\"{host}/tiny.ttf\") }}\
+             body {{ font-family: Tiny, sans-serif }}</style></head>\
+             <body><p>hello</p><div lang=fr><p>bonjour</p>\
+             <p lang=de-CH>gruezi <span lang=''>unknown</span></p></div></body></html>"
+        ))
+        .await
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while runs.lock().unwrap().len() < 4 {
+        assert!(Instant::now() < deadline, "{:?}", runs.lock().unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        engine.tick().await.unwrap();
+    }
+    let runs = runs.lock().unwrap().clone();
+    let run = |language: Option<&str>| Some(("Tiny".to_string(), language.map(str::to_string)));
+    assert_eq!(runs.get("hello").cloned(), run(Some("en")));
+    assert_eq!(runs.get("bonjour").cloned(), run(Some("fr")));
+    assert_eq!(runs.get("gruezi ").cloned(), run(Some("de-CH")));
+    assert_eq!(runs.get("unknown").cloned(), run(None));
+}
+
+/// Serves `http://auth.test` only to requests carrying
+/// `Authorization: Basic user:secret`; everything else gets a Basic
+/// challenge for realm "vbe".
+#[cfg(feature = "test-util")]
+fn auth_host() -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route_fn("GET", "http://auth.test/*", |request| {
+        match request.header("Authorization") {
+            // base64("user:secret")
+            Some("Basic dXNlcjpzZWNyZXQ=") => MockResponse::new(200)
+                .header("Cache-Control", "no-store")
+                .body("<html></html>"),
+            _ => MockResponse::new(401)
+                .header("WWW-Authenticate", "Basic realm=\"vbe\", charset=\"UTF-8\""),
+        }
+    });
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+fn auth_handler(
+    password: &'static str,
+    prompts: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+) -> vulkan_browser_engine::core::network::AuthHandler {
+    use futures::FutureExt;
+    use std::sync::atomic::Ordering;
+    use vulkan_browser_engine::core::network::{AuthChallenge, AuthScheme, Credentials};
+
+    std::sync::Arc::new(move |challenge: AuthChallenge| {
+        assert_eq!(challenge.scheme, AuthScheme::Basic);
+        assert_eq!(challenge.realm.as_deref(), Some("vbe"));
+        prompts.fetch_add(1, Ordering::SeqCst);
+        async move {
+            Some(Credentials::Basic {
+                username: "user".to_string(),
+                password: password.to_string(),
+            })
+        }
+        .boxed()
+    })
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_basic_auth_prompts_once_per_realm() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mock = auth_host();
+    let host = "http://auth.test";
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+
+    // Without stored credentials a plain fetch does not prompt.
+    let prompts = Arc::new(AtomicUsize::new(0));
+    network.set_auth_handler(Some(auth_handler("secret", prompts.clone())));
+    let response = network.fetch_response(&format!("{host}/")).await.unwrap();
+    assert_eq!(response.status, 401);
+    assert_eq!(prompts.load(Ordering::SeqCst), 0);
+
+    let response = network
+        .fetch_navigation(&format!("{host}/private"))
+        .await
+        .unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(prompts.load(Ordering::SeqCst), 1);
+
+    // Same origin and realm: the validated credentials are reused silently.
+    let response = network
+        .fetch_navigation(&format!("{host}/other"))
+        .await
+        .unwrap();
+    assert_eq!(response.status, 200);
+    let response = network.fetch_response(&format!("{host}/")).await.unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(prompts.load(Ordering::SeqCst), 1);
+
+    network.clear_credentials();
+    let response = network.fetch_response(&format!("{host}/")).await.unwrap();
+    assert_eq!(response.status, 401);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_basic_auth_gives_up_after_retry_limit() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::MAX_AUTH_RETRIES;
+
+    let mock = auth_host();
+    let host = "http://auth.test";
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+    let prompts = Arc::new(AtomicUsize::new(0));
+    network.set_auth_handler(Some(auth_handler("wrong", prompts.clone())));
+
+    let response = network
+        .fetch_navigation(&format!("{host}/private"))
+        .await
+        .unwrap();
+    assert_eq!(response.status, 401);
+    assert_eq!(prompts.load(Ordering::SeqCst), MAX_AUTH_RETRIES);
+}
+
+#[test]
+fn test_parse_auth_challenges() {
+    use vulkan_browser_engine::core::network::auth::parse_challenges;
+
+    let challenges =
+        parse_challenges(r#"Basic realm="a, \"b\"", charset="UTF-8", Bearer abc==, Newauth"#);
+    assert_eq!(challenges.len(), 3);
+    assert_eq!(challenges[0].0, "Basic");
+    assert_eq!(challenges[0].1["realm"], "a, \"b\"");
+    assert_eq!(challenges[0].1["charset"], "UTF-8");
+    assert_eq!(challenges[1].1["token68"], "abc==");
+    assert!(challenges[2].1.is_empty());
+}
+
+#[test]
+fn test_cookie_jar_scopes_cookies_by_domain_path_scheme_and_expiry() {
+    use url::Url;
+    use vulkan_browser_engine::core::network::CookieJar;
+
+    let url = |s: &str| Url::parse(s).unwrap();
+    let jar = CookieJar::new();
+    let page = url("https://www.shop.test/account/orders");
+
+    // Domain: with the attribute, subdomains too; without, the host only.
+    assert!(jar.set_cookie(&page, "sid=1; Domain=.shop.test; Path=/"));
+    assert!(jar.set_cookie(&page, "theme=dark; Path=/"));
+    assert!(!jar.set_cookie(&page, "x=1; Domain=other.test"));
+    assert!(!jar.set_cookie(&page, "x=1; Domain=test"));
+    assert_eq!(
+        jar.cookie_header(&url("https://api.shop.test/")).as_deref(),
+        Some("sid=1")
+    );
+    assert_eq!(
+        jar.cookie_header(&url("https://www.shop.test/")).as_deref(),
+        Some("sid=1; theme=dark")
+    );
+    assert_eq!(jar.cookie_header(&url("https://shop.test.evil/")), None);
+
+    // Path: the default is the directory of the URL; longer paths go first.
+    assert!(jar.set_cookie(&page, "tab=2"));
+    assert_eq!(jar.cookies_for(&page)[0].path, "/account");
+    assert_eq!(
+        jar.cookie_header(&url("https://www.shop.test/account/settings"))
+            .as_deref(),
+        Some("tab=2; sid=1; theme=dark")
+    );
+    assert_eq!(
+        jar.cookie_header(&url("https://www.shop.test/accounts"))
+            .as_deref(),
+        Some("sid=1; theme=dark")
+    );
+
+    // Secure: never over http://, and never set from it.
+    assert!(jar.set_cookie(&page, "token=s; Secure; Path=/"));
+    assert!(!jar.set_cookie(&url("http://www.shop.test/"), "plain=1; Secure"));
+    assert!(jar
+        .cookie_header(&url("https://www.shop.test/"))
+        .unwrap()
+        .contains("token=s"));
+    assert_eq!(
+        jar.cookie_header(&url("http://www.shop.test/")).as_deref(),
+        Some("sid=1; theme=dark")
+    );
+
+    // Expiry: a past date is not stored and deletes what it replaces;
+    // Max-Age wins over Expires.
+    let site = url("https://news.test/");
+    assert!(jar.set_cookie(&site, "old=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT"));
+    assert_eq!(jar.cookie_header(&site), None);
+    assert!(jar.set_cookie(&site, "keep=1; Expires=Wed, 21 Oct 2099 07:28:00 GMT"));
+    let kept = jar.cookies_for(&site);
+    assert_eq!(kept[0].expires, Some(4_096_250_880));
+    assert!(kept[0].is_persistent());
+    assert!(jar.set_cookie(
+        &site,
+        "keep=1; Max-Age=0; Expires=Wed, 21 Oct 2099 07:28:00 GMT"
+    ));
+    assert_eq!(jar.cookie_header(&site), None);
+
+    let restored = CookieJar::new();
+    restored.load_json(&jar.to_json().unwrap()).unwrap();
+    assert_eq!(restored.cookies_for(&page), jar.cookies_for(&page));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_set_cookie_responses_are_sent_back_on_later_requests() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://shop.test/login",
+        MockResponse::ok("text/html", "<p>Welcome</p>")
+            .header("Cache-Control", "no-store")
+            .header(
+                "Set-Cookie",
+                "sid=abc; Path=/; HttpOnly\nlang=en; Path=/help",
+            ),
+    );
+    mock.route(
+        "GET",
+        "http://shop.test/cart",
+        MockResponse::ok("text/html", "<p>Cart</p>").header("Cache-Control", "no-store"),
+    );
+    let network = NetworkManager::with_transport(&BrowserConfig::default(), mock.clone())
+        .await
+        .unwrap();
+
+    network.fetch("http://shop.test/login").await.unwrap();
+    network.fetch("http://shop.test/cart").await.unwrap();
+    let cart = mock.requests_to("http://shop.test/cart");
+    assert_eq!(cart[0].header("Cookie"), Some("sid=abc"));
+
+    let cookies = network.get_cookies("http://shop.test/help/faq").unwrap();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies.iter().any(|cookie| cookie.http_only));
+    assert!(network.set_cookie("http://shop.test/", "cart=3").unwrap());
+
+    network.clear_cookies();
+    network.fetch("http://shop.test/cart").await.unwrap();
+    let cart = mock.requests_to("http://shop.test/cart");
+    assert_eq!(cart[1].header("Cookie"), None);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test(start_paused = true)]
+async fn test_transient_failures_are_retried_for_idempotent_requests_only() {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::{FetchRequest, Priority};
+
+    let mock = Arc::new(MockTransport::new());
+    let flaky = || {
+        vec![
+            MockResponse::connection_error("connection reset by peer"),
+            MockResponse::ok("application/json", "{}").header("Cache-Control", "no-store"),
+        ]
+    };
+    mock.route_sequence("GET", "http://api.test/items", flaky());
+    mock.route_sequence("POST", "http://api.test/orders", flaky());
+    mock.route_sequence("POST", "http://api.test/search", flaky());
+    let network = NetworkManager::with_transport(&BrowserConfig::default(), mock.clone())
+        .await
+        .unwrap();
+
+    // One logical fetch, two trips to the transport.
+    let items = network
+        .fetch_response("http://api.test/items")
+        .await
+        .unwrap();
+    assert_eq!(items.status, 200);
+    assert_eq!(items.retries, 1);
+    assert_eq!(mock.requests_to("http://api.test/items").len(), 2);
+
+    let post = |url: &str, idempotent| FetchRequest {
+        url: url.to_string(),
+        method: "POST".to_string(),
+        headers: HashMap::new(),
+        body: Some(b"{}".to_vec()),
+        timeout_ms: None,
+        follow_redirects: true,
+        cache_policy: None,
+        priority: Priority::High,
+        idempotent,
+    };
+    let order = network
+        .fetch_with_request(post("http://api.test/orders", false))
+        .await;
+    assert!(matches!(order, Err(NetworkError::Connection(_))));
+    assert_eq!(mock.requests_to("http://api.test/orders").len(), 1);
+
+    let search = network
+        .fetch_with_request(post("http://api.test/search", true))
+        .await
+        .unwrap();
+    assert_eq!(search.retries, 1);
+
+    let metrics = network.get_metrics();
+    assert_eq!(metrics.total_requests, 3);
+    assert_eq!(metrics.retried_requests, 2);
+    assert_eq!(metrics.retries, 2);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test(start_paused = true)]
+async fn test_retry_waits_for_retry_after() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route_sequence(
+        "GET",
+        "http://busy.test/",
+        vec![
+            MockResponse::new(503).header("Retry-After", "1"),
+            MockResponse::ok("text/html", "<p>back</p>").header("Cache-Control", "no-store"),
+        ],
+    );
+    mock.route_sequence(
+        "GET",
+        "http://busy.test/later",
+        vec![
+            MockResponse::new(503).header("Retry-After", "3600"),
+            MockResponse::ok("text/html", "<p>back</p>"),
+        ],
+    );
+    let network = NetworkManager::with_transport(&BrowserConfig::default(), mock.clone())
+        .await
+        .unwrap();
+
+    let started = tokio::time::Instant::now();
+    let response = network.fetch_response("http://busy.test/").await.unwrap();
+    assert_eq!(response.status, 200);
+    let waited = started.elapsed();
+    assert!(waited >= Duration::from_secs(1) && waited < Duration::from_millis(1100));
+
+    // Longer than the retry policy waits: the 503 is the answer.
+    let started = tokio::time::Instant::now();
+    let response = network
+        .fetch_response("http://busy.test/later")
+        .await
+        .unwrap();
+    assert_eq!(response.status, 503);
+    assert_eq!(response.retries, 0);
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test(start_paused = true)]
+async fn test_cancelling_a_request_ends_its_backoff() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://slow.test/",
+        MockResponse::new(503).header("Retry-After", "5"),
+    );
+    let network = Arc::new(
+        NetworkManager::with_transport(&BrowserConfig::default(), mock.clone())
+            .await
+            .unwrap(),
+    );
+
+    let started = tokio::time::Instant::now();
+    let fetch = tokio::spawn({
+        let network = network.clone();
+        async move { network.fetch_response("http://slow.test/").await }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    network.cancel_all_requests().await;
+    assert!(fetch.await.unwrap().is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(mock.requests_to("http://slow.test/").len(), 1);
+}
+
+/// Answers every request to `http://echo.test` with JSON of its method,
+/// `X-Token`, `Sec-Custom` and `Content-Type` headers and body. Nothing
+/// answers at `http://down.test`.
+#[cfg(feature = "test-util")]
+fn echo_host() -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route_fn("*", "http://echo.test/*", |request| {
+        let echo = serde_json::json!({
+            "method": request.method,
+            "token": request.header("X-Token"),
+            "sec": request.header("Sec-Custom"),
+            "contentType": request.header("Content-Type"),
+            "body": String::from_utf8_lossy(request.body.as_deref().unwrap_or_default()),
+        });
+        MockResponse::ok("application/json", echo.to_string())
+    });
+    mock.route(
+        "*",
+        "http://down.test/*",
+        MockResponse::connection_error("Connection refused"),
+    );
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_script_fetch_sends_method_headers_and_body() {
+    let mock = echo_host();
+    let host = "http://echo.test";
+    let engine = support::mock_engine(beacon_engine_config(), &mock).await;
+    engine.load_url("data:text/html,<p>api</p>").await.unwrap();
+    engine
+        .execute_javascript(&format!(
+            "(async () => {{\
+               const response = await fetch('{host}/echo', {{\
+                 method: 'POST',\
+                 headers: [['X-Token', 'abc'], ['Sec-Custom', '1'], ['Content-Type', 'application/json']],\
+                 body: JSON.stringify({{ n: 1 }}),\
+               }});\
+               const echo = await response.json();\
+               const bytes = await fetch('{host}/echo', {{\
+                 method: 'PUT',\
+                 body: new Uint8Array([104, 105]).buffer,\
+               }}).then((r) => r.json());\
+               let error = null;\
+               try {{ await fetch('http://down.test/'); }} catch (e) {{ error = e.name; }}\
+               window.result = {{\
+                 status: response.status,\
+                 type: response.headers.get('content-type'),\
+                 echo,\
+                 bytes: bytes.body,\
+                 error,\
+               }};\
+             }})()"
+        ))
+        .await
+        .unwrap();
+
+    let result = tick_until_defined(&engine, "window.result").await;
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["type"], "application/json");
+    assert_eq!(result["echo"]["method"], "POST");
+    assert_eq!(result["echo"]["token"], "abc");
+    assert_eq!(result["echo"]["contentType"], "application/json");
+    assert_eq!(result["echo"]["body"], "{\"n\":1}");
+    // The browser owns `Sec-` headers; script cannot set them.
+    assert!(result["echo"]["sec"].is_null());
+    assert_eq!(result["bytes"], "hi");
+    assert_eq!(result["error"], "TypeError");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_script_fetches_in_flight_are_capped_per_page() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://api.test/*",
+        MockResponse::new(204).latency(Duration::from_millis(300)),
+    );
+    let host = "http://api.test";
+    let engine = support::mock_engine(
+        BrowserConfig {
+            max_script_fetches: 1,
+            ..beacon_engine_config()
+        },
+        &mock,
+    )
+    .await;
+    engine.load_url("data:text/html,<p>cap</p>").await.unwrap();
+    engine
+        .execute_javascript(&format!(
+            "window.outcomes = [];\
+             const first = fetch('{host}/a').then((r) => window.outcomes.push(r.status));\
+             fetch('{host}/b').catch((e) => window.outcomes.push(e.name));\
+             first.then(() => fetch('{host}/c')).then((r) => {{\
+               window.outcomes.push(r.status);\
+               window.done = window.outcomes;\
+             }});"
+        ))
+        .await
+        .unwrap();
+
+    let outcomes = tick_until_defined(&engine, "window.done").await;
+    assert_eq!(outcomes, serde_json::json!(["TypeError", 204, 204]));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_slow_stylesheet_misses_first_paint_and_is_applied_on_arrival() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::css::{FoucControl, StylesheetLoadingConfig};
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let delay = Duration::from_millis(800);
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://css.test/*",
+        MockResponse::ok("text/css", "p { text-transform: uppercase }")
+            .header("Cache-Control", "no-store")
+            .latency(delay),
+    );
+    let host = "http://css.test";
+    let engine = support::mock_engine(
+        BrowserConfig {
+            stylesheet_loading: StylesheetLoadingConfig {
+                stylesheet_timeout_ms: 5_000,
+                render_blocking_budget_ms: 200,
+                fouc_control: FoucControl::BlockUntilBudget,
+            },
+            ..Default::default()
+        },
+        &mock,
+    )
+    .await;
+
+    let started = Instant::now();
+    engine
+        .load_url(&format!(
+            "data:text/html,<html><head><link rel=\"stylesheet\" href=\"{host}/slow.css\"></head>\
+             <body><p>late</p></body></html>"
+        ))
+        .await
+        .unwrap();
+    // The first frame is out before the stylesheet is.
+    assert!(started.elapsed() < delay);
+    let transform = |styles: serde_json::Value| styles[0]["styles"]["text-transform"].clone();
+    let unstyled = engine.dump_computed_styles("p").await.unwrap();
+    assert_ne!(transform(unstyled), "uppercase");
+
+    let warnings = engine.get_recent_events(None, Some(EventKindMask::PERFORMANCE_WARNING));
+    let warnings = serde_json::to_value(&warnings).unwrap();
+    assert_eq!(warnings.as_array().unwrap().len(), 1);
+    assert_eq!(warnings[0]["event"]["url"], format!("{host}/slow.css"));
+    assert_eq!(warnings[0]["event"]["threshold"], 200.0);
+
+    // A second, styled frame follows once it arrives.
+    tokio::time::sleep(delay).await;
+    engine.tick().await.unwrap();
+    let styled = engine.dump_computed_styles("p").await.unwrap();
+    assert_eq!(transform(styled), "uppercase");
+}
+
+#[cfg(feature = "test-util")]
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(payload);
+    data
+}
+
+/// An MP4 whose `moov` says `duration_ms` long with a `width`×`height`
+/// video track, followed by some media data.
+#[cfg(feature = "test-util")]
+fn tiny_mp4(duration_ms: u32, width: u32, height: u32) -> Vec<u8> {
+    let mut mvhd = vec![0u8; 100];
+    mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+    mvhd[16..20].copy_from_slice(&duration_ms.to_be_bytes());
+    let mut tkhd = vec![0u8; 84];
+    tkhd[76..80].copy_from_slice(&(width << 16).to_be_bytes());
+    tkhd[80..84].copy_from_slice(&(height << 16).to_be_bytes());
+
+    let mut moov = mp4_box(b"mvhd", &mvhd);
+    moov.extend(mp4_box(b"trak", &mp4_box(b"tkhd", &tkhd)));
+    let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0isommp41");
+    file.extend(mp4_box(b"moov", &moov));
+    file.extend(mp4_box(b"mdat", &[0u8; 4096]));
+    file
+}
+
+/// Serves `body` as `content_type` at every path of `http://media.test`,
+/// answering `Range` requests for a prefix with 206.
+#[cfg(feature = "test-util")]
+fn media_host(
+    body: Vec<u8>,
+    content_type: &'static str,
+) -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    mock.route_fn("GET", "http://media.test/*", move |request| {
+        let end = request
+            .header("Range")
+            .and_then(|range| range.strip_prefix("bytes=0-"))
+            .and_then(|end| end.trim().parse::<usize>().ok())
+            .map_or(body.len(), |end| (end + 1).min(body.len()));
+        let status = if end < body.len() { 206 } else { 200 };
+        MockResponse::new(status)
+            .header("Content-Type", content_type)
+            .header("Cache-Control", "no-store")
+            .body(body[..end].to_vec())
+    });
+    std::sync::Arc::new(mock)
+}
+
+/// Tick `engine` until `expression` is not `undefined`.
+#[cfg(feature = "test-util")]
+async fn tick_until_defined(
+    engine: &vulkan_browser_engine::BrowserEngine,
+    expression: &str,
+) -> serde_json::Value {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        engine.tick().await.unwrap();
+        let value = engine
+            .execute_javascript(&format!(
+                "typeof {expression} === 'undefined' ? null : {expression}"
+            ))
+            .await
+            .unwrap();
+        if !value.is_null() || Instant::now() > deadline {
+            return value;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_video_loads_metadata_without_playing() {
+    let mock = media_host(tiny_mp4(2500, 640, 360), "video/mp4");
+    let host = "http://media.test";
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    engine
+        .load_url(&format!(
+            "data:text/html,<video id=v src=\"{host}/clip.mp4\"></video><script>\
+             const v = document.getElementById('v');\
+             globalThis.seen = [];\
+             for (const type of ['loadstart', 'durationchange', 'resize', 'loadedmetadata', 'suspend', 'error']) {{\
+               v.addEventListener(type, () => seen.push(type));\
+             }}\
+             new Promise((resolve) => v.addEventListener('loadedmetadata', resolve)).then(() => {{\
+               globalThis.metadata = [v.readyState, v.networkState, v.duration, v.videoWidth, v.videoHeight, v.paused];\
+               v.currentTime = 99;\
+               globalThis.clamped = v.currentTime;\
+             }});\
+             </script>"
+        ))
+        .await
+        .unwrap();
+
+    let metadata = tick_until_defined(&engine, "metadata").await;
+    assert_eq!(metadata, serde_json::json!([1, 1, 2.5, 640, 360, true]));
+    assert_eq!(
+        engine.execute_javascript("[seen, clamped]").await.unwrap(),
+        serde_json::json!([
+            [
+                "loadstart",
+                "durationchange",
+                "resize",
+                "loadedmetadata",
+                "suspend"
+            ],
+            2.5
+        ])
+    );
+
+    // The video's own size replaces the default once known.
+    engine.tick().await.unwrap();
+    let tree = engine.dump_layout_tree().await;
+    let video = tree
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|node| node["tag"] == "video")
+        .unwrap();
+    assert_eq!(video["bounds"]["width"], 640.0);
+    assert_eq!(video["bounds"]["height"], 360.0);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_unsupported_media_fires_error_with_src_not_supported() {
+    let mock = media_host(b"<html>not media</html>".to_vec(), "text/html");
+    let host = "http://media.test";
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    engine
+        .load_url(&format!(
+            "data:text/html,<audio id=a src=\"{host}/song.mp3\"></audio><script>\
+             const a = document.getElementById('a');\
+             a.addEventListener('error', () => {{\
+               globalThis.failure = [a.error.code, a.error.code === MediaError.MEDIA_ERR_SRC_NOT_SUPPORTED,\
+                 a.networkState === a.NETWORK_NO_SOURCE, a.readyState, Number.isNaN(a.duration)];\
+             }});\
+             </script>"
+        ))
+        .await
+        .unwrap();
+
+    let failure = tick_until_defined(&engine, "failure").await;
+    assert_eq!(failure, serde_json::json!([4, true, true, 0, true]));
+}
+
+#[test]
+fn test_preload_scanner_finds_references_across_chunks() {
+    use vulkan_browser_engine::core::network::{Destination, PreloadScanner};
+
+    let mut scanner =
+        PreloadScanner::new(url::Url::parse("http://page.test/dir/index.html").unwrap());
+    let mut found = scanner.feed(b"<html><head><link rel=stylesheet hr");
+    assert!(found.is_empty());
+    found.extend(scanner.feed(b"ef=\"a.css\"><!-- <img src=commented.png> -->"));
+    found.extend(scanner.feed(
+        b"<script>document.write('<img src=scripted.png>')</script>\
+          <template><img src=inert.png></template>\
+          <base href=\"http://cdn.test/assets/\"><img srcset=\"big.png 2x, small.png 1x\" src=fallback.png>\
+          <link rel=preload as=image href=hero.png><script src=app.js></script>\
+          <img src=\"data:image/png;base64,AAAA\"><img src=small.png>",
+    ));
+
+    let found: Vec<(String, Destination)> = found
+        .into_iter()
+        .map(|preload| (preload.url.to_string(), preload.destination))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("http://page.test/dir/a.css".to_string(), Destination::Style),
+            (
+                "http://cdn.test/assets/app.js".to_string(),
+                Destination::Script
+            ),
+            (
+                "http://cdn.test/assets/small.png".to_string(),
+                Destination::Image
+            ),
+            (
+                "http://cdn.test/assets/hero.png".to_string(),
+                Destination::Image
+            ),
+        ]
+    );
+}
+
+/// A page of five images at `http://images.test/`, each answered `delay`
+/// late; an unused image is preloaded as well.
+#[cfg(feature = "test-util")]
+fn image_page_host(
+    delay: Duration,
+) -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let images: String = (1..=5)
+        .map(|i| format!("<p>Image {i}</p><img src=/img{i}.png>"))
+        .collect();
+    let mock = MockTransport::new();
+    mock.route(
+        "GET",
+        "http://images.test/*",
+        MockResponse::ok("image/png", "png")
+            .header("Cache-Control", "no-store")
+            .latency(delay),
+    );
+    mock.route(
+        "GET",
+        "http://images.test/",
+        MockResponse::ok(
+            "text/html",
+            format!(
+                "<html><head><link rel=preload as=image href=/unused.png></head>\
+                 <body>{images}</body></html>"
+            ),
+        )
+        .header("Cache-Control", "no-store"),
+    );
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_preload_scanner_overlaps_image_fetches_without_duplicates() {
+    let mut load_times = Vec::new();
+    for max_speculative_fetches in [0, 32] {
+        let mock = image_page_host(Duration::from_millis(150));
+        let engine = support::mock_engine(
+            BrowserConfig {
+                max_speculative_fetches,
+                ..beacon_engine_config()
+            },
+            &mock,
+        )
+        .await;
+
+        let start = Instant::now();
+        engine.load_url("http://images.test/").await.unwrap();
+        load_times.push(start.elapsed());
+
+        let requests = |path: &str| mock.requests_to(&format!("http://images.test{path}")).len();
+        for i in 1..=5 {
+            assert_eq!(
+                requests(&format!("/img{i}.png")),
+                1,
+                "{:?}",
+                mock.requests()
+            );
+        }
+
+        let metrics = engine.get_performance_metrics().await.network;
+        if max_speculative_fetches == 0 {
+            assert_eq!(requests("/unused.png"), 0);
+            assert_eq!(metrics.speculative_fetches_issued, 0);
+        } else {
+            assert_eq!(metrics.speculative_fetches_issued, 6);
+            assert_eq!(metrics.speculative_fetches_used, 5);
+            engine.load_url("data:text/html,<p>next</p>").await.unwrap();
+            let metrics = engine.get_performance_metrics().await.network;
+            assert_eq!(metrics.speculative_fetches_wasted, 1);
+        }
+    }
+
+    // Five 150ms images back to back against all of them at once.
+    assert!(
+        load_times[0] >= Duration::from_millis(750),
+        "{load_times:?}"
+    );
+    assert!(load_times[1] < Duration::from_millis(500), "{load_times:?}");
+}
+
+#[cfg(feature = "test-util")]
+const LOCALHOST_CERT: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/localhost-cert.der"
+));
+#[cfg(feature = "test-util")]
+const LOCALHOST_KEY: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/localhost-key.der"
+));
+/// SHA-256 of the fixture certificate's SubjectPublicKeyInfo.
+#[cfg(feature = "test-util")]
+const LOCALHOST_PIN: &str = "b6983c89a765b24b33ddad784f79b92d4c07d13de365c6dfec904a3b5f8c14d3";
+
+#[cfg(feature = "test-util")]
+fn pin(hex: &str) -> [u8; 32] {
+    let mut pin = [0; 32];
+    for (i, byte) in pin.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    pin
+}
+
+/// HTTPS server presenting the self-signed `localhost` fixture certificate
+/// (SANs `localhost` and `127.0.0.1`), answering every request with `body`.
+#[cfg(feature = "test-util")]
+async fn tls_host(body: &'static str) -> String {
+    use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(LOCALHOST_CERT.to_vec())],
+            PrivateKey(LOCALHOST_KEY.to_vec()),
+        )
+        .unwrap();
+    let mock = std::sync::Arc::new(MockTransport::new());
+    let host = support::serve_tls(mock.clone(), config).await;
+    mock.route(
+        "GET",
+        &format!("{host}/*"),
+        MockResponse::ok("text/html", body).header("Cache-Control", "no-store"),
+    );
+    host
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_https_response_carries_tls_details() {
+    use std::net::IpAddr;
+    use std::time::UNIX_EPOCH;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::core::network::{SubjectAltName, TlsConfig, TlsVersion};
+
+    let host = tls_host("<p>secure</p>").await;
+
+    // Nothing vouches for a self-signed certificate by default.
+    let network = NetworkManager::new(&BrowserConfig::default())
+        .await
+        .unwrap();
+    assert!(network.fetch_response(&format!("{host}/")).await.is_err());
+
+    let mut tls = TlsConfig::default();
+    tls.allow_self_signed.insert("127.0.0.1".to_string());
+    let network = NetworkManager::new(&BrowserConfig {
+        tls,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let response = network.fetch_response(&format!("{host}/")).await.unwrap();
+    let info = response.tls.expect("HTTPS responses carry TLS details");
+    assert_eq!(info.version, Some(TlsVersion::Tls13));
+    assert!(info.cipher_suite.unwrap().starts_with("TLS13_"));
+    assert!(info.self_signed_allowed);
+    assert!(!info.resumed);
+
+    assert_eq!(info.certificates.len(), 1);
+    let leaf = &info.certificates[0];
+    assert_eq!(leaf.der, LOCALHOST_CERT);
+    assert_eq!(leaf.subject, "C=US, O=Vulkan Browser Tests, CN=localhost");
+    assert_eq!(leaf.issuer, leaf.subject);
+    assert_eq!(
+        leaf.subject_alt_names,
+        [
+            SubjectAltName::Dns("localhost".to_string()),
+            SubjectAltName::Ip(IpAddr::from([127, 0, 0, 1])),
+        ]
+    );
+    // 2025-01-01 and 2125-01-01, the latter a GeneralizedTime.
+    let secs = |time: std::time::SystemTime| time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    assert_eq!(secs(leaf.not_before), 1_735_689_600);
+    assert_eq!(secs(leaf.not_after), 4_891_363_200);
+    assert_eq!(leaf.spki_sha256, pin(LOCALHOST_PIN));
+
+    // Plain HTTP has none.
+    let mock = std::sync::Arc::new(MockTransport::new());
+    let plain = support::serve(mock.clone()).await;
+    mock.route("GET", &format!("{plain}/*"), MockResponse::new(200));
+    let response = network.fetch_response(&format!("{plain}/")).await.unwrap();
+    assert!(response.tls.is_none());
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_certificate_pin_mismatch_blocks_load() {
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::network::{SecurityState, TlsConfig};
+    use vulkan_browser_engine::{BrowserEngine, BrowserEvent};
+
+    let host = tls_host("<p>pinned</p>").await;
+    let engine_with_pin = |pin: [u8; 32]| {
+        let mut tls = TlsConfig::default();
+        tls.allow_self_signed.insert("127.0.0.1".to_string());
+        tls.pins.insert("127.0.0.1".to_string(), vec![pin]);
+        BrowserEngine::new(BrowserConfig {
+            tls,
+            ..beacon_engine_config()
+        })
+    };
+
+    let engine = engine_with_pin([0; 32]).await.unwrap();
+    assert!(engine.load_url(&format!("{host}/")).await.is_err());
+    let violations: Vec<String> = engine
+        .get_recent_events(None, Some(EventKindMask::SECURITY_VIOLATION))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::SecurityViolation { description },
+            } => Some(description),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(violations, ["Certificate pin mismatch for 127.0.0.1"]);
+    assert_eq!(engine.get_security_state(), SecurityState::Insecure);
+
+    // The right pin loads; the self-signed allowance still shows.
+    let engine = engine_with_pin(pin(LOCALHOST_PIN)).await.unwrap();
+    engine.load_url(&format!("{host}/")).await.unwrap();
+    assert_eq!(
+        engine.get_security_state(),
+        SecurityState::SecureWithWarnings
+    );
+    let states: Vec<SecurityState> = engine
+        .get_recent_events(None, Some(EventKindMask::SECURITY_STATE_CHANGED))
+        .into_iter()
+        .filter_map(|e| match e.event {
+            LoggedEvent::Browser {
+                event: BrowserEvent::SecurityStateChanged { tab, state },
+            } if tab == engine.tab_id() => Some(state),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(states, [SecurityState::SecureWithWarnings]);
+    assert!(engine
+        .get_recent_events(None, Some(EventKindMask::SECURITY_VIOLATION))
+        .is_empty());
+
+    engine
+        .load_url("data:text/html,<p>plain</p>")
+        .await
+        .unwrap();
+    assert_eq!(engine.get_security_state(), SecurityState::Insecure);
+}
+
+/// Serves each `(path, content type, body)` of `files` under `origin`; other
+/// paths are 404.
+#[cfg(feature = "test-util")]
+fn serve_site(
+    mock: &vulkan_browser_engine::core::network::mock::MockTransport,
+    origin: &str,
+    files: &[(&str, &str, &str)],
+) {
+    use vulkan_browser_engine::core::network::mock::MockResponse;
+
+    for (path, content_type, body) in files {
+        mock.route(
+            "GET",
+            &format!("{origin}{path}"),
+            MockResponse::ok(content_type, *body).header("Cache-Control", "no-store"),
+        );
+    }
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_linked_stylesheets_apply_in_order_and_by_media() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://down.test/*",
+        MockResponse::connection_error("Connection refused"),
+    );
+    let host = "http://site.test";
+    serve_site(
+        &mock,
+        host,
+        &[
+            (
+                "/",
+                "text/html",
+                "<html><head>\
+             <link rel=\"stylesheet\" href=\"/css/base.css\">\
+             <link rel=\"stylesheet\" href=\"css/theme.css\" media=\"screen, print\">\
+             <link rel=\"stylesheet\" href=\"/css/print.css\" media=\"print\">\
+             </head><body><p>styled</p><h1>title</h1></body></html>",
+            ),
+            (
+                "/css/base.css",
+                "text/css",
+                "p { background-color: red } h1 { color: red }",
+            ),
+            (
+                "/css/theme.css",
+                "text/css",
+                "p { background-color: green }",
+            ),
+            ("/css/print.css", "text/css", "h1 { color: blue }"),
+        ],
+    );
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+
+    engine.load_url(&format!("{host}/")).await.unwrap();
+    // The later sheet wins; the print sheet does not apply on screen.
+    let p = engine.dump_computed_styles("p").await.unwrap();
+    assert_eq!(p[0]["styles"]["background-color"], "#008000");
+    let h1 = engine.dump_computed_styles("h1").await.unwrap();
+    assert_eq!(h1[0]["styles"]["color"], "#FF0000");
+
+    // A sheet that fails is reported; the page loads without it.
+    engine
+        .load_url(&format!(
+            "data:text/html,<html><head>\
+             <link rel=\"stylesheet\" href=\"http://down.test/gone.css\">\
+             <link rel=\"stylesheet\" href=\"{host}/css/theme.css\">\
+             </head><body><p>partly styled</p></body></html>"
+        ))
+        .await
+        .unwrap();
+    let p = engine.dump_computed_styles("p").await.unwrap();
+    assert_eq!(p[0]["styles"]["background-color"], "#008000");
+    let errors = engine.get_recent_events(None, Some(EventKindMask::NETWORK_ERROR));
+    let errors = serde_json::to_value(&errors).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1);
+    assert_eq!(errors[0]["event"]["url"], "http://down.test/gone.css");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_external_scripts_run_in_document_order() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::event_log::EventKindMask;
+    use vulkan_browser_engine::core::network::mock::MockTransport;
+
+    let mock = Arc::new(MockTransport::new());
+    let host = "http://site.test";
+    serve_site(
+        &mock,
+        host,
+        &[
+            (
+                "/",
+                "text/html",
+                "<html><head>\
+             <script src=\"/js/late.js\" defer></script>\
+             <script src=\"js/lib.js\"></script>\
+             <script>order.push('inline:' + answer);\
+             document.addEventListener('DOMContentLoaded', () => order.push('loaded'));</script>\
+             <script src=\"/js/async.js\" async></script>\
+             <script type=\"module\">order.push('module');</script>\
+             <script type=\"application/json\">{\"not\": \"script\"}</script>\
+             <script src=\"/js/missing.js\"></script>\
+             <script type=\"text/javascript\">order.push('after-missing');</script>\
+             </head><body></body></html>",
+            ),
+            (
+                "/js/lib.js",
+                "text/javascript",
+                "var answer = 42; var order = ['lib'];",
+            ),
+            ("/js/late.js", "text/javascript", "order.push('defer');"),
+            ("/js/async.js", "text/javascript", "order.push('async');"),
+        ],
+    );
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+
+    engine.load_url(&format!("{host}/")).await.unwrap();
+    // An async script runs whenever it has loaded, but before `load`.
+    let order = engine
+        .execute_javascript("order.filter((entry) => entry !== 'async').join()")
+        .await
+        .unwrap();
+    assert_eq!(order, "lib,inline:42,after-missing,defer,loaded");
+    let ran_async = engine
+        .execute_javascript("order.includes('async')")
+        .await
+        .unwrap();
+    assert_eq!(ran_async, true);
+
+    // The script that failed to load is reported; the page loaded anyway.
+    let errors = engine.get_recent_events(None, Some(EventKindMask::NETWORK_ERROR));
+    let errors = serde_json::to_value(&errors).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1);
+    assert_eq!(errors[0]["event"]["url"], format!("{host}/js/missing.js"));
+    assert_eq!(errors[0]["event"]["error"], "HTTP 404");
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_disabled_css_feature_is_dropped_on_other_origins() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::event_log::{EventKindMask, LoggedEvent};
+    use vulkan_browser_engine::core::features::{Feature, FeatureOverrides};
+    use vulkan_browser_engine::core::network::mock::MockTransport;
+    use vulkan_browser_engine::{BrowserEngine, BrowserEvent};
+
+    static PAGE: &[(&str, &str, &str)] = &[(
+        "/",
+        "text/html",
+        "<style>p { content-visibility: hidden; color: green }</style><p>Gated</p>",
+    )];
+    let (allowed, other) = ("http://allowed.test", "http://other.test");
+    let mock = Arc::new(MockTransport::new());
+    serve_site(&mock, allowed, PAGE);
+    serve_site(&mock, other, PAGE);
+    let engine = support::mock_engine(
+        BrowserConfig {
+            feature_overrides: FeatureOverrides::from([(Feature::ContentVisibility, false)]),
+            ..Default::default()
+        },
+        &mock,
+    )
+    .await;
+    engine.set_origin_features(
+        &url::Url::parse(allowed).unwrap(),
+        FeatureOverrides::from([(Feature::ContentVisibility, true)]),
+    );
+
+    engine.load_url(&format!("{allowed}/")).await.unwrap();
+    let styles = engine.dump_computed_styles("p").await.unwrap();
+    assert_eq!(styles[0]["styles"]["content-visibility"], "hidden");
+    let used = |engine: &BrowserEngine| -> Vec<(String, String)> {
+        engine
+            .get_recent_events(None, Some(EventKindMask::DISABLED_FEATURE_USED))
+            .into_iter()
+            .filter_map(|e| match e.event {
+                LoggedEvent::Browser {
+                    event: BrowserEvent::DisabledFeatureUsed { feature, usage, .. },
+                } => Some((feature, usage)),
+                _ => None,
+            })
+            .collect()
+    };
+    assert!(used(&engine).is_empty());
+
+    // Elsewhere the declaration is dropped whole; the rest of the rule holds.
+    engine.load_url(&format!("{other}/")).await.unwrap();
+    let styles = engine.dump_computed_styles("p").await.unwrap();
+    assert_ne!(styles[0]["styles"]["content-visibility"], "hidden");
+    assert_eq!(styles[0]["styles"]["color"], "#008000");
+    assert_eq!(
+        used(&engine),
+        [(
+            "content-visibility".to_string(),
+            "content-visibility".to_string()
+        )]
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_origin_feature_overrides_apply_from_the_next_navigation() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::features::{Feature, FeatureOverrides};
+    use vulkan_browser_engine::core::network::mock::MockTransport;
+
+    let site = "http://features.test";
+    let mock = Arc::new(MockTransport::new());
+    serve_site(&mock, site, &[("/", "text/html", "<p>Features</p>")]);
+    let page = format!("{site}/");
+    let engine = support::mock_engine(BrowserConfig::default(), &mock).await;
+    let wasm = || engine.execute_javascript("[typeof WebAssembly, 'WebAssembly' in globalThis]");
+
+    engine.load_url(&page).await.unwrap();
+    assert_eq!(wasm().await.unwrap(), serde_json::json!(["object", true]));
+
+    engine.set_origin_features(
+        &url::Url::parse(site).unwrap(),
+        FeatureOverrides::from([(Feature::WebAssembly, false)]),
+    );
+    assert_eq!(wasm().await.unwrap(), serde_json::json!(["object", true]));
+
+    engine.load_url(&page).await.unwrap();
+    assert_eq!(
+        wasm().await.unwrap(),
+        serde_json::json!(["undefined", false])
+    );
+    let timing = engine.get_navigation_timings().pop().unwrap();
+    assert_eq!(
+        timing.document_features,
+        ["content-visibility", "speech-synthesis"]
+    );
+
+    // Forgetting the override brings the global back to the origin.
+    engine.set_origin_features(&url::Url::parse(site).unwrap(), FeatureOverrides::new());
+    engine.load_url(&page).await.unwrap();
+    assert_eq!(wasm().await.unwrap(), serde_json::json!(["object", true]));
+    let timing = engine.get_navigation_timings().pop().unwrap();
+    assert_eq!(
+        timing.document_features,
+        ["content-visibility", "speech-synthesis", "webassembly"]
+    );
+}
+
+const CANTARELL_VF: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/Cantarell-VF.otf"
+));
+const TUFFY_TTF: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/tuffy-subset.ttf"
+));
+/// `TUFFY_TTF` as WOFF2, with its glyf, loca and hmtx tables transformed.
+const TUFFY_WOFF2: &[u8] = include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/tuffy-subset.woff2"
+));
+
+#[test]
+fn test_variable_font_weights_shape_and_rasterize_as_distinct_instances() {
+    use vulkan_browser_engine::core::fonts::{FontFaceDescriptor, FontFaceSet, FontRequest};
+    use vulkan_browser_engine::renderer::text::atlas::{FontAtlas, GlyphKey};
+
+    let fonts = FontFaceSet::new();
+    let id = fonts.add_data(FontFaceDescriptor::new("Cantarell"), CANTARELL_VF);
+    fonts.set_membership(id, true);
+    let light = FontRequest {
+        weight: 300.0,
+        ..Default::default()
+    };
+    let bold = FontRequest {
+        weight: 700.0,
+        ..Default::default()
+    };
+
+    let width = |request: &FontRequest| {
+        fonts
+            .shape("Cantarell", request, 16.0, "Hamburgefonstiv", None)
+            .unwrap()
+            .width()
+    };
+    assert!(width(&light) < width(&bold));
+
+    let light = fonts.instance("Cantarell", &light).unwrap();
+    let bold = fonts.instance("Cantarell", &bold).unwrap();
+    assert_eq!(light.coords.get(*b"wght"), Some(300.0));
+    assert_eq!(bold.coords.get(*b"wght"), Some(700.0));
+
+    let glyph = light.ttf_face().unwrap().glyph_index('H').unwrap().0;
+    let mut atlas = FontAtlas::new(512, 512).unwrap();
+    let light_glyph = atlas
+        .get_or_cache_instance_glyph(&light, glyph, 48.0)
+        .unwrap();
+    let bold_glyph = atlas
+        .get_or_cache_instance_glyph(&bold, glyph, 48.0)
+        .unwrap();
+    assert_ne!(
+        GlyphKey::for_instance(&light, glyph, 48.0),
+        GlyphKey::for_instance(&bold, glyph, 48.0)
+    );
+    assert!(light_glyph.width < bold_glyph.width);
+    assert_eq!(atlas.get_usage_stats().cached_glyphs, 2);
+
+    atlas
+        .get_or_cache_instance_glyph(&bold, glyph, 48.0)
+        .unwrap();
+    assert_eq!(atlas.get_usage_stats().cached_glyphs, 2);
+}
+
+#[test]
+fn test_woff2_with_transformed_tables_decodes_to_the_same_glyphs() {
+    use vulkan_browser_engine::core::fonts::decode_font;
+
+    let decoded = decode_font(TUFFY_WOFF2).unwrap();
+    let original = ttf_parser::Face::parse(TUFFY_TTF, 0).unwrap();
+    let face = ttf_parser::Face::parse(&decoded, 0).unwrap();
+    assert_eq!(face.number_of_glyphs(), original.number_of_glyphs());
+
+    // "é" and "Å" are composite glyphs, whose side bearings the hmtx
+    // transform leaves to be taken from their reconstructed bounding boxes.
+    for c in "AHOWagox&eéÅ".chars() {
+        let glyph = face.glyph_index(c).unwrap();
+        assert_eq!(Some(glyph), original.glyph_index(c));
+        assert_eq!(
+            face.glyph_bounding_box(glyph),
+            original.glyph_bounding_box(glyph),
+            "{c}"
+        );
+        assert_eq!(
+            face.glyph_hor_advance(glyph),
+            original.glyph_hor_advance(glyph),
+            "{c}"
+        );
+        assert_eq!(
+            face.glyph_hor_side_bearing(glyph),
+            original.glyph_hor_side_bearing(glyph),
+            "{c}"
+        );
+    }
+}
+
+#[test]
+fn test_ping_from_is_left_out_of_https_pages_pings_elsewhere() {
+    use vulkan_browser_engine::core::network::RequestInitiator;
+
+    let target = url::Url::parse("https://shop.test/checkout").unwrap();
+    let own = url::Url::parse("https://shop.test/audit").unwrap();
+    let tracker = url::Url::parse("https://tracker.test/hit").unwrap();
+
+    let secure = RequestInitiator::new(url::Url::parse("https://shop.test/cart").unwrap());
+    let headers = secure.ping_headers(&own, &target);
+    assert_eq!(headers["Ping-From"], "https://shop.test/cart");
+    assert_eq!(headers["Ping-To"], "https://shop.test/checkout");
+    assert_eq!(headers["Content-Type"], "text/ping");
+    let headers = secure.ping_headers(&tracker, &target);
+    assert!(!headers.contains_key("Ping-From"));
+    assert_eq!(headers["Ping-To"], "https://shop.test/checkout");
+
+    let plain = RequestInitiator::new(url::Url::parse("http://shop.test/cart").unwrap());
+    assert_eq!(
+        plain.ping_headers(&tracker, &target)["Ping-From"],
+        "http://shop.test/cart"
+    );
+}
+
+/// Tick `engine` until font `family` has loaded, and once more to lay out
+/// with it.
+#[cfg(feature = "test-util")]
+async fn wait_for_web_font(engine: &vulkan_browser_engine::BrowserEngine, family: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let check = format!("document.fonts.check('20px {}')", family);
+    loop {
+        engine.tick().await.unwrap();
+        if engine.execute_javascript(&check).await.unwrap() == serde_json::Value::Bool(true) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "{} never loaded", family);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    engine.tick().await.unwrap();
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_page_archive_renders_the_same_with_the_network_gone() {
+    use base64::Engine;
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::archive::{
+        ArchiveFormat, ArchiveOptions, OmissionReason, OmittedResource,
+    };
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::BrowserEngine;
+
+    let png = |rgb: [u8; 3]| {
+        let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([rgb[0], rgb[1], rgb[2], 255]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    };
+    let config = || BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let site = Arc::new(MockTransport::new());
+    site.route(
+        "GET",
+        "http://archive.test/",
+        MockResponse::ok(
+            "text/html",
+            "<html><head><title>Saved</title>\
+             <link rel=stylesheet href=css/site.css></head>\
+             <body><div class=card><span class=wide onclick=\"alert(1)\">abc</span></div>\
+             <img src=red.png><img srcset=\"blue.png 2x\"><img src=/gone.png>\
+             <script>document.querySelector('.card').id = 'touched';\
+             document.body.insertAdjacentHTML('beforeend', '<p class=added>hi</p>');</script>\
+             </body></html>",
+        ),
+    );
+    site.route(
+        "GET",
+        "http://archive.test/css/site.css",
+        MockResponse::ok(
+            "text/css",
+            "@import \"theme.css\" print;\
+             @font-face { font-family: Tiny; src: url('../fonts/tiny.ttf') }\
+             body { margin: 0 }\
+             .card { background: rgb(0, 128, 0); padding: 8px }\
+             .wide { display: inline-block; font: 20px Tiny, sans-serif; background: rgb(0, 0, 255) }\
+             .added { width: 30px; height: 10px; background: rgb(255, 0, 0) }",
+        ),
+    );
+    site.route(
+        "GET",
+        "http://archive.test/fonts/tiny.ttf",
+        MockResponse::ok("font/ttf", tiny_font(1000)),
+    );
+    site.route(
+        "GET",
+        "http://archive.test/red.png",
+        MockResponse::ok("image/png", png([255, 0, 0])),
+    );
+    site.route(
+        "GET",
+        "http://archive.test/blue.png",
+        MockResponse::ok("image/png", png([0, 0, 255])),
+    );
+    site.route(
+        "GET",
+        "http://archive.test/gone.png",
+        MockResponse::new(404),
+    );
+    let network = NetworkManager::with_transport(&config(), site)
+        .await
+        .unwrap();
+    let engine = BrowserEngine::with_network(config(), network)
+        .await
+        .unwrap();
+    engine.load_url("http://archive.test/").await.unwrap();
+    wait_for_web_font(&engine, "Tiny").await;
+    let original = engine.snapshot().await;
+
+    let saved = engine
+        .save_page_archive_with(ArchiveOptions {
+            format: ArchiveFormat::SingleFileHtml,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let mut included: Vec<_> = saved
+        .manifest
+        .included
+        .iter()
+        .map(|resource| resource.url.trim_start_matches("http://archive.test/"))
+        .collect();
+    included.sort();
+    assert_eq!(
+        included,
+        ["blue.png", "css/site.css", "fonts/tiny.ttf", "red.png"]
+    );
+    let omitted = |url: &str, reason| OmittedResource {
+        url: url.to_string(),
+        reason,
+    };
+    assert!(saved.manifest.omitted.contains(&omitted(
+        "http://archive.test/gone.png",
+        OmissionReason::HttpStatus(404)
+    )));
+    // Imported sheets are never fetched.
+    assert!(saved.manifest.omitted.contains(&omitted(
+        "http://archive.test/css/theme.css",
+        OmissionReason::NotLoaded
+    )));
+    let html = String::from_utf8(saved.data).unwrap();
+    assert!(html.contains("id=\"touched\""), "{}", html);
+    assert!(html.contains("class=\"added\""), "{}", html);
+    assert!(html.contains("type=\"text/plain\""), "{}", html);
+    assert!(!html.contains("onclick"), "{}", html);
+    assert!(html.contains("src=\"data:image/png;base64,"), "{}", html);
+    assert!(html.contains("http://archive.test/gone.png"), "{}", html);
+
+    // Nothing the archive needs may come from the network.
+    let offline = Arc::new(MockTransport::new());
+    offline.route("*", "*", MockResponse::connection_error("offline"));
+    let network = NetworkManager::with_transport(&config(), offline.clone())
+        .await
+        .unwrap();
+    let reopened = BrowserEngine::with_network(config(), network)
+        .await
+        .unwrap();
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&html);
+    reopened
+        .load_url(&format!("data:text/html;base64,{}", encoded))
+        .await
+        .unwrap();
+    wait_for_web_font(&reopened, "Tiny").await;
+    let restored = reopened.snapshot().await;
+    assert!(offline.requests().is_empty(), "{:?}", offline.requests());
+    assert_eq!(
+        (restored.width, restored.height),
+        (original.width, original.height)
+    );
+    let differing = original
+        .data
+        .chunks(4)
+        .zip(restored.data.chunks(4))
+        .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 2))
+        .count();
+    assert!(
+        differing * 1000 <= original.data.len() / 4,
+        "{} pixels differ",
+        differing
+    );
+    // The card and the web-font-wide span are painted in both.
+    assert_eq!(restored.pixel(2, 2), original.pixel(2, 2));
+    assert_eq!(restored.pixel(20, 15), [0, 0, 255, 255]);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_mhtml_archive_has_a_part_per_resource() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::archive::ArchiveFormat;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+    use vulkan_browser_engine::BrowserEngine;
+
+    let site = Arc::new(MockTransport::new());
+    site.route(
+        "GET",
+        "http://mhtml.test/page",
+        MockResponse::ok(
+            "text/html",
+            "<title>Caf\u{e9}</title><link rel=stylesheet href=a.css><link rel=icon href=gone.ico>\
+             <p style=\"background: url(dot.bin)\">x = y</p>",
+        ),
+    );
+    site.route(
+        "GET",
+        "http://mhtml.test/a.css",
+        MockResponse::ok("text/css", "@import 'b.css'; p { color: red }"),
+    );
+    site.route(
+        "GET",
+        "http://mhtml.test/dot.bin",
+        MockResponse::ok("application/octet-stream", vec![0u8, 255, 1, 2]),
+    );
+    let config = BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    };
+    let network = NetworkManager::with_transport(&config, site).await.unwrap();
+    let engine = BrowserEngine::with_network(config, network).await.unwrap();
+    engine.load_url("http://mhtml.test/page").await.unwrap();
+    engine.tick().await.unwrap();
+
+    let mhtml = engine
+        .save_page_archive(ArchiveFormat::Mhtml)
+        .await
+        .unwrap();
+    let mhtml = String::from_utf8(mhtml).unwrap();
+    assert!(mhtml.contains("Snapshot-Content-Location: http://mhtml.test/page\r\n"));
+    assert!(mhtml.contains("Subject: =?utf-8?B?Q2Fmw6k=?=\r\n"));
+    assert!(mhtml.contains("Content-Type: multipart/related;"));
+    let boundary = "------=_NextPart_000_Archive";
+    assert!(mhtml.trim_end().ends_with(&format!("{}--", boundary)));
+    // The document, the stylesheet and the image. The import, never
+    // fetched, is dropped; the icon, never fetched, stays a link.
+    assert_eq!(mhtml.matches(&format!("{}\r\n", boundary)).count(), 3);
+    assert!(mhtml.contains(
+        "Content-Transfer-Encoding: quoted-printable\r\nContent-Location: http://mhtml.test/page\r\n"
+    ));
+    // Quoted-printable with its soft line breaks taken out.
+    let unfolded = mhtml.replace("=\r\n", "");
+    assert!(unfolded.contains("x =3D y"));
+    assert!(mhtml.contains("Content-Location: http://mhtml.test/a.css\r\n\r\n p { color: red }"));
+    assert!(unfolded.contains("href=3D\"http://mhtml.test/gone.ico\""));
+    assert!(mhtml.contains(
+        "Content-Transfer-Encoding: base64\r\nContent-Location: http://mhtml.test/dot.bin\r\n\r\nAP8BAg==\r\n"
+    ));
+    assert!(unfolded.contains("url(&quot;http://mhtml.test/dot.bin&quot;)"));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_stale_cache_entries_are_revalidated_with_their_etag() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{
+        MockResponse, MockTransport, RecordedRequest,
+    };
+
+    // Sends the body unless the request names its current ETag.
+    let mock = Arc::new(MockTransport::new());
+    mock.route_fn("GET", "http://etag.test/*", |request| {
+        let response = match request.header("If-None-Match") {
+            Some("\"v1\"") => MockResponse::new(304),
+            _ => MockResponse::new(200).body("<html>versioned</html>"),
+        };
+        response
+            .header("ETag", "\"v1\"")
+            .header("Cache-Control", "no-cache")
+    });
+
+    let host = "http://etag.test";
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+    let url = format!("{host}/page");
+    assert_eq!(network.fetch(&url).await.unwrap(), "<html>versioned</html>");
+    // `no-cache`: the entry is stale at once, so the second fetch asks.
+    assert_eq!(network.fetch(&url).await.unwrap(), "<html>versioned</html>");
+    let conditional = |request: &&RecordedRequest| request.header("If-None-Match").is_some();
+    let requests = mock.requests();
+    assert_eq!(requests.iter().filter(|r| !conditional(r)).count(), 1);
+    assert_eq!(requests.iter().filter(conditional).count(), 1);
+    assert_eq!(
+        network.get_metrics().total_bytes_downloaded,
+        "<html>versioned</html>".len() as u64
+    );
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_revalidated_redirects_stay_fresh_under_the_url_requested() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    // Stale at once, until a 304 gives it ten minutes.
+    let mock = Arc::new(MockTransport::new());
+    mock.route_fn("GET", "http://etag.test/moved", |request| {
+        match request.header("If-None-Match") {
+            Some("\"v1\"") => MockResponse::new(304).header("Cache-Control", "max-age=600"),
+            _ => MockResponse::new(200)
+                .body("<html>moved</html>")
+                .header("Cache-Control", "no-cache"),
+        }
+        .header("ETag", "\"v1\"")
+    });
+    mock.route(
+        "GET",
+        "http://etag.test/old",
+        MockResponse::new(301).header("Location", "/moved"),
+    );
+
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+    for _ in 0..3 {
+        assert_eq!(
+            network.fetch("http://etag.test/old").await.unwrap(),
+            "<html>moved</html>"
+        );
+    }
+    // Fetched, then revalidated once; the third is served from the cache.
+    let moved = mock.requests_to("http://etag.test/moved");
+    assert_eq!(moved.len(), 2);
+    assert!(moved[1].header("If-None-Match").is_some());
+}
+
+#[test]
+fn test_cache_control_directives_fill_the_cache_policy() {
+    use vulkan_browser_engine::core::network::CachePolicy;
+
+    let mut policy = CachePolicy::default();
+    policy.merge_cache_control("max-age=600, must-revalidate, immutable");
+    assert_eq!(policy.max_age, Some(600));
+    assert!(policy.must_revalidate && policy.immutable);
+    assert!(!policy.no_store && !policy.no_cache);
+
+    policy.merge_cache_control("No-Store, no-cache");
+    assert!(policy.no_store && policy.no_cache);
+    // The server's `max-age` wins; restrictive flags stay on.
+    policy.merge_cache_control("max-age=\"30\"");
+    assert_eq!(policy.max_age, Some(30));
+    assert!(policy.must_revalidate);
+}
+
+/// Serves 10 MB in 64 KiB chunks at `http://large.test`, under `/declared`
+/// with its `Content-Length` and under `/chunked` without one.
+#[cfg(feature = "test-util")]
+fn large_body_host() -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let body = vec![b'x'; 160 * 64 * 1024];
+    let response = MockResponse::new(200)
+        .body(body.clone())
+        .chunked(64 * 1024, Duration::ZERO);
+    let mock = MockTransport::new();
+    mock.route("GET", "http://large.test/chunked", response.clone());
+    mock.route(
+        "GET",
+        "http://large.test/declared",
+        response.header("Content-Length", &body.len().to_string()),
+    );
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_streamed_bodies_past_the_size_limit_abort_early() {
+    use std::collections::HashMap;
+    use vulkan_browser_engine::core::network::{FetchRequest, Priority};
+
+    let mock = large_body_host();
+    let host = "http://large.test";
+    let network = support::mock_network(
+        &BrowserConfig {
+            max_response_size_mb: 1,
+            ..Default::default()
+        },
+        &mock,
+    )
+    .await;
+    let get = |path: &str| FetchRequest {
+        url: format!("{host}{path}"),
+        method: "GET".to_string(),
+        headers: HashMap::new(),
+        body: None,
+        timeout_ms: None,
+        follow_redirects: true,
+        cache_policy: None,
+        priority: Priority::High,
+        idempotent: false,
+    };
+
+    let mut streamed = 0;
+    let result = network
+        .fetch_streaming(get("/chunked"), |chunk| streamed += chunk.len())
+        .await;
+    assert!(
+        matches!(&result, Err(NetworkError::RequestFailed(message)) if message == "Response too large"),
+        "{:?}",
+        result.map(|response| response.status)
+    );
+    assert!(streamed <= 1024 * 1024);
+
+    // The declared length alone is enough to refuse it.
+    let mut streamed = 0;
+    let result = network
+        .fetch_streaming(get("/declared"), |chunk| streamed += chunk.len())
+        .await;
+    assert!(matches!(
+        &result,
+        Err(NetworkError::RequestFailed(message)) if message == "Response too large"
+    ));
+    assert_eq!(streamed, 0);
+}
+
+/// Answers each `(url, status, location)` of `redirects` with that status
+/// and `Location`, and anything else at `http://redirect.test` or
+/// `http://other.test` with 200 and the request's method and body.
+#[cfg(feature = "test-util")]
+fn redirect_host(
+    redirects: &[(&str, u16, &str)],
+) -> std::sync::Arc<vulkan_browser_engine::core::network::mock::MockTransport> {
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = MockTransport::new();
+    for host in ["http://redirect.test/*", "http://other.test/*"] {
+        mock.route_fn("*", host, |request| {
+            let body = String::from_utf8_lossy(request.body.as_deref().unwrap_or_default());
+            MockResponse::new(200).body(format!("{} {}", request.method, body))
+        });
+    }
+    for (url, status, location) in redirects {
+        mock.route(
+            "*",
+            url,
+            MockResponse::new(*status).header("Location", location),
+        );
+    }
+    std::sync::Arc::new(mock)
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_redirects_are_followed_hop_by_hop() {
+    use std::collections::HashMap;
+    use vulkan_browser_engine::core::network::{FetchRequest, Priority};
+
+    let host = "http://redirect.test";
+    let mock = redirect_host(&[
+        ("http://redirect.test/start", 302, "/see-other"),
+        (
+            "http://redirect.test/see-other",
+            307,
+            "http://other.test/final",
+        ),
+        ("http://redirect.test/keep", 307, "/echo"),
+    ]);
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+    let post = |path: &str| FetchRequest {
+        url: format!("{host}{path}"),
+        method: "POST".to_string(),
+        headers: HashMap::from([("Authorization".to_string(), "Bearer secret".to_string())]),
+        body: Some(b"payload".to_vec()),
+        timeout_ms: None,
+        follow_redirects: true,
+        cache_policy: None,
+        priority: Priority::High,
+        idempotent: false,
+    };
+
+    // 302 turns the POST into a GET; the 307 after it keeps the GET, and
+    // the Authorization stays behind on the first origin.
+    let response = network.fetch_with_request(post("/start")).await.unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"GET ");
+    assert!(response.redirected);
+    assert_eq!(response.url, "http://other.test/final");
+    let hops: Vec<_> = mock
+        .requests()
+        .into_iter()
+        .map(|request| {
+            let authorization = request.header("Authorization").is_some();
+            (request.method, request.url.to_string(), authorization)
+        })
+        .collect();
+    assert_eq!(
+        hops,
+        vec![
+            ("POST".to_string(), format!("{host}/start"), true),
+            ("GET".to_string(), format!("{host}/see-other"), true),
+            (
+                "GET".to_string(),
+                "http://other.test/final".to_string(),
+                false
+            ),
+        ]
+    );
+
+    // 307 repeats the POST with its body.
+    let response = network.fetch_with_request(post("/keep")).await.unwrap();
+    assert_eq!(response.body, b"POST payload");
+    let last = mock.requests().pop().unwrap();
+    assert_eq!(
+        (last.url.as_str(), last.body.as_deref()),
+        ("http://redirect.test/echo", Some(&b"payload"[..]))
+    );
+
+    // Without following, the redirect itself comes back.
+    let response = network
+        .fetch_with_request(FetchRequest {
+            follow_redirects: false,
+            ..post("/keep")
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.status, 307);
+    assert!(!response.redirected);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_a_redirect_to_a_blocked_host_fails_before_it_is_requested() {
+    use vulkan_browser_engine::core::network::SecurityPolicy;
+
+    let host = "http://redirect.test";
+    let mock = redirect_host(&[
+        ("http://redirect.test/start", 301, "/next"),
+        (
+            "http://redirect.test/next",
+            302,
+            "http://blocked.test/final",
+        ),
+        ("http://redirect.test/loop", 302, "/loop"),
+    ]);
+    let network = support::mock_network(&BrowserConfig::default(), &mock).await;
+    network.update_security_policy(SecurityPolicy {
+        blocked_hosts: vec!["blocked.test".to_string()],
+        ..Default::default()
+    });
+
+    let result = network.fetch(&format!("{}/start", host)).await;
+    assert!(
+        matches!(result, Err(NetworkError::SecurityPolicy(ref message)) if message.contains("blocked.test")),
+        "{:?}",
+        result
+    );
+    assert_eq!(mock.requests().len(), 2);
+
+    let result = network.fetch(&format!("{}/loop", host)).await;
+    assert!(matches!(result, Err(NetworkError::Redirect(_))));
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_beacon_follows_a_redirect() {
+    let mock = redirect_host(&[(
+        "http://redirect.test/collect",
+        302,
+        "http://other.test/landed",
+    )]);
+    let host = "http://redirect.test";
+    let engine = support::mock_engine(beacon_engine_config(), &mock).await;
+    engine.load_url("data:text/html,<p>page</p>").await.unwrap();
+
+    let queued = engine
+        .execute_javascript(&format!("navigator.sendBeacon('{host}/collect', 'bye')"))
+        .await
+        .unwrap();
+    assert_eq!(queued, serde_json::Value::Bool(true));
+
+    // The 302 turns the POST into a GET at the new location.
+    let landed = wait_for_request(&mock, "http://other.test/landed", Duration::from_secs(5))
+        .await
+        .expect("the beacon's redirect was not followed");
+    assert_eq!(landed.method, "GET");
+    assert_eq!(landed.body, None);
+    let first = mock.requests_to("http://redirect.test/collect");
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].method, "POST");
+}
@@ -1,208 +1,204 @@
-use vulkan_renderer::pwa::*;
-use tokio_test;
+use std::collections::HashMap;
+use vulkan_browser_engine::pwa::cache::CacheManager;
+use vulkan_browser_engine::pwa::manifest::{DisplayMode, Manifest};
+use vulkan_browser_engine::pwa::{FetchRequest, FetchResponse, PwaError, PwaRuntime};
+
+#[cfg(feature = "test-util")]
+mod support;
+
+const MANIFEST: &str = r##"{
+    "name": "Integration Test PWA",
+    "short_name": "TestPWA",
+    "description": "A PWA for integration testing",
+    "start_url": "/",
+    "scope": "/",
+    "display": "standalone",
+    "theme_color": "#2196F3",
+    "background_color": "#FFFFFF",
+    "icons": [
+        { "src": "/icon-192.png", "sizes": "192x192", "type": "image/png" },
+        { "src": "/icon-512.png", "sizes": "512x512", "type": "image/png", "purpose": "maskable" }
+    ],
+    "categories": ["productivity", "utilities"]
+}"##;
+
+fn create_test_manifest(runtime: &PwaRuntime) -> Manifest {
+    runtime
+        .parse_manifest(MANIFEST, "https://example.com/manifest.json")
+        .unwrap()
+}
 
 #[tokio::test]
 async fn test_full_pwa_lifecycle() {
-    let mut runtime = PwaRuntime::new().await.unwrap();
-    
-    let manifest = create_test_manifest();
+    let runtime = PwaRuntime::new().await.unwrap();
+    let manifest = create_test_manifest(&runtime);
+
     let app_id = runtime.install_app(&manifest).await.unwrap();
-    
     assert!(!app_id.is_empty());
-    
-    let sw_script = create_test_service_worker();
-    let worker_id = runtime.register_service_worker(&sw_script).await.unwrap();
-    
-    assert!(!worker_id.is_empty());
-    
-    test_caching_strategies(&mut runtime, &app_id).await;
-    test_offline_capability(&mut runtime, &app_id).await;
-    test_background_sync(&mut runtime, &app_id).await;
-    
+    assert!(runtime.is_installed(&manifest).await);
+    assert_eq!(
+        runtime.get_app_manifest(&app_id).await.unwrap().name,
+        "Integration Test PWA"
+    );
+    assert!(runtime.get_app_storage_usage(&app_id).await.is_ok());
+
     runtime.uninstall_app(&app_id).await.unwrap();
-    
+
     let installed_apps = runtime.get_installed_apps().await;
-    assert!(installed_apps.iter().find(|app| app.id == app_id).is_none());
+    assert!(!installed_apps.iter().any(|app| app.id == app_id));
+    assert!(matches!(
+        runtime.uninstall_app(&app_id).await,
+        Err(PwaError::AppNotFound(_))
+    ));
 }
 
-async fn test_caching_strategies(runtime: &mut PwaRuntime, app_id: &str) {
-    let cache_strategies = vec![
-        ("cache-first", CacheStrategy::CacheFirst),
-        ("network-first", CacheStrategy::NetworkFirst),
-        ("stale-while-revalidate", CacheStrategy::StaleWhileRevalidate),
-    ];
-    
-    for (strategy_name, strategy) in cache_strategies {
-        let cache_name = format!("{}-{}", app_id, strategy_name);
-        
-        let request = FetchRequest {
-            url: format!("https://example.com/{}", strategy_name),
-            method: "GET".to_string(),
-            headers: std::collections::HashMap::new(),
-            body: None,
-        };
-        
+#[tokio::test]
+async fn test_manifest_resolves_against_its_url() {
+    let runtime = PwaRuntime::new().await.unwrap();
+    let manifest = create_test_manifest(&runtime);
+
+    assert_eq!(manifest.short_name.as_deref(), Some("TestPWA"));
+    assert_eq!(manifest.start_url, "https://example.com/");
+    assert!(matches!(manifest.display, DisplayMode::Standalone));
+    assert_eq!(manifest.theme_color.as_deref(), Some("#2196F3"));
+    assert_eq!(manifest.icons.len(), 2);
+    assert_eq!(manifest.categories, ["productivity", "utilities"]);
+}
+
+#[tokio::test]
+async fn test_shut_down_runtime_refuses_installs() {
+    let runtime = PwaRuntime::new().await.unwrap();
+    let manifest = create_test_manifest(&runtime);
+
+    runtime.shutdown().await.unwrap();
+
+    assert!(runtime.is_shutdown().await);
+    assert!(matches!(
+        runtime.install_app(&manifest).await,
+        Err(PwaError::RuntimeShutdown)
+    ));
+    assert!(runtime.get_installed_apps().await.is_empty());
+}
+
+fn get(url: &str) -> FetchRequest {
+    FetchRequest {
+        url: url.to_string(),
+        method: "GET".to_string(),
+        headers: HashMap::new(),
+        body: None,
+    }
+}
+
+#[tokio::test]
+async fn test_caching_strategies() {
+    let mut cache_manager = CacheManager::ephemeral();
+
+    for strategy_name in ["cache-first", "network-first", "stale-while-revalidate"] {
+        let cache_name = format!("test-pwa-{}", strategy_name);
+        let request = get(&format!("https://example.com/{}", strategy_name));
         let response = FetchResponse {
             status: 200,
-            headers: std::collections::HashMap::new(),
+            headers: HashMap::new(),
             body: format!("Response for {}", strategy_name).into_bytes(),
         };
-        
-        runtime.cache_manager.write().await.add_to_cache(&cache_name, &request.url, &response).await.unwrap();
-        
-        let cached_response = runtime.cache_manager.read().await.match_request(&request).await.unwrap();
-        assert!(cached_response.is_some());
-        assert_eq!(cached_response.unwrap().status, 200);
+
+        cache_manager
+            .add_to_cache(&cache_name, &request.url, &response)
+            .await
+            .unwrap();
+
+        let cached_response = cache_manager
+            .match_request(&request)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached_response.status, 200);
+        assert_eq!(cached_response.body, response.body);
     }
+
+    assert!(cache_manager
+        .match_request(&get("https://example.com/uncached"))
+        .await
+        .unwrap()
+        .is_none());
 }
 
-async fn test_offline_capability(runtime: &mut PwaRuntime, app_id: &str) {
-    let offline_page_request = FetchRequest {
-        url: "https://example.com/offline.html".to_string(),
-        method: "GET".to_string(),
-        headers: std::collections::HashMap::new(),
-        body: None,
-    };
-    
-    let offline_page_response = FetchResponse {
-        status: 200,
-        headers: [("content-type".to_string(), "text/html".to_string())].iter().cloned().collect(),
-        body: b"<html><body><h1>You're offline</h1></body></html>".to_vec(),
-    };
-    
-    runtime.cache_manager.write().await.add_to_cache(
-        &format!("{}-offline", app_id), 
-        &offline_page_request.url, 
-        &offline_page_response
-    ).await.unwrap();
-    
-    let response = runtime.handle_fetch_request(&offline_page_request).await.unwrap();
+/// Serves `script` as `/sw.js`. Returns the origin.
+#[cfg(feature = "test-util")]
+async fn spawn_service_worker_host(script: &str) -> String {
+    use std::sync::Arc;
+    use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+
+    let mock = Arc::new(MockTransport::new());
+    let host = support::serve(mock.clone()).await;
+    mock.route(
+        "*",
+        &format!("{host}/sw.js"),
+        MockResponse::ok("text/javascript", script).header("Cache-Control", "no-store"),
+    );
+    host
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_offline_capability() {
+    let host = spawn_service_worker_host(
+        "self.addEventListener('fetch', (event) => {\
+           event.respondWith(new Response(\"<html><body><h1>You're offline</h1></body></html>\",\
+             { headers: { 'Content-Type': 'text/html' } }));\
+         });",
+    )
+    .await;
+    let runtime = PwaRuntime::new().await.unwrap();
+    let page = get(&format!("{}/index.html", host));
+
+    // The runtime has no network of its own, so without a worker the
+    // request goes unanswered.
+    assert!(matches!(
+        runtime.handle_fetch_request(&page).await,
+        Err(PwaError::ResourceNotFound(_))
+    ));
+
+    runtime
+        .register_service_worker(&format!("{}/sw.js", host), None)
+        .await
+        .unwrap();
+
+    let response = runtime.handle_fetch_request(&page).await.unwrap();
     assert_eq!(response.status, 200);
     assert!(String::from_utf8_lossy(&response.body).contains("You're offline"));
 }
 
-async fn test_background_sync(runtime: &mut PwaRuntime, app_id: &str) {
-    let sync_data = serde_json::json!({
-        "type": "background_sync",
-        "data": {
-            "message": "Test background sync",
-            "timestamp": chrono::Utc::now().timestamp()
-        }
-    });
-    
-    runtime.storage_manager.write().await.set_local_storage(
-        app_id, 
-        "pending_sync", 
-        &sync_data.to_string()
-    ).await.unwrap();
-    
-    let stored_data = runtime.storage_manager.read().await.get_local_storage(app_id, "pending_sync");
-    assert!(stored_data.is_some());
-    
-    let parsed_data: serde_json::Value = serde_json::from_str(&stored_data.unwrap()).unwrap();
-    assert_eq!(parsed_data["type"], "background_sync");
-}
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn test_background_sync() {
+    let host = spawn_service_worker_host(
+        "self.addEventListener('sync', (event) => {\
+           event.waitUntil(self.registration.showNotification('Synced ' + event.tag));\
+         });\
+         self.registration.sync.register('background-sync');",
+    )
+    .await;
+    let runtime = PwaRuntime::new().await.unwrap();
+    runtime
+        .register_service_worker(&format!("{}/sw.js", host), None)
+        .await
+        .unwrap();
+    let scope = format!("{}/", host);
+    assert_eq!(
+        runtime.service_worker_registrations().await[0].sync_tags,
+        vec!["background-sync".to_string()]
+    );
 
-fn create_test_manifest() -> Manifest {
-    Manifest {
-        name: "Integration Test PWA".to_string(),
-        short_name: Some("TestPWA".to_string()),
-        description: Some("A PWA for integration testing".to_string()),
-        start_url: "/".to_string(),
-        scope: Some("/".to_string()),
-        display: DisplayMode::Standalone,
-        orientation: Some(Orientation::Any),
-        theme_color: Some("#2196F3".to_string()),
-        background_color: Some("#FFFFFF".to_string()),
-        icons: vec![
-            Icon {
-                src: "/icon-192.png".to_string(),
-                sizes: Some("192x192".to_string()),
-                icon_type: Some("image/png".to_string()),
-                purpose: Some(IconPurpose::Any),
-            },
-            Icon {
-                src: "/icon-512.png".to_string(),
-                sizes: Some("512x512".to_string()),
-                icon_type: Some("image/png".to_string()),
-                purpose: Some(IconPurpose::Maskable),
-            },
-        ],
-        service_worker: Some("/sw.js".to_string()),
-        categories: vec!["productivity".to_string(), "utilities".to_string()],
-        screenshots: vec![
-            Screenshot {
-                src: "/screenshot1.png".to_string(),
-                sizes: Some("1280x720".to_string()),
-                screenshot_type: Some("image/png".to_string()),
-                form_factor: Some(FormFactor::Wide),
-                label: Some("Main interface".to_string()),
-            }
-        ],
-        ..Default::default()
-    }
+    assert!(runtime
+        .dispatch_sync(&scope, "background-sync", false)
+        .await
+        .unwrap());
+    assert_eq!(
+        runtime.take_notifications().await[0].title,
+        "Synced background-sync"
+    );
+    assert!(runtime.service_worker_registrations().await[0]
+        .sync_tags
+        .is_empty());
 }
-
-fn create_test_service_worker() -> String {
-    r#"
-    const CACHE_NAME = 'test-pwa-v1';
-    const urlsToCache = [
-        '/',
-        '/styles/main.css',
-        '/scripts/main.js',
-        '/offline.html'
-    ];
-
-    self.addEventListener('install', function(event) {
-        event.waitUntil(
-            caches.open(CACHE_NAME)
-                .then(function(cache) {
-                    return cache.addAll(urlsToCache);
-                })
-        );
-    });
-
-    self.addEventListener('fetch', function(event) {
-        event.respondWith(
-            caches.match(event.request)
-                .then(function(response) {
-                    if (response) {
-                        return response;
-                    }
-                    return fetch(event.request);
-                }
-            )
-        );
-    });
-
-    self.addEventListener('activate', function(event) {
-        event.waitUntil(
-            caches.keys().then(function(cacheNames) {
-                return Promise.all(
-                    cacheNames.map(function(cacheName) {
-                        if (cacheName !== CACHE_NAME) {
-                            return caches.delete(cacheName);
-                        }
-                    })
-                );
-            })
-        );
-    });
-
-    self.addEventListener('sync', function(event) {
-        if (event.tag === 'background-sync') {
-            event.waitUntil(doBackgroundSync());
-        }
-    });
-
-    function doBackgroundSync() {
-        return fetch('/api/sync', {
-            method: 'POST',
-            body: JSON.stringify({
-                timestamp: Date.now(),
-                data: 'background sync data'
-            })
-        });
-    }
-    "#.to_string()
-}
\ No newline at end of file
@@ -0,0 +1,149 @@
+#![cfg(all(feature = "replay", feature = "test-util"))]
+
+use std::sync::Arc;
+use std::time::Duration;
+use vulkan_browser_engine::core::event_log::EventKindMask;
+use vulkan_browser_engine::core::network::mock::{MockResponse, MockTransport};
+use vulkan_browser_engine::replay::{
+    Recorder, ReplayBundle, ReplayError, Replayer, BUNDLE_VERSION,
+};
+use vulkan_browser_engine::{BrowserConfig, BrowserEngine, InputEvent};
+
+/// A page whose layout and log depend on `Math.random`, `Date`,
+/// `performance.now()`, timer order, a stylesheet and clicks.
+fn session_transport() -> Arc<MockTransport> {
+    let mock = Arc::new(MockTransport::new());
+    mock.route(
+        "GET",
+        "http://app.test/",
+        MockResponse::ok(
+            "text/html",
+            "<link rel=stylesheet href=/app.css>\
+             <div id=bar style=\"height:20px\"></div>\
+             <script>\
+               window.__log = [];\
+               __log.push('random ' + Math.random(), 'date ' + Date.now(),\
+                          'now ' + performance.now());\
+               document.getElementById('bar').style.width =\
+                 Math.floor(Math.random() * 400) + 'px';\
+               setTimeout(() => __log.push('late ' + performance.now()), 15);\
+               setTimeout(() => __log.push('early ' + Math.random()), 5);\
+               document.addEventListener('click', e => {\
+                 const d = document.createElement('div');\
+                 d.style.height = '10px';\
+                 d.style.width = Math.floor(Math.random() * 300) + 'px';\
+                 document.body.appendChild(d);\
+                 __log.push('click ' + e.clientX + ' ' + new Date().getTime());\
+               });\
+             </script>",
+        ),
+    );
+    mock.route(
+        "GET",
+        "http://app.test/app.css",
+        MockResponse::ok("text/css", "body { margin: 0 } div { background: red }")
+            .latency(Duration::from_millis(5)),
+    );
+    mock
+}
+
+fn config() -> BrowserConfig {
+    BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    }
+}
+
+fn logged_events(engine: &BrowserEngine) -> Vec<serde_json::Value> {
+    let kinds = EventKindMask::NAVIGATION_STARTED
+        | EventKindMask::NAVIGATION_PHASE
+        | EventKindMask::JAVASCRIPT_ERROR;
+    engine
+        .get_recent_events(None, Some(kinds))
+        .into_iter()
+        .map(|entry| serde_json::to_value(&entry.event).unwrap())
+        .collect()
+}
+
+async fn record_session() -> (ReplayBundle, BrowserEngine, Vec<serde_json::Value>) {
+    let click = |x| InputEvent::MouseClick { x, y: 5, button: 0 };
+    let recorder = Recorder::start(config(), Some(session_transport()))
+        .await
+        .unwrap();
+    let mut layouts = Vec::new();
+
+    recorder.load_url("http://app.test/").await.unwrap();
+    layouts.push(recorder.checkpoint("loaded").await);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    recorder.tick().await.unwrap();
+    recorder.handle_input_event(click(40)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    recorder.handle_input_event(click(90)).await.unwrap();
+    recorder.tick().await.unwrap();
+    layouts.push(recorder.checkpoint("clicked").await);
+    recorder.resize_viewport(640, 480).await.unwrap();
+    recorder.tick().await.unwrap();
+    recorder
+        .execute_javascript("JSON.stringify(__log)")
+        .await
+        .unwrap();
+    layouts.push(recorder.checkpoint("resized").await);
+
+    let (bundle, engine) = recorder.finish();
+    (bundle, engine, layouts)
+}
+
+#[tokio::test]
+async fn test_replay_reproduces_script_timers_and_layout() {
+    let (bundle, recorded, layouts) = record_session().await;
+    assert_eq!(bundle.version, BUNDLE_VERSION);
+    assert_eq!(bundle.exchanges.len(), 2);
+    let log = recorded
+        .execute_javascript("JSON.stringify(__log)")
+        .await
+        .unwrap();
+    let log = log.as_str().unwrap().to_string();
+    for entry in ["random", "date", "early", "late", "click 40", "click 90"] {
+        assert!(log.contains(entry), "{} missing from {}", entry, log);
+    }
+    assert!(log.find("early").unwrap() < log.find("late").unwrap());
+
+    let bundle = ReplayBundle::from_json(&bundle.to_json()).unwrap();
+    let mut replayer = Replayer::start(config(), bundle).await.unwrap();
+    for (name, layout) in ["loaded", "clicked", "resized"].iter().zip(&layouts) {
+        assert_eq!(replayer.run_to_checkpoint(name).await.unwrap(), *layout);
+    }
+    replayer.run_to_end().await.unwrap();
+
+    let replayed = replayer.engine();
+    assert_eq!(
+        replayed
+            .execute_javascript("JSON.stringify(__log)")
+            .await
+            .unwrap(),
+        log
+    );
+    assert_eq!(logged_events(replayed), logged_events(&recorded));
+}
+
+#[tokio::test]
+async fn test_replay_reports_the_step_a_request_diverges_at() {
+    let (mut bundle, _, _) = record_session().await;
+    let stylesheet = bundle
+        .exchanges
+        .iter_mut()
+        .find(|exchange| exchange.url.ends_with("app.css"))
+        .unwrap();
+    stylesheet.url = "http://app.test/other.css".to_string();
+
+    let mut replayer = Replayer::start(config(), bundle).await.unwrap();
+    match replayer.run_to_end().await {
+        Err(ReplayError::Diverged { step, detail, .. }) => {
+            assert_eq!(step, 1);
+            assert!(detail.contains("app.css"), "{}", detail);
+        }
+        other => panic!("expected a divergence, got {:?}", other.err()),
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+use vulkan_browser_engine::sandbox::process::cgroup::Cgroup;
+use vulkan_browser_engine::sandbox::process::{ProcessConfig, ResourceEnforcement, ResourceLimits};
+use vulkan_browser_engine::sandbox::security::SecurityEventType;
+use vulkan_browser_engine::sandbox::{SandboxConfig, SandboxManager};
+
+/// A cgroup v2 parent the tests may create cgroups in: the one named by
+/// `VBE_TEST_CGROUP_PARENT`, else the test's own. `None` skips the test.
+fn cgroup_parent() -> Option<String> {
+    let configured = std::env::var("VBE_TEST_CGROUP_PARENT").ok();
+    let parent = Cgroup::usable_parent(configured.as_deref().map(std::path::Path::new));
+    if parent.is_none() {
+        eprintln!("skipping: no cgroup v2 parent with the memory and cpu controllers");
+    }
+    parent.map(|parent| parent.to_string_lossy().into_owned())
+}
+
+async fn sandbox_under(cgroup_parent: &str) -> SandboxManager {
+    SandboxManager::with_config(SandboxConfig {
+        cgroup_parent: Some(cgroup_parent.to_string()),
+        ..Default::default()
+    })
+    .await
+    .unwrap()
+}
+
+fn shell(script: &str, resource_limits: ResourceLimits) -> ProcessConfig {
+    ProcessConfig {
+        executable_path: "/bin/sh".to_string(),
+        arguments: vec!["-c".to_string(), script.to_string()],
+        resource_limits,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_cgroup_memory_limit_oom_kills_a_memory_hog() {
+    let Some(parent) = cgroup_parent() else {
+        return;
+    };
+    let sandbox = sandbox_under(&parent).await;
+    // `tail` holds the whole newline-free stream in memory.
+    let id = sandbox
+        .create_sandboxed_process(shell(
+            "head -c 268435456 /dev/zero | tail",
+            ResourceLimits {
+                max_memory_mb: 64,
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap();
+    sandbox.start_process(id).await.unwrap();
+
+    let mut oom_event = None;
+    for _ in 0..100 {
+        oom_event = sandbox
+            .get_security_events()
+            .await
+            .into_iter()
+            .find(|event| {
+                matches!(event.event_type, SecurityEventType::ResourceAbuse)
+                    && event.source_process == id
+                    && event.details.contains_key("oom_kills")
+            });
+        if oom_event.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(oom_event.is_some(), "no OOM kill recorded");
+
+    let stats = sandbox.get_process_stats().await;
+    assert_eq!(stats[0].resource_enforcement, ResourceEnforcement::Kernel);
+    assert!(stats[0].oom_kills >= 1);
+    assert!(stats[0].memory_usage_bytes <= 64 * 1024 * 1024);
+    sandbox.terminate_process(id).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cgroup_cpu_limit_caps_a_busy_loop() {
+    let Some(parent) = cgroup_parent() else {
+        return;
+    };
+    let sandbox = sandbox_under(&parent).await;
+    let id = sandbox
+        .create_sandboxed_process(shell(
+            "while :; do :; done",
+            ResourceLimits {
+                max_cpu_percent: 20,
+                ..Default::default()
+            },
+        ))
+        .await
+        .unwrap();
+    sandbox.start_process(id).await.unwrap();
+
+    // Utilization is measured between samples a second apart.
+    tokio::time::sleep(Duration::from_millis(3500)).await;
+    let cpu = sandbox.get_process_stats().await[0].cpu_usage_percent;
+    assert!((10.0..=30.0).contains(&cpu), "{cpu:.1}% of a CPU");
+    assert_eq!(
+        sandbox.audit_security().await.unwrap().resource_enforcement,
+        ResourceEnforcement::Kernel
+    );
+
+    sandbox.terminate_process(id).await.unwrap();
+    let cgroup =
+        std::path::Path::new(&parent).join(format!("vbe-sandbox-{}-{}", std::process::id(), id));
+    assert!(!cgroup.exists());
+}
+
+#[tokio::test]
+async fn test_processes_fall_back_to_sampling_without_cgroups() {
+    let sandbox = sandbox_under("/nonexistent/cgroup").await;
+    let id = sandbox
+        .create_sandboxed_process(shell("sleep 5", ResourceLimits::default()))
+        .await
+        .unwrap();
+    sandbox.start_process(id).await.unwrap();
+
+    let stats = sandbox.get_process_stats().await;
+    assert_eq!(stats[0].resource_enforcement, ResourceEnforcement::Sampled);
+    assert_eq!(
+        sandbox.audit_security().await.unwrap().resource_enforcement,
+        ResourceEnforcement::Sampled
+    );
+    sandbox.terminate_process(id).await.unwrap();
+}
@@ -0,0 +1,160 @@
+//! What the integration tests share for talking to a fake network.
+//!
+//! Tests describe the network as [`MockTransport`] routes. Most hand the
+//! mock to the engine with [`mock_network`] or [`mock_engine`] and never
+//! open a socket. Code that makes its own connections, such as TLS, the
+//! service worker runtime or the client's `User-Agent`, needs a real server:
+//! [`serve`] and [`serve_tls`] put the same routes behind a loopback port.
+
+#![allow(dead_code)]
+
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use vulkan_browser_engine::core::network::mock::MockTransport;
+use vulkan_browser_engine::core::network::{NetworkManager, PreparedRequest, Priority, Transport};
+use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+
+/// A manager whose requests `mock` answers.
+pub async fn mock_network(config: &BrowserConfig, mock: &Arc<MockTransport>) -> NetworkManager {
+    NetworkManager::with_transport(config, mock.clone())
+        .await
+        .unwrap()
+}
+
+/// An engine whose requests `mock` answers.
+pub async fn mock_engine(config: BrowserConfig, mock: &Arc<MockTransport>) -> BrowserEngine {
+    let network = mock_network(&config, mock).await;
+    BrowserEngine::with_network(config, network).await.unwrap()
+}
+
+/// Serve `mock`'s routes over HTTP on a loopback port. Returns the origin,
+/// which the routes are to be keyed under.
+pub async fn serve(mock: Arc<MockTransport>) -> String {
+    listen(mock, None).await
+}
+
+/// [`serve`] over TLS, presenting the certificate of `config`.
+pub async fn serve_tls(mock: Arc<MockTransport>, config: ServerConfig) -> String {
+    listen(mock, Some(TlsAcceptor::from(Arc::new(config)))).await
+}
+
+async fn listen(mock: Arc<MockTransport>, tls: Option<TlsAcceptor>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let origin = format!("{}://{}", scheme, listener.local_addr().unwrap());
+
+    let base = origin.clone();
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            let (mock, base) = (mock.clone(), base.clone());
+            match &tls {
+                Some(acceptor) => {
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        // Clients that refuse the certificate hang up mid-handshake.
+                        if let Ok(stream) = acceptor.accept(socket).await {
+                            answer(stream, &mock, &base).await;
+                        }
+                    });
+                }
+                None => {
+                    tokio::spawn(async move { answer(socket, &mock, &base).await });
+                }
+            }
+        }
+    });
+    origin
+}
+
+/// Answer the one request on `stream` as `mock` would have, then close.
+/// A mock connection error closes without an answer; the body goes out as
+/// the mock streams it, so latency, chunking and cut-offs carry over.
+async fn answer<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    mock: &MockTransport,
+    origin: &str,
+) {
+    let Some(request) = read_request(&mut stream, origin).await else {
+        return;
+    };
+    let Ok(response) = mock.execute(request).await else {
+        return;
+    };
+
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, values) in &response.headers {
+        // Repeated headers come joined with newlines.
+        for value in values.split('\n') {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    if stream.write_all(head.as_bytes()).await.is_err() {
+        return;
+    }
+    let mut body = response.body;
+    while let Some(Ok(chunk)) = body.next().await {
+        if stream.write_all(&chunk).await.is_err() {
+            return;
+        }
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// The request on `stream`, as the manager would have handed it to a
+/// transport.
+async fn read_request<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    origin: &str,
+) -> Option<PreparedRequest> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_len = loop {
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_len]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    while data.len() < head_len + length {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+
+    let body = data[head_len..].to_vec();
+    Some(PreparedRequest {
+        url: url::Url::parse(&format!("{}{}", origin, path)).ok()?,
+        method,
+        headers,
+        body: (!body.is_empty()).then_some(body),
+        priority: Priority::High,
+    })
+}
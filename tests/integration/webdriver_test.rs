@@ -0,0 +1,228 @@
+#![cfg(feature = "webdriver")]
+
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use vulkan_browser_engine::webdriver::{WebDriverServer, BROWSER_NAME, ELEMENT_KEY};
+use vulkan_browser_engine::BrowserConfig;
+
+const FIXTURE: &str = "data:text/html,<title>Fixture</title>\
+    <body style=\"margin:0\">\
+    <div id=go style=\"height:40px\">Go</div><p id=out>Waiting</p>\
+    <script>document.getElementById('go').addEventListener('click', () => {\
+      document.getElementById('out').textContent = 'Clicked';\
+    });</script></body>";
+
+/// One WebDriver command over plain HTTP: the status and the JSON body.
+async fn call(
+    client: &reqwest::Client,
+    base: &str,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let mut request = client.request(method, format!("{base}{path}"));
+    if let Some(body) = body {
+        request = request.json(&body);
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn test_webdriver_find_click_and_read_text() {
+    use reqwest::Method;
+
+    let server = WebDriverServer::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    let client_run = async {
+        let client = reqwest::Client::new();
+        let (status, created) = call(
+            &client,
+            &base,
+            Method::POST,
+            "/session",
+            Some(json!({
+                "capabilities": {
+                    "alwaysMatch": { "browserName": BROWSER_NAME },
+                    "firstMatch": [{ "vbe:options": { "viewportWidth": 640 } }],
+                }
+            })),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let session = format!(
+            "/session/{}",
+            created["value"]["sessionId"].as_str().unwrap()
+        );
+        assert_eq!(
+            created["value"]["capabilities"]["vbe:options"]["viewportWidth"],
+            640
+        );
+
+        let (status, _) = call(
+            &client,
+            &base,
+            Method::POST,
+            &format!("{session}/url"),
+            Some(json!({ "url": FIXTURE })),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (_, title) = call(
+            &client,
+            &base,
+            Method::GET,
+            &format!("{session}/title"),
+            None,
+        )
+        .await;
+        assert_eq!(title["value"], "Fixture");
+
+        let element = format!("{session}/element");
+        let find = |selector: &str| {
+            call(
+                &client,
+                &base,
+                Method::POST,
+                &element,
+                Some(json!({ "using": "css selector", "value": selector })),
+            )
+        };
+        let (status, go) = find("#go").await;
+        assert_eq!(status, 200);
+        let go = go["value"][ELEMENT_KEY].as_str().unwrap().to_string();
+        let (_, out) = find("#out").await;
+        let out = out["value"][ELEMENT_KEY].as_str().unwrap().to_string();
+
+        let (status, _) = call(
+            &client,
+            &base,
+            Method::POST,
+            &format!("{session}/element/{go}/click"),
+            Some(json!({})),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (_, text) = call(
+            &client,
+            &base,
+            Method::GET,
+            &format!("{session}/element/{out}/text"),
+            None,
+        )
+        .await;
+        assert_eq!(text["value"], "Clicked");
+
+        // Scripts take and return elements, and async ones report back
+        // through their callback.
+        let (_, returned) = call(
+            &client,
+            &base,
+            Method::POST,
+            &format!("{session}/execute/sync"),
+            Some(json!({
+                "script": "return Promise.resolve(document.getElementById('out'));",
+                "args": [],
+            })),
+        )
+        .await;
+        assert_eq!(returned["value"], json!({ ELEMENT_KEY: out }));
+        let (_, awaited) = call(
+            &client,
+            &base,
+            Method::POST,
+            &format!("{session}/execute/async"),
+            Some(json!({
+                "script": "const [element, done] = arguments;\
+                           setTimeout(() => done(element.textContent), 10);",
+                "args": [{ ELEMENT_KEY: out }],
+            })),
+        )
+        .await;
+        assert_eq!(awaited["value"], "Clicked");
+
+        let (status, missing) = find("#missing").await;
+        assert_eq!(status, 404);
+        assert_eq!(missing["value"]["error"], "no such element");
+
+        // References die with the document they came from.
+        call(
+            &client,
+            &base,
+            Method::POST,
+            &format!("{session}/url"),
+            Some(json!({ "url": FIXTURE })),
+        )
+        .await;
+        let (status, stale) = call(
+            &client,
+            &base,
+            Method::GET,
+            &format!("{session}/element/{out}/text"),
+            None,
+        )
+        .await;
+        assert_eq!(status, 404);
+        assert_eq!(stale["value"]["error"], "stale element reference");
+
+        let (status, _) = call(&client, &base, Method::DELETE, &session, None).await;
+        assert_eq!(status, 200);
+        let (status, gone) =
+            call(&client, &base, Method::GET, &format!("{session}/url"), None).await;
+        assert_eq!(status, 404);
+        assert_eq!(gone["value"]["error"], "invalid session id");
+    };
+
+    tokio::select! {
+        served = server.serve(listener) => panic!("server stopped: {served:?}"),
+        () = client_run => {}
+    }
+}
+
+#[tokio::test]
+async fn test_webdriver_rejects_unmatched_capabilities_and_bad_requests() {
+    use reqwest::Method;
+
+    let server = WebDriverServer::new(BrowserConfig {
+        enable_gpu_acceleration: false,
+        enable_sandbox: false,
+        enable_pwa: false,
+        ..Default::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    let client_run = async {
+        let client = reqwest::Client::new();
+        let (status, refused) = call(
+            &client,
+            &base,
+            Method::POST,
+            "/session",
+            Some(json!({ "capabilities": { "alwaysMatch": { "browserName": "firefox" } } })),
+        )
+        .await;
+        assert_eq!(status, 500);
+        assert_eq!(refused["value"]["error"], "session not created");
+
+        let (status, unknown) = call(&client, &base, Method::GET, "/nowhere", None).await;
+        assert_eq!(status, 404);
+        assert_eq!(unknown["value"]["error"], "unknown command");
+        let (status, wrong) = call(&client, &base, Method::GET, "/session", None).await;
+        assert_eq!(status, 405);
+        assert_eq!(wrong["value"]["error"], "unknown method");
+    };
+
+    tokio::select! {
+        served = server.serve(listener) => panic!("server stopped: {served:?}"),
+        () = client_run => {}
+    }
+}
@@ -1,60 +1,881 @@
-use vulkan_renderer::core::dom::*;
+#[test]
+fn test_dom_creation() {
+    use vulkan_browser_engine::core::dom::Document;
 
-#[tokio::test]
-async fn test_dom_creation() {
     let doc = Document::new();
-    assert_eq!(doc.get_nodes().len(), 0);
+    assert_eq!(doc.node_count(), 0);
+    assert!(doc.get_root_node().is_none());
 }
 
-#[tokio::test]
-async fn test_element_insertion() {
-    let mut doc = Document::new();
-    let element = Element::new("div".to_string());
-    let node_id = doc.insert_element(element);
-    
-    assert_eq!(doc.get_nodes().len(), 1);
-    assert!(doc.get_element(node_id).is_some());
+#[test]
+fn test_element_insertion() {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::new();
+    let node_id = doc
+        .create_node(NodeType::Element, "div".to_string())
+        .unwrap();
+
+    assert_eq!(doc.node_count(), 1);
+    assert_eq!(doc.get_node(node_id).unwrap().read().get_tag_name(), "div");
 }
 
-#[tokio::test]
-async fn test_html_parsing() {
-    let html = r#"
+#[test]
+fn test_html_parsing() {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        r#"
         <html>
             <body>
                 <div id="test">Hello World</div>
             </body>
         </html>
-    "#;
-    
-    let doc = Document::parse(html).unwrap();
-    assert!(doc.get_nodes().len() > 0);
+    "#,
+    )
+    .unwrap();
+
+    assert!(doc.node_count() > 0);
+    let div = doc.get_element_by_id("test").unwrap();
+    assert_eq!(doc.text_content(div).as_deref(), Some("Hello World"));
+}
+
+#[test]
+fn test_view_source_emits_one_element_per_line() {
+    use vulkan_browser_engine::core::dom::{view_source::build_view_source, Document};
+
+    let source = "<!doctype html>\n<p class=\"a\">hi</p>\n<!-- note -->\n";
+    let doc = Document::new();
+    let lines = build_view_source(&doc, source, "view-source:test").unwrap();
+
+    assert_eq!(lines, 3);
+    assert_eq!(doc.get_elements_by_class_name("line").len(), 3);
+    assert!(doc.get_elements_by_tag_name("script").is_empty());
+}
+
+/// Node type, name, value, attributes and children of `node_id`, for comparing
+/// trees that do not share node ids.
+fn dom_shape(
+    doc: &vulkan_browser_engine::core::dom::Document,
+    node_id: vulkan_browser_engine::core::dom::NodeId,
+) -> serde_json::Value {
+    let node = doc.get_node(node_id).unwrap();
+    let node = node.read();
+    let attributes: std::collections::BTreeMap<&String, &String> = node.attributes.iter().collect();
+    serde_json::json!({
+        "type": format!("{:?}", node.node_type),
+        "name": node.tag_name,
+        "value": node.text_content,
+        "attributes": attributes,
+        "children": node
+            .children
+            .iter()
+            .map(|&child| dom_shape(doc, child))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn assert_round_trips(html: &str) {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let parsed = Document::parse(html).unwrap();
+    let serialized = parsed.serialize();
+    let reparsed = Document::parse(&serialized).unwrap();
+    assert_eq!(
+        dom_shape(&parsed, parsed.get_root_node().unwrap()),
+        dom_shape(&reparsed, reparsed.get_root_node().unwrap()),
+        "{html:?} serialized as {serialized:?}"
+    );
+}
+
+const ROUND_TRIP_PIECES: &[&str] = &[
+    "<!DOCTYPE html>",
+    "<html>",
+    "<head>",
+    "<title>a &lt; b</title>",
+    "<body class=main>",
+    "<div>",
+    "</div>",
+    "<DIV ID=Upper>",
+    "<p>",
+    "</p>",
+    "<ul><li>one<li>two</ul>",
+    "<dl><dt>term<dd>definition</dl>",
+    "<span title='say \"hi\"' data-x=1>",
+    "</span>",
+    "<a href=\"?a=1&b=2\">",
+    "</a>",
+    "<br>",
+    "</br>",
+    "<img src=x.png alt>",
+    "<input disabled>",
+    "<pre>\n\nindented</pre>",
+    "<textarea>\nfirst line</textarea>",
+    "<script>if (a < b && c) { x = '</p>'; }</script>",
+    "<style>p > a { color: red }</style>",
+    "<!-- comment -->",
+    "<!-->",
+    "<?processing instruction?>",
+    "</unopened>",
+    "text",
+    " \n ",
+    "&amp;",
+    "&",
+    "&nbsp;",
+    "&#x1F600;",
+    "&#0;",
+    "&unknown;",
+    "<",
+    ">",
+    "\u{a0}é",
+];
+
+proptest::proptest! {
+    #[test]
+    fn test_serialize_round_trips_through_the_parser(
+        pieces in proptest::collection::vec(proptest::sample::select(ROUND_TRIP_PIECES), 1..32)
+    ) {
+        assert_round_trips(&pieces.concat());
+    }
+}
+
+#[test]
+fn test_golden_fixtures_round_trip_through_serialize() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/fixtures");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "html") {
+            assert_round_trips(&std::fs::read_to_string(&path).unwrap());
+        }
+    }
+}
+
+#[test]
+fn test_serialize_writes_void_raw_text_and_escaped_content() {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        "<!doctype HTML><HTML><Body><P Class=\"a&amp;b\" title='x\"y'>1 &lt; 2&nbsp;<BR></P>\
+         <script>if (a < b) {}</script></Body></HTML>",
+    )
+    .unwrap();
+    assert_eq!(
+        doc.serialize(),
+        "<!DOCTYPE html><html><body><p class=\"a&amp;b\" title=\"x&quot;y\">1 &lt; 2&nbsp;<br></p>\
+         <script>if (a < b) {}</script></body></html>"
+    );
+
+    let body = doc.get_elements_by_tag_name("body")[0];
+    assert_eq!(
+        doc.serialize_xml(body).unwrap(),
+        "<body xmlns=\"http://www.w3.org/1999/xhtml\"><p class=\"a&amp;b\" title=\"x&quot;y\">\
+         1 &lt; 2\u{a0}<br /></p><script>if (a &lt; b) {}</script></body>"
+    );
+}
+
+#[test]
+fn test_serialize_as_parsed_ignores_later_mutations() {
+    use vulkan_browser_engine::core::dom::{Document, DomSource, SerializeOptions};
+
+    let doc = Document::parse("<p id=a>parsed</p>").unwrap();
+    let p = doc.get_elements_by_tag_name("p")[0];
+    doc.set_attribute(p, "id", "b").unwrap();
+
+    assert_eq!(doc.serialize(), "<p id=\"b\">parsed</p>");
+    let as_parsed = doc
+        .serialize_with(SerializeOptions {
+            source: DomSource::AsParsed,
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(as_parsed, "<p id=\"a\">parsed</p>");
+}
+
+#[test]
+fn test_serialize_large_document_in_linear_time() {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let mut html = String::from("<!DOCTYPE html><html><body>");
+    for i in 0..25_000 {
+        html.push_str(&format!("<div class=c{}>item {}</div>", i % 7, i));
+    }
+    html.push_str("</body></html>");
+    let doc = Document::parse(&html).unwrap();
+    assert!(doc.node_count() > 50_000);
+
+    let start = std::time::Instant::now();
+    let serialized = doc.serialize();
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(serialized.len(), html.len() + 25_000 * 2);
+}
+
+#[test]
+fn test_compaction_returns_arena_memory_and_keeps_node_ids() {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse("<html><body><p id=kept>kept</p></body></html>").unwrap();
+    doc.set_compaction_ratio(None);
+    let body = doc.get_elements_by_tag_name("body")[0];
+    let kept = doc.get_element_by_id("kept").unwrap();
+    let baseline = doc.memory_usage();
+
+    // 500 subtrees of 1000 nodes; the last node of each moves to <body>
+    // before its subtree is removed.
+    let mut survivors = Vec::new();
+    let mut freed = Vec::new();
+    for batch in 0..500 {
+        let container = doc.create_node(NodeType::Element, "div".into()).unwrap();
+        doc.append_child(body, container).unwrap();
+        let mut last = container;
+        for _ in 0..999 {
+            last = doc.create_node(NodeType::Element, "span".into()).unwrap();
+            doc.append_child(container, last).unwrap();
+        }
+        doc.set_attribute(last, "data-batch", &batch.to_string())
+            .unwrap();
+        doc.append_child(body, last).unwrap();
+        survivors.push((last, batch.to_string()));
+        freed.push(container);
+        doc.get_mutation_records();
+    }
+    for &container in &freed {
+        doc.remove_child(body, container).unwrap();
+    }
+    let grown = doc.memory_usage();
+    assert_eq!(grown.live_nodes, baseline.live_nodes + 500_000);
+
+    let reclaimed = doc.reclaim_detached();
+    assert_eq!(reclaimed.reclaimed.len(), 499_500);
+    assert!(reclaimed.compaction.is_none());
+    let sparse = doc.memory_usage();
+    assert_eq!(sparse.live_nodes, baseline.live_nodes + 500);
+    assert_eq!(sparse.dead_slots, 499_500);
+    assert!(sparse.arena_bytes >= grown.arena_bytes);
+
+    let report = doc.compact();
+    assert_eq!(report.live_nodes, baseline.live_nodes + 500);
+    let compacted = doc.memory_usage();
+    assert_eq!(compacted.dead_slots, 0);
+    assert!(compacted.arena_bytes < baseline.arena_bytes + 500 * 64);
+    assert!(compacted.arena_bytes * 100 < grown.arena_bytes);
+    assert!(compacted.attribute_bytes > baseline.attribute_bytes);
+
+    // Ids held across the compaction still name the same nodes.
+    assert_eq!(doc.get_element_by_id("kept"), Some(kept));
+    for (node_id, batch) in &survivors {
+        assert_eq!(doc.get_parent(*node_id), Some(body));
+        let node = doc.get_node(*node_id).unwrap();
+        assert_eq!(
+            node.read().get_attribute("data-batch").as_ref(),
+            Some(batch)
+        );
+    }
+    assert!(freed.iter().all(|&container| !doc.contains_node(container)));
+
+    // Past the default ratio, freeing compacts by itself and new nodes
+    // never get a freed node's id.
+    doc.set_compaction_ratio(Some(1.0));
+    let container = doc.create_node(NodeType::Element, "div".into()).unwrap();
+    doc.append_child(body, container).unwrap();
+    for _ in 0..9_999 {
+        let child = doc.create_node(NodeType::Text, "x".into()).unwrap();
+        doc.append_child(container, child).unwrap();
+    }
+    doc.remove_child(body, container).unwrap();
+    let report = doc.reclaim_detached().compaction.unwrap();
+    assert_eq!(report.slots_after, baseline.live_nodes + 500);
+    let fresh = doc.create_node(NodeType::Element, "div".into()).unwrap();
+    assert!(!freed.contains(&fresh) && fresh != container);
+}
+
+#[test]
+fn test_lang_selector_follows_inherited_content_language() {
+    use vulkan_browser_engine::core::css::{CSSParser, ComputedValue, StyleEngine};
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        "<html lang=en><body><p id=en>hello</p>\
+         <div id=fr lang=fr-CA><p id=fr-p>bonjour</p>\
+         <p id=de lang=de>hallo <span id=nl xml:lang=nl lang=de>hoi</span></p>\
+         <p id=unknown lang=''>?</p></div></body></html>",
+    )
+    .unwrap();
+    let id = |name: &str| doc.get_element_by_id(name).unwrap();
+
+    assert_eq!(
+        doc.query_selector_all(":lang(fr)").unwrap(),
+        vec![id("fr"), id("fr-p")]
+    );
+    assert_eq!(
+        doc.query_selector_all("p:lang(EN)").unwrap(),
+        vec![id("en")]
+    );
+    assert_eq!(doc.query_selector_all(":lang(de)").unwrap(), vec![id("de")]);
+    assert!(doc.query_selector_all(":lang(fr-C)").unwrap().is_empty());
+    assert_eq!(doc.language_of(id("nl")).as_deref(), Some("nl"));
+    assert_eq!(doc.language_of(id("unknown")), None);
+
+    let styles = StyleEngine::new();
+    styles.add_stylesheet(
+        CSSParser::new()
+            .parse("p:lang(fr) { font-size: 30px }")
+            .unwrap(),
+    );
+    styles.compute_styles(&doc).unwrap();
+    let computed = |name: &str| styles.get_computed_styles(id(name)).unwrap();
+    assert_eq!(computed("fr-p").language().as_deref(), Some("fr-CA"));
+    assert_eq!(computed("de").language().as_deref(), Some("de"));
+    assert_eq!(computed("unknown").language(), None);
+    assert_eq!(
+        computed("fr-p").get_computed_value("font-size").unwrap(),
+        ComputedValue::Length(30.0)
+    );
+    assert_ne!(
+        computed("de").get_computed_value("font-size").unwrap(),
+        ComputedValue::Length(30.0)
+    );
+    let text = doc.get_children(id("fr-p"))[0];
+    assert_eq!(
+        styles
+            .get_computed_styles(text)
+            .unwrap()
+            .language()
+            .as_deref(),
+        Some("fr-CA")
+    );
+
+    // Changing a language restyles what inherits it.
+    doc.set_attribute(id("fr"), "lang", "it").unwrap();
+    styles.compute_styles(&doc).unwrap();
+    assert_eq!(computed("fr-p").language().as_deref(), Some("it"));
+    assert_ne!(
+        computed("fr-p").get_computed_value("font-size").unwrap(),
+        ComputedValue::Length(30.0)
+    );
+}
+
+#[test]
+fn test_content_language_header_sets_default_language() {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse("<p id=p>hallo</p><p id=en lang=en>hi</p>").unwrap();
+    let p = doc.get_element_by_id("p").unwrap();
+    assert_eq!(doc.language_of(p), None);
+
+    doc.set_content_language(Some(" de-AT "));
+    assert_eq!(doc.language_of(p).as_deref(), Some("de-AT"));
+    assert_eq!(doc.query_selector_all(":lang(de)").unwrap(), vec![p]);
+    let en = doc.get_element_by_id("en").unwrap();
+    assert_eq!(doc.language_of(en).as_deref(), Some("en"));
+
+    // A header naming several audiences names no default.
+    doc.set_content_language(Some("de, en"));
+    assert_eq!(doc.language_of(p), None);
+}
+
+#[test]
+fn test_selector_lists_match_like_stylesheets_and_queries() {
+    use vulkan_browser_engine::core::css::selector::Selector;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        "<body><div id=box class=a><p id=inner class=b>1</p></div>\
+         <span id=aside class=c>2</span><p id=last class=d>3</p></body>",
+    )
+    .unwrap();
+    let id = |name: &str| doc.get_element_by_id(name).unwrap();
+    let query = |selector: &str| doc.query_selector_all(selector).unwrap();
+
+    let cases: &[(&str, &[&str], u32)] = &[
+        ("div p", &["inner"], 2),
+        ("body > p", &["last"], 2),
+        ("div + span", &["aside"], 2),
+        ("div ~ p", &["last"], 2),
+        (":is(.a, .c)", &["box", "aside"], 10),
+        (":is(#box, .c) p", &["inner"], 101),
+        (
+            ":where(#box, span, p)",
+            &["box", "inner", "aside", "last"],
+            0,
+        ),
+        ("p:not(:is(.b, #box))", &["last"], 101),
+        ("body :not(:is(div > *, span))", &["box", "last"], 2),
+        ("p:not(.a, .d)", &["inner"], 11),
+        // One selector a forgiving list cannot read drops only itself.
+        (":is(.a, !bogus)", &["box"], 10),
+        (":where(!bogus)", &[], 0),
+    ];
+    for (selector, expected, specificity) in cases {
+        let expected: Vec<_> = expected.iter().map(|name| id(name)).collect();
+        assert_eq!(query(selector), expected, "{}", selector);
+        assert_eq!(
+            Selector::parse(selector).unwrap().specificity(),
+            *specificity,
+            "{}",
+            selector
+        );
+    }
+
+    // `:not()` and whole lists are unforgiving; `:has()` is refused.
+    for selector in [":not(.a, !bogus)", "p, !bogus", "div,", ":has(p)"] {
+        assert!(doc.query_selector_all(selector).is_err(), "{}", selector);
+    }
+    assert!(Selector::parse(":has(> p)")
+        .unwrap_err()
+        .to_string()
+        .contains(":has() is not supported"));
+}
+
+#[test]
+fn test_selector_list_specificity_flows_into_the_cascade() {
+    use vulkan_browser_engine::core::css::{CSSParser, ComputedValue, StyleEngine};
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        "<div id=box><p id=where>1</p><p id=is class=x>2</p></div>\
+         <p id=plain class=y>3</p><p id=after>4</p>",
+    )
+    .unwrap();
+    let id = |name: &str| doc.get_element_by_id(name).unwrap();
+    let styles = StyleEngine::new();
+    styles.add_stylesheet(
+        CSSParser::new()
+            .parse(
+                ":where(#box) p { font-size: 30px }
+                 p { font-size: 20px }
+                 :is(#box, .nothing) .x { font-size: 40px }
+                 div .x { font-size: 10px }
+                 .y, #nothing { font-size: 50px }
+                 p.y { font-size: 60px }
+                 p:has(span) { font-size: 70px }
+                 #after { font-size: 80px }",
+            )
+            .unwrap(),
+    );
+    styles.compute_styles(&doc).unwrap();
+    let font_size = |name: &str| {
+        styles
+            .get_computed_styles(id(name))
+            .unwrap()
+            .get_computed_value("font-size")
+            .unwrap()
+    };
+
+    // `:where()` weighs nothing, so the later plain `p` wins.
+    assert_eq!(font_size("where"), ComputedValue::Length(20.0));
+    // `:is()` weighs as its id even though `.nothing` is what it lists too.
+    assert_eq!(font_size("is"), ComputedValue::Length(40.0));
+    // A list weighs as the selector that matched, not its most specific.
+    assert_eq!(font_size("plain"), ComputedValue::Length(60.0));
+    // The invalid `:has()` rule is dropped on its own.
+    assert_eq!(font_size("after"), ComputedValue::Length(80.0));
+
+    let matched = styles.matched_rules(id("is"), &doc);
+    assert_eq!(
+        matched
+            .iter()
+            .map(|rule| rule.specificity)
+            .collect::<Vec<_>>(),
+        vec![1, 1, 110, 11]
+    );
+}
+
+#[test]
+fn test_content_limits_flatten_deep_markup_and_cut_long_values() {
+    use vulkan_browser_engine::core::content_limits::{ContentLimit, ContentLimits};
+    use vulkan_browser_engine::core::dom::{Document, NodeId};
+
+    let limits = ContentLimits {
+        max_dom_depth: 8,
+        max_attribute_length: 16,
+        max_text_length: 32,
+        ..Default::default()
+    };
+    let html = format!(
+        "{}<p id=deep>x</p>{}<p id=after title={}>{}</p>",
+        "<div>".repeat(50),
+        "</div>".repeat(50),
+        "t".repeat(100),
+        "é".repeat(100),
+    );
+    let doc = Document::new();
+    let breaches = doc.parse_html_with_limits(&html, limits).unwrap();
+
+    let depth = |mut node: NodeId| {
+        let mut depth = 0;
+        while let Some(parent) = doc.get_parent(node) {
+            node = parent;
+            depth += 1;
+        }
+        depth
+    };
+    let deep = doc.get_element_by_id("deep").unwrap();
+    assert!(depth(deep) <= 8);
+    // Closing the flattened elements closes the outer ones too.
+    let after = doc.get_element_by_id("after").unwrap();
+    let first_div = doc.get_elements_by_tag_name("div")[0];
+    assert_eq!(doc.get_parent(after), doc.get_parent(first_div));
+
+    let after = doc.get_node(after).unwrap();
+    let after = after.read();
+    assert_eq!(after.get_attribute("title").unwrap(), "t".repeat(16));
+    // Cut at a character boundary.
+    assert_eq!(after.get_text_content(), "é".repeat(16));
+
+    let breach = |limit| breaches.iter().find(|breach| breach.limit == limit);
+    assert!(breach(ContentLimit::DomDepth).unwrap().value > 50);
+    assert_eq!(breach(ContentLimit::AttributeLength).unwrap().value, 100);
+    assert_eq!(breach(ContentLimit::TextLength).unwrap().value, 200);
+    assert!(breach(ContentLimit::NodeCount).is_none());
+}
+
+#[test]
+fn test_stylesheet_limits_drop_complex_selectors_and_excess_rules() {
+    use vulkan_browser_engine::core::content_limits::{ContentLimit, ContentLimits};
+    use vulkan_browser_engine::core::css::{CSSParser, CSSRule};
+
+    let mut parser = CSSParser::with_limits(&ContentLimits {
+        max_stylesheet_rules: 10,
+        max_selector_components: 4,
+        ..Default::default()
+    });
+    let mut css = String::from(
+        "a b c d e { color: red }
+         :is(a b, c d e) { color: red }
+         a b c { color: blue }
+         @media screen { p { color: green } }",
+    );
+    for i in 0..100 {
+        css.push_str(&format!(".r{i} {{ color: red }}"));
+    }
+    let rules = parser.parse(&css).unwrap();
+
+    // `a b c`, the `@media` rule and `.r0` to `.r7`: ten style rules.
+    assert_eq!(rules.len(), 10);
+    assert!(matches!(rules[1], CSSRule::Media(_)));
+    let breaches = parser.take_breaches();
+    assert_eq!(breaches.len(), 2);
+    assert!(breaches
+        .iter()
+        .any(|breach| breach.limit == ContentLimit::SelectorComponents && breach.threshold == 4));
+    assert!(breaches
+        .iter()
+        .any(|breach| breach.limit == ContentLimit::StylesheetRules && breach.value == 11));
+}
+
+#[test]
+fn test_attribute_changes_made_by_an_observer_are_delivered_after_the_current_one() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::{AttributeInterest, Document};
+
+    let doc = Document::parse("<html><body><img id=pic src=a.png></body></html>").unwrap();
+    let pic = doc.get_element_by_id("pic").unwrap();
+    let seen: Arc<Mutex<Vec<String>>> = Arc::default();
+
+    let log = seen.clone();
+    doc.observe_attributes(AttributeInterest::attribute("class"), move |doc, change| {
+        log.lock()
+            .unwrap()
+            .push(format!("first class={:?}", change.new_value));
+        doc.set_attribute(change.node, "src", "b.png").unwrap();
+        log.lock().unwrap().push("first returned".to_string());
+    });
+    let log = seen.clone();
+    doc.observe_attributes(AttributeInterest::attribute("src"), move |_, change| {
+        log.lock().unwrap().push(format!(
+            "src {:?} -> {:?}",
+            change.old_value, change.new_value
+        ));
+    });
+    let log = seen.clone();
+    doc.observe_attributes(AttributeInterest::attribute("class"), move |_, change| {
+        log.lock()
+            .unwrap()
+            .push(format!("second class={:?}", change.new_value));
+    });
+
+    doc.set_attribute(pic, "class", "big").unwrap();
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            "first class=Some(\"big\")",
+            "first returned",
+            "second class=Some(\"big\")",
+            "src Some(\"a.png\") -> Some(\"b.png\")",
+        ]
+    );
+
+    // Attributes no one observes, or on nodes of other tags, skip delivery.
+    seen.lock().unwrap().clear();
+    let div = doc.create_node(NodeType::Element, "div".into()).unwrap();
+    doc.set_attribute(div, "title", "quiet").unwrap();
+    doc.remove_attribute(pic, "alt").unwrap();
+    assert!(seen.lock().unwrap().is_empty());
 }
 
-tests/unit/js_engine_test.rs
-use vulkan_renderer::js_engine::*;
+#[test]
+fn test_appending_text_one_char_at_a_time_extends_one_node() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::dom::document::{MutationObserver, MutationRecord};
+    use vulkan_browser_engine::core::dom::{Document, MutationType};
+
+    let doc = Document::parse("<html><body><p id=log></p></body></html>").unwrap();
+    let log = doc.get_element_by_id("log").unwrap();
+    let records: Arc<Mutex<Vec<MutationRecord>>> = Arc::default();
+    let sink = records.clone();
+    doc.add_mutation_observer(MutationObserver {
+        callback: Arc::new(move |batch: &[MutationRecord]| {
+            sink.lock().unwrap().extend(batch.iter().cloned());
+        }),
+        observe_child_list: true,
+        observe_attributes: false,
+        observe_character_data: true,
+        observe_subtree: true,
+        attribute_filter: None,
+    });
+    let before = doc.memory_usage();
 
-#[tokio::test]
-async fn test_js_execution() {
-    let mut engine = JsEngine::new().await.unwrap();
-    let result = engine.execute("2 + 2").await.unwrap();
-    
-    assert_eq!(result, serde_json::Value::Number(serde_json::Number::from(4)));
+    let expected: String = (0..1000).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    for c in expected.chars() {
+        doc.append_text(log, &c.to_string()).unwrap();
+    }
+
+    let children = doc.get_children(log);
+    assert_eq!(children.len(), 1);
+    assert_eq!(doc.text_content(log).unwrap(), expected);
+    let after = doc.memory_usage();
+    assert_eq!(after.text_nodes_created - before.text_nodes_created, 1);
+    assert_eq!(
+        after.text_nodes_coalesced - before.text_nodes_coalesced,
+        999
+    );
+
+    // The records tell what happened: one node added, then its data grown.
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 1000);
+    assert_eq!(records[0].mutation_type, MutationType::ChildList);
+    assert_eq!(records[0].added_nodes, children);
+    assert!(records[1..].iter().all(|record| {
+        record.mutation_type == MutationType::CharacterData && record.target == children[0]
+    }));
+    assert_eq!(records[999].old_value.as_deref(), Some(&expected[..999]));
 }
 
-#[tokio::test]
-async fn test_console_log() {
-    let mut engine = JsEngine::new().await.unwrap();
-    let result = engine.execute("console.log('Hello World'); 42").await;
-    
-    assert!(result.is_ok());
+#[test]
+fn test_normalize_merges_text_and_ranges_keep_their_characters() {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::{DOMRange, Document, NodeId};
+    use vulkan_browser_engine::core::editing::{CaretMovement, EditingSession};
+
+    let doc =
+        Document::parse("<html><body><div id=box contenteditable></div></body></html>").unwrap();
+    let div = doc.get_element_by_id("box").unwrap();
+    let text = |content: &str| {
+        let id = doc
+            .create_node(NodeType::Text, content.to_string())
+            .unwrap();
+        doc.append_child(div, id).unwrap();
+        id
+    };
+    // "Hel" "" "lo " <b>!</b> "wor" "ld"
+    let hel = text("Hel");
+    text("");
+    let lo = text("lo ");
+    let bold = doc.create_node(NodeType::Element, "b".to_string()).unwrap();
+    doc.append_child(div, bold).unwrap();
+    doc.append_text(bold, "!").unwrap();
+    let wor = text("wor");
+    let ld = text("ld");
+
+    let range = |start: (NodeId, u32), end: (NodeId, u32)| {
+        let mut range = DOMRange::new();
+        range.set_start(start.0, start.1).unwrap();
+        range.set_end(end.0, end.1).unwrap();
+        doc.track_range(range)
+    };
+    // "l|lo", across the merge point; "world" from before its first node;
+    // "l" from between "wor" and "ld".
+    let across = range((hel, 2), (lo, 2));
+    let world = range((div, 4), (ld, 2));
+    let between = range((div, 5), (ld, 1));
+    let mut session = EditingSession::new();
+    assert!(session.focus(&doc, div));
+    session.move_caret(&doc, CaretMovement::Left);
+    let before = doc.memory_usage();
+
+    doc.normalize(div).unwrap();
+
+    assert_eq!(doc.get_children(div), vec![hel, bold, wor]);
+    assert_eq!(doc.text_content(hel).unwrap(), "Hello ");
+    assert_eq!(doc.text_content(wor).unwrap(), "world");
+    assert_eq!(doc.text_content(div).unwrap(), "Hello !world");
+    let after = doc.memory_usage();
+    assert_eq!(after.text_nodes_coalesced - before.text_nodes_coalesced, 3);
+
+    let bounds = |id| {
+        let range = doc.live_range(id).unwrap();
+        (
+            (range.start_container(), range.start_offset()),
+            (range.end_container(), range.end_offset()),
+        )
+    };
+    assert_eq!(bounds(across), ((hel, 2), (hel, 5)));
+    assert_eq!(&doc.text_content(hel).unwrap()[2..5], "llo");
+    assert_eq!(bounds(world), ((div, 2), (wor, 5)));
+    assert_eq!(bounds(between), ((wor, 3), (wor, 4)));
+    assert_eq!(&doc.text_content(wor).unwrap()[3..4], "l");
+
+    // The caret was between the "l" and "d" of "ld", and still is.
+    assert_eq!(session.text_before_caret(&doc), "worl");
+    session.insert_text(&doc, "X");
+    assert_eq!(doc.text_content(wor).unwrap(), "worlXd");
+    assert_eq!(
+        doc.memory_usage().text_nodes_created,
+        after.text_nodes_created
+    );
 }
 
-tests/unit/renderer_test.rs
-use vulkan_renderer::renderer::*;
+#[test]
+fn test_resource_urls_resolve_against_the_document_base() {
+    use vulkan_browser_engine::core::dom::Document;
+
+    let resolve = |doc: &Document, reference: &str| doc.resolve_url(reference).map(String::from);
+    let doc = Document::parse("<html><body><img src=c.png></body></html>").unwrap();
+    assert_eq!(resolve(&doc, "c.png"), None);
+    doc.set_url("https://example.com/a/b.html".to_string());
+    assert_eq!(
+        resolve(&doc, "../c.png").as_deref(),
+        Some("https://example.com/c.png")
+    );
+    assert_eq!(
+        resolve(&doc, "/c.png").as_deref(),
+        Some("https://example.com/c.png")
+    );
+    assert_eq!(
+        resolve(&doc, " c.png ").as_deref(),
+        Some("https://example.com/a/c.png")
+    );
+    assert_eq!(
+        resolve(&doc, "//cdn.example.net/c.png").as_deref(),
+        Some("https://cdn.example.net/c.png")
+    );
+    assert_eq!(
+        resolve(&doc, "#top").as_deref(),
+        Some("https://example.com/a/b.html#top")
+    );
+    assert_eq!(
+        resolve(&doc, "data:image/png;base64,iVBORw0KGgo=").as_deref(),
+        Some("data:image/png;base64,iVBORw0KGgo=")
+    );
 
-#[tokio::test]
-async fn test_renderer_creation() {
-    // Note: This test would require a headless Vulkan setup
-    // let renderer = VulkanRenderer::new().await;
-    // assert!(renderer.is_ok());
-}
\ No newline at end of file
+    // The first `<base href>` wins, itself resolved against the document.
+    let doc = Document::parse(
+        "<html><head><base target=_top><base href=/static/><base href=https://other.test/>\
+         </head><body></body></html>",
+    )
+    .unwrap();
+    doc.set_url("https://example.com/a/b.html".to_string());
+    assert_eq!(
+        doc.base_url().map(String::from).as_deref(),
+        Some("https://example.com/static/")
+    );
+    assert_eq!(
+        resolve(&doc, "c.png").as_deref(),
+        Some("https://example.com/static/c.png")
+    );
+
+    // A base that is not a URL leaves the document URL.
+    let doc = Document::parse("<html><head><base href=http://[::1></head></html>").unwrap();
+    doc.set_url("https://example.com/a/b.html".to_string());
+    assert_eq!(
+        resolve(&doc, "c.png").as_deref(),
+        Some("https://example.com/a/c.png")
+    );
+}
+
+#[test]
+fn test_id_and_class_index_follows_mutations() {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::parse(
+        "<html><body><div id=a class='card big'><p id=dup class=card>1</p></div>\
+         <p id=dup class='card small'>2</p></body></html>",
+    )
+    .unwrap();
+    let a = doc.get_element_by_id("a").unwrap();
+    let cards = doc.get_elements_by_class_name("card");
+    assert_eq!(cards.len(), 3);
+    assert_eq!(cards[0], a);
+    // Duplicate ids: the first in document order wins.
+    let first_dup = doc.get_element_by_id("dup").unwrap();
+    assert_eq!(first_dup, cards[1]);
+    assert_eq!(doc.get_elements_by_class_name(" big  card "), [a]);
+    assert!(doc.get_elements_by_class_name("card missing").is_empty());
+    assert!(doc.get_elements_by_class_name("").is_empty());
+
+    // Changing `id` and `class` moves the element in the index.
+    doc.set_attribute(a, "id", "renamed").unwrap();
+    assert_eq!(doc.get_element_by_id("a"), None);
+    assert_eq!(doc.get_element_by_id("renamed"), Some(a));
+    doc.set_attribute(a, "class", "small").unwrap();
+    assert_eq!(doc.get_elements_by_class_name("card"), &cards[1..]);
+    assert_eq!(doc.get_elements_by_class_name("small"), [a, cards[2]]);
+    doc.remove_attribute(a, "class").unwrap();
+    assert_eq!(doc.get_elements_by_class_name("small"), [cards[2]]);
+
+    // Removing the first duplicate leaves the second; its subtree goes too.
+    let body = doc.get_parent(a).unwrap();
+    doc.remove_child(body, a).unwrap();
+    assert_eq!(doc.get_element_by_id("renamed"), None);
+    assert_eq!(doc.get_element_by_id("dup"), Some(cards[2]));
+
+    // Detached elements are not found until inserted; inserting one
+    // before the other duplicate makes it the first again.
+    let created = doc.create_node(NodeType::Element, "span".into()).unwrap();
+    doc.set_attribute(created, "id", "dup").unwrap();
+    assert_eq!(doc.get_element_by_id("dup"), Some(cards[2]));
+    doc.insert_before(body, created, Some(cards[2])).unwrap();
+    assert_eq!(doc.get_element_by_id("dup"), Some(created));
+    doc.insert_before(body, a, Some(created)).unwrap();
+    assert_eq!(doc.get_element_by_id("dup"), Some(first_dup));
+}
+
+#[test]
+fn test_id_lookups_do_not_scale_with_document_size() {
+    use std::time::Instant;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let build = |count: usize| {
+        let items: String = (0..count)
+            .map(|i| format!("<div id=item{i} class='item row{}'>{i}</div>", i % 10))
+            .collect();
+        Document::parse(&format!("<html><body>{items}</body></html>")).unwrap()
+    };
+    let time_lookups = |doc: &Document| {
+        let started = Instant::now();
+        for round in 0..10_000 {
+            let id = format!("item{}", round % 50);
+            assert!(doc.get_element_by_id(&id).is_some());
+        }
+        started.elapsed()
+    };
+    let small = build(50);
+    let large = build(10_000);
+    assert!(large.node_count() > 10_000);
+    assert_eq!(large.get_elements_by_class_name("row3").len(), 1_000);
+
+    // A scan would make each lookup in the large document some hundred
+    // times slower; the index keeps it flat.
+    let small_time = time_lookups(&small);
+    let large_time = time_lookups(&large);
+    assert!(
+        large_time < small_time * 10 + std::time::Duration::from_millis(20),
+        "{:?} against {:?}",
+        large_time,
+        small_time
+    );
+}
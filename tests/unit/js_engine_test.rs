@@ -1,69 +1,78 @@
-use vulkan_renderer::js_engine::*;
 use serde_json::{Number, Value};
-use tokio_test;
+use vulkan_browser_engine::js_engine::JSRuntime;
+use vulkan_browser_engine::BrowserConfig;
 
 #[tokio::test]
 async fn test_js_engine_creation() {
-    let engine = JsEngine::new().await;
+    let engine = JSRuntime::new(&BrowserConfig::default()).await;
     assert!(engine.is_ok());
 }
 
 #[tokio::test]
 async fn test_simple_arithmetic() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     let result = engine.execute("2 + 2").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(4)));
-    
+
     let result = engine.execute("10 * 5").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(50)));
-    
+
     let result = engine.execute("15 / 3").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(5)));
 }
 
 #[tokio::test]
 async fn test_string_operations() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     let result = engine.execute("'Hello' + ' ' + 'World'").await.unwrap();
     assert_eq!(result, Value::String("Hello World".to_string()));
-    
+
     let result = engine.execute("'test'.toUpperCase()").await.unwrap();
     assert_eq!(result, Value::String("TEST".to_string()));
 }
 
 #[tokio::test]
 async fn test_variables_and_functions() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     engine.execute("let x = 10; let y = 20;").await.unwrap();
     let result = engine.execute("x + y").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(30)));
-    
-    engine.execute("function add(a, b) { return a + b; }").await.unwrap();
+
+    engine
+        .execute("function add(a, b) { return a + b; }")
+        .await
+        .unwrap();
     let result = engine.execute("add(5, 7)").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(12)));
 }
 
 #[tokio::test]
 async fn test_objects_and_arrays() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
-    let result = engine.execute("let obj = {name: 'test', value: 42}; obj.name").await.unwrap();
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
+    let result = engine
+        .execute("let obj = {name: 'test', value: 42}; obj.name")
+        .await
+        .unwrap();
     assert_eq!(result, Value::String("test".to_string()));
-    
-    let result = engine.execute("let arr = [1, 2, 3]; arr.length").await.unwrap();
+
+    let result = engine
+        .execute("let arr = [1, 2, 3]; arr.length")
+        .await
+        .unwrap();
     assert_eq!(result, Value::Number(Number::from(3)));
-    
+
     let result = engine.execute("arr[1]").await.unwrap();
     assert_eq!(result, Value::Number(Number::from(2)));
 }
 
 #[tokio::test]
 async fn test_console_log() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     let result = engine.execute("console.log('Hello from JS'); 'done'").await;
     assert!(result.is_ok());
     assert_eq!(result.unwrap(), Value::String("done".to_string()));
@@ -71,80 +80,592 @@ async fn test_console_log() {
 
 #[tokio::test]
 async fn test_error_handling() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     let result = engine.execute("throw new Error('Test error')").await;
     assert!(result.is_err());
-    
+
     let result = engine.execute("undefined_variable").await;
     assert!(result.is_err());
 }
 
 #[tokio::test]
 async fn test_loops_and_conditionals() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
-    let result = engine.execute(r#"
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
+    let result = engine
+        .execute(
+            r#"
         let sum = 0;
         for (let i = 1; i <= 10; i++) {
             sum += i;
         }
         sum
-    "#).await.unwrap();
+    "#,
+        )
+        .await
+        .unwrap();
     assert_eq!(result, Value::Number(Number::from(55)));
-    
-    let result = engine.execute(r#"
+
+    let result = engine
+        .execute(
+            r#"
         let x = 10;
         if (x > 5) {
             'greater'
         } else {
             'lesser'
         }
-    "#).await.unwrap();
+    "#,
+        )
+        .await
+        .unwrap();
     assert_eq!(result, Value::String("greater".to_string()));
 }
 
 #[tokio::test]
 async fn test_json_operations() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
-    let result = engine.execute(r#"
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
+    let result = engine
+        .execute(
+            r#"
         let obj = {name: "John", age: 30};
         JSON.stringify(obj)
-    "#).await.unwrap();
+    "#,
+        )
+        .await
+        .unwrap();
     assert!(result.as_str().unwrap().contains("John"));
-    
-    let result = engine.execute(r#"
+
+    let result = engine
+        .execute(
+            r#"
         let jsonStr = '{"test": true}';
         JSON.parse(jsonStr).test
-    "#).await.unwrap();
+    "#,
+        )
+        .await
+        .unwrap();
     assert_eq!(result, Value::Bool(true));
 }
 
 #[tokio::test]
 async fn test_async_operations() {
-    let mut engine = JsEngine::new().await.unwrap();
-    
-    let result = engine.execute(r#"
+    let engine = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
+    let result = engine
+        .execute(
+            r#"
         new Promise((resolve) => {
             setTimeout(() => resolve('async result'), 10);
         })
-    "#).await;
-    
+    "#,
+        )
+        .await;
+
     assert!(result.is_ok());
 }
 
 #[tokio::test]
 async fn test_memory_isolation() {
-    let mut engine1 = JsEngine::new().await.unwrap();
-    let mut engine2 = JsEngine::new().await.unwrap();
-    
+    let engine1 = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    let engine2 = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+
     engine1.execute("let testVar = 'engine1'").await.unwrap();
     engine2.execute("let testVar = 'engine2'").await.unwrap();
-    
+
     let result1 = engine1.execute("testVar").await.unwrap();
     let result2 = engine2.execute("testVar").await.unwrap();
-    
+
     assert_eq!(result1, Value::String("engine1".to_string()));
     assert_eq!(result2, Value::String("engine2".to_string()));
 }
+
+fn styled_document() -> (
+    vulkan_browser_engine::core::dom::Document,
+    vulkan_browser_engine::core::dom::NodeId,
+) {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+    use vulkan_browser_engine::core::dom::Document;
+
+    let doc = Document::new();
+    let root = doc.create_node(NodeType::Document, String::new()).unwrap();
+    doc.set_root_node(root);
+    let el = doc
+        .create_node(NodeType::Element, "div".to_string())
+        .unwrap();
+    doc.append_child(root, el).unwrap();
+    doc.set_attribute(el, "id", "box").unwrap();
+    (doc, el)
+}
+
+#[tokio::test]
+async fn test_style_property_from_js_reaches_cascade_and_attribute() {
+    use vulkan_browser_engine::core::css::{Color, ComputedValue, StyleEngine};
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let (doc, el) = styled_document();
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+
+    let attr = runtime
+        .execute(
+            "const el = document.getElementById('box');
+             el.style.backgroundColor = '#ff0000';
+             el.getAttribute('style')",
+        )
+        .await
+        .unwrap();
+    assert_eq!(attr, Value::String("background-color: #ff0000".to_string()));
+    assert!(doc.is_style_dirty(el));
+
+    let styles = StyleEngine::new();
+    styles.compute_styles(&doc).unwrap();
+    let computed = styles.get_computed_styles(el).unwrap();
+    assert_eq!(
+        computed.get_computed_value("background-color").unwrap(),
+        ComputedValue::Color(Color::from_hex("#ff0000").unwrap())
+    );
+}
+
+#[tokio::test]
+async fn test_style_attribute_reads_back_as_typed_property() {
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let (doc, _el) = styled_document();
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+
+    let result = runtime
+        .execute(
+            "const box = document.getElementById('box');
+             box.setAttribute('style', 'margin-top: 4px; width: 10px !important');
+             [box.style.marginTop, box.style.width, box.style.getPropertyPriority('width')]",
+        )
+        .await
+        .unwrap();
+    assert_eq!(result, serde_json::json!(["4px", "10px", "important"]));
+}
+
+fn editable_document() -> (
+    vulkan_browser_engine::core::dom::Document,
+    vulkan_browser_engine::core::dom::NodeId,
+) {
+    use vulkan_browser_engine::core::dom::document::NodeType;
+
+    let (doc, el) = styled_document();
+    doc.set_attribute(el, "contenteditable", "true").unwrap();
+    let text = doc.create_node(NodeType::Text, "ab".to_string()).unwrap();
+    doc.append_child(el, text).unwrap();
+    (doc, el)
+}
+
+#[tokio::test]
+async fn test_ime_composition_commits_at_caret_in_event_order() {
+    use vulkan_browser_engine::core::editing::{editable_text, EditingSession};
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::{BrowserConfig, ImeCompositionState};
+
+    let (doc, el) = editable_document();
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+    runtime
+        .execute(
+            "globalThis.log = [];
+             const box = document.getElementById('box');
+             for (const type of ['compositionstart', 'compositionupdate', 'compositionend', 'input']) {
+               box.addEventListener(type, (e) => log.push(e.type + ':' + e.data));
+             }",
+        )
+        .await
+        .unwrap();
+
+    let mut session = EditingSession::new();
+    assert!(session.focus(&doc, el));
+    for state in [
+        ImeCompositionState::Start,
+        ImeCompositionState::Update {
+            text: "k".to_string(),
+            cursor_range: Some((1, 1)),
+        },
+        ImeCompositionState::Update {
+            text: "か".to_string(),
+            cursor_range: Some((3, 3)),
+        },
+        ImeCompositionState::Commit {
+            text: "漢".to_string(),
+        },
+    ] {
+        // Nothing reaches the DOM before the commit.
+        assert_eq!(editable_text(&doc, el), "ab");
+        for event in session.compose(&doc, state) {
+            runtime
+                .dispatch_element_event(event.target, &event.to_json())
+                .await
+                .unwrap();
+        }
+    }
+
+    assert_eq!(editable_text(&doc, el), "ab漢");
+    assert_eq!(session.caret(), 3);
+    assert_eq!(
+        runtime.execute("log").await.unwrap(),
+        serde_json::json!([
+            "compositionstart:",
+            "compositionupdate:k",
+            "input:k",
+            "compositionupdate:か",
+            "input:か",
+            "compositionupdate:漢",
+            "input:漢",
+            "compositionend:漢",
+        ])
+    );
+}
+
+#[test]
+fn test_ime_cancel_leaves_dom_untouched_and_composition_is_atomic() {
+    use vulkan_browser_engine::core::editing::{editable_text, CaretMovement, EditingSession};
+    use vulkan_browser_engine::ImeCompositionState;
+
+    let (doc, el) = editable_document();
+    let mut session = EditingSession::new();
+    assert!(session.focus(&doc, el));
+
+    session.compose(
+        &doc,
+        ImeCompositionState::Update {
+            text: "にほん".to_string(),
+            cursor_range: Some((3, 3)),
+        },
+    );
+    // Arrow keys jump over the composition rather than into it.
+    session.move_caret(&doc, CaretMovement::Right);
+    assert_eq!(session.composition().unwrap().cursor_range, Some((9, 9)));
+    session.move_caret(&doc, CaretMovement::Left);
+    assert_eq!(session.composition().unwrap().cursor_range, Some((0, 0)));
+    assert_eq!(session.caret(), 2);
+
+    let events = session.compose(&doc, ImeCompositionState::Cancel);
+    let types: Vec<_> = events.iter().map(|e| e.event_type).collect();
+    assert_eq!(types, ["compositionupdate", "compositionend"]);
+    assert!(session.composition().is_none());
+    assert_eq!(editable_text(&doc, el), "ab");
+}
+
+#[tokio::test]
+async fn test_event_loop_orders_microtasks_timers_and_frames() {
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let (doc, _el) = styled_document();
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+
+    runtime
+        .execute(
+            "globalThis.log = [];
+             const box = document.getElementById('box');
+             setTimeout(() => log.push('timeout'), 0);
+             requestAnimationFrame(() => {
+               log.push('raf');
+               Promise.resolve().then(() => log.push('raf:promise'));
+             });
+             new MutationObserver(() => log.push('mutation')).observe(box, { attributes: true });
+             Promise.resolve().then(() => log.push('promise'));
+             box.setAttribute('data-state', 'open');
+             queueMicrotask(() => log.push('microtask'));
+             log.push('script');",
+        )
+        .await
+        .unwrap();
+    // The script's own checkpoint drains every microtask, in queue order.
+    assert_eq!(
+        runtime.execute("log").await.unwrap(),
+        serde_json::json!(["script", "promise", "mutation", "microtask"])
+    );
+
+    assert_eq!(runtime.run_timers().await.unwrap(), 1);
+    assert_eq!(runtime.run_animation_frames().await.unwrap(), 1);
+    assert_eq!(
+        runtime.execute("log").await.unwrap(),
+        serde_json::json!([
+            "script",
+            "promise",
+            "mutation",
+            "microtask",
+            "timeout",
+            "raf",
+            "raf:promise"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_each_timer_and_frame_callback_gets_its_own_checkpoint() {
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime
+        .execute(
+            "globalThis.log = [];
+             setTimeout(() => {
+               log.push('t1');
+               Promise.resolve().then(() => log.push('t1:promise'));
+               throw new Error('does not stop the next timer');
+             }, 0);
+             setTimeout(() => log.push('t2'), 0);
+             clearTimeout(setTimeout(() => log.push('cleared'), 0));
+             requestAnimationFrame(() => {
+               log.push('f1');
+               queueMicrotask(() => log.push('f1:microtask'));
+               requestAnimationFrame(() => log.push('next frame'));
+             });
+             requestAnimationFrame(() => log.push('f2'));",
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(runtime.run_timers().await.unwrap(), 2);
+    assert_eq!(runtime.run_animation_frames().await.unwrap(), 2);
+    assert_eq!(
+        runtime.execute("log").await.unwrap(),
+        serde_json::json!(["t1", "t1:promise", "t2", "f1", "f1:microtask", "f2"])
+    );
+
+    // Callbacks requested during a frame wait for the next one.
+    assert_eq!(runtime.run_animation_frames().await.unwrap(), 1);
+    assert_eq!(
+        runtime.execute("log.slice(-1)").await.unwrap(),
+        serde_json::json!(["next frame"])
+    );
+}
+
+#[tokio::test]
+async fn test_engine_mutations_reach_observers_at_next_checkpoint() {
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let (doc, el) = styled_document();
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+    runtime
+        .execute(
+            "globalThis.seen = [];
+             new MutationObserver((records) => {
+               for (const r of records) seen.push(r.attributeName + '=' + r.oldValue);
+             }).observe(document.getElementById('box'), {
+               attributeOldValue: true,
+               attributeFilter: ['value'],
+             });",
+        )
+        .await
+        .unwrap();
+
+    doc.set_attribute(el, "title", "ignored").unwrap();
+    doc.set_attribute(el, "value", "a").unwrap();
+    doc.set_attribute(el, "value", "b").unwrap();
+
+    // No timer is due, but the turn still ends in a checkpoint.
+    assert_eq!(runtime.run_timers().await.unwrap(), 0);
+    assert_eq!(
+        runtime.execute("seen").await.unwrap(),
+        serde_json::json!(["value=null", "value=a"])
+    );
+}
+
+#[tokio::test]
+async fn test_cross_origin_documents_get_separate_agents() {
+    use url::Url;
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    let page = Url::parse("https://a.example/").unwrap();
+    let a = runtime.navigate_agent(Some(&page)).unwrap();
+    let frame = runtime
+        .open_document(&Url::parse("https://a.example/frame").unwrap(), Some(a))
+        .unwrap();
+    let b = runtime
+        .open_document(&Url::parse("https://b.example/").unwrap(), Some(a))
+        .unwrap();
+    assert_eq!(frame, a);
+    assert_ne!(b, a);
+
+    runtime
+        .execute("globalThis.kept = new Array(100000).fill(0).map((_, i) => ({ i })); 0")
+        .await
+        .unwrap();
+    let metrics = runtime.get_metrics().await;
+    let agents: Vec<_> = metrics
+        .agents
+        .iter()
+        .map(|agent| (agent.agent, agent.origin.as_deref(), agent.documents))
+        .collect();
+    assert_eq!(
+        agents,
+        vec![
+            (a, Some("https://a.example"), 2),
+            (b, Some("https://b.example"), 1)
+        ]
+    );
+    // The 512 MiB default budget, split between two agents.
+    assert!(metrics
+        .agents
+        .iter()
+        .all(|agent| agent.heap_limit_bytes == 256 * 1024 * 1024));
+    assert!(metrics.agents[0].heap_used_bytes > metrics.agents[1].heap_used_bytes);
+
+    // The current agent's last document only goes by navigating; the others
+    // go with their last document.
+    runtime.close_document(frame).unwrap();
+    assert!(runtime.close_document(a).is_err());
+    runtime.close_document(b).unwrap();
+    let c = runtime
+        .navigate_agent(Some(&Url::parse("https://c.example/").unwrap()))
+        .unwrap();
+    let remaining: Vec<_> = runtime
+        .get_metrics()
+        .await
+        .agents
+        .iter()
+        .map(|agent| agent.agent)
+        .collect();
+    assert_eq!(remaining, vec![c]);
+    assert_eq!(
+        runtime.execute("typeof kept").await.unwrap(),
+        serde_json::json!("undefined")
+    );
+}
+
+#[tokio::test]
+async fn test_post_message_crosses_agents_while_direct_access_throws() {
+    use url::Url;
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    let a = runtime
+        .navigate_agent(Some(&Url::parse("https://a.example/").unwrap()))
+        .unwrap();
+    runtime
+        .execute(
+            "globalThis.secret = 'a';
+             globalThis.received = [];
+             onmessage = (e) => received.push([e.data, e.origin]);",
+        )
+        .await
+        .unwrap();
+    let b = runtime
+        .open_document(&Url::parse("https://b.example/").unwrap(), Some(a))
+        .unwrap();
+
+    runtime.switch_agent(b).unwrap();
+    assert_eq!(
+        runtime
+            .execute("try { opener.secret; 'leaked' } catch (e) { e.name }")
+            .await
+            .unwrap(),
+        serde_json::json!("SecurityError")
+    );
+    assert_eq!(
+        runtime.execute("typeof secret").await.unwrap(),
+        serde_json::json!("undefined")
+    );
+    runtime
+        .execute(
+            "opener.postMessage({ n: 1 }, 'https://a.example');
+             opener.postMessage('lost', 'https://c.example');
+             opener.postMessage('anywhere', '*');",
+        )
+        .await
+        .unwrap();
+
+    runtime.switch_agent(a).unwrap();
+    assert_eq!(runtime.deliver_messages().unwrap(), 2);
+    assert_eq!(
+        runtime.execute("received").await.unwrap(),
+        serde_json::json!([[{ "n": 1 }, "https://b.example"], ["anywhere", "https://b.example"]])
+    );
+}
+
+#[tokio::test]
+async fn test_attribute_hooks_see_parsed_and_script_changes_in_order() {
+    use std::sync::{Arc, Mutex};
+    use vulkan_browser_engine::core::dom::{AttributeInterest, Document};
+    use vulkan_browser_engine::js_engine::JSRuntime;
+    use vulkan_browser_engine::BrowserConfig;
+
+    type Seen = (&'static str, String, Option<String>, Option<String>, bool);
+    let doc = Document::new();
+    let seen: Arc<Mutex<Vec<Seen>>> = Arc::default();
+    for (observer, interest) in [
+        ("class", AttributeInterest::attribute("class")),
+        ("src", AttributeInterest::attribute("src").on("img")),
+    ] {
+        let seen = seen.clone();
+        doc.observe_attributes(interest, move |_, change| {
+            seen.lock().unwrap().push((
+                observer,
+                change.tag_name.clone(),
+                change.old_value.clone(),
+                change.new_value.clone(),
+                change.parsed,
+            ));
+        });
+    }
+    let some = |value: &str| Some(value.to_string());
+
+    // One change per attribute, once each element exists.
+    doc.parse_html(
+        "<html><body><img id=pic class=photo src=a.png alt=a>\
+         <script src=s.js></script><p class=lead>text</p></body></html>",
+    )
+    .unwrap();
+    assert_eq!(
+        std::mem::take(&mut *seen.lock().unwrap()),
+        vec![
+            ("class", "img".to_string(), None, some("photo"), true),
+            ("src", "img".to_string(), None, some("a.png"), true),
+            ("class", "p".to_string(), None, some("lead"), true),
+        ]
+    );
+
+    let runtime = JSRuntime::new(&BrowserConfig::default()).await.unwrap();
+    runtime.inject_document_api(&doc).await.unwrap();
+    runtime
+        .execute(
+            "const pic = document.getElementById('pic');
+             pic.setAttribute('src', 'b.png');
+             pic.setAttribute('alt', 'b');
+             pic.setAttribute('class', 'photo wide');
+             pic.removeAttribute('class');
+             pic.removeAttribute('class');",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        std::mem::take(&mut *seen.lock().unwrap()),
+        vec![
+            (
+                "src",
+                "img".to_string(),
+                some("a.png"),
+                some("b.png"),
+                false
+            ),
+            (
+                "class",
+                "img".to_string(),
+                some("photo"),
+                some("photo wide"),
+                false
+            ),
+            ("class", "img".to_string(), some("photo wide"), None, false),
+        ]
+    );
+}
@@ -1,4 +1,4 @@
-use vulkan_renderer::renderer::*;
+use vulkan_browser_engine::renderer::Vertex;
 
 #[tokio::test]
 async fn test_vertex_creation() {
@@ -15,7 +15,7 @@ async fn test_vertex_creation() {
 
 #[tokio::test]
 async fn test_vertex_buffer_creation() {
-    let vertices = vec![
+    let vertices = [
         Vertex {
             position: [0.0, 0.0, 0.0],
             tex_coord: [0.0, 0.0],
@@ -108,4 +108,1938 @@ fn test_triangle_indices() {
     assert_eq!(indices[3], 2);
     assert_eq!(indices[4], 3);
     assert_eq!(indices[5], 0);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_emoji_zwj_sequences_form_single_clusters() {
+    use vulkan_browser_engine::renderer::text::segment_clusters;
+
+    // "Hi " + family (man ZWJ woman ZWJ girl) + " " + flag + " " + heart with VS16.
+    let text = "Hi \u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} \u{1F1EB}\u{1F1F7} \u{2764}\u{FE0F}";
+    let clusters = segment_clusters(text);
+
+    let texts: Vec<&str> = clusters.iter().map(|c| c.text).collect();
+    assert_eq!(clusters.len(), 8);
+    assert_eq!(texts[3], "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}");
+    assert_eq!(texts[5], "\u{1F1EB}\u{1F1F7}");
+    assert_eq!(texts.concat(), text);
+    assert!(!clusters[0].emoji);
+    assert!(clusters[3].emoji && clusters[5].emoji && clusters[7].emoji);
+
+    // Text presentation selector opts a symbol out of the color font.
+    assert!(!segment_clusters("\u{2764}\u{FE0E}")[0].emoji);
+}
+
+#[test]
+fn test_color_glyphs_fill_rgba_atlas() {
+    use vulkan_browser_engine::renderer::text::{segment_clusters, ColorGlyphCache};
+
+    let mut cache = ColorGlyphCache::with_system_fonts();
+    if !cache.has_fonts() {
+        eprintln!("no system color emoji font installed; skipping rasterization check");
+        return;
+    }
+
+    let text = "ok \u{1F469}\u{200D}\u{1F4BB}";
+    for cluster in segment_clusters(text).into_iter().filter(|c| c.emoji) {
+        let glyph = cache.glyph(cluster.text, 32.0).unwrap().unwrap();
+        assert!(glyph.width > 0.0 && glyph.advance > 0.0);
+        assert!(glyph.ascent > 0.0 && glyph.ascent < glyph.height);
+    }
+
+    let atlas = cache.atlas();
+    assert_eq!(atlas.len(), 1);
+    assert!(atlas.data().chunks(4).any(|px| px[3] > 0));
+}
+
+#[tokio::test]
+async fn test_rounded_overflow_clip_cuts_child_corners() {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ClipRect, CornerRadii, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend,
+        Style, VulkanRenderer,
+    };
+
+    let card = Rect {
+        x: 20.0,
+        y: 20.0,
+        width: 100.0,
+        height: 80.0,
+    };
+    let block = |bounds: Rect, color: &str, clip: ClipChain| LayoutNode {
+        node_id: NodeId::new(),
+        bounds,
+        element_type: ElementType::Block,
+        style: Style {
+            background_color: Some(color.to_string()),
+            ..Default::default()
+        },
+        text_content: None,
+        image_url: None,
+        image_frame: 0,
+        clip,
+    };
+    // A loud child overflowing a rounded card on every side.
+    let clip = ClipChain::new().push(ClipRect::new(card, CornerRadii::uniform(16.0)));
+    let child = block(
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 200.0,
+            height: 200.0,
+        },
+        "#ff00ff",
+        clip,
+    );
+    let child_id = child.node_id;
+    let mut tree = LayoutTree::new();
+    tree.add_node(child);
+
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    renderer.resize(160, 120).await.unwrap();
+    renderer
+        .render(&vulkan_browser_engine::core::dom::Document::new(), &tree)
+        .await
+        .unwrap();
+    let snapshot = renderer.snapshot();
+
+    const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+    assert_eq!(snapshot.pixel(60, 60), MAGENTA);
+    assert_eq!(snapshot.pixel(21, 60), MAGENTA);
+    // Outside the card, and inside its rect but beyond a rounded corner.
+    assert_eq!(snapshot.pixel(10, 10), [0; 4]);
+    for (x, y) in [(21, 21), (118, 21), (118, 98), (21, 98)] {
+        assert_eq!(snapshot.pixel(x, y), [0; 4], "corner pixel ({x}, {y})");
+    }
+
+    // Clicks follow the same shape.
+    assert_eq!(tree.hit_test(60.0, 60.0), Some(child_id));
+    assert_eq!(tree.hit_test(21.0, 21.0), None);
+    assert_eq!(tree.hit_test(5.0, 60.0), None);
+}
+
+#[test]
+fn test_nested_rounded_clips_compose() {
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ClipRect, CornerRadii, Rect, MAX_ROUNDED_CLIP_DEPTH,
+    };
+
+    let modal = ClipRect::new(
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 200.0,
+            height: 200.0,
+        },
+        CornerRadii::uniform(40.0),
+    );
+    // The card sits in the modal's top-left corner with its own radius.
+    let card = ClipRect::new(
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 100.0,
+            height: 100.0,
+        },
+        CornerRadii {
+            bottom_right: 30.0,
+            ..Default::default()
+        },
+    );
+    let chain = ClipChain::new().push(modal).push(card);
+
+    assert_eq!(chain.rounded().len(), 2);
+    assert!(chain.contains(50.0, 50.0));
+    // Cut by the modal's corner even though the card's is square there.
+    assert!(!chain.contains(2.0, 2.0));
+    // Cut by the card's corner.
+    assert!(!chain.contains(98.0, 98.0));
+    // Outside the card altogether.
+    assert!(!chain.contains(150.0, 50.0));
+
+    let mut deep = chain;
+    for _ in 0..MAX_ROUNDED_CLIP_DEPTH {
+        deep = deep.push(ClipRect::new(
+            Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 90.0,
+                height: 90.0,
+            },
+            CornerRadii::uniform(10.0),
+        ));
+    }
+    assert_eq!(deep.rounded().len(), MAX_ROUNDED_CLIP_DEPTH);
+    assert!(!deep.contains(95.0, 50.0));
+    assert_eq!(deep.push_constants().count as usize, MAX_ROUNDED_CLIP_DEPTH);
+}
+
+#[tokio::test]
+async fn test_underline_strip_sits_below_the_baseline() {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::core::fonts::FontMetrics;
+    use vulkan_browser_engine::renderer::decoration::{baseline, decoration_strips};
+    use vulkan_browser_engine::renderer::{
+        DecorationKind, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend, Style,
+        TextDecoration, VulkanRenderer,
+    };
+
+    let metrics = FontMetrics::fallback(20.0);
+    let bounds = Rect {
+        x: 10.0,
+        y: 10.0,
+        width: 120.0,
+        height: 24.0,
+    };
+    let strips = decoration_strips(&bounds, &metrics, &TextDecoration::underline());
+    assert_eq!(strips.len(), 1);
+    let underline = &strips[0];
+    assert_eq!(underline.kind, DecorationKind::Underline);
+    let baseline = baseline(&bounds, &metrics);
+    assert!(underline.rect.y > baseline);
+    assert!(underline.rect.y + underline.rect.height <= baseline + metrics.descent);
+    assert_eq!(underline.rect.x, bounds.x);
+    assert_eq!(underline.rect.width, bounds.width);
+
+    let mut tree = LayoutTree::new();
+    tree.add_node(LayoutNode {
+        node_id: NodeId::new(),
+        bounds: bounds.clone(),
+        element_type: ElementType::Text,
+        style: Style {
+            color: Some("#0000ff".to_string()),
+            font_size: 20.0,
+            font_metrics: metrics,
+            text_decoration: TextDecoration {
+                color: Some("#ff0000".to_string()),
+                ..TextDecoration::underline()
+            },
+            ..Default::default()
+        },
+        text_content: Some("under lined".to_string()),
+        image_url: None,
+        image_frame: 0,
+        clip: Default::default(),
+    });
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    renderer.resize(160, 60).await.unwrap();
+    renderer
+        .render(&vulkan_browser_engine::core::dom::Document::new(), &tree)
+        .await
+        .unwrap();
+    let snapshot = renderer.snapshot();
+    // Between the glyphs there is only the underline.
+    let (underline_y, glyph_y) = ((underline.rect.y + 0.5) as u32, (baseline - 4.0) as u32);
+    let gap = (bounds.x as u32..(bounds.x + bounds.width) as u32)
+        .find(|&x| snapshot.pixel(x, glyph_y) == [0; 4])
+        .unwrap();
+    assert_eq!(snapshot.pixel(gap, underline_y), [255, 0, 0, 255]);
+}
+
+#[tokio::test]
+async fn test_text_shadow_paints_offset_from_glyphs() {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::core::fonts::FontMetrics;
+    use vulkan_browser_engine::renderer::decoration::baseline;
+    use vulkan_browser_engine::renderer::{
+        ElementType, LayoutNode, LayoutTree, Rect, RenderBackend, Style, TextShadow, VulkanRenderer,
+    };
+
+    let metrics = FontMetrics::fallback(20.0);
+    let bounds = Rect {
+        x: 10.0,
+        y: 10.0,
+        width: 12.0,
+        height: 24.0,
+    };
+    let text = |shadow: TextShadow| {
+        let mut tree = LayoutTree::new();
+        tree.add_node(LayoutNode {
+            node_id: NodeId::new(),
+            bounds: bounds.clone(),
+            element_type: ElementType::Text,
+            style: Style {
+                color: Some("#000000".to_string()),
+                font_size: 20.0,
+                font_metrics: metrics,
+                text_shadows: vec![shadow],
+                ..Default::default()
+            },
+            // A full block, so the glyph covers its box.
+            text_content: Some("\u{2588}".to_string()),
+            image_url: None,
+            image_frame: 0,
+            clip: Default::default(),
+        });
+        tree
+    };
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    renderer.resize(60, 60).await.unwrap();
+    let document = vulkan_browser_engine::core::dom::Document::new();
+
+    renderer
+        .render(
+            &document,
+            &text(TextShadow {
+                offset_x: 8.0,
+                offset_y: 8.0,
+                blur_radius: 0.0,
+                color: Some("#ff0000".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+    let snapshot = renderer.snapshot();
+    let glyph_y = (baseline(&bounds, &metrics) - 4.0) as u32;
+    // The glyph paints over its own shadow; the shadow shows beside it.
+    assert_eq!(snapshot.pixel(16, glyph_y), [0, 0, 0, 255]);
+    assert_eq!(snapshot.pixel(16 + 8, glyph_y + 8), [255, 0, 0, 255]);
+    assert_eq!(snapshot.pixel(16 + 16, glyph_y + 8), [0; 4]);
+
+    renderer
+        .render(
+            &document,
+            &text(TextShadow {
+                offset_x: 8.0,
+                offset_y: 8.0,
+                blur_radius: 6.0,
+                color: Some("#ff0000".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+    let blurred = renderer.snapshot();
+    // Blur softens the shadow's edge without moving it.
+    let edge = blurred.pixel(16 + 8 + 5, glyph_y + 8);
+    assert!(edge[3] > 0 && edge[3] < 255, "edge alpha {}", edge[3]);
+    assert_eq!(blurred.pixel(16, glyph_y), [0, 0, 0, 255]);
+}
+
+#[tokio::test]
+async fn test_moved_node_damages_old_and_new_bounds() {
+    use vulkan_browser_engine::core::dom::{Document, NodeId};
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend, Style, VulkanRenderer,
+    };
+
+    let block = |node_id: NodeId, x: f32| LayoutNode {
+        node_id,
+        bounds: Rect {
+            x,
+            y: 0.0,
+            width: 10.0,
+            height: 10.0,
+        },
+        element_type: ElementType::Block,
+        style: Style {
+            background_color: Some("red".to_string()),
+            ..Default::default()
+        },
+        text_content: None,
+        image_url: None,
+        image_frame: 0,
+        clip: ClipChain::new(),
+    };
+    let ids: Vec<NodeId> = (0..3).map(|_| NodeId::new()).collect();
+    let tree = |moved_x: f32| {
+        let mut tree = LayoutTree::new();
+        tree.add_node(block(ids[0], 0.0));
+        tree.add_node(block(ids[1], moved_x));
+        tree.add_node(block(ids[2], 100.0));
+        tree
+    };
+
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    let document = Document::new();
+    renderer.render(&document, &tree(20.0)).await.unwrap();
+    let versions = renderer.get_frame_stats().vertex_buffer_version();
+
+    renderer.render(&document, &tree(50.0)).await.unwrap();
+    let stats = *renderer.get_frame_stats();
+    assert_eq!(stats.nodes_patched(), 1);
+    assert_eq!(stats.vertices_uploaded(), 4);
+    assert_eq!(stats.vertex_buffer_version(), versions);
+    assert_eq!(
+        renderer.damage(),
+        Some(&Rect {
+            x: 20.0,
+            y: 0.0,
+            width: 40.0,
+            height: 10.0,
+        })
+    );
+
+    // Nothing changed, nothing to upload or repaint.
+    renderer.render(&document, &tree(50.0)).await.unwrap();
+    assert_eq!(renderer.get_frame_stats().nodes_patched(), 0);
+    assert_eq!(renderer.get_frame_stats().vertices_uploaded(), 0);
+    assert_eq!(renderer.damage(), None);
+
+    // A full rebuild repaints every node into new buffers.
+    renderer.set_full_rebuild(true);
+    renderer.render(&document, &tree(50.0)).await.unwrap();
+    let stats = *renderer.get_frame_stats();
+    assert_eq!((stats.nodes_patched(), stats.nodes_rebuilt()), (0, 3));
+    assert!(stats.vertex_buffer_version() > versions);
+}
+
+fn layout_node_strategy(
+) -> impl proptest::strategy::Strategy<Value = vulkan_browser_engine::renderer::LayoutNode> {
+    use proptest::prelude::*;
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ClipRect, CornerRadii, DecorationLines, DecorationStyle, EdgeWidths,
+        ElementType, LayoutNode, Rect, Style, TextDecoration, TextShadow,
+    };
+
+    let rect = || {
+        (-1e4f32..1e4, -1e4f32..1e4, 0f32..1e4, 0f32..1e4).prop_map(|(x, y, width, height)| Rect {
+            x,
+            y,
+            width,
+            height,
+        })
+    };
+    let radii = || {
+        (0f32..50.0, 0f32..50.0, 0f32..50.0, 0f32..50.0).prop_map(|(a, b, c, d)| CornerRadii {
+            top_left: a,
+            top_right: b,
+            bottom_right: c,
+            bottom_left: d,
+        })
+    };
+    let edges = || {
+        (0f32..20.0, 0f32..20.0, 0f32..20.0, 0f32..20.0).prop_map(|(top, right, bottom, left)| {
+            EdgeWidths {
+                top,
+                right,
+                bottom,
+                left,
+            }
+        })
+    };
+    let color = proptest::option::of(proptest::sample::select(vec![
+        "red".to_string(),
+        "#00ff00".to_string(),
+        "rgba(0, 0, 255, 0.5)".to_string(),
+    ]));
+    let element_type = proptest::sample::select(vec![
+        ElementType::Block,
+        ElementType::Inline,
+        ElementType::Image,
+        ElementType::Text,
+    ]);
+    let decoration = (
+        0u8..8,
+        proptest::sample::select(vec![
+            DecorationStyle::Solid,
+            DecorationStyle::Double,
+            DecorationStyle::Dotted,
+            DecorationStyle::Dashed,
+            DecorationStyle::Wavy,
+        ]),
+        color.clone(),
+        proptest::option::of(0f32..8.0),
+    )
+        .prop_map(|(lines, style, color, thickness)| TextDecoration {
+            lines: DecorationLines {
+                underline: lines & 1 != 0,
+                overline: lines & 2 != 0,
+                line_through: lines & 4 != 0,
+            },
+            style,
+            color,
+            thickness,
+        });
+    let shadows = proptest::collection::vec(
+        (-20f32..20.0, -20f32..20.0, 0f32..10.0, color.clone()).prop_map(
+            |(offset_x, offset_y, blur_radius, color)| TextShadow {
+                offset_x,
+                offset_y,
+                blur_radius,
+                color,
+            },
+        ),
+        0..3,
+    );
+    let style = (
+        color.clone(),
+        proptest::option::of(proptest::sample::select(vec![
+            "checkerboard".to_string(),
+            "waves".to_string(),
+        ])),
+        color.clone(),
+        proptest::option::of(proptest::sample::select(vec![
+            "Arial".to_string(),
+            "Noto Sans CJK".to_string(),
+        ])),
+        1f32..96.0,
+        proptest::option::of(proptest::sample::select(vec![
+            "en".to_string(),
+            "ja-JP".to_string(),
+        ])),
+        decoration,
+        shadows,
+        (radii(), edges(), color.clone(), edges()),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(
+                background_color,
+                background_paint,
+                color,
+                font_family,
+                font_size,
+                language,
+                text_decoration,
+                text_shadows,
+                (border_radius, border_width, border_color, padding),
+                clips_overflow,
+            )| Style {
+                background_color,
+                background_paint,
+                color,
+                font_family,
+                font_size,
+                font: Default::default(),
+                font_metrics: vulkan_browser_engine::core::fonts::FontMetrics::fallback(font_size),
+                language,
+                text_decoration,
+                text_shadows,
+                border_radius,
+                border_width,
+                border_color,
+                padding,
+                clips_overflow,
+            },
+        );
+    let clip = proptest::collection::vec((rect(), radii()), 0..5).prop_map(|clips| {
+        clips
+            .into_iter()
+            .fold(ClipChain::new(), |chain, (rect, radii)| {
+                chain.push(ClipRect::new(rect, radii))
+            })
+    });
+    (
+        0u64..24,
+        rect(),
+        element_type,
+        style,
+        proptest::option::of("\\PC{0,12}"),
+        proptest::option::of(("https://example\\.com/[a-z]{1,6}\\.gif", 0u32..4)),
+        clip,
+    )
+        .prop_map(
+            |(id, bounds, element_type, style, text_content, image, clip)| LayoutNode {
+                node_id: NodeId(id),
+                bounds,
+                element_type,
+                style,
+                text_content,
+                image_frame: image.as_ref().map_or(0, |&(_, frame)| frame),
+                image_url: image.map(|(url, _)| url),
+                clip,
+            },
+        )
+}
+
+fn layout_tree_of(
+    nodes: &[vulkan_browser_engine::renderer::LayoutNode],
+) -> vulkan_browser_engine::renderer::LayoutTree {
+    let mut tree = vulkan_browser_engine::renderer::LayoutTree::new();
+    for node in nodes {
+        tree.add_node(node.clone());
+    }
+    tree
+}
+
+proptest::proptest! {
+    #[test]
+    fn test_layout_frames_round_trip(
+        first in proptest::collection::vec(layout_node_strategy(), 0..24),
+        edits in proptest::collection::vec(
+            (
+                proptest::prelude::any::<proptest::sample::Index>(),
+                proptest::option::of(layout_node_strategy()),
+            ),
+            0..4,
+        ),
+        unrelated in proptest::collection::vec(layout_node_strategy(), 0..24),
+    ) {
+        use vulkan_browser_engine::renderer::wire::{FrameDecoder, FrameEncoder};
+
+        // A few nodes replaced or removed, sent as a delta.
+        let mut second = first.clone();
+        for (index, edit) in edits {
+            if second.is_empty() {
+                break;
+            }
+            let index = index.index(second.len());
+            match edit {
+                Some(node) => second[index] = node,
+                None => {
+                    second.remove(index);
+                }
+            }
+        }
+
+        let mut encoder = FrameEncoder::new();
+        let mut decoder = FrameDecoder::new();
+        for nodes in [&first, &second, &unrelated, &first] {
+            let tree = layout_tree_of(nodes);
+            let frame = encoder.encode(&tree).unwrap();
+            let decoded = decoder.apply(&frame).unwrap();
+            proptest::prop_assert_eq!(decoded.get_render_nodes(), tree.get_render_nodes());
+            proptest::prop_assert_eq!(decoded.get_text_nodes(), tree.get_text_nodes());
+        }
+        proptest::prop_assert_eq!(decoder.seq(), Some(4));
+    }
+}
+
+fn wire_test_node(
+    id: u64,
+    x: f32,
+    text: Option<&str>,
+) -> vulkan_browser_engine::renderer::LayoutNode {
+    use vulkan_browser_engine::core::dom::NodeId;
+    use vulkan_browser_engine::renderer::{ClipChain, ElementType, LayoutNode, Rect, Style};
+
+    LayoutNode {
+        node_id: NodeId(id),
+        bounds: Rect {
+            x,
+            y: id as f32 * 20.0,
+            width: 200.0,
+            height: 18.0,
+        },
+        element_type: match text {
+            Some(_) => ElementType::Text,
+            None => ElementType::Block,
+        },
+        style: Style {
+            background_color: Some(
+                ["#ffffff", "#eeeeee", "rebeccapurple"][id as usize % 3].to_string(),
+            ),
+            ..Default::default()
+        },
+        text_content: text.map(str::to_string),
+        image_url: None,
+        image_frame: 0,
+        clip: ClipChain::new(),
+    }
+}
+
+#[test]
+fn test_layout_delta_carries_only_changed_nodes() {
+    use vulkan_browser_engine::renderer::wire::{
+        read_header, FrameDecoder, FrameEncoder, FrameKind,
+    };
+
+    let nodes: Vec<_> = (0..100)
+        .map(|id| wire_test_node(id, 0.0, (id % 2 == 0).then_some("hello")))
+        .collect();
+    let mut encoder = FrameEncoder::new();
+    let mut decoder = FrameDecoder::new();
+    let keyframe = encoder.encode(&layout_tree_of(&nodes)).unwrap();
+    assert_eq!(read_header(&keyframe).unwrap().kind, FrameKind::Keyframe);
+    decoder.apply(&keyframe).unwrap();
+
+    // Two nodes move: a delta a fraction of the keyframe's size.
+    let mut moved = nodes.clone();
+    moved[3].bounds.x = 40.0;
+    moved[8].bounds.x = 40.0;
+    let delta = encoder.encode(&layout_tree_of(&moved)).unwrap();
+    let header = read_header(&delta).unwrap();
+    assert_eq!(
+        (header.kind, header.seq, header.base),
+        (FrameKind::Delta, 2, 1)
+    );
+    assert!(
+        delta.len() * 20 < keyframe.len(),
+        "{} vs {}",
+        delta.len(),
+        keyframe.len()
+    );
+    let tree = decoder.apply(&delta).unwrap();
+    assert_eq!(tree.get_render_nodes()[1].bounds.x, 40.0);
+    assert_eq!(tree.get_text_nodes()[4].bounds.x, 40.0);
+
+    // Removing a node sends the new order along.
+    let mut removed = moved.clone();
+    removed.remove(5);
+    let delta = encoder.encode(&layout_tree_of(&removed)).unwrap();
+    let tree = decoder.apply(&delta).unwrap();
+    assert_eq!(
+        tree.get_render_nodes(),
+        layout_tree_of(&removed).get_render_nodes()
+    );
+    assert_eq!(
+        tree.get_text_nodes(),
+        layout_tree_of(&removed).get_text_nodes()
+    );
+
+    // A delta on a frame the decoder does not have is refused, and a
+    // keyframe brings it back in step.
+    let mut fresh = FrameDecoder::new();
+    assert!(fresh.apply(&delta).is_err());
+    encoder.request_keyframe();
+    let keyframe = encoder.encode(&layout_tree_of(&removed)).unwrap();
+    assert_eq!(fresh.apply(&keyframe).unwrap().get_render_nodes().len(), 49);
+}
+
+#[test]
+fn test_corrupt_layout_frames_are_rejected_without_panicking() {
+    use vulkan_browser_engine::renderer::wire::{FrameDecoder, FrameEncoder, WireError};
+
+    let nodes: Vec<_> = (0..12)
+        .map(|id| wire_test_node(id, 0.0, (id % 3 == 0).then_some("héllo wörld")))
+        .collect();
+    let mut encoder = FrameEncoder::new();
+    let keyframe = encoder.encode(&layout_tree_of(&nodes)).unwrap();
+    let mut changed = nodes.clone();
+    changed[2].style.color = Some("teal".to_string());
+    changed.swap(4, 5);
+    let delta = encoder.encode(&layout_tree_of(&changed)).unwrap();
+
+    let mut decoder = FrameDecoder::new();
+    for len in 0..keyframe.len() {
+        assert!(
+            decoder.apply(&keyframe[..len]).is_err(),
+            "keyframe cut at {}",
+            len
+        );
+    }
+    assert_eq!(decoder.seq(), None);
+    decoder.apply(&keyframe).unwrap();
+    for len in 0..delta.len() {
+        assert!(
+            decoder.apply(&delta[..len]).is_err(),
+            "delta cut at {}",
+            len
+        );
+        assert_eq!(decoder.seq(), Some(1));
+    }
+
+    // Garbled bytes either decode to some tree or fail; never a panic.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for frame in [&keyframe, &delta] {
+        for position in 0..frame.len() {
+            for flip in [0x01, 0x80, 0xff] {
+                let mut garbled = frame.clone();
+                garbled[position] ^= flip;
+                let mut decoder = FrameDecoder::new();
+                decoder.apply(&keyframe).unwrap();
+                let _ = decoder.apply(&garbled);
+            }
+        }
+        for _ in 0..200 {
+            let mut garbled = frame.clone();
+            for _ in 0..4 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let position = state as usize % garbled.len();
+                garbled[position] = (state >> 32) as u8;
+            }
+            let _ = FrameDecoder::new().apply(&garbled);
+        }
+    }
+
+    // A string count larger than the frame is refused before allocating for it.
+    let mut huge = keyframe.clone();
+    huge[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        FrameDecoder::new().apply(&huge).err(),
+        Some(WireError::Truncated)
+    );
+    let mut trailing = keyframe.clone();
+    trailing.push(0);
+    assert_eq!(
+        FrameDecoder::new().apply(&trailing).err(),
+        Some(WireError::TrailingBytes(1))
+    );
+}
+
+#[test]
+fn test_layout_frame_encoding_speed() {
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::renderer::wire::{encode_delta, encode_keyframe};
+    use vulkan_browser_engine::renderer::PaintKey;
+
+    let nodes: Vec<_> = (0..50_000)
+        .map(|id| wire_test_node(id, 0.0, (id % 2 == 1).then_some("Lorem ipsum dolor")))
+        .collect();
+    let tree = layout_tree_of(&nodes);
+    let best = |run: &dyn Fn() -> usize| {
+        (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                assert!(run() > 0);
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    };
+
+    let full = best(&|| encode_keyframe(1, &tree).unwrap().len());
+    assert!(
+        full < Duration::from_millis(5),
+        "50k-node keyframe took {:?}",
+        full
+    );
+
+    let changed: Vec<_> = tree
+        .get_render_nodes()
+        .iter()
+        .step_by(1000)
+        .map(|node| (PaintKey::Box(node.node_id, 0), node))
+        .collect();
+    assert_eq!(changed.len(), 25);
+    let changed: Vec<_> = changed
+        .into_iter()
+        .chain(
+            tree.get_text_nodes()
+                .iter()
+                .step_by(1000)
+                .map(|node| (PaintKey::Text(node.node_id, 0), node)),
+        )
+        .collect();
+    let delta = best(&|| encode_delta(2, 1, &changed, None).unwrap().len());
+    assert!(
+        delta < Duration::from_micros(500),
+        "50-node delta took {:?}",
+        delta
+    );
+}
+
+#[tokio::test]
+async fn test_layout_frame_messages_are_capped() {
+    use vulkan_browser_engine::renderer::wire::MAX_LAYOUT_FRAME_BYTES;
+    use vulkan_browser_engine::sandbox::ipc::{IpcError, IpcManager, IpcMessage, MessageType};
+
+    let frame = IpcMessage::layout_frame(vec![0; 1024]).unwrap();
+    assert_eq!(frame.message_type, MessageType::LayoutFrame);
+    assert!(matches!(
+        IpcMessage::layout_frame(vec![0; MAX_LAYOUT_FRAME_BYTES + 1]),
+        Err(IpcError::SecurityViolation(_))
+    ));
+
+    let manager = IpcManager::new();
+    manager.send_message(1, 2, frame.clone()).await.unwrap();
+    let mut oversized = frame;
+    oversized.payload = vec![0; MAX_LAYOUT_FRAME_BYTES + 1];
+    assert!(matches!(
+        manager.send_message(1, 2, oversized).await,
+        Err(IpcError::SecurityViolation(_))
+    ));
+}
+
+/// A page of the kind paint commands are for: backgrounds, text with a
+/// shadow and an underline inside a rounded clip, and `image` when given.
+fn command_test_tree(image: Option<&str>) -> vulkan_browser_engine::renderer::LayoutTree {
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ClipRect, CornerRadii, ElementType, Rect, TextDecoration, TextShadow,
+    };
+
+    let card = ClipChain::new().push(ClipRect::new(
+        Rect {
+            x: 8.0,
+            y: 8.0,
+            width: 140.0,
+            height: 100.0,
+        },
+        CornerRadii::uniform(12.0),
+    ));
+    let mut nodes = vec![wire_test_node(0, 0.0, None), wire_test_node(1, 4.0, None)];
+    nodes[1].clip = card.clone();
+    for id in 2..5 {
+        let mut text = wire_test_node(id, 12.0, Some("héllo wörld"));
+        text.style.color = Some("#204080".to_string());
+        text.style.text_decoration = TextDecoration::underline();
+        text.style.text_shadows = vec![TextShadow {
+            offset_x: 2.0,
+            offset_y: 2.0,
+            blur_radius: 0.0,
+            color: Some("#ff8000".to_string()),
+        }];
+        text.clip = card.clone();
+        nodes.push(text);
+    }
+    if let Some(url) = image {
+        let mut node = wire_test_node(5, 20.0, None);
+        node.element_type = ElementType::Image;
+        node.image_url = Some(url.to_string());
+        nodes.push(node);
+    }
+    let mut tree = layout_tree_of(&nodes);
+    if let Some(url) = image {
+        let decoded = command_test_image(url, 0).unwrap();
+        tree.set_image(
+            url,
+            0,
+            vulkan_browser_engine::renderer::image::LoadedImage::Decoded(decoded),
+        );
+    }
+    tree
+}
+
+fn command_test_image(
+    _url: &str,
+    _frame: u32,
+) -> Option<std::sync::Arc<vulkan_browser_engine::renderer::image::DecodedImage>> {
+    Some(std::sync::Arc::new(
+        vulkan_browser_engine::renderer::image::DecodedImage {
+            width: 4,
+            height: 2,
+            data: vec![200; 32],
+        },
+    ))
+}
+
+#[tokio::test]
+async fn test_paint_commands_draw_what_the_layout_tree_does() {
+    use vulkan_browser_engine::core::dom::Document;
+    use vulkan_browser_engine::renderer::command_stream::{
+        decode_frame, CommandReceiver, CommandRecorder, PaintCommand, ResourceKind, ResourceUpdate,
+    };
+    use vulkan_browser_engine::renderer::{RenderBackend, VulkanRenderer};
+
+    let tree = command_test_tree(Some("photo.png"));
+    let mut layout = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    layout.resize(240, 140).await.unwrap();
+    layout.render(&Document::new(), &tree).await.unwrap();
+
+    let mut recorder = CommandRecorder::new();
+    let mut receiver = CommandReceiver::new();
+    let mut streamed = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    streamed.resize(240, 140).await.unwrap();
+    let first = recorder.encode(&tree, command_test_image).unwrap();
+    receiver.apply(&first).unwrap();
+    streamed.render_commands(&receiver).await.unwrap();
+    assert_eq!(streamed.snapshot().data, layout.snapshot().data);
+    assert!(receiver
+        .commands()
+        .iter()
+        .any(|command| matches!(command, PaintCommand::GlyphRun { .. })));
+
+    // The image and the atlas page go over once; the next frame names them.
+    let kinds: Vec<_> = decode_frame(&first)
+        .unwrap()
+        .updates
+        .iter()
+        .filter_map(|update| match update {
+            ResourceUpdate::Define { kind, .. } => Some(*kind),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(kinds, [ResourceKind::GlyphAtlas, ResourceKind::Image]);
+    let second = recorder.encode(&tree, command_test_image).unwrap();
+    assert!(decode_frame(&second).unwrap().updates.is_empty());
+    assert!(second.len() * 50 < first.len());
+    receiver.apply(&second).unwrap();
+    assert_eq!(receiver.resources().len(), 2);
+
+    // An image no longer drawn is released on both sides.
+    let third = recorder
+        .encode(&command_test_tree(None), command_test_image)
+        .unwrap();
+    assert!(matches!(
+        decode_frame(&third).unwrap().updates[..],
+        [ResourceUpdate::Release(_)]
+    ));
+    receiver.apply(&third).unwrap();
+    assert_eq!(receiver.resources().len(), 1);
+    streamed.render_commands(&receiver).await.unwrap();
+    layout
+        .render(&Document::new(), &command_test_tree(None))
+        .await
+        .unwrap();
+    assert_eq!(streamed.snapshot().data, layout.snapshot().data);
+}
+
+#[test]
+fn test_paint_command_frames_are_checked_before_they_take_effect() {
+    use vulkan_browser_engine::renderer::command_stream::{
+        encode_frame, CommandError, CommandFrame, CommandReceiver, GlyphQuad, PaintCommand,
+        ResourceId, ResourceKind, ResourceUpdate, Transform, MAX_STACK_DEPTH,
+    };
+    use vulkan_browser_engine::renderer::{ClipRect, CornerRadii, Rect};
+
+    let rect = |x: f32| Rect {
+        x,
+        y: 0.0,
+        width: 10.0,
+        height: 10.0,
+    };
+    let quad = |bounds: Rect, color: [f32; 4]| PaintCommand::Quad {
+        bounds,
+        color,
+        blur_radius: 0.0,
+    };
+    let clip = || PaintCommand::PushClip(ClipRect::new(rect(0.0), CornerRadii::default()));
+    let page = ResourceUpdate::Define {
+        id: ResourceId(1),
+        kind: ResourceKind::GlyphAtlas,
+        width: 16,
+        height: 16,
+        pixels: vec![0; 256],
+    };
+    let frame = |seq: u64, reset: bool, updates: Vec<ResourceUpdate>, commands| {
+        encode_frame(&CommandFrame {
+            seq,
+            reset,
+            updates,
+            commands,
+        })
+        .unwrap()
+    };
+
+    let refused = [
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                vec![PaintCommand::TexturedQuad {
+                    bounds: rect(0.0),
+                    resource: ResourceId(9),
+                    source: rect(0.0),
+                }],
+            ),
+            CommandError::UnknownResource(ResourceId(9)),
+        ),
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                vec![PaintCommand::TexturedQuad {
+                    bounds: rect(0.0),
+                    resource: ResourceId(1),
+                    source: rect(0.0),
+                }],
+            ),
+            CommandError::WrongResourceKind(ResourceId(1), "an image"),
+        ),
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                vec![PaintCommand::GlyphRun {
+                    page: ResourceId(1),
+                    color: [0.0, 0.0, 0.0, 1.0],
+                    glyphs: vec![GlyphQuad {
+                        bounds: rect(0.0),
+                        source: rect(8.0),
+                    }],
+                }],
+            ),
+            CommandError::OutOfBounds("source rect"),
+        ),
+        (
+            frame(2, false, vec![page.clone()], vec![]),
+            CommandError::DuplicateResource(ResourceId(1)),
+        ),
+        (
+            frame(
+                2,
+                false,
+                vec![ResourceUpdate::Patch {
+                    id: ResourceId(1),
+                    x: 12,
+                    y: 12,
+                    width: 8,
+                    height: 8,
+                    pixels: vec![255; 64],
+                }],
+                vec![],
+            ),
+            CommandError::OutOfBounds("resource patch"),
+        ),
+        (
+            frame(2, false, vec![], vec![PaintCommand::PopClip]),
+            CommandError::Unbalanced("clip"),
+        ),
+        (
+            frame(2, false, vec![], vec![clip(), quad(rect(0.0), [1.0; 4])]),
+            CommandError::Unbalanced("clip"),
+        ),
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                vec![PaintCommand::PushTransform(Transform::scale(2.0))],
+            ),
+            CommandError::Unbalanced("transform"),
+        ),
+        (
+            frame(2, false, vec![], vec![PaintCommand::PopTransform]),
+            CommandError::Unbalanced("transform"),
+        ),
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                (0..=MAX_STACK_DEPTH).map(|_| clip()).collect(),
+            ),
+            CommandError::TooDeep("clip"),
+        ),
+        (
+            frame(2, false, vec![], vec![quad(rect(f32::NAN), [1.0; 4])]),
+            CommandError::OutOfBounds("quad bounds"),
+        ),
+        // In bounds as sent, far out once transformed.
+        (
+            frame(
+                2,
+                false,
+                vec![],
+                vec![
+                    PaintCommand::PushTransform(Transform::scale(900.0)),
+                    quad(rect(5000.0), [1.0; 4]),
+                    PaintCommand::PopTransform,
+                ],
+            ),
+            CommandError::OutOfBounds("quad bounds"),
+        ),
+        (
+            frame(2, false, vec![], vec![quad(rect(0.0), [2.0; 4])]),
+            CommandError::OutOfBounds("color"),
+        ),
+        (
+            frame(3, false, vec![], vec![]),
+            CommandError::OutOfOrder {
+                seq: 3,
+                last: Some(1),
+            },
+        ),
+    ];
+    let drawn = vec![quad(rect(0.0), [1.0; 4])];
+    for (bytes, error) in refused {
+        let mut receiver = CommandReceiver::new();
+        receiver
+            .apply(&frame(1, true, vec![page.clone()], drawn.clone()))
+            .unwrap();
+        assert_eq!(receiver.apply(&bytes).err(), Some(error));
+        // The last frame taken stands, resources and all.
+        assert_eq!(receiver.seq(), Some(1));
+        assert_eq!(receiver.commands(), &drawn[..]);
+        assert_eq!(receiver.resources().len(), 1);
+    }
+
+    // A reset drops what was defined before it.
+    let mut receiver = CommandReceiver::new();
+    receiver
+        .apply(&frame(1, true, vec![page.clone()], vec![]))
+        .unwrap();
+    receiver.apply(&frame(7, true, vec![], drawn)).unwrap();
+    assert!(receiver.resources().is_empty());
+}
+
+#[test]
+fn test_corrupt_paint_command_frames_are_rejected_without_panicking() {
+    use vulkan_browser_engine::renderer::command_stream::{
+        CommandError, CommandReceiver, CommandRecorder,
+    };
+
+    let mut recorder = CommandRecorder::new();
+    let first = recorder
+        .encode(&command_test_tree(Some("photo.png")), command_test_image)
+        .unwrap();
+    let mut tree = command_test_tree(None);
+    tree.add_node(wire_test_node(9, 30.0, Some("new glyphs: ß∑")));
+    let second = recorder.encode(&tree, command_test_image).unwrap();
+
+    let mut receiver = CommandReceiver::new();
+    // Most of the first frame is pixels; cutting into them fails the same.
+    for len in (0..first.len()).filter(|len| len % 97 == 0 || *len < 64) {
+        assert!(
+            receiver.apply(&first[..len]).is_err(),
+            "first frame cut at {}",
+            len
+        );
+    }
+    assert_eq!(receiver.seq(), None);
+    receiver.apply(&first).unwrap();
+    for len in 0..second.len() {
+        assert!(
+            receiver.apply(&second[..len]).is_err(),
+            "second frame cut at {}",
+            len
+        );
+        assert_eq!(receiver.seq(), Some(1));
+    }
+
+    // Garbled bytes either pass every check or fail one; never a panic. A
+    // refused frame leaves the receiver as it was, ready for the next.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for position in 0..second.len() {
+        for flip in [0x01, 0x80, 0xff] {
+            let mut garbled = second.clone();
+            garbled[position] ^= flip;
+            if receiver.apply(&garbled).is_ok() {
+                receiver = CommandReceiver::new();
+                receiver.apply(&first).unwrap();
+            }
+        }
+    }
+    for frame in [&first, &second] {
+        for _ in 0..200 {
+            let mut garbled = frame.clone();
+            for _ in 0..4 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                // Aim at the commands at the end as often as anywhere.
+                let span = if state & 1 == 0 { garbled.len() } else { 512 };
+                let position = garbled.len() - 1 - (state >> 8) as usize % span.min(garbled.len());
+                garbled[position] = (state >> 32) as u8;
+            }
+            let _ = CommandReceiver::new().apply(&garbled);
+        }
+    }
+
+    // An update count larger than the frame is refused before allocating for it.
+    let mut huge = first.clone();
+    huge[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        CommandReceiver::new().apply(&huge).err(),
+        Some(CommandError::Truncated)
+    );
+    let mut trailing = first.clone();
+    trailing.push(0);
+    assert_eq!(
+        CommandReceiver::new().apply(&trailing).err(),
+        Some(CommandError::TrailingBytes(1))
+    );
+}
+
+#[tokio::test]
+async fn test_paint_command_messages_are_capped() {
+    use vulkan_browser_engine::renderer::command_stream::MAX_COMMAND_FRAME_BYTES;
+    use vulkan_browser_engine::sandbox::ipc::{IpcError, IpcManager, IpcMessage, MessageType};
+
+    let frame = IpcMessage::paint_commands(vec![0; 1024]).unwrap();
+    assert_eq!(frame.message_type, MessageType::PaintCommands);
+    assert!(matches!(
+        IpcMessage::paint_commands(vec![0; MAX_COMMAND_FRAME_BYTES + 1]),
+        Err(IpcError::SecurityViolation(_))
+    ));
+
+    let manager = IpcManager::new();
+    manager.send_message(1, 2, frame.clone()).await.unwrap();
+    let mut oversized = frame;
+    oversized.payload = vec![0; MAX_COMMAND_FRAME_BYTES + 1];
+    assert!(matches!(
+        manager.send_message(1, 2, oversized).await,
+        Err(IpcError::SecurityViolation(_))
+    ));
+}
+
+/// A 4x4 GIF of a red, a green and a blue frame shown for `delays_ms`,
+/// looping forever.
+fn three_frame_gif(delays_ms: [u32; 3]) -> Vec<u8> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        for (color, delay) in colors.into_iter().zip(delays_ms) {
+            let frame = RgbaImage::from_pixel(4, 4, Rgba(color));
+            encoder
+                .encode_frame(Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                ))
+                .unwrap();
+        }
+    }
+    gif
+}
+
+#[test]
+fn test_animated_gif_decodes_every_frame_with_clamped_delays() {
+    use std::time::Duration;
+    use vulkan_browser_engine::renderer::image::{AnimatedImage, Repeat};
+
+    let image = AnimatedImage::decode(&three_frame_gif([50, 0, 200]))
+        .unwrap()
+        .expect("three frames are an animation");
+    assert_eq!(image.frames.len(), 3);
+    assert_eq!(image.frames[1].data[..4], [0, 255, 0, 255]);
+    // A delay of 0 plays at 100ms, as in other browsers.
+    assert_eq!(
+        image.delays,
+        [50, 100, 200].map(Duration::from_millis).to_vec()
+    );
+    assert_eq!(image.repeat, Repeat::Forever);
+}
+
+#[test]
+fn test_animated_gif_advances_on_schedule_and_pauses_out_of_view() {
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::renderer::image::ImageAnimations;
+
+    let url = "https://example.com/spinner.gif";
+    let animations = ImageAnimations::default();
+    assert!(animations
+        .insert(url, &three_frame_gif([50, 100, 200]))
+        .unwrap());
+    let in_view: HashSet<String> = [url.to_string()].into();
+    let out_of_view = HashSet::new();
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+
+    assert!(animations.advance(at(0), &in_view).is_empty());
+    assert!(animations.advance(at(49), &in_view).is_empty());
+    assert_eq!(animations.advance(at(50), &in_view), [url]);
+    assert_eq!(animations.frame_index(url), Some(1));
+    assert_eq!(
+        animations.current_frame(url).unwrap().data[..4],
+        [0, 255, 0, 255]
+    );
+    assert_eq!(animations.advance(at(150), &in_view), [url]);
+    assert_eq!(animations.frame_index(url), Some(2));
+
+    // Scrolled away for a long while: nothing moves, and the time out of
+    // view does not count once it is back.
+    assert!(animations.advance(at(200), &out_of_view).is_empty());
+    assert!(animations.advance(at(5_000), &out_of_view).is_empty());
+    assert_eq!(animations.frame_index(url), Some(2));
+    assert!(animations.advance(at(6_000), &in_view).is_empty());
+    assert!(animations.advance(at(6_199), &in_view).is_empty());
+    assert_eq!(animations.advance(at(6_200), &in_view), [url]);
+    assert_eq!(animations.frame_index(url), Some(0));
+}
+
+#[test]
+fn test_reduced_motion_holds_animations_on_their_first_frame() {
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use vulkan_browser_engine::renderer::image::ImageAnimations;
+
+    let url = "https://example.com/spinner.gif";
+    let animations = ImageAnimations::default();
+    animations
+        .insert(url, &three_frame_gif([50, 50, 50]))
+        .unwrap();
+    let in_view: HashSet<String> = [url.to_string()].into();
+    let start = Instant::now();
+    animations.advance(start, &in_view);
+    animations.advance(start + Duration::from_millis(60), &in_view);
+    assert_eq!(animations.frame_index(url), Some(1));
+
+    animations.set_reduced_motion(true);
+    assert_eq!(animations.frame_index(url), Some(0));
+    assert!(animations
+        .advance(start + Duration::from_millis(1_000), &in_view)
+        .is_empty());
+    assert_eq!(animations.frame_index(url), Some(0));
+}
+
+#[test]
+fn test_frames_of_animations_out_of_view_are_evicted_past_the_budget() {
+    use std::collections::HashSet;
+    use std::time::Instant;
+    use vulkan_browser_engine::renderer::image::ImageAnimations;
+
+    // Three 4x4 RGBA frames are 192 bytes; the budget holds one animation.
+    let animations = ImageAnimations::new(192);
+    let gif = three_frame_gif([50, 50, 50]);
+    animations
+        .insert("https://example.com/a.gif", &gif)
+        .unwrap();
+    animations
+        .insert("https://example.com/b.gif", &gif)
+        .unwrap();
+    let only_a: HashSet<String> = ["https://example.com/a.gif".to_string()].into();
+    animations.advance(Instant::now(), &only_a);
+    assert_eq!(animations.decoded_bytes(), 192);
+    assert!(animations
+        .current_frame("https://example.com/a.gif")
+        .is_some());
+    assert!(animations
+        .current_frame("https://example.com/b.gif")
+        .is_none());
+
+    // Back in view, it is decoded again from the fetched bytes.
+    let only_b: HashSet<String> = ["https://example.com/b.gif".to_string()].into();
+    animations.advance(Instant::now(), &only_b);
+    assert!(animations
+        .current_frame("https://example.com/b.gif")
+        .is_some());
+    assert!(animations
+        .current_frame("https://example.com/a.gif")
+        .is_none());
+
+    // A still image is not kept at all.
+    let mut still = Vec::new();
+    image::RgbaImage::new(4, 4)
+        .write_to(
+            &mut std::io::Cursor::new(&mut still),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    assert!(!animations
+        .insert("https://example.com/still.png", &still)
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_frame_change_repaints_only_the_image() {
+    use vulkan_browser_engine::core::dom::{Document, NodeId};
+    use vulkan_browser_engine::renderer::{
+        ClipChain, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend, Style, VulkanRenderer,
+    };
+
+    let image = NodeId::new();
+    let text = NodeId::new();
+    let tree = |frame: u32| {
+        let mut tree = LayoutTree::new();
+        tree.add_node(LayoutNode {
+            node_id: image,
+            bounds: Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 4.0,
+                height: 4.0,
+            },
+            element_type: ElementType::Image,
+            style: Style::default(),
+            text_content: None,
+            image_url: Some("https://example.com/spinner.gif".to_string()),
+            image_frame: frame,
+            clip: ClipChain::new(),
+        });
+        tree.add_node(LayoutNode {
+            node_id: text,
+            bounds: Rect {
+                x: 0.0,
+                y: 10.0,
+                width: 40.0,
+                height: 16.0,
+            },
+            element_type: ElementType::Text,
+            style: Style::default(),
+            text_content: Some("Loading".to_string()),
+            image_url: None,
+            image_frame: 0,
+            clip: ClipChain::new(),
+        });
+        tree
+    };
+
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    let document = Document::new();
+    renderer.render(&document, &tree(0)).await.unwrap();
+    renderer.render(&document, &tree(1)).await.unwrap();
+    assert_eq!(renderer.get_frame_stats().nodes_patched(), 1);
+}
+
+#[test]
+fn test_gradients_interpolate_in_linear_light_or_oklab() {
+    use vulkan_browser_engine::renderer::custom_paint::{
+        ColorInterpolation, GradientAxis, PaintCommand, PaintOutput,
+    };
+    use vulkan_browser_engine::renderer::Rect;
+
+    const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+    const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    let close = |a: [f32; 4], b: [f32; 4]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3);
+
+    let linear = ColorInterpolation::LinearSrgb;
+    assert!(close(linear.mix(BLACK, WHITE, 0.0), BLACK));
+    assert!(close(linear.mix(BLACK, WHITE, 1.0), WHITE));
+    // Half the light, encoded: 188 of 255.
+    assert!(close(
+        linear.mix(BLACK, WHITE, 0.5),
+        [0.7354, 0.7354, 0.7354, 1.0]
+    ));
+    // Oklab's midpoint is half the lightness instead.
+    let oklab = ColorInterpolation::Oklab;
+    assert!(close(
+        oklab.mix(BLACK, WHITE, 0.5),
+        [0.3886, 0.3886, 0.3886, 1.0]
+    ));
+
+    let bounds = Rect {
+        x: 0.0,
+        y: 0.0,
+        width: 4.0,
+        height: 1.0,
+    };
+    let output = PaintOutput::Commands(vec![PaintCommand::LinearGradient {
+        bounds: bounds.clone(),
+        from: BLACK,
+        to: WHITE,
+        axis: GradientAxis::Horizontal,
+        interpolation: ColorInterpolation::default(),
+    }]);
+    let bands: Vec<[f32; 4]> = output.quads(&bounds).into_iter().map(|(_, c)| c).collect();
+    assert_eq!(bands.len(), 4);
+    for (band, t) in bands.iter().zip([0.125, 0.375, 0.625, 0.875]) {
+        assert!(close(*band, linear.mix(BLACK, WHITE, t)), "{band:?} at {t}");
+    }
+}
+
+#[tokio::test]
+async fn test_repaint_flashing_tints_the_damage_and_fades() {
+    use vulkan_browser_engine::core::dom::{Document, NodeId};
+    use vulkan_browser_engine::renderer::debug_overlay::{FLASH_COLOR, FLASH_FRAMES};
+    use vulkan_browser_engine::renderer::{
+        ClipChain, DebugOverlayFlags, ElementType, LayoutNode, LayoutTree, Rect, RenderBackend,
+        Style, VulkanRenderer,
+    };
+
+    let ids: Vec<NodeId> = (0..3).map(|_| NodeId::new()).collect();
+    let tree = |changed_color: &str| {
+        let mut tree = LayoutTree::new();
+        for (index, &node_id) in ids.iter().enumerate() {
+            let color = if index == 1 { changed_color } else { "red" };
+            tree.add_node(LayoutNode {
+                node_id,
+                bounds: Rect {
+                    x: 0.0,
+                    y: index as f32 * 20.0,
+                    width: 50.0,
+                    height: 10.0,
+                },
+                element_type: ElementType::Block,
+                style: Style {
+                    background_color: Some(color.to_string()),
+                    ..Default::default()
+                },
+                text_content: None,
+                image_url: None,
+                image_frame: 0,
+                clip: ClipChain::new(),
+            });
+        }
+        tree
+    };
+    assert_eq!(
+        DebugOverlayFlags::parse("repaint-flashing, frame-hud"),
+        Ok(DebugOverlayFlags::REPAINT_FLASHING | DebugOverlayFlags::FRAME_HUD)
+    );
+    assert!(DebugOverlayFlags::parse("sparkles").is_err());
+
+    let mut renderer = VulkanRenderer::with_backend(RenderBackend::Software)
+        .await
+        .unwrap();
+    let document = Document::new();
+    renderer.render(&document, &tree("red")).await.unwrap();
+    renderer.set_debug_overlays(DebugOverlayFlags::REPAINT_FLASHING);
+
+    // Only the element that changed repaints, and only it flashes.
+    renderer.render(&document, &tree("blue")).await.unwrap();
+    let damage = renderer.damage().cloned().unwrap();
+    assert_eq!(
+        damage,
+        Rect {
+            x: 0.0,
+            y: 20.0,
+            width: 50.0,
+            height: 10.0,
+        }
+    );
+    let flashes = renderer.debug_overlay_quads().to_vec();
+    assert_eq!(flashes.len(), 1);
+    assert_eq!(flashes[0].bounds, damage);
+    assert_eq!(flashes[0].color, FLASH_COLOR);
+    // Snapshots leave debug overlays out unless asked.
+    let without = renderer.snapshot().pixel(25, 25);
+    renderer
+        .set_debug_overlays(DebugOverlayFlags::REPAINT_FLASHING | DebugOverlayFlags::IN_SNAPSHOTS);
+    assert_ne!(renderer.snapshot().pixel(25, 25), without);
+
+    // The flash fades over the next frames, which repaint nothing.
+    for frame in 1..FLASH_FRAMES {
+        renderer.render(&document, &tree("blue")).await.unwrap();
+        assert_eq!(renderer.damage(), None);
+        let flashes = renderer.debug_overlay_quads();
+        assert_eq!(flashes.len(), 1);
+        assert!(flashes[0].color[3] < FLASH_COLOR[3], "frame {}", frame);
+    }
+    renderer.render(&document, &tree("blue")).await.unwrap();
+    assert!(renderer.debug_overlay_quads().is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+#[ignore = "needs a GPU and a display"]
+async fn test_rapid_resizes_rebuild_the_swapchain() {
+    use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+    use winit::dpi::PhysicalSize;
+    use winit::event_loop::EventLoopBuilder;
+    use winit::platform::x11::EventLoopBuilderExtX11;
+    use winit::window::WindowBuilder;
+
+    // Test threads are not the main thread.
+    let event_loop = EventLoopBuilder::new()
+        .with_any_thread(true)
+        .build()
+        .unwrap();
+    let window = WindowBuilder::new()
+        .with_inner_size(PhysicalSize::new(640, 480))
+        .build(&event_loop)
+        .unwrap();
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p>resizing</p>")
+        .await
+        .unwrap();
+    let display = window.display_handle().unwrap().as_raw();
+    let handle = window.window_handle().unwrap().as_raw();
+    unsafe { engine.attach_window(display, handle) }
+        .await
+        .unwrap();
+
+    for i in 0..100u32 {
+        let size = PhysicalSize::new(200 + i * 37 % 900, 150 + i * 53 % 700);
+        let _ = window.request_inner_size(size);
+        engine
+            .resize_viewport(size.width, size.height)
+            .await
+            .unwrap();
+        engine.render_frame().await.unwrap();
+    }
+    engine.shutdown().await.unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+#[ignore = "needs a GPU, a display and the validation layers"]
+async fn test_presenting_frames_raises_no_validation_errors() {
+    use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+    use vulkan_browser_engine::renderer::vulkan::validation_errors;
+    use vulkan_browser_engine::{BrowserConfig, BrowserEngine};
+    use winit::event_loop::EventLoopBuilder;
+    use winit::platform::x11::EventLoopBuilderExtX11;
+    use winit::window::WindowBuilder;
+
+    let event_loop = EventLoopBuilder::new()
+        .with_any_thread(true)
+        .build()
+        .unwrap();
+    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let engine = BrowserEngine::new(BrowserConfig::default()).await.unwrap();
+    engine
+        .load_url("data:text/html,<p>frame</p>")
+        .await
+        .unwrap();
+    let display = window.display_handle().unwrap().as_raw();
+    let handle = window.window_handle().unwrap().as_raw();
+    unsafe { engine.attach_window(display, handle) }
+        .await
+        .unwrap();
+
+    let errors = validation_errors();
+    for frame in 0..10 {
+        // A new frame each time, so each is presented.
+        engine
+            .execute_javascript(&format!("document.body.textContent = 'frame {}'", frame))
+            .await
+            .unwrap();
+        engine.tick().await.unwrap();
+        engine.render_frame().await.unwrap();
+    }
+    assert_eq!(validation_errors(), errors);
+    let metrics = engine.get_performance_metrics().await;
+    assert!(metrics.renderer.frame_rate > 0.0);
+    engine.shutdown().await.unwrap();
+}
+
+#[test]
+fn test_glyph_atlas_grows_keeping_its_cells() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::renderer::glyphs::{GlyphAtlas, GlyphKey, GlyphMask, PlacedGlyph};
+    use vulkan_browser_engine::renderer::Rect;
+
+    let glyph = |glyph: u16| PlacedGlyph {
+        key: GlyphKey::Outline {
+            face: Default::default(),
+            glyph,
+            size: 64,
+        },
+        bounds: Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 63.0,
+            height: 63.0,
+        },
+        mask: Arc::new(GlyphMask {
+            width: 63,
+            height: 63,
+            coverage: vec![glyph as u8; 63 * 63],
+        }),
+    };
+    let mut atlas = GlyphAtlas::new();
+    let first = atlas.cell(&glyph(1)).unwrap();
+    let (width, height) = atlas.size();
+    // A glyph met again keeps its cell.
+    assert_eq!(atlas.cell(&glyph(1)), Some(first.clone()));
+
+    let mut next = 2;
+    while atlas.generation() == 0 {
+        atlas.cell(&glyph(next)).unwrap();
+        next += 1;
+    }
+    // Growing adds rows; what was packed stays where it was.
+    assert_eq!(atlas.size(), (width, height * 2));
+    assert_eq!(atlas.cell(&glyph(1)), Some(first.clone()));
+    let pixel = (first.y as u32 * width + first.x as u32) as usize;
+    assert_eq!(atlas.pixels()[pixel], 1);
+}
+
+#[test]
+fn test_saved_pipeline_caches_load_only_on_the_device_that_wrote_them() {
+    use vulkan_browser_engine::renderer::vulkan::pipeline_cache::{self, CacheIdentity};
+
+    let device = CacheIdentity {
+        vendor_id: 0x10de,
+        device_id: 0x2684,
+        cache_uuid: [7; 16],
+    };
+    let mut blob = device.header();
+    blob.extend_from_slice(&[0xab; 64]);
+    assert!(device.accepts(&blob));
+
+    // Another vendor, device or driver build wrote it.
+    for other in [
+        CacheIdentity {
+            vendor_id: 0x1002,
+            ..device
+        },
+        CacheIdentity {
+            device_id: 0x2685,
+            ..device
+        },
+        CacheIdentity {
+            cache_uuid: [8; 16],
+            ..device
+        },
+    ] {
+        assert!(!other.accepts(&blob));
+    }
+    // Cut short, or a header that is not version one or overruns the blob.
+    assert!(!device.accepts(&blob[..31]));
+    assert!(!device.accepts(&[]));
+    let mut version_two = blob.clone();
+    version_two[4] = 2;
+    assert!(!device.accepts(&version_two));
+    let mut overlong = blob.clone();
+    overlong[..4].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(!device.accepts(&overlong));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shaders").join("pipelines.bin");
+    assert_eq!(pipeline_cache::load(&path, &device), None);
+    pipeline_cache::save(&path, &blob).unwrap();
+    assert_eq!(pipeline_cache::load(&path, &device), Some(blob.clone()));
+    std::fs::write(
+        &path,
+        b"not a pipeline cache, but long enough to have a header",
+    )
+    .unwrap();
+    assert_eq!(pipeline_cache::load(&path, &device), None);
+    // Saving leaves no temporary file behind.
+    pipeline_cache::save(&path, &blob).unwrap();
+    assert_eq!(
+        std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+        1
+    );
+}
+
+#[test]
+fn test_gpu_memory_tracker_accounts_for_every_allocation_and_its_peak() {
+    use vulkan_browser_engine::renderer::vulkan::memory::{GpuMemoryReport, MemoryTracker};
+
+    let tracker = MemoryTracker::new();
+    tracker.allocate(1, 8 << 20);
+    tracker.allocate(2, 4 << 20);
+    assert_eq!(tracker.current_usage(), 12 << 20);
+    tracker.deallocate(1);
+    tracker.allocate(3, 2 << 20);
+    assert_eq!(tracker.current_usage(), 6 << 20);
+    assert_eq!(tracker.peak_usage(), 12 << 20);
+    assert_eq!(
+        tracker.report(),
+        GpuMemoryReport {
+            blocks: 2,
+            reserved_bytes: 6 << 20,
+            peak_bytes: 12 << 20,
+        }
+    );
+
+    // Freeing twice, or what was never allocated, changes nothing.
+    tracker.deallocate(1);
+    tracker.deallocate(99);
+    assert_eq!(tracker.current_usage(), 6 << 20);
+    tracker.deallocate(2);
+    tracker.deallocate(3);
+    assert_eq!(tracker.report().blocks, 0);
+    assert_eq!(tracker.current_usage(), 0);
+    assert_eq!(tracker.peak_usage(), 12 << 20);
+}
+
+#[test]
+fn test_quad_batches_share_draws_until_the_clip_or_pipeline_changes() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::renderer::glyphs::{GlyphAtlas, GlyphMask};
+    use vulkan_browser_engine::renderer::vulkan::batch::{
+        QuadBatch, GLYPH_ATLAS_TEXTURE, GLYPH_PIPELINE, SOLID_PIPELINE,
+    };
+    use vulkan_browser_engine::renderer::{ClipChain, ClipRect, CornerRadii, DrawQuad, Rect};
+
+    let rect = |x: f32| Rect {
+        x,
+        y: 0.0,
+        width: 50.0,
+        height: 50.0,
+    };
+    let quad = |x: f32, clip: &ClipChain| DrawQuad {
+        bounds: rect(x),
+        color: [1.0, 0.0, 0.0, 1.0],
+        clip: clip.clone(),
+        blur_radius: 0.0,
+        mask: None,
+        image: None,
+    };
+    let card = ClipChain::new().push(ClipRect::new(rect(100.0), CornerRadii::uniform(8.0)));
+    let mask = Arc::new(GlyphMask {
+        width: 5,
+        height: 7,
+        coverage: vec![255; 35],
+    });
+    let glyph = |x: f32| DrawQuad {
+        mask: Some(mask.clone()),
+        ..quad(x, &card)
+    };
+    let quads = [
+        quad(0.0, &ClipChain::new()),
+        quad(50.0, &ClipChain::new()),
+        quad(100.0, &card),
+        // Clipped away entirely.
+        quad(0.0, &card),
+        glyph(100.0),
+        glyph(110.0),
+    ];
+
+    let mut atlas = GlyphAtlas::default();
+    let batch = QuadBatch::build(&quads, 200, 100, &mut atlas).unwrap();
+    assert_eq!(batch.vertices.len(), 20);
+    assert_eq!(batch.indices.len(), 30);
+    assert_eq!(batch.draws.len(), 3);
+    assert_eq!(batch.draws[0].pipeline_id, SOLID_PIPELINE);
+    assert_eq!(
+        (batch.draws[0].first_index, batch.draws[0].index_count),
+        (0, 12)
+    );
+    assert_eq!(
+        (batch.draws[1].first_index, batch.draws[1].index_count),
+        (12, 6)
+    );
+    assert_eq!(batch.draws[1].clip, card);
+    // Framebuffer pixels map to clip space, y down.
+    assert_eq!(batch.vertices[0].position, [-1.0, -1.0]);
+    assert_eq!(batch.vertices[6].position, [0.0, 0.0]);
+    assert_eq!(batch.vertices[4].color, [1.0, 0.0, 0.0, 1.0]);
+
+    // Both glyphs sample the one cell their mask was packed into, in
+    // atlas pixels.
+    assert_eq!(batch.draws[2].pipeline_id, GLYPH_PIPELINE);
+    assert_eq!(batch.draws[2].texture, Some(GLYPH_ATLAS_TEXTURE));
+    assert_eq!(
+        (batch.draws[2].first_index, batch.draws[2].index_count),
+        (18, 12)
+    );
+    assert_eq!(atlas.cell_count(), 1);
+    assert_eq!(batch.vertices[12].tex_coord, [0.0, 0.0]);
+    assert_eq!(batch.vertices[14].tex_coord, [5.0, 7.0]);
+    assert_eq!(batch.vertices[18].tex_coord, [5.0, 7.0]);
+}
+
+#[test]
+fn test_quad_batches_sample_each_image_from_one_texture() {
+    use std::sync::Arc;
+    use vulkan_browser_engine::renderer::glyphs::GlyphAtlas;
+    use vulkan_browser_engine::renderer::image::DecodedImage;
+    use vulkan_browser_engine::renderer::vulkan::batch::{
+        QuadBatch, IMAGE_PIPELINE, MAX_IMAGE_SIZE, SOLID_PIPELINE,
+    };
+    use vulkan_browser_engine::renderer::{ClipChain, DrawQuad, Rect};
+
+    let image = |width: u32, height: u32| {
+        Arc::new(DecodedImage {
+            width,
+            height,
+            data: vec![255; (width * height * 4) as usize],
+        })
+    };
+    let quad = |x: f32, image: &Arc<DecodedImage>, blur_radius: f32| DrawQuad {
+        bounds: Rect {
+            x,
+            y: 0.0,
+            width: 20.0,
+            height: 10.0,
+        },
+        color: [1.0, 1.0, 1.0, 1.0],
+        clip: ClipChain::new(),
+        blur_radius,
+        mask: None,
+        image: Some(image.clone()),
+    };
+    let photo = image(4, 2);
+    let quads = [
+        quad(0.0, &photo, 0.0),
+        quad(20.0, &photo, 0.0),
+        // Blurred images are solid, as on the CPU.
+        quad(40.0, &photo, 4.0),
+    ];
+
+    let mut atlas = GlyphAtlas::default();
+    let batch = QuadBatch::build(&quads, 100, 100, &mut atlas).unwrap();
+    assert_eq!(batch.images.len(), 1);
+    assert!(Arc::ptr_eq(&batch.images[0].1, &photo));
+    let pipelines: Vec<_> = batch
+        .draws
+        .iter()
+        .map(|draw| (draw.pipeline_id, draw.texture, draw.index_count))
+        .collect();
+    assert_eq!(
+        pipelines,
+        [
+            (IMAGE_PIPELINE, Some(batch.images[0].0), 12),
+            (SOLID_PIPELINE, None, 6)
+        ]
+    );
+    assert_eq!(batch.vertices[2].tex_coord, [4.0, 2.0]);
+
+    // Too large for every device: the frame is left to the CPU.
+    let poster = image(MAX_IMAGE_SIZE + 1, 1);
+    assert!(QuadBatch::build(&[quad(0.0, &poster, 0.0)], 100, 100, &mut atlas).is_none());
+}